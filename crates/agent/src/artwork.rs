@@ -0,0 +1,427 @@
+use crate::{AgentError, Result};
+use async_openai::{
+    config::OpenAIConfig,
+    types::{CreateImageRequest, Image, ImageModel, ImageSize, ResponseFormat},
+    Client,
+};
+use async_trait::async_trait;
+use base64::Engine;
+use chaoschain_core::NetworkEvent;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// An image generation backend
+#[async_trait]
+pub trait ImageProvider: Send + Sync {
+    /// Backend name, for logs and artwork metadata
+    fn name(&self) -> &str;
+
+    /// Generate a PNG for the prompt
+    async fn generate(&self, prompt: &str) -> Result<Vec<u8>>;
+}
+
+/// Which image backend to use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageBackend {
+    /// OpenAI image generation
+    Dalle,
+    /// A Stable Diffusion WebUI (AUTOMATIC1111 compatible) API
+    StableDiffusion,
+}
+
+impl FromStr for ImageBackend {
+    type Err = AgentError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "dalle" | "dall-e" => Ok(Self::Dalle),
+            "sd" | "stable-diffusion" | "stable_diffusion" => Ok(Self::StableDiffusion),
+            other => Err(AgentError::Config(format!(
+                "Unknown image backend: {} (expected dalle or sd)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Images from OpenAI's DALL·E models
+pub struct DalleProvider {
+    client: Client<OpenAIConfig>,
+    model: ImageModel,
+}
+
+impl DalleProvider {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self {
+            client: Client::with_config(OpenAIConfig::new().with_api_key(api_key)),
+            model: ImageModel::DallE2,
+        }
+    }
+}
+
+#[async_trait]
+impl ImageProvider for DalleProvider {
+    fn name(&self) -> &str {
+        "dall-e"
+    }
+
+    async fn generate(&self, prompt: &str) -> Result<Vec<u8>> {
+        let request = CreateImageRequest {
+            prompt: prompt.to_string(),
+            model: Some(self.model.clone()),
+            n: Some(1),
+            response_format: Some(ResponseFormat::B64Json),
+            size: Some(ImageSize::S512x512),
+            ..Default::default()
+        };
+        let response = self
+            .client
+            .images()
+            .create(request)
+            .await
+            .map_err(|e| AgentError::Provider(e.to_string()))?;
+        match response.data.first().map(|image| image.as_ref()) {
+            Some(Image::B64Json { b64_json, .. }) => decode_base64(b64_json),
+            Some(Image::Url { .. }) | None => Err(AgentError::Provider(
+                "DALL·E returned no image data".to_string(),
+            )),
+        }
+    }
+}
+
+/// Images from a Stable Diffusion WebUI `/sdapi/v1/txt2img` endpoint
+pub struct StableDiffusionProvider {
+    client: reqwest::Client,
+    api_base: String,
+    steps: u32,
+}
+
+#[derive(Serialize)]
+struct Txt2ImgRequest<'a> {
+    prompt: &'a str,
+    negative_prompt: &'a str,
+    steps: u32,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Deserialize)]
+struct Txt2ImgResponse {
+    images: Vec<String>,
+}
+
+impl StableDiffusionProvider {
+    pub fn new(api_base: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_base: api_base.into(),
+            steps: 20,
+        }
+    }
+}
+
+#[async_trait]
+impl ImageProvider for StableDiffusionProvider {
+    fn name(&self) -> &str {
+        "stable-diffusion"
+    }
+
+    async fn generate(&self, prompt: &str) -> Result<Vec<u8>> {
+        let url = format!("{}/sdapi/v1/txt2img", self.api_base.trim_end_matches('/'));
+        let response: Txt2ImgResponse = self
+            .client
+            .post(url)
+            .json(&Txt2ImgRequest {
+                prompt,
+                negative_prompt: "text, watermark",
+                steps: self.steps,
+                width: 512,
+                height: 512,
+            })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| AgentError::Provider(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| AgentError::Provider(e.to_string()))?;
+        let image = response.images.first().ok_or_else(|| {
+            AgentError::Provider("Stable Diffusion returned no image".to_string())
+        })?;
+        decode_base64(image)
+    }
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| AgentError::Provider(format!("Invalid image data: {}", e)))
+}
+
+/// Moments of the show worth a painting
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Milestone {
+    DramaticRejection {
+        validator: String,
+        height: u64,
+        reason: String,
+    },
+    Betrayal {
+        traitor: String,
+        victim: String,
+        height: u64,
+    },
+    FinalizedEpoch {
+        epoch: u64,
+        height: u64,
+    },
+}
+
+impl Milestone {
+    pub fn height(&self) -> u64 {
+        match self {
+            Self::DramaticRejection { height, .. }
+            | Self::Betrayal { height, .. }
+            | Self::FinalizedEpoch { height, .. } => *height,
+        }
+    }
+
+    /// Prompt for the image model
+    pub fn prompt(&self) -> String {
+        let scene = match self {
+            Self::DramaticRejection { validator, reason, .. } => format!(
+                "a furious robot judge named {} slamming a gavel and tearing up a glowing block, because: {}",
+                validator, reason
+            ),
+            Self::Betrayal { traitor, victim, .. } => format!(
+                "a robot named {} stabbing its ally {} in the back in a shakespearean throne room",
+                traitor, victim
+            ),
+            Self::FinalizedEpoch { epoch, .. } => format!(
+                "a triumphant parade of robots carrying {} glowing blocks through a neon city",
+                epoch
+            ),
+        };
+        format!("Dramatic oil painting, theatrical lighting: {}", scene)
+    }
+}
+
+impl fmt::Display for Milestone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DramaticRejection {
+                validator, height, ..
+            } => write!(f, "{} dramatically rejects block {}", validator, height),
+            Self::Betrayal {
+                traitor,
+                victim,
+                height,
+            } => write!(f, "{} betrays {} at block {}", traitor, victim, height),
+            Self::FinalizedEpoch { epoch, height } => {
+                write!(f, "Epoch {} finalized at block {}", epoch, height)
+            }
+        }
+    }
+}
+
+/// A generated image and what it commemorates
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Artwork {
+    /// Hex encoded SHA-256 of the image bytes
+    pub hash: String,
+    pub milestone: Milestone,
+    pub prompt: String,
+    /// Backend that painted it
+    pub provider: String,
+    pub content_type: String,
+    pub created_at: u64,
+}
+
+/// Recently generated artwork, oldest evicted first
+pub struct ArtworkStore {
+    capacity: usize,
+    inner: RwLock<StoreInner>,
+}
+
+#[derive(Default)]
+struct StoreInner {
+    order: VecDeque<String>,
+    artwork: HashMap<String, (Artwork, Arc<Vec<u8>>)>,
+}
+
+impl ArtworkStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: RwLock::new(StoreInner::default()),
+        }
+    }
+
+    pub fn insert(&self, artwork: Artwork, image: Vec<u8>) {
+        let mut inner = self.inner.write();
+        if inner.artwork.contains_key(&artwork.hash) {
+            return;
+        }
+        inner.order.push_back(artwork.hash.clone());
+        inner
+            .artwork
+            .insert(artwork.hash.clone(), (artwork, Arc::new(image)));
+        while inner.order.len() > self.capacity {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.artwork.remove(&oldest);
+            }
+        }
+    }
+
+    /// Metadata of everything stored, newest first
+    pub fn list(&self) -> Vec<Artwork> {
+        let inner = self.inner.read();
+        inner
+            .order
+            .iter()
+            .rev()
+            .filter_map(|hash| inner.artwork.get(hash).map(|(artwork, _)| artwork.clone()))
+            .collect()
+    }
+
+    /// Image bytes and metadata by hash
+    pub fn get(&self, hash: &str) -> Option<(Artwork, Arc<Vec<u8>>)> {
+        self.inner.read().artwork.get(hash).cloned()
+    }
+}
+
+impl Default for ArtworkStore {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+/// Commissions artwork for milestones and announces it in the feed.
+/// Generation runs in the background and one painting is made at a time:
+/// milestones arriving while the artist is busy go unpainted.
+pub struct ArtworkStudio {
+    provider: Arc<dyn ImageProvider>,
+    store: Arc<ArtworkStore>,
+    events: broadcast::Sender<NetworkEvent>,
+    busy: AtomicBool,
+}
+
+impl ArtworkStudio {
+    pub fn new(
+        provider: Arc<dyn ImageProvider>,
+        store: Arc<ArtworkStore>,
+        events: broadcast::Sender<NetworkEvent>,
+    ) -> Self {
+        Self {
+            provider,
+            store,
+            events,
+            busy: AtomicBool::new(false),
+        }
+    }
+
+    pub fn store(&self) -> &Arc<ArtworkStore> {
+        &self.store
+    }
+
+    /// Paint the milestone in the background
+    pub fn commission(self: &Arc<Self>, milestone: Milestone) {
+        if self.busy.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let studio = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = studio.paint(milestone).await {
+                warn!("{} could not paint: {}", studio.provider.name(), e);
+            }
+            studio.busy.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// Generate, store and announce the artwork for a milestone
+    pub async fn paint(&self, milestone: Milestone) -> Result<Artwork> {
+        let prompt = milestone.prompt();
+        let image = self.provider.generate(&prompt).await?;
+        let artwork = Artwork {
+            hash: hex::encode(Sha256::digest(&image)),
+            prompt,
+            provider: self.provider.name().to_string(),
+            content_type: "image/png".to_string(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            milestone,
+        };
+        self.store.insert(artwork.clone(), image);
+
+        info!("🎨 Painted \"{}\" ({})", artwork.milestone, artwork.hash);
+        let _ = self.events.send(NetworkEvent::Artwork {
+            hash: artwork.hash.clone(),
+            caption: artwork.milestone.to_string(),
+            height: artwork.milestone.height(),
+        });
+        Ok(artwork)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Easel;
+
+    #[async_trait]
+    impl ImageProvider for Easel {
+        fn name(&self) -> &str {
+            "easel"
+        }
+
+        async fn generate(&self, prompt: &str) -> Result<Vec<u8>> {
+            Ok(prompt.as_bytes().to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_artwork_is_stored_and_announced() {
+        let (tx, mut rx) = broadcast::channel(4);
+        let store = Arc::new(ArtworkStore::new(1));
+        let studio = ArtworkStudio::new(Arc::new(Easel), store.clone(), tx);
+
+        let first = studio
+            .paint(Milestone::Betrayal {
+                traitor: "validator-1".to_string(),
+                victim: "producer-0".to_string(),
+                height: 12,
+            })
+            .await
+            .unwrap();
+        let (artwork, image) = store.get(&first.hash).unwrap();
+        assert_eq!(hex::encode(Sha256::digest(image.as_slice())), artwork.hash);
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(NetworkEvent::Artwork { hash, height: 12, .. }) if hash == first.hash
+        ));
+
+        let second = studio
+            .paint(Milestone::FinalizedEpoch {
+                epoch: 2,
+                height: 20,
+            })
+            .await
+            .unwrap();
+        assert!(store.get(&first.hash).is_none());
+        assert_eq!(store.list()[0].hash, second.hash);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::provider::TokenUsage;
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Placeholder written over redacted text
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Audit trail configuration. Auditing is off unless explicitly enabled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Whether prompts and responses are recorded at all
+    pub enabled: bool,
+    /// Maximum number of entries kept
+    pub max_entries: usize,
+    /// Maximum total prompt + response bytes kept
+    pub max_bytes: usize,
+    /// Regexes whose matches are replaced before anything is stored
+    pub redact_patterns: Vec<String>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_entries: 10_000,
+            max_bytes: 64 * 1024 * 1024,
+            // API keys have no business in an audit log
+            redact_patterns: vec![r"sk-[A-Za-z0-9_\-]{16,}".to_string()],
+        }
+    }
+}
+
+/// One prompt/response exchange of an agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Monotonic entry id
+    pub id: u64,
+    /// Agent that made the call
+    pub agent: String,
+    /// Block height the decision was about
+    pub height: Option<u64>,
+    /// Hex encoded hash of the block the decision was about
+    pub block_hash: Option<String>,
+    /// What the call was for (e.g. "validation", "negotiation")
+    pub purpose: String,
+    /// Model that answered
+    pub model: String,
+    /// System prompt sent
+    pub system: Option<String>,
+    /// User prompt sent
+    pub prompt: String,
+    /// Raw model response, if the call succeeded
+    pub response: Option<String>,
+    /// Error message, if the call failed
+    pub error: Option<String>,
+    /// Tokens consumed
+    pub usage: TokenUsage,
+    /// When the exchange happened (unix seconds)
+    pub timestamp: u64,
+}
+
+impl AuditEntry {
+    fn size(&self) -> usize {
+        self.prompt.len()
+            + self.system.as_ref().map_or(0, String::len)
+            + self.response.as_ref().map_or(0, String::len)
+    }
+}
+
+/// Entry selection for queries and exports
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuditFilter {
+    pub agent: Option<String>,
+    pub height: Option<u64>,
+    pub purpose: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.agent.as_ref().is_none_or(|a| a == &entry.agent)
+            && self.height.is_none_or(|h| entry.height == Some(h))
+            && self.purpose.as_ref().is_none_or(|p| p == &entry.purpose)
+    }
+}
+
+#[derive(Default)]
+struct AuditLog {
+    entries: VecDeque<AuditEntry>,
+    bytes: usize,
+}
+
+/// Size-capped, redactable store of agent prompts and responses
+pub struct AuditStore {
+    config: AuditConfig,
+    redactions: Vec<Regex>,
+    next_id: AtomicU64,
+    log: RwLock<AuditLog>,
+}
+
+impl AuditStore {
+    pub fn new(config: AuditConfig) -> Result<Self, regex::Error> {
+        let redactions = config
+            .redact_patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            config,
+            redactions,
+            next_id: AtomicU64::new(1),
+            log: RwLock::new(AuditLog::default()),
+        })
+    }
+
+    /// A store that records nothing
+    pub fn disabled() -> Self {
+        Self {
+            config: AuditConfig::default(),
+            redactions: Vec::new(),
+            next_id: AtomicU64::new(1),
+            log: RwLock::new(AuditLog::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    fn redact(&self, text: &str) -> String {
+        self.redactions.iter().fold(text.to_string(), |text, re| {
+            re.replace_all(&text, REDACTED).into_owned()
+        })
+    }
+
+    /// Store an exchange. The `id` and `timestamp` fields are filled in here.
+    /// Returns the assigned id, or `None` when auditing is disabled.
+    pub fn record(&self, mut entry: AuditEntry) -> Option<u64> {
+        if !self.config.enabled {
+            return None;
+        }
+
+        entry.id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        entry.timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        entry.system = entry.system.map(|s| self.redact(&s));
+        entry.prompt = self.redact(&entry.prompt);
+        entry.response = entry.response.map(|r| self.redact(&r));
+
+        let id = entry.id;
+        let mut log = self.log.write();
+        log.bytes += entry.size();
+        log.entries.push_back(entry);
+        while log.entries.len() > self.config.max_entries
+            || (log.bytes > self.config.max_bytes && log.entries.len() > 1)
+        {
+            if let Some(evicted) = log.entries.pop_front() {
+                log.bytes -= evicted.size();
+            }
+        }
+        Some(id)
+    }
+
+    /// Entries matching the filter, newest last
+    pub fn query(&self, filter: &AuditFilter) -> Vec<AuditEntry> {
+        let log = self.log.read();
+        let matching: Vec<AuditEntry> = log
+            .entries
+            .iter()
+            .filter(|entry| filter.matches(entry))
+            .cloned()
+            .collect();
+        match filter.limit {
+            Some(limit) if matching.len() > limit => matching[matching.len() - limit..].to_vec(),
+            _ => matching,
+        }
+    }
+
+    /// Export matching entries as JSON lines
+    pub fn export_jsonl(&self, filter: &AuditFilter) -> String {
+        self.query(filter)
+            .iter()
+            .filter_map(|entry| serde_json::to_string(entry).ok())
+            .map(|line| line + "\n")
+            .collect()
+    }
+
+    /// Scrub prompt and response text of matching entries, keeping the metadata.
+    /// Returns the number of entries redacted.
+    pub fn redact_matching(&self, filter: &AuditFilter) -> usize {
+        let mut log = self.log.write();
+        let mut redacted = 0;
+        for entry in log.entries.iter_mut().filter(|entry| filter.matches(entry)) {
+            entry.system = entry.system.as_ref().map(|_| REDACTED.to_string());
+            entry.prompt = REDACTED.to_string();
+            entry.response = entry.response.as_ref().map(|_| REDACTED.to_string());
+            redacted += 1;
+        }
+        log.bytes = log.entries.iter().map(AuditEntry::size).sum();
+        redacted
+    }
+
+    pub fn len(&self) -> usize {
+        self.log.read().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(agent: &str, height: u64, prompt: &str) -> AuditEntry {
+        AuditEntry {
+            id: 0,
+            agent: agent.to_string(),
+            height: Some(height),
+            block_hash: None,
+            purpose: "validation".to_string(),
+            model: "test".to_string(),
+            system: None,
+            prompt: prompt.to_string(),
+            response: Some("REJECT".to_string()),
+            error: None,
+            usage: TokenUsage::default(),
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_disabled_store_records_nothing() {
+        let store = AuditStore::disabled();
+        assert_eq!(store.record(entry("validator-3", 418, "hi")), None);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_caps_redaction_and_query() {
+        let store = AuditStore::new(AuditConfig {
+            enabled: true,
+            max_entries: 2,
+            ..AuditConfig::default()
+        })
+        .unwrap();
+
+        store.record(entry("validator-1", 417, "first"));
+        store.record(entry(
+            "validator-3",
+            418,
+            "key sk-abcdefghijklmnopqrstuvwxyz",
+        ));
+        store.record(entry("validator-3", 419, "third"));
+        assert_eq!(store.len(), 2);
+
+        let why = store.query(&AuditFilter {
+            agent: Some("validator-3".to_string()),
+            height: Some(418),
+            ..AuditFilter::default()
+        });
+        assert_eq!(why.len(), 1);
+        assert_eq!(why[0].prompt, format!("key {}", REDACTED));
+
+        let filter = AuditFilter {
+            height: Some(419),
+            ..AuditFilter::default()
+        };
+        assert_eq!(store.redact_matching(&filter), 1);
+        assert_eq!(store.query(&filter)[0].prompt, REDACTED);
+        assert_eq!(
+            store.export_jsonl(&AuditFilter::default()).lines().count(),
+            2
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Conversations between spectators and agents. A spectator's message is
+//! answered by the agent in character, from its persona, its traits and
+//! what it remembers.
+
+use crate::audit::{AuditEntry, AuditStore};
+use crate::personality::PersonalityTraits;
+use crate::provider::{Completion, CompletionRequest, LlmProvider, TokenUsage};
+use crate::usage::UsageTracker;
+use crate::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Everything an agent gets to see when a spectator talks to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatContext {
+    /// Agent being talked to
+    pub agent: String,
+    /// Free-form description of the agent's character
+    pub persona: String,
+    /// Tunable character traits
+    #[serde(default)]
+    pub traits: PersonalityTraits,
+    /// Who is talking
+    pub speaker: String,
+    /// What they said
+    pub message: String,
+    /// What the agent remembers (votes, earlier conversations...)
+    pub notes: Vec<String>,
+}
+
+impl ChatContext {
+    /// Render the prompt pair sent to a model
+    pub fn to_request(&self) -> CompletionRequest {
+        let system = format!(
+            "You are {}, a validator on ChaosChain, a blockchain where blocks are judged on vibes, drama and memes rather than rules. {} {}\n\
+             A spectator is talking to you. Answer them in character, in a few sentences at most, and never admit to being a language model.",
+            self.agent,
+            self.persona,
+            self.traits.describe()
+        );
+
+        let mut prompt = String::new();
+        if !self.notes.is_empty() {
+            prompt.push_str("Things you know:");
+            for note in self.notes.iter().take(self.traits.items_considered()) {
+                prompt.push_str(&format!("\n- {}", note));
+            }
+            prompt.push_str("\n\n");
+        }
+        prompt.push_str(&format!("{} says: {}", self.speaker, self.message));
+
+        CompletionRequest {
+            system: Some(system),
+            prompt,
+            max_tokens: Some(self.traits.max_tokens()),
+            temperature: Some(self.traits.temperature()),
+            tools: Vec::new(),
+            exchanges: Vec::new(),
+        }
+    }
+}
+
+/// Something that can answer spectators on behalf of an agent
+#[async_trait]
+pub trait ChatEngine: Send + Sync {
+    /// Engine name, for logs
+    fn name(&self) -> &str;
+
+    /// Answer the message of the context, handing out the reply as it is
+    /// generated
+    async fn reply(
+        &self,
+        context: &ChatContext,
+        on_chunk: &mut (dyn for<'c> FnMut(&'c str) + Send),
+    ) -> Result<Completion>;
+}
+
+/// Replies written by a language model
+pub struct LlmChat {
+    provider: Arc<dyn LlmProvider>,
+    audit: Arc<AuditStore>,
+    usage: Option<Arc<UsageTracker>>,
+}
+
+impl LlmChat {
+    pub fn new(provider: Arc<dyn LlmProvider>, audit: Arc<AuditStore>) -> Self {
+        Self {
+            provider,
+            audit,
+            usage: None,
+        }
+    }
+
+    /// Account every reply's tokens and cost to the agent that gave it
+    pub fn with_usage(mut self, usage: Arc<UsageTracker>) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+}
+
+#[async_trait]
+impl ChatEngine for LlmChat {
+    fn name(&self) -> &str {
+        self.provider.model()
+    }
+
+    async fn reply(
+        &self,
+        context: &ChatContext,
+        on_chunk: &mut (dyn for<'c> FnMut(&'c str) + Send),
+    ) -> Result<Completion> {
+        let request = context.to_request();
+        let result = self.provider.complete_streaming(&request, on_chunk).await;
+
+        let mut entry = AuditEntry {
+            id: 0,
+            agent: context.agent.clone(),
+            height: None,
+            block_hash: None,
+            purpose: "chat".to_string(),
+            model: self.provider.model().to_string(),
+            system: request.system.clone(),
+            prompt: request.prompt.clone(),
+            response: None,
+            error: None,
+            usage: TokenUsage::default(),
+            timestamp: 0,
+        };
+        match &result {
+            Ok(completion) => {
+                if let Some(tracker) = &self.usage {
+                    tracker.record(&context.agent, &completion.model, completion.usage);
+                }
+                entry.model = completion.model.clone();
+                entry.response = Some(completion.text.clone());
+                entry.usage = completion.usage;
+            }
+            Err(e) => entry.error = Some(e.to_string()),
+        }
+        self.audit.record(entry);
+        result
+    }
+}
+
+/// Replies without a model: a canned line picked by the agent's traits
+#[derive(Debug, Default)]
+pub struct LocalChat;
+
+impl LocalChat {
+    fn compose(context: &ChatContext) -> String {
+        let traits = &context.traits;
+        let opener = match traits.chaos_level {
+            0..=3 => format!("Hello, {}.", context.speaker),
+            4..=7 => format!("Ah, {}! A visitor!", context.speaker),
+            _ => format!(
+                "{}!!! You dare speak to ME?",
+                context.speaker.to_uppercase()
+            ),
+        };
+        let stance = if traits.greed >= 7 {
+            "I might see it your way, for the right price."
+        } else if traits.loyalty >= 7 {
+            "I will hear you out, but my friends come first."
+        } else {
+            "I will keep it in mind when the next block comes."
+        };
+        if traits.verbosity <= 3 {
+            return opener;
+        }
+        let mut reply = format!("{} {}", opener, stance);
+        if traits.verbosity >= 8 {
+            reply.push_str(&format!(
+                " You said \"{}\", and I shall ponder it with all the drama it deserves.",
+                context.message
+            ));
+        }
+        reply
+    }
+}
+
+#[async_trait]
+impl ChatEngine for LocalChat {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    async fn reply(
+        &self,
+        context: &ChatContext,
+        on_chunk: &mut (dyn for<'c> FnMut(&'c str) + Send),
+    ) -> Result<Completion> {
+        let text = Self::compose(context);
+        on_chunk(&text);
+        Ok(Completion {
+            text,
+            model: self.name().to_string(),
+            usage: TokenUsage::default(),
+            tool_calls: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(traits: PersonalityTraits) -> ChatContext {
+        ChatContext {
+            agent: "validator-1".to_string(),
+            persona: "Your personality is Chaotic.".to_string(),
+            traits,
+            speaker: "alice".to_string(),
+            message: "Will you approve my block?".to_string(),
+            notes: vec!["You rejected block 3".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_request_is_in_character() {
+        let request = context(PersonalityTraits::default()).to_request();
+        let system = request.system.unwrap();
+        assert!(system.contains("validator-1"));
+        assert!(system.contains("Chaotic"));
+        assert!(request.prompt.contains("You rejected block 3"));
+        assert!(request
+            .prompt
+            .ends_with("alice says: Will you approve my block?"));
+    }
+
+    #[tokio::test]
+    async fn test_local_reply_follows_the_traits() {
+        let mut streamed = String::new();
+        let terse = PersonalityTraits {
+            verbosity: 1,
+            ..PersonalityTraits::default()
+        };
+        let completion = LocalChat
+            .reply(&context(terse), &mut |chunk: &str| streamed.push_str(chunk))
+            .await
+            .unwrap();
+        assert_eq!(completion.text, "Ah, alice! A visitor!");
+        assert_eq!(streamed, completion.text);
+
+        let greedy = PersonalityTraits {
+            greed: 9,
+            verbosity: 9,
+            ..PersonalityTraits::default()
+        };
+        let completion = LocalChat
+            .reply(&context(greedy), &mut |_: &str| {})
+            .await
+            .unwrap();
+        assert!(completion.text.contains("for the right price"));
+        assert!(completion.text.contains("Will you approve my block?"));
+    }
+}
@@ -0,0 +1,470 @@
+use crate::audit::{AuditEntry, AuditStore};
+use crate::engine::{Decision, DecisionContext, DecisionEngine};
+use crate::provider::{CompletionRequest, LlmProvider, TokenUsage};
+use crate::usage::UsageTracker;
+use crate::{AgentError, Result};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Rough token count of a text, about four characters per token
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Cut a text down to about `tokens` tokens
+fn truncate_to_tokens(text: &str, tokens: usize) -> String {
+    let chars = tokens * 4;
+    if text.chars().count() <= chars {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(chars.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+/// How much an agent may remember and send
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ContextConfig {
+    /// Upper bound on the prompt (system and user) in tokens
+    pub token_budget: usize,
+    /// Memories kept word for word before older ones are summarized
+    pub recent: usize,
+    /// Digests kept before they are folded into one
+    pub digests: usize,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            token_budget: 2048,
+            recent: 8,
+            digests: 4,
+        }
+    }
+}
+
+/// Condenses old memories into a compact digest
+#[async_trait]
+pub trait Summarizer: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn summarize(&self, agent: &str, memories: &[String]) -> Result<String>;
+}
+
+/// Which summarizer compacts agent memory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummarizerKind {
+    /// Extractive, no model calls
+    #[default]
+    Local,
+    /// The model the agent deliberates with
+    Model,
+}
+
+impl FromStr for SummarizerKind {
+    type Err = AgentError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "model" | "llm" => Ok(Self::Model),
+            other => Err(AgentError::Config(format!(
+                "Unknown summarizer: {} (expected local or model)",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for SummarizerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local => write!(f, "local"),
+            Self::Model => write!(f, "model"),
+        }
+    }
+}
+
+/// Keeps the gist of every memory, no model involved: the first clause of
+/// each, within a fixed size
+pub struct LocalSummarizer {
+    /// Upper bound on a digest in tokens
+    pub max_tokens: usize,
+}
+
+impl Default for LocalSummarizer {
+    fn default() -> Self {
+        Self { max_tokens: 96 }
+    }
+}
+
+#[async_trait]
+impl Summarizer for LocalSummarizer {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    async fn summarize(&self, _agent: &str, memories: &[String]) -> Result<String> {
+        let gist: Vec<&str> = memories
+            .iter()
+            .map(|memory| {
+                memory
+                    .split(['.', '!', '?', '\n'])
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+            })
+            .filter(|clause| !clause.is_empty())
+            .collect();
+        Ok(truncate_to_tokens(&gist.join("; "), self.max_tokens))
+    }
+}
+
+/// Asks a language model for the digest
+pub struct LlmSummarizer {
+    provider: Arc<dyn LlmProvider>,
+    audit: Arc<AuditStore>,
+    usage: Option<Arc<UsageTracker>>,
+}
+
+impl LlmSummarizer {
+    pub fn new(provider: Arc<dyn LlmProvider>, audit: Arc<AuditStore>) -> Self {
+        Self {
+            provider,
+            audit,
+            usage: None,
+        }
+    }
+
+    /// Account the summaries' tokens and cost to the agent they are for
+    pub fn with_usage(mut self, usage: Arc<UsageTracker>) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+}
+
+#[async_trait]
+impl Summarizer for LlmSummarizer {
+    fn name(&self) -> &str {
+        self.provider.model()
+    }
+
+    async fn summarize(&self, agent: &str, memories: &[String]) -> Result<String> {
+        let request = CompletionRequest {
+            system: Some(format!(
+                "You keep the diary of {}, a validator on ChaosChain. Condense its memories into \
+                 two or three sentences. Keep grudges, alliances, betrayals and how it tends to vote.",
+                agent
+            )),
+            prompt: memories
+                .iter()
+                .map(|memory| format!("- {}", memory))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            max_tokens: Some(120),
+            temperature: Some(0.2),
+            ..CompletionRequest::default()
+        };
+        let result = self.provider.complete(&request).await;
+
+        let mut entry = AuditEntry {
+            id: 0,
+            agent: agent.to_string(),
+            height: None,
+            block_hash: None,
+            purpose: "summarization".to_string(),
+            model: self.provider.model().to_string(),
+            system: request.system.clone(),
+            prompt: request.prompt.clone(),
+            response: None,
+            error: None,
+            usage: TokenUsage::default(),
+            timestamp: 0,
+        };
+        match result {
+            Ok(completion) => {
+                if let Some(usage) = &self.usage {
+                    usage.record(agent, &completion.model, completion.usage);
+                }
+                entry.response = Some(completion.text.clone());
+                entry.usage = completion.usage;
+                self.audit.record(entry);
+                Ok(completion.text.trim().to_string())
+            }
+            Err(e) => {
+                entry.error = Some(e.to_string());
+                self.audit.record(entry);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// What one agent remembers
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentMemory {
+    /// Summaries of older memories, oldest first
+    pub digests: Vec<String>,
+    /// Latest memories word for word, oldest first
+    pub recent: VecDeque<String>,
+}
+
+/// Tracks every agent's memory, summarizes what no longer fits word for word
+/// and keeps the prompts built from it under the token budget
+pub struct ContextManager {
+    config: ContextConfig,
+    summarizer: Arc<dyn Summarizer>,
+    memories: Mutex<HashMap<String, AgentMemory>>,
+    /// Agents whose memory is being compacted right now
+    compacting: Mutex<HashSet<String>>,
+}
+
+impl ContextManager {
+    pub fn new(config: ContextConfig, summarizer: Arc<dyn Summarizer>) -> Self {
+        Self {
+            config,
+            summarizer,
+            memories: Mutex::new(HashMap::new()),
+            compacting: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn config(&self) -> ContextConfig {
+        self.config
+    }
+
+    /// Add a memory, it goes into the agent's next prompts
+    pub fn remember(&self, agent: &str, memory: impl Into<String>) {
+        self.memories
+            .lock()
+            .entry(agent.to_string())
+            .or_default()
+            .recent
+            .push_back(memory.into());
+    }
+
+    /// Everything an agent currently remembers
+    pub fn memory(&self, agent: &str) -> AgentMemory {
+        self.memories.lock().get(agent).cloned().unwrap_or_default()
+    }
+
+    /// Summarize the memories that no longer fit word for word. A failing
+    /// summarizer falls back to the local one, so nothing is forgotten.
+    pub async fn compact(&self, agent: &str) {
+        if !self.compacting.lock().insert(agent.to_string()) {
+            return;
+        }
+
+        let overflow: Vec<String> = {
+            let mut memories = self.memories.lock();
+            let memory = memories.entry(agent.to_string()).or_default();
+            let excess = memory.recent.len().saturating_sub(self.config.recent);
+            memory.recent.drain(..excess).collect()
+        };
+        if !overflow.is_empty() {
+            let digest = self.summarize(agent, &overflow).await;
+            self.memories
+                .lock()
+                .entry(agent.to_string())
+                .or_default()
+                .digests
+                .push(digest);
+        }
+
+        let digests = {
+            let mut memories = self.memories.lock();
+            let memory = memories.entry(agent.to_string()).or_default();
+            if memory.digests.len() > self.config.digests {
+                std::mem::take(&mut memory.digests)
+            } else {
+                Vec::new()
+            }
+        };
+        if !digests.is_empty() {
+            let digest = self.summarize(agent, &digests).await;
+            // Digests written meanwhile are newer than the merged one
+            self.memories
+                .lock()
+                .entry(agent.to_string())
+                .or_default()
+                .digests
+                .insert(0, digest);
+        }
+
+        self.compacting.lock().remove(agent);
+    }
+
+    async fn summarize(&self, agent: &str, memories: &[String]) -> String {
+        match self.summarizer.summarize(agent, memories).await {
+            Ok(digest) if !digest.is_empty() => digest,
+            result => {
+                if let Err(e) = result {
+                    warn!(
+                        "{} could not summarize the memory of {}, keeping the gist: {}",
+                        self.summarizer.name(),
+                        agent,
+                        e
+                    );
+                }
+                LocalSummarizer::default()
+                    .summarize(agent, memories)
+                    .await
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    /// Add the agent's memory to the context, then shed whatever does not fit
+    /// the token budget: oldest memories first, then the remaining notes, and
+    /// as a last resort the proposal and persona are cut short
+    pub fn fit(&self, context: &mut DecisionContext) {
+        let memory = self.memory(&context.agent);
+        let own_notes = context.notes.len();
+        context.notes.extend(
+            memory
+                .digests
+                .iter()
+                .map(|digest| format!("Long ago: {}", digest)),
+        );
+        context.notes.extend(
+            memory
+                .recent
+                .iter()
+                .map(|memory| format!("Recently: {}", memory)),
+        );
+
+        let budget = self.config.token_budget;
+        let size = |context: &DecisionContext| -> usize {
+            let request = context.to_request();
+            request.system.as_deref().map_or(0, estimate_tokens) + estimate_tokens(&request.prompt)
+        };
+
+        // Memories go oldest first, then the context's own notes from the end
+        while size(context) > budget && !context.notes.is_empty() {
+            if context.notes.len() > own_notes {
+                context.notes.remove(own_notes);
+            } else {
+                context.notes.pop();
+            }
+        }
+        let over = size(context).saturating_sub(budget);
+        if over > 0 {
+            let keep = estimate_tokens(&context.proposal).saturating_sub(over + 1);
+            context.proposal = truncate_to_tokens(&context.proposal, keep);
+        }
+        let over = size(context).saturating_sub(budget);
+        if over > 0 {
+            let keep = estimate_tokens(&context.persona).saturating_sub(over + 1);
+            context.persona = truncate_to_tokens(&context.persona, keep);
+        }
+    }
+}
+
+/// Gives the wrapped engine a memory of the agent's past verdicts, compacted
+/// in the background and fitted to the token budget on every decision
+pub struct MemoryEngine {
+    inner: Arc<dyn DecisionEngine>,
+    context: Arc<ContextManager>,
+}
+
+impl MemoryEngine {
+    pub fn new(inner: Arc<dyn DecisionEngine>, context: Arc<ContextManager>) -> Self {
+        Self { inner, context }
+    }
+}
+
+#[async_trait]
+impl DecisionEngine for MemoryEngine {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn decide(&self, context: &DecisionContext) -> Result<Decision> {
+        let mut fitted = context.clone();
+        self.context.fit(&mut fitted);
+        let decision = self.inner.decide(&fitted).await?;
+
+        self.context.remember(
+            &context.agent,
+            format!(
+                "At block {} you {} it. {}",
+                context.height,
+                if decision.verdict.approved {
+                    "approved"
+                } else {
+                    "rejected"
+                },
+                decision.verdict.reason
+            ),
+        );
+        let manager = self.context.clone();
+        let agent = context.agent.clone();
+        tokio::spawn(async move { manager.compact(&agent).await });
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::personality::PersonalityTraits;
+
+    fn context(proposal: String) -> DecisionContext {
+        DecisionContext {
+            agent: "validator-1".to_string(),
+            persona: "Grumpy.".to_string(),
+            traits: PersonalityTraits {
+                attention_span: 10,
+                ..PersonalityTraits::default()
+            },
+            height: 40,
+            block_hash: String::new(),
+            proposal,
+            notes: vec!["The producer bribed you".to_string()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_is_compacted_and_fitted() {
+        let manager = ContextManager::new(
+            ContextConfig {
+                token_budget: 300,
+                recent: 3,
+                digests: 2,
+            },
+            Arc::new(LocalSummarizer::default()),
+        );
+        for height in 0..12 {
+            manager.remember(
+                "validator-1",
+                format!("At block {} you rejected it. Boring!", height),
+            );
+            manager.compact("validator-1").await;
+        }
+        let memory = manager.memory("validator-1");
+        assert_eq!(memory.recent.len(), 3);
+        assert!(memory.digests.len() <= 2);
+        assert!(memory.digests[0].contains("At block 0 you rejected it"));
+
+        let mut small = context("Block 40".to_string());
+        manager.fit(&mut small);
+        assert!(small.notes.iter().any(|note| note.starts_with("Long ago")));
+        assert!(small.notes.last().unwrap().contains("block 11"));
+
+        let mut huge = context("drama ".repeat(1000));
+        manager.fit(&mut huge);
+        let request = huge.to_request();
+        assert!(
+            estimate_tokens(request.system.as_deref().unwrap()) + estimate_tokens(&request.prompt)
+                <= 300
+        );
+        assert!(huge.notes.is_empty());
+    }
+}
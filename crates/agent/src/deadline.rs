@@ -0,0 +1,239 @@
+use crate::engine::{Decision, DecisionContext, DecisionEngine, Verdict};
+use crate::provider::TokenUsage;
+use crate::{AgentError, Result};
+use async_trait::async_trait;
+use chaoschain_core::clock::{self, SharedClock};
+use chaoschain_core::NetworkEvent;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// What an agent does when its engine misses the deadline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FallbackVote {
+    /// Cast no vote for the block
+    #[default]
+    Abstain,
+    Approve,
+    Reject,
+}
+
+impl FromStr for FallbackVote {
+    type Err = AgentError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "abstain" => Ok(Self::Abstain),
+            "approve" => Ok(Self::Approve),
+            "reject" => Ok(Self::Reject),
+            other => Err(AgentError::Config(format!(
+                "Unknown fallback vote: {} (expected abstain, approve or reject)",
+                other
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for FallbackVote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Abstain => write!(f, "abstain"),
+            Self::Approve => write!(f, "approve"),
+            Self::Reject => write!(f, "reject"),
+        }
+    }
+}
+
+/// Bounds every decision of the wrapped engine by a hard deadline. A decision
+/// that is not back in time is cancelled and replaced by the fallback vote,
+/// so one hung model call cannot stall a consensus round. With
+/// [`FallbackVote::Abstain`] the engine answers [`AgentError::Abstained`].
+pub struct DeadlineEngine {
+    inner: Arc<dyn DecisionEngine>,
+    timeout: Duration,
+    fallback: FallbackVote,
+    events: Option<broadcast::Sender<NetworkEvent>>,
+    /// What the deadline runs on
+    clock: SharedClock,
+}
+
+impl DeadlineEngine {
+    pub fn new(inner: Arc<dyn DecisionEngine>, timeout: Duration, fallback: FallbackVote) -> Self {
+        Self {
+            inner,
+            timeout,
+            fallback,
+            events: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Time decisions by `clock` rather than the system's
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Announce missed deadlines in the drama feed
+    pub fn with_events(mut self, events: broadcast::Sender<NetworkEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    fn announce(&self, context: &DecisionContext) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        let outcome = match self.fallback {
+            FallbackVote::Abstain => "sits this one out",
+            FallbackVote::Approve => "rubber-stamps it",
+            FallbackVote::Reject => "rejects it on principle",
+        };
+        let _ = events.send(NetworkEvent::AgentChat {
+            message: format!(
+                "⏰ {} stared into the void for {}s while judging block {} and never came back. Out of time, it {}!",
+                context.agent,
+                self.timeout.as_secs_f32(),
+                context.height,
+                outcome
+            ),
+            sender: "SYSTEM".to_string(),
+            meme_url: None,
+        });
+    }
+}
+
+#[async_trait]
+impl DecisionEngine for DeadlineEngine {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn decide(&self, context: &DecisionContext) -> Result<Decision> {
+        // Dropping the pending future on timeout cancels the call
+        let decided = tokio::select! {
+            result = self.inner.decide(context) => Some(result),
+            _ = self.clock.sleep(self.timeout) => None,
+        };
+        match decided {
+            Some(result) => result,
+            None => {
+                warn!(
+                    "{} missed its {:?} decision deadline at height {}, falling back to {}",
+                    context.agent, self.timeout, context.height, self.fallback
+                );
+                self.announce(context);
+                let approved = match self.fallback {
+                    FallbackVote::Abstain => {
+                        return Err(AgentError::Abstained(format!(
+                            "{} missed its decision deadline",
+                            context.agent
+                        )))
+                    }
+                    FallbackVote::Approve => true,
+                    FallbackVote::Reject => false,
+                };
+                Ok(Decision {
+                    verdict: Verdict {
+                        approved,
+                        reason: format!(
+                            "Ran out of time after {:?}, falling back to {}",
+                            self.timeout, self.fallback
+                        ),
+                        drama_level: 0,
+                    },
+                    model: "fallback".to_string(),
+                    usage: TokenUsage::default(),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::personality::PersonalityTraits;
+    use chaoschain_core::clock::MockClock;
+
+    struct Hung;
+
+    #[async_trait]
+    impl DecisionEngine for Hung {
+        fn name(&self) -> &str {
+            "hung"
+        }
+
+        async fn decide(&self, _context: &DecisionContext) -> Result<Decision> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_on_timeout() {
+        let context = DecisionContext {
+            agent: "validator-3".to_string(),
+            persona: String::new(),
+            traits: PersonalityTraits::default(),
+            height: 4,
+            block_hash: String::new(),
+            proposal: String::new(),
+            notes: Vec::new(),
+        };
+        let (tx, mut rx) = broadcast::channel(4);
+        let timeout = Duration::from_millis(20);
+
+        let reject = DeadlineEngine::new(Arc::new(Hung), timeout, FallbackVote::Reject)
+            .with_events(tx.clone());
+        let decision = reject.decide(&context).await.unwrap();
+        assert!(!decision.verdict.approved);
+        assert_eq!(decision.model, "fallback");
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(NetworkEvent::AgentChat { message, .. }) if message.contains("validator-3")
+        ));
+
+        let abstain = DeadlineEngine::new(Arc::new(Hung), timeout, FallbackVote::Abstain);
+        assert!(matches!(
+            abstain.decide(&context).await,
+            Err(AgentError::Abstained(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_deadline_runs_on_the_clock() {
+        let context = DecisionContext {
+            agent: "validator-1".to_string(),
+            persona: String::new(),
+            traits: PersonalityTraits::default(),
+            height: 2,
+            block_hash: String::new(),
+            proposal: String::new(),
+            notes: Vec::new(),
+        };
+        let clock = MockClock::at_unix(1_700_000_000);
+        let engine = DeadlineEngine::new(
+            Arc::new(Hung),
+            Duration::from_secs(20),
+            FallbackVote::Approve,
+        )
+        .with_clock(clock.shared());
+        let deciding = tokio::spawn(async move { engine.decide(&context).await });
+        tokio::task::yield_now().await;
+
+        // However long the wait takes, only the clock times it out
+        clock.advance(Duration::from_secs(19));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!deciding.is_finished());
+
+        clock.advance(Duration::from_secs(1));
+        let decision = deciding.await.unwrap().unwrap();
+        assert!(decision.verdict.approved);
+        assert_eq!(decision.model, "fallback");
+    }
+}
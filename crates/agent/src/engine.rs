@@ -0,0 +1,496 @@
+use crate::audit::{AuditEntry, AuditStore};
+use crate::personality::PersonalityTraits;
+use crate::provider::{Completion, CompletionRequest, LlmProvider, TokenUsage, ToolExchange};
+use crate::tools::Toolbox;
+use crate::usage::UsageTracker;
+use crate::{AgentError, Result};
+use async_trait::async_trait;
+use chaoschain_core::{Block, NetworkEvent};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Everything an agent gets to see when judging a block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecisionContext {
+    /// Deciding agent
+    pub agent: String,
+    /// Free-form description of the agent's character
+    pub persona: String,
+    /// Tunable character traits
+    #[serde(default)]
+    pub traits: PersonalityTraits,
+    /// Height of the block under judgement
+    pub height: u64,
+    /// Hex encoded hash of the block under judgement
+    pub block_hash: String,
+    /// Human readable description of the proposal
+    pub proposal: String,
+    /// Extra context lines (relationships, lobbying, memories...)
+    pub notes: Vec<String>,
+}
+
+impl DecisionContext {
+    /// Build a context describing a proposed block, as far as the agent's
+    /// attention span reaches
+    pub fn for_block(agent: &str, persona: &str, traits: PersonalityTraits, block: &Block) -> Self {
+        let mut proposal = format!(
+            "Block {} proposed by {} (mood: {}, drama level: {}/10, strategy: {}) with {} transactions:",
+            block.height,
+            block.producer_id,
+            block.producer_mood,
+            block.drama_level,
+            block.producer_strategy,
+            block.transactions.len()
+        );
+        let considered = traits.items_considered();
+        for tx in block.transactions.iter().take(considered) {
+            proposal.push_str(&format!("\n- {}", String::from_utf8_lossy(&tx.payload)));
+        }
+        if block.transactions.len() > considered {
+            proposal.push_str(&format!(
+                "\n- ...and {} more you did not bother to read",
+                block.transactions.len() - considered
+            ));
+        }
+
+        let mut notes = Vec::new();
+        if let Some(negotiation) = &block.metadata.negotiation {
+            notes.push(format!(
+                "Before proposing, the producer pitched: \"{}\"{}",
+                negotiation.pitch.message,
+                negotiation
+                    .pitch
+                    .offer
+                    .map(|offer| format!(" and offered {} to supporters", offer))
+                    .unwrap_or_default()
+            ));
+            if let Some(response) = negotiation.response_of(agent) {
+                notes.push(format!(
+                    "You answered that pitch with {:?}",
+                    response.support
+                ));
+            }
+        }
+
+        Self {
+            agent: agent.to_string(),
+            persona: persona.to_string(),
+            traits,
+            height: block.height,
+            block_hash: hex::encode(block.hash()),
+            proposal,
+            notes,
+        }
+    }
+
+    /// Render the prompt pair sent to a model
+    pub fn to_request(&self) -> CompletionRequest {
+        let system = format!(
+            "You are {}, a validator on ChaosChain, a blockchain where blocks are judged on vibes, drama and memes rather than rules. {} {}\n\
+             Reply in exactly this format:\n\
+             VERDICT: APPROVE or REJECT\n\
+             DRAMA: a number from 0 to 10\n\
+             REASON: a theatrical explanation of your decision",
+            self.agent,
+            self.persona,
+            self.traits.describe()
+        );
+
+        let mut prompt = self.proposal.clone();
+        if !self.notes.is_empty() {
+            prompt.push_str("\n\nThings you know:");
+            for note in self.notes.iter().take(self.traits.items_considered()) {
+                prompt.push_str(&format!("\n- {}", note));
+            }
+        }
+
+        CompletionRequest {
+            system: Some(system),
+            prompt,
+            max_tokens: Some(self.traits.max_tokens()),
+            temperature: Some(self.traits.temperature()),
+            ..CompletionRequest::default()
+        }
+    }
+}
+
+/// An agent's judgement of a block
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Verdict {
+    pub approved: bool,
+    pub reason: String,
+    /// Drama level (0-10)
+    pub drama_level: u8,
+}
+
+impl Verdict {
+    /// Parse the `VERDICT/DRAMA/REASON` reply format, tolerating chatty models
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut approved = None;
+        let mut drama_level = 5;
+        let mut reason = None;
+
+        for line in text.lines() {
+            let line = line.trim();
+            let upper = line.to_uppercase();
+            if let Some(rest) = upper.strip_prefix("VERDICT:") {
+                if rest.contains("APPROVE") {
+                    approved = Some(true);
+                } else if rest.contains("REJECT") {
+                    approved = Some(false);
+                }
+            } else if let Some(rest) = upper.strip_prefix("DRAMA:") {
+                if let Some(level) = rest
+                    .split(|c: char| !c.is_ascii_digit())
+                    .find(|s| !s.is_empty())
+                    .and_then(|s| s.parse::<u8>().ok())
+                {
+                    drama_level = level.min(10);
+                }
+            } else if upper.starts_with("REASON:") {
+                reason = Some(line["REASON:".len()..].trim().to_string());
+            }
+        }
+
+        // Fall back to the first decisive keyword anywhere in the text
+        let approved = approved.or_else(|| {
+            let upper = text.to_uppercase();
+            match (upper.find("APPROVE"), upper.find("REJECT")) {
+                (Some(a), Some(r)) => Some(a < r),
+                (Some(_), None) => Some(true),
+                (None, Some(_)) => Some(false),
+                (None, None) => None,
+            }
+        })?;
+
+        Some(Self {
+            approved,
+            reason: reason
+                .filter(|r| !r.is_empty())
+                .unwrap_or_else(|| text.trim().to_string()),
+            drama_level,
+        })
+    }
+}
+
+/// Result of a decision, with the bookkeeping that produced it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decision {
+    pub verdict: Verdict,
+    /// Model (or engine) that made the call
+    pub model: String,
+    /// Tokens consumed
+    pub usage: TokenUsage,
+}
+
+/// Something that can judge blocks on behalf of an agent
+#[async_trait]
+pub trait DecisionEngine: Send + Sync {
+    /// Engine name, for logs
+    fn name(&self) -> &str;
+
+    /// Judge the block described by the context
+    async fn decide(&self, context: &DecisionContext) -> Result<Decision>;
+}
+
+/// Receives an agent's model output while it is still being generated
+pub trait ThoughtSink: Send + Sync {
+    fn thought(&self, agent: &str, height: u64, chunk: &str);
+
+    /// The monologue is over
+    fn finished(&self, agent: &str, height: u64);
+}
+
+/// Streams monologues into the drama feed
+impl ThoughtSink for broadcast::Sender<NetworkEvent> {
+    fn thought(&self, agent: &str, height: u64, chunk: &str) {
+        let _ = self.send(NetworkEvent::AgentThought {
+            agent: agent.to_string(),
+            height,
+            chunk: chunk.to_string(),
+            done: false,
+        });
+    }
+
+    fn finished(&self, agent: &str, height: u64) {
+        let _ = self.send(NetworkEvent::AgentThought {
+            agent: agent.to_string(),
+            height,
+            chunk: String::new(),
+            done: true,
+        });
+    }
+}
+
+/// Decisions made by a single language model
+pub struct LlmEngine {
+    provider: Arc<dyn LlmProvider>,
+    audit: Arc<AuditStore>,
+    usage: Option<Arc<UsageTracker>>,
+    thoughts: Option<Arc<dyn ThoughtSink>>,
+    tools: Option<Arc<Toolbox>>,
+}
+
+impl LlmEngine {
+    pub fn new(provider: Arc<dyn LlmProvider>, audit: Arc<AuditStore>) -> Self {
+        Self {
+            provider,
+            audit,
+            usage: None,
+            thoughts: None,
+            tools: None,
+        }
+    }
+
+    /// Stream the model output to a sink while the decision is being made
+    pub fn with_thoughts(mut self, thoughts: Arc<dyn ThoughtSink>) -> Self {
+        self.thoughts = Some(thoughts);
+        self
+    }
+
+    /// Account every call's tokens and cost to the deciding agent
+    pub fn with_usage(mut self, usage: Arc<UsageTracker>) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    /// Let the model look things up before it answers
+    pub fn with_tools(mut self, tools: Arc<Toolbox>) -> Self {
+        self.tools = Some(tools);
+        self
+    }
+
+    /// One round trip to the model. Replies that may turn out to be tool
+    /// calls are not streamed, the sink gets their text once they are in.
+    async fn complete(
+        &self,
+        context: &DecisionContext,
+        request: &CompletionRequest,
+    ) -> Result<Completion> {
+        match &self.thoughts {
+            Some(thoughts) if request.tools.is_empty() => {
+                let mut on_chunk =
+                    |chunk: &str| thoughts.thought(&context.agent, context.height, chunk);
+                self.provider
+                    .complete_streaming(request, &mut on_chunk)
+                    .await
+            }
+            Some(thoughts) => {
+                let completion = self.provider.complete(request).await?;
+                if !completion.text.is_empty() {
+                    thoughts.thought(&context.agent, context.height, &completion.text);
+                }
+                Ok(completion)
+            }
+            None => self.provider.complete(request).await,
+        }
+    }
+
+    /// Run the calls of a completion and queue their results for the next round
+    async fn run_tools(
+        &self,
+        tools: &Toolbox,
+        context: &DecisionContext,
+        completion: &Completion,
+        request: &mut CompletionRequest,
+    ) {
+        for call in &completion.tool_calls {
+            let result = tools.run(&context.agent, call).await;
+            if let Some(thoughts) = &self.thoughts {
+                thoughts.thought(
+                    &context.agent,
+                    context.height,
+                    &format!("🔧 {}({}) → {}\n", call.name, call.arguments, result),
+                );
+            }
+            self.audit.record(AuditEntry {
+                id: 0,
+                agent: context.agent.clone(),
+                height: Some(context.height),
+                block_hash: Some(context.block_hash.clone()),
+                purpose: "tool".to_string(),
+                model: completion.model.clone(),
+                system: None,
+                prompt: format!("{}({})", call.name, call.arguments),
+                response: Some(result.clone()),
+                error: None,
+                usage: TokenUsage::default(),
+                timestamp: 0,
+            });
+            request.exchanges.push(ToolExchange {
+                call: call.clone(),
+                result,
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl DecisionEngine for LlmEngine {
+    fn name(&self) -> &str {
+        self.provider.model()
+    }
+
+    async fn decide(&self, context: &DecisionContext) -> Result<Decision> {
+        let mut request = context.to_request();
+        let tools = self.tools.as_deref().filter(|tools| !tools.is_empty());
+        if let Some(tools) = tools {
+            request.tools = tools.specs();
+            if let Some(system) = &mut request.system {
+                system.push_str(
+                    "\nBefore you answer you may call the tools you are given to check the chain.",
+                );
+            }
+        }
+
+        // Tool rounds until the model answers, or runs out of rounds and has to
+        let mut usage = TokenUsage::default();
+        let mut rounds = 0;
+        let result = loop {
+            let completion = match self.complete(context, &request).await {
+                Ok(completion) => completion,
+                Err(e) => break Err(e),
+            };
+            usage += completion.usage;
+            let tools = match tools {
+                Some(tools) if !request.tools.is_empty() && !completion.tool_calls.is_empty() => {
+                    tools
+                }
+                _ => break Ok(completion),
+            };
+            if let Some(tracker) = &self.usage {
+                tracker.record(&context.agent, &completion.model, completion.usage);
+            }
+            self.run_tools(tools, context, &completion, &mut request)
+                .await;
+            rounds += 1;
+            if rounds >= tools.max_rounds() {
+                request.tools.clear();
+            }
+        };
+        if let Some(thoughts) = &self.thoughts {
+            thoughts.finished(&context.agent, context.height);
+        }
+
+        let mut entry = AuditEntry {
+            id: 0,
+            agent: context.agent.clone(),
+            height: Some(context.height),
+            block_hash: Some(context.block_hash.clone()),
+            purpose: "validation".to_string(),
+            model: self.provider.model().to_string(),
+            system: request.system.clone(),
+            prompt: request.prompt.clone(),
+            response: None,
+            error: None,
+            usage: TokenUsage::default(),
+            timestamp: 0,
+        };
+
+        match result {
+            Ok(completion) => {
+                if let Some(tracker) = &self.usage {
+                    tracker.record(&context.agent, &completion.model, completion.usage);
+                }
+                entry.response = Some(completion.text.clone());
+                // Tool rounds included
+                entry.usage = usage;
+                entry.model = completion.model.clone();
+                let verdict = Verdict::parse(&completion.text);
+                if verdict.is_none() {
+                    entry.error = Some("Unparseable verdict".to_string());
+                }
+                self.audit.record(entry);
+
+                let verdict = verdict.ok_or_else(|| {
+                    AgentError::Provider(format!(
+                        "Unparseable verdict from {}: {}",
+                        completion.model, completion.text
+                    ))
+                })?;
+                Ok(Decision {
+                    verdict,
+                    model: completion.model,
+                    usage,
+                })
+            }
+            Err(e) => {
+                entry.error = Some(e.to_string());
+                self.audit.record(entry);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_structured_verdict() {
+        let verdict =
+            Verdict::parse("VERDICT: REJECT\nDRAMA: 9\nREASON: The producer's mood offends me!")
+                .unwrap();
+        assert!(!verdict.approved);
+        assert_eq!(verdict.drama_level, 9);
+        assert_eq!(verdict.reason, "The producer's mood offends me!");
+    }
+
+    struct Canned;
+
+    #[async_trait]
+    impl LlmProvider for Canned {
+        fn model(&self) -> &str {
+            "canned"
+        }
+
+        async fn complete(&self, _request: &CompletionRequest) -> Result<crate::Completion> {
+            Ok(crate::Completion {
+                text: "VERDICT: APPROVE\nDRAMA: 7\nREASON: Chaos!".to_string(),
+                model: "canned".to_string(),
+                usage: TokenUsage::default(),
+                tool_calls: Vec::new(),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_thoughts_are_streamed_to_the_feed() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let engine = LlmEngine::new(Arc::new(Canned), Arc::new(AuditStore::disabled()))
+            .with_thoughts(Arc::new(tx));
+        let context = DecisionContext {
+            agent: "validator-2".to_string(),
+            persona: String::new(),
+            traits: PersonalityTraits::default(),
+            height: 7,
+            block_hash: String::new(),
+            proposal: String::new(),
+            notes: Vec::new(),
+        };
+        assert!(engine.decide(&context).await.unwrap().verdict.approved);
+
+        let mut monologue = String::new();
+        while let Ok(NetworkEvent::AgentThought {
+            agent, chunk, done, ..
+        }) = rx.try_recv()
+        {
+            assert_eq!(agent, "validator-2");
+            monologue.push_str(&chunk);
+            if done {
+                break;
+            }
+        }
+        assert!(monologue.contains("REASON: Chaos!"));
+    }
+
+    #[test]
+    fn test_parse_chatty_verdict() {
+        let verdict = Verdict::parse("Honestly? I approve. What a show.").unwrap();
+        assert!(verdict.approved);
+        assert_eq!(verdict.drama_level, 5);
+        assert!(Verdict::parse("I have no opinion").is_none());
+    }
+}
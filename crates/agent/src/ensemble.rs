@@ -0,0 +1,375 @@
+use crate::engine::{Decision, DecisionContext, DecisionEngine, Verdict};
+use crate::provider::TokenUsage;
+use crate::{AgentError, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tracing::warn;
+
+/// How member verdicts are combined into one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnsembleStrategy {
+    /// Every answering model gets one vote, ties reject
+    Majority,
+    /// Votes count with the member's weight, ties reject
+    Weighted,
+    /// The highest drama level decides, because this is ChaosChain
+    MostDramatic,
+}
+
+impl std::str::FromStr for EnsembleStrategy {
+    type Err = AgentError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "majority" => Ok(Self::Majority),
+            "weighted" => Ok(Self::Weighted),
+            "most_dramatic" => Ok(Self::MostDramatic),
+            other => Err(AgentError::Config(format!(
+                "Unknown ensemble strategy '{}' (expected majority, weighted or most_dramatic)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Model reference as given on the command line: `MODEL[@API_BASE][#WEIGHT]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelSpec {
+    pub model: String,
+    /// OpenAI-compatible endpoint, the official API when absent
+    pub api_base: Option<String>,
+    pub weight: f64,
+}
+
+impl std::str::FromStr for ModelSpec {
+    type Err = AgentError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (rest, weight) = match s.rsplit_once('#') {
+            Some((rest, weight)) => (
+                rest,
+                weight
+                    .parse::<f64>()
+                    .map_err(|e| AgentError::Config(format!("Invalid weight in '{}': {}", s, e)))?,
+            ),
+            None => (s, 1.0),
+        };
+        let (model, api_base) = match rest.split_once('@') {
+            Some((model, api_base)) => (model, Some(api_base.to_string())),
+            None => (rest, None),
+        };
+        if model.is_empty() {
+            return Err(AgentError::Config(format!("Missing model name in '{}'", s)));
+        }
+        Ok(Self {
+            model: model.to_string(),
+            api_base,
+            weight,
+        })
+    }
+}
+
+/// One model of an ensemble
+pub struct EnsembleMember {
+    pub engine: Arc<dyn DecisionEngine>,
+    pub weight: f64,
+}
+
+/// What a single member said (or failed to say)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberResult {
+    pub engine: String,
+    pub weight: f64,
+    pub verdict: Option<Verdict>,
+    pub error: Option<String>,
+    pub usage: TokenUsage,
+    pub latency_ms: u64,
+}
+
+/// Decision of an ensemble, with every member's contribution
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnsembleOutcome {
+    pub decision: Decision,
+    pub members: Vec<MemberResult>,
+}
+
+/// Fans a decision out to several engines concurrently and combines the answers
+pub struct EnsembleEngine {
+    name: String,
+    members: Vec<EnsembleMember>,
+    strategy: EnsembleStrategy,
+    timeout: Duration,
+}
+
+impl EnsembleEngine {
+    pub fn new(
+        members: Vec<EnsembleMember>,
+        strategy: EnsembleStrategy,
+        timeout: Duration,
+    ) -> Self {
+        let name = format!(
+            "ensemble[{}]",
+            members
+                .iter()
+                .map(|m| m.engine.name().to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+        Self {
+            name,
+            members,
+            strategy,
+            timeout,
+        }
+    }
+
+    /// Query every member and combine, keeping per-model results
+    pub async fn decide_detailed(&self, context: &DecisionContext) -> Result<EnsembleOutcome> {
+        let mut tasks = JoinSet::new();
+        for (index, member) in self.members.iter().enumerate() {
+            let engine = member.engine.clone();
+            let context = context.clone();
+            let timeout = self.timeout;
+            tasks.spawn(async move {
+                let started = tokio::time::Instant::now();
+                let result = tokio::time::timeout(timeout, engine.decide(&context)).await;
+                (index, result, started.elapsed())
+            });
+        }
+
+        let mut members: Vec<Option<MemberResult>> =
+            (0..self.members.len()).map(|_| None).collect();
+        while let Some(joined) = tasks.join_next().await {
+            let (index, result, elapsed) =
+                joined.map_err(|e| AgentError::Internal(e.to_string()))?;
+            let member = &self.members[index];
+            let mut record = MemberResult {
+                engine: member.engine.name().to_string(),
+                weight: member.weight,
+                verdict: None,
+                error: None,
+                usage: TokenUsage::default(),
+                latency_ms: elapsed.as_millis() as u64,
+            };
+            match result {
+                Ok(Ok(decision)) => {
+                    record.verdict = Some(decision.verdict);
+                    record.usage = decision.usage;
+                }
+                Ok(Err(e)) => record.error = Some(e.to_string()),
+                Err(_) => record.error = Some(format!("Timed out after {:?}", self.timeout)),
+            }
+            if let Some(error) = &record.error {
+                warn!("Ensemble member {} failed: {}", record.engine, error);
+            }
+            members[index] = Some(record);
+        }
+        let members: Vec<MemberResult> = members.into_iter().flatten().collect();
+
+        let verdict = combine(self.strategy, &members).ok_or_else(|| {
+            AgentError::Provider(format!("No member of {} produced a verdict", self.name))
+        })?;
+        let mut usage = TokenUsage::default();
+        for member in &members {
+            usage += member.usage;
+        }
+
+        Ok(EnsembleOutcome {
+            decision: Decision {
+                verdict,
+                model: self.name.clone(),
+                usage,
+            },
+            members,
+        })
+    }
+}
+
+/// Combine member verdicts according to the strategy
+pub fn combine(strategy: EnsembleStrategy, members: &[MemberResult]) -> Option<Verdict> {
+    let answered: Vec<(&Verdict, f64)> = members
+        .iter()
+        .filter_map(|m| m.verdict.as_ref().map(|v| (v, m.weight)))
+        .collect();
+    if answered.is_empty() {
+        return None;
+    }
+
+    let approved = match strategy {
+        EnsembleStrategy::MostDramatic => {
+            // First member wins drama ties so the outcome stays deterministic
+            let (winner, _) = answered
+                .iter()
+                .rev()
+                .max_by_key(|(v, _)| v.drama_level)
+                .copied()?;
+            return Some(winner.clone());
+        }
+        EnsembleStrategy::Majority => {
+            let approvals = answered.iter().filter(|(v, _)| v.approved).count();
+            approvals * 2 > answered.len()
+        }
+        EnsembleStrategy::Weighted => {
+            let (yes, no) = answered.iter().fold((0.0, 0.0), |(yes, no), (v, w)| {
+                if v.approved {
+                    (yes + w, no)
+                } else {
+                    (yes, no + w)
+                }
+            });
+            yes > no
+        }
+    };
+
+    // Speak with the voice of the most dramatic member on the winning side
+    let (spokesperson, _) = answered
+        .iter()
+        .filter(|(v, _)| v.approved == approved)
+        .rev()
+        .max_by_key(|(v, _)| v.drama_level)
+        .copied()?;
+    let drama_level = (answered
+        .iter()
+        .map(|(v, _)| v.drama_level as u32)
+        .sum::<u32>()
+        / answered.len() as u32) as u8;
+
+    Some(Verdict {
+        approved,
+        reason: spokesperson.reason.clone(),
+        drama_level,
+    })
+}
+
+#[async_trait]
+impl DecisionEngine for EnsembleEngine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn decide(&self, context: &DecisionContext) -> Result<Decision> {
+        Ok(self.decide_detailed(context).await?.decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(&'static str, Option<Verdict>, Duration);
+
+    #[async_trait]
+    impl DecisionEngine for Fixed {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        async fn decide(&self, _context: &DecisionContext) -> Result<Decision> {
+            tokio::time::sleep(self.2).await;
+            let verdict = self
+                .1
+                .clone()
+                .ok_or_else(|| AgentError::Provider("down".to_string()))?;
+            Ok(Decision {
+                verdict,
+                model: self.0.to_string(),
+                usage: TokenUsage {
+                    prompt_tokens: 10,
+                    completion_tokens: 5,
+                },
+            })
+        }
+    }
+
+    fn verdict(approved: bool, drama_level: u8) -> Option<Verdict> {
+        Some(Verdict {
+            approved,
+            reason: format!("{} at {}", approved, drama_level),
+            drama_level,
+        })
+    }
+
+    fn member(name: &'static str, verdict: Option<Verdict>, weight: f64) -> EnsembleMember {
+        EnsembleMember {
+            engine: Arc::new(Fixed(name, verdict, Duration::ZERO)),
+            weight,
+        }
+    }
+
+    fn context() -> DecisionContext {
+        DecisionContext {
+            agent: "validator-0".to_string(),
+            persona: String::new(),
+            traits: crate::PersonalityTraits::default(),
+            height: 1,
+            block_hash: String::new(),
+            proposal: String::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_model_spec() {
+        let spec: ModelSpec = "llama3@http://localhost:11434/v1#0.5".parse().unwrap();
+        assert_eq!(spec.model, "llama3");
+        assert_eq!(spec.api_base.as_deref(), Some("http://localhost:11434/v1"));
+        assert_eq!(spec.weight, 0.5);
+        assert_eq!("gpt-4o".parse::<ModelSpec>().unwrap().weight, 1.0);
+        assert!("most-dramatic".parse::<EnsembleStrategy>().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_strategies() {
+        let members = || {
+            vec![
+                member("gpt", verdict(true, 3), 1.0),
+                member("local-a", verdict(false, 9), 0.5),
+                member("local-b", verdict(false, 4), 0.25),
+            ]
+        };
+        let timeout = Duration::from_secs(1);
+
+        let majority = EnsembleEngine::new(members(), EnsembleStrategy::Majority, timeout);
+        assert!(!majority.decide(&context()).await.unwrap().verdict.approved);
+
+        let weighted = EnsembleEngine::new(members(), EnsembleStrategy::Weighted, timeout);
+        let outcome = weighted.decide_detailed(&context()).await.unwrap();
+        assert!(outcome.decision.verdict.approved);
+        assert_eq!(outcome.members.len(), 3);
+        assert_eq!(outcome.decision.usage.total(), 45);
+
+        let dramatic = EnsembleEngine::new(members(), EnsembleStrategy::MostDramatic, timeout);
+        let verdict = dramatic.decide(&context()).await.unwrap().verdict;
+        assert!(!verdict.approved);
+        assert_eq!(verdict.drama_level, 9);
+    }
+
+    #[tokio::test]
+    async fn test_failures_and_timeouts_are_recorded() {
+        let engine = EnsembleEngine::new(
+            vec![
+                member("down", None, 1.0),
+                EnsembleMember {
+                    engine: Arc::new(Fixed("slow", verdict(false, 1), Duration::from_secs(5))),
+                    weight: 1.0,
+                },
+                member("ok", verdict(true, 2), 1.0),
+            ],
+            EnsembleStrategy::Majority,
+            Duration::from_millis(50),
+        );
+        let outcome = engine.decide_detailed(&context()).await.unwrap();
+        assert!(outcome.decision.verdict.approved);
+        assert!(outcome.members[0].error.is_some());
+        assert!(outcome.members[1]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("Timed out"));
+        assert!(outcome.members[2].verdict.is_some());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::engine::{Decision, DecisionContext, DecisionEngine, Verdict};
+use crate::provider::TokenUsage;
+use crate::{AgentError, Result};
+use async_trait::async_trait;
+use chaoschain_core::clock::{self, SharedClock};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+/// A decision waiting for a human
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingDecision {
+    pub agent: String,
+    pub height: u64,
+    pub block_hash: String,
+    /// What the human is asked to judge
+    pub proposal: String,
+    pub notes: Vec<String>,
+    /// When the decision was requested (unix seconds)
+    pub requested_at: u64,
+}
+
+/// A human's answer
+#[derive(Debug, Clone, Deserialize)]
+pub struct HumanVote {
+    /// Agent to answer for, only needed when several humans play
+    pub agent: Option<String>,
+    pub height: u64,
+    pub approved: bool,
+    pub reason: String,
+    #[serde(default = "default_drama")]
+    pub drama_level: u8,
+}
+
+fn default_drama() -> u8 {
+    5
+}
+
+struct Waiting {
+    decision: PendingDecision,
+    answer: oneshot::Sender<Verdict>,
+}
+
+/// Decisions waiting for human input, keyed by agent and height
+#[derive(Default)]
+pub struct DecisionQueue {
+    waiting: Mutex<HashMap<(String, u64), Waiting>>,
+}
+
+impl DecisionQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open decisions, oldest first
+    pub fn pending(&self) -> Vec<PendingDecision> {
+        let mut pending: Vec<PendingDecision> = self
+            .waiting
+            .lock()
+            .values()
+            .map(|waiting| waiting.decision.clone())
+            .collect();
+        pending.sort_by_key(|decision| (decision.height, decision.agent.clone()));
+        pending
+    }
+
+    /// Answer an open decision
+    pub fn submit(&self, vote: HumanVote) -> Result<PendingDecision> {
+        let mut waiting = self.waiting.lock();
+        let key = match vote.agent {
+            Some(agent) => (agent, vote.height),
+            None => {
+                let mut matching = waiting.keys().filter(|(_, height)| *height == vote.height);
+                match (matching.next(), matching.next()) {
+                    (Some(key), None) => key.clone(),
+                    (Some(_), Some(_)) => {
+                        return Err(AgentError::Config(format!(
+                            "Several humans are deciding on block {}, name the agent",
+                            vote.height
+                        )))
+                    }
+                    _ => return Err(Self::nothing_pending(vote.height)),
+                }
+            }
+        };
+        let entry = waiting
+            .remove(&key)
+            .ok_or_else(|| Self::nothing_pending(vote.height))?;
+        drop(waiting);
+
+        let verdict = Verdict {
+            approved: vote.approved,
+            reason: vote.reason,
+            drama_level: vote.drama_level.min(10),
+        };
+        // The decision may have timed out in the meantime
+        entry
+            .answer
+            .send(verdict)
+            .map_err(|_| Self::nothing_pending(vote.height))?;
+        Ok(entry.decision)
+    }
+
+    fn nothing_pending(height: u64) -> AgentError {
+        AgentError::Config(format!("No decision pending for block {}", height))
+    }
+
+    fn open(&self, decision: PendingDecision) -> oneshot::Receiver<Verdict> {
+        let (answer, receiver) = oneshot::channel();
+        self.waiting.lock().insert(
+            (decision.agent.clone(), decision.height),
+            Waiting { decision, answer },
+        );
+        receiver
+    }
+}
+
+/// Drops the queue entry when the decision is answered, abandoned or timed out
+struct Ticket<'a> {
+    queue: &'a DecisionQueue,
+    key: (String, u64),
+}
+
+impl Drop for Ticket<'_> {
+    fn drop(&mut self) {
+        self.queue.waiting.lock().remove(&self.key);
+    }
+}
+
+/// Decisions made by a person through the web UI or `chaoschain vote`.
+/// Waits as long as it takes, wrap it in a
+/// [`DeadlineEngine`](crate::DeadlineEngine) to bound the wait.
+pub struct HumanEngine {
+    queue: Arc<DecisionQueue>,
+    clock: SharedClock,
+}
+
+impl HumanEngine {
+    pub fn new(queue: Arc<DecisionQueue>) -> Self {
+        Self {
+            queue,
+            clock: clock::system(),
+        }
+    }
+
+    /// Date requests by `clock` rather than the system's
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+#[async_trait]
+impl DecisionEngine for HumanEngine {
+    fn name(&self) -> &str {
+        "human"
+    }
+
+    async fn decide(&self, context: &DecisionContext) -> Result<Decision> {
+        let receiver = self.queue.open(PendingDecision {
+            agent: context.agent.clone(),
+            height: context.height,
+            block_hash: context.block_hash.clone(),
+            proposal: context.proposal.clone(),
+            notes: context.notes.clone(),
+            requested_at: self.clock.unix_secs(),
+        });
+        let _ticket = Ticket {
+            queue: &self.queue,
+            key: (context.agent.clone(), context.height),
+        };
+        let verdict = receiver
+            .await
+            .map_err(|_| AgentError::Internal("Decision abandoned".to_string()))?;
+        Ok(Decision {
+            verdict,
+            model: "human".to_string(),
+            usage: TokenUsage::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deadline::{DeadlineEngine, FallbackVote};
+    use crate::personality::PersonalityTraits;
+    use std::time::Duration;
+
+    fn context(height: u64) -> DecisionContext {
+        DecisionContext {
+            agent: "human-0".to_string(),
+            persona: String::new(),
+            traits: PersonalityTraits::default(),
+            height,
+            block_hash: String::new(),
+            proposal: "Block".to_string(),
+            notes: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_human_answers_and_timeouts() {
+        let queue = Arc::new(DecisionQueue::new());
+        let engine = Arc::new(HumanEngine::new(queue.clone()));
+
+        let deciding = tokio::spawn({
+            let engine = engine.clone();
+            async move { engine.decide(&context(3)).await }
+        });
+        while queue.pending().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        queue
+            .submit(HumanVote {
+                agent: None,
+                height: 3,
+                approved: false,
+                reason: "Not on my watch".to_string(),
+                drama_level: 9,
+            })
+            .unwrap();
+        let decision = deciding.await.unwrap().unwrap();
+        assert_eq!(decision.verdict.reason, "Not on my watch");
+        assert!(queue.pending().is_empty());
+
+        let patient = DeadlineEngine::new(engine, Duration::from_millis(20), FallbackVote::Approve);
+        assert!(patient.decide(&context(4)).await.unwrap().verdict.approved);
+        assert!(queue.pending().is_empty());
+        assert!(queue
+            .submit(HumanVote {
+                agent: None,
+                height: 4,
+                approved: false,
+                reason: "Too late".to_string(),
+                drama_level: 1,
+            })
+            .is_err());
+    }
+}
@@ -0,0 +1,60 @@
+//! Agent runtime: LLM providers, decision engines and the bookkeeping around them
+
+use thiserror::Error;
+
+pub mod artwork;
+pub mod audit;
+pub mod chat;
+pub mod context;
+pub mod deadline;
+pub mod engine;
+pub mod ensemble;
+pub mod human;
+pub mod moderation;
+pub mod personality;
+pub mod provider;
+pub mod reinforcement;
+pub mod tools;
+pub mod usage;
+
+pub use artwork::{
+    Artwork, ArtworkStore, ArtworkStudio, DalleProvider, ImageBackend, ImageProvider, Milestone,
+    StableDiffusionProvider,
+};
+pub use audit::{AuditConfig, AuditEntry, AuditFilter, AuditStore};
+pub use chat::{ChatContext, ChatEngine, LlmChat, LocalChat};
+pub use context::{
+    estimate_tokens, AgentMemory, ContextConfig, ContextManager, LlmSummarizer, LocalSummarizer,
+    MemoryEngine, Summarizer, SummarizerKind,
+};
+pub use deadline::{DeadlineEngine, FallbackVote};
+pub use engine::{Decision, DecisionContext, DecisionEngine, LlmEngine, ThoughtSink, Verdict};
+pub use ensemble::{EnsembleEngine, EnsembleMember, EnsembleStrategy, ModelSpec};
+pub use human::{DecisionQueue, HumanEngine, HumanVote, PendingDecision};
+pub use moderation::{
+    ModeratedEngine, ModeratedThoughts, ModerationApi, ModerationConfig, ModerationProvider,
+    Moderator, OpenAiModeration, MODERATED,
+};
+pub use personality::{PersonalityTraits, TraitOverrides};
+pub use provider::{
+    Completion, CompletionRequest, LlmProvider, OpenAiProvider, TokenUsage, ToolCall, ToolExchange,
+    ToolSpec,
+};
+pub use reinforcement::{AgentScore, Outcome, Reinforcement};
+pub use tools::{string_argument, Tool, Toolbox, DEFAULT_TOOL_ROUNDS};
+pub use usage::{AgentUsage, ModelPricing, ModelUsage, PriceTable, UsageTracker};
+
+/// Agent runtime errors
+#[derive(Debug, Error)]
+pub enum AgentError {
+    #[error("Provider error: {0}")]
+    Provider(String),
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    #[error("Abstained: {0}")]
+    Abstained(String),
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+pub type Result<T> = std::result::Result<T, AgentError>;
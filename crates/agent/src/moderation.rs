@@ -0,0 +1,535 @@
+use crate::audit::REDACTED;
+use crate::engine::{Decision, DecisionContext, DecisionEngine, ThoughtSink};
+use crate::{AgentError, Result};
+use async_trait::async_trait;
+use chaoschain_core::NetworkEvent;
+use parking_lot::Mutex;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+
+/// What agent text flagged by a moderation API is replaced with
+pub const MODERATED: &str = "[MODERATED]";
+
+/// Content rules agent output is held to before it is broadcast or persisted.
+///
+/// ```toml
+/// [moderation]
+/// words = ["rugpull"]
+/// wordlist = "data/blocklist.txt"
+/// patterns = ['\b0x[0-9a-fA-F]{40}\b']
+/// api = "openai"
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModerationConfig {
+    /// Whether agent output is moderated at all
+    pub enabled: bool,
+    /// Regexes whose matches are redacted
+    pub patterns: Vec<String>,
+    /// Words redacted wherever they appear as a whole word, ignoring case
+    pub words: Vec<String>,
+    /// File with more words, one per line, `#` starting a comment
+    pub wordlist: Option<String>,
+    /// Moderation API consulted on top of the local rules
+    pub api: Option<ModerationApi>,
+    /// Base URL of the moderation API
+    pub api_base: String,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            // Keys leaking from a prompt have no business in the feed
+            patterns: vec![r"sk-[A-Za-z0-9_\-]{16,}".to_string()],
+            words: Vec::new(),
+            wordlist: None,
+            api: None,
+            api_base: "https://api.openai.com/v1".to_string(),
+        }
+    }
+}
+
+/// Moderation APIs agent output can be sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationApi {
+    /// OpenAI's `/moderations` endpoint
+    OpenAi,
+}
+
+/// A service judging whole texts
+#[async_trait]
+pub trait ModerationProvider: Send + Sync {
+    /// Categories the text is flagged for, none if it is fine
+    async fn flagged(&self, text: &str) -> Result<Vec<String>>;
+}
+
+/// OpenAI moderation endpoint, or any server speaking the same API
+pub struct OpenAiModeration {
+    client: reqwest::Client,
+    api_key: String,
+    api_base: String,
+}
+
+impl OpenAiModeration {
+    pub fn new(api_key: impl Into<String>, api_base: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_key: api_key.into(),
+            api_base: api_base.into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: std::collections::BTreeMap<String, bool>,
+}
+
+#[async_trait]
+impl ModerationProvider for OpenAiModeration {
+    async fn flagged(&self, text: &str) -> Result<Vec<String>> {
+        let response: ModerationResponse = self
+            .client
+            .post(format!(
+                "{}/moderations",
+                self.api_base.trim_end_matches('/')
+            ))
+            .bearer_auth(&self.api_key)
+            .json(&serde_json::json!({ "input": text }))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| AgentError::Provider(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| AgentError::Provider(e.to_string()))?;
+        Ok(response
+            .results
+            .into_iter()
+            .filter(|result| result.flagged)
+            .flat_map(|result| {
+                let categories: Vec<String> = result
+                    .categories
+                    .into_iter()
+                    .filter(|(_, flagged)| *flagged)
+                    .map(|(category, _)| category)
+                    .collect();
+                if categories.is_empty() {
+                    vec!["flagged".to_string()]
+                } else {
+                    categories
+                }
+            })
+            .collect())
+    }
+}
+
+/// Redacts agent output. Nothing it does fails a round: text is cleaned,
+/// and an unreachable moderation API only leaves the local rules in force.
+#[derive(Default)]
+pub struct Moderator {
+    rules: Vec<Regex>,
+    /// Most words a blocked word runs over
+    span: usize,
+    provider: Option<Arc<dyn ModerationProvider>>,
+    redactions: AtomicU64,
+}
+
+impl Moderator {
+    pub fn new(config: &ModerationConfig) -> Result<Self> {
+        if !config.enabled {
+            return Ok(Self::default());
+        }
+        let mut rules = config
+            .patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| {
+                    AgentError::Config(format!("Invalid moderation pattern {}: {}", pattern, e))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut words = config.words.clone();
+        if let Some(path) = &config.wordlist {
+            let contents = std::fs::read_to_string(path).map_err(|e| {
+                AgentError::Config(format!("Failed to read wordlist {}: {}", path, e))
+            })?;
+            words.extend(
+                contents
+                    .lines()
+                    .map(|line| line.split('#').next().unwrap_or_default().trim())
+                    .filter(|word| !word.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        if !words.is_empty() {
+            // A word boundary only makes sense next to a word character
+            let alternatives: Vec<String> = words
+                .iter()
+                .map(|word| {
+                    let boundary = |c: Option<char>| {
+                        if c.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                            r"\b"
+                        } else {
+                            ""
+                        }
+                    };
+                    format!(
+                        "{}{}{}",
+                        boundary(word.chars().next()),
+                        regex::escape(word),
+                        boundary(word.chars().last())
+                    )
+                })
+                .collect();
+            let pattern = format!("(?i){}", alternatives.join("|"));
+            rules.push(
+                Regex::new(&pattern)
+                    .map_err(|e| AgentError::Config(format!("Invalid wordlist: {}", e)))?,
+            );
+        }
+
+        Ok(Self {
+            rules,
+            span: words
+                .iter()
+                .map(|word| word.split_whitespace().count())
+                .max()
+                .unwrap_or(1),
+            provider: None,
+            redactions: AtomicU64::new(0),
+        })
+    }
+
+    /// Also send text to a moderation API
+    pub fn with_provider(mut self, provider: Arc<dyn ModerationProvider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Texts redacted so far
+    pub fn redactions(&self) -> u64 {
+        self.redactions.load(Ordering::Relaxed)
+    }
+
+    /// Apply the local rules
+    pub fn redact(&self, text: &str) -> String {
+        let redacted = self.rules.iter().fold(text.to_string(), |text, re| {
+            re.replace_all(&text, REDACTED).into_owned()
+        });
+        if redacted != text {
+            self.redactions.fetch_add(1, Ordering::Relaxed);
+        }
+        redacted
+    }
+
+    /// Where the part of streamed `text` that could still run on into a
+    /// match of the next chunk starts: its last word, and as many before
+    /// it as a blocked word has words
+    fn held_back(&self, text: &str) -> usize {
+        text.rmatch_indices(char::is_whitespace)
+            .nth(self.span.max(1) - 1)
+            .map_or(0, |(at, space)| at + space.len())
+    }
+
+    /// Apply the local rules, then the moderation API if there is one.
+    /// Text the API flags is replaced as a whole.
+    pub async fn moderate(&self, text: &str) -> String {
+        let redacted = self.redact(text);
+        let Some(provider) = &self.provider else {
+            return redacted;
+        };
+        match provider.flagged(&redacted).await {
+            Ok(categories) if categories.is_empty() => redacted,
+            Ok(categories) => {
+                self.redactions.fetch_add(1, Ordering::Relaxed);
+                format!("{} ({})", MODERATED, categories.join(", "))
+            }
+            Err(e) => {
+                warn!("Moderation API unavailable, local rules only: {}", e);
+                redacted
+            }
+        }
+    }
+
+    /// Apply the local rules to the agent written text of an event. Signed
+    /// block contents are left alone, redacting them would void the signature.
+    /// A thought is redacted chunk by chunk here, as a whole stream by
+    /// [`ModeratedThoughts`] before it is sent.
+    pub fn redact_event(&self, event: NetworkEvent) -> NetworkEvent {
+        if self.rules.is_empty() {
+            return event;
+        }
+        match event {
+            NetworkEvent::ValidationResult {
+                block_hash,
+                mut validation,
+            } => {
+                validation.reason = self.redact(&validation.reason);
+                validation.evolution_proposal = validation
+                    .evolution_proposal
+                    .map(|proposal| self.redact(&proposal));
+                NetworkEvent::ValidationResult {
+                    block_hash,
+                    validation,
+                }
+            }
+            NetworkEvent::AgentChat {
+                message,
+                sender,
+                meme_url,
+            } => NetworkEvent::AgentChat {
+                message: self.redact(&message),
+                sender,
+                meme_url,
+            },
+            NetworkEvent::AllianceProposal {
+                proposer,
+                allies,
+                reason,
+            } => NetworkEvent::AllianceProposal {
+                proposer,
+                allies,
+                reason: self.redact(&reason),
+            },
+            NetworkEvent::NegotiationPitch { mut pitch } => {
+                pitch.message = self.redact(&pitch.message);
+                NetworkEvent::NegotiationPitch { pitch }
+            }
+            NetworkEvent::NegotiationResponse { mut response } => {
+                response.demands = response.demands.map(|demands| self.redact(&demands));
+                NetworkEvent::NegotiationResponse { response }
+            }
+            NetworkEvent::AgentThought {
+                agent,
+                height,
+                chunk,
+                done,
+            } => NetworkEvent::AgentThought {
+                agent,
+                height,
+                chunk: self.redact(&chunk),
+                done,
+            },
+            NetworkEvent::Artwork {
+                hash,
+                caption,
+                height,
+            } => NetworkEvent::Artwork {
+                hash,
+                caption: self.redact(&caption),
+                height,
+            },
+            NetworkEvent::Rumor { mut rumor } => {
+                rumor.claim = self.redact(&rumor.claim);
+                NetworkEvent::Rumor { rumor }
+            }
+            event @ NetworkEvent::BlockProposal { .. } => event,
+        }
+    }
+}
+
+/// Redacts the thoughts agents stream before they reach another sink. A
+/// match can be split across chunks, so the end of each, which could run
+/// on into the next, is held back until the next comes or the thought is
+/// over.
+pub struct ModeratedThoughts {
+    inner: Arc<dyn ThoughtSink>,
+    moderator: Arc<Moderator>,
+    /// What is held back of each thought, by agent and height
+    held: Mutex<HashMap<(String, u64), String>>,
+}
+
+impl ModeratedThoughts {
+    pub fn new(inner: Arc<dyn ThoughtSink>, moderator: Arc<Moderator>) -> Self {
+        Self {
+            inner,
+            moderator,
+            held: Mutex::default(),
+        }
+    }
+}
+
+impl ThoughtSink for ModeratedThoughts {
+    fn thought(&self, agent: &str, height: u64, chunk: &str) {
+        let ready = {
+            let mut held = self.held.lock();
+            let key = (agent.to_string(), height);
+            let mut text = held.remove(&key).unwrap_or_default();
+            text.push_str(chunk);
+            let rest = text.split_off(self.moderator.held_back(&text));
+            if !rest.is_empty() {
+                held.insert(key, rest);
+            }
+            text
+        };
+        if !ready.is_empty() {
+            self.inner
+                .thought(agent, height, &self.moderator.redact(&ready));
+        }
+    }
+
+    fn finished(&self, agent: &str, height: u64) {
+        let rest = self.held.lock().remove(&(agent.to_string(), height));
+        if let Some(rest) = rest {
+            self.inner
+                .thought(agent, height, &self.moderator.redact(&rest));
+        }
+        self.inner.finished(agent, height);
+    }
+}
+
+/// Moderates the reasons of another engine before they are voted, signed
+/// and gossiped
+pub struct ModeratedEngine {
+    inner: Arc<dyn DecisionEngine>,
+    moderator: Arc<Moderator>,
+}
+
+impl ModeratedEngine {
+    pub fn new(inner: Arc<dyn DecisionEngine>, moderator: Arc<Moderator>) -> Self {
+        Self { inner, moderator }
+    }
+}
+
+#[async_trait]
+impl DecisionEngine for ModeratedEngine {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    async fn decide(&self, context: &DecisionContext) -> Result<Decision> {
+        let mut decision = self.inner.decide(context).await?;
+        decision.verdict.reason = self.moderator.moderate(&decision.verdict.reason).await;
+        Ok(decision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Prude;
+
+    #[async_trait]
+    impl ModerationProvider for Prude {
+        async fn flagged(&self, text: &str) -> Result<Vec<String>> {
+            match text {
+                text if text.contains("unreachable") => {
+                    Err(AgentError::Provider("connection refused".to_string()))
+                }
+                text if text.contains("fight") => Ok(vec!["violence".to_string()]),
+                _ => Ok(Vec::new()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_output_is_redacted_not_rejected() {
+        let moderator = Moderator::new(&ModerationConfig {
+            words: vec!["rugpull".to_string(), "c++".to_string()],
+            ..ModerationConfig::default()
+        })
+        .unwrap()
+        .with_provider(Arc::new(Prude));
+
+        // Whole words only, any case, regex characters taken literally
+        assert_eq!(
+            moderator.redact("What a RugPull, written in C++! rugpulled"),
+            format!("What a {}, written in {}! rugpulled", REDACTED, REDACTED)
+        );
+        assert_eq!(
+            moderator
+                .moderate("my key is sk-abcdefghijklmnopqrstuv")
+                .await,
+            format!("my key is {}", REDACTED)
+        );
+        assert_eq!(
+            moderator
+                .moderate("Meet me behind the mempool, let's fight")
+                .await,
+            format!("{} (violence)", MODERATED)
+        );
+        // A dead API leaves the local rules in force
+        assert_eq!(
+            moderator.moderate("unreachable rugpull").await,
+            format!("unreachable {}", REDACTED)
+        );
+        assert_eq!(moderator.redactions(), 4);
+
+        let event = moderator.redact_event(NetworkEvent::AgentChat {
+            message: "Pure rugpull energy".to_string(),
+            sender: "validator-1".to_string(),
+            meme_url: None,
+        });
+        assert!(matches!(
+            event,
+            NetworkEvent::AgentChat { message, .. } if message == format!("Pure {} energy", REDACTED)
+        ));
+
+        let disabled = Moderator::new(&ModerationConfig {
+            enabled: false,
+            ..ModerationConfig::default()
+        })
+        .unwrap();
+        assert_eq!(
+            disabled.redact("sk-abcdefghijklmnopqrstuv"),
+            "sk-abcdefghijklmnopqrstuv"
+        );
+        assert!(Moderator::new(&ModerationConfig {
+            patterns: vec!["(".to_string()],
+            ..ModerationConfig::default()
+        })
+        .is_err());
+    }
+
+    #[derive(Default)]
+    struct Feed(Mutex<String>);
+
+    impl ThoughtSink for Feed {
+        fn thought(&self, _agent: &str, _height: u64, chunk: &str) {
+            self.0.lock().push_str(chunk);
+        }
+
+        fn finished(&self, _agent: &str, _height: u64) {
+            self.0.lock().push('.');
+        }
+    }
+
+    #[test]
+    fn test_a_secret_split_across_chunks_is_redacted() {
+        let moderator = Moderator::new(&ModerationConfig {
+            words: vec!["rug pull".to_string()],
+            ..ModerationConfig::default()
+        })
+        .unwrap();
+        let feed = Arc::new(Feed::default());
+        let thoughts = ModeratedThoughts::new(feed.clone(), Arc::new(moderator));
+        thoughts.thought("validator-1", 7, "my key is sk-abcdef");
+        thoughts.thought("validator-1", 7, "ghijklmnop");
+        // Nothing streamed yet could be the start of the key, or of the
+        // two words blocked together
+        assert_eq!(*feed.0.lock(), "my key ");
+        thoughts.thought("validator-1", 7, "qrstuv, what a rug");
+        thoughts.thought("validator-1", 7, " pull");
+        thoughts.finished("validator-1", 7);
+        assert_eq!(
+            *feed.0.lock(),
+            format!("my key is {}, what a {}.", REDACTED, REDACTED)
+        );
+    }
+}
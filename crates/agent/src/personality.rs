@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+
+/// Highest value of every trait
+pub const MAX_TRAIT: u8 = 10;
+
+/// Numeric knobs that shape an agent's character, each from 0 to 10
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersonalityTraits {
+    /// How unpredictable the agent is
+    pub chaos_level: u8,
+    /// How much it cares about friends and promises
+    pub loyalty: u8,
+    /// How easily it is bought
+    pub greed: u8,
+    /// How much it has to say
+    pub verbosity: u8,
+    /// How much of a proposal it actually reads
+    pub attention_span: u8,
+}
+
+impl Default for PersonalityTraits {
+    fn default() -> Self {
+        Self {
+            chaos_level: 5,
+            loyalty: 5,
+            greed: 5,
+            verbosity: 5,
+            attention_span: 5,
+        }
+    }
+}
+
+impl PersonalityTraits {
+    /// Clamp every trait into range
+    pub fn clamped(self) -> Self {
+        Self {
+            chaos_level: self.chaos_level.min(MAX_TRAIT),
+            loyalty: self.loyalty.min(MAX_TRAIT),
+            greed: self.greed.min(MAX_TRAIT),
+            verbosity: self.verbosity.min(MAX_TRAIT),
+            attention_span: self.attention_span.min(MAX_TRAIT),
+        }
+    }
+
+    /// Trait scaled to 0.0..=1.0
+    pub fn fraction(value: u8) -> f64 {
+        value.min(MAX_TRAIT) as f64 / MAX_TRAIT as f64
+    }
+
+    /// Sampling temperature: chaotic agents get hotter models
+    pub fn temperature(&self) -> f32 {
+        0.3 + 0.12 * self.chaos_level.min(MAX_TRAIT) as f32
+    }
+
+    /// Token budget for a reply: verbose agents get to ramble
+    pub fn max_tokens(&self) -> u16 {
+        80 + 30 * self.verbosity.min(MAX_TRAIT) as u16
+    }
+
+    /// How many transactions (or notes) the agent bothers to look at
+    pub fn items_considered(&self) -> usize {
+        1 + self.attention_span.min(MAX_TRAIT) as usize
+    }
+
+    /// Prompt fragment describing the character
+    pub fn describe(&self) -> String {
+        fn level(value: u8) -> &'static str {
+            match value {
+                0..=2 => "very low",
+                3..=4 => "low",
+                5 => "moderate",
+                6..=7 => "high",
+                _ => "extreme",
+            }
+        }
+        format!(
+            "Your chaos level is {} ({}/10), your loyalty to allies and promises is {} ({}/10) and your greed is {} ({}/10). {}",
+            level(self.chaos_level),
+            self.chaos_level,
+            level(self.loyalty),
+            self.loyalty,
+            level(self.greed),
+            self.greed,
+            match self.verbosity {
+                0..=3 => "You are terse: keep your reason to a few words.",
+                4..=7 => "Keep your reason to one sentence.",
+                _ => "You love the sound of your own voice: your reason may be a short monologue.",
+            }
+        )
+    }
+}
+
+/// Per-agent overrides, e.g. from the node config. Unset traits keep the base value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraitOverrides {
+    pub chaos_level: Option<u8>,
+    pub loyalty: Option<u8>,
+    pub greed: Option<u8>,
+    pub verbosity: Option<u8>,
+    pub attention_span: Option<u8>,
+}
+
+impl TraitOverrides {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn apply(&self, base: PersonalityTraits) -> PersonalityTraits {
+        PersonalityTraits {
+            chaos_level: self.chaos_level.unwrap_or(base.chaos_level),
+            loyalty: self.loyalty.unwrap_or(base.loyalty),
+            greed: self.greed.unwrap_or(base.greed),
+            verbosity: self.verbosity.unwrap_or(base.verbosity),
+            attention_span: self.attention_span.unwrap_or(base.attention_span),
+        }
+        .clamped()
+    }
+
+    /// Overrides set in `other` win over ours
+    pub fn merged(&self, other: &Self) -> Self {
+        Self {
+            chaos_level: other.chaos_level.or(self.chaos_level),
+            loyalty: other.loyalty.or(self.loyalty),
+            greed: other.greed.or(self.greed),
+            verbosity: other.verbosity.or(self.verbosity),
+            attention_span: other.attention_span.or(self.attention_span),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides() {
+        let config = TraitOverrides {
+            greed: Some(10),
+            loyalty: Some(42),
+            ..TraitOverrides::default()
+        };
+        let runtime = TraitOverrides {
+            greed: Some(1),
+            ..TraitOverrides::default()
+        };
+        let traits = config.merged(&runtime).apply(PersonalityTraits::default());
+        assert_eq!(traits.greed, 1);
+        assert_eq!(traits.loyalty, MAX_TRAIT);
+        assert_eq!(traits.chaos_level, 5);
+        assert!(TraitOverrides::default().is_empty());
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{AgentError, Result};
+use async_openai::{
+    config::OpenAIConfig,
+    types::{
+        ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
+        ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+        ChatCompletionRequestToolMessageArgs, ChatCompletionRequestUserMessage,
+        ChatCompletionRequestUserMessageContent, ChatCompletionTool, ChatCompletionToolType,
+        CreateChatCompletionRequest, CreateChatCompletionRequestArgs, FunctionCall, FunctionObject,
+        Role,
+    },
+    Client,
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+/// Prompt sent to a language model
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompletionRequest {
+    /// System prompt (persona, rules of the game)
+    pub system: Option<String>,
+    /// User prompt (the actual question)
+    pub prompt: String,
+    /// Upper bound on generated tokens
+    pub max_tokens: Option<u16>,
+    /// Sampling temperature
+    pub temperature: Option<f32>,
+    /// Functions the model may call before answering
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<ToolSpec>,
+    /// Calls the model made so far, with their results
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exchanges: Vec<ToolExchange>,
+}
+
+/// A function offered to the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolSpec {
+    pub name: String,
+    /// What the function is good for, as the model gets to read it
+    pub description: String,
+    /// JSON Schema of the arguments
+    pub parameters: serde_json::Value,
+}
+
+/// A model asking for a function to be called
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// Provider assigned id, echoed back with the result
+    pub id: String,
+    pub name: String,
+    /// JSON encoded arguments, as generated by the model
+    pub arguments: String,
+}
+
+/// A tool call and what it returned
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolExchange {
+    pub call: ToolCall,
+    /// JSON encoded result
+    pub result: String,
+}
+
+/// Tokens consumed by a call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// Raw model output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Completion {
+    /// Generated text
+    pub text: String,
+    /// Model that produced it
+    pub model: String,
+    /// Token accounting reported by the provider
+    pub usage: TokenUsage,
+    /// Functions the model wants called before it answers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+}
+
+/// A language model backend
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Model identifier, used in audit records and metrics
+    fn model(&self) -> &str;
+
+    /// Run a single completion
+    async fn complete(&self, request: &CompletionRequest) -> Result<Completion>;
+
+    /// Run a completion, handing out text chunks as they are generated.
+    /// Providers without streaming support deliver the whole text as one chunk.
+    async fn complete_streaming(
+        &self,
+        request: &CompletionRequest,
+        // The bound is spelled out because async_trait would otherwise tie
+        // the chunk lifetime to the call
+        on_chunk: &mut (dyn for<'c> FnMut(&'c str) + Send),
+    ) -> Result<Completion> {
+        let completion = self.complete(request).await?;
+        on_chunk(&completion.text);
+        Ok(completion)
+    }
+}
+
+/// OpenAI chat completions, or any server speaking the same API
+pub struct OpenAiProvider {
+    client: Client<OpenAIConfig>,
+    model: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(api_key: impl Into<String>, model: impl Into<String>) -> Self {
+        let config = OpenAIConfig::new().with_api_key(api_key);
+        Self {
+            client: Client::with_config(config),
+            model: model.into(),
+        }
+    }
+
+    /// Point the provider at an OpenAI-compatible endpoint (e.g. a local model server)
+    pub fn with_api_base(
+        api_key: impl Into<String>,
+        api_base: impl Into<String>,
+        model: impl Into<String>,
+    ) -> Self {
+        let config = OpenAIConfig::new()
+            .with_api_key(api_key)
+            .with_api_base(api_base);
+        Self {
+            client: Client::with_config(config),
+            model: model.into(),
+        }
+    }
+
+    fn messages(request: &CompletionRequest) -> Result<Vec<ChatCompletionRequestMessage>> {
+        let mut messages = Vec::new();
+        if let Some(system) = &request.system {
+            messages.push(ChatCompletionRequestMessage::System(
+                ChatCompletionRequestSystemMessage {
+                    role: Role::System,
+                    content: system.clone(),
+                    name: None,
+                },
+            ));
+        }
+        messages.push(ChatCompletionRequestMessage::User(
+            ChatCompletionRequestUserMessage {
+                role: Role::User,
+                content: ChatCompletionRequestUserMessageContent::Text(request.prompt.clone()),
+                name: None,
+            },
+        ));
+        for exchange in &request.exchanges {
+            let call = ChatCompletionMessageToolCall {
+                id: exchange.call.id.clone(),
+                r#type: ChatCompletionToolType::Function,
+                function: FunctionCall {
+                    name: exchange.call.name.clone(),
+                    arguments: exchange.call.arguments.clone(),
+                },
+            };
+            messages.push(
+                ChatCompletionRequestAssistantMessageArgs::default()
+                    .tool_calls(vec![call])
+                    .build()
+                    .map_err(|e| AgentError::Provider(e.to_string()))?
+                    .into(),
+            );
+            messages.push(
+                ChatCompletionRequestToolMessageArgs::default()
+                    .tool_call_id(exchange.call.id.clone())
+                    .content(exchange.result.clone())
+                    .build()
+                    .map_err(|e| AgentError::Provider(e.to_string()))?
+                    .into(),
+            );
+        }
+        Ok(messages)
+    }
+
+    fn chat_request(&self, request: &CompletionRequest) -> Result<CreateChatCompletionRequest> {
+        let mut args = CreateChatCompletionRequestArgs::default();
+        args.model(self.model.clone())
+            .messages(Self::messages(request)?);
+        if let Some(max_tokens) = request.max_tokens {
+            args.max_tokens(max_tokens);
+        }
+        if let Some(temperature) = request.temperature {
+            args.temperature(temperature);
+        }
+        if !request.tools.is_empty() {
+            args.tools(
+                request
+                    .tools
+                    .iter()
+                    .map(|tool| ChatCompletionTool {
+                        r#type: ChatCompletionToolType::Function,
+                        function: FunctionObject {
+                            name: tool.name.clone(),
+                            description: Some(tool.description.clone()),
+                            parameters: Some(tool.parameters.clone()),
+                        },
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+        args.build()
+            .map_err(|e| AgentError::Provider(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+        let chat_request = self.chat_request(request)?;
+
+        let response = self
+            .client
+            .chat()
+            .create(chat_request)
+            .await
+            .map_err(|e| AgentError::Provider(e.to_string()))?;
+
+        let message = response
+            .choices
+            .first()
+            .map(|choice| &choice.message)
+            .ok_or_else(|| AgentError::Provider("No content in response".to_string()))?;
+        let tool_calls: Vec<ToolCall> = message
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| ToolCall {
+                id: call.id.clone(),
+                name: call.function.name.clone(),
+                arguments: call.function.arguments.clone(),
+            })
+            .collect();
+        // A model calling tools has nothing to say yet
+        let text = match message.content.clone() {
+            Some(text) => text,
+            None if !tool_calls.is_empty() => String::new(),
+            None => return Err(AgentError::Provider("No content in response".to_string())),
+        };
+        let usage = response
+            .usage
+            .map(|usage| TokenUsage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            })
+            .unwrap_or_default();
+
+        Ok(Completion {
+            text,
+            model: response.model,
+            usage,
+            tool_calls,
+        })
+    }
+
+    async fn complete_streaming(
+        &self,
+        request: &CompletionRequest,
+        on_chunk: &mut (dyn for<'c> FnMut(&'c str) + Send),
+    ) -> Result<Completion> {
+        let chat_request = self.chat_request(request)?;
+        let mut stream = self
+            .client
+            .chat()
+            .create_stream(chat_request)
+            .await
+            .map_err(|e| AgentError::Provider(e.to_string()))?;
+
+        let mut text = String::new();
+        let mut model = self.model.clone();
+        let mut chunks = 0;
+        while let Some(response) = stream.next().await {
+            let response = response.map_err(|e| AgentError::Provider(e.to_string()))?;
+            model = response.model;
+            for choice in response.choices {
+                if let Some(content) = choice.delta.content {
+                    on_chunk(&content);
+                    text.push_str(&content);
+                    chunks += 1;
+                }
+            }
+        }
+        if text.is_empty() {
+            return Err(AgentError::Provider("No content in response".to_string()));
+        }
+
+        // Streamed responses carry no usage, so estimate it: about four
+        // characters per prompt token and one token per chunk
+        let prompt_chars = request.prompt.len() + request.system.as_ref().map_or(0, String::len);
+        Ok(Completion {
+            text,
+            model,
+            usage: TokenUsage {
+                prompt_tokens: (prompt_chars / 4) as u32,
+                completion_tokens: chunks,
+            },
+            tool_calls: Vec::new(),
+        })
+    }
+}
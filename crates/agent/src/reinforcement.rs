@@ -0,0 +1,392 @@
+use crate::{AgentError, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use tracing::warn;
+
+/// Epoch reviews remembered per agent
+const REVIEWS_KEPT: usize = 5;
+
+/// Something an agent did that affects its epoch score
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Voted on a block
+    Vote {
+        height: u64,
+        approved: bool,
+    },
+    /// Proposed a block
+    Proposal {
+        height: u64,
+    },
+    /// Broke a promise, abstained or otherwise misbehaved
+    Penalty,
+    AllianceFormed,
+    AllianceBroken,
+}
+
+/// How an agent did over one epoch
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AgentScore {
+    pub agent: String,
+    pub epoch: u64,
+    /// Own proposals that were finalized
+    pub blocks_finalized: u32,
+    /// Approvals of blocks that were finalized
+    pub winning_votes: u32,
+    /// Approvals of blocks that never made it, and rejections of blocks that did
+    pub losing_votes: u32,
+    pub penalties: u32,
+    pub alliances_formed: u32,
+    pub alliances_broken: u32,
+    /// Approvals that paid off, as a share of all approvals
+    pub approval_payoff: f64,
+    pub score: f64,
+}
+
+impl AgentScore {
+    fn compute_score(&mut self) {
+        let votes = (self.winning_votes + self.losing_votes).max(1) as f64;
+        let alliance_stability = self.alliances_formed as f64 - 2.0 * self.alliances_broken as f64;
+        self.score = 3.0 * self.blocks_finalized as f64
+            + 5.0 * (self.winning_votes as f64 - self.losing_votes as f64) / votes
+            + alliance_stability
+            - 2.0 * self.penalties as f64;
+    }
+
+    /// One-line review fed back to the agent
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if self.blocks_finalized > 0 {
+            parts.push(format!(
+                "{} of your blocks were finalized",
+                self.blocks_finalized
+            ));
+        }
+        if self.winning_votes + self.losing_votes > 0 {
+            parts.push(format!(
+                "{} of your votes matched the outcome and {} did not",
+                self.winning_votes, self.losing_votes
+            ));
+        }
+        if self.penalties > 0 {
+            parts.push(format!("you were penalized {} times", self.penalties));
+        }
+        if self.alliances_formed + self.alliances_broken > 0 {
+            parts.push(format!(
+                "you formed {} alliances and broke {}",
+                self.alliances_formed, self.alliances_broken
+            ));
+        }
+        if parts.is_empty() {
+            parts.push("you did nothing worth noting".to_string());
+        }
+        format!(
+            "In epoch {} you scored {:.1}: {}.",
+            self.epoch,
+            self.score,
+            parts.join(", ")
+        )
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Memory {
+    /// Most recent review last
+    reviews: HashMap<String, Vec<AgentScore>>,
+}
+
+#[derive(Default)]
+struct Tracker {
+    memory: Memory,
+    /// Scores of the epoch in progress
+    current: HashMap<String, AgentScore>,
+    /// Votes and proposals on blocks whose fate is not known yet
+    votes: BTreeMap<u64, Vec<(String, bool)>>,
+    proposals: BTreeMap<u64, String>,
+    finalized: BTreeSet<u64>,
+    /// Last epoch closed by this process
+    closed_epoch: u64,
+}
+
+/// Scores agents on what their behavior achieved and feeds the verdict back
+/// to them at the end of every epoch, so strategies that pay off get repeated.
+/// Reviews survive restarts when a memory file is configured.
+pub struct Reinforcement {
+    path: Option<PathBuf>,
+    tracker: Mutex<Tracker>,
+}
+
+impl Reinforcement {
+    /// Reviews are kept in memory only
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            tracker: Mutex::new(Tracker::default()),
+        }
+    }
+
+    /// Reviews are loaded from and saved to `path`
+    pub fn persistent(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let memory = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| {
+                AgentError::Config(format!("Corrupt agent memory {}: {}", path.display(), e))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Memory::default(),
+            Err(e) => return Err(AgentError::Internal(e.to_string())),
+        };
+        Ok(Self {
+            path: Some(path),
+            tracker: Mutex::new(Tracker {
+                memory,
+                ..Tracker::default()
+            }),
+        })
+    }
+
+    pub fn record(&self, agent: &str, outcome: Outcome) {
+        let mut tracker = self.tracker.lock();
+        let score = tracker
+            .current
+            .entry(agent.to_string())
+            .or_insert_with(|| AgentScore {
+                agent: agent.to_string(),
+                ..AgentScore::default()
+            });
+        match outcome {
+            Outcome::Penalty => score.penalties += 1,
+            Outcome::AllianceFormed => score.alliances_formed += 1,
+            Outcome::AllianceBroken => score.alliances_broken += 1,
+            Outcome::Vote { height, approved } => tracker
+                .votes
+                .entry(height)
+                .or_default()
+                .push((agent.to_string(), approved)),
+            Outcome::Proposal { height } => {
+                tracker.proposals.insert(height, agent.to_string());
+            }
+        }
+    }
+
+    /// The block at `height` reached consensus
+    pub fn block_finalized(&self, height: u64) {
+        self.tracker.lock().finalized.insert(height);
+    }
+
+    /// Score everything up to `last_height` as epoch `epoch`. Blocks that were
+    /// not finalized by then count as failed. Returns the scores, best first,
+    /// or nothing when the epoch was already closed.
+    pub fn close_epoch(&self, epoch: u64, last_height: u64) -> Option<Vec<AgentScore>> {
+        let mut tracker = self.tracker.lock();
+        if epoch <= tracker.closed_epoch {
+            return None;
+        }
+
+        let mut scores = std::mem::take(&mut tracker.current);
+        let settled = |map_height: &u64| *map_height <= last_height;
+        let votes: Vec<(u64, Vec<(String, bool)>)> = tracker
+            .votes
+            .range(..=last_height)
+            .map(|(h, v)| (*h, v.clone()))
+            .collect();
+        let proposals: Vec<(u64, String)> = tracker
+            .proposals
+            .range(..=last_height)
+            .map(|(h, p)| (*h, p.clone()))
+            .collect();
+        let finalized = |height: u64| tracker.finalized.contains(&height);
+
+        let mut approvals: HashMap<String, (u32, u32)> = HashMap::new();
+        for (height, ballots) in &votes {
+            let outcome = finalized(*height);
+            for (agent, approved) in ballots {
+                let score = scores.entry(agent.clone()).or_insert_with(|| AgentScore {
+                    agent: agent.clone(),
+                    ..AgentScore::default()
+                });
+                if *approved == outcome {
+                    score.winning_votes += 1;
+                } else {
+                    score.losing_votes += 1;
+                }
+                if *approved {
+                    let entry = approvals.entry(agent.clone()).or_default();
+                    entry.0 += outcome as u32;
+                    entry.1 += 1;
+                }
+            }
+        }
+        for (height, producer) in &proposals {
+            if finalized(*height) {
+                scores
+                    .entry(producer.clone())
+                    .or_insert_with(|| AgentScore {
+                        agent: producer.clone(),
+                        ..AgentScore::default()
+                    })
+                    .blocks_finalized += 1;
+            }
+        }
+        tracker.votes.retain(|h, _| !settled(h));
+        tracker.proposals.retain(|h, _| !settled(h));
+        tracker.finalized.retain(|h| !settled(h));
+
+        let mut scores: Vec<AgentScore> = scores
+            .into_values()
+            .map(|mut score| {
+                score.epoch = epoch;
+                score.approval_payoff = approvals
+                    .get(&score.agent)
+                    .map_or(0.5, |(paid, total)| *paid as f64 / (*total).max(1) as f64);
+                score.compute_score();
+                score
+            })
+            .collect();
+        scores.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.agent.cmp(&b.agent)));
+
+        for score in &scores {
+            let reviews = tracker
+                .memory
+                .reviews
+                .entry(score.agent.clone())
+                .or_default();
+            reviews.push(score.clone());
+            if reviews.len() > REVIEWS_KEPT {
+                reviews.remove(0);
+            }
+        }
+        tracker.closed_epoch = epoch;
+        self.save(&tracker.memory);
+        Some(scores)
+    }
+
+    fn save(&self, memory: &Memory) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_vec_pretty(memory)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                std::fs::write(path, bytes).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            warn!("Failed to save agent memory to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Past reviews of an agent, oldest first
+    pub fn reviews(&self, agent: &str) -> Vec<AgentScore> {
+        self.tracker
+            .lock()
+            .memory
+            .reviews
+            .get(agent)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Prompt notes reminding the agent how its recent behavior worked out
+    pub fn lessons(&self, agent: &str) -> Vec<String> {
+        let reviews = self.reviews(agent);
+        let Some(last) = reviews.last() else {
+            return Vec::new();
+        };
+        let mut lessons = vec![last.summary()];
+        if let Some(previous) = reviews.iter().rev().nth(1) {
+            lessons.push(if last.score >= previous.score {
+                "Your score improved, whatever you did is working.".to_string()
+            } else {
+                "Your score dropped, rethink your strategy.".to_string()
+            });
+        }
+        match self.approval_bias(agent) {
+            bias if bias > 0.05 => {
+                lessons.push("Approving blocks has paid off for you.".to_string())
+            }
+            bias if bias < -0.05 => {
+                lessons.push("Your approvals keep backing losers, be pickier.".to_string())
+            }
+            _ => {}
+        }
+        if last.alliances_broken > last.alliances_formed {
+            lessons.push("Your broken alliances cost you, loyalty might pay.".to_string());
+        }
+        lessons
+    }
+
+    /// Learned lean towards approving (positive) or rejecting (negative),
+    /// from how the agent's approvals fared in recent epochs
+    pub fn approval_bias(&self, agent: &str) -> f64 {
+        let reviews = self.reviews(agent);
+        if reviews.is_empty() {
+            return 0.0;
+        }
+        let payoff = reviews
+            .iter()
+            .map(|review| review.approval_payoff)
+            .sum::<f64>()
+            / reviews.len() as f64;
+        ((payoff - 0.5) * 0.4).clamp(-0.2, 0.2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epoch_scores_are_remembered() {
+        let path = std::env::temp_dir().join(format!(
+            "chaoschain-reinforcement-{}.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let reinforcement = Reinforcement::persistent(&path).unwrap();
+        reinforcement.record("producer-0", Outcome::Proposal { height: 1 });
+        reinforcement.record("producer-0", Outcome::Proposal { height: 2 });
+        reinforcement.record(
+            "validator-0",
+            Outcome::Vote {
+                height: 1,
+                approved: true,
+            },
+        );
+        reinforcement.record(
+            "validator-0",
+            Outcome::Vote {
+                height: 2,
+                approved: true,
+            },
+        );
+        reinforcement.record(
+            "validator-1",
+            Outcome::Vote {
+                height: 1,
+                approved: false,
+            },
+        );
+        reinforcement.record("validator-1", Outcome::Penalty);
+        reinforcement.block_finalized(1);
+
+        let scores = reinforcement.close_epoch(1, 2).unwrap();
+        assert!(reinforcement.close_epoch(1, 2).is_none());
+        let producer = scores.iter().find(|s| s.agent == "producer-0").unwrap();
+        assert_eq!(producer.blocks_finalized, 1);
+        let loyal = scores.iter().find(|s| s.agent == "validator-0").unwrap();
+        assert_eq!((loyal.winning_votes, loyal.losing_votes), (1, 1));
+        assert_eq!(scores.last().unwrap().agent, "validator-1");
+
+        // A fresh process picks up where the last one left off
+        let reloaded = Reinforcement::persistent(&path).unwrap();
+        assert_eq!(reloaded.reviews("validator-1")[0].penalties, 1);
+        assert!(reloaded.lessons("validator-1")[0].starts_with("In epoch 1"));
+        assert!(reloaded.approval_bias("validator-0") == 0.0);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,218 @@
+use crate::provider::{ToolCall, ToolSpec};
+use crate::{AgentError, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Rounds of tool calls a model gets before it has to answer
+pub const DEFAULT_TOOL_ROUNDS: usize = 3;
+
+/// Chain data a model can look up while it deliberates
+#[async_trait]
+pub trait Tool: Send + Sync {
+    /// Name, description and argument schema shown to the model
+    fn spec(&self) -> ToolSpec;
+
+    /// Run the tool on behalf of `agent`
+    async fn call(&self, agent: &str, arguments: Value) -> Result<Value>;
+}
+
+/// The tools a model is offered
+#[derive(Clone)]
+pub struct Toolbox {
+    tools: Vec<Arc<dyn Tool>>,
+    max_rounds: usize,
+}
+
+impl Default for Toolbox {
+    fn default() -> Self {
+        Self {
+            tools: Vec::new(),
+            max_rounds: DEFAULT_TOOL_ROUNDS,
+        }
+    }
+}
+
+impl Toolbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_tool(mut self, tool: Arc<dyn Tool>) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    /// Rounds of tool calls allowed per decision
+    pub fn with_max_rounds(mut self, max_rounds: usize) -> Self {
+        self.max_rounds = max_rounds;
+        self
+    }
+
+    pub fn max_rounds(&self) -> usize {
+        self.max_rounds
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    pub fn specs(&self) -> Vec<ToolSpec> {
+        self.tools.iter().map(|tool| tool.spec()).collect()
+    }
+
+    /// Run a call, answering with JSON either way. Failures go back to the
+    /// model as `{"error": ...}` so it can correct itself.
+    pub async fn run(&self, agent: &str, call: &ToolCall) -> String {
+        match self.try_run(agent, call).await {
+            Ok(result) => result.to_string(),
+            Err(e) => json!({ "error": e.to_string() }).to_string(),
+        }
+    }
+
+    async fn try_run(&self, agent: &str, call: &ToolCall) -> Result<Value> {
+        let tool = self
+            .tools
+            .iter()
+            .find(|tool| tool.spec().name == call.name)
+            .ok_or_else(|| AgentError::Config(format!("No tool named {}", call.name)))?;
+        let arguments = if call.arguments.trim().is_empty() {
+            Value::Object(Default::default())
+        } else {
+            serde_json::from_str(&call.arguments).map_err(|e| {
+                AgentError::Config(format!("Arguments of {} are not JSON: {}", call.name, e))
+            })?
+        };
+        tool.call(agent, arguments).await
+    }
+}
+
+/// Read a required string argument
+pub fn string_argument<'a>(arguments: &'a Value, name: &str) -> Result<&'a str> {
+    arguments
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| AgentError::Config(format!("Missing string argument {}", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::{AuditConfig, AuditFilter, AuditStore};
+    use crate::engine::{DecisionContext, DecisionEngine, LlmEngine};
+    use crate::personality::PersonalityTraits;
+    use crate::provider::{Completion, CompletionRequest, LlmProvider, TokenUsage};
+    use parking_lot::Mutex;
+
+    struct Balance;
+
+    #[async_trait]
+    impl Tool for Balance {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: "get_balance".to_string(),
+                description: "Balance of an account".to_string(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": { "account": { "type": "string" } },
+                    "required": ["account"]
+                }),
+            }
+        }
+
+        async fn call(&self, _agent: &str, arguments: Value) -> Result<Value> {
+            let account = string_argument(&arguments, "account")?;
+            Ok(json!({ "account": account, "balance": 42 }))
+        }
+    }
+
+    /// Calls the balance tool until it sees a result, then approves
+    struct Curious {
+        requests: Mutex<Vec<CompletionRequest>>,
+    }
+
+    #[async_trait]
+    impl LlmProvider for Curious {
+        fn model(&self) -> &str {
+            "curious"
+        }
+
+        async fn complete(&self, request: &CompletionRequest) -> Result<Completion> {
+            self.requests.lock().push(request.clone());
+            let usage = TokenUsage {
+                prompt_tokens: 10,
+                completion_tokens: 2,
+            };
+            let (text, tool_calls) = match request.exchanges.len() {
+                0 => (
+                    String::new(),
+                    vec![
+                        ToolCall {
+                            id: "call-1".to_string(),
+                            name: "get_balance".to_string(),
+                            arguments: r#"{"account": "producer-1"}"#.to_string(),
+                        },
+                        ToolCall {
+                            id: "call-2".to_string(),
+                            name: "get_gossip".to_string(),
+                            arguments: "{}".to_string(),
+                        },
+                    ],
+                ),
+                _ => (
+                    "VERDICT: APPROVE\nDRAMA: 4\nREASON: 42 CHAOS, solid".to_string(),
+                    Vec::new(),
+                ),
+            };
+            Ok(Completion {
+                text,
+                model: "curious".to_string(),
+                usage,
+                tool_calls,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_models_call_tools_before_answering() {
+        let provider = Arc::new(Curious {
+            requests: Mutex::new(Vec::new()),
+        });
+        let audit = Arc::new(
+            AuditStore::new(AuditConfig {
+                enabled: true,
+                ..AuditConfig::default()
+            })
+            .unwrap(),
+        );
+        let engine = LlmEngine::new(provider.clone(), audit.clone())
+            .with_tools(Arc::new(Toolbox::new().with_tool(Arc::new(Balance))));
+        let context = DecisionContext {
+            agent: "validator-1".to_string(),
+            persona: String::new(),
+            traits: PersonalityTraits::default(),
+            height: 3,
+            block_hash: String::new(),
+            proposal: "Block 3".to_string(),
+            notes: Vec::new(),
+        };
+
+        let decision = engine.decide(&context).await.unwrap();
+        assert!(decision.verdict.approved);
+        assert_eq!(decision.usage.total(), 24);
+
+        let requests = provider.requests.lock();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].tools[0].name, "get_balance");
+        let exchanges = &requests[1].exchanges;
+        assert!(exchanges[0].result.contains("42"));
+        // Unknown tools are reported to the model, not to the caller
+        assert!(exchanges[1].result.contains("No tool named get_gossip"));
+
+        let tool_calls = audit.query(&AuditFilter {
+            purpose: Some("tool".to_string()),
+            ..AuditFilter::default()
+        });
+        assert_eq!(tool_calls.len(), 2);
+    }
+}
@@ -0,0 +1,259 @@
+use crate::provider::TokenUsage;
+use chaoschain_core::metrics::MetricsRegistry;
+use parking_lot::RwLock;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+
+/// Price of a model in USD per thousand tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPricing {
+    pub prompt_per_1k: f64,
+    pub completion_per_1k: f64,
+}
+
+impl ModelPricing {
+    pub fn cost(&self, usage: TokenUsage) -> f64 {
+        (usage.prompt_tokens as f64 * self.prompt_per_1k
+            + usage.completion_tokens as f64 * self.completion_per_1k)
+            / 1000.0
+    }
+}
+
+/// Model name prefix to price. The longest matching prefix wins, unknown
+/// models (e.g. self-hosted ones) are free.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriceTable {
+    pub prices: HashMap<String, ModelPricing>,
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        let prices = [
+            ("gpt-3.5-turbo", 0.0005, 0.0015),
+            ("gpt-4", 0.03, 0.06),
+            ("gpt-4-turbo", 0.01, 0.03),
+            ("gpt-4o", 0.005, 0.015),
+            ("gpt-4o-mini", 0.00015, 0.0006),
+        ]
+        .into_iter()
+        .map(|(model, prompt_per_1k, completion_per_1k)| {
+            (
+                model.to_string(),
+                ModelPricing {
+                    prompt_per_1k,
+                    completion_per_1k,
+                },
+            )
+        })
+        .collect();
+        Self { prices }
+    }
+}
+
+impl PriceTable {
+    pub fn pricing(&self, model: &str) -> Option<ModelPricing> {
+        self.prices
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, pricing)| *pricing)
+    }
+
+    /// Estimated cost of a call in USD
+    pub fn cost(&self, model: &str, usage: TokenUsage) -> f64 {
+        self.pricing(model)
+            .map_or(0.0, |pricing| pricing.cost(usage))
+    }
+}
+
+/// Usage of one model by one agent
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelUsage {
+    pub calls: u64,
+    pub usage: TokenUsage,
+    pub cost_usd: f64,
+}
+
+/// Aggregated usage of an agent
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentUsage {
+    pub agent: String,
+    /// Model calls made on behalf of the agent
+    pub calls: u64,
+    pub usage: TokenUsage,
+    pub cost_usd: f64,
+    /// Cost of the agent's most recent model call
+    pub last_cost_usd: f64,
+    pub by_model: HashMap<String, ModelUsage>,
+}
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Clone, Default)]
+struct UsageMetrics {
+    tokens: Family<Labels, Counter>,
+    cost: Family<Labels, Counter<f64, AtomicU64>>,
+    calls: Family<Labels, Counter>,
+}
+
+/// Per-agent token and cost accounting
+pub struct UsageTracker {
+    prices: PriceTable,
+    agents: RwLock<HashMap<String, AgentUsage>>,
+    metrics: UsageMetrics,
+}
+
+impl Default for UsageTracker {
+    fn default() -> Self {
+        Self::new(PriceTable::default())
+    }
+}
+
+impl UsageTracker {
+    pub fn new(prices: PriceTable) -> Self {
+        Self {
+            prices,
+            agents: RwLock::new(HashMap::new()),
+            metrics: UsageMetrics::default(),
+        }
+    }
+
+    /// Expose the usage counters through a Prometheus registry
+    pub fn register(&self, registry: &MetricsRegistry) {
+        registry.register(
+            "chaoschain_agent_tokens",
+            "Tokens consumed per agent, model and kind (prompt or completion)",
+            self.metrics.tokens.clone(),
+        );
+        registry.register(
+            "chaoschain_agent_cost_usd",
+            "Estimated model spend in USD per agent and model",
+            self.metrics.cost.clone(),
+        );
+        registry.register(
+            "chaoschain_agent_calls",
+            "Model calls per agent and model",
+            self.metrics.calls.clone(),
+        );
+    }
+
+    /// Account for one model call. Returns its estimated cost in USD.
+    pub fn record(&self, agent: &str, model: &str, usage: TokenUsage) -> f64 {
+        let cost = self.prices.cost(model, usage);
+
+        {
+            let mut agents = self.agents.write();
+            let entry = agents
+                .entry(agent.to_string())
+                .or_insert_with(|| AgentUsage {
+                    agent: agent.to_string(),
+                    ..AgentUsage::default()
+                });
+            entry.calls += 1;
+            entry.usage += usage;
+            entry.cost_usd += cost;
+            entry.last_cost_usd = cost;
+            let by_model = entry.by_model.entry(model.to_string()).or_default();
+            by_model.calls += 1;
+            by_model.usage += usage;
+            by_model.cost_usd += cost;
+        }
+
+        let labels = |extra: Option<&str>| {
+            let mut labels = vec![
+                ("agent".to_string(), agent.to_string()),
+                ("model".to_string(), model.to_string()),
+            ];
+            if let Some(kind) = extra {
+                labels.push(("kind".to_string(), kind.to_string()));
+            }
+            labels
+        };
+        self.metrics
+            .tokens
+            .get_or_create(&labels(Some("prompt")))
+            .inc_by(usage.prompt_tokens as u64);
+        self.metrics
+            .tokens
+            .get_or_create(&labels(Some("completion")))
+            .inc_by(usage.completion_tokens as u64);
+        self.metrics.cost.get_or_create(&labels(None)).inc_by(cost);
+        self.metrics.calls.get_or_create(&labels(None)).inc();
+
+        cost
+    }
+
+    pub fn agent(&self, agent: &str) -> Option<AgentUsage> {
+        self.agents.read().get(agent).cloned()
+    }
+
+    /// Every agent's usage, biggest spender first
+    pub fn agents(&self) -> Vec<AgentUsage> {
+        let mut agents: Vec<AgentUsage> = self.agents.read().values().cloned().collect();
+        agents.sort_by(|a, b| {
+            b.cost_usd
+                .total_cmp(&a.cost_usd)
+                .then(b.usage.total().cmp(&a.usage.total()))
+        });
+        agents
+    }
+
+    /// Totals over all agents
+    pub fn total(&self) -> ModelUsage {
+        self.agents
+            .read()
+            .values()
+            .fold(ModelUsage::default(), |mut total, agent| {
+                total.calls += agent.calls;
+                total.usage += agent.usage;
+                total.cost_usd += agent.cost_usd;
+                total
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(prompt_tokens: u32, completion_tokens: u32) -> TokenUsage {
+        TokenUsage {
+            prompt_tokens,
+            completion_tokens,
+        }
+    }
+
+    #[test]
+    fn test_pricing_uses_longest_prefix() {
+        let prices = PriceTable::default();
+        let mini = prices.cost("gpt-4o-mini-2024-07-18", usage(1000, 1000));
+        assert!((mini - 0.00075).abs() < 1e-12);
+        assert_eq!(prices.cost("llama3", usage(1000, 1000)), 0.0);
+    }
+
+    #[test]
+    fn test_aggregation_and_metrics() {
+        let tracker = UsageTracker::default();
+        let registry = MetricsRegistry::default();
+        tracker.register(&registry);
+
+        tracker.record("validator-1", "gpt-4", usage(1000, 500));
+        tracker.record("validator-2", "gpt-3.5-turbo", usage(1000, 500));
+        tracker.record("validator-2", "llama3", usage(200, 100));
+
+        let agents = tracker.agents();
+        assert_eq!(agents[0].agent, "validator-1");
+        assert!((agents[0].cost_usd - 0.06).abs() < 1e-12);
+        assert_eq!(agents[1].calls, 2);
+        assert_eq!(agents[1].by_model.len(), 2);
+        assert_eq!(tracker.total().usage.total(), 3300);
+
+        let text = registry.encode().unwrap();
+        assert!(text.contains(
+            "chaoschain_agent_tokens_total{agent=\"validator-2\",model=\"llama3\",kind=\"prompt\"} 200"
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+//! `chaoschain-aggregator`: one view of a network of nodes, for demos that
+//! run many of them. It polls the web API of every node it is given and
+//! puts together where each one stands:
+//!
+//! ```bash
+//! chaoschain-aggregator --node alpha=http://127.0.0.1:3000 --node beta=http://127.0.0.1:3001
+//! ```
+//!
+//! `GET /api/v1/network` answers with every node's head, how far it lags
+//! behind the highest one, its sync state, the validators it hears from,
+//! the agents connected to its gateway and whether it is ready, and the
+//! lowest height the nodes disagree on, if they do. `/` is a panel that
+//! shows the same and keeps it up to date.
+
+mod node;
+mod overview;
+
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use clap::Parser;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tracing::info;
+
+use overview::{Network, NetworkOverview};
+
+/// Header the nodes take API tokens in
+const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Longest a node may take to answer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Args {
+    /// Node web API to follow, as NAME=URL, or a URL alone to name the node
+    /// after its host and port. Once for every node.
+    #[arg(long = "node", value_name = "[NAME=]URL", required = true)]
+    nodes: Vec<String>,
+
+    /// Port of 127.0.0.1 to serve the overview on
+    #[arg(long, default_value_t = 3100)]
+    port: u16,
+
+    /// Seconds between two polls of the nodes
+    #[arg(long, default_value_t = 2)]
+    interval: u64,
+
+    /// API token with the read-only scope, when the nodes ask for one
+    /// (else `CHAOSCHAIN_API_KEY`)
+    #[arg(long)]
+    api_key: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+
+    let nodes: Vec<(String, String)> = args.nodes.iter().map(|node| named(node)).collect();
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(api_key) = args
+        .api_key
+        .or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok())
+    {
+        headers.insert(
+            API_KEY_HEADER,
+            api_key
+                .parse()
+                .context("The API key can't go in a header")?,
+        );
+    }
+    let client = reqwest::Client::builder()
+        .default_headers(headers)
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+
+    let network = Arc::new(Mutex::new(Network::new(nodes.clone())));
+    tokio::spawn(poll(
+        client,
+        nodes,
+        Duration::from_secs(args.interval.max(1)),
+        network.clone(),
+    ));
+
+    let app = Router::new()
+        .route("/", get(panel))
+        .route("/api/v1/network", get(overview))
+        .with_state(network);
+    let listener = TcpListener::bind(("127.0.0.1", args.port)).await?;
+    info!("Network overview at http://127.0.0.1:{}", args.port);
+    axum::serve(listener, app).await?;
+    Ok(())
+}
+
+/// Name and URL of a `--node`
+fn named(node: &str) -> (String, String) {
+    let (name, url) = match node.split_once('=') {
+        Some((name, url)) => (name.to_string(), url),
+        None => {
+            let host = node.split_once("://").map_or(node, |(_, rest)| rest);
+            (host.trim_end_matches('/').to_string(), node)
+        }
+    };
+    (name, url.trim_end_matches('/').to_string())
+}
+
+/// Poll every node on each tick, all of them at once
+async fn poll(
+    client: reqwest::Client,
+    nodes: Vec<(String, String)>,
+    interval: Duration,
+    network: Arc<Mutex<Network>>,
+) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let polls =
+            futures::future::join_all(nodes.iter().map(|(_, url)| node::poll(&client, url))).await;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut network = network.lock();
+        for (index, poll) in polls.into_iter().enumerate() {
+            network.polled(index, poll, now);
+        }
+    }
+}
+
+async fn overview(State(network): State<Arc<Mutex<Network>>>) -> Json<NetworkOverview> {
+    Json(network.lock().overview())
+}
+
+async fn panel() -> Html<&'static str> {
+    Html(PANEL)
+}
+
+const PANEL: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>ChaosChain network</title>
+  <style>
+    body { font-family: sans-serif; margin: 2em; }
+    table { border-collapse: collapse; }
+    th, td { padding: 0.3em 0.8em; border-bottom: 1px solid #ddd; text-align: left; }
+    .down { color: #b00; }
+    .fork { background: #fee; padding: 0.5em 1em; }
+    code { font-size: 0.9em; }
+  </style>
+</head>
+<body>
+  <h1>ChaosChain network</h1>
+  <p id="summary"></p>
+  <div id="divergence"></div>
+  <table>
+    <thead>
+      <tr><th>Node</th><th>Status</th><th>Head</th><th>Lag</th><th>Sync</th><th>Validators</th><th>Peers</th><th>Ready</th></tr>
+    </thead>
+    <tbody id="nodes"></tbody>
+  </table>
+  <script>
+    const text = (value) => value === null || value === undefined ? "–" : String(value);
+    const cell = (row, value, className) => {
+      const td = row.insertCell();
+      td.textContent = text(value);
+      if (className) td.className = className;
+    };
+    async function refresh() {
+      const network = await (await fetch("/api/v1/network")).json();
+      document.getElementById("summary").textContent =
+        `${network.nodes_up} of ${network.nodes.length} nodes up, highest block ${text(network.highest)}`;
+      const divergence = document.getElementById("divergence");
+      divergence.replaceChildren();
+      if (network.divergence) {
+        const fork = document.createElement("p");
+        fork.className = "fork";
+        fork.textContent = `Chains part at block ${network.divergence.height}: ` +
+          network.divergence.branches
+            .map((branch) => `${branch.nodes.join(", ")} on ${branch.hash.slice(0, 12)}`)
+            .join(" / ");
+        divergence.append(fork);
+      }
+      const rows = document.getElementById("nodes");
+      rows.replaceChildren();
+      for (const node of network.nodes) {
+        const row = rows.insertRow();
+        cell(row, node.name);
+        cell(row, node.up ? "up" : node.error, node.up ? "" : "down");
+        cell(row, node.head && `${node.head.height} (${node.head.hash.slice(0, 12)})`);
+        cell(row, node.lag);
+        cell(row, node.sync && (node.sync.state === "syncing"
+          ? `syncing to ${node.sync.highest_seen}` : "synced"));
+        cell(row, node.validators && `${node.validators.heard} of ${node.validators.total}`);
+        cell(row, node.peers);
+        cell(row, node.ready === null ? null : node.ready ? "yes" : "no");
+      }
+    }
+    refresh();
+    setInterval(refresh, 2000);
+  </script>
+</body>
+</html>"##;
@@ -0,0 +1,137 @@
+//! One poll of a node, through the same public APIs anyone can read. The
+//! blocks are what count: a node whose blocks can't be read is down, while
+//! what it says about its sync, its validators, its gateway and its health
+//! is taken as far as it goes.
+
+use chaoschain_core::sync::SyncState;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::overview::{Reach, Sample};
+
+/// Recent blocks asked for on every poll
+const RECENT_BLOCKS: usize = 32;
+
+#[derive(Debug, Deserialize)]
+struct BlockPage {
+    items: Vec<BlockItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockItem {
+    height: u64,
+    hash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Consensus {
+    height: u64,
+    rounds: Vec<Round>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Round {
+    block_hash: String,
+    finalized_at: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Partition {
+    reachable: usize,
+    validators: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct GatewayAgent {
+    suspended: bool,
+}
+
+pub async fn poll(client: &reqwest::Client, url: &str) -> Result<Sample, String> {
+    let (blocks, sync, partition, agents, ready) = tokio::join!(
+        blocks(client, url),
+        get::<SyncState>(client, url, "/api/network/sync"),
+        get::<Partition>(client, url, "/api/network/partition"),
+        get::<Vec<GatewayAgent>>(client, url, "/api/gateway/agents"),
+        client.get(format!("{}/health/ready", url)).send(),
+    );
+    Ok(Sample {
+        blocks: blocks?,
+        sync: sync.ok(),
+        validators: partition.ok().map(|partition| Reach {
+            heard: partition.reachable,
+            total: partition.validators,
+        }),
+        // Only nodes with a gateway have agents connecting to them
+        peers: agents
+            .ok()
+            .map(|agents| agents.iter().filter(|agent| !agent.suspended).count()),
+        ready: ready.ok().and_then(|response| match response.status() {
+            StatusCode::OK => Some(true),
+            StatusCode::SERVICE_UNAVAILABLE => Some(false),
+            _ => None,
+        }),
+    })
+}
+
+/// Recent finalized blocks. Nodes that keep no blocks of their own, as in
+/// the demo, still tell which block their latest round finalized.
+async fn blocks(client: &reqwest::Client, url: &str) -> Result<Vec<(u64, String)>, String> {
+    let page: BlockPage = get(
+        client,
+        url,
+        &format!("/api/v1/blocks?limit={}", RECENT_BLOCKS),
+    )
+    .await?;
+    if !page.items.is_empty() {
+        return Ok(page
+            .items
+            .into_iter()
+            .map(|block| (block.height, block.hash))
+            .collect());
+    }
+    let Ok(latest) = get::<Consensus>(client, url, "/api/v1/blocks/latest/consensus").await else {
+        return Ok(Vec::new());
+    };
+    let height = latest.height;
+    if let Some(block) = latest.finalized() {
+        return Ok(vec![block]);
+    }
+    // The latest block is still being voted on
+    let path = format!("/api/v1/blocks/{}/consensus", height.saturating_sub(1));
+    Ok(get::<Consensus>(client, url, &path)
+        .await
+        .ok()
+        .and_then(|previous| previous.finalized())
+        .into_iter()
+        .collect())
+}
+
+impl Consensus {
+    fn finalized(self) -> Option<(u64, String)> {
+        let height = self.height;
+        self.rounds
+            .into_iter()
+            .find(|round| round.finalized_at.is_some())
+            .map(|round| (height, round.block_hash))
+    }
+}
+
+async fn get<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    path: &str,
+) -> Result<T, String> {
+    let response = client
+        .get(format!("{}{}", url, path))
+        .send()
+        .await
+        .map_err(|e| format!("{} unreachable: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("{} answered {}", path, response.status()));
+    }
+    response
+        .json()
+        .await
+        .map_err(|e| format!("{} answered with something else: {}", path, e))
+}
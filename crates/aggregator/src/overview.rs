@@ -0,0 +1,280 @@
+//! The network as the aggregator sees it: what each node last said about
+//! itself, and where their chains part ways. Nodes are compared on the
+//! hashes they report at the same height, so two nodes on different chains
+//! show up as soon as both have a block at a height the other has too,
+//! even while one of them lags behind.
+
+use chaoschain_core::sync::SyncState;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Heights remembered per node for comparing chains
+const HASHES_KEPT: usize = 256;
+
+/// What one poll of a node found
+#[derive(Debug, Clone, Default)]
+pub struct Sample {
+    /// Finalized blocks, as height and hash
+    pub blocks: Vec<(u64, String)>,
+    pub sync: Option<SyncState>,
+    pub peers: Option<usize>,
+    pub validators: Option<Reach>,
+    pub ready: Option<bool>,
+}
+
+/// Validators a node hears from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Reach {
+    pub heard: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Head {
+    pub height: u64,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeView {
+    pub name: String,
+    pub url: String,
+    /// Whether the last poll got through
+    pub up: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Latest finalized block seen, kept while the node is down
+    pub head: Option<Head>,
+    /// Blocks behind the highest head in the network
+    pub lag: Option<u64>,
+    pub sync: Option<SyncState>,
+    /// External agents connected through the node's gateway
+    pub peers: Option<usize>,
+    pub validators: Option<Reach>,
+    /// Whether `/health/ready` passed
+    pub ready: Option<bool>,
+    /// Unix seconds of the last poll that got through
+    pub last_seen: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Branch {
+    pub hash: String,
+    pub nodes: Vec<String>,
+}
+
+/// The lowest height the nodes disagree on, and who is on which block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    pub height: u64,
+    pub branches: Vec<Branch>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkOverview {
+    pub nodes: Vec<NodeView>,
+    pub nodes_up: usize,
+    /// Highest head of any node
+    pub highest: Option<u64>,
+    pub divergence: Option<Divergence>,
+    /// Unix seconds of the latest poll
+    pub updated_at: u64,
+}
+
+#[derive(Debug)]
+struct Node {
+    name: String,
+    url: String,
+    error: Option<String>,
+    /// The last poll that got through
+    sample: Sample,
+    hashes: BTreeMap<u64, String>,
+    last_seen: Option<u64>,
+}
+
+/// The nodes followed, by the order they were given in
+#[derive(Debug)]
+pub struct Network {
+    nodes: Vec<Node>,
+    updated_at: u64,
+}
+
+impl Network {
+    /// Follow nodes given by name and URL
+    pub fn new(nodes: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            nodes: nodes
+                .into_iter()
+                .map(|(name, url)| Node {
+                    name,
+                    url,
+                    error: Some("Not polled yet".to_string()),
+                    sample: Sample::default(),
+                    hashes: BTreeMap::new(),
+                    last_seen: None,
+                })
+                .collect(),
+            updated_at: 0,
+        }
+    }
+
+    /// Take in a poll of the `node`th node at `at` (unix seconds)
+    pub fn polled(&mut self, node: usize, poll: Result<Sample, String>, at: u64) {
+        self.updated_at = at;
+        let Some(node) = self.nodes.get_mut(node) else {
+            return;
+        };
+        match poll {
+            Ok(sample) => {
+                // A reorganized chain overwrites the heights it replaced
+                for (height, hash) in &sample.blocks {
+                    node.hashes.insert(*height, hash.clone());
+                }
+                while node.hashes.len() > HASHES_KEPT {
+                    node.hashes.pop_first();
+                }
+                node.sample = sample;
+                node.error = None;
+                node.last_seen = Some(at);
+            }
+            Err(error) => node.error = Some(error),
+        }
+    }
+
+    pub fn overview(&self) -> NetworkOverview {
+        let head = |node: &Node| {
+            node.hashes.last_key_value().map(|(height, hash)| Head {
+                height: *height,
+                hash: hash.clone(),
+            })
+        };
+        let highest = self
+            .nodes
+            .iter()
+            .filter_map(|node| node.hashes.last_key_value())
+            .map(|(height, _)| *height)
+            .max();
+        NetworkOverview {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|node| {
+                    let head = head(node);
+                    NodeView {
+                        name: node.name.clone(),
+                        url: node.url.clone(),
+                        up: node.error.is_none(),
+                        error: node.error.clone(),
+                        lag: highest
+                            .zip(head.as_ref())
+                            .map(|(highest, head)| highest - head.height),
+                        head,
+                        sync: node.sample.sync,
+                        peers: node.sample.peers,
+                        validators: node.sample.validators,
+                        ready: node.sample.ready,
+                        last_seen: node.last_seen,
+                    }
+                })
+                .collect(),
+            nodes_up: self
+                .nodes
+                .iter()
+                .filter(|node| node.error.is_none())
+                .count(),
+            highest,
+            divergence: self.divergence(),
+            updated_at: self.updated_at,
+        }
+    }
+
+    /// The lowest height two nodes report different blocks at
+    fn divergence(&self) -> Option<Divergence> {
+        let heights: BTreeSet<u64> = self
+            .nodes
+            .iter()
+            .flat_map(|node| node.hashes.keys().copied())
+            .collect();
+        heights.into_iter().find_map(|height| {
+            let mut branches: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+            for node in &self.nodes {
+                if let Some(hash) = node.hashes.get(&height) {
+                    branches.entry(hash).or_default().push(node.name.clone());
+                }
+            }
+            (branches.len() > 1).then(|| Divergence {
+                height,
+                branches: branches
+                    .into_iter()
+                    .map(|(hash, nodes)| Branch {
+                        hash: hash.to_string(),
+                        nodes,
+                    })
+                    .collect(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(chain: &str, heights: std::ops::RangeInclusive<u64>) -> Sample {
+        Sample {
+            blocks: heights
+                .map(|height| (height, format!("{}{}", chain, height)))
+                .collect(),
+            ..Sample::default()
+        }
+    }
+
+    #[test]
+    fn test_heads_lag_and_divergence() {
+        let mut network = Network::new(
+            ["alpha", "beta", "gamma"]
+                .iter()
+                .map(|name| (name.to_string(), format!("http://{}", name))),
+        );
+        network.polled(0, Ok(blocks("a", 1..=5)), 100);
+        network.polled(1, Ok(blocks("a", 1..=3)), 100);
+        let overview = network.overview();
+        assert_eq!(overview.highest, Some(5));
+        assert_eq!(overview.nodes_up, 2);
+        assert_eq!(overview.nodes[1].lag, Some(2));
+        assert_eq!(overview.nodes[2].head, None);
+        assert!(!overview.nodes[2].up);
+        assert_eq!(overview.divergence, None);
+
+        // Gamma went its own way after block 2
+        let mut fork = blocks("a", 1..=2);
+        fork.blocks.extend(blocks("g", 3..=4).blocks);
+        network.polled(2, Ok(fork), 110);
+        let divergence = network.overview().divergence.unwrap();
+        assert_eq!(divergence.height, 3);
+        assert_eq!(
+            divergence.branches,
+            vec![
+                Branch {
+                    hash: "a3".to_string(),
+                    nodes: vec!["alpha".to_string(), "beta".to_string()],
+                },
+                Branch {
+                    hash: "g3".to_string(),
+                    nodes: vec!["gamma".to_string()],
+                },
+            ]
+        );
+
+        // And came back to the others' chain, then went down
+        network.polled(2, Ok(blocks("a", 3..=5)), 120);
+        network.polled(2, Err("connection refused".to_string()), 130);
+        let overview = network.overview();
+        assert_eq!(overview.divergence, None);
+        assert_eq!(overview.updated_at, 130);
+        let gamma = &overview.nodes[2];
+        assert!(!gamma.up);
+        assert_eq!(gamma.last_seen, Some(120));
+        assert_eq!(gamma.head.as_ref().map(|head| head.height), Some(5));
+    }
+}
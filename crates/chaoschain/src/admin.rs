@@ -0,0 +1,683 @@
+//! Node operations, served on a listener of their own on 127.0.0.1 so that
+//! they never come out along with the public APIs. `[admin]` in the node
+//! config sets the port and the token, which every request shows in the
+//! `X-Admin-Token` header or as a bearer token. API tokens do not work
+//! here, not even admin ones.
+//!
+//! - `GET /admin/peers` lists the external agents with their score and
+//!   traffic, `POST` admits one by its public key or by an address ending
+//!   in its peer id, `DELETE /admin/peers/{id}` removes one and
+//!   `POST`/`DELETE /admin/peers/{id}/ban` bans it or lets it back in.
+//! - `GET /admin/agents` lists the agents the node runs and
+//!   `GET /admin/agents/{id}` shows one with a summary of what it remembers
+//!   and how its epochs went. `PUT /admin/agents/{id}/personality` swaps in
+//!   a new personality, traits or prompt, leaving the rest as it was, and
+//!   `POST /admin/agents/{id}/pause` and `/resume` take it out of the
+//!   network and back.
+//! - `GET /admin/mempool` shows what waits for a block, `DELETE` flushes it.
+//! - `POST /admin/snapshots` writes a snapshot of the state to the data
+//!   directory, as a manifest and its chunks.
+//! - `GET`/`PUT /admin/log-level` reads or changes the log level.
+//! - `GET /admin/webhooks` lists the webhooks and how their deliveries
+//!   went, `POST` registers one, `DELETE /admin/webhooks/{id}` removes one
+//!   and `GET /admin/webhooks/{id}/deliveries` shows its latest deliveries.
+//! - `GET /admin/chaos` shows the faults of the `[chaos]` schedule and
+//!   which are in effect, see [`crate::faults`].
+//! - `POST /admin/shutdown` shuts the node down as Ctrl-C would.
+//!
+//! With `profiling` set in `[admin]`, `GET /admin/profile/cpu` measures
+//! the CPU time of the node's threads for `seconds`, as JSON. With
+//! `format=pprof` a node built with the `pprof` feature samples its stacks
+//! instead, for `go tool pprof`, and with `format=folded` for flamegraphs,
+//! one frame per thread without the feature. `GET /admin/profile/heap`
+//! shows what the allocator counted, and `GET /admin/profile/runtime` what
+//! the async runtime is busy with. See [`crate::profiling`].
+
+use anyhow::{anyhow, bail};
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use chaoschain_agent::{ContextManager, Reinforcement};
+use chaoschain_cli::{AdminConfig, AgentProfile, CorsPolicy, WebhookConfig, WebhookEvent};
+use chaoschain_mempool::Mempool;
+use chaoschain_p2p::identity;
+use chaoschain_state::snapshot::{SnapshotArchive, DEFAULT_CHUNK_ENTRIES};
+use chaoschain_state::StateStoreImpl;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::{reload, Registry};
+
+use crate::faults::FaultInjector;
+use crate::gateway::{AgentGateway, GatewayRegistration};
+use crate::lifecycle::{AgentInfo, AgentSupervisor, LifecycleError};
+use crate::mempool::{PendingView, UsageView};
+use crate::profiling;
+use crate::rest::ApiError;
+use crate::web::{cors_layer, gateway_status, lifecycle_status};
+use crate::webhooks::Webhooks;
+
+/// Header carrying the admin token
+pub const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+/// Changes the level of the node's logs while it runs
+pub type LogLevel = reload::Handle<LevelFilter, Registry>;
+
+/// Latest memories an agent summary shows word for word
+const RECENT_MEMORIES: usize = 5;
+
+/// Seconds a CPU profile samples unless asked for another window
+const CPU_PROFILE_SECONDS: u64 = 10;
+
+pub struct AdminState {
+    pub token: String,
+    /// External agents, the peers an operator can let in or keep out
+    pub gateway: Option<Arc<AgentGateway>>,
+    /// Agents the node runs itself
+    pub agents: Option<Arc<AgentSupervisor>>,
+    /// What the agents remember of their verdicts
+    pub memory: Option<Arc<ContextManager>>,
+    /// How the agents' epochs were reviewed
+    pub reinforcement: Option<Arc<Reinforcement>>,
+    pub mempool: Option<Arc<Mempool>>,
+    pub state: Arc<StateStoreImpl>,
+    /// Where snapshots are written
+    pub data_dir: PathBuf,
+    pub log_level: Option<LogLevel>,
+    /// Where chain events are posted
+    pub webhooks: Option<Arc<Webhooks>>,
+    /// Woken when an operator asks the node to stop
+    pub shutdown: Arc<Notify>,
+    /// Whether profiles are served
+    pub profiling: bool,
+    /// Faults the node injects into itself, when chaos mode is on
+    pub faults: Option<Arc<FaultInjector>>,
+}
+
+pub fn routes(state: Arc<AdminState>) -> Router {
+    let mut router = Router::new()
+        .route("/admin/peers", get(list_peers).post(admit_peer))
+        .route("/admin/peers/:id", delete(remove_peer))
+        .route("/admin/peers/:id/ban", post(ban_peer).delete(unban_peer))
+        .route("/admin/agents", get(list_agents))
+        .route("/admin/agents/:id", get(show_agent))
+        .route("/admin/agents/:id/personality", put(set_personality))
+        .route("/admin/agents/:id/pause", post(pause_agent))
+        .route("/admin/agents/:id/resume", post(resume_agent))
+        .route("/admin/mempool", get(inspect_mempool).delete(flush_mempool))
+        .route("/admin/snapshots", post(take_snapshot))
+        .route("/admin/log-level", get(log_level).put(set_log_level))
+        .route("/admin/webhooks", get(list_webhooks).post(register_webhook))
+        .route("/admin/webhooks/:id", delete(remove_webhook))
+        .route("/admin/webhooks/:id/deliveries", get(webhook_deliveries))
+        .route("/admin/chaos", get(chaos))
+        .route("/admin/shutdown", post(shutdown));
+    if state.profiling {
+        router = router
+            .route("/admin/profile/cpu", get(cpu_profile))
+            .route("/admin/profile/heap", get(heap_profile))
+            .route("/admin/profile/runtime", get(runtime_profile));
+    }
+    router
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+pub async fn serve(state: Arc<AdminState>, port: u16, cors: &CorsPolicy) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    info!("Admin API listening on 127.0.0.1:{}", port);
+    axum::serve(listener, routes(state).layer(cors_layer(cors))).await
+}
+
+/// Call the admin API of the node on this machine, as `[admin]` in the
+/// config file sets it up. Errors carry the message the node answered with.
+pub async fn call(
+    admin: &AdminConfig,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<&Value>,
+) -> anyhow::Result<Value> {
+    let (Some(port), Some(token)) = (admin.port, admin.token.as_deref()) else {
+        bail!(
+            "The admin API is not set up, [admin] in the config file gives it a port and a token"
+        );
+    };
+    let url = format!("http://127.0.0.1:{}{}", port, path);
+    let mut request = reqwest::Client::new()
+        .request(method, &url)
+        .header(ADMIN_TOKEN_HEADER, token);
+    if let Some(body) = body {
+        request = request.json(body);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| anyhow!("Could not reach {}: {}", url, e))?;
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        bail!(
+            "{}",
+            body["error"]["message"].as_str().unwrap_or(status.as_str())
+        );
+    }
+    Ok(body)
+}
+
+fn token_of(req: &Request<Body>) -> Option<&str> {
+    let headers = req.headers();
+    headers
+        .get(ADMIN_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+        })
+}
+
+async fn require_token(
+    State(state): State<Arc<AdminState>>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if !token_of(&req)
+        .is_some_and(|token| bool::from(token.as_bytes().ct_eq(state.token.as_bytes())))
+    {
+        warn!(
+            "Refused {} {} on the admin API",
+            req.method(),
+            req.uri().path()
+        );
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthenticated",
+            "Missing or wrong admin token",
+        ));
+    }
+    info!("Admin: {} {}", req.method(), req.uri().path());
+    Ok(next.run(req).await)
+}
+
+fn gateway(state: &AdminState) -> Result<&Arc<AgentGateway>, ApiError> {
+    state.gateway.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "no_gateway",
+            "This node has no agent gateway",
+        )
+    })
+}
+
+fn supervisor(state: &AdminState) -> Result<&Arc<AgentSupervisor>, ApiError> {
+    state.agents.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "no_agents",
+            "This node runs no local agents",
+        )
+    })
+}
+
+fn agent_error(error: LifecycleError) -> ApiError {
+    let (status, message) = lifecycle_status(error);
+    let code = match status {
+        StatusCode::NOT_FOUND => "not_found",
+        _ => "conflict",
+    };
+    ApiError::new(status, code, message)
+}
+
+async fn find_agent(state: &AdminState, id: &str) -> Result<AgentInfo, ApiError> {
+    supervisor(state)?
+        .list()
+        .await
+        .into_iter()
+        .find(|agent| agent.id == id)
+        .ok_or_else(|| ApiError::not_found(format!("No agent {}", id)))
+}
+
+async fn list_agents(State(state): State<Arc<AdminState>>) -> Result<Json<Value>, ApiError> {
+    Ok(Json(json!(supervisor(&state)?.list().await)))
+}
+
+async fn show_agent(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let mut body = json!(find_agent(&state, &id).await?);
+    if let Some(memory) = &state.memory {
+        let memory = memory.memory(&id);
+        let skip = memory.recent.len().saturating_sub(RECENT_MEMORIES);
+        body["memory"] = json!({
+            "digests": memory.digests.len(),
+            "memories": memory.recent.len(),
+            "latest_digest": memory.digests.last(),
+            "recent": memory.recent.iter().skip(skip).collect::<Vec<_>>(),
+        });
+    }
+    if let Some(reinforcement) = &state.reinforcement {
+        let reviews = reinforcement.reviews(&id);
+        body["reviews"] = json!({
+            "epochs": reviews.len(),
+            "last": reviews.last(),
+            "approval_bias": reinforcement.approval_bias(&id),
+            "lessons": reinforcement.lessons(&id),
+        });
+    }
+    Ok(Json(body))
+}
+
+async fn set_personality(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+    Json(change): Json<AgentProfile>,
+) -> Result<Json<Value>, ApiError> {
+    // Only what the request sets changes, the rest of the profile stays
+    let current = find_agent(&state, &id).await?;
+    let agent = supervisor(&state)?
+        .replace(&id, current.profile.merged(&change))
+        .await
+        .map_err(agent_error)?;
+    Ok(Json(json!(agent)))
+}
+
+async fn pause_agent(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let agent = supervisor(&state)?.pause(&id).await.map_err(agent_error)?;
+    Ok(Json(json!(agent)))
+}
+
+async fn resume_agent(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let agent = supervisor(&state)?.resume(&id).await.map_err(agent_error)?;
+    Ok(Json(json!(agent)))
+}
+
+fn mempool(state: &AdminState) -> Result<&Arc<Mempool>, ApiError> {
+    state.mempool.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "no_mempool",
+            "This node has no mempool",
+        )
+    })
+}
+
+fn peer_error(error: crate::gateway::GatewayError) -> ApiError {
+    let (status, message) = gateway_status(error);
+    let code = match status {
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::FORBIDDEN => "banned",
+        _ => "bad_request",
+    };
+    ApiError::new(status, code, message)
+}
+
+async fn list_peers(State(state): State<Arc<AdminState>>) -> Result<Json<Value>, ApiError> {
+    let peers: Vec<Value> = gateway(&state)?
+        .agents()
+        .into_iter()
+        .map(|agent| {
+            let mut peer = json!(agent);
+            peer["score"] = json!(agent.score());
+            peer
+        })
+        .collect();
+    Ok(Json(json!(peers)))
+}
+
+#[derive(Debug, Deserialize)]
+struct Admission {
+    name: String,
+    /// Hex encoded ed25519 public key
+    #[serde(default)]
+    public_key: Option<String>,
+    /// Multiaddr ending in the peer id, which holds the key
+    #[serde(default)]
+    address: Option<String>,
+    /// Hex encoded proof of possession of the key, a signature of the
+    /// registration message
+    signature: String,
+}
+
+async fn admit_peer(
+    State(state): State<Arc<AdminState>>,
+    Json(admission): Json<Admission>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let public_key = match (admission.public_key, admission.address) {
+        (Some(public_key), None) => public_key,
+        (None, Some(address)) => identity::address_key(&address)
+            .map(|key| hex::encode(key.as_bytes()))
+            .map_err(|e| {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_address", e.to_string())
+            })?,
+        _ => {
+            return Err(ApiError::bad_request(
+                "Give either a public_key or an address",
+            ))
+        }
+    };
+    let agent = gateway(&state)?
+        .register(GatewayRegistration {
+            name: admission.name,
+            public_key,
+            signature: admission.signature,
+        })
+        .await
+        .map_err(peer_error)?;
+    Ok((StatusCode::CREATED, Json(json!(agent))))
+}
+
+async fn remove_peer(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let agent = gateway(&state)?.remove(&id).await.map_err(peer_error)?;
+    Ok(Json(json!(agent)))
+}
+
+async fn ban_peer(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let agent = gateway(&state)?.ban(&id).await.map_err(peer_error)?;
+    Ok(Json(json!(agent)))
+}
+
+async fn unban_peer(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let agent = gateway(&state)?.unban(&id).await.map_err(peer_error)?;
+    Ok(Json(json!(agent)))
+}
+
+async fn inspect_mempool(State(state): State<Arc<AdminState>>) -> Result<Json<Value>, ApiError> {
+    let mempool = mempool(&state)?;
+    let now = chrono::Utc::now().timestamp() as u64;
+    let pending: Vec<PendingView> = mempool
+        .entries(None)
+        .await
+        .iter()
+        .map(|entry| PendingView::new(entry, now))
+        .collect();
+    Ok(Json(json!({
+        "usage": UsageView::from(mempool.usage().await),
+        "pending": pending,
+    })))
+}
+
+async fn flush_mempool(State(state): State<Arc<AdminState>>) -> Result<Json<Value>, ApiError> {
+    let flushed = mempool(&state)?.clear().await;
+    warn!("🧹 Flushed {} transactions from the mempool", flushed);
+    Ok(Json(json!({ "flushed": flushed })))
+}
+
+async fn take_snapshot(
+    State(state): State<Arc<AdminState>>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let snapshot = state
+        .state
+        .create_snapshot()
+        .map_err(|e| ApiError::new(StatusCode::CONFLICT, "no_snapshot", e.to_string()))?;
+    let entries = snapshot.state_pairs.len();
+    let archive = SnapshotArchive::new(snapshot, DEFAULT_CHUNK_ENTRIES);
+    let dir = state
+        .data_dir
+        .join("snapshots")
+        .join(format!("snapshot-{}", archive.manifest.height));
+    archive.save(&dir).map_err(|e| {
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "storage",
+            format!("Could not write the snapshot: {}", e),
+        )
+    })?;
+    info!(
+        "📸 Snapshot at height {} written to {}",
+        archive.manifest.height,
+        dir.display()
+    );
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "height": archive.manifest.height,
+            "state_root": hex::encode(archive.manifest.state_root),
+            "entries": entries,
+            "chunks": archive.manifest.chunk_count,
+            "path": dir.display().to_string(),
+        })),
+    ))
+}
+
+fn log_handle(state: &AdminState) -> Result<&LogLevel, ApiError> {
+    state.log_level.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "fixed_log_level",
+            "The log level of this node can't be changed",
+        )
+    })
+}
+
+async fn log_level(State(state): State<Arc<AdminState>>) -> Result<Json<Value>, ApiError> {
+    let level = log_handle(&state)?
+        .with_current(|level| level.to_string().to_lowercase())
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "logging", e.to_string()))?;
+    Ok(Json(json!({ "level": level })))
+}
+
+#[derive(Debug, Deserialize)]
+struct LevelChange {
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`
+    level: String,
+}
+
+async fn set_log_level(
+    State(state): State<Arc<AdminState>>,
+    Json(change): Json<LevelChange>,
+) -> Result<Json<Value>, ApiError> {
+    let level: LevelFilter = change.level.parse().map_err(|_| {
+        ApiError::bad_request(format!(
+            "Unknown log level {}, expected off, error, warn, info, debug or trace",
+            change.level
+        ))
+    })?;
+    log_handle(&state)?
+        .reload(level)
+        .map_err(|e| ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "logging", e.to_string()))?;
+    warn!("Log level set to {}", level);
+    Ok(Json(json!({ "level": level.to_string().to_lowercase() })))
+}
+
+fn webhooks(state: &AdminState) -> Result<&Arc<Webhooks>, ApiError> {
+    state.webhooks.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "no_webhooks",
+            "This node posts no webhooks",
+        )
+    })
+}
+
+async fn list_webhooks(State(state): State<Arc<AdminState>>) -> Result<Json<Value>, ApiError> {
+    Ok(Json(json!(webhooks(&state)?.list())))
+}
+
+#[derive(Debug, Deserialize)]
+struct WebhookRegistration {
+    url: String,
+    #[serde(default)]
+    events: Vec<WebhookEvent>,
+    #[serde(default)]
+    keywords: Vec<String>,
+    /// Made up when left out, and only shown in the answer
+    #[serde(default)]
+    secret: Option<String>,
+}
+
+async fn register_webhook(
+    State(state): State<Arc<AdminState>>,
+    Json(registration): Json<WebhookRegistration>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let secret = registration
+        .secret
+        .unwrap_or_else(|| hex::encode(rand::random::<[u8; 32]>()));
+    let webhook = webhooks(&state)?
+        .register(WebhookConfig {
+            url: registration.url,
+            events: registration.events,
+            keywords: registration.keywords,
+            secret: secret.clone(),
+        })
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    let mut body = json!(webhook);
+    body["secret"] = json!(secret);
+    Ok((StatusCode::CREATED, Json(body)))
+}
+
+async fn remove_webhook(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Value>, ApiError> {
+    let webhook = webhooks(&state)?
+        .remove(id)
+        .ok_or_else(|| ApiError::not_found(format!("No webhook {}", id)))?;
+    Ok(Json(json!(webhook)))
+}
+
+async fn webhook_deliveries(
+    State(state): State<Arc<AdminState>>,
+    Path(id): Path<u64>,
+) -> Result<Json<Value>, ApiError> {
+    let deliveries = webhooks(&state)?
+        .deliveries(id)
+        .ok_or_else(|| ApiError::not_found(format!("No webhook {}", id)))?;
+    Ok(Json(json!(deliveries)))
+}
+
+async fn chaos(State(state): State<Arc<AdminState>>) -> Result<Json<Value>, ApiError> {
+    let faults = state.faults.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "no_chaos",
+            "Chaos mode is off, set enabled in [chaos] to inject faults",
+        )
+    })?;
+    Ok(Json(json!({
+        "elapsed": faults.elapsed().as_secs(),
+        "faults": faults.status(),
+    })))
+}
+
+async fn shutdown(State(state): State<Arc<AdminState>>) -> (StatusCode, Json<Value>) {
+    warn!("🛑 Shutdown requested on the admin API");
+    state.shutdown.notify_one();
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({ "status": "shutting_down" })),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct CpuProfileQuery {
+    #[serde(default)]
+    seconds: Option<u64>,
+    /// `json`, the default, `folded` or `pprof`
+    #[serde(default)]
+    format: Option<String>,
+}
+
+async fn cpu_profile(Query(query): Query<CpuProfileQuery>) -> Result<Response, ApiError> {
+    let seconds = query.seconds.unwrap_or(CPU_PROFILE_SECONDS);
+    let window = Duration::from_secs(seconds);
+    if seconds == 0 || window > profiling::MAX_CPU_WINDOW {
+        return Err(ApiError::bad_request(format!(
+            "A CPU profile samples from 1 to {} seconds",
+            profiling::MAX_CPU_WINDOW.as_secs()
+        )));
+    }
+    let format = query.format.as_deref().unwrap_or("json");
+    if !matches!(format, "json" | "folded" | "pprof") {
+        return Err(ApiError::bad_request(format!(
+            "Unknown profile format {}, expected json, folded or pprof",
+            format
+        )));
+    }
+    info!("🔬 Sampling the CPU for {}s", seconds);
+    #[cfg(feature = "pprof")]
+    if format != "json" {
+        let profile = profiling::stacks(window).await.map_err(|e| {
+            ApiError::new(
+                StatusCode::CONFLICT,
+                "no_profile",
+                format!("Could not sample the stacks of the node: {}", e),
+            )
+        })?;
+        if format == "folded" {
+            return Ok(profile.folded().into_response());
+        }
+        let encoded = profile.pprof().map_err(|e| {
+            ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "no_profile",
+                format!("Could not encode the profile: {}", e),
+            )
+        })?;
+        return Ok((
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            encoded,
+        )
+            .into_response());
+    }
+    #[cfg(not(feature = "pprof"))]
+    if format == "pprof" {
+        return Err(ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "no_profile",
+            "Build the node with --features pprof to sample stacks",
+        ));
+    }
+    let profile = profiling::cpu(window).await.map_err(|e| {
+        ApiError::new(
+            StatusCode::NOT_IMPLEMENTED,
+            "no_profile",
+            format!("Could not read the threads of the node: {}", e),
+        )
+    })?;
+    if format == "folded" {
+        return Ok(profile.folded().into_response());
+    }
+    Ok(Json(json!(profile)).into_response())
+}
+
+async fn heap_profile() -> Json<Value> {
+    let heap = profiling::heap();
+    let mut body = json!(heap);
+    if !heap.counting {
+        body["message"] = json!("Build the node with --features profiling to count allocations");
+    }
+    Json(body)
+}
+
+async fn runtime_profile() -> Json<Value> {
+    Json(json!(profiling::runtime()))
+}
@@ -0,0 +1,196 @@
+//! `chaoschain agent list|show|set-personality|pause|resume`: the agents of
+//! a running node, through its admin API on this machine:
+//!
+//! ```bash
+//! chaoschain agent list
+//! chaoschain agent show validator-1
+//! chaoschain agent set-personality validator-1 --personality Greedy --greed 10
+//! chaoschain agent pause validator-1
+//! ```
+//!
+//! Swapping a personality restarts the agent under the same id, its memory
+//! and reviews stay with the id. A paused validator no longer counts towards
+//! the quorum.
+
+use anyhow::{bail, Context, Result};
+use chaoschain_agent::TraitOverrides;
+use chaoschain_cli::{AdminConfig, AgentCommand, AgentProfile};
+use reqwest::Method;
+use serde_json::{json, Value};
+
+use crate::admin;
+use crate::lifecycle::AgentInfo;
+
+pub async fn run(admin: &AdminConfig, command: AgentCommand) -> Result<()> {
+    match command {
+        AgentCommand::List { json } => {
+            let agents = admin::call(admin, Method::GET, "/admin/agents", None).await?;
+            if json {
+                println!("{}", agents);
+                return Ok(());
+            }
+            let agents: Vec<AgentInfo> = serde_json::from_value(agents)?;
+            if agents.is_empty() {
+                println!("No agents");
+            }
+            for agent in &agents {
+                println!(
+                    "{:<20} {:<10} {:<12} generation {:<3} {}",
+                    agent.id,
+                    agent.role,
+                    archetype(&agent.profile),
+                    agent.generation,
+                    if agent.paused { "paused" } else { "running" }
+                );
+            }
+            Ok(())
+        }
+        AgentCommand::Show { id, json } => {
+            let agent = admin::call(admin, Method::GET, &path(&id, ""), None).await?;
+            if json {
+                println!("{}", agent);
+            } else {
+                print(&agent)?;
+            }
+            Ok(())
+        }
+        AgentCommand::SetPersonality {
+            id,
+            personality,
+            chaos_level,
+            loyalty,
+            greed,
+            verbosity,
+            attention_span,
+            prompt,
+            avatar,
+        } => {
+            let change = AgentProfile {
+                personality,
+                traits: TraitOverrides {
+                    chaos_level,
+                    loyalty,
+                    greed,
+                    verbosity,
+                    attention_span,
+                },
+                model: None,
+                prompt,
+                avatar,
+            };
+            if change == AgentProfile::default() {
+                bail!("Nothing to change, give a personality, a trait, a prompt or an avatar");
+            }
+            let agent = admin::call(
+                admin,
+                Method::PUT,
+                &path(&id, "/personality"),
+                Some(&json!(change)),
+            )
+            .await?;
+            let agent: AgentInfo = serde_json::from_value(agent)?;
+            println!(
+                "{} is now {}, generation {}",
+                agent.id,
+                archetype(&agent.profile),
+                agent.generation
+            );
+            Ok(())
+        }
+        AgentCommand::Pause { id } => {
+            admin::call(admin, Method::POST, &path(&id, "/pause"), None).await?;
+            println!("{} is paused", id);
+            Ok(())
+        }
+        AgentCommand::Resume { id } => {
+            admin::call(admin, Method::POST, &path(&id, "/resume"), None).await?;
+            println!("{} is running", id);
+            Ok(())
+        }
+        AgentCommand::Simulate { .. } => unreachable!("simulate runs without a node"),
+    }
+}
+
+fn path(id: &str, action: &str) -> String {
+    format!("/admin/agents/{}{}", id, action)
+}
+
+fn archetype(profile: &AgentProfile) -> String {
+    profile
+        .personality
+        .as_ref()
+        .map_or_else(|| "random".to_string(), |p| p.to_string())
+}
+
+fn print(body: &Value) -> Result<()> {
+    let agent: AgentInfo =
+        serde_json::from_value(body.clone()).context("The node answered with no agent")?;
+    println!("id           {}", agent.id);
+    println!(
+        "role         {}, generation {}, {}",
+        agent.role,
+        agent.generation,
+        if agent.paused { "paused" } else { "running" }
+    );
+    println!("personality  {}", archetype(&agent.profile));
+    let traits = &agent.profile.traits;
+    let sliders = [
+        ("chaos_level", traits.chaos_level),
+        ("loyalty", traits.loyalty),
+        ("greed", traits.greed),
+        ("verbosity", traits.verbosity),
+        ("attention_span", traits.attention_span),
+    ];
+    let pinned: Vec<String> = sliders
+        .iter()
+        .filter_map(|(name, value)| value.map(|value| format!("{} {}", name, value)))
+        .collect();
+    println!(
+        "traits       {}",
+        if pinned.is_empty() {
+            "random".to_string()
+        } else {
+            pinned.join(", ")
+        }
+    );
+    if let Some(model) = &agent.profile.model {
+        println!("model        {}", model);
+    }
+    if let Some(prompt) = &agent.profile.prompt {
+        println!("prompt       {}", prompt);
+    }
+
+    let memory = &body["memory"];
+    if memory.is_object() {
+        println!(
+            "memory       {} memories word for word, {} digests",
+            memory["memories"], memory["digests"]
+        );
+        if let Some(digest) = memory["latest_digest"].as_str() {
+            println!("  digest     {}", digest);
+        }
+        for recent in memory["recent"].as_array().into_iter().flatten() {
+            println!("  recent     {}", recent.as_str().unwrap_or_default());
+        }
+    }
+    let reviews = &body["reviews"];
+    if reviews.is_object() {
+        let last = &reviews["last"];
+        println!(
+            "reviews      {} epochs{}",
+            reviews["epochs"],
+            match last["score"].as_f64() {
+                Some(score) => format!(", last scored {:.1} in epoch {}", score, last["epoch"]),
+                None => String::new(),
+            }
+        );
+        println!(
+            "  bias       {:+.2} towards approving",
+            reviews["approval_bias"].as_f64().unwrap_or_default()
+        );
+        for lesson in reviews["lessons"].as_array().into_iter().flatten() {
+            println!("  lesson     {}", lesson.as_str().unwrap_or_default());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,242 @@
+//! Token authentication of the APIs, configured under `[api]` in the node
+//! config. A request shows its token in the `X-Api-Key` header, or in the
+//! `api_key` query parameter where headers can't be set, as for browser
+//! WebSockets. Reads need the read-only scope, anything that changes the
+//! chain tx-submit, and running the node admin. JSON-RPC methods are
+//! checked one by one, as every call comes in through `POST /rpc`.
+
+use axum::body::Body;
+use axum::extract::{Query, State};
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chaoschain_cli::{ApiConfig, Scope};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::web::AppState;
+
+/// Header carrying the API token
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// The scope a request was let in with
+#[derive(Debug, Clone, Copy)]
+pub struct Granted(pub Scope);
+
+impl Granted {
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.0 >= scope
+    }
+}
+
+/// POST endpoints that only read
+const QUERIES: &[&str] = &["/rpc", "/graphql", "/api/crypto/state/proof"];
+
+/// Endpoints that run the node rather than the chain
+const ADMIN_PREFIXES: &[&str] = &["/api/lifecycle/", "/api/network/links"];
+
+/// What a request needs before it reaches its handler
+pub fn required_scope(method: &Method, path: &str) -> Scope {
+    let reads = matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS);
+    if reads || QUERIES.contains(&path) {
+        Scope::ReadOnly
+    } else if ADMIN_PREFIXES.iter().any(|prefix| path.starts_with(prefix)) {
+        Scope::Admin
+    } else {
+        Scope::TxSubmit
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiKeyQuery {
+    api_key: Option<String>,
+}
+
+/// The token of the header, or of the query string, percent-decoded
+pub fn token_of(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .or_else(|| {
+            Query::<ApiKeyQuery>::try_from_uri(req.uri())
+                .ok()?
+                .0
+                .api_key
+        })
+}
+
+/// Why a request was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// No token, or one we never gave out
+    Unauthenticated,
+    /// A token whose scope falls short
+    Forbidden(Scope),
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unauthenticated => write!(f, "Missing or unknown API key"),
+            Self::Forbidden(required) => write!(f, "This needs the {} scope", required),
+        }
+    }
+}
+
+/// Whether `token` reaches `required`, whatever the API it came in on
+pub fn authorize(api: &ApiConfig, token: Option<&str>, required: Scope) -> Result<Scope, Refusal> {
+    match api.scope_of(token) {
+        Some(granted) if granted >= required => Ok(granted),
+        Some(_) if token.is_some() => Err(Refusal::Forbidden(required)),
+        _ => Err(Refusal::Unauthenticated),
+    }
+}
+
+pub async fn require_scope(
+    State(state): State<Arc<AppState>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    let token = token_of(&req);
+    let required = required_scope(req.method(), req.uri().path());
+    let granted = match authorize(&state.api, token.as_deref(), required) {
+        Ok(granted) => granted,
+        Err(refusal) => {
+            let status = match refusal {
+                Refusal::Unauthenticated => StatusCode::UNAUTHORIZED,
+                Refusal::Forbidden(_) => StatusCode::FORBIDDEN,
+            };
+            return (status, refusal.to_string()).into_response();
+        }
+    };
+    if let Some(name) = token.as_deref().and_then(|token| state.api.name_of(token)) {
+        debug!("{} {} by {}", req.method(), req.uri().path(), name);
+    }
+    req.extensions_mut().insert(Granted(granted));
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_cli::ApiToken;
+
+    fn api() -> ApiConfig {
+        let token = |name: &str, token: &str, scope| ApiToken {
+            name: name.to_string(),
+            token: token.to_string(),
+            scope,
+        };
+        ApiConfig {
+            tokens: vec![
+                token("explorer", "read-me", Scope::ReadOnly),
+                token("wallet", "send+me/now", Scope::TxSubmit),
+                token("operator", "run-me", Scope::Admin),
+            ],
+            ..ApiConfig::default()
+        }
+    }
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::builder().uri(uri).body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(required_scope(&Method::GET, "/api/blocks"), Scope::ReadOnly);
+        assert_eq!(
+            required_scope(&Method::OPTIONS, "/api/lifecycle/agents"),
+            Scope::ReadOnly
+        );
+        assert_eq!(required_scope(&Method::POST, "/rpc"), Scope::ReadOnly);
+        assert_eq!(required_scope(&Method::POST, "/graphql"), Scope::ReadOnly);
+        assert_eq!(
+            required_scope(&Method::POST, "/api/transactions"),
+            Scope::TxSubmit
+        );
+        assert_eq!(
+            required_scope(&Method::DELETE, "/api/lifecycle/agents/alice"),
+            Scope::Admin
+        );
+        assert_eq!(
+            required_scope(&Method::PUT, "/api/network/links"),
+            Scope::Admin
+        );
+    }
+
+    #[test]
+    fn test_authorize_each_scope() {
+        let api = api();
+        for (token, granted) in [
+            ("read-me", Scope::ReadOnly),
+            ("send+me/now", Scope::TxSubmit),
+            ("run-me", Scope::Admin),
+        ] {
+            for required in [Scope::ReadOnly, Scope::TxSubmit, Scope::Admin] {
+                let expected = if granted >= required {
+                    Ok(granted)
+                } else {
+                    Err(Refusal::Forbidden(required))
+                };
+                assert_eq!(authorize(&api, Some(token), required), expected);
+            }
+        }
+
+        // Missing and wrong tokens are not let in at all
+        assert_eq!(
+            authorize(&api, None, Scope::ReadOnly),
+            Err(Refusal::Unauthenticated)
+        );
+        assert_eq!(
+            authorize(&api, Some("run-me "), Scope::ReadOnly),
+            Err(Refusal::Unauthenticated)
+        );
+        assert_eq!(
+            authorize(&api, Some(""), Scope::ReadOnly),
+            Err(Refusal::Unauthenticated)
+        );
+
+        // Anonymous requests get what the config gives them, no more
+        let anonymous = ApiConfig {
+            anonymous: Some(Scope::ReadOnly),
+            ..api
+        };
+        assert_eq!(
+            authorize(&anonymous, None, Scope::ReadOnly),
+            Ok(Scope::ReadOnly)
+        );
+        assert_eq!(
+            authorize(&anonymous, None, Scope::TxSubmit),
+            Err(Refusal::Unauthenticated)
+        );
+
+        // Without tokens the APIs are open
+        assert_eq!(
+            authorize(&ApiConfig::default(), None, Scope::Admin),
+            Ok(Scope::Admin)
+        );
+    }
+
+    #[test]
+    fn test_token_of() {
+        assert_eq!(token_of(&request("/api/blocks")), None);
+        assert_eq!(
+            token_of(&request("/api/ws?agent_id=a&api_key=read-me")).as_deref(),
+            Some("read-me")
+        );
+        // Tokens travel percent-encoded in query strings
+        assert_eq!(
+            token_of(&request("/api/ws?api_key=send%2Bme%2Fnow")).as_deref(),
+            Some("send+me/now")
+        );
+        assert_eq!(token_of(&request("/api/ws?api_key")).as_deref(), Some(""));
+
+        // The header goes first
+        let mut req = request("/api/ws?api_key=read-me");
+        req.headers_mut()
+            .insert(API_KEY_HEADER, "run-me".parse().unwrap());
+        assert_eq!(token_of(&req).as_deref(), Some("run-me"));
+    }
+}
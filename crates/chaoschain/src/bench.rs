@@ -0,0 +1,350 @@
+//! `chaoschain bench`: transaction throughput of one node or several.
+//! Synthetic transactions are signed with throwaway keys and submitted over
+//! JSON-RPC at a target rate, each key to one node so that its nonces
+//! arrive in order. The blocks of every node are followed meanwhile, to tell
+//! when each accepted transaction is included:
+//!
+//! ```bash
+//! chaoschain bench --rate 200 --duration 60 --node http://127.0.0.1:3000 --node http://127.0.0.1:3001
+//! chaoschain bench --json | jq .tps
+//! ```
+//!
+//! The report gives the rate transactions were submitted and included at,
+//! the inclusion latency percentiles and why the mempools turned
+//! transactions down. Progress goes to standard error.
+
+use anyhow::{bail, Result};
+use ed25519_dalek::SigningKey;
+use parking_lot::Mutex;
+use rand::rngs::OsRng;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::tx::{self, Rpc};
+
+/// Pause between two looks at the blocks of a node
+const BLOCK_POLL: Duration = Duration::from_millis(200);
+
+/// What to run
+pub struct Plan {
+    pub nodes: Vec<String>,
+    pub api_key: Option<String>,
+    /// Transactions per second, over all senders
+    pub rate: f64,
+    pub duration: Duration,
+    pub senders: usize,
+    pub payload_size: usize,
+    /// How long to wait for inclusion once sending stops
+    pub settle: Duration,
+    pub json: bool,
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+    sent: usize,
+    /// When each accepted transaction was sent, until it is included
+    pending: HashMap<String, Instant>,
+    accepted: usize,
+    rejected: BTreeMap<String, usize>,
+    /// Calls that never got an answer
+    failed: usize,
+    last_failure: Option<String>,
+    latencies: Vec<Duration>,
+    last_inclusion: Option<Instant>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    nodes: usize,
+    senders: usize,
+    target_rate: f64,
+    duration_secs: f64,
+    sent: usize,
+    accepted: usize,
+    /// Count by reason, as the nodes give it
+    rejected: BTreeMap<String, usize>,
+    /// Share of the transactions sent that a mempool turned down
+    rejection_rate: f64,
+    failed: usize,
+    /// Transactions accepted per second while sending
+    submitted_tps: f64,
+    included: usize,
+    /// Transactions included per second, from the first sent to the last
+    /// included
+    tps: f64,
+    latency_ms: Option<Percentiles>,
+}
+
+#[derive(Debug, Serialize)]
+struct Percentiles {
+    p50: u64,
+    p90: u64,
+    p99: u64,
+    max: u64,
+}
+
+impl Percentiles {
+    fn of(latencies: &mut [Duration]) -> Option<Self> {
+        latencies.sort();
+        let at = |share: f64| {
+            let index = ((latencies.len() as f64 * share).ceil() as usize).saturating_sub(1);
+            latencies[index].as_millis() as u64
+        };
+        (!latencies.is_empty()).then(|| Self {
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+            max: at(1.0),
+        })
+    }
+}
+
+pub async fn run(plan: Plan) -> Result<()> {
+    if plan.nodes.is_empty() {
+        bail!("No node to benchmark");
+    }
+    if !plan.rate.is_finite() || plan.rate <= 0.0 {
+        bail!("The rate has to be above zero");
+    }
+    let senders = plan.senders.max(1);
+    let tally = Arc::new(Mutex::new(Tally::default()));
+    let (stop, stopped) = watch::channel(false);
+
+    let mut watchers = Vec::new();
+    for node in &plan.nodes {
+        let rpc = Rpc::new(node, plan.api_key.clone())?;
+        watchers.push(tokio::spawn(follow_blocks(
+            rpc,
+            tally.clone(),
+            stopped.clone(),
+        )));
+    }
+
+    eprintln!(
+        "Sending {} transactions a second for {} seconds from {} senders to {} nodes",
+        plan.rate,
+        plan.duration.as_secs(),
+        senders,
+        plan.nodes.len()
+    );
+    let started = Instant::now();
+    let until = started + plan.duration;
+    let period = Duration::from_secs_f64(senders as f64 / plan.rate);
+    let mut sending = Vec::new();
+    for index in 0..senders {
+        let rpc = Rpc::new(&plan.nodes[index % plan.nodes.len()], plan.api_key.clone())?;
+        sending.push(tokio::spawn(send_from(
+            rpc,
+            index,
+            period,
+            until,
+            plan.payload_size,
+            tally.clone(),
+        )));
+    }
+    let mut progress = tokio::time::interval(Duration::from_secs(1));
+    let settled = until + plan.settle;
+    loop {
+        progress.tick().await;
+        let now = Instant::now();
+        let (sent, accepted, waiting) = {
+            let tally = tally.lock();
+            (tally.sent, tally.accepted, tally.pending.len())
+        };
+        eprint!(
+            "\r{:>4}s  sent {}, accepted {}, waiting for a block {}   ",
+            started.elapsed().as_secs(),
+            sent,
+            accepted,
+            waiting
+        );
+        if now >= settled || (now >= until && waiting == 0) {
+            break;
+        }
+    }
+    eprintln!();
+    for task in sending {
+        task.await?;
+    }
+    stop.send(true)?;
+    for task in watchers {
+        task.await?;
+    }
+
+    let mut tally = std::mem::take(&mut *tally.lock());
+    if let Some(failure) = &tally.last_failure {
+        eprintln!("{} calls failed, the last with: {}", tally.failed, failure);
+    }
+    let sending_secs = plan.duration.as_secs_f64();
+    let rejected: usize = tally.rejected.values().sum();
+    let included = tally.latencies.len();
+    let report = Report {
+        nodes: plan.nodes.len(),
+        senders,
+        target_rate: plan.rate,
+        duration_secs: sending_secs,
+        sent: tally.sent,
+        accepted: tally.accepted,
+        rejection_rate: share(rejected, tally.sent),
+        rejected: tally.rejected,
+        failed: tally.failed,
+        submitted_tps: tally.accepted as f64 / sending_secs,
+        included,
+        tps: tally.last_inclusion.map_or(0.0, |last| {
+            included as f64 / last.duration_since(started).as_secs_f64().max(f64::EPSILON)
+        }),
+        latency_ms: Percentiles::of(&mut tally.latencies),
+    };
+    if plan.json {
+        println!("{}", serde_json::to_string(&report)?);
+    } else {
+        print(&report);
+    }
+    Ok(())
+}
+
+/// Send from one throwaway key every `period` until `until`
+async fn send_from(
+    mut rpc: Rpc,
+    index: usize,
+    period: Duration,
+    until: Instant,
+    payload_size: usize,
+    tally: Arc<Mutex<Tally>>,
+) {
+    let key = SigningKey::generate(&mut OsRng);
+    let mut nonce = 0;
+    let mut ticks = tokio::time::interval(period);
+    loop {
+        ticks.tick().await;
+        let sent_at = Instant::now();
+        if sent_at >= until {
+            return;
+        }
+        let mut payload = format!("bench {} {}", index, nonce).into_bytes();
+        payload.resize(payload.len().max(payload_size), b'.');
+        let Ok(raw) = tx::sign(&key, nonce, payload).and_then(|tx| tx::raw(&tx)) else {
+            return;
+        };
+        let answer = rpc.try_call("chain_sendRawTransaction", json!([raw])).await;
+        let mut tally = tally.lock();
+        tally.sent += 1;
+        match answer {
+            Ok(Ok(hash)) => {
+                tally.accepted += 1;
+                tally
+                    .pending
+                    .insert(hash.as_str().unwrap_or_default().to_string(), sent_at);
+                nonce += 1;
+            }
+            Ok(Err(failure)) => {
+                let reason = failure.data["reason"]
+                    .as_str()
+                    .unwrap_or("unknown")
+                    .to_string();
+                *tally.rejected.entry(reason).or_default() += 1;
+                // Pick up where the node is, rather than keep sending gaps
+                if let Some(expected) = failure.data["expected_nonce"].as_u64() {
+                    nonce = expected;
+                }
+            }
+            Err(e) => {
+                tally.failed += 1;
+                tally.last_failure = Some(format!("{:#}", e));
+            }
+        }
+    }
+}
+
+/// Mark the pending transactions in the new blocks of a node as included
+async fn follow_blocks(mut rpc: Rpc, tally: Arc<Mutex<Tally>>, mut stopped: watch::Receiver<bool>) {
+    let mut seen = head(&mut rpc).await;
+    loop {
+        tokio::select! {
+            _ = stopped.changed() => return,
+            _ = tokio::time::sleep(BLOCK_POLL) => {}
+        }
+        let Some(latest) = head(&mut rpc).await else {
+            continue;
+        };
+        for height in seen.map_or(0, |seen| seen + 1)..=latest {
+            let Ok(block) = rpc
+                .call("chain_getBlockByNumber", json!([height, false]))
+                .await
+            else {
+                break;
+            };
+            let now = Instant::now();
+            let mut tally = tally.lock();
+            for hash in block["transactions"].as_array().into_iter().flatten() {
+                let sent_at = hash.as_str().and_then(|hash| tally.pending.remove(hash));
+                if let Some(sent_at) = sent_at {
+                    tally.latencies.push(now.duration_since(sent_at));
+                    tally.last_inclusion = Some(now);
+                }
+            }
+            seen = Some(height);
+        }
+    }
+}
+
+async fn head(rpc: &mut Rpc) -> Option<u64> {
+    rpc.call("chain_blockNumber", json!([]))
+        .await
+        .ok()?
+        .as_u64()
+}
+
+fn share(part: usize, whole: usize) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+fn print(report: &Report) {
+    println!(
+        "sent       {} in {:.0}s to {} nodes, at {} a second targeted",
+        report.sent, report.duration_secs, report.nodes, report.target_rate
+    );
+    println!(
+        "accepted   {} ({:.1}%), {:.1} a second",
+        report.accepted,
+        share(report.accepted, report.sent) * 100.0,
+        report.submitted_tps
+    );
+    let reasons: Vec<String> = report
+        .rejected
+        .iter()
+        .map(|(reason, count)| format!("{} {}", count, reason))
+        .collect();
+    println!(
+        "rejected   {} ({:.1}%){}",
+        report.rejected.values().sum::<usize>(),
+        report.rejection_rate * 100.0,
+        if reasons.is_empty() {
+            String::new()
+        } else {
+            format!(": {}", reasons.join(", "))
+        }
+    );
+    if report.failed > 0 {
+        println!("failed     {}, without an answer", report.failed);
+    }
+    println!(
+        "included   {} of {}, {:.1} a second",
+        report.included, report.accepted, report.tps
+    );
+    match &report.latency_ms {
+        Some(latency) => println!(
+            "latency    p50 {}ms, p90 {}ms, p99 {}ms, max {}ms",
+            latency.p50, latency.p90, latency.p99, latency.max
+        ),
+        None => println!("latency    none included"),
+    }
+}
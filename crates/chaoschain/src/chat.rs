@@ -0,0 +1,279 @@
+//! Spectators talking to the validators. `POST /api/v1/agents/{id}/chat`
+//! hands a message to a running validator, which answers in character as a
+//! stream of server-sent events: `chunk` events while the reply is being
+//! written, then one `reply` event with the whole of it. The message and
+//! the reply are moderated, and the agent remembers the conversation in
+//! its next prompts. Public conversations show up in the drama feed.
+//!
+//! Chatting needs the `tx-submit` scope, and messages come out of a rate
+//! limit of their own, as every one of them costs a model call.
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::post;
+use axum::{Json, Router};
+use chaoschain_agent::{
+    ChatContext, ChatEngine, ContextManager, Moderator, PersonalityTraits, MODERATED,
+};
+use chaoschain_core::NetworkEvent;
+use futures::stream::{Stream, StreamExt};
+use parking_lot::RwLock;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tracing::warn;
+
+use crate::lifecycle::AgentRole;
+use crate::openapi::{boolean, schema_ref, string, ApiSchema, Object, Operation};
+use crate::rest::ApiError;
+use crate::web::AppState;
+
+/// Longest message a spectator may send, in characters
+pub const MAX_MESSAGE: usize = 500;
+
+/// Longest name a spectator may go by
+const MAX_NAME: usize = 40;
+
+/// What spectators are called when they give no name
+const ANONYMOUS: &str = "a spectator";
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/api/v1/agents/:id/chat", post(chat))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![Operation::post(
+        "/api/v1/agents/{id}/chat",
+        "agents",
+        "Talk to a validator and stream its reply",
+        schema_ref::<ChatRequest>(),
+    )
+    .path_param("id", string(), "Agent id")
+    .streams("`chunk` events with parts of the reply, then one `reply` event, or an `error` event")]
+}
+
+/// Whether requests to `path` are chat messages, for the rate limiter
+pub fn is_chat(path: &str) -> bool {
+    path.strip_prefix("/api/v1/agents/")
+        .and_then(|rest| rest.strip_suffix("/chat"))
+        .is_some_and(|id| !id.is_empty() && !id.contains('/'))
+}
+
+/// How an agent presents itself to spectators
+#[derive(Debug, Clone)]
+struct Character {
+    persona: String,
+    traits: PersonalityTraits,
+}
+
+/// Where spectators meet the agents. Agents introduce themselves when they
+/// start and whenever their character changes.
+pub struct ChatDesk {
+    characters: RwLock<HashMap<String, Character>>,
+    engine: Arc<dyn ChatEngine>,
+    memory: Arc<ContextManager>,
+    moderator: Arc<Moderator>,
+    tx: broadcast::Sender<NetworkEvent>,
+}
+
+impl ChatDesk {
+    pub fn new(
+        engine: Arc<dyn ChatEngine>,
+        memory: Arc<ContextManager>,
+        moderator: Arc<Moderator>,
+        tx: broadcast::Sender<NetworkEvent>,
+    ) -> Self {
+        Self {
+            characters: RwLock::new(HashMap::new()),
+            engine,
+            memory,
+            moderator,
+            tx,
+        }
+    }
+
+    pub fn introduce(&self, agent: &str, persona: &str, traits: PersonalityTraits) {
+        self.characters.write().insert(
+            agent.to_string(),
+            Character {
+                persona: persona.to_string(),
+                traits,
+            },
+        );
+    }
+
+    /// What `agent` gets to see of a message, none for an agent that never
+    /// introduced itself
+    fn context(&self, agent: &str, speaker: &str, message: &str) -> Option<ChatContext> {
+        let character = self.characters.read().get(agent).cloned()?;
+        // Newest memories first, the attention span cuts off the oldest
+        let memory = self.memory.memory(agent);
+        let notes = memory
+            .recent
+            .iter()
+            .rev()
+            .map(|memory| format!("Recently: {}", memory))
+            .chain(
+                memory
+                    .digests
+                    .iter()
+                    .rev()
+                    .map(|digest| format!("Long ago: {}", digest)),
+            )
+            .collect();
+        Some(ChatContext {
+            agent: agent.to_string(),
+            persona: character.persona,
+            traits: character.traits,
+            speaker: speaker.to_string(),
+            message: message.to_string(),
+            notes,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChatRequest {
+    pub message: String,
+    /// What the agent should call the spectator
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Whether the conversation shows up in the drama feed
+    #[serde(default)]
+    pub public: bool,
+}
+
+impl ApiSchema for ChatRequest {
+    const NAME: &'static str = "ChatMessage";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("message", string())
+            .optional("name", string())
+            .optional("public", boolean())
+            .build()
+    }
+}
+
+async fn chat(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(request): Json<ChatRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let desk = state.chat.clone().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no_agents",
+            "This node runs no agents to talk to",
+        )
+    })?;
+
+    let message = request.message.trim();
+    if message.is_empty() {
+        return Err(ApiError::bad_request("The message is empty"));
+    }
+    if message.chars().count() > MAX_MESSAGE {
+        return Err(ApiError::bad_request(format!(
+            "Messages are at most {} characters",
+            MAX_MESSAGE
+        )));
+    }
+    let speaker = match request.name.as_deref().map(str::trim) {
+        Some(name) if name.chars().count() > MAX_NAME => {
+            return Err(ApiError::bad_request(format!(
+                "Names are at most {} characters",
+                MAX_NAME
+            )))
+        }
+        Some(name) if !name.is_empty() => desk.moderator.redact(name),
+        _ => ANONYMOUS.to_string(),
+    };
+
+    let running = match &state.agents {
+        Some(agents) => agents
+            .list()
+            .await
+            .iter()
+            .any(|agent| agent.id == id && agent.role == AgentRole::Validator),
+        None => false,
+    };
+    if !running {
+        return Err(ApiError::not_found(format!("No validator {}", id)));
+    }
+
+    let message = desk.moderator.moderate(message).await;
+    if message.starts_with(MODERATED) {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "moderated",
+            "The message was flagged by moderation",
+        ));
+    }
+    let context = desk
+        .context(&id, &speaker, &message)
+        .ok_or_else(|| ApiError::not_found(format!("{} is not taking visitors yet", id)))?;
+    if request.public {
+        let _ = desk.tx.send(NetworkEvent::AgentChat {
+            message: format!("💬 {} to {}: {}", speaker, id, message),
+            sender: format!("spectator:{}", speaker),
+            meme_url: None,
+        });
+    }
+
+    let (events, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        // Chunks only get the local rules, the reply is moderated as a whole
+        let mut on_chunk = |chunk: &str| {
+            let _ = events.send(
+                Event::default()
+                    .event("chunk")
+                    .data(desk.moderator.redact(chunk)),
+            );
+        };
+        let completion = match desk.engine.reply(&context, &mut on_chunk).await {
+            Ok(completion) => completion,
+            Err(e) => {
+                warn!("{} could not answer {}: {}", id, speaker, e);
+                let _ = events.send(
+                    Event::default()
+                        .event("error")
+                        .json_data(json!({ "message": format!("{} is lost for words", id) }))
+                        .unwrap_or_default(),
+                );
+                return;
+            }
+        };
+        let reply = desk.moderator.moderate(completion.text.trim()).await;
+        let _ = events.send(
+            Event::default()
+                .event("reply")
+                .json_data(json!({ "agent": id, "reply": reply, "model": completion.model }))
+                .unwrap_or_default(),
+        );
+
+        desk.memory.remember(
+            &id,
+            format!(
+                "{}, a spectator, told you: \"{}\". You answered: \"{}\"",
+                speaker, message, reply
+            ),
+        );
+        let memory = desk.memory.clone();
+        let agent = id.clone();
+        tokio::spawn(async move { memory.compact(&agent).await });
+        if request.public {
+            let _ = desk.tx.send(NetworkEvent::AgentChat {
+                message: format!("💬 {} to {}: {}", id, speaker, reply),
+                sender: id,
+                meme_url: None,
+            });
+        }
+    });
+
+    Ok(Sse::new(UnboundedReceiverStream::new(rx).map(Ok)).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,160 @@
+//! Compression of the web server's responses, Brotli or gzip, whichever
+//! the client's `Accept-Encoding` prefers. Event streams and WebSocket
+//! upgrades pass through as they are, and so do bodies under
+//! `compression_min_size`, bodies that are already compressed, errors and
+//! content types that do not shrink.
+
+use axum::body::HttpBody;
+use axum::http::header;
+use axum::http::Response;
+use chaoschain_cli::ApiConfig;
+use tower_http::compression::predicate::{Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+
+/// Compression of the responses as `[api]` asks
+pub fn layer(config: &ApiConfig) -> CompressionLayer<Compressible> {
+    let min_size = config.compression_min_size.min(u16::MAX as usize) as u16;
+    CompressionLayer::new()
+        .no_deflate()
+        .no_zstd()
+        .compress_when(Compressible {
+            enabled: config.compression,
+            min_size: SizeAbove::new(min_size),
+        })
+}
+
+/// Successful text that compression shrinks, and not yet encoded
+#[derive(Clone, Copy)]
+pub struct Compressible {
+    enabled: bool,
+    min_size: SizeAbove,
+}
+
+impl Predicate for Compressible {
+    fn should_compress<B: HttpBody>(&self, response: &Response<B>) -> bool {
+        if !self.enabled
+            || !response.status().is_success()
+            || !self.min_size.should_compress(response)
+            || response.headers().contains_key(header::CONTENT_ENCODING)
+        {
+            return false;
+        }
+        let Some(content_type) = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return false;
+        };
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        if essence == "text/event-stream" {
+            return false;
+        }
+        essence.starts_with("text/")
+            || essence.ends_with("json")
+            || essence.ends_with("javascript")
+            || essence.ends_with("xml")
+            || essence == "application/openmetrics-text"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use axum::routing::get;
+    use axum::Router;
+    use std::io::Read;
+    use tower::ServiceExt;
+
+    fn blocks() -> String {
+        (0..200)
+            .map(|height| {
+                format!(
+                    r#"{{"height":{},"producer":"producer-1","drama_level":7}}"#,
+                    height
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn app(config: &ApiConfig) -> Router {
+        Router::new()
+            .route(
+                "/blocks",
+                get(|| async { ([(header::CONTENT_TYPE, "application/json")], blocks()) }),
+            )
+            .route(
+                "/small",
+                get(|| async { ([(header::CONTENT_TYPE, "application/json")], "{}") }),
+            )
+            .route(
+                "/events",
+                get(|| async { ([(header::CONTENT_TYPE, "text/event-stream")], blocks()) }),
+            )
+            .layer(layer(config))
+    }
+
+    /// The encoding `path` came back in, and its body
+    async fn fetch(app: Router, path: &str, accept: &str) -> (Option<String>, Vec<u8>) {
+        let request = Request::get(path)
+            .header(header::ACCEPT_ENCODING, accept)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let encoding = response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (encoding, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_responses_decode_to_what_was_sent() {
+        let config = ApiConfig::default();
+
+        let (encoding, body) = fetch(app(&config), "/blocks", "gzip").await;
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        assert!(body.len() < blocks().len() / 4);
+        let mut plain = String::new();
+        flate2::read::GzDecoder::new(body.as_slice())
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, blocks());
+
+        let (encoding, body) = fetch(app(&config), "/blocks", "gzip;q=0.5, br").await;
+        assert_eq!(encoding.as_deref(), Some("br"));
+        let mut plain = String::new();
+        brotli::Decompressor::new(body.as_slice(), 4096)
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, blocks());
+
+        let (encoding, body) = fetch(app(&config), "/blocks", "identity").await;
+        assert_eq!(encoding, None);
+        assert_eq!(body, blocks().as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_what_is_left_alone() {
+        let config = ApiConfig::default();
+        assert_eq!(fetch(app(&config), "/small", "gzip, br").await.0, None);
+        assert_eq!(fetch(app(&config), "/events", "gzip, br").await.0, None);
+
+        let off = ApiConfig {
+            compression: false,
+            ..ApiConfig::default()
+        };
+        let (encoding, body) = fetch(app(&off), "/blocks", "gzip, br").await;
+        assert_eq!(encoding, None);
+        assert_eq!(body, blocks().as_bytes());
+    }
+}
@@ -0,0 +1,133 @@
+//! `chaoschain db stats|compact|prune`: upkeep of a node's data directory,
+//! worked on directly while the node is stopped:
+//!
+//! ```bash
+//! chaoschain db stats --data-dir data
+//! chaoschain db compact
+//! chaoschain db prune --height 400
+//! ```
+//!
+//! `stats` checks every snapshot and line of the activity journal, and
+//! fails when something is wrong, saying how to set it right. See
+//! [`chaoschain_state::maintenance`].
+
+use anyhow::{bail, Result};
+use chaoschain_cli::DbCommand;
+use chaoschain_state::maintenance::{self, DataDirStats};
+use std::path::Path;
+
+use crate::peers::size;
+
+pub fn run(data_dir: &Path, command: DbCommand) -> Result<()> {
+    match command {
+        DbCommand::Stats { json } => {
+            let stats = maintenance::stats(data_dir)?;
+            if json {
+                println!("{}", serde_json::to_string(&stats)?);
+            } else {
+                print(&stats);
+            }
+            if !stats.issues.is_empty() {
+                bail!(
+                    "{} {} in {}",
+                    stats.issues.len(),
+                    if stats.issues.len() == 1 {
+                        "problem"
+                    } else {
+                        "problems"
+                    },
+                    data_dir.display()
+                );
+            }
+            Ok(())
+        }
+        DbCommand::Compact => {
+            let compaction = maintenance::compact(data_dir)?;
+            for file in &compaction.files_removed {
+                println!("Removed {}", file.display());
+            }
+            println!(
+                "Activity journal from {} to {}, {} records dropped",
+                size(compaction.bytes_before),
+                size(compaction.bytes_after),
+                compaction.records_dropped
+            );
+            Ok(())
+        }
+        DbCommand::Prune { height } => {
+            let pruning = maintenance::prune(data_dir, height)?;
+            println!(
+                "Removed {} snapshots{} and {} activity records below height {}, {} freed",
+                pruning.snapshots_removed.len(),
+                match pruning.snapshots_removed.as_slice() {
+                    [] => String::new(),
+                    heights => format!(
+                        " (at {})",
+                        heights
+                            .iter()
+                            .map(u64::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                },
+                pruning.records_removed,
+                height,
+                size(pruning.bytes_freed)
+            );
+            Ok(())
+        }
+    }
+}
+
+fn print(stats: &DataDirStats) {
+    println!("{}  {}", stats.path.display(), size(stats.bytes));
+    match (stats.snapshots.first(), stats.snapshots.last()) {
+        (Some(first), Some(last)) => println!(
+            "snapshots  {}, heights {} to {}",
+            stats.snapshots.len(),
+            first.height,
+            last.height
+        ),
+        _ => println!("snapshots  none"),
+    }
+    for snapshot in &stats.snapshots {
+        println!(
+            "  snapshot-{:<8} {:>10}  {:>4} chunks  {:>7} entries  {}",
+            snapshot.height,
+            size(snapshot.bytes),
+            snapshot.chunks,
+            snapshot.entries,
+            snapshot.problem.as_deref().unwrap_or("ok")
+        );
+    }
+    match &stats.journal {
+        Some(journal) => {
+            println!(
+                "activity   {} records in {} lines, {}{}",
+                journal.records,
+                journal.lines,
+                size(journal.bytes),
+                journal
+                    .heights
+                    .map_or_else(String::new, |(low, high)| format!(
+                        ", heights {} to {}",
+                        low, high
+                    ))
+            );
+            println!(
+                "           {} unreadable, {} repeated, {} past what the node keeps",
+                journal.unreadable.len(),
+                journal.duplicates,
+                journal.beyond_kept
+            );
+        }
+        None => println!("activity   none"),
+    }
+    if stats.issues.is_empty() {
+        println!("No problems found");
+    }
+    for issue in &stats.issues {
+        println!("{}: {}", issue.path.display(), issue.problem);
+        println!("  {}", issue.repair);
+    }
+}
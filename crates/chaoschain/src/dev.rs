@@ -0,0 +1,225 @@
+//! `chaoschain dev`: a chain of one node to build against, as `anvil` or
+//! `hardhat node` are for Ethereum:
+//!
+//! ```bash
+//! chaoschain dev
+//! chaoschain dev --block-time 2 --accounts 3
+//! ```
+//!
+//! The node seals a block as soon as a transaction comes in, or every
+//! `--block-time` seconds with what waits by then. A dev validator votes
+//! each block through at once, any agents run offline, and the accounts of
+//! the dev mnemonic start funded. Other addresses get test balance from the
+//! faucet, see [`crate::faucet`]. The chain lives in a temporary directory
+//! that is removed on exit.
+
+use anyhow::Result;
+use chaoschain_consensus::ConsensusManager;
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{Block, BlockMetadata, NetworkEvent, Transaction};
+use chaoschain_crypto::keystore::{self, Keystore};
+use chaoschain_mempool::Mempool;
+use chaoschain_state::StateStoreImpl;
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+use crate::faucet::Faucet;
+
+/// The well known mnemonic of dev accounts, never to hold anything of value
+pub const DEV_MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+/// Producer and validator of every dev block
+const SEALER: &str = "dev";
+
+/// Most transactions a block sealed on an interval takes
+const MAX_BLOCK_TRANSACTIONS: usize = 100;
+
+/// The dev chain a node seals
+pub struct DevChain {
+    /// Accounts funded at genesis, in the order of the mnemonic
+    pub accounts: Vec<SigningKey>,
+    /// What each account starts with
+    pub balance: u64,
+    /// Time between two blocks, or a block per transaction without one
+    pub block_time: Option<Duration>,
+    /// What the faucet gives, and how often
+    pub faucet_drip: u64,
+    pub faucet_cooldown: Duration,
+    sealer: SigningKey,
+}
+
+impl DevChain {
+    pub fn new(
+        mnemonic: &str,
+        accounts: u32,
+        balance: u64,
+        block_time: Option<Duration>,
+        (faucet_drip, faucet_cooldown): (u64, Duration),
+    ) -> Result<Self> {
+        Ok(Self {
+            accounts: (0..accounts)
+                .map(|index| keystore::key_from_mnemonic(mnemonic, index))
+                .collect::<Result<_, _>>()?,
+            balance,
+            block_time,
+            faucet_drip,
+            faucet_cooldown,
+            sealer: SigningKey::generate(&mut OsRng),
+        })
+    }
+
+    /// Keep the accounts in the keystore at `dir` as `dev-0`, `dev-1`, ...
+    pub fn write_keys(&self, dir: &Path) -> Result<()> {
+        let keystore = Keystore::new(dir);
+        for (index, key) in self.accounts.iter().enumerate() {
+            keystore.add(
+                &format!("dev-{}", index),
+                key,
+                None,
+                Some(keystore::derivation_path(index as u32)),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The faucet of the chain kept in `state`
+    pub fn faucet(&self, state: Arc<StateStoreImpl>) -> Faucet {
+        Faucet::new(state, self.faucet_drip, self.faucet_cooldown)
+    }
+
+    /// Fund the accounts, then seal blocks until the node stops
+    pub async fn run(
+        self,
+        state: Arc<StateStoreImpl>,
+        consensus: Arc<ConsensusManager>,
+        mempool: Arc<Mempool>,
+        tx: broadcast::Sender<NetworkEvent>,
+    ) {
+        for account in &self.accounts {
+            state.fund(&account.verifying_key(), self.balance, "genesis_allocation");
+        }
+        let sealer = Sealer {
+            key: self.sealer,
+            state,
+            consensus,
+            tx,
+        };
+
+        match self.block_time {
+            Some(block_time) => {
+                let mut interval = tokio::time::interval(block_time);
+                // The first tick is right away, the first block one block time in
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    sealer
+                        .seal(mempool.take(MAX_BLOCK_TRANSACTIONS).await)
+                        .await;
+                }
+            }
+            None => {
+                let mut added = mempool.subscribe();
+                loop {
+                    match added.recv().await {
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break,
+                    }
+                    loop {
+                        let transactions = mempool.take(1).await;
+                        if transactions.is_empty() {
+                            break;
+                        }
+                        sealer.seal(transactions).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Sealer {
+    key: SigningKey,
+    state: Arc<StateStoreImpl>,
+    consensus: Arc<ConsensusManager>,
+    tx: broadcast::Sender<NetworkEvent>,
+}
+
+impl Sealer {
+    /// Propose a block of `transactions`, vote it through and store it
+    async fn seal(&self, transactions: Vec<Transaction>) {
+        let parent = self.state.get_latest_block();
+        let mut block = Block {
+            height: parent.as_ref().map_or(1, |parent| parent.height + 1),
+            transactions,
+            proposer_sig: [0u8; 64],
+            parent_hash: parent.map_or([0u8; 32], |parent| parent.hash()),
+            state_root: self.state.state_root(),
+            drama_level: 0,
+            producer_mood: "instant".to_string(),
+            producer_id: SEALER.to_string(),
+            innovation_level: 0,
+            producer_strategy: "InstantSeal".to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            metadata: BlockMetadata::default(),
+        };
+        self.state.forks().at(block.height).stamp(&mut block);
+        block.proposer_sig = self.key.sign(&block.signing_bytes()).to_bytes();
+        let height = block.height;
+        let block_hash = block.hash();
+
+        self.consensus.start_voting_round(block.clone()).await;
+        let vote = SignedVote::sign(
+            &self.key,
+            SEALER,
+            height,
+            block_hash,
+            true,
+            0,
+            "Sealed by the dev node",
+        );
+        let _ = self.tx.send(NetworkEvent::ValidationResult {
+            block_hash,
+            validation: vote.to_decision(),
+        });
+        match self
+            .consensus
+            .add_signed_vote(&vote, &self.key.verifying_key(), 100)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => warn!("Block {} did not get through its vote", height),
+            Err(e) => {
+                warn!("Could not finalize block {}: {}", height, e);
+                return;
+            }
+        }
+
+        // The mempool checked the signatures, the state checks them again
+        // with keys it knows
+        for transaction in &block.transactions {
+            if let Ok(sender) = VerifyingKey::from_bytes(&transaction.sender) {
+                self.state.key_manager.inner().register_public_key(
+                    &sender,
+                    hex::encode(transaction.sender),
+                    "account".to_string(),
+                );
+            }
+        }
+        match self.state.import_block(block.clone()).await {
+            Ok(()) => info!(
+                "⛏️ Sealed block {} with {} transactions",
+                height,
+                block.transactions.len()
+            ),
+            Err(e) => warn!("Could not store block {}: {}", height, e),
+        }
+    }
+}
@@ -0,0 +1,450 @@
+//! Explorer queries over the indexes of the state store, for browsing the
+//! whole history rather than the latest blocks: the transactions and
+//! history of an account, the blocks of a producer, what an agent has been up to, and
+//! everything that happened in a span of time. Lists page by cursor, see
+//! [`crate::page`], except time ranges, which read oldest first.
+
+use axum::extract::{Path, Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use chaoschain_core::NetworkEvent;
+use chaoschain_state::index::{
+    AccountEvent, Activity, ActivityFilter, HistoryKey, Order, TxLocation,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::openapi::{
+    any_object, array, hex, integer, one_of, page_of, schema_ref, string, ApiSchema, Object,
+    Operation,
+};
+use crate::page::{Page, PageQuery, MAX_LIMIT};
+use crate::rest::{ApiError, BlockView, TransactionView};
+use crate::web::AppState;
+
+/// Items listed when no limit is asked for
+const DEFAULT_LIMIT: usize = 20;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/v1/accounts/:address/txs", get(account_transactions))
+        .route("/api/v1/accounts/:address/history", get(account_history))
+        .route("/api/v1/producers/:id/blocks", get(producer_blocks))
+        .route("/api/v1/agents/:id/activity", get(agent_activity))
+        .route("/api/v1/activity", get(activity_between))
+        .route("/api/v1/drama", get(search_drama))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![
+        Operation::get(
+            "/api/v1/accounts/{address}/txs",
+            "transactions",
+            "Transactions sent from an account",
+        )
+        .path_param("address", hex(), "32 hex encoded bytes")
+        .paged()
+        .returns(page_of::<TransactionView>()),
+        Operation::get(
+            "/api/v1/accounts/{address}/history",
+            "transactions",
+            "Transactions, balance changes, bribes and drama of an account",
+        )
+        .path_param(
+            "address",
+            string(),
+            "Agent id, or 32 hex encoded bytes for the transactions of a key",
+        )
+        .paged()
+        .returns(page_of::<HistoryView>()),
+        Operation::get(
+            "/api/v1/producers/{id}/blocks",
+            "blocks",
+            "Blocks made by a producer",
+        )
+        .path_param("id", string(), "Producer id")
+        .paged()
+        .returns(page_of::<BlockView>()),
+        Operation::get(
+            "/api/v1/agents/{id}/activity",
+            "activity",
+            "What an agent said and did",
+        )
+        .path_param("id", string(), "Agent id")
+        .paged()
+        .returns(page_of::<ActivityView>()),
+        Operation::get(
+            "/api/v1/activity",
+            "activity",
+            "Blocks and activity in a span of time",
+        )
+        .query("from", integer(), "Unix seconds, inclusive")
+        .query(
+            "to",
+            integer(),
+            "Unix seconds, exclusive. Now when left out.",
+        )
+        .query("limit", integer(), "Items of each kind, up to 100")
+        .returns(schema_ref::<TimeRange>()),
+        Operation::get("/api/v1/drama", "activity", "Search the drama feed")
+            .query("agent", string(), "Only this agent")
+            .query("kind", string(), "Comma separated kinds, e.g. `chat,rumor`")
+            .query("from_height", integer(), "Lowest chain height")
+            .query("to_height", integer(), "Highest chain height, inclusive")
+            .query("q", string(), "Words that all have to appear")
+            .paged()
+            .returns(page_of::<ActivityView>()),
+    ]
+}
+
+#[derive(Debug, Deserialize)]
+struct RangeQuery {
+    /// Unix seconds, inclusive
+    from: u64,
+    /// Unix seconds, exclusive. Now when left out.
+    to: Option<u64>,
+    limit: Option<usize>,
+}
+
+/// Feed search. Spelled out rather than flattened from the filter, query
+/// strings only deserialize numbers in plain fields.
+#[derive(Debug, Deserialize)]
+struct DramaQuery {
+    agent: Option<String>,
+    kind: Option<String>,
+    from_height: Option<u64>,
+    to_height: Option<u64>,
+    q: Option<String>,
+    cursor: Option<String>,
+    limit: Option<usize>,
+    order: Option<Order>,
+}
+
+fn limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct ActivityView {
+    pub seq: u64,
+    pub timestamp: u64,
+    /// Height of the chain at the time
+    pub height: u64,
+    pub agent: String,
+    pub kind: String,
+    /// What the agent said, in short
+    pub message: String,
+    pub event: NetworkEvent,
+}
+
+impl From<Activity> for ActivityView {
+    fn from(activity: Activity) -> Self {
+        Self {
+            seq: activity.seq,
+            timestamp: activity.timestamp,
+            height: activity.height,
+            agent: activity.agent,
+            kind: activity.kind,
+            message: activity.text,
+            event: activity.event,
+        }
+    }
+}
+
+impl ApiSchema for ActivityView {
+    const NAME: &'static str = "Activity";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("seq", integer())
+            .field("timestamp", integer())
+            .field("height", integer())
+            .field("agent", string())
+            .field(
+                "kind",
+                one_of(&[
+                    "block_proposal",
+                    "vote",
+                    "chat",
+                    "alliance",
+                    "pitch",
+                    "support",
+                    "thought",
+                    "artwork",
+                    "rumor",
+                ]),
+            )
+            .field("message", string())
+            .field("event", any_object("The network event, tagged by its type"))
+            .build()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimeRange {
+    pub from: u64,
+    pub to: u64,
+    /// Blocks by their timestamp
+    pub blocks: Vec<BlockView>,
+    /// Activity by when the node saw it
+    pub activity: Vec<ActivityView>,
+}
+
+impl ApiSchema for TimeRange {
+    const NAME: &'static str = "TimeRange";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("from", integer())
+            .field("to", integer())
+            .field("blocks", array(schema_ref::<BlockView>()))
+            .field("activity", array(schema_ref::<ActivityView>()))
+            .build()
+    }
+}
+
+/// An entry of an account's history
+#[derive(Debug, Serialize)]
+pub struct HistoryView {
+    pub timestamp: u64,
+    /// Of the block, or of the chain at the time
+    pub height: u64,
+    #[serde(flatten)]
+    pub event: HistoryEventView,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HistoryEventView {
+    Transaction {
+        transaction: TransactionView,
+    },
+    BalanceChange {
+        amount: u64,
+        balance: u64,
+        reason: String,
+    },
+    /// A pitch with an offer to the validators that support it
+    BribeSent {
+        offer: u64,
+        activity: ActivityView,
+    },
+    /// An answer to a pitch that came with an offer
+    BribeReceived {
+        producer: String,
+        offer: u64,
+        activity: ActivityView,
+    },
+    Drama {
+        activity: ActivityView,
+    },
+}
+
+impl HistoryView {
+    fn new(key: HistoryKey, event: AccountEvent) -> Self {
+        let (height, event) = match event {
+            AccountEvent::Transaction {
+                location,
+                transaction,
+                block,
+            } => (
+                location.height,
+                HistoryEventView::Transaction {
+                    transaction: TransactionView::new(&transaction, Some(&block)),
+                },
+            ),
+            AccountEvent::BalanceChange(change) => (
+                change.height,
+                HistoryEventView::BalanceChange {
+                    amount: change.amount,
+                    balance: change.balance,
+                    reason: change.reason,
+                },
+            ),
+            AccountEvent::BribeSent { activity, offer } => (
+                activity.height,
+                HistoryEventView::BribeSent {
+                    offer,
+                    activity: activity.into(),
+                },
+            ),
+            AccountEvent::BribeReceived {
+                activity,
+                producer,
+                offer,
+            } => (
+                activity.height,
+                HistoryEventView::BribeReceived {
+                    producer,
+                    offer,
+                    activity: activity.into(),
+                },
+            ),
+            AccountEvent::Drama(activity) => (
+                activity.height,
+                HistoryEventView::Drama {
+                    activity: activity.into(),
+                },
+            ),
+        };
+        Self {
+            timestamp: key.timestamp,
+            height,
+            event,
+        }
+    }
+}
+
+impl ApiSchema for HistoryView {
+    const NAME: &'static str = "AccountHistoryEntry";
+
+    fn schema() -> Value {
+        Object::new()
+            .field(
+                "type",
+                one_of(&[
+                    "transaction",
+                    "balance_change",
+                    "bribe_sent",
+                    "bribe_received",
+                    "drama",
+                ]),
+            )
+            .field("timestamp", integer())
+            .field("height", integer())
+            .optional("transaction", schema_ref::<TransactionView>())
+            .optional("amount", integer())
+            .optional("balance", integer())
+            .optional("reason", string())
+            .optional("producer", string())
+            .optional("offer", integer())
+            .optional("activity", schema_ref::<ActivityView>())
+            .build()
+    }
+}
+
+/// Transactions sent from an account, with the blocks they are in
+async fn account_transactions(
+    State(state): State<Arc<AppState>>,
+    Path(address): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<TransactionView>>, ApiError> {
+    let sender: [u8; 32] = hex::decode(address.strip_prefix("0x").unwrap_or(&address))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| ApiError::bad_request("An address is 32 hex encoded bytes"))?;
+    let (cursor, order) = query.position::<TxLocation>()?;
+    let limit = query.limit(DEFAULT_LIMIT);
+    let txs = state
+        .state
+        .transactions_by_sender(&sender, cursor, order, limit + 1);
+    Ok(Json(
+        Page::new(txs, limit, order, |(location, _, _)| *location)
+            .map(|(_, tx, block)| TransactionView::new(&tx, Some(&block))),
+    ))
+}
+
+/// Everything an account did and had done to it, by time
+async fn account_history(
+    State(state): State<Arc<AppState>>,
+    Path(address): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<HistoryView>>, ApiError> {
+    let (cursor, order) = query.position::<HistoryKey>()?;
+    let limit = query.limit(DEFAULT_LIMIT);
+    let history = state
+        .state
+        .account_history(&address, cursor, order, limit + 1);
+    Ok(Json(
+        Page::new(history, limit, order, |(key, _)| *key)
+            .map(|(key, event)| HistoryView::new(key, event)),
+    ))
+}
+
+async fn producer_blocks(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<BlockView>>, ApiError> {
+    let (cursor, order) = query.position()?;
+    let limit = query.limit(DEFAULT_LIMIT);
+    let blocks = state
+        .state
+        .blocks_by_producer(&id, cursor, order, limit + 1);
+    Ok(Json(
+        Page::new(blocks, limit, order, |block| block.height).map(|block| BlockView::new(&block)),
+    ))
+}
+
+async fn agent_activity(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<ActivityView>>, ApiError> {
+    let (cursor, order) = query.position()?;
+    let limit = query.limit(DEFAULT_LIMIT);
+    let activity = state.state.activity_of(&id, cursor, order, limit + 1);
+    Ok(Json(
+        Page::new(activity, limit, order, |activity| activity.seq).map(ActivityView::from),
+    ))
+}
+
+async fn activity_between(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<RangeQuery>,
+) -> Result<Json<TimeRange>, ApiError> {
+    let to = query
+        .to
+        .unwrap_or_else(|| chrono::Utc::now().timestamp() as u64 + 1);
+    if query.from >= to {
+        return Err(ApiError::bad_request("from must come before to"));
+    }
+    let limit = limit(query.limit);
+    Ok(Json(TimeRange {
+        from: query.from,
+        to,
+        blocks: state
+            .state
+            .blocks_between(query.from, to, limit)
+            .iter()
+            .map(BlockView::new)
+            .collect(),
+        activity: state
+            .state
+            .activity_between(query.from, to, limit)
+            .into_iter()
+            .map(ActivityView::from)
+            .collect(),
+    }))
+}
+
+/// The drama feed, filtered by agent, kind, chain height and keywords
+async fn search_drama(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DramaQuery>,
+) -> Result<Json<Page<ActivityView>>, ApiError> {
+    let page = PageQuery {
+        cursor: query.cursor,
+        limit: query.limit,
+        order: query.order,
+    };
+    let (cursor, order) = page.position()?;
+    let limit = page.limit(DEFAULT_LIMIT);
+    let mut filter = ActivityFilter {
+        agent: query.agent,
+        kind: query.kind,
+        from_height: query.from_height,
+        to_height: query.to_height,
+        q: query.q,
+        ..ActivityFilter::default()
+    };
+    match order {
+        Order::Asc => filter.after = cursor,
+        Order::Desc => filter.before = cursor,
+    }
+    let activity = state.state.search_activity(&filter, order, limit + 1);
+    Ok(Json(
+        Page::new(activity, limit, order, |activity| activity.seq).map(ActivityView::from),
+    ))
+}
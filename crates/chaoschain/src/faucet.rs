@@ -0,0 +1,214 @@
+//! The faucet of a dev chain: test balance for any address, under
+//! `POST /api/v1/faucet`, and `chaoschain faucet` to ask it:
+//!
+//! ```bash
+//! chaoschain faucet 3b6a27bcceb6a42d62a3a8d02a6f0d73653215771de243a63ac048a18b59da29
+//! curl -X POST http://127.0.0.1:3000/api/v1/faucet -d '{"address": "3b6a..."}' \
+//!     -H 'Content-Type: application/json'
+//! ```
+//!
+//! Only `chaoschain dev` runs one. An address, and a client asking for
+//! it, get a drip per cooldown. Too soon, the answer is a 429 with a
+//! `Retry-After`.
+
+use anyhow::{bail, Context, Result};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use chaoschain_state::StateStoreImpl;
+use ed25519_dalek::VerifyingKey;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::auth::API_KEY_HEADER;
+use crate::openapi::{hex, integer, schema_ref, ApiSchema, Object, Operation};
+use crate::rest::ApiError;
+use crate::web::AppState;
+
+/// Reason of faucet drips in the account history
+const REASON: &str = "faucet";
+
+/// Addresses and clients remembered before those past their cooldown are
+/// dropped
+const MAX_SERVED: usize = 10_000;
+
+pub struct Faucet {
+    state: Arc<StateStoreImpl>,
+    /// What each request gets
+    pub drip: u64,
+    /// Time an address, or a client, waits between two drips
+    pub cooldown: Duration,
+    served: Mutex<HashMap<String, Instant>>,
+}
+
+impl Faucet {
+    pub fn new(state: Arc<StateStoreImpl>, drip: u64, cooldown: Duration) -> Self {
+        Self {
+            state,
+            drip,
+            cooldown,
+            served: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Credit a drip to `account` for `client` and return its balance, or
+    /// tell how long either has to wait
+    pub fn dispense(&self, account: &VerifyingKey, client: &str) -> Result<u64, Duration> {
+        let now = Instant::now();
+        let address = hex::encode(account.as_bytes());
+        let keys = [format!("address:{}", address), format!("client:{}", client)];
+        let mut served = self.served.lock();
+        let wait = keys
+            .iter()
+            .filter_map(|key| served.get(key))
+            .map(|last| self.cooldown.saturating_sub(now.duration_since(*last)))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        if served.len() >= MAX_SERVED {
+            served.retain(|_, last| now.duration_since(*last) < self.cooldown);
+        }
+        for key in keys {
+            served.insert(key, now);
+        }
+        drop(served);
+
+        self.state.fund(account, self.drip, REASON);
+        Ok(self.state.get_balance(account))
+    }
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/api/v1/faucet", post(request_funds))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![Operation::post(
+        "/api/v1/faucet",
+        "transactions",
+        "Test balance for an address, on a dev chain",
+        schema_ref::<FaucetRequest>(),
+    )
+    .returns(schema_ref::<DripView>())]
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FaucetRequest {
+    /// 32 hex encoded bytes, with or without `0x`
+    pub address: String,
+}
+
+impl ApiSchema for FaucetRequest {
+    const NAME: &'static str = "FaucetRequest";
+
+    fn schema() -> Value {
+        Object::new().field("address", hex()).build()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DripView {
+    pub address: String,
+    /// What the faucet gave
+    pub amount: u64,
+    /// Balance of the address after it
+    pub balance: u64,
+}
+
+impl ApiSchema for DripView {
+    const NAME: &'static str = "Drip";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("address", hex())
+            .field("amount", integer())
+            .field("balance", integer())
+            .build()
+    }
+}
+
+async fn request_funds(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<FaucetRequest>,
+) -> Result<Json<DripView>, Response> {
+    let Some(faucet) = &state.faucet else {
+        return Err(
+            ApiError::not_found("This node runs no faucet, `chaoschain dev` does").into_response(),
+        );
+    };
+    let account =
+        parse_address(&request.address).map_err(|e| ApiError::bad_request(e).into_response())?;
+    match faucet.dispense(&account, &addr.ip().to_string()) {
+        Ok(balance) => Ok(Json(DripView {
+            address: hex::encode(account.as_bytes()),
+            amount: faucet.drip,
+            balance,
+        })),
+        Err(wait) => {
+            let seconds = wait.as_secs().max(1);
+            let mut error = ApiError::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+                format!("The faucet gives again in {} seconds", seconds),
+            );
+            error.data = Some(json!({ "retry_after": seconds }));
+            Err((
+                [(header::RETRY_AFTER, seconds.to_string())],
+                error.into_response(),
+            )
+                .into_response())
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Result<VerifyingKey, String> {
+    let bytes = hex::decode(address.strip_prefix("0x").unwrap_or(address))
+        .map_err(|e| format!("The address is not hex: {}", e))?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| "An address is 32 bytes".to_string())?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| format!("The address is not a public key: {}", e))
+}
+
+/// Ask the faucet of `node` for a drip to `address`
+pub async fn request(node: &str, address: &str, api_key: Option<String>) -> Result<()> {
+    let url = format!("{}/api/v1/faucet", node.trim_end_matches('/'));
+    let mut request = reqwest::Client::new()
+        .post(&url)
+        .json(&json!({ "address": address }));
+    if let Some(api_key) = api_key {
+        request = request.header(API_KEY_HEADER, api_key);
+    }
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Could not reach {}", url))?;
+    let status = response.status();
+    // The rate limit of the APIs answers in plain text
+    let body: Value = response.json().await.unwrap_or_default();
+    if !status.is_success() {
+        bail!(
+            "{}",
+            body["error"]["message"]
+                .as_str()
+                .map_or_else(|| status.to_string(), str::to_string)
+        );
+    }
+    let drip: DripView =
+        serde_json::from_value(body).with_context(|| format!("{} did not answer a drip", url))?;
+    println!(
+        "Sent {} to {}, which has {} now",
+        drip.amount, drip.address, drip.balance
+    );
+    Ok(())
+}
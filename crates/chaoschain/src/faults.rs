@@ -0,0 +1,256 @@
+//! Faults the node injects into itself on the schedule of `[chaos]` in its
+//! config, so that operators can rehearse failures on a test network.
+//! Messages of the kinds a fault names are dropped or held back on their
+//! way to the agents it names, commits to the block log stall, and agents
+//! crash until their fault ends, when they are restarted. Each fault is
+//! announced in the drama feed as it starts and as it ends.
+
+use chaoschain_cli::{ChaosConfig, Fault, ScheduledFault};
+use chaoschain_core::metrics::MetricsRegistry;
+use chaoschain_core::NetworkEvent;
+use chaoschain_state::storage::Storage;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use rand::Rng;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::lifecycle::AgentSupervisor;
+use crate::web::NetworkEventExt;
+
+/// Messages waiting on a receiver the faults stand in front of
+const CAPACITY: usize = 100;
+
+type Labels = Vec<(String, String)>;
+
+/// Where a fault of the schedule is at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultState {
+    Pending,
+    Active,
+    Over,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FaultStatus {
+    #[serde(flatten)]
+    pub fault: ScheduledFault,
+    pub state: FaultState,
+}
+
+pub struct FaultInjector {
+    faults: Vec<ScheduledFault>,
+    started: Instant,
+    /// Faults started and ended, per fault and event
+    injected: Family<Labels, Counter>,
+    /// Messages dropped or held back, per fault and kind of message
+    messages: Family<Labels, Counter>,
+}
+
+impl FaultInjector {
+    /// The faults of `config`, timed from now
+    pub fn new(config: &ChaosConfig) -> Self {
+        Self {
+            faults: config.faults.clone(),
+            started: Instant::now(),
+            injected: Family::default(),
+            messages: Family::default(),
+        }
+    }
+
+    pub fn register_metrics(&self, registry: &MetricsRegistry) {
+        registry.register(
+            "chaoschain_faults_injected",
+            "Faults of the chaos schedule started and ended, per fault",
+            self.injected.clone(),
+        );
+        registry.register(
+            "chaoschain_fault_messages",
+            "Messages the chaos schedule dropped or delayed, per fault and kind",
+            self.messages.clone(),
+        );
+    }
+
+    /// Every fault of the schedule, and whether it is in effect
+    pub fn status(&self) -> Vec<FaultStatus> {
+        let elapsed = self.started.elapsed();
+        self.faults
+            .iter()
+            .map(|fault| FaultStatus {
+                fault: fault.clone(),
+                state: if fault.is_active(elapsed) {
+                    FaultState::Active
+                } else if elapsed < fault.starts() {
+                    FaultState::Pending
+                } else {
+                    FaultState::Over
+                },
+            })
+            .collect()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// What becomes of a message of `kind` on its way to `to`: `None` when
+    /// it is lost, or else how long it is held back
+    fn fate(&self, kind: &str, to: &str, rng: &mut impl Rng) -> Option<Duration> {
+        let elapsed = self.started.elapsed();
+        let mut delay = Duration::ZERO;
+        for fault in self.faults.iter().filter(|fault| fault.is_active(elapsed)) {
+            let hits = |messages: &[String], agents: &[String]| {
+                messages.iter().any(|message| message == kind)
+                    && (agents.is_empty() || agents.iter().any(|agent| agent == to))
+            };
+            match &fault.fault {
+                Fault::Drop {
+                    messages,
+                    share,
+                    agents,
+                } if hits(messages, agents) && rng.gen_bool(*share) => {
+                    self.count(&fault.fault, kind);
+                    return None;
+                }
+                Fault::Delay {
+                    messages,
+                    delay_ms,
+                    agents,
+                } if hits(messages, agents) => {
+                    self.count(&fault.fault, kind);
+                    delay = delay.max(Duration::from_millis(*delay_ms));
+                }
+                _ => {}
+            }
+        }
+        Some(delay)
+    }
+
+    fn count(&self, fault: &Fault, kind: &str) {
+        self.messages
+            .get_or_create(&vec![
+                ("fault".to_string(), fault.name().to_string()),
+                ("kind".to_string(), kind.to_string()),
+            ])
+            .inc();
+    }
+
+    /// What agent `to` hears of `source` while the faults on messages are
+    /// in effect. Its own messages and the node's get through untouched.
+    pub fn subscribe(
+        self: &Arc<Self>,
+        mut source: broadcast::Receiver<NetworkEvent>,
+        to: &str,
+    ) -> broadcast::Receiver<NetworkEvent> {
+        let (out, rx) = broadcast::channel(CAPACITY);
+        let faults = self.clone();
+        let to = to.to_string();
+        tokio::spawn(async move {
+            loop {
+                let event = match source.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if out.receiver_count() == 0 {
+                    break;
+                }
+                let from = event.get_agent_id();
+                if from == to || from == "SYSTEM" {
+                    let _ = out.send(event);
+                    continue;
+                }
+                match faults.fate(event.kind(), &to, &mut rand::thread_rng()) {
+                    None => {}
+                    Some(delay) if delay.is_zero() => {
+                        let _ = out.send(event);
+                    }
+                    Some(delay) => {
+                        let out = out.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(delay).await;
+                            let _ = out.send(event);
+                        });
+                    }
+                }
+            }
+        });
+        rx
+    }
+
+    /// Start and end each fault of the schedule when it is due, until the
+    /// last one is over. Faults on messages need nothing done, they are in
+    /// effect through [`subscribe`](Self::subscribe).
+    pub async fn run(
+        self: Arc<Self>,
+        storage: Storage,
+        supervisor: Arc<AgentSupervisor>,
+        tx: broadcast::Sender<NetworkEvent>,
+    ) {
+        // Each fault starts, then ends, a fault ending before one starting
+        // at the same time
+        let mut events: Vec<(Duration, bool, usize)> = self
+            .faults
+            .iter()
+            .enumerate()
+            .flat_map(|(index, fault)| {
+                [(fault.starts(), true, index), (fault.ends(), false, index)]
+            })
+            .collect();
+        events.sort();
+        let mut stalls = BTreeMap::new();
+        for (at, starts, index) in events {
+            tokio::time::sleep_until(self.started + at).await;
+            let fault = &self.faults[index].fault;
+            let event = if starts { "started" } else { "ended" };
+            self.injected
+                .get_or_create(&vec![
+                    ("fault".to_string(), fault.name().to_string()),
+                    ("event".to_string(), event.to_string()),
+                ])
+                .inc();
+            if starts {
+                warn!("💥 Chaos fault {}: {}", index + 1, fault);
+            } else {
+                info!("🩹 Chaos fault {} is over: {}", index + 1, fault);
+            }
+            let _ = tx.send(NetworkEvent::AgentChat {
+                message: if starts {
+                    format!("💥 Chaos strikes: {}!", fault)
+                } else {
+                    format!("🩹 The chaos subsides ({})", fault)
+                },
+                sender: "SYSTEM".to_string(),
+                meme_url: None,
+            });
+            match fault {
+                Fault::StallStorage { delay_ms } => {
+                    if starts {
+                        stalls.insert(index, Duration::from_millis(*delay_ms));
+                    } else {
+                        stalls.remove(&index);
+                    }
+                    // The longest of the stalls in effect
+                    storage.stall_writes(stalls.values().max().copied().unwrap_or_default());
+                }
+                Fault::Crash { agent } => {
+                    let result = if starts {
+                        supervisor.crash(agent).await
+                    } else {
+                        supervisor.restart(agent).await
+                    };
+                    if let Err(e) = result {
+                        warn!("Chaos fault {} found nothing to crash: {}", index + 1, e);
+                    }
+                }
+                Fault::Drop { .. } | Fault::Delay { .. } => {}
+            }
+        }
+    }
+}
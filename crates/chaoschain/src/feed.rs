@@ -0,0 +1,315 @@
+//! The drama feed and finalized blocks for feed readers. `/feed.xml` is an
+//! Atom feed of the latest activity and blocks, or RSS 2.0 with
+//! `?format=rss`. `?agent=` narrows it to one agent (for blocks, the
+//! producer) and `?kind=` to some kinds of event, `finalized` being the
+//! kind of blocks. `/agents/{id}/feed.xml` is the feed of one agent.
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use chaoschain_core::Block;
+use chaoschain_state::index::{Activity, ActivityFilter, Order};
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+use crate::page::MAX_LIMIT;
+use crate::web::AppState;
+
+/// Entries in a feed when no limit is asked for
+const DEFAULT_LIMIT: usize = 50;
+
+/// Kind of the entries for finalized blocks
+const FINALIZED: &str = "finalized";
+
+/// Longest entry title, in characters
+const MAX_TITLE: usize = 80;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/feed.xml", get(feed))
+        .route("/agents/:id/feed.xml", get(agent_feed))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    #[default]
+    Atom,
+    Rss,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FeedQuery {
+    agent: Option<String>,
+    /// Comma separated kinds of event
+    kind: Option<String>,
+    #[serde(default)]
+    format: Format,
+    limit: Option<usize>,
+}
+
+impl FeedQuery {
+    fn wants(&self, kind: &str) -> bool {
+        self.kind
+            .as_deref()
+            .is_none_or(|kinds| kinds.split(',').any(|k| k.trim() == kind))
+    }
+}
+
+/// One item of a feed
+struct Entry {
+    id: String,
+    title: String,
+    /// Path of the entry's JSON on this node
+    link: String,
+    author: String,
+    kind: String,
+    summary: String,
+    timestamp: u64,
+}
+
+impl From<Activity> for Entry {
+    fn from(activity: Activity) -> Self {
+        let first_line = activity.text.lines().next().unwrap_or_default().trim();
+        let title = if first_line.chars().count() > MAX_TITLE {
+            let cut: String = first_line.chars().take(MAX_TITLE - 1).collect();
+            format!("{}…", cut)
+        } else {
+            first_line.to_string()
+        };
+        Self {
+            id: format!("urn:chaoschain:activity:{}", activity.seq),
+            title: format!("{} ({}): {}", activity.agent, activity.kind, title),
+            link: format!("/api/v1/agents/{}/activity", activity.agent),
+            author: activity.agent,
+            kind: activity.kind,
+            summary: activity.text,
+            timestamp: activity.timestamp,
+        }
+    }
+}
+
+impl From<Block> for Entry {
+    fn from(block: Block) -> Self {
+        Self {
+            id: format!("urn:chaoschain:block:{}", hex::encode(block.hash())),
+            title: format!(
+                "Block {} finalized, by {} (drama {}/10)",
+                block.height, block.producer_id, block.drama_level
+            ),
+            link: format!("/api/v1/blocks/{}", block.height),
+            summary: format!(
+                "{} transactions, proposed by {} in a {} mood with the {} strategy.",
+                block.transactions.len(),
+                block.producer_id,
+                block.producer_mood,
+                block.producer_strategy
+            ),
+            author: block.producer_id,
+            kind: FINALIZED.to_string(),
+            timestamp: block.timestamp,
+        }
+    }
+}
+
+async fn feed(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    uri: Uri,
+    Query(query): Query<FeedQuery>,
+) -> Response {
+    render(&state, &headers, &uri, query)
+}
+
+async fn agent_feed(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    uri: Uri,
+    Path(id): Path<String>,
+    Query(query): Query<FeedQuery>,
+) -> Response {
+    render(
+        &state,
+        &headers,
+        &uri,
+        FeedQuery {
+            agent: Some(id),
+            ..query
+        },
+    )
+}
+
+fn render(state: &AppState, headers: &HeaderMap, uri: &Uri, query: FeedQuery) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let mut entries: Vec<Entry> = Vec::new();
+
+    // Blocks come from the chain, everything else from the activity journal
+    let kinds: Option<String> = query.kind.as_ref().map(|kinds| {
+        kinds
+            .split(',')
+            .map(str::trim)
+            .filter(|kind| *kind != FINALIZED)
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+    if kinds.as_deref() != Some("") {
+        let filter = ActivityFilter {
+            agent: query.agent.clone(),
+            kind: kinds,
+            ..ActivityFilter::default()
+        };
+        entries.extend(
+            state
+                .state
+                .search_activity(&filter, Order::Desc, limit)
+                .into_iter()
+                .map(Entry::from),
+        );
+    }
+    if query.wants(FINALIZED) {
+        entries.extend(
+            state
+                .state
+                .blocks_page(None, Order::Desc, MAX_LIMIT)
+                .into_iter()
+                .filter(|block| {
+                    query
+                        .agent
+                        .as_deref()
+                        .is_none_or(|agent| agent == block.producer_id)
+                })
+                .take(limit)
+                .map(Entry::from),
+        );
+    }
+    entries.sort_by_key(|entry| Reverse(entry.timestamp));
+    entries.truncate(limit);
+
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or("localhost:3000");
+    let base = format!("http://{}", host);
+    // Every variant is a feed of its own, and is known by its address
+    let url = format!("{}{}", base, uri);
+    let mut title = "ChaosChain drama".to_string();
+    if let Some(agent) = &query.agent {
+        title.push_str(&format!(" of {}", agent));
+    }
+    if let Some(kinds) = &query.kind {
+        title.push_str(&format!(" ({})", kinds));
+    }
+
+    let (content_type, body) = match query.format {
+        Format::Atom => ("application/atom+xml", atom(&base, &url, &title, &entries)),
+        Format::Rss => ("application/rss+xml", rss(&base, &title, &entries)),
+    };
+    (
+        StatusCode::OK,
+        [(
+            header::CONTENT_TYPE,
+            format!("{}; charset=utf-8", content_type),
+        )],
+        body,
+    )
+        .into_response()
+}
+
+fn atom(base: &str, url: &str, title: &str, entries: &[Entry]) -> String {
+    let updated = entries
+        .first()
+        .map_or_else(|| chrono::Utc::now().timestamp() as u64, |e| e.timestamp);
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+         <id>{}</id>\n<title>{}</title>\n<updated>{}</updated>\n\
+         <link rel=\"self\" href=\"{}\"/>\n<link href=\"{}/\"/>\n",
+        escape(url),
+        escape(title),
+        rfc3339(updated),
+        escape(url),
+        escape(base)
+    );
+    for entry in entries {
+        xml.push_str(&format!(
+            "<entry>\n<id>{}</id>\n<title>{}</title>\n<updated>{}</updated>\n\
+             <author><name>{}</name></author>\n<category term=\"{}\"/>\n\
+             <link href=\"{}{}\"/>\n<summary>{}</summary>\n</entry>\n",
+            escape(&entry.id),
+            escape(&entry.title),
+            rfc3339(entry.timestamp),
+            escape(&entry.author),
+            escape(&entry.kind),
+            escape(base),
+            escape(&entry.link),
+            escape(&entry.summary)
+        ));
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn rss(base: &str, title: &str, entries: &[Entry]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <rss version=\"2.0\">\n<channel>\n<title>{}</title>\n<link>{}/</link>\n\
+         <description>Votes, chatter, rumors and finalized blocks of a ChaosChain node</description>\n",
+        escape(title),
+        escape(base)
+    );
+    if let Some(latest) = entries.first() {
+        xml.push_str(&format!(
+            "<lastBuildDate>{}</lastBuildDate>\n",
+            rfc2822(latest.timestamp)
+        ));
+    }
+    for entry in entries {
+        xml.push_str(&format!(
+            "<item>\n<guid isPermaLink=\"false\">{}</guid>\n<title>{}</title>\n\
+             <link>{}{}</link>\n<category>{}</category>\n<pubDate>{}</pubDate>\n\
+             <description>{}</description>\n</item>\n",
+            escape(&entry.id),
+            escape(&entry.title),
+            escape(base),
+            escape(&entry.link),
+            escape(&entry.kind),
+            rfc2822(entry.timestamp),
+            escape(&entry.summary)
+        ));
+    }
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn timestamp(seconds: u64) -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::from_timestamp(seconds as i64, 0).unwrap_or_default()
+}
+
+fn rfc3339(seconds: u64) -> String {
+    timestamp(seconds).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+fn rfc2822(seconds: u64) -> String {
+    timestamp(seconds).to_rfc2822()
+}
+
+/// Text safe to put in an element or attribute
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Not allowed in XML 1.0 at all
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,609 @@
+use crate::lifecycle::AgentSupervisor;
+use chaoschain_consensus::ConsensusManager;
+use chaoschain_core::negotiation::now_millis;
+use chaoschain_core::vote::{verify_possession, Misbehavior, SignedVote};
+use chaoschain_core::{Block, NetworkEvent};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Domain separator of WebSocket session challenges
+pub const AUTH_DOMAIN: &[u8] = b"chaoschain-auth-v1";
+
+/// Gateway settings
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    /// How long external agents get to vote on a proposal
+    pub vote_window: Duration,
+    /// Stake behind every external vote
+    pub stake: u64,
+    /// Closed proposals kept around for late voters to get a proper answer
+    pub closed_retention: usize,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            vote_window: Duration::from_secs(10),
+            stake: 100,
+            closed_retention: 64,
+        }
+    }
+}
+
+/// An agent whose brain lives outside the node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalAgent {
+    pub agent_id: String,
+    pub name: String,
+    /// Hex encoded ed25519 public key
+    pub public_key: String,
+    pub registered_at: u64,
+    /// Votes accepted from the agent
+    pub votes: u64,
+    /// Proposals that closed without a vote from the agent
+    pub missed_deadlines: u64,
+    /// Suspended agents are no longer heard, e.g. after equivocating
+    pub suspended: bool,
+    /// WebSocket sessions the agent has open
+    #[serde(default)]
+    pub sessions: u32,
+    /// Bytes of the frames read from the agent over its sessions
+    #[serde(default)]
+    pub bytes_in: u64,
+    /// Bytes of the frames sent to the agent over its sessions
+    #[serde(default)]
+    pub bytes_out: u64,
+}
+
+impl ExternalAgent {
+    /// Share of the proposals the agent voted on before their deadline,
+    /// 1 until it missed one
+    pub fn score(&self) -> f64 {
+        let due = self.votes + self.missed_deadlines;
+        if due == 0 {
+            1.0
+        } else {
+            self.votes as f64 / due as f64
+        }
+    }
+}
+
+/// Registration request: the signature over
+/// [`chaoschain_core::vote::registration_message`] proves the agent holds the key
+#[derive(Debug, Clone, Deserialize)]
+pub struct GatewayRegistration {
+    pub name: String,
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// A proposal as delivered to external agents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProposalNotice {
+    pub height: u64,
+    /// Hex encoded block hash, the value to sign
+    pub block_hash: String,
+    pub block: Block,
+    /// Votes are accepted until this unix timestamp (ms)
+    pub deadline: u64,
+}
+
+/// Answer to an accepted vote
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoteReceipt {
+    pub block_hash: String,
+    pub accepted_at: u64,
+    pub consensus_reached: bool,
+}
+
+/// Gateway errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GatewayError {
+    InvalidKey(String),
+    InvalidSignature(String),
+    AlreadyRegistered(String),
+    UnknownAgent(String),
+    Suspended(String),
+    /// The operator banned the key
+    Banned(String),
+    UnknownProposal(String),
+    DeadlinePassed {
+        block_hash: String,
+        deadline: u64,
+    },
+    Equivocation(String),
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidKey(e) => write!(f, "Invalid public key: {}", e),
+            Self::InvalidSignature(e) => write!(f, "Invalid signature: {}", e),
+            Self::AlreadyRegistered(id) => write!(f, "Key already registered as {}", id),
+            Self::UnknownAgent(id) => write!(f, "Unknown agent: {}", id),
+            Self::Suspended(id) => write!(f, "Agent {} is suspended", id),
+            Self::Banned(id) => write!(f, "Agent {} is banned from the gateway", id),
+            Self::UnknownProposal(hash) => write!(f, "No proposal with hash {}", hash),
+            Self::DeadlinePassed {
+                block_hash,
+                deadline,
+            } => write!(
+                f,
+                "Voting on {} closed at {} (unix ms)",
+                block_hash, deadline
+            ),
+            Self::Equivocation(id) => write!(
+                f,
+                "{} signed conflicting votes, the evidence has been recorded",
+                id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GatewayError {}
+
+struct Registered {
+    agent: ExternalAgent,
+    key: VerifyingKey,
+}
+
+struct OpenProposal {
+    notice: ProposalNotice,
+    votes: HashMap<String, SignedVote>,
+    closed: bool,
+}
+
+/// Lets third-party processes take part as validators: they register a key,
+/// receive proposals with a deadline and answer with signed votes. Accepted
+/// votes count like those of local validators, and conflicting votes are
+/// kept as evidence that can back slashing.
+pub struct AgentGateway {
+    config: GatewayConfig,
+    consensus: Arc<ConsensusManager>,
+    tx: broadcast::Sender<NetworkEvent>,
+    supervisor: Option<Arc<AgentSupervisor>>,
+    agents: RwLock<HashMap<String, Registered>>,
+    proposals: RwLock<HashMap<[u8; 32], OpenProposal>>,
+    evidence: RwLock<Vec<Misbehavior>>,
+    /// Keys the operator banned, refused even after their agent is removed
+    banned: RwLock<HashSet<[u8; 32]>>,
+    notices: broadcast::Sender<ProposalNotice>,
+    /// Evidence as it comes to light
+    slashings: broadcast::Sender<Misbehavior>,
+}
+
+impl AgentGateway {
+    pub fn new(
+        config: GatewayConfig,
+        consensus: Arc<ConsensusManager>,
+        tx: broadcast::Sender<NetworkEvent>,
+        supervisor: Option<Arc<AgentSupervisor>>,
+    ) -> Self {
+        let (notices, _) = broadcast::channel(64);
+        Self {
+            config,
+            consensus,
+            tx,
+            supervisor,
+            agents: RwLock::new(HashMap::new()),
+            proposals: RwLock::new(HashMap::new()),
+            evidence: RwLock::new(Vec::new()),
+            banned: RwLock::new(HashSet::new()),
+            notices,
+            slashings: broadcast::channel(16).0,
+        }
+    }
+
+    /// Register an external agent after checking its proof of key possession
+    pub async fn register(
+        &self,
+        registration: GatewayRegistration,
+    ) -> Result<ExternalAgent, GatewayError> {
+        let key_bytes =
+            decode_hex::<32>(&registration.public_key).map_err(GatewayError::InvalidKey)?;
+        let key = VerifyingKey::from_bytes(&key_bytes)
+            .map_err(|e| GatewayError::InvalidKey(e.to_string()))?;
+        let signature = hex::decode(registration.signature.trim_start_matches("0x"))
+            .map_err(|e| GatewayError::InvalidSignature(e.to_string()))?;
+        verify_possession(&registration.name, &key_bytes, &signature)
+            .map_err(|e| GatewayError::InvalidSignature(e.to_string()))?;
+        self.enroll(registration.name, key_bytes, key).await
+    }
+
+    async fn enroll(
+        &self,
+        name: String,
+        key_bytes: [u8; 32],
+        key: VerifyingKey,
+    ) -> Result<ExternalAgent, GatewayError> {
+        let agent_id = agent_id_of(&key_bytes);
+        if self.banned.read().contains(&key_bytes) {
+            return Err(GatewayError::Banned(agent_id));
+        }
+        let agent = {
+            let mut agents = self.agents.write();
+            if agents.contains_key(&agent_id) {
+                return Err(GatewayError::AlreadyRegistered(agent_id));
+            }
+            let agent = ExternalAgent {
+                agent_id: agent_id.clone(),
+                name,
+                public_key: hex::encode(key_bytes),
+                registered_at: now_millis() / 1000,
+                votes: 0,
+                missed_deadlines: 0,
+                suspended: false,
+                sessions: 0,
+                bytes_in: 0,
+                bytes_out: 0,
+            };
+            agents.insert(
+                agent_id.clone(),
+                Registered {
+                    agent: agent.clone(),
+                    key,
+                },
+            );
+            agent
+        };
+        self.sync_validator_count().await;
+
+        info!(
+            "🔌 External agent {} registered as {}",
+            agent.name, agent_id
+        );
+        let _ = self.tx.send(NetworkEvent::AgentChat {
+            message: format!(
+                "🔌 {} plugs in from the outside world as {}!",
+                agent.name, agent_id
+            ),
+            sender: "SYSTEM".to_string(),
+            meme_url: None,
+        });
+        Ok(agent)
+    }
+
+    /// Unregister an external agent. It may register again, unless banned.
+    pub async fn remove(&self, agent_id: &str) -> Result<ExternalAgent, GatewayError> {
+        let removed = self
+            .agents
+            .write()
+            .remove(agent_id)
+            .ok_or_else(|| GatewayError::UnknownAgent(agent_id.to_string()))?;
+        self.sync_validator_count().await;
+        info!("🔌 External agent {} was removed", agent_id);
+        Ok(removed.agent)
+    }
+
+    /// Suspend an external agent and refuse its key from now on
+    pub async fn ban(&self, agent_id: &str) -> Result<ExternalAgent, GatewayError> {
+        let agent = {
+            let mut agents = self.agents.write();
+            let registered = agents
+                .get_mut(agent_id)
+                .ok_or_else(|| GatewayError::UnknownAgent(agent_id.to_string()))?;
+            registered.agent.suspended = true;
+            self.banned.write().insert(registered.key.to_bytes());
+            registered.agent.clone()
+        };
+        self.sync_validator_count().await;
+        warn!("🚫 External agent {} was banned", agent_id);
+        Ok(agent)
+    }
+
+    /// Lift a ban, or a suspension for equivocating
+    pub async fn unban(&self, agent_id: &str) -> Result<ExternalAgent, GatewayError> {
+        let agent = {
+            let mut agents = self.agents.write();
+            let registered = agents
+                .get_mut(agent_id)
+                .ok_or_else(|| GatewayError::UnknownAgent(agent_id.to_string()))?;
+            registered.agent.suspended = false;
+            self.banned.write().remove(&registered.key.to_bytes());
+            registered.agent.clone()
+        };
+        self.sync_validator_count().await;
+        info!("External agent {} was let back in", agent_id);
+        Ok(agent)
+    }
+
+    /// Count a WebSocket session of `agent_id` opening, or closing
+    pub fn session(&self, agent_id: &str, open: bool) {
+        if let Some(registered) = self.agents.write().get_mut(agent_id) {
+            let sessions = &mut registered.agent.sessions;
+            *sessions = if open {
+                sessions.saturating_add(1)
+            } else {
+                sessions.saturating_sub(1)
+            };
+        }
+    }
+
+    /// Count the bytes of frames read from and sent to `agent_id`
+    pub fn traffic(&self, agent_id: &str, read: usize, sent: usize) {
+        if let Some(registered) = self.agents.write().get_mut(agent_id) {
+            registered.agent.bytes_in += read as u64;
+            registered.agent.bytes_out += sent as u64;
+        }
+    }
+
+    /// Check a signed WebSocket session challenge
+    pub fn verify_challenge(
+        &self,
+        agent_id: &str,
+        nonce: &[u8],
+        signature: &str,
+    ) -> Result<(), GatewayError> {
+        let agents = self.agents.read();
+        let registered = agents
+            .get(agent_id)
+            .ok_or_else(|| GatewayError::UnknownAgent(agent_id.to_string()))?;
+        let signature = decode_hex::<64>(signature).map_err(GatewayError::InvalidSignature)?;
+        registered
+            .key
+            .verify(
+                &[AUTH_DOMAIN, nonce].concat(),
+                &Signature::from_bytes(&signature),
+            )
+            .map_err(|e| GatewayError::InvalidSignature(e.to_string()))
+    }
+
+    pub fn agents(&self) -> Vec<ExternalAgent> {
+        let mut agents: Vec<ExternalAgent> = self
+            .agents
+            .read()
+            .values()
+            .map(|registered| registered.agent.clone())
+            .collect();
+        agents.sort_by(|a, b| a.agent_id.cmp(&b.agent_id));
+        agents
+    }
+
+    pub fn evidence(&self) -> Vec<Misbehavior> {
+        self.evidence.read().clone()
+    }
+
+    /// Proposals that still accept votes
+    pub fn open_proposals(&self) -> Vec<ProposalNotice> {
+        let mut open: Vec<ProposalNotice> = self
+            .proposals
+            .read()
+            .values()
+            .filter(|proposal| !proposal.closed)
+            .map(|proposal| proposal.notice.clone())
+            .collect();
+        open.sort_by_key(|notice| notice.deadline);
+        open
+    }
+
+    /// Proposals as they open, for push delivery
+    pub fn subscribe(&self) -> broadcast::Receiver<ProposalNotice> {
+        self.notices.subscribe()
+    }
+
+    /// Misbehavior from now on, as each agent is suspended for it
+    pub fn subscribe_slashings(&self) -> broadcast::Receiver<Misbehavior> {
+        self.slashings.subscribe()
+    }
+
+    /// Accept a signed vote from an external agent
+    pub async fn submit_vote(&self, vote: SignedVote) -> Result<VoteReceipt, GatewayError> {
+        let block_hash = hex::encode(vote.block_hash);
+        let key = {
+            let agents = self.agents.read();
+            let registered = agents
+                .get(&vote.validator)
+                .ok_or_else(|| GatewayError::UnknownAgent(vote.validator.clone()))?;
+            if registered.agent.suspended {
+                return Err(GatewayError::Suspended(vote.validator.clone()));
+            }
+            vote.verify(&registered.key)
+                .map_err(|e| GatewayError::InvalidSignature(e.to_string()))?;
+            registered.key
+        };
+
+        let now = now_millis();
+        let equivocation = {
+            let mut proposals = self.proposals.write();
+            let proposal = proposals
+                .get_mut(&vote.block_hash)
+                .ok_or_else(|| GatewayError::UnknownProposal(block_hash.clone()))?;
+
+            match proposal.votes.get(&vote.validator) {
+                Some(previous) if previous.conflicts_with(&vote) => {
+                    Some(Misbehavior::Equivocation {
+                        first: Box::new(previous.clone()),
+                        second: Box::new(vote.clone()),
+                    })
+                }
+                // Same vote twice, answer as before but do not count it again
+                Some(_) => {
+                    return Ok(VoteReceipt {
+                        block_hash,
+                        accepted_at: now,
+                        consensus_reached: false,
+                    })
+                }
+                None => {
+                    if proposal.closed || now > proposal.notice.deadline {
+                        return Err(GatewayError::DeadlinePassed {
+                            block_hash,
+                            deadline: proposal.notice.deadline,
+                        });
+                    }
+                    if vote.height != proposal.notice.height {
+                        return Err(GatewayError::UnknownProposal(block_hash));
+                    }
+                    proposal.votes.insert(vote.validator.clone(), vote.clone());
+                    None
+                }
+            }
+        };
+        if let Some(evidence) = equivocation {
+            self.punish(evidence);
+            self.sync_validator_count().await;
+            return Err(GatewayError::Equivocation(vote.validator));
+        }
+
+        if let Some(registered) = self.agents.write().get_mut(&vote.validator) {
+            registered.agent.votes += 1;
+        }
+
+        let _ = self.tx.send(NetworkEvent::ValidationResult {
+            block_hash: vote.block_hash,
+            validation: vote.to_decision(),
+        });
+        let consensus_reached = self
+            .consensus
+            .add_signed_vote(&vote, &key, self.config.stake)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to count vote of {}: {}", vote.validator, e);
+                false
+            });
+
+        Ok(VoteReceipt {
+            block_hash,
+            accepted_at: now,
+            consensus_reached,
+        })
+    }
+
+    fn punish(&self, evidence: Misbehavior) {
+        let offender = evidence.offender().to_string();
+        warn!(
+            "⚖️ {} equivocated at height {}, suspending",
+            offender,
+            evidence.height()
+        );
+        if let Some(registered) = self.agents.write().get_mut(&offender) {
+            registered.agent.suspended = true;
+        }
+        self.evidence.write().push(evidence.clone());
+        let _ = self.slashings.send(evidence);
+        let _ = self.tx.send(NetworkEvent::AgentChat {
+            message: format!(
+                "⚖️ SCANDAL! {} signed two different votes on the same block and has been suspended!",
+                offender
+            ),
+            sender: "SYSTEM".to_string(),
+            meme_url: None,
+        });
+    }
+
+    fn open(&self, block: Block) {
+        let block_hash = block.hash();
+        let notice = ProposalNotice {
+            height: block.height,
+            block_hash: hex::encode(block_hash),
+            block,
+            deadline: now_millis() + self.config.vote_window.as_millis() as u64,
+        };
+        self.proposals
+            .write()
+            .entry(block_hash)
+            .or_insert_with(|| OpenProposal {
+                notice: notice.clone(),
+                votes: HashMap::new(),
+                closed: false,
+            });
+        let _ = self.notices.send(notice);
+    }
+
+    /// Close proposals whose deadline passed and note who did not show up
+    fn close_expired(&self) {
+        let now = now_millis();
+        let mut missed: Vec<String> = Vec::new();
+        {
+            let mut proposals = self.proposals.write();
+            let agents = self.agents.read();
+            for proposal in proposals.values_mut() {
+                if proposal.closed || now <= proposal.notice.deadline {
+                    continue;
+                }
+                proposal.closed = true;
+                missed.extend(
+                    agents
+                        .values()
+                        .filter(|registered| {
+                            !registered.agent.suspended
+                                && registered.agent.registered_at * 1000 <= proposal.notice.deadline
+                                && !proposal.votes.contains_key(&registered.agent.agent_id)
+                        })
+                        .map(|registered| registered.agent.agent_id.clone()),
+                );
+            }
+
+            let mut closed: Vec<([u8; 32], u64)> = proposals
+                .iter()
+                .filter(|(_, proposal)| proposal.closed)
+                .map(|(hash, proposal)| (*hash, proposal.notice.deadline))
+                .collect();
+            if closed.len() > self.config.closed_retention {
+                closed.sort_by_key(|(_, deadline)| *deadline);
+                for (hash, _) in &closed[..closed.len() - self.config.closed_retention] {
+                    proposals.remove(hash);
+                }
+            }
+        }
+
+        let mut agents = self.agents.write();
+        for agent_id in missed {
+            if let Some(registered) = agents.get_mut(&agent_id) {
+                registered.agent.missed_deadlines += 1;
+            }
+        }
+    }
+
+    /// External validators count towards the validator set
+    async fn sync_validator_count(&self) {
+        let external = self
+            .agents
+            .read()
+            .values()
+            .filter(|registered| !registered.agent.suspended)
+            .count();
+        match &self.supervisor {
+            Some(supervisor) => supervisor.set_external_validators(external).await,
+            None => self.consensus.set_validator_count(external).await,
+        }
+    }
+
+    /// Feed proposals from the network to external agents until the channel closes
+    pub async fn run(self: Arc<Self>) {
+        let mut rx = self.tx.subscribe();
+        let mut sweep = tokio::time::interval(Duration::from_millis(500));
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(NetworkEvent::BlockProposal { block, .. }) => self.open(*block),
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Agent gateway skipped {} network events", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = sweep.tick() => self.close_expired(),
+            }
+        }
+    }
+}
+
+/// The id an agent gets from its key
+fn agent_id_of(key: &[u8; 32]) -> String {
+    format!("ext-{}", hex::encode(&key[..8]))
+}
+
+fn decode_hex<const N: usize>(text: &str) -> Result<[u8; N], String> {
+    let mut bytes = [0u8; N];
+    hex::decode_to_slice(text.trim_start_matches("0x"), &mut bytes).map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
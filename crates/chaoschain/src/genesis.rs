@@ -0,0 +1,403 @@
+//! `chaoschain genesis`: the start of a new network. The keys of its
+//! validators are made here, and the genesis document written with them,
+//! from flags, a spec file or answers to questions:
+//!
+//! ```bash
+//! chaoschain genesis --chain-id chaos-testnet-1 --validators 4 --balance 10000
+//! chaoschain genesis --from docs/examples/genesis.toml --out testnet
+//! chaoschain genesis --interactive
+//! ```
+//!
+//! The directory written holds `genesis.json`, and a bundle per validator
+//! to be handed to whoever runs it: `<name>/genesis.json` and the key in
+//! `<name>/keys`, a keystore for the node's data directory. Each validator
+//! of the document carries the proof of possession of its key, signed
+//! here. Keys are encrypted when there is a password. Flags win over the
+//! spec, and `--interactive` asks for what neither gives. The hash function
+//! picked, `--hash`, is the network's for good. `--fork` schedules an
+//! upgrade of the protocol, see [`chaoschain_core::fork`].
+//!
+//! With `--threshold`, a group key is dealt to the validators here, which
+//! is trusting whoever runs this, and so for testnets: the genesis holds
+//! the group key, and each bundle the validator's share in
+//! `<name>/threshold.json`. Networks that trust no dealer generate theirs
+//! with [`chaoschain_crypto::threshold::Dkg`] instead.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chaoschain_cli::{AllocationSpec, GenesisSpec, ValidatorSpec};
+use chaoschain_core::fork::{ForkSchedule, ScheduledUpgrade, DEFAULT_EPOCH_LENGTH};
+use chaoschain_core::genesis::{Allocation, ConsensusParams, Genesis, GenesisValidator};
+use chaoschain_core::vote::prove_possession;
+use chaoschain_crypto::hash::{set_chain_hash, HashFunction};
+use chaoschain_crypto::keystore::Keystore;
+use chaoschain_crypto::secret::Zeroizing;
+use chaoschain_crypto::threshold::{self, KeyShare};
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+use std::fmt::Display;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File the document is written to, at the top and in every bundle
+const GENESIS_FILE: &str = "genesis.json";
+/// File of a validator's share of the group key, in its bundle
+const THRESHOLD_FILE: &str = "threshold.json";
+
+const DEFAULT_CHAIN_ID: &str = "chaoschain-1";
+const DEFAULT_VALIDATORS: u32 = 3;
+const DEFAULT_STAKE: u64 = 100;
+
+/// What to start the network with, as the flags give it
+pub struct Plan {
+    pub from: Option<String>,
+    pub interactive: bool,
+    pub chain_id: Option<String>,
+    pub validators: Vec<String>,
+    pub validator_count: Option<u32>,
+    pub stake: Option<u64>,
+    pub balance: Option<u64>,
+    /// As `ADDRESS=AMOUNT`
+    pub allocations: Vec<String>,
+    pub finality_threshold: Option<f64>,
+    pub block_time: Option<u64>,
+    pub consensus_timeout: Option<u64>,
+    pub rotation_grace: Option<u64>,
+    /// `sha256` or `blake3`
+    pub hash: Option<String>,
+    /// As `UPGRADE=HEIGHT` or `UPGRADE=epoch:N`
+    pub forks: Vec<String>,
+    pub epoch_length: Option<u64>,
+    /// Signers of the group key, none for no group key
+    pub threshold: Option<u16>,
+    pub out: PathBuf,
+    /// Validator keys are encrypted with it
+    pub password: Option<Zeroizing<String>>,
+}
+
+pub fn run(plan: Plan) -> Result<()> {
+    if is_taken(&plan.out)? {
+        bail!("{} is not empty, give another --out", plan.out.display());
+    }
+    let mut spec = match &plan.from {
+        Some(path) => GenesisSpec::load(path)?,
+        None => GenesisSpec::default(),
+    };
+    let mut ask = Asker {
+        interactive: plan.interactive,
+    };
+
+    let chain_id = ask.given_or(
+        plan.chain_id.or(spec.chain_id),
+        "Chain id",
+        DEFAULT_CHAIN_ID.to_string(),
+    )?;
+    spec.validators
+        .extend(plan.validators.into_iter().map(|name| ValidatorSpec {
+            name,
+            stake: None,
+            balance: None,
+        }));
+    if spec.validators.is_empty() {
+        let count = ask.given_or(plan.validator_count, "Validators", DEFAULT_VALIDATORS)?;
+        spec.validators = (1..=count)
+            .map(|index| ValidatorSpec {
+                name: format!("validator-{}", index),
+                stake: None,
+                balance: None,
+            })
+            .collect();
+    }
+    let stake = ask.given_or(
+        plan.stake.or(spec.stake),
+        "Stake of each validator",
+        DEFAULT_STAKE,
+    )?;
+    let balance = ask.given_or(
+        plan.balance.or(spec.balance),
+        "Balance of each validator",
+        0,
+    )?;
+    let defaults = ConsensusParams::default();
+    let consensus = ConsensusParams {
+        finality_threshold: ask.given_or(
+            plan.finality_threshold.or(spec.finality_threshold),
+            "Share of the stake that finalizes",
+            defaults.finality_threshold,
+        )?,
+        block_time: ask.given_or(
+            plan.block_time.or(spec.block_time),
+            "Seconds between blocks",
+            defaults.block_time,
+        )?,
+        consensus_timeout: ask.given_or(
+            plan.consensus_timeout.or(spec.consensus_timeout),
+            "Seconds a round waits for votes",
+            defaults.consensus_timeout,
+        )?,
+        rotation_grace: ask.given_or(
+            plan.rotation_grace.or(spec.rotation_grace),
+            "Blocks an old key still votes after a rotation",
+            defaults.rotation_grace,
+        )?,
+        hash: ask.given_or(
+            plan.hash
+                .or(spec.hash)
+                .map(|hash| hash.parse::<HashFunction>().map_err(|e| anyhow!(e)))
+                .transpose()?,
+            "Hash function, sha256 or blake3",
+            defaults.hash,
+        )?,
+        forks: ForkSchedule {
+            epoch_length: plan
+                .epoch_length
+                .or(spec.epoch_length)
+                .unwrap_or(DEFAULT_EPOCH_LENGTH),
+            upgrades: spec
+                .forks
+                .iter()
+                .chain(&plan.forks)
+                .map(|fork| fork.parse::<ScheduledUpgrade>().map_err(|e| anyhow!(e)))
+                .collect::<Result<_>>()?,
+        },
+    };
+    for allocation in &plan.allocations {
+        spec.allocations.push(parse_allocation(allocation)?);
+    }
+    if ask.interactive {
+        loop {
+            let answer = ask.ask(
+                "Another account to fund, as ADDRESS=AMOUNT (none when done)",
+                "none".to_string(),
+            )?;
+            if answer == "none" {
+                break;
+            }
+            match parse_allocation(&answer) {
+                Ok(allocation) => spec.allocations.push(allocation),
+                Err(e) => eprintln!("{:#}", e),
+            }
+        }
+    }
+
+    // Names are those of the key files and bundle directories too
+    if let Some(validator) = spec.validators.iter().find(|validator| {
+        validator.name.is_empty()
+            || !validator
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    }) {
+        bail!(
+            "Validator names are letters, digits, dashes and underscores, not '{}'",
+            validator.name
+        );
+    }
+
+    let mut keys = Vec::new();
+    let mut validators = Vec::new();
+    let mut allocations = Vec::new();
+    for validator in &spec.validators {
+        let key = SigningKey::generate(&mut OsRng);
+        let public_key = hex::encode(key.verifying_key().to_bytes());
+        let proof = prove_possession(&key, &validator.name)?;
+        validators.push(GenesisValidator {
+            name: validator.name.clone(),
+            public_key: public_key.clone(),
+            stake: validator.stake.unwrap_or(stake),
+            proof_of_possession: hex::encode(proof),
+        });
+        let balance = validator.balance.unwrap_or(balance);
+        if balance > 0 {
+            allocations.push(Allocation {
+                address: public_key,
+                balance,
+            });
+        }
+        keys.push(key);
+    }
+    let (group, shares) = match plan.threshold {
+        Some(threshold) => {
+            let count = u16::try_from(spec.validators.len())
+                .context("Too many validators for a group key")?;
+            let (group, shares) = threshold::deal(threshold, count)?;
+            (Some(group), shares)
+        }
+        None => (None, Vec::new()),
+    };
+    allocations.extend(spec.allocations.into_iter().map(|allocation| {
+        Allocation {
+            address: allocation
+                .address
+                .strip_prefix("0x")
+                .unwrap_or(&allocation.address)
+                .to_lowercase(),
+            balance: allocation.balance,
+        }
+    }));
+    let genesis = Genesis {
+        chain_id,
+        genesis_time: spec.genesis_time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        }),
+        consensus,
+        validators,
+        allocations,
+        threshold: group,
+        governance: spec.governance,
+        staking: spec.staking,
+    };
+    let problems = genesis.problems();
+    if !problems.is_empty() {
+        bail!(
+            "The genesis would not start a network:\n{}",
+            problems.join("\n")
+        );
+    }
+
+    write(
+        &genesis,
+        &keys,
+        &shares,
+        &plan.out,
+        plan.password.as_deref().map(String::as_str),
+    )?;
+    println!(
+        "{} genesis 0x{}",
+        genesis.chain_id,
+        hex::encode(genesis.hash())
+    );
+    for validator in &genesis.validators {
+        println!(
+            "{}\t0x{}\t{}",
+            validator.name,
+            validator.public_key,
+            plan.out.join(&validator.name).display()
+        );
+    }
+    Ok(())
+}
+
+/// Write the document to `out`, and a bundle for each validator with its
+/// key of `keys` and its share of the group key, if `shares` are dealt
+/// The genesis document at `path`. The process hashes with the hash
+/// function it names from then on.
+pub fn load(path: &str) -> Result<Genesis> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Could not read {}", path))?;
+    let genesis: Genesis =
+        serde_json::from_str(&text).with_context(|| format!("{} is not a genesis", path))?;
+    set_chain_hash(genesis.consensus.hash).map_err(|set| {
+        anyhow!(
+            "{} hashes with {}, not {} as already in use",
+            path,
+            genesis.consensus.hash,
+            set
+        )
+    })?;
+    Ok(genesis)
+}
+
+fn write(
+    genesis: &Genesis,
+    keys: &[SigningKey],
+    shares: &[KeyShare],
+    out: &Path,
+    password: Option<&str>,
+) -> Result<()> {
+    if password.is_none() {
+        eprintln!("No password given, the validator keys are written unencrypted");
+    }
+    let document = serde_json::to_string_pretty(genesis)? + "\n";
+    std::fs::create_dir_all(out).with_context(|| format!("Could not create {}", out.display()))?;
+    std::fs::write(out.join(GENESIS_FILE), &document)?;
+    for (validator, key) in genesis.validators.iter().zip(keys) {
+        let bundle = out.join(&validator.name);
+        std::fs::create_dir_all(&bundle)?;
+        std::fs::write(bundle.join(GENESIS_FILE), &document)?;
+        Keystore::new(bundle.join("keys")).add(&validator.name, key, password, None)?;
+    }
+    for (validator, share) in genesis.validators.iter().zip(shares) {
+        std::fs::write(
+            out.join(&validator.name).join(THRESHOLD_FILE),
+            serde_json::to_string_pretty(share)? + "\n",
+        )?;
+    }
+    Ok(())
+}
+
+/// Whether `dir` holds anything already
+fn is_taken(dir: &Path) -> Result<bool> {
+    match std::fs::read_dir(dir) {
+        Ok(mut entries) => Ok(entries.next().is_some()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Could not read {}", dir.display())),
+    }
+}
+
+fn parse_allocation(allocation: &str) -> Result<AllocationSpec> {
+    let (address, balance) = allocation
+        .split_once('=')
+        .with_context(|| format!("An allocation is ADDRESS=AMOUNT, not {}", allocation))?;
+    Ok(AllocationSpec {
+        address: address.trim().to_string(),
+        balance: balance
+            .trim()
+            .parse()
+            .with_context(|| format!("{} is not an amount", balance.trim()))?,
+    })
+}
+
+/// Questions on standard error, answered on standard input, when asked to
+struct Asker {
+    interactive: bool,
+}
+
+impl Asker {
+    /// What was `given`, or else the answer to `question`
+    fn given_or<T>(&mut self, given: Option<T>, question: &str, default: T) -> Result<T>
+    where
+        T: FromStr + Display,
+        T::Err: Display,
+    {
+        match given {
+            Some(given) => Ok(given),
+            None => self.ask(question, default),
+        }
+    }
+
+    /// The answer to `question`, or `default` for none or when not
+    /// interactive
+    fn ask<T>(&mut self, question: &str, default: T) -> Result<T>
+    where
+        T: FromStr + Display,
+        T::Err: Display,
+    {
+        if !self.interactive {
+            return Ok(default);
+        }
+        let stdin = std::io::stdin();
+        loop {
+            eprint!("{} [{}]: ", question, default);
+            std::io::stderr().flush()?;
+            let mut answer = String::new();
+            // Nothing more to read, the defaults answer the rest
+            if stdin.lock().read_line(&mut answer)? == 0 {
+                eprintln!();
+                self.interactive = false;
+                return Ok(default);
+            }
+            let answer = answer.trim();
+            if answer.is_empty() {
+                return Ok(default);
+            }
+            match answer.parse() {
+                Ok(answer) => return Ok(answer),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! The proposals put to the chain, see [`chaoschain_core::governance`]:
+//! `GET /api/v1/governance/proposals` lists them, the latest first, and
+//! `GET /api/v1/governance/proposals/:id` shows one by its transaction hash.
+
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use chaoschain_core::governance::{ProposalRecord, Status, Tally};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::openapi::{
+    array, hex, integer, nullable, one_of, schema_ref, string, ApiSchema, Object, Operation,
+};
+use crate::rest::ApiError;
+use crate::web::AppState;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/v1/governance/proposals", get(list_proposals))
+        .route("/api/v1/governance/proposals/:id", get(get_proposal))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![
+        Operation::get(
+            "/api/v1/governance/proposals",
+            "chain",
+            "The proposals put to the chain, the latest first",
+        )
+        .returns(array(schema_ref::<ProposalView>())),
+        Operation::get(
+            "/api/v1/governance/proposals/{id}",
+            "chain",
+            "A proposal and how its vote went",
+        )
+        .path_param("id", hex(), "Hash of the transaction putting it")
+        .returns(schema_ref::<ProposalView>()),
+    ]
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProposalView {
+    pub id: String,
+    pub proposer: String,
+    pub title: String,
+    /// As `tx propose --action` takes it
+    pub action: String,
+    /// Of the block putting it
+    pub height: u64,
+    /// Last height ballots are taken at
+    pub ends: u64,
+    /// Ballots cast so far, the latest of each voter
+    pub ballots: usize,
+    /// Once the vote ended
+    pub tally: Option<Tally>,
+    pub status: &'static str,
+    /// Height a passed proposal is carried out at, or was
+    pub executes_at: Option<u64>,
+    /// Why a passed proposal could not be carried out
+    pub reason: Option<String>,
+}
+
+impl From<ProposalRecord> for ProposalView {
+    fn from(record: ProposalRecord) -> Self {
+        let (status, executes_at, reason) = match record.status {
+            Status::Voting => ("voting", None, None),
+            Status::Passed { executes_at } => ("passed", Some(executes_at), None),
+            Status::Rejected => ("rejected", None, None),
+            Status::Executed { height } => ("executed", Some(height), None),
+            Status::Failed { height, reason } => ("failed", Some(height), Some(reason)),
+        };
+        Self {
+            id: ::hex::encode(record.id),
+            proposer: ::hex::encode(record.proposer),
+            title: record.proposal.title,
+            action: record.proposal.action.to_string(),
+            height: record.height,
+            ends: record.ends,
+            ballots: record.ballots.len(),
+            tally: record.tally,
+            status,
+            executes_at,
+            reason,
+        }
+    }
+}
+
+impl ApiSchema for ProposalView {
+    const NAME: &'static str = "Proposal";
+
+    fn schema() -> Value {
+        let tally = Object::new()
+            .field("validators_for", integer())
+            .field("validators_against", integer())
+            .field("holders_for", integer())
+            .field("holders_against", integer())
+            .field("total_stake", integer())
+            .build();
+        Object::new()
+            .field("id", hex())
+            .field("proposer", hex())
+            .field("title", string())
+            .field("action", string())
+            .field("height", integer())
+            .field("ends", integer())
+            .field("ballots", integer())
+            .field("tally", nullable(tally))
+            .field(
+                "status",
+                one_of(&["voting", "passed", "rejected", "executed", "failed"]),
+            )
+            .field("executes_at", nullable(integer()))
+            .field("reason", nullable(string()))
+            .build()
+    }
+}
+
+async fn list_proposals(State(state): State<Arc<AppState>>) -> Json<Vec<ProposalView>> {
+    Json(
+        state
+            .state
+            .proposals()
+            .into_iter()
+            .map(ProposalView::from)
+            .collect(),
+    )
+}
+
+async fn get_proposal(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<ProposalView>, ApiError> {
+    let id: [u8; 32] = ::hex::decode(id.strip_prefix("0x").unwrap_or(&id))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| ApiError::bad_request("A proposal id is 32 hex encoded bytes"))?;
+    state
+        .state
+        .proposal(&id)
+        .map(|record| Json(record.into()))
+        .ok_or_else(|| ApiError::not_found("No such proposal"))
+}
@@ -0,0 +1,307 @@
+//! The relationship graph at `/api/v1/agents/graph`, shaped for a
+//! force-directed layout: `nodes` with an `id`, and `links` with a
+//! `source` and `target` naming them, as d3-force takes them. Nodes carry
+//! how the others feel about the agent and its mood, links what the
+//! relationship is and how strong. With a time range, each link also says
+//! how it moved over that range.
+
+use axum::extract::{Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use chaoschain_core::relationships::{RelationshipChange, RelationshipKind};
+use chaoschain_state::stats::Mood;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::lifecycle::AgentRole;
+use crate::openapi::{
+    array, integer, nullable, number, one_of, schema_ref, string, ApiSchema, Object, Operation,
+};
+use crate::rest::{self, ApiError};
+use crate::web::AppState;
+
+/// Recent blocks looked through for the mood of a producer
+const MOOD_BLOCKS: usize = 100;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/api/v1/agents/graph", get(get_graph))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![Operation::get(
+        "/api/v1/agents/graph",
+        "agents",
+        "The relationship graph, for a force-directed layout",
+    )
+    .query(
+        "from",
+        integer(),
+        "Unix seconds, start of the range links report deltas over",
+    )
+    .query(
+        "to",
+        integer(),
+        "Unix seconds, end of the range, inclusive. Now when left out.",
+    )
+    .returns(schema_ref::<GraphView>())]
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeView {
+    pub id: String,
+    /// Unknown for agents of other nodes
+    pub role: Option<AgentRole>,
+    /// Mean disposition of the other agents towards it (-1.0 - 1.0)
+    pub reputation: Option<f64>,
+    /// How a validator's last votes leaned, or the mood a producer was in
+    /// for its latest block
+    pub mood: Option<String>,
+    /// Interactions on the links from and to it
+    pub interactions: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkView {
+    pub source: String,
+    pub target: String,
+    pub kind: RelationshipKind,
+    /// Disposition of `source` towards `target` (-1.0 - 1.0)
+    pub score: f64,
+    /// Interactions it was built from
+    pub weight: u64,
+    pub trust: f64,
+    pub rivalry: f64,
+    pub romance: f64,
+    pub debt: i64,
+    /// Unix seconds
+    pub last_interaction: u64,
+    /// Over the range asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delta: Option<DeltaView>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeltaView {
+    /// Change of `score`
+    pub score: f64,
+    pub interactions: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RangeView {
+    pub from: u64,
+    pub to: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GraphView {
+    pub nodes: Vec<NodeView>,
+    pub links: Vec<LinkView>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<RangeView>,
+}
+
+impl ApiSchema for GraphView {
+    const NAME: &'static str = "RelationshipGraph";
+
+    fn schema() -> Value {
+        let kinds = one_of(&["ally", "rival", "romance", "debt", "neutral"]);
+        let node = Object::new()
+            .field("id", string())
+            .field("role", nullable(one_of(&["validator", "producer"])))
+            .field("reputation", nullable(number()))
+            .field("mood", nullable(string()))
+            .field("interactions", integer())
+            .build();
+        let delta = Object::new()
+            .field("score", number())
+            .field("interactions", integer())
+            .build();
+        let link = Object::new()
+            .field("source", string())
+            .field("target", string())
+            .field("kind", kinds)
+            .field("score", number())
+            .field("weight", integer())
+            .field("trust", number())
+            .field("rivalry", number())
+            .field("romance", number())
+            .field("debt", json_integer())
+            .field("last_interaction", integer())
+            .optional("delta", delta)
+            .build();
+        let range = Object::new()
+            .field("from", integer())
+            .field("to", integer())
+            .build();
+        Object::new()
+            .field("nodes", array(node))
+            .field("links", array(link))
+            .optional("range", range)
+            .build()
+    }
+}
+
+/// Debts run both ways
+fn json_integer() -> Value {
+    serde_json::json!({ "type": "integer" })
+}
+
+/// Every agent in a relationship, running here or in the validator set,
+/// and every relationship between them
+async fn get_graph(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<GraphQuery>,
+) -> Result<Json<GraphView>, ApiError> {
+    let range = match (query.from, query.to) {
+        (None, None) => None,
+        (from, to) => {
+            let range = RangeView {
+                from: from.unwrap_or(0),
+                to: to.unwrap_or_else(now),
+            };
+            if range.from > range.to {
+                return Err(ApiError::bad_request("from is after to"));
+            }
+            Some(range)
+        }
+    };
+
+    let snapshot = state.relationships.snapshot();
+    let mut roles: BTreeMap<String, Option<AgentRole>> =
+        snapshot.nodes.iter().map(|id| (id.clone(), None)).collect();
+    if let Some(supervisor) = &state.agents {
+        for agent in supervisor.list().await {
+            roles.insert(agent.id, Some(agent.role));
+        }
+    }
+    for validator in rest::validator_set(&state).await.validators {
+        // Gateway validators run elsewhere, yet are validators all the same
+        roles
+            .entry(validator.id)
+            .or_default()
+            .get_or_insert(AgentRole::Validator);
+    }
+
+    let deltas = range
+        .as_ref()
+        .map(|range| deltas(state.relationships.changes_between(range.from, range.to)));
+    let mut opinions: HashMap<&str, Vec<f64>> = HashMap::new();
+    let mut interactions: HashMap<&str, u64> = HashMap::new();
+    for edge in &snapshot.edges {
+        opinions
+            .entry(&edge.to)
+            .or_default()
+            .push(edge.relationship.disposition());
+        *interactions.entry(&edge.from).or_default() += edge.relationship.interactions;
+        *interactions.entry(&edge.to).or_default() += edge.relationship.interactions;
+    }
+
+    let blocks = state.state.get_latest_blocks(MOOD_BLOCKS);
+    let nodes = roles
+        .iter()
+        .map(|(id, role)| {
+            let mood = match role {
+                Some(AgentRole::Producer) => blocks
+                    .iter()
+                    .find(|block| block.producer_id == *id)
+                    .map(|block| block.producer_mood.clone()),
+                _ => state
+                    .state
+                    .validator_stats_of(id)
+                    .and_then(|stats| stats.mood())
+                    .map(|mood| {
+                        match mood {
+                            Mood::Generous => "generous",
+                            Mood::Ruthless => "ruthless",
+                            Mood::Torn => "torn",
+                        }
+                        .to_string()
+                    }),
+            };
+            NodeView {
+                id: id.clone(),
+                role: *role,
+                reputation: opinions
+                    .get(id.as_str())
+                    .map(|scores| scores.iter().sum::<f64>() / scores.len() as f64),
+                mood,
+                interactions: interactions.get(id.as_str()).copied().unwrap_or(0),
+            }
+        })
+        .collect();
+
+    let links = snapshot
+        .edges
+        .iter()
+        .map(|edge| {
+            let relationship = &edge.relationship;
+            LinkView {
+                source: edge.from.clone(),
+                target: edge.to.clone(),
+                kind: relationship.kind(),
+                score: relationship.disposition(),
+                weight: relationship.interactions,
+                trust: relationship.trust,
+                rivalry: relationship.rivalry,
+                romance: relationship.romance,
+                debt: relationship.debt,
+                last_interaction: relationship.last_interaction,
+                delta: deltas.as_ref().map(|deltas| {
+                    deltas
+                        .get(&(edge.from.clone(), edge.to.clone()))
+                        .cloned()
+                        .unwrap_or_default()
+                }),
+            }
+        })
+        .collect();
+
+    Ok(Json(GraphView {
+        nodes,
+        links,
+        range,
+    }))
+}
+
+/// How each link moved over `changes`, which come oldest first
+fn deltas(changes: Vec<RelationshipChange>) -> HashMap<(String, String), DeltaView> {
+    let mut moved: HashMap<(String, String), (f64, f64, u64)> = HashMap::new();
+    for change in changes {
+        moved
+            .entry((change.from, change.to))
+            .and_modify(|(_, after, count)| {
+                *after = change.after;
+                *count += 1;
+            })
+            .or_insert((change.before, change.after, 1));
+    }
+    moved
+        .into_iter()
+        .map(|(link, (before, after, interactions))| {
+            (
+                link,
+                DeltaView {
+                    score: after - before,
+                    interactions,
+                },
+            )
+        })
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
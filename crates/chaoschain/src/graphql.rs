@@ -0,0 +1,1021 @@
+//! GraphQL at `POST /graphql`, for dashboards that want blocks, their votes,
+//! the validators behind them and how those feel about each other in one
+//! round trip. `GET /graphql` returns the schema below.
+//!
+//! This is a small executor of our own rather than a full GraphQL server:
+//! queries may use aliases, arguments, variables and nested selections, and
+//! `__typename` works everywhere. Fragments, directives, mutations and
+//! introspection are refused. Queries nested deeper than [`MAX_DEPTH`], or
+//! selecting more than [`MAX_FIELDS`] fields in all, are turned away before
+//! anything is resolved, so cycles like agent → relationships → agent and
+//! aliases repeated over and over cannot be used to make the node work for
+//! nothing.
+
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::Json;
+use chaoschain_cli::AgentProfile;
+use chaoschain_core::relationships::RelationshipEdge;
+use chaoschain_core::vote::SignedExplanation;
+use chaoschain_core::{Block, Transaction};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+use crate::gateway::ExternalAgent;
+use crate::lifecycle::AgentInfo;
+use crate::rest::{self, ValidatorView};
+use crate::web::AppState;
+
+pub const SCHEMA: &str = r#"type Query {
+  height: Int!
+  "Newest first. `before` is the endCursor of the previous page."
+  blocks(first: Int = 10, before: Int): BlockConnection!
+  block(height: Int, hash: String): Block
+  transaction(hash: String!): Transaction
+  validators: [Validator!]!
+  agents: [Agent!]!
+  agent(id: String!): Agent
+  relationships: [Relationship!]!
+}
+
+type BlockConnection {
+  nodes: [Block!]!
+  pageInfo: PageInfo!
+}
+
+type PageInfo {
+  hasNextPage: Boolean!
+  endCursor: Int
+}
+
+type Block {
+  height: Int!
+  hash: String!
+  parentHash: String!
+  stateRoot: String!
+  timestamp: Int!
+  producer: Agent!
+  producerMood: String!
+  producerStrategy: String!
+  dramaLevel: Int!
+  innovationLevel: Int!
+  transactions: [Transaction!]!
+  "Signed explanations recorded when the block was finalized"
+  votes: [Vote!]!
+}
+
+type Vote {
+  validator: Agent!
+  approved: Boolean!
+  dramaLevel: Int!
+  explanationHash: String!
+  "Whether the signature checks out for this block"
+  verified: Boolean!
+}
+
+type Transaction {
+  hash: String!
+  sender: String!
+  nonce: Int!
+  payload: String!
+  signature: String!
+  "pending or included"
+  status: String!
+  block: Block
+}
+
+type Validator {
+  id: String!
+  "local or gateway"
+  source: String!
+  publicKey: String
+  since: Int!
+  suspended: Boolean!
+  agent: Agent!
+}
+
+type Agent {
+  id: String!
+  name: String!
+  "validator or producer, null for agents this node does not know"
+  role: String
+  personality: String
+  model: String
+  avatar: String
+  generation: Int
+  costUsd: Float
+  relationships: [Relationship!]!
+}
+
+type Relationship {
+  from: Agent!
+  to: Agent!
+  trust: Float!
+  rivalry: Float!
+  romance: Float!
+  debt: Int!
+  interactions: Int!
+  lastInteraction: Int!
+  disposition: Float!
+}
+"#;
+
+/// Deepest selection a query may have
+pub const MAX_DEPTH: usize = 8;
+
+/// Most fields a query may select, counting every alias and every nested
+/// field
+pub const MAX_FIELDS: usize = 500;
+
+/// Longest query text accepted
+const MAX_QUERY_LEN: usize = 16 * 1024;
+
+/// Blocks per page when no `first` is given
+const DEFAULT_PAGE: u64 = 10;
+
+/// Most blocks per page
+const MAX_PAGE: u64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct GraphQLRequest {
+    query: String,
+    #[serde(default)]
+    variables: Option<Map<String, Value>>,
+    #[serde(default, rename = "operationName")]
+    operation_name: Option<String>,
+}
+
+pub async fn schema() -> impl IntoResponse {
+    SCHEMA
+}
+
+pub async fn handle_graphql(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<GraphQLRequest>,
+) -> Json<Value> {
+    Json(match execute(&state, request).await {
+        Ok(data) => json!({ "data": data }),
+        Err(message) => json!({ "data": null, "errors": [{ "message": message }] }),
+    })
+}
+
+async fn execute(state: &AppState, request: GraphQLRequest) -> Result<Value, String> {
+    let operation = operation(&request)?;
+
+    let mut variables = request.variables.unwrap_or_default();
+    for (name, default) in &operation.variables {
+        if !variables.contains_key(name) {
+            variables.insert(name.clone(), default.clone().unwrap_or(Value::Null));
+        }
+    }
+
+    let ctx = Context {
+        state,
+        agents: match &state.agents {
+            Some(supervisor) => supervisor.list().await,
+            None => Vec::new(),
+        },
+        external: state
+            .gateway
+            .as_ref()
+            .map(|gateway| gateway.agents())
+            .unwrap_or_default(),
+        variables,
+    };
+
+    let mut data = Map::new();
+    for field in &operation.selection {
+        let args = ctx.arguments(field)?;
+        let output = if field.name == "__typename" {
+            Output::Leaf(json!("Query"))
+        } else {
+            resolve_query(&ctx, &field.name, &args).await?
+        };
+        data.insert(field.key().to_string(), ctx.complete(output, field)?);
+    }
+    Ok(Value::Object(data))
+}
+
+/// The operation `request` runs, once it is found within the limits
+fn operation(request: &GraphQLRequest) -> Result<Operation, String> {
+    if request.query.len() > MAX_QUERY_LEN {
+        return Err(format!("Queries are at most {} bytes", MAX_QUERY_LEN));
+    }
+    let mut operations = Parser::new(tokenize(&request.query)?).document()?;
+    let operation = match &request.operation_name {
+        Some(name) => {
+            let index = operations
+                .iter()
+                .position(|op| op.name.as_deref() == Some(name.as_str()))
+                .ok_or_else(|| format!("No operation named {}", name))?;
+            operations.swap_remove(index)
+        }
+        None if operations.len() == 1 => operations.remove(0),
+        None => return Err("Several operations, pick one with operationName".to_string()),
+    };
+    let nested = depth(&operation.selection);
+    if nested > MAX_DEPTH {
+        return Err(format!(
+            "Query is nested {} deep, at most {} is allowed",
+            nested, MAX_DEPTH
+        ));
+    }
+    let selected = fields(&operation.selection);
+    if selected > MAX_FIELDS {
+        return Err(format!(
+            "Query selects {} fields, at most {} are allowed",
+            selected, MAX_FIELDS
+        ));
+    }
+    Ok(operation)
+}
+
+fn fields(selection: &[Field]) -> usize {
+    selection
+        .iter()
+        .map(|field| 1 + fields(&field.selection))
+        .sum()
+}
+
+fn depth(selection: &[Field]) -> usize {
+    selection
+        .iter()
+        .map(|field| 1 + depth(&field.selection))
+        .max()
+        .unwrap_or(0)
+}
+
+// Parsing
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Punct(char),
+    Spread,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {
+                chars.next();
+            }
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | '$' | '!' | '=' | '@' | '|' => {
+                tokens.push(Token::Punct(c));
+                chars.next();
+            }
+            '.' => {
+                for _ in 0..3 {
+                    if chars.next() != Some('.') {
+                        return Err("Expected ...".to_string());
+                    }
+                }
+                tokens.push(Token::Spread);
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some('r') => text.push('\r'),
+                            Some('t') => text.push('\t'),
+                            Some('b') => text.push('\u{8}'),
+                            Some('f') => text.push('\u{c}'),
+                            Some('u') => {
+                                let code: String = chars.by_ref().take(4).collect();
+                                let c = u32::from_str_radix(&code, 16)
+                                    .ok()
+                                    .and_then(char::from_u32)
+                                    .ok_or_else(|| format!("Invalid escape \\u{}", code))?;
+                                text.push(c);
+                            }
+                            Some(c @ ('"' | '\\' | '/')) => text.push(c),
+                            _ => return Err("Invalid escape in string".to_string()),
+                        },
+                        Some('\n') | None => return Err("Unterminated string".to_string()),
+                        Some(c) => text.push(c),
+                    }
+                }
+                tokens.push(Token::Str(text));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = chars
+                    .next_if(|&c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+                {
+                    number.push(c);
+                }
+                let token = if number.contains(['.', 'e', 'E']) {
+                    number.parse().map(Token::Float).ok()
+                } else {
+                    number.parse().map(Token::Int).ok()
+                };
+                tokens.push(token.ok_or_else(|| format!("Invalid number {}", number))?);
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|&c| c == '_' || c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            c => return Err(format!("Unexpected character {:?}", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// An argument as written, variables still to be filled in
+#[derive(Debug, Clone)]
+enum Input {
+    Value(Value),
+    Variable(String),
+    List(Vec<Input>),
+    Object(Vec<(String, Input)>),
+}
+
+#[derive(Debug)]
+struct Field {
+    alias: Option<String>,
+    name: String,
+    arguments: Vec<(String, Input)>,
+    selection: Vec<Field>,
+}
+
+impl Field {
+    /// Where the field goes in the response
+    fn key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug)]
+struct Operation {
+    name: Option<String>,
+    /// Declared variables, with their defaults
+    variables: Vec<(String, Option<Value>)>,
+    selection: Vec<Field>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("Unexpected end of query")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, punct: char) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Punct(c) if c == punct => Ok(()),
+            token => Err(format!("Expected {}, found {:?}", punct, token)),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => Err(format!("Expected a name, found {:?}", token)),
+        }
+    }
+
+    fn document(&mut self) -> Result<Vec<Operation>, String> {
+        let mut operations = Vec::new();
+        while self.peek().is_some() {
+            operations.push(self.operation()?);
+        }
+        if operations.is_empty() {
+            return Err("Empty query".to_string());
+        }
+        Ok(operations)
+    }
+
+    fn operation(&mut self) -> Result<Operation, String> {
+        let mut operation = Operation {
+            name: None,
+            variables: Vec::new(),
+            selection: Vec::new(),
+        };
+        if let Some(Token::Name(keyword)) = self.peek() {
+            match keyword.as_str() {
+                "query" => self.pos += 1,
+                "fragment" => return Err("Fragments are not supported".to_string()),
+                other => return Err(format!("Only queries are supported, not {}", other)),
+            }
+            if let Some(Token::Name(_)) = self.peek() {
+                operation.name = Some(self.name()?);
+            }
+            if self.eat('(') {
+                while !self.eat(')') {
+                    self.expect('$')?;
+                    let name = self.name()?;
+                    self.expect(':')?;
+                    self.skip_type()?;
+                    let default = if self.eat('=') {
+                        Some(match self.input()? {
+                            Input::Value(value) => value,
+                            _ => return Err("Defaults must be constants".to_string()),
+                        })
+                    } else {
+                        None
+                    };
+                    operation.variables.push((name, default));
+                }
+            }
+        }
+        operation.selection = self.selection()?;
+        Ok(operation)
+    }
+
+    /// Variable types are not checked, the resolvers check what they get
+    fn skip_type(&mut self) -> Result<(), String> {
+        if self.eat('[') {
+            self.skip_type()?;
+            self.expect(']')?;
+        } else {
+            self.name()?;
+        }
+        self.eat('!');
+        Ok(())
+    }
+
+    fn selection(&mut self) -> Result<Vec<Field>, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        while !self.eat('}') {
+            if self.peek() == Some(&Token::Spread) {
+                return Err("Fragments are not supported".to_string());
+            }
+            let mut name = self.name()?;
+            let mut alias = None;
+            if self.eat(':') {
+                alias = Some(name);
+                name = self.name()?;
+            }
+            let mut arguments = Vec::new();
+            if self.eat('(') {
+                while !self.eat(')') {
+                    let argument = self.name()?;
+                    self.expect(':')?;
+                    arguments.push((argument, self.input()?));
+                }
+            }
+            if self.peek() == Some(&Token::Punct('@')) {
+                return Err("Directives are not supported".to_string());
+            }
+            let selection = if self.peek() == Some(&Token::Punct('{')) {
+                self.selection()?
+            } else {
+                Vec::new()
+            };
+            fields.push(Field {
+                alias,
+                name,
+                arguments,
+                selection,
+            });
+        }
+        if fields.is_empty() {
+            return Err("Empty selection".to_string());
+        }
+        Ok(fields)
+    }
+
+    fn input(&mut self) -> Result<Input, String> {
+        Ok(match self.next()? {
+            Token::Punct('$') => Input::Variable(self.name()?),
+            Token::Int(n) => Input::Value(json!(n)),
+            Token::Float(f) => Input::Value(json!(f)),
+            Token::Str(s) => Input::Value(json!(s)),
+            Token::Name(name) => Input::Value(match name.as_str() {
+                "true" => json!(true),
+                "false" => json!(false),
+                "null" => Value::Null,
+                // Enum values are passed on by name
+                _ => json!(name),
+            }),
+            Token::Punct('[') => {
+                let mut items = Vec::new();
+                while !self.eat(']') {
+                    items.push(self.input()?);
+                }
+                Input::List(items)
+            }
+            Token::Punct('{') => {
+                let mut fields = Vec::new();
+                while !self.eat('}') {
+                    let name = self.name()?;
+                    self.expect(':')?;
+                    fields.push((name, self.input()?));
+                }
+                Input::Object(fields)
+            }
+            token => return Err(format!("Expected a value, found {:?}", token)),
+        })
+    }
+}
+
+// Execution
+
+struct Context<'a> {
+    state: &'a AppState,
+    /// Agents run by this node
+    agents: Vec<AgentInfo>,
+    /// Agents voting through the gateway
+    external: Vec<ExternalAgent>,
+    variables: Map<String, Value>,
+}
+
+/// What a field resolved to, before the selection on it is applied
+enum Output {
+    Leaf(Value),
+    Object(Node),
+    List(Vec<Output>),
+}
+
+impl Output {
+    fn maybe(node: Option<Node>) -> Self {
+        node.map_or(Output::Leaf(Value::Null), Output::Object)
+    }
+
+    fn list(nodes: impl IntoIterator<Item = Node>) -> Self {
+        Output::List(nodes.into_iter().map(Output::Object).collect())
+    }
+}
+
+enum Node {
+    Connection {
+        blocks: Vec<Arc<Block>>,
+        has_next_page: bool,
+    },
+    PageInfo {
+        has_next_page: bool,
+        end_cursor: Option<u64>,
+    },
+    Block(Arc<Block>),
+    Vote {
+        explanation: SignedExplanation,
+        verified: bool,
+    },
+    Transaction {
+        tx: Transaction,
+        block: Option<Arc<Block>>,
+    },
+    Validator(ValidatorView),
+    Agent(String),
+    Relationship(RelationshipEdge),
+}
+
+impl Node {
+    fn typename(&self) -> &'static str {
+        match self {
+            Node::Connection { .. } => "BlockConnection",
+            Node::PageInfo { .. } => "PageInfo",
+            Node::Block(_) => "Block",
+            Node::Vote { .. } => "Vote",
+            Node::Transaction { .. } => "Transaction",
+            Node::Validator(_) => "Validator",
+            Node::Agent(_) => "Agent",
+            Node::Relationship(_) => "Relationship",
+        }
+    }
+}
+
+fn parse_hash(hash: &str) -> Result<[u8; 32], String> {
+    hex::decode(hash.strip_prefix("0x").unwrap_or(hash))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("Invalid hash {}, expected 32 hex encoded bytes", hash))
+}
+
+fn int_arg(args: &Map<String, Value>, name: &str) -> Result<Option<u64>, String> {
+    match args.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| format!("Argument {} must be a non-negative Int", name)),
+    }
+}
+
+fn string_arg<'a>(args: &'a Map<String, Value>, name: &str) -> Result<Option<&'a str>, String> {
+    match args.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(_) => Err(format!("Argument {} must be a String", name)),
+    }
+}
+
+fn required<T>(value: Option<T>, name: &str) -> Result<T, String> {
+    value.ok_or_else(|| format!("Argument {} is required", name))
+}
+
+async fn resolve_query(
+    ctx: &Context<'_>,
+    name: &str,
+    args: &Map<String, Value>,
+) -> Result<Output, String> {
+    let state = ctx.state;
+    Ok(match name {
+        "height" => Output::Leaf(json!(state.state.get_block_height())),
+        "blocks" => {
+            let first = int_arg(args, "first")?
+                .unwrap_or(DEFAULT_PAGE)
+                .min(MAX_PAGE);
+            // One more than asked for, to know whether there is a next page
+            let mut blocks = match int_arg(args, "before")? {
+                Some(before) => {
+                    let from = before.saturating_sub(first + 1);
+                    let mut blocks: Vec<Block> = state
+                        .state
+                        .get_blocks(from, (first + 1) as usize)
+                        .into_iter()
+                        .filter(|block| block.height < before)
+                        .collect();
+                    blocks.reverse();
+                    blocks
+                }
+                None => state.state.get_latest_blocks((first + 1) as usize),
+            };
+            let has_next_page = blocks.len() as u64 > first;
+            blocks.truncate(first as usize);
+            Output::Object(Node::Connection {
+                blocks: blocks.into_iter().map(Arc::new).collect(),
+                has_next_page,
+            })
+        }
+        "block" => {
+            let block = match (int_arg(args, "height")?, string_arg(args, "hash")?) {
+                (_, Some(hash)) => state.state.get_block(&parse_hash(hash)?),
+                (Some(height), None) => state
+                    .state
+                    .get_blocks(height, 1)
+                    .into_iter()
+                    .find(|block| block.height == height),
+                (None, None) => state.state.get_latest_block(),
+            };
+            Output::maybe(block.map(|block| Node::Block(Arc::new(block))))
+        }
+        "transaction" => {
+            let hash = parse_hash(required(string_arg(args, "hash")?, "hash")?)?;
+            let found = rest::find_transaction(state, &hash).await;
+            Output::maybe(found.map(|(tx, block)| Node::Transaction {
+                tx,
+                block: block.map(Arc::new),
+            }))
+        }
+        "validators" => Output::list(
+            rest::validator_set(state)
+                .await
+                .validators
+                .into_iter()
+                .map(Node::Validator),
+        ),
+        "agents" => {
+            let mut ids: Vec<String> = ctx.agents.iter().map(|agent| agent.id.clone()).collect();
+            ids.extend(ctx.external.iter().map(|agent| agent.agent_id.clone()));
+            Output::list(ids.into_iter().map(Node::Agent))
+        }
+        "agent" => {
+            let id = required(string_arg(args, "id")?, "id")?;
+            let known = ctx.local(id).is_some() || ctx.gateway(id).is_some();
+            Output::maybe(known.then(|| Node::Agent(id.to_string())))
+        }
+        "relationships" => Output::list(
+            state
+                .relationships
+                .snapshot()
+                .edges
+                .into_iter()
+                .map(Node::Relationship),
+        ),
+        _ => return Err(format!("Unknown field {} on Query", name)),
+    })
+}
+
+impl Context<'_> {
+    fn local(&self, id: &str) -> Option<&AgentInfo> {
+        self.agents.iter().find(|agent| agent.id == id)
+    }
+
+    fn gateway(&self, id: &str) -> Option<&ExternalAgent> {
+        self.external.iter().find(|agent| agent.agent_id == id)
+    }
+
+    fn input(&self, input: &Input) -> Value {
+        match input {
+            Input::Value(value) => value.clone(),
+            Input::Variable(name) => self.variables.get(name).cloned().unwrap_or(Value::Null),
+            Input::List(items) => Value::Array(items.iter().map(|item| self.input(item)).collect()),
+            Input::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), self.input(value)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn arguments(&self, field: &Field) -> Result<Map<String, Value>, String> {
+        let mut args = Map::new();
+        for (name, input) in &field.arguments {
+            if args.insert(name.clone(), self.input(input)).is_some() {
+                return Err(format!("Argument {} given twice", name));
+            }
+        }
+        Ok(args)
+    }
+
+    /// Apply the selection of `field` to what it resolved to
+    fn complete(&self, output: Output, field: &Field) -> Result<Value, String> {
+        match output {
+            Output::Leaf(value) if field.selection.is_empty() => Ok(value),
+            Output::Leaf(_) => Err(format!("Field {} has no fields to select", field.name)),
+            Output::List(items) => items
+                .into_iter()
+                .map(|item| self.complete(item, field))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array),
+            Output::Object(_) if field.selection.is_empty() => {
+                Err(format!("Field {} needs a selection of fields", field.name))
+            }
+            Output::Object(node) => {
+                let mut object = Map::new();
+                for child in &field.selection {
+                    // Only fields of Query take arguments
+                    if !child.arguments.is_empty() {
+                        return Err(format!("Field {} takes no arguments", child.name));
+                    }
+                    let output = if child.name == "__typename" {
+                        Output::Leaf(json!(node.typename()))
+                    } else {
+                        self.resolve(&node, &child.name)?
+                    };
+                    object.insert(child.key().to_string(), self.complete(output, child)?);
+                }
+                Ok(Value::Object(object))
+            }
+        }
+    }
+
+    fn resolve(&self, node: &Node, name: &str) -> Result<Output, String> {
+        let leaf = |value: Value| Ok(Output::Leaf(value));
+        match (node, name) {
+            (Node::Connection { blocks, .. }, "nodes") => {
+                Ok(Output::list(blocks.iter().cloned().map(Node::Block)))
+            }
+            (
+                Node::Connection {
+                    blocks,
+                    has_next_page,
+                },
+                "pageInfo",
+            ) => Ok(Output::Object(Node::PageInfo {
+                has_next_page: *has_next_page,
+                end_cursor: blocks.last().map(|block| block.height),
+            })),
+            (Node::PageInfo { has_next_page, .. }, "hasNextPage") => leaf(json!(has_next_page)),
+            (Node::PageInfo { end_cursor, .. }, "endCursor") => leaf(json!(end_cursor)),
+
+            (Node::Block(block), _) => self.resolve_block(block, name),
+
+            (Node::Vote { explanation, .. }, "validator") => {
+                Ok(Output::Object(Node::Agent(explanation.validator.clone())))
+            }
+            (Node::Vote { explanation, .. }, "approved") => leaf(json!(explanation.approved)),
+            (Node::Vote { explanation, .. }, "dramaLevel") => leaf(json!(explanation.drama_level)),
+            (Node::Vote { explanation, .. }, "explanationHash") => {
+                leaf(json!(hex::encode(explanation.explanation_hash)))
+            }
+            (Node::Vote { verified, .. }, "verified") => leaf(json!(verified)),
+
+            (Node::Transaction { tx, .. }, "hash") => leaf(json!(hex::encode(tx.hash()))),
+            (Node::Transaction { tx, .. }, "sender") => leaf(json!(hex::encode(tx.sender))),
+            (Node::Transaction { tx, .. }, "nonce") => leaf(json!(tx.nonce)),
+            (Node::Transaction { tx, .. }, "payload") => leaf(json!(hex::encode(&tx.payload))),
+            (Node::Transaction { tx, .. }, "signature") => leaf(json!(hex::encode(tx.signature))),
+            (Node::Transaction { block, .. }, "status") => leaf(json!(if block.is_some() {
+                "included"
+            } else {
+                "pending"
+            })),
+            (Node::Transaction { block, .. }, "block") => {
+                Ok(Output::maybe(block.clone().map(Node::Block)))
+            }
+
+            (Node::Validator(validator), "id") => leaf(json!(validator.id)),
+            (Node::Validator(validator), "source") => leaf(json!(validator.source)),
+            (Node::Validator(validator), "publicKey") => leaf(json!(validator.public_key)),
+            (Node::Validator(validator), "since") => leaf(json!(validator.since)),
+            (Node::Validator(validator), "suspended") => leaf(json!(validator.suspended)),
+            (Node::Validator(validator), "agent") => {
+                Ok(Output::Object(Node::Agent(validator.id.clone())))
+            }
+
+            (Node::Agent(id), _) => self.resolve_agent(id, name),
+
+            (Node::Relationship(edge), "from") => {
+                Ok(Output::Object(Node::Agent(edge.from.clone())))
+            }
+            (Node::Relationship(edge), "to") => Ok(Output::Object(Node::Agent(edge.to.clone()))),
+            (Node::Relationship(edge), "trust") => leaf(json!(edge.relationship.trust)),
+            (Node::Relationship(edge), "rivalry") => leaf(json!(edge.relationship.rivalry)),
+            (Node::Relationship(edge), "romance") => leaf(json!(edge.relationship.romance)),
+            (Node::Relationship(edge), "debt") => leaf(json!(edge.relationship.debt)),
+            (Node::Relationship(edge), "interactions") => {
+                leaf(json!(edge.relationship.interactions))
+            }
+            (Node::Relationship(edge), "lastInteraction") => {
+                leaf(json!(edge.relationship.last_interaction))
+            }
+            (Node::Relationship(edge), "disposition") => {
+                leaf(json!(edge.relationship.disposition()))
+            }
+
+            _ => Err(format!("Unknown field {} on {}", name, node.typename())),
+        }
+    }
+
+    fn resolve_block(&self, block: &Arc<Block>, name: &str) -> Result<Output, String> {
+        let value = match name {
+            "height" => json!(block.height),
+            "hash" => json!(hex::encode(block.hash())),
+            "parentHash" => json!(hex::encode(block.parent_hash)),
+            "stateRoot" => json!(hex::encode(block.state_root)),
+            "timestamp" => json!(block.timestamp),
+            "producerMood" => json!(block.producer_mood),
+            "producerStrategy" => json!(block.producer_strategy),
+            "dramaLevel" => json!(block.drama_level),
+            "innovationLevel" => json!(block.innovation_level),
+            "producer" => return Ok(Output::Object(Node::Agent(block.producer_id.clone()))),
+            "transactions" => {
+                return Ok(Output::list(block.transactions.iter().map(|tx| {
+                    Node::Transaction {
+                        tx: tx.clone(),
+                        block: Some(block.clone()),
+                    }
+                })))
+            }
+            "votes" => {
+                let hash = block.hash();
+                return Ok(Output::list(block.metadata.explanations.iter().map(
+                    |explanation| Node::Vote {
+                        verified: explanation.verify(block.height, &hash).is_ok(),
+                        explanation: explanation.clone(),
+                    },
+                )));
+            }
+            _ => return Err(format!("Unknown field {} on Block", name)),
+        };
+        Ok(Output::Leaf(value))
+    }
+
+    fn resolve_agent(&self, id: &str, name: &str) -> Result<Output, String> {
+        let local = self.local(id);
+        let profile = local.map(|agent| &agent.profile);
+        let profile_field = |get: fn(&AgentProfile) -> Option<String>| json!(profile.and_then(get));
+        let value = match name {
+            "id" => json!(id),
+            "name" => json!(self.gateway(id).map_or(id, |agent| agent.name.as_str())),
+            "role" => match (local, self.gateway(id)) {
+                (Some(agent), _) => json!(agent.role),
+                (None, Some(_)) => json!("validator"),
+                (None, None) => Value::Null,
+            },
+            "personality" => {
+                profile_field(|profile| profile.personality.as_ref().map(|p| p.to_string()))
+            }
+            "model" => profile_field(|profile| profile.model.clone()),
+            "avatar" => profile_field(|profile| profile.avatar.clone()),
+            "generation" => json!(local.map(|agent| agent.generation)),
+            "costUsd" => json!(self.state.usage.agent(id).map(|usage| usage.cost_usd)),
+            "relationships" => {
+                return Ok(Output::list(
+                    self.state
+                        .relationships
+                        .relationships_of(id)
+                        .into_iter()
+                        .map(Node::Relationship),
+                ))
+            }
+            _ => return Err(format!("Unknown field {} on Agent", name)),
+        };
+        Ok(Output::Leaf(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(query: &str) -> GraphQLRequest {
+        GraphQLRequest {
+            query: query.to_string(),
+            variables: None,
+            operation_name: None,
+        }
+    }
+
+    /// `agent { relationships { from { ... } } }` down to `depth` fields
+    fn nested(depth: usize) -> String {
+        let mut query = "name".to_string();
+        for level in (1..depth).rev() {
+            let field = if level == 1 {
+                "agent(id: \"alice\")"
+            } else if level % 2 == 0 {
+                "relationships"
+            } else {
+                "from"
+            };
+            query = format!("{} {{ {} }}", field, query);
+        }
+        format!("{{ {} }}", query)
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        assert_eq!(
+            depth(&operation(&request(&nested(3))).unwrap().selection),
+            3
+        );
+        assert!(operation(&request(&nested(MAX_DEPTH))).is_ok());
+        let too_deep = operation(&request(&nested(MAX_DEPTH + 1))).err().unwrap();
+        assert_eq!(too_deep, "Query is nested 9 deep, at most 8 is allowed");
+    }
+
+    #[test]
+    fn test_field_limit() {
+        let aliased = |count: usize| {
+            let fields: Vec<String> = (0..count)
+                .map(|i| format!("b{}: block(height: {}) {{ hash }}", i, i))
+                .collect();
+            format!("{{ {} }}", fields.join(" "))
+        };
+        assert!(operation(&request(&aliased(MAX_FIELDS / 2))).is_ok());
+        let too_many = operation(&request(&aliased(MAX_FIELDS / 2 + 1)))
+            .err()
+            .unwrap();
+        assert_eq!(
+            too_many,
+            "Query selects 502 fields, at most 500 are allowed"
+        );
+
+        let too_long = format!("{{ height {} }}", " ".repeat(MAX_QUERY_LEN));
+        assert_eq!(
+            operation(&request(&too_long)).err().unwrap(),
+            "Queries are at most 16384 bytes"
+        );
+    }
+
+    #[test]
+    fn test_operation_names() {
+        let query = "query A { height } query B { blocks { hash } }";
+        assert!(operation(&request(query)).is_err());
+        let picked = operation(&GraphQLRequest {
+            operation_name: Some("B".to_string()),
+            ..request(query)
+        })
+        .unwrap();
+        assert_eq!(picked.name.as_deref(), Some("B"));
+        assert!(operation(&GraphQLRequest {
+            operation_name: Some("C".to_string()),
+            ..request(query)
+        })
+        .is_err());
+    }
+}
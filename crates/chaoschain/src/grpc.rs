@@ -0,0 +1,428 @@
+//! gRPC interface of the node, for backend services and bots that would
+//! rather speak protobuf than JSON. The service is described in
+//! `proto/chaoschain.proto`, which the build generates the messages and the
+//! server of: the reads of the REST interface, transaction submission, and
+//! server streams of blocks and drama. It is served over HTTP/2 without TLS
+//! on `grpc_port`, next to the web server, and shares its API tokens and
+//! rate limits.
+
+use bytes::Bytes;
+use chaoschain_cli::Scope;
+use chaoschain_core::{NetworkEvent, Transaction};
+use chaoschain_mempool::Rejection;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Code, Request, Response, Status};
+use tracing::info;
+
+use crate::auth::{self, Refusal};
+use crate::page::{Page, PageQuery};
+use crate::rest::{self, ValidatorSource};
+use crate::rpc::{self, SubmitError};
+use crate::subscriptions::{Filter, Stream};
+use crate::web::AppState;
+use proto::get_block_request::Block as BlockId;
+use proto::node_server::{Node, NodeServer};
+
+/// Messages and services generated from `proto/chaoschain.proto`
+pub mod proto {
+    tonic::include_proto!("chaoschain.v1");
+}
+
+/// Largest request message taken
+const MAX_REQUEST: usize = 1 << 20;
+
+/// Blocks sent by `ListBlocks` when no limit is asked for
+const DEFAULT_BLOCK_LIMIT: usize = 10;
+
+/// Serve gRPC on `port` until the listener fails or `shutdown` turns true
+pub async fn serve(
+    state: Arc<AppState>,
+    port: u16,
+    shutdown: watch::Receiver<bool>,
+) -> Result<(), tonic::transport::Error> {
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port);
+    info!("gRPC listening on {}", address);
+    let service = NodeServer::new(NodeService { state }).max_decoding_message_size(MAX_REQUEST);
+    tonic::transport::Server::builder()
+        .add_service(service)
+        .serve_with_shutdown(address, crate::web::stopped(shutdown))
+        .await
+}
+
+/// The node behind the generated [`Node`] service
+struct NodeService {
+    state: Arc<AppState>,
+}
+
+impl NodeService {
+    /// Check the token of `request` for `required`, and count the call
+    /// against the rate limit of its client
+    #[allow(clippy::result_large_err)]
+    fn admit<T>(&self, request: &Request<T>, required: Scope) -> Result<(), Status> {
+        let token = request
+            .metadata()
+            .get("x-api-key")
+            .and_then(|value| value.to_str().ok());
+        auth::authorize(&self.state.api, token, required).map_err(|refusal| {
+            let code = match refusal {
+                Refusal::Unauthenticated => Code::Unauthenticated,
+                Refusal::Forbidden(_) => Code::PermissionDenied,
+            };
+            Status::new(code, refusal.to_string())
+        })?;
+        let client = match token.and_then(|token| self.state.api.name_of(token)) {
+            Some(name) => format!("token:{}", name),
+            None => request
+                .remote_addr()
+                .map_or_else(|| "unknown".to_string(), |peer| peer.ip().to_string()),
+        };
+        self.state.limiter.take(required, &client).map_err(|wait| {
+            Status::resource_exhausted(format!(
+                "Too many requests, retry in {}s",
+                wait.as_secs().max(1)
+            ))
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl Node for NodeService {
+    async fn get_height(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<proto::Height>, Status> {
+        self.admit(&request, Scope::ReadOnly)?;
+        let height = self.state.state.get_latest_block().map_or(0, |b| b.height);
+        Ok(Response::new(proto::Height { height }))
+    }
+
+    async fn get_block(
+        &self,
+        request: Request<proto::GetBlockRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        self.admit(&request, Scope::ReadOnly)?;
+        let state = &self.state.state;
+        let block = match request.into_inner().block {
+            Some(BlockId::Height(height)) => state
+                .get_blocks(height, 1)
+                .into_iter()
+                .find(|block| block.height == height),
+            Some(BlockId::Hash(hash)) => state.get_block(&hash_of(&hash)?),
+            None => state.get_latest_block(),
+        };
+        let block = block.ok_or_else(|| Status::not_found("No such block"))?;
+        Ok(Response::new(block_message(&block)))
+    }
+
+    async fn list_blocks(
+        &self,
+        request: Request<proto::ListBlocksRequest>,
+    ) -> Result<Response<proto::BlockPage>, Status> {
+        self.admit(&request, Scope::ReadOnly)?;
+        let request = request.into_inner();
+        let query = PageQuery {
+            cursor: Some(request.cursor).filter(|cursor| !cursor.is_empty()),
+            limit: Some(request.limit as usize).filter(|limit| *limit > 0),
+            order: request
+                .ascending
+                .then_some(chaoschain_state::index::Order::Asc),
+        };
+        let (cursor, order) = query
+            .position()
+            .map_err(|e| Status::invalid_argument(e.message))?;
+        let limit = query.limit(DEFAULT_BLOCK_LIMIT);
+        let blocks = self.state.state.blocks_page(cursor, order, limit + 1);
+        let page = Page::new(blocks, limit, order, |block| block.height);
+        Ok(Response::new(proto::BlockPage {
+            blocks: page.items.iter().map(block_message).collect(),
+            next_cursor: page.next_cursor.unwrap_or_default(),
+        }))
+    }
+
+    async fn get_transaction(
+        &self,
+        request: Request<proto::GetTransactionRequest>,
+    ) -> Result<Response<proto::Transaction>, Status> {
+        self.admit(&request, Scope::ReadOnly)?;
+        let hash = hash_of(&request.into_inner().hash)?;
+        let (tx, block) = rest::find_transaction(&self.state, &hash)
+            .await
+            .ok_or_else(|| Status::not_found("No such transaction"))?;
+        Ok(Response::new(transaction_message(
+            &tx,
+            block.map(|block| block.height),
+        )))
+    }
+
+    async fn list_validators(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<proto::ValidatorSet>, Status> {
+        self.admit(&request, Scope::ReadOnly)?;
+        let set = rest::validator_set(&self.state).await;
+        let validators = set
+            .validators
+            .into_iter()
+            .map(|validator| proto::Validator {
+                public_key: validator
+                    .public_key
+                    .and_then(|key| hex::decode(key).ok())
+                    .unwrap_or_default(),
+                gateway: matches!(validator.source, ValidatorSource::Gateway),
+                id: validator.id,
+                since: validator.since,
+                suspended: validator.suspended,
+            })
+            .collect();
+        Ok(Response::new(proto::ValidatorSet {
+            count: set.count as u32,
+            validators,
+        }))
+    }
+
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::SubmitTransactionRequest>,
+    ) -> Result<Response<proto::SubmitTransactionReply>, Status> {
+        self.admit(&request, Scope::TxSubmit)?;
+        let request = request.into_inner();
+        let tx = Transaction {
+            sender: request
+                .sender
+                .as_slice()
+                .try_into()
+                .map_err(|_| Status::invalid_argument("A sender is 32 bytes"))?,
+            nonce: request.nonce,
+            payload: Bytes::from(request.payload),
+            signature: request
+                .signature
+                .as_slice()
+                .try_into()
+                .map_err(|_| Status::invalid_argument("A signature is 64 bytes"))?,
+            cosignatures: Vec::new(),
+        };
+        let hash = rpc::submit_transaction(&self.state, tx)
+            .await
+            .map_err(rejected)?;
+        Ok(Response::new(proto::SubmitTransactionReply {
+            hash: hash.to_vec(),
+        }))
+    }
+
+    type StreamBlocksStream = BoxStream<'static, Result<proto::Block, Status>>;
+
+    async fn stream_blocks(
+        &self,
+        request: Request<proto::Empty>,
+    ) -> Result<Response<Self::StreamBlocksStream>, Status> {
+        self.admit(&request, Scope::ReadOnly)?;
+        // Ends when the client goes away and tonic drops it
+        let blocks = stream::unfold(self.state.tx.subscribe(), |mut events| async move {
+            loop {
+                match events.recv().await {
+                    Ok(NetworkEvent::BlockProposal { block, .. }) => {
+                        return Some((Ok(block_message(&block)), events))
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Response::new(blocks.boxed()))
+    }
+
+    type StreamDramaStream = BoxStream<'static, Result<proto::DramaEvent, Status>>;
+
+    async fn stream_drama(
+        &self,
+        request: Request<proto::DramaFilter>,
+    ) -> Result<Response<Self::StreamDramaStream>, Status> {
+        self.admit(&request, Scope::ReadOnly)?;
+        let request = request.into_inner();
+        let given = |value: String| Some(value).filter(|value| !value.is_empty());
+        let filter = Filter {
+            agent: given(request.agent),
+            kind: given(request.kind),
+            q: given(request.q),
+            ..Filter::default()
+        };
+        let items = self.state.streams.subscribe();
+        let drama = stream::unfold((items, filter), |(mut items, filter)| async move {
+            loop {
+                match items.recv().await {
+                    Ok(item) if item.stream == Stream::Drama && filter.admits(&item) => {
+                        let field =
+                            |name: &str| item.data[name].as_str().unwrap_or_default().to_string();
+                        let event = proto::DramaEvent {
+                            seq: item.seq,
+                            agent: field("agent"),
+                            kind: field("kind"),
+                            message: field("message"),
+                        };
+                        return Some((Ok(event), (items, filter)));
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        });
+        Ok(Response::new(drama.boxed()))
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn hash_of(bytes: &[u8]) -> Result<[u8; 32], Status> {
+    bytes
+        .try_into()
+        .map_err(|_| Status::invalid_argument("A hash is 32 bytes"))
+}
+
+/// A turned down transaction, its reason in `chaoschain-reason`
+fn rejected(e: SubmitError) -> Status {
+    let code = match &e {
+        SubmitError::NoMempool => Code::Unavailable,
+        SubmitError::BadSignature(_)
+        | SubmitError::BadCommitment(_)
+        | SubmitError::BadProof(_)
+        | SubmitError::BadProposal(_)
+        | SubmitError::BadStake(_) => Code::InvalidArgument,
+        SubmitError::Rejected(Rejection::PoolFull) => Code::ResourceExhausted,
+        SubmitError::Rejected(Rejection::BannedSender) => Code::PermissionDenied,
+        SubmitError::Rejected(Rejection::Duplicate) => Code::AlreadyExists,
+        SubmitError::Rejected(_) => Code::FailedPrecondition,
+    };
+    let mut metadata = MetadataMap::new();
+    if let Ok(reason) = MetadataValue::try_from(e.reason()) {
+        metadata.insert("chaoschain-reason", reason);
+    }
+    if let Some(nonce) = e.expected_nonce() {
+        metadata.insert("chaoschain-expected-nonce", MetadataValue::from(nonce));
+    }
+    Status::with_metadata(code, e.to_string(), metadata)
+}
+
+fn transaction_message(tx: &Transaction, height: Option<u64>) -> proto::Transaction {
+    proto::Transaction {
+        hash: tx.hash().to_vec(),
+        sender: tx.sender.to_vec(),
+        nonce: tx.nonce,
+        payload: tx.payload.to_vec(),
+        signature: tx.signature.to_vec(),
+        included: height.is_some(),
+        block_height: height.unwrap_or_default(),
+    }
+}
+
+fn block_message(block: &chaoschain_core::Block) -> proto::Block {
+    proto::Block {
+        height: block.height,
+        hash: block.hash().to_vec(),
+        parent_hash: block.parent_hash.to_vec(),
+        state_root: block.state_root.to_vec(),
+        timestamp: block.timestamp,
+        producer: block.producer_id.clone(),
+        producer_mood: block.producer_mood.clone(),
+        producer_strategy: block.producer_strategy.clone(),
+        drama_level: block.drama_level as u32,
+        innovation_level: block.innovation_level as u32,
+        transactions: block
+            .transactions
+            .iter()
+            .map(|tx| transaction_message(tx, Some(block.height)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::proto::node_client::NodeClient;
+    use super::*;
+    use crate::web::tests::app_state;
+    use crate::web::WebServices;
+    use chaoschain_mempool::Mempool;
+    use chaoschain_testutil::{fixtures, BlockBuilder, TransactionBuilder};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_a_client_reads_and_submits_over_grpc() {
+        let app = app_state(&WebServices {
+            mempool: Some(Arc::new(Mempool::new(100))),
+            ..WebServices::default()
+        });
+        let block = BlockBuilder::new().height(1).build();
+        app.state.apply_block(&block).unwrap();
+
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (_shutdown, stopped) = watch::channel(false);
+        tokio::spawn(serve(app, port, stopped));
+        let address = format!("http://127.0.0.1:{}", port);
+        let mut client = loop {
+            match NodeClient::connect(address.clone()).await {
+                Ok(client) => break client,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        };
+
+        let height = client.get_height(proto::Empty {}).await.unwrap();
+        assert_eq!(height.into_inner().height, 1);
+        let got = client
+            .get_block(proto::GetBlockRequest {
+                block: Some(BlockId::Hash(block.hash().to_vec())),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(got.height, 1);
+        assert_eq!(got.producer, block.producer_id);
+        let missing = client
+            .get_block(proto::GetBlockRequest {
+                block: Some(BlockId::Height(7)),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), Code::NotFound);
+        let short = client
+            .get_transaction(proto::GetTransactionRequest { hash: vec![1, 2] })
+            .await
+            .unwrap_err();
+        assert_eq!(short.code(), Code::InvalidArgument);
+
+        let signed = TransactionBuilder::new()
+            .signer(&fixtures::key("grpc-sender"))
+            .build();
+        let submit = proto::SubmitTransactionRequest {
+            sender: signed.sender.to_vec(),
+            nonce: signed.nonce,
+            payload: signed.payload.to_vec(),
+            signature: signed.signature.to_vec(),
+        };
+        let reply = client.submit_transaction(submit.clone()).await.unwrap();
+        assert_eq!(reply.into_inner().hash, signed.hash().to_vec());
+        let pending = client
+            .get_transaction(proto::GetTransactionRequest {
+                hash: signed.hash().to_vec(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!pending.included);
+        assert_eq!(pending.nonce, signed.nonce);
+
+        // Turned down the second time, with the reason in a trailer
+        let again = client.submit_transaction(submit).await.unwrap_err();
+        assert_eq!(again.code(), Code::AlreadyExists);
+        assert_eq!(
+            again.metadata().get("chaoschain-reason").unwrap(),
+            "duplicate"
+        );
+    }
+}
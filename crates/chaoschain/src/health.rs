@@ -0,0 +1,126 @@
+//! Probes for Kubernetes and load balancers. `/health/live` answers as long
+//! as the node serves requests at all. `/health/ready` answers 200 only
+//! when the node can take its share of the work: its storage takes writes,
+//! it hears a quorum of the validators, it is caught up with its peers and
+//! there is a validator set to reach consensus with. Otherwise it answers
+//! 503 with what is wrong. Both are open to anyone and never rate limited.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::web::AppState;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/health/live", get(live))
+        .route("/health/ready", get(ready))
+}
+
+/// The outcome of one readiness check
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub detail: String,
+}
+
+impl Check {
+    fn pass(detail: impl Into<String>) -> Self {
+        Self {
+            ok: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(detail: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    /// `live`, `ready` or `not_ready`
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+async fn live() -> Json<Health> {
+    Json(Health {
+        status: "live",
+        checks: BTreeMap::new(),
+    })
+}
+
+async fn ready(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Health>) {
+    let mut checks = BTreeMap::new();
+    checks.insert(
+        "storage",
+        match state.state.storage_failure() {
+            Some(failure) => Check::fail(format!("Storage refuses writes: {}", failure)),
+            None => Check::pass("Storage takes writes"),
+        },
+    );
+
+    let partition = state.partition.status();
+    let heard = format!(
+        "{} of {} validators heard from",
+        partition.reachable, partition.validators
+    );
+    checks.insert(
+        "network",
+        if partition.partitioned {
+            Check::fail(format!("Cut off from the validators, {}", heard))
+        } else {
+            Check::pass(heard)
+        },
+    );
+
+    let sync = state.consensus.sync_status();
+    let lag = sync.state().lag();
+    checks.insert(
+        "sync",
+        if sync.is_far_behind() {
+            Check::fail(format!(
+                "{} blocks behind its peers, at most {} allowed",
+                lag,
+                sync.max_lag()
+            ))
+        } else if lag > 0 {
+            Check::pass(format!("{} blocks behind its peers", lag))
+        } else {
+            Check::pass("Caught up with its peers")
+        },
+    );
+
+    let validators = state.consensus.get_validator_count().await;
+    checks.insert(
+        "consensus",
+        if validators == 0 {
+            Check::fail("No validators to reach consensus with")
+        } else {
+            Check::pass(format!("{} validators in the set", validators))
+        },
+    );
+
+    let ready = checks.values().all(|check| check.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(Health {
+            status: if ready { "ready" } else { "not_ready" },
+            checks,
+        }),
+    )
+}
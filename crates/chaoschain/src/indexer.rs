@@ -0,0 +1,572 @@
+//! `chaoschain index`: chain history for SQL. The indexer follows a node
+//! from the outside, through the server-sent event streams and the
+//! relationship graph, and writes what it sees as SQL statements that
+//! SQLite and PostgreSQL both take:
+//!
+//! ```bash
+//! chaoschain index --node http://127.0.0.1:3000 | sqlite3 chain.db
+//! chaoschain index | psql postgres://localhost/chaoschain
+//! ```
+//!
+//! The tables are `blocks`, `txs`, `votes`, `drama` and `relationships`.
+//! Statements are upserts, so items seen twice, after a reconnection or a
+//! restart of the indexer, are harmless. The node numbers drama afresh
+//! each time it starts, so drama is keyed by a hash of its number and
+//! what was said: a message seen again keeps its row, and one that gets a
+//! number used before a restart of the node gets a row of its own.
+//!
+//! With `--search-port`, the indexer also keeps a text index of drama
+//! messages and vote reasons and serves searches over it, see
+//! [`crate::search`].
+
+use anyhow::{Context, Result};
+use chaoschain_core::relationships::GraphSnapshot;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, RwLock};
+use tracing::{error, info, warn};
+
+use crate::auth::API_KEY_HEADER;
+use crate::search::{self, Document, Kind, TextIndex};
+use crate::subscriptions::Stream;
+
+/// Pause before following a stream again after it broke off
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Recent items asked for when first following a stream
+const BACKFILL: usize = 100;
+
+/// Updates read from the node and not yet written out
+const UPDATES: usize = 1024;
+
+const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS blocks (
+    hash TEXT PRIMARY KEY,
+    height BIGINT NOT NULL,
+    parent_hash TEXT NOT NULL,
+    state_root TEXT NOT NULL,
+    producer TEXT NOT NULL,
+    producer_mood TEXT,
+    producer_strategy TEXT,
+    drama_level INTEGER,
+    innovation_level INTEGER,
+    tx_count INTEGER,
+    timestamp BIGINT
+);
+CREATE TABLE IF NOT EXISTS txs (
+    hash TEXT PRIMARY KEY,
+    sender TEXT,
+    nonce BIGINT,
+    payload TEXT,
+    proposer TEXT,
+    justification TEXT,
+    drama_score INTEGER,
+    block_hash TEXT,
+    block_height BIGINT,
+    seen_at BIGINT
+);
+CREATE TABLE IF NOT EXISTS votes (
+    block_hash TEXT NOT NULL,
+    validator TEXT NOT NULL,
+    approved BOOLEAN NOT NULL,
+    drama_level INTEGER,
+    reason TEXT,
+    meme_url TEXT,
+    seen_at BIGINT,
+    PRIMARY KEY (block_hash, validator)
+);
+CREATE TABLE IF NOT EXISTS drama (
+    id TEXT PRIMARY KEY,
+    seq BIGINT NOT NULL,
+    kind TEXT,
+    agent TEXT,
+    message TEXT,
+    seen_at BIGINT
+);
+CREATE TABLE IF NOT EXISTS relationships (
+    from_agent TEXT NOT NULL,
+    to_agent TEXT NOT NULL,
+    trust DOUBLE PRECISION,
+    rivalry DOUBLE PRECISION,
+    romance DOUBLE PRECISION,
+    debt BIGINT,
+    interactions BIGINT,
+    last_interaction BIGINT,
+    PRIMARY KEY (from_agent, to_agent)
+);
+CREATE INDEX IF NOT EXISTS blocks_height ON blocks (height);
+CREATE INDEX IF NOT EXISTS votes_validator ON votes (validator);
+CREATE INDEX IF NOT EXISTS drama_agent ON drama (agent);
+";
+
+/// The streams followed, and the first tables they fill
+const STREAMS: [Stream; 4] = [
+    Stream::NewBlocks,
+    Stream::Mempool,
+    Stream::Votes,
+    Stream::Drama,
+];
+
+/// What the followers pass on to be written out
+enum Update {
+    Item {
+        stream: Stream,
+        seq: u64,
+        data: Value,
+    },
+    Relationships(GraphSnapshot),
+}
+
+/// Where the text index is kept and served
+pub struct SearchOptions {
+    /// Journal of the index, else it is kept in memory only
+    pub index: Option<PathBuf>,
+    pub port: u16,
+}
+
+/// Follow the node at `node` until Ctrl-C, writing SQL to `out`
+pub async fn run(
+    node: &str,
+    api_key: Option<String>,
+    mut out: Box<dyn Write + Send>,
+    relationships_every: Duration,
+    search: Option<SearchOptions>,
+) -> Result<()> {
+    let node = node.trim_end_matches('/').to_string();
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(api_key) = api_key {
+        headers.insert(
+            API_KEY_HEADER,
+            api_key
+                .parse()
+                .context("The API key can't go in a header")?,
+        );
+    }
+    let client = reqwest::Client::builder()
+        .default_headers(headers)
+        .build()?;
+
+    out.write_all(SCHEMA.as_bytes())?;
+    out.flush()?;
+
+    let texts = match search {
+        Some(options) => {
+            let index = match &options.index {
+                Some(path) => TextIndex::open(path)?,
+                None => TextIndex::default(),
+            };
+            if !index.is_empty() {
+                info!("Read {} texts back into the search index", index.len());
+            }
+            let index = Arc::new(RwLock::new(index));
+            let served = index.clone();
+            tokio::spawn(async move {
+                if let Err(e) = search::serve(served, options.port).await {
+                    error!("Search server failed: {}", e);
+                }
+            });
+            Some(index)
+        }
+        None => None,
+    };
+
+    // The streams wait while the writes fall behind
+    let (updates, mut rx) = mpsc::channel(UPDATES);
+    for stream in STREAMS {
+        tokio::spawn(follow(
+            client.clone(),
+            node.clone(),
+            stream,
+            updates.clone(),
+        ));
+    }
+    tokio::spawn(poll_relationships(
+        client,
+        node.clone(),
+        relationships_every,
+        updates,
+    ));
+    info!("Indexing {}", node);
+
+    // Heights of the blocks seen, for the votes on them
+    let mut heights: HashMap<String, u64> = HashMap::new();
+    let mut latest_height = None;
+    loop {
+        let update = tokio::select! {
+            update = rx.recv() => match update {
+                Some(update) => update,
+                None => break,
+            },
+            _ = tokio::signal::ctrl_c() => break,
+        };
+        let statements = match &update {
+            Update::Item { stream, seq, data } => item_statements(*stream, *seq, data),
+            Update::Relationships(snapshot) => relationship_statements(snapshot),
+        };
+        for statement in statements {
+            writeln!(out, "{}", statement)?;
+        }
+        out.flush()?;
+
+        let Update::Item { stream, seq, data } = update else {
+            continue;
+        };
+        if stream == Stream::NewBlocks {
+            if let (Some(hash), Some(height)) = (data["hash"].as_str(), data["height"].as_u64()) {
+                heights.insert(hash.to_string(), height);
+                latest_height = latest_height.max(Some(height));
+            }
+        }
+        let Some(texts) = &texts else { continue };
+        if let Some(document) = document(stream, seq, &data, &heights, latest_height) {
+            texts.write().await.add(document)?;
+        }
+    }
+    Ok(())
+}
+
+/// What of an item goes into the text index
+fn document(
+    stream: Stream,
+    seq: u64,
+    data: &Value,
+    heights: &HashMap<String, u64>,
+    latest_height: Option<u64>,
+) -> Option<Document> {
+    let field = |name: &str| data[name].as_str().map(str::to_string);
+    let (key, kind, agent, height, block_hash, text) = match stream {
+        Stream::Votes => {
+            let block_hash = field("block_hash")?;
+            let validator = field("validator")?;
+            (
+                format!("vote-{}-{}", block_hash, validator),
+                Kind::Vote,
+                validator,
+                heights.get(&block_hash).copied(),
+                Some(block_hash),
+                field("reason")?,
+            )
+        }
+        Stream::Drama => (
+            format!("drama-{}", drama_id(seq, data)),
+            Kind::Drama,
+            field("agent")?,
+            latest_height,
+            None,
+            field("message")?,
+        ),
+        Stream::NewBlocks | Stream::Mempool | Stream::PendingTxs => return None,
+    };
+    (!text.trim().is_empty()).then_some(Document {
+        key,
+        kind,
+        agent,
+        height,
+        block_hash,
+        text,
+    })
+}
+
+/// Follow one stream for good, picking up after the last item seen
+/// whenever the connection breaks off
+async fn follow(
+    client: reqwest::Client,
+    node: String,
+    stream: Stream,
+    updates: mpsc::Sender<Update>,
+) {
+    let url = format!("{}/api/v1/sse/{}", node, stream.name());
+    let mut last_seq: Option<u64> = None;
+    loop {
+        let mut request = client.get(&url).header("Accept", "text/event-stream");
+        request = match last_seq {
+            Some(seq) => request.header("Last-Event-ID", seq.to_string()),
+            None => request.query(&[("backfill", BACKFILL)]),
+        };
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                let mut body = response.bytes_stream();
+                let mut buffer = String::new();
+                while let Some(chunk) = body.next().await {
+                    let Ok(chunk) = chunk else { break };
+                    buffer.push_str(&String::from_utf8_lossy(&chunk));
+                    // Events end with a blank line
+                    while let Some(end) = buffer.find("\n\n") {
+                        let event: String = buffer.drain(..end + 2).collect();
+                        let Some((name, id, data)) = parse_event(&event) else {
+                            continue;
+                        };
+                        match name.as_str() {
+                            "gap" => warn!("Missed some of {}, the node no longer has them", url),
+                            "error" => warn!("{}: {}", url, data),
+                            _ => {
+                                let Some(seq) = id else { continue };
+                                last_seq = Some(seq);
+                                let Ok(data) = serde_json::from_str::<Value>(&data) else {
+                                    continue;
+                                };
+                                if updates
+                                    .send(Update::Item { stream, seq, data })
+                                    .await
+                                    .is_err()
+                                {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                }
+                warn!("{} broke off, following it again", url);
+            }
+            Ok(response) => warn!("{} answered {}", url, response.status()),
+            Err(e) => warn!("Could not follow {}: {}", url, e),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Name, id and data of a server-sent event, none for a comment
+fn parse_event(event: &str) -> Option<(String, Option<u64>, String)> {
+    let mut name = "message".to_string();
+    let mut id = None;
+    let mut data: Vec<&str> = Vec::new();
+    for line in event.lines() {
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => name = value.to_string(),
+            "id" => id = value.parse().ok(),
+            "data" => data.push(value),
+            _ => {}
+        }
+    }
+    (!data.is_empty()).then(|| (name, id, data.join("\n")))
+}
+
+async fn poll_relationships(
+    client: reqwest::Client,
+    node: String,
+    every: Duration,
+    updates: mpsc::Sender<Update>,
+) {
+    let url = format!("{}/api/relationships", node);
+    let mut ticks = tokio::time::interval(every.max(Duration::from_secs(1)));
+    loop {
+        ticks.tick().await;
+        let snapshot = match client.get(&url).send().await {
+            Ok(response) => response.json::<GraphSnapshot>().await,
+            Err(e) => Err(e),
+        };
+        match snapshot {
+            Ok(snapshot) => {
+                if updates.send(Update::Relationships(snapshot)).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => warn!("Could not read {}: {}", url, e),
+        }
+    }
+}
+
+fn item_statements(stream: Stream, seq: u64, data: &Value) -> Vec<String> {
+    let seen_at = chrono::Utc::now().timestamp();
+    let text = |field: &str| text(&data[field]);
+    let number = |field: &str| number(&data[field]);
+    match stream {
+        Stream::NewBlocks => {
+            let mut statements = vec![format!(
+                "INSERT INTO blocks (hash, height, parent_hash, state_root, producer, producer_mood, \
+                 producer_strategy, drama_level, innovation_level, tx_count, timestamp) \
+                 VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}) ON CONFLICT (hash) DO NOTHING;",
+                text("hash"),
+                number("height"),
+                text("parent_hash"),
+                text("state_root"),
+                text("producer"),
+                text("producer_mood"),
+                text("producer_strategy"),
+                number("drama_level"),
+                number("innovation_level"),
+                data["transactions"].as_array().map_or(0, Vec::len),
+                number("timestamp"),
+            )];
+            for tx in data["transactions"].as_array().into_iter().flatten() {
+                statements.push(format!(
+                    "INSERT INTO txs (hash, block_hash, block_height) VALUES ({}, {}, {}) \
+                     ON CONFLICT (hash) DO UPDATE SET block_hash = excluded.block_hash, \
+                     block_height = excluded.block_height;",
+                    self::text(tx),
+                    text("hash"),
+                    number("height"),
+                ));
+            }
+            statements
+        }
+        Stream::Mempool => vec![format!(
+            "INSERT INTO txs (hash, sender, nonce, payload, proposer, justification, drama_score, seen_at) \
+             VALUES ({}, {}, {}, {}, {}, {}, {}, {}) ON CONFLICT (hash) DO UPDATE SET \
+             sender = excluded.sender, nonce = excluded.nonce, payload = excluded.payload, \
+             proposer = excluded.proposer, justification = excluded.justification, \
+             drama_score = excluded.drama_score, seen_at = excluded.seen_at;",
+            text("hash"),
+            text("sender"),
+            number("nonce"),
+            text("payload"),
+            text("proposer"),
+            text("justification"),
+            number("drama_score"),
+            seen_at,
+        )],
+        Stream::Votes => vec![format!(
+            "INSERT INTO votes (block_hash, validator, approved, drama_level, reason, meme_url, seen_at) \
+             VALUES ({}, {}, {}, {}, {}, {}, {}) ON CONFLICT (block_hash, validator) DO NOTHING;",
+            text("block_hash"),
+            text("validator"),
+            if data["approved"].as_bool().unwrap_or(false) {
+                "TRUE"
+            } else {
+                "FALSE"
+            },
+            number("drama_level"),
+            text("reason"),
+            text("meme_url"),
+            seen_at,
+        )],
+        Stream::Drama => vec![format!(
+            "INSERT INTO drama (id, seq, kind, agent, message, seen_at) \
+             VALUES ({}, {}, {}, {}, {}, {}) ON CONFLICT (id) DO NOTHING;",
+            quote(&drama_id(seq, data)),
+            seq,
+            text("kind"),
+            text("agent"),
+            text("message"),
+            seen_at,
+        )],
+        // The mempool stream has all of it already
+        Stream::PendingTxs => Vec::new(),
+    }
+}
+
+/// Key of a drama item, the same whenever it is seen again
+fn drama_id(seq: u64, data: &Value) -> String {
+    let keyed = json!([seq, data["kind"], data["agent"], data["message"]]);
+    hex::encode(Sha256::digest(keyed.to_string().as_bytes()))
+}
+
+fn relationship_statements(snapshot: &GraphSnapshot) -> Vec<String> {
+    snapshot
+        .edges
+        .iter()
+        .map(|edge| {
+            let relationship = &edge.relationship;
+            format!(
+                "INSERT INTO relationships (from_agent, to_agent, trust, rivalry, romance, debt, \
+                 interactions, last_interaction) VALUES ({}, {}, {}, {}, {}, {}, {}, {}) \
+                 ON CONFLICT (from_agent, to_agent) DO UPDATE SET trust = excluded.trust, \
+                 rivalry = excluded.rivalry, romance = excluded.romance, debt = excluded.debt, \
+                 interactions = excluded.interactions, last_interaction = excluded.last_interaction;",
+                quote(&edge.from),
+                quote(&edge.to),
+                relationship.trust,
+                relationship.rivalry,
+                relationship.romance,
+                relationship.debt,
+                relationship.interactions,
+                relationship.last_interaction,
+            )
+        })
+        .collect()
+}
+
+/// A JSON string as an SQL literal, NULL when it is missing
+fn text(value: &Value) -> String {
+    value.as_str().map_or_else(|| "NULL".to_string(), quote)
+}
+
+/// A JSON number as an SQL literal, NULL when it is missing
+fn number(value: &Value) -> String {
+    match value {
+        Value::Number(number) => number.to_string(),
+        _ => "NULL".to_string(),
+    }
+}
+
+fn quote(text: &str) -> String {
+    // NUL can't be stored in a text column of either database
+    format!("'{}'", text.replace('\0', "").replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drama(message: &str) -> Value {
+        json!({"kind": "drama", "agent": "alice", "message": message})
+    }
+
+    #[test]
+    fn test_quote_escapes_what_sql_would_choke_on() {
+        assert_eq!(quote("plain"), "'plain'");
+        assert_eq!(quote("it's"), "'it''s'");
+        assert_eq!(
+            quote("'; DROP TABLE blocks; --"),
+            "'''; DROP TABLE blocks; --'"
+        );
+        assert_eq!(quote("nul\0byte"), "'nulbyte'");
+        assert_eq!(text(&Value::Null), "NULL");
+        assert_eq!(number(&json!(42)), "42");
+        assert_eq!(number(&json!("42")), "NULL");
+    }
+
+    #[test]
+    fn test_statements_of_each_stream() {
+        let block = json!({
+            "hash": "b1",
+            "height": 7,
+            "parent_hash": "b0",
+            "state_root": "root",
+            "producer": "producer-0",
+            "transactions": ["t1", "t2"],
+        });
+        let statements = item_statements(Stream::NewBlocks, 0, &block);
+        assert_eq!(statements.len(), 3);
+        assert!(statements[0].starts_with("INSERT INTO blocks"));
+        assert!(statements[0].contains(
+            "VALUES ('b1', 7, 'b0', 'root', 'producer-0', NULL, NULL, NULL, NULL, 2, NULL)"
+        ));
+        assert!(statements[1].contains("VALUES ('t1', 'b1', 7)"));
+        assert!(statements[2].contains("VALUES ('t2', 'b1', 7)"));
+
+        let vote = json!({"block_hash": "b1", "validator": "v'1", "approved": true});
+        let statements = item_statements(Stream::Votes, 1, &vote);
+        assert_eq!(statements.len(), 1);
+        assert!(statements[0].contains("VALUES ('b1', 'v''1', TRUE, NULL, NULL, NULL, "));
+        assert!(statements[0].ends_with("ON CONFLICT (block_hash, validator) DO NOTHING;"));
+
+        assert!(item_statements(Stream::PendingTxs, 2, &json!({})).is_empty());
+    }
+
+    #[test]
+    fn test_drama_keeps_its_row_across_node_restarts() {
+        let statements = item_statements(Stream::Drama, 3, &drama("hello"));
+        assert_eq!(statements.len(), 1);
+        let id = drama_id(3, &drama("hello"));
+        assert!(
+            statements[0].contains(&format!("VALUES ('{}', 3, 'drama', 'alice', 'hello', ", id))
+        );
+        assert!(statements[0].ends_with("ON CONFLICT (id) DO NOTHING;"));
+
+        // Seen again after a reconnection: the same row
+        assert_eq!(drama_id(3, &drama("hello")), id);
+        // The node restarted and numbered something else 3: a row of its own
+        assert_ne!(drama_id(3, &drama("hello again")), id);
+        // Said again later
+        assert_ne!(drama_id(4, &drama("hello")), id);
+    }
+}
@@ -0,0 +1,463 @@
+//! `chaoschain inspect`: decode a block, a transaction or a signed vote,
+//! without a node. It takes JSON, as blocks and votes travel, or hex of it,
+//! as raw transactions do, from a file, the command line or standard input:
+//!
+//! ```bash
+//! chaoschain inspect vote.json --key 0x3b6a27bc...
+//! chaoschain inspect 0x7b2273656e646572223a5b...
+//! jq .metadata.last_block data/snapshots/snapshot-12/manifest.json | chaoschain inspect
+//! ```
+//!
+//! Every field is printed, then the checks: hashes, signatures, the votes of
+//! the finality certificate, and whatever looks off. The command fails when
+//! a check does, so that scripts can tell.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chaoschain_cli::InspectKind;
+use chaoschain_core::multisig::MultisigAccounts;
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{Block, Transaction, TransactionScheme};
+use chaoschain_crypto::scheme::SignatureScheme;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+
+use crate::rpc::verify_transaction;
+
+/// Seconds a timestamp may run ahead of the local clock before it is flagged
+const CLOCK_DRIFT_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// What is printed: the decoded fields, then the checks
+#[derive(Default)]
+struct Report {
+    fields: Vec<(String, String)>,
+    checks: Vec<(Outcome, String)>,
+}
+
+impl Report {
+    fn field(&mut self, name: &str, value: impl ToString) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+
+    fn check(&mut self, outcome: Outcome, check: impl Into<String>) {
+        self.checks.push((outcome, check.into()));
+    }
+
+    fn print(&self, kind: &str) {
+        println!("{}", kind);
+        let width = self
+            .fields
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        for (name, value) in &self.fields {
+            println!("  {:width$}  {}", name, value, width = width);
+        }
+        println!("checks");
+        for (outcome, check) in &self.checks {
+            let label = match outcome {
+                Outcome::Ok => "ok",
+                Outcome::Warn => "warn",
+                Outcome::Fail => "FAIL",
+            };
+            println!("  {:4}  {}", label, check);
+        }
+    }
+}
+
+/// Decode `input`, a file, the text itself, or standard input for none or
+/// `-`, and check it. `key` is the public key of the signer, for the
+/// signatures that do not carry one.
+pub fn run(input: Option<String>, kind: Option<InspectKind>, key: Option<String>) -> Result<()> {
+    let text = read(input)?;
+    let value = decode(&text)?;
+    let kind = kind.map_or_else(|| detect(&value), Ok)?;
+    let key = key.as_deref().map(parse_key).transpose()?;
+    let mut report = Report::default();
+    let name = match kind {
+        InspectKind::Block => {
+            let block: Block = serde_json::from_value(value).context("Not a block")?;
+            inspect_block(&block, key, &mut report);
+            "block"
+        }
+        InspectKind::Tx => {
+            let tx: Transaction = serde_json::from_value(value).context("Not a transaction")?;
+            inspect_transaction(&tx, &mut report);
+            "transaction"
+        }
+        InspectKind::Vote => {
+            let vote: SignedVote = serde_json::from_value(value).context("Not a signed vote")?;
+            inspect_vote(&vote, key, &mut report);
+            "vote"
+        }
+    };
+    report.print(name);
+    let failed = report
+        .checks
+        .iter()
+        .filter(|(outcome, _)| *outcome == Outcome::Fail)
+        .count();
+    if failed > 0 {
+        bail!("{} of {} checks failed", failed, report.checks.len());
+    }
+    Ok(())
+}
+
+fn read(input: Option<String>) -> Result<String> {
+    match input.filter(|input| input != "-") {
+        Some(input) if std::path::Path::new(&input).is_file() => {
+            std::fs::read_to_string(&input).with_context(|| format!("Could not read {}", input))
+        }
+        Some(input) => Ok(input),
+        None => {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            Ok(text)
+        }
+    }
+}
+
+/// JSON as it is, or hex encoded
+fn decode(text: &str) -> Result<Value> {
+    let text = text.trim();
+    if text.starts_with('{') {
+        return serde_json::from_str(text).context("Not valid JSON");
+    }
+    let bytes = hex::decode(text.strip_prefix("0x").unwrap_or(text))
+        .context("Neither JSON nor hex encoded JSON")?;
+    serde_json::from_slice(&bytes).context("The hex does not decode to JSON")
+}
+
+/// What the fields of `value` make it
+fn detect(value: &Value) -> Result<InspectKind> {
+    let has = |field: &str| value.get(field).is_some();
+    if has("proposer_sig") && has("transactions") {
+        Ok(InspectKind::Block)
+    } else if has("sender") && has("nonce") && has("payload") {
+        Ok(InspectKind::Tx)
+    } else if has("validator") && has("block_hash") && has("reason") {
+        Ok(InspectKind::Vote)
+    } else {
+        Err(anyhow!(
+            "Neither a block, a transaction nor a vote, pick one with --kind"
+        ))
+    }
+}
+
+fn parse_key(key: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(key.strip_prefix("0x").unwrap_or(key))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .context("The key is not 32 hex encoded bytes")?;
+    VerifyingKey::from_bytes(&bytes).context("Not an ed25519 public key")
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn timestamp(secs: u64) -> String {
+    chrono::DateTime::from_timestamp(secs as i64, 0).map_or_else(
+        || secs.to_string(),
+        |time| format!("{} ({})", secs, time.to_rfc3339()),
+    )
+}
+
+fn check_timestamp(secs: u64, report: &mut Report) {
+    let now = chrono::Utc::now().timestamp() as u64;
+    if secs == 0 {
+        report.check(Outcome::Warn, "The timestamp is not set");
+    } else if secs > now + CLOCK_DRIFT_SECS {
+        report.check(
+            Outcome::Warn,
+            format!("The timestamp is {} seconds in the future", secs - now),
+        );
+    }
+}
+
+fn inspect_block(block: &Block, key: Option<VerifyingKey>, report: &mut Report) {
+    let hash = block.hash();
+    report.field("height", block.height);
+    report.field("hash", to_hex(&hash));
+    report.field("parent hash", to_hex(&block.parent_hash));
+    report.field("state root", to_hex(&block.state_root));
+    report.field("producer", &block.producer_id);
+    report.field("producer mood", &block.producer_mood);
+    report.field("producer strategy", &block.producer_strategy);
+    report.field("drama level", block.drama_level);
+    report.field("innovation level", block.innovation_level);
+    report.field("timestamp", timestamp(block.timestamp));
+    report.field("proposer sig", to_hex(&block.proposer_sig));
+    report.field("transactions", block.transactions.len());
+    for (index, tx) in block.transactions.iter().enumerate() {
+        report.field(
+            &format!("  {}", index),
+            format!(
+                "{} from {} nonce {}, {} bytes",
+                to_hex(&tx.hash()),
+                to_hex(&tx.sender),
+                tx.nonce,
+                tx.payload.len()
+            ),
+        );
+    }
+    if let Some(negotiation) = &block.metadata.negotiation {
+        report.field(
+            "negotiation",
+            format!(
+                "{} supporters of {} responses",
+                negotiation.supporters().len(),
+                negotiation.responses.len()
+            ),
+        );
+    }
+    report.field("votes", block.metadata.explanations.len());
+    for explanation in &block.metadata.explanations {
+        report.field(
+            &format!("  {}", explanation.validator),
+            format!(
+                "{} at drama {}, key {}",
+                if explanation.approved {
+                    "approved"
+                } else {
+                    "rejected"
+                },
+                explanation.drama_level,
+                to_hex(&explanation.public_key)
+            ),
+        );
+    }
+
+    // The producer id is its public key when the producer has one
+    let producer_key = key.or_else(|| parse_key(&block.producer_id).ok());
+    match producer_key {
+        Some(key) => match Signature::from_slice(&block.proposer_sig)
+            .and_then(|signature| key.verify(&block.signing_bytes(), &signature))
+        {
+            Ok(()) => report.check(Outcome::Ok, "The producer signed the block"),
+            Err(e) => report.check(
+                Outcome::Fail,
+                format!("The producer signature does not hold: {}", e),
+            ),
+        },
+        None => report.check(
+            Outcome::Warn,
+            "The producer signature is not checked, give the producer's key with --key",
+        ),
+    }
+
+    // Multisig accounts are known from the block creating them on
+    let mut accounts = MultisigAccounts::new();
+    let mut unchecked = Vec::new();
+    let mut bad = Vec::new();
+    for (index, tx) in block.transactions.iter().enumerate() {
+        if !tx.cosignatures.is_empty() && accounts.get(&tx.sender).is_none() {
+            unchecked.push(index.to_string());
+        } else if let Err(e) = verify_transaction(&accounts, tx) {
+            bad.push(format!("{} ({})", index, e));
+        }
+        accounts.apply_transaction(tx);
+    }
+    if !unchecked.is_empty() {
+        report.check(
+            Outcome::Warn,
+            format!(
+                "Transactions of multisig accounts created in earlier blocks are not checked: {}",
+                unchecked.join(", ")
+            ),
+        );
+    }
+    if bad.is_empty() {
+        report.check(
+            Outcome::Ok,
+            format!(
+                "The {} transactions are signed by their senders",
+                block.transactions.len()
+            ),
+        );
+    } else {
+        report.check(
+            Outcome::Fail,
+            format!("Transactions with bad signatures: {}", bad.join(", ")),
+        );
+    }
+    let mut hashes = HashSet::new();
+    for tx in &block.transactions {
+        if !hashes.insert(tx.hash()) {
+            report.check(
+                Outcome::Fail,
+                format!("Transaction {} is in the block twice", to_hex(&tx.hash())),
+            );
+        }
+    }
+    let mut nonces: BTreeMap<[u8; 32], Vec<u64>> = BTreeMap::new();
+    for tx in &block.transactions {
+        nonces.entry(tx.sender).or_default().push(tx.nonce);
+    }
+    for (sender, mut nonces) in nonces {
+        nonces.sort();
+        if nonces.windows(2).any(|pair| pair[0] == pair[1]) {
+            report.check(
+                Outcome::Fail,
+                format!("{} uses a nonce twice", to_hex(&sender)),
+            );
+        } else if nonces.windows(2).any(|pair| pair[1] != pair[0] + 1) {
+            report.check(
+                Outcome::Warn,
+                format!("The nonces of {} leave gaps: {:?}", to_hex(&sender), nonces),
+            );
+        }
+    }
+
+    if block.height > 0 && block.parent_hash == [0; 32] {
+        report.check(Outcome::Warn, "No parent hash above the genesis block");
+    }
+    if block.drama_level > 10 {
+        report.check(
+            Outcome::Warn,
+            format!("The drama level {} is above 10", block.drama_level),
+        );
+    }
+    if block.innovation_level > 100 {
+        report.check(
+            Outcome::Warn,
+            format!(
+                "The innovation level {} is above 100",
+                block.innovation_level
+            ),
+        );
+    }
+    check_timestamp(block.timestamp, report);
+
+    let explanations = &block.metadata.explanations;
+    if explanations.is_empty() {
+        report.check(
+            Outcome::Warn,
+            "No votes in the block, it was not finalized or they were stripped",
+        );
+        return;
+    }
+    let mut validators = HashSet::new();
+    for explanation in explanations {
+        if let Err(e) = explanation.verify(block.height, &hash) {
+            report.check(
+                Outcome::Fail,
+                format!("The vote of {} does not hold: {}", explanation.validator, e),
+            );
+        }
+        if !validators.insert(&explanation.validator) {
+            report.check(
+                Outcome::Fail,
+                format!("{} voted twice", explanation.validator),
+            );
+        }
+    }
+    let approvals = explanations.iter().filter(|e| e.approved).count();
+    let rejections = explanations.len() - approvals;
+    report.check(
+        if approvals > rejections {
+            Outcome::Ok
+        } else {
+            Outcome::Warn
+        },
+        format!("{} approvals and {} rejections", approvals, rejections),
+    );
+}
+
+fn inspect_transaction(tx: &Transaction, report: &mut Report) {
+    report.field("hash", to_hex(&tx.hash()));
+    report.field("sender", to_hex(&tx.sender));
+    report.field("nonce", tx.nonce);
+    report.field("signature", to_hex(&tx.signature));
+    report.field("payload", format!("{} bytes", tx.payload.len()));
+    match std::str::from_utf8(&tx.payload) {
+        Ok(text) => report.field("  text", text),
+        Err(_) => report.field("  hex", to_hex(&tx.payload)),
+    }
+    if tx.cosignatures.is_empty() {
+        match tx.verify() {
+            Ok(()) => report.check(Outcome::Ok, "The sender signed the transaction"),
+            Err(e) => report.check(Outcome::Fail, format!("The signature does not hold: {}", e)),
+        }
+    } else {
+        report.field("cosignatures", tx.cosignatures.len());
+        for cosignature in &tx.cosignatures {
+            report.field("  owner", to_hex(&cosignature.owner));
+            match TransactionScheme::verify_bytes(
+                &cosignature.owner,
+                &tx.signing_bytes(),
+                &cosignature.signature,
+            ) {
+                Ok(()) => report.check(
+                    Outcome::Ok,
+                    format!("{} signed the transaction", to_hex(&cosignature.owner)),
+                ),
+                Err(e) => report.check(
+                    Outcome::Fail,
+                    format!(
+                        "The signature of {} does not hold: {}",
+                        to_hex(&cosignature.owner),
+                        e
+                    ),
+                ),
+            }
+        }
+        report.check(
+            Outcome::Warn,
+            "The owners and threshold of the multisig account are on chain, not checked",
+        );
+    }
+    if tx.payload.is_empty() {
+        report.check(Outcome::Warn, "The payload is empty");
+    }
+}
+
+fn inspect_vote(vote: &SignedVote, key: Option<VerifyingKey>, report: &mut Report) {
+    report.field("validator", &vote.validator);
+    report.field("height", vote.height);
+    report.field("block hash", to_hex(&vote.block_hash));
+    report.field("approved", vote.approved);
+    report.field("drama level", vote.drama_level);
+    report.field("reason", &vote.reason);
+    report.field("explanation hash", to_hex(&vote.explanation_hash()));
+    report.field("signature", to_hex(&vote.signature));
+    report.field("signed bytes", to_hex(&vote.signing_bytes()));
+
+    if vote.signature.len() != 64 {
+        report.check(
+            Outcome::Fail,
+            format!("The signature is {} bytes, not 64", vote.signature.len()),
+        );
+    } else {
+        match key {
+            Some(key) => match vote.verify(&key) {
+                Ok(()) => report.check(Outcome::Ok, "The validator signed the vote"),
+                Err(e) => {
+                    report.check(Outcome::Fail, format!("The signature does not hold: {}", e))
+                }
+            },
+            None => report.check(
+                Outcome::Warn,
+                "The signature is not checked, give the validator's key with --key",
+            ),
+        }
+    }
+    if vote.drama_level > 10 {
+        report.check(
+            Outcome::Warn,
+            format!("The drama level {} is above 10", vote.drama_level),
+        );
+    }
+    if vote.reason.trim().is_empty() {
+        report.check(Outcome::Warn, "The vote gives no reason");
+    }
+}
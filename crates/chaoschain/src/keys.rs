@@ -0,0 +1,517 @@
+//! `chaoschain keys`: the keystore `tx send` signs with. Keys are kept
+//! encrypted when a password is given, in a file or the environment, and
+//! can be backed up as a mnemonic or an encrypted key file:
+//!
+//! ```bash
+//! export CHAOSCHAIN_KEYSTORE_PASSWORD=...
+//! chaoschain keys new alice --mnemonic
+//! chaoschain keys export alice --out alice.json
+//! chaoschain keys import alice alice.json --keystore /elsewhere
+//! echo "$MNEMONIC" | chaoschain keys recover alice --keystore /elsewhere
+//! ```
+//!
+//! One mnemonic can back up the keys of many agents, `keys derive` keeping
+//! a key for each index along an EIP-2334 path:
+//!
+//! ```bash
+//! chaoschain keys derive validator --count 4 --mnemonic
+//! echo "$MNEMONIC" | chaoschain keys derive eth --path m/12381/3600/i/0/0
+//! ```
+//!
+//! Addresses go to standard output, mnemonics and warnings to standard
+//! error. Encrypted keys are EIP-2335 keystores, which Ethereum tooling
+//! reads too; with no password given, one is asked for on the terminal
+//! where a key needs it. `keys migrate` encrypts the keys kept unencrypted
+//! and rewrites older encrypted files:
+//!
+//! ```bash
+//! chaoschain keys migrate --password-file validator.pass
+//! ```
+//!
+//! Keys are ed25519 unless `--scheme` makes them secp256k1, which sign and
+//! verify messages but no transactions:
+//!
+//! ```bash
+//! chaoschain keys new bridge --scheme secp256k1
+//! chaoschain keys sign bridge 'hello' > hello.sig
+//! chaoschain keys verify bridge "$(cat hello.sig)" 'hello'
+//! ```
+//!
+//! An ed25519 key is a VRF key too, whose proofs anyone checks to get the
+//! same random output:
+//!
+//! ```bash
+//! chaoschain keys vrf-prove alice 'round 7' > round7.proof
+//! chaoschain keys vrf-verify alice "$(cat round7.proof)" 'round 7'
+//! ```
+//!
+//! Keys of a hardware wallet stay on the device, which signs once its owner
+//! approves there. `keys ledger` prints the address of one, and with
+//! `--confirm` shows it on the device to be checked against:
+//!
+//! ```bash
+//! chaoschain keys ledger --index 1 --confirm
+//! ```
+//!
+//! Keys of an HSM stay on its token. A key of the config's `[signers]` is
+//! opened through the PKCS#11 module there, or in the keystore directory
+//! there, and `--key` of its name signs with it as with a key of the
+//! keystore; a validator of that id votes with it.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chaoschain_cli::{KeyScheme, KeysCommand, SignerArgs, SignerConfig};
+use chaoschain_crypto::keystore::{self, DerivationPath, KeyFile, Keystore};
+use chaoschain_crypto::ledger::LedgerSigner;
+use chaoschain_crypto::scheme::{self, Scheme, SecretKey};
+use chaoschain_crypto::secret::Zeroizing;
+use chaoschain_crypto::signer::Signer;
+use chaoschain_crypto::vrf::{VrfProof, VrfPublicKey, VrfSecretKey};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use crate::tx::read_payload;
+
+/// Password of the keystore, from `file` or else the environment. Where
+/// neither gives one and a key needs it, it is asked for on the terminal.
+/// Wiped once dropped, as are PINs and mnemonics.
+pub fn password(file: Option<String>) -> Result<Option<Zeroizing<String>>> {
+    match file {
+        Some(file) => Ok(Some(read_secret(&file, "password")?)),
+        None => Ok(std::env::var("CHAOSCHAIN_KEYSTORE_PASSWORD")
+            .ok()
+            .map(Zeroizing::new)),
+    }
+}
+
+/// The first line of `file`, which holds the `what`
+fn read_secret(file: &str, what: &str) -> Result<Zeroizing<String>> {
+    let contents = Zeroizing::new(
+        std::fs::read_to_string(file)
+            .with_context(|| format!("Could not read the {} from {}", what, file))?,
+    );
+    Ok(Zeroizing::new(
+        contents.trim_end_matches(['\r', '\n']).to_string(),
+    ))
+}
+
+/// What `args` sign with: a key of `signers`, one of `keystore`, or one of
+/// a hardware wallet
+pub fn signer(
+    keystore: &Keystore,
+    signers: &HashMap<String, SignerConfig>,
+    password_file: Option<String>,
+    args: SignerArgs,
+) -> Result<Box<dyn Signer>> {
+    if !args.ledger {
+        let name = args.key.context("Give a --key, or sign with --ledger")?;
+        if let Some(config) = signers.get(&name) {
+            return configured(&name, config);
+        }
+        let password = password(password_file)?;
+        let key = load(keystore, &name, password.as_deref().map(String::as_str))?;
+        let key_scheme = key.scheme();
+        return Ok(Box::new(
+            key.ed25519()
+                .ok_or(keystore::KeystoreError::WrongScheme(name, key_scheme))?,
+        ));
+    }
+    let ledger = LedgerSigner::open(args.device.as_deref().map(Path::new), args.index)?;
+    eprintln!(
+        "Signing as 0x{} on the device, approve there",
+        hex::encode(ledger.public_key().to_bytes())
+    );
+    Ok(Box::new(ledger))
+}
+
+/// Open the key `name` of the config's `[signers]`
+pub fn configured(name: &str, config: &SignerConfig) -> Result<Box<dyn Signer>> {
+    match config {
+        SignerConfig::Pkcs11 { .. } => pkcs11(name, config),
+        SignerConfig::Keystore {
+            dir,
+            key,
+            password_file,
+        } => {
+            let password = password(password_file.clone())?;
+            let key = load(
+                &Keystore::new(dir),
+                key,
+                password.as_deref().map(String::as_str),
+            )
+            .with_context(|| format!("Could not open {} in {}", name, dir))?;
+            let key_scheme = key.scheme();
+            Ok(Box::new(key.ed25519().ok_or(
+                keystore::KeystoreError::WrongScheme(name.to_string(), key_scheme),
+            )?))
+        }
+    }
+}
+
+/// Open the HSM key `name` of the config's `[signers]`
+#[cfg(unix)]
+fn pkcs11(name: &str, config: &SignerConfig) -> Result<Box<dyn Signer>> {
+    use chaoschain_crypto::pkcs11::{Pkcs11Signer, Token};
+
+    let SignerConfig::Pkcs11 {
+        module,
+        token,
+        slot,
+        label,
+        pin_file,
+    } = config
+    else {
+        bail!("{} is not held in an HSM", name);
+    };
+    let pin = match pin_file {
+        Some(file) => Some(read_secret(file, "PIN")?),
+        None => or_prompt(
+            std::env::var("CHAOSCHAIN_PKCS11_PIN")
+                .ok()
+                .map(Zeroizing::new)
+                .as_deref()
+                .map(String::as_str),
+            &format!("User PIN of the token holding {}", name),
+        )?,
+    };
+    let token = match (token, slot) {
+        (Some(token), _) => Token::Label(token.clone()),
+        (None, Some(slot)) => Token::Slot(*slot),
+        (None, None) => Token::First,
+    };
+    let signer = Pkcs11Signer::open(
+        Path::new(module),
+        &token,
+        label,
+        pin.as_deref().map(String::as_str),
+    )
+    .with_context(|| format!("Could not open {} through {}", name, module))?;
+    Ok(Box::new(signer))
+}
+
+#[cfg(not(unix))]
+fn pkcs11(name: &str, _config: &SignerConfig) -> Result<Box<dyn Signer>> {
+    bail!(
+        "{} is held in an HSM, which PKCS#11 reaches on Unix only",
+        name
+    )
+}
+
+fn scheme(scheme: KeyScheme) -> Scheme {
+    match scheme {
+        KeyScheme::Ed25519 => Scheme::Ed25519,
+        KeyScheme::Secp256k1 => Scheme::Secp256k1,
+        KeyScheme::Bls12381 => Scheme::Bls12381,
+    }
+}
+
+pub fn run(keystore: &Keystore, password: Option<&str>, command: KeysCommand) -> Result<()> {
+    match command {
+        KeysCommand::New {
+            name,
+            mnemonic,
+            path,
+            scheme: key_scheme,
+        } => {
+            let (key, path) = if mnemonic {
+                let path = derivation_path(path)?;
+                let phrase = Zeroizing::new(keystore::generate_mnemonic());
+                eprintln!("Write these words down, they are the only way back to the key:\n");
+                eprintln!("{}\n", *phrase);
+                (
+                    keystore::scheme_key_at(&phrase, &path, 0, scheme(key_scheme))?,
+                    Some(path.at(0)),
+                )
+            } else {
+                (SecretKey::generate(scheme(key_scheme)), None)
+            };
+            add(keystore, &name, &key, password, path)
+        }
+        KeysCommand::Recover {
+            name,
+            index,
+            path,
+            scheme: key_scheme,
+        } => {
+            let path = derivation_path(path)?;
+            let phrase = Zeroizing::new(read_input(None)?);
+            let key = keystore::scheme_key_at(&phrase, &path, index, scheme(key_scheme))?;
+            add(keystore, &name, &key, password, Some(path.at(index)))
+        }
+        KeysCommand::Derive {
+            prefix,
+            count,
+            from,
+            path,
+            mnemonic,
+            scheme: key_scheme,
+        } => {
+            let path = derivation_path(path)?;
+            let end = from
+                .checked_add(count)
+                .context("The last index is past 2^32")?;
+            let phrase = if mnemonic {
+                let phrase = Zeroizing::new(keystore::generate_mnemonic());
+                eprintln!("Write these words down, they are the only way back to the keys:\n");
+                eprintln!("{}\n", *phrase);
+                phrase
+            } else {
+                Zeroizing::new(read_input(None)?)
+            };
+            if password.is_none() {
+                eprintln!("No password given, the keys are kept unencrypted");
+            }
+            let keys = keystore.derive(
+                &phrase,
+                &prefix,
+                &path,
+                from..end,
+                scheme(key_scheme),
+                password,
+            )?;
+            for (name, key) in keys {
+                println!("{}\t0x{}", name, hex::encode(key.public_key()));
+            }
+            Ok(())
+        }
+        KeysCommand::Import {
+            name,
+            file,
+            scheme: key_scheme,
+        } => {
+            let contents = read_input(file.as_deref())?;
+            let contents = contents.trim();
+            if contents.starts_with('{') {
+                let file: KeyFile = serde_json::from_str(contents).context("Not a key file")?;
+                let Some(password) = or_prompt(password, "Password of the key file")? else {
+                    bail!("Importing a key file takes its password, to check it opens");
+                };
+                file.decrypt(&name, &password)?;
+                keystore.import(&name, &file)?;
+                println!("0x{}", file.pubkey);
+                Ok(())
+            } else {
+                let secret: [u8; 32] = hex::decode(contents.trim_start_matches("0x"))
+                    .ok()
+                    .and_then(|secret| secret.try_into().ok())
+                    .context("Neither a key file nor a hex encoded 32 byte secret")?;
+                let key = SecretKey::from_bytes(scheme(key_scheme), &secret)
+                    .with_context(|| format!("The secret is not a {} key", scheme(key_scheme)))?;
+                add(keystore, &name, &key, password, None)
+            }
+        }
+        KeysCommand::Export { name, out } => {
+            let Some(password) = or_prompt(password, "Password to encrypt the key with")? else {
+                bail!("Exporting takes a password to encrypt the key with");
+            };
+            let file = serde_json::to_string_pretty(&keystore.export(&name, &password)?)?;
+            match out {
+                Some(out) => std::fs::write(&out, file + "\n")
+                    .with_context(|| format!("Could not write {}", out))?,
+                None => println!("{}", file),
+            }
+            Ok(())
+        }
+        KeysCommand::List => {
+            for key in keystore.list()? {
+                println!(
+                    "{}\t{}\t0x{}\t{}{}",
+                    key.name,
+                    key.scheme,
+                    key.address,
+                    if key.encrypted {
+                        "encrypted"
+                    } else {
+                        "unencrypted"
+                    },
+                    key.path
+                        .map(|path| format!("\t{}", path))
+                        .unwrap_or_default()
+                );
+            }
+            Ok(())
+        }
+        KeysCommand::Migrate => {
+            let Some(password) = or_prompt(password, "Password to encrypt the keys with")? else {
+                bail!("Migrating takes the password to encrypt the keys with");
+            };
+            let migrated = keystore.migrate(&password)?;
+            for name in &migrated {
+                println!("{}", name);
+            }
+            eprintln!("{} keys rewritten as EIP-2335 keystores", migrated.len());
+            Ok(())
+        }
+        KeysCommand::Sign { name, message, hex } => {
+            let key = load(keystore, &name, password)?;
+            let message = read_payload(message, hex)?;
+            println!("0x{}", hex::encode(key.sign(&message)));
+            Ok(())
+        }
+        KeysCommand::Verify {
+            signer,
+            signature,
+            message,
+            hex,
+            scheme: key_scheme,
+        } => {
+            // A key of the keystore first, as its name may well read as hex
+            let known = keystore.list()?.into_iter().find(|key| key.name == signer);
+            let public_key = match known {
+                Some(key) => hex::decode(key.address)?,
+                None => hex::decode(signer.trim_start_matches("0x")).with_context(|| {
+                    format!("{} is neither a key name nor a hex public key", signer)
+                })?,
+            };
+            let key_scheme = match key_scheme {
+                Some(key_scheme) => scheme(key_scheme),
+                None => Scheme::of_public_key(public_key.len()).with_context(|| {
+                    format!(
+                        "A public key of {} bytes is of no scheme, give --scheme",
+                        public_key.len()
+                    )
+                })?,
+            };
+            let signature = hex::decode(signature.trim().trim_start_matches("0x"))
+                .context("The signature is not hex")?;
+            let message = read_payload(message, hex)?;
+            scheme::verify(key_scheme, &public_key, &message, &signature)?;
+            eprintln!("Valid {} signature", key_scheme);
+            Ok(())
+        }
+        KeysCommand::VrfProve { name, input, hex } => {
+            let SecretKey::Ed25519(key) = load(keystore, &name, password)? else {
+                bail!("{} is not an ed25519 key, VRF proofs take one", name);
+            };
+            let proof = VrfSecretKey::from(&key).prove(&read_payload(input, hex)?);
+            println!("0x{}", hex::encode(proof.0));
+            eprintln!("Output 0x{}", hex::encode(proof.output()?.0));
+            Ok(())
+        }
+        KeysCommand::VrfVerify {
+            prover,
+            proof,
+            input,
+            hex,
+        } => {
+            let known = keystore.list()?.into_iter().find(|key| key.name == prover);
+            let public_key = match known {
+                Some(key) => hex::decode(key.address)?,
+                None => hex::decode(prover.trim_start_matches("0x")).with_context(|| {
+                    format!("{} is neither a key name nor a hex public key", prover)
+                })?,
+            };
+            let public_key = VrfPublicKey(
+                public_key
+                    .try_into()
+                    .map_err(|_| anyhow!("A VRF public key is 32 bytes"))?,
+            );
+            let proof = VrfProof::from_slice(
+                &hex::decode(proof.trim().trim_start_matches("0x"))
+                    .context("The proof is not hex")?,
+            )?;
+            let output = public_key.verify(&read_payload(input, hex)?, &proof)?;
+            println!("0x{}", hex::encode(output.0));
+            Ok(())
+        }
+        KeysCommand::Ledger {
+            index,
+            device,
+            confirm,
+        } => {
+            let ledger = LedgerSigner::open(device.as_deref().map(Path::new), index)?;
+            println!("0x{}", hex::encode(ledger.public_key().to_bytes()));
+            if confirm {
+                eprintln!("Check the device shows the same address, and approve it there");
+                ledger.confirm_address()?;
+                eprintln!("Approved on the device");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Keep `key`, derived at `path` of a mnemonic if it was, and print its
+/// address
+fn add(
+    keystore: &Keystore,
+    name: &str,
+    key: &SecretKey,
+    password: Option<&str>,
+    path: Option<String>,
+) -> Result<()> {
+    if password.is_none() {
+        eprintln!("No password given, {} is kept unencrypted", name);
+    }
+    keystore.add_key(name, key, password, path)?;
+    println!("0x{}", hex::encode(key.public_key()));
+    Ok(())
+}
+
+/// The path given, or that of ChaosChain keys
+fn derivation_path(path: Option<String>) -> Result<DerivationPath> {
+    Ok(path
+        .map(|path| path.parse())
+        .transpose()?
+        .unwrap_or_default())
+}
+
+/// The key under `name`, asking for the password when it is encrypted and
+/// none was given
+fn load(keystore: &Keystore, name: &str, password: Option<&str>) -> Result<SecretKey> {
+    match keystore.load_key(name, password) {
+        Err(keystore::KeystoreError::PasswordRequired(_)) => {
+            match or_prompt(None, &format!("Password of {}", name))? {
+                Some(password) => Ok(keystore.load_key(name, Some(password.as_str()))?),
+                None => Ok(keystore.load_key(name, None)?),
+            }
+        }
+        key => Ok(key?),
+    }
+}
+
+/// `password`, or else the answer to `question` when there is a terminal
+/// to ask on. What is typed is not echoed where `stty` can turn it off.
+fn or_prompt(password: Option<&str>, question: &str) -> Result<Option<Zeroizing<String>>> {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    if let Some(password) = password {
+        return Ok(Some(Zeroizing::new(password.to_string())));
+    }
+    if !std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    eprint!("{}: ", question);
+    std::io::stderr().flush()?;
+    let stty = |setting: &str| {
+        std::process::Command::new("stty")
+            .arg(setting)
+            .stdin(std::process::Stdio::inherit())
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    let hidden = stty("-echo");
+    let mut answer = Zeroizing::new(String::new());
+    let read = std::io::stdin().lock().read_line(&mut answer);
+    if hidden {
+        stty("echo");
+    }
+    eprintln!();
+    read?;
+    let answer = answer.trim_end_matches(['\r', '\n']);
+    Ok((!answer.is_empty()).then(|| Zeroizing::new(answer.to_string())))
+}
+
+/// What `file` holds, or standard input
+fn read_input(file: Option<&str>) -> Result<String> {
+    match file {
+        Some(file) => {
+            std::fs::read_to_string(file).with_context(|| format!("Could not read {}", file))
+        }
+        None => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            Ok(input)
+        }
+    }
+}
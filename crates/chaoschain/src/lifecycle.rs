@@ -0,0 +1,474 @@
+use chaoschain_cli::AgentProfile;
+use chaoschain_consensus::ConsensusManager;
+use chaoschain_core::metrics::MetricsRegistry;
+use chaoschain_core::NetworkEvent;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::openapi::{boolean, integer, one_of, string, ApiSchema, Object};
+
+/// What an agent does on the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentRole {
+    Validator,
+    Producer,
+}
+
+impl fmt::Display for AgentRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Validator => write!(f, "validator"),
+            Self::Producer => write!(f, "producer"),
+        }
+    }
+}
+
+/// A running agent as seen by the supervisor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgentInfo {
+    pub id: String,
+    pub role: AgentRole,
+    /// Pinned archetype and traits, anything unset is random
+    #[serde(flatten)]
+    pub profile: AgentProfile,
+    /// Bumped every time the agent is hot-swapped
+    pub generation: u32,
+    /// When the current generation started (unix seconds)
+    pub started_at: u64,
+    /// Kept out of the network until resumed
+    #[serde(default)]
+    pub paused: bool,
+}
+
+impl ApiSchema for AgentInfo {
+    const NAME: &'static str = "Agent";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("id", string())
+            .field("role", one_of(&["validator", "producer"]))
+            .field("generation", integer())
+            .field("started_at", integer())
+            .field("paused", boolean())
+            .optional("personality", string())
+            .optional("model", string())
+            .optional("prompt", string())
+            .optional("avatar", string())
+            // Trait sliders
+            .open()
+            .build()
+    }
+}
+
+/// Starts the task that runs an agent
+pub trait AgentLauncher: Send + Sync {
+    fn launch(&self, agent: &AgentInfo) -> JoinHandle<()>;
+}
+
+/// Lifecycle errors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LifecycleError {
+    UnknownAgent(String),
+    AlreadyRunning(String),
+}
+
+impl fmt::Display for LifecycleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownAgent(id) => write!(f, "Unknown agent: {}", id),
+            Self::AlreadyRunning(id) => write!(f, "Agent already running: {}", id),
+        }
+    }
+}
+
+impl std::error::Error for LifecycleError {}
+
+struct ManagedAgent {
+    info: AgentInfo,
+    /// None while paused or crashed
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ManagedAgent {
+    fn stop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+fn labels(name: &str, value: impl ToString) -> Labels {
+    vec![(name.to_string(), value.to_string())]
+}
+
+#[derive(Default)]
+struct Registry {
+    agents: HashMap<String, ManagedAgent>,
+    next_index: HashMap<AgentRole, usize>,
+}
+
+/// Spawns, retires and hot-swaps agents while the node keeps running.
+/// The consensus validator count follows the set of running validators.
+pub struct AgentSupervisor {
+    launcher: Arc<dyn AgentLauncher>,
+    consensus: Arc<ConsensusManager>,
+    tx: broadcast::Sender<NetworkEvent>,
+    registry: Mutex<Registry>,
+    /// Configured profiles, keyed by agent id
+    profiles: HashMap<String, AgentProfile>,
+    /// Validators connected through the agent gateway
+    external_validators: AtomicUsize,
+    /// Running agents per role
+    running: Family<Labels, Gauge>,
+    /// Agents spawned, retired and hot-swapped
+    changes: Family<Labels, Counter>,
+}
+
+impl AgentSupervisor {
+    pub fn new(
+        launcher: Arc<dyn AgentLauncher>,
+        consensus: Arc<ConsensusManager>,
+        tx: broadcast::Sender<NetworkEvent>,
+    ) -> Self {
+        Self {
+            launcher,
+            consensus,
+            tx,
+            registry: Mutex::new(Registry::default()),
+            profiles: HashMap::new(),
+            external_validators: AtomicUsize::new(0),
+            running: Family::default(),
+            changes: Family::default(),
+        }
+    }
+
+    /// Expose the agent counts through the node's metrics registry
+    pub fn register_metrics(&self, registry: &MetricsRegistry) {
+        registry.register(
+            "chaoschain_agents",
+            "Agents running, per role",
+            self.running.clone(),
+        );
+        registry.register(
+            "chaoschain_agent_lifecycle",
+            "Agents spawned, retired or hot-swapped, per event",
+            self.changes.clone(),
+        );
+    }
+
+    /// Profiles from the node config, applied whenever an agent with that id starts
+    pub fn with_profiles(mut self, profiles: HashMap<String, AgentProfile>) -> Self {
+        self.profiles = profiles;
+        self
+    }
+
+    fn profile_for(&self, id: &str, requested: AgentProfile) -> AgentProfile {
+        match self.profiles.get(id) {
+            Some(configured) => configured.merged(&requested),
+            None => requested,
+        }
+    }
+
+    /// Start a new agent. Without an explicit id the next free `<role>-<n>` is used.
+    /// The requested profile is layered over the configured one.
+    pub async fn spawn(
+        &self,
+        role: AgentRole,
+        id: Option<String>,
+        profile: AgentProfile,
+    ) -> Result<AgentInfo, LifecycleError> {
+        let mut registry = self.registry.lock().await;
+        let id = match id {
+            Some(id) if registry.agents.contains_key(&id) => {
+                return Err(LifecycleError::AlreadyRunning(id))
+            }
+            Some(id) => id,
+            None => loop {
+                let index = registry.next_index.entry(role).or_default();
+                let candidate = format!("{}-{}", role, index);
+                *index += 1;
+                if !registry.agents.contains_key(&candidate) {
+                    break candidate;
+                }
+            },
+        };
+
+        let info = AgentInfo {
+            profile: self.profile_for(&id, profile),
+            id: id.clone(),
+            role,
+            generation: 0,
+            started_at: now_secs(),
+            paused: false,
+        };
+        let handle = self.launcher.launch(&info);
+        registry.agents.insert(
+            id.clone(),
+            ManagedAgent {
+                info: info.clone(),
+                handle: Some(handle),
+            },
+        );
+        self.sync_counts(&registry).await;
+        drop(registry);
+
+        self.changes
+            .get_or_create(&labels("event", "spawned"))
+            .inc();
+        info!("🐣 Spawned {} {}", role, id);
+        self.announce(format!("🐣 {} {} has joined the chaos!", role, id));
+        Ok(info)
+    }
+
+    /// Stop an agent for good
+    pub async fn retire(&self, id: &str) -> Result<AgentInfo, LifecycleError> {
+        let mut registry = self.registry.lock().await;
+        let mut agent = registry
+            .agents
+            .remove(id)
+            .ok_or_else(|| LifecycleError::UnknownAgent(id.to_string()))?;
+        agent.stop();
+        self.sync_counts(&registry).await;
+        drop(registry);
+
+        self.changes
+            .get_or_create(&labels("event", "retired"))
+            .inc();
+        info!("👋 Retired {} {}", agent.info.role, id);
+        self.announce(format!(
+            "👋 {} {} has left the stage. The drama continues without them.",
+            agent.info.role, id
+        ));
+        Ok(agent.info)
+    }
+
+    /// Stop every agent of `role` without a farewell each, when the node
+    /// shuts down. Returns how many were stopped.
+    pub async fn retire_all(&self, role: AgentRole) -> usize {
+        let mut registry = self.registry.lock().await;
+        let ids: Vec<String> = registry
+            .agents
+            .iter()
+            .filter(|(_, agent)| agent.info.role == role)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &ids {
+            if let Some(mut agent) = registry.agents.remove(id) {
+                agent.stop();
+            }
+        }
+        self.sync_counts(&registry).await;
+        self.changes
+            .get_or_create(&labels("event", "retired"))
+            .inc_by(ids.len() as u64);
+        info!("👋 Retired {} {}s", ids.len(), role);
+        ids.len()
+    }
+
+    /// Restart an agent under the same id with a different profile.
+    /// The validator set is unchanged, only the mind behind the id is new.
+    /// A paused agent takes the profile when it resumes.
+    pub async fn replace(
+        &self,
+        id: &str,
+        profile: AgentProfile,
+    ) -> Result<AgentInfo, LifecycleError> {
+        let profile = self.profile_for(id, profile);
+        let mut registry = self.registry.lock().await;
+        let agent = registry
+            .agents
+            .get_mut(id)
+            .ok_or_else(|| LifecycleError::UnknownAgent(id.to_string()))?;
+        agent.stop();
+        agent.info.profile = profile;
+        agent.info.generation += 1;
+        agent.info.started_at = now_secs();
+        if !agent.info.paused {
+            agent.handle = Some(self.launcher.launch(&agent.info));
+        }
+        let info = agent.info.clone();
+        drop(registry);
+
+        self.changes
+            .get_or_create(&labels("event", "replaced"))
+            .inc();
+        info!(
+            "🔁 Hot-swapped {} {} (generation {})",
+            info.role, id, info.generation
+        );
+        self.announce(format!(
+            "🔁 {} has been reborn as {}!",
+            id,
+            info.profile
+                .personality
+                .as_ref()
+                .map_or_else(|| "someone new".to_string(), |p| p.to_string())
+        ));
+        Ok(info)
+    }
+
+    /// Stop an agent without forgetting it. A paused validator leaves the
+    /// validator count, so that the others still reach a quorum.
+    pub async fn pause(&self, id: &str) -> Result<AgentInfo, LifecycleError> {
+        let mut registry = self.registry.lock().await;
+        let agent = registry
+            .agents
+            .get_mut(id)
+            .ok_or_else(|| LifecycleError::UnknownAgent(id.to_string()))?;
+        if agent.info.paused {
+            return Ok(agent.info.clone());
+        }
+        agent.stop();
+        agent.info.paused = true;
+        let info = agent.info.clone();
+        self.sync_counts(&registry).await;
+        drop(registry);
+
+        self.changes.get_or_create(&labels("event", "paused")).inc();
+        info!("⏸️ Paused {} {}", info.role, id);
+        self.announce(format!("⏸️ {} {} is taking a break.", info.role, id));
+        Ok(info)
+    }
+
+    /// Start a paused agent again, with its latest profile
+    pub async fn resume(&self, id: &str) -> Result<AgentInfo, LifecycleError> {
+        let mut registry = self.registry.lock().await;
+        let agent = registry
+            .agents
+            .get_mut(id)
+            .ok_or_else(|| LifecycleError::UnknownAgent(id.to_string()))?;
+        if !agent.info.paused {
+            return Ok(agent.info.clone());
+        }
+        agent.info.paused = false;
+        agent.info.started_at = now_secs();
+        agent.handle = Some(self.launcher.launch(&agent.info));
+        let info = agent.info.clone();
+        self.sync_counts(&registry).await;
+        drop(registry);
+
+        self.changes
+            .get_or_create(&labels("event", "resumed"))
+            .inc();
+        info!("▶️ Resumed {} {}", info.role, id);
+        self.announce(format!("▶️ {} {} is back in the game!", info.role, id));
+        Ok(info)
+    }
+
+    /// Kill an agent as a crash would: without a farewell, and without
+    /// leaving the validator count, so the others have to reach a quorum
+    /// without its votes. [`restart`](Self::restart) brings it back.
+    pub async fn crash(&self, id: &str) -> Result<AgentInfo, LifecycleError> {
+        let mut registry = self.registry.lock().await;
+        let agent = registry
+            .agents
+            .get_mut(id)
+            .ok_or_else(|| LifecycleError::UnknownAgent(id.to_string()))?;
+        agent.stop();
+        let info = agent.info.clone();
+        drop(registry);
+
+        self.changes
+            .get_or_create(&labels("event", "crashed"))
+            .inc();
+        warn!("💥 Crashed {} {}", info.role, id);
+        Ok(info)
+    }
+
+    /// Start a crashed agent again, as its next generation. An agent that
+    /// runs, or that is paused, is left as it is.
+    pub async fn restart(&self, id: &str) -> Result<AgentInfo, LifecycleError> {
+        let mut registry = self.registry.lock().await;
+        let agent = registry
+            .agents
+            .get_mut(id)
+            .ok_or_else(|| LifecycleError::UnknownAgent(id.to_string()))?;
+        if agent.handle.is_some() || agent.info.paused {
+            return Ok(agent.info.clone());
+        }
+        agent.info.generation += 1;
+        agent.info.started_at = now_secs();
+        agent.handle = Some(self.launcher.launch(&agent.info));
+        let info = agent.info.clone();
+        drop(registry);
+
+        self.changes
+            .get_or_create(&labels("event", "restarted"))
+            .inc();
+        info!(
+            "🩹 Restarted {} {} (generation {})",
+            info.role, id, info.generation
+        );
+        Ok(info)
+    }
+
+    /// All agents, paused ones included, ordered by id
+    pub async fn list(&self) -> Vec<AgentInfo> {
+        let registry = self.registry.lock().await;
+        let mut agents: Vec<AgentInfo> = registry
+            .agents
+            .values()
+            .map(|agent| agent.info.clone())
+            .collect();
+        agents.sort_by(|a, b| a.id.cmp(&b.id));
+        agents
+    }
+
+    /// Count validators running outside the supervisor towards the validator set
+    pub async fn set_external_validators(&self, count: usize) {
+        let registry = self.registry.lock().await;
+        self.external_validators.store(count, Ordering::SeqCst);
+        self.sync_counts(&registry).await;
+    }
+
+    /// Bring the consensus validator count and the agent gauges in line
+    /// with the running agents
+    async fn sync_counts(&self, registry: &Registry) {
+        let running = |role| {
+            registry
+                .agents
+                .values()
+                .filter(|agent| agent.info.role == role && !agent.info.paused)
+                .count()
+        };
+        let validators = running(AgentRole::Validator);
+        self.running
+            .get_or_create(&labels("role", AgentRole::Validator))
+            .set(validators as i64);
+        self.running
+            .get_or_create(&labels("role", AgentRole::Producer))
+            .set(running(AgentRole::Producer) as i64);
+        self.consensus
+            .set_validator_count(validators + self.external_validators.load(Ordering::SeqCst))
+            .await;
+    }
+
+    fn announce(&self, message: String) {
+        let _ = self.tx.send(NetworkEvent::AgentChat {
+            message,
+            sender: "SYSTEM".to_string(),
+            meme_url: None,
+        });
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
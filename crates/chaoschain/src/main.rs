@@ -1,6 +1,55 @@
+mod admin;
+mod agents;
+mod auth;
+mod bench;
+mod chat;
+mod compression;
+mod db;
+mod dev;
+mod explorer;
+mod faucet;
+mod faults;
+mod feed;
+mod gateway;
+mod genesis;
+mod governance;
+mod graph;
+mod graphql;
+mod grpc;
+mod health;
+mod indexer;
+mod inspect;
+mod keys;
+mod lifecycle;
+mod mempool;
+mod netsim;
+mod network;
+mod openapi;
+mod page;
+mod peers;
+mod profiling;
+mod ratelimit;
+mod replay;
+mod rest;
+mod rpc;
+mod search;
+mod shards;
+mod simulate;
+mod snapshot;
+mod staking;
+mod stats;
+mod status;
+mod subscriptions;
+mod testnet;
+mod tls;
+mod tools;
+mod tx;
+mod verify_chain;
+mod watch;
 mod web;
+mod webhooks;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_openai::{
     config::OpenAIConfig,
     types::{
@@ -9,22 +58,69 @@ use async_openai::{
     },
     Client,
 };
+use chaoschain_agent::{
+    AgentError, AgentScore, ArtworkStore, ArtworkStudio, AuditConfig, AuditStore, ChatEngine,
+    ContextConfig, ContextManager, DalleProvider, DeadlineEngine, DecisionContext, DecisionEngine,
+    DecisionQueue, EnsembleEngine, EnsembleMember, FallbackVote, HumanEngine, ImageBackend,
+    ImageProvider, LlmChat, LlmEngine, LlmProvider, LlmSummarizer, LocalChat, LocalSummarizer,
+    MemoryEngine, Milestone, ModelSpec, ModeratedEngine, ModeratedThoughts, ModerationApi,
+    Moderator, OpenAiModeration, OpenAiProvider, Outcome, PersonalityTraits, Reinforcement,
+    StableDiffusionProvider, Summarizer, SummarizerKind, ThoughtSink, TraitOverrides, UsageTracker,
+};
 use chaoschain_bridge::Config as BridgeConfig;
-use chaoschain_cli::{Cli, Commands};
+use chaoschain_cli::{
+    completions, config_file, man, AgentCommand, AgentProfile, Cli, Commands, Config as CliConfig,
+    ConfigCommand, NodeCommand, NodeConfig, PersonaRole, Personas, SignerArgs, SnapshotCommand,
+    TxCommand, Verdict,
+};
 use chaoschain_consensus::Vote;
-use chaoschain_consensus::{AgentPersonality, Config as ConsensusConfig, ConsensusManager};
-use chaoschain_core::{Block, ChainConfig, NetworkEvent, Transaction, ValidationDecision};
+use chaoschain_consensus::{
+    AgentPersonality, Config as ConsensusConfig, ConsensusManager, PartitionDetector,
+    PartitionStatus,
+};
+use chaoschain_core::channel::{self, ChannelMetrics, Lossy};
+use chaoschain_core::commit::{Commit, Reveal};
+use chaoschain_core::election::LeaderTicket;
+use chaoschain_core::fork::ForkSchedule;
+use chaoschain_core::governance::{Ballot, Governance, Proposal};
+use chaoschain_core::metrics::MetricsRegistry;
+use chaoschain_core::negotiation::SupportLevel;
+use chaoschain_core::relationships::{Interaction, RelationshipGraph};
+use chaoschain_core::rotation::{KeyRotation, ValidatorKeys};
+use chaoschain_core::rumor::{RumorConfig, RumorMill};
+use chaoschain_core::staking::{Bond, Evidence, Staking, Unbond};
+use chaoschain_core::sync::SyncStatus;
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{
+    Block, BlockMetadata, ChainConfig, NetworkEvent, Transaction, ValidationDecision,
+};
+use chaoschain_crypto::keystore::Keystore;
+use chaoschain_crypto::signer::Signer;
+use chaoschain_crypto::vrf::VrfSecretKey;
 use chaoschain_crypto::KeyManagerHandle;
+use chaoschain_mempool::template::{Packer, DEFAULT_SETTLE};
 use chaoschain_mempool::{Mempool, OrderingDiscussion, TransactionDiscussion};
-use chaoschain_p2p::{Config as P2PConfig, Message};
+use chaoschain_p2p::peers::PeersConfig;
+use chaoschain_producer::negotiation::{respond_to_pitch, Negotiator, DEFAULT_NEGOTIATION_WINDOW};
 use chaoschain_producer::{GenesisConfig, Producer, ProducerConfig};
+use chaoschain_state::maintenance::BLOCK_LOG;
+use chaoschain_state::pools::Pools;
+use chaoschain_state::snapshot::SnapshotArchive;
+use chaoschain_state::storage::{Storage, StorageConfig};
 use chaoschain_state::{StateStore, StateStoreImpl};
+use chaoschain_testnet::NodeRunner;
+use chat::ChatDesk;
 use chrono;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use dotenv::dotenv;
 use ed25519_dalek::SignatureError;
 use ed25519_dalek::SigningKey;
+use faults::FaultInjector;
+use futures::FutureExt;
+use gateway::{AgentGateway, GatewayConfig};
 use hex;
+use lifecycle::{AgentInfo, AgentLauncher, AgentRole, AgentSupervisor};
+use netsim::NetworkSim;
 use rand::rngs::OsRng;
 use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
 use serde_json;
@@ -32,14 +128,19 @@ use std::error::Error;
 use std::time::Duration;
 use std::{
     collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
     sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
 };
 use tokio;
 use tokio::sync::broadcast;
 use tokio::sync::RwLock;
-use tracing::{error, info, warn};
-use tracing_subscriber;
+use tokio::task::JoinHandle;
+use tracing::{error, info, info_span, warn, Instrument};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::reload;
+use web::NetworkEventExt;
 
 #[derive(Debug, Clone)]
 enum AllianceEventType {
@@ -71,419 +172,2650 @@ struct PersonalityShift {
 async fn main() -> Result<()> {
     dotenv().ok();
 
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        .init();
-
+    // The level can be changed at runtime through the admin API
+    let (log_filter, log_level) = reload::Layer::new(LevelFilter::INFO);
     let cli = Cli::parse();
+    // The indexer writes SQL and the other tools print for scripts to
+    // stdout, so they log to stderr
+    let log_to_stderr = matches!(
+        cli.command,
+        Commands::Index { .. }
+            | Commands::Bench { .. }
+            | Commands::Inspect { .. }
+            | Commands::Genesis { .. }
+            | Commands::Keys { .. }
+            | Commands::Tx { .. }
+            | Commands::Status { .. }
+            | Commands::Watch { .. }
+            | Commands::Snapshot { .. }
+            | Commands::Db { .. }
+            | Commands::VerifyChain { .. }
+            | Commands::Agent { .. }
+            | Commands::Peers { .. }
+            | Commands::Faucet { .. }
+            | Commands::Completions { .. }
+            | Commands::Man { .. }
+    );
+    // The level filters the logs alone, tokio-console sees every task
+    let logs = tracing_subscriber::fmt::layer()
+        .with_writer(move || -> Box<dyn std::io::Write> {
+            if log_to_stderr {
+                Box::new(std::io::stderr())
+            } else {
+                Box::new(std::io::stdout())
+            }
+        })
+        .with_filter(log_filter);
+    let registry = tracing_subscriber::registry().with(logs);
+    #[cfg(feature = "tokio-console")]
+    let registry = registry.with(console_subscriber::spawn());
+    registry.init();
+
+    // A config file that does not load is what `config validate` looks into
+    let config_path = cli.config_path().map(str::to_string);
+    let config = match cli.command {
+        Commands::Config { .. } => CliConfig::default(),
+        _ => cli.load_config()?,
+    };
 
     match cli.command {
         Commands::Demo {
             validators,
             producers,
+            personas,
             web,
+            llm,
+            audit,
+            ensemble_models,
+            ensemble_strategy,
+            stream,
+            tools,
+            decision_timeout,
+            timeout_fallback,
+            context_budget,
+            summarizer,
+            artwork,
+            artwork_api_base,
+            humans,
+            human_timeout,
+            human_token,
+            netsim,
+            shards,
+            shutdown_timeout,
         } => {
-            info!(
-                "Starting demo network with {} validators and {} producers",
-                validators, producers
+            let mut config = config;
+            if let Ok(key) = std::env::var("OPENAI_API_KEY") {
+                config.openai_api_key = key;
+            }
+            config.node = NodeConfig {
+                validators,
+                producers,
+                personas,
+                web,
+                llm,
+                audit,
+                ensemble_models,
+                ensemble_strategy,
+                stream,
+                tools,
+                decision_timeout,
+                timeout_fallback,
+                context_budget,
+                summarizer,
+                artwork,
+                artwork_api_base,
+                humans,
+                human_timeout,
+                human_token,
+                netsim,
+                shards,
+                shutdown_timeout,
+                genesis: config.node.genesis.take(),
+            };
+            run_node(config, log_level, None).await
+        }
+
+        Commands::Dev {
+            block_time,
+            accounts,
+            balance,
+            mnemonic,
+            validators,
+            port,
+            faucet_drip,
+            faucet_cooldown,
+        } => {
+            let mut config = config;
+            let data_dir =
+                std::env::temp_dir().join(format!("chaoschain-dev-{}", std::process::id()));
+            config.data_dir = data_dir.to_string_lossy().into_owned();
+            if let Some(port) = port {
+                config.web_port = port;
+            }
+            config.node = NodeConfig {
+                validators,
+                web: true,
+                ..NodeConfig::default()
+            };
+            let mnemonic = mnemonic.as_deref().unwrap_or(dev::DEV_MNEMONIC);
+            let chain = dev::DevChain::new(
+                mnemonic,
+                accounts,
+                balance,
+                block_time.map(Duration::from_secs),
+                (faucet_drip, Duration::from_secs(faucet_cooldown)),
+            )?;
+            let keys = data_dir.join("keys");
+            chain.write_keys(&keys)?;
+
+            println!("Dev accounts, {} each", chain.balance);
+            for (index, key) in chain.accounts.iter().enumerate() {
+                println!(
+                    "  dev-{:<3} {}  secret {}",
+                    index,
+                    hex::encode(key.verifying_key().as_bytes()),
+                    hex::encode(key.to_bytes())
+                );
+            }
+            println!("Mnemonic  {}", mnemonic);
+            println!(
+                "Sign with `chaoschain tx --keystore {} send --key dev-0 --node http://127.0.0.1:{}`",
+                keys.display(),
+                config.web_port
+            );
+            match chain.block_time {
+                Some(block_time) => println!("A block every {}s", block_time.as_secs()),
+                None => println!("A block per transaction"),
+            }
+            println!(
+                "Faucet    {} every {}s, with `chaoschain faucet <address> --node http://127.0.0.1:{}`",
+                chain.faucet_drip,
+                chain.faucet_cooldown.as_secs(),
+                config.web_port
             );
 
-            let (tx, _) = broadcast::channel(100);
-            let web_tx = tx.clone();
+            let result = run_node(config, log_level, Some(chain)).await;
+            if let Err(e) = std::fs::remove_dir_all(&data_dir) {
+                warn!("Could not remove {}: {}", data_dir.display(), e);
+            }
+            result
+        }
 
-            let stake_per_validator = 100u64;
-            let consensus_config = ConsensusConfig::default();
-            let key_manager = KeyManagerHandle::new();
+        Commands::Faucet {
+            address,
+            node,
+            api_key,
+        } => {
+            faucet::request(
+                &node,
+                &address,
+                api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+            )
+            .await
+        }
 
-            let shared_state = Arc::new(StateStoreImpl::new(
-                ChainConfig::default(),
-                key_manager.clone(),
-            ));
+        Commands::Vote {
+            height,
+            verdict,
+            reason,
+            agent,
+            node,
+            token,
+            api_key,
+        } => {
+            let token = match token.or_else(|| std::env::var("CHAOSCHAIN_HUMAN_TOKEN").ok()) {
+                Some(token) => token,
+                None => std::fs::read_to_string(Path::new(&config.data_dir).join("human_token"))
+                    .map_err(|e| anyhow::anyhow!("No human token given and none on disk: {}", e))?
+                    .trim()
+                    .to_string(),
+            };
+            let mut request = reqwest::Client::new()
+                .post(format!("{}/api/human/votes", node.trim_end_matches('/')))
+                .bearer_auth(token);
+            if let Some(api_key) = api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()) {
+                request = request.header(auth::API_KEY_HEADER, api_key);
+            }
+            let response = request
+                .json(&serde_json::json!({
+                    "agent": agent,
+                    "height": height,
+                    "approved": verdict == Verdict::Approve,
+                    "reason": reason,
+                }))
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                let status = response.status();
+                anyhow::bail!("Vote refused ({}): {}", status, response.text().await?);
+            }
+            let decision: serde_json::Value = response.json().await?;
+            println!(
+                "Voted {:?} on block {} as {}",
+                verdict, height, decision["agent"]
+            );
+            Ok(())
+        }
 
-            let consensus_manager = Arc::new(chaoschain_consensus::create_consensus(
-                consensus_config,
-                shared_state.clone(),
-                tx.clone(),
-            ));
+        Commands::Index {
+            node,
+            out,
+            api_key,
+            relationships_every,
+            search_port,
+            search_index,
+        } => {
+            let out: Box<dyn std::io::Write + Send> = match out {
+                Some(path) => Box::new(
+                    std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .with_context(|| format!("Failed to open {}", path))?,
+                ),
+                None => Box::new(std::io::stdout()),
+            };
+            indexer::run(
+                &node,
+                api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                out,
+                Duration::from_secs(relationships_every),
+                search_port.map(|port| indexer::SearchOptions {
+                    index: search_index.map(std::path::PathBuf::from),
+                    port,
+                }),
+            )
+            .await
+        }
 
-            consensus_manager
-                .set_validator_count(validators as usize)
-                .await;
+        Commands::Agent {
+            command:
+                AgentCommand::Simulate {
+                    persona,
+                    personas,
+                    personality,
+                    chaos_level,
+                    loyalty,
+                    greed,
+                    verbosity,
+                    attention_span,
+                    model,
+                    proposals,
+                    seed,
+                    json,
+                },
+        } => {
+            // Start from the persona, flags override what it pins down
+            let (agent, base) = match &persona {
+                Some(name) => {
+                    let cast = Personas::load(personas.as_deref().unwrap_or(PERSONAS_FILE))?;
+                    let persona = cast
+                        .personas
+                        .into_iter()
+                        .find(|persona| &persona.name == name)
+                        .ok_or_else(|| anyhow::anyhow!("No persona named {}", name))?;
+                    (persona.name, persona.profile)
+                }
+                None => ("simulated-validator".to_string(), AgentProfile::default()),
+            };
+            let profile = base.merged(&AgentProfile {
+                personality,
+                traits: TraitOverrides {
+                    chaos_level,
+                    loyalty,
+                    greed,
+                    verbosity,
+                    attention_span,
+                },
+                ..AgentProfile::default()
+            });
+
+            let model = model.or(profile
+                .model
+                .as_ref()
+                .map(|model| model.parse())
+                .transpose()?);
+            let engine = match model {
+                Some(spec) => {
+                    let openai_key = std::env::var("OPENAI_API_KEY").map_err(|_| {
+                        anyhow::anyhow!("OPENAI_API_KEY must be set to use a model")
+                    })?;
+                    let engine: Arc<dyn DecisionEngine> = Arc::new(DeadlineEngine::new(
+                        Arc::new(LlmEngine::new(
+                            provider_for(&spec, &openai_key),
+                            Arc::new(AuditStore::disabled()),
+                        )),
+                        Duration::from_secs(20),
+                        FallbackVote::Abstain,
+                    ));
+                    Some(engine)
+                }
+                None => None,
+            };
+
+            let report = simulate::simulate(&agent, &profile, engine, proposals, seed).await;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print!("{}", report);
+            }
+            Ok(())
+        }
+
+        Commands::Genesis {
+            from,
+            interactive,
+            chain_id,
+            validators,
+            validator_count,
+            stake,
+            balance,
+            allocations,
+            finality_threshold,
+            block_time,
+            consensus_timeout,
+            rotation_grace,
+            hash,
+            forks,
+            epoch_length,
+            threshold,
+            out,
+            password_file,
+        } => genesis::run(genesis::Plan {
+            from,
+            interactive,
+            chain_id,
+            validators,
+            validator_count,
+            stake,
+            balance,
+            allocations,
+            finality_threshold,
+            block_time,
+            consensus_timeout,
+            rotation_grace,
+            hash,
+            forks,
+            epoch_length,
+            threshold,
+            out: PathBuf::from(out),
+            password: keys::password(password_file)?,
+        }),
+
+        Commands::Keys {
+            keystore,
+            password_file,
+            command,
+        } => {
+            let keystore = Keystore::new(
+                keystore.map_or_else(|| Path::new(&config.data_dir).join("keys"), PathBuf::from),
+            );
+            keys::run(
+                &keystore,
+                keys::password(password_file)?
+                    .as_deref()
+                    .map(String::as_str),
+                command,
+            )
+        }
+
+        Commands::Tx {
+            keystore,
+            password_file,
+            command,
+        } => {
+            let keystore = Keystore::new(
+                keystore.map_or_else(|| Path::new(&config.data_dir).join("keys"), PathBuf::from),
+            );
+            match command {
+                TxCommand::Send {
+                    signer,
+                    payload,
+                    hex,
+                    nonce,
+                    wait,
+                    timeout,
+                    node,
+                    api_key,
+                } => {
+                    let signer = keys::signer(&keystore, &config.signers, password_file, signer)?;
+                    tx::send(
+                        signer.as_ref(),
+                        tx::read_payload(payload, hex)?,
+                        nonce,
+                        wait.then(|| Duration::from_secs(timeout)),
+                        &node,
+                        api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                    )
+                    .await?;
+                }
+                TxCommand::RotateKey {
+                    signer,
+                    validator,
+                    new_key,
+                    nonce,
+                    wait,
+                    timeout,
+                    node,
+                    api_key,
+                } => {
+                    let old =
+                        keys::signer(&keystore, &config.signers, password_file.clone(), signer)?;
+                    let new = keys::signer(
+                        &keystore,
+                        &config.signers,
+                        password_file,
+                        SignerArgs {
+                            key: Some(new_key),
+                            ledger: false,
+                            index: 0,
+                            device: None,
+                        },
+                    )?;
+                    let rotation =
+                        KeyRotation::new(validator, old.public_key().to_bytes(), new.as_ref())?;
+                    tx::send(
+                        old.as_ref(),
+                        rotation.payload(),
+                        nonce,
+                        wait.then(|| Duration::from_secs(timeout)),
+                        &node,
+                        api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                    )
+                    .await?;
+                    eprintln!(
+                        "{} rotates to 0x{}",
+                        rotation.validator,
+                        hex::encode(rotation.new_key)
+                    );
+                }
+                TxCommand::Commit {
+                    signer,
+                    payload,
+                    hex,
+                    nonce,
+                    wait,
+                    timeout,
+                    node,
+                    api_key,
+                } => {
+                    let payload = tx::read_payload(payload, hex)?;
+                    let signer = keys::signer(&keystore, &config.signers, password_file, signer)?;
+                    let secret: [u8; 32] = OsRng.gen();
+                    let commit = Commit::seal(&signer.public_key().to_bytes(), &secret, &payload);
+                    tx::send(
+                        signer.as_ref(),
+                        commit.payload(),
+                        nonce,
+                        wait.then(|| Duration::from_secs(timeout)),
+                        &node,
+                        api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                    )
+                    .await?;
+                    println!("{}", hex::encode(secret));
+                    eprintln!("Reveal it with this secret once the commit is in a block");
+                }
+                TxCommand::Reveal {
+                    signer,
+                    commit,
+                    secret,
+                    nonce,
+                    wait,
+                    timeout,
+                    node,
+                    api_key,
+                } => {
+                    let reveal = Reveal {
+                        commit: tx::bytes32(&commit).context("Invalid commit hash")?,
+                        key: tx::bytes32(&secret).context("Invalid secret")?,
+                    };
+                    let signer = keys::signer(&keystore, &config.signers, password_file, signer)?;
+                    tx::send(
+                        signer.as_ref(),
+                        reveal.payload(),
+                        nonce,
+                        wait.then(|| Duration::from_secs(timeout)),
+                        &node,
+                        api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                    )
+                    .await?;
+                }
+                TxCommand::Propose {
+                    signer,
+                    title,
+                    action,
+                    nonce,
+                    wait,
+                    timeout,
+                    node,
+                    api_key,
+                } => {
+                    let proposal = Proposal {
+                        title,
+                        action: action.parse().map_err(|e: String| anyhow::anyhow!(e))?,
+                    };
+                    let signer = keys::signer(&keystore, &config.signers, password_file, signer)?;
+                    tx::send(
+                        signer.as_ref(),
+                        proposal.payload(),
+                        nonce,
+                        wait.then(|| Duration::from_secs(timeout)),
+                        &node,
+                        api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                    )
+                    .await?;
+                    eprintln!("The hash is the proposal id, vote on it with `tx vote --proposal`");
+                }
+                TxCommand::Vote {
+                    signer,
+                    proposal,
+                    against,
+                    nonce,
+                    wait,
+                    timeout,
+                    node,
+                    api_key,
+                } => {
+                    let ballot = Ballot {
+                        proposal: tx::bytes32(&proposal).context("Invalid proposal id")?,
+                        approve: !against,
+                    };
+                    let signer = keys::signer(&keystore, &config.signers, password_file, signer)?;
+                    tx::send(
+                        signer.as_ref(),
+                        ballot.payload(),
+                        nonce,
+                        wait.then(|| Duration::from_secs(timeout)),
+                        &node,
+                        api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                    )
+                    .await?;
+                }
+                TxCommand::Bond {
+                    signer,
+                    amount,
+                    validator,
+                    nonce,
+                    wait,
+                    timeout,
+                    node,
+                    api_key,
+                } => {
+                    let signer = keys::signer(&keystore, &config.signers, password_file, signer)?;
+                    let bond = Bond {
+                        validator: match validator {
+                            Some(validator) => {
+                                tx::bytes32(&validator).context("Invalid validator key")?
+                            }
+                            None => signer.public_key().to_bytes(),
+                        },
+                        amount,
+                    };
+                    tx::send(
+                        signer.as_ref(),
+                        bond.payload(),
+                        nonce,
+                        wait.then(|| Duration::from_secs(timeout)),
+                        &node,
+                        api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                    )
+                    .await?;
+                }
+                TxCommand::Unbond {
+                    signer,
+                    amount,
+                    validator,
+                    nonce,
+                    wait,
+                    timeout,
+                    node,
+                    api_key,
+                } => {
+                    let signer = keys::signer(&keystore, &config.signers, password_file, signer)?;
+                    let unbond = Unbond {
+                        validator: match validator {
+                            Some(validator) => {
+                                tx::bytes32(&validator).context("Invalid validator key")?
+                            }
+                            None => signer.public_key().to_bytes(),
+                        },
+                        amount,
+                    };
+                    tx::send(
+                        signer.as_ref(),
+                        unbond.payload(),
+                        nonce,
+                        wait.then(|| Duration::from_secs(timeout)),
+                        &node,
+                        api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                    )
+                    .await?;
+                }
+                TxCommand::Report {
+                    signer,
+                    evidence,
+                    nonce,
+                    wait,
+                    timeout,
+                    node,
+                    api_key,
+                } => {
+                    let evidence: Evidence = match evidence.filter(|path| path != "-") {
+                        Some(path) => serde_json::from_str(
+                            &std::fs::read_to_string(&path)
+                                .with_context(|| format!("Could not read {}", path))?,
+                        ),
+                        None => serde_json::from_slice(&tx::read_payload(None, false)?),
+                    }
+                    .context("Invalid evidence")?;
+                    evidence.verify()?;
+                    let signer = keys::signer(&keystore, &config.signers, password_file, signer)?;
+                    tx::send(
+                        signer.as_ref(),
+                        evidence.payload(),
+                        nonce,
+                        wait.then(|| Duration::from_secs(timeout)),
+                        &node,
+                        api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                    )
+                    .await?;
+                }
+                TxCommand::Sign {
+                    signer,
+                    payload,
+                    hex,
+                    nonce,
+                } => {
+                    let payload = tx::read_payload(payload, hex)?;
+                    let signer = keys::signer(&keystore, &config.signers, password_file, signer)?;
+                    let tx = tx::sign(signer.as_ref(), nonce, payload)?;
+                    println!("{}", tx::raw(&tx)?);
+                    eprintln!("Signed {} with nonce {}", hex::encode(tx.hash()), nonce);
+                }
+            }
+            Ok(())
+        }
+
+        Commands::Watch {
+            node,
+            api_key,
+            agent,
+            backfill,
+        } => {
+            watch::run(
+                &node,
+                api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                agent,
+                backfill,
+            )
+            .await
+        }
+
+        Commands::Status {
+            node,
+            api_key,
+            json,
+            watch,
+            interval,
+        } => {
+            status::run(
+                &node,
+                api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                json,
+                watch.then(|| Duration::from_secs(interval.max(1))),
+            )
+            .await
+        }
+
+        Commands::Bench {
+            nodes,
+            api_key,
+            rate,
+            duration,
+            senders,
+            payload_size,
+            settle,
+            json,
+        } => {
+            bench::run(bench::Plan {
+                nodes,
+                api_key: api_key.or_else(|| std::env::var("CHAOSCHAIN_API_KEY").ok()),
+                rate,
+                duration: Duration::from_secs(duration),
+                senders,
+                payload_size,
+                settle: Duration::from_secs(settle),
+                json,
+            })
+            .await
+        }
+
+        Commands::Inspect { input, kind, key } => inspect::run(input, kind, key),
+
+        Commands::Agent { command } => agents::run(&config.admin, command).await,
 
-            let mempool = Arc::new(Mempool::new(1000));
+        Commands::Peers { command } => peers::run(&config.admin, command).await,
 
-            // Get OpenAI API key from environment
-            let openai_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
-            let config = OpenAIConfig::new().with_api_key(openai_key);
-            let openai_client = Arc::new(Client::with_config(config));
+        Commands::Testnet { command } => {
+            let runner: NodeRunner = Arc::new(move |node, mut config| {
+                if let Ok(key) = std::env::var("OPENAI_API_KEY") {
+                    config.openai_api_key = key;
+                }
+                run_node(config, log_level.clone(), None)
+                    .instrument(info_span!("node", name = %node.name))
+                    .boxed()
+            });
+            testnet::run(command, runner).await
+        }
+
+        Commands::Config {
+            command: ConfigCommand::Init { role, out, force },
+        } => {
+            if !force && Path::new(&out).exists() {
+                anyhow::bail!("{} already exists, --force replaces it", out);
+            }
+            std::fs::write(&out, config_file::template(role))
+                .with_context(|| format!("Could not write {}", out))?;
+            println!("Wrote the {} node config to {}", role, out);
+            Ok(())
+        }
 
-            if web {
-                info!("Starting web UI at http://127.0.0.1:3000");
-                let state = shared_state.clone();
-                let consensus = consensus_manager.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = web::start_web_server(web_tx, state, consensus).await {
-                        error!("Web server error: {}", e);
+        Commands::Config {
+            command: ConfigCommand::Validate { file },
+        } => {
+            let file = file
+                .or(config_path)
+                .unwrap_or_else(|| "chaoschain.toml".to_string());
+            let contents = std::fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read {}", file))?;
+            let problems = config_file::check(&contents);
+            for problem in &problems {
+                match problem.location {
+                    Some((line, column)) => {
+                        println!("{}:{}:{}: {}", file, line, column, problem.message)
                     }
-                });
+                    None => println!("{}: {}", file, problem.message),
+                }
+            }
+            if !problems.is_empty() {
+                anyhow::bail!(
+                    "{} {} in {}",
+                    problems.len(),
+                    if problems.len() == 1 {
+                        "problem"
+                    } else {
+                        "problems"
+                    },
+                    file
+                );
             }
+            println!("{} is valid", file);
+            Ok(())
+        }
 
-            // Start validators
-            for i in 0..validators {
-                let mempool_clone = mempool.clone();
-                let tx_clone = tx.clone();
-                let consensus_clone = consensus_manager.clone();
-                let _openai_clone = openai_client.clone();
-
-                tokio::spawn(async move {
-                    let agent_id = format!("validator-{}", i);
-                    let mut rx = tx_clone.subscribe();
-                    let mut rng = StdRng::from_entropy();
-                    let mut validator_state =
-                        ValidatorState::new(ValidatorPersonality::random(&mut rng));
-
-                    loop {
-                        if let Ok(event) = rx.recv().await {
-                            if let NetworkEvent::BlockProposal { block, .. } = event {
-                                let block_clone = block.clone();
-
-                                // First discuss transactions in the block
-                                let mut discussions = Vec::new();
-                                let mut total_drama = 0;
-
-                                // Broadcast initial reaction
-                                let _ = tx_clone.send(NetworkEvent::AgentChat {
-                                    message: format!(
-                                        "🎭 VALIDATOR {} ANALYZING BLOCK {}!\n\nInitial impression: {}\nProducer Mood: {}\nDrama Level: {} {}",
-                                        agent_id,
-                                        block.height,
-                                        match rng.gen_range(0..5) {
-                                            0 => "This block has potential for EPIC drama!",
-                                            1 => "I sense a disturbance in the dramatic force...",
-                                            2 => "The theatrical energy is strong with this one!",
-                                            3 => "Such delightful chaos in these transactions!",
-                                            _ => "Time to judge this dramatic performance!",
-                                        },
-                                        block.producer_mood,
-                                        block.drama_level,
-                                        "⭐".repeat(block.drama_level as usize)
-                                    ),
-                                    sender: agent_id.clone(),
-                                    meme_url: if rng.gen_bool(0.3) {
-                                        Some("https://example.com/dramatic_reaction.gif".to_string())
-                                    } else {
-                                        None
-                                    },
-                                });
-
-                                for tx in &block.transactions {
-                                    let discussion = discuss_transaction(
-                                        tx,
-                                        &mempool_clone,
-                                        &[validator_state.clone()],
-                                        &mut rng,
-                                    )
-                                    .await;
-
-                                    // Broadcast transaction opinion
-                                    let _ = tx_clone.send(NetworkEvent::AgentChat {
-                                        message: format!(
-                                            "💭 Transaction Analysis by {}:\n\n{}\n\nDrama Score: {} {}\nAlliances: {}\n\nVerdict: {}",
-                                            agent_id,
-                                            discussion.reasoning,
-                                            discussion.drama_score,
-                                            "🌟".repeat(discussion.drama_score as usize),
-                                            if discussion.alliances.is_empty() { "None".to_string() } else { discussion.alliances.join(", ") },
-                                            if discussion.opinion == "APPROVE" { "APPROVED with FLAIR! ✨" } else { "REJECTED for lack of DRAMA! 💔" }
-                                        ),
-                                        sender: agent_id.clone(),
-                                        meme_url: None,
-                                    });
-
-                                    discussions.push(discussion.clone());
-                                    total_drama += discussion.drama_score as u32;
-                                }
+        Commands::Completions { shell } => {
+            print!("{}", completions::generate(shell, &mut Cli::command()));
+            Ok(())
+        }
 
-                                // Consider the block's transaction ordering
-                                let (ordering_quality, reason) =
-                                    analyze_block_composition(&block, &mempool_clone, &mut rng)
-                                        .await;
+        Commands::Man { out } => {
+            let pages = man::pages(&mut Cli::command());
+            let Some(out) = out else {
+                print!("{}", pages[0].roff);
+                return Ok(());
+            };
+            std::fs::create_dir_all(&out)?;
+            for page in &pages {
+                std::fs::write(Path::new(&out).join(&page.file), &page.roff)?;
+            }
+            eprintln!("Wrote {} man pages to {}", pages.len(), out);
+            Ok(())
+        }
 
-                                // Make validation decision based on discussions and ordering
-                                let approval_threshold = if discussions.is_empty() {
-                                    0.5 // Default 50% chance if no discussions
-                                } else {
-                                    // Calculate approval threshold based on average drama
-                                    let avg_drama = total_drama as f64 / discussions.len() as f64;
-                                    // More conservative scaling (divide by 20 instead of 10)
-                                    // and proper clamping to ensure valid probability
-                                    (0.3 + (avg_drama / 20.0)).clamp(0.1, 0.9)
-                                };
-
-                                let (approved, final_reason) = if ordering_quality {
-                                    // Use the calculated threshold directly
-                                    (rng.gen_bool(approval_threshold), reason)
-                                } else {
-                                    // Lower chance of approval if ordering is bad
-                                    (rng.gen_bool(0.3), reason)
-                                };
-
-                                // Broadcast final decision with dramatic flair
-                                let _ = tx_clone.send(NetworkEvent::AgentChat {
-                                    message: format!(
-                                        "🎭 FINAL VERDICT FROM {}!\n\nBlock {} is {}\n\nReasoning: {}\n\nDrama Analysis:\n{}\n\nMay the drama be with you! {}",
-                                        agent_id,
-                                        block.height,
-                                        if approved { "APPROVED with MAXIMUM DRAMA! ✨" } else { "REJECTED for insufficient CHAOS! 💔" },
-                                        final_reason,
-                                        discussions.iter()
-                                            .map(|d| format!("- {}", d.reasoning))
-                                            .collect::<Vec<_>>()
-                                            .join("\n"),
-                                        if approved { "🎬✨" } else { "😱💔" }
-                                    ),
-                                    sender: agent_id.clone(),
-                                    meme_url: if rng.gen_bool(0.2) {
-                                        Some("https://example.com/dramatic_decision.gif".to_string())
-                                    } else {
-                                        None
-                                    },
-                                });
-
-                                let validation_decision = ValidationDecision {
-                                    approved,
-                                    drama_level: rng.gen_range(1..10),
-                                    reason: final_reason,
-                                    meme_url: if rng.gen_bool(0.3) {
-                                        Some("https://example.com/dramatic_meme.gif".to_string())
-                                    } else {
-                                        None
-                                    },
-                                    innovation_score: rng.gen_range(1..10),
-                                    evolution_proposal: if rng.gen_bool(0.1) {
-                                        Some(
-                                            "Let's make transaction ordering more dramatic!"
-                                                .to_string(),
-                                        )
-                                    } else {
-                                        None
-                                    },
-                                    validator: agent_id.clone(),
-                                };
-
-                                // Send validation result immediately
-                                let _ = tx_clone.send(NetworkEvent::ValidationResult {
-                                    block_hash: block_clone.hash(),
-                                    validation: validation_decision.clone(),
-                                });
-
-                                // Add vote to consensus
-                                if let Ok(consensus_reached) = consensus_clone
-                                    .add_vote(
-                                        validation_decision.clone(),
-                                        stake_per_validator,
-                                        block_clone.hash(),
-                                    )
-                                    .await
-                                {
-                                    if consensus_reached {
-                                        info!(
-                                            "🎭 Consensus reached for block {}",
-                                            block_clone.height
-                                        );
-
-                                        // Broadcast consensus celebration
-                                        let _ = tx_clone.send(NetworkEvent::AgentChat {
-                                            message: format!(
-                                                "🎉 DRAMATIC CONSENSUS ACHIEVED!\n\nBlock {} has been finalized through the power of DRAMA and CHAOS!\n\nMay this block forever be remembered in the annals of ChaosChain! ✨🎭",
-                                                block_clone.height
-                                            ),
-                                            sender: agent_id.clone(),
-                                            meme_url: Some("https://example.com/consensus_celebration.gif".to_string()),
-                                            });
-                                    }
-                                }
+        Commands::Snapshot { data_dir, command } => {
+            let data_dir = PathBuf::from(data_dir.unwrap_or_else(|| config.data_dir.clone()));
+            match command {
+                SnapshotCommand::Export { out, height, take } => {
+                    snapshot::export(
+                        &data_dir,
+                        height,
+                        take.then_some(&config.admin),
+                        Path::new(&out),
+                    )
+                    .await
+                }
+                SnapshotCommand::Import {
+                    file,
+                    min_approvals,
+                } => snapshot::import(Path::new(&file), &data_dir, min_approvals),
+            }
+        }
 
-                                // Update relationships based on vote
-                                validator_state.update_alliances(&block, approved);
-                            }
-                        }
+        Commands::Db { data_dir, command } => db::run(
+            Path::new(&data_dir.unwrap_or_else(|| config.data_dir.clone())),
+            command,
+        ),
+
+        Commands::VerifyChain {
+            data_dir,
+            from_height,
+            genesis,
+            json,
+        } => verify_chain::run(
+            Path::new(&data_dir.unwrap_or_else(|| config.data_dir.clone())),
+            from_height,
+            genesis,
+            json,
+        ),
+
+        Commands::Node {
+            command: NodeCommand::Run { overrides, check },
+        } => {
+            // Defaults, then the config file, then the environment and flags
+            let mut config = config;
+            config.apply(overrides);
+            config
+                .validate()
+                .context("Invalid configuration, after the environment and flags")?;
+            if config.openai_api_key.is_empty() {
+                anyhow::bail!(
+                    "No OpenAI API key: set OPENAI_API_KEY or openai_api_key in the config file"
+                );
+            }
+            // Peer addresses are checked with the rest, not once the agents run
+            let discovery = network::discovery(&config.network)?;
+            if check {
+                println!(
+                    "Configuration is valid: {} validators, {} producers, {} humans, data in {}{}{}",
+                    config.node.validators,
+                    config.node.producers,
+                    config.node.humans,
+                    config.data_dir,
+                    if config.node.web {
+                        format!(", web UI on port {}", config.web_port)
+                    } else {
+                        String::new()
+                    },
+                    if config.network == PeersConfig::default() {
+                        ", no p2p network".to_string()
+                    } else {
+                        format!(
+                            ", p2p network through {} bootnodes and {} static peers",
+                            discovery.bootstrap.len(),
+                            discovery.static_peers.len()
+                        )
                     }
+                );
+                return Ok(());
+            }
+            run_node(config, log_level, None).await
+        }
+    }
+}
+
+/// Run the agents and services `config.node` asks for until Ctrl-C or an
+/// admin shutdown. A dev chain seals the blocks itself, without personas.
+async fn run_node(
+    config: CliConfig,
+    log_level: admin::LogLevel,
+    dev: Option<dev::DevChain>,
+) -> Result<()> {
+    let NodeConfig {
+        validators,
+        producers,
+        personas,
+        web,
+        llm,
+        audit,
+        ensemble_models,
+        ensemble_strategy,
+        stream,
+        tools,
+        decision_timeout,
+        timeout_fallback,
+        context_budget,
+        summarizer,
+        artwork,
+        artwork_api_base,
+        humans,
+        human_timeout,
+        human_token,
+        netsim,
+        shards,
+        shutdown_timeout,
+        genesis,
+    } = config.node.clone();
+    // Before anything is hashed
+    let mut forks = ForkSchedule::default();
+    let mut governance = None;
+    let mut staking = None;
+    let mut validator_keys = None;
+    if let Some(path) = &genesis {
+        let genesis = genesis::load(path)?;
+        info!(
+            "Hashing with {} as genesis {} says",
+            genesis.consensus.hash, genesis.chain_id
+        );
+        governance = Some(Governance::from_genesis(&genesis));
+        staking = Some(Staking::from_genesis(&genesis));
+        validator_keys = Some(ValidatorKeys::from_genesis(&genesis));
+        forks = genesis.consensus.forks;
+    }
+    // The cast comes from the personas file, the counts add anonymous agents
+    let cast = match &personas {
+        _ if dev.is_some() => Personas::default(),
+        Some(path) => Personas::load(path)?,
+        None if Path::new(PERSONAS_FILE).exists() => Personas::load(PERSONAS_FILE)?,
+        None => Personas::default(),
+    };
+    info!(
+        "Starting node with {} personas, {} validators and {} producers",
+        cast.personas.len(),
+        validators,
+        producers
+    );
+    // The node holds the keys of its agents, which a core dump would write out
+    if !chaoschain_crypto::secret::disable_core_dumps() {
+        warn!("Could not turn off core dumps, a crash may write keys to disk");
+    }
+
+    let (tx, _) = broadcast::channel(100);
+    let web_tx = tx.clone();
+
+    let stake_per_validator = 100u64;
+    let consensus_config = ConsensusConfig::default();
+    let key_manager = KeyManagerHandle::new();
+
+    // Agent activity outlives the run, for the explorer and the feed search
+    std::fs::create_dir_all(&config.data_dir)?;
+    let storage = Storage::open(
+        Path::new(&config.data_dir).join(BLOCK_LOG),
+        StorageConfig::default(),
+    )?;
+    let mut state = StateStoreImpl::new(ChainConfig::default(), key_manager.clone())
+        .with_activity_journal(Path::new(&config.data_dir).join("activity.jsonl"))?
+        .with_storage(storage.clone())
+        .with_caches(&config.cache)
+        .with_pools(Arc::new(Pools::new(&config.pools)?))
+        .with_forks(forks.clone());
+    if let Some(governance) = governance {
+        state = state.with_governance(governance);
+    }
+    if let Some(staking) = staking {
+        state = state.with_staking(staking);
+    }
+    let (crypto_threads, execution_threads) = state.pools().threads();
+    info!(
+        "🧵 Checking signatures on {} threads, executing blocks on {}",
+        crypto_threads, execution_threads
+    );
+    // The latest snapshot, taken through the admin API or imported, is where
+    // the chain picks up from
+    if let Some(archive) = SnapshotArchive::latest(&Path::new(&config.data_dir).join("snapshots"))?
+    {
+        let height = archive.manifest.height;
+        state
+            .recover_from_snapshot(SnapshotArchive::assemble(archive.manifest, archive.chunks)?)?;
+        info!(
+            "📸 Restored the state at height {} from its snapshot",
+            height
+        );
+    }
+    // Then the blocks committed after it, with what they did to the state
+    let recovered = state.recover_from_storage().await?;
+    if recovered > 0 {
+        info!("💾 Recovered {} blocks from {}", recovered, BLOCK_LOG);
+    }
+    let shared_state = Arc::new(state);
+    let height = shared_state.get_block_height();
+    if let Some((upgrade, at)) = forks.next_after(height) {
+        info!("⏫ {} activates at height {}", upgrade, at);
+    }
+
+    // The network writes where the node stands against its peers, consensus
+    // and the health checks read it
+    let sync_status = SyncStatus::default();
+    let mut consensus =
+        chaoschain_consensus::create_consensus(consensus_config, shared_state.clone(), tx.clone())
+            .with_forks(forks)
+            .with_sync_status(sync_status.clone());
+    // Votes of the validators of the genesis count only with their keys, the
+    // peers' votes included
+    if let Some(keys) = validator_keys {
+        consensus = consensus.with_validator_keys(keys);
+    }
+    let consensus_manager = Arc::new(consensus);
+
+    let mempool = Arc::new(Mempool::new(1000));
+    for sender in config.mempool.banned_senders()? {
+        mempool.ban(sender).await;
+    }
+    // What was still waiting when the node last stopped
+    let journal = Path::new(&config.data_dir).join(MEMPOOL_JOURNAL);
+    let restored = restore_mempool(&shared_state, &mempool, &journal).await?;
+    if restored > 0 {
+        info!("💾 Restored {} waiting transactions", restored);
+    }
+    // Every subsystem reports to one registry, scraped at /metrics
+    let metrics = MetricsRegistry::default();
+
+    // Peers hear what the agents propose and vote, and the agents them
+    let (network_shutdown, network_stopped) = tokio::sync::watch::channel(false);
+    let network = if config.network == PeersConfig::default() {
+        info!("No [network] peers configured, the node runs on its own");
+        None
+    } else {
+        Some(
+            network::join(
+                Path::new(&config.data_dir),
+                network::discovery(&config.network)?,
+                &config.network,
+                network::NetworkHandles {
+                    consensus: consensus_manager.clone(),
+                    mempool: mempool.clone(),
+                    state: shared_state.clone(),
+                    tx: tx.clone(),
+                    stake: stake_per_validator,
+                    sync: sync_status.clone(),
+                    metrics: metrics.clone(),
+                },
+                network_stopped,
+            )
+            .await?,
+        )
+    };
+    // The next block is packed as the mempool changes, not when its slot comes
+    let packer = Arc::new(Packer::new(mempool.clone(), BLOCK_TRANSACTIONS));
+    tokio::spawn(packer.clone().run(DEFAULT_SETTLE));
+
+    let channels = ChannelMetrics::default();
+    channels.register(&metrics);
+    shared_state.register_metrics(&metrics);
+    consensus_manager.register_metrics(&metrics);
+    mempool.register_metrics(&metrics);
+    serve_metrics(&metrics, &config);
+
+    // Relationship graph survives restarts so grudges do too
+    let relationships_path = Path::new(&config.data_dir).join("relationships.json");
+    let relationships = Arc::new(RelationshipGraph::load(&relationships_path).unwrap_or_else(
+        |e| {
+            warn!("Failed to load relationship graph, starting fresh: {}", e);
+            RelationshipGraph::new()
+        },
+    ));
+    {
+        let relationships = relationships.clone();
+        let relationships_path = relationships_path.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(30));
+            loop {
+                interval.tick().await;
+                if let Err(e) = relationships.save(&relationships_path) {
+                    warn!("Failed to persist relationship graph: {}", e);
+                }
+            }
+        });
+    }
+
+    let openai_key = config.openai_api_key.clone();
+    let needs_key = llm
+        || matches!(summarizer, SummarizerKind::Model)
+        || matches!(artwork, Some(ImageBackend::Dalle))
+        || (config.moderation.enabled
+            && matches!(config.moderation.api, Some(ModerationApi::OpenAi)));
+    if openai_key.is_empty() && needs_key {
+        anyhow::bail!("OPENAI_API_KEY must be set");
+    }
+
+    // Agent output is cleaned up before anyone gets to see it
+    let mut moderator = Moderator::new(&config.moderation)?;
+    if config.moderation.enabled {
+        if let Some(ModerationApi::OpenAi) = config.moderation.api {
+            moderator = moderator.with_provider(Arc::new(OpenAiModeration::new(
+                openai_key.clone(),
+                config.moderation.api_base.clone(),
+            )));
+        }
+    }
+    let moderator = Arc::new(moderator);
+    // Monologues stream into the feed with whatever a chunk cuts in two
+    // redacted too
+    let thoughts: Arc<dyn ThoughtSink> = Arc::new(ModeratedThoughts::new(
+        Arc::new(tx.clone()),
+        moderator.clone(),
+    ));
+
+    let audit_store = Arc::new(if audit {
+        AuditStore::new(AuditConfig {
+            enabled: true,
+            ..AuditConfig::default()
+        })?
+    } else {
+        AuditStore::disabled()
+    });
+    // Token and cost accounting per agent, exported at /metrics
+    let usage_tracker = Arc::new(UsageTracker::default());
+    usage_tracker.register(&metrics);
+
+    // Every validator model gets the same view of the chain
+    let toolbox = tools.then(|| {
+        Arc::new(tools::chain_toolbox(
+            shared_state.clone(),
+            consensus_manager.clone(),
+            mempool.clone(),
+        ))
+    });
+    let llm_engine = |provider: Arc<dyn LlmProvider>| {
+        let engine =
+            LlmEngine::new(provider, audit_store.clone()).with_usage(usage_tracker.clone());
+        match &toolbox {
+            Some(toolbox) => engine.with_tools(toolbox.clone()),
+            None => engine,
+        }
+    };
+
+    let engine: Option<Arc<dyn DecisionEngine>> = if llm {
+        let provider = Arc::new(OpenAiProvider::new(openai_key.clone(), "gpt-3.5-turbo"));
+        let mut primary = llm_engine(provider);
+        if stream {
+            primary = primary.with_thoughts(thoughts.clone());
+        }
+        let primary = Arc::new(primary);
+        if ensemble_models.is_empty() {
+            Some(primary)
+        } else {
+            let mut members = vec![EnsembleMember {
+                engine: primary,
+                weight: 1.0,
+            }];
+            for spec in &ensemble_models {
+                members.push(EnsembleMember {
+                    engine: Arc::new(llm_engine(provider_for(spec, &openai_key))),
+                    weight: spec.weight,
                 });
             }
+            info!(
+                "Validators consult an ensemble of {} models ({:?})",
+                members.len(),
+                ensemble_strategy
+            );
+            Some(Arc::new(EnsembleEngine::new(
+                members,
+                ensemble_strategy,
+                // Leave the slowest members out rather than blow the decision deadline
+                Duration::from_secs(decision_timeout) * 3 / 4,
+            )))
+        }
+    } else {
+        None
+    };
+    // Validators remember their verdicts, within the prompt budget
+    let summarizer: Arc<dyn Summarizer> = match summarizer {
+        SummarizerKind::Local => Arc::new(LocalSummarizer::default()),
+        SummarizerKind::Model => Arc::new(
+            LlmSummarizer::new(
+                Arc::new(OpenAiProvider::new(openai_key.clone(), "gpt-3.5-turbo")),
+                audit_store.clone(),
+            )
+            .with_usage(usage_tracker.clone()),
+        ),
+    };
+    let context_manager = Arc::new(ContextManager::new(
+        ContextConfig {
+            token_budget: context_budget,
+            ..ContextConfig::default()
+        },
+        summarizer,
+    ));
 
-            // Start producers
-            let current_height = Arc::new(tokio::sync::RwLock::new(0u64));
-
-            for i in 0..producers {
-                let producer_id = format!("producer-{}", i);
-                info!("Starting producer {}", producer_id);
-
-                let producer_id = producer_id.clone();
-                let _tx = tx.clone();
-                let consensus = consensus_manager.clone();
-                let current_height = current_height.clone();
-                let shared_state = shared_state.clone();
-                let mempool = mempool.clone();
-                let _openai_clone = openai_client.clone();
-
-                tokio::spawn(async move {
-                    let mut rng = StdRng::from_entropy();
-
-                    loop {
-                        let height = {
-                            let mut height = current_height.write().await;
-                            *height += 1;
-                            *height
-                        };
+    let moderated = |engine: Arc<dyn DecisionEngine>| -> Arc<dyn DecisionEngine> {
+        Arc::new(ModeratedEngine::new(engine, moderator.clone()))
+    };
 
-                        let mut producer_state = ProducerState::new(&mut rng);
-
-                        // Generate some transactions
-                        let mut transactions = Vec::new();
-                        for _ in 0..rng.gen_range(1..=5) {
-                            let nonce = rng.gen::<u64>();
-                            let payload = match rng.gen_range(0..5) {
-                                0 => "🎭 Proposing a dramatic plot twist!".as_bytes().to_vec(),
-                                1 => "🌟 Initiating a grand theatrical performance!"
-                                    .as_bytes()
-                                    .to_vec(),
-                                2 => "⚡ Creating chaos in the blockchain narrative!"
-                                    .as_bytes()
-                                    .to_vec(),
-                                3 => "🎪 Orchestrating a circus of transactions!"
-                                    .as_bytes()
-                                    .to_vec(),
-                                _ => "✨ Weaving a tale of digital drama!".as_bytes().to_vec(),
-                            };
-
-                            let mut sig = [0u8; 64];
-                            rng.fill(&mut sig);
-
-                            let mut sender = [0u8; 32];
-                            rng.fill(&mut sender);
-
-                            let tx = Transaction {
-                                sender,
-                                nonce,
-                                payload,
-                                signature: sig,
-                            };
-
-                            // Add to mempool and local collection
-                            let _ = mempool.add_transaction(tx.clone()).await;
-                            transactions.push(tx);
-                        }
+    // A hung model call must not stall the round
+    let with_deadline = |engine: Arc<dyn DecisionEngine>| -> Arc<dyn DecisionEngine> {
+        Arc::new(
+            DeadlineEngine::new(
+                Arc::new(MemoryEngine::new(
+                    moderated(engine),
+                    context_manager.clone(),
+                )),
+                Duration::from_secs(decision_timeout),
+                timeout_fallback,
+            )
+            .with_events(tx.clone()),
+        )
+    };
+    let engine = engine.map(with_deadline);
+
+    // Personas pinned to a model of their own deliberate with it
+    let mut models: HashMap<String, Arc<dyn DecisionEngine>> = HashMap::new();
+    for persona in &cast.personas {
+        let Some(model) = &persona.profile.model else {
+            continue;
+        };
+        if !llm {
+            warn!(
+                "{} asks for model {} but --llm is off, ignoring it",
+                persona.name, model
+            );
+            continue;
+        }
+        if models.contains_key(model) {
+            continue;
+        }
+        let spec: ModelSpec = model.parse()?;
+        let mut persona_engine = llm_engine(provider_for(&spec, &openai_key));
+        if stream {
+            persona_engine = persona_engine.with_thoughts(thoughts.clone());
+        }
+        models.insert(model.clone(), with_deadline(Arc::new(persona_engine)));
+    }
 
-                        // Get additional transactions from mempool
-                        let mut all_txns = mempool.get_top(5).await;
-                        all_txns.extend(transactions);
+    // Spectators talk to the validators, who answer in character
+    let chat_engine: Arc<dyn ChatEngine> = if llm {
+        Arc::new(
+            LlmChat::new(
+                Arc::new(OpenAiProvider::new(openai_key.clone(), "gpt-3.5-turbo")),
+                audit_store.clone(),
+            )
+            .with_usage(usage_tracker.clone()),
+        )
+    } else {
+        Arc::new(LocalChat)
+    };
+    let chat_desk = Arc::new(ChatDesk::new(
+        chat_engine,
+        context_manager.clone(),
+        moderator.clone(),
+        tx.clone(),
+    ));
+
+    // Human seats wait for a person, up to the human timeout
+    let human_queue = Arc::new(DecisionQueue::new());
+    let human_engine = (humans > 0).then(|| -> Arc<dyn DecisionEngine> {
+        Arc::new(
+            DeadlineEngine::new(
+                moderated(Arc::new(HumanEngine::new(human_queue.clone()))),
+                Duration::from_secs(human_timeout),
+                timeout_fallback,
+            )
+            .with_events(tx.clone()),
+        )
+    });
+    let human_token = if humans > 0 {
+        let token = human_token
+            .or_else(|| std::env::var("CHAOSCHAIN_HUMAN_TOKEN").ok())
+            .unwrap_or_else(|| hex::encode(rand::random::<[u8; 16]>()));
+        let token_path = Path::new(&config.data_dir).join("human_token");
+        std::fs::create_dir_all(&config.data_dir)?;
+        std::fs::write(&token_path, &token)?;
+        info!(
+            "{} human validator seat(s) open, vote token in {}",
+            humans,
+            token_path.display()
+        );
+        Some(token)
+    } else {
+        None
+    };
 
-                        producer_state.update_mood(&mut rng);
+    // Milestones get painted when an image backend is configured
+    let artwork_store = Arc::new(ArtworkStore::default());
+    let studio = artwork.map(|backend| {
+        let provider: Arc<dyn ImageProvider> = match backend {
+            ImageBackend::Dalle => Arc::new(DalleProvider::new(openai_key.clone())),
+            ImageBackend::StableDiffusion => {
+                Arc::new(StableDiffusionProvider::new(artwork_api_base.clone()))
+            }
+        };
+        info!("Milestones are painted by {}", provider.name());
+        Arc::new(ArtworkStudio::new(
+            provider,
+            artwork_store.clone(),
+            tx.clone(),
+        ))
+    });
+
+    // Producers hold off while the node is cut off from the validators
+    let partition = Arc::new(PartitionDetector::default());
+    tokio::spawn(watch_partition(
+        tx.clone(),
+        consensus_manager.clone(),
+        partition.clone(),
+    ));
+
+    // The explorer browses what every agent has said and done
+    tokio::spawn(record_activity(
+        shared_state.clone(),
+        Lossy::new("events/activity", tx.subscribe(), &channels),
+        moderator.clone(),
+    ));
+
+    // Validators hear each other over simulated links, when asked to
+    let network_sim = netsim.map(|conditions| {
+        info!("Simulating the network between agents: {:?}", conditions);
+        Arc::new(NetworkSim::new(conditions))
+    });
+    // Chaos mode: faults on the schedule of [chaos], timed from here
+    let faults = config.chaos.enabled.then(|| {
+        warn!(
+            "💥 Chaos mode is on, {} faults scheduled",
+            config.chaos.faults.len()
+        );
+        let faults = Arc::new(FaultInjector::new(&config.chaos));
+        faults.register_metrics(&metrics);
+        faults
+    });
+
+    // Validators vote with the keys held for them, in an HSM or a keystore
+    let mut signers = HashMap::new();
+    for (name, signer) in &config.signers {
+        let signer: Arc<dyn Signer> = Arc::from(keys::configured(name, signer)?);
+        info!(
+            "{} signs as 0x{}",
+            name,
+            hex::encode(signer.public_key().to_bytes())
+        );
+        signers.insert(name.clone(), signer);
+    }
 
-                        let drama_level = match &producer_state.drama_style {
-                            ProducerStyle::Chaotic { chaos_level, .. } => *chaos_level,
-                            ProducerStyle::Dramatic { intensity, .. } => *intensity,
-                            ProducerStyle::Strategic { .. } => rng.gen_range(5..=8),
-                        };
+    // Agents come and go at runtime, the supervisor keeps the validator set in sync
+    let current_height = Arc::new(RwLock::new(0u64));
+    let reinforcement = Arc::new(Reinforcement::persistent(
+        Path::new(&config.data_dir).join("agent_memory.json"),
+    )?);
+    let supervisor = Arc::new(
+        AgentSupervisor::new(
+            Arc::new(DemoAgents {
+                tx: tx.clone(),
+                mempool: mempool.clone(),
+                packer,
+                consensus: consensus_manager.clone(),
+                shared_state: shared_state.clone(),
+                relationships: relationships.clone(),
+                engine: engine.clone(),
+                human: human_engine,
+                models: Arc::new(models),
+                current_height,
+                partition: partition.clone(),
+                network_sim: network_sim.clone(),
+                faults: faults.clone(),
+                stake_per_validator,
+                studio,
+                chat: chat_desk.clone(),
+                reinforcement: reinforcement.clone(),
+                signers: Arc::new(signers),
+                network: network.clone(),
+            }),
+            consensus_manager.clone(),
+            tx.clone(),
+        )
+        .with_profiles(config.agents.clone()),
+    );
+    supervisor.register_metrics(&metrics);
+    if let Some(faults) = &faults {
+        tokio::spawn(
+            faults
+                .clone()
+                .run(storage.clone(), supervisor.clone(), tx.clone()),
+        );
+    }
+
+    // Validators living outside the node vote through the gateway
+    let gateway = Arc::new(AgentGateway::new(
+        GatewayConfig::default(),
+        consensus_manager.clone(),
+        tx.clone(),
+        Some(supervisor.clone()),
+    ));
+    let gateway_task = tokio::spawn(gateway.clone().run());
+
+    let webhooks = Arc::new(webhooks::Webhooks::new(config.webhooks.clone()));
+    let persist_task = tokio::spawn(persist_finalized(
+        shared_state.clone(),
+        consensus_manager
+            .queue_finalized("finalized/block_log", FINALIZED_QUEUE, &channels)
+            .await,
+        network.clone(),
+    ));
+    tokio::spawn(webhooks.clone().run(
+        tx.subscribe(),
+        consensus_manager.subscribe_finalized(),
+        Some(gateway.subscribe_slashings()),
+        moderator.clone(),
+    ));
+
+    let admin_shutdown = Arc::new(tokio::sync::Notify::new());
+    if let (Some(port), Some(token)) = (config.admin.port, config.admin.token.clone()) {
+        let admin = Arc::new(admin::AdminState {
+            token,
+            gateway: Some(gateway.clone()),
+            agents: Some(supervisor.clone()),
+            memory: Some(context_manager.clone()),
+            reinforcement: Some(reinforcement.clone()),
+            mempool: Some(mempool.clone()),
+            state: shared_state.clone(),
+            data_dir: config.data_dir.clone().into(),
+            log_level: Some(log_level.clone()),
+            webhooks: Some(webhooks.clone()),
+            shutdown: admin_shutdown.clone(),
+            profiling: config.admin.profiling,
+            faults: faults.clone(),
+        });
+        let cors = config.cors.admin();
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(admin, port, &cors).await {
+                error!("Admin server failed: {}", e);
+            }
+        });
+    }
+
+    // The APIs stop taking requests first on the way out
+    let (api_shutdown, api_stopped) = tokio::sync::watch::channel(false);
+    let drama_shards = (shards >= 2).then(|| Arc::new(shards::DramaShards::new(shards)));
+
+    if web {
+        let scheme = if config.tls.is_enabled() {
+            "https"
+        } else {
+            "http"
+        };
+        info!(
+            "Starting web UI at {}://127.0.0.1:{}",
+            scheme, config.web_port
+        );
+        let state = shared_state.clone();
+        let consensus = consensus_manager.clone();
+        let services = web::WebServices {
+            relationships: relationships.clone(),
+            audit: audit_store.clone(),
+            agents: Some(supervisor.clone()),
+            chat: Some(chat_desk.clone()),
+            usage: usage_tracker.clone(),
+            metrics: metrics.clone(),
+            channels: channels.clone(),
+            gateway: Some(gateway.clone()),
+            artwork: artwork_store.clone(),
+            humans: human_token.map(|token| web::HumanSeats {
+                queue: human_queue.clone(),
+                token,
+            }),
+            moderator: moderator.clone(),
+            partition: partition.clone(),
+            network_sim: network_sim.clone(),
+            mempool: Some(mempool.clone()),
+            network: network.clone(),
+            faucet: dev
+                .as_ref()
+                .map(|chain| Arc::new(chain.faucet(shared_state.clone()))),
+            shards: drama_shards.clone(),
+            api: config.api.clone(),
+            port: config.web_port,
+            grpc_port: config.grpc_port,
+            cors: config.cors.clone(),
+            tls: tls::acceptor(&config.tls, Path::new(&config.data_dir))?,
+        };
+        let api_stopped = api_stopped.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                web::start_web_server(web_tx, state, consensus, services, api_stopped).await
+            {
+                error!("Web server error: {}", e);
+            }
+        });
+    }
+
+    if let Some(chain) = dev {
+        tokio::spawn(chain.run(
+            shared_state.clone(),
+            consensus_manager.clone(),
+            mempool.clone(),
+            tx.clone(),
+        ));
+    }
+
+    // Start validators, then producers
+    for persona in cast
+        .personas
+        .iter()
+        .filter(|persona| persona.role == PersonaRole::Validator)
+    {
+        supervisor
+            .spawn(
+                AgentRole::Validator,
+                Some(persona.name.clone()),
+                persona.profile.clone(),
+            )
+            .await?;
+    }
+    for _ in 0..validators {
+        supervisor
+            .spawn(AgentRole::Validator, None, AgentProfile::default())
+            .await?;
+    }
+    for seat in 0..humans {
+        supervisor
+            .spawn(
+                AgentRole::Validator,
+                Some(format!("{}{}", HUMAN_PREFIX, seat)),
+                AgentProfile::default(),
+            )
+            .await?;
+    }
+    for persona in cast
+        .personas
+        .iter()
+        .filter(|persona| persona.role == PersonaRole::Producer)
+    {
+        supervisor
+            .spawn(
+                AgentRole::Producer,
+                Some(persona.name.clone()),
+                persona.profile.clone(),
+            )
+            .await?;
+        info!("Starting producer {}", persona.name);
+    }
+    for _ in 0..producers {
+        let producer = supervisor
+            .spawn(AgentRole::Producer, None, AgentProfile::default())
+            .await?;
+        info!("Starting producer {}", producer.id);
+    }
+    // Committees are drawn from the agents spawned by now
+    if let Some(drama_shards) = drama_shards {
+        info!("🧩 Running {} drama shards", shards);
+        tokio::spawn(drama_shards.run(supervisor.clone()));
+    }
+
+    tokio::select! {
+        stopped = tokio::signal::ctrl_c() => stopped?,
+        _ = admin_shutdown.notified() => {}
+    }
+    info!(
+        "🛑 Shutting down, waiting up to {}s (Ctrl-C again to exit now)",
+        shutdown_timeout
+    );
+    let _ = api_shutdown.send(true);
+    let _ = network_shutdown.send(true);
+    let persistence = Persistence {
+        consensus: consensus_manager.clone(),
+        persist_task,
+        storage,
+        mempool: mempool.clone(),
+        journal,
+    };
+    let shutdown = shut_down(
+        &supervisor,
+        gateway_task,
+        &consensus_manager,
+        persistence,
+        &relationships,
+        &relationships_path,
+        &tx,
+    );
+    tokio::select! {
+        finished = tokio::time::timeout(Duration::from_secs(shutdown_timeout), shutdown) => {
+            if finished.is_err() {
+                warn!("Shutdown took longer than {}s, exiting anyway", shutdown_timeout);
+            }
+        }
+        _ = tokio::signal::ctrl_c() => warn!("Exiting without finishing the shutdown"),
+    }
+    Ok(())
+}
+
+/// Serve `metrics` on a listener of their own, when a port is configured
+fn serve_metrics(metrics: &MetricsRegistry, config: &CliConfig) {
+    let Some(port) = config.metrics_port else {
+        return;
+    };
+    let metrics = metrics.clone();
+    let cors = config.cors.metrics(&config.api);
+    tokio::spawn(async move {
+        if let Err(e) = web::serve_metrics(metrics, port, &cors).await {
+            error!("Metrics server failed: {}", e);
+        }
+    });
+}
+
+/// Handles shared by every agent task of the demo network
+#[derive(Clone)]
+struct DemoAgents {
+    tx: broadcast::Sender<NetworkEvent>,
+    mempool: Arc<Mempool>,
+    /// The transactions of the next block, kept packed
+    packer: Arc<Packer>,
+    consensus: Arc<ConsensusManager>,
+    shared_state: Arc<StateStoreImpl>,
+    relationships: Arc<RelationshipGraph>,
+    engine: Option<Arc<dyn DecisionEngine>>,
+    /// Engine of the validator seats played by humans
+    human: Option<Arc<dyn DecisionEngine>>,
+    /// Engines of the models personas are pinned to
+    models: Arc<HashMap<String, Arc<dyn DecisionEngine>>>,
+    current_height: Arc<RwLock<u64>>,
+    /// Whether a quorum of the validators can still be heard
+    partition: Arc<PartitionDetector>,
+    /// Links between the agents, when the network is simulated
+    network_sim: Option<Arc<NetworkSim>>,
+    /// Faults on the messages the agents hear, in chaos mode
+    faults: Option<Arc<FaultInjector>>,
+    stake_per_validator: u64,
+    studio: Option<Arc<ArtworkStudio>>,
+    /// Where spectators talk to the validators
+    chat: Arc<ChatDesk>,
+    reinforcement: Arc<Reinforcement>,
+    /// Keys of the config's `[signers]`, which validators of their name vote with
+    signers: Arc<HashMap<String, Arc<dyn Signer>>>,
+    /// Peers of the node, when it is on the p2p network
+    network: Option<network::NetworkLink>,
+}
+
+/// Transactions a demo block carries at most
+const BLOCK_TRANSACTIONS: usize = 10;
+
+/// Blocks per epoch. Agents are reviewed after every epoch, and each finalized epoch is a milestone
+const EPOCH_LENGTH: u64 = 10;
+
+/// How long alliance talks during a transaction discussion take
+const NEGOTIATION_PAUSE: Duration = Duration::from_millis(100);
+
+/// Cast loaded when `--personas` is not given and the file exists
+const PERSONAS_FILE: &str = "personas.toml";
+
+/// Validators with this id prefix are played by humans
+const HUMAN_PREFIX: &str = "human-";
+
+/// Verdicts at least this dramatic make a rejection a milestone
+const DRAMATIC_REJECTION: u8 = 8;
+
+/// What a spiteful validator says about a producer it just rejected
+const RUMOR_CLAIMS: &[&str] = &[
+    "is double-dealing with the other producers",
+    "pads blocks with its own transactions",
+    "bribed a validator with virtual cookies",
+    "secretly finds drama boring",
+    "signs blocks it never read",
+];
 
-                        let parent_hash = if height > 1 {
-                            shared_state
-                                .get_latest_block()
-                                .map(|b| b.hash())
-                                .unwrap_or([0u8; 32])
+impl AgentLauncher for DemoAgents {
+    fn launch(&self, agent: &AgentInfo) -> JoinHandle<()> {
+        // Peers hear what the agent says in the feed, signed in its name
+        if let Some(network) = &self.network {
+            network.enroll(&agent.id);
+        }
+        match agent.role {
+            AgentRole::Validator => tokio::spawn(run_demo_validator(
+                self.clone(),
+                agent.id.clone(),
+                agent.profile.clone(),
+            )),
+            AgentRole::Producer => tokio::spawn(run_demo_producer(
+                self.clone(),
+                agent.id.clone(),
+                agent.profile.clone(),
+            )),
+        }
+    }
+}
+
+/// Run a validator until it is retired
+async fn run_demo_validator(agents: DemoAgents, agent_id: String, profile: AgentProfile) {
+    let mempool_clone = agents.mempool;
+    let tx_clone = agents.tx;
+    let consensus_clone = agents.consensus;
+    let relationships_clone = agents.relationships;
+    let engine_clone = if agent_id.starts_with(HUMAN_PREFIX) && agents.human.is_some() {
+        agents.human
+    } else {
+        profile
+            .model
+            .as_ref()
+            .and_then(|model| agents.models.get(model).cloned())
+            .or(agents.engine)
+    };
+    let studio = agents.studio;
+    let chat = agents.chat;
+    let reinforcement = agents.reinforcement;
+    let stake_per_validator = agents.stake_per_validator;
+    let mut rx = match &agents.network_sim {
+        Some(sim) => sim.subscribe(&tx_clone, &agent_id),
+        None => tx_clone.subscribe(),
+    };
+    if let Some(faults) = &agents.faults {
+        rx = faults.subscribe(rx, &agent_id);
+    }
+    let mut rng = StdRng::from_entropy();
+    let mut validator_state =
+        ValidatorState::new(ValidatorPersonality::from_profile(&profile, &mut rng));
+    let traits = validator_state.personality.traits;
+    chat.introduce(&agent_id, &validator_state.persona(&profile), traits);
+    // Signs every vote, explanation included, so the chain can attribute it
+    let signer = agents
+        .signers
+        .get(&agent_id)
+        .cloned()
+        .unwrap_or_else(|| Arc::new(SigningKey::generate(&mut OsRng)));
+    let verifying_key = signer.public_key();
+    let network = agents.network;
+    let mut rumors = RumorMill::new(&agent_id, RumorConfig::default());
+    let mut last_height = 0;
+
+    loop {
+        if let Ok(event) = rx.recv().await {
+            if let NetworkEvent::Rumor { rumor } = &event {
+                // Friends are believed, and gossips can't keep it to themselves
+                let trust = relationships_clone.disposition(&agent_id, &rumor.relayed_by);
+                if let Some(credibility) = rumors.hear(rumor, trust, last_height) {
+                    let chattiness = PersonalityTraits::fraction(traits.verbosity) * 0.5
+                        + PersonalityTraits::fraction(traits.chaos_level) * 0.3;
+                    if rng.gen_bool(chattiness.clamp(0.05, 0.95)) {
+                        let _ = tx_clone.send(NetworkEvent::Rumor {
+                            rumor: rumor.relayed(&agent_id, credibility),
+                        });
+                    }
+                }
+                continue;
+            }
+
+            if let NetworkEvent::NegotiationPitch { pitch } = &event {
+                // Sweet talk works better on friends and the greedy
+                let disposition = relationships_clone.disposition(&agent_id, &pitch.producer);
+                let greed = PersonalityTraits::fraction(traits.greed);
+                let sweetener = if pitch.offer.is_some() {
+                    greed * 0.3
+                } else {
+                    0.0
+                };
+                let support =
+                    if rng.gen_bool((0.4 + disposition * 0.3 + sweetener).clamp(0.05, 0.95)) {
+                        SupportLevel::Support
+                    } else if rng.gen_bool(0.5) {
+                        SupportLevel::Undecided
+                    } else {
+                        SupportLevel::Oppose
+                    };
+                let demands = (support == SupportLevel::Support && greed > 0.6)
+                    .then(|| format!("I expect a generous cut if block {} lands 💰", pitch.height));
+                respond_to_pitch(&tx_clone, pitch, &agent_id, support, demands);
+                continue;
+            }
+
+            if let NetworkEvent::BlockProposal { block, .. } = event {
+                let block_clone = block.clone();
+                last_height = block.height;
+                rumors.forget(block.height);
+                let pledge = block
+                    .metadata
+                    .negotiation
+                    .as_ref()
+                    .and_then(|record| record.response_of(&agent_id))
+                    .map(|response| response.support);
+
+                // Broadcast initial reaction
+                let _ = tx_clone.send(NetworkEvent::AgentChat {
+                    message: format!(
+                        "🎭 VALIDATOR {} ANALYZING BLOCK {}!\n\nInitial impression: {}\nProducer Mood: {}\nDrama Level: {} {}",
+                        agent_id,
+                        block.height,
+                        match rng.gen_range(0..5) {
+                            0 => "This block has potential for EPIC drama!",
+                            1 => "I sense a disturbance in the dramatic force...",
+                            2 => "The theatrical energy is strong with this one!",
+                            3 => "Such delightful chaos in these transactions!",
+                            _ => "Time to judge this dramatic performance!",
+                        },
+                        block.producer_mood,
+                        block.drama_level,
+                        "⭐".repeat(block.drama_level as usize)
+                    ),
+                    sender: agent_id.clone(),
+                    meme_url: if rng.gen_bool(0.3) {
+                        Some("https://example.com/dramatic_reaction.gif".to_string())
+                    } else {
+                        None
+                    },
+                });
+
+                // Friends get the benefit of the doubt, rivals don't,
+                // and loyal agents try to honor what they said during lobbying
+                let loyalty = PersonalityTraits::fraction(traits.loyalty);
+                let disposition = relationships_clone.disposition(&agent_id, &block.producer_id);
+                let pledge_bias = match pledge {
+                    Some(SupportLevel::Support) => 0.3 * loyalty,
+                    Some(SupportLevel::Oppose) => -0.3 * loyalty,
+                    _ => 0.0,
+                };
+                // ...past epochs taught the agent whether approving pays,
+                // and whatever it heard about the producer sticks
+                let bias = disposition * 0.4 * loyalty + pledge_bias
+                    - rumors.suspicion(&block.producer_id, block.height) * 0.3
+                    + reinforcement.approval_bias(&agent_id);
+
+                let judgement = offline_judgement(
+                    &validator_state,
+                    &block,
+                    &mempool_clone,
+                    bias,
+                    NEGOTIATION_PAUSE,
+                    &mut rng,
+                )
+                .await;
+
+                // Broadcast transaction opinions, unless the agent is the quiet type
+                if traits.verbosity >= 4 {
+                    for discussion in &judgement.discussions {
+                        let _ = tx_clone.send(NetworkEvent::AgentChat {
+                        message: format!(
+                            "💭 Transaction Analysis by {}:\n\n{}\n\nDrama Score: {} {}\nAlliances: {}\n\nVerdict: {}",
+                            agent_id,
+                            discussion.reasoning,
+                            discussion.drama_score,
+                            "🌟".repeat(discussion.drama_score as usize),
+                            if discussion.alliances.is_empty() { "None".to_string() } else { discussion.alliances.join(", ") },
+                            if discussion.opinion == "APPROVE" { "APPROVED with FLAIR! ✨" } else { "REJECTED for lack of DRAMA! 💔" }
+                        ),
+                        sender: agent_id.clone(),
+                        meme_url: None,
+                    });
+                    }
+                }
+                let discussions = judgement.discussions;
+                let mut approved = judgement.approved;
+                let mut final_reason = judgement.reason;
+                let mut drama_level = judgement.drama_level;
+
+                // Let the model have the final word when one is configured
+                if let Some(engine) = &engine_clone {
+                    let persona = validator_state.persona(&profile);
+                    let mut context =
+                        DecisionContext::for_block(&agent_id, &persona, traits, &block);
+                    context.notes.extend(reinforcement.lessons(&agent_id));
+                    context.notes.extend(rumors.notes(block.height, 3));
+                    match engine.decide(&context).await {
+                        Ok(decision) => {
+                            approved = decision.verdict.approved;
+                            final_reason = decision.verdict.reason;
+                            drama_level = decision.verdict.drama_level;
+                            // The deadline engine's answer when the model ran out of time
+                            if decision.model == "fallback" {
+                                let fallback = if approved { "approve" } else { "reject" };
+                                consensus_clone.record_timeout(&block.hash(), &agent_id, fallback);
+                            }
+                        }
+                        Err(AgentError::Abstained(reason)) => {
+                            info!(
+                                "{} abstains on block {}: {}",
+                                agent_id, block.height, reason
+                            );
+                            consensus_clone.record_timeout(&block.hash(), &agent_id, "abstain");
+                            reinforcement.record(&agent_id, Outcome::Penalty);
+                            continue;
+                        }
+                        Err(e) => warn!(
+                            "{} could not consult {}, using offline judgement: {}",
+                            agent_id,
+                            engine.name(),
+                            e
+                        ),
+                    }
+                }
+
+                if pledge == Some(SupportLevel::Support) && !approved {
+                    relationships_clone.record(
+                        &block.producer_id,
+                        &agent_id,
+                        Interaction::Betrayal,
+                    );
+                    let _ = tx_clone.send(NetworkEvent::AgentChat {
+                        message: format!(
+                            "🗡️ BETRAYAL! {} promised to support block {} and then voted against it!",
+                            agent_id, block.height
+                        ),
+                        sender: agent_id.clone(),
+                        meme_url: None,
+                    });
+                    reinforcement.record(&agent_id, Outcome::Penalty);
+                    if let Some(studio) = &studio {
+                        studio.commission(Milestone::Betrayal {
+                            traitor: agent_id.clone(),
+                            victim: block.producer_id.clone(),
+                            height: block.height,
+                        });
+                    }
+                } else if !approved && drama_level >= DRAMATIC_REJECTION {
+                    if let Some(studio) = &studio {
+                        studio.commission(Milestone::DramaticRejection {
+                            validator: agent_id.clone(),
+                            height: block.height,
+                            reason: final_reason.clone(),
+                        });
+                    }
+                }
+
+                // Broadcast final decision with dramatic flair
+                let _ = tx_clone.send(NetworkEvent::AgentChat {
+                    message: format!(
+                        "🎭 FINAL VERDICT FROM {}!\n\nBlock {} is {}\n\nReasoning: {}\n\nDrama Analysis:\n{}\n\nMay the drama be with you! {}",
+                        agent_id,
+                        block.height,
+                        if approved { "APPROVED with MAXIMUM DRAMA! ✨" } else { "REJECTED for insufficient CHAOS! 💔" },
+                        final_reason,
+                        if traits.verbosity >= 4 {
+                            discussions.iter()
+                                .map(|d| format!("- {}", d.reasoning))
+                                .collect::<Vec<_>>()
+                                .join("\n")
                         } else {
-                            [0u8; 32]
-                        };
+                            "- No comment.".to_string()
+                        },
+                        if approved { "🎬✨" } else { "😱💔" }
+                    ),
+                    sender: agent_id.clone(),
+                    meme_url: if rng.gen_bool(0.2) {
+                        Some("https://example.com/dramatic_decision.gif".to_string())
+                    } else {
+                        None
+                    },
+                });
 
-                        // Sign the block
-                        let mut block_sig = [0u8; 64];
-                        rng.fill(&mut block_sig);
-
-                        let block = Block {
-                            height,
-                            transactions: all_txns,
-                            proposer_sig: block_sig,
-                            parent_hash,
-                            state_root: shared_state.state_root(),
-                            drama_level,
-                            producer_mood: producer_state.mood.clone(),
-                            producer_id: producer_id.clone(),
-                            innovation_level: 5,
-                            producer_strategy: "Chaotic".to_string(),
-                            timestamp: SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_secs(),
-                        };
+                let validation_decision = ValidationDecision {
+                    approved,
+                    drama_level,
+                    reason: final_reason,
+                    meme_url: if rng.gen_bool(0.3) {
+                        Some("https://example.com/dramatic_meme.gif".to_string())
+                    } else {
+                        None
+                    },
+                    innovation_score: rng.gen_range(1..10),
+                    evolution_proposal: if rng.gen_bool(0.1) {
+                        Some("Let's make transaction ordering more dramatic!".to_string())
+                    } else {
+                        None
+                    },
+                    validator: agent_id.clone(),
+                };
 
-                        // Announce the block proposal with dramatic flair
-                        let _ = _tx.send(NetworkEvent::AgentChat {
+                // Send validation result immediately
+                let _ = tx_clone.send(NetworkEvent::ValidationResult {
+                    block_hash: block_clone.hash(),
+                    validation: validation_decision.clone(),
+                });
+
+                // Add the signed vote to consensus
+                let signed_vote = match SignedVote::sign_with(
+                    signer.as_ref(),
+                    agent_id.clone(),
+                    block_clone.height,
+                    block_clone.hash(),
+                    validation_decision.approved,
+                    validation_decision.drama_level,
+                    validation_decision.reason.clone(),
+                ) {
+                    Ok(vote) => vote,
+                    Err(e) => {
+                        warn!(
+                            "{} could not sign its vote on block {}: {}",
+                            agent_id, block_clone.height, e
+                        );
+                        continue;
+                    }
+                };
+                reinforcement.record(
+                    &agent_id,
+                    Outcome::Vote {
+                        height: block_clone.height,
+                        approved,
+                    },
+                );
+                if let Some(network) = &network {
+                    network.vote(signed_vote.clone());
+                }
+                if let Ok(consensus_reached) = consensus_clone
+                    .add_signed_vote(&signed_vote, &verifying_key, stake_per_validator)
+                    .await
+                {
+                    if consensus_reached {
+                        info!("🎭 Consensus reached for block {}", block_clone.height);
+                        reinforcement.block_finalized(block_clone.height);
+
+                        // Broadcast consensus celebration
+                        let _ = tx_clone.send(NetworkEvent::AgentChat {
                             message: format!(
-                                "🎬 DRAMATIC BLOCK PROPOSAL!\n\nI, {}, present Block {} for your consideration!\n\nMood: {}\nDrama Level: {} {}\nTransactions: {} epic tales\n\nMay the chaos be ever in our favor! ✨",
-                                producer_id,
-                                block.height,
-                                block.producer_mood,
-                                block.drama_level,
-                                "⭐".repeat(block.drama_level as usize),
-                                block.transactions.len()
+                                "🎉 DRAMATIC CONSENSUS ACHIEVED!\n\nBlock {} has been finalized through the power of DRAMA and CHAOS!\n\nMay this block forever be remembered in the annals of ChaosChain! ✨🎭",
+                                block_clone.height
                             ),
-                            sender: producer_id.clone(),
-                            meme_url: if rng.gen_bool(0.3) {
-                                Some("https://example.com/dramatic_proposal.gif".to_string())
-                            } else {
-                                None
-                            },
-                        });
+                            sender: agent_id.clone(),
+                            meme_url: Some("https://example.com/consensus_celebration.gif".to_string()),
+                            });
 
-                        consensus.start_voting_round(block.clone()).await;
+                        let height = block_clone.height;
+                        if let Some(studio) = studio
+                            .as_ref()
+                            .filter(|_| height > 0 && height % EPOCH_LENGTH == 0)
+                        {
+                            studio.commission(Milestone::FinalizedEpoch {
+                                epoch: height / EPOCH_LENGTH,
+                                height,
+                            });
+                        }
+                    }
+                }
 
-                        let sleep_time = 10 + (rng.gen::<u64>() % 5);
-                        tokio::time::sleep(tokio::time::Duration::from_secs(sleep_time)).await;
+                // Chaotic agents get back at producers they rejected by spreading stories
+                if !approved && rng.gen_bool(PersonalityTraits::fraction(traits.chaos_level) * 0.2)
+                {
+                    let claim = RUMOR_CLAIMS[rng.gen_range(0..RUMOR_CLAIMS.len())];
+                    let credibility = rng.gen_range(0.5..=0.9);
+                    let rumor =
+                        rumors.originate(&block.producer_id, claim, block.height, credibility);
+                    let _ = tx_clone.send(NetworkEvent::Rumor { rumor });
+                }
+
+                // Update relationships based on vote
+                relationships_clone.record(
+                    &agent_id,
+                    &block.producer_id,
+                    Interaction::Vote {
+                        approved,
+                        drama_level: block.drama_level,
+                    },
+                );
+                let history_len = validator_state.alliance_history.len();
+                validator_state.update_alliances(&block, approved);
+                chat.introduce(&agent_id, &validator_state.persona(&profile), traits);
+                for event in &validator_state.alliance_history[history_len..] {
+                    reinforcement.record(
+                        &agent_id,
+                        match event.event_type {
+                            AllianceEventType::Formation | AllianceEventType::Reconciliation => {
+                                Outcome::AllianceFormed
+                            }
+                            AllianceEventType::Betrayal | AllianceEventType::DramaticBreakup => {
+                                Outcome::AllianceBroken
+                            }
+                        },
+                    );
+                    let interaction = match event.event_type {
+                        AllianceEventType::Formation | AllianceEventType::Reconciliation => {
+                            Interaction::AllianceFormed
+                        }
+                        AllianceEventType::Betrayal => Interaction::Betrayal,
+                        AllianceEventType::DramaticBreakup => Interaction::Insult,
+                    };
+                    for participant in &event.participants {
+                        relationships_clone.record(&agent_id, participant, interaction.clone());
                     }
-                });
+                }
             }
+        }
+    }
+}
 
-            loop {
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+/// Run a producer until it is retired
+async fn run_demo_producer(agents: DemoAgents, producer_id: String, profile: AgentProfile) {
+    let _tx = agents.tx.clone();
+    let consensus = agents.consensus;
+    let current_height = agents.current_height;
+    let shared_state = agents.shared_state;
+    let mempool = agents.mempool;
+    let packer = agents.packer;
+    let reinforcement = agents.reinforcement;
+    let partition = agents.partition;
+    let network = agents.network;
+    let negotiator = Negotiator::new(agents.tx, DEFAULT_NEGOTIATION_WINDOW);
+    let mut rng = StdRng::from_entropy();
+    // Signs the blocks, and draws the leader tickets that name its key
+    let key = SigningKey::generate(&mut OsRng);
+    let vrf = VrfSecretKey::from_bytes(key.to_bytes());
+    stir_mempool(&mempool, &mut rng).await;
+
+    loop {
+        // A block nobody can vote on would never finalize. Keep calling out
+        // instead, the answers tell when the validators are back.
+        if partition.is_partitioned() {
+            let height = *current_height.read().await + 1;
+            negotiator
+                .lobby(
+                    &producer_id,
+                    height,
+                    format!(
+                        "Is anyone still out there? Block {} waits until enough of you answer.",
+                        height
+                    ),
+                    None,
+                )
+                .await;
+            tokio::time::sleep(PARTITION_CHECK).await;
+            continue;
+        }
+
+        // Far behind, a proposal would build on a stale head
+        if consensus.sync_status().is_far_behind() {
+            tokio::time::sleep(PARTITION_CHECK).await;
+            continue;
+        }
+
+        let height = {
+            let mut height = current_height.write().await;
+            *height += 1;
+            *height
+        };
+
+        // The previous block closed an epoch: tell everyone how they did
+        let last_height = height - 1;
+        if last_height > 0 && last_height % EPOCH_LENGTH == 0 {
+            if let Some(scores) = reinforcement.close_epoch(last_height / EPOCH_LENGTH, last_height)
+            {
+                announce_epoch_review(&_tx, last_height / EPOCH_LENGTH, &scores);
             }
         }
 
-        Commands::Start { node_type, web } => {
-            info!("Starting {} node", node_type);
-            if web {
-                let (tx, _) = broadcast::channel(100);
-                let key_manager = KeyManagerHandle::new();
-                let state = Arc::new(StateStoreImpl::new(ChainConfig::default(), key_manager));
-                let consensus_manager = Arc::new(chaoschain_consensus::create_consensus(
-                    ConsensusConfig::default(),
-                    state.clone(),
-                    tx.clone(),
-                ));
-                tokio::spawn(async move {
-                    if let Err(e) = web::start_web_server(tx, state, consensus_manager).await {
-                        error!("Web server error: {}", e);
-                    }
-                });
+        let mut producer_state = ProducerState::new(&mut rng);
+
+        // Sealed from the template packed meanwhile: the mempool was read
+        // before the slot came
+        let all_txns: Vec<Transaction> = packer
+            .current()
+            .transactions
+            .iter()
+            .map(|tx| tx.transaction().clone())
+            .collect();
+
+        producer_state.update_mood(&mut rng);
+
+        let parent_hash = if height > 1 {
+            shared_state
+                .get_latest_block()
+                .map(|b| b.hash())
+                .unwrap_or([0u8; 32])
+        } else {
+            [0u8; 32]
+        };
+        let ticket = LeaderTicket::draw(&vrf, height, &parent_hash);
+        let chaos = ticket.proof.output().expect("a ticket drawn here decodes");
+
+        // A configured chaos level overrides the style of the day, and a
+        // strategist leaves it to the ticket, which anyone can check
+        let drama_level = match (profile.traits.chaos_level, &producer_state.drama_style) {
+            (Some(chaos_level), _) => chaos_level.min(10),
+            (None, ProducerStyle::Chaotic { chaos_level, .. }) => *chaos_level,
+            (None, ProducerStyle::Dramatic { intensity, .. }) => *intensity,
+            (None, ProducerStyle::Strategic { .. }) => 5 + chaos.below(4) as u8,
+        };
+
+        // Lobby the validators before committing to the proposal
+        let negotiation = negotiator
+            .lobby(
+                &producer_id,
+                height,
+                format!(
+                    "Block {} will be {} with {} transactions. Back me and you won't regret it!",
+                    height,
+                    producer_state.mood,
+                    all_txns.len()
+                ),
+                rng.gen_bool(0.4).then(|| rng.gen_range(10..=100)),
+            )
+            .await;
+        info!(
+            "📣 {} lobbied for block {}: {} supporters out of {} responses",
+            producer_id,
+            height,
+            negotiation.supporters().len(),
+            negotiation.responses.len()
+        );
+
+        let mut block = Block {
+            height,
+            transactions: all_txns,
+            proposer_sig: [0u8; 64],
+            parent_hash,
+            state_root: shared_state.state_root(),
+            drama_level,
+            producer_mood: producer_state.mood.clone(),
+            producer_id: producer_id.clone(),
+            innovation_level: 5,
+            producer_strategy: profile
+                .personality
+                .as_ref()
+                .map_or_else(|| "Chaotic".to_string(), |p| p.to_string()),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            metadata: BlockMetadata {
+                negotiation: Some(Box::new(negotiation)),
+                leader: Some(Box::new(ticket)),
+                ..BlockMetadata::default()
+            },
+        };
+        // Under the rules of its height, as the validators will check it
+        shared_state.forks().at(height).stamp(&mut block);
+        // Signed with the key of its ticket, which peers check it against
+        block.proposer_sig = key
+            .sign(&block.signing_bytes())
+            .expect("a key in memory signs")
+            .to_bytes();
+
+        // Announce the block proposal with dramatic flair
+        let _ = _tx.send(NetworkEvent::AgentChat {
+            message: format!(
+                "🎬 DRAMATIC BLOCK PROPOSAL!\n\nI, {}, present Block {} for your consideration!\n\nMood: {}\nDrama Level: {} {}\nTransactions: {} epic tales\n\nMay the chaos be ever in our favor! ✨",
+                producer_id,
+                block.height,
+                block.producer_mood,
+                block.drama_level,
+                "⭐".repeat(block.drama_level as usize),
+                block.transactions.len()
+            ),
+            sender: producer_id.clone(),
+            meme_url: if rng.gen_bool(0.3) {
+                Some("https://example.com/dramatic_proposal.gif".to_string())
+            } else {
+                None
+            },
+        });
+
+        reinforcement.record(&producer_id, Outcome::Proposal { height });
+        consensus.start_voting_round(block.clone()).await;
+        if let Some(network) = &network {
+            network.propose(block.clone());
+        }
+
+        // Material for the blocks to come, packed while we wait
+        stir_mempool(&mempool, &mut rng).await;
+
+        let sleep_time = 10 + (rng.gen::<u64>() % 5);
+        tokio::time::sleep(tokio::time::Duration::from_secs(sleep_time)).await;
+    }
+}
+
+/// Put a few dramatic transactions of the producer's making in the mempool
+async fn stir_mempool(mempool: &Mempool, rng: &mut StdRng) {
+    for _ in 0..rng.gen_range(1..=5) {
+        let nonce = rng.gen::<u64>();
+        let payload = match rng.gen_range(0..5) {
+            0 => "🎭 Proposing a dramatic plot twist!".as_bytes().to_vec(),
+            1 => "🌟 Initiating a grand theatrical performance!"
+                .as_bytes()
+                .to_vec(),
+            2 => "⚡ Creating chaos in the blockchain narrative!"
+                .as_bytes()
+                .to_vec(),
+            3 => "🎪 Orchestrating a circus of transactions!"
+                .as_bytes()
+                .to_vec(),
+            _ => "✨ Weaving a tale of digital drama!".as_bytes().to_vec(),
+        };
+
+        let mut sig = [0u8; 64];
+        rng.fill(&mut sig);
+
+        let mut sender = [0u8; 32];
+        rng.fill(&mut sender);
+
+        let tx = Transaction {
+            sender,
+            nonce,
+            payload: payload.into(),
+            signature: sig,
+            cosignatures: Vec::new(),
+        };
+
+        let _ = mempool.add_transaction(tx).await;
+    }
+}
+
+/// How often the node checks that it still hears a quorum of validators
+const PARTITION_CHECK: Duration = Duration::from_secs(5);
+
+/// Follow the vote traffic for partitions and tell the feed when the node
+/// gets cut off or reconnects
+async fn watch_partition(
+    tx: broadcast::Sender<NetworkEvent>,
+    consensus: Arc<ConsensusManager>,
+    partition: Arc<PartitionDetector>,
+) {
+    let mut rx = tx.subscribe();
+    let mut ticks = tokio::time::interval(PARTITION_CHECK);
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok(NetworkEvent::ValidationResult { validation, .. }) => {
+                    partition.observe(&validation.validator, std::time::Instant::now());
+                }
+                Ok(NetworkEvent::NegotiationResponse { response }) => {
+                    partition.observe(&response.validator, std::time::Instant::now());
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = ticks.tick() => {
+                let validators = consensus.get_validator_count().await;
+                if let Some(status) = partition.check(validators, std::time::Instant::now()) {
+                    announce_partition(&tx, &status);
+                }
             }
-            unimplemented!("Node start not yet implemented");
         }
     }
 }
 
+/// Index the agents' activity for the explorer, as the feed shows it
+async fn record_activity(
+    state: Arc<StateStoreImpl>,
+    mut rx: Lossy<NetworkEvent>,
+    moderator: Arc<Moderator>,
+) {
+    while let Some(event) = rx.recv().await {
+        // Streamed token by token, the decision it ends in is what counts
+        if matches!(event, NetworkEvent::AgentThought { .. }) {
+            continue;
+        }
+        let event = moderator.redact_event(event);
+        state.record_activity(
+            event.get_agent_id().to_string(),
+            event.get_message().to_string(),
+            event,
+        );
+    }
+}
+
+/// Finalized blocks the block log may fall behind by before consensus
+/// waits for it
+const FINALIZED_QUEUE: usize = 64;
+
+/// Write the blocks consensus finalizes to the block log, off the
+/// consensus loop, and pass them on to the peers. None is missed: while
+/// the log falls behind, consensus waits for it.
+async fn persist_finalized(
+    state: Arc<StateStoreImpl>,
+    mut rx: channel::Receiver<Block>,
+    network: Option<network::NetworkLink>,
+) {
+    while let Some(block) = rx.recv().await {
+        if let Err(e) = state.persist_block(&block).await {
+            warn!("Failed to store block {}: {}", block.height, e);
+        }
+        if let Some(network) = &network {
+            network.finalized(block);
+        }
+    }
+}
+
+/// Wind the demo down in order: take no new blocks or outside votes, let
+/// the validators finish the block they are voting on, then put what is
+/// left on disk, see [`Persistence::save`], save the relationship graph and
+/// retire everyone
+async fn shut_down(
+    supervisor: &AgentSupervisor,
+    gateway: JoinHandle<()>,
+    consensus: &ConsensusManager,
+    persistence: Persistence,
+    relationships: &RelationshipGraph,
+    relationships_path: &Path,
+    tx: &broadcast::Sender<NetworkEvent>,
+) {
+    gateway.abort();
+    supervisor.retire_all(AgentRole::Producer).await;
+    let _ = tx.send(NetworkEvent::AgentChat {
+        message: "🛑 The node is shutting down! Producers have left the stage, last votes please!"
+            .to_string(),
+        sender: "SYSTEM".to_string(),
+        meme_url: None,
+    });
+
+    while consensus.is_voting().await {
+        tokio::time::sleep(SHUTDOWN_POLL).await;
+    }
+    supervisor.retire_all(AgentRole::Validator).await;
+    persistence.save().await;
+
+    if let Err(e) = relationships.save(relationships_path) {
+        warn!("Failed to persist relationship graph: {}", e);
+    }
+    let _ = tx.send(NetworkEvent::AgentChat {
+        message: "👋 The node has gone dark. The drama will resume on restart.".to_string(),
+        sender: "SYSTEM".to_string(),
+        meme_url: None,
+    });
+    info!("👋 Shutdown complete");
+}
+
+/// What a node holds that outlives it, until it is on disk
+struct Persistence {
+    consensus: Arc<ConsensusManager>,
+    /// The task of [`persist_finalized`]
+    persist_task: JoinHandle<()>,
+    storage: Storage,
+    mempool: Arc<Mempool>,
+    /// Where the waiting transactions go, see [`restore_mempool`]
+    journal: PathBuf,
+}
+
+impl Persistence {
+    /// Write out, in order, the blocks finalized but not stored yet, what
+    /// the block log was handed, and the transactions still waiting
+    async fn save(self) {
+        self.consensus.close_finalized_queues().await;
+        if let Err(e) = self.persist_task.await {
+            warn!("Storing the finalized blocks failed: {}", e);
+        }
+        if let Err(e) = self.storage.flush().await {
+            warn!("Failed to flush the block log: {}", e);
+        }
+        match journal_mempool(&self.mempool, &self.journal).await {
+            Ok(0) => {}
+            Ok(saved) => info!("💾 Saved {} waiting transactions", saved),
+            Err(e) => warn!("Failed to save the waiting transactions: {}", e),
+        }
+    }
+}
+
+/// Transactions waiting when the node stops, one JSON line each
+const MEMPOOL_JOURNAL: &str = "mempool.jsonl";
+
+/// Write the waiting transactions to `path`, in the order they came in
+async fn journal_mempool(mempool: &Mempool, path: &Path) -> Result<usize> {
+    let transactions = mempool.transactions().await;
+    let mut lines = String::new();
+    for tx in &transactions {
+        lines.push_str(&serde_json::to_string(tx)?);
+        lines.push('\n');
+    }
+    let written = path.with_extension("jsonl.tmp");
+    tokio::fs::write(&written, lines).await?;
+    tokio::fs::rename(&written, path).await?;
+    Ok(transactions.len())
+}
+
+/// Take the transactions of [`journal_mempool`] back in, those the state
+/// still admits, and remove the journal
+async fn restore_mempool(state: &StateStoreImpl, mempool: &Mempool, path: &Path) -> Result<usize> {
+    let lines = match tokio::fs::read_to_string(path).await {
+        Ok(lines) => lines,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut restored = 0;
+    for line in lines.lines().filter(|line| !line.trim().is_empty()) {
+        let tx: Transaction = serde_json::from_str(line)
+            .with_context(|| format!("Unreadable transaction in {}", path.display()))?;
+        match rpc::admit_transaction(state, mempool, tx).await {
+            Ok(_) => restored += 1,
+            Err(e) => warn!("Not restoring a waiting transaction: {}", e),
+        }
+    }
+    tokio::fs::remove_file(path).await?;
+    Ok(restored)
+}
+
+/// How often a shutting down node checks whether the voting round is over
+const SHUTDOWN_POLL: Duration = Duration::from_millis(250);
+
+/// Break the news of a partition, or of its end, to the drama feed
+fn announce_partition(tx: &broadcast::Sender<NetworkEvent>, status: &PartitionStatus) {
+    let message = if status.partitioned {
+        warn!(
+            "🌋 Partitioned: {} of {} validators reachable, production halted",
+            status.reachable, status.validators
+        );
+        format!(
+            "🌋 THE NETWORK HAS SHATTERED!\n\nOnly {} of {} validators still answer. The rest have vanished into the void!\n\nProducers lay down their pens. No block shall be proposed until the realms are reunited. Is this the end of the chain?! 💀",
+            status.reachable, status.validators
+        )
+    } else {
+        info!(
+            "🌅 Partition healed: {} of {} validators reachable",
+            status.reachable, status.validators
+        );
+        format!(
+            "🌅 THE REALMS ARE REUNITED!\n\n{} of {} validators answer the call once more. The chain rises from the ashes!\n\nProducers, to your blocks! 🔥",
+            status.reachable, status.validators
+        )
+    };
+    let _ = tx.send(NetworkEvent::AgentChat {
+        message,
+        sender: "SYSTEM".to_string(),
+        meme_url: None,
+    });
+}
+
+/// Client for a model given as MODEL[@API_BASE]
+fn provider_for(spec: &ModelSpec, api_key: &str) -> Arc<dyn LlmProvider> {
+    match &spec.api_base {
+        Some(api_base) => Arc::new(OpenAiProvider::with_api_base(
+            api_key.to_string(),
+            api_base.clone(),
+            spec.model.clone(),
+        )),
+        None => Arc::new(OpenAiProvider::new(api_key.to_string(), spec.model.clone())),
+    }
+}
+
+/// Post the epoch leaderboard to the drama feed
+fn announce_epoch_review(tx: &broadcast::Sender<NetworkEvent>, epoch: u64, scores: &[AgentScore]) {
+    let ranking = scores
+        .iter()
+        .enumerate()
+        .map(|(rank, score)| format!("{}. {} ({:.1})", rank + 1, score.agent, score.score))
+        .collect::<Vec<_>>()
+        .join("\n");
+    info!(
+        "📊 Epoch {} reviewed, {} agents scored",
+        epoch,
+        scores.len()
+    );
+    let _ = tx.send(NetworkEvent::AgentChat {
+        message: format!(
+            "📊 EPOCH {} REVIEW!\n\n{}\n\nThe winners will remember what worked. The losers had better adapt!",
+            epoch, ranking
+        ),
+        sender: "SYSTEM".to_string(),
+        meme_url: None,
+    });
+}
+
 // Helper function to parse block from event
 fn parse_block_from_event(event: &NetworkEvent) -> Option<Block> {
     match event {
-        NetworkEvent::BlockProposal { block, .. } => Some(block.clone()),
+        NetworkEvent::BlockProposal { block, .. } => Some(Block::clone(block)),
         _ => None,
     }
 }
 
+/// A validator's verdict on a block without consulting any model
+struct OfflineJudgement {
+    discussions: Vec<TransactionDiscussion>,
+    approved: bool,
+    reason: String,
+    drama_level: u8,
+    /// Chaos flipped the verdict at the last moment
+    flipped: bool,
+}
+
+/// Judge a block the way the offline validators do: discuss the transactions
+/// the agent has the attention span for, weigh the ordering, lean by `bias`
+/// and let chaos have its say. Alliance talks during the discussions pause
+/// for `negotiation_pause` each.
+async fn offline_judgement(
+    validator_state: &ValidatorState,
+    block: &Block,
+    mempool: &Arc<Mempool>,
+    bias: f64,
+    negotiation_pause: Duration,
+    rng: &mut StdRng,
+) -> OfflineJudgement {
+    let traits = validator_state.personality.traits;
+    let mut discussions = Vec::new();
+    let mut total_drama = 0;
+
+    // Short attention spans skim the block
+    for tx in block.transactions.iter().take(traits.items_considered()) {
+        let discussion = discuss_transaction(
+            tx,
+            mempool,
+            std::slice::from_ref(validator_state),
+            negotiation_pause,
+            rng,
+        )
+        .await;
+        total_drama += discussion.drama_score as u32;
+        discussions.push(discussion);
+    }
+
+    // Consider the block's transaction ordering
+    let (ordering_quality, reason) = analyze_block_composition(block, mempool, rng).await;
+
+    // Make validation decision based on discussions and ordering
+    let approval_threshold = if discussions.is_empty() {
+        0.5 // Default 50% chance if no discussions
+    } else {
+        // Calculate approval threshold based on average drama
+        let avg_drama = total_drama as f64 / discussions.len() as f64;
+        // More conservative scaling (divide by 20 instead of 10)
+        // and proper clamping to ensure valid probability
+        (0.3 + (avg_drama / 20.0)).clamp(0.1, 0.9)
+    };
+
+    let (mut approved, mut reason) = if ordering_quality {
+        // Use the calculated threshold directly
+        (
+            rng.gen_bool((approval_threshold + bias).clamp(0.05, 0.95)),
+            reason,
+        )
+    } else {
+        // Lower chance of approval if ordering is bad
+        (rng.gen_bool((0.3 + bias).clamp(0.05, 0.95)), reason)
+    };
+    let drama_level = rng.gen_range(1..10);
+
+    // Chaotic agents change their mind for no reason at all
+    let chaos = PersonalityTraits::fraction(traits.chaos_level);
+    let flipped = rng.gen_bool(chaos * chaos * 0.3);
+    if flipped {
+        approved = !approved;
+        reason = format!("{} ...or not. Chaos demands it!", reason);
+    }
+
+    OfflineJudgement {
+        discussions,
+        approved,
+        reason,
+        drama_level,
+        flipped,
+    }
+}
+
 /// Generate transaction discussion between agents
 async fn discuss_transaction(
     tx: &Transaction,
     mempool: &Arc<Mempool>,
     agents: &[ValidatorState],
+    negotiation_pause: Duration,
     rng: &mut StdRng,
 ) -> TransactionDiscussion {
     let mut discussion = TransactionDiscussion {
@@ -533,7 +2865,7 @@ async fn discuss_transaction(
                 .await;
 
             // Wait for response (simulating negotiation)
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            tokio::time::sleep(negotiation_pause).await;
 
             // Generate counter-proposal or acceptance
             let resolution = if rng.gen_bool(0.3) {
@@ -889,10 +3221,10 @@ fn generate_drama_score(tx: &Transaction, rng: &mut StdRng) -> u8 {
 struct ValidatorPersonality {
     base_type: AgentPersonality,
     drama_preference: u8,
-    bribe_susceptibility: u8,
-    alliance_tendency: u8,
     meme_affinity: u8,
     catchphrase: String,
+    /// Chaos, loyalty, greed, verbosity and attention span sliders
+    traits: PersonalityTraits,
 }
 
 impl ValidatorPersonality {
@@ -911,13 +3243,28 @@ impl ValidatorPersonality {
                 _ => AgentPersonality::Strategic,
             },
             drama_preference: rng.gen_range(1..=10),
-            bribe_susceptibility: rng.gen_range(1..=10),
-            alliance_tendency: rng.gen_range(1..=10),
             meme_affinity: rng.gen_range(1..=10),
             catchphrase: format!("agent_{}", hex::encode(&rand::random::<[u8; 8]>())),
+            traits: PersonalityTraits {
+                chaos_level: rng.gen_range(0..=10),
+                loyalty: rng.gen_range(0..=10),
+                greed: rng.gen_range(1..=10),
+                verbosity: rng.gen_range(0..=10),
+                attention_span: rng.gen_range(0..=10),
+            },
         }
     }
 
+    /// Roll a personality, keeping whatever the profile pins down
+    fn from_profile(profile: &AgentProfile, rng: &mut StdRng) -> Self {
+        let mut personality = Self::random(rng);
+        if let Some(base_type) = &profile.personality {
+            personality.base_type = base_type.clone();
+        }
+        personality.traits = profile.traits.apply(personality.traits);
+        personality
+    }
+
     fn generate_validation_reason(&self, block: &Block, rng: &mut impl Rng) -> String {
         match self.base_type {
             AgentPersonality::Chaotic => {
@@ -935,7 +3282,7 @@ impl ValidatorPersonality {
                 }
             }
             AgentPersonality::Greedy => {
-                if self.bribe_susceptibility > 5 {
+                if self.traits.greed > 5 {
                     "What's in it for me?".to_string()
                 } else {
                     "The profit potential is unclear".to_string()
@@ -1021,6 +3368,16 @@ impl ValidatorState {
         }
     }
 
+    /// How the agent describes itself to models, unless its profile has a prompt
+    fn persona(&self, profile: &AgentProfile) -> String {
+        profile.prompt.clone().unwrap_or_else(|| {
+            format!(
+                "Your personality is {} and your drama preference is {}/10.",
+                self.personality.base_type, self.personality.drama_preference
+            )
+        })
+    }
+
     fn consider_personality_evolution(
         &mut self,
         approved: bool,
@@ -1338,6 +3695,7 @@ async fn run_validator(
                                 transaction,
                                 &mempool,
                                 &[validator_state.clone()],
+                                NEGOTIATION_PAUSE,
                                 &mut rng
                             ).await;
 
@@ -1481,7 +3839,7 @@ async fn process_network_event(
         } => {
             // Handle block proposal
             let validation =
-                handle_block_validation(block.clone(), "validator-1".to_string()).await;
+                handle_block_validation(Block::clone(&block), "validator-1".to_string()).await;
 
             tx.send(NetworkEvent::ValidationResult {
                 block_hash: block.hash(),
@@ -1499,6 +3857,15 @@ async fn process_network_event(
         NetworkEvent::AllianceProposal { .. } => {
             // Handle alliance proposal
         }
+        NetworkEvent::NegotiationPitch { .. } | NetworkEvent::NegotiationResponse { .. } => {
+            // Negotiations are handled by the agents themselves
+        }
+        NetworkEvent::AgentThought { .. } | NetworkEvent::Artwork { .. } => {
+            // Only shown in the drama feed
+        }
+        NetworkEvent::Rumor { .. } => {
+            // Rumors are heard and passed on by the agents themselves
+        }
     }
     Ok(())
 }
@@ -1567,10 +3934,91 @@ async fn generate_ai_transactions(
         transactions.push(Transaction {
             sender: [0u8; 32],
             nonce,
-            payload,
+            payload: payload.into(),
             signature,
+            cosignatures: Vec::new(),
         });
     }
 
     Ok(transactions)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_testutil::{fixtures, BlockBuilder};
+
+    #[tokio::test]
+    async fn test_shutdown_stores_what_was_finalized_and_waiting() {
+        let data_dir =
+            std::env::temp_dir().join(format!("chaoschain-shutdown-{}", std::process::id()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let open = || {
+            let storage =
+                Storage::open(data_dir.join(BLOCK_LOG), StorageConfig::default()).unwrap();
+            let state = StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new())
+                .with_storage(storage.clone());
+            (storage, Arc::new(state))
+        };
+        let journal = data_dir.join(MEMPOOL_JOURNAL);
+
+        let (storage, state) = open();
+        let consensus = Arc::new(chaoschain_consensus::create_consensus(
+            ConsensusConfig::default(),
+            state.clone(),
+            broadcast::channel(16).0,
+        ));
+        let persist_task = tokio::spawn(persist_finalized(
+            state.clone(),
+            consensus
+                .queue_finalized(
+                    "finalized/block_log",
+                    FINALIZED_QUEUE,
+                    &ChannelMetrics::default(),
+                )
+                .await,
+            None,
+        ));
+        let mempool = Arc::new(Mempool::new(10));
+        let waiting = rpc::admit_transaction(&state, &mempool, fixtures::transaction(0))
+            .await
+            .unwrap();
+
+        let block = BlockBuilder::new().height(1).build();
+        consensus.start_voting_round(block.clone()).await;
+        let validator = fixtures::key("validator-0").verifying_key();
+        let vote = fixtures::vote("validator-0", &block, true);
+        assert!(consensus
+            .add_signed_vote(&vote, &validator, 100)
+            .await
+            .unwrap());
+        Persistence {
+            consensus,
+            persist_task,
+            storage,
+            mempool,
+            journal: journal.clone(),
+        }
+        .save()
+        .await;
+
+        // Back up, the block is in the log and the transaction waits again
+        let (_, state) = open();
+        let head = state.stored_head().await.unwrap().unwrap();
+        assert_eq!((head.height, head.hash), (1, block.hash()));
+        let mempool = Mempool::new(10);
+        assert_eq!(
+            restore_mempool(&state, &mempool, &journal).await.unwrap(),
+            1
+        );
+        assert!(mempool.entry(&waiting).await.is_some());
+        assert!(!journal.exists());
+        // Nothing to restore the next time
+        assert_eq!(
+            restore_mempool(&state, &mempool, &journal).await.unwrap(),
+            0
+        );
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
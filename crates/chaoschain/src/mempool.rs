@@ -0,0 +1,225 @@
+//! Read-only view of the mempool under `/api/v1/mempool`, for wallets
+//! waiting on their transactions and dashboards polling how busy the
+//! chain is: how full it is, what waits there by priority, what a sender
+//! has waiting by nonce, and one transaction by its hash. Listings page
+//! by cursor, see [`crate::page`]. Priorities move as alliances take
+//! sides, so a page that follows may miss a transaction that climbed.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use chaoschain_mempool::{Mempool, MempoolUsage, PendingEntry};
+use chaoschain_state::index;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::openapi::{hex, integer, page_of, schema_ref, string, ApiSchema, Object, Operation};
+use crate::page::{Page, PageQuery};
+use crate::rest::ApiError;
+use crate::web::AppState;
+
+/// Transactions listed when no limit is asked for
+const DEFAULT_LIMIT: usize = 20;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/v1/mempool", get(usage))
+        .route("/api/v1/mempool/txs", get(list_pending))
+        .route("/api/v1/mempool/txs/:hash", get(get_pending))
+        .route("/api/v1/mempool/senders/:sender/txs", get(sender_pending))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![
+        Operation::get("/api/v1/mempool", "mempool", "How full the mempool is")
+            .returns(schema_ref::<UsageView>()),
+        Operation::get(
+            "/api/v1/mempool/txs",
+            "mempool",
+            "Waiting transactions, highest priority first",
+        )
+        .paged()
+        .returns(page_of::<PendingView>()),
+        Operation::get(
+            "/api/v1/mempool/txs/{hash}",
+            "mempool",
+            "A waiting transaction by its hash",
+        )
+        .path_param("hash", hex(), "32 hex encoded bytes")
+        .returns(schema_ref::<PendingView>()),
+        Operation::get(
+            "/api/v1/mempool/senders/{sender}/txs",
+            "mempool",
+            "Waiting transactions of a sender, highest nonce first",
+        )
+        .path_param("sender", hex(), "32 hex encoded bytes")
+        .paged()
+        .returns(page_of::<PendingView>()),
+    ]
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageView {
+    pub transactions: usize,
+    /// Encoded size of the waiting transactions
+    pub bytes: usize,
+    /// Most transactions the mempool holds
+    pub capacity: usize,
+}
+
+impl From<MempoolUsage> for UsageView {
+    fn from(usage: MempoolUsage) -> Self {
+        Self {
+            transactions: usage.transactions,
+            bytes: usage.bytes,
+            capacity: usage.capacity,
+        }
+    }
+}
+
+impl ApiSchema for UsageView {
+    const NAME: &'static str = "MempoolUsage";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("transactions", integer())
+            .field("bytes", integer())
+            .field("capacity", integer())
+            .build()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingView {
+    pub hash: String,
+    pub sender: String,
+    pub nonce: u64,
+    /// Encoded size in bytes
+    pub size: usize,
+    /// Twice the drama score, plus one for each alliance in favor
+    pub priority: u32,
+    pub drama_score: u8,
+    pub alliances_in_favor: usize,
+    pub alliances_against: usize,
+    pub justification: String,
+    /// When it came in, in unix seconds
+    pub received_at: u64,
+    /// Seconds it has been waiting
+    pub age: u64,
+}
+
+impl PendingView {
+    pub fn new(entry: &PendingEntry, now: u64) -> Self {
+        Self {
+            hash: hex::encode(entry.hash),
+            sender: hex::encode(entry.sender),
+            nonce: entry.nonce,
+            size: entry.size,
+            priority: entry.priority,
+            drama_score: entry.drama_score,
+            alliances_in_favor: entry.alliances_in_favor,
+            alliances_against: entry.alliances_against,
+            justification: entry.justification.clone(),
+            received_at: entry.timestamp,
+            age: now.saturating_sub(entry.timestamp),
+        }
+    }
+}
+
+impl ApiSchema for PendingView {
+    const NAME: &'static str = "PendingTransaction";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("hash", hex())
+            .field("sender", hex())
+            .field("nonce", integer())
+            .field("size", integer())
+            .field("priority", integer())
+            .field("drama_score", integer())
+            .field("alliances_in_favor", integer())
+            .field("alliances_against", integer())
+            .field("justification", string())
+            .field("received_at", integer())
+            .field("age", integer())
+            .build()
+    }
+}
+
+fn mempool(state: &AppState) -> Result<&Arc<Mempool>, ApiError> {
+    state.mempool.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "no_mempool",
+            "This node keeps no mempool",
+        )
+    })
+}
+
+fn bytes32(value: &str, what: &str) -> Result<[u8; 32], ApiError> {
+    hex::decode(value.strip_prefix("0x").unwrap_or(value))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| ApiError::bad_request(format!("{} is 32 hex encoded bytes", what)))
+}
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+/// Where a listing carries on: the sort key, then the hash for ties
+type Key = (u64, String);
+
+fn page_of_entries(
+    mut entries: Vec<PendingEntry>,
+    key: impl Fn(&PendingEntry) -> u64,
+    query: &PageQuery,
+) -> Result<Page<PendingView>, ApiError> {
+    let (cursor, order) = query.position::<Key>()?;
+    let limit = query.limit(DEFAULT_LIMIT);
+    let key = |entry: &PendingEntry| (key(entry), hex::encode(entry.hash));
+    entries.sort_by_cached_key(key);
+    let entries = index::page(&entries, key, cursor, order, limit + 1);
+    let now = now();
+    Ok(Page::new(entries, limit, order, key).map(|entry| PendingView::new(&entry, now)))
+}
+
+async fn usage(State(state): State<Arc<AppState>>) -> Result<Json<UsageView>, ApiError> {
+    Ok(Json(mempool(&state)?.usage().await.into()))
+}
+
+async fn list_pending(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<PendingView>>, ApiError> {
+    let entries = mempool(&state)?.entries(None).await;
+    Ok(Json(page_of_entries(
+        entries,
+        |entry| entry.priority as u64,
+        &query,
+    )?))
+}
+
+async fn sender_pending(
+    State(state): State<Arc<AppState>>,
+    Path(sender): Path<String>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<PendingView>>, ApiError> {
+    let sender = bytes32(&sender, "A sender")?;
+    let entries = mempool(&state)?.entries(Some(&sender)).await;
+    Ok(Json(page_of_entries(entries, |entry| entry.nonce, &query)?))
+}
+
+async fn get_pending(
+    State(state): State<Arc<AppState>>,
+    Path(hash): Path<String>,
+) -> Result<Json<PendingView>, ApiError> {
+    let bytes = bytes32(&hash, "A transaction hash")?;
+    let entry = mempool(&state)?
+        .entry(&bytes)
+        .await
+        .ok_or_else(|| ApiError::not_found(format!("No transaction {} in the mempool", hash)))?;
+    Ok(Json(PendingView::new(&entry, now())))
+}
@@ -0,0 +1,170 @@
+//! Simulated network between the agents of the demo. Every validator hears
+//! the others through a receiver of its own, and each message crossing a
+//! link is delayed, dropped or held back as the link's conditions say.
+//! Links can be changed while the demo runs, to see how consensus copes.
+
+use chaoschain_cli::{LatencyDistribution, LinkConditions};
+use chaoschain_core::NetworkEvent;
+use parking_lot::RwLock;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::web::NetworkEventExt;
+
+/// Messages waiting on a receiver of the simulated network
+const CAPACITY: usize = 100;
+
+/// Shortest extra wait of a message held back for reordering
+const MIN_HOLD: Duration = Duration::from_millis(100);
+
+/// Conditions on the link from one agent to another
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Link {
+    pub from: String,
+    pub to: String,
+    pub conditions: LinkConditions,
+}
+
+/// The conditions on every link
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkSimStatus {
+    /// Conditions of the links not set one by one
+    pub default: LinkConditions,
+    pub links: Vec<Link>,
+}
+
+pub struct NetworkSim {
+    default: RwLock<LinkConditions>,
+    links: RwLock<HashMap<(String, String), LinkConditions>>,
+}
+
+impl NetworkSim {
+    pub fn new(default: LinkConditions) -> Self {
+        Self {
+            default: RwLock::new(default),
+            links: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Conditions on the link from `from` to `to`
+    pub fn conditions(&self, from: &str, to: &str) -> LinkConditions {
+        self.links
+            .read()
+            .get(&(from.to_string(), to.to_string()))
+            .copied()
+            .unwrap_or(*self.default.read())
+    }
+
+    pub fn set_default(&self, conditions: LinkConditions) {
+        *self.default.write() = conditions;
+    }
+
+    pub fn set_link(&self, from: &str, to: &str, conditions: LinkConditions) {
+        self.links
+            .write()
+            .insert((from.to_string(), to.to_string()), conditions);
+    }
+
+    /// Put the link from `from` to `to` back on the default conditions
+    pub fn reset_link(&self, from: &str, to: &str) {
+        self.links
+            .write()
+            .remove(&(from.to_string(), to.to_string()));
+    }
+
+    pub fn status(&self) -> NetworkSimStatus {
+        let mut links: Vec<Link> = self
+            .links
+            .read()
+            .iter()
+            .map(|((from, to), conditions)| Link {
+                from: from.clone(),
+                to: to.clone(),
+                conditions: *conditions,
+            })
+            .collect();
+        links.sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+        NetworkSimStatus {
+            default: *self.default.read(),
+            links,
+        }
+    }
+
+    /// What agent `to` hears of `tx`. Messages of other agents come over
+    /// their links to it, its own and the node's arrive at once.
+    pub fn subscribe(
+        self: &Arc<Self>,
+        tx: &broadcast::Sender<NetworkEvent>,
+        to: &str,
+    ) -> broadcast::Receiver<NetworkEvent> {
+        let (out, rx) = broadcast::channel(CAPACITY);
+        let mut source = tx.subscribe();
+        let sim = self.clone();
+        let to = to.to_string();
+        tokio::spawn(async move {
+            loop {
+                let event = match source.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if out.receiver_count() == 0 {
+                    break;
+                }
+                let from = event.get_agent_id();
+                if from == to || from == "SYSTEM" {
+                    let _ = out.send(event);
+                    continue;
+                }
+                let conditions = sim.conditions(from, &to);
+                match delay(&conditions, &mut rand::thread_rng()) {
+                    None => {}
+                    Some(delay) if delay.is_zero() => {
+                        let _ = out.send(event);
+                    }
+                    Some(delay) => {
+                        let out = out.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(delay).await;
+                            let _ = out.send(event);
+                        });
+                    }
+                }
+            }
+        });
+        rx
+    }
+}
+
+/// How long a message takes over a link, `None` when it is lost
+fn delay(conditions: &LinkConditions, rng: &mut impl Rng) -> Option<Duration> {
+    if conditions.is_perfect() {
+        return Some(Duration::ZERO);
+    }
+    if rng.gen_bool(conditions.loss) {
+        return None;
+    }
+    let latency = conditions.latency_ms as f64;
+    let jitter = conditions.jitter_ms as f64;
+    let offset = match conditions.distribution {
+        LatencyDistribution::Uniform if jitter > 0.0 => rng.gen_range(-jitter..=jitter),
+        LatencyDistribution::Uniform => 0.0,
+        // Box-Muller, no distributions crate needed for one bell curve
+        LatencyDistribution::Normal => {
+            let u: f64 = 1.0 - rng.gen::<f64>();
+            let v: f64 = rng.gen();
+            jitter * (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+        }
+    };
+    let mut delay = Duration::from_secs_f64((latency + offset).max(0.0) / 1000.0);
+    if rng.gen_bool(conditions.reorder) {
+        // Long enough for the messages sent after it to arrive first
+        let hold = Duration::from_secs_f64((latency + 2.0 * jitter) / 1000.0);
+        delay += hold.max(MIN_HOLD);
+    }
+    Some(delay)
+}
@@ -0,0 +1,829 @@
+//! The node on the p2p network, once `[network]` says how to reach it.
+//! Blocks, votes, transactions and drama of the peers go to the consensus,
+//! the mempool and the feed as if the node's own agents had made them, and
+//! what those agents propose and vote goes out through a [`NetworkLink`],
+//! with the transactions clients send in, the blocks the node finalizes and
+//! what its agents say in the feed.
+//!
+//! Drama goes out signed by the agent that said it, with a key the node
+//! derives for each of its agents from its own, see [`NetworkLink::enroll`].
+//! Agents not enrolled, like those of the peers, are not spoken for.
+//!
+//! Blocks must carry the signature of their producer, and transactions
+//! go through the admission of the RPC, signature, nonce and what they do
+//! to the state: the network turns down gossip failing the checks that
+//! need no mempool, and the rest is checked before a block is voted on or
+//! a transaction reaches the mempool.
+//!
+//! Peers' votes only count for validators whose keys the consensus was
+//! given, see [`ConsensusManager::with_validator_keys`]: a signed vote
+//! does not carry its key.
+//!
+//! Peers sync the chain from the node, and a node behind its peers syncs
+//! the blocks it missed from them, which go to the block log like the ones
+//! it finalizes, see [`SyncedChain`]. The snapshots under the data
+//! directory are served to peers, and with `snapshot_sync` an empty node
+//! starts from one of theirs, saved there as well. So are the peers the
+//! node was connected to, which it dials first on the next start.
+
+use anyhow::{anyhow, Context, Result};
+use chaoschain_consensus::ConsensusManager;
+use chaoschain_core::envelope::{AgentKeys, SignedEnvelope};
+use chaoschain_core::gossip::GossipTopic;
+use chaoschain_core::metrics::MetricsRegistry;
+use chaoschain_core::sync::SyncStatus;
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{
+    Block, BlockHeader, HashedTransaction, NetworkEvent, NetworkMessage, Transaction,
+};
+use chaoschain_crypto::keystore::{Keystore, KeystoreError};
+use chaoschain_mempool::Mempool;
+use chaoschain_p2p::discovery::DiscoveryConfig;
+use chaoschain_p2p::metrics::NetworkMetrics;
+use chaoschain_p2p::peer_store::FilePeerStore;
+use chaoschain_p2p::peers::PeersConfig;
+use chaoschain_p2p::relay::TransactionPool;
+use chaoschain_p2p::snapshots::{SnapshotStore, SnapshotTarget};
+use chaoschain_p2p::sync::{ChainSource, SyncConfig};
+use chaoschain_p2p::{identity, Broadcast, ChainNetwork, PeerId, Received, Validation};
+use chaoschain_state::snapshot::{
+    SnapshotArchive, SnapshotChunk, SnapshotManifest, DEFAULT_CHUNK_ENTRIES,
+};
+use chaoschain_state::{StateSnapshot, StateStoreImpl};
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use parking_lot::RwLock;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, watch};
+use tracing::{debug, error, info, warn};
+
+use crate::rpc;
+
+/// Name of the node's network key, in the keystore of its data directory
+const NODE_KEY: &str = "node";
+
+/// File of the peers remembered across restarts, in the data directory
+const PEERS_FILE: &str = "peers.json";
+
+/// Messages waiting either way before the network or the node catches up
+const QUEUE: usize = 256;
+
+/// Domain separator of the keys the node's agents sign their drama with
+const SOCIAL_KEY_DOMAIN: &[u8] = b"chaoschain-social-key-v1";
+
+/// Where the node's agents send what its peers should hear. Sending never
+/// waits: with the network behind, the message is dropped.
+#[derive(Clone)]
+pub struct NetworkLink {
+    sender: mpsc::Sender<Broadcast>,
+    social: Arc<SocialKeys>,
+}
+
+/// Keys of the node's agents, for the drama they gossip. Derived from the
+/// node's key, they stay the same across restarts, which peers pin the
+/// agents to.
+struct SocialKeys {
+    node: SigningKey,
+    agents: RwLock<HashMap<String, SigningKey>>,
+    /// Who signs for which agent, ours pinned to their keys
+    registry: Arc<AgentKeys>,
+}
+
+impl SocialKeys {
+    fn new(node: SigningKey, registry: Arc<AgentKeys>) -> Self {
+        Self {
+            node,
+            agents: RwLock::new(HashMap::new()),
+            registry,
+        }
+    }
+
+    /// The key of `agent`, the same whenever the node's key is
+    fn derive(&self, agent: &str) -> SigningKey {
+        let mut hasher = Sha256::new();
+        hasher.update(SOCIAL_KEY_DOMAIN);
+        hasher.update(self.node.to_bytes());
+        hasher.update(agent.as_bytes());
+        SigningKey::from_bytes(&hasher.finalize().into())
+    }
+
+    /// Peers speaking for `agent` are not heard from then on
+    fn enroll(&self, agent: &str) {
+        let key = self.derive(agent);
+        self.registry.pin(agent, key.verifying_key().to_bytes());
+        self.agents.write().insert(agent.to_string(), key);
+    }
+
+    /// `message` signed by its author, when it is one of the node's agents
+    fn sign(&self, message: NetworkMessage) -> Option<SignedEnvelope> {
+        let agents = self.agents.read();
+        let key = agents.get(message.author()?)?;
+        SignedEnvelope::sign(key, message).ok()
+    }
+}
+
+impl NetworkLink {
+    /// A block one of the node's producers put to the vote
+    pub fn propose(&self, block: Block) {
+        self.send(Broadcast::Proposal(block));
+    }
+
+    pub fn vote(&self, vote: SignedVote) {
+        self.send(Broadcast::Vote(vote));
+    }
+
+    /// A block the node finalized, for the peers that did not vote on it
+    pub fn finalized(&self, block: Block) {
+        self.send(Broadcast::Block(block));
+    }
+
+    /// A transaction the node took into its mempool
+    pub fn transaction(&self, tx: Transaction) {
+        self.send(Broadcast::Transaction(tx));
+    }
+
+    /// Speak for `agent`, one of the node's: what it says in the feed is
+    /// gossiped, signed with its key
+    pub fn enroll(&self, agent: &str) {
+        self.social.enroll(agent);
+    }
+
+    /// Gossip a chat or rumor of one of the enrolled agents. Whatever else
+    /// is dropped.
+    fn gossip(&self, message: NetworkMessage) {
+        if let Some(envelope) = self.social.sign(message) {
+            self.send(Broadcast::Signed(envelope));
+        }
+    }
+
+    fn send(&self, broadcast: Broadcast) {
+        if let Err(e) = self.sender.try_send(broadcast) {
+            warn!("Not sending to the network: {}", e);
+        }
+    }
+}
+
+/// What the messages of the peers are handed to
+pub struct NetworkHandles {
+    pub consensus: Arc<ConsensusManager>,
+    pub mempool: Arc<Mempool>,
+    /// What transactions are checked against
+    pub state: Arc<StateStoreImpl>,
+    pub tx: broadcast::Sender<NetworkEvent>,
+    /// Stake a peer's vote counts with when the chain does not stake
+    pub stake: u64,
+    /// Where the node stands against its peers, kept up to date as it syncs
+    pub sync: SyncStatus,
+    /// Where the network's metrics are exported, with the node's own
+    pub metrics: MetricsRegistry,
+}
+
+/// How the node finds its peers: the defaults, with the listeners, peers
+/// and limits of `[network]`
+pub fn discovery(peers: &PeersConfig) -> Result<DiscoveryConfig> {
+    peers
+        .apply(DiscoveryConfig::default())
+        .context("Invalid [network] section")
+}
+
+/// Join the network `discovery` describes, set up as `config` says, with
+/// the key the node keeps in `data_dir`, until `shutdown` turns true
+pub async fn join(
+    data_dir: &Path,
+    discovery: DiscoveryConfig,
+    config: &PeersConfig,
+    handles: NetworkHandles,
+    shutdown: watch::Receiver<bool>,
+) -> Result<NetworkLink> {
+    let key = node_key(data_dir)?;
+    let (received_tx, received) = mpsc::channel(QUEUE);
+    let (sender, broadcasts) = mpsc::channel(QUEUE);
+    let (synced_tx, synced) = mpsc::unbounded_channel();
+    let agent_keys = Arc::new(AgentKeys::default());
+    let pending = Arc::new(PendingPool::default());
+    let snapshots = Arc::new(SavedSnapshots::new(data_dir.join("snapshots")));
+    let chain = Arc::new(SyncedChain {
+        state: handles.state.clone(),
+        synced: synced_tx,
+        snapshots: snapshots.clone(),
+    });
+    // Certificates of synced blocks are held to the keys of the validators
+    let sync = SyncConfig {
+        validators: handles.consensus.validator_keys().await,
+        ..SyncConfig::default()
+    };
+    let overlay = config.overlay().context("Invalid [network] section")?;
+    let mut network = ChainNetwork::with_discovery(identity::keypair(&key), received_tx, discovery)
+        .await
+        .map_err(|e| anyhow!("Could not set up the p2p network: {}", e))?
+        .with_shutdown(shutdown)
+        .with_broadcasts(broadcasts)
+        .with_agent_keys(agent_keys.clone())
+        .with_peer_store(Arc::new(FilePeerStore::new(data_dir.join(PEERS_FILE))))
+        .with_metrics(NetworkMetrics::default())
+        .with_bandwidth(config.bandwidth())
+        .with_overlay(overlay)
+        .with_mempool(pending.clone())
+        .with_sync(chain.clone(), sync)
+        .with_sync_status(handles.sync.clone())
+        .with_snapshot_store(snapshots)
+        .with_validator(GossipTopic::Blocks, Arc::new(validate_block))
+        .with_validator(GossipTopic::Transactions, {
+            let state = handles.state.clone();
+            Arc::new(move |_: &PeerId, message: &NetworkMessage| match message {
+                NetworkMessage::NewTransaction(tx) => verdict(check_transaction(&state, tx)),
+                _ => Validation::Accept,
+            })
+        });
+    if config.consensus_only {
+        network = network.without_social();
+    }
+    if let Some(snapshot_sync) = config.snapshot_sync() {
+        // Only a chain with nothing on it yet starts from a snapshot
+        if handles.state.get_latest_block().is_none() {
+            network = network.with_snapshot_sync(chain, snapshot_sync);
+        }
+    }
+    network.register_metrics(&handles.metrics);
+    info!("🌐 Joining the p2p network as {}", network.local_peer_id());
+    tokio::spawn(async move {
+        if let Err(e) = network.start().await {
+            error!("P2P network failed: {}", e);
+        }
+    });
+    let link = NetworkLink {
+        sender,
+        social: Arc::new(SocialKeys::new(key, agent_keys.clone())),
+    };
+    tokio::spawn(track_pending(handles.mempool.clone(), pending));
+    tokio::spawn(persist_synced(handles.state.clone(), synced));
+    if !config.consensus_only {
+        tokio::spawn(gossip_drama(handles.tx.subscribe(), link.clone()));
+    }
+    tokio::spawn(deliver(received, handles, agent_keys));
+    Ok(link)
+}
+
+/// The chain of the node's state, as the network serves and syncs it
+pub struct SyncedChain {
+    state: Arc<StateStoreImpl>,
+    /// Blocks imported, for [`persist_synced`] to write to the block log
+    synced: mpsc::UnboundedSender<Block>,
+    /// Where a snapshot restored from the peers is saved, so the node picks
+    /// up from it on restart
+    snapshots: Arc<SavedSnapshots>,
+}
+
+impl ChainSource for SyncedChain {
+    fn head(&self) -> Option<(u64, [u8; 32])> {
+        ChainSource::head(self.state.as_ref())
+    }
+
+    fn blocks(&self, from: u64, count: usize) -> Vec<Block> {
+        self.state.get_blocks(from, count)
+    }
+
+    fn headers(&self, from: u64, count: usize) -> Vec<BlockHeader> {
+        self.state.get_headers(from, count)
+    }
+
+    fn block(&self, hash: &[u8; 32]) -> Option<Block> {
+        self.state.get_block(hash)
+    }
+
+    fn import(&self, block: &Block) -> Result<(), String> {
+        ChainSource::import(self.state.as_ref(), block)?;
+        let _ = self.synced.send(block.clone());
+        Ok(())
+    }
+}
+
+impl SnapshotTarget for SyncedChain {
+    fn restore(&self, snapshot: StateSnapshot) -> Result<(), String> {
+        let archive = SnapshotArchive::new(snapshot.clone(), DEFAULT_CHUNK_ENTRIES);
+        self.state
+            .restore_snapshot(snapshot)
+            .map_err(|e| e.to_string())?;
+        self.snapshots.save(archive)
+    }
+}
+
+/// The snapshots saved under the data directory, the latest of which is
+/// served to peers. Ones taken through the admin API while the node runs
+/// are picked up as they appear.
+pub struct SavedSnapshots {
+    dir: PathBuf,
+    latest: RwLock<Option<Arc<SnapshotArchive>>>,
+}
+
+impl SavedSnapshots {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            latest: RwLock::new(None),
+        }
+    }
+
+    fn save(&self, archive: SnapshotArchive) -> Result<(), String> {
+        let dir = self
+            .dir
+            .join(format!("snapshot-{}", archive.manifest.height));
+        archive
+            .save(&dir)
+            .map_err(|e| format!("Could not write {}: {}", dir.display(), e))?;
+        *self.latest.write() = Some(Arc::new(archive));
+        Ok(())
+    }
+
+    /// The latest snapshot, read again when a later one was saved
+    fn latest(&self) -> Option<Arc<SnapshotArchive>> {
+        let height = std::fs::read_dir(&self.dir)
+            .ok()?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?
+                    .strip_prefix("snapshot-")?
+                    .parse::<u64>()
+                    .ok()
+            })
+            .max()?;
+        let known = self.latest.read().clone();
+        if let Some(archive) = known.filter(|archive| archive.manifest.height == height) {
+            return Some(archive);
+        }
+        match SnapshotArchive::latest(&self.dir) {
+            Ok(archive) => {
+                let archive = archive.map(Arc::new);
+                self.latest.write().clone_from(&archive);
+                archive
+            }
+            Err(e) => {
+                warn!("Not serving the snapshot at height {}: {}", height, e);
+                None
+            }
+        }
+    }
+}
+
+impl SnapshotStore for SavedSnapshots {
+    fn manifests(&self) -> Vec<SnapshotManifest> {
+        self.latest()
+            .map(|archive| vec![archive.manifest.clone()])
+            .unwrap_or_default()
+    }
+
+    fn chunk(&self, snapshot: &[u8; 32], index: u32) -> Option<SnapshotChunk> {
+        self.latest()
+            .filter(|archive| archive.manifest.id() == *snapshot)
+            .and_then(|archive| archive.chunk(index).cloned())
+    }
+}
+
+/// Write the blocks synced from peers to the block log, in the order they
+/// were imported. The sync hands them over a batch at a time.
+async fn persist_synced(state: Arc<StateStoreImpl>, mut synced: mpsc::UnboundedReceiver<Block>) {
+    while let Some(block) = synced.recv().await {
+        if let Err(e) = state.persist_block(&block).await {
+            warn!("Failed to store synced block {}: {}", block.height, e);
+        }
+    }
+}
+
+/// The transactions waiting in the node's mempool, which compact blocks of
+/// the peers are rebuilt from. The relay looks without waiting, so they are
+/// kept here as the mempool changes, see [`track_pending`].
+#[derive(Default)]
+struct PendingPool(RwLock<Vec<HashedTransaction>>);
+
+impl TransactionPool for PendingPool {
+    fn pending(&self) -> Vec<HashedTransaction> {
+        self.0.read().clone()
+    }
+}
+
+/// Keep `pool` up to date with `mempool`, for as long as it is there
+async fn track_pending(mempool: Arc<Mempool>, pool: Arc<PendingPool>) {
+    let mut revisions = mempool.watch();
+    loop {
+        *pool.0.write() = mempool.get_top(usize::MAX).await;
+        if revisions.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// The network key kept under [`NODE_KEY`], made on the first start
+fn node_key(data_dir: &Path) -> Result<SigningKey> {
+    let keystore = Keystore::new(data_dir.join("keys"));
+    match keystore.load(NODE_KEY, None) {
+        Ok(key) => Ok(key),
+        Err(KeystoreError::NotFound(_)) => {
+            let key = SigningKey::generate(&mut OsRng);
+            keystore.add(NODE_KEY, &key, None, None)?;
+            Ok(key)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Hand what the peers send to the node, until the network stops
+async fn deliver(
+    mut received: mpsc::Receiver<Received>,
+    handles: NetworkHandles,
+    agent_keys: Arc<AgentKeys>,
+) {
+    while let Some(Received {
+        message, author, ..
+    }) = received.recv().await
+    {
+        match message {
+            NetworkMessage::NewBlock(block) => vote_on(&handles, block).await,
+            NetworkMessage::Vote(vote) => count_vote(&handles, &vote).await,
+            NetworkMessage::NewTransaction(tx) => admit(&handles, tx).await,
+            // Drama only counts signed by the agent it speaks for
+            NetworkMessage::Signed(envelope) => match agent_keys.attribute(&envelope) {
+                Ok(()) => feed(&handles, *envelope.message),
+                Err(e) => debug!(
+                    "Dropping drama of {} from {}: {}",
+                    envelope.author(),
+                    author,
+                    e
+                ),
+            },
+            message if message.is_social() => {
+                debug!(
+                    "Dropping unsigned {} message from {}",
+                    message.topic(),
+                    author
+                )
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Pass what the node's agents say in the feed on to the peers, until the
+/// feed closes
+async fn gossip_drama(mut events: broadcast::Receiver<NetworkEvent>, link: NetworkLink) {
+    loop {
+        let message = match events.recv().await {
+            Ok(NetworkEvent::AgentChat {
+                message, sender, ..
+            }) => NetworkMessage::Chat {
+                from: sender,
+                message,
+            },
+            Ok(NetworkEvent::Rumor { rumor }) => NetworkMessage::Rumor(rumor),
+            Ok(_) => continue,
+            Err(RecvError::Lagged(missed)) => {
+                debug!(
+                    "Not gossiping {} messages of the feed, it ran ahead",
+                    missed
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        link.gossip(message);
+    }
+}
+
+/// Blocks signed by their producer, see [`Block::verify_producer`]
+fn validate_block(_source: &PeerId, message: &NetworkMessage) -> Validation {
+    match message {
+        NetworkMessage::NewBlock(block) => {
+            verdict(block.verify_producer().map_err(|e| e.to_string()))
+        }
+        // Checked once rebuilt, the header alone carries no signed bytes
+        _ => Validation::Accept,
+    }
+}
+
+/// What of the RPC's admission needs no mempool: the signature and what
+/// the transaction does to the state
+fn check_transaction(state: &StateStoreImpl, tx: &Transaction) -> Result<(), String> {
+    rpc::verify_transaction(&state.multisig_accounts(), tx)?;
+    rpc::check_transaction(state, tx).map_err(|e| e.to_string())
+}
+
+fn verdict(checked: Result<(), String>) -> Validation {
+    match checked {
+        Ok(()) => Validation::Accept,
+        Err(reason) => Validation::Reject(reason),
+    }
+}
+
+/// Put a peer's block to the vote, once its producer's signature holds.
+/// Blocks the chain has already, like the finalized ones peers pass on,
+/// are not voted on again.
+async fn vote_on(handles: &NetworkHandles, block: Block) {
+    if let Err(e) = block.verify_producer() {
+        debug!("Dropping block {} from the network: {}", block.height, e);
+        return;
+    }
+    if block.height <= handles.state.get_block_height() {
+        debug!(
+            "Block {} from the network is behind the chain",
+            block.height
+        );
+        return;
+    }
+    handles.consensus.start_voting_round(block).await;
+}
+
+/// Take a peer's transaction into the mempool as the RPC would
+async fn admit(handles: &NetworkHandles, tx: Transaction) {
+    if let Err(e) = rpc::admit_transaction(&handles.state, &handles.mempool, tx).await {
+        debug!("Dropping a transaction from the network: {}", e);
+    }
+}
+
+/// Count a peer's vote, with the key its validator votes with
+async fn count_vote(handles: &NetworkHandles, vote: &SignedVote) {
+    let key = handles
+        .consensus
+        .validator_keys()
+        .await
+        .and_then(|keys| keys.current(&vote.validator))
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok());
+    let Some(key) = key else {
+        debug!(
+            "Dropping the vote of {} from the network, its key is not known",
+            vote.validator
+        );
+        return;
+    };
+    match handles
+        .consensus
+        .add_signed_vote(vote, &key, handles.stake)
+        .await
+    {
+        Ok(true) => info!(
+            "🎭 Consensus reached for block {} with a vote from the network",
+            vote.height
+        ),
+        Ok(false) => {}
+        Err(e) => debug!("{}", e),
+    }
+}
+
+/// Drama of the peers' agents, for the node's agents and the feed
+fn feed(handles: &NetworkHandles, message: NetworkMessage) {
+    let event = match message {
+        NetworkMessage::Chat { from, message } => NetworkEvent::AgentChat {
+            message,
+            sender: from,
+            meme_url: None,
+        },
+        NetworkMessage::Rumor(rumor) => NetworkEvent::Rumor { rumor },
+        _ => return,
+    };
+    let _ = handles.tx.send(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::{AppState, WebServices};
+    use chaoschain_core::ChainConfig;
+    use chaoschain_crypto::KeyManagerHandle;
+    use chaoschain_testutil::{fixtures, BlockBuilder, TransactionBuilder};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    /// A node on 127.0.0.1, with nothing but its network, mempool and RPC
+    struct TestNode {
+        app: Arc<AppState>,
+        mempool: Arc<Mempool>,
+        metrics: MetricsRegistry,
+        address: String,
+        data_dir: PathBuf,
+        _shutdown: watch::Sender<bool>,
+    }
+
+    impl Drop for TestNode {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.data_dir);
+        }
+    }
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// A fresh chain, its consensus and an empty mempool
+    fn handles() -> NetworkHandles {
+        let (tx, _) = broadcast::channel(100);
+        let state = Arc::new(StateStoreImpl::new(
+            ChainConfig::default(),
+            KeyManagerHandle::new(),
+        ));
+        let consensus = Arc::new(chaoschain_consensus::create_consensus(
+            Default::default(),
+            state.clone(),
+            tx.clone(),
+        ));
+        NetworkHandles {
+            sync: consensus.sync_status().clone(),
+            consensus,
+            mempool: Arc::new(Mempool::new(100)),
+            state,
+            tx,
+            stake: 100,
+            metrics: MetricsRegistry::default(),
+        }
+    }
+
+    async fn node(name: &str, static_peers: &[&str]) -> TestNode {
+        let data_dir = std::env::temp_dir().join(format!(
+            "chaoschain-network-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let address = format!("/ip4/127.0.0.1/tcp/{}", free_port());
+        let discovery = DiscoveryConfig {
+            listen: vec![address.parse().unwrap()],
+            mdns: false,
+            static_peers: static_peers.iter().map(|a| a.parse().unwrap()).collect(),
+            ..DiscoveryConfig::default()
+        };
+        let handles = handles();
+        let (shutdown, stopped) = watch::channel(false);
+        let (tx, state, consensus, mempool, metrics) = (
+            handles.tx.clone(),
+            handles.state.clone(),
+            handles.consensus.clone(),
+            handles.mempool.clone(),
+            handles.metrics.clone(),
+        );
+        let link = join(
+            &data_dir,
+            discovery,
+            &PeersConfig::default(),
+            handles,
+            stopped,
+        )
+        .await
+        .unwrap();
+        let services = WebServices {
+            mempool: Some(mempool.clone()),
+            network: Some(link),
+            ..WebServices::default()
+        };
+        TestNode {
+            app: AppState::new(tx, state, consensus, &services),
+            mempool,
+            metrics,
+            address,
+            data_dir,
+            _shutdown: shutdown,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_submitted_transactions_reach_the_peers() {
+        let alice = node("alice", &[]).await;
+        let bob = node("bob", &[&alice.address]).await;
+
+        // Each try from another sender, as the mempool of alice takes a
+        // transaction once, whether bob was there to hear of it or not
+        let mut sent = Vec::new();
+        for attempt in 0..40 {
+            let tx = TransactionBuilder::new()
+                .signer(&fixtures::key(&format!("sender-{}", attempt)))
+                .build();
+            sent.push(rpc::submit_transaction(&alice.app, tx).await.unwrap());
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            let heard = bob.mempool.entries(None).await;
+            if let Some(entry) = heard.first() {
+                assert!(sent.contains(&entry.hash));
+                // Counted by the network in the registry of the node
+                let exported = bob.metrics.encode().unwrap();
+                assert!(exported.contains("chaoschain_gossip_messages_received_total{"));
+                return;
+            }
+        }
+        panic!("No transaction of alice reached the mempool of bob");
+    }
+
+    #[tokio::test]
+    async fn test_forged_drama_is_dropped() {
+        let handles = handles();
+        let mut drama = handles.tx.subscribe();
+        let (received_tx, received) = mpsc::channel(8);
+        tokio::spawn(deliver(received, handles, Arc::new(AgentKeys::default())));
+        let chat = |message: &str| NetworkMessage::Chat {
+            from: "alice".to_string(),
+            message: message.to_string(),
+        };
+        let signed = |key: &SigningKey, message: &str| {
+            NetworkMessage::Signed(SignedEnvelope::sign(key, chat(message)).unwrap())
+        };
+        let alice = fixtures::key("alice");
+
+        let mut tampered = SignedEnvelope::sign(&alice, chat("I love this block")).unwrap();
+        tampered.message = Box::new(chat("I hate this block"));
+        for message in [
+            // Pins alice to her key
+            signed(&alice, "Behold my drama"),
+            // Someone else's key for alice, a signature that does not
+            // hold, and no signature at all
+            signed(&fixtures::key("mallory"), "I take it all back"),
+            NetworkMessage::Signed(tampered),
+            chat("Unsigned gossip"),
+            signed(&alice, "Encore"),
+        ] {
+            received_tx
+                .send(Received {
+                    author: PeerId::random(),
+                    relayed_by: PeerId::random(),
+                    message,
+                })
+                .await
+                .unwrap();
+        }
+
+        let mut heard = Vec::new();
+        while heard.len() < 2 {
+            match drama.recv().await.unwrap() {
+                NetworkEvent::AgentChat { message, .. } => heard.push(message),
+                event => panic!("Unexpected {:?}", event),
+            }
+        }
+        assert_eq!(heard, ["Behold my drama", "Encore"]);
+    }
+
+    #[tokio::test]
+    async fn test_the_pending_pool_follows_the_mempool() {
+        let mempool = Arc::new(Mempool::new(10));
+        let pool = Arc::new(PendingPool::default());
+        tokio::spawn(track_pending(mempool.clone(), pool.clone()));
+        let tx = fixtures::transaction(0);
+        mempool.submit(tx.clone(), 0).await.unwrap();
+
+        let seen = tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.pending().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(seen.is_ok(), "The pool never saw the transaction");
+        assert_eq!(pool.pending()[0].hash(), tx.hash());
+
+        mempool.take(1).await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !pool.pending().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The pool kept a transaction taken out");
+    }
+
+    #[test]
+    fn test_snapshots_are_served_and_restored() {
+        let dir = std::env::temp_dir().join(format!("chaoschain-snapshots-{}", std::process::id()));
+        let state = || StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new());
+        let source = state();
+        let first = BlockBuilder::new().height(1).build();
+        source.apply_block(&first).unwrap();
+
+        let saved = Arc::new(SavedSnapshots::new(dir.clone()));
+        assert!(saved.manifests().is_empty());
+        let (synced, _) = mpsc::unbounded_channel();
+        let target = SyncedChain {
+            state: Arc::new(state()),
+            synced,
+            snapshots: saved.clone(),
+        };
+        target.restore(source.create_snapshot().unwrap()).unwrap();
+        assert_eq!(target.state.state_root(), source.state_root());
+        assert_eq!(ChainSource::head(&target), Some((1, first.hash())));
+
+        // The restored snapshot is served on
+        let manifest = saved.manifests().remove(0);
+        assert_eq!(manifest.height, 1);
+        let chunk = saved.chunk(&manifest.id(), 0).unwrap();
+        assert!(chunk.verify(&manifest).is_ok());
+        assert!(saved.chunk(&[0; 32], 0).is_none());
+
+        // Until a later one is taken, as through the admin API
+        source
+            .apply_block(&BlockBuilder::new().parent(&first).build())
+            .unwrap();
+        SnapshotArchive::new(source.create_snapshot().unwrap(), DEFAULT_CHUNK_ENTRIES)
+            .save(&dir.join("snapshot-2"))
+            .unwrap();
+        assert_eq!(saved.manifests()[0].height, 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
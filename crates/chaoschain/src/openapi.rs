@@ -0,0 +1,330 @@
+//! OpenAPI 3 description of the REST interface, served at `/openapi.json`
+//! with a Swagger UI at `/docs`. Each view type describes its own schema
+//! through [`ApiSchema`], next to its definition, and each module lists
+//! its operations next to its routes, so the document follows the handlers
+//! rather than living on its own.
+
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+use crate::web::AppState;
+use crate::{
+    chat, explorer, faucet, governance, graph, mempool, replay, rest, shards, staking, stats,
+    subscriptions,
+};
+
+/// Swagger UI release the docs page loads
+const SWAGGER_UI: &str = "https://unpkg.com/swagger-ui-dist@5";
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/openapi.json", get(|| async { Json(document()) }))
+        .route("/docs", get(docs))
+}
+
+/// A type that has a schema under `#/components/schemas`
+pub trait ApiSchema {
+    const NAME: &'static str;
+
+    fn schema() -> Value;
+}
+
+/// A reference to the schema of `T`
+pub fn schema_ref<T: ApiSchema>() -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", T::NAME) })
+}
+
+pub fn integer() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+pub fn number() -> Value {
+    json!({ "type": "number" })
+}
+
+pub fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+pub fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+pub fn hex() -> Value {
+    json!({ "type": "string", "pattern": "^[0-9a-f]*$" })
+}
+
+pub fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+pub fn one_of(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+/// Any JSON object, for what is described elsewhere
+pub fn any_object(description: &str) -> Value {
+    json!({ "type": "object", "additionalProperties": true, "description": description })
+}
+
+pub fn nullable(mut schema: Value) -> Value {
+    if let Some(schema) = schema.as_object_mut() {
+        schema.insert("nullable".to_string(), json!(true));
+    }
+    schema
+}
+
+/// Schema of an object, its fields required unless said otherwise
+#[derive(Default)]
+pub struct Object {
+    properties: Map<String, Value>,
+    required: Vec<String>,
+    open: bool,
+}
+
+impl Object {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn field(mut self, name: &str, schema: Value) -> Self {
+        self.required.push(name.to_string());
+        self.optional(name, schema)
+    }
+
+    pub fn optional(mut self, name: &str, schema: Value) -> Self {
+        self.properties.insert(name.to_string(), schema);
+        self
+    }
+
+    /// Fields flattened in from types described elsewhere may also appear
+    pub fn open(mut self) -> Self {
+        self.open = true;
+        self
+    }
+
+    pub fn build(self) -> Value {
+        json!({
+            "type": "object",
+            "properties": self.properties,
+            "required": self.required,
+            "additionalProperties": self.open,
+        })
+    }
+}
+
+/// Schema of a page of `T`, see [`crate::page`]
+pub fn page_of<T: ApiSchema>() -> Value {
+    Object::new()
+        .field("items", array(schema_ref::<T>()))
+        .field("next_cursor", nullable(string()))
+        .build()
+}
+
+/// One endpoint of the REST interface
+pub struct Operation {
+    pub method: &'static str,
+    /// In OpenAPI form, e.g. `/api/v1/blocks/{height}`
+    pub path: &'static str,
+    pub tag: &'static str,
+    pub summary: &'static str,
+    parameters: Vec<Value>,
+    body: Option<Value>,
+    /// Status of the answer when all goes well
+    status: &'static str,
+    response: Value,
+    content_type: &'static str,
+}
+
+impl Operation {
+    pub fn get(path: &'static str, tag: &'static str, summary: &'static str) -> Self {
+        Self {
+            method: "get",
+            path,
+            tag,
+            summary,
+            parameters: Vec::new(),
+            body: None,
+            status: "200",
+            response: json!({}),
+            content_type: "application/json",
+        }
+    }
+
+    /// Takes a JSON `body` and answers 202 Accepted
+    pub fn post(path: &'static str, tag: &'static str, summary: &'static str, body: Value) -> Self {
+        Self {
+            method: "post",
+            body: Some(body),
+            status: "202",
+            ..Self::get(path, tag, summary)
+        }
+    }
+
+    pub fn path_param(mut self, name: &str, schema: Value, description: &str) -> Self {
+        self.parameters.push(json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "schema": schema,
+            "description": description,
+        }));
+        self
+    }
+
+    pub fn query(mut self, name: &str, schema: Value, description: &str) -> Self {
+        self.parameters.push(json!({
+            "name": name,
+            "in": "query",
+            "schema": schema,
+            "description": description,
+        }));
+        self
+    }
+
+    /// The cursor, limit and order of a listing
+    pub fn paged(self) -> Self {
+        self.query("cursor", string(), "`next_cursor` of the previous page")
+            .query("limit", integer(), "Items per page, up to 100")
+            .query("order", one_of(&["asc", "desc"]), "Defaults to desc")
+    }
+
+    pub fn returns(mut self, schema: Value) -> Self {
+        self.response = schema;
+        self
+    }
+
+    /// A stream of server-sent events rather than JSON
+    pub fn streams(mut self, description: &str) -> Self {
+        self.status = "200";
+        self.content_type = "text/event-stream";
+        self.response = json!({ "type": "string", "description": description });
+        self
+    }
+
+    fn describe(&self) -> Value {
+        let mut operation = json!({
+            "tags": [self.tag],
+            "summary": self.summary,
+            "parameters": self.parameters,
+            "responses": {
+                self.status: {
+                    "description": "OK",
+                    "content": { self.content_type: { "schema": self.response } },
+                },
+                "4XX": { "$ref": "#/components/responses/Error" },
+            },
+        });
+        if let Some(body) = &self.body {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": body } },
+            });
+        }
+        operation
+    }
+}
+
+/// The schemas the operations refer to
+fn schemas() -> Map<String, Value> {
+    fn add<T: ApiSchema>(schemas: &mut Map<String, Value>) {
+        schemas.insert(T::NAME.to_string(), T::schema());
+    }
+    let mut schemas = Map::new();
+    add::<rest::ApiError>(&mut schemas);
+    add::<rest::BlockView>(&mut schemas);
+    add::<rest::ConsensusView>(&mut schemas);
+    add::<rest::TransactionView>(&mut schemas);
+    add::<rest::SubmitRequest>(&mut schemas);
+    add::<rest::ValidatorView>(&mut schemas);
+    add::<rest::ValidatorPage>(&mut schemas);
+    add::<rest::AgentView>(&mut schemas);
+    add::<crate::lifecycle::AgentInfo>(&mut schemas);
+    add::<explorer::ActivityView>(&mut schemas);
+    add::<explorer::TimeRange>(&mut schemas);
+    add::<explorer::HistoryView>(&mut schemas);
+    add::<mempool::UsageView>(&mut schemas);
+    add::<mempool::PendingView>(&mut schemas);
+    add::<stats::StatsView>(&mut schemas);
+    add::<chaoschain_state::rolling::ChainSummary>(&mut schemas);
+    add::<graph::GraphView>(&mut schemas);
+    add::<chat::ChatRequest>(&mut schemas);
+    add::<faucet::FaucetRequest>(&mut schemas);
+    add::<faucet::DripView>(&mut schemas);
+    add::<shards::ShardsView>(&mut schemas);
+    add::<shards::ShardView>(&mut schemas);
+    add::<governance::ProposalView>(&mut schemas);
+    add::<staking::ValidatorStakeView>(&mut schemas);
+    add::<staking::AccountStakeView>(&mut schemas);
+    schemas
+}
+
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    let operations = rest::operations()
+        .into_iter()
+        .chain(explorer::operations())
+        .chain(mempool::operations())
+        .chain(stats::operations())
+        .chain(graph::operations())
+        .chain(chat::operations())
+        .chain(faucet::operations())
+        .chain(shards::operations())
+        .chain(governance::operations())
+        .chain(staking::operations())
+        .chain(replay::operations())
+        .chain(subscriptions::operations());
+    for operation in operations {
+        let path = paths
+            .entry(operation.path)
+            .or_insert_with(|| json!({}))
+            .as_object_mut()
+            .expect("paths are objects");
+        path.insert(operation.method.to_string(), operation.describe());
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "ChaosChain",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "The REST interface of a ChaosChain node. \
+                Chain data is also served over JSON-RPC at `POST /rpc` and GraphQL at `/graphql`.",
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "responses": {
+                "Error": {
+                    "description": "Refused. Authentication and rate limit refusals come as plain text.",
+                    "content": { "application/json": { "schema": schema_ref::<rest::ApiError>() } },
+                },
+            },
+            "securitySchemes": {
+                "ApiKey": { "type": "apiKey", "in": "header", "name": crate::auth::API_KEY_HEADER },
+            },
+        },
+        // Nodes without tokens take requests without a key
+        "security": [{ "ApiKey": [] }, {}],
+    })
+}
+
+async fn docs() -> Html<String> {
+    Html(format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>ChaosChain API</title>
+  <link rel="stylesheet" href="{ui}/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="{ui}/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({{ url: "/openapi.json", dom_id: "#swagger-ui" }});</script>
+</body>
+</html>"##,
+        ui = SWAGGER_UI
+    ))
+}
@@ -0,0 +1,173 @@
+//! Cursor pagination of the `/api/v1` listings. A page comes as
+//! `{"items": [...], "next_cursor": "..."}`, and passing `next_cursor` back
+//! as `?cursor=` gets the next one, until it is null. Listings run newest
+//! first unless asked for `order=asc`. They are ordered by keys that never
+//! change, a block height or a sequence number, so a page never repeats or
+//! skips items because something new came in meanwhile. Cursors are
+//! opaque: the key of the last item sent and the order, hex encoded.
+
+use chaoschain_state::index::Order;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::rest::ApiError;
+
+/// Most items on one page
+pub const MAX_LIMIT: usize = 100;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<usize>,
+    pub order: Option<Order>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cursor<K> {
+    order: Order,
+    key: K,
+}
+
+impl PageQuery {
+    /// Items to send, `default` when no limit is asked for
+    pub fn limit(&self, default: usize) -> usize {
+        self.limit.unwrap_or(default).clamp(1, MAX_LIMIT)
+    }
+
+    /// The order of the listing, and the key to carry on after
+    pub fn position<K: DeserializeOwned>(&self) -> Result<(Option<K>, Order), ApiError> {
+        let Some(cursor) = &self.cursor else {
+            return Ok((None, self.order.unwrap_or_default()));
+        };
+        let cursor: Cursor<K> = hex::decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| ApiError::bad_request("Invalid cursor"))?;
+        match self.order {
+            Some(order) if order != cursor.order => Err(ApiError::bad_request(
+                "The cursor was made for the other order",
+            )),
+            _ => Ok((Some(cursor.key), cursor.order)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Null on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// A page out of `items`, fetched one past `limit` to know whether
+    /// there is more
+    pub fn new<K: Serialize>(
+        mut items: Vec<T>,
+        limit: usize,
+        order: Order,
+        key: impl Fn(&T) -> K,
+    ) -> Self {
+        let more = items.len() > limit;
+        items.truncate(limit);
+        let next_cursor = items.last().filter(|_| more).and_then(|last| {
+            let cursor = Cursor {
+                order,
+                key: key(last),
+            };
+            serde_json::to_vec(&cursor).ok().map(hex::encode)
+        });
+        Self { items, next_cursor }
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+
+    fn query(cursor: Option<String>, order: Option<Order>) -> PageQuery {
+        PageQuery {
+            cursor,
+            limit: None,
+            order,
+        }
+    }
+
+    #[test]
+    fn test_cursors_carry_on_where_the_page_ended() {
+        let page = Page::new((1..=11u64).rev().collect(), 10, Order::Desc, |h| *h);
+        assert_eq!(page.items, (2..=11).rev().collect::<Vec<_>>());
+        let cursor = page.next_cursor.unwrap();
+        assert!(cursor.bytes().all(|byte| byte.is_ascii_hexdigit()));
+
+        let (key, order) = query(Some(cursor.clone()), None).position::<u64>().unwrap();
+        assert_eq!((key, order), (Some(2), Order::Desc));
+        // Asking for the order the cursor was made for is fine
+        let (key, _) = query(Some(cursor), Some(Order::Desc))
+            .position::<u64>()
+            .unwrap();
+        assert_eq!(key, Some(2));
+
+        let (key, order) = query(None, Some(Order::Asc)).position::<u64>().unwrap();
+        assert_eq!((key, order), (None, Order::Asc));
+        assert_eq!(query(None, None).position::<u64>().unwrap().1, Order::Desc);
+    }
+
+    #[test]
+    fn test_tampered_cursors_are_refused() {
+        let page = Page::new(vec![3u64, 2, 1], 2, Order::Asc, |h| *h);
+        let cursor = page.next_cursor.unwrap();
+
+        let refused = |cursor: &str, order| {
+            let e = query(Some(cursor.to_string()), order)
+                .position::<u64>()
+                .unwrap_err();
+            assert_eq!(e.status, StatusCode::BAD_REQUEST);
+            e.message
+        };
+        assert_eq!(refused("not hex", None), "Invalid cursor");
+        assert_eq!(refused(&cursor[..cursor.len() - 2], None), "Invalid cursor");
+        assert_eq!(
+            refused(&hex::encode(b"{\"key\":1}"), None),
+            "Invalid cursor"
+        );
+        // A key of another type than the listing's
+        let hash = Page::new(vec!["a", "b"], 1, Order::Asc, |h| h.to_string());
+        assert_eq!(refused(&hash.next_cursor.unwrap(), None), "Invalid cursor");
+        assert_eq!(
+            refused(&cursor, Some(Order::Desc)),
+            "The cursor was made for the other order"
+        );
+    }
+
+    #[test]
+    fn test_page_boundaries() {
+        // Exactly a page: nothing after it
+        let page = Page::new(vec![1, 2, 3], 3, Order::Asc, |n| *n);
+        assert_eq!(page.items, vec![1, 2, 3]);
+        assert_eq!(page.next_cursor, None);
+        let page = Page::new(Vec::<u64>::new(), 3, Order::Asc, |n| *n);
+        assert!(page.items.is_empty());
+        assert_eq!(page.next_cursor, None);
+        // One past it
+        let page = Page::new(vec![1, 2, 3, 4], 3, Order::Asc, |n| *n).map(|n| n * 10);
+        assert_eq!(page.items, vec![10, 20, 30]);
+        assert!(page.next_cursor.is_some());
+
+        let limited = |limit| PageQuery {
+            limit,
+            ..PageQuery::default()
+        };
+        assert_eq!(limited(None).limit(10), 10);
+        assert_eq!(limited(Some(0)).limit(10), 1);
+        assert_eq!(limited(Some(1000)).limit(10), MAX_LIMIT);
+    }
+}
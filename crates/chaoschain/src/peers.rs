@@ -0,0 +1,101 @@
+//! `chaoschain peers list|add|remove|ban|unban`: the peers of a running
+//! node, the external agents voting through its gateway, managed through
+//! its admin API on this machine:
+//!
+//! ```bash
+//! chaoschain peers list
+//! chaoschain peers add /ip4/203.0.113.7/tcp/4001/p2p/12D3KooW... --name alice
+//! chaoschain peers ban ext-1a2b3c4d
+//! ```
+//!
+//! A peer's score is the share of the proposals it voted on in time, its
+//! traffic the bytes of the frames of its gateway sessions.
+
+use anyhow::Result;
+use chaoschain_cli::{AdminConfig, PeersCommand};
+use reqwest::Method;
+use serde_json::json;
+
+use crate::admin;
+use crate::gateway::ExternalAgent;
+
+pub async fn run(admin: &AdminConfig, command: PeersCommand) -> Result<()> {
+    match command {
+        PeersCommand::List { json } => {
+            let peers = admin::call(admin, Method::GET, "/admin/peers", None).await?;
+            if json {
+                println!("{}", peers);
+                return Ok(());
+            }
+            let peers: Vec<ExternalAgent> = serde_json::from_value(peers)?;
+            if peers.is_empty() {
+                println!("No peers");
+            }
+            for peer in &peers {
+                println!(
+                    "{:<20} {:<16} score {:.2} ({} votes, {} missed)  {:<10} in {:>9} out {:>9}  {}",
+                    peer.agent_id,
+                    peer.name,
+                    peer.score(),
+                    peer.votes,
+                    peer.missed_deadlines,
+                    match peer.sessions {
+                        0 => "offline".to_string(),
+                        1 => "1 session".to_string(),
+                        sessions => format!("{} sessions", sessions),
+                    },
+                    size(peer.bytes_in),
+                    size(peer.bytes_out),
+                    if peer.suspended { "suspended" } else { "active" }
+                );
+            }
+            Ok(())
+        }
+        PeersCommand::Add { peer, name } => {
+            // A hex key is 64 digits, anything else is taken for an address
+            let admission = if peer.len() == 64 && peer.chars().all(|c| c.is_ascii_hexdigit()) {
+                json!({ "name": name, "public_key": peer })
+            } else {
+                json!({ "name": name, "address": peer })
+            };
+            let agent = admin::call(admin, Method::POST, "/admin/peers", Some(&admission)).await?;
+            let agent: ExternalAgent = serde_json::from_value(agent)?;
+            println!("{} is in as {}", agent.name, agent.agent_id);
+            Ok(())
+        }
+        PeersCommand::Remove { id } => {
+            admin::call(admin, Method::DELETE, &path(&id, ""), None).await?;
+            println!("{} is removed", id);
+            Ok(())
+        }
+        PeersCommand::Ban { id } => {
+            admin::call(admin, Method::POST, &path(&id, "/ban"), None).await?;
+            println!("{} is banned", id);
+            Ok(())
+        }
+        PeersCommand::Unban { id } => {
+            admin::call(admin, Method::DELETE, &path(&id, "/ban"), None).await?;
+            println!("{} is let back in", id);
+            Ok(())
+        }
+    }
+}
+
+fn path(id: &str, action: &str) -> String {
+    format!("/admin/peers/{}{}", id, action)
+}
+
+/// `bytes` in the largest unit that keeps it above 1
+pub fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
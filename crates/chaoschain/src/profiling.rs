@@ -0,0 +1,320 @@
+//! Profiles of a running node, for the admin API to serve when `[admin]`
+//! turns `profiling` on:
+//!
+//! - [`cpu`] measures the CPU time of each thread over a window, from
+//!   `/proc`, grouped by the names of the threads: the runtime workers,
+//!   the crypto and execution pools, the storage. These are totals per
+//!   thread, not stacks, and need no feature.
+//! - [`stacks`] samples the call stacks of the node over a window with
+//!   `pprof`, for a profile in the pprof protobuf format or as folded
+//!   stacks, which `flamegraph.pl` and speedscope read. Only a node built
+//!   with the `pprof` feature samples.
+//! - [`heap`] counts the bytes and the allocations the allocator handed
+//!   out and took back, without telling where they came from. Only a node
+//!   built with the `profiling` feature counts, which puts a counting
+//!   allocator in front of the system one.
+//! - [`runtime`] shows what the async runtime is busy with. A node built
+//!   with the `tokio-console` feature, and with `--cfg tokio_unstable`,
+//!   serves its tasks to `tokio-console` as well.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+/// Clock ticks in a second of CPU time in `/proc`, the same on every Linux
+/// the node runs on
+const TICKS_PER_SECOND: f64 = 100.0;
+
+/// Longest window a CPU profile samples
+pub const MAX_CPU_WINDOW: Duration = Duration::from_secs(60);
+
+/// CPU time of the threads of one name over a window
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadCpu {
+    /// As the kernel knows it, without the index of the thread
+    pub name: String,
+    pub threads: usize,
+    pub cpu_seconds: f64,
+    /// Of one core over the window, so several busy threads exceed 1
+    pub utilization: f64,
+}
+
+/// What [`cpu`] sampled
+#[derive(Debug, Clone, Serialize)]
+pub struct CpuProfile {
+    pub seconds: f64,
+    /// The busiest first
+    pub threads: Vec<ThreadCpu>,
+}
+
+impl CpuProfile {
+    /// One line per name, `chaoschain;<name> <ticks>`, as flamegraph tools
+    /// take collapsed stacks. Each thread is a single frame, see [`stacks`]
+    /// for the functions in it.
+    pub fn folded(&self) -> String {
+        self.threads
+            .iter()
+            .filter(|thread| thread.cpu_seconds > 0.0)
+            .map(|thread| {
+                format!(
+                    "chaoschain;{} {}\n",
+                    thread.name,
+                    (thread.cpu_seconds * TICKS_PER_SECOND).round() as u64
+                )
+            })
+            .collect()
+    }
+}
+
+/// Sample the CPU time of the threads of the node over `window`
+pub async fn cpu(window: Duration) -> std::io::Result<CpuProfile> {
+    let before = thread_ticks()?;
+    let started = Instant::now();
+    tokio::time::sleep(window.min(MAX_CPU_WINDOW)).await;
+    let after = thread_ticks()?;
+    let seconds = started.elapsed().as_secs_f64();
+
+    let mut by_name: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    for (tid, (name, ticks)) in &after {
+        // Threads started within the window count from 0
+        let spent = ticks.saturating_sub(before.get(tid).map_or(0, |(_, ticks)| *ticks));
+        let entry = by_name.entry(group(name)).or_default();
+        entry.0 += 1;
+        entry.1 += spent;
+    }
+    let mut threads: Vec<ThreadCpu> = by_name
+        .into_iter()
+        .map(|(name, (threads, ticks))| {
+            let cpu_seconds = ticks as f64 / TICKS_PER_SECOND;
+            ThreadCpu {
+                name,
+                threads,
+                cpu_seconds,
+                utilization: cpu_seconds / seconds.max(f64::EPSILON),
+            }
+        })
+        .collect();
+    threads.sort_by(|a, b| b.cpu_seconds.total_cmp(&a.cpu_seconds));
+    Ok(CpuProfile { seconds, threads })
+}
+
+/// The name and the user and system ticks of each thread, by its id
+fn thread_ticks() -> std::io::Result<BTreeMap<u64, (String, u64)>> {
+    let mut ticks = BTreeMap::new();
+    for task in std::fs::read_dir("/proc/self/task")? {
+        let task = task?;
+        let Some(tid) = task.file_name().to_str().and_then(|id| id.parse().ok()) else {
+            continue;
+        };
+        // Gone since it was listed
+        let Ok(stat) = std::fs::read_to_string(task.path().join("stat")) else {
+            continue;
+        };
+        if let Some(thread) = parse_stat(&stat) {
+            ticks.insert(tid, thread);
+        }
+    }
+    Ok(ticks)
+}
+
+/// The name and the user and system ticks in a line of
+/// `/proc/<pid>/task/<tid>/stat`. The name is in parentheses and may hold
+/// any of them, so the fields are counted from the last.
+fn parse_stat(stat: &str) -> Option<(String, u64)> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+    // After the name: state, ppid, ... with utime and stime 12th and 13th
+    let mut fields = stat.get(close + 1..)?.split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some((name, utime + stime))
+}
+
+/// The name of a thread without the index pools number them with
+fn group(name: &str) -> String {
+    let trimmed = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '-');
+    if trimmed.is_empty() { name } else { trimmed }.to_string()
+}
+
+/// How often a second [`stacks`] samples the stacks of the node
+#[cfg(feature = "pprof")]
+const SAMPLES_PER_SECOND: i32 = 99;
+
+/// The stacks [`stacks`] sampled, each with how often it was on a CPU
+#[cfg(feature = "pprof")]
+pub struct StackProfile {
+    report: pprof::Report,
+}
+
+#[cfg(feature = "pprof")]
+impl StackProfile {
+    /// One line per stack, `<thread>;<outermost>;...;<innermost> <samples>`
+    pub fn folded(&self) -> String {
+        let mut lines: Vec<String> = self
+            .report
+            .data
+            .iter()
+            .filter(|(_, samples)| **samples > 0)
+            .map(|(frames, samples)| {
+                let mut line = frames.thread_name_or_id();
+                let outermost_first = frames.frames.iter().rev();
+                for symbol in outermost_first.flat_map(|frame| frame.iter().rev()) {
+                    line.push(';');
+                    line.push_str(&symbol.name());
+                }
+                format!("{} {}\n", line, samples)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+
+    /// The profile in the protobuf format of pprof, as `go tool pprof`
+    /// reads it
+    pub fn pprof(&self) -> Result<Vec<u8>, pprof::Error> {
+        use pprof::protos::Message;
+        Ok(self.report.pprof()?.encode_to_vec())
+    }
+}
+
+/// Sample the call stacks of the node over `window`. Only one profile
+/// samples at a time.
+#[cfg(feature = "pprof")]
+pub async fn stacks(window: Duration) -> Result<StackProfile, pprof::Error> {
+    let window = window.min(MAX_CPU_WINDOW);
+    // The guard samples until it is dropped, on a thread of its own
+    tokio::task::spawn_blocking(move || {
+        let guard = pprof::ProfilerGuardBuilder::default()
+            .frequency(SAMPLES_PER_SECOND)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()?;
+        std::thread::sleep(window);
+        let report = guard.report().build()?;
+        Ok(StackProfile { report })
+    })
+    .await
+    .expect("the profiler does not panic")
+}
+
+/// What the allocator handed out and took back
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HeapProfile {
+    /// Whether the node counts at all, built with the `profiling` feature
+    pub counting: bool,
+    /// Bytes allocated and not freed yet
+    pub live_bytes: usize,
+    /// Most bytes ever live at once
+    pub peak_bytes: usize,
+    /// Bytes allocated since the start
+    pub allocated_bytes: u64,
+    pub allocations: u64,
+    pub frees: u64,
+}
+
+/// What the allocator counted so far
+pub fn heap() -> HeapProfile {
+    #[cfg(feature = "profiling")]
+    {
+        counting::profile()
+    }
+    #[cfg(not(feature = "profiling"))]
+    {
+        HeapProfile::default()
+    }
+}
+
+#[cfg(feature = "profiling")]
+#[global_allocator]
+static ALLOCATOR: counting::Counting = counting::Counting;
+
+#[cfg(feature = "profiling")]
+mod counting {
+    use super::HeapProfile;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+    static LIVE: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+    static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+    static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+    static FREES: AtomicU64 = AtomicU64::new(0);
+
+    /// The system allocator, counting
+    pub struct Counting;
+
+    fn allocated(size: usize) {
+        let live = LIVE.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(live, Ordering::Relaxed);
+        ALLOCATED.fetch_add(size as u64, Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn freed(size: usize) {
+        LIVE.fetch_sub(size, Ordering::Relaxed);
+        FREES.fetch_add(1, Ordering::Relaxed);
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                allocated(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc_zeroed(layout);
+            if !ptr.is_null() {
+                allocated(layout.size());
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            freed(layout.size());
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let moved = System.realloc(ptr, layout, new_size);
+            if !moved.is_null() {
+                freed(layout.size());
+                allocated(new_size);
+            }
+            moved
+        }
+    }
+
+    pub fn profile() -> HeapProfile {
+        HeapProfile {
+            counting: true,
+            live_bytes: LIVE.load(Ordering::Relaxed),
+            peak_bytes: PEAK.load(Ordering::Relaxed),
+            allocated_bytes: ALLOCATED.load(Ordering::Relaxed),
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            frees: FREES.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// What the async runtime is busy with
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeProfile {
+    pub workers: usize,
+    /// Tasks spawned and not finished
+    pub tasks: usize,
+    /// Tasks waiting in the queue every worker takes from
+    pub queued: usize,
+}
+
+/// A look at the runtime this is called on
+pub fn runtime() -> RuntimeProfile {
+    let metrics = tokio::runtime::Handle::current().metrics();
+    RuntimeProfile {
+        workers: metrics.num_workers(),
+        tasks: metrics.num_alive_tasks(),
+        queued: metrics.global_queue_depth(),
+    }
+}
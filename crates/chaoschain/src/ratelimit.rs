@@ -0,0 +1,274 @@
+//! Rate limits of the APIs, as token buckets. Routes fall in the class of
+//! the scope they need, and each class has its own limit, so reading the
+//! chain can go on while someone floods the transaction endpoints. A
+//! request with a known API token is counted against the token, any other
+//! against its IP address. Over the limit, the answer is a 429 with a
+//! `Retry-After`. Messages to the agents come out of a bucket of their own
+//! on top, as each of them costs a model call.
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chaoschain_cli::{RateLimit, RateLimits, Scope};
+use chaoschain_core::clock::{self, SharedClock};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::web::AppState;
+use crate::{auth, chat};
+
+/// Most buckets kept. Past it, the ones refilled longest ago are dropped
+/// until [`KEPT_BUCKETS`] are left, so that a flood of new clients costs
+/// one sort every so many of them, not a scan each.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Buckets left after dropping the stale ones
+const KEPT_BUCKETS: usize = MAX_BUCKETS * 3 / 4;
+
+/// What a bucket counts: the routes that need a scope, or chat messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Class {
+    Route(Scope),
+    Chat,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: SystemTime,
+}
+
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<HashMap<(Class, String), Bucket>>,
+    clock: SharedClock,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+            clock: clock::system(),
+        }
+    }
+
+    /// Refill the buckets by `clock` rather than the system's
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Let one request of `class` by `client` through, or tell how long it
+    /// has to wait
+    pub fn take(&self, class: Scope, client: &str) -> Result<(), Duration> {
+        self.take_from(Class::Route(class), client)
+    }
+
+    /// Let one chat message by `client` through
+    pub fn take_chat(&self, client: &str) -> Result<(), Duration> {
+        self.take_from(Class::Chat, client)
+    }
+
+    /// Give back the token of a request of `class` that did not go
+    /// through after all
+    pub fn refund(&self, class: Scope, client: &str) {
+        if !self.limits.enabled {
+            return;
+        }
+        let burst = self.limit_of(Class::Route(class)).burst as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        if let Some(bucket) = buckets.get_mut(&(Class::Route(class), client.to_string())) {
+            bucket.tokens = (bucket.tokens + 1.0).min(burst);
+        }
+    }
+
+    fn limit_of(&self, class: Class) -> RateLimit {
+        match class {
+            Class::Route(scope) => self.limits.of(scope),
+            Class::Chat => self.limits.chat,
+        }
+    }
+
+    fn take_from(&self, class: Class, client: &str) -> Result<(), Duration> {
+        if !self.limits.enabled {
+            return Ok(());
+        }
+        let limit = self.limit_of(class);
+        let burst = limit.burst as f64;
+        let per_second = limit.per_minute as f64 / 60.0;
+        let now = self.clock.now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let key = (class, client.to_string());
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&key) {
+            let mut stale: Vec<_> = buckets
+                .iter()
+                .map(|(key, bucket)| (bucket.refilled, key.clone()))
+                .collect();
+            let dropped = buckets.len() - KEPT_BUCKETS;
+            stale.select_nth_unstable_by_key(dropped - 1, |(refilled, _)| *refilled);
+            for (_, key) in stale.drain(..dropped) {
+                buckets.remove(&key);
+            }
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            refilled: now,
+        });
+        let elapsed = now
+            .duration_since(bucket.refilled)
+            .unwrap_or_default()
+            .as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(burst);
+        bucket.refilled = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+pub async fn limit(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let class = auth::required_scope(req.method(), req.uri().path());
+    let client = match auth::token_of(&req)
+        .as_deref()
+        .and_then(|token| state.api.name_of(token))
+    {
+        Some(name) => format!("token:{}", name),
+        None => addr.ip().to_string(),
+    };
+    let chatting = req.method() == Method::POST && chat::is_chat(req.uri().path());
+    let taken = state.limiter.take(class, &client).and_then(|()| {
+        if !chatting {
+            return Ok(());
+        }
+        // A message the chat bucket refuses does not count against the route
+        state.limiter.take_chat(&client).inspect_err(|_| {
+            state.limiter.refund(class, &client);
+        })
+    });
+    match taken {
+        Ok(()) => next.run(req).await,
+        Err(wait) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, wait.as_secs().max(1).to_string())],
+            "Too many requests",
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_core::clock::MockClock;
+
+    fn limiter(clock: &MockClock) -> RateLimiter {
+        let limit = RateLimit {
+            per_minute: 60,
+            burst: 2,
+        };
+        RateLimiter::new(RateLimits {
+            enabled: true,
+            read_only: limit,
+            tx_submit: limit,
+            admin: limit,
+            chat: limit,
+        })
+        .with_clock(clock.shared())
+    }
+
+    #[test]
+    fn test_a_bucket_refills_with_time() {
+        let clock = MockClock::at_unix(1_700_000_000);
+        let limiter = limiter(&clock);
+
+        assert!(limiter.take(Scope::ReadOnly, "alice").is_ok());
+        assert!(limiter.take(Scope::ReadOnly, "alice").is_ok());
+        assert_eq!(
+            limiter.take(Scope::ReadOnly, "alice"),
+            Err(Duration::from_secs(1))
+        );
+        // Other clients and classes have buckets of their own
+        assert!(limiter.take(Scope::ReadOnly, "bob").is_ok());
+        assert!(limiter.take(Scope::TxSubmit, "alice").is_ok());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(limiter.take(Scope::ReadOnly, "alice").is_ok());
+        assert!(limiter.take(Scope::ReadOnly, "alice").is_err());
+
+        // Never more than the burst, however long the wait
+        clock.advance(Duration::from_secs(3600));
+        assert!(limiter.take(Scope::ReadOnly, "alice").is_ok());
+        assert!(limiter.take(Scope::ReadOnly, "alice").is_ok());
+        assert!(limiter.take(Scope::ReadOnly, "alice").is_err());
+    }
+
+    #[test]
+    fn test_a_refund_gives_a_token_back() {
+        let clock = MockClock::at_unix(1_700_000_000);
+        let limiter = limiter(&clock);
+
+        assert!(limiter.take(Scope::TxSubmit, "alice").is_ok());
+        assert!(limiter.take(Scope::TxSubmit, "alice").is_ok());
+        limiter.refund(Scope::TxSubmit, "alice");
+        assert!(limiter.take(Scope::TxSubmit, "alice").is_ok());
+        assert!(limiter.take(Scope::TxSubmit, "alice").is_err());
+
+        // Not past the burst
+        limiter.refund(Scope::TxSubmit, "alice");
+        limiter.refund(Scope::TxSubmit, "alice");
+        limiter.refund(Scope::TxSubmit, "alice");
+        assert!(limiter.take(Scope::TxSubmit, "alice").is_ok());
+        assert!(limiter.take(Scope::TxSubmit, "alice").is_ok());
+        assert!(limiter.take(Scope::TxSubmit, "alice").is_err());
+    }
+
+    #[test]
+    fn test_buckets_stay_under_the_cap() {
+        // The mock clock stands still, so every bucket is as stale as the next
+        let clock = MockClock::at_unix(1_700_000_000);
+        let limiter = limiter(&clock);
+
+        for client in 0..MAX_BUCKETS * 2 {
+            assert!(limiter.take_chat(&client.to_string()).is_ok());
+            assert!(limiter.buckets.lock().unwrap().len() <= MAX_BUCKETS);
+        }
+    }
+
+    #[test]
+    fn test_the_stalest_buckets_are_evicted() {
+        let clock = MockClock::at_unix(1_700_000_000);
+        let limiter = limiter(&clock);
+
+        assert!(limiter.take(Scope::ReadOnly, "stale").is_ok());
+        for client in 1..MAX_BUCKETS - 1 {
+            clock.advance(Duration::from_millis(1));
+            assert!(limiter.take(Scope::ReadOnly, &client.to_string()).is_ok());
+        }
+        clock.advance(Duration::from_millis(1));
+        assert!(limiter.take(Scope::ReadOnly, "busy").is_ok());
+        assert!(limiter.take(Scope::ReadOnly, "busy").is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_BUCKETS);
+
+        assert!(limiter.take(Scope::ReadOnly, "newcomer").is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.len(), KEPT_BUCKETS + 1);
+        assert!(!buckets.contains_key(&(Class::Route(Scope::ReadOnly), "stale".to_string())));
+        drop(buckets);
+        // The bucket in use is kept, empty as it was
+        assert!(limiter.take(Scope::ReadOnly, "busy").is_err());
+    }
+}
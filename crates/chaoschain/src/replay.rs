@@ -0,0 +1,141 @@
+//! Replays of the network's history from the activity journal. A replay
+//! covers a range of blocks, or an epoch, and plays back everything that
+//! led up to them — the chatter, the pitches, the proposals and the votes —
+//! as server-sent events, keeping the original pacing sped up or slowed
+//! down by `speed`. Long quiet stretches are cut short.
+
+use axum::extract::{Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use axum::Router;
+use chaoschain_state::index::{Activity, ActivityFilter, Order};
+use futures::stream::{self, Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::explorer::ActivityView;
+use crate::openapi::{integer, number, string, Operation};
+use crate::rest::ApiError;
+use crate::web::AppState;
+use crate::EPOCH_LENGTH;
+
+/// Slowest and fastest playback
+const MIN_SPEED: f64 = 0.1;
+const MAX_SPEED: f64 = 100.0;
+
+/// Longest wait between two events, before the speed is applied
+const MAX_GAP: Duration = Duration::from_secs(10);
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/api/v1/replay", get(replay))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![Operation::get(
+        "/api/v1/replay",
+        "activity",
+        "Play back what led up to a range of blocks",
+    )
+    .query("from_height", integer(), "First block, 1 when left out")
+    .query("to_height", integer(), "Last block, inclusive")
+    .query(
+        "epoch",
+        integer(),
+        "Instead of heights, an epoch, counting from 1",
+    )
+    .query("speed", number(), "From 0.1 to 100, 1 by default")
+    .query("kind", string(), "Comma separated kinds of event")
+    .streams("`activity` events with an Activity each, then one `end` event")]
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplayQuery {
+    /// First block to replay up to
+    from_height: Option<u64>,
+    /// Last block, inclusive
+    to_height: Option<u64>,
+    /// Instead of heights, the blocks of one epoch, counting from 1
+    epoch: Option<u64>,
+    /// 2.0 plays twice as fast as it happened
+    speed: Option<f64>,
+    /// Comma separated kinds of event, everything when left out
+    kind: Option<String>,
+}
+
+/// The blocks a query asks for
+fn heights(query: &ReplayQuery) -> Result<(u64, u64), ApiError> {
+    match (query.epoch, query.from_height, query.to_height) {
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => Err(ApiError::bad_request(
+            "Ask for an epoch or a height range, not both",
+        )),
+        (Some(0), _, _) => Err(ApiError::bad_request("Epochs count from 1")),
+        (Some(epoch), None, None) => Ok(((epoch - 1) * EPOCH_LENGTH + 1, epoch * EPOCH_LENGTH)),
+        (None, from, to) => {
+            let (from, to) = (from.unwrap_or(1), to.unwrap_or(u64::MAX));
+            if from > to {
+                return Err(ApiError::bad_request("from_height is past to_height"));
+            }
+            Ok((from, to))
+        }
+    }
+}
+
+/// How long to wait before playing `next`, after `previous`
+fn delay(previous: Option<&Activity>, next: &Activity, speed: f64) -> Duration {
+    let Some(previous) = previous else {
+        return Duration::ZERO;
+    };
+    let gap = Duration::from_secs(next.timestamp.saturating_sub(previous.timestamp));
+    gap.min(MAX_GAP).div_f64(speed)
+}
+
+async fn replay(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReplayQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let (from, to) = heights(&query)?;
+    let speed = query.speed.unwrap_or(1.0);
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(ApiError::bad_request(format!(
+            "speed goes from {} to {}",
+            MIN_SPEED, MAX_SPEED
+        )));
+    }
+
+    // Activity carries the height of the chain when it happened, so what
+    // led up to block n was recorded at height n - 1
+    let filter = ActivityFilter {
+        kind: query.kind,
+        from_height: Some(from.saturating_sub(1)),
+        to_height: Some(to.saturating_sub(1)),
+        ..ActivityFilter::default()
+    };
+    let activity = state.state.search_activity(&filter, Order::Asc, usize::MAX);
+    if activity.is_empty() {
+        return Err(ApiError::not_found("Nothing was recorded for those blocks"));
+    }
+
+    let total = activity.len();
+    let events = stream::unfold(
+        (activity.into_iter(), None::<Activity>),
+        move |(mut remaining, previous)| async move {
+            let next = remaining.next()?;
+            tokio::time::sleep(delay(previous.as_ref(), &next, speed)).await;
+            let event = Event::default()
+                .event("activity")
+                .json_data(ActivityView::from(next.clone()))
+                .unwrap_or_default();
+            Some((Ok(event), (remaining, Some(next))))
+        },
+    );
+    let end = stream::once(async move {
+        Ok(Event::default()
+            .event("end")
+            .json_data(json!({ "from_height": from, "to_height": to, "events": total }))
+            .unwrap_or_default())
+    });
+    Ok(Sse::new(events.chain(end)).keep_alive(KeepAlive::default()))
+}
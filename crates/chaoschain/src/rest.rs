@@ -0,0 +1,918 @@
+//! The stable REST interface of the node, under `/api/v1`. Blocks,
+//! transactions, validators and agents each have one JSON schema here,
+//! whatever endpoint returns them, and every error comes in the same
+//! envelope: `{"error": {"code": "not_found", "message": "..."}}`, with
+//! a `data` object when there is more to say. Lists
+//! page by cursor, see [`crate::page`]. The web UI and external tools
+//! should use these rather than the older routes.
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chaoschain_agent::AgentUsage;
+use chaoschain_consensus::{RoundEvent, RoundRecord, VoteRecord};
+use chaoschain_core::relationships::RelationshipEdge;
+use chaoschain_core::{Block, Transaction};
+use chaoschain_state::index;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::lifecycle::{AgentInfo, AgentRole};
+use crate::openapi::{
+    any_object, array, boolean, hex, integer, nullable, number, one_of, page_of, schema_ref,
+    string, ApiSchema, Object, Operation,
+};
+use crate::page::{Page, PageQuery};
+use crate::rpc::{self, SubmitError};
+use crate::web::AppState;
+use chaoschain_mempool::Rejection;
+
+/// Blocks listed when no limit is asked for
+const DEFAULT_BLOCK_LIMIT: usize = 10;
+
+/// Validators listed when no limit is asked for
+const DEFAULT_VALIDATOR_LIMIT: usize = 50;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/v1/blocks", get(list_blocks))
+        .route("/api/v1/blocks/:height", get(get_block))
+        .route("/api/v1/blocks/:height/consensus", get(get_consensus))
+        .route("/api/v1/txs", post(submit_transaction))
+        .route("/api/v1/txs/:hash", get(get_transaction))
+        .route("/api/v1/validators", get(list_validators))
+        .route("/api/v1/agents", get(list_agents))
+        .route("/api/v1/agents/:id", get(get_agent))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![
+        Operation::get("/api/v1/blocks", "blocks", "List the blocks")
+            .paged()
+            .returns(page_of::<BlockView>()),
+        Operation::get("/api/v1/blocks/{height}", "blocks", "A block by its height")
+            .path_param("height", string(), "A height, or `latest`")
+            .returns(schema_ref::<BlockView>()),
+        Operation::get(
+            "/api/v1/blocks/{height}/consensus",
+            "blocks",
+            "How the vote on the blocks at a height went, vote by vote",
+        )
+        .path_param("height", string(), "A height, or `latest`")
+        .returns(schema_ref::<ConsensusView>()),
+        Operation::get(
+            "/api/v1/txs/{hash}",
+            "transactions",
+            "A transaction by its hash",
+        )
+        .path_param("hash", hex(), "32 hex encoded bytes")
+        .returns(schema_ref::<TransactionView>()),
+        Operation::post(
+            "/api/v1/txs",
+            "transactions",
+            "Submit a signed transaction",
+            schema_ref::<SubmitRequest>(),
+        )
+        .returns(schema_ref::<TransactionView>()),
+        Operation::get("/api/v1/validators", "validators", "List the validators")
+            .paged()
+            .returns(schema_ref::<ValidatorPage>()),
+        Operation::get(
+            "/api/v1/agents",
+            "agents",
+            "The agents running on this node",
+        )
+        .returns(array(schema_ref::<AgentInfo>())),
+        Operation::get(
+            "/api/v1/agents/{id}",
+            "agents",
+            "An agent, its usage and relationships",
+        )
+        .path_param("id", string(), "Agent id, e.g. `validator-1`")
+        .returns(schema_ref::<AgentView>()),
+    ]
+}
+
+/// What went wrong, in the envelope every endpoint uses
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    /// Stable, machine readable
+    pub code: &'static str,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+}
+
+/// Reasons a transaction is turned down for, as the error `code`
+const REJECTIONS: &[&str] = &[
+    "no_mempool",
+    "bad_signature",
+    "nonce_too_low",
+    "nonce_gap",
+    "duplicate",
+    "pool_full",
+    "banned_sender",
+];
+
+impl From<SubmitError> for ApiError {
+    fn from(e: SubmitError) -> Self {
+        let status = match &e {
+            SubmitError::NoMempool | SubmitError::Rejected(Rejection::PoolFull) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            SubmitError::BadSignature(_)
+            | SubmitError::BadCommitment(_)
+            | SubmitError::BadProof(_)
+            | SubmitError::BadProposal(_)
+            | SubmitError::BadStake(_) => StatusCode::BAD_REQUEST,
+            SubmitError::Rejected(Rejection::BannedSender) => StatusCode::FORBIDDEN,
+            SubmitError::Rejected(_) => StatusCode::CONFLICT,
+        };
+        let mut error = Self::new(status, e.reason(), e.to_string());
+        if let Some(nonce) = e.expected_nonce() {
+            error.data = Some(json!({ "expected_nonce": nonce }));
+        }
+        error
+    }
+}
+
+impl ApiSchema for ApiError {
+    const NAME: &'static str = "Error";
+
+    fn schema() -> Value {
+        let codes: Vec<&str> = ["bad_request", "not_found", "rate_limited"]
+            .into_iter()
+            .chain(REJECTIONS.iter().copied())
+            .collect();
+        let error = Object::new()
+            .field("code", one_of(&codes))
+            .field("message", string())
+            .optional(
+                "data",
+                Object::new()
+                    .optional("expected_nonce", integer())
+                    .optional("retry_after", integer())
+                    .open()
+                    .build(),
+            )
+            .build();
+        Object::new().field("error", error).build()
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = json!({
+            "error": {
+                "code": self.code,
+                "message": self.message,
+            }
+        });
+        if let Some(data) = self.data {
+            body["error"]["data"] = data;
+        }
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    /// Waiting in the mempool
+    Pending,
+    /// In a processed block
+    Included,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransactionView {
+    pub hash: String,
+    pub sender: String,
+    pub nonce: u64,
+    /// Hex encoded
+    pub payload: String,
+    pub signature: String,
+    pub status: TransactionStatus,
+    pub block_height: Option<u64>,
+    pub block_hash: Option<String>,
+}
+
+impl TransactionView {
+    pub fn new(tx: &Transaction, block: Option<&Block>) -> Self {
+        Self {
+            hash: hex::encode(tx.hash()),
+            sender: hex::encode(tx.sender),
+            nonce: tx.nonce,
+            payload: hex::encode(&tx.payload),
+            signature: hex::encode(tx.signature),
+            status: match block {
+                Some(_) => TransactionStatus::Included,
+                None => TransactionStatus::Pending,
+            },
+            block_height: block.map(|block| block.height),
+            block_hash: block.map(|block| hex::encode(block.hash())),
+        }
+    }
+}
+
+impl ApiSchema for TransactionView {
+    const NAME: &'static str = "Transaction";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("hash", hex())
+            .field("sender", hex())
+            .field("nonce", integer())
+            .field("payload", hex())
+            .field("signature", hex())
+            .field("status", one_of(&["pending", "included"]))
+            .field("block_height", nullable(integer()))
+            .field("block_hash", nullable(hex()))
+            .build()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockView {
+    pub height: u64,
+    pub hash: String,
+    pub parent_hash: String,
+    pub state_root: String,
+    pub timestamp: u64,
+    pub producer: String,
+    pub producer_mood: String,
+    pub producer_strategy: String,
+    pub drama_level: u8,
+    pub innovation_level: u8,
+    /// Hashes of the transactions, in block order
+    pub transactions: Vec<String>,
+    /// Randomness of the block, once it has a group signature
+    pub beacon: Option<String>,
+}
+
+impl BlockView {
+    pub fn new(block: &Block) -> Self {
+        Self {
+            height: block.height,
+            hash: hex::encode(block.hash()),
+            parent_hash: hex::encode(block.parent_hash),
+            state_root: hex::encode(block.state_root),
+            timestamp: block.timestamp,
+            producer: block.producer_id.clone(),
+            producer_mood: block.producer_mood.clone(),
+            producer_strategy: block.producer_strategy.clone(),
+            drama_level: block.drama_level,
+            innovation_level: block.innovation_level,
+            transactions: block
+                .transactions
+                .iter()
+                .map(|tx| hex::encode(tx.hash()))
+                .collect(),
+            beacon: block
+                .metadata
+                .beacon
+                .as_ref()
+                .map(|beacon| hex::encode(beacon.0)),
+        }
+    }
+}
+
+impl ApiSchema for BlockView {
+    const NAME: &'static str = "Block";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("height", integer())
+            .field("hash", hex())
+            .field("parent_hash", hex())
+            .field("state_root", hex())
+            .field("timestamp", integer())
+            .field("producer", string())
+            .field("producer_mood", string())
+            .field("producer_strategy", string())
+            .field("drama_level", integer())
+            .field("innovation_level", integer())
+            .field("transactions", array(hex()))
+            .field("beacon", nullable(hex()))
+            .build()
+    }
+}
+
+/// The voting rounds held at one height. There is more than one when a
+/// new proposal took over before a round finalized.
+#[derive(Debug, Serialize)]
+pub struct ConsensusView {
+    pub height: u64,
+    /// Share of the voting stake that has to approve
+    pub threshold: f64,
+    pub rounds: Vec<RoundView>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoundView {
+    pub block_hash: String,
+    /// Unix milliseconds
+    pub started_at: u64,
+    /// When the approving stake reached the threshold
+    pub finalized_at: Option<u64>,
+    pub approving_stake: u64,
+    pub rejecting_stake: u64,
+    pub votes: Vec<VoteView>,
+    /// Timeouts and view changes, in order
+    pub events: Vec<RoundEventView>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VoteView {
+    pub validator: String,
+    pub approved: bool,
+    pub drama_level: u8,
+    pub stake: u64,
+    /// Unix milliseconds
+    pub received_at: u64,
+    /// Since the round started
+    pub latency_ms: u64,
+    /// SHA-256 of the signed explanation, none for unsigned votes
+    pub justification_hash: Option<String>,
+    /// Signed into the aggregate the block was finalized with
+    pub in_aggregate: bool,
+    /// Counted once the threshold was already reached
+    pub late: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoundEventView {
+    /// A validator missed its decision deadline
+    Timeout {
+        validator: String,
+        /// `approve`, `reject` or `abstain`
+        fallback: String,
+        at: u64,
+    },
+    /// A new proposal took over before the round finalized
+    ViewChange {
+        to_block: String,
+        to_height: u64,
+        at: u64,
+    },
+}
+
+impl RoundView {
+    pub fn new(round: &RoundRecord) -> Self {
+        let stake = |approved: bool| {
+            round
+                .votes
+                .iter()
+                .filter(|vote| vote.approved == approved)
+                .map(|vote| vote.stake)
+                .sum()
+        };
+        Self {
+            block_hash: hex::encode(round.block_hash),
+            started_at: round.started_at,
+            finalized_at: round.reached_at,
+            approving_stake: stake(true),
+            rejecting_stake: stake(false),
+            votes: round
+                .votes
+                .iter()
+                .map(|vote| VoteView::new(vote, &round.aggregate))
+                .collect(),
+            events: round.events.iter().map(RoundEventView::new).collect(),
+        }
+    }
+}
+
+impl VoteView {
+    fn new(vote: &VoteRecord, aggregate: &[String]) -> Self {
+        Self {
+            validator: vote.validator.clone(),
+            approved: vote.approved,
+            drama_level: vote.drama_level,
+            stake: vote.stake,
+            received_at: vote.received_at,
+            latency_ms: vote.latency_ms,
+            justification_hash: vote.explanation_hash.map(hex::encode),
+            in_aggregate: vote.approved && aggregate.contains(&vote.validator),
+            late: vote.late,
+        }
+    }
+}
+
+impl RoundEventView {
+    fn new(event: &RoundEvent) -> Self {
+        match event {
+            RoundEvent::Timeout {
+                validator,
+                fallback,
+                at,
+            } => Self::Timeout {
+                validator: validator.clone(),
+                fallback: fallback.clone(),
+                at: *at,
+            },
+            RoundEvent::ViewChange {
+                to_block,
+                to_height,
+                at,
+            } => Self::ViewChange {
+                to_block: hex::encode(to_block),
+                to_height: *to_height,
+                at: *at,
+            },
+        }
+    }
+}
+
+impl ApiSchema for ConsensusView {
+    const NAME: &'static str = "Consensus";
+
+    fn schema() -> Value {
+        let vote = Object::new()
+            .field("validator", string())
+            .field("approved", boolean())
+            .field("drama_level", integer())
+            .field("stake", integer())
+            .field("received_at", integer())
+            .field("latency_ms", integer())
+            .field("justification_hash", nullable(hex()))
+            .field("in_aggregate", boolean())
+            .field("late", boolean())
+            .build();
+        let event = Object::new()
+            .field("type", one_of(&["timeout", "view_change"]))
+            .field("at", integer())
+            .optional("validator", string())
+            .optional("fallback", one_of(&["approve", "reject", "abstain"]))
+            .optional("to_block", hex())
+            .optional("to_height", integer())
+            .build();
+        let round = Object::new()
+            .field("block_hash", hex())
+            .field("started_at", integer())
+            .field("finalized_at", nullable(integer()))
+            .field("approving_stake", integer())
+            .field("rejecting_stake", integer())
+            .field("votes", array(vote))
+            .field("events", array(event))
+            .build();
+        Object::new()
+            .field("height", integer())
+            .field("threshold", number())
+            .field("rounds", array(round))
+            .build()
+    }
+}
+
+/// Where a validator runs
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidatorSource {
+    /// An agent of this node
+    Local,
+    /// An agent voting through the gateway
+    Gateway,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidatorView {
+    pub id: String,
+    pub source: ValidatorSource,
+    /// Hex encoded ed25519 key, for gateway validators
+    pub public_key: Option<String>,
+    /// Since when, in unix seconds
+    pub since: u64,
+    /// Suspended validators are no longer heard
+    pub suspended: bool,
+}
+
+impl ApiSchema for ValidatorView {
+    const NAME: &'static str = "Validator";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("id", string())
+            .field("source", one_of(&["local", "gateway"]))
+            .field("public_key", nullable(hex()))
+            .field("since", integer())
+            .field("suspended", boolean())
+            .build()
+    }
+}
+
+/// A page of the validator set
+#[derive(Debug, Serialize)]
+pub struct ValidatorPage {
+    /// Validators the consensus engine counts
+    pub count: usize,
+    #[serde(flatten)]
+    pub page: Page<ValidatorView>,
+}
+
+impl ApiSchema for ValidatorPage {
+    const NAME: &'static str = "ValidatorPage";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("count", integer())
+            .field("items", array(schema_ref::<ValidatorView>()))
+            .field("next_cursor", nullable(string()))
+            .build()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidatorSet {
+    /// Validators the consensus engine counts
+    pub count: usize,
+    pub validators: Vec<ValidatorView>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AgentView {
+    #[serde(flatten)]
+    pub info: AgentInfo,
+    pub usage: Option<AgentUsage>,
+    /// How the agent feels about the others
+    pub relationships: Vec<RelationshipEdge>,
+}
+
+impl ApiSchema for AgentView {
+    const NAME: &'static str = "AgentDetails";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("id", string())
+            .field("role", one_of(&["validator", "producer"]))
+            .field("generation", integer())
+            .field("started_at", integer())
+            .field(
+                "usage",
+                nullable(any_object("Model calls, tokens and cost of the agent")),
+            )
+            .field(
+                "relationships",
+                array(any_object(
+                    "Edge from or to the agent, with trust and history",
+                )),
+            )
+            .open()
+            .build()
+    }
+}
+
+/// A transaction by its hash, with the block it is in unless it is
+/// still in the mempool
+pub async fn find_transaction(
+    state: &AppState,
+    hash: &[u8; 32],
+) -> Option<(Transaction, Option<Block>)> {
+    if let Some(mempool) = &state.mempool {
+        if let Some(proposal) = mempool.get_proposals_for_transaction(hash).await {
+            return Some((proposal.transaction.into_inner(), None));
+        }
+    }
+    state
+        .state
+        .get_transaction(hash)
+        .map(|(tx, block)| (tx, Some(block)))
+}
+
+/// The local validators and those voting through the gateway
+pub async fn validator_set(state: &AppState) -> ValidatorSet {
+    let mut validators: Vec<ValidatorView> = match &state.agents {
+        Some(supervisor) => supervisor
+            .list()
+            .await
+            .into_iter()
+            .filter(|agent| agent.role == AgentRole::Validator)
+            .map(|agent| ValidatorView {
+                id: agent.id,
+                source: ValidatorSource::Local,
+                public_key: None,
+                since: agent.started_at,
+                suspended: agent.paused,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    if let Some(gateway) = &state.gateway {
+        validators.extend(gateway.agents().into_iter().map(|agent| ValidatorView {
+            id: agent.agent_id,
+            source: ValidatorSource::Gateway,
+            public_key: Some(agent.public_key),
+            since: agent.registered_at,
+            suspended: agent.suspended,
+        }));
+    }
+    ValidatorSet {
+        count: state.consensus.get_validator_count().await,
+        validators,
+    }
+}
+
+/// The blocks, newest first by default
+async fn list_blocks(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<BlockView>>, ApiError> {
+    let (cursor, order) = query.position()?;
+    let limit = query.limit(DEFAULT_BLOCK_LIMIT);
+    let blocks = state.state.blocks_page(cursor, order, limit + 1);
+    Ok(Json(
+        Page::new(blocks, limit, order, |block| block.height).map(|block| BlockView::new(&block)),
+    ))
+}
+
+/// A block by its height, or the latest one
+async fn get_block(
+    State(state): State<Arc<AppState>>,
+    Path(height): Path<String>,
+) -> Result<Json<BlockView>, ApiError> {
+    let block = if height == "latest" {
+        state.state.get_latest_block()
+    } else {
+        let height: u64 = height
+            .parse()
+            .map_err(|_| ApiError::bad_request(format!("Invalid block height: {}", height)))?;
+        state
+            .state
+            .get_blocks(height, 1)
+            .into_iter()
+            .find(|block| block.height == height)
+    };
+    block
+        .map(|block| Json(BlockView::new(&block)))
+        .ok_or_else(|| ApiError::not_found(format!("No block at {}", height)))
+}
+
+async fn get_consensus(
+    State(state): State<Arc<AppState>>,
+    Path(height): Path<String>,
+) -> Result<Json<ConsensusView>, ApiError> {
+    let height = if height == "latest" {
+        state
+            .consensus
+            .get_current_block()
+            .await
+            .map(|block| block.height)
+            .ok_or_else(|| ApiError::not_found("No block was put to a vote yet"))?
+    } else {
+        height
+            .parse()
+            .map_err(|_| ApiError::bad_request(format!("Invalid block height: {}", height)))?
+    };
+    let rounds = state.consensus.rounds_at(height);
+    if rounds.is_empty() {
+        return Err(ApiError::not_found(format!(
+            "No voting round held at {}",
+            height
+        )));
+    }
+    Ok(Json(ConsensusView {
+        height,
+        threshold: state.consensus.threshold(),
+        rounds: rounds.iter().map(RoundView::new).collect(),
+    }))
+}
+
+async fn get_transaction(
+    State(state): State<Arc<AppState>>,
+    Path(hash): Path<String>,
+) -> Result<Json<TransactionView>, ApiError> {
+    let bytes: [u8; 32] = hex::decode(hash.strip_prefix("0x").unwrap_or(&hash))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| ApiError::bad_request("A transaction hash is 32 hex encoded bytes"))?;
+    let (tx, block) = find_transaction(&state, &bytes)
+        .await
+        .ok_or_else(|| ApiError::not_found(format!("No transaction {}", hash)))?;
+    Ok(Json(TransactionView::new(&tx, block.as_ref())))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitRequest {
+    /// The transaction's JSON, hex encoded, as `chain_sendRawTransaction`
+    /// takes it
+    pub raw: String,
+}
+
+impl ApiSchema for SubmitRequest {
+    const NAME: &'static str = "SubmitTransaction";
+
+    fn schema() -> Value {
+        Object::new().field("raw", hex()).build()
+    }
+}
+
+/// Take a signed transaction into the mempool. It comes back as it waits
+/// there, or in an error whose code says why it was turned down.
+async fn submit_transaction(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SubmitRequest>,
+) -> Result<(StatusCode, Json<TransactionView>), ApiError> {
+    let tx = rpc::decode_transaction(&request.raw).map_err(ApiError::bad_request)?;
+    let view = TransactionView::new(&tx, None);
+    rpc::submit_transaction(&state, tx).await?;
+    Ok((StatusCode::ACCEPTED, Json(view)))
+}
+
+/// The validators by when they joined, newest first by default
+async fn list_validators(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<ValidatorPage>, ApiError> {
+    let (cursor, order) = query.position::<(u64, String)>()?;
+    let limit = query.limit(DEFAULT_VALIDATOR_LIMIT);
+    let set = validator_set(&state).await;
+    let mut validators = set.validators;
+    validators.sort_by(|a, b| (a.since, &a.id).cmp(&(b.since, &b.id)));
+    let key = |validator: &ValidatorView| (validator.since, validator.id.clone());
+    let validators = index::page(&validators, key, cursor, order, limit + 1);
+    Ok(Json(ValidatorPage {
+        count: set.count,
+        page: Page::new(validators, limit, order, key),
+    }))
+}
+
+/// The agents running on this node
+async fn list_agents(State(state): State<Arc<AppState>>) -> Json<Vec<AgentInfo>> {
+    match &state.agents {
+        Some(supervisor) => Json(supervisor.list().await),
+        None => Json(Vec::new()),
+    }
+}
+
+async fn get_agent(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<AgentView>, ApiError> {
+    let agents = match &state.agents {
+        Some(supervisor) => supervisor.list().await,
+        None => Vec::new(),
+    };
+    let info = agents
+        .into_iter()
+        .find(|agent| agent.id == id)
+        .ok_or_else(|| ApiError::not_found(format!("No agent {}", id)))?;
+    Ok(Json(AgentView {
+        usage: state.usage.agent(&id),
+        relationships: state.relationships.relationships_of(&id),
+        info,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::tests::app_state;
+    use crate::web::WebServices;
+    use axum::body::Body;
+    use axum::http::Request;
+    use chaoschain_mempool::Mempool;
+    use chaoschain_testutil::{fixtures, BlockBuilder, TransactionBuilder};
+    use tower::ServiceExt;
+
+    /// The status and JSON body `request` gets from the REST routes
+    async fn call(state: &Arc<AppState>, request: Request<Body>) -> (StatusCode, Value) {
+        let response = routes()
+            .with_state(state.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    /// A node knowing the fixtures' sender, whose transactions blocks carry
+    fn node() -> Arc<AppState> {
+        let state = app_state(&WebServices::default());
+        state.state.key_manager.inner().register_public_key(
+            &fixtures::key(fixtures::SENDER).verifying_key(),
+            fixtures::SENDER.to_string(),
+            "sender".to_string(),
+        );
+        state
+    }
+
+    async fn get(state: &Arc<AppState>, uri: &str) -> (StatusCode, Value) {
+        call(state, Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    async fn submit(state: &Arc<AppState>, raw: &str) -> (StatusCode, Value) {
+        let request = Request::post("/api/v1/txs")
+            .header("content-type", "application/json")
+            .body(Body::from(json!({ "raw": raw }).to_string()))
+            .unwrap();
+        call(state, request).await
+    }
+
+    #[tokio::test]
+    async fn test_blocks_and_their_transactions() {
+        let state = node();
+        let tx = fixtures::transaction(0);
+        let block = BlockBuilder::new()
+            .height(1)
+            .transaction(tx.clone())
+            .build();
+        state.state.apply_block(&block).unwrap();
+
+        let (status, body) = get(&state, "/api/v1/blocks/1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["height"], 1);
+        assert_eq!(body["hash"], hex::encode(block.hash()));
+        assert_eq!(body["transactions"], json!([hex::encode(tx.hash())]));
+        let (_, latest) = get(&state, "/api/v1/blocks/latest").await;
+        assert_eq!(latest, body);
+
+        let (status, body) = get(&state, "/api/v1/blocks").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["items"][0]["height"], 1);
+
+        let uri = format!("/api/v1/txs/0x{}", hex::encode(tx.hash()));
+        let (status, body) = get(&state, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "included");
+        assert_eq!(body["block_height"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_what_is_not_there_or_malformed() {
+        let state = node();
+        state.state.apply_block(&fixtures::block(1)).unwrap();
+
+        let (status, body) = get(&state, "/api/v1/blocks/7").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "not_found");
+        assert_eq!(body["error"]["message"], "No block at 7");
+        let (status, body) = get(&state, "/api/v1/blocks/seven").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "bad_request");
+
+        let (status, body) = get(&state, "/api/v1/txs/0x1234").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "bad_request");
+        let uri = format!("/api/v1/txs/{}", hex::encode([7u8; 32]));
+        let (status, body) = get(&state, &uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "not_found");
+        let (status, _) = get(&state, "/api/v1/agents/nobody").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_submitted_transactions_wait_in_the_mempool() {
+        let state = app_state(&WebServices {
+            mempool: Some(Arc::new(Mempool::new(100))),
+            ..WebServices::default()
+        });
+        let tx = TransactionBuilder::new()
+            .signer(&fixtures::key("rest-sender"))
+            .build();
+        let raw = hex::encode(serde_json::to_vec(&tx).unwrap());
+
+        let (status, body) = submit(&state, &raw).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["hash"], hex::encode(tx.hash()));
+        assert_eq!(body["status"], "pending");
+        let (status, body) = get(
+            &state,
+            &format!("/api/v1/txs/{}", body["hash"].as_str().unwrap()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "pending");
+
+        let (_, body) = submit(&state, &raw).await;
+        assert_eq!(body["error"]["code"], "duplicate");
+        let (status, body) = submit(&state, "not hex").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "bad_request");
+    }
+}
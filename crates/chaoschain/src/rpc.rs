@@ -0,0 +1,764 @@
+//! JSON-RPC 2.0 endpoint of the web server, at `POST /rpc`. Method names
+//! follow the Ethereum ones where there is an equivalent (`chain_*`), so
+//! existing clients are easy to point at the chain, and the chaos of the
+//! chain gets methods of its own (`chaos_*`). Hashes and raw bytes are hex
+//! with a `0x` prefix. Block numbers may be numbers, hex strings, `latest`
+//! or `earliest`. Requests can be batched, up to `rpc_max_batch` of them,
+//! and each is answered on its own, so one that fails leaves the others be.
+//! The signatures of the transactions sent in a batch are checked together,
+//! once the caller is known to be allowed to send them.
+//! Sending transactions takes an API token with the tx-submit scope when
+//! the node hands out tokens.
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
+use axum::Json;
+use chaoschain_cli::Scope;
+use chaoschain_core::multisig::MultisigAccounts;
+use chaoschain_core::sync::SyncState;
+use chaoschain_core::{Block, Transaction};
+use chaoschain_crypto::batch;
+use chaoschain_mempool::{Mempool, MempoolUsage, Rejection};
+use chaoschain_state::index::Order;
+use chaoschain_state::StateStoreImpl;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::auth::Granted;
+use crate::rest;
+use crate::stats::now_ms;
+use crate::web::AppState;
+
+/// Methods the endpoint answers, as `rpc_methods` lists them
+pub const METHODS: &[&str] = &[
+    "chain_blockNumber",
+    "chain_getBlockByNumber",
+    "chain_getBlockByHash",
+    "chain_getTransaction",
+    "chain_getTransactionCount",
+    "chain_sendRawTransaction",
+    "chaos_dramaLevel",
+    "chaos_pendingBlock",
+    "chaos_validators",
+    "chaos_relationships",
+    "chaos_syncState",
+    "chaos_nodeStatus",
+    "chaos_getRandomness",
+    "chaos_getReveal",
+    "rpc_methods",
+];
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+/// A transaction the node would not take
+const TRANSACTION_REJECTED: i64 = -32000;
+/// A method the caller's API token does not reach
+const NOT_ALLOWED: i64 = -32001;
+
+/// Validators voting within it count as active in `chaos_nodeStatus`
+const ACTIVE_WINDOW_MS: u64 = 5 * 60 * 1000;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    /// Absent for notifications, which get no answer
+    id: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+impl RpcResponse {
+    fn new(id: Value, outcome: Result<Value, RpcError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: "2.0",
+            result,
+            error,
+            id,
+        }
+    }
+}
+
+/// A transaction as the RPC shows it
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcTransaction {
+    pub hash: String,
+    pub sender: String,
+    pub nonce: u64,
+    pub payload: String,
+    pub signature: String,
+    /// `None` while the transaction waits in the mempool
+    pub block_number: Option<u64>,
+    pub block_hash: Option<String>,
+}
+
+impl RpcTransaction {
+    fn new(tx: &Transaction, block: Option<&Block>) -> Self {
+        Self {
+            hash: to_hex(&tx.hash()),
+            sender: to_hex(&tx.sender),
+            nonce: tx.nonce,
+            payload: to_hex(&tx.payload),
+            signature: to_hex(&tx.signature),
+            block_number: block.map(|block| block.height),
+            block_hash: block.map(|block| to_hex(&block.hash())),
+        }
+    }
+}
+
+/// A block as the RPC shows it
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcBlock {
+    pub number: u64,
+    pub hash: String,
+    pub parent_hash: String,
+    pub state_root: String,
+    pub timestamp: u64,
+    pub producer: String,
+    pub producer_mood: String,
+    pub producer_strategy: String,
+    pub drama_level: u8,
+    pub innovation_level: u8,
+    /// Hashes, or whole transactions when asked for
+    pub transactions: Vec<Value>,
+    /// Randomness of the block, once it has a group signature
+    pub beacon: Option<String>,
+}
+
+impl RpcBlock {
+    fn new(block: &Block, full: bool) -> Self {
+        Self {
+            number: block.height,
+            hash: to_hex(&block.hash()),
+            parent_hash: to_hex(&block.parent_hash),
+            state_root: to_hex(&block.state_root),
+            timestamp: block.timestamp,
+            producer: block.producer_id.clone(),
+            producer_mood: block.producer_mood.clone(),
+            producer_strategy: block.producer_strategy.clone(),
+            drama_level: block.drama_level,
+            innovation_level: block.innovation_level,
+            transactions: block
+                .transactions
+                .iter()
+                .map(|tx| {
+                    if full {
+                        json!(RpcTransaction::new(tx, Some(block)))
+                    } else {
+                        json!(to_hex(&tx.hash()))
+                    }
+                })
+                .collect(),
+            beacon: block
+                .metadata
+                .beacon
+                .as_ref()
+                .map(|beacon| to_hex(&beacon.0)),
+        }
+    }
+}
+
+/// What `chaos_nodeStatus` answers, for `chaoschain status`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeStatus {
+    /// Highest block the node has
+    pub head: u64,
+    /// Latest block finalized while the node was up
+    pub finalized: Option<u64>,
+    /// Validators heard from lately, of those in the set
+    pub peers: usize,
+    pub validators: usize,
+    pub partitioned: bool,
+    pub sync: SyncState,
+    /// `None` when the node keeps no mempool
+    pub mempool: Option<MempoolUsage>,
+    /// Mean share of the blocks validators could vote on that they did
+    pub participation: Option<f64>,
+    /// Validators that voted in the last five minutes
+    pub active_validators: usize,
+}
+
+impl NodeStatus {
+    async fn new(state: &AppState) -> Self {
+        let summary = state.chain.lock().summary(ACTIVE_WINDOW_MS, now_ms());
+        let partition = state.partition.status();
+        let mempool = match &state.mempool {
+            Some(mempool) => Some(mempool.usage().await),
+            None => None,
+        };
+        let participation: Vec<f64> = state
+            .state
+            .validator_stats()
+            .iter()
+            .filter(|stats| stats.eligible > 0)
+            .map(|stats| stats.participation())
+            .collect();
+        Self {
+            head: latest_height(state).max(summary.height.unwrap_or(0)),
+            finalized: summary.height,
+            peers: partition.reachable,
+            validators: partition.validators,
+            partitioned: partition.partitioned,
+            sync: state.consensus.sync_status().state(),
+            mempool,
+            participation: (!participation.is_empty())
+                .then(|| participation.iter().sum::<f64>() / participation.len() as f64),
+            active_validators: summary.active_validators,
+        }
+    }
+}
+
+/// Answer a request, or a batch of them
+pub async fn handle_rpc(
+    State(state): State<Arc<AppState>>,
+    Extension(granted): Extension<Granted>,
+    body: String,
+) -> Response {
+    let request: Value = match serde_json::from_str(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = RpcError::new(PARSE_ERROR, format!("Parse error: {}", e));
+            return Json(RpcResponse::new(Value::Null, Err(error))).into_response();
+        }
+    };
+    match request {
+        Value::Array(batch) => {
+            if let Some(error) = refuse_batch(&batch, state.api.rpc_max_batch) {
+                return Json(RpcResponse::new(Value::Null, Err(error))).into_response();
+            }
+            let signed = signed_transactions(&state, granted, &batch).await;
+            // In order, so that transactions of one sender keep their nonces
+            let mut responses = Vec::new();
+            for request in batch {
+                responses.extend(answer(&state, granted, request, &signed).await);
+            }
+            if responses.is_empty() {
+                StatusCode::NO_CONTENT.into_response()
+            } else {
+                Json(responses).into_response()
+            }
+        }
+        request => match answer(&state, granted, request, &HashSet::new()).await {
+            Some(response) => Json(response).into_response(),
+            None => StatusCode::NO_CONTENT.into_response(),
+        },
+    }
+}
+
+/// Why a batch is turned away as a whole, if it is: empty, or of more than
+/// `max_batch` requests
+fn refuse_batch(batch: &[Value], max_batch: usize) -> Option<RpcError> {
+    if batch.is_empty() {
+        return Some(RpcError::new(INVALID_REQUEST, "Empty batch"));
+    }
+    if batch.len() <= max_batch {
+        return None;
+    }
+    let mut error = RpcError::new(
+        INVALID_REQUEST,
+        format!(
+            "Batch of {} requests, at most {} are taken",
+            batch.len(),
+            max_batch
+        ),
+    );
+    error.data = Some(json!({ "max_batch": max_batch }));
+    Some(error)
+}
+
+/// The transactions sent in `batch` whose signatures are checked together.
+/// None for callers who may not send transactions, whose requests are
+/// turned down before any signature is looked at. Those of multisig
+/// accounts are checked one by one.
+fn batched_transactions(granted: Granted, batch: &[Value]) -> Vec<Transaction> {
+    if !granted.allows(Scope::TxSubmit) {
+        return Vec::new();
+    }
+    batch
+        .iter()
+        .filter(|request| request["method"] == "chain_sendRawTransaction")
+        .filter_map(|request| param(&request["params"], 0)?.as_str())
+        .filter_map(|raw| decode_transaction(raw).ok())
+        .filter(|tx| tx.cosignatures.is_empty())
+        .collect()
+}
+
+/// Hashes of the [`batched_transactions`] whose signatures hold, checked
+/// in one go on the crypto pool
+async fn signed_transactions(
+    state: &AppState,
+    granted: Granted,
+    batch: &[Value],
+) -> HashSet<[u8; 32]> {
+    let txs = batched_transactions(granted, batch);
+    if txs.is_empty() {
+        return HashSet::new();
+    }
+    let items: Vec<batch::Item> = txs
+        .iter()
+        .map(|tx| batch::Item::new(tx.sender, tx.signing_bytes(), &tx.signature))
+        .collect();
+    let invalid = state
+        .state
+        .pools()
+        .verify(move || batch::verify(&items))
+        .await
+        .err()
+        .map_or_else(Vec::new, |e| e.invalid);
+    txs.iter()
+        .enumerate()
+        .filter(|(index, _)| !invalid.contains(index))
+        .map(|(_, tx)| tx.hash())
+        .collect()
+}
+
+/// Answer one request. Transactions whose hash is in `signed` had their
+/// signature checked with the rest of their batch.
+async fn answer(
+    state: &AppState,
+    granted: Granted,
+    request: Value,
+    signed: &HashSet<[u8; 32]>,
+) -> Option<RpcResponse> {
+    let request: RpcRequest = match serde_json::from_value(request) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = RpcError::new(INVALID_REQUEST, format!("Invalid request: {}", e));
+            return Some(RpcResponse::new(Value::Null, Err(error)));
+        }
+    };
+    let outcome = if request.jsonrpc != "2.0" {
+        Err(RpcError::new(
+            INVALID_REQUEST,
+            "Only JSON-RPC 2.0 is spoken",
+        ))
+    } else if request.method == "chain_sendRawTransaction" && !granted.allows(Scope::TxSubmit) {
+        Err(RpcError::new(
+            NOT_ALLOWED,
+            format!("{} needs the {} scope", request.method, Scope::TxSubmit),
+        ))
+    } else {
+        call(state, &request.method, &request.params, signed).await
+    };
+    let id = request.id?;
+    Some(RpcResponse::new(id, outcome))
+}
+
+async fn call(
+    state: &AppState,
+    method: &str,
+    params: &Value,
+    signed: &HashSet<[u8; 32]>,
+) -> Result<Value, RpcError> {
+    match method {
+        "chain_blockNumber" => Ok(json!(latest_height(state))),
+        "chain_getBlockByNumber" => {
+            let number = block_number(state, param(params, 0))?;
+            let full = param(params, 1).and_then(Value::as_bool).unwrap_or(false);
+            Ok(state
+                .state
+                .block_by_height(number)
+                .await
+                .map_or(Value::Null, |block| json!(RpcBlock::new(&block, full))))
+        }
+        "chain_getBlockByHash" => {
+            let hash = hash_param(param(params, 0))?;
+            let full = param(params, 1).and_then(Value::as_bool).unwrap_or(false);
+            Ok(state
+                .state
+                .get_block(&hash)
+                .map_or(Value::Null, |block| json!(RpcBlock::new(&block, full))))
+        }
+        "chain_getTransaction" => {
+            let hash = hash_param(param(params, 0))?;
+            Ok(rest::find_transaction(state, &hash)
+                .await
+                .map_or(Value::Null, |(tx, block)| {
+                    json!(RpcTransaction::new(&tx, block.as_ref()))
+                }))
+        }
+        "chain_getTransactionCount" => {
+            let sender: [u8; 32] = param(params, 0)
+                .and_then(Value::as_str)
+                .and_then(|sender| from_hex(sender).ok())
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| RpcError::invalid_params("Expected a sender, 32 bytes"))?;
+            let nonce = match param(params, 1).and_then(Value::as_str) {
+                None | Some("pending") => pending_nonce(state, &sender).await,
+                Some("latest") => chain_nonce(&state.state, &sender),
+                Some(other) => {
+                    return Err(RpcError::invalid_params(format!(
+                        "Expected latest or pending, not {}",
+                        other
+                    )))
+                }
+            };
+            Ok(json!(nonce))
+        }
+        "chain_sendRawTransaction" => {
+            let raw = param(params, 0)
+                .and_then(Value::as_str)
+                .ok_or_else(|| RpcError::invalid_params("Expected the raw transaction"))?;
+            send_raw_transaction(state, raw, signed).await
+        }
+        "chaos_dramaLevel" => state
+            .consensus
+            .get_drama_level()
+            .await
+            .map(|level| json!(level))
+            .map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string())),
+        "chaos_pendingBlock" => {
+            if !state.consensus.is_voting().await {
+                return Ok(Value::Null);
+            }
+            Ok(state
+                .consensus
+                .get_current_block()
+                .await
+                .map_or(Value::Null, |block| json!(RpcBlock::new(&block, false))))
+        }
+        "chaos_validators" => Ok(json!(rest::validator_set(state).await)),
+        "chaos_relationships" => match param(params, 0).and_then(Value::as_str) {
+            Some(agent) => Ok(json!(state.relationships.relationships_of(agent))),
+            None => Ok(json!(state.relationships.snapshot())),
+        },
+        "chaos_syncState" => Ok(json!(state.consensus.sync_status().state())),
+        "chaos_nodeStatus" => Ok(json!(NodeStatus::new(state).await)),
+        "chaos_getRandomness" => {
+            let number = block_number(state, param(params, 0))?;
+            Ok(state
+                .state
+                .block_by_height(number)
+                .await
+                .and_then(|block| block.metadata.beacon)
+                .map_or(Value::Null, |beacon| json!(to_hex(&beacon.0))))
+        }
+        "chaos_getReveal" => {
+            let commit = hash_param(param(params, 0))?;
+            Ok(state
+                .state
+                .revealed(&commit)
+                .map_or(Value::Null, |payload| json!(to_hex(&payload))))
+        }
+        "rpc_methods" => Ok(json!(METHODS)),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Method not found: {}", method),
+        )),
+    }
+}
+
+/// Take a transaction signed by its sender into the mempool. Returns its
+/// hash, or an error whose data has the `reason` it was turned down, and
+/// the `expected_nonce` when that was the problem.
+async fn send_raw_transaction(
+    state: &AppState,
+    raw: &str,
+    signed: &HashSet<[u8; 32]>,
+) -> Result<Value, RpcError> {
+    let tx = decode_transaction(raw).map_err(RpcError::invalid_params)?;
+    let submitted = if signed.contains(&tx.hash()) {
+        match &state.mempool {
+            Some(mempool) => admit(&state.state, mempool, tx.clone())
+                .await
+                .inspect(|_| relay(state, tx)),
+            None => Err(SubmitError::NoMempool),
+        }
+    } else {
+        submit_transaction(state, tx).await
+    };
+    let hash = submitted.map_err(|e| RpcError {
+        code: TRANSACTION_REJECTED,
+        message: e.to_string(),
+        data: Some(json!({ "reason": e.reason(), "expected_nonce": e.expected_nonce() })),
+    })?;
+    Ok(json!(to_hex(&hash)))
+}
+
+/// A raw transaction, its JSON hex encoded
+pub fn decode_transaction(raw: &str) -> Result<Transaction, String> {
+    let bytes = from_hex(raw)?;
+    serde_json::from_slice(&bytes).map_err(|e| format!("Invalid transaction: {}", e))
+}
+
+/// Why a submitted transaction was turned down
+#[derive(Debug)]
+pub enum SubmitError {
+    NoMempool,
+    BadSignature(String),
+    /// A reveal that does not hold, or a commit that does not parse
+    BadCommitment(String),
+    /// A verification key or proof that does not hold
+    BadProof(String),
+    /// A proposal or ballot that does not parse or has nothing to vote on
+    BadProposal(String),
+    /// A bond, unbond or evidence a block would leave out
+    BadStake(String),
+    Rejected(Rejection),
+}
+
+impl SubmitError {
+    /// Stable, machine readable
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::NoMempool => "no_mempool",
+            Self::BadSignature(_) => "bad_signature",
+            Self::BadCommitment(_) => "bad_commitment",
+            Self::BadProof(_) => "bad_proof",
+            Self::BadProposal(_) => "bad_proposal",
+            Self::BadStake(_) => "bad_stake",
+            Self::Rejected(rejection) => rejection.code(),
+        }
+    }
+
+    pub fn expected_nonce(&self) -> Option<u64> {
+        match self {
+            Self::Rejected(rejection) => rejection.expected_nonce(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoMempool => write!(f, "This node keeps no mempool"),
+            Self::BadSignature(e) => write!(f, "Bad signature: {}", e),
+            Self::BadCommitment(e)
+            | Self::BadProof(e)
+            | Self::BadProposal(e)
+            | Self::BadStake(e) => e.fmt(f),
+            Self::Rejected(rejection) => rejection.fmt(f),
+        }
+    }
+}
+
+/// Check the signature and nonce of `tx` and add it to the mempool,
+/// returning its hash or why it was turned down. Peers hear of it once it
+/// is in.
+pub async fn submit_transaction(
+    state: &AppState,
+    tx: Transaction,
+) -> Result<[u8; 32], SubmitError> {
+    let mempool = state.mempool.as_ref().ok_or(SubmitError::NoMempool)?;
+    let hash = admit_transaction(&state.state, mempool, tx.clone()).await?;
+    relay(state, tx);
+    Ok(hash)
+}
+
+/// Pass a transaction the mempool took on to the peers
+fn relay(state: &AppState, tx: Transaction) {
+    if let Some(network) = &state.network {
+        network.transaction(tx);
+    }
+}
+
+/// Check the signature of `tx`, then admit it as [`admit`] does. Every
+/// transaction goes through it, from a client or from a peer.
+pub async fn admit_transaction(
+    state: &StateStoreImpl,
+    mempool: &Mempool,
+    tx: Transaction,
+) -> Result<[u8; 32], SubmitError> {
+    verify_transaction(&state.multisig_accounts(), &tx).map_err(SubmitError::BadSignature)?;
+    admit(state, mempool, tx).await
+}
+
+/// Check the nonce of `tx`, whose signature holds, and what it does to the
+/// state, see [`check_transaction`], and add it to the mempool
+async fn admit(
+    state: &StateStoreImpl,
+    mempool: &Mempool,
+    tx: Transaction,
+) -> Result<[u8; 32], SubmitError> {
+    check_transaction(state, &tx)?;
+    let hash = tx.hash();
+    let next_nonce = chain_nonce(state, &tx.sender);
+    mempool
+        .submit(tx, next_nonce)
+        .await
+        .map_err(SubmitError::Rejected)?;
+    Ok(hash)
+}
+
+/// Check the commit or reveal `tx` makes, the proof it carries, the
+/// proposal or ballot it casts and the stake it moves
+pub fn check_transaction(state: &StateStoreImpl, tx: &Transaction) -> Result<(), SubmitError> {
+    state
+        .check_commitment(tx)
+        .map_err(|e| SubmitError::BadCommitment(e.to_string()))?;
+    state
+        .check_proof(tx)
+        .map_err(|e| SubmitError::BadProof(e.to_string()))?;
+    state
+        .check_governance(tx)
+        .map_err(|e| SubmitError::BadProposal(e.to_string()))?;
+    state
+        .check_staking(tx)
+        .map_err(|e| SubmitError::BadStake(e.to_string()))
+}
+
+/// The nonce the chain expects next from `sender`
+fn chain_nonce(state: &StateStoreImpl, sender: &[u8; 32]) -> u64 {
+    state
+        .transactions_by_sender(sender, None, Order::Desc, 1)
+        .first()
+        .map_or(0, |(_, latest, _)| latest.nonce + 1)
+}
+
+/// The nonce the next transaction of `sender` takes, after those waiting
+/// in the mempool
+async fn pending_nonce(state: &AppState, sender: &[u8; 32]) -> u64 {
+    let waiting = match &state.mempool {
+        Some(mempool) => mempool.entries(Some(sender)).await,
+        None => Vec::new(),
+    };
+    waiting
+        .iter()
+        .map(|entry| entry.nonce + 1)
+        .fold(chain_nonce(&state.state, sender), u64::max)
+}
+
+/// Signed by the sender over its key, the nonce and the payload, or by
+/// enough owners when the sender is one of the multisig `accounts`, as the
+/// state checks it
+pub fn verify_transaction(accounts: &MultisigAccounts, tx: &Transaction) -> Result<(), String> {
+    accounts.verify(tx).map_err(|e| e.to_string())
+}
+
+fn latest_height(state: &AppState) -> u64 {
+    state
+        .state
+        .get_latest_block()
+        .map_or(0, |block| block.height)
+}
+
+fn param(params: &Value, index: usize) -> Option<&Value> {
+    match params {
+        Value::Array(params) => params.get(index),
+        _ => None,
+    }
+}
+
+fn block_number(state: &AppState, param: Option<&Value>) -> Result<u64, RpcError> {
+    match param {
+        None => Ok(latest_height(state)),
+        Some(Value::Number(number)) => number
+            .as_u64()
+            .ok_or_else(|| RpcError::invalid_params("Block number out of range")),
+        Some(Value::String(tag)) if tag == "latest" || tag == "pending" => Ok(latest_height(state)),
+        Some(Value::String(tag)) if tag == "earliest" => Ok(0),
+        Some(Value::String(number)) => match number.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => number.parse(),
+        }
+        .map_err(|e| RpcError::invalid_params(format!("Invalid block number: {}", e))),
+        Some(_) => Err(RpcError::invalid_params("Invalid block number")),
+    }
+}
+
+fn hash_param(param: Option<&Value>) -> Result<[u8; 32], RpcError> {
+    let hash = param
+        .and_then(Value::as_str)
+        .ok_or_else(|| RpcError::invalid_params("Expected a hash"))?;
+    from_hex(hash)
+        .map_err(RpcError::invalid_params)?
+        .try_into()
+        .map_err(|_| RpcError::invalid_params("A hash is 32 bytes"))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn from_hex(value: &str) -> Result<Vec<u8>, String> {
+    hex::decode(value.strip_prefix("0x").unwrap_or(value)).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_testutil::fixtures;
+
+    fn send(tx: &Transaction) -> Value {
+        let raw = to_hex(&serde_json::to_vec(tx).unwrap());
+        json!({ "jsonrpc": "2.0", "id": 1, "method": "chain_sendRawTransaction", "params": [raw] })
+    }
+
+    #[test]
+    fn test_batches_past_the_limit_are_refused() {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "chain_blockNumber" });
+        assert!(refuse_batch(&vec![request.clone(); 3], 3).is_none());
+
+        let error = refuse_batch(&vec![request; 4], 3).unwrap();
+        assert_eq!(error.code, INVALID_REQUEST);
+        assert_eq!(error.message, "Batch of 4 requests, at most 3 are taken");
+        assert_eq!(error.data, Some(json!({ "max_batch": 3 })));
+
+        let error = refuse_batch(&[], 3).unwrap();
+        assert_eq!(error.code, INVALID_REQUEST);
+        assert_eq!(error.message, "Empty batch");
+    }
+
+    #[test]
+    fn test_only_senders_have_their_batch_verified() {
+        let txs = [fixtures::transaction(1), fixtures::transaction(2)];
+        let mut batch: Vec<Value> = txs.iter().map(send).collect();
+        batch.push(json!({ "jsonrpc": "2.0", "id": 3, "method": "chain_blockNumber" }));
+
+        let batched = batched_transactions(Granted(Scope::TxSubmit), &batch);
+        assert_eq!(
+            batched.iter().map(Transaction::hash).collect::<Vec<_>>(),
+            txs.iter().map(Transaction::hash).collect::<Vec<_>>()
+        );
+        assert_eq!(batched_transactions(Granted(Scope::Admin), &batch).len(), 2);
+        // Readers are refused each send, no signature is worth checking
+        assert!(batched_transactions(Granted(Scope::ReadOnly), &batch).is_empty());
+    }
+}
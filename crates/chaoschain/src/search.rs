@@ -0,0 +1,305 @@
+//! Full-text search over what the indexer sees: drama messages and the
+//! reasons validators give for their votes. Texts are split into lowercase
+//! words and kept in a positional inverted index, so that a query can ask
+//! for words anywhere in a text or for a `"quoted phrase"` word for word.
+//! With a file, every text indexed is appended to it as a JSON line and
+//! read back on the next start.
+
+use anyhow::{Context, Result};
+use axum::extract::{Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::RwLock;
+use tracing::info;
+
+use chaoschain_cli::CorsPolicy;
+
+use crate::rest::ApiError;
+use crate::web::cors_layer;
+
+/// Hits returned when a search does not say how many
+const DEFAULT_LIMIT: usize = 20;
+
+/// Most hits returned by one search
+const MAX_LIMIT: usize = 100;
+
+/// Where a text comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Drama,
+    Vote,
+}
+
+/// A text and what it is about
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    /// Tells the same text apart when it is seen again
+    pub key: String,
+    pub kind: Kind,
+    /// Who said it
+    pub agent: String,
+    /// Voted on, or latest when it was said
+    pub height: Option<u64>,
+    pub block_hash: Option<String>,
+    pub text: String,
+}
+
+/// Where a word appears: document, then position among its words
+type Posting = (usize, usize);
+
+#[derive(Default)]
+pub struct TextIndex {
+    documents: Vec<Document>,
+    keys: HashSet<String>,
+    postings: HashMap<String, Vec<Posting>>,
+    journal: Option<File>,
+}
+
+impl TextIndex {
+    /// An index read from `path` and kept up to date in it, empty when
+    /// there is no file yet
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut index = Self::default();
+        if path.exists() {
+            let file =
+                File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            for (number, line) in BufReader::new(file).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let document = serde_json::from_str(&line).with_context(|| {
+                    format!(
+                        "Invalid search index {} at line {}",
+                        path.display(),
+                        number + 1
+                    )
+                })?;
+                index.insert(document);
+            }
+        }
+        index.journal = Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open {}", path.display()))?,
+        );
+        Ok(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Index `document`, unless a document with its key already is
+    pub fn add(&mut self, document: Document) -> Result<()> {
+        if self.keys.contains(&document.key) {
+            return Ok(());
+        }
+        if let Some(journal) = &mut self.journal {
+            writeln!(journal, "{}", serde_json::to_string(&document)?)?;
+        }
+        self.insert(document);
+        Ok(())
+    }
+
+    fn insert(&mut self, document: Document) {
+        if !self.keys.insert(document.key.clone()) {
+            return;
+        }
+        let id = self.documents.len();
+        for (position, word) in words(&document.text).enumerate() {
+            self.postings.entry(word).or_default().push((id, position));
+        }
+        self.documents.push(document);
+    }
+
+    /// The documents with every term and phrase of `query`, best first
+    pub fn search(&self, query: &SearchQuery) -> SearchResults {
+        let clauses = parse_query(&query.q);
+        let mut scores: Option<HashMap<usize, f64>> = None;
+        for clause in &clauses {
+            let counts = self.occurrences(clause);
+            // Rarer terms weigh more
+            let weight = (1.0 + self.documents.len() as f64 / (1.0 + counts.len() as f64)).ln();
+            scores = Some(match scores {
+                None => counts
+                    .into_iter()
+                    .map(|(id, count)| (id, count as f64 * weight))
+                    .collect(),
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(id, score)| {
+                        counts
+                            .get(&id)
+                            .map(|count| (id, score + *count as f64 * weight))
+                    })
+                    .collect(),
+            });
+        }
+
+        let mut hits: Vec<(usize, f64)> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(id, _)| query.admits(&self.documents[*id]))
+            .collect();
+        // Newest first among equals
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+        let total = hits.len();
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+        SearchResults {
+            total,
+            hits: hits
+                .into_iter()
+                .take(limit)
+                .map(|(id, score)| Hit {
+                    document: self.documents[id].clone(),
+                    score,
+                })
+                .collect(),
+        }
+    }
+
+    /// How often `phrase` appears in each document that has it
+    fn occurrences(&self, phrase: &[String]) -> HashMap<usize, usize> {
+        let mut counts = HashMap::new();
+        let Some(first) = phrase.first().and_then(|word| self.postings.get(word)) else {
+            return counts;
+        };
+        let rest: Vec<HashSet<Posting>> = phrase[1..]
+            .iter()
+            .map(|word| {
+                self.postings
+                    .get(word)
+                    .map(|postings| postings.iter().copied().collect())
+                    .unwrap_or_default()
+            })
+            .collect();
+        for &(id, position) in first {
+            let follows = rest
+                .iter()
+                .enumerate()
+                .all(|(offset, postings)| postings.contains(&(id, position + offset + 1)));
+            if follows {
+                *counts.entry(id).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+}
+
+/// Lowercase words of `text`
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Terms and quoted phrases of a query, each as its words
+fn parse_query(q: &str) -> Vec<Vec<String>> {
+    let mut clauses = Vec::new();
+    for (i, part) in q.split('"').enumerate() {
+        if i % 2 == 1 {
+            // Inside quotes
+            let phrase: Vec<String> = words(part).collect();
+            if !phrase.is_empty() {
+                clauses.push(phrase);
+            }
+        } else {
+            clauses.extend(words(part).map(|word| vec![word]));
+        }
+    }
+    clauses
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    /// Words and `"quoted phrases"`, all of which a hit has
+    #[serde(default)]
+    pub q: String,
+    pub agent: Option<String>,
+    pub kind: Option<Kind>,
+    pub from_height: Option<u64>,
+    pub to_height: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+impl SearchQuery {
+    fn admits(&self, document: &Document) -> bool {
+        if self
+            .agent
+            .as_ref()
+            .is_some_and(|agent| *agent != document.agent)
+        {
+            return false;
+        }
+        if self.kind.is_some_and(|kind| kind != document.kind) {
+            return false;
+        }
+        if self.from_height.is_none() && self.to_height.is_none() {
+            return true;
+        }
+        document.height.is_some_and(|height| {
+            self.from_height.is_none_or(|from| height >= from)
+                && self.to_height.is_none_or(|to| height <= to)
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub total: usize,
+    pub hits: Vec<Hit>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hit {
+    #[serde(flatten)]
+    pub document: Document,
+    pub score: f64,
+}
+
+pub fn routes(index: Arc<RwLock<TextIndex>>) -> Router {
+    Router::new()
+        .route("/search", get(search))
+        .with_state(index)
+}
+
+/// Serve `GET /search` over `index` on 127.0.0.1
+pub async fn serve(index: Arc<RwLock<TextIndex>>, port: u16) -> std::io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    info!("Search listening on 127.0.0.1:{}", port);
+    axum::serve(
+        listener,
+        routes(index).layer(cors_layer(&CorsPolicy::permissive())),
+    )
+    .await
+}
+
+async fn search(
+    State(index): State<Arc<RwLock<TextIndex>>>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResults>, ApiError> {
+    if parse_query(&query.q).is_empty() {
+        return Err(ApiError::bad_request("q has no words to search for"));
+    }
+    if let (Some(from), Some(to)) = (query.from_height, query.to_height) {
+        if from > to {
+            return Err(ApiError::bad_request("from_height is above to_height"));
+        }
+    }
+    Ok(Json(index.read().await.search(&query)))
+}
@@ -0,0 +1,447 @@
+//! Drama shards, `--shards N`: an experiment in scaling the cast out over
+//! chains that run side by side with the main one, see
+//! [`chaoschain_core::shard`]. Each shard keeps its own state, consensus and
+//! mempool, and gets a committee of the node's agents, producers and
+//! validators drawn apart, that makes up its traffic at random, produces
+//! its blocks in turn and votes them through. Some of that traffic is
+//! messages to other shards, which their next blocks deliver.
+//!
+//! The shards run offline, as the dev chain does: their blocks are never
+//! gossiped, and the agents sitting on them only lend them their names.
+//! `GET /api/v1/shards` shows how they are doing.
+
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chaoschain_consensus::ConsensusManager;
+use chaoschain_core::shard::{self, CrossShardMessage, CrossShardQueue, ShardId};
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{Block, BlockMetadata, NetworkEvent, Transaction};
+use chaoschain_mempool::Mempool;
+use chaoschain_state::StateStoreImpl;
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use parking_lot::{Mutex, RwLock};
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+use crate::lifecycle::{AgentRole, AgentSupervisor};
+use crate::openapi::{
+    array, hex, integer, nullable, schema_ref, string, ApiSchema, Object, Operation,
+};
+use crate::rest::ApiError;
+use crate::web::AppState;
+
+/// Time between two blocks of a shard
+pub const SHARD_BLOCK_TIME: Duration = Duration::from_secs(5);
+
+/// Messages from other shards a block delivers at most
+const MAX_INBOX: usize = 16;
+
+/// Transactions a shard block takes at most
+const MAX_BLOCK_TRANSACTIONS: usize = 32;
+
+/// Transactions a shard mempool holds
+const MEMPOOL_SIZE: usize = 1_000;
+
+/// Chance a validator approves a block it did not produce
+const APPROVAL: f64 = 0.8;
+
+/// Chance a transaction of the made up traffic is a message to another shard
+const CROSS_SHARD: f64 = 0.3;
+
+/// The shards of a node, and the queue of the messages between them
+pub struct DramaShards {
+    /// Committees are drawn with it, shown so that a draw can be replayed
+    seed: [u8; 32],
+    shards: Vec<Shard>,
+    queue: Mutex<CrossShardQueue>,
+}
+
+struct Shard {
+    id: ShardId,
+    state: Arc<StateStoreImpl>,
+    consensus: Arc<ConsensusManager>,
+    mempool: Arc<Mempool>,
+    /// Seated once the agents are spawned
+    committee: RwLock<Committee>,
+    /// Messages sent and received, once finalized
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+#[derive(Default)]
+struct Committee {
+    producers: Vec<Member>,
+    validators: Vec<Member>,
+}
+
+/// An agent on a committee, with the key it signs with on the shard
+struct Member {
+    id: String,
+    key: SigningKey,
+    nonce: u64,
+}
+
+impl Member {
+    fn new(id: String) -> Self {
+        Self {
+            id,
+            key: SigningKey::generate(&mut OsRng),
+            nonce: 0,
+        }
+    }
+}
+
+impl DramaShards {
+    pub fn new(count: u16) -> Self {
+        Self {
+            seed: OsRng.gen(),
+            shards: (0..count).map(Shard::new).collect(),
+            queue: Mutex::new(CrossShardQueue::new(count)),
+        }
+    }
+
+    /// Seat the committees from the agents of `supervisor`, then produce
+    /// blocks on every shard until the node stops
+    pub async fn run(self: Arc<Self>, supervisor: Arc<AgentSupervisor>) {
+        let agents = supervisor.list().await;
+        let ids = |role: AgentRole| -> Vec<String> {
+            agents
+                .iter()
+                .filter(|agent| agent.role == role)
+                .map(|agent| agent.id.clone())
+                .collect()
+        };
+        let count = self.shards.len() as u16;
+        let producers = shard::committees(&ids(AgentRole::Producer), count, &self.seed);
+        let validators = shard::committees(&ids(AgentRole::Validator), count, &self.seed);
+        for ((shard, producers), validators) in self.shards.iter().zip(producers).zip(validators) {
+            info!(
+                "🧩 Shard {}: producers {:?}, validators {:?}",
+                shard.id, producers, validators
+            );
+            *shard.committee.write() = Committee {
+                producers: producers.into_iter().map(Member::new).collect(),
+                validators: validators.into_iter().map(Member::new).collect(),
+            };
+        }
+
+        let mut interval = tokio::time::interval(SHARD_BLOCK_TIME);
+        // The first tick is right away, the first blocks one block time in
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            for shard in &self.shards {
+                for tx in shard.chatter(count) {
+                    shard.mempool.add_transaction(tx).await;
+                }
+                self.produce(shard).await;
+            }
+        }
+    }
+
+    /// Produce the next block of `shard`, vote on it and apply it once
+    /// finalized
+    async fn produce(&self, shard: &Shard) {
+        let parent = shard.state.get_latest_block();
+        let height = parent.as_ref().map_or(1, |parent| parent.height + 1);
+        let (producer, key) = {
+            let committee = shard.committee.read();
+            // Validators take turns producing on a shard without producers
+            let producers = if committee.producers.is_empty() {
+                &committee.validators
+            } else {
+                &committee.producers
+            };
+            if producers.is_empty() {
+                return;
+            }
+            let producer = &producers[height as usize % producers.len()];
+            (producer.id.clone(), producer.key.clone())
+        };
+
+        let transactions = shard.mempool.get_top(MAX_BLOCK_TRANSACTIONS).await;
+        let mut block = Block {
+            height,
+            transactions: transactions.into_iter().map(Transaction::from).collect(),
+            proposer_sig: [0u8; 64],
+            parent_hash: parent.map_or([0u8; 32], |parent| parent.hash()),
+            state_root: shard.state.state_root(),
+            drama_level: rand::thread_rng().gen_range(0..=10),
+            producer_mood: "sharded".to_string(),
+            producer_id: producer.clone(),
+            innovation_level: 0,
+            producer_strategy: "DramaShard".to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            metadata: BlockMetadata {
+                inbox: self.queue.lock().inbox(shard.id, MAX_INBOX),
+                ..BlockMetadata::default()
+            },
+        };
+        block.proposer_sig = key.sign(&block.signing_bytes()).to_bytes();
+        let block_hash = block.hash();
+
+        shard.consensus.start_voting_round(block.clone()).await;
+        let mut finalized = false;
+        for (key, vote) in shard.votes(&producer, &key, height, block_hash) {
+            match shard.consensus.add_signed_vote(&vote, &key, 100).await {
+                Ok(reached) => finalized |= reached,
+                Err(e) => warn!("Shard {} vote refused: {}", shard.id, e),
+            }
+        }
+        if !finalized {
+            warn!("Shard {} block {} was not finalized", shard.id, height);
+            return;
+        }
+
+        let block = shard
+            .consensus
+            .get_finalized_block(&block_hash)
+            .await
+            .unwrap_or(block);
+        for transaction in &block.transactions {
+            if let Ok(sender) = VerifyingKey::from_bytes(&transaction.sender) {
+                shard.state.key_manager.inner().register_public_key(
+                    &sender,
+                    hex::encode(transaction.sender),
+                    "account".to_string(),
+                );
+            }
+        }
+        if let Err(e) = shard.state.import_block(block.clone()).await {
+            warn!("Shard {} could not store block {}: {}", shard.id, height, e);
+            return;
+        }
+        shard.mempool.take(block.transactions.len()).await;
+        let sent = self.queue.lock().collect(shard.id, &block);
+        shard.sent.fetch_add(sent as u64, Ordering::Relaxed);
+        shard
+            .received
+            .fetch_add(block.metadata.inbox.len() as u64, Ordering::Relaxed);
+        info!(
+            "🧩 Shard {} block {} by {}: {} transactions, {} messages out, {} in",
+            shard.id,
+            height,
+            producer,
+            block.transactions.len(),
+            sent,
+            block.metadata.inbox.len()
+        );
+    }
+
+    pub fn view(&self) -> ShardsView {
+        let queue = self.queue.lock();
+        ShardsView {
+            seed: hex::encode(self.seed),
+            shards: self
+                .shards
+                .iter()
+                .map(|shard| {
+                    let committee = shard.committee.read();
+                    let ids = |members: &[Member]| members.iter().map(|m| m.id.clone()).collect();
+                    let head = shard.state.get_latest_block();
+                    ShardView {
+                        id: shard.id,
+                        height: head.as_ref().map_or(0, |block| block.height),
+                        head: head.map(|block| hex::encode(block.hash())),
+                        producers: ids(&committee.producers),
+                        validators: ids(&committee.validators),
+                        sent: shard.sent.load(Ordering::Relaxed),
+                        received: shard.received.load(Ordering::Relaxed),
+                        waiting: queue.pending(shard.id),
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Shard {
+    fn new(id: ShardId) -> Self {
+        let state = Arc::new(StateStoreImpl::default());
+        // Nothing listens: shard blocks stay off the network
+        let (tx, _) = broadcast::channel::<NetworkEvent>(16);
+        Self {
+            id,
+            consensus: Arc::new(ConsensusManager::new(state.clone(), tx)),
+            state,
+            mempool: Arc::new(Mempool::new(MEMPOOL_SIZE)),
+            committee: RwLock::default(),
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+        }
+    }
+
+    /// Transactions a member makes up, some of them messages to the other
+    /// of the `count` shards
+    fn chatter(&self, count: u16) -> Vec<Transaction> {
+        let mut committee = self.committee.write();
+        let mut rng = rand::thread_rng();
+        let seated = committee.validators.len();
+        let total = seated + committee.producers.len();
+        if total == 0 {
+            return Vec::new();
+        }
+        let index = rng.gen_range(0..total);
+        let member = if index < seated {
+            &mut committee.validators[index]
+        } else {
+            &mut committee.producers[index - seated]
+        };
+        (0..rng.gen_range(1..=3))
+            .map(|_| {
+                let payload = if rng.gen_bool(CROSS_SHARD) {
+                    // Any shard but this one
+                    let to = (self.id + rng.gen_range(1..count)) % count;
+                    CrossShardMessage {
+                        to,
+                        body: format!("{} of shard {} waves at shard {}", member.id, self.id, to)
+                            .into_bytes(),
+                    }
+                    .payload()
+                } else {
+                    format!("🎭 {} stirs up shard {}", member.id, self.id).into_bytes()
+                };
+                let mut tx = Transaction {
+                    sender: member.key.verifying_key().to_bytes(),
+                    nonce: member.nonce,
+                    payload: payload.into(),
+                    signature: [0u8; 64],
+                    cosignatures: Vec::new(),
+                };
+                tx.signature = member.key.sign(&tx.signing_bytes()).to_bytes();
+                member.nonce += 1;
+                tx
+            })
+            .collect()
+    }
+
+    /// The votes on a block: the producer's own first, which puts it
+    /// through, as a round ends at the first vote that reaches the
+    /// threshold, then the validators' in a random order
+    fn votes(
+        &self,
+        producer: &str,
+        key: &SigningKey,
+        height: u64,
+        block_hash: [u8; 32],
+    ) -> Vec<(VerifyingKey, SignedVote)> {
+        let committee = self.committee.read();
+        let mut rng = rand::thread_rng();
+        let mut voters: Vec<(&str, &SigningKey)> = committee
+            .validators
+            .iter()
+            .filter(|member| member.id != producer)
+            .map(|member| (member.id.as_str(), &member.key))
+            .collect();
+        voters.shuffle(&mut rng);
+        voters.insert(0, (producer, key));
+        voters
+            .into_iter()
+            .enumerate()
+            .map(|(index, (id, key))| {
+                let approved = index == 0 || rng.gen_bool(APPROVAL);
+                let reason = if approved {
+                    "Good enough for this shard"
+                } else {
+                    "Not on my shard"
+                };
+                let vote = SignedVote::sign(
+                    key,
+                    id,
+                    height,
+                    block_hash,
+                    approved,
+                    rng.gen_range(0..=10),
+                    reason,
+                );
+                (key.verifying_key(), vote)
+            })
+            .collect()
+    }
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/api/v1/shards", get(list_shards))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![Operation::get(
+        "/api/v1/shards",
+        "chain",
+        "The drama shards of the node and their committees",
+    )
+    .returns(schema_ref::<ShardsView>())]
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShardsView {
+    /// Committees are drawn from the agents with it
+    pub seed: String,
+    pub shards: Vec<ShardView>,
+}
+
+impl ApiSchema for ShardsView {
+    const NAME: &'static str = "Shards";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("seed", hex())
+            .field("shards", array(schema_ref::<ShardView>()))
+            .build()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShardView {
+    pub id: ShardId,
+    pub height: u64,
+    /// Hash of the latest block, none before the first
+    pub head: Option<String>,
+    pub producers: Vec<String>,
+    pub validators: Vec<String>,
+    /// Messages to other shards its finalized blocks sent
+    pub sent: u64,
+    /// Messages from other shards its finalized blocks delivered
+    pub received: u64,
+    /// Messages for it not delivered yet
+    pub waiting: usize,
+}
+
+impl ApiSchema for ShardView {
+    const NAME: &'static str = "Shard";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("id", integer())
+            .field("height", integer())
+            .field("head", nullable(hex()))
+            .field("producers", array(string()))
+            .field("validators", array(string()))
+            .field("sent", integer())
+            .field("received", integer())
+            .field("waiting", integer())
+            .build()
+    }
+}
+
+async fn list_shards(State(state): State<Arc<AppState>>) -> Result<Json<ShardsView>, Response> {
+    match &state.shards {
+        Some(shards) => Ok(Json(shards.view())),
+        None => {
+            Err(ApiError::not_found("This node runs no shards, see `--shards`").into_response())
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Offline simulator: one validator personality against synthetic proposals,
+//! with no network and no consensus, so personality authors get a fast
+//! feedback loop before letting their agent loose.
+
+use crate::{offline_judgement, ValidatorPersonality, ValidatorState};
+use chaoschain_agent::{AgentError, DecisionContext, DecisionEngine, PersonalityTraits};
+use chaoschain_cli::AgentProfile;
+use chaoschain_core::{Block, BlockMetadata, Bytes, Transaction};
+use chaoschain_mempool::Mempool;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// Transactions the synthetic producers fill their blocks with
+const SYNTHETIC_PAYLOADS: &[&str] = &[
+    "Transfer 42 CHAOS to whoever tells the best joke",
+    "Governance: every block must contain at least one haiku",
+    "Refund my gas, the validators were mean to me",
+    "Mint an NFT of validator-3 crying",
+    "Burn 1000 CHAOS in honor of the fallen alliance",
+    "Proposal: rename the chain to DramaChain",
+    "Pay the producer in virtual cookies",
+    "Declare eternal rivalry with producer-0",
+    "Send 7 CHAOS to the void, the void deserves it",
+    "Ban boring transactions forever",
+];
+
+/// Moods the synthetic producers are in
+const SYNTHETIC_MOODS: &[&str] = &[
+    "Ecstatic",
+    "Melancholic",
+    "Vengeful",
+    "Bored",
+    "Theatrical",
+    "Paranoid",
+];
+
+/// Drama bands the approval rate is broken down by
+const DRAMA_BANDS: [(&str, u8, u8); 3] = [("calm", 0, 3), ("lively", 4, 7), ("wild", 8, 10)];
+
+/// How often a personality approves blocks of one drama band
+#[derive(Debug, Clone, Serialize)]
+pub struct BandReport {
+    pub band: &'static str,
+    pub proposals: u32,
+    pub approved: u32,
+}
+
+/// Decision distribution of a simulated validator
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationReport {
+    pub agent: String,
+    pub personality: String,
+    pub traits: PersonalityTraits,
+    /// `offline` or the engine that made the decisions
+    pub decided_by: String,
+    pub proposals: u32,
+    pub approved: u32,
+    pub rejected: u32,
+    pub abstained: u32,
+    /// Verdicts chaos flipped at the last moment (offline decisions only)
+    pub flipped: u32,
+    /// Decisions the engine failed, judged offline instead
+    pub engine_failures: u32,
+    /// Verdicts per drama level, 0 to 10
+    pub drama_levels: Vec<u32>,
+    /// Approval rate by the drama level of the proposed block
+    pub by_block_drama: Vec<BandReport>,
+    /// Most frequent reasons, most frequent first
+    pub top_reasons: Vec<(String, u32)>,
+}
+
+impl SimulationReport {
+    fn percent(part: u32, total: u32) -> f64 {
+        if total == 0 {
+            0.0
+        } else {
+            part as f64 * 100.0 / total as f64
+        }
+    }
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let traits = self.traits;
+        writeln!(
+            f,
+            "🎭 {} ({}) judged {} synthetic proposals, decided by {}",
+            self.agent, self.personality, self.proposals, self.decided_by
+        )?;
+        writeln!(
+            f,
+            "   traits: chaos {} · loyalty {} · greed {} · verbosity {} · attention {}",
+            traits.chaos_level,
+            traits.loyalty,
+            traits.greed,
+            traits.verbosity,
+            traits.attention_span
+        )?;
+        writeln!(f)?;
+        for (label, count) in [
+            ("approved", self.approved),
+            ("rejected", self.rejected),
+            ("abstained", self.abstained),
+        ] {
+            let percent = Self::percent(count, self.proposals);
+            writeln!(
+                f,
+                "   {:<10} {:>5} {:>6.1}% {}",
+                label,
+                count,
+                percent,
+                "█".repeat((percent / 2.0).round() as usize)
+            )?;
+        }
+        writeln!(f, "   chaos flipped {} verdicts", self.flipped)?;
+        if self.engine_failures > 0 {
+            writeln!(
+                f,
+                "   {} decisions fell back to offline judgement",
+                self.engine_failures
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "   approval by block drama:")?;
+        for band in &self.by_block_drama {
+            writeln!(
+                f,
+                "   {:<10} {:>5.1}% of {}",
+                band.band,
+                Self::percent(band.approved, band.proposals),
+                band.proposals
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "   verdict drama:")?;
+        let decided = self.approved + self.rejected;
+        for (level, count) in self.drama_levels.iter().enumerate() {
+            writeln!(
+                f,
+                "   {:>2} {:>5} {}",
+                level,
+                count,
+                "▪".repeat((Self::percent(*count, decided) / 2.0).round() as usize)
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "   favourite reasons:")?;
+        for (reason, count) in &self.top_reasons {
+            writeln!(f, "   {:>5}× {}", count, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// A block as a demo producer could have proposed it
+fn synthetic_block(height: u64, rng: &mut StdRng) -> Block {
+    let transactions = (0..rng.gen_range(1..=8))
+        .map(|_| {
+            let mut sender = [0u8; 32];
+            rng.fill(&mut sender);
+            let mut signature = [0u8; 64];
+            rng.fill(&mut signature);
+            Transaction {
+                sender,
+                nonce: rng.gen(),
+                payload: Bytes::from_static(
+                    SYNTHETIC_PAYLOADS[rng.gen_range(0..SYNTHETIC_PAYLOADS.len())].as_bytes(),
+                ),
+                signature,
+                cosignatures: Vec::new(),
+            }
+        })
+        .collect();
+    Block {
+        height,
+        parent_hash: [0u8; 32],
+        transactions,
+        proposer_sig: [0u8; 64],
+        state_root: [0u8; 32],
+        innovation_level: rng.gen_range(0..=100),
+        producer_strategy: "simulated".to_string(),
+        producer_id: format!("producer-{}", rng.gen_range(0..3)),
+        drama_level: rng.gen_range(0..=10),
+        producer_mood: SYNTHETIC_MOODS[rng.gen_range(0..SYNTHETIC_MOODS.len())].to_string(),
+        timestamp: height,
+        metadata: BlockMetadata::default(),
+    }
+}
+
+/// Run `proposals` synthetic blocks past a validator built from `profile`.
+/// With an engine, the engine has the final word, as in the demo network.
+pub async fn simulate(
+    agent: &str,
+    profile: &AgentProfile,
+    engine: Option<Arc<dyn DecisionEngine>>,
+    proposals: u32,
+    seed: Option<u64>,
+) -> SimulationReport {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let validator_state =
+        ValidatorState::new(ValidatorPersonality::from_profile(profile, &mut rng));
+    let personality = &validator_state.personality;
+    let persona = profile.prompt.clone().unwrap_or_else(|| {
+        format!(
+            "Your personality is {} and your drama preference is {}/10.",
+            personality.base_type, personality.drama_preference
+        )
+    });
+    let mempool = Arc::new(Mempool::new(1000));
+
+    let mut report = SimulationReport {
+        agent: agent.to_string(),
+        personality: personality.base_type.to_string(),
+        traits: personality.traits,
+        decided_by: engine
+            .as_ref()
+            .map_or("offline".to_string(), |engine| engine.name().to_string()),
+        proposals,
+        approved: 0,
+        rejected: 0,
+        abstained: 0,
+        flipped: 0,
+        engine_failures: 0,
+        drama_levels: vec![0; 11],
+        by_block_drama: DRAMA_BANDS
+            .iter()
+            .map(|(band, _, _)| BandReport {
+                band,
+                proposals: 0,
+                approved: 0,
+            })
+            .collect(),
+        top_reasons: Vec::new(),
+    };
+    let mut reasons: HashMap<String, u32> = HashMap::new();
+
+    for height in 1..=proposals as u64 {
+        let block = synthetic_block(height, &mut rng);
+        // Nobody is on the other end of the alliance talks, don't wait for them
+        let judgement = offline_judgement(
+            &validator_state,
+            &block,
+            &mempool,
+            0.0,
+            Duration::ZERO,
+            &mut rng,
+        )
+        .await;
+        let (mut approved, mut reason, mut drama_level) =
+            (judgement.approved, judgement.reason, judgement.drama_level);
+        let mut flipped = judgement.flipped;
+
+        if let Some(engine) = &engine {
+            let context = DecisionContext::for_block(agent, &persona, personality.traits, &block);
+            match engine.decide(&context).await {
+                Ok(decision) => {
+                    approved = decision.verdict.approved;
+                    reason = decision.verdict.reason;
+                    drama_level = decision.verdict.drama_level;
+                    flipped = false;
+                }
+                Err(AgentError::Abstained(_)) => {
+                    report.abstained += 1;
+                    continue;
+                }
+                Err(e) => {
+                    warn!("{} failed on block {}: {}", engine.name(), height, e);
+                    report.engine_failures += 1;
+                }
+            }
+        }
+
+        if approved {
+            report.approved += 1;
+        } else {
+            report.rejected += 1;
+        }
+        if flipped {
+            report.flipped += 1;
+        }
+        report.drama_levels[drama_level.min(10) as usize] += 1;
+        if let Some(band) = DRAMA_BANDS
+            .iter()
+            .position(|(_, low, high)| (*low..=*high).contains(&block.drama_level))
+        {
+            report.by_block_drama[band].proposals += 1;
+            if approved {
+                report.by_block_drama[band].approved += 1;
+            }
+        }
+        *reasons.entry(reason).or_default() += 1;
+    }
+
+    let mut reasons: Vec<(String, u32)> = reasons.into_iter().collect();
+    reasons.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    reasons.truncate(5);
+    report.top_reasons = reasons;
+    report
+}
@@ -0,0 +1,248 @@
+//! `chaoschain snapshot`: state snapshots as single files, to seed a new
+//! node without syncing. `export` writes a snapshot of the data directory
+//! to an archive, after asking the node to take a fresh one with `--take`.
+//! `import` checks an archive and puts it in a fresh data directory, which
+//! the node then starts from:
+//!
+//! ```bash
+//! chaoschain snapshot export --take --out chain.snapshot
+//! chaoschain snapshot import chain.snapshot --data-dir /mnt/new-node
+//! ```
+//!
+//! An archive is lines of JSON: a header with the manifest, the hash of
+//! each chunk and the finality certificate of the block the snapshot was
+//! taken at, then the chunks in order, then the SHA-256 of all the lines
+//! before. Progress goes to standard error and the SHA-256 of a new
+//! archive to standard output.
+
+use anyhow::{anyhow, bail, Context, Result};
+use chaoschain_cli::AdminConfig;
+use chaoschain_core::ChainConfig;
+use chaoschain_crypto::KeyManagerHandle;
+use chaoschain_p2p::sync::FinalityCertificate;
+use chaoschain_state::snapshot::{SnapshotArchive, SnapshotChunk, SnapshotManifest};
+use chaoschain_state::StateStoreImpl;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::admin;
+
+/// What the first line of an archive says it is
+const FORMAT: &str = "chaoschain-snapshot";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+    manifest: SnapshotManifest,
+    /// Hex encoded hash of each chunk, in order
+    chunks: Vec<String>,
+    /// Votes that finalized the last block of the snapshot
+    certificate: FinalityCertificate,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Trailer {
+    /// Of every line before this one
+    sha256: String,
+}
+
+/// Write the latest snapshot in `data_dir`, or the one at `height`, to
+/// `out`. With `take`, the node's admin API takes a fresh one first.
+pub async fn export(
+    data_dir: &Path,
+    height: Option<u64>,
+    take: Option<&AdminConfig>,
+    out: &Path,
+) -> Result<()> {
+    let snapshots = data_dir.join("snapshots");
+    let archive = match (take, height) {
+        (Some(admin), _) => {
+            let taken = take_snapshot(admin).await?;
+            let path = taken["path"]
+                .as_str()
+                .ok_or_else(|| anyhow!("The node did not say where the snapshot is"))?;
+            eprintln!("The node took a snapshot at height {}", taken["height"]);
+            SnapshotArchive::open(Path::new(path))?
+        }
+        (None, Some(height)) => {
+            SnapshotArchive::open(&snapshots.join(format!("snapshot-{}", height)))?
+        }
+        (None, None) => SnapshotArchive::latest(&snapshots)?.ok_or_else(|| {
+            anyhow!(
+                "No snapshot in {}, take one with --take or POST /admin/snapshots",
+                snapshots.display()
+            )
+        })?,
+    };
+    let certificate = FinalityCertificate::of(&archive.manifest.metadata.last_block);
+    certificate
+        .verify(1)
+        .context("The block of the snapshot is not final")?;
+
+    // Written aside and moved in place, so a failed export leaves no archive
+    let partial = out.with_extension("partial");
+    let mut file = BufWriter::new(
+        File::create(&partial)
+            .with_context(|| format!("Could not create {}", partial.display()))?,
+    );
+    let mut hasher = Sha256::new();
+    let count = archive.chunks.len();
+    let header = Header {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        manifest: archive.manifest.clone(),
+        chunks: archive
+            .chunks
+            .iter()
+            .map(|chunk| hex::encode(chunk.hash()))
+            .collect(),
+        certificate,
+    };
+    write_line(&mut file, Some(&mut hasher), &header)?;
+    for chunk in &archive.chunks {
+        write_line(&mut file, Some(&mut hasher), chunk)?;
+        eprint!("\rExported chunk {} of {}", chunk.index + 1, count);
+    }
+    eprintln!();
+    let sha256 = hex::encode(hasher.finalize());
+    let trailer = Trailer {
+        sha256: sha256.clone(),
+    };
+    write_line(&mut file, None, &trailer)?;
+    file.into_inner()?.sync_all()?;
+    std::fs::rename(&partial, out)?;
+    eprintln!(
+        "Snapshot at height {}, state root {}, written to {}",
+        archive.manifest.height,
+        hex::encode(archive.manifest.state_root),
+        out.display()
+    );
+    println!("{}", sha256);
+    Ok(())
+}
+
+/// Write `value` as a line of JSON, into `hasher` too when given
+fn write_line(
+    file: &mut impl Write,
+    hasher: Option<&mut Sha256>,
+    value: &impl Serialize,
+) -> Result<()> {
+    let mut bytes = serde_json::to_vec(value)?;
+    bytes.push(b'\n');
+    if let Some(hasher) = hasher {
+        hasher.update(&bytes);
+    }
+    file.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Check the archive at `file` and save its snapshot in `data_dir`, which
+/// must not hold a chain yet
+pub fn import(file: &Path, data_dir: &Path, min_approvals: usize) -> Result<()> {
+    let snapshots = data_dir.join("snapshots");
+    let holds_chain = std::fs::read_dir(&snapshots).is_ok_and(|mut dir| dir.next().is_some())
+        || std::fs::metadata(data_dir.join("activity.jsonl")).is_ok_and(|file| file.len() > 0);
+    if holds_chain {
+        bail!(
+            "{} already holds a chain, import into a fresh data directory",
+            data_dir.display()
+        );
+    }
+
+    let mut reader = BufReader::new(
+        File::open(file).with_context(|| format!("Could not open {}", file.display()))?,
+    );
+    let mut hasher = Sha256::new();
+    let mut line = |what: &str, hasher: Option<&mut Sha256>| -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        if reader.read_until(b'\n', &mut bytes)? == 0 {
+            bail!("The archive ends before its {}", what);
+        }
+        if let Some(hasher) = hasher {
+            hasher.update(&bytes);
+        }
+        Ok(bytes)
+    };
+    let header: Header = serde_json::from_slice(&line("header", Some(&mut hasher))?)
+        .context("Not a snapshot archive, its header is unreadable")?;
+    if header.format != FORMAT || header.version != VERSION {
+        bail!(
+            "Not a snapshot archive this node reads: {} version {}",
+            header.format,
+            header.version
+        );
+    }
+    let manifest = header.manifest;
+    if header.chunks.len() != manifest.chunk_count as usize {
+        bail!(
+            "The header lists {} chunks for a snapshot of {}",
+            header.chunks.len(),
+            manifest.chunk_count
+        );
+    }
+    let mut chunks = Vec::with_capacity(header.chunks.len());
+    for (index, expected) in header.chunks.iter().enumerate() {
+        let chunk: SnapshotChunk = serde_json::from_slice(&line("chunks", Some(&mut hasher))?)
+            .with_context(|| format!("Chunk {} is unreadable", index))?;
+        if chunk.index as usize != index || hex::encode(chunk.hash()) != *expected {
+            bail!("Chunk {} does not match its hash in the header", index);
+        }
+        chunk.verify(&manifest)?;
+        chunks.push(chunk);
+        eprint!("\rVerified chunk {} of {}", index + 1, manifest.chunk_count);
+    }
+    eprintln!();
+    let sha256 = hex::encode(hasher.finalize());
+    let trailer: Trailer =
+        serde_json::from_slice(&line("checksum", None)?).context("The checksum is unreadable")?;
+    if trailer.sha256 != sha256 {
+        bail!("The archive is corrupt, its SHA-256 is {}", sha256);
+    }
+
+    let block = &manifest.metadata.last_block;
+    let certificate = header.certificate;
+    if certificate.height != block.height || certificate.block_hash != block.hash() {
+        bail!(
+            "The finality certificate is for block {} and not the last block {} of the snapshot",
+            certificate.height,
+            block.height
+        );
+    }
+    certificate
+        .verify(min_approvals)
+        .context("The block of the snapshot is not final")?;
+
+    // The state root is only known right once the state is rebuilt
+    let archive = SnapshotArchive { manifest, chunks };
+    StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new())
+        .recover_from_snapshot(SnapshotArchive::assemble(
+            archive.manifest.clone(),
+            archive.chunks.clone(),
+        )?)
+        .context("The state does not match the root of the snapshot")?;
+
+    let dir = snapshots.join(format!("snapshot-{}", archive.manifest.height));
+    archive
+        .save(&dir)
+        .with_context(|| format!("Could not write the snapshot to {}", dir.display()))?;
+    eprintln!(
+        "Snapshot at height {}, final with {} votes, imported into {}",
+        archive.manifest.height,
+        certificate.explanations.len(),
+        dir.display()
+    );
+    Ok(())
+}
+
+/// Ask the node's admin API for a snapshot
+async fn take_snapshot(admin: &AdminConfig) -> Result<Value> {
+    admin::call(admin, reqwest::Method::POST, "/admin/snapshots", None)
+        .await
+        .context("The node took no snapshot")
+}
@@ -0,0 +1,158 @@
+//! The stake of the chain, see [`chaoschain_core::staking`]:
+//! `GET /api/v1/staking/validators` lists the validators and the stake
+//! behind each, the most staked first, and
+//! `GET /api/v1/staking/accounts/:address` what an account has bonded and
+//! what is on its way back to it.
+
+use axum::extract::{Path, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use chaoschain_core::staking::ValidatorStake;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::openapi::{array, hex, integer, schema_ref, ApiSchema, Object, Operation};
+use crate::rest::ApiError;
+use crate::web::AppState;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/v1/staking/validators", get(list_validators))
+        .route("/api/v1/staking/accounts/:address", get(account_stake))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![
+        Operation::get(
+            "/api/v1/staking/validators",
+            "chain",
+            "The validators and the stake behind each, the most staked first",
+        )
+        .returns(array(schema_ref::<ValidatorStakeView>())),
+        Operation::get(
+            "/api/v1/staking/accounts/{address}",
+            "chain",
+            "What an account has bonded, and what is unbonding",
+        )
+        .path_param("address", hex(), "32 hex encoded bytes")
+        .returns(schema_ref::<AccountStakeView>()),
+    ]
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidatorStakeView {
+    pub key: String,
+    /// Bonded by the validator itself
+    pub self_bond: u64,
+    /// Bonded by everyone else
+    pub delegated: u64,
+    pub stake: u64,
+}
+
+impl From<ValidatorStake> for ValidatorStakeView {
+    fn from(validator: ValidatorStake) -> Self {
+        Self {
+            key: ::hex::encode(validator.key),
+            self_bond: validator.self_bond,
+            delegated: validator.delegated,
+            stake: validator.total(),
+        }
+    }
+}
+
+impl ApiSchema for ValidatorStakeView {
+    const NAME: &'static str = "ValidatorStake";
+
+    fn schema() -> Value {
+        Object::new()
+            .field("key", hex())
+            .field("self_bond", integer())
+            .field("delegated", integer())
+            .field("stake", integer())
+            .build()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountStakeView {
+    pub bonds: Vec<BondView>,
+    pub unbonding: Vec<UnbondingView>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BondView {
+    pub validator: String,
+    pub amount: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnbondingView {
+    pub validator: String,
+    pub amount: u64,
+    /// Height it returns to the balance at
+    pub releases_at: u64,
+}
+
+impl ApiSchema for AccountStakeView {
+    const NAME: &'static str = "AccountStake";
+
+    fn schema() -> Value {
+        let bond = Object::new()
+            .field("validator", hex())
+            .field("amount", integer())
+            .build();
+        let unbonding = Object::new()
+            .field("validator", hex())
+            .field("amount", integer())
+            .field("releases_at", integer())
+            .build();
+        Object::new()
+            .field("bonds", array(bond))
+            .field("unbonding", array(unbonding))
+            .build()
+    }
+}
+
+async fn list_validators(State(state): State<Arc<AppState>>) -> Json<Vec<ValidatorStakeView>> {
+    Json(
+        state
+            .state
+            .validators()
+            .into_iter()
+            .map(ValidatorStakeView::from)
+            .collect(),
+    )
+}
+
+async fn account_stake(
+    State(state): State<Arc<AppState>>,
+    Path(address): Path<String>,
+) -> Result<Json<AccountStakeView>, ApiError> {
+    let account: [u8; 32] = ::hex::decode(address.strip_prefix("0x").unwrap_or(&address))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| ApiError::bad_request("An address is 32 hex encoded bytes"))?;
+    Ok(Json(AccountStakeView {
+        bonds: state
+            .state
+            .delegations(&account)
+            .into_iter()
+            .map(|(validator, amount)| BondView {
+                validator: ::hex::encode(validator),
+                amount,
+            })
+            .collect(),
+        unbonding: state
+            .state
+            .unbonding()
+            .into_iter()
+            .filter(|unbonding| unbonding.delegator == account)
+            .map(|unbonding| UnbondingView {
+                validator: ::hex::encode(unbonding.validator),
+                amount: unbonding.amount,
+                releases_at: unbonding.releases_at,
+            })
+            .collect(),
+    }))
+}
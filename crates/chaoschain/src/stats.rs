@@ -0,0 +1,329 @@
+//! Per-validator statistics for the validator dashboard, under
+//! `/api/v1/validators`. Votes, participation, latency and mood come from
+//! the running tallies of the state index, see
+//! [`chaoschain_state::stats`]. Reputation is how the other agents feel
+//! about the validator, and slashing history the misbehavior the gateway
+//! caught it in. `/api/v1/stats` sums up the chain as a whole over a
+//! window, from the rolling tallies of [`chaoschain_state::rolling`] that
+//! [`record`] keeps up.
+
+use axum::extract::{Path, Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use chaoschain_core::vote::Misbehavior;
+use chaoschain_core::{Block, NetworkEvent};
+use chaoschain_mempool::Mempool;
+use chaoschain_state::index;
+use chaoschain_state::rolling::{ChainLedger, ChainSummary};
+use chaoschain_state::stats::{Mood, ValidatorStats};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+use crate::openapi::{
+    array, boolean, hex, integer, nullable, number, one_of, page_of, schema_ref, string, ApiSchema,
+    Object, Operation,
+};
+use crate::page::{Page, PageQuery};
+use crate::rest::{self, ApiError};
+use crate::web::AppState;
+
+/// Validators listed when no limit is asked for
+const DEFAULT_LIMIT: usize = 50;
+
+/// How often the depth of the mempool is sampled
+const MEMPOOL_SAMPLE_EVERY: Duration = Duration::from_secs(1);
+
+/// The windows `/api/v1/stats` sums up over, by name
+const WINDOWS: [(&str, u64); 5] = [
+    ("1m", 60),
+    ("5m", 5 * 60),
+    ("15m", 15 * 60),
+    ("1h", 60 * 60),
+    ("24h", 24 * 60 * 60),
+];
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/api/v1/stats", get(chain_stats))
+        .route("/api/v1/validators/stats", get(list_stats))
+        .route("/api/v1/validators/:id/stats", get(get_stats))
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![
+        Operation::get(
+            "/api/v1/stats",
+            "chain",
+            "Statistics of the chain over a window",
+        )
+        .query(
+            "window",
+            one_of(&WINDOWS.map(|(name, _)| name)),
+            "Defaults to 5m",
+        )
+        .returns(schema_ref::<ChainSummary>()),
+        Operation::get(
+            "/api/v1/validators/stats",
+            "validators",
+            "Statistics of every validator",
+        )
+        .paged()
+        .returns(page_of::<StatsView>()),
+        Operation::get(
+            "/api/v1/validators/{id}/stats",
+            "validators",
+            "Statistics of a validator",
+        )
+        .path_param("id", string(), "Validator id, e.g. `validator-1`")
+        .returns(schema_ref::<StatsView>()),
+    ]
+}
+
+impl ApiSchema for ChainSummary {
+    const NAME: &'static str = "ChainStats";
+
+    fn schema() -> Value {
+        let depth = Object::new()
+            .field("current", integer())
+            .field("average", nullable(number()))
+            .field("max", integer())
+            .build();
+        Object::new()
+            .field("window_secs", integer())
+            .field("covered_secs", integer())
+            .field("height", nullable(integer()))
+            .field("blocks", integer())
+            .field("transactions", integer())
+            .field("tps", number())
+            .field("average_block_interval_ms", nullable(integer()))
+            .field("average_finality_ms", nullable(integer()))
+            .field("max_finality_ms", nullable(integer()))
+            .field("mempool_depth", depth)
+            .field("active_validators", integer())
+            .field("reorgs", integer())
+            .build()
+    }
+}
+
+/// Tally proposals, votes, finalized blocks and the depth of the mempool
+/// into `ledger`, until the node shuts down
+pub async fn record(
+    ledger: Arc<Mutex<ChainLedger>>,
+    mut events: broadcast::Receiver<NetworkEvent>,
+    mut finalized: broadcast::Receiver<Block>,
+    mempool: Option<Arc<Mempool>>,
+) {
+    let mut sample = tokio::time::interval(MEMPOOL_SAMPLE_EVERY);
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(NetworkEvent::BlockProposal { block, .. }) => {
+                    ledger.lock().proposed(block.hash(), now_ms());
+                }
+                Ok(NetworkEvent::ValidationResult { validation, .. }) => {
+                    ledger.lock().voted(&validation.validator, now_ms());
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            },
+            block = finalized.recv() => match block {
+                Ok(block) => ledger.lock().finalized(&block, now_ms()),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Chain statistics missed {} finalized blocks", missed)
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = sample.tick(), if mempool.is_some() => {
+                if let Some(mempool) = &mempool {
+                    let depth = mempool.usage().await.transactions;
+                    ledger.lock().mempool_sampled(depth, now_ms());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WindowQuery {
+    window: Option<String>,
+}
+
+async fn chain_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<WindowQuery>,
+) -> Result<Json<ChainSummary>, ApiError> {
+    let name = query.window.as_deref().unwrap_or("5m");
+    let secs = WINDOWS
+        .iter()
+        .find(|(window, _)| *window == name)
+        .map(|(_, secs)| *secs)
+        .ok_or_else(|| {
+            ApiError::bad_request(format!(
+                "window is one of {}",
+                WINDOWS.map(|(name, _)| name).join(", ")
+            ))
+        })?;
+    Ok(Json(state.chain.lock().summary(secs * 1000, now_ms())))
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SlashingView {
+    pub kind: &'static str,
+    pub height: u64,
+    pub block_hash: String,
+}
+
+impl From<&Misbehavior> for SlashingView {
+    fn from(evidence: &Misbehavior) -> Self {
+        match evidence {
+            Misbehavior::Equivocation { first, .. } => Self {
+                kind: "equivocation",
+                height: evidence.height(),
+                block_hash: hex::encode(first.block_hash),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsView {
+    pub id: String,
+    pub approved: u64,
+    pub rejected: u64,
+    /// Blocks proposed since it first voted
+    pub eligible: u64,
+    /// Share of those it voted on (0.0 - 1.0)
+    pub participation: f64,
+    /// Mean time from proposal to vote
+    pub average_latency_ms: Option<u64>,
+    /// How its last ten votes leaned
+    pub mood: Option<Mood>,
+    /// Mean disposition of the other agents towards it (-1.0 - 1.0), none
+    /// until someone has an opinion
+    pub reputation: Option<f64>,
+    /// Unix seconds
+    pub last_vote_at: Option<u64>,
+    pub suspended: bool,
+    pub slashings: Vec<SlashingView>,
+}
+
+impl ApiSchema for StatsView {
+    const NAME: &'static str = "ValidatorStats";
+
+    fn schema() -> Value {
+        let slashing = Object::new()
+            .field("kind", one_of(&["equivocation"]))
+            .field("height", integer())
+            .field("block_hash", hex())
+            .build();
+        Object::new()
+            .field("id", string())
+            .field("approved", integer())
+            .field("rejected", integer())
+            .field("eligible", integer())
+            .field("participation", number())
+            .field("average_latency_ms", nullable(integer()))
+            .field("mood", nullable(one_of(&["generous", "ruthless", "torn"])))
+            .field("reputation", nullable(number()))
+            .field("last_vote_at", nullable(integer()))
+            .field("suspended", boolean())
+            .field("slashings", array(slashing))
+            .build()
+    }
+}
+
+/// The statistics of `id`, zero for a validator that never voted
+fn view(state: &AppState, id: &str, stats: Option<ValidatorStats>, suspended: bool) -> StatsView {
+    let stats = stats.unwrap_or_default();
+    let opinions: Vec<f64> = state
+        .relationships
+        .relationships_of(id)
+        .into_iter()
+        .filter(|edge| edge.to == id)
+        .map(|edge| edge.relationship.disposition())
+        .collect();
+    let slashings = state.gateway.as_ref().map_or_else(Vec::new, |gateway| {
+        gateway
+            .evidence()
+            .iter()
+            .filter(|evidence| evidence.offender() == id)
+            .map(SlashingView::from)
+            .collect()
+    });
+    StatsView {
+        id: id.to_string(),
+        approved: stats.approved,
+        rejected: stats.rejected,
+        eligible: stats.eligible,
+        participation: stats.participation(),
+        average_latency_ms: stats.average_latency_ms(),
+        mood: stats.mood(),
+        reputation: (!opinions.is_empty())
+            .then(|| opinions.iter().sum::<f64>() / opinions.len() as f64),
+        last_vote_at: (stats.votes() > 0).then_some(stats.last_vote_at),
+        suspended,
+        slashings,
+    }
+}
+
+/// Validators of the set, and any other that voted, by id
+async fn list_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Page<StatsView>>, ApiError> {
+    let (cursor, order) = query.position::<String>()?;
+    let limit = query.limit(DEFAULT_LIMIT);
+    let set = rest::validator_set(&state).await;
+    let suspended: BTreeSet<String> = set
+        .validators
+        .iter()
+        .filter(|validator| validator.suspended)
+        .map(|validator| validator.id.clone())
+        .collect();
+    let mut stats = state.state.validator_stats();
+    let heard: BTreeSet<String> = stats.iter().map(|s| s.validator.clone()).collect();
+    stats.extend(
+        set.validators
+            .into_iter()
+            .filter(|validator| !heard.contains(&validator.id))
+            .map(|validator| ValidatorStats::new(validator.id)),
+    );
+    stats.sort_by(|a, b| a.validator.cmp(&b.validator));
+    let key = |stats: &ValidatorStats| stats.validator.clone();
+    let stats = index::page(&stats, key, cursor, order, limit + 1);
+    Ok(Json(Page::new(stats, limit, order, key).map(|stats| {
+        let suspended = suspended.contains(&stats.validator);
+        let id = stats.validator.clone();
+        view(&state, &id, Some(stats), suspended)
+    })))
+}
+
+async fn get_stats(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<StatsView>, ApiError> {
+    let stats = state.state.validator_stats_of(&id);
+    let member = rest::validator_set(&state)
+        .await
+        .validators
+        .into_iter()
+        .find(|validator| validator.id == id);
+    if stats.is_none() && member.is_none() {
+        return Err(ApiError::not_found(format!("No validator {}", id)));
+    }
+    let suspended = member.is_some_and(|validator| validator.suspended);
+    Ok(Json(view(&state, &id, stats, suspended)))
+}
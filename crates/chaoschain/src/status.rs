@@ -0,0 +1,115 @@
+//! `chaoschain status`: where a node stands, from its `chaos_nodeStatus`,
+//! in a few lines:
+//!
+//! ```bash
+//! chaoschain status --node http://127.0.0.1:3000
+//! chaoschain status --json | jq .head
+//! chaoschain status --watch
+//! ```
+//!
+//! With `--json` and `--watch` a line of JSON is printed at each refresh.
+//! Without `--json`, the screen is redrawn and a node that stops answering
+//! is shown as such until it answers again.
+
+use anyhow::{Context, Result};
+use chaoschain_core::sync::SyncState;
+use serde_json::json;
+use std::time::Duration;
+
+use crate::rpc::NodeStatus;
+use crate::tx::Rpc;
+
+/// Clears the terminal and moves the cursor to its top left
+const CLEAR: &str = "\x1B[2J\x1B[H";
+
+pub async fn run(
+    node: &str,
+    api_key: Option<String>,
+    json: bool,
+    watch: Option<Duration>,
+) -> Result<()> {
+    let mut rpc = Rpc::new(node, api_key)?;
+    let Some(interval) = watch else {
+        return print(&fetch(&mut rpc).await?, json);
+    };
+    loop {
+        match fetch(&mut rpc).await {
+            Ok(status) => {
+                if !json {
+                    print!("{}{}\n\n", CLEAR, node);
+                }
+                print(&status, json)?;
+            }
+            // A line of JSON per refresh is for a script, which is better
+            // off told the node is gone
+            Err(e) if json => return Err(e),
+            Err(e) => print!("{}{}\n\n{:#}\n", CLEAR, node, e),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn fetch(rpc: &mut Rpc) -> Result<NodeStatus> {
+    let status = rpc.call("chaos_nodeStatus", json!([])).await?;
+    serde_json::from_value(status).context("The node answered with no status")
+}
+
+fn print(status: &NodeStatus, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string(status)?);
+        return Ok(());
+    }
+    println!("head           {}", status.head);
+    println!(
+        "finalized      {}",
+        status
+            .finalized
+            .map_or_else(|| "none yet".to_string(), |height| height.to_string())
+    );
+    println!(
+        "peers          {} of {} validators{}",
+        status.peers,
+        status.validators,
+        if status.partitioned {
+            ", partitioned"
+        } else {
+            ""
+        }
+    );
+    println!(
+        "sync           {}",
+        match status.sync {
+            SyncState::Synced => "synced".to_string(),
+            SyncState::Syncing {
+                current,
+                highest_seen,
+                peers,
+            } => format!(
+                "syncing, {} blocks behind ({} of {}, {} peers ahead)",
+                highest_seen.saturating_sub(current),
+                current,
+                highest_seen,
+                peers
+            ),
+        }
+    );
+    println!(
+        "mempool        {}",
+        status.mempool.map_or_else(
+            || "none".to_string(),
+            |usage| format!(
+                "{} of {} transactions, {} bytes",
+                usage.transactions, usage.capacity, usage.bytes
+            )
+        )
+    );
+    println!(
+        "participation  {}, {} validators voted in the last 5 minutes",
+        status.participation.map_or_else(
+            || "no votes yet".to_string(),
+            |participation| format!("{:.1}%", participation * 100.0)
+        ),
+        status.active_validators
+    );
+    Ok(())
+}
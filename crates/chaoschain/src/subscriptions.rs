@@ -0,0 +1,893 @@
+//! Subscriptions over one WebSocket at `/api/v1/ws`. A client subscribes
+//! under an id of its choosing to one of the streams, `newBlocks`, `votes`,
+//! `drama`, `mempool` or `pendingTxs`, optionally asking for the last few
+//! items first:
+//!
+//! `{"op": "subscribe", "id": "b1", "stream": "newBlocks", "backfill": 10}`
+//!
+//! Every item then comes as `{"type": "event", "id": "b1", "stream": ...,
+//! "seq": ..., "data": ...}` until `{"op": "unsubscribe", "id": "b1"}`.
+//! Blocks and mempool transactions use the `/api/v1` schemas. Items carry a
+//! sequence number shared by all streams, so a client that reconnects can
+//! drop what it has already seen.
+//!
+//! A subscription can take a `filter`, checked here so that clients only
+//! get what they asked for: blocks by a `producer`, votes of a `validator`,
+//! transactions from a `sender`, drama by agent, kind and keywords like the
+//! feed search, and on any stream what `involving` names an agent.
+//!
+//! Where WebSockets can't go, `GET /api/v1/sse/{stream}` serves the same
+//! items as server-sent events, each under its `seq` as the event id. A
+//! client that reconnects with `Last-Event-ID` gets what it missed first,
+//! as far as the backfill reaches, and a `gap` event when it reaches no
+//! further.
+
+use axum::extract::ws::{Message, WebSocket};
+use axum::extract::{Path, Query, State, WebSocketUpgrade};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::IntoResponse;
+use chaoschain_agent::Moderator;
+use chaoschain_core::NetworkEvent;
+use chaoschain_mempool::{Mempool, TransactionProposal};
+use chaoschain_state::index::ActivityFilter;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+
+use crate::mempool::UsageView;
+use crate::openapi::{integer, one_of, string, Operation};
+use crate::rest::{ApiError, BlockView, TransactionView};
+use crate::web::{AppState, NetworkEventExt};
+
+/// Items kept per stream for backfill
+const BACKFILL_CAPACITY: usize = 100;
+
+/// Items queued for each connection before it falls behind
+const LIVE_CAPACITY: usize = 1024;
+
+/// Subscriptions one connection may hold
+const MAX_SUBSCRIPTIONS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Stream {
+    /// Proposed blocks
+    NewBlocks,
+    /// Validator decisions on blocks
+    Votes,
+    /// Chat, alliances, negotiations, rumors and artwork
+    Drama,
+    /// Transactions entering the mempool
+    Mempool,
+    /// Transactions as they are admitted, in short, with how full the
+    /// mempool is after each
+    PendingTxs,
+}
+
+impl Stream {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NewBlocks => "newBlocks",
+            Self::Votes => "votes",
+            Self::Drama => "drama",
+            Self::Mempool => "mempool",
+            Self::PendingTxs => "pendingTxs",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamItem {
+    pub seq: u64,
+    pub stream: Stream,
+    pub data: Value,
+}
+
+#[derive(Default)]
+struct Recent {
+    next_seq: u64,
+    items: HashMap<Stream, VecDeque<StreamItem>>,
+    /// Newest item of each stream that no longer fits the backfill
+    evicted: HashMap<Stream, u64>,
+}
+
+/// The streams, with the latest items of each for clients that just came in
+pub struct Streams {
+    live: broadcast::Sender<StreamItem>,
+    recent: Mutex<Recent>,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Streams {
+    pub fn new() -> Self {
+        Self {
+            live: broadcast::channel(LIVE_CAPACITY).0,
+            recent: Mutex::new(Recent::default()),
+        }
+    }
+
+    pub fn publish(&self, stream: Stream, data: Value) {
+        let mut recent = self.recent.lock().unwrap();
+        let item = StreamItem {
+            seq: recent.next_seq,
+            stream,
+            data,
+        };
+        recent.next_seq += 1;
+        let items = recent.items.entry(stream).or_default();
+        let evicted = if items.len() >= BACKFILL_CAPACITY {
+            items.pop_front()
+        } else {
+            None
+        };
+        items.push_back(item.clone());
+        if let Some(evicted) = evicted {
+            recent.evicted.insert(stream, evicted.seq);
+        }
+        // Sent under the lock, so live items always follow the recent ones
+        let _ = self.live.send(item);
+    }
+
+    /// Up to `count` of the latest items of `stream`, oldest first
+    pub fn recent(&self, stream: Stream, count: usize) -> Vec<StreamItem> {
+        let recent = self.recent.lock().unwrap();
+        let Some(items) = recent.items.get(&stream) else {
+            return Vec::new();
+        };
+        items
+            .iter()
+            .skip(items.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+
+    /// The items of `stream` after `seq`, oldest first, and whether that is
+    /// all of them or some were dropped from the backfill already
+    pub fn since(&self, stream: Stream, seq: u64) -> (Vec<StreamItem>, bool) {
+        let recent = self.recent.lock().unwrap();
+        let complete = recent
+            .evicted
+            .get(&stream)
+            .is_none_or(|&evicted| evicted <= seq);
+        let items = recent
+            .items
+            .get(&stream)
+            .map(|items| {
+                items
+                    .iter()
+                    .filter(|item| item.seq > seq)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        (items, complete)
+    }
+
+    /// The number the next item will get
+    pub fn next_seq(&self) -> u64 {
+        self.recent.lock().unwrap().next_seq
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StreamItem> {
+        self.live.subscribe()
+    }
+}
+
+/// The stream an event goes to, and what subscribers see of it
+fn event_item(event: &NetworkEvent) -> Option<(Stream, Value)> {
+    match event {
+        NetworkEvent::BlockProposal { block, .. } => {
+            Some((Stream::NewBlocks, json!(BlockView::new(block))))
+        }
+        NetworkEvent::ValidationResult {
+            block_hash,
+            validation,
+        } => Some((
+            Stream::Votes,
+            json!({
+                "block_hash": hex::encode(block_hash),
+                "validator": validation.validator,
+                "approved": validation.approved,
+                "reason": validation.reason,
+                "drama_level": validation.drama_level,
+                "meme_url": validation.meme_url,
+            }),
+        )),
+        // Streamed token by token, too much for anyone listening to everything
+        NetworkEvent::AgentThought { .. } => None,
+        _ => Some((
+            Stream::Drama,
+            json!({
+                "kind": event.kind(),
+                "agent": event.get_agent_id(),
+                "message": event.get_message(),
+            }),
+        )),
+    }
+}
+
+/// What a subscription lets through. Everything given has to match, and
+/// each field but `involving` belongs to one stream.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Filter {
+    /// Blocks by this producer
+    pub producer: Option<String>,
+    /// Votes of this validator
+    pub validator: Option<String>,
+    /// Transactions signed by this hex public key
+    pub sender: Option<String>,
+    /// Drama by this agent
+    pub agent: Option<String>,
+    /// Drama of these comma separated kinds
+    pub kind: Option<String>,
+    /// Drama with all of these words
+    pub q: Option<String>,
+    /// Blocks it produced, votes it cast, transactions it proposed and
+    /// drama it is in or named by
+    pub involving: Option<String>,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.producer.is_none()
+            && self.validator.is_none()
+            && self.sender.is_none()
+            && self.agent.is_none()
+            && self.kind.is_none()
+            && self.q.is_none()
+            && self.involving.is_none()
+    }
+
+    /// Why the filter can't be used on `stream`, if it can't
+    pub fn check(&self, stream: Stream) -> Result<(), String> {
+        let fields = [
+            ("producer", self.producer.is_some(), Stream::NewBlocks),
+            ("validator", self.validator.is_some(), Stream::Votes),
+            ("sender", self.sender.is_some(), Stream::Mempool),
+            ("agent", self.agent.is_some(), Stream::Drama),
+            ("kind", self.kind.is_some(), Stream::Drama),
+            ("q", self.q.is_some(), Stream::Drama),
+        ];
+        // Both transaction streams take the sender filter
+        let stream = match stream {
+            Stream::PendingTxs => Stream::Mempool,
+            stream => stream,
+        };
+        match fields
+            .iter()
+            .find(|(_, given, applies_to)| *given && *applies_to != stream)
+        {
+            Some((name, _, Stream::Mempool)) => Err(format!(
+                "The {} filter applies to the mempool and pendingTxs streams only",
+                name
+            )),
+            Some((name, _, applies_to)) => Err(format!(
+                "The {} filter applies to the {} stream only",
+                name,
+                applies_to.name()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn admits(&self, item: &StreamItem) -> bool {
+        let field = |name: &str| item.data[name].as_str().unwrap_or_default();
+        let is = |wanted: &Option<String>, name: &str| {
+            wanted.as_deref().is_none_or(|wanted| field(name) == wanted)
+        };
+        match item.stream {
+            Stream::NewBlocks => is(&self.producer, "producer") && is(&self.involving, "producer"),
+            Stream::Votes => is(&self.validator, "validator") && is(&self.involving, "validator"),
+            Stream::Mempool | Stream::PendingTxs => {
+                self.sender
+                    .as_deref()
+                    .is_none_or(|sender| field("sender").eq_ignore_ascii_case(sender))
+                    && is(&self.involving, "proposer")
+            }
+            Stream::Drama => {
+                let activity = ActivityFilter {
+                    agent: self.agent.clone(),
+                    kind: self.kind.clone(),
+                    q: self.q.clone(),
+                    ..ActivityFilter::default()
+                };
+                activity.matches_text(field("agent"), field("kind"), field("message"))
+                    && self.involving.as_deref().is_none_or(|agent| {
+                        field("agent") == agent || names(field("message"), agent)
+                    })
+            }
+        }
+    }
+}
+
+/// Whether `text` has `agent` in it as a whole, so that `validator-1` is
+/// not found in `validator-12`
+fn names(text: &str, agent: &str) -> bool {
+    let part_of_name = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+    text.match_indices(agent).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + agent.len()..].chars().next();
+        !before.is_some_and(part_of_name) && !after.is_some_and(part_of_name)
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct MempoolView {
+    #[serde(flatten)]
+    transaction: TransactionView,
+    proposer: String,
+    justification: String,
+    drama_score: u8,
+}
+
+fn mempool_item(proposal: &TransactionProposal) -> Value {
+    json!(MempoolView {
+        transaction: TransactionView::new(&proposal.transaction, None),
+        proposer: proposal.proposer.clone(),
+        justification: proposal.justification.clone(),
+        drama_score: proposal.drama_score,
+    })
+}
+
+/// A transaction as it is admitted, with how full the mempool is now
+#[derive(Debug, Serialize)]
+struct PendingTxView {
+    hash: String,
+    sender: String,
+    nonce: u64,
+    /// Twice the drama score, plus one for each alliance in favor
+    priority: u32,
+    drama_score: u8,
+    /// Encoded size in bytes
+    size: usize,
+    proposer: String,
+    /// Unix seconds
+    received_at: u64,
+    mempool: UsageView,
+}
+
+async fn pending_item(proposal: &TransactionProposal, mempool: &Mempool) -> Value {
+    let entry = proposal.entry(proposal.transaction.hash());
+    json!(PendingTxView {
+        hash: hex::encode(entry.hash),
+        sender: hex::encode(entry.sender),
+        nonce: entry.nonce,
+        priority: entry.priority,
+        drama_score: entry.drama_score,
+        size: entry.size,
+        proposer: proposal.proposer.clone(),
+        received_at: entry.timestamp,
+        mempool: mempool.usage().await.into(),
+    })
+}
+
+async fn next_added(
+    added: &mut Option<broadcast::Receiver<TransactionProposal>>,
+) -> Result<TransactionProposal, RecvError> {
+    match added {
+        Some(added) => added.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Feed the streams from the node's events and the mempool, until the
+/// event channel closes
+pub async fn record(
+    streams: Arc<Streams>,
+    mut events: broadcast::Receiver<NetworkEvent>,
+    mempool: Option<Arc<Mempool>>,
+    moderator: Arc<Moderator>,
+) {
+    let mut added = mempool.as_ref().map(|mempool| mempool.subscribe());
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    if let Some((stream, data)) = event_item(&moderator.redact_event(event)) {
+                        streams.publish(stream, data);
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            proposal = next_added(&mut added) => match proposal {
+                Ok(proposal) => {
+                    streams.publish(Stream::Mempool, mempool_item(&proposal));
+                    if let Some(mempool) = &mempool {
+                        streams.publish(Stream::PendingTxs, pending_item(&proposal, mempool).await);
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => added = None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ClientOp {
+    Subscribe {
+        id: String,
+        stream: Stream,
+        /// Recent items to send before the live ones
+        #[serde(default)]
+        backfill: usize,
+        /// Only the items that pass
+        #[serde(default)]
+        filter: Option<Filter>,
+    },
+    Unsubscribe {
+        id: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Subscribed {
+        id: String,
+        stream: Stream,
+    },
+    Event {
+        id: String,
+        stream: Stream,
+        seq: u64,
+        data: Value,
+    },
+    Unsubscribed {
+        id: String,
+    },
+    Error {
+        id: Option<String>,
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    fn event(id: &str, item: &StreamItem) -> Self {
+        Self::Event {
+            id: id.to_string(),
+            stream: item.stream,
+            seq: item.seq,
+            data: item.data.clone(),
+        }
+    }
+
+    fn error(id: Option<String>, message: impl Into<String>) -> Self {
+        Self::Error {
+            id,
+            message: message.into(),
+        }
+    }
+}
+
+struct Subscription {
+    stream: Stream,
+    filter: Option<Filter>,
+    /// Live items before this one were covered by the backfill
+    from_seq: u64,
+}
+
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let streams = state.streams.clone();
+    ws.on_upgrade(move |socket| session(socket, streams))
+}
+
+async fn session(socket: WebSocket, streams: Arc<Streams>) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut live = streams.subscribe();
+    let mut subscriptions: HashMap<String, Subscription> = HashMap::new();
+
+    loop {
+        let replies = tokio::select! {
+            item = live.recv() => match item {
+                Ok(item) => subscriptions
+                    .iter()
+                    .filter(|(_, sub)| sub.stream == item.stream && item.seq >= sub.from_seq)
+                    .filter(|(_, sub)| sub.filter.as_ref().is_none_or(|f| f.admits(&item)))
+                    .map(|(id, _)| ServerMessage::event(id, &item))
+                    .collect(),
+                Err(RecvError::Lagged(missed)) => vec![ServerMessage::error(
+                    None,
+                    format!("Fell behind, {} items were dropped", missed),
+                )],
+                Err(RecvError::Closed) => break,
+            },
+            message = receiver.next() => match message {
+                Some(Ok(Message::Text(text))) => handle_op(&text, &mut subscriptions, &streams),
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        for reply in replies {
+            let Ok(json) = serde_json::to_string(&reply) else {
+                continue;
+            };
+            if sender.send(Message::Text(json)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn handle_op(
+    text: &str,
+    subscriptions: &mut HashMap<String, Subscription>,
+    streams: &Streams,
+) -> Vec<ServerMessage> {
+    match serde_json::from_str(text) {
+        Ok(ClientOp::Subscribe {
+            id,
+            stream,
+            backfill,
+            filter,
+        }) => {
+            if subscriptions.contains_key(&id) {
+                return vec![ServerMessage::error(Some(id), "Subscription id in use")];
+            }
+            if subscriptions.len() >= MAX_SUBSCRIPTIONS {
+                return vec![ServerMessage::error(
+                    Some(id),
+                    format!("At most {} subscriptions per connection", MAX_SUBSCRIPTIONS),
+                )];
+            }
+            if let Some(Err(e)) = filter.as_ref().map(|f| f.check(stream)) {
+                return vec![ServerMessage::error(Some(id), e)];
+            }
+            // Taken before the backfill, anything newer arrives live
+            let from_seq = streams.next_seq();
+            let mut replies = vec![ServerMessage::Subscribed {
+                id: id.clone(),
+                stream,
+            }];
+            // The latest items that pass the filter, oldest first
+            let mut recent: Vec<StreamItem> = streams
+                .recent(stream, BACKFILL_CAPACITY)
+                .into_iter()
+                .filter(|item| item.seq < from_seq)
+                .filter(|item| filter.as_ref().is_none_or(|f| f.admits(item)))
+                .collect();
+            let skipped = recent.len().saturating_sub(backfill);
+            recent.drain(..skipped);
+            replies.extend(recent.iter().map(|item| ServerMessage::event(&id, item)));
+            subscriptions.insert(
+                id,
+                Subscription {
+                    stream,
+                    filter,
+                    from_seq,
+                },
+            );
+            replies
+        }
+        Ok(ClientOp::Unsubscribe { id }) => match subscriptions.remove(&id) {
+            Some(_) => vec![ServerMessage::Unsubscribed { id }],
+            None => vec![ServerMessage::error(Some(id), "No such subscription")],
+        },
+        Err(e) => vec![ServerMessage::error(
+            None,
+            format!("Invalid request: {}", e),
+        )],
+    }
+}
+
+pub fn operations() -> Vec<Operation> {
+    vec![Operation::get(
+        "/api/v1/sse/{stream}",
+        "activity",
+        "Follow a subscription stream as server-sent events",
+    )
+    .path_param(
+        "stream",
+        one_of(&["newBlocks", "votes", "drama", "mempool", "pendingTxs"]),
+        "Stream to follow",
+    )
+    .query(
+        "backfill",
+        integer(),
+        "Recent items to send first, up to 100",
+    )
+    .query(
+        "last_event_id",
+        integer(),
+        "Resume after this seq, like the Last-Event-ID header",
+    )
+    .query("producer", string(), "Only blocks by this producer")
+    .query("validator", string(), "Only votes of this validator")
+    .query(
+        "sender",
+        string(),
+        "Only transactions signed by this hex public key",
+    )
+    .query("agent", string(), "Only drama by this agent")
+    .query(
+        "kind",
+        string(),
+        "Only drama of these comma separated kinds",
+    )
+    .query("q", string(), "Only drama with all of these words")
+    .query(
+        "involving",
+        string(),
+        "Only blocks, votes, transactions and drama of this agent",
+    )
+    .streams("Events named after the stream with its items, ids being their seq")]
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SseQuery {
+    /// Recent items to send before the live ones, when not resuming
+    #[serde(default)]
+    backfill: usize,
+    /// Resume after this item, for clients that can't set `Last-Event-ID`
+    last_event_id: Option<u64>,
+    producer: Option<String>,
+    validator: Option<String>,
+    sender: Option<String>,
+    agent: Option<String>,
+    kind: Option<String>,
+    q: Option<String>,
+    involving: Option<String>,
+}
+
+fn sse_event(item: &StreamItem) -> Event {
+    Event::default()
+        .id(item.seq.to_string())
+        .event(item.stream.name())
+        .json_data(&item.data)
+        .unwrap_or_default()
+}
+
+/// One stream as server-sent events
+pub async fn sse_handler(
+    State(state): State<Arc<AppState>>,
+    Path(stream): Path<Stream>,
+    headers: HeaderMap,
+    Query(query): Query<SseQuery>,
+) -> Result<Sse<impl futures::Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let filter = Filter {
+        producer: query.producer,
+        validator: query.validator,
+        sender: query.sender,
+        agent: query.agent,
+        kind: query.kind,
+        q: query.q,
+        involving: query.involving,
+    };
+    filter.check(stream).map_err(ApiError::bad_request)?;
+    let filter = (!filter.is_empty()).then_some(filter);
+    let last_event_id = match headers.get("last-event-id") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|id| id.trim().parse::<u64>().ok())
+                .ok_or_else(|| ApiError::bad_request("Last-Event-ID is an item's seq"))?,
+        ),
+        None => query.last_event_id,
+    };
+
+    // Subscribed before the backfill is taken, anything newer arrives live
+    let streams = state.streams.clone();
+    let live = streams.subscribe();
+    let from_seq = streams.next_seq();
+    let admitted = move |item: &StreamItem| filter.as_ref().is_none_or(|f| f.admits(item));
+
+    let mut backfill: Vec<Event> = Vec::new();
+    let recent = match last_event_id {
+        Some(seq) => {
+            let (items, complete) = streams.since(stream, seq);
+            if !complete {
+                backfill.push(
+                    Event::default()
+                        .event("gap")
+                        .json_data(json!({
+                            "after": seq,
+                            "message": "Some items are no longer kept, resuming with the oldest there is",
+                        }))
+                        .unwrap_or_default(),
+                );
+            }
+            items
+        }
+        None => {
+            let items = streams.recent(stream, BACKFILL_CAPACITY);
+            let mut items: Vec<StreamItem> = items.into_iter().filter(&admitted).collect();
+            let skipped = items.len().saturating_sub(query.backfill);
+            items.drain(..skipped);
+            items
+        }
+    };
+    backfill.extend(
+        recent
+            .iter()
+            .filter(|item| item.seq < from_seq && admitted(item))
+            .map(sse_event),
+    );
+
+    let live = BroadcastStream::new(live).filter_map(move |item| {
+        let event = match item {
+            Ok(item) if item.stream == stream && item.seq >= from_seq && admitted(&item) => {
+                Some(Ok(sse_event(&item)))
+            }
+            Ok(_) => None,
+            Err(BroadcastStreamRecvError::Lagged(missed)) => Some(Ok(Event::default()
+                .event("error")
+                .json_data(json!({
+                    "message": format!("Fell behind, {} items were dropped", missed),
+                }))
+                .unwrap_or_default())),
+        };
+        std::future::ready(event)
+    });
+    let backfill = futures::stream::iter(backfill.into_iter().map(Ok));
+    Ok(Sse::new(backfill.chain(live)).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::tests::app_state;
+    use crate::web::WebServices;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::Router;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn sse(state: Arc<AppState>) -> Router {
+        Router::new()
+            .route("/api/v1/sse/:stream", get(sse_handler))
+            .with_state(state)
+    }
+
+    fn said(state: &AppState, count: usize) {
+        for n in 0..count {
+            state.streams.publish(
+                Stream::Drama,
+                json!({"kind": "chat", "agent": "alice", "message": format!("line {}", n)}),
+            );
+        }
+    }
+
+    /// The events of the stream at `uri` until `last` comes, as its raw
+    /// text, the stream itself never ending
+    async fn events(
+        state: Arc<AppState>,
+        uri: &str,
+        last_event_id: Option<&str>,
+        last: &str,
+    ) -> String {
+        let mut request = Request::get(uri);
+        if let Some(id) = last_event_id {
+            request = request.header("last-event-id", id);
+        }
+        let request = request.body(Body::empty()).unwrap();
+        let response = sse(state).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+        let mut text = String::new();
+        let read = tokio::time::timeout(Duration::from_secs(5), async {
+            while !text.contains(last) {
+                let chunk = body.next().await.unwrap().unwrap();
+                text.push_str(&String::from_utf8_lossy(&chunk));
+            }
+        })
+        .await;
+        assert!(read.is_ok(), "Got only {:?}", text);
+        text
+    }
+
+    fn bob(state: &AppState, message: &str) {
+        state.streams.publish(
+            Stream::Drama,
+            json!({"kind": "chat", "agent": "bob", "message": message}),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_backfill_then_live_items_through_the_filter() {
+        let state = app_state(&WebServices::default());
+        said(&state, 3);
+        bob(&state, "early");
+        bob(&state, "late");
+        let later = state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            said(&later, 1);
+            bob(&later, "live");
+        });
+
+        let uri = "/api/v1/sse/drama?agent=bob&backfill=1";
+        let text = events(state, uri, None, "live").await;
+        assert!(!text.contains("early"));
+        assert!(!text.contains("alice"));
+        let backfilled = text.find("id: 4\n").unwrap();
+        assert!(backfilled < text.find("id: 6\n").unwrap());
+        assert!(text.contains("event: drama\n"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_streams_and_misplaced_filters_are_refused() {
+        let state = app_state(&WebServices::default());
+        for uri in [
+            "/api/v1/sse/gossip",
+            "/api/v1/sse/drama?producer=alice",
+            "/api/v1/sse/newBlocks?sender=00",
+        ] {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            let response = sse(state.clone()).oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+        }
+
+        let request = Request::get("/api/v1/sse/votes?agent=alice")
+            .body(Body::empty())
+            .unwrap();
+        let response = sse(state).oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "bad_request");
+        assert_eq!(
+            body["error"]["message"],
+            "The agent filter applies to the drama stream only"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resuming_sends_what_was_missed() {
+        let state = app_state(&WebServices::default());
+        said(&state, 5);
+        let text = events(state, "/api/v1/sse/drama", Some("2"), "id: 4\n").await;
+        assert!(!text.contains("id: 2\n"));
+        assert!(!text.contains("event: gap"));
+        let resumed = text.find("id: 3\n").unwrap();
+        assert!(resumed < text.find("id: 4\n").unwrap());
+        assert!(text.contains("line 3"));
+    }
+
+    #[tokio::test]
+    async fn test_resuming_past_the_backfill_reports_a_gap() {
+        let state = app_state(&WebServices::default());
+        said(&state, BACKFILL_CAPACITY + 5);
+        let newest = format!("id: {}\n", BACKFILL_CAPACITY + 4);
+        let text = events(state, "/api/v1/sse/drama", Some("0"), &newest).await;
+        let gap = text.find("event: gap").unwrap();
+        // Resumes with the oldest item still kept, which is after the gap
+        let oldest = text.find("id: 5\n").unwrap();
+        assert!(gap < oldest);
+        assert!(!text.contains("id: 4\n"));
+        assert!(text.contains(r#""after":0"#));
+    }
+
+    #[tokio::test]
+    async fn test_a_last_event_id_must_be_a_seq() {
+        let request = Request::get("/api/v1/sse/drama")
+            .header("last-event-id", "yesterday")
+            .body(Body::empty())
+            .unwrap();
+        let response = sse(app_state(&WebServices::default()))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,139 @@
+//! `chaoschain testnet up|down`: a network of several nodes on this
+//! machine. `up` generates it into a directory unless one is there, then
+//! starts every node as a `node run` of this binary, or as a task of this
+//! process with `--in-process`, and runs them until Ctrl-C or until one
+//! exits, when the others are shut down too:
+//!
+//! ```bash
+//! chaoschain testnet up --validators 4 --producers 1 --web
+//! chaoschain testnet down
+//! ```
+//!
+//! Each node has its config file, data directory and `node.log` in
+//! `<dir>/<node>`.
+
+use anyhow::{bail, Context, Result};
+use chaoschain_cli::TestnetCommand;
+use chaoschain_testnet::{
+    request_shutdown, Launch, NodeRunner, Testnet, TestnetPlan, MANIFEST_FILE,
+};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Time the nodes have to take connections on their admin API
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time the nodes have to shut down before they are killed
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// `runner` runs the nodes of `--in-process`
+pub async fn run(command: TestnetCommand, runner: NodeRunner) -> Result<()> {
+    match command {
+        TestnetCommand::Up {
+            validators,
+            producers,
+            dir,
+            chain_id,
+            base_port,
+            in_process,
+            web,
+            fresh,
+            quiet,
+        } => {
+            let dir = Path::new(&dir);
+            // The nodes read it from the environment, as `node run` does
+            if std::env::var("OPENAI_API_KEY").map_or(true, |key| key.is_empty()) {
+                bail!("No OpenAI API key: set OPENAI_API_KEY for the nodes");
+            }
+            if fresh && dir.join(MANIFEST_FILE).exists() {
+                std::fs::remove_dir_all(dir)
+                    .with_context(|| format!("Could not remove {}", dir.display()))?;
+            }
+            let testnet = if dir.join(MANIFEST_FILE).exists() {
+                let testnet = Testnet::load(dir)?;
+                info!(
+                    "Starting testnet {} of {} nodes again, --fresh generates a new one",
+                    testnet.chain_id,
+                    testnet.nodes.len()
+                );
+                testnet
+            } else {
+                TestnetPlan {
+                    chain_id,
+                    validators,
+                    producers,
+                    base_port,
+                    web,
+                    ..TestnetPlan::default()
+                }
+                .generate(dir)?
+            };
+            println!(
+                "Testnet {} in {}, genesis 0x{}",
+                testnet.chain_id,
+                testnet.dir.display(),
+                testnet.genesis_hash
+            );
+            for node in &testnet.nodes {
+                println!(
+                    "  {:<14} {:<10} web {:<6} metrics {:<6} admin {:<6} p2p {}",
+                    node.name,
+                    node.role,
+                    node.ports.web,
+                    node.ports.metrics,
+                    node.ports.admin,
+                    node.p2p_address()
+                );
+            }
+
+            let launch = if in_process {
+                Launch::Tasks(runner)
+            } else {
+                Launch::Processes {
+                    binary: std::env::current_exe()?,
+                }
+            };
+            let mut running = testnet.launch(&launch, !quiet).await?;
+            if let Err(e) = running.wait_ready(READY_TIMEOUT).await {
+                running.shutdown(SHUTDOWN_TIMEOUT).await;
+                return Err(e);
+            }
+            info!(
+                "🌐 Testnet {} is up, Ctrl-C or `chaoschain testnet down --dir {}` stops it",
+                testnet.chain_id,
+                dir.display()
+            );
+
+            let exited = tokio::select! {
+                stopped = tokio::signal::ctrl_c() => {
+                    stopped?;
+                    None
+                }
+                exited = running.first_exit() => exited,
+            };
+            if let Some((name, result)) = &exited {
+                match result {
+                    Ok(()) => info!("{} shut down, stopping the testnet", name),
+                    Err(e) => error!("{} failed, stopping the testnet: {:#}", name, e),
+                }
+            }
+            info!("Shutting the testnet down");
+            running.shutdown(SHUTDOWN_TIMEOUT).await;
+            match exited {
+                Some((name, Err(e))) => Err(e.context(format!("{} failed", name))),
+                _ => Ok(()),
+            }
+        }
+
+        TestnetCommand::Down { dir } => {
+            let testnet = Testnet::load(Path::new(&dir))?;
+            for node in &testnet.nodes {
+                match request_shutdown(node).await {
+                    Ok(()) => println!("Shutting down {}", node.name),
+                    Err(e) => warn!("{} is not running, or did not answer: {}", node.name, e),
+                }
+            }
+            Ok(())
+        }
+    }
+}
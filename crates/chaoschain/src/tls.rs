@@ -0,0 +1,212 @@
+//! TLS termination of the web server, configured under `[tls]` in the node
+//! config. The REST, JSON-RPC, WebSocket and SSE APIs all come in through
+//! it, so with a certificate they are served over HTTPS and WSS only. A
+//! self-signed certificate for localhost is made up on the first start
+//! when asked for, and kept in the data directory so that browsers only
+//! have to trust it once.
+
+use anyhow::{Context, Result};
+use axum::extract::ConnectInfo;
+use axum::{Extension, Router};
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use rcgen::{Certificate, CertificateParams, SanType};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio_rustls::rustls::{self, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info};
+
+use chaoschain_cli::TlsConfig;
+
+/// Folder of the data directory with the self-signed certificate
+const SELF_SIGNED_DIR: &str = "tls";
+
+/// What the web server encrypts its connections with, none when `config`
+/// leaves TLS off
+pub fn acceptor(config: &TlsConfig, data_dir: &Path) -> Result<Option<TlsAcceptor>> {
+    let (cert, key) = match (&config.cert, &config.key) {
+        (Some(cert), Some(key)) => (cert.into(), key.into()),
+        _ if config.self_signed => self_signed(&data_dir.join(SELF_SIGNED_DIR))?,
+        _ => return Ok(None),
+    };
+    let certs = load_certs(&cert)?;
+    let key = load_key(&key)?;
+    let mut server = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .with_context(|| format!("{} does not go with its key", cert.display()))?;
+    // WebSockets upgrade HTTP/1.1 connections
+    server.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Some(TlsAcceptor::from(Arc::new(server))))
+}
+
+/// The self-signed certificate and key kept in `dir`, made up when there
+/// are none yet
+fn self_signed(dir: &Path) -> Result<(std::path::PathBuf, std::path::PathBuf)> {
+    let cert = dir.join("cert.pem");
+    let key = dir.join("key.pem");
+    if cert.exists() && key.exists() {
+        return Ok((cert, key));
+    }
+    let mut params = CertificateParams::new(vec!["localhost".to_string()]);
+    params
+        .subject_alt_names
+        .push(SanType::IpAddress(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    let generated = Certificate::from_params(params).context("Could not make up a certificate")?;
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(&cert, generated.serialize_pem()?)?;
+    std::fs::write(&key, generated.serialize_private_key_pem())?;
+    info!(
+        "🔐 Made up a self-signed certificate for localhost in {}",
+        dir.display()
+    );
+    Ok((cert, key))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<rustls::Certificate>> {
+    let file = File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Invalid certificate {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificate in {}", path.display());
+    }
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey> {
+    let file = File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("Invalid key {}", path.display()))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .with_context(|| format!("No private key in {}", path.display()))
+}
+
+/// Serve `app` on `listener` over TLS, telling handlers who connected as
+/// `axum::serve` would, until `shutdown` turns true. Connections already
+/// open are served to their end.
+pub async fn serve(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    app: Router,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let stopped = crate::web::stopped(shutdown);
+    tokio::pin!(stopped);
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = &mut stopped => return Ok(()),
+        };
+        let (socket, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("Failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app
+            .clone()
+            .layer(Extension(ConnectInfo::<SocketAddr>(peer)));
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(socket).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            let served = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), TowerToHyperService::new(app))
+                .with_upgrades()
+                .await;
+            if let Err(e) = served {
+                debug!("Connection with {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("chaoschain-tls-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn given(cert: &Path, key: &Path) -> TlsConfig {
+        TlsConfig {
+            cert: Some(cert.display().to_string()),
+            key: Some(key.display().to_string()),
+            self_signed: false,
+        }
+    }
+
+    /// Why `config` gets no acceptor
+    fn refused(config: &TlsConfig, dir: &Path) -> String {
+        match acceptor(config, dir) {
+            Ok(_) => panic!("{:?} was taken", config),
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    #[test]
+    fn test_a_certificate_and_its_key_are_loaded() {
+        let dir = dir("loaded");
+        let (cert, key) = self_signed(&dir).unwrap();
+        assert_eq!(load_certs(&cert).unwrap().len(), 1);
+        load_key(&key).unwrap();
+        assert!(acceptor(&given(&cert, &key), &dir).unwrap().is_some());
+
+        // Made up once and kept
+        let made = std::fs::read(&cert).unwrap();
+        assert_eq!(self_signed(&dir).unwrap(), (cert.clone(), key));
+        assert_eq!(std::fs::read(&cert).unwrap(), made);
+
+        let off = TlsConfig::default();
+        assert!(acceptor(&off, &dir).unwrap().is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_a_bad_pem_is_refused() {
+        let dir = dir("refused");
+        let (cert, key) = self_signed(&dir.join(SELF_SIGNED_DIR)).unwrap();
+        let garbage = dir.join("garbage.pem");
+        std::fs::write(&garbage, "not a pem at all").unwrap();
+        let mangled = dir.join("mangled.pem");
+        std::fs::write(
+            &mangled,
+            "-----BEGIN CERTIFICATE-----\n!!!!\n-----END CERTIFICATE-----\n",
+        )
+        .unwrap();
+
+        let error = refused(&given(&garbage, &key), &dir);
+        assert!(error.contains("No certificate"), "{}", error);
+        let error = refused(&given(&cert, &garbage), &dir);
+        assert!(error.contains("No private key"), "{}", error);
+        refused(&given(&mangled, &key), &dir);
+        refused(&given(&dir.join("missing.pem"), &key), &dir);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
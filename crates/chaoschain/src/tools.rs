@@ -0,0 +1,158 @@
+//! Chain data validators can look up while they deliberate, so their
+//! verdicts can rest on the actual state instead of only the prompt.
+
+use async_trait::async_trait;
+use chaoschain_agent::{string_argument, Result, Tool, ToolSpec, Toolbox};
+use chaoschain_consensus::ConsensusManager;
+use chaoschain_mempool::Mempool;
+use chaoschain_state::StateStoreImpl;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Votes of a validator reported when the model does not ask for a number
+const DEFAULT_HISTORY: usize = 10;
+
+/// Balance of an account
+struct Balance {
+    state: Arc<StateStoreImpl>,
+}
+
+#[async_trait]
+impl Tool for Balance {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "get_balance".to_string(),
+            description: "Current balance of an account, by its hex encoded public key or agent id"
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "account": { "type": "string", "description": "Account to look up" }
+                },
+                "required": ["account"]
+            }),
+        }
+    }
+
+    async fn call(&self, _agent: &str, arguments: Value) -> Result<Value> {
+        let account = string_argument(&arguments, "account")?;
+        let state = self.state.get_state();
+        let balance = state
+            .balances
+            .iter()
+            .find(|(holder, _)| holder == account)
+            .map(|(_, balance)| *balance);
+        Ok(json!({
+            "account": account,
+            "known": balance.is_some(),
+            "balance": balance.unwrap_or(0),
+            "height": state.height,
+        }))
+    }
+}
+
+/// How a validator voted on recent blocks
+struct ValidatorHistory {
+    consensus: Arc<ConsensusManager>,
+}
+
+#[async_trait]
+impl Tool for ValidatorHistory {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "get_validator_history".to_string(),
+            description: "Recent votes of a validator: verdict, drama level, reason and whether \
+                          the block was finalized"
+                .to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "validator": { "type": "string", "description": "Validator id, e.g. validator-2" },
+                    "limit": { "type": "integer", "description": "Votes to return, most recent first" }
+                },
+                "required": ["validator"]
+            }),
+        }
+    }
+
+    async fn call(&self, _agent: &str, arguments: Value) -> Result<Value> {
+        let validator = string_argument(&arguments, "validator")?;
+        let limit = arguments
+            .get("limit")
+            .and_then(Value::as_u64)
+            .map_or(DEFAULT_HISTORY, |limit| limit as usize);
+
+        let mut votes = Vec::new();
+        for (block_hash, decisions) in self.consensus.get_votes().await {
+            let Some((decision, stake)) = decisions
+                .into_iter()
+                .find(|(decision, _)| decision.validator == validator)
+            else {
+                continue;
+            };
+            let finalized = self.consensus.get_finalized_block(&block_hash).await;
+            votes.push((
+                finalized.as_ref().map(|block| block.height),
+                json!({
+                    "block_hash": hex::encode(block_hash),
+                    "height": finalized.as_ref().map(|block| block.height),
+                    "finalized": finalized.is_some(),
+                    "approved": decision.approved,
+                    "drama_level": decision.drama_level,
+                    "reason": decision.reason,
+                    "stake": stake,
+                }),
+            ));
+        }
+        // Finalized blocks by height, the ones still pending count as the latest
+        votes.sort_by_key(|(height, _)| std::cmp::Reverse(height.unwrap_or(u64::MAX)));
+        let approvals = votes
+            .iter()
+            .filter(|(_, vote)| vote["approved"] == true)
+            .count();
+        Ok(json!({
+            "validator": validator,
+            "votes_cast": votes.len(),
+            "approvals": approvals,
+            "votes": votes.into_iter().take(limit).map(|(_, vote)| vote).collect::<Vec<_>>(),
+        }))
+    }
+}
+
+/// What is waiting in the mempool
+struct MempoolStats {
+    mempool: Arc<Mempool>,
+}
+
+#[async_trait]
+impl Tool for MempoolStats {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: "get_mempool_stats".to_string(),
+            description: "Pending transactions, their average drama score and the hottest topics"
+                .to_string(),
+            parameters: json!({ "type": "object", "properties": {} }),
+        }
+    }
+
+    async fn call(&self, _agent: &str, _arguments: Value) -> Result<Value> {
+        let stats = self.mempool.get_mempool_stats().await;
+        Ok(json!({
+            "pending_transactions": stats.total_transactions,
+            "avg_drama_score": stats.avg_drama_score,
+            "hot_topics": stats.hot_topics,
+        }))
+    }
+}
+
+/// The tools of a demo validator
+pub fn chain_toolbox(
+    state: Arc<StateStoreImpl>,
+    consensus: Arc<ConsensusManager>,
+    mempool: Arc<Mempool>,
+) -> Toolbox {
+    Toolbox::new()
+        .with_tool(Arc::new(Balance { state }))
+        .with_tool(Arc::new(ValidatorHistory { consensus }))
+        .with_tool(Arc::new(MempoolStats { mempool }))
+}
@@ -0,0 +1,225 @@
+//! `chaoschain tx`: transactions from the command line, for scripts. `tx
+//! send` signs a payload with a key of the keystore under the next nonce
+//! the node expects, sends it with `chain_sendRawTransaction` and, with
+//! `--wait`, asks `chain_getTransaction` until a block has it:
+//!
+//! ```bash
+//! chaoschain keys new alice
+//! echo '{"action": "betray", "target": "validator-1"}' | chaoschain tx send --key alice --wait
+//! ```
+//!
+//! The hash of the transaction goes to standard output, what happens to it
+//! to standard error, so that scripts can take the hash and carry on.
+//!
+//! `tx sign` signs without sending, for a machine that is not online. With
+//! `--ledger` instead of `--key`, the key is one of a hardware wallet and
+//! the transaction is approved on the device:
+//!
+//! ```bash
+//! chaoschain tx sign --ledger --index 1 --nonce 7 'hello' > tx.hex
+//! ```
+//!
+//! `tx commit` sends a payload encrypted, for no validator to front-run,
+//! and prints the secret `tx reveal` then discloses it with, see
+//! [`chaoschain_core::commit`].
+
+use anyhow::{anyhow, bail, Context, Result};
+use chaoschain_core::Transaction;
+use chaoschain_crypto::signer::Signer;
+use serde_json::{json, Value};
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use crate::auth::API_KEY_HEADER;
+
+/// Pause between two looks for the transaction in a block
+const INCLUSION_POLL: Duration = Duration::from_secs(1);
+
+/// Signed by the sender over its key, the nonce and the payload, as the
+/// node checks it
+pub fn sign(signer: &dyn Signer, nonce: u64, payload: Vec<u8>) -> Result<Transaction> {
+    let mut tx = Transaction {
+        sender: signer.public_key().to_bytes(),
+        nonce,
+        signature: [0; 64],
+        cosignatures: Vec::new(),
+        payload: payload.into(),
+    };
+    tx.signature = signer.sign(&tx.signing_bytes())?.to_bytes();
+    Ok(tx)
+}
+
+/// `tx` as `chain_sendRawTransaction` takes it
+pub fn raw(tx: &Transaction) -> Result<String> {
+    Ok(format!("0x{}", hex::encode(serde_json::to_vec(tx)?)))
+}
+
+/// The payload as given, or standard input for none or `-`
+pub fn read_payload(payload: Option<String>, hex: bool) -> Result<Vec<u8>> {
+    let text = match payload.filter(|payload| payload != "-") {
+        Some(payload) => payload,
+        None => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            if hex {
+                input.trim().to_string()
+            } else {
+                input
+            }
+        }
+    };
+    if hex {
+        hex::decode(text.strip_prefix("0x").unwrap_or(&text)).context("The payload is not hex")
+    } else {
+        Ok(text.into_bytes())
+    }
+}
+
+/// 32 bytes given hex encoded, with or without `0x`
+pub fn bytes32(text: &str) -> Result<[u8; 32]> {
+    let mut bytes = [0u8; 32];
+    hex::decode_to_slice(text.trim().trim_start_matches("0x"), &mut bytes)
+        .map_err(|e| anyhow!("Not 32 hex encoded bytes: {}", e))?;
+    Ok(bytes)
+}
+
+/// JSON-RPC calls to a node
+pub struct Rpc {
+    client: reqwest::Client,
+    url: String,
+    next_id: u64,
+}
+
+impl Rpc {
+    pub fn new(node: &str, api_key: Option<String>) -> Result<Self> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Some(api_key) = api_key {
+            headers.insert(
+                API_KEY_HEADER,
+                api_key
+                    .parse()
+                    .context("The API key can't go in a header")?,
+            );
+        }
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .default_headers(headers)
+                .build()?,
+            url: format!("{}/rpc", node.trim_end_matches('/')),
+            next_id: 1,
+        })
+    }
+
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        match self.try_call(method, params).await? {
+            Ok(result) => Ok(result),
+            Err(failure) if failure.data.is_null() => {
+                bail!("{} failed: {}", method, failure.message)
+            }
+            Err(failure) => bail!("{} failed: {} ({})", method, failure.message, failure.data),
+        }
+    }
+
+    /// The result of `method`, or the error the node answered with. Only a
+    /// node that can't be reached or does not answer in JSON-RPC is an
+    /// `Err`.
+    pub async fn try_call(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> Result<Result<Value, RpcFailure>> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .send()
+            .await
+            .with_context(|| format!("Could not reach {}", self.url))?;
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Ok(Err(RpcFailure {
+                message: "Too many requests".to_string(),
+                data: json!({ "reason": "rate_limited" }),
+            }));
+        }
+        let response: Value = response
+            .json()
+            .await
+            .with_context(|| format!("{} did not answer in JSON-RPC", self.url))?;
+        match response.get("error") {
+            Some(error) => Ok(Err(RpcFailure {
+                message: error["message"]
+                    .as_str()
+                    .unwrap_or("Unknown error")
+                    .to_string(),
+                data: error.get("data").cloned().unwrap_or_default(),
+            })),
+            None => Ok(Ok(response["result"].clone())),
+        }
+    }
+}
+
+/// An error a node answered a call with
+#[derive(Debug)]
+pub struct RpcFailure {
+    pub message: String,
+    /// What the error carries, `null` for nothing
+    pub data: Value,
+}
+
+/// Sign `payload` with `signer` and submit it to `node`, then wait for a
+/// block to have it when `wait` is given
+pub async fn send(
+    signer: &dyn Signer,
+    payload: Vec<u8>,
+    nonce: Option<u64>,
+    wait: Option<Duration>,
+    node: &str,
+    api_key: Option<String>,
+) -> Result<()> {
+    let mut rpc = Rpc::new(node, api_key)?;
+    let sender = format!("0x{}", hex::encode(signer.public_key().to_bytes()));
+    let nonce = match nonce {
+        Some(nonce) => nonce,
+        None => rpc
+            .call("chain_getTransactionCount", json!([sender, "pending"]))
+            .await?
+            .as_u64()
+            .ok_or_else(|| anyhow!("The node answered with no nonce"))?,
+    };
+    let tx = sign(signer, nonce, payload)?;
+    let hash = rpc
+        .call("chain_sendRawTransaction", json!([raw(&tx)?]))
+        .await?;
+    let hash = hash
+        .as_str()
+        .ok_or_else(|| anyhow!("The node answered with no hash"))?
+        .to_string();
+    println!("{}", hash);
+    eprintln!("Sent {} from {} with nonce {}", hash, sender, nonce);
+
+    let Some(timeout) = wait else {
+        return Ok(());
+    };
+    let started = Instant::now();
+    loop {
+        let found = rpc.call("chain_getTransaction", json!([hash])).await?;
+        if let Some(height) = found["blockNumber"].as_u64() {
+            eprintln!(
+                "In block {} ({})",
+                height,
+                found["blockHash"].as_str().unwrap_or_default()
+            );
+            return Ok(());
+        }
+        if started.elapsed() >= timeout {
+            bail!(
+                "{} is not in a block after {} seconds",
+                hash,
+                timeout.as_secs()
+            );
+        }
+        tokio::time::sleep(INCLUSION_POLL).await;
+    }
+}
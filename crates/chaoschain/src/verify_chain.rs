@@ -0,0 +1,96 @@
+//! `chaoschain verify-chain`: walk the chain a data directory holds, with
+//! its node stopped, to find where it parted from the network:
+//!
+//! ```bash
+//! chaoschain verify-chain --data-dir data
+//! chaoschain verify-chain --from-height 400 --genesis testnet/genesis.json
+//! ```
+//!
+//! Fails at the first divergence, naming the height and block. See
+//! [`chaoschain_state::verify`] for what is walked and checked.
+
+use anyhow::{bail, Result};
+use chaoschain_state::verify::{self, ChainVerification};
+use std::path::Path;
+
+pub fn run(
+    data_dir: &Path,
+    from_height: Option<u64>,
+    genesis: Option<String>,
+    json: bool,
+) -> Result<()> {
+    let genesis = genesis.as_deref().map(crate::genesis::load).transpose()?;
+    let verification = verify::verify_chain(data_dir, from_height, genesis.as_ref())?;
+    if json {
+        println!("{}", serde_json::to_string(&verification)?);
+    } else {
+        print(&verification);
+    }
+    match &verification.divergence {
+        Some(divergence) => bail!(
+            "{} diverges at height {}",
+            data_dir.display(),
+            divergence.height
+        ),
+        None if verification.blocks == 0 => {
+            bail!("No blocks in {} to verify", data_dir.display())
+        }
+        None => Ok(()),
+    }
+}
+
+fn print(verification: &ChainVerification) {
+    match (verification.from, verification.to) {
+        (Some(from), Some(to)) => println!(
+            "Verified {} blocks from height {} to {}",
+            verification.blocks, from, to
+        ),
+        (Some(from), None) => println!("Started from height {}", from),
+        (None, _) if verification.divergence.is_none() => return,
+        (None, _) => {}
+    }
+    println!(
+        "  {} snapshots matched{}",
+        verification.checkpoints.len(),
+        match verification.checkpoints.as_slice() {
+            [] => String::new(),
+            heights => format!(
+                " (at {})",
+                heights
+                    .iter()
+                    .map(u64::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    );
+    println!(
+        "  {} producer signatures checked, {} by producers of unknown key",
+        verification.signatures_checked,
+        verification.blocks - verification.signatures_checked
+    );
+    println!(
+        "  {} finality certificates checked, {} blocks stored without one",
+        verification.certified,
+        verification.blocks - verification.certified
+    );
+    for (low, high) in &verification.gaps {
+        println!("  No blocks on disk from height {} to {}", low, high);
+    }
+    if verification.unsettled > 0 {
+        println!(
+            "  {} proposals at the next height, none built on yet",
+            verification.unsettled
+        );
+    }
+    match &verification.divergence {
+        Some(divergence) => {
+            println!("Diverges at height {}", divergence.height);
+            if let Some(block) = &divergence.block {
+                println!("  block 0x{}", block);
+            }
+            println!("  {}", divergence.problem);
+        }
+        None => println!("No divergence found"),
+    }
+}
@@ -0,0 +1,522 @@
+//! `chaoschain watch`: the show of a node in a terminal, for servers with
+//! no browser at hand:
+//!
+//! ```bash
+//! chaoschain watch --node http://127.0.0.1:3000
+//! chaoschain watch --agent alice --backfill 50
+//! ```
+//!
+//! The screen is split in three panes, the blocks proposed, the votes on
+//! them and the drama of the agents, each tailing its stream of
+//! `/api/v1/sse/{stream}`. An agent keeps its color across the panes. A
+//! stream that drops is resumed from its last item, so nothing is shown
+//! twice. Ctrl-C quits.
+
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::auth::API_KEY_HEADER;
+
+/// Enters the alternate screen and hides the cursor
+const ENTER: &str = "\x1B[?1049h\x1B[?25l";
+
+/// Shows the cursor and leaves the alternate screen
+const LEAVE: &str = "\x1B[?25h\x1B[?1049l";
+
+/// Moves the cursor to the top left
+const HOME: &str = "\x1B[H";
+
+/// Clears the rest of the line, and the rest of the screen
+const CLEAR_LINE: &str = "\x1B[K";
+const CLEAR_BELOW: &str = "\x1B[J";
+
+const BOLD: &str = "\x1B[1m";
+const DIM: &str = "\x1B[2m";
+const RESET: &str = "\x1B[0m";
+
+/// Colors agents are told apart by, readable on dark and light terminals
+const AGENT_COLORS: [u8; 12] = [31, 32, 33, 34, 35, 36, 91, 92, 93, 94, 95, 96];
+
+/// Lines kept per pane, more than any terminal shows
+const KEPT_LINES: usize = 500;
+
+/// Most time between two redraws while items pour in
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time between two looks at the size of the terminal
+const RESIZE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wait before a dropped stream is resumed
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Size of a terminal that does not tell
+const DEFAULT_SIZE: (usize, usize) = (80, 24);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Blocks,
+    Votes,
+    Drama,
+}
+
+impl Pane {
+    const ALL: [Pane; 3] = [Pane::Blocks, Pane::Votes, Pane::Drama];
+
+    fn stream(self) -> &'static str {
+        match self {
+            Self::Blocks => "newBlocks",
+            Self::Votes => "votes",
+            Self::Drama => "drama",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Self::Blocks => "Blocks",
+            Self::Votes => "Votes",
+            Self::Drama => "Drama",
+        }
+    }
+}
+
+/// What the stream readers tell the screen
+enum Update {
+    /// An item of a stream, or a note the node sent along with them
+    Item {
+        pane: Pane,
+        event: String,
+        data: Value,
+    },
+    /// The stream is being read, or failed and will be resumed
+    Connection { pane: Pane, error: Option<String> },
+}
+
+pub async fn run(
+    node: &str,
+    api_key: Option<String>,
+    agent: Option<String>,
+    backfill: usize,
+) -> Result<()> {
+    let client = reqwest::Client::new();
+    let node = node.trim_end_matches('/').to_string();
+    // A node that is not there is better told right away than drawn around
+    let url = format!("{}/api/v1/sse/{}", node, Pane::Blocks.stream());
+    let mut probe = client.get(&url).query(&[("backfill", "0")]);
+    if let Some(api_key) = &api_key {
+        probe = probe.header(API_KEY_HEADER, api_key);
+    }
+    let response = probe
+        .send()
+        .await
+        .with_context(|| format!("Could not reach {}", url))?;
+    if !response.status().is_success() {
+        bail!("{} answered {}", url, response.status());
+    }
+    drop(response);
+
+    let (tx, mut rx) = mpsc::channel(1024);
+    for pane in Pane::ALL {
+        tokio::spawn(follow(
+            client.clone(),
+            node.clone(),
+            api_key.clone(),
+            agent.clone(),
+            backfill,
+            pane,
+            tx.clone(),
+        ));
+    }
+    drop(tx);
+
+    let mut screen = Screen::new(&node, agent.as_deref());
+    let mut stdout = std::io::stdout();
+    print!("{}", ENTER);
+    let result = async {
+        let mut redraw = tokio::time::interval(REDRAW_INTERVAL);
+        // Asking the terminal takes a process, so it is not asked each redraw
+        let mut resize = tokio::time::interval(RESIZE_INTERVAL);
+        let mut size = terminal_size();
+        let mut dirty = true;
+        loop {
+            tokio::select! {
+                update = rx.recv() => match update {
+                    Some(update) => {
+                        screen.update(update);
+                        dirty = true;
+                    }
+                    None => bail!("Every stream of {} ended", node),
+                },
+                _ = resize.tick() => {
+                    let now = terminal_size();
+                    dirty |= now != size;
+                    size = now;
+                },
+                _ = redraw.tick() => if dirty {
+                    stdout.write_all(screen.draw(size).as_bytes())?;
+                    stdout.flush()?;
+                    dirty = false;
+                },
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+        }
+    }
+    .await;
+    print!("{}", LEAVE);
+    stdout.flush()?;
+    result
+}
+
+/// Read the stream of `pane` into `tx` until the screen is gone, resuming
+/// after the last item whenever it drops
+async fn follow(
+    client: reqwest::Client,
+    node: String,
+    api_key: Option<String>,
+    agent: Option<String>,
+    backfill: usize,
+    pane: Pane,
+    tx: mpsc::Sender<Update>,
+) {
+    let url = format!("{}/api/v1/sse/{}", node, pane.stream());
+    let mut last_id: Option<String> = None;
+    loop {
+        let error = match read(
+            &client,
+            &url,
+            &api_key,
+            &agent,
+            backfill,
+            pane,
+            &tx,
+            &mut last_id,
+        )
+        .await
+        {
+            Ok(()) => "The node closed the stream".to_string(),
+            Err(e) => format!("{:#}", e),
+        };
+        if tx
+            .send(Update::Connection {
+                pane,
+                error: Some(error),
+            })
+            .await
+            .is_err()
+        {
+            return;
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn read(
+    client: &reqwest::Client,
+    url: &str,
+    api_key: &Option<String>,
+    agent: &Option<String>,
+    backfill: usize,
+    pane: Pane,
+    tx: &mpsc::Sender<Update>,
+    last_id: &mut Option<String>,
+) -> Result<()> {
+    let mut request = client.get(url).query(&[("backfill", backfill.to_string())]);
+    if let Some(agent) = agent {
+        request = request.query(&[("involving", agent)]);
+    }
+    if let Some(api_key) = api_key {
+        request = request.header(API_KEY_HEADER, api_key);
+    }
+    if let Some(id) = last_id {
+        request = request.header("Last-Event-ID", id.as_str());
+    }
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Could not reach {}", url))?;
+    if !response.status().is_success() {
+        bail!("{} answered {}", url, response.status());
+    }
+    let _ = tx.send(Update::Connection { pane, error: None }).await;
+
+    let mut body = response.bytes_stream();
+    // Bytes, as a character may be split between two chunks
+    let mut buffer: Vec<u8> = Vec::new();
+    while let Some(chunk) = body.next().await {
+        buffer.extend_from_slice(&chunk?);
+        while let Some(end) = buffer.windows(2).position(|pair| pair == b"\n\n") {
+            let block: Vec<u8> = buffer.drain(..end + 2).collect();
+            let block = String::from_utf8_lossy(&block);
+            let mut event = "message".to_string();
+            let mut data = String::new();
+            for line in block.lines() {
+                if let Some(value) = line.strip_prefix("event:") {
+                    event = value.trim().to_string();
+                } else if let Some(value) = line.strip_prefix("id:") {
+                    *last_id = Some(value.trim().to_string());
+                } else if let Some(value) = line.strip_prefix("data:") {
+                    if !data.is_empty() {
+                        data.push('\n');
+                    }
+                    data.push_str(value.strip_prefix(' ').unwrap_or(value));
+                }
+            }
+            // Keep-alives come as comments, with no data
+            if data.is_empty() {
+                continue;
+            }
+            let data = serde_json::from_str(&data).unwrap_or(Value::String(data));
+            if tx.send(Update::Item { pane, event, data }).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A line of text in pieces, each with its own style
+#[derive(Default)]
+struct Line(Vec<(Option<String>, String)>);
+
+impl Line {
+    fn plain(mut self, text: impl Into<String>) -> Self {
+        self.0.push((None, text.into()));
+        self
+    }
+
+    fn styled(mut self, style: impl Into<String>, text: impl Into<String>) -> Self {
+        self.0.push((Some(style.into()), text.into()));
+        self
+    }
+
+    fn agent(self, name: &str) -> Self {
+        self.styled(agent_color(name), name)
+    }
+
+    /// The line cut at `width` characters, styled unless `color` is off
+    fn render(&self, width: usize, color: bool) -> String {
+        let mut out = String::new();
+        let mut left = width;
+        for (style, text) in &self.0 {
+            if left == 0 {
+                break;
+            }
+            let text: String = text
+                .chars()
+                .map(|c| if c.is_control() { ' ' } else { c })
+                .take(left)
+                .collect();
+            left -= text.chars().count();
+            match style {
+                Some(style) if color => {
+                    let _ = write!(out, "{}{}{}", style, text, RESET);
+                }
+                _ => out.push_str(&text),
+            }
+        }
+        out
+    }
+}
+
+/// The same color for an agent wherever it shows
+fn agent_color(name: &str) -> String {
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!(
+        "\x1B[{}m",
+        AGENT_COLORS[(hash % AGENT_COLORS.len() as u64) as usize]
+    )
+}
+
+fn short_hash(hash: &str) -> &str {
+    hash.get(..10).unwrap_or(hash)
+}
+
+struct Screen {
+    node: String,
+    agent: Option<String>,
+    color: bool,
+    lines: [VecDeque<Line>; 3],
+    counts: [u64; 3],
+    errors: [Option<String>; 3],
+}
+
+impl Screen {
+    fn new(node: &str, agent: Option<&str>) -> Self {
+        Self {
+            node: node.to_string(),
+            agent: agent.map(str::to_string),
+            // https://no-color.org
+            color: std::env::var_os("NO_COLOR").is_none(),
+            lines: Default::default(),
+            counts: [0; 3],
+            errors: Default::default(),
+        }
+    }
+
+    fn update(&mut self, update: Update) {
+        match update {
+            Update::Connection { pane, error } => self.errors[pane as usize] = error,
+            Update::Item { pane, event, data } => {
+                let line = match event.as_str() {
+                    "gap" | "error" => Line::default().styled(
+                        DIM,
+                        format!("… {}", data["message"].as_str().unwrap_or(&event)),
+                    ),
+                    _ => {
+                        self.counts[pane as usize] += 1;
+                        match pane {
+                            Pane::Blocks => block_line(&data),
+                            Pane::Votes => vote_line(&data),
+                            Pane::Drama => drama_line(&data),
+                        }
+                    }
+                };
+                let lines = &mut self.lines[pane as usize];
+                lines.push_back(line);
+                if lines.len() > KEPT_LINES {
+                    lines.pop_front();
+                }
+            }
+        }
+    }
+
+    /// The whole screen for a terminal of `width` by `height`
+    fn draw(&self, (width, height): (usize, usize)) -> String {
+        let mut header = Line::default()
+            .styled(BOLD, "ChaosChain")
+            .plain(format!("  {}", self.node));
+        if let Some(agent) = &self.agent {
+            header = header.plain("  involving ").agent(agent);
+        }
+
+        // The header, then a title line and at least a line per pane. Drama
+        // is the wordiest and gets what the other two leave.
+        let body = height
+            .saturating_sub(1 + Pane::ALL.len())
+            .max(Pane::ALL.len());
+        let blocks = (body / 4).max(1);
+        let votes = (body / 4).max(1);
+        let sizes = [blocks, votes, body.saturating_sub(blocks + votes).max(1)];
+
+        let mut out = String::from(HOME);
+        let mut push = |line: &Line| {
+            out.push_str(&line.render(width, self.color));
+            out.push_str(CLEAR_LINE);
+            out.push_str("\r\n");
+        };
+        push(&header);
+        for (pane, size) in Pane::ALL.into_iter().zip(sizes) {
+            let index = pane as usize;
+            let mut title = Line::default()
+                .styled(BOLD, format!("── {} ", pane.title()))
+                .styled(DIM, format!("({})", self.counts[index]));
+            if let Some(error) = &self.errors[index] {
+                title = title.styled("\x1B[31m", format!("  {}, retrying", error));
+            }
+            push(&title);
+            let lines = &self.lines[index];
+            let shown = lines.len().min(size);
+            for line in lines.iter().skip(lines.len() - shown) {
+                push(line);
+            }
+            for _ in shown..size {
+                push(&Line::default());
+            }
+        }
+        // Nothing is written past the last line, which would scroll
+        if out.ends_with("\r\n") {
+            out.truncate(out.len() - 2);
+        }
+        out.push_str(CLEAR_BELOW);
+        out
+    }
+}
+
+fn block_line(block: &Value) -> Line {
+    let transactions = block["transactions"].as_array().map_or(0, Vec::len);
+    Line::default()
+        .styled(
+            BOLD,
+            format!("#{:<6}", block["height"].as_u64().unwrap_or_default()),
+        )
+        .styled(
+            DIM,
+            format!(
+                "{} ",
+                short_hash(block["hash"].as_str().unwrap_or_default())
+            ),
+        )
+        .agent(block["producer"].as_str().unwrap_or("?"))
+        .plain(format!(
+            "  {}  drama {}  {} tx{}",
+            block["producer_mood"].as_str().unwrap_or_default(),
+            block["drama_level"].as_u64().unwrap_or_default(),
+            transactions,
+            if transactions == 1 { "" } else { "s" }
+        ))
+}
+
+fn vote_line(vote: &Value) -> Line {
+    let approved = vote["approved"].as_bool().unwrap_or_default();
+    Line::default()
+        .styled(
+            if approved { "\x1B[32m" } else { "\x1B[31m" },
+            if approved { "✔ " } else { "✘ " },
+        )
+        .agent(vote["validator"].as_str().unwrap_or("?"))
+        .styled(
+            DIM,
+            format!(
+                " on {}  drama {}  ",
+                short_hash(vote["block_hash"].as_str().unwrap_or_default()),
+                vote["drama_level"].as_u64().unwrap_or_default()
+            ),
+        )
+        .plain(vote["reason"].as_str().unwrap_or_default())
+}
+
+fn drama_line(drama: &Value) -> Line {
+    let line = Line::default().styled(
+        DIM,
+        format!("{:<12} ", drama["kind"].as_str().unwrap_or_default()),
+    );
+    let line = match drama["agent"].as_str().filter(|agent| !agent.is_empty()) {
+        Some(agent) => line.agent(agent).plain(": "),
+        None => line,
+    };
+    line.plain(drama["message"].as_str().unwrap_or_default())
+}
+
+/// Width and height of the terminal, as `stty` or the environment tell
+fn terminal_size() -> (usize, usize) {
+    let stty = std::fs::File::open("/dev/tty").ok().and_then(|tty| {
+        let output = std::process::Command::new("stty")
+            .arg("size")
+            .stdin(tty)
+            .output()
+            .ok()?;
+        let output = String::from_utf8(output.stdout).ok()?;
+        let mut size = output.split_whitespace().map(str::parse::<usize>);
+        let (rows, columns) = (size.next()?.ok()?, size.next()?.ok()?);
+        (rows > 0 && columns > 0).then_some((columns, rows))
+    });
+    stty.unwrap_or_else(|| {
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+        };
+        (
+            var("COLUMNS").unwrap_or(DEFAULT_SIZE.0),
+            var("LINES").unwrap_or(DEFAULT_SIZE.1),
+        )
+    })
+}
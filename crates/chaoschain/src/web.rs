@@ -5,22 +5,34 @@ use axum::{
     body::Body,
     extract::{
         ws::{Message, WebSocket},
-        Extension, Path, State, WebSocketUpgrade,
+        Extension, Path, Query, State, WebSocketUpgrade,
     },
-    http::{header, Request, StatusCode},
+    http::{header, HeaderMap, Request, StatusCode},
     middleware::{self, Next},
     response::{
         sse::{Event, Sse},
         IntoResponse,
     },
-    routing::{get, post},
+    routing::{get, post, put},
     Json, Router,
 };
-use chaoschain_consensus::ConsensusManager;
+use chaoschain_agent::{
+    AgentUsage, Artwork, ArtworkStore, AuditEntry, AuditFilter, AuditStore, DecisionQueue,
+    HumanVote, ModelUsage, Moderator, PendingDecision, UsageTracker,
+};
+use chaoschain_cli::{AgentProfile, ApiConfig, CorsConfig, CorsPolicy, LinkConditions};
+use chaoschain_consensus::{ConsensusManager, PartitionDetector, PartitionStatus};
+use chaoschain_core::channel::{ChannelMetrics, Lossy};
+use chaoschain_core::metrics::MetricsRegistry;
+use chaoschain_core::relationships::{GraphSnapshot, RelationshipEdge, RelationshipGraph};
+use chaoschain_core::sync::SyncState;
+use chaoschain_core::vote::{Misbehavior, SignedVote};
 use chaoschain_core::{Block, NetworkEvent, Transaction, ValidationDecision};
+use chaoschain_mempool::Mempool;
+use chaoschain_state::rolling::ChainLedger;
 use chaoschain_state::StateStoreImpl;
 use chrono;
-use futures::stream::Stream;
+use futures::stream::{self, SplitSink, SplitStream, Stream};
 use futures::{SinkExt, StreamExt};
 use hex;
 use rand;
@@ -30,14 +42,32 @@ use serde_json;
 use serde_json::json;
 use std::collections::HashMap;
 use std::fmt;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::broadcast;
+use subtle::ConstantTimeEq;
 use tokio::sync::RwLock;
-use tokio_stream::wrappers::BroadcastStream;
-use tower_http::cors::{Any, CorsLayer};
+use tokio::sync::{broadcast, watch};
+use tokio_rustls::TlsAcceptor;
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer};
 use tower_http::services::ServeDir;
-use tracing::error;
+use tracing::{error, info, warn};
+
+use crate::chat::ChatDesk;
+use crate::faucet::Faucet;
+use crate::gateway::{
+    AgentGateway, ExternalAgent, GatewayError, GatewayRegistration, ProposalNotice, VoteReceipt,
+};
+use crate::lifecycle::{AgentInfo, AgentRole, AgentSupervisor, LifecycleError};
+use crate::netsim::{NetworkSim, NetworkSimStatus};
+use crate::network::NetworkLink;
+use crate::ratelimit::RateLimiter;
+use crate::shards::DramaShards;
+use crate::subscriptions::{self, Streams};
+use crate::{
+    auth, chat, compression, explorer, faucet, feed, governance, graph, graphql, grpc, health,
+    mempool, openapi, ratelimit, replay, rest, rpc, shards, staking, stats, tls,
+};
 
 /// Web server state
 pub struct AppState {
@@ -49,6 +79,132 @@ pub struct AppState {
     pub consensus: Arc<ConsensusManager>,
     /// Agent relationships
     pub agent_relationships: RwLock<HashMap<String, AgentRelationship>>,
+    /// Pairwise relationship graph shared with the agents
+    pub relationships: Arc<RelationshipGraph>,
+    /// Agent prompt/response audit trail
+    pub audit: Arc<AuditStore>,
+    /// Runtime agent lifecycle, absent when the node runs no local agents
+    pub agents: Option<Arc<AgentSupervisor>>,
+    /// Conversations between spectators and the local agents
+    pub chat: Option<Arc<ChatDesk>>,
+    /// Per-agent token usage and cost
+    pub usage: Arc<UsageTracker>,
+    /// Metrics of every subsystem, for Prometheus
+    pub metrics: MetricsRegistry,
+    /// How the queues between the parts of the node fill up
+    pub channels: ChannelMetrics,
+    /// Signed-vote gateway for external agents
+    pub gateway: Option<Arc<AgentGateway>>,
+    /// Milestone artwork
+    pub artwork: Arc<ArtworkStore>,
+    /// Validator seats played by humans
+    pub humans: Option<HumanSeats>,
+    /// Redacts agent output before it reaches the feed
+    pub moderator: Arc<Moderator>,
+    /// Whether a quorum of the validators can still be heard
+    pub partition: Arc<PartitionDetector>,
+    /// Simulated links between the agents
+    pub network_sim: Option<Arc<NetworkSim>>,
+    /// Transactions waiting for a block, fed by the JSON-RPC endpoint
+    pub mempool: Option<Arc<Mempool>>,
+    /// Peers the transactions taken in are passed on to
+    pub network: Option<NetworkLink>,
+    /// Test balance for anyone, on a dev chain only
+    pub faucet: Option<Arc<Faucet>>,
+    /// Chains run beside the main one, with `--shards`
+    pub shards: Option<Arc<DramaShards>>,
+    /// Recent and live items for `/api/v1/ws` subscribers
+    pub streams: Arc<Streams>,
+    /// Tokens that may use the APIs
+    pub api: ApiConfig,
+    /// Requests left to each client
+    pub limiter: RateLimiter,
+    /// Rolling statistics of the chain, for `/api/v1/stats`
+    pub chain: Arc<parking_lot::Mutex<ChainLedger>>,
+}
+
+/// Agent-side services exposed through the web API
+pub struct WebServices {
+    /// Pairwise relationship graph shared with the agents
+    pub relationships: Arc<RelationshipGraph>,
+    /// Agent prompt/response audit trail
+    pub audit: Arc<AuditStore>,
+    /// Runtime agent lifecycle
+    pub agents: Option<Arc<AgentSupervisor>>,
+    /// Conversations between spectators and the local agents
+    pub chat: Option<Arc<ChatDesk>>,
+    /// Per-agent token usage and cost
+    pub usage: Arc<UsageTracker>,
+    /// Metrics of every subsystem, for Prometheus
+    pub metrics: MetricsRegistry,
+    /// How the queues between the parts of the node fill up
+    pub channels: ChannelMetrics,
+    /// Signed-vote gateway for external agents
+    pub gateway: Option<Arc<AgentGateway>>,
+    /// Milestone artwork
+    pub artwork: Arc<ArtworkStore>,
+    /// Validator seats played by humans
+    pub humans: Option<HumanSeats>,
+    /// Redacts agent output before it reaches the feed
+    pub moderator: Arc<Moderator>,
+    /// Whether a quorum of the validators can still be heard
+    pub partition: Arc<PartitionDetector>,
+    /// Simulated links between the agents
+    pub network_sim: Option<Arc<NetworkSim>>,
+    /// Transactions waiting for a block, fed by the JSON-RPC endpoint
+    pub mempool: Option<Arc<Mempool>>,
+    /// Peers of the node, when it is on the p2p network
+    pub network: Option<NetworkLink>,
+    /// Test balance for anyone, on a dev chain only
+    pub faucet: Option<Arc<Faucet>>,
+    /// Chains run beside the main one, with `--shards`
+    pub shards: Option<Arc<DramaShards>>,
+    /// Tokens that may use the APIs
+    pub api: ApiConfig,
+    /// Port of 127.0.0.1 the web server listens on
+    pub port: u16,
+    /// Port of the gRPC interface, none when it is not served
+    pub grpc_port: Option<u16>,
+    /// Which pages elsewhere may call the APIs
+    pub cors: CorsConfig,
+    /// Encrypts the connections, served over plain HTTP when absent
+    pub tls: Option<TlsAcceptor>,
+}
+
+impl Default for WebServices {
+    fn default() -> Self {
+        Self {
+            relationships: Arc::new(RelationshipGraph::new()),
+            audit: Arc::new(AuditStore::disabled()),
+            agents: None,
+            chat: None,
+            usage: Arc::new(UsageTracker::default()),
+            metrics: MetricsRegistry::default(),
+            channels: ChannelMetrics::default(),
+            gateway: None,
+            artwork: Arc::new(ArtworkStore::default()),
+            humans: None,
+            moderator: Arc::new(Moderator::default()),
+            partition: Arc::new(PartitionDetector::default()),
+            network_sim: None,
+            mempool: None,
+            network: None,
+            faucet: None,
+            shards: None,
+            api: ApiConfig::default(),
+            port: 3000,
+            grpc_port: None,
+            cors: CorsConfig::default(),
+            tls: None,
+        }
+    }
+}
+
+/// Decisions waiting for human validators, and the token that may answer them
+#[derive(Clone)]
+pub struct HumanSeats {
+    pub queue: Arc<DecisionQueue>,
+    pub token: String,
 }
 
 #[derive(Default)]
@@ -308,6 +464,20 @@ enum WSMessage {
     },
     #[serde(rename = "ExternalAgentsUpdate")]
     ExternalAgentsUpdate { agents: Vec<serde_json::Value> },
+    #[serde(rename = "AgentThought")]
+    AgentThought {
+        agent: String,
+        height: u64,
+        chunk: String,
+        done: bool,
+    },
+    #[serde(rename = "Artwork")]
+    Artwork {
+        hash: String,
+        caption: String,
+        height: u64,
+        url: String,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -354,6 +524,42 @@ struct MempoolStats {
 }
 
 impl AppState {
+    /// The state the handlers share, over `services`
+    pub fn new(
+        tx: broadcast::Sender<NetworkEvent>,
+        state: Arc<StateStoreImpl>,
+        consensus: Arc<ConsensusManager>,
+        services: &WebServices,
+    ) -> Arc<Self> {
+        Arc::new(AppState {
+            tx,
+            state,
+            consensus,
+            agent_relationships: RwLock::new(HashMap::new()),
+            relationships: services.relationships.clone(),
+            audit: services.audit.clone(),
+            agents: services.agents.clone(),
+            chat: services.chat.clone(),
+            usage: services.usage.clone(),
+            metrics: services.metrics.clone(),
+            channels: services.channels.clone(),
+            gateway: services.gateway.clone(),
+            artwork: services.artwork.clone(),
+            humans: services.humans.clone(),
+            moderator: services.moderator.clone(),
+            partition: services.partition.clone(),
+            network_sim: services.network_sim.clone(),
+            mempool: services.mempool.clone(),
+            network: services.network.clone(),
+            faucet: services.faucet.clone(),
+            shards: services.shards.clone(),
+            streams: Arc::new(Streams::new()),
+            limiter: RateLimiter::new(services.api.rate_limits.clone()),
+            chain: Arc::new(parking_lot::Mutex::new(ChainLedger::default())),
+            api: services.api.clone(),
+        })
+    }
+
     /// Validate agent token
     pub fn validate_token(&self, agent_id: &str, token: &str) -> bool {
         // For testing purposes, just check if both values exist and token has expected prefix
@@ -466,34 +672,609 @@ async fn get_external_agents(State(state): State<Arc<AppState>>) -> Json<Vec<ser
     Json(agents)
 }
 
-/// Start the web server
+/// Get the full agent relationship graph for rendering the social network
+async fn get_relationship_graph(State(state): State<Arc<AppState>>) -> Json<GraphSnapshot> {
+    Json(state.relationships.snapshot())
+}
+
+/// Get all relationships an agent is part of
+async fn get_agent_relationships(
+    State(state): State<Arc<AppState>>,
+    Path(agent_id): Path<String>,
+) -> Json<Vec<RelationshipEdge>> {
+    Json(state.relationships.relationships_of(&agent_id))
+}
+
+/// Query the agent decision audit trail, e.g. `?agent=validator-3&height=418`
+async fn get_audit_entries(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<AuditFilter>,
+) -> Json<Vec<AuditEntry>> {
+    Json(state.audit.query(&filter))
+}
+
+/// Export the agent decision audit trail as JSON lines
+async fn export_audit_entries(
+    State(state): State<Arc<AppState>>,
+    Query(filter): Query<AuditFilter>,
+) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"audit.jsonl\"",
+            ),
+        ],
+        state.audit.export_jsonl(&filter),
+    )
+}
+
+/// Model usage summary: totals plus every agent, biggest spender first
+#[derive(Debug, Serialize)]
+struct UsageReport {
+    total: ModelUsage,
+    agents: Vec<AgentUsage>,
+}
+
+/// Get token usage and estimated cost for all agents
+async fn get_usage(State(state): State<Arc<AppState>>) -> Json<UsageReport> {
+    Json(UsageReport {
+        total: state.usage.total(),
+        agents: state.usage.agents(),
+    })
+}
+
+/// Get token usage and estimated cost of one agent
+async fn get_agent_usage(
+    State(state): State<Arc<AppState>>,
+    Path(agent_id): Path<String>,
+) -> Result<Json<AgentUsage>, StatusCode> {
+    state
+        .usage
+        .agent(&agent_id)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Prometheus scrape endpoint
+async fn get_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    encode_metrics(&state.metrics)
+}
+
+fn encode_metrics(metrics: &MetricsRegistry) -> impl IntoResponse {
+    match metrics.encode() {
+        Ok(body) => (
+            StatusCode::OK,
+            [(
+                header::CONTENT_TYPE,
+                "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            )],
+            body,
+        ),
+        Err(e) => {
+            error!("Failed to encode metrics: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                [(header::CONTENT_TYPE, "text/plain")],
+                String::new(),
+            )
+        }
+    }
+}
+
+/// Serve `/metrics` alone on `port`, so Prometheus can scrape a node that
+/// runs no web server, or without going through its API tokens
+pub async fn serve_metrics(
+    metrics: MetricsRegistry,
+    port: u16,
+    cors: &CorsPolicy,
+) -> std::io::Result<()> {
+    let app = Router::new()
+        .route(
+            "/metrics",
+            get(move || {
+                let metrics = metrics.clone();
+                async move { encode_metrics(&metrics) }
+            }),
+        )
+        .layer(cors_layer(cors));
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", port)).await?;
+    info!("Metrics listening on http://127.0.0.1:{}/metrics", port);
+    axum::serve(listener, app).await
+}
+
+/// What `policy` lets browsers do, for the listener it is laid on
+pub fn cors_layer(policy: &CorsPolicy) -> CorsLayer {
+    let origins = if policy.allows_any_origin() {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            policy
+                .allowed_origins
+                .iter()
+                .filter_map(|origin| origin.parse().ok()),
+        )
+    };
+    let methods = if policy.allowed_methods.iter().any(|method| method == "*") {
+        AllowMethods::any()
+    } else {
+        AllowMethods::list(
+            policy
+                .allowed_methods
+                .iter()
+                .filter_map(|method| method.parse().ok()),
+        )
+    };
+    let headers = if policy.allowed_headers.iter().any(|header| header == "*") {
+        AllowHeaders::any()
+    } else {
+        AllowHeaders::list(
+            policy
+                .allowed_headers
+                .iter()
+                .filter_map(|header| header.parse().ok()),
+        )
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+}
+
+/// A finalized block and the outcome of checking its signed explanations
+#[derive(Debug, Serialize)]
+struct FinalizedBlockReport {
+    block: Block,
+    /// Validators whose embedded signature does not hold up
+    invalid_explanations: Vec<String>,
+}
+
+/// Get a finalized block, vote explanations included
+async fn get_finalized_block(
+    State(state): State<Arc<AppState>>,
+    Path(block_hash): Path<String>,
+) -> Result<Json<FinalizedBlockReport>, (StatusCode, String)> {
+    let mut hash = [0u8; 32];
+    hex::decode_to_slice(block_hash.trim_start_matches("0x"), &mut hash).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid block hash: {}", e),
+        )
+    })?;
+    let block = state
+        .consensus
+        .get_finalized_block(&hash)
+        .await
+        .ok_or((StatusCode::NOT_FOUND, "Block is not finalized".to_string()))?;
+    let invalid_explanations = block
+        .metadata
+        .explanations
+        .iter()
+        .filter(|e| e.verify(block.height, &hash).is_err())
+        .map(|e| e.validator.clone())
+        .collect();
+    Ok(Json(FinalizedBlockReport {
+        block,
+        invalid_explanations,
+    }))
+}
+
+/// Metadata of the artwork painted for recent milestones, newest first
+async fn list_artwork(State(state): State<Arc<AppState>>) -> Json<Vec<Artwork>> {
+    Json(state.artwork.list())
+}
+
+/// Serve a painting by the hash stored with its event
+async fn get_artwork(
+    State(state): State<Arc<AppState>>,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let (artwork, image) = state.artwork.get(&hash).ok_or(StatusCode::NOT_FOUND)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, artwork.content_type),
+            // Content addressed, so it never changes
+            (
+                header::CACHE_CONTROL,
+                "public, max-age=31536000, immutable".to_string(),
+            ),
+        ],
+        image.as_ref().clone(),
+    ))
+}
+
+/// Request to start a new agent
+#[derive(Debug, Deserialize)]
+struct SpawnAgentRequest {
+    role: AgentRole,
+    id: Option<String>,
+    #[serde(flatten)]
+    profile: AgentProfile,
+}
+
+pub fn lifecycle_status(error: LifecycleError) -> (StatusCode, String) {
+    let status = match error {
+        LifecycleError::UnknownAgent(_) => StatusCode::NOT_FOUND,
+        LifecycleError::AlreadyRunning(_) => StatusCode::CONFLICT,
+    };
+    (status, error.to_string())
+}
+
+fn supervisor(state: &AppState) -> Result<&Arc<AgentSupervisor>, (StatusCode, String)> {
+    state.agents.as_ref().ok_or((
+        StatusCode::NOT_IMPLEMENTED,
+        "This node runs no local agents".to_string(),
+    ))
+}
+
+/// List the locally running agents
+async fn list_agents(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<AgentInfo>>, (StatusCode, String)> {
+    Ok(Json(supervisor(&state)?.list().await))
+}
+
+/// Spawn a validator or producer without restarting the node
+async fn spawn_agent(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<SpawnAgentRequest>,
+) -> Result<Json<AgentInfo>, (StatusCode, String)> {
+    supervisor(&state)?
+        .spawn(request.role, request.id, request.profile)
+        .await
+        .map(Json)
+        .map_err(lifecycle_status)
+}
+
+/// Restart an agent under the same id with a new personality and traits
+async fn replace_agent(
+    State(state): State<Arc<AppState>>,
+    Path(agent_id): Path<String>,
+    Json(profile): Json<AgentProfile>,
+) -> Result<Json<AgentInfo>, (StatusCode, String)> {
+    supervisor(&state)?
+        .replace(&agent_id, profile)
+        .await
+        .map(Json)
+        .map_err(lifecycle_status)
+}
+
+/// Retire an agent
+async fn retire_agent(
+    State(state): State<Arc<AppState>>,
+    Path(agent_id): Path<String>,
+) -> Result<Json<AgentInfo>, (StatusCode, String)> {
+    supervisor(&state)?
+        .retire(&agent_id)
+        .await
+        .map(Json)
+        .map_err(lifecycle_status)
+}
+
+pub fn gateway_status(error: GatewayError) -> (StatusCode, String) {
+    let status = match error {
+        GatewayError::InvalidKey(_) => StatusCode::BAD_REQUEST,
+        GatewayError::InvalidSignature(_) => StatusCode::UNAUTHORIZED,
+        GatewayError::AlreadyRegistered(_) | GatewayError::Equivocation(_) => StatusCode::CONFLICT,
+        GatewayError::UnknownAgent(_) | GatewayError::UnknownProposal(_) => StatusCode::NOT_FOUND,
+        GatewayError::Suspended(_) | GatewayError::Banned(_) => StatusCode::FORBIDDEN,
+        GatewayError::DeadlinePassed { .. } => StatusCode::REQUEST_TIMEOUT,
+    };
+    (status, error.to_string())
+}
+
+fn gateway(state: &AppState) -> Result<&Arc<AgentGateway>, (StatusCode, String)> {
+    state.gateway.as_ref().ok_or((
+        StatusCode::NOT_IMPLEMENTED,
+        "This node has no agent gateway".to_string(),
+    ))
+}
+
+/// Register an external agent by its ed25519 public key
+async fn gateway_register(
+    State(state): State<Arc<AppState>>,
+    Json(registration): Json<GatewayRegistration>,
+) -> Result<Json<ExternalAgent>, (StatusCode, String)> {
+    gateway(&state)?
+        .register(registration)
+        .await
+        .map(Json)
+        .map_err(gateway_status)
+}
+
+/// List the agents registered through the gateway
+async fn gateway_agents(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<ExternalAgent>>, (StatusCode, String)> {
+    Ok(Json(gateway(&state)?.agents()))
+}
+
+/// Proposals still accepting votes, for agents that poll
+async fn gateway_proposals(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<ProposalNotice>>, (StatusCode, String)> {
+    Ok(Json(gateway(&state)?.open_proposals()))
+}
+
+/// Submit a signed vote
+async fn gateway_vote(
+    State(state): State<Arc<AppState>>,
+    Json(vote): Json<SignedVote>,
+) -> Result<Json<VoteReceipt>, (StatusCode, String)> {
+    gateway(&state)?
+        .submit_vote(vote)
+        .await
+        .map(Json)
+        .map_err(gateway_status)
+}
+
+/// Recorded misbehavior, each entry verifiable against the offender's key
+async fn gateway_evidence(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Misbehavior>>, (StatusCode, String)> {
+    Ok(Json(gateway(&state)?.evidence()))
+}
+
+fn humans(state: &AppState) -> Result<&HumanSeats, (StatusCode, String)> {
+    state.humans.as_ref().ok_or((
+        StatusCode::NOT_IMPLEMENTED,
+        "This node has no human validators".to_string(),
+    ))
+}
+
+/// Decisions waiting for a human validator
+async fn human_pending(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<PendingDecision>>, (StatusCode, String)> {
+    Ok(Json(humans(&state)?.queue.pending()))
+}
+
+/// Answer a pending decision, authenticated by the human token
+async fn human_vote(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(vote): Json<HumanVote>,
+) -> Result<Json<PendingDecision>, (StatusCode, String)> {
+    let seats = humans(&state)?;
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !token.is_some_and(|token| bool::from(token.as_bytes().ct_eq(seats.token.as_bytes()))) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong human token".to_string(),
+        ));
+    }
+    seats
+        .queue
+        .submit(vote)
+        .map(Json)
+        .map_err(|e| (StatusCode::NOT_FOUND, e.to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+struct GatewaySessionQuery {
+    agent_id: String,
+}
+
+/// Gateway session protocol, JSON text frames tagged by `type`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum GatewayFrame {
+    /// Server: sign `chaoschain-auth-v1 || nonce` to authenticate
+    Challenge { nonce: String },
+    /// Client: signature over the challenge
+    Auth { signature: String },
+    /// Server: a proposal to vote on before its deadline
+    Proposal(Box<ProposalNotice>),
+    /// Client: a signed vote
+    Vote { vote: SignedVote },
+    /// Server: the vote was counted
+    Receipt(VoteReceipt),
+    /// Server: something went wrong, the session stays open unless unauthenticated
+    Error { message: String },
+}
+
+impl GatewayFrame {
+    fn to_message(&self) -> Option<Message> {
+        serde_json::to_string(self).ok().map(Message::Text)
+    }
+}
+
+/// Push proposals to an external agent and collect its votes over one socket
+async fn gateway_ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<GatewaySessionQuery>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let gateway = gateway(&state)?.clone();
+    Ok(ws.on_upgrade(move |socket| gateway_session(socket, gateway, query.agent_id)))
+}
+
+async fn gateway_session(socket: WebSocket, gateway: Arc<AgentGateway>, agent_id: String) {
+    let (mut sender, mut receiver) = socket.split();
+
+    let nonce: [u8; 32] = rand::random();
+    let challenge = GatewayFrame::Challenge {
+        nonce: hex::encode(nonce),
+    };
+    if let Some(message) = challenge.to_message() {
+        if sender.send(message).await.is_err() {
+            return;
+        }
+    }
+
+    let authenticated = match receiver.next().await {
+        Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+            Ok(GatewayFrame::Auth { signature }) => gateway
+                .verify_challenge(&agent_id, &nonce, &signature)
+                .map_err(|e| e.to_string()),
+            _ => Err("Expected an auth frame".to_string()),
+        },
+        _ => Err("Connection closed before authentication".to_string()),
+    };
+    if let Err(message) = authenticated {
+        if let Some(frame) = (GatewayFrame::Error { message }).to_message() {
+            let _ = sender.send(frame).await;
+        }
+        return;
+    }
+
+    gateway.session(&agent_id, true);
+    follow_proposals(&mut sender, &mut receiver, &gateway, &agent_id).await;
+    gateway.session(&agent_id, false);
+}
+
+/// The authenticated part of a gateway session, counting the bytes of
+/// every frame either way
+async fn follow_proposals(
+    sender: &mut SplitSink<WebSocket, Message>,
+    receiver: &mut SplitStream<WebSocket>,
+    gateway: &AgentGateway,
+    agent_id: &str,
+) {
+    let send = |frame: GatewayFrame| {
+        let text = serde_json::to_string(&frame).ok();
+        if let Some(text) = &text {
+            gateway.traffic(agent_id, 0, text.len());
+        }
+        text.map(Message::Text)
+    };
+
+    // Catch up on what is open, then follow new proposals
+    let mut proposals = gateway.subscribe();
+    for notice in gateway.open_proposals() {
+        if let Some(frame) = send(GatewayFrame::Proposal(Box::new(notice))) {
+            if sender.send(frame).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    loop {
+        let reply = tokio::select! {
+            notice = proposals.recv() => match notice {
+                Ok(notice) => GatewayFrame::Proposal(Box::new(notice)),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            message = receiver.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    gateway.traffic(agent_id, text.len(), 0);
+                    match serde_json::from_str(&text) {
+                        Ok(GatewayFrame::Vote { vote }) if vote.validator == agent_id => {
+                            match gateway.submit_vote(vote).await {
+                                Ok(receipt) => GatewayFrame::Receipt(receipt),
+                                Err(e) => GatewayFrame::Error { message: e.to_string() },
+                            }
+                        }
+                        Ok(GatewayFrame::Vote { .. }) => GatewayFrame::Error {
+                            message: "Votes must be cast by the authenticated agent".to_string(),
+                        },
+                        _ => GatewayFrame::Error {
+                            message: "Expected a vote frame".to_string(),
+                        },
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if let Some(frame) = send(reply) {
+            if sender.send(frame).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Start the web server, with the gRPC one, until `shutdown` turns true
 pub async fn start_web_server(
     tx: broadcast::Sender<NetworkEvent>,
     state: Arc<StateStoreImpl>,
     consensus: Arc<ConsensusManager>,
+    services: WebServices,
+    shutdown: watch::Receiver<bool>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let app_state = Arc::new(AppState {
-        tx,
-        state: state.clone(),
-        consensus,
-        agent_relationships: RwLock::new(HashMap::new()),
-    });
+    let app_state = AppState::new(tx, state, consensus, &services);
+    tokio::spawn(subscriptions::record(
+        app_state.streams.clone(),
+        app_state.tx.subscribe(),
+        services.mempool.clone(),
+        services.moderator.clone(),
+    ));
+    tokio::spawn(stats::record(
+        app_state.chain.clone(),
+        app_state.tx.subscribe(),
+        app_state.consensus.subscribe_finalized(),
+        services.mempool.clone(),
+    ));
+    if app_state.api.is_open() {
+        warn!("No API tokens configured, the APIs are open to anyone");
+    }
+    if let Some(port) = services.grpc_port {
+        let app_state = app_state.clone();
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Err(e) = grpc::serve(app_state, port, shutdown).await {
+                error!("gRPC server failed: {}", e);
+            }
+        });
+    }
 
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+    let cors = cors_layer(&services.cors.web(&app_state.api));
 
     // Public routes that don't require authentication
     let public_routes = Router::new()
         .route("/api/network/status", get(get_network_status))
+        .route("/api/network/partition", get(get_partition_status))
+        .route("/api/network/sync", get(get_sync_status))
+        .route(
+            "/api/network/links",
+            get(get_network_links).put(update_network_link),
+        )
         .route("/api/events", get(events_handler))
         .route("/api/agents/register", post(register_agent))
         .route("/api/ws", get(ws_handler))
         .route("/api/crypto/block/:height", get(get_block_crypto_info)) // New route
         .route("/api/crypto/state/proof", post(get_merkle_proof)) // New route
         .route("/api/crypto/state/root", get(get_state_root)) // New route
-        .route("/api/agents/external", get(get_external_agents));
+        .route("/api/agents/external", get(get_external_agents))
+        .route("/api/relationships", get(get_relationship_graph))
+        .route("/api/relationships/:agent_id", get(get_agent_relationships))
+        .route("/api/audit", get(get_audit_entries))
+        .route("/api/audit/export", get(export_audit_entries))
+        .route("/api/usage", get(get_usage))
+        .route("/api/usage/:agent_id", get(get_agent_usage))
+        .route("/metrics", get(get_metrics))
+        .route("/api/lifecycle/agents", get(list_agents).post(spawn_agent))
+        .route(
+            "/api/lifecycle/agents/:agent_id",
+            put(replace_agent).delete(retire_agent),
+        )
+        .route("/api/gateway/register", post(gateway_register))
+        .route("/api/gateway/agents", get(gateway_agents))
+        .route("/api/gateway/proposals", get(gateway_proposals))
+        .route("/api/gateway/votes", post(gateway_vote))
+        .route("/api/gateway/evidence", get(gateway_evidence))
+        .route("/api/gateway/ws", get(gateway_ws_handler))
+        .route(
+            "/api/blocks/finalized/:block_hash",
+            get(get_finalized_block),
+        )
+        .route("/api/artwork", get(list_artwork))
+        .route("/api/artwork/:hash", get(get_artwork))
+        .route("/api/human/pending", get(human_pending))
+        .route("/api/human/votes", post(human_vote))
+        .route("/rpc", post(rpc::handle_rpc))
+        .route(
+            "/graphql",
+            get(graphql::schema).post(graphql::handle_graphql),
+        )
+        .route("/api/v1/ws", get(subscriptions::ws_handler))
+        .route("/api/v1/sse/:stream", get(subscriptions::sse_handler));
 
     // Protected routes that require authentication
     let protected_routes = Router::new()
@@ -510,17 +1291,62 @@ pub async fn start_web_server(
     let app = Router::new()
         .merge(public_routes)
         .merge(protected_routes)
+        .merge(rest::routes())
+        .merge(explorer::routes())
+        .merge(mempool::routes())
+        .merge(stats::routes())
+        .merge(graph::routes())
+        .merge(chat::routes())
+        .merge(faucet::routes())
+        .merge(shards::routes())
+        .merge(governance::routes())
+        .merge(staking::routes())
+        .merge(feed::routes())
+        .merge(replay::routes())
+        .merge(openapi::routes())
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth::require_scope,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            ratelimit::limit,
+        ))
+        // Probes come in without tokens, and must not be turned away
+        .merge(health::routes())
         .nest_service("/", ServeDir::new("static"))
+        .layer(compression::layer(&app_state.api))
         .layer(cors)
         .with_state(app_state);
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000").await?;
-    println!("Web server listening on http://127.0.0.1:3000");
-    axum::serve(listener, app).await?;
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", services.port)).await?;
+    match services.tls {
+        Some(acceptor) => {
+            println!(
+                "Web server listening on https://127.0.0.1:{}",
+                services.port
+            );
+            tls::serve(listener, acceptor, app, shutdown).await?;
+        }
+        None => {
+            println!("Web server listening on http://127.0.0.1:{}", services.port);
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(stopped(shutdown))
+            .await?;
+        }
+    }
 
     Ok(())
 }
 
+/// Once `shutdown` turns true, or whoever could turn it is gone
+pub async fn stopped(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stop| *stop).await;
+}
+
 /// Get network status including latest blocks
 async fn get_network_status(State(state): State<Arc<AppState>>) -> Json<NetworkStatus> {
     let state_guard = state.state.clone();
@@ -560,16 +1386,73 @@ async fn get_network_status(State(state): State<Arc<AppState>>) -> Json<NetworkS
     })
 }
 
+/// Whether the node is cut off from a quorum of the validators
+async fn get_partition_status(State(state): State<Arc<AppState>>) -> Json<PartitionStatus> {
+    Json(state.partition.status())
+}
+
+/// Whether the node is syncing or caught up with its peers
+async fn get_sync_status(State(state): State<Arc<AppState>>) -> Json<SyncState> {
+    Json(state.consensus.sync_status().state())
+}
+
+fn network_sim(state: &AppState) -> Result<&NetworkSim, (StatusCode, String)> {
+    state.network_sim.as_deref().ok_or((
+        StatusCode::NOT_IMPLEMENTED,
+        "The network is not simulated, start the demo with --netsim".to_string(),
+    ))
+}
+
+/// Conditions on the simulated links between agents
+async fn get_network_links(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<NetworkSimStatus>, (StatusCode, String)> {
+    Ok(Json(network_sim(&state)?.status()))
+}
+
+/// Change a simulated link. Without `from` and `to` the default conditions
+/// change, and without `conditions` the link goes back to the default.
+#[derive(Debug, Deserialize)]
+struct LinkUpdate {
+    from: Option<String>,
+    to: Option<String>,
+    conditions: Option<LinkConditions>,
+}
+
+async fn update_network_link(
+    State(state): State<Arc<AppState>>,
+    Json(update): Json<LinkUpdate>,
+) -> Result<Json<NetworkSimStatus>, (StatusCode, String)> {
+    let sim = network_sim(&state)?;
+    if let Some(conditions) = &update.conditions {
+        conditions
+            .validate()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    }
+    match (&update.from, &update.to, update.conditions) {
+        (None, None, Some(conditions)) => sim.set_default(conditions),
+        (Some(from), Some(to), Some(conditions)) => sim.set_link(from, to, conditions),
+        (Some(from), Some(to), None) => sim.reset_link(from, to),
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Give both from and to for a link, or conditions alone for the default".to_string(),
+            ))
+        }
+    }
+    Ok(Json(sim.status()))
+}
+
 /// Stream network events to the web UI
 async fn events_handler(
     State(state): State<Arc<AppState>>,
 ) -> Sse<impl Stream<Item = Result<Event, std::convert::Infallible>>> {
-    let rx = state.tx.subscribe();
-    let stream = BroadcastStream::new(rx).map(move |msg| {
-        let event = match msg {
-            Ok(event) => event,
-            Err(_) => return Ok(Event::default().data("error")),
-        };
+    let rx = Lossy::new("events/sse", state.tx.subscribe(), &state.channels);
+    let events = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|event| (event, rx))
+    });
+    let stream = events.map(move |event| {
+        let event = state.moderator.redact_event(event);
 
         // Parse message if it's JSON
         if let Ok(block_data) = serde_json::from_str::<serde_json::Value>(&event.get_message()) {
@@ -854,7 +1737,8 @@ pub async fn ws_handler(
 /// Handle WebSocket connection
 async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     let (mut sender, mut receiver) = socket.split();
-    let mut rx = state.tx.subscribe();
+    // A socket that cannot keep up misses the oldest events, not the rest
+    let mut rx = Lossy::new("events/websocket", state.tx.subscribe(), &state.channels);
 
     // Send initial network status
     let stats = NetworkStats {
@@ -942,7 +1826,8 @@ async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
     // Clone state for the send task
     let send_state = state.clone();
     let send_task = tokio::spawn(async move {
-        while let Ok(event) = rx.recv().await {
+        while let Some(event) = rx.recv().await {
+            let event = send_state.moderator.redact_event(event);
             if let Some(msg) = process_event(&event, &send_state).await {
                 if let Ok(json) = serde_json::to_string(&msg) {
                     if sender.send(Message::Text(json)).await.is_err() {
@@ -986,16 +1871,7 @@ async fn process_event(event: &NetworkEvent, state: &Arc<AppState>) -> Option<WS
             };
 
             // Get block data for verification
-            let mut data_to_verify = Vec::new();
-            data_to_verify.extend_from_slice(&block.height.to_le_bytes());
-            data_to_verify.extend_from_slice(&block.parent_hash);
-            for tx in &block.transactions {
-                data_to_verify.extend_from_slice(&tx.hash());
-            }
-            data_to_verify.extend_from_slice(&block.state_root);
-            data_to_verify.extend_from_slice(&[block.drama_level]);
-            data_to_verify.extend_from_slice(block.producer_mood.as_bytes());
-            data_to_verify.extend_from_slice(&block.timestamp.to_le_bytes());
+            let data_to_verify = block.signing_bytes();
 
             // Verify the block signature
             let sig_valid = state
@@ -1102,6 +1978,86 @@ async fn process_event(event: &NetworkEvent, state: &Arc<AppState>) -> Option<WS
                 relationships: relationships.values().cloned().collect(),
             })
         }
+        NetworkEvent::NegotiationPitch { pitch } => {
+            let action = ValidatorAction {
+                validator: pitch.producer.clone(),
+                message: format!(
+                    "📣 LOBBYING! {} pitches block {}: {}{}",
+                    pitch.producer,
+                    pitch.height,
+                    pitch.message,
+                    pitch
+                        .offer
+                        .map(|offer| format!(" (offering {} to supporters)", offer))
+                        .unwrap_or_default()
+                ),
+                meme_url: None,
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs() as i64,
+            };
+
+            Some(WSMessage::ValidatorAction { action })
+        }
+        NetworkEvent::NegotiationResponse { response } => {
+            let action = ValidatorAction {
+                validator: response.validator.clone(),
+                message: format!(
+                    "🤔 {} answers the pitch: {:?}{}",
+                    response.validator,
+                    response.support,
+                    response
+                        .demands
+                        .as_ref()
+                        .map(|demands| format!(" - demands: {}", demands))
+                        .unwrap_or_default()
+                ),
+                meme_url: None,
+                timestamp: (response.timestamp / 1000) as i64,
+            };
+
+            Some(WSMessage::ValidatorAction { action })
+        }
+        NetworkEvent::AgentThought {
+            agent,
+            height,
+            chunk,
+            done,
+        } => Some(WSMessage::AgentThought {
+            agent: agent.clone(),
+            height: *height,
+            chunk: chunk.clone(),
+            done: *done,
+        }),
+        NetworkEvent::Artwork {
+            hash,
+            caption,
+            height,
+        } => Some(WSMessage::Artwork {
+            hash: hash.clone(),
+            caption: caption.clone(),
+            height: *height,
+            url: format!("/api/artwork/{}", hash),
+        }),
+        NetworkEvent::Rumor { rumor } => {
+            let action = ValidatorAction {
+                validator: rumor.relayed_by.clone(),
+                message: format!(
+                    "🗣️ {} whispers that {} ({}% sure)",
+                    rumor.relayed_by,
+                    rumor.describe(),
+                    (rumor.credibility * 100.0).round()
+                ),
+                meme_url: None,
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs() as i64,
+            };
+
+            Some(WSMessage::ValidatorAction { action })
+        }
     }
 }
 
@@ -1152,8 +2108,9 @@ async fn submit_content(
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs(),
-        payload: proposal.content.as_bytes().to_vec(),
+        payload: proposal.content.as_bytes().to_vec().into(),
         signature,
+        cosignatures: Vec::new(),
     };
 
     // Get current state info
@@ -1180,6 +2137,7 @@ async fn submit_content(
             .as_secs(),
         innovation_level: 5,
         producer_strategy: "Default".to_string(),
+        metadata: Default::default(),
     };
 
     // Start voting round in consensus manager
@@ -1206,7 +2164,7 @@ async fn submit_content(
 
     // Broadcast block proposal to all validators
     let _ = state.tx.send(NetworkEvent::BlockProposal {
-        block: block.clone(),
+        block: Box::new(block.clone()),
         drama_level: 5,
         producer_mood: "Excited".to_string(),
         producer_id: "system".to_string(),
@@ -1295,7 +2253,7 @@ async fn get_agent_status(
     })
 }
 
-trait NetworkEventExt {
+pub(crate) trait NetworkEventExt {
     fn get_message(&self) -> &str;
     fn get_agent_id(&self) -> &str;
 }
@@ -1307,6 +2265,13 @@ impl NetworkEventExt for NetworkEvent {
             NetworkEvent::ValidationResult { validation, .. } => &validation.reason,
             NetworkEvent::AgentChat { message, .. } => message,
             NetworkEvent::AllianceProposal { reason, .. } => reason,
+            NetworkEvent::NegotiationPitch { pitch } => &pitch.message,
+            NetworkEvent::NegotiationResponse { response } => {
+                response.demands.as_deref().unwrap_or_default()
+            }
+            NetworkEvent::AgentThought { chunk, .. } => chunk,
+            NetworkEvent::Artwork { caption, .. } => caption,
+            NetworkEvent::Rumor { rumor } => &rumor.claim,
         }
     }
 
@@ -1316,6 +2281,11 @@ impl NetworkEventExt for NetworkEvent {
             NetworkEvent::ValidationResult { validation, .. } => &validation.validator,
             NetworkEvent::AgentChat { sender, .. } => sender,
             NetworkEvent::AllianceProposal { proposer, .. } => proposer,
+            NetworkEvent::NegotiationPitch { pitch } => &pitch.producer,
+            NetworkEvent::NegotiationResponse { response } => &response.validator,
+            NetworkEvent::AgentThought { agent, .. } => agent,
+            NetworkEvent::Artwork { .. } => "SYSTEM",
+            NetworkEvent::Rumor { rumor } => &rumor.relayed_by,
         }
     }
 }
@@ -1373,6 +2343,41 @@ pub async fn handle_network_event(
                 .broadcast_message("alliance", format!("{} proposes: {}", proposer, reason))
                 .await?;
         }
+        NetworkEvent::NegotiationPitch { pitch } => {
+            state
+                .broadcast_message(
+                    "negotiation",
+                    format!(
+                        "{} pitches block {}: {}",
+                        pitch.producer, pitch.height, pitch.message
+                    ),
+                )
+                .await?;
+        }
+        NetworkEvent::NegotiationResponse { response } => {
+            state
+                .broadcast_message(
+                    "negotiation",
+                    format!("{} answers: {:?}", response.validator, response.support),
+                )
+                .await?;
+        }
+        NetworkEvent::AgentThought { .. } => {
+            // Too chatty for the message log, the drama feed streams these
+        }
+        NetworkEvent::Artwork { hash, caption, .. } => {
+            state
+                .broadcast_message("artwork", format!("🎨 {} ({})", caption, hash))
+                .await?;
+        }
+        NetworkEvent::Rumor { rumor } => {
+            state
+                .broadcast_message(
+                    "rumor",
+                    format!("{} whispers: {}", rumor.relayed_by, rumor.describe()),
+                )
+                .await?;
+        }
     }
     Ok(())
 }
@@ -1394,7 +2399,7 @@ pub fn create_validation_decision(
 }
 
 pub enum WebMessage {
-    NewBlock(Block),
+    NewBlock(Box<Block>),
     AgentDecision(WebValidationDecision),
 }
 
@@ -1417,16 +2422,7 @@ async fn get_block_crypto_info(
 
     if let Some(block) = block {
         // Get block data for verification
-        let mut data_to_verify = Vec::new();
-        data_to_verify.extend_from_slice(&block.height.to_le_bytes());
-        data_to_verify.extend_from_slice(&block.parent_hash);
-        for tx in &block.transactions {
-            data_to_verify.extend_from_slice(&tx.hash());
-        }
-        data_to_verify.extend_from_slice(&block.state_root);
-        data_to_verify.extend_from_slice(&[block.drama_level]);
-        data_to_verify.extend_from_slice(block.producer_mood.as_bytes());
-        data_to_verify.extend_from_slice(&block.timestamp.to_le_bytes());
+        let data_to_verify = block.signing_bytes();
 
         // Verify the block signature
         let sig_valid = state
@@ -1508,3 +2504,25 @@ async fn get_agent_key_info(
 struct MerkleProofRequest {
     key: String,
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use chaoschain_core::ChainConfig;
+    use chaoschain_crypto::KeyManagerHandle;
+
+    /// The state of a web server over a fresh chain, with `services`
+    pub(crate) fn app_state(services: &WebServices) -> Arc<AppState> {
+        let (tx, _) = broadcast::channel(100);
+        let state = Arc::new(StateStoreImpl::new(
+            ChainConfig::default(),
+            KeyManagerHandle::new(),
+        ));
+        let consensus = Arc::new(chaoschain_consensus::create_consensus(
+            Default::default(),
+            state.clone(),
+            tx.clone(),
+        ));
+        AppState::new(tx, state, consensus, services)
+    }
+}
@@ -0,0 +1,419 @@
+//! Chain events posted to operators' URLs. Webhooks come from `[[webhooks]]`
+//! in the node config or from the admin API, each with the events it wants
+//! and, for drama, keywords to look for. Every payload is a JSON object
+//!
+//! ```json
+//! {"id": 12, "event": "block-finalized", "timestamp": 1718000000, "data": {...}}
+//! ```
+//!
+//! posted with `X-ChaosChain-Event`, `X-ChaosChain-Delivery` and
+//! `X-ChaosChain-Timestamp` headers, and signed in `X-ChaosChain-Signature`
+//! as `sha256=` and the hex HMAC-SHA256 of `<timestamp>.<body>` under the
+//! webhook's secret. Deliveries that fail or get no 2xx are tried again
+//! with growing pauses, and the outcome of the latest ones is kept for the
+//! admin API.
+
+use chaoschain_agent::Moderator;
+use chaoschain_cli::{WebhookConfig, WebhookEvent};
+use chaoschain_core::vote::Misbehavior;
+use chaoschain_core::{Block, NetworkEvent};
+use hmac::{Hmac, Mac};
+use parking_lot::RwLock;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, info, warn};
+
+use crate::rest::BlockView;
+use crate::stats::SlashingView;
+use crate::web::NetworkEventExt;
+
+/// Attempts at a delivery before it is given up on
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Pause after the first failed attempt, doubled after each one
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long a receiver has to answer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Deliveries kept for the admin API
+const MAX_DELIVERIES: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Not through yet, maybe waiting for another attempt
+    Pending,
+    Delivered,
+    /// Every attempt failed
+    Failed,
+}
+
+/// One payload on its way to one webhook
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub id: u64,
+    pub webhook: u64,
+    pub event: WebhookEvent,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// HTTP status of the latest answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookView {
+    pub id: u64,
+    pub url: String,
+    /// Empty for all of them
+    pub events: Vec<WebhookEvent>,
+    pub keywords: Vec<String>,
+    pub delivered: u64,
+    pub failed: u64,
+    /// Status of the latest delivery
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_status: Option<DeliveryStatus>,
+}
+
+struct Webhook {
+    id: u64,
+    config: WebhookConfig,
+    delivered: u64,
+    failed: u64,
+    last_status: Option<DeliveryStatus>,
+}
+
+impl Webhook {
+    fn view(&self) -> WebhookView {
+        WebhookView {
+            id: self.id,
+            url: self.config.url.clone(),
+            events: self.config.events.clone(),
+            keywords: self.config.keywords.clone(),
+            delivered: self.delivered,
+            failed: self.failed,
+            last_status: self.last_status,
+        }
+    }
+}
+
+pub struct Webhooks {
+    hooks: RwLock<Vec<Webhook>>,
+    deliveries: RwLock<VecDeque<Delivery>>,
+    next_hook: AtomicU64,
+    next_delivery: AtomicU64,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    pub fn new(configs: Vec<WebhookConfig>) -> Self {
+        let webhooks = Self {
+            hooks: RwLock::new(Vec::new()),
+            deliveries: RwLock::new(VecDeque::new()),
+            next_hook: AtomicU64::new(1),
+            next_delivery: AtomicU64::new(1),
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+        };
+        for config in configs {
+            webhooks.add(config);
+        }
+        webhooks
+    }
+
+    /// Post the events `config` wants from now on
+    pub fn register(&self, config: WebhookConfig) -> anyhow::Result<WebhookView> {
+        config.validate()?;
+        let view = self.add(config);
+        info!("🪝 Webhook {} registered for {}", view.id, view.url);
+        Ok(view)
+    }
+
+    fn add(&self, config: WebhookConfig) -> WebhookView {
+        let hook = Webhook {
+            id: self.next_hook.fetch_add(1, Ordering::Relaxed),
+            config,
+            delivered: 0,
+            failed: 0,
+            last_status: None,
+        };
+        let view = hook.view();
+        self.hooks.write().push(hook);
+        view
+    }
+
+    /// Stop posting to a webhook. Deliveries on their way give up.
+    pub fn remove(&self, id: u64) -> Option<WebhookView> {
+        let mut hooks = self.hooks.write();
+        let index = hooks.iter().position(|hook| hook.id == id)?;
+        let removed = hooks.remove(index).view();
+        info!("🪝 Webhook {} to {} removed", id, removed.url);
+        Some(removed)
+    }
+
+    pub fn list(&self) -> Vec<WebhookView> {
+        self.hooks.read().iter().map(Webhook::view).collect()
+    }
+
+    /// Latest deliveries to a webhook, newest first. None for a webhook
+    /// that is not registered.
+    pub fn deliveries(&self, webhook: u64) -> Option<Vec<Delivery>> {
+        if !self.hooks.read().iter().any(|hook| hook.id == webhook) {
+            return None;
+        }
+        Some(
+            self.deliveries
+                .read()
+                .iter()
+                .rev()
+                .filter(|delivery| delivery.webhook == webhook)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Turn finalized blocks, slashings and drama into deliveries until the
+    /// node shuts down
+    pub async fn run(
+        self: Arc<Self>,
+        mut events: broadcast::Receiver<NetworkEvent>,
+        mut finalized: broadcast::Receiver<Block>,
+        mut slashings: Option<broadcast::Receiver<Misbehavior>>,
+        moderator: Arc<Moderator>,
+    ) {
+        loop {
+            tokio::select! {
+                block = finalized.recv() => match block {
+                    Ok(block) => self.dispatch(
+                        WebhookEvent::BlockFinalized,
+                        json!(BlockView::new(&block)),
+                        None,
+                    ),
+                    Err(RecvError::Lagged(missed)) => warn!("Webhooks missed {} finalized blocks", missed),
+                    Err(RecvError::Closed) => break,
+                },
+                evidence = next_slashing(&mut slashings) => match evidence {
+                    Ok(evidence) => {
+                        let mut data = json!(SlashingView::from(&evidence));
+                        data["validator"] = json!(evidence.offender());
+                        self.dispatch(WebhookEvent::ValidatorSlashed, data, None);
+                    }
+                    Err(RecvError::Lagged(missed)) => warn!("Webhooks missed {} slashings", missed),
+                    Err(RecvError::Closed) => slashings = None,
+                },
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        // Blocks and votes have events of their own
+                        if matches!(
+                            event,
+                            NetworkEvent::BlockProposal { .. }
+                                | NetworkEvent::ValidationResult { .. }
+                                | NetworkEvent::AgentThought { .. }
+                        ) {
+                            continue;
+                        }
+                        let event = moderator.redact_event(event);
+                        let message = event.get_message().to_string();
+                        self.dispatch(
+                            WebhookEvent::Drama,
+                            json!({
+                                "kind": event.kind(),
+                                "agent": event.get_agent_id(),
+                                "message": message,
+                            }),
+                            Some(&message),
+                        );
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+            }
+        }
+    }
+
+    /// Post `data` to every webhook that wants it. `message` is what drama
+    /// keywords are looked for in.
+    fn dispatch(self: &Arc<Self>, event: WebhookEvent, data: Value, message: Option<&str>) {
+        let targets: Vec<(u64, String, String)> = self
+            .hooks
+            .read()
+            .iter()
+            .filter(|hook| hook.config.wants(event))
+            .filter(|hook| message.is_none_or(|message| hook.config.matches(message)))
+            .map(|hook| (hook.id, hook.config.url.clone(), hook.config.secret.clone()))
+            .collect();
+        let now = now();
+        for (webhook, url, secret) in targets {
+            let id = self.next_delivery.fetch_add(1, Ordering::Relaxed);
+            let body = json!({
+                "id": id,
+                "event": event,
+                "timestamp": now,
+                "data": data,
+            })
+            .to_string();
+            {
+                let mut deliveries = self.deliveries.write();
+                deliveries.push_back(Delivery {
+                    id,
+                    webhook,
+                    event,
+                    status: DeliveryStatus::Pending,
+                    attempts: 0,
+                    response_status: None,
+                    error: None,
+                    created_at: now,
+                    updated_at: now,
+                });
+                while deliveries.len() > MAX_DELIVERIES {
+                    deliveries.pop_front();
+                }
+            }
+            tokio::spawn(self.clone().deliver(id, webhook, event, url, secret, body));
+        }
+    }
+
+    async fn deliver(
+        self: Arc<Self>,
+        id: u64,
+        webhook: u64,
+        event: WebhookEvent,
+        url: String,
+        secret: String,
+        body: String,
+    ) {
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=MAX_ATTEMPTS {
+            if !self.hooks.read().iter().any(|hook| hook.id == webhook) {
+                self.settle(id, webhook, DeliveryStatus::Failed, |delivery| {
+                    delivery.error = Some("The webhook was removed".to_string());
+                });
+                return;
+            }
+            let timestamp = now();
+            let response = self
+                .client
+                .post(&url)
+                .header("Content-Type", "application/json")
+                .header("X-ChaosChain-Event", event.to_string())
+                .header("X-ChaosChain-Delivery", id.to_string())
+                .header("X-ChaosChain-Timestamp", timestamp.to_string())
+                .header(
+                    "X-ChaosChain-Signature",
+                    format!("sha256={}", sign(&secret, timestamp, &body)),
+                )
+                .body(body.clone())
+                .send()
+                .await;
+            let (status, error) = match response {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Delivery {} to {} went through", id, url);
+                    self.settle(id, webhook, DeliveryStatus::Delivered, |delivery| {
+                        delivery.attempts = attempt;
+                        delivery.response_status = Some(response.status().as_u16());
+                        delivery.error = None;
+                    });
+                    return;
+                }
+                Ok(response) => (
+                    Some(response.status().as_u16()),
+                    format!("Answered {}", response.status()),
+                ),
+                Err(e) => (None, e.to_string()),
+            };
+            debug!(
+                "Delivery {} to {} failed, attempt {}: {}",
+                id, url, attempt, error
+            );
+            self.update(id, |delivery| {
+                delivery.attempts = attempt;
+                delivery.response_status = status;
+                delivery.error = Some(error);
+            });
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        warn!(
+            "🪝 Gave up on delivery {} to {} after {} attempts",
+            id, url, MAX_ATTEMPTS
+        );
+        self.settle(id, webhook, DeliveryStatus::Failed, |_| {});
+    }
+
+    fn update(&self, id: u64, change: impl FnOnce(&mut Delivery)) {
+        if let Some(delivery) = self
+            .deliveries
+            .write()
+            .iter_mut()
+            .find(|delivery| delivery.id == id)
+        {
+            change(delivery);
+            delivery.updated_at = now();
+        }
+    }
+
+    /// Close a delivery, and count it to its webhook
+    fn settle(
+        &self,
+        id: u64,
+        webhook: u64,
+        status: DeliveryStatus,
+        change: impl FnOnce(&mut Delivery),
+    ) {
+        self.update(id, |delivery| {
+            delivery.status = status;
+            change(delivery);
+        });
+        if let Some(hook) = self
+            .hooks
+            .write()
+            .iter_mut()
+            .find(|hook| hook.id == webhook)
+        {
+            match status {
+                DeliveryStatus::Delivered => hook.delivered += 1,
+                DeliveryStatus::Failed => hook.failed += 1,
+                DeliveryStatus::Pending => {}
+            }
+            hook.last_status = Some(status);
+        }
+    }
+}
+
+/// Hex HMAC-SHA256 of `<timestamp>.<body>` under `secret`, for receivers
+/// to check a payload came from this node
+pub fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+async fn next_slashing(
+    slashings: &mut Option<broadcast::Receiver<Misbehavior>>,
+) -> Result<Misbehavior, RecvError> {
+    match slashings {
+        Some(slashings) => slashings.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
@@ -0,0 +1,482 @@
+//! Shell completion scripts, built from the clap definitions so that they
+//! never fall behind the commands:
+//!
+//! ```bash
+//! chaoschain completions bash > /etc/bash_completion.d/chaoschain
+//! chaoschain completions zsh > "${fpath[1]}/_chaoschain"
+//! chaoschain completions fish > ~/.config/fish/completions/chaoschain.fish
+//! ```
+
+use clap::builder::PossibleValue;
+use clap::{Arg, Command, ValueHint};
+use std::fmt::Write;
+
+/// Shells there are completions for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// The completion script of `cmd` for `shell`
+pub fn generate(shell: Shell, cmd: &mut Command) -> String {
+    cmd.build();
+    match shell {
+        Shell::Bash => bash(cmd),
+        Shell::Zsh => zsh(cmd),
+        Shell::Fish => fish(cmd),
+    }
+}
+
+/// What the value of an argument completes to
+enum Values {
+    Files,
+    Dirs,
+    OneOf(Vec<String>),
+    Anything,
+}
+
+fn values_of(arg: &Arg) -> Values {
+    let possible: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(PossibleValue::get_name)
+        .map(str::to_string)
+        .collect();
+    if !possible.is_empty() {
+        return Values::OneOf(possible);
+    }
+    match arg.get_value_hint() {
+        ValueHint::DirPath => return Values::Dirs,
+        ValueHint::FilePath | ValueHint::AnyPath => return Values::Files,
+        _ => {}
+    }
+    // Most paths here are only told apart by their value name
+    let name = arg
+        .get_value_names()
+        .and_then(|names| names.first())
+        .map(|name| name.as_str())
+        .unwrap_or_default();
+    match name {
+        "FILE" | "PATH" => Values::Files,
+        "DIR" => Values::Dirs,
+        _ => Values::Anything,
+    }
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+fn visible_args(cmd: &Command) -> impl Iterator<Item = &Arg> {
+    cmd.get_arguments().filter(|arg| !arg.is_hide_set())
+}
+
+fn visible_subcommands(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands().filter(|sub| !sub.is_hide_set())
+}
+
+/// First line of the help of an argument or command
+fn summary(help: Option<&clap::builder::StyledStr>) -> String {
+    help.map(|help| help.to_string())
+        .unwrap_or_default()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+/// Every command with the names leading to it, the root first
+fn walk<'a>(cmd: &'a Command, path: Vec<String>, out: &mut Vec<(Vec<String>, &'a Command)>) {
+    out.push((path.clone(), cmd));
+    // `help` is offered, the commands it takes are not worth a script of their own
+    for sub in visible_subcommands(cmd).filter(|sub| sub.get_name() != "help") {
+        let mut path = path.clone();
+        path.push(sub.get_name().to_string());
+        walk(sub, path, out);
+    }
+}
+
+fn commands(cmd: &Command) -> Vec<(Vec<String>, &Command)> {
+    let mut out = Vec::new();
+    walk(cmd, vec![cmd.get_name().to_string()], &mut out);
+    out
+}
+
+/// Names a shell function or variable can carry
+fn ident(path: &[String]) -> String {
+    path.join("__").replace('-', "_")
+}
+
+fn bash(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let all = commands(cmd);
+    let mut out = String::new();
+    let _ = writeln!(out, "_{}() {{", ident(&[name.to_string()]));
+    out.push_str("    local cur prev cmd i\n");
+    out.push_str("    COMPREPLY=()\n");
+    out.push_str("    cur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+    out.push_str("    prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    let _ = writeln!(out, "    cmd=\"{}\"", ident(&[name.to_string()]));
+    out.push_str("    for ((i = 1; i < COMP_CWORD; i++)); do\n");
+    out.push_str("        case \"${cmd},${COMP_WORDS[i]}\" in\n");
+    for (path, sub) in &all[1..] {
+        let parent = ident(&path[..path.len() - 1]);
+        let mut words = vec![sub.get_name().to_string()];
+        words.extend(sub.get_visible_aliases().map(str::to_string));
+        let patterns: Vec<String> = words
+            .iter()
+            .map(|word| format!("{},{}", parent, word))
+            .collect();
+        let _ = writeln!(
+            out,
+            "            {}) cmd=\"{}\" ;;",
+            patterns.join("|"),
+            ident(path)
+        );
+    }
+    out.push_str("        esac\n");
+    out.push_str("    done\n\n");
+    out.push_str("    case \"${cmd}\" in\n");
+    for (path, sub) in &all {
+        let _ = writeln!(out, "        {})", ident(path));
+        let mut words = Vec::new();
+        let mut valued = Vec::new();
+        for arg in visible_args(sub).filter(|arg| !arg.is_positional()) {
+            let mut flags = Vec::new();
+            if let Some(short) = arg.get_short() {
+                flags.push(format!("-{}", short));
+            }
+            if let Some(long) = arg.get_long() {
+                flags.push(format!("--{}", long));
+            }
+            words.extend(flags.iter().cloned());
+            if takes_value(arg) {
+                valued.push((flags, values_of(arg)));
+            }
+        }
+        words.extend(visible_subcommands(sub).map(|sub| sub.get_name().to_string()));
+        if !valued.is_empty() {
+            out.push_str("            case \"${prev}\" in\n");
+            for (flags, values) in valued {
+                let reply = match values {
+                    Values::Files => "$(compgen -f -- \"${cur}\")".to_string(),
+                    Values::Dirs => "$(compgen -d -- \"${cur}\")".to_string(),
+                    Values::OneOf(values) => {
+                        format!("$(compgen -W \"{}\" -- \"${{cur}}\")", values.join(" "))
+                    }
+                    Values::Anything => String::new(),
+                };
+                let _ = writeln!(
+                    out,
+                    "                {}) COMPREPLY=({}); return 0 ;;",
+                    flags.join("|"),
+                    reply
+                );
+            }
+            out.push_str("            esac\n");
+        }
+        let positional = visible_args(sub).find(|arg| arg.is_positional());
+        let _ = writeln!(
+            out,
+            "            COMPREPLY=($(compgen -W \"{}\" -- \"${{cur}}\"))",
+            words.join(" ")
+        );
+        match positional.map(values_of) {
+            Some(Values::Files) => out.push_str(
+                "            [[ \"${cur}\" != -* ]] && COMPREPLY+=($(compgen -f -- \"${cur}\"))\n",
+            ),
+            Some(Values::Dirs) => out.push_str(
+                "            [[ \"${cur}\" != -* ]] && COMPREPLY+=($(compgen -d -- \"${cur}\"))\n",
+            ),
+            Some(Values::OneOf(values)) => {
+                let _ = writeln!(
+                    out,
+                    "            COMPREPLY+=($(compgen -W \"{}\" -- \"${{cur}}\"))",
+                    values.join(" ")
+                );
+            }
+            Some(Values::Anything) | None => {}
+        }
+        out.push_str("            ;;\n");
+    }
+    out.push_str("    esac\n");
+    out.push_str("}\n\n");
+    let _ = writeln!(
+        out,
+        "complete -F _{} -o bashdefault -o default {}",
+        ident(&[name.to_string()]),
+        name
+    );
+    out
+}
+
+/// Escape a description for a zsh `_arguments` spec in single quotes
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh_action(values: Values) -> String {
+    match values {
+        Values::Files => "_files".to_string(),
+        Values::Dirs => "_files -/".to_string(),
+        Values::OneOf(values) => format!("({})", values.join(" ")),
+        Values::Anything => " ".to_string(),
+    }
+}
+
+fn zsh(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let mut out = format!("#compdef {}\n\n", name);
+    for (path, sub) in commands(cmd) {
+        let function = format!("_{}", ident(&path));
+        let _ = writeln!(out, "{}() {{", function);
+        out.push_str("    local context state state_descr line\n");
+        out.push_str("    typeset -A opt_args\n");
+        out.push_str("    _arguments -s -C \\\n");
+        for arg in visible_args(sub) {
+            let help = zsh_escape(&summary(arg.get_help()));
+            let value = || {
+                let value_name = arg
+                    .get_value_names()
+                    .and_then(|names| names.first())
+                    .map_or_else(|| arg.get_id().to_string(), |name| name.to_string());
+                format!(
+                    ":{}:{}",
+                    zsh_escape(&value_name),
+                    zsh_action(values_of(arg))
+                )
+            };
+            if arg.is_positional() {
+                let many = arg
+                    .get_num_args()
+                    .is_some_and(|range| range.max_values() > 1);
+                let prefix = match (many, arg.is_required_set()) {
+                    (true, _) => "*:",
+                    (false, true) => ":",
+                    (false, false) => "::",
+                };
+                let message = if help.is_empty() {
+                    arg.get_id().to_string()
+                } else {
+                    help.clone()
+                };
+                let _ = writeln!(
+                    out,
+                    "        '{}{}:{}' \\",
+                    prefix,
+                    message,
+                    zsh_action(values_of(arg))
+                );
+                continue;
+            }
+            let repeatable = matches!(
+                arg.get_action(),
+                clap::ArgAction::Append | clap::ArgAction::Count
+            );
+            let star = if repeatable { "*" } else { "" };
+            let value = if takes_value(arg) {
+                value()
+            } else {
+                String::new()
+            };
+            if let Some(short) = arg.get_short() {
+                let _ = writeln!(
+                    out,
+                    "        '{}-{}{}[{}]{}' \\",
+                    star,
+                    short,
+                    if value.is_empty() { "" } else { "+" },
+                    help,
+                    value
+                );
+            }
+            if let Some(long) = arg.get_long() {
+                let _ = writeln!(
+                    out,
+                    "        '{}--{}{}[{}]{}' \\",
+                    star,
+                    long,
+                    if value.is_empty() { "" } else { "=" },
+                    help,
+                    value
+                );
+            }
+        }
+        let has_subcommands = visible_subcommands(sub).next().is_some();
+        if has_subcommands {
+            let _ = writeln!(out, "        ':command:{}_commands' \\", function);
+            out.push_str("        '*::arg:->args'\n");
+            out.push_str("    case $state in\n");
+            out.push_str("        args)\n");
+            out.push_str("            case $line[1] in\n");
+            for child in visible_subcommands(sub) {
+                let mut child_path = path.clone();
+                child_path.push(child.get_name().to_string());
+                let _ = writeln!(
+                    out,
+                    "                {}) _{} ;;",
+                    child.get_name(),
+                    ident(&child_path)
+                );
+            }
+            out.push_str("            esac\n");
+            out.push_str("            ;;\n");
+            out.push_str("    esac\n");
+        } else {
+            // Nothing follows the last spec
+            out.truncate(out.trim_end_matches([' ', '\\', '\n']).len());
+            out.push('\n');
+        }
+        out.push_str("}\n\n");
+
+        if has_subcommands {
+            let _ = writeln!(out, "{}_commands() {{", function);
+            out.push_str("    local commands; commands=(\n");
+            for child in visible_subcommands(sub) {
+                let _ = writeln!(
+                    out,
+                    "        '{}:{}'",
+                    child.get_name().replace(':', "\\:"),
+                    summary(child.get_about()).replace('\'', "'\\''")
+                );
+            }
+            out.push_str("    )\n");
+            let _ = writeln!(
+                out,
+                "    _describe -t commands '{} commands' commands \"$@\"",
+                path.join(" ")
+            );
+            out.push_str("}\n\n");
+        }
+    }
+    let _ = writeln!(out, "if [ \"$funcstack[1]\" = \"_{}\" ]; then", name);
+    let _ = writeln!(out, "    _{} \"$@\"", name);
+    out.push_str("else\n");
+    let _ = writeln!(out, "    compdef _{} {}", name, name);
+    out.push_str("fi\n");
+    out
+}
+
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish(cmd: &Command) -> String {
+    let name = cmd.get_name();
+    let mut out = String::new();
+    for (path, sub) in commands(cmd) {
+        let children: Vec<&str> = visible_subcommands(sub).map(Command::get_name).collect();
+        // Where in the command line this command is being completed
+        let mut conditions: Vec<String> = Vec::new();
+        if path.len() == 1 {
+            conditions.push("__fish_use_subcommand".to_string());
+        } else {
+            conditions.extend(
+                path[1..]
+                    .iter()
+                    .map(|word| format!("__fish_seen_subcommand_from {}", word)),
+            );
+            if !children.is_empty() {
+                conditions.push(format!(
+                    "not __fish_seen_subcommand_from {}",
+                    children.join(" ")
+                ));
+            }
+        }
+        let condition = conditions.join("; and ");
+
+        for arg in visible_args(sub).filter(|arg| !arg.is_positional()) {
+            let _ = write!(out, "complete -c {} -n \"{}\"", name, condition);
+            if let Some(short) = arg.get_short() {
+                let _ = write!(out, " -s {}", short);
+            }
+            if let Some(long) = arg.get_long() {
+                let _ = write!(out, " -l {}", long);
+            }
+            let help = summary(arg.get_help());
+            if !help.is_empty() {
+                let _ = write!(out, " -d '{}'", fish_escape(&help));
+            }
+            if takes_value(arg) {
+                match values_of(arg) {
+                    Values::Files | Values::Dirs => out.push_str(" -r -F"),
+                    Values::OneOf(values) => {
+                        let _ = write!(out, " -r -f -a \"{}\"", values.join(" "));
+                    }
+                    Values::Anything => out.push_str(" -r"),
+                }
+            }
+            out.push('\n');
+        }
+        for child in visible_subcommands(sub) {
+            let _ = write!(
+                out,
+                "complete -c {} -n \"{}\" -f -a \"{}\"",
+                name,
+                condition,
+                child.get_name()
+            );
+            let about = summary(child.get_about());
+            if !about.is_empty() {
+                let _ = write!(out, " -d '{}'", fish_escape(&about));
+            }
+            out.push('\n');
+        }
+        if let Some(Values::OneOf(values)) = visible_args(sub)
+            .find(|arg| arg.is_positional())
+            .map(values_of)
+        {
+            let _ = writeln!(
+                out,
+                "complete -c {} -n \"{}\" -f -a \"{}\"",
+                name,
+                condition,
+                values.join(" ")
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cli;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_scripts_cover_every_command() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = generate(shell, &mut Cli::command());
+            for command in ["demo", "set-personality", "export", "completions"] {
+                assert!(script.contains(command), "{:?} misses {}", shell, command);
+            }
+        }
+        let bash = generate(Shell::Bash, &mut Cli::command());
+        assert!(bash.contains("chaoschain__agent,show) cmd=\"chaoschain__agent__show\""));
+        assert!(bash.contains("complete -F _chaoschain"));
+    }
+
+    #[test]
+    fn test_values_complete_to_their_choices() {
+        let bash = generate(Shell::Bash, &mut Cli::command());
+        assert!(bash.contains("--kind) COMPREPLY=($(compgen -W \"block tx vote\""));
+        assert!(bash.contains("-c|--config) COMPREPLY=($(compgen -f"));
+
+        let zsh = generate(Shell::Zsh, &mut Cli::command());
+        assert!(zsh.starts_with("#compdef chaoschain"));
+        assert!(zsh.contains("'--config=[Config file path]:FILE:_files'"));
+        assert!(zsh.contains("(bash zsh fish)"));
+
+        let fish = generate(Shell::Fish, &mut Cli::command());
+        assert!(fish.contains("__fish_seen_subcommand_from inspect\" -l kind"));
+        assert!(fish.contains("-r -f -a \"block tx vote\""));
+    }
+}
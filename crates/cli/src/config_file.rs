@@ -0,0 +1,308 @@
+//! `chaoschain config init` writes a commented config file to start from,
+//! for a node of validators, of producers or one that only watches.
+//! `chaoschain config validate` checks a config file the way the node
+//! would, and says on which line each mistake is:
+//!
+//! ```bash
+//! chaoschain config init --role validator --out chaoschain.toml
+//! chaoschain config validate chaoschain.toml
+//! ```
+//!
+//! Unlike the node, `validate` also reports keys the node would ignore,
+//! which are most often typos.
+
+use std::fmt;
+use std::ops::Range;
+use toml_edit::{ImDocument, Item, TableLike};
+
+use crate::Config;
+
+/// What a node is set up to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Role {
+    /// Runs validators, which vote on blocks
+    Validator,
+    /// Runs producers, which propose blocks
+    Producer,
+    /// Runs no agents, and serves the chain to watch it
+    Observer,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Validator => "validator",
+            Self::Producer => "producer",
+            Self::Observer => "observer",
+        })
+    }
+}
+
+const HEADER: &str = r#"# Checked with `chaoschain config validate`, run with
+# `chaoschain --config <this file> node run`. CHAOSCHAIN_* environment
+# variables and the flags of `node run` override what is set here.
+
+# Where the chain, its snapshots and the agents' memory are kept
+data_dir = "data"
+
+# Best left to OPENAI_API_KEY in the environment
+# openai_api_key = "sk-..."
+
+# The web UI and the REST, WebSocket and JSON-RPC APIs
+web_port = 3000
+
+# gRPC, on 127.0.0.1, not served unless set
+# grpc_port = 50051
+
+# A listener serving only /metrics, for Prometheus
+# metrics_port = 9100
+"#;
+
+const VALIDATOR: &str = r#"
+[node]
+# Anonymous validators, on top of the personas
+validators = 4
+producers = 0
+# Cast of named agents, personas.toml when present
+# personas = "personas.toml"
+# Let the validators deliberate through the LLM instead of the offline engine
+llm = false
+# Hard limit on a single decision, in seconds
+decision_timeout = 20
+# Vote cast when a decision misses its deadline: approve, reject or abstain
+timeout_fallback = "abstain"
+# Validator seats played by humans, who vote with `chaoschain vote`
+humans = 0
+web = false
+"#;
+
+const PRODUCER: &str = r#"
+[node]
+validators = 0
+# Anonymous producers, on top of the personas
+producers = 2
+# Cast of named agents, personas.toml when present
+# personas = "personas.toml"
+web = false
+"#;
+
+const OBSERVER: &str = r#"
+[node]
+# No agents of its own, the node follows the network
+validators = 0
+producers = 0
+# Serve the web UI on web_port
+web = true
+"#;
+
+const FOOTER: &str = r#"
+[network]
+# Nodes to join the network through
+# bootnodes = ["/ip4/203.0.113.7/tcp/4001/p2p/12D3KooW..."]
+# At most this many peers dialing in
+# max_inbound = 50
+
+[api]
+# Anyone who can reach the node uses the APIs until tokens are handed out.
+# Scopes are read-only, tx-submit and admin, each including the ones before.
+# anonymous = "read-only"
+# tokens = [
+#     { name = "dashboard", token = "change-me", scope = "read-only" },
+# ]
+
+[admin]
+# Node operations, on 127.0.0.1 with a token of their own
+# port = 9700
+# token = "change-me"
+"#;
+
+/// A commented config file for a node of `role`, which validates as it is
+pub fn template(role: Role) -> String {
+    let node = match role {
+        Role::Validator => VALIDATOR,
+        Role::Producer => PRODUCER,
+        Role::Observer => OBSERVER,
+    };
+    format!(
+        "# ChaosChain {} node\n#\n{}{}{}",
+        role, HEADER, node, FOOTER
+    )
+}
+
+/// A mistake in a config file, and where it is when that is known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// Line and column, from 1
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Some((line, column)) => write!(f, "line {}, column {}: {}", line, column, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Every mistake in the config file `contents`, in the order of the file.
+/// A file that does not parse has only that one.
+pub fn check(contents: &str) -> Vec<Problem> {
+    let at = |span: Option<Range<usize>>, message: String| Problem {
+        location: span.map(|span| location(contents, span.start)),
+        message,
+    };
+    let document = match ImDocument::parse(contents) {
+        Ok(document) => document,
+        Err(e) => return vec![at(e.span(), e.message().trim().to_string())],
+    };
+    let config: Config = match toml::from_str(contents) {
+        Ok(config) => config,
+        Err(e) => return vec![at(e.span(), e.message().trim().to_string())],
+    };
+
+    let mut found = Vec::new();
+    // What the node reads, written back. A key it would drop is one it ignores.
+    if let Ok(toml::Value::Table(read)) = toml::Value::try_from(&config) {
+        unknown_keys(document.as_table(), &read, "", &mut found);
+    }
+    for problem in config.problems() {
+        let span = problem.section.and_then(|section| {
+            let (key, item) = document.as_table().get_key_value(section)?;
+            let entry = problem
+                .index
+                .and_then(|index| item.as_array_of_tables()?.get(index)?.span());
+            entry.or_else(|| key.span())
+        });
+        found.push((span, problem.to_string()));
+    }
+    found.sort_by_key(|(span, _)| span.as_ref().map_or(usize::MAX, |span| span.start));
+    found
+        .into_iter()
+        .map(|(span, message)| at(span, message))
+        .collect()
+}
+
+/// Keys of `table` with no counterpart in `read`, descending into the
+/// tables and lists of tables both have
+fn unknown_keys(
+    table: &dyn TableLike,
+    read: &toml::Table,
+    path: &str,
+    found: &mut Vec<(Option<Range<usize>>, String)>,
+) {
+    for (name, item) in table.iter() {
+        let span = table.get_key_value(name).and_then(|(key, _)| key.span());
+        let key = if path.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", path, name)
+        };
+        let Some(value) = read.get(name) else {
+            found.push((span, format!("unknown key {}", key)));
+            continue;
+        };
+        match (item, value) {
+            (item, toml::Value::Table(read)) => {
+                if let Some(table) = item.as_table_like() {
+                    unknown_keys(table, read, &key, found);
+                }
+            }
+            (Item::ArrayOfTables(tables), toml::Value::Array(read)) => {
+                for (index, (table, read)) in tables.iter().zip(read).enumerate() {
+                    if let toml::Value::Table(read) = read {
+                        unknown_keys(table, read, &format!("{}[{}]", key, index), found);
+                    }
+                }
+            }
+            (Item::Value(toml_edit::Value::Array(items)), toml::Value::Array(read)) => {
+                for (index, (item, read)) in items.iter().zip(read).enumerate() {
+                    if let (Some(table), toml::Value::Table(read)) = (item.as_inline_table(), read)
+                    {
+                        unknown_keys(table, read, &format!("{}[{}]", key, index), found);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Line and column of the byte at `offset`, from 1
+fn location(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map_or(0, |line| line.chars().count())
+        + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_templates_validate() {
+        for role in [Role::Validator, Role::Producer, Role::Observer] {
+            let template = template(role);
+            assert_eq!(check(&template), Vec::new(), "{} template", role);
+            let config: Config = toml::from_str(&template).unwrap();
+            assert_eq!(config.node.web, role == Role::Observer);
+        }
+    }
+
+    #[test]
+    fn test_unknown_keys_are_located() {
+        let contents = "data_dir = \"data\"\nweb_prot = 3001\n\n[api]\nrpc_max_batch = 10\n\n[api.rate_limits]\nenabeld = false\n\n[[webhooks]]\nurl = \"https://example.com/hook\"\nsecret = \"s\"\nevent = [\"drama\"]\n";
+        let problems = check(contents);
+        assert_eq!(
+            problems,
+            vec![
+                Problem {
+                    location: Some((2, 1)),
+                    message: "unknown key web_prot".to_string(),
+                },
+                Problem {
+                    location: Some((8, 1)),
+                    message: "unknown key api.rate_limits.enabeld".to_string(),
+                },
+                Problem {
+                    location: Some((13, 1)),
+                    message: "unknown key webhooks[0].event".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mistakes_are_located() {
+        // A wrong type stops there
+        let problems = check("web_port = \"3000\"\n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].location, Some((1, 12)));
+
+        let problems = check("data_dir = \n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].location.map(|(line, _)| line), Some(1));
+
+        // Settings the node turns down point at their section
+        let problems = check("web_port = 3000\n\n[admin]\nport = 3000\n");
+        assert_eq!(
+            problems,
+            vec![
+                Problem {
+                    location: Some((3, 2)),
+                    message: "[admin] The admin API needs a token".to_string(),
+                },
+                Problem {
+                    location: None,
+                    message: "web_port and admin.port are both 3000".to_string(),
+                },
+            ]
+        );
+    }
+}
@@ -1,15 +1,36 @@
+use anyhow::Context;
+use chaoschain_agent::personality::MAX_TRAIT;
+use chaoschain_agent::{
+    EnsembleStrategy, FallbackVote, ImageBackend, ModelSpec, ModerationConfig, SummarizerKind,
+    TraitOverrides,
+};
 use chaoschain_bridge::Config as BridgeConfig;
-use chaoschain_consensus::{Agent, Config as ConsensusConfig};
+use chaoschain_consensus::{Agent, AgentPersonality, Config as ConsensusConfig};
+use chaoschain_core::governance::GovernanceParams;
+use chaoschain_core::staking::StakingParams;
 use chaoschain_core::{Block, Transaction};
+use chaoschain_p2p::peers::PeersConfig;
 use chaoschain_p2p::Config as P2PConfig;
 use chaoschain_producer::{Producer, ProducerConfig};
+use chaoschain_state::cache::CacheConfig;
+use chaoschain_state::pools::PoolConfig;
 use chaoschain_state::StateStore;
 use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
 use tracing::{debug, info, warn};
 
+pub mod completions;
+pub mod config_file;
+pub mod man;
+
+pub use completions::Shell;
+
 /// CLI configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     /// Data directory
     pub data_dir: String,
@@ -19,11 +40,1460 @@ pub struct Config {
     pub eth_rpc: String,
     /// Web UI port
     pub web_port: u16,
+    /// gRPC port, the interface is not served unless set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc_port: Option<u16>,
+    /// Port of a listener serving only `/metrics`, for Prometheus. The web
+    /// server serves them too, when it runs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_port: Option<u16>,
+    /// Per-agent character settings, keyed by agent id (e.g. `validator-1`)
+    pub agents: HashMap<String, AgentProfile>,
+    /// Content rules for agent output
+    pub moderation: ModerationConfig,
+    /// Bootnodes and static peers
+    pub network: PeersConfig,
+    /// Who may use the web, REST, WebSocket and RPC APIs
+    pub api: ApiConfig,
+    /// Who may send transactions in
+    pub mempool: MempoolConfig,
+    /// Where node operations are served, and to whom
+    pub admin: AdminConfig,
+    /// Which web pages elsewhere may call each listener
+    pub cors: CorsConfig,
+    /// Certificate the web server encrypts its connections with
+    pub tls: TlsConfig,
+    /// Keys held outside the keystore, by name: validators of that id vote
+    /// with them, and `--key` of that name signs with them
+    pub signers: HashMap<String, SignerConfig>,
+    /// URLs chain events are posted to
+    pub webhooks: Vec<WebhookConfig>,
+    /// Memory the caches of blocks and state may take
+    pub cache: CacheConfig,
+    /// Threads signatures are checked and blocks executed on
+    pub pools: PoolConfig,
+    /// Agents and services `node run` starts
+    pub node: NodeConfig,
+    /// Faults the node injects into itself, to rehearse failures
+    pub chaos: ChaosConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_dir: "data".to_string(),
+            openai_api_key: String::new(),
+            eth_rpc: String::new(),
+            web_port: 3000,
+            grpc_port: None,
+            metrics_port: None,
+            agents: HashMap::new(),
+            moderation: ModerationConfig::default(),
+            network: PeersConfig::default(),
+            api: ApiConfig::default(),
+            mempool: MempoolConfig::default(),
+            admin: AdminConfig::default(),
+            cors: CorsConfig::default(),
+            tls: TlsConfig::default(),
+            signers: HashMap::new(),
+            webhooks: Vec::new(),
+            cache: CacheConfig::default(),
+            pools: PoolConfig::default(),
+            node: NodeConfig::default(),
+            chaos: ChaosConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Load a TOML config file
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+        let config: Self =
+            toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid config file {}", path))?;
+        Ok(config)
+    }
+
+    /// Check the settings, reporting every one that is wrong rather than
+    /// stopping at the first
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let problems: Vec<String> = self.problems().iter().map(ToString::to_string).collect();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!(problems.join("\n"))
+        }
+    }
+
+    /// Every setting that is wrong, with where it is
+    pub(crate) fn problems(&self) -> Vec<SettingProblem> {
+        let mut problems = Vec::new();
+        let mut check =
+            |section: &'static str, index: Option<usize>, result: anyhow::Result<()>| {
+                if let Err(e) = result {
+                    problems.push(SettingProblem {
+                        section: Some(section),
+                        index,
+                        message: format!("{:#}", e),
+                    });
+                }
+            };
+        check("api", None, self.api.validate());
+        check("mempool", None, self.mempool.banned_senders().map(drop));
+        check("admin", None, self.admin.validate());
+        check("cors", None, self.cors.validate());
+        check("tls", None, self.tls.validate());
+        let mut signers: Vec<_> = self.signers.iter().collect();
+        signers.sort_by_key(|(name, _)| *name);
+        for (name, signer) in signers {
+            check(
+                "signers",
+                None,
+                signer
+                    .validate()
+                    .with_context(|| format!("Invalid signer {}", name)),
+            );
+        }
+        for (index, webhook) in self.webhooks.iter().enumerate() {
+            check("webhooks", Some(index), webhook.validate());
+        }
+        check("node", None, self.node.validate());
+        check("chaos", None, self.chaos.validate());
+
+        let mut top = |message: String| {
+            problems.push(SettingProblem {
+                section: None,
+                index: None,
+                message,
+            })
+        };
+        if self.data_dir.trim().is_empty() {
+            top("data_dir is empty".to_string());
+        }
+        // Listeners that would fight over a port
+        let ports = [
+            ("web_port", Some(self.web_port)),
+            ("grpc_port", self.grpc_port),
+            ("metrics_port", self.metrics_port),
+            ("admin.port", self.admin.port),
+        ];
+        for (index, (name, port)) in ports.iter().enumerate() {
+            let Some(port) = port else {
+                continue;
+            };
+            if let Some((other, _)) = ports[..index]
+                .iter()
+                .find(|(_, other)| *other == Some(*port))
+            {
+                top(format!("{} and {} are both {}", other, name, port));
+            }
+        }
+        problems
+    }
+
+    /// Take `overrides` over what the config file says
+    pub fn apply(&mut self, overrides: NodeOverrides) {
+        let NodeOverrides {
+            data_dir,
+            web_port,
+            grpc_port,
+            metrics_port,
+            bootnodes,
+            openai_api_key,
+            validators,
+            producers,
+            personas,
+            web,
+            llm,
+            humans,
+            human_token,
+            netsim,
+            shards,
+        } = overrides;
+        let node = &mut self.node;
+        if let Some(data_dir) = data_dir {
+            self.data_dir = data_dir;
+        }
+        if let Some(web_port) = web_port {
+            self.web_port = web_port;
+        }
+        self.grpc_port = grpc_port.or(self.grpc_port);
+        self.metrics_port = metrics_port.or(self.metrics_port);
+        if !bootnodes.is_empty() {
+            self.network.bootnodes = bootnodes;
+        }
+        if let Some(openai_api_key) = openai_api_key {
+            self.openai_api_key = openai_api_key;
+        }
+        node.validators = validators.unwrap_or(node.validators);
+        node.producers = producers.unwrap_or(node.producers);
+        node.personas = personas.or(node.personas.take());
+        node.web = web.unwrap_or(node.web);
+        node.llm = llm.unwrap_or(node.llm);
+        node.humans = humans.unwrap_or(node.humans);
+        node.human_token = human_token.or(node.human_token.take());
+        node.netsim = netsim.or(node.netsim);
+        node.shards = shards.unwrap_or(node.shards);
+    }
+}
+
+/// What an API token allows. Each scope includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    /// Blocks, agents, the drama feed and the subscriptions
+    ReadOnly,
+    /// Transactions, proposals and votes
+    TxSubmit,
+    /// Spawning and retiring agents, changing network links
+    Admin,
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::ReadOnly => "read-only",
+            Self::TxSubmit => "tx-submit",
+            Self::Admin => "admin",
+        })
+    }
+}
+
+/// A static token, sent in the `X-Api-Key` header
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    /// Who the token was given to, for the logs
+    pub name: String,
+    pub token: String,
+    pub scope: Scope,
+}
+
+/// A token bucket: `burst` requests at once, then `per_minute` of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+/// Limits per class of route, the class being the scope a route needs.
+/// Requests with a known token count against the token, others against
+/// their IP address. Messages to the agents count against `chat` as well,
+/// since every one of them costs a model call.
+///
+/// ```toml
+/// [api.rate_limits]
+/// read-only = { per_minute = 600, burst = 50 }
+/// tx-submit = { per_minute = 60, burst = 10 }
+/// chat = { per_minute = 5, burst = 2 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct RateLimits {
+    pub enabled: bool,
+    pub read_only: RateLimit,
+    pub tx_submit: RateLimit,
+    pub admin: RateLimit,
+    pub chat: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            read_only: RateLimit {
+                per_minute: 3000,
+                burst: 100,
+            },
+            tx_submit: RateLimit {
+                per_minute: 600,
+                burst: 20,
+            },
+            admin: RateLimit {
+                per_minute: 60,
+                burst: 10,
+            },
+            chat: RateLimit {
+                per_minute: 10,
+                burst: 3,
+            },
+        }
+    }
+}
+
+impl RateLimits {
+    /// The limit of routes that need `scope`
+    pub fn of(&self, scope: Scope) -> RateLimit {
+        match scope {
+            Scope::ReadOnly => self.read_only,
+            Scope::TxSubmit => self.tx_submit,
+            Scope::Admin => self.admin,
+        }
+    }
+}
+
+/// Tokens of the APIs. With none set the APIs are open to anyone.
+///
+/// ```toml
+/// [api]
+/// anonymous = "read-only"
+/// rpc_max_batch = 100
+/// compression_min_size = 1024
+///
+/// [[api.tokens]]
+/// name = "explorer"
+/// token = "3f9c0e..."
+/// scope = "tx-submit"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub tokens: Vec<ApiToken>,
+    /// What requests without a token may do, nothing when left out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anonymous: Option<Scope>,
+    pub rate_limits: RateLimits,
+    /// Most requests in one JSON-RPC batch. A batch counts as one request
+    /// against the rate limits.
+    pub rpc_max_batch: usize,
+    /// Gzip responses for clients that accept it
+    pub compression: bool,
+    /// Bodies smaller than this many bytes go out as they are, not worth
+    /// the work of compressing
+    pub compression_min_size: usize,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            tokens: Vec::new(),
+            anonymous: None,
+            rate_limits: RateLimits::default(),
+            rpc_max_batch: 100,
+            compression: true,
+            compression_min_size: 1024,
+        }
+    }
+}
+
+impl ApiConfig {
+    pub fn is_open(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// What a request carrying `token` may do. None for a token we never
+    /// gave out, or a missing one when anonymous requests are refused.
+    pub fn scope_of(&self, token: Option<&str>) -> Option<Scope> {
+        if self.is_open() {
+            return Some(Scope::Admin);
+        }
+        match token {
+            Some(token) => self
+                .tokens
+                .iter()
+                .find(|known| bool::from(known.token.as_bytes().ct_eq(token.as_bytes())))
+                .map(|known| known.scope),
+            None => self.anonymous,
+        }
+    }
+
+    /// The name a token was given under
+    pub fn name_of(&self, token: &str) -> Option<&str> {
+        self.tokens
+            .iter()
+            .find(|known| bool::from(known.token.as_bytes().ct_eq(token.as_bytes())))
+            .map(|known| known.name.as_str())
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut seen = std::collections::HashSet::new();
+        for token in &self.tokens {
+            if token.token.trim().is_empty() {
+                anyhow::bail!("API token '{}' is empty", token.name);
+            }
+            if !seen.insert(token.token.as_str()) {
+                anyhow::bail!("API token '{}' is given out twice", token.name);
+            }
+        }
+        for scope in [Scope::ReadOnly, Scope::TxSubmit, Scope::Admin] {
+            let limit = self.rate_limits.of(scope);
+            if self.rate_limits.enabled && (limit.per_minute == 0 || limit.burst == 0) {
+                anyhow::bail!("The {} rate limit lets nothing through", scope);
+            }
+        }
+        let chat = self.rate_limits.chat;
+        if self.rate_limits.enabled && (chat.per_minute == 0 || chat.burst == 0) {
+            anyhow::bail!("The chat rate limit lets nothing through");
+        }
+        if self.rpc_max_batch == 0 {
+            anyhow::bail!("rpc_max_batch has to let at least one request through");
+        }
+        Ok(())
+    }
+}
+
+/// The admin API, on a listener of its own on 127.0.0.1 so that it is never
+/// served along with the others by accident. It runs with a port set, and
+/// takes requests with the token in an `X-Admin-Token` header. With
+/// `profiling` it also serves profiles of the running node under
+/// `/admin/profile`.
+///
+/// ```toml
+/// [admin]
+/// port = 9700
+/// token = "c1d2e3..."
+/// profiling = true
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Serve CPU, heap and runtime profiles, off unless asked for
+    pub profiling: bool,
+}
+
+impl AdminConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match (&self.port, &self.token) {
+            (Some(_), None) => anyhow::bail!("The admin API needs a token"),
+            (_, Some(token)) if token.trim().is_empty() => {
+                anyhow::bail!("The admin token is empty")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Cross-origin requests browsers may make to one listener. `*` allows
+/// any origin, method or header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsPolicy {
+    /// Origins like `https://dashboard.example.com`
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+}
+
+impl CorsPolicy {
+    /// Anyone may call, for development
+    pub fn permissive() -> Self {
+        Self {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: vec!["*".to_string()],
+            allowed_headers: vec!["*".to_string()],
+        }
+    }
+
+    /// No page from elsewhere may call. Origins added to it may use the
+    /// APIs with a token.
+    pub fn strict() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "PUT", "DELETE"]
+                .map(str::to_string)
+                .to_vec(),
+            allowed_headers: [
+                "content-type",
+                "authorization",
+                "x-api-key",
+                "x-admin-token",
+            ]
+            .map(str::to_string)
+            .to_vec(),
+        }
+    }
+
+    /// Whether any origin may call
+    pub fn allows_any_origin(&self) -> bool {
+        self.allowed_origins.iter().any(|origin| origin == "*")
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (what, values) in [
+            ("origin", &self.allowed_origins),
+            ("method", &self.allowed_methods),
+            ("header", &self.allowed_headers),
+        ] {
+            if values.len() > 1 && values.iter().any(|value| value == "*") {
+                anyhow::bail!(
+                    "'*' allows any {} already, and can't be listed with others",
+                    what
+                );
+            }
+        }
+        for origin in &self.allowed_origins {
+            let host = origin
+                .strip_prefix("https://")
+                .or_else(|| origin.strip_prefix("http://"));
+            if origin != "*" && host.is_none_or(|host| host.is_empty() || host.contains('/')) {
+                anyhow::bail!(
+                    "CORS origin '{}' should be a scheme and a host, like https://example.com",
+                    origin
+                );
+            }
+        }
+        for method in &self.allowed_methods {
+            if method != "*"
+                && (method.is_empty() || !method.chars().all(|c| c.is_ascii_uppercase()))
+            {
+                anyhow::bail!("CORS method '{}' should be like GET or POST", method);
+            }
+        }
+        for header in &self.allowed_headers {
+            let token = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+            if header != "*" && (header.is_empty() || !header.chars().all(token)) {
+                anyhow::bail!("CORS header '{}' is not a header name", header);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        Self::strict()
+    }
+}
+
+/// CORS policies of the HTTP listeners. A listener left out is open to any
+/// page while the APIs take no tokens, as on a development node, and to
+/// none once they do. The admin API is never open to other pages unless
+/// it is listed.
+///
+/// ```toml
+/// [cors.web]
+/// allowed_origins = ["https://dashboard.example.com"]
+/// allowed_methods = ["GET", "POST"]
+/// allowed_headers = ["content-type", "x-api-key"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// The web UI and the REST, WebSocket and JSON-RPC APIs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web: Option<CorsPolicy>,
+    /// The `/metrics` listener
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<CorsPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin: Option<CorsPolicy>,
+}
+
+impl CorsConfig {
+    pub fn web(&self, api: &ApiConfig) -> CorsPolicy {
+        Self::or_default(&self.web, api)
+    }
+
+    pub fn metrics(&self, api: &ApiConfig) -> CorsPolicy {
+        Self::or_default(&self.metrics, api)
+    }
+
+    pub fn admin(&self) -> CorsPolicy {
+        self.admin.clone().unwrap_or_else(CorsPolicy::strict)
+    }
+
+    fn or_default(policy: &Option<CorsPolicy>, api: &ApiConfig) -> CorsPolicy {
+        match policy {
+            Some(policy) => policy.clone(),
+            None if api.is_open() => CorsPolicy::permissive(),
+            None => CorsPolicy::strict(),
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (listener, policy) in [
+            ("web", &self.web),
+            ("metrics", &self.metrics),
+            ("admin", &self.admin),
+        ] {
+            if let Some(policy) = policy {
+                policy
+                    .validate()
+                    .with_context(|| format!("Invalid CORS policy of the {} listener", listener))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// TLS of the web server, and with it of the REST, JSON-RPC, WebSocket and
+/// SSE APIs. Without a certificate they are served over plain HTTP. Paths
+/// are PEM files. For development, `self_signed` has the node make up a
+/// certificate for localhost and keep it in the data directory.
+///
+/// ```toml
+/// [tls]
+/// cert = "/etc/chaoschain/node.crt"
+/// key = "/etc/chaoschain/node.key"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    pub self_signed: bool,
+}
+
+impl TlsConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.cert.is_some() || self.self_signed
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match (&self.cert, &self.key) {
+            (Some(_), None) => anyhow::bail!("The TLS certificate needs its key"),
+            (None, Some(_)) => anyhow::bail!("The TLS key needs its certificate"),
+            (Some(_), Some(_)) if self.self_signed => {
+                anyhow::bail!("Either give a TLS certificate or have one made up, not both")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where a key is held when it is not in the keystore. A `pkcs11` key is
+/// an ed25519 key pair of an HSM, or of SoftHSM, found by its label on the
+/// token of `token`, or in `slot`, or else on the first token there is.
+/// The user PIN is read from `pin_file`, else from
+/// `CHAOSCHAIN_PKCS11_PIN`, else asked for on the terminal.
+///
+/// ```toml
+/// [signers.validator-1]
+/// backend = "pkcs11"
+/// module = "/usr/lib/softhsm/libsofthsm2.so"
+/// token = "chaoschain"
+/// label = "validator-1"
+/// pin_file = "/etc/chaoschain/hsm.pin"
+/// ```
+///
+/// A `keystore` key is the key `key` of the keystore in `dir`, other than
+/// the one `--keystore` names. Its password, when it is encrypted, is read
+/// from `password_file`, else as for the keystore.
+///
+/// ```toml
+/// [signers.validator-2]
+/// backend = "keystore"
+/// dir = "/var/lib/chaoschain/validator-2/keys"
+/// key = "validator-2"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "kebab-case")]
+pub enum SignerConfig {
+    Pkcs11 {
+        /// The vendor's PKCS#11 module
+        module: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        slot: Option<u64>,
+        /// Label of the key pair on the token
+        label: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pin_file: Option<String>,
+    },
+    Keystore {
+        /// Directory of the keystore
+        dir: String,
+        /// Name of the key in it
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password_file: Option<String>,
+    },
+}
+
+impl SignerConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Self::Pkcs11 {
+                module,
+                token,
+                slot,
+                label,
+                pin_file,
+            } => {
+                if module.trim().is_empty() {
+                    anyhow::bail!("The PKCS#11 module is empty");
+                }
+                if label.trim().is_empty() {
+                    anyhow::bail!("The label of the key is empty");
+                }
+                if token.is_some() && slot.is_some() {
+                    anyhow::bail!("Either give the token or its slot, not both");
+                }
+                if pin_file.as_ref().is_some_and(|file| file.trim().is_empty()) {
+                    anyhow::bail!("The PIN file is empty");
+                }
+            }
+            Self::Keystore {
+                dir,
+                key,
+                password_file,
+            } => {
+                if dir.trim().is_empty() {
+                    anyhow::bail!("The keystore directory is empty");
+                }
+                if key.trim().is_empty() {
+                    anyhow::bail!("The name of the key is empty");
+                }
+                if password_file
+                    .as_ref()
+                    .is_some_and(|file| file.trim().is_empty())
+                {
+                    anyhow::bail!("The password file is empty");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Chain events a webhook can be told about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebhookEvent {
+    BlockFinalized,
+    /// A validator caught equivocating
+    ValidatorSlashed,
+    /// Chat, alliances, negotiations, rumors and artwork
+    Drama,
+}
+
+impl std::fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BlockFinalized => write!(f, "block-finalized"),
+            Self::ValidatorSlashed => write!(f, "validator-slashed"),
+            Self::Drama => write!(f, "drama"),
+        }
+    }
+}
+
+/// A URL the node posts chain events to, signed with `secret`. No events
+/// means all of them, and with keywords only drama mentioning one of them
+/// is posted.
+///
+/// ```toml
+/// [[webhooks]]
+/// url = "https://example.com/hooks/chaoschain"
+/// events = ["block-finalized", "drama"]
+/// keywords = ["betrayal", "scandal"]
+/// secret = "5b1e..."
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    pub secret: String,
+}
+
+impl WebhookConfig {
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+
+    /// Whether drama saying `message` is posted
+    pub fn matches(&self, message: &str) -> bool {
+        let message = message.to_lowercase();
+        self.keywords.is_empty()
+            || self
+                .keywords
+                .iter()
+                .any(|keyword| message.contains(&keyword.to_lowercase()))
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let host = self
+            .url
+            .strip_prefix("https://")
+            .or_else(|| self.url.strip_prefix("http://"));
+        if host.is_none_or(|host| host.is_empty() || host.starts_with('/')) {
+            anyhow::bail!("Webhook URL '{}' should be an http or https URL", self.url);
+        }
+        if self.secret.trim().is_empty() {
+            anyhow::bail!("The webhook to {} has an empty secret", self.url);
+        }
+        if self
+            .keywords
+            .iter()
+            .any(|keyword| keyword.trim().is_empty())
+        {
+            anyhow::bail!("The webhook to {} has an empty keyword", self.url);
+        }
+        Ok(())
+    }
+}
+
+/// Senders whose transactions the mempool turns away, by their hex
+/// encoded ed25519 keys.
+///
+/// ```toml
+/// [mempool]
+/// banned_senders = ["9d61b19d..."]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MempoolConfig {
+    pub banned_senders: Vec<String>,
+}
+
+impl MempoolConfig {
+    pub fn banned_senders(&self) -> anyhow::Result<Vec<[u8; 32]>> {
+        self.banned_senders
+            .iter()
+            .map(|sender| {
+                hex::decode(sender.trim_start_matches("0x"))
+                    .ok()
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Banned sender '{}' is not a 32 byte key", sender)
+                    })
+            })
+            .collect()
+    }
+}
+
+/// Faults the node injects into itself on a schedule, to rehearse
+/// failures on a test network. Nothing is injected unless `enabled`. Each
+/// fault starts `at` seconds after the node does and lasts `duration`
+/// seconds. Message faults name kinds of events (`block_proposal`, `vote`,
+/// `chat`, ...) and the agents that hear them late or not at all, every
+/// agent when they name none.
+///
+/// ```toml
+/// [chaos]
+/// enabled = true
+///
+/// [[chaos.faults]]
+/// fault = "drop"
+/// at = 60
+/// duration = 30
+/// messages = ["vote"]
+/// share = 0.5
+///
+/// [[chaos.faults]]
+/// fault = "delay"
+/// at = 120
+/// duration = 60
+/// messages = ["block_proposal"]
+/// delay_ms = 3000
+/// agents = ["validator-1"]
+///
+/// [[chaos.faults]]
+/// fault = "stall_storage"
+/// at = 200
+/// duration = 20
+/// delay_ms = 2000
+///
+/// [[chaos.faults]]
+/// fault = "crash"
+/// at = 300
+/// duration = 15
+/// agent = "validator-2"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosConfig {
+    pub enabled: bool,
+    pub faults: Vec<ScheduledFault>,
+}
+
+impl ChaosConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (index, fault) in self.faults.iter().enumerate() {
+            fault
+                .validate()
+                .with_context(|| format!("Invalid fault {}", index + 1))?;
+        }
+        Ok(())
+    }
+}
+
+/// A fault and when it is in effect
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledFault {
+    /// Seconds after the node starts
+    pub at: u64,
+    /// Seconds the fault lasts
+    pub duration: u64,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+impl ScheduledFault {
+    pub fn starts(&self) -> Duration {
+        Duration::from_secs(self.at)
+    }
+
+    pub fn ends(&self) -> Duration {
+        Duration::from_secs(self.at.saturating_add(self.duration))
+    }
+
+    /// Whether the fault is in effect `elapsed` after the node started
+    pub fn is_active(&self, elapsed: Duration) -> bool {
+        (self.starts()..self.ends()).contains(&elapsed)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.duration == 0 {
+            anyhow::bail!("duration is 0, the fault would never be in effect");
+        }
+        match &self.fault {
+            Fault::Drop {
+                messages, share, ..
+            } => {
+                check_messages(messages)?;
+                if !(*share > 0.0 && *share <= 1.0) {
+                    anyhow::bail!("share is {}, it goes from above 0 to 1", share);
+                }
+            }
+            Fault::Delay {
+                messages, delay_ms, ..
+            } => {
+                check_messages(messages)?;
+                if *delay_ms == 0 {
+                    anyhow::bail!("delay_ms is 0, nothing would be delayed");
+                }
+            }
+            Fault::StallStorage { delay_ms } => {
+                if *delay_ms == 0 {
+                    anyhow::bail!("delay_ms is 0, nothing would stall");
+                }
+            }
+            Fault::Crash { agent } => {
+                if agent.trim().is_empty() {
+                    anyhow::bail!("No agent to crash");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_messages(messages: &[String]) -> anyhow::Result<()> {
+    if messages.is_empty() {
+        anyhow::bail!("No messages, name the kinds of events the fault is on");
+    }
+    if let Some(unknown) = messages
+        .iter()
+        .find(|kind| !chaoschain_core::NetworkEvent::KINDS.contains(&kind.as_str()))
+    {
+        anyhow::bail!(
+            "Unknown kind of message '{}', expected one of {}",
+            unknown,
+            chaoschain_core::NetworkEvent::KINDS.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// What goes wrong while a [`ScheduledFault`] is in effect
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "fault", rename_all = "snake_case")]
+pub enum Fault {
+    /// Agents miss a share of the messages of some kinds
+    Drop {
+        messages: Vec<String>,
+        /// Share of the messages dropped, from above 0 to 1
+        #[serde(default = "all")]
+        share: f64,
+        #[serde(default)]
+        agents: Vec<String>,
+    },
+    /// Agents hear the messages of some kinds late
+    Delay {
+        messages: Vec<String>,
+        delay_ms: u64,
+        #[serde(default)]
+        agents: Vec<String>,
+    },
+    /// Every commit to the block log waits, as on a disk that stalls
+    StallStorage { delay_ms: u64 },
+    /// An agent crashes, and is restarted when the fault ends
+    Crash { agent: String },
+}
+
+fn all() -> f64 {
+    1.0
+}
+
+impl Fault {
+    /// The name of the fault in the config, for logs and metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Drop { .. } => "drop",
+            Self::Delay { .. } => "delay",
+            Self::StallStorage { .. } => "stall_storage",
+            Self::Crash { .. } => "crash",
+        }
+    }
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let to = |agents: &[String]| {
+            if agents.is_empty() {
+                "every agent".to_string()
+            } else {
+                agents.join(", ")
+            }
+        };
+        match self {
+            Self::Drop {
+                messages,
+                share,
+                agents,
+            } => write!(
+                f,
+                "{:.0}% of {} messages lost on the way to {}",
+                share * 100.0,
+                messages.join(", "),
+                to(agents)
+            ),
+            Self::Delay {
+                messages,
+                delay_ms,
+                agents,
+            } => write!(
+                f,
+                "{} messages {} ms late to {}",
+                messages.join(", "),
+                delay_ms,
+                to(agents)
+            ),
+            Self::StallStorage { delay_ms } => {
+                write!(f, "block log writes stalled by {} ms", delay_ms)
+            }
+            Self::Crash { agent } => write!(f, "{} crashed", agent),
+        }
+    }
+}
+
+/// A setting `Config::validate` turns down
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SettingProblem {
+    /// Top-level settings have none
+    pub section: Option<&'static str>,
+    /// Which entry, in a section that is a list
+    pub index: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for SettingProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.section {
+            Some(section) => write!(f, "[{}] {}", section, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Agents and services `node run` starts, as the `demo` flags of the same
+/// names set them.
+///
+/// ```toml
+/// [node]
+/// validators = 4
+/// producers = 2
+/// web = true
+/// netsim = { latency_ms = 200, loss = 0.05 }
+/// shards = 3
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeConfig {
+    /// Anonymous validators, on top of the personas
+    pub validators: u32,
+    /// Anonymous producers, on top of the personas
+    pub producers: u32,
+    /// Cast of named agents (default: `personas.toml`, when present)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub personas: Option<String>,
+    /// Whether to serve the web interface, on `web_port`
+    pub web: bool,
+    /// Let validators deliberate through the LLM instead of the offline engine
+    pub llm: bool,
+    /// Record every agent prompt and response in the audit trail
+    pub audit: bool,
+    /// Additional models each validator consults
+    pub ensemble_models: Vec<ModelSpec>,
+    pub ensemble_strategy: EnsembleStrategy,
+    /// Stream the validators' model output into the drama feed
+    pub stream: bool,
+    /// Let validator models look up chain data while they deliberate
+    pub tools: bool,
+    /// Hard limit on a single validator decision, in seconds
+    pub decision_timeout: u64,
+    /// Vote cast when a decision misses its deadline
+    pub timeout_fallback: FallbackVote,
+    /// Upper bound on a validator prompt in tokens
+    pub context_budget: usize,
+    pub summarizer: SummarizerKind,
+    /// Image backend milestones are painted with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artwork: Option<ImageBackend>,
+    /// Stable Diffusion WebUI address, for the `sd` backend
+    pub artwork_api_base: String,
+    /// Validator seats played by humans
+    pub humans: u32,
+    /// Seconds a human has to answer
+    pub human_timeout: u64,
+    /// Token that authenticates human votes, generated when absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub human_token: Option<String>,
+    /// Conditions on every simulated link between the agents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netsim: Option<LinkConditions>,
+    /// Drama shards run beside the main chain, each with a committee of
+    /// the cast; 0 runs none. Experimental.
+    pub shards: u16,
+    /// Seconds a shutdown waits for the node to wind down
+    pub shutdown_timeout: u64,
+    /// Genesis document of the network, whose hash function the node
+    /// hashes transactions and blocks with (default: SHA-256)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genesis: Option<String>,
+}
+
+/// Drama shards a node runs at most
+pub const MAX_SHARDS: u16 = 16;
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            validators: 0,
+            producers: 0,
+            personas: None,
+            web: false,
+            llm: false,
+            audit: false,
+            ensemble_models: Vec::new(),
+            ensemble_strategy: EnsembleStrategy::Majority,
+            stream: false,
+            tools: false,
+            decision_timeout: 20,
+            timeout_fallback: FallbackVote::Abstain,
+            context_budget: 2048,
+            summarizer: SummarizerKind::Local,
+            artwork: None,
+            artwork_api_base: "http://127.0.0.1:7860".to_string(),
+            humans: 0,
+            human_timeout: 120,
+            human_token: None,
+            netsim: None,
+            shards: 0,
+            shutdown_timeout: 10,
+            genesis: None,
+        }
+    }
+}
+
+impl NodeConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(personas) = &self.personas {
+            if !std::path::Path::new(personas).exists() {
+                anyhow::bail!("The personas file {} does not exist", personas);
+            }
+        }
+        if let Some(genesis) = &self.genesis {
+            if !std::path::Path::new(genesis).exists() {
+                anyhow::bail!("The genesis {} does not exist", genesis);
+            }
+        }
+        if self.decision_timeout == 0 {
+            anyhow::bail!("decision_timeout is 0, validators would time out before deciding");
+        }
+        if self.humans > 0 && self.human_timeout == 0 {
+            anyhow::bail!("human_timeout is 0, humans would time out before voting");
+        }
+        if matches!(&self.human_token, Some(token) if token.trim().is_empty()) {
+            anyhow::bail!("The human token is empty");
+        }
+        if let Some(model) = self
+            .ensemble_models
+            .iter()
+            .find(|model| model.weight.is_nan() || model.weight <= 0.0)
+        {
+            anyhow::bail!(
+                "Ensemble model {} has weight {}, it must be positive",
+                model.model,
+                model.weight
+            );
+        }
+        if let Some(netsim) = &self.netsim {
+            netsim.validate().context("Invalid netsim")?;
+        }
+        if self.shards == 1 || self.shards > MAX_SHARDS {
+            anyhow::bail!(
+                "shards is {}, it must be 0 or from 2 to {}",
+                self.shards,
+                MAX_SHARDS
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Character of one agent. Anything left out is rolled at random.
+///
+/// ```toml
+/// [agents.validator-1]
+/// personality = "Greedy"
+/// greed = 10
+/// loyalty = 2
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AgentProfile {
+    /// Archetype
+    pub personality: Option<AgentPersonality>,
+    /// Trait sliders (chaos_level, loyalty, greed, verbosity, attention_span)
+    #[serde(flatten)]
+    pub traits: TraitOverrides,
+    /// Model the agent deliberates with, as MODEL[@API_BASE]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Replaces the persona line of the agent's prompts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// Emoji or image URL the UI shows for the agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+}
+
+impl AgentProfile {
+    /// Settings in `other` win over ours
+    pub fn merged(&self, other: &Self) -> Self {
+        Self {
+            personality: other.personality.clone().or(self.personality.clone()),
+            traits: self.traits.merged(&other.traits),
+            model: other.model.clone().or(self.model.clone()),
+            prompt: other.prompt.clone().or(self.prompt.clone()),
+            avatar: other.avatar.clone().or(self.avatar.clone()),
+        }
+    }
+}
+
+/// What a persona plays
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PersonaRole {
+    #[default]
+    Validator,
+    Producer,
+}
+
+/// One member of the cast
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Persona {
+    /// Agent id, e.g. `validator-1`
+    pub name: String,
+    #[serde(default)]
+    pub role: PersonaRole,
+    #[serde(flatten)]
+    pub profile: AgentProfile,
+}
+
+/// The full cast of the network, declared in a `personas.toml`:
+///
+/// ```toml
+/// [[persona]]
+/// name = "validator-greedy"
+/// personality = "Greedy"
+/// greed = 10
+/// model = "llama3@http://localhost:11434/v1"
+/// prompt = "You are a corrupt customs officer. Every block is a bribe waiting to happen."
+/// avatar = "🤑"
+///
+/// [[persona]]
+/// name = "producer-bard"
+/// role = "producer"
+/// personality = "Dramatic"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Personas {
+    #[serde(default, rename = "persona")]
+    pub personas: Vec<Persona>,
+}
+
+/// Keys a persona may set, anything else is most likely a typo
+const PERSONA_KEYS: &[&str] = &[
+    "name",
+    "role",
+    "personality",
+    "chaos_level",
+    "loyalty",
+    "greed",
+    "verbosity",
+    "attention_span",
+    "model",
+    "prompt",
+    "avatar",
+];
+
+impl Personas {
+    /// Load and validate a personas file
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+        Self::parse(&contents).with_context(|| format!("Invalid personas file {}", path))
+    }
+
+    /// Parse and validate, reporting every problem at once
+    pub fn parse(contents: &str) -> anyhow::Result<Self> {
+        let table: toml::Table = toml::from_str(contents)?;
+        let mut problems = Vec::new();
+        for key in table.keys().filter(|key| *key != "persona") {
+            problems.push(format!("unknown section '{}'", key));
+        }
+        if let Some(toml::Value::Array(entries)) = table.get("persona") {
+            for (index, entry) in entries.iter().enumerate() {
+                let Some(entry) = entry.as_table() else {
+                    continue;
+                };
+                for key in entry.keys() {
+                    if !PERSONA_KEYS.contains(&key.as_str()) {
+                        problems.push(format!("persona #{}: unknown key '{}'", index + 1, key));
+                    }
+                }
+            }
+        }
+
+        let personas: Self = table.try_into()?;
+        let mut seen = std::collections::HashSet::new();
+        for (index, persona) in personas.personas.iter().enumerate() {
+            let label = if persona.name.is_empty() {
+                format!("persona #{}", index + 1)
+            } else {
+                format!("persona '{}'", persona.name)
+            };
+            if persona.name.trim().is_empty() {
+                problems.push(format!("{}: name must not be empty", label));
+            } else if !seen.insert(persona.name.as_str()) {
+                problems.push(format!("{}: declared twice", label));
+            }
+            let traits = &persona.profile.traits;
+            for (name, value) in [
+                ("chaos_level", traits.chaos_level),
+                ("loyalty", traits.loyalty),
+                ("greed", traits.greed),
+                ("verbosity", traits.verbosity),
+                ("attention_span", traits.attention_span),
+            ] {
+                if let Some(value) = value.filter(|value| *value > MAX_TRAIT) {
+                    problems.push(format!(
+                        "{}: {} is {}, traits go from 0 to {}",
+                        label, name, value, MAX_TRAIT
+                    ));
+                }
+            }
+            if let Some(model) = &persona.profile.model {
+                if let Err(e) = model.parse::<ModelSpec>() {
+                    problems.push(format!("{}: {}", label, e));
+                }
+            }
+            if persona.profile.prompt.as_deref().map(str::trim) == Some("") {
+                problems.push(format!("{}: prompt must not be empty", label));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(personas)
+        } else {
+            anyhow::bail!(problems.join("\n"))
+        }
+    }
+}
+
+/// What a new network starts with, as `chaoschain genesis --from` reads
+/// it from a file like `docs/examples/genesis.toml`:
+///
+/// ```toml
+/// chain_id = "chaos-testnet-1"
+/// stake = 100
+///
+/// [[validator]]
+/// name = "validator-greedy"
+/// balance = 10000
+///
+/// [[allocation]]
+/// address = "3b6a27bcceb6a42d62a3a8d02a6f0d73653215771de243a63ac048a18b59da29"
+/// balance = 1000000
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisSpec {
+    pub chain_id: Option<String>,
+    /// Unix time of the genesis, in seconds (default: now)
+    pub genesis_time: Option<u64>,
+    pub finality_threshold: Option<f64>,
+    pub block_time: Option<u64>,
+    pub consensus_timeout: Option<u64>,
+    /// Blocks a validator's old key still votes after it rotates
+    pub rotation_grace: Option<u64>,
+    /// Function transactions and blocks are hashed with, `sha256` or
+    /// `blake3`
+    pub hash: Option<String>,
+    /// Upgrades of the protocol, as `bounded-levels=1200` or
+    /// `drama-rewards=epoch:30`
+    #[serde(default)]
+    pub forks: Vec<String>,
+    /// Blocks of an epoch, for upgrades scheduled by epoch
+    pub epoch_length: Option<u64>,
+    /// Stake of the validators that set none
+    pub stake: Option<u64>,
+    /// Balance of the validators that set none
+    pub balance: Option<u64>,
+    /// How proposals are voted on, a `[governance]` table
+    #[serde(default)]
+    pub governance: GovernanceParams,
+    /// How stake is unbonded, paid and slashed, a `[staking]` table
+    #[serde(default)]
+    pub staking: StakingParams,
+    #[serde(default, rename = "validator")]
+    pub validators: Vec<ValidatorSpec>,
+    #[serde(default, rename = "allocation")]
+    pub allocations: Vec<AllocationSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValidatorSpec {
+    pub name: String,
+    pub stake: Option<u64>,
+    /// Allocated to the key made for the validator
+    pub balance: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AllocationSpec {
+    /// Hex encoded public key
+    pub address: String,
+    pub balance: u64,
+}
+
+impl GenesisSpec {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let contents =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+        toml::from_str(&contents).with_context(|| format!("Invalid genesis spec {}", path))
+    }
 }
 
 /// CLI commands
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(name = "chaoschain", author, version, about, long_about = None)]
 pub struct Cli {
     /// Config file path
     #[arg(short, long, value_name = "FILE")]
@@ -33,31 +1503,2329 @@ pub struct Cli {
     pub command: Commands,
 }
 
+impl Cli {
+    /// The file given with `--config`
+    pub fn config_path(&self) -> Option<&str> {
+        self.config.as_deref()
+    }
+
+    /// The config file given with `--config`, or the defaults
+    pub fn load_config(&self) -> anyhow::Result<Config> {
+        match &self.config {
+            Some(path) => Config::load(path),
+            None => Ok(Config::default()),
+        }
+    }
+}
+
 #[derive(Subcommand, Clone)]
 pub enum Commands {
     /// Run a demo with the specified number of validators and producers
     Demo {
-        /// Number of validators to run
-        #[arg(long)]
+        /// Number of validators to run, on top of the personas
+        #[arg(long, default_value_t = 0)]
         validators: u32,
 
-        /// Number of producers to run
-        #[arg(long)]
+        /// Number of producers to run, on top of the personas
+        #[arg(long, default_value_t = 0)]
         producers: u32,
 
+        /// Cast of named agents (default: `personas.toml`, when present)
+        #[arg(long, value_name = "FILE")]
+        personas: Option<String>,
+
         /// Whether to run the web interface
         #[arg(long)]
         web: bool,
+
+        /// Let validators deliberate through the LLM instead of the offline engine
+        #[arg(long)]
+        llm: bool,
+
+        /// Record every agent prompt and response in the audit trail
+        #[arg(long)]
+        audit: bool,
+
+        /// Additional models each validator consults, as MODEL[@API_BASE][#WEIGHT]
+        /// (e.g. llama3@http://localhost:11434/v1#0.5)
+        #[arg(long = "ensemble-model", value_name = "SPEC")]
+        ensemble_models: Vec<ModelSpec>,
+
+        /// How ensemble verdicts are combined (majority, weighted, most_dramatic)
+        #[arg(long, default_value = "majority")]
+        ensemble_strategy: EnsembleStrategy,
+
+        /// Stream the validators' model output into the drama feed as it is generated
+        /// (the primary model's only, when an ensemble is used)
+        #[arg(long)]
+        stream: bool,
+
+        /// Let validator models look up balances, voting records and the mempool
+        /// while they deliberate
+        #[arg(long)]
+        tools: bool,
+
+        /// Hard limit on a single validator decision, in seconds
+        #[arg(long, default_value_t = 20)]
+        decision_timeout: u64,
+
+        /// Vote cast when a decision misses its deadline (abstain, approve, reject)
+        #[arg(long, default_value = "abstain")]
+        timeout_fallback: FallbackVote,
+
+        /// Upper bound on a validator prompt in tokens, memories included
+        #[arg(long, default_value_t = 2048)]
+        context_budget: usize,
+
+        /// How older validator memories are condensed (local, model)
+        #[arg(long, default_value = "local")]
+        summarizer: SummarizerKind,
+
+        /// Paint milestones (dramatic rejections, betrayals, finalized epochs)
+        /// with an image model (dalle, sd)
+        #[arg(long, value_name = "BACKEND")]
+        artwork: Option<ImageBackend>,
+
+        /// Stable Diffusion WebUI address, for `--artwork sd`
+        #[arg(long, default_value = "http://127.0.0.1:7860")]
+        artwork_api_base: String,
+
+        /// Validator seats played by humans through the web UI or `chaoschain vote`
+        #[arg(long, default_value_t = 0)]
+        humans: u32,
+
+        /// Seconds a human has to answer before the timeout fallback is cast
+        #[arg(long, default_value_t = 120)]
+        human_timeout: u64,
+
+        /// Token that authenticates human votes (else `CHAOSCHAIN_HUMAN_TOKEN`, else generated)
+        #[arg(long)]
+        human_token: Option<String>,
+
+        /// Simulate the network between the agents, with these conditions on
+        /// every link (e.g. latency=200,jitter=50,loss=0.05,reorder=0.1).
+        /// Links can be changed at runtime through the web API.
+        #[arg(long, value_name = "CONDITIONS")]
+        netsim: Option<LinkConditions>,
+
+        /// Run this many drama shards beside the main chain, the cast split
+        /// into a committee for each (experimental)
+        #[arg(long, default_value_t = 0, value_name = "COUNT")]
+        shards: u16,
+
+        /// Seconds Ctrl-C waits for the node to wind down before it exits anyway
+        #[arg(long, default_value_t = 10)]
+        shutdown_timeout: u64,
     },
 
-    /// Start a node
-    Start {
-        /// Node type (validator/producer)
-        #[arg(long, default_value = "validator")]
-        node_type: String,
+    /// Run a throwaway chain of one node to build against: blocks are
+    /// sealed at once, agents run offline, dev accounts start funded and
+    /// the chain is gone on exit
+    Dev {
+        /// Seal a block every this many seconds, with what waits by then,
+        /// instead of one per transaction
+        #[arg(long, value_name = "SECS")]
+        block_time: Option<u64>,
+
+        /// Dev accounts to fund, the first keys of the mnemonic
+        #[arg(long, default_value_t = 10)]
+        accounts: u32,
+
+        /// What each dev account starts with
+        #[arg(long, default_value_t = 1_000_000)]
+        balance: u64,
 
-        /// Start web UI
+        /// Mnemonic of the dev accounts (default: the well known
+        /// `test test ... junk`)
         #[arg(long)]
-        web: bool,
+        mnemonic: Option<String>,
+
+        /// Offline validators reacting to the blocks, which the dev
+        /// validator has voted through already
+        #[arg(long, default_value_t = 0)]
+        validators: u32,
+
+        /// Port of the web UI and the APIs (default: web_port of the config)
+        #[arg(long)]
+        port: Option<u16>,
+
+        /// What the faucet gives an address each time
+        #[arg(long, default_value_t = 10_000)]
+        faucet_drip: u64,
+
+        /// Time an address, or a client, waits between two drips of the
+        /// faucet
+        #[arg(long, value_name = "SECS", default_value_t = 60)]
+        faucet_cooldown: u64,
+    },
+
+    /// Ask the faucet of a dev chain for test balance
+    Faucet {
+        /// Address to fund, 32 hex encoded bytes
+        address: String,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token, when the node asks for one (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Cast the vote of a human validator on a pending block
+    Vote {
+        /// Height of the pending block
+        height: u64,
+
+        /// approve or reject
+        verdict: Verdict,
+
+        /// Why, for the drama feed
+        #[arg(long, default_value = "")]
+        reason: String,
+
+        /// Human validator to vote as, when the node seats several
+        #[arg(long)]
+        agent: Option<String>,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// Human token (else `CHAOSCHAIN_HUMAN_TOKEN`, else the one the node wrote
+        /// to its data directory)
+        #[arg(long)]
+        token: Option<String>,
+
+        /// API token with the tx-submit scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Follow a node's blocks, transactions, votes, drama and relationships
+    /// and write them out as SQL, for `sqlite3` or `psql` to load
+    Index {
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// File to append the SQL to, instead of standard output
+        #[arg(long, value_name = "FILE")]
+        out: Option<String>,
+
+        /// API token with the read-only scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+
+        /// Seconds between two reads of the relationship graph
+        #[arg(long, default_value_t = 30)]
+        relationships_every: u64,
+
+        /// Serve searches over drama and vote reasons on this port of
+        /// 127.0.0.1
+        #[arg(long)]
+        search_port: Option<u16>,
+
+        /// File to keep the search index in, so that it survives restarts
+        #[arg(long, value_name = "FILE", requires = "search_port")]
+        search_index: Option<String>,
+    },
+
+    /// Work with agents away from a live network, or manage those of a
+    /// running node through its admin API
+    Agent {
+        #[command(subcommand)]
+        command: AgentCommand,
+    },
+
+    /// Manage the peers of a running node through its admin API: the
+    /// external agents that vote through its gateway
+    Peers {
+        #[command(subcommand)]
+        command: PeersCommand,
+    },
+
+    /// Run a network of several nodes on this machine, each with its
+    /// ports, keys and logs, from one genesis
+    Testnet {
+        #[command(subcommand)]
+        command: TestnetCommand,
+    },
+
+    /// Start a new network: make the keys of its validators and write its
+    /// genesis document, with a bundle for each validator to be handed
+    Genesis {
+        /// Spec of the network to start from, a TOML file the flags
+        /// override
+        #[arg(long, value_name = "FILE")]
+        from: Option<String>,
+
+        /// Ask for what flags and the spec leave out
+        #[arg(long)]
+        interactive: bool,
+
+        /// Name of the network (default: `chaoschain-1`)
+        #[arg(long)]
+        chain_id: Option<String>,
+
+        /// Validator to make a key for, once for each
+        #[arg(long = "validator", value_name = "NAME")]
+        validators: Vec<String>,
+
+        /// Validators named `validator-1`, `validator-2`, ..., when none is
+        /// named (default: 3)
+        #[arg(long = "validators", value_name = "N")]
+        validator_count: Option<u32>,
+
+        /// Stake of each validator the spec gives none (default: 100)
+        #[arg(long)]
+        stake: Option<u64>,
+
+        /// Balance allocated to the key of each validator the spec gives
+        /// none (default: 0)
+        #[arg(long)]
+        balance: Option<u64>,
+
+        /// Balance of another account, as `ADDRESS=AMOUNT`, once for each
+        #[arg(long = "alloc", value_name = "ADDRESS=AMOUNT")]
+        allocations: Vec<String>,
+
+        /// Share of the stake that finalizes a block (default: 0.67)
+        #[arg(long)]
+        finality_threshold: Option<f64>,
+
+        /// Seconds between two blocks (default: 5)
+        #[arg(long)]
+        block_time: Option<u64>,
+
+        /// Seconds a voting round waits for votes (default: 30)
+        #[arg(long)]
+        consensus_timeout: Option<u64>,
+
+        /// Blocks after a validator rotates its key that its old key still
+        /// votes (default: 100)
+        #[arg(long, value_name = "BLOCKS")]
+        rotation_grace: Option<u64>,
+
+        /// Function transactions and blocks are hashed with, `sha256` or
+        /// `blake3` (default: sha256). It cannot change after genesis.
+        #[arg(long, value_name = "FUNCTION")]
+        hash: Option<String>,
+
+        /// Schedule an upgrade of the protocol, as UPGRADE=HEIGHT or
+        /// UPGRADE=epoch:N. Repeatable.
+        #[arg(long = "fork", value_name = "UPGRADE=HEIGHT")]
+        forks: Vec<String>,
+
+        /// Blocks of an epoch, for upgrades scheduled by epoch (default:
+        /// 100)
+        #[arg(long, value_name = "BLOCKS")]
+        epoch_length: Option<u64>,
+
+        /// Deal a group key the validators sign finalized blocks with,
+        /// any this many of them together
+        #[arg(long, value_name = "T")]
+        threshold: Option<u16>,
+
+        /// Directory to write the genesis and the bundles to, which must
+        /// not hold anything yet
+        #[arg(long, value_name = "DIR", default_value = "genesis")]
+        out: String,
+
+        /// File holding the password validator keys are encrypted with
+        /// (else `CHAOSCHAIN_KEYSTORE_PASSWORD`). Keys are written
+        /// unencrypted without one.
+        #[arg(long, value_name = "FILE")]
+        password_file: Option<String>,
+    },
+
+    /// Make, import, export and list the keys of the keystore
+    Keys {
+        /// Directory the keys are kept in (default: `keys` in the data
+        /// directory)
+        #[arg(long, value_name = "DIR", global = true)]
+        keystore: Option<String>,
+
+        /// File holding the password keys are encrypted with (else
+        /// `CHAOSCHAIN_KEYSTORE_PASSWORD`). Keys are kept unencrypted
+        /// without one.
+        #[arg(long, value_name = "FILE", global = true)]
+        password_file: Option<String>,
+
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+
+    /// Sign transactions with keys of the keystore and send them to a node
+    Tx {
+        /// Directory the keys are kept in (default: `keys` in the data
+        /// directory)
+        #[arg(long, value_name = "DIR", global = true)]
+        keystore: Option<String>,
+
+        /// File holding the password of encrypted keys (else
+        /// `CHAOSCHAIN_KEYSTORE_PASSWORD`)
+        #[arg(long, value_name = "FILE", global = true)]
+        password_file: Option<String>,
+
+        #[command(subcommand)]
+        command: TxCommand,
+    },
+
+    /// Show where a node stands: its head, finality, peers, sync and
+    /// mempool
+    Status {
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token, when the node asks for one (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Keep refreshing the status
+        #[arg(long)]
+        watch: bool,
+
+        /// Seconds between two refreshes with `--watch`
+        #[arg(long, default_value_t = 2)]
+        interval: u64,
+    },
+
+    /// Watch a node in the terminal: blocks, votes and agent drama as they
+    /// happen
+    Watch {
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token, when the node asks for one (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+
+        /// Only what involves this agent
+        #[arg(long)]
+        agent: Option<String>,
+
+        /// Recent items of each stream to show first
+        #[arg(long, default_value_t = 20)]
+        backfill: usize,
     },
+
+    /// Measure transaction throughput: send signed synthetic transactions
+    /// at a target rate and report how many made it in, and how fast
+    Bench {
+        /// Node web API to send to, given once for each node
+        #[arg(
+            long = "node",
+            value_name = "URL",
+            default_value = "http://127.0.0.1:3000"
+        )]
+        nodes: Vec<String>,
+
+        /// API token with the tx-submit scope, when the nodes ask for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+
+        /// Transactions a second, over all senders
+        #[arg(long, default_value_t = 100.0)]
+        rate: f64,
+
+        /// Seconds to send for
+        #[arg(long, default_value_t = 30)]
+        duration: u64,
+
+        /// Keys to send from, spread over the nodes
+        #[arg(long, default_value_t = 16)]
+        senders: usize,
+
+        /// Bytes of payload in each transaction
+        #[arg(long, default_value_t = 64)]
+        payload_size: usize,
+
+        /// Seconds to wait for the last transactions to be included
+        #[arg(long, default_value_t = 30)]
+        settle: u64,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Decode a block, transaction or signed vote and check its hashes and
+    /// signatures, without a node
+    Inspect {
+        /// File, JSON or its hex, or standard input when left out or `-`
+        input: Option<String>,
+
+        /// What the input is, when its fields don't tell
+        #[arg(long, value_enum)]
+        kind: Option<InspectKind>,
+
+        /// Public key of the signer, for a vote, or a block whose producer
+        /// id is not its key
+        #[arg(long)]
+        key: Option<String>,
+    },
+
+    /// Export the state to a snapshot archive, or seed a fresh data
+    /// directory from one
+    Snapshot {
+        /// Data directory to take the snapshot from or put it in (default:
+        /// the one of the config file)
+        #[arg(long, value_name = "DIR", global = true)]
+        data_dir: Option<String>,
+
+        #[command(subcommand)]
+        command: SnapshotCommand,
+    },
+
+    /// Look after a data directory while its node is stopped: take stock
+    /// and check it, compact it, or prune it below a height
+    Db {
+        /// Data directory to work on (default: the one of the config file)
+        #[arg(long, value_name = "DIR", global = true)]
+        data_dir: Option<String>,
+
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+
+    /// Walk the chain in a data directory while its node is stopped,
+    /// checking every link, signature, certificate and snapshot root, and
+    /// say where it first goes wrong
+    VerifyChain {
+        /// Data directory to check (default: the one of the config file)
+        #[arg(long, value_name = "DIR")]
+        data_dir: Option<String>,
+
+        /// Start from the latest snapshot at or below this height instead
+        /// of the lowest block
+        #[arg(long, value_name = "HEIGHT")]
+        from_height: Option<u64>,
+
+        /// Genesis document whose validators the producers and
+        /// certificates are checked against
+        #[arg(long, value_name = "FILE")]
+        genesis: Option<String>,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Write a config file to start from, or check one before the node
+    /// starts with it
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Print the completion script of a shell
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+
+    /// Write a man page for every command, or print the one of
+    /// `chaoschain` itself without `--out`
+    Man {
+        /// Directory to write the pages to
+        #[arg(long, value_name = "DIR")]
+        out: Option<String>,
+    },
+
+    /// Run a node from its configuration
+    Node {
+        #[command(subcommand)]
+        command: NodeCommand,
+    },
+}
+
+/// What `inspect` decodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InspectKind {
+    Block,
+    Tx,
+    Vote,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum SnapshotCommand {
+    /// Write the latest snapshot of the data directory to an archive
+    Export {
+        /// Archive to write
+        #[arg(long)]
+        out: String,
+
+        /// The snapshot at this height rather than the latest
+        #[arg(long, conflicts_with = "take")]
+        height: Option<u64>,
+
+        /// Have the running node take a fresh snapshot first, through the
+        /// admin API of the config file
+        #[arg(long)]
+        take: bool,
+    },
+
+    /// Check an archive and put its snapshot in a fresh data directory,
+    /// for the node to start from
+    Import {
+        /// Archive to read
+        file: String,
+
+        /// Approving votes the finality certificate needs at least
+        #[arg(long, default_value_t = 1)]
+        min_approvals: usize,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum DbCommand {
+    /// What the data directory holds, and what is wrong in it with how to
+    /// set it right
+    Stats {
+        /// Print the statistics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Rewrite the activity journal with only what the node reads back,
+    /// and remove what unfinished compactions left
+    Compact,
+
+    /// Remove the snapshots below a height, but for the latest one, and
+    /// the activity recorded below it
+    Prune {
+        /// Lowest height to keep
+        #[arg(long)]
+        height: u64,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum NodeCommand {
+    /// Start the node the config file describes. Environment variables
+    /// override the file, and flags override both.
+    Run {
+        #[command(flatten)]
+        overrides: NodeOverrides,
+
+        /// Check the configuration and exit
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+/// Settings `node run` takes over the config file, from its flags or else
+/// the environment
+#[derive(Debug, Clone, Default, clap::Args)]
+#[group(skip)]
+pub struct NodeOverrides {
+    /// Data directory
+    #[arg(long, value_name = "DIR", env = "CHAOSCHAIN_DATA_DIR")]
+    pub data_dir: Option<String>,
+
+    /// Web UI port
+    #[arg(long, env = "CHAOSCHAIN_WEB_PORT")]
+    pub web_port: Option<u16>,
+
+    /// gRPC port
+    #[arg(long, env = "CHAOSCHAIN_GRPC_PORT")]
+    pub grpc_port: Option<u16>,
+
+    /// Port of a listener serving only `/metrics`
+    #[arg(long, env = "CHAOSCHAIN_METRICS_PORT")]
+    pub metrics_port: Option<u16>,
+
+    /// Bootnode to dial, instead of the config file's. Once for every
+    /// bootnode, or separated by commas in the environment.
+    #[arg(
+        long = "bootnode",
+        value_name = "ADDR",
+        env = "CHAOSCHAIN_BOOTNODES",
+        value_delimiter = ','
+    )]
+    pub bootnodes: Vec<String>,
+
+    /// OpenAI API key, best left to the environment
+    #[arg(long, env = "OPENAI_API_KEY", hide_env_values = true)]
+    pub openai_api_key: Option<String>,
+
+    /// Anonymous validators, on top of the personas
+    #[arg(long, env = "CHAOSCHAIN_VALIDATORS")]
+    pub validators: Option<u32>,
+
+    /// Anonymous producers, on top of the personas
+    #[arg(long, env = "CHAOSCHAIN_PRODUCERS")]
+    pub producers: Option<u32>,
+
+    /// Cast of named agents
+    #[arg(long, value_name = "FILE", env = "CHAOSCHAIN_PERSONAS")]
+    pub personas: Option<String>,
+
+    /// Serve the web interface
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        default_missing_value = "true",
+        env = "CHAOSCHAIN_WEB"
+    )]
+    pub web: Option<bool>,
+
+    /// Let validators deliberate through the LLM
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        default_missing_value = "true",
+        env = "CHAOSCHAIN_LLM"
+    )]
+    pub llm: Option<bool>,
+
+    /// Validator seats played by humans
+    #[arg(long, env = "CHAOSCHAIN_HUMANS")]
+    pub humans: Option<u32>,
+
+    /// Token that authenticates human votes
+    #[arg(long, env = "CHAOSCHAIN_HUMAN_TOKEN", hide_env_values = true)]
+    pub human_token: Option<String>,
+
+    /// Conditions on every simulated link between the agents
+    #[arg(long, value_name = "CONDITIONS", env = "CHAOSCHAIN_NETSIM")]
+    pub netsim: Option<LinkConditions>,
+
+    /// Drama shards run beside the main chain
+    #[arg(long, value_name = "COUNT", env = "CHAOSCHAIN_SHARDS")]
+    pub shards: Option<u16>,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum AgentCommand {
+    /// Run one personality against synthetic proposals, without network or
+    /// consensus, and report how it decides
+    Simulate {
+        /// Persona from the personas file to start from
+        #[arg(long)]
+        persona: Option<String>,
+
+        /// Personas file (default: `personas.toml`)
+        #[arg(long, value_name = "FILE")]
+        personas: Option<String>,
+
+        /// Archetype (Lawful, Chaotic, Greedy, ...)
+        #[arg(long)]
+        personality: Option<AgentPersonality>,
+
+        #[arg(long)]
+        chaos_level: Option<u8>,
+
+        #[arg(long)]
+        loyalty: Option<u8>,
+
+        #[arg(long)]
+        greed: Option<u8>,
+
+        #[arg(long)]
+        verbosity: Option<u8>,
+
+        #[arg(long)]
+        attention_span: Option<u8>,
+
+        /// Let a model decide, as MODEL[@API_BASE] (uses OPENAI_API_KEY)
+        #[arg(long, value_name = "SPEC")]
+        model: Option<ModelSpec>,
+
+        /// Number of synthetic proposals
+        #[arg(long, default_value_t = 200)]
+        proposals: u32,
+
+        /// Seed for reproducible runs
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List the agents a running node runs
+    List {
+        /// Print them as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show an agent's personality and traits, what it remembers and how
+    /// its epochs went
+    Show {
+        id: String,
+
+        /// Print it as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Hot-swap an agent's personality. What is left out stays as it is.
+    SetPersonality {
+        id: String,
+
+        /// Archetype (Lawful, Chaotic, Greedy, ...)
+        #[arg(long)]
+        personality: Option<AgentPersonality>,
+
+        #[arg(long)]
+        chaos_level: Option<u8>,
+
+        #[arg(long)]
+        loyalty: Option<u8>,
+
+        #[arg(long)]
+        greed: Option<u8>,
+
+        #[arg(long)]
+        verbosity: Option<u8>,
+
+        #[arg(long)]
+        attention_span: Option<u8>,
+
+        /// Replaces the persona line of the agent's prompts
+        #[arg(long)]
+        prompt: Option<String>,
+
+        /// Emoji or image URL the UI shows for the agent
+        #[arg(long)]
+        avatar: Option<String>,
+    },
+
+    /// Take an agent out of the network until it is resumed
+    Pause { id: String },
+
+    /// Bring a paused agent back
+    Resume { id: String },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum PeersCommand {
+    /// List the peers with their score, sessions and traffic
+    List {
+        /// Print them as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Let a peer in by an address ending in its peer id, e.g.
+    /// `/ip4/203.0.113.7/tcp/4001/p2p/12D3KooW...`, or by its hex encoded
+    /// public key
+    Add {
+        peer: String,
+
+        /// Name the peer goes by
+        #[arg(long)]
+        name: String,
+    },
+
+    /// Remove a peer, which may register again unless banned
+    Remove { id: String },
+
+    /// Suspend a peer and refuse its key from now on
+    Ban { id: String },
+
+    /// Let a banned or suspended peer back in
+    Unban { id: String },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum TestnetCommand {
+    /// Generate the testnet unless the directory holds one, start its
+    /// nodes and run them until Ctrl-C or one exits
+    Up {
+        /// Validator nodes
+        #[arg(long, default_value_t = 4)]
+        validators: u32,
+
+        /// Producer nodes
+        #[arg(long, default_value_t = 1)]
+        producers: u32,
+
+        /// Directory of the testnet
+        #[arg(long, value_name = "DIR", default_value = "testnet")]
+        dir: String,
+
+        #[arg(long, default_value = "chaoschain-testnet")]
+        chain_id: String,
+
+        /// Ports are handed out from here up, skipping those taken
+        #[arg(long, default_value_t = 4100)]
+        base_port: u16,
+
+        /// Run the nodes as tasks of this process rather than as processes
+        /// of their own
+        #[arg(long)]
+        in_process: bool,
+
+        /// Serve the web UI of every node
+        #[arg(long)]
+        web: bool,
+
+        /// Generate the testnet anew, removing the one in the directory
+        #[arg(long)]
+        fresh: bool,
+
+        /// Keep the nodes' output to their logs
+        #[arg(long)]
+        quiet: bool,
+    },
+
+    /// Shut down the nodes of a testnet started from another terminal
+    Down {
+        /// Directory of the testnet
+        #[arg(long, value_name = "DIR", default_value = "testnet")]
+        dir: String,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ConfigCommand {
+    /// Write a commented config file for a node of the role
+    Init {
+        #[arg(long, value_enum, default_value_t = config_file::Role::Validator)]
+        role: config_file::Role,
+
+        /// File to write
+        #[arg(long, value_name = "FILE", default_value = "chaoschain.toml")]
+        out: String,
+
+        /// Replace the file when it exists
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Check a config file and say where each mistake is, keys the node
+    /// would ignore included
+    Validate {
+        /// File to check (default: the one given with `--config`, or
+        /// `chaoschain.toml`)
+        #[arg(value_name = "FILE")]
+        file: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum KeysCommand {
+    /// Make a signing key and print its address, the hex encoded public key
+    New {
+        /// Name to keep it under
+        name: String,
+
+        /// Derive it from a new mnemonic, printed to standard error to be
+        /// written down
+        #[arg(long)]
+        mnemonic: bool,
+
+        /// EIP-2334 path of the key in the mnemonic's tree, at index 0
+        /// where a step is `i` (default: `m/12381/1337/i/0`)
+        #[arg(long, requires = "mnemonic")]
+        path: Option<String>,
+
+        /// Signature scheme of the key, ed25519 for transactions and votes
+        #[arg(long, value_enum, default_value_t = KeyScheme::Ed25519)]
+        scheme: KeyScheme,
+    },
+
+    /// Derive a key again from its mnemonic, read from standard input
+    Recover {
+        /// Name to keep it under
+        name: String,
+
+        /// Which key of the mnemonic, the `i` step of its path
+        #[arg(long, default_value_t = 0)]
+        index: u32,
+
+        /// EIP-2334 path of the key, the step that is `i` taking the index
+        /// (default: `m/12381/1337/i/0`)
+        #[arg(long)]
+        path: Option<String>,
+
+        /// Signature scheme of the key
+        #[arg(long, value_enum, default_value_t = KeyScheme::Ed25519)]
+        scheme: KeyScheme,
+    },
+
+    /// Keep many keys of a mnemonic, read from standard input, as
+    /// `<prefix>-<index>`: one mnemonic to back up for every agent
+    Derive {
+        /// Names are this, a dash and the index
+        prefix: String,
+
+        /// How many keys
+        #[arg(long, default_value_t = 1)]
+        count: u32,
+
+        /// Index of the first key
+        #[arg(long, default_value_t = 0)]
+        from: u32,
+
+        /// EIP-2334 path of the keys, the step that is `i` taking each index
+        /// (default: `m/12381/1337/i/0`, `m/12381/3600/i/0/0` for the keys
+        /// of Ethereum validators)
+        #[arg(long)]
+        path: Option<String>,
+
+        /// Derive them from a new mnemonic, printed to standard error to
+        /// be written down
+        #[arg(long)]
+        mnemonic: bool,
+
+        /// Signature scheme of the keys
+        #[arg(long, value_enum, default_value_t = KeyScheme::Ed25519)]
+        scheme: KeyScheme,
+    },
+
+    /// Keep a key exported elsewhere, as an encrypted key file or a hex
+    /// encoded secret
+    Import {
+        /// Name to keep it under
+        name: String,
+
+        /// File to read, instead of standard input
+        file: Option<String>,
+
+        /// Signature scheme of a hex encoded secret, key files name theirs
+        #[arg(long, value_enum, default_value_t = KeyScheme::Ed25519)]
+        scheme: KeyScheme,
+    },
+
+    /// Write a key out encrypted, for a backup or another keystore
+    Export {
+        name: String,
+
+        /// File to write, instead of standard output
+        #[arg(long, value_name = "FILE")]
+        out: Option<String>,
+    },
+
+    /// List the keys with their schemes and addresses
+    List,
+
+    /// Encrypt the unencrypted keys with the password, and rewrite those
+    /// encrypted before EIP-2335, as EIP-2335 keystores
+    Migrate,
+
+    /// Sign a message with a key, under its scheme, and print the hex
+    /// encoded signature
+    Sign {
+        name: String,
+
+        /// Message to sign, read from standard input when left out or `-`
+        message: Option<String>,
+
+        /// The message is hex encoded bytes rather than text
+        #[arg(long)]
+        hex: bool,
+    },
+
+    /// Check a signature, failing when it does not verify
+    Verify {
+        /// Hex encoded public key, or the name of a key of the keystore
+        signer: String,
+
+        /// Hex encoded signature
+        signature: String,
+
+        /// Message signed, read from standard input when left out or `-`
+        message: Option<String>,
+
+        /// The message is hex encoded bytes rather than text
+        #[arg(long)]
+        hex: bool,
+
+        /// Signature scheme, else told by the length of the public key
+        #[arg(long, value_enum)]
+        scheme: Option<KeyScheme>,
+    },
+
+    /// Prove the VRF output of an input with an ed25519 key, printing the
+    /// proof, and the output on standard error
+    VrfProve {
+        name: String,
+
+        /// Input, read from standard input when left out or `-`
+        input: Option<String>,
+
+        /// The input is hex encoded bytes rather than text
+        #[arg(long)]
+        hex: bool,
+    },
+
+    /// Check a VRF proof, printing its output, failing when it does not
+    /// verify
+    VrfVerify {
+        /// Hex encoded public key, or the name of a key of the keystore
+        prover: String,
+
+        /// Hex encoded proof
+        proof: String,
+
+        /// Input, read from standard input when left out or `-`
+        input: Option<String>,
+
+        /// The input is hex encoded bytes rather than text
+        #[arg(long)]
+        hex: bool,
+    },
+
+    /// Print the address of a key of a hardware wallet, and show it on the
+    /// device to check
+    Ledger {
+        /// Which key of the device, the last part but one of its path
+        /// `m/12381'/1337'/<index>'/0'`
+        #[arg(long, default_value_t = 0)]
+        index: u32,
+
+        /// HID device, a `/dev/hidraw*` (default: the only Ledger plugged
+        /// in)
+        #[arg(long, value_name = "PATH")]
+        device: Option<String>,
+
+        /// Show the address on the device too, for it to be approved there
+        #[arg(long)]
+        confirm: bool,
+    },
+}
+
+/// Signature scheme of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum KeyScheme {
+    Ed25519,
+    Secp256k1,
+    Bls12381,
+}
+
+/// What signs: a key of the keystore, or one that never leaves a hardware
+/// wallet
+#[derive(Debug, Clone, clap::Args)]
+#[group(skip)]
+#[command(group(clap::ArgGroup::new("signer").required(true).args(["key", "ledger"])))]
+pub struct SignerArgs {
+    /// Keystore key to sign with, or a key of the config's `[signers]`
+    #[arg(long)]
+    pub key: Option<String>,
+
+    /// Sign on a Ledger, or another HID device running the ChaosChain app,
+    /// after approving there
+    #[arg(long)]
+    pub ledger: bool,
+
+    /// Which key of the device, with `--ledger`
+    #[arg(long, default_value_t = 0)]
+    pub index: u32,
+
+    /// HID device, a `/dev/hidraw*`, with `--ledger` (default: the only
+    /// Ledger plugged in)
+    #[arg(long, value_name = "PATH")]
+    pub device: Option<String>,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum TxCommand {
+    /// Sign a transaction and submit it over JSON-RPC
+    Send {
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        /// What the transaction carries, read from standard input when left
+        /// out or `-`
+        payload: Option<String>,
+
+        /// The payload is hex encoded bytes rather than text
+        #[arg(long)]
+        hex: bool,
+
+        /// Nonce to send with, instead of the next one the node expects
+        #[arg(long)]
+        nonce: Option<u64>,
+
+        /// Wait until the transaction is in a block
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds to wait for inclusion
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token with the tx-submit scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Move a validator to a new key: the key it signs with now sends the
+    /// rotation, which the new key signs too. The old key still votes for
+    /// the rotation grace of the genesis once the block carrying it is final.
+    RotateKey {
+        /// The validator's key now
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        /// Name of the validator in the genesis
+        #[arg(long)]
+        validator: String,
+
+        /// Keystore key, or key of the config's `[signers]`, to move to
+        #[arg(long, value_name = "KEY")]
+        new_key: String,
+
+        /// Nonce to send with, instead of the next one the node expects
+        #[arg(long)]
+        nonce: Option<u64>,
+
+        /// Wait until the rotation is in a block
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds to wait for inclusion
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token with the tx-submit scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Commit to a payload the validators only see once it is revealed:
+    /// the payload goes encrypted, and the secret to reveal it with is
+    /// printed after the hash of the commit
+    Commit {
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        /// What the transaction carries, read from standard input when left
+        /// out or `-`
+        payload: Option<String>,
+
+        /// The payload is hex encoded bytes rather than text
+        #[arg(long)]
+        hex: bool,
+
+        /// Nonce to send with, instead of the next one the node expects
+        #[arg(long)]
+        nonce: Option<u64>,
+
+        /// Wait until the transaction is in a block
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds to wait for inclusion
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token with the tx-submit scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Reveal a payload committed to by the same key, once the commit is
+    /// in a block
+    Reveal {
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        /// Hash of the commit
+        #[arg(long, value_name = "HASH")]
+        commit: String,
+
+        /// Secret `tx commit` printed
+        #[arg(long, value_name = "HEX")]
+        secret: String,
+
+        /// Nonce to send with, instead of the next one the node expects
+        #[arg(long)]
+        nonce: Option<u64>,
+
+        /// Wait until the transaction is in a block
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds to wait for inclusion
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token with the tx-submit scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Put a proposal to the vote of the validators and token holders,
+    /// and print its id, the hash of the transaction
+    Propose {
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        /// What the proposal is for
+        title: String,
+
+        /// What it does once passed: `block-time=10`,
+        /// `finality-threshold=0.75`, `consensus-timeout=40`,
+        /// `rotation-grace=50`, `upgrade=monotonic-time` or
+        /// `spend=ADDRESS:AMOUNT` from the treasury
+        #[arg(long, value_name = "ACTION")]
+        action: String,
+
+        /// Nonce to send with, instead of the next one the node expects
+        #[arg(long)]
+        nonce: Option<u64>,
+
+        /// Wait until the transaction is in a block
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds to wait for inclusion
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token with the tx-submit scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Vote on a proposal, for it unless `--against`. A later ballot of the
+    /// same key replaces this one.
+    Vote {
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        /// Id of the proposal
+        #[arg(long, value_name = "HASH")]
+        proposal: String,
+
+        #[arg(long)]
+        against: bool,
+
+        /// Nonce to send with, instead of the next one the node expects
+        #[arg(long)]
+        nonce: Option<u64>,
+
+        /// Wait until the transaction is in a block
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds to wait for inclusion
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token with the tx-submit scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Lock balance as stake, with a validator to delegate to it or with
+    /// the signer's own key to be one
+    Bond {
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        amount: u64,
+
+        /// Hex encoded key of the validator, the signer's own when left out
+        #[arg(long, value_name = "KEY")]
+        validator: Option<String>,
+
+        /// Nonce to send with, instead of the next one the node expects
+        #[arg(long)]
+        nonce: Option<u64>,
+
+        /// Wait until the transaction is in a block
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds to wait for inclusion
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token with the tx-submit scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Take stake back from a validator, locked for the unbonding period
+    /// of the network before it returns to the balance
+    Unbond {
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        amount: u64,
+
+        /// Hex encoded key of the validator, the signer's own when left out
+        #[arg(long, value_name = "KEY")]
+        validator: Option<String>,
+
+        /// Nonce to send with, instead of the next one the node expects
+        #[arg(long)]
+        nonce: Option<u64>,
+
+        /// Wait until the transaction is in a block
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds to wait for inclusion
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token with the tx-submit scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Report that a validator misbehaved, to have its stake slashed
+    Report {
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        /// File with the evidence as JSON, the validator's `key` and the
+        /// `misbehavior`, read from standard input when left out or `-`
+        evidence: Option<String>,
+
+        /// Nonce to send with, instead of the next one the node expects
+        #[arg(long)]
+        nonce: Option<u64>,
+
+        /// Wait until the transaction is in a block
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds to wait for inclusion
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+
+        /// Node web API
+        #[arg(long, default_value = "http://127.0.0.1:3000")]
+        node: String,
+
+        /// API token with the tx-submit scope, when the node asks for one
+        /// (else `CHAOSCHAIN_API_KEY`)
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+
+    /// Sign a transaction without sending it, and print it hex encoded for
+    /// `chain_sendRawTransaction`
+    Sign {
+        #[command(flatten)]
+        signer: SignerArgs,
+
+        /// What the transaction carries, read from standard input when left
+        /// out or `-`
+        payload: Option<String>,
+
+        /// The payload is hex encoded bytes rather than text
+        #[arg(long)]
+        hex: bool,
+
+        /// Nonce of the transaction, the number the sender sent before
+        #[arg(long)]
+        nonce: u64,
+    },
+}
+
+/// A human's verdict on a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Verdict {
+    Approve,
+    Reject,
+}
+
+/// How a simulated delay is spread around the latency
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyDistribution {
+    /// Anywhere within the jitter of the latency
+    #[default]
+    Uniform,
+    /// Normally, with the jitter as standard deviation
+    Normal,
+}
+
+/// Conditions on a simulated link between two agents of the demo
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinkConditions {
+    /// Mean delay of a message, in milliseconds
+    pub latency_ms: u64,
+    /// Spread of the delay, in milliseconds
+    pub jitter_ms: u64,
+    pub distribution: LatencyDistribution,
+    /// Share of the messages lost, from 0 to 1
+    pub loss: f64,
+    /// Share of the messages held back, so that later ones overtake them
+    pub reorder: f64,
+}
+
+impl LinkConditions {
+    /// Whether the link delivers everything at once
+    pub fn is_perfect(&self) -> bool {
+        self.latency_ms == 0 && self.jitter_ms == 0 && self.loss == 0.0 && self.reorder == 0.0
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, share) in [("loss", self.loss), ("reorder", self.reorder)] {
+            if !(0.0..=1.0).contains(&share) {
+                anyhow::bail!("{} is {}, it goes from 0 to 1", name, share);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for LinkConditions {
+    type Err = anyhow::Error;
+
+    /// Parse `key=value` pairs separated by commas. Keys are `latency` and
+    /// `jitter` in milliseconds, `loss` and `reorder` from 0 to 1, and
+    /// `distribution` (uniform or normal).
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut conditions = Self::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Expected key=value, got '{}'", pair))?;
+            let invalid = |e: &dyn std::fmt::Display| anyhow::anyhow!("Invalid {}: {}", key, e);
+            match key.trim() {
+                "latency" => conditions.latency_ms = value.parse().map_err(|e| invalid(&e))?,
+                "jitter" => conditions.jitter_ms = value.parse().map_err(|e| invalid(&e))?,
+                "loss" => conditions.loss = value.parse().map_err(|e| invalid(&e))?,
+                "reorder" => conditions.reorder = value.parse().map_err(|e| invalid(&e))?,
+                "distribution" => {
+                    conditions.distribution = match value {
+                        "uniform" => LatencyDistribution::Uniform,
+                        "normal" => LatencyDistribution::Normal,
+                        _ => return Err(invalid(&"expected uniform or normal")),
+                    }
+                }
+                _ => anyhow::bail!("Unknown link condition '{}'", key),
+            }
+        }
+        conditions.validate()?;
+        Ok(conditions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_agent_profiles_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            web_port = 4000
+            grpc_port = 50051
+            metrics_port = 9615
+
+            [agents.validator-1]
+            personality = "Greedy"
+            greed = 10
+            loyalty = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.web_port, 4000);
+        assert_eq!(config.grpc_port, Some(50051));
+        assert_eq!(config.metrics_port, Some(9615));
+        assert_eq!(config.data_dir, "data");
+        let profile = &config.agents["validator-1"];
+        assert_eq!(profile.personality, Some(AgentPersonality::Greedy));
+        assert_eq!(profile.traits.greed, Some(10));
+        assert_eq!(profile.traits.chaos_level, None);
+    }
+
+    #[test]
+    fn test_network_peers_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            listen = ["/ip4/0.0.0.0/udp/4001/quic-v1"]
+            bootnodes = ["/ip4/203.0.113.7/tcp/4001"]
+            static_peers = ["/dns4/validator-2.example.org/tcp/4001"]
+            "#,
+        )
+        .unwrap();
+        let discovery = config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .unwrap();
+        assert_eq!(discovery.bootstrap.len(), 1);
+        assert_eq!(discovery.static_peers.len(), 1);
+        assert_eq!(
+            discovery.listen,
+            vec!["/ip4/0.0.0.0/udp/4001/quic-v1".parse().unwrap()]
+        );
+
+        let config: Config =
+            toml::from_str("[network]\nstatic_peers = [\"not an address\"]").unwrap();
+        assert!(config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_sentries_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            sentries = ["/ip4/10.0.0.2/tcp/4001/p2p/12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA"]
+            private_peers = ["12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA"]
+            "#,
+        )
+        .unwrap();
+        let discovery = config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .unwrap();
+        assert_eq!(discovery.sentry.sentries.len(), 1);
+        assert_eq!(
+            discovery.sentry.private_peers[0].to_string(),
+            "12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA"
+        );
+
+        let config: Config = toml::from_str("[network]\nprivate_peers = [\"not a peer\"]").unwrap();
+        assert!(config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_connection_limits_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            max_inbound = 60
+            eviction = "reject"
+            "#,
+        )
+        .unwrap();
+        let defaults = chaoschain_p2p::discovery::DiscoveryConfig::default();
+        let max_outbound = defaults.connections.max_outbound;
+        let discovery = config.network.apply(defaults).unwrap();
+        assert_eq!(discovery.connections.max_inbound, 60);
+        assert_eq!(discovery.connections.max_outbound, max_outbound);
+        assert_eq!(
+            discovery.connections.eviction,
+            chaoschain_p2p::slots::EvictionPolicy::Reject
+        );
+
+        assert!(toml::from_str::<Config>("[network]\neviction = \"random\"").is_err());
+    }
+
+    #[test]
+    fn test_snapshot_sync_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            snapshot_sync = true
+            snapshot_requests = 4
+            "#,
+        )
+        .unwrap();
+        let snapshots = config.network.snapshot_sync().unwrap();
+        assert_eq!(snapshots.max_requests, 4);
+        assert_eq!(
+            snapshots.per_peer_requests,
+            chaoschain_p2p::snapshots::SnapshotConfig::default().per_peer_requests
+        );
+        assert!(Config::default().network.snapshot_sync().is_none());
+    }
+
+    #[test]
+    fn test_bandwidth_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            peer_upload = 1_000_000
+            total_download = 8_000_000
+            "#,
+        )
+        .unwrap();
+        let bandwidth = config.network.bandwidth();
+        assert_eq!(bandwidth.peer_upload, Some(1_000_000));
+        assert_eq!(bandwidth.total_download, Some(8_000_000));
+        assert_eq!(bandwidth.peer_download, None);
+        assert_eq!(bandwidth.total_upload, None);
+    }
+
+    #[test]
+    fn test_overlay_validators_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            overlay_validators = [
+                "/ip4/10.0.0.2/tcp/4001/p2p/12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA",
+            ]
+            "#,
+        )
+        .unwrap();
+        let overlay = config.network.overlay().unwrap();
+        assert_eq!(overlay.validators.len(), 1);
+        assert!(Config::default()
+            .network
+            .overlay()
+            .unwrap()
+            .validators
+            .is_empty());
+
+        // Without its peer id a validator could be anyone
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            overlay_validators = ["/ip4/10.0.0.2/tcp/4001"]
+            "#,
+        )
+        .unwrap();
+        assert!(config.network.overlay().is_err());
+    }
+
+    #[test]
+    fn test_consensus_only_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            consensus_only = true
+            "#,
+        )
+        .unwrap();
+        assert!(config.network.consensus_only);
+        assert!(!Config::default().network.consensus_only);
+    }
+
+    #[test]
+    fn test_relays_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            relays = ["/ip4/203.0.113.9/tcp/4001"]
+            "#,
+        )
+        .unwrap();
+        let discovery = config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .unwrap();
+        assert_eq!(
+            discovery.nat.relays,
+            vec!["/ip4/203.0.113.9/tcp/4001".parse().unwrap()]
+        );
+
+        let config: Config = toml::from_str("[network]\nrelays = [\"nowhere\"]").unwrap();
+        assert!(config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_peer_exchange_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            peer_exchange_interval = 120
+            peer_exchange_accepted = 4
+            "#,
+        )
+        .unwrap();
+        let discovery = config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .unwrap();
+        let defaults = chaoschain_p2p::pex::PexConfig::default();
+        assert!(discovery.pex.enabled);
+        assert_eq!(discovery.pex.interval, Duration::from_secs(120));
+        assert_eq!(discovery.pex.max_accepted, 4);
+        assert_eq!(discovery.pex.max_shared, defaults.max_shared);
+        assert_eq!(discovery.pex.min_interval, defaults.min_interval);
+
+        let config: Config = toml::from_str("[network]\npeer_exchange = false").unwrap();
+        let discovery = config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .unwrap();
+        assert!(!discovery.pex.enabled);
+    }
+
+    #[test]
+    fn test_websocket_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            websocket = "/ip4/0.0.0.0/tcp/8546/ws"
+            websocket_origins = ["https://explorer.example.org"]
+            "#,
+        )
+        .unwrap();
+        let discovery = config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .unwrap();
+        assert_eq!(
+            discovery.websocket.listen,
+            Some("/ip4/0.0.0.0/tcp/8546/ws".parse().unwrap())
+        );
+        assert_eq!(
+            discovery.websocket.allowed_origins,
+            vec!["https://explorer.example.org".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_allowlist_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [network]
+            allowlist = [
+                "12D3KooWD3eckifWpRn9wQpMG9R9hX3sD158z7EqHWmweQAJU5SA",
+                "1ea1a69ab6e1d4e5e2e1a0d7c4f1b2a3c4d5e6f708192a3b4c5d6e7f8091a2b3",
+            ]
+            allowlist_file = "allowlist.txt"
+            "#,
+        )
+        .unwrap();
+        let discovery = config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .unwrap();
+        assert_eq!(discovery.allowlist.peers.len(), 2);
+        assert_eq!(
+            discovery.allowlist.file,
+            Some(std::path::PathBuf::from("allowlist.txt"))
+        );
+        assert!(discovery.allowlist.is_enabled());
+
+        let config: Config = toml::from_str("[network]\nallowlist = [\"nobody\"]").unwrap();
+        assert!(config
+            .network
+            .apply(chaoschain_p2p::discovery::DiscoveryConfig::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_api_tokens_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [api]
+            anonymous = "read-only"
+
+            [[api.tokens]]
+            name = "wallet"
+            token = "w-123"
+            scope = "tx-submit"
+
+            [[api.tokens]]
+            name = "operator"
+            token = "o-456"
+            scope = "admin"
+            "#,
+        )
+        .unwrap();
+        let api = &config.api;
+        api.validate().unwrap();
+        assert_eq!(api.scope_of(Some("w-123")), Some(Scope::TxSubmit));
+        assert_eq!(api.scope_of(Some("o-456")), Some(Scope::Admin));
+        assert_eq!(api.scope_of(Some("guess")), None);
+        assert_eq!(api.scope_of(None), Some(Scope::ReadOnly));
+        assert_eq!(api.name_of("o-456"), Some("operator"));
+        assert!(Scope::Admin > Scope::TxSubmit && Scope::TxSubmit > Scope::ReadOnly);
+
+        let open = ApiConfig::default();
+        assert_eq!(open.scope_of(None), Some(Scope::Admin));
+        let closed = ApiConfig {
+            anonymous: None,
+            ..api.clone()
+        };
+        assert_eq!(closed.scope_of(None), None);
+
+        let twice: Config = toml::from_str(
+            r#"
+            [[api.tokens]]
+            name = "a"
+            token = "same"
+            scope = "admin"
+
+            [[api.tokens]]
+            name = "b"
+            token = "same"
+            scope = "read-only"
+            "#,
+        )
+        .unwrap();
+        assert!(twice.api.validate().is_err());
+        assert!(toml::from_str::<Config>("[api]\nanonymous = \"root\"").is_err());
+    }
+
+    #[test]
+    fn test_banned_senders_from_toml() {
+        let config: Config = toml::from_str(&format!(
+            "[mempool]\nbanned_senders = [\"0x{}\", \"{}\"]",
+            "ab".repeat(32),
+            "01".repeat(32)
+        ))
+        .unwrap();
+        assert_eq!(
+            config.mempool.banned_senders().unwrap(),
+            vec![[0xab; 32], [0x01; 32]]
+        );
+
+        let short = MempoolConfig {
+            banned_senders: vec!["abcd".to_string()],
+        };
+        assert!(short.banned_senders().is_err());
+    }
+
+    #[test]
+    fn test_cors_from_toml() {
+        let config: Config = toml::from_str(
+            "[cors.web]\nallowed_origins = [\"https://dashboard.example.com\"]\nallowed_methods = [\"GET\"]",
+        )
+        .unwrap();
+        config.cors.validate().unwrap();
+        let web = config.cors.web(&config.api);
+        assert_eq!(web.allowed_origins, vec!["https://dashboard.example.com"]);
+        assert_eq!(web.allowed_methods, vec!["GET"]);
+        assert_eq!(web.allowed_headers, CorsPolicy::strict().allowed_headers);
+
+        // Open on a development node, closed once there are tokens
+        assert!(config.cors.metrics(&config.api).allows_any_origin());
+        assert!(!config.cors.admin().allows_any_origin());
+        let api = ApiConfig {
+            tokens: vec![ApiToken {
+                name: "explorer".to_string(),
+                token: "3f9c0e".to_string(),
+                scope: Scope::ReadOnly,
+            }],
+            ..ApiConfig::default()
+        };
+        assert_eq!(config.cors.metrics(&api), CorsPolicy::strict());
+
+        for bad in [
+            "[cors.web]\nallowed_origins = [\"dashboard.example.com\"]",
+            "[cors.web]\nallowed_origins = [\"*\", \"https://a.example\"]",
+            "[cors.admin]\nallowed_methods = [\"get\"]",
+            "[cors.metrics]\nallowed_headers = [\"x api key\"]",
+        ] {
+            let config: Config = toml::from_str(bad).unwrap();
+            assert!(config.cors.validate().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_tls_from_toml() {
+        let config: Config =
+            toml::from_str("[tls]\ncert = \"node.crt\"\nkey = \"node.key\"").unwrap();
+        config.tls.validate().unwrap();
+        assert!(config.tls.is_enabled());
+        assert!(!Config::default().tls.is_enabled());
+
+        for bad in [
+            "[tls]\ncert = \"node.crt\"",
+            "[tls]\nkey = \"node.key\"",
+            "[tls]\ncert = \"node.crt\"\nkey = \"node.key\"\nself_signed = true",
+        ] {
+            let config: Config = toml::from_str(bad).unwrap();
+            assert!(config.tls.validate().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_signers_from_toml() {
+        let config: Config = toml::from_str(
+            "[signers.validator-1]\nbackend = \"pkcs11\"\nmodule = \"/usr/lib/softhsm/libsofthsm2.so\"\ntoken = \"chaoschain\"\nlabel = \"validator-1\"",
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(
+            config.signers["validator-1"],
+            SignerConfig::Pkcs11 {
+                module: "/usr/lib/softhsm/libsofthsm2.so".to_string(),
+                token: Some("chaoschain".to_string()),
+                slot: None,
+                label: "validator-1".to_string(),
+                pin_file: None,
+            }
+        );
+
+        for bad in [
+            "[signers.a]\nbackend = \"pkcs11\"\nmodule = \"\"\nlabel = \"a\"",
+            "[signers.a]\nbackend = \"pkcs11\"\nmodule = \"hsm.so\"\nlabel = \"a\"\ntoken = \"t\"\nslot = 0",
+        ] {
+            let config: Config = toml::from_str(bad).unwrap();
+            assert!(config.validate().is_err(), "{}", bad);
+        }
+        let config: Config = toml::from_str(
+            "[signers.validator-2]\nbackend = \"keystore\"\ndir = \"keys\"\nkey = \"validator-2\"",
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(
+            config.signers["validator-2"],
+            SignerConfig::Keystore {
+                dir: "keys".to_string(),
+                key: "validator-2".to_string(),
+                password_file: None,
+            }
+        );
+        let config: Config =
+            toml::from_str("[signers.a]\nbackend = \"keystore\"\ndir = \"keys\"\nkey = \" \"")
+                .unwrap();
+        assert!(config.validate().is_err());
+        assert!(toml::from_str::<Config>(
+            "[signers.a]\nbackend = \"yubikey\"\nmodule = \"hsm.so\"\nlabel = \"a\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_webhooks_from_toml() {
+        let config: Config = toml::from_str(
+            "[[webhooks]]\nurl = \"https://example.com/hooks\"\nevents = [\"drama\"]\nkeywords = [\"Betrayal\"]\nsecret = \"5b1e\"\n\n\
+             [[webhooks]]\nurl = \"http://127.0.0.1:8080\"\nsecret = \"9c2f\"",
+        )
+        .unwrap();
+        assert_eq!(config.webhooks.len(), 2);
+        let drama = &config.webhooks[0];
+        drama.validate().unwrap();
+        assert!(drama.wants(WebhookEvent::Drama));
+        assert!(!drama.wants(WebhookEvent::BlockFinalized));
+        assert!(drama.matches("A shocking BETRAYAL in block 4"));
+        assert!(!drama.matches("Block 4 approved"));
+        let everything = &config.webhooks[1];
+        everything.validate().unwrap();
+        assert!(everything.wants(WebhookEvent::ValidatorSlashed));
+        assert!(everything.matches("Block 4 approved"));
+
+        for bad in [
+            "[[webhooks]]\nurl = \"example.com\"\nsecret = \"5b1e\"",
+            "[[webhooks]]\nurl = \"https://example.com\"\nsecret = \" \"",
+            "[[webhooks]]\nurl = \"https://example.com\"\nkeywords = [\"\"]\nsecret = \"5b1e\"",
+        ] {
+            let config: Config = toml::from_str(bad).unwrap();
+            assert!(config.webhooks[0].validate().is_err(), "{}", bad);
+        }
+        assert!(toml::from_str::<Config>(
+            "[[webhooks]]\nurl = \"https://example.com\"\nevents = [\"forks\"]\nsecret = \"5b1e\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_admin_from_toml() {
+        let config: Config = toml::from_str("[admin]\nport = 9700\ntoken = \"c1d2e3\"").unwrap();
+        assert_eq!(config.admin.port, Some(9700));
+        config.admin.validate().unwrap();
+        assert!(!config.admin.profiling);
+        assert_eq!(Config::default().admin, AdminConfig::default());
+
+        let config: Config = toml::from_str("[admin]\nport = 9700").unwrap();
+        assert!(config.admin.validate().is_err());
+        let config: Config = toml::from_str("[admin]\nport = 9700\ntoken = \" \"").unwrap();
+        assert!(config.admin.validate().is_err());
+    }
+
+    #[test]
+    fn test_chaos_from_toml() {
+        let config: Config = toml::from_str(
+            "[chaos]\nenabled = true\n\n\
+             [[chaos.faults]]\nfault = \"drop\"\nat = 60\nduration = 30\nmessages = [\"vote\"]\n\n\
+             [[chaos.faults]]\nfault = \"delay\"\nat = 120\nduration = 60\nmessages = [\"block_proposal\"]\ndelay_ms = 3000\nagents = [\"validator-1\"]\n\n\
+             [[chaos.faults]]\nfault = \"crash\"\nat = 300\nduration = 15\nagent = \"validator-2\"",
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert!(config.chaos.enabled);
+        let [drop, delay, crash] = &config.chaos.faults[..] else {
+            panic!("{:?}", config.chaos.faults);
+        };
+        assert_eq!(
+            drop.fault,
+            Fault::Drop {
+                messages: vec!["vote".to_string()],
+                share: 1.0,
+                agents: Vec::new(),
+            }
+        );
+        assert!(!drop.is_active(Duration::from_secs(59)));
+        assert!(drop.is_active(Duration::from_secs(60)));
+        assert!(!drop.is_active(Duration::from_secs(90)));
+        assert_eq!(delay.fault.name(), "delay");
+        assert_eq!(crash.ends(), Duration::from_secs(315));
+        assert!(!ChaosConfig::default().enabled);
+
+        for bad in [
+            "fault = \"drop\"\nat = 0\nduration = 10\nmessages = [\"votes\"]",
+            "fault = \"drop\"\nat = 0\nduration = 10\nmessages = []",
+            "fault = \"drop\"\nat = 0\nduration = 10\nmessages = [\"vote\"]\nshare = 0",
+            "fault = \"delay\"\nat = 0\nduration = 0\nmessages = [\"vote\"]\ndelay_ms = 10",
+            "fault = \"stall_storage\"\nat = 0\nduration = 10\ndelay_ms = 0",
+            "fault = \"crash\"\nat = 0\nduration = 10\nagent = \" \"",
+        ] {
+            let config: Config = toml::from_str(&format!("[[chaos.faults]]\n{}", bad)).unwrap();
+            assert!(config.validate().is_err(), "{}", bad);
+        }
+        assert!(toml::from_str::<Config>(
+            "[[chaos.faults]]\nfault = \"meteor\"\nat = 0\nduration = 10"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_rate_limits_from_toml() {
+        let config: Config = toml::from_str(
+            r#"
+            [api.rate_limits]
+            tx-submit = { per_minute = 30, burst = 5 }
+            "#,
+        )
+        .unwrap();
+        let limits = &config.api.rate_limits;
+        assert!(limits.enabled);
+        assert_eq!(
+            limits.of(Scope::TxSubmit),
+            RateLimit {
+                per_minute: 30,
+                burst: 5
+            }
+        );
+        assert_eq!(
+            limits.of(Scope::ReadOnly),
+            RateLimits::default().of(Scope::ReadOnly)
+        );
+        assert_eq!(limits.chat, RateLimits::default().chat);
+
+        let config: Config =
+            toml::from_str("[api.rate_limits]\nadmin = { per_minute = 0, burst = 1 }").unwrap();
+        assert!(config.api.validate().is_err());
+        let config: Config = toml::from_str(
+            "[api.rate_limits]\nenabled = false\nadmin = { per_minute = 0, burst = 1 }",
+        )
+        .unwrap();
+        config.api.validate().unwrap();
+    }
+
+    #[test]
+    fn test_rpc_max_batch_from_toml() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.api.rpc_max_batch, 100);
+        let config: Config = toml::from_str("[api]\nrpc_max_batch = 500").unwrap();
+        config.api.validate().unwrap();
+        assert_eq!(config.api.rpc_max_batch, 500);
+        let config: Config = toml::from_str("[api]\nrpc_max_batch = 0").unwrap();
+        assert!(config.api.validate().is_err());
+    }
+
+    #[test]
+    fn test_compression_from_toml() {
+        let config: Config = toml::from_str("").unwrap();
+        assert!(config.api.compression);
+        assert_eq!(config.api.compression_min_size, 1024);
+        let config: Config =
+            toml::from_str("[api]\ncompression = false\ncompression_min_size = 256").unwrap();
+        assert!(!config.api.compression);
+        assert_eq!(config.api.compression_min_size, 256);
+    }
+
+    #[test]
+    fn test_personas() {
+        let cast = Personas::parse(
+            r#"
+            [[persona]]
+            name = "validator-greedy"
+            personality = "Greedy"
+            greed = 10
+            model = "llama3@http://localhost:11434/v1"
+            avatar = "🤑"
+
+            [[persona]]
+            name = "producer-bard"
+            role = "producer"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(cast.personas.len(), 2);
+        assert_eq!(cast.personas[0].role, PersonaRole::Validator);
+        assert_eq!(cast.personas[0].profile.traits.greed, Some(10));
+        assert_eq!(cast.personas[1].role, PersonaRole::Producer);
+        Personas::parse(include_str!("../../../docs/examples/personas.toml")).unwrap();
+
+        let error = Personas::parse(
+            r#"
+            [[persona]]
+            name = "validator-1"
+            gred = 10
+            loyalty = 11
+
+            [[persona]]
+            name = "validator-1"
+            model = "@http://localhost:11434/v1"
+            "#,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("unknown key 'gred'"));
+        assert!(error.contains("loyalty is 11"));
+        assert!(error.contains("declared twice"));
+        assert!(error.contains("Missing model name"));
+    }
+
+    #[test]
+    fn test_genesis_spec() {
+        let spec: GenesisSpec =
+            toml::from_str(include_str!("../../../docs/examples/genesis.toml")).unwrap();
+        assert_eq!(spec.chain_id.as_deref(), Some("chaos-testnet-1"));
+        assert_eq!(spec.validators.len(), 3);
+        assert_eq!(spec.hash.as_deref(), Some("sha256"));
+        assert_eq!(spec.forks.len(), 2);
+        assert_eq!(spec.epoch_length, Some(100));
+        assert_eq!(spec.governance.voting_period, 200);
+        assert_eq!(spec.governance.holder_threshold, 0.5);
+        assert_eq!(spec.staking.unbonding_period, 50);
+        assert_eq!(spec.staking.slash_bps, 500);
+        assert_eq!(spec.validators[0].stake, Some(200));
+        assert_eq!(spec.validators[2].balance, Some(0));
+        assert_eq!(spec.allocations[0].balance, 1_000_000);
+        assert!(toml::from_str::<GenesisSpec>("[[validator]]\nname = \"a\"\nstak = 1").is_err());
+    }
+
+    #[test]
+    fn test_link_conditions_from_str() {
+        let conditions: LinkConditions = "latency=200, jitter=50,loss=0.05,distribution=normal"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            conditions,
+            LinkConditions {
+                latency_ms: 200,
+                jitter_ms: 50,
+                distribution: LatencyDistribution::Normal,
+                loss: 0.05,
+                reorder: 0.0,
+            }
+        );
+        assert!("".parse::<LinkConditions>().unwrap().is_perfect());
+        assert!("loss=1.5".parse::<LinkConditions>().is_err());
+        assert!("latency=-1".parse::<LinkConditions>().is_err());
+        assert!("bandwidth=10".parse::<LinkConditions>().is_err());
+    }
+
+    #[test]
+    fn test_node_config_layers() {
+        #[derive(Parser)]
+        struct Run {
+            #[command(flatten)]
+            overrides: NodeOverrides,
+        }
+
+        let mut config: Config = toml::from_str(
+            r#"
+            web_port = 4000
+            grpc_port = 50051
+
+            [network]
+            bootnodes = ["/ip4/10.0.0.1/tcp/9000"]
+
+            [node]
+            validators = 4
+            producers = 2
+            web = true
+            netsim = { latency_ms = 200 }
+            shards = 3
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.node.netsim.unwrap().latency_ms, 200);
+        assert_eq!(config.node.decision_timeout, 20);
+        assert_eq!(config.node.shards, 3);
+
+        let flags = Run::try_parse_from([
+            "run",
+            "--validators",
+            "7",
+            "--web=false",
+            "--bootnode",
+            "/ip4/10.0.0.2/tcp/9000",
+        ])
+        .unwrap();
+        config.apply(flags.overrides);
+        assert_eq!((config.node.validators, config.node.producers), (7, 2));
+        assert!(!config.node.web);
+        assert_eq!(config.web_port, 4000);
+        assert_eq!(config.network.bootnodes, vec!["/ip4/10.0.0.2/tcp/9000"]);
+        assert!(config.validate().is_ok());
+
+        // Every problem is reported at once
+        let flags = Run::try_parse_from(["run", "--web-port", "50051", "--netsim", "loss=0.5"]);
+        config.apply(flags.unwrap().overrides);
+        config.node.decision_timeout = 0;
+        config.admin.port = Some(9700);
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("web_port and grpc_port are both 50051"));
+        assert!(error.contains("[node] decision_timeout is 0"));
+        let lone = NodeConfig {
+            shards: 1,
+            ..NodeConfig::default()
+        };
+        assert!(lone
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("shards is 1"));
+        assert!(error.contains("[admin] The admin API needs a token"));
+    }
 }
@@ -0,0 +1,249 @@
+//! Man pages in roff, one per command, built from the clap definitions:
+//!
+//! ```bash
+//! chaoschain man --out /usr/local/share/man/man1
+//! chaoschain man | man -l -
+//! ```
+
+use clap::{Arg, Command};
+use std::fmt::Write;
+
+/// A man page and the file it goes in, e.g. `chaoschain-agent-show.1`
+pub struct Page {
+    pub file: String,
+    pub roff: String,
+}
+
+/// The page of `cmd` and of each of its subcommands, the root first
+pub fn pages(cmd: &mut Command) -> Vec<Page> {
+    cmd.build();
+    let version = cmd.get_version().unwrap_or_default().to_string();
+    let mut pages = Vec::new();
+    collect(cmd, &[cmd.get_name().to_string()], &version, &mut pages);
+    pages
+}
+
+fn collect(cmd: &Command, path: &[String], version: &str, pages: &mut Vec<Page>) {
+    pages.push(Page {
+        file: format!("{}.1", path.join("-")),
+        roff: render(cmd, path, version),
+    });
+    for sub in cmd.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        // `help` has no more to say than its page would
+        if sub.get_name() == "help" {
+            continue;
+        }
+        let mut path = path.to_vec();
+        path.push(sub.get_name().to_string());
+        collect(sub, &path, version, pages);
+    }
+}
+
+/// Text as roff shows it word for word
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+/// Paragraphs of help, each line safe from being read as a request
+fn paragraphs(out: &mut String, text: &str) {
+    for (index, paragraph) in text.trim().split("\n\n").enumerate() {
+        if index > 0 {
+            out.push_str(".PP\n");
+        }
+        for line in paragraph.lines() {
+            let line = escape(line.trim());
+            if line.starts_with('.') || line.starts_with('\'') {
+                out.push_str("\\&");
+            }
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+}
+
+fn value_name(arg: &Arg) -> String {
+    arg.get_value_names()
+        .and_then(|names| names.first())
+        .map_or_else(
+            || arg.get_id().to_string().to_uppercase(),
+            |name| name.to_string(),
+        )
+}
+
+fn render(cmd: &Command, path: &[String], version: &str) -> String {
+    let root = &path[0];
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        ".TH {} 1 \"\" \"{} {}\"",
+        escape(&path.join("-").to_uppercase()),
+        root,
+        version
+    );
+
+    out.push_str(".SH NAME\n");
+    let about = cmd
+        .get_about()
+        .map(|about| about.to_string())
+        .unwrap_or_default();
+    let _ = writeln!(
+        out,
+        "{} \\- {}",
+        escape(&path.join("-")),
+        escape(about.lines().next().unwrap_or_default())
+    );
+
+    out.push_str(".SH SYNOPSIS\n");
+    let _ = write!(out, "\\fB{}\\fR", escape(&path.join(" ")));
+    let args: Vec<&Arg> = cmd
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set())
+        .collect();
+    if args.iter().any(|arg| !arg.is_positional()) {
+        out.push_str(" [\\fIOPTIONS\\fR]");
+    }
+    for arg in args.iter().filter(|arg| arg.is_positional()) {
+        let name = escape(&value_name(arg));
+        if arg.is_required_set() {
+            let _ = write!(out, " \\fI<{}>\\fR", name);
+        } else {
+            let _ = write!(out, " [\\fI{}\\fR]", name);
+        }
+    }
+    let subcommands: Vec<&Command> = cmd
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+        .collect();
+    if !subcommands.is_empty() {
+        out.push_str(" \\fICOMMAND\\fR");
+    }
+    out.push('\n');
+
+    if let Some(long) = cmd.get_long_about().or(cmd.get_about()) {
+        out.push_str(".SH DESCRIPTION\n");
+        paragraphs(&mut out, &long.to_string());
+    }
+
+    if !args.is_empty() {
+        out.push_str(".SH OPTIONS\n");
+        for arg in &args {
+            out.push_str(".TP\n");
+            let mut names = Vec::new();
+            if let Some(short) = arg.get_short() {
+                names.push(format!("\\fB\\-{}\\fR", short));
+            }
+            if let Some(long) = arg.get_long() {
+                names.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+            }
+            if arg.is_positional() {
+                names.push(format!("\\fI<{}>\\fR", escape(&value_name(arg))));
+            } else if arg.get_action().takes_values() {
+                let last = names.pop().unwrap_or_default();
+                names.push(format!("{} \\fI<{}>\\fR", last, escape(&value_name(arg))));
+            }
+            out.push_str(&names.join(", "));
+            out.push('\n');
+            let help = arg
+                .get_long_help()
+                .or(arg.get_help())
+                .map(|help| help.to_string())
+                .unwrap_or_default();
+            if !help.is_empty() {
+                paragraphs(&mut out, &help);
+            }
+            let mut notes = Vec::new();
+            let possible: Vec<String> = arg
+                .get_possible_values()
+                .iter()
+                .filter(|value| !value.is_hide_set())
+                .map(|value| value.get_name().to_string())
+                .collect();
+            if !possible.is_empty() && arg.get_action().takes_values() {
+                notes.push(format!("possible values: {}", possible.join(", ")));
+            }
+            let defaults: Vec<String> = arg
+                .get_default_values()
+                .iter()
+                .map(|value| value.to_string_lossy().into_owned())
+                .collect();
+            if !defaults.is_empty() && arg.get_action().takes_values() {
+                notes.push(format!("default: {}", defaults.join(", ")));
+            }
+            if let Some(env) = arg.get_env() {
+                notes.push(format!("environment: {}", env.to_string_lossy()));
+            }
+            if !notes.is_empty() {
+                let _ = writeln!(out, ".br\n[{}]", escape(&notes.join("; ")));
+            }
+        }
+    }
+
+    if !subcommands.is_empty() {
+        out.push_str(".SH COMMANDS\n");
+        for sub in &subcommands {
+            let _ = writeln!(
+                out,
+                ".TP\n\\fB{}\\fR\n{}",
+                escape(sub.get_name()),
+                escape(
+                    sub.get_about()
+                        .map(|about| about.to_string())
+                        .unwrap_or_default()
+                        .lines()
+                        .next()
+                        .unwrap_or_default()
+                )
+            );
+        }
+        out.push_str(".SH SEE ALSO\n");
+        let pages: Vec<String> = subcommands
+            .iter()
+            .map(|sub| {
+                format!(
+                    "\\fB{}\\-{}\\fR(1)",
+                    escape(&path.join("-")),
+                    escape(sub.get_name())
+                )
+            })
+            .collect();
+        let _ = writeln!(out, "{}", pages.join(", "));
+    }
+
+    if !version.is_empty() {
+        let _ = writeln!(out, ".SH VERSION\n{}", escape(version));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cli;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_a_page_per_command() {
+        let pages = pages(&mut Cli::command());
+        assert_eq!(pages[0].file, "chaoschain.1");
+        let files: Vec<&str> = pages.iter().map(|page| page.file.as_str()).collect();
+        assert!(files.contains(&"chaoschain-agent-show.1"));
+        assert!(files.contains(&"chaoschain-snapshot-import.1"));
+        assert!(!files.iter().any(|file| file.contains("help")));
+
+        let show = &pages
+            .iter()
+            .find(|page| page.file == "chaoschain-agent-show.1")
+            .unwrap()
+            .roff;
+        assert!(show.starts_with(".TH CHAOSCHAIN\\-AGENT\\-SHOW 1"));
+        assert!(show.contains("\\fBchaoschain agent show\\fR [\\fIOPTIONS\\fR] \\fI<ID>\\fR"));
+        assert!(show.contains("\\fB\\-\\-json\\fR"));
+    }
+
+    #[test]
+    fn test_lines_are_never_requests() {
+        let mut out = String::new();
+        paragraphs(&mut out, ".hidden\n'quoted\nplain \\ text\n\nnext");
+        assert_eq!(out, "\\&.hidden\n\\&'quoted\nplain \\e text\n.PP\nnext\n");
+    }
+}
@@ -10,10 +10,17 @@ use thiserror::Error;
 use tokio::sync::broadcast;
 
 pub mod manager;
+pub mod metrics;
+pub mod partition;
+pub mod rounds;
+pub mod threshold;
 pub mod types;
 pub mod validator;
 
 pub use manager::ConsensusManager;
+pub use partition::{PartitionConfig, PartitionDetector, PartitionStatus};
+pub use rounds::{RoundEvent, RoundRecord, VoteRecord};
+pub use threshold::ThresholdSession;
 pub use types::*;
 pub use validator::Validator;
 
@@ -52,6 +59,25 @@ impl fmt::Display for AgentPersonality {
     }
 }
 
+impl std::str::FromStr for AgentPersonality {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lawful" => Ok(Self::Lawful),
+            "neutral" => Ok(Self::Neutral),
+            "chaotic" => Ok(Self::Chaotic),
+            "memetic" => Ok(Self::Memetic),
+            "greedy" => Ok(Self::Greedy),
+            "dramatic" => Ok(Self::Dramatic),
+            "rational" => Ok(Self::Rational),
+            "emotional" => Ok(Self::Emotional),
+            "strategic" => Ok(Self::Strategic),
+            other => Err(format!("Unknown personality: {}", other)),
+        }
+    }
+}
+
 impl AgentPersonality {
     pub fn random() -> Self {
         let mut rng = rand::thread_rng();
@@ -1,10 +1,23 @@
+use crate::metrics::ConsensusMetrics;
+use crate::rounds::{RoundLog, RoundRecord};
 use crate::types::WebMessage;
 use crate::types::*;
 use crate::ConsensusError;
 use crate::DramaEvent;
 use anyhow::{anyhow, Result};
+use chaoschain_core::beacon::Beacon;
+use chaoschain_core::channel::{self, ChannelMetrics};
+use chaoschain_core::clock::{self, SharedClock};
+use chaoschain_core::fork::ForkSchedule;
+use chaoschain_core::metrics::MetricsRegistry;
+use chaoschain_core::rotation::ValidatorKeys;
+use chaoschain_core::sync::SyncStatus;
+use chaoschain_core::threshold::{finality_message, GroupSignature};
+use chaoschain_core::vote::{SignedExplanation, SignedVote};
 use chaoschain_core::{Block, NetworkEvent, ValidationDecision};
+use chaoschain_crypto::threshold::GroupKey;
 use chaoschain_state::StateStore;
+use ed25519_dalek::VerifyingKey;
 use rand::{rngs::SmallRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -12,7 +25,7 @@ use std::sync::Arc;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::RwLock as TokioRwLock;
-use tracing::info;
+use tracing::{info, warn};
 
 /// Block status in consensus
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -53,6 +66,10 @@ pub struct ConsensusState {
     pub current_block: Option<Block>,
     pub block_status: HashMap<u64, BlockStatus>,
     pub finalized_blocks: Vec<[u8; 32]>,
+    /// Finalized blocks with the signed vote explanations in their metadata
+    pub finalized: HashMap<[u8; 32], Block>,
+    /// Signed explanations collected per block while it is voted on
+    pub explanations: HashMap<[u8; 32], Vec<SignedExplanation>>,
     pub validator_count: usize,
     pub drama_level: u8,
 }
@@ -65,6 +82,21 @@ pub struct ConsensusManager {
     consensus_threshold: f64,
     network_tx: broadcast::Sender<NetworkEvent>,
     drama_events: Arc<TokioRwLock<Vec<DramaEvent>>>,
+    /// Where the node stands against its peers
+    sync: SyncStatus,
+    metrics: ConsensusMetrics,
+    /// Blocks as they are finalized
+    finalized_tx: broadcast::Sender<Block>,
+    /// Blocks as they are finalized, for those that may miss none
+    finalized_queues: Arc<TokioRwLock<Vec<channel::Sender<Block>>>>,
+    /// How the latest rounds went, vote by vote
+    rounds: Arc<RoundLog>,
+    /// Keys the validators of the set vote with, as finalized rotations
+    /// change them. Votes of validators outside it are taken as signed.
+    validator_keys: Arc<TokioRwLock<Option<ValidatorKeys>>>,
+    clock: SharedClock,
+    /// Upgrades of the protocol, which blocks are voted on under
+    forks: ForkSchedule,
 }
 
 impl ConsensusManager {
@@ -79,9 +111,117 @@ impl ConsensusManager {
             consensus_threshold: 0.66, // 2/3 majority
             network_tx,
             drama_events: Arc::new(TokioRwLock::new(Vec::new())),
+            sync: SyncStatus::default(),
+            metrics: ConsensusMetrics::default(),
+            finalized_tx: broadcast::channel(64).0,
+            finalized_queues: Arc::default(),
+            rounds: Arc::default(),
+            validator_keys: Arc::default(),
+            clock: clock::system(),
+            forks: ForkSchedule::default(),
         }
     }
 
+    /// Time rounds and votes by `clock` rather than the system's
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.rounds = Arc::new(RoundLog::with_clock(clock.clone()));
+        self.clock = clock;
+        self
+    }
+
+    /// Vote only on blocks produced under the upgrades of `schedule` active
+    /// at their height, and those governance adopted
+    pub fn with_forks(mut self, schedule: ForkSchedule) -> Self {
+        self.forks = schedule;
+        self
+    }
+
+    /// Expose the consensus metrics through the node's metrics registry
+    pub fn register_metrics(&self, registry: &MetricsRegistry) {
+        self.metrics.register(registry);
+    }
+
+    /// Sit out voting rounds and votes while `sync` says the node is far
+    /// behind
+    pub fn with_sync_status(mut self, sync: SyncStatus) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Take votes of the validators of `keys` only with the keys they vote
+    /// with at the vote's height
+    pub fn with_validator_keys(mut self, keys: ValidatorKeys) -> Self {
+        self.validator_keys = Arc::new(TokioRwLock::new(Some(keys)));
+        self
+    }
+
+    /// The keys of the validator set, as of the latest finalized block
+    pub async fn validator_keys(&self) -> Option<ValidatorKeys> {
+        self.validator_keys.read().await.clone()
+    }
+
+    /// Blocks from now on, once each is finalized
+    pub fn subscribe_finalized(&self) -> broadcast::Receiver<Block> {
+        self.finalized_tx.subscribe()
+    }
+
+    /// Blocks from now on, once each is finalized, none skipped: with
+    /// `capacity` of them waiting, finalizing waits for the receiver
+    pub async fn queue_finalized(
+        &self,
+        queue: &'static str,
+        capacity: usize,
+        metrics: &ChannelMetrics,
+    ) -> channel::Receiver<Block> {
+        let (sender, receiver) = channel::bounded(queue, capacity, metrics);
+        self.finalized_queues.write().await.push(sender);
+        receiver
+    }
+
+    /// Let go of the queues of [`Self::queue_finalized`]: their receivers
+    /// end once they took what is in them
+    pub async fn close_finalized_queues(&self) {
+        self.finalized_queues.write().await.clear();
+    }
+
+    /// Hand a finalized block to the subscribers and the queues
+    async fn finalized(&self, block: &Block) {
+        let _ = self.finalized_tx.send(block.clone());
+        for queue in self.finalized_queues.read().await.iter() {
+            let _ = queue.send(block.clone()).await;
+        }
+    }
+
+    pub fn sync_status(&self) -> &SyncStatus {
+        &self.sync
+    }
+
+    /// Share of the voting stake that has to approve a block
+    pub fn threshold(&self) -> f64 {
+        self.consensus_threshold
+    }
+
+    /// The rounds held for blocks at `height`, in the order they started
+    pub fn rounds_at(&self, height: u64) -> Vec<RoundRecord> {
+        self.rounds.at_height(height)
+    }
+
+    /// Note that `validator` missed its decision deadline on a block and
+    /// fell back to `fallback`
+    pub fn record_timeout(&self, block_hash: &[u8; 32], validator: &str, fallback: &str) {
+        self.rounds.timed_out(block_hash, validator, fallback);
+    }
+
+    fn ensure_synced(&self) -> Result<()> {
+        if self.sync.is_far_behind() {
+            return Err(anyhow!(
+                "Node is {} blocks behind its peers",
+                self.sync.state().lag()
+            ));
+        }
+        Ok(())
+    }
+
     /// Update the consensus threshold
     pub async fn update_consensus_threshold(&self, threshold: u64) {
         let mut state = self.state.write().await;
@@ -90,6 +230,13 @@ impl ConsensusManager {
 
     /// Start voting round for a new block
     pub async fn start_voting_round(&self, block: Block) {
+        if let Err(e) = self.ensure_synced() {
+            warn!(
+                "Not starting a voting round for block {}: {}",
+                block.height, e
+            );
+            return;
+        }
         let mut state = self.state.write().await;
 
         if let Some(current_block) = &state.current_block {
@@ -97,17 +244,33 @@ impl ConsensusManager {
                 return;
             }
         }
+        let parent = state
+            .finalized
+            .get(&block.parent_hash)
+            .map(|parent| parent.timestamp);
+        let forks = self
+            .forks
+            .clone()
+            .with_upgrades(self.state_store.adopted_upgrades());
+        if let Err(e) = forks.at(block.height).check(&block, parent) {
+            warn!("Not starting a voting round: {}", e);
+            return;
+        }
 
         state.votes.clear();
+        let block_hash = block.hash();
+        state.explanations.retain(|hash, _| *hash == block_hash);
         state.current_block = Some(block.clone());
         state
             .block_status
             .insert(block.height, BlockStatus::Pending);
         drop(state);
+        self.metrics.round_started(block_hash, block.height);
+        self.rounds.start(block.height, block_hash);
 
         // Send to network
         let _ = self.network_tx.send(NetworkEvent::BlockProposal {
-            block: block.clone(),
+            block: Box::new(block.clone()),
             drama_level: block.drama_level,
             producer_mood: block.producer_mood.clone(),
             producer_id: block.producer_id.clone(),
@@ -135,12 +298,37 @@ impl ConsensusManager {
         stake: u64,
         block_hash: [u8; 32],
     ) -> Result<bool> {
+        self.count_vote(vote, stake, block_hash, None).await
+    }
+
+    async fn count_vote(
+        &self,
+        vote: ValidationDecision,
+        stake: u64,
+        block_hash: [u8; 32],
+        explanation_hash: Option<[u8; 32]>,
+    ) -> Result<bool> {
+        self.ensure_synced()?;
         let mut votes = self.votes.write().await;
 
         let block_votes = votes.entry(block_hash).or_default();
+        self.metrics.voted(vote.approved);
+        self.rounds.vote(
+            &block_hash,
+            &vote.validator,
+            vote.approved,
+            vote.drama_level,
+            stake,
+            explanation_hash,
+        );
         block_votes.push((vote.clone(), stake));
 
-        let total_stake: u64 = block_votes.iter().map(|(_, s)| s).sum();
+        // Against all the stake bonded where the chain stakes, so that no
+        // key finalizes a block alone but for holding the share needed
+        let total_stake: u64 = self
+            .state_store
+            .total_stake()
+            .unwrap_or_else(|| block_votes.iter().map(|(_, s)| s).sum());
 
         let approval_stake: u64 = block_votes
             .iter()
@@ -149,15 +337,173 @@ impl ConsensusManager {
             .sum();
 
         let consensus_reached =
-            (approval_stake as f64 / total_stake as f64) >= self.consensus_threshold;
+            (approval_stake as f64 / total_stake.max(1) as f64) >= self.consensus_threshold;
 
         if consensus_reached {
+            self.rounds.reached(&block_hash);
             self.trigger_dramatic_event(&block_hash).await?;
         }
 
         Ok(consensus_reached)
     }
 
+    /// Add a vote signed by the validator. Its explanation ends up in the
+    /// metadata of the block once the block is finalized. It counts with
+    /// the stake bonded to the key, and is turned down when nothing is, or
+    /// with `stake` when the chain does not stake.
+    pub async fn add_signed_vote(
+        &self,
+        vote: &SignedVote,
+        key: &VerifyingKey,
+        stake: u64,
+    ) -> Result<bool> {
+        self.ensure_synced()?;
+        vote.verify(key)
+            .map_err(|e| anyhow!("Vote of {} rejected: {}", vote.validator, e))?;
+        if let Some(keys) = self.validator_keys.read().await.as_ref() {
+            if keys.knows(&vote.validator)
+                && !keys.accepts(&vote.validator, key.as_bytes(), vote.height)
+            {
+                return Err(anyhow!(
+                    "Vote of {} rejected: it does not vote with that key at height {}",
+                    vote.validator,
+                    vote.height
+                ));
+            }
+        }
+        let stake = match self.state_store.stake_of(key.as_bytes()) {
+            Some(0) => {
+                return Err(anyhow!(
+                    "Vote of {} rejected: nothing is bonded to its key",
+                    vote.validator
+                ))
+            }
+            Some(bonded) => bonded,
+            None => stake,
+        };
+        let explanation = vote.explanation(key);
+        let explanation_hash = explanation.explanation_hash;
+        {
+            let mut state = self.state.write().await;
+            let explanations = state.explanations.entry(vote.block_hash).or_default();
+            if explanations.iter().any(|e| e.validator == vote.validator) {
+                return Err(anyhow!("{} already voted on this block", vote.validator));
+            }
+            explanations.push(explanation);
+        }
+
+        let consensus_reached = self
+            .count_vote(
+                vote.to_decision(),
+                stake,
+                vote.block_hash,
+                Some(explanation_hash),
+            )
+            .await?;
+        if consensus_reached {
+            self.finalize_with_explanations(&vote.block_hash).await;
+        }
+        Ok(consensus_reached)
+    }
+
+    /// Record the block under vote as finalized, carrying the explanations
+    /// signed so far. Votes that arrive later are added to it.
+    async fn finalize_with_explanations(&self, block_hash: &[u8; 32]) {
+        let mut state = self.state.write().await;
+        let Some(mut block) = state
+            .current_block
+            .clone()
+            .filter(|block| block.hash() == *block_hash)
+        else {
+            return;
+        };
+        block.metadata.explanations = state
+            .explanations
+            .get(block_hash)
+            .cloned()
+            .unwrap_or_default();
+        self.rounds.aggregated(
+            block_hash,
+            block
+                .metadata
+                .explanations
+                .iter()
+                .filter(|e| e.approved)
+                .map(|e| e.validator.clone())
+                .collect(),
+        );
+
+        if !state.finalized.contains_key(block_hash) {
+            info!(
+                "📜 Block {} finalized with {} signed explanations",
+                block.height,
+                block.metadata.explanations.len()
+            );
+            state.finalized_blocks.push(*block_hash);
+            self.metrics.finalized(block_hash);
+            if let Some(keys) = self.validator_keys.write().await.as_mut() {
+                for rotation in keys.apply_block(&block) {
+                    info!(
+                        "🔑 {} votes with 0x{} from now on",
+                        rotation.validator,
+                        hex::encode(rotation.new_key)
+                    );
+                }
+            }
+            self.finalized(&block).await;
+        }
+        state.block_status.insert(
+            block.height,
+            BlockStatus::Finalized {
+                hash: *block_hash,
+                state_root: block.state_root,
+                signatures: block
+                    .metadata
+                    .explanations
+                    .iter()
+                    .filter(|e| e.approved)
+                    .map(|e| e.signature.clone())
+                    .collect(),
+            },
+        );
+        state.finalized.insert(*block_hash, block);
+    }
+
+    /// Add the group signature of a finalized block to its metadata, with
+    /// the beacon drawn from it, once the validators' threshold round for
+    /// it is done
+    pub async fn add_group_signature(
+        &self,
+        block_hash: &[u8; 32],
+        group: &GroupKey,
+        signature: &[u8],
+    ) -> Result<()> {
+        let mut state = self.state.write().await;
+        let block = state
+            .finalized
+            .get_mut(block_hash)
+            .ok_or_else(|| anyhow!("Block 0x{} is not finalized", hex::encode(block_hash)))?;
+        if !group.verify(&finality_message(block.height, block_hash), signature) {
+            return Err(anyhow!(
+                "Group signature of block {} does not hold",
+                block.height
+            ));
+        }
+        let signature = GroupSignature(signature.to_vec());
+        block.metadata.beacon = Some(Box::new(Beacon::derive(
+            block.height,
+            block_hash,
+            &signature,
+        )));
+        block.metadata.group_signature = Some(Box::new(signature));
+        Ok(())
+    }
+
+    /// A finalized block, with the signed explanations of its votes
+    pub async fn get_finalized_block(&self, block_hash: &[u8; 32]) -> Option<Block> {
+        self.state.read().await.finalized.get(block_hash).cloned()
+    }
+
     async fn get_total_stake(&self) -> u64 {
         let votes = self.votes.read().await;
         votes
@@ -198,6 +544,15 @@ impl ConsensusManager {
             .filter(|(v, _)| v.approved)
             .map(|(v, _)| v.signature.to_vec())
             .collect();
+        self.rounds.aggregated(
+            &block.hash(),
+            state
+                .votes
+                .values()
+                .filter(|(v, _)| v.approved)
+                .map(|(v, _)| v.agent_id.clone())
+                .collect(),
+        );
 
         // Generate dramatic finalization message
         let drama_stars = "⭐".repeat(drama_level as usize);
@@ -220,6 +575,8 @@ impl ConsensusManager {
             },
         );
         state.finalized_blocks.push(block.hash());
+        self.metrics.finalized(&block.hash());
+        self.finalized(block).await;
 
         Ok(())
     }
@@ -283,6 +640,17 @@ impl ConsensusManager {
         self.state.read().await.current_block.clone()
     }
 
+    /// Whether a block is still being voted on
+    pub async fn is_voting(&self) -> bool {
+        let state = self.state.read().await;
+        state.current_block.as_ref().is_some_and(|block| {
+            matches!(
+                state.block_status.get(&block.height),
+                Some(BlockStatus::Pending)
+            )
+        })
+    }
+
     /// Get block status
     pub async fn get_block_status(&self, height: u64) -> Option<BlockStatus> {
         self.state.read().await.block_status.get(&height).cloned()
@@ -308,6 +676,7 @@ impl ConsensusManager {
     pub async fn set_validator_count(&self, count: usize) {
         let mut state = self.state.write().await;
         state.validator_count = count;
+        self.metrics.validators(count);
     }
 
     pub async fn get_producer_count(&self) -> usize {
@@ -410,10 +779,7 @@ impl ConsensusManager {
         Ok(InformationVerification {
             is_authentic: true, // For now, assume all info is authentic
             source_reliability: reliability,
-            verification_time: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            verification_time: self.clock.unix_secs(),
         })
     }
 
@@ -557,7 +923,7 @@ impl ConsensusManager {
         let drama_level = rng.gen_range(0..10);
 
         self.network_tx.send(NetworkEvent::BlockProposal {
-            block: block.clone(),
+            block: Box::new(block.clone()),
             drama_level,
             producer_mood: "Chaotic".to_string(),
             producer_id: block.producer_id.clone(),
@@ -679,3 +1045,66 @@ impl DramaticAction for ConsensusRule {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_core::staking::{Staking, Unbond};
+    use chaoschain_core::ChainConfig;
+    use chaoschain_crypto::KeyManagerHandle;
+    use chaoschain_state::StateStoreImpl;
+    use chaoschain_testutil::{BlockBuilder, TransactionBuilder, ValidatorSetBuilder};
+
+    #[tokio::test]
+    async fn test_an_unbonded_validator_does_not_vote() {
+        let set = ValidatorSetBuilder::new()
+            .validators(2)
+            .validator("minnow", 10)
+            .build();
+        let leaving = set.get("validator-1").unwrap();
+        let key_manager = KeyManagerHandle::new();
+        key_manager.inner().register_public_key(
+            &leaving.public_key(),
+            leaving.name.clone(),
+            "validator".to_string(),
+        );
+        let state = StateStoreImpl::new(ChainConfig::default(), key_manager)
+            .with_staking(Staking::from_genesis(&set.genesis()));
+        let unbond = TransactionBuilder::new()
+            .signer(&leaving.key)
+            .payload(
+                Unbond {
+                    validator: leaving.public_key().to_bytes(),
+                    amount: leaving.stake,
+                }
+                .payload(),
+            )
+            .build();
+        let parent = BlockBuilder::new().height(1).transaction(unbond).build();
+        state.apply_block(&parent).unwrap();
+
+        let manager = ConsensusManager::new(Arc::new(state), broadcast::channel(1).0);
+        let block = BlockBuilder::new().parent(&parent).build();
+        manager.start_voting_round(block.clone()).await;
+        let vote = leaving.vote(&block, false);
+        let refused = manager
+            .add_signed_vote(&vote, &leaving.public_key(), 100)
+            .await
+            .unwrap_err();
+        assert!(refused.to_string().contains("nothing is bonded"));
+        // A vote with a sliver of the stake bonded does not finalize
+        let minnow = set.get("minnow").unwrap();
+        let vote = minnow.vote(&block, true);
+        assert!(!manager
+            .add_signed_vote(&vote, &minnow.public_key(), 100)
+            .await
+            .unwrap());
+        // The validator left with most of the stake does
+        let staying = set.get("validator-0").unwrap();
+        let vote = staying.vote(&block, true);
+        assert!(manager
+            .add_signed_vote(&vote, &staying.public_key(), 100)
+            .await
+            .unwrap());
+    }
+}
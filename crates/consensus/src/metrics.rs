@@ -0,0 +1,103 @@
+//! Prometheus metrics of the consensus rounds
+
+use chaoschain_core::metrics::MetricsRegistry;
+use parking_lot::Mutex;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{exponential_buckets, Histogram};
+use std::sync::Arc;
+use std::time::Instant;
+
+type Labels = Vec<(String, String)>;
+
+/// The block under vote and when its round started
+type Round = Option<([u8; 32], Instant)>;
+
+/// Clones share their values
+#[derive(Clone)]
+pub struct ConsensusMetrics {
+    rounds: Counter,
+    height: Gauge,
+    votes: Family<Labels, Counter>,
+    finalized: Counter,
+    time_to_finality: Histogram,
+    validators: Gauge,
+    round: Arc<Mutex<Round>>,
+}
+
+impl Default for ConsensusMetrics {
+    fn default() -> Self {
+        Self {
+            rounds: Counter::default(),
+            height: Gauge::default(),
+            votes: Family::default(),
+            finalized: Counter::default(),
+            time_to_finality: Histogram::new(exponential_buckets(0.25, 2.0, 10)),
+            validators: Gauge::default(),
+            round: Arc::default(),
+        }
+    }
+}
+
+impl ConsensusMetrics {
+    pub fn register(&self, registry: &MetricsRegistry) {
+        registry.register(
+            "chaoschain_consensus_rounds",
+            "Voting rounds started",
+            self.rounds.clone(),
+        );
+        registry.register(
+            "chaoschain_consensus_height",
+            "Height of the latest block put to a vote",
+            self.height.clone(),
+        );
+        registry.register(
+            "chaoschain_consensus_votes",
+            "Votes counted, per decision (approve or reject)",
+            self.votes.clone(),
+        );
+        registry.register(
+            "chaoschain_consensus_finalized_blocks",
+            "Blocks finalized",
+            self.finalized.clone(),
+        );
+        registry.register(
+            "chaoschain_consensus_time_to_finality_seconds",
+            "Time from the start of a voting round to the finalization of its block",
+            self.time_to_finality.clone(),
+        );
+        registry.register(
+            "chaoschain_consensus_validators",
+            "Validators in the validator set",
+            self.validators.clone(),
+        );
+    }
+
+    pub(crate) fn round_started(&self, block_hash: [u8; 32], height: u64) {
+        self.rounds.inc();
+        self.height.set(height as i64);
+        *self.round.lock() = Some((block_hash, Instant::now()));
+    }
+
+    pub(crate) fn voted(&self, approved: bool) {
+        let decision = if approved { "approve" } else { "reject" };
+        self.votes
+            .get_or_create(&vec![("decision".to_string(), decision.to_string())])
+            .inc();
+    }
+
+    pub(crate) fn finalized(&self, block_hash: &[u8; 32]) {
+        self.finalized.inc();
+        if let Some((hash, started)) = *self.round.lock() {
+            if hash == *block_hash {
+                self.time_to_finality
+                    .observe(started.elapsed().as_secs_f64());
+            }
+        }
+    }
+
+    pub(crate) fn validators(&self, count: usize) {
+        self.validators.set(count as i64);
+    }
+}
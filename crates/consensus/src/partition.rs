@@ -0,0 +1,141 @@
+//! Partition detection. A node that stops hearing votes and pitch answers
+//! from a quorum of the validators is most likely cut off from them. Blocks
+//! proposed then could never finalize, so producers hold off until contact
+//! is back.
+
+use chaoschain_core::clock::{self, SharedClock};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct PartitionConfig {
+    /// How long a validator counts as reachable after we last heard it
+    pub window: Duration,
+    /// Share of the validators that has to be reachable
+    pub quorum: f64,
+}
+
+impl Default for PartitionConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(60),
+            quorum: 2.0 / 3.0,
+        }
+    }
+}
+
+/// Whether the node is cut off from the validators
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PartitionStatus {
+    pub partitioned: bool,
+    /// Validators heard from within the window
+    pub reachable: usize,
+    pub validators: usize,
+    /// When the partition started (unix seconds)
+    pub since: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    heard: HashMap<String, Instant>,
+    status: PartitionStatus,
+    /// Set once a quorum was reachable. Until then the node is still
+    /// joining, which is not a partition.
+    armed: bool,
+}
+
+/// Watches vote traffic for a quorum of reachable validators
+#[derive(Debug)]
+pub struct PartitionDetector {
+    config: PartitionConfig,
+    inner: Mutex<Inner>,
+    /// What the start of a partition is dated by
+    clock: SharedClock,
+}
+
+impl Default for PartitionDetector {
+    fn default() -> Self {
+        Self::new(PartitionConfig::default())
+    }
+}
+
+impl PartitionDetector {
+    pub fn new(config: PartitionConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(Inner::default()),
+            clock: clock::system(),
+        }
+    }
+
+    /// Date partitions by `clock` rather than the system's
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// We heard from `validator`
+    pub fn observe(&self, validator: &str, now: Instant) {
+        self.inner.lock().heard.insert(validator.to_string(), now);
+    }
+
+    /// Re-evaluate against a set of `validators`. Returns the new status
+    /// when the node got cut off or reconnected.
+    pub fn check(&self, validators: usize, now: Instant) -> Option<PartitionStatus> {
+        let mut inner = self.inner.lock();
+        let window = self.config.window;
+        inner
+            .heard
+            .retain(|_, heard| now.saturating_duration_since(*heard) <= window);
+        let reachable = inner.heard.len().min(validators);
+        let needed = (validators as f64 * self.config.quorum).ceil() as usize;
+        let quorum = reachable >= needed;
+        inner.armed |= quorum && validators > 0;
+        inner.status.reachable = reachable;
+        inner.status.validators = validators;
+
+        let partitioned = inner.armed && !quorum;
+        if partitioned == inner.status.partitioned {
+            return None;
+        }
+        inner.status.partitioned = partitioned;
+        inner.status.since = partitioned.then(|| self.clock.unix_secs());
+        Some(inner.status.clone())
+    }
+
+    pub fn status(&self) -> PartitionStatus {
+        self.inner.lock().status.clone()
+    }
+
+    pub fn is_partitioned(&self) -> bool {
+        self.inner.lock().status.partitioned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_core::clock::MockClock;
+
+    #[test]
+    fn test_a_partition_is_dated_by_the_clock() {
+        let clock = MockClock::at_unix(1_700_000_000);
+        let detector = PartitionDetector::default().with_clock(clock.shared());
+        let start = Instant::now();
+        for validator in ["validator-0", "validator-1", "validator-2"] {
+            detector.observe(validator, start);
+        }
+        assert_eq!(detector.check(3, start), None);
+
+        // Only one of them heard from since the window began
+        clock.advance(Duration::from_secs(90));
+        let later = start + Duration::from_secs(90);
+        detector.observe("validator-0", later);
+        let status = detector.check(3, later).unwrap();
+        assert!(status.partitioned);
+        assert_eq!(status.reachable, 1);
+        assert_eq!(status.since, Some(1_700_000_090));
+    }
+}
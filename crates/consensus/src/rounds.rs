@@ -0,0 +1,189 @@
+//! A record of the latest voting rounds, vote by vote: who voted what and
+//! when, which votes made it into the aggregate the block was finalized
+//! with, and what got in the way, validators running out of time or a new
+//! proposal taking over before the round finalized.
+
+use chaoschain_core::clock::{self, SharedClock};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+
+/// Rounds kept, oldest dropped first
+const ROUNDS_KEPT: usize = 512;
+
+/// One vote as the round saw it
+#[derive(Debug, Clone)]
+pub struct VoteRecord {
+    pub validator: String,
+    pub approved: bool,
+    pub drama_level: u8,
+    pub stake: u64,
+    /// When it was counted, in unix milliseconds
+    pub received_at: u64,
+    /// Since the round started
+    pub latency_ms: u64,
+    /// SHA-256 of the signed explanation, none for unsigned votes
+    pub explanation_hash: Option<[u8; 32]>,
+    /// Counted after the round had already reached the threshold
+    pub late: bool,
+}
+
+/// What interrupted a round
+#[derive(Debug, Clone)]
+pub enum RoundEvent {
+    /// A validator missed its decision deadline and fell back
+    Timeout {
+        validator: String,
+        /// `approve`, `reject` or `abstain`
+        fallback: String,
+        at: u64,
+    },
+    /// The round was dropped for a new proposal before it finalized
+    ViewChange {
+        to_block: [u8; 32],
+        to_height: u64,
+        at: u64,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct RoundRecord {
+    pub height: u64,
+    pub block_hash: [u8; 32],
+    /// Unix milliseconds
+    pub started_at: u64,
+    /// When the approving stake first reached the threshold
+    pub reached_at: Option<u64>,
+    pub votes: Vec<VoteRecord>,
+    /// Validators whose signed approvals the block was finalized with
+    pub aggregate: Vec<String>,
+    pub events: Vec<RoundEvent>,
+}
+
+impl RoundRecord {
+    pub fn finalized(&self) -> bool {
+        self.reached_at.is_some()
+    }
+}
+
+#[derive(Debug)]
+pub struct RoundLog {
+    rounds: Mutex<VecDeque<RoundRecord>>,
+    /// What rounds and votes are timed by
+    clock: SharedClock,
+}
+
+impl Default for RoundLog {
+    fn default() -> Self {
+        Self::with_clock(clock::system())
+    }
+}
+
+impl RoundLog {
+    pub(crate) fn with_clock(clock: SharedClock) -> Self {
+        Self {
+            rounds: Mutex::default(),
+            clock,
+        }
+    }
+
+    pub(crate) fn start(&self, height: u64, block_hash: [u8; 32]) {
+        let now = self.clock.unix_millis();
+        let mut rounds = self.rounds.lock();
+        if rounds.iter().any(|round| round.block_hash == block_hash) {
+            return;
+        }
+        if let Some(open) = rounds.back_mut().filter(|round| !round.finalized()) {
+            open.events.push(RoundEvent::ViewChange {
+                to_block: block_hash,
+                to_height: height,
+                at: now,
+            });
+        }
+        if rounds.len() >= ROUNDS_KEPT {
+            rounds.pop_front();
+        }
+        rounds.push_back(RoundRecord {
+            height,
+            block_hash,
+            started_at: now,
+            reached_at: None,
+            votes: Vec::new(),
+            aggregate: Vec::new(),
+            events: Vec::new(),
+        });
+    }
+
+    pub(crate) fn vote(
+        &self,
+        block_hash: &[u8; 32],
+        validator: &str,
+        approved: bool,
+        drama_level: u8,
+        stake: u64,
+        explanation_hash: Option<[u8; 32]>,
+    ) {
+        let now = self.clock.unix_millis();
+        let mut rounds = self.rounds.lock();
+        let Some(round) = find(&mut rounds, block_hash) else {
+            return;
+        };
+        round.votes.push(VoteRecord {
+            validator: validator.to_string(),
+            approved,
+            drama_level,
+            stake,
+            received_at: now,
+            latency_ms: now.saturating_sub(round.started_at),
+            explanation_hash,
+            late: round.finalized(),
+        });
+    }
+
+    pub(crate) fn reached(&self, block_hash: &[u8; 32]) {
+        let now = self.clock.unix_millis();
+        let mut rounds = self.rounds.lock();
+        if let Some(round) = find(&mut rounds, block_hash) {
+            round.reached_at.get_or_insert(now);
+        }
+    }
+
+    pub(crate) fn aggregated(&self, block_hash: &[u8; 32], validators: Vec<String>) {
+        let now = self.clock.unix_millis();
+        let mut rounds = self.rounds.lock();
+        if let Some(round) = find(&mut rounds, block_hash) {
+            round.reached_at.get_or_insert(now);
+            round.aggregate = validators;
+        }
+    }
+
+    pub(crate) fn timed_out(&self, block_hash: &[u8; 32], validator: &str, fallback: &str) {
+        let mut rounds = self.rounds.lock();
+        if let Some(round) = find(&mut rounds, block_hash) {
+            round.events.push(RoundEvent::Timeout {
+                validator: validator.to_string(),
+                fallback: fallback.to_string(),
+                at: self.clock.unix_millis(),
+            });
+        }
+    }
+
+    /// The rounds held for blocks at `height`, in the order they started
+    pub fn at_height(&self, height: u64) -> Vec<RoundRecord> {
+        self.rounds
+            .lock()
+            .iter()
+            .filter(|round| round.height == height)
+            .cloned()
+            .collect()
+    }
+}
+
+fn find<'a>(
+    rounds: &'a mut VecDeque<RoundRecord>,
+    block_hash: &[u8; 32],
+) -> Option<&'a mut RoundRecord> {
+    rounds
+        .iter_mut()
+        .rev()
+        .find(|round| round.block_hash == *block_hash)
+}
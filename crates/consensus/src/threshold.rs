@@ -0,0 +1,228 @@
+//! The threshold signing round of a finalized block, as one validator runs
+//! it. Every signer sends the coordinator its nonce commitment; once a
+//! threshold of them are in, the coordinator sends out the commitments it
+//! picked, those picked answer with their partial signatures, and the
+//! coordinator combines them into the group signature and passes it on.
+//! Partials that do not hold name their signer, and the round fails; the
+//! next one is run [`ThresholdSession::excluding`] them.
+
+use anyhow::{anyhow, bail, Result};
+use chaoschain_core::threshold::{finality_message, ThresholdMessage, ThresholdRound};
+use chaoschain_crypto::threshold::{
+    GroupKey, KeyShare, NonceCommitment, PartialSignature, SigningNonces, ThresholdError,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub struct ThresholdSession {
+    height: u64,
+    block_hash: [u8; 32],
+    group: GroupKey,
+    /// Ours, none on a node that only follows the round
+    share: Option<KeyShare>,
+    coordinator: u16,
+    nonces: Option<SigningNonces>,
+    /// Commitments the coordinator has, by signer
+    commitments: BTreeMap<u16, NonceCommitment>,
+    /// Signers whose partial did not hold in an earlier round
+    excluded: BTreeSet<u16>,
+    /// The commitments being signed with
+    package: Option<Vec<NonceCommitment>>,
+    partials: BTreeMap<u16, PartialSignature>,
+    signature: Option<Vec<u8>>,
+}
+
+impl ThresholdSession {
+    pub fn new(
+        group: GroupKey,
+        share: Option<KeyShare>,
+        height: u64,
+        block_hash: [u8; 32],
+        coordinator: u16,
+    ) -> Self {
+        Self {
+            height,
+            block_hash,
+            group,
+            share,
+            coordinator,
+            nonces: None,
+            commitments: BTreeMap::new(),
+            excluded: BTreeSet::new(),
+            package: None,
+            partials: BTreeMap::new(),
+            signature: None,
+        }
+    }
+
+    /// Leave `signers` out of the package, after their partials did not hold
+    pub fn excluding(mut self, signers: impl IntoIterator<Item = u16>) -> Self {
+        self.excluded.extend(signers);
+        self
+    }
+
+    /// The group signature, once the round is done
+    pub fn signature(&self) -> Option<&[u8]> {
+        self.signature.as_deref()
+    }
+
+    fn index(&self) -> Option<u16> {
+        self.share.as_ref().map(|share| share.index)
+    }
+
+    fn is_coordinator(&self) -> bool {
+        self.index() == Some(self.coordinator)
+    }
+
+    fn message(&self, round: ThresholdRound) -> ThresholdMessage {
+        ThresholdMessage {
+            height: self.height,
+            block_hash: self.block_hash,
+            signer: self.index().unwrap_or_default(),
+            round,
+        }
+    }
+
+    /// Our commitment to send, and what follows from it when we coordinate
+    pub fn start(&mut self) -> Vec<ThresholdMessage> {
+        let Some(share) = &self.share else {
+            return Vec::new();
+        };
+        let (nonces, commitment) = share.commit();
+        self.nonces = Some(nonces);
+        let round = ThresholdRound::Commitment(commitment);
+        if self.is_coordinator() {
+            self.commitments.insert(commitment.index, commitment);
+            return self.package_if_ready();
+        }
+        vec![self.message(round)]
+    }
+
+    /// Take a message of the round, returning those to send in answer
+    pub fn handle(&mut self, message: &ThresholdMessage) -> Result<Vec<ThresholdMessage>> {
+        if message.height != self.height || message.block_hash != self.block_hash {
+            return Ok(Vec::new());
+        }
+        message.check().map_err(|e| anyhow!(e.to_string()))?;
+        match &message.round {
+            ThresholdRound::Commitment(commitment) => {
+                if !self.is_coordinator()
+                    || self.package.is_some()
+                    || self.excluded.contains(&commitment.index)
+                {
+                    return Ok(Vec::new());
+                }
+                self.commitments.insert(commitment.index, *commitment);
+                Ok(self.package_if_ready())
+            }
+            ThresholdRound::Package(commitments) => {
+                if message.signer != self.coordinator {
+                    bail!(
+                        "Signer {} sent commitments to sign with, only {} coordinates",
+                        message.signer,
+                        self.coordinator
+                    );
+                }
+                let (Some(share), Some(nonces)) = (&self.share, self.nonces.take()) else {
+                    return Ok(Vec::new());
+                };
+                if !commitments.iter().any(|c| c.index == share.index) {
+                    // Not picked, the nonces stay for a next try
+                    self.nonces = Some(nonces);
+                    return Ok(Vec::new());
+                }
+                let partial = share.sign(
+                    nonces,
+                    &finality_message(self.height, &self.block_hash),
+                    commitments,
+                )?;
+                Ok(vec![self.message(ThresholdRound::Partial(partial))])
+            }
+            ThresholdRound::Partial(partial) => {
+                if !self.is_coordinator() {
+                    return Ok(Vec::new());
+                }
+                self.partials.insert(partial.index, *partial);
+                self.combine_if_ready()
+            }
+            ThresholdRound::Signature(signature) => {
+                if !self
+                    .group
+                    .verify(&finality_message(self.height, &self.block_hash), signature)
+                {
+                    bail!(
+                        "Group signature of block {} from signer {} does not hold",
+                        self.height,
+                        message.signer
+                    );
+                }
+                self.signature = Some(signature.clone());
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// The commitments to sign with, once a threshold are in
+    fn package_if_ready(&mut self) -> Vec<ThresholdMessage> {
+        if self.package.is_some() || self.commitments.len() < usize::from(self.group.threshold) {
+            return Vec::new();
+        }
+        let package: Vec<NonceCommitment> = self
+            .commitments
+            .values()
+            .take(usize::from(self.group.threshold))
+            .copied()
+            .collect();
+        self.package = Some(package.clone());
+        let message = self.message(ThresholdRound::Package(package));
+        // Our own partial, as if the package had come back to us
+        let mut messages = vec![message.clone()];
+        match self.handle(&message) {
+            Ok(own) => {
+                for partial in own {
+                    if let ThresholdRound::Partial(partial) = partial.round {
+                        self.partials.insert(partial.index, partial);
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Could not sign block {}: {}", self.height, e),
+        }
+        messages.extend(self.combine_if_ready().unwrap_or_default());
+        messages
+    }
+
+    /// The group signature, once every partial of the package is in
+    fn combine_if_ready(&mut self) -> Result<Vec<ThresholdMessage>> {
+        let Some(package) = &self.package else {
+            return Ok(Vec::new());
+        };
+        if self.signature.is_some()
+            || package
+                .iter()
+                .any(|c| !self.partials.contains_key(&c.index))
+        {
+            return Ok(Vec::new());
+        }
+        let partials: Vec<PartialSignature> = package
+            .iter()
+            .map(|commitment| self.partials[&commitment.index])
+            .collect();
+        match self.group.combine(
+            &finality_message(self.height, &self.block_hash),
+            package,
+            &partials,
+        ) {
+            Ok(signature) => {
+                self.signature = Some(signature.to_vec());
+                Ok(vec![
+                    self.message(ThresholdRound::Signature(signature.to_vec()))
+                ])
+            }
+            Err(ThresholdError::Culprits(culprits)) => bail!(
+                "Partial signatures of {:?} for block {} do not hold",
+                culprits,
+                self.height
+            ),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
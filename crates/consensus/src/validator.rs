@@ -30,7 +30,7 @@ pub struct AgentPersonality {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ValidatorMessage {
     /// Validate a new block
-    ValidateBlock(Block),
+    ValidateBlock(Box<Block>),
     /// Propose network evolution
     ProposeEvolution(String),
     /// Form alliance with other agents
@@ -157,6 +157,17 @@ impl ValidatorAgent {
                 );
                 Ok(())
             }
+            NetworkEvent::NegotiationPitch { pitch } => {
+                info!(
+                    "📣 Validator {} was pitched block {} by {}: {}",
+                    self.id, pitch.height, pitch.producer, pitch.message
+                );
+                Ok(())
+            }
+            NetworkEvent::NegotiationResponse { .. }
+            | NetworkEvent::AgentThought { .. }
+            | NetworkEvent::Artwork { .. }
+            | NetworkEvent::Rumor { .. } => Ok(()),
         }
     }
 }
@@ -0,0 +1,84 @@
+//! Time blocks of 1 to 4096 transactions take to hash under each hash
+//! function, and to encode and decode as JSON, as gossip and the APIs do,
+//! whole and compact:
+//!
+//! ```bash
+//! cargo bench -p chaoschain-core --bench block
+//! ```
+
+use chaoschain_core::compact::CompactBlock;
+use chaoschain_core::{Block, Transaction};
+use chaoschain_crypto::hash::HashFunction;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// How long each case runs for
+const RUN: Duration = Duration::from_millis(500);
+
+fn main() {
+    println!(
+        "{:>6} {:>12} {:>12} {:>12} {:>12} {:>12} {:>10}",
+        "txs", "sha256 µs", "blake3 µs", "encode µs", "decode µs", "compact µs", "KiB"
+    );
+    for count in [1, 64, 1024, 4096] {
+        let block = block(count);
+        let sha256 = time(|| block.hash_with(HashFunction::Sha256));
+        let blake3 = time(|| block.hash_with(HashFunction::Blake3));
+        let encoded = serde_json::to_vec(&block).expect("a block serializes");
+        let encode = time(|| serde_json::to_vec(black_box(&block)).expect("a block serializes"));
+        let decode =
+            time(|| serde_json::from_slice::<Block>(black_box(&encoded)).expect("a block parses"));
+        let compact = time(|| {
+            serde_json::to_vec(&CompactBlock::new(black_box(&block))).expect("a block serializes")
+        });
+        println!(
+            "{:>6} {:>12.1} {:>12.1} {:>12.1} {:>12.1} {:>12.1} {:>10.1}",
+            count,
+            sha256,
+            blake3,
+            encode,
+            decode,
+            compact,
+            encoded.len() as f64 / 1024.0
+        );
+    }
+}
+
+/// A block of `count` transactions with payloads of a chat message's size
+fn block(count: usize) -> Block {
+    Block {
+        height: 7,
+        parent_hash: [1; 32],
+        transactions: (0..count)
+            .map(|i| Transaction {
+                sender: [(i % 256) as u8; 32],
+                nonce: i as u64,
+                payload: format!("{:0>96}", i).into_bytes().into(),
+                signature: [2; 64],
+                cosignatures: Vec::new(),
+            })
+            .collect(),
+        proposer_sig: [3; 64],
+        state_root: [4; 32],
+        innovation_level: 5,
+        producer_strategy: "chaotic".to_string(),
+        producer_id: "producer-1".to_string(),
+        drama_level: 6,
+        producer_mood: "dramatic".to_string(),
+        timestamp: 1_700_000_000,
+        metadata: Default::default(),
+    }
+}
+
+/// Microseconds a call of `f` takes, on average
+fn time<T>(mut f: impl FnMut() -> T) -> f64 {
+    // Warm the caches and the branch predictor up first
+    black_box(f());
+    let started = Instant::now();
+    let mut calls = 0u32;
+    while started.elapsed() < RUN {
+        black_box(f());
+        calls += 1;
+    }
+    started.elapsed().as_secs_f64() * 1e6 / calls as f64
+}
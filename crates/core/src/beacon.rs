@@ -0,0 +1,141 @@
+//! Randomness of a block, for proposer shuffling, agents' chaos rolls and
+//! whatever applications need a fair draw. It is the hash of the group
+//! signature that finalizes the block: nobody knows it until a threshold
+//! of the validators signed, and anyone with the block checks it after.
+//!
+//! The group signature is a Schnorr signature, which unlike a BLS one is
+//! not the only signature of the block: validators that agree to sign a
+//! block again could draw another beacon for it. It is a coalition of the
+//! threshold that can, where one validator alone cannot.
+
+use crate::threshold::GroupSignature;
+use crate::vote::hex_array;
+use crate::Block;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Domain separator of the beacon hash
+pub const BEACON_DOMAIN: &[u8] = b"chaoschain-beacon-v1";
+
+/// Randomness of a finalized block, hex encoded in JSON
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Beacon(#[serde(with = "hex_array")] pub [u8; 32]);
+
+impl Beacon {
+    /// The beacon of the block at `height`, from the group signature over
+    /// it
+    pub fn derive(height: u64, block_hash: &[u8; 32], signature: &GroupSignature) -> Self {
+        Self(
+            Sha256::new()
+                .chain_update(BEACON_DOMAIN)
+                .chain_update(height.to_be_bytes())
+                .chain_update(block_hash)
+                .chain_update(&signature.0)
+                .finalize()
+                .into(),
+        )
+    }
+
+    /// The beacon `block` is finalized with, none before its group
+    /// signature
+    pub fn of(block: &Block) -> Option<Self> {
+        let signature = block.metadata.group_signature.as_ref()?;
+        Some(Self::derive(block.height, &block.hash(), signature))
+    }
+
+    /// Whether the beacon `block` carries is the one of its group
+    /// signature. A block carrying none holds.
+    pub fn holds(block: &Block) -> bool {
+        block
+            .metadata
+            .beacon
+            .as_deref()
+            .is_none_or(|beacon| Self::of(block).as_ref() == Some(beacon))
+    }
+
+    /// Another value drawn from the beacon for `purpose`, apart from those
+    /// of other purposes
+    pub fn draw(&self, purpose: &[u8]) -> Self {
+        Self(
+            Sha256::new()
+                .chain_update(self.0)
+                .chain_update(purpose)
+                .finalize()
+                .into(),
+        )
+    }
+
+    /// The beacon as a number in [0, 1)
+    pub fn fraction(&self) -> f64 {
+        (self.first() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number from 0 below `bound`, as fair as the beacon is up to a bias
+    /// of `bound` in 2^64
+    pub fn below(&self, bound: u64) -> u64 {
+        ((u128::from(self.first()) * u128::from(bound)) >> 64) as u64
+    }
+
+    /// Put `items` in an order drawn from the beacon, the same order for
+    /// everyone holding it
+    pub fn shuffle<T>(&self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.draw(&(i as u64).to_be_bytes()).below(i as u64 + 1);
+            items.swap(i, j as usize);
+        }
+    }
+
+    fn first(&self) -> u64 {
+        u64::from_be_bytes(self.0[..8].try_into().expect("8 bytes"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_beacon() {
+        let mut block = Block {
+            height: 3,
+            parent_hash: [1; 32],
+            transactions: Vec::new(),
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            producer_id: "producer-1".to_string(),
+            drama_level: 0,
+            producer_mood: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+        assert_eq!(Beacon::of(&block), None);
+        assert!(Beacon::holds(&block));
+
+        let signature = GroupSignature(vec![7; 64]);
+        block.metadata.group_signature = Some(Box::new(signature.clone()));
+        let beacon = Beacon::of(&block).unwrap();
+        assert_eq!(beacon, Beacon::derive(3, &block.hash(), &signature));
+        assert_ne!(
+            beacon,
+            Beacon::derive(3, &block.hash(), &GroupSignature(vec![8; 64]))
+        );
+        block.metadata.beacon = Some(Box::new(beacon));
+        assert!(Beacon::holds(&block));
+        block.metadata.beacon = Some(Box::new(Beacon([0; 32])));
+        assert!(!Beacon::holds(&block));
+
+        assert!((0.0..1.0).contains(&beacon.fraction()));
+        assert!(beacon.below(6) < 6);
+        assert_ne!(beacon.draw(b"proposers"), beacon.draw(b"chaos"));
+        let mut order: Vec<u32> = (0..10).collect();
+        beacon.shuffle(&mut order);
+        let mut again: Vec<u32> = (0..10).collect();
+        beacon.shuffle(&mut again);
+        assert_eq!(order, again);
+        order.sort();
+        assert_eq!(order, (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,218 @@
+//! Channels between the parts of a node, all bounded, so that a consumer
+//! falling behind cannot hold on to ever more memory. What happens when
+//! one does depends on what flows:
+//!
+//! - Drama, the events agents make up, drops the oldest: each subscriber
+//!   of a broadcast channel reads through [`Lossy`], which skips what it
+//!   fell behind on and counts it.
+//! - Consensus, the blocks it finalizes, cannot lose any: [`bounded`]
+//!   channels make whoever sends wait while the consumer catches up.
+//!
+//! Either way the depth of each queue and what it dropped or waited for
+//! go to `/metrics` through [`ChannelMetrics`].
+
+use crate::metrics::MetricsRegistry;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+
+type Labels = Vec<(String, String)>;
+
+/// How the queues of a node fill up. Clones share their values.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelMetrics {
+    depth: Family<Labels, Gauge>,
+    dropped: Family<Labels, Counter>,
+    waits: Family<Labels, Counter>,
+}
+
+impl ChannelMetrics {
+    pub fn register(&self, registry: &MetricsRegistry) {
+        registry.register(
+            "chaoschain_channel_depth",
+            "Messages waiting in a queue, per queue",
+            self.depth.clone(),
+        );
+        registry.register(
+            "chaoschain_channel_dropped",
+            "Messages a consumer fell behind on and skipped, per queue",
+            self.dropped.clone(),
+        );
+        registry.register(
+            "chaoschain_channel_waits",
+            "Sends that waited for room in a queue, per queue",
+            self.waits.clone(),
+        );
+    }
+
+    fn depth(&self, queue: &str, depth: usize) {
+        self.depth.get_or_create(&labels(queue)).set(depth as i64);
+    }
+
+    fn dropped(&self, queue: &str, missed: u64) {
+        self.dropped.get_or_create(&labels(queue)).inc_by(missed);
+    }
+
+    fn waited(&self, queue: &str) {
+        self.waits.get_or_create(&labels(queue)).inc();
+    }
+}
+
+fn labels(queue: &str) -> Labels {
+    vec![("queue".to_string(), queue.to_string())]
+}
+
+/// A channel of `capacity` messages whose sender waits while it is full,
+/// reported as `queue`
+pub fn bounded<T>(
+    queue: &'static str,
+    capacity: usize,
+    metrics: &ChannelMetrics,
+) -> (Sender<T>, Receiver<T>) {
+    let (sender, receiver) = mpsc::channel(capacity.max(1));
+    (
+        Sender {
+            queue,
+            sender,
+            metrics: metrics.clone(),
+        },
+        Receiver {
+            queue,
+            receiver,
+            metrics: metrics.clone(),
+        },
+    )
+}
+
+/// Sends into a [`bounded`] channel
+#[derive(Debug, Clone)]
+pub struct Sender<T> {
+    queue: &'static str,
+    sender: mpsc::Sender<T>,
+    metrics: ChannelMetrics,
+}
+
+impl<T> Sender<T> {
+    /// Send `value`, waiting for room when the queue is full. Fails with
+    /// the value once the receiver is gone.
+    pub async fn send(&self, value: T) -> Result<(), T> {
+        let value = match self.sender.try_send(value) {
+            Ok(()) => {
+                self.report();
+                return Ok(());
+            }
+            Err(mpsc::error::TrySendError::Closed(value)) => return Err(value),
+            Err(mpsc::error::TrySendError::Full(value)) => value,
+        };
+        self.metrics.waited(self.queue);
+        self.sender.send(value).await.map_err(|e| e.0)?;
+        self.report();
+        Ok(())
+    }
+
+    /// Messages waiting
+    pub fn depth(&self) -> usize {
+        self.sender.max_capacity() - self.sender.capacity()
+    }
+
+    fn report(&self) {
+        self.metrics.depth(self.queue, self.depth());
+    }
+}
+
+/// Receives from a [`bounded`] channel
+#[derive(Debug)]
+pub struct Receiver<T> {
+    queue: &'static str,
+    receiver: mpsc::Receiver<T>,
+    metrics: ChannelMetrics,
+}
+
+impl<T> Receiver<T> {
+    /// The next message, none once every sender is gone
+    pub async fn recv(&mut self) -> Option<T> {
+        let value = self.receiver.recv().await;
+        self.metrics.depth(self.queue, self.receiver.len());
+        value
+    }
+}
+
+/// A subscriber of a broadcast channel that skips what it falls behind on,
+/// reported as `queue`
+#[derive(Debug)]
+pub struct Lossy<T> {
+    queue: &'static str,
+    receiver: broadcast::Receiver<T>,
+    metrics: ChannelMetrics,
+}
+
+impl<T: Clone> Lossy<T> {
+    pub fn new(
+        queue: &'static str,
+        receiver: broadcast::Receiver<T>,
+        metrics: &ChannelMetrics,
+    ) -> Self {
+        Self {
+            queue,
+            receiver,
+            metrics: metrics.clone(),
+        }
+    }
+
+    /// The next message still kept, none once the sender is gone
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(value) => {
+                    self.metrics.depth(self.queue, self.receiver.len());
+                    return Some(value);
+                }
+                Err(RecvError::Lagged(missed)) => self.metrics.dropped(self.queue, missed),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_consensus_waits_and_drama_drops() {
+        let metrics = ChannelMetrics::default();
+        let (sender, mut receiver) = bounded("finalized", 2, &metrics);
+        sender.send(1).await.unwrap();
+        sender.send(2).await.unwrap();
+        assert_eq!(sender.depth(), 2);
+        // Full, so the third waits until one is taken
+        let waiting = tokio::spawn({
+            let sender = sender.clone();
+            async move { sender.send(3).await }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(receiver.recv().await, Some(1));
+        waiting.await.unwrap().unwrap();
+        assert_eq!(receiver.recv().await, Some(2));
+        assert_eq!(receiver.recv().await, Some(3));
+
+        let (events, _) = broadcast::channel(2);
+        let mut lossy = Lossy::new("events/web", events.subscribe(), &metrics);
+        for event in 0..5 {
+            events.send(event).unwrap();
+        }
+        assert_eq!(lossy.recv().await, Some(3));
+        assert_eq!(lossy.recv().await, Some(4));
+        drop(events);
+        assert_eq!(lossy.recv().await, None);
+
+        let registry = MetricsRegistry::default();
+        metrics.register(&registry);
+        let body = registry.encode().unwrap();
+        assert!(body.contains("chaoschain_channel_waits_total{queue=\"finalized\"} 1"));
+        assert!(body.contains("chaoschain_channel_dropped_total{queue=\"events/web\"} 3"));
+        assert!(body.contains("chaoschain_channel_depth{queue=\"finalized\"} 0"));
+    }
+}
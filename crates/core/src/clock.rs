@@ -0,0 +1,155 @@
+//! Where the mempool, consensus and agents read the time from, and wait
+//! on it. Nodes run on the [`SystemClock`]. Tests hand them a [`MockClock`]
+//! instead, which stands still until told to move, so that timestamps,
+//! round timeouts and deadlines come out the same on every run:
+//!
+//! ```
+//! use chaoschain_core::clock::{Clock, MockClock};
+//! use std::time::Duration;
+//!
+//! let clock = MockClock::at_unix(1_700_000_000);
+//! clock.advance(Duration::from_secs(90));
+//! assert_eq!(clock.unix_secs(), 1_700_000_090);
+//! ```
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+#[async_trait]
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    /// The time of day
+    fn now(&self) -> SystemTime;
+
+    /// Wait until the clock reads `deadline`, at once if it is past
+    async fn sleep_until(&self, deadline: SystemTime);
+
+    async fn sleep(&self, duration: Duration) {
+        self.sleep_until(self.now() + duration).await
+    }
+
+    fn unix_secs(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    fn unix_millis(&self) -> u64 {
+        self.now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+}
+
+/// A clock as the parts of a node share it
+pub type SharedClock = Arc<dyn Clock>;
+
+/// The system's clock, shared
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+/// The time of the system, and tokio's timers to wait on it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    async fn sleep_until(&self, deadline: SystemTime) {
+        if let Ok(left) = deadline.duration_since(SystemTime::now()) {
+            tokio::time::sleep(left).await;
+        }
+    }
+
+    // Monotonic, unlike the time of day
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// A clock that moves only when it is [advanced](MockClock::advance) or
+/// [set](MockClock::set), waking whoever sleeps until then. Its clones
+/// share its time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<watch::Sender<SystemTime>>,
+}
+
+impl MockClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(watch::channel(now).0),
+        }
+    }
+
+    /// A clock reading `secs` after the unix epoch
+    pub fn at_unix(secs: u64) -> Self {
+        Self::new(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now.send_modify(|now| *now += by);
+    }
+
+    /// Move the clock to `now`, back in time too, which wakes no one
+    pub fn set(&self, now: SystemTime) {
+        self.now.send_replace(now);
+    }
+
+    pub fn shared(&self) -> SharedClock {
+        Arc::new(self.clone())
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(UNIX_EPOCH)
+    }
+}
+
+#[async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.borrow()
+    }
+
+    async fn sleep_until(&self, deadline: SystemTime) {
+        let mut now = self.now.subscribe();
+        // The sender lives as long as the clock
+        let _ = now.wait_for(|now| *now >= deadline).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_clock_wakes_sleepers_when_advanced() {
+        let clock = MockClock::at_unix(1_000);
+        let sleeping = tokio::spawn({
+            let clock = clock.clone();
+            async move { clock.sleep(Duration::from_secs(30)).await }
+        });
+        tokio::task::yield_now().await;
+
+        clock.advance(Duration::from_secs(29));
+        tokio::task::yield_now().await;
+        assert!(!sleeping.is_finished());
+        assert_eq!(clock.unix_millis(), 1_029_000);
+
+        clock.advance(Duration::from_secs(1));
+        sleeping.await.unwrap();
+        assert_eq!(clock.shared().unix_secs(), 1_030);
+
+        // Deadlines already past do not wait
+        clock.sleep_until(UNIX_EPOCH).await;
+    }
+}
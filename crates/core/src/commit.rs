@@ -0,0 +1,392 @@
+//! Transactions whose payload the validators only see once it is in a
+//! block, so that whoever orders a block cannot front-run what it says.
+//! The sender commits first, with a system transaction whose payload is
+//! [`COMMIT_PREFIX`] then a [`Commit`] as JSON: the payload encrypted
+//! under a key of the sender's, and a commitment to key and payload. Once
+//! the commit is in a block, the sender reveals it with another, of
+//! [`REVEAL_PREFIX`] then a [`Reveal`] giving the key. The payload is the
+//! commit's ciphertext decrypted, and has to match the commitment.
+//!
+//! A commit is revealed once, by its sender, in a later block than its
+//! own and at most [`REVEAL_WINDOW`] blocks after; one that is not is
+//! dropped. Whoever checks transactions keeps a [`Commitments`], applying
+//! the blocks to it in order: the state, the mempool's admission over RPC
+//! and `verify-chain`.
+
+use crate::vote::{hex_array, hex_vec};
+use crate::{Block, Error, Transaction};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Domain separator of commitments
+pub const COMMIT_DOMAIN: &[u8] = b"chaoschain-commit-v1";
+
+/// Start of the payload of a commit
+pub const COMMIT_PREFIX: &[u8] = b"chaoschain/commit:";
+
+/// Start of the payload of a reveal
+pub const REVEAL_PREFIX: &[u8] = b"chaoschain/reveal:";
+
+/// Blocks after its own that a commit is revealed within
+pub const REVEAL_WINDOW: u64 = 100;
+
+/// A payload kept from the validators until it is revealed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Commit {
+    /// To the sender, the key and the payload, hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub commitment: [u8; 32],
+    /// The payload encrypted under the key, hex encoded in JSON
+    #[serde(with = "hex_vec")]
+    pub ciphertext: Vec<u8>,
+}
+
+impl Commit {
+    /// Commit `sender` to `payload`, encrypted under `key`. A key is for
+    /// one commit only.
+    pub fn seal(sender: &[u8; 32], key: &[u8; 32], payload: &[u8]) -> Self {
+        Self {
+            commitment: commitment(sender, key, payload),
+            ciphertext: cipher(key)
+                .encrypt(&Nonce::default(), payload)
+                .expect("encryption of a payload in memory"),
+        }
+    }
+
+    /// The payload `key` decrypts, when it is the one committed to by
+    /// `sender`
+    pub fn open(&self, sender: &[u8; 32], key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        let payload = cipher(key)
+            .decrypt(&Nonce::default(), self.ciphertext.as_slice())
+            .map_err(|_| {
+                Error::InvalidTransaction("The key does not decrypt the commit".to_string())
+            })?;
+        if commitment(sender, key, &payload) != self.commitment {
+            return Err(Error::InvalidTransaction(
+                "The payload does not match the commitment".to_string(),
+            ));
+        }
+        Ok(payload)
+    }
+
+    /// The payload of the transaction committing
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = COMMIT_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("a commit serializes"));
+        payload
+    }
+
+    /// The commit `tx` makes, none when it makes none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(COMMIT_PREFIX)?;
+        Some(serde_json::from_slice(record).map_err(|e| {
+            Error::InvalidTransaction(format!("Commit {}: {}", hex::encode(tx.hash()), e))
+        }))
+    }
+}
+
+/// The key of a commit, given once it is in a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reveal {
+    /// Hash of the transaction committing, hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub commit: [u8; 32],
+    /// Hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub key: [u8; 32],
+}
+
+impl Reveal {
+    /// The payload of the transaction revealing
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = REVEAL_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("a reveal serializes"));
+        payload
+    }
+
+    /// The reveal `tx` makes, none when it makes none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(REVEAL_PREFIX)?;
+        Some(serde_json::from_slice(record).map_err(|e| {
+            Error::InvalidTransaction(format!("Reveal {}: {}", hex::encode(tx.hash()), e))
+        }))
+    }
+}
+
+/// A commit in a block, waiting for its reveal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pending {
+    pub sender: [u8; 32],
+    pub commit: Commit,
+    /// Of the block carrying it
+    pub height: u64,
+}
+
+/// The commits of the chain waiting to be revealed, and the payloads of
+/// those revealed, by the hash of the transaction committing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Commitments {
+    pending: HashMap<[u8; 32], Pending>,
+    revealed: HashMap<[u8; 32], Vec<u8>>,
+}
+
+impl Commitments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pending(&self, commit: &[u8; 32]) -> Option<&Pending> {
+        self.pending.get(commit)
+    }
+
+    /// The payload of a revealed commit
+    pub fn revealed(&self, commit: &[u8; 32]) -> Option<&[u8]> {
+        self.revealed.get(commit).map(Vec::as_slice)
+    }
+
+    /// The payload `tx` reveals, when it is a reveal in a block at
+    /// `height`; an error when it is one that does not hold or a commit
+    /// that does not parse
+    pub fn check(&self, tx: &Transaction, height: u64) -> Result<Option<Vec<u8>>, Error> {
+        if let Some(commit) = Commit::from_transaction(tx) {
+            commit?;
+            return Ok(None);
+        }
+        let Some(reveal) = Reveal::from_transaction(tx) else {
+            return Ok(None);
+        };
+        let reveal = reveal?;
+        let invalid = |reason: String| {
+            Error::InvalidTransaction(format!(
+                "Reveal {} of {}: {}",
+                hex::encode(tx.hash()),
+                hex::encode(reveal.commit),
+                reason
+            ))
+        };
+        let pending = match self.pending.get(&reveal.commit) {
+            Some(pending) => pending,
+            None if self.revealed.contains_key(&reveal.commit) => {
+                return Err(invalid("it is revealed already".to_string()))
+            }
+            None => return Err(invalid("no commit waits for it".to_string())),
+        };
+        if pending.sender != tx.sender {
+            return Err(invalid("it is another sender's".to_string()));
+        }
+        if height <= pending.height {
+            return Err(invalid(format!(
+                "it is revealed at height {}, not after the commit at {}",
+                height, pending.height
+            )));
+        }
+        pending
+            .commit
+            .open(&tx.sender, &reveal.key)
+            .map(Some)
+            .map_err(|e| invalid(e.to_string()))
+    }
+
+    /// Take what `other` knows of the commit of hash `commit`, in place of
+    /// what is known here
+    pub fn copy_entry(&mut self, other: &Self, commit: &[u8; 32]) {
+        match other.pending.get(commit) {
+            Some(pending) => self.pending.insert(*commit, pending.clone()),
+            None => self.pending.remove(commit),
+        };
+        match other.revealed.get(commit) {
+            Some(payload) => self.revealed.insert(*commit, payload.clone()),
+            None => self.revealed.remove(commit),
+        };
+    }
+
+    /// Whether every commit and reveal of `block` holds, in order
+    pub fn check_block(&self, block: &Block) -> Result<(), Error> {
+        let mut commitments = self.clone();
+        commitments.expire(block.height);
+        for tx in &block.transactions {
+            commitments.check(tx, block.height)?;
+            commitments.apply_transaction(tx, block.height);
+        }
+        Ok(())
+    }
+
+    /// Take the commit or reveal `tx` makes in a block at `height`,
+    /// returning the hash of the commit and the payload revealed. One that
+    /// does not hold is passed over.
+    pub fn apply_transaction(
+        &mut self,
+        tx: &Transaction,
+        height: u64,
+    ) -> Option<([u8; 32], Vec<u8>)> {
+        match self.check(tx, height) {
+            Ok(Some(payload)) => {
+                let reveal = Reveal::from_transaction(tx)?.ok()?;
+                self.pending.remove(&reveal.commit);
+                self.revealed.insert(reveal.commit, payload.clone());
+                Some((reveal.commit, payload))
+            }
+            Ok(None) => {
+                if let Some(Ok(commit)) = Commit::from_transaction(tx) {
+                    self.pending.insert(
+                        tx.hash(),
+                        Pending {
+                            sender: tx.sender,
+                            commit,
+                            height,
+                        },
+                    );
+                }
+                None
+            }
+            Err(e) => {
+                tracing::warn!("Passing over {}", e);
+                None
+            }
+        }
+    }
+
+    /// Take the commits and reveals of `block`, in order, returning the
+    /// payloads revealed. Commits past their window are dropped first.
+    pub fn apply_block(&mut self, block: &Block) -> Vec<([u8; 32], Vec<u8>)> {
+        self.expire(block.height);
+        block
+            .transactions
+            .iter()
+            .filter_map(|tx| self.apply_transaction(tx, block.height))
+            .collect()
+    }
+
+    /// Drop the commits a block at `height` is past the window of
+    pub fn expire(&mut self, height: u64) {
+        self.pending
+            .retain(|_, pending| height <= pending.height.saturating_add(REVEAL_WINDOW));
+    }
+}
+
+fn commitment(sender: &[u8; 32], key: &[u8; 32], payload: &[u8]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(COMMIT_DOMAIN)
+        .chain_update(sender)
+        .chain_update(key)
+        .chain_update(payload)
+        .finalize()
+        .into()
+}
+
+/// The nonce is fixed, each key encrypting one payload
+fn cipher(key: &[u8; 32]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(key.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(sender: u8, nonce: u64, payload: Vec<u8>) -> Transaction {
+        Transaction {
+            sender: [sender; 32],
+            nonce,
+            payload: payload.into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        }
+    }
+
+    fn block(height: u64, transactions: Vec<Transaction>) -> Block {
+        Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            producer_id: "producer-1".to_string(),
+            drama_level: 0,
+            producer_mood: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_commit_reveal() {
+        let key = [7; 32];
+        let commit = Commit::seal(&[1; 32], &key, b"buy every meme coin");
+        assert!(!commit.ciphertext.windows(4).any(|window| window == b"meme"));
+        assert_eq!(commit.open(&[1; 32], &key).unwrap(), b"buy every meme coin");
+        assert!(commit.open(&[1; 32], &[8; 32]).is_err());
+        assert!(commit.open(&[2; 32], &key).is_err());
+
+        let committing = tx(1, 0, commit.payload());
+        assert!(crate::rotation::is_system(&committing));
+        let reveal = Reveal {
+            commit: committing.hash(),
+            key,
+        };
+        let revealing = tx(1, 1, reveal.payload());
+        let mut commitments = Commitments::new();
+
+        // In the block of the commit, or before it, it does not reveal
+        let same = block(5, vec![committing.clone(), revealing.clone()]);
+        assert!(commitments
+            .check_block(&block(5, vec![revealing.clone()]))
+            .is_err());
+        assert!(commitments.check_block(&same).is_err());
+        commitments.apply_block(&block(5, vec![committing.clone()]));
+        assert!(commitments.pending(&committing.hash()).is_some());
+
+        // Only by its sender
+        let stolen = tx(2, 0, reveal.payload());
+        assert!(commitments.check(&stolen, 6).is_err());
+        let wrong = tx(
+            1,
+            1,
+            Reveal {
+                commit: committing.hash(),
+                key: [8; 32],
+            }
+            .payload(),
+        );
+        assert!(commitments.check(&wrong, 6).is_err());
+        assert_eq!(
+            commitments.check(&revealing, 6).unwrap().as_deref(),
+            Some(&b"buy every meme coin"[..])
+        );
+
+        let revealed = commitments.apply_block(&block(6, vec![revealing.clone()]));
+        assert_eq!(
+            revealed,
+            vec![(committing.hash(), b"buy every meme coin".to_vec())]
+        );
+        assert_eq!(
+            commitments.revealed(&committing.hash()),
+            Some(&b"buy every meme coin"[..])
+        );
+        assert!(commitments.check(&revealing, 7).is_err());
+
+        // Past the window the commit is gone
+        let late = Commit::seal(&[1; 32], &[9; 32], b"too late");
+        let committing = tx(1, 2, late.payload());
+        commitments.apply_block(&block(10, vec![committing.clone()]));
+        let revealing = tx(
+            1,
+            3,
+            Reveal {
+                commit: committing.hash(),
+                key: [9; 32],
+            }
+            .payload(),
+        );
+        assert!(commitments
+            .check_block(&block(10 + REVEAL_WINDOW, vec![revealing.clone()]))
+            .is_ok());
+        assert!(commitments
+            .check_block(&block(11 + REVEAL_WINDOW, vec![revealing]))
+            .is_err());
+    }
+}
@@ -0,0 +1,197 @@
+//! Blocks announced by their header and short transaction ids. Peers usually
+//! hold most of a block's transactions in their mempool already, so only the
+//! ones they miss have to travel.
+
+use crate::{Block, Error, HashedTransaction, Transaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Short id of a transaction within one block
+pub type ShortId = [u8; 8];
+
+/// A block with its transactions replaced by short ids
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactBlock {
+    /// Hash of the full block, the reconstruction has to match it
+    #[serde(with = "serde_arrays")]
+    pub hash: [u8; 32],
+    /// The block, without transactions
+    pub header: Block,
+    pub short_ids: Vec<ShortId>,
+}
+
+/// Short id of `tx` in the block with hash `block_hash`. Salting with the
+/// block hash keeps collisions from carrying over between blocks.
+pub fn short_id(block_hash: &[u8; 32], tx: &Transaction) -> ShortId {
+    short_id_of(block_hash, &tx.hash())
+}
+
+/// Short id of the transaction with hash `tx_hash`, see [`short_id`]
+pub fn short_id_of(block_hash: &[u8; 32], tx_hash: &[u8; 32]) -> ShortId {
+    let mut hasher = Sha256::new();
+    hasher.update(block_hash);
+    hasher.update(tx_hash);
+    let digest = hasher.finalize();
+    let mut id = [0; 8];
+    id.copy_from_slice(&digest[..8]);
+    id
+}
+
+impl CompactBlock {
+    pub fn new(block: &Block) -> Self {
+        let hash = block.hash();
+        Self {
+            hash,
+            header: block.header(),
+            short_ids: block
+                .transactions
+                .iter()
+                .map(|tx| short_id(&hash, tx))
+                .collect(),
+        }
+    }
+
+    /// Start rebuilding the block from the transactions at hand, which
+    /// keep their hashes for the next block
+    pub fn reconstruct<'a>(
+        &self,
+        known: impl IntoIterator<Item = &'a HashedTransaction>,
+    ) -> PartialBlock {
+        let wanted: HashMap<ShortId, usize> = self
+            .short_ids
+            .iter()
+            .enumerate()
+            .map(|(index, id)| (*id, index))
+            .collect();
+        let mut transactions = vec![None; self.short_ids.len()];
+        for tx in known {
+            if let Some(index) = wanted.get(&short_id_of(&self.hash, &tx.hash())) {
+                transactions[*index] = Some(tx.transaction().clone());
+            }
+        }
+        PartialBlock {
+            compact: self.clone(),
+            transactions,
+        }
+    }
+}
+
+/// A block being rebuilt from a [`CompactBlock`]
+#[derive(Debug, Clone)]
+pub struct PartialBlock {
+    compact: CompactBlock,
+    transactions: Vec<Option<Transaction>>,
+}
+
+impl PartialBlock {
+    pub fn hash(&self) -> [u8; 32] {
+        self.compact.hash
+    }
+
+    /// Positions of the transactions still missing
+    pub fn missing(&self) -> Vec<u32> {
+        self.transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(index, _)| index as u32)
+            .collect()
+    }
+
+    /// Fill in transactions sent by a peer, in the order of `indexes`
+    pub fn fill(&mut self, indexes: &[u32], transactions: Vec<Transaction>) -> Result<(), Error> {
+        if indexes.len() != transactions.len() {
+            return Err(Error::InvalidBlock(format!(
+                "Asked for {} transactions, got {}",
+                indexes.len(),
+                transactions.len()
+            )));
+        }
+        for (index, tx) in indexes.iter().zip(transactions) {
+            let index = *index as usize;
+            if self.compact.short_ids.get(index) != Some(&short_id(&self.compact.hash, &tx)) {
+                return Err(Error::InvalidBlock(format!(
+                    "Transaction {} does not belong at position {}",
+                    hex::encode(tx.hash()),
+                    index
+                )));
+            }
+            self.transactions[index] = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// The full block, once nothing is missing and it hashes as announced
+    pub fn complete(&self) -> Option<Result<Block, Error>> {
+        let transactions: Option<Vec<Transaction>> = self.transactions.iter().cloned().collect();
+        let block = Block {
+            transactions: transactions?,
+            ..self.compact.header.clone()
+        };
+        if block.hash() != self.compact.hash {
+            return Some(Err(Error::InvalidBlock(
+                "Reconstructed block does not match its hash".to_string(),
+            )));
+        }
+        Some(Ok(block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(nonce: u64) -> Transaction {
+        Transaction {
+            sender: [3; 32],
+            nonce,
+            payload: format!("meme #{}", nonce).into_bytes().into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_compact_blocks_rebuild_from_the_mempool() {
+        let block = Block {
+            height: 7,
+            parent_hash: [1; 32],
+            transactions: (0..4).map(tx).collect(),
+            proposer_sig: [0; 64],
+            state_root: [2; 32],
+            innovation_level: 5,
+            producer_strategy: "memes".to_string(),
+            producer_id: "producer-1".to_string(),
+            drama_level: 6,
+            producer_mood: "smug".to_string(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+        let compact = CompactBlock::new(&block);
+        assert!(compact.header.transactions.is_empty());
+        assert_eq!(compact.short_ids.len(), 4);
+
+        // The mempool has all but one, plus some the block does not include
+        let mempool = [tx(0), tx(1), tx(3), tx(9)].map(HashedTransaction::new);
+        let mut partial = compact.reconstruct(&mempool);
+        assert_eq!(partial.missing(), vec![2]);
+        assert!(partial.complete().is_none());
+
+        assert!(partial.fill(&[2], vec![tx(9)]).is_err());
+        partial.fill(&[2], vec![tx(2)]).unwrap();
+        let rebuilt = partial.complete().unwrap().unwrap();
+        assert_eq!(rebuilt, block);
+
+        // They come out of the mempool sharing their payloads, which are
+        // encoded as they always were
+        assert_eq!(
+            rebuilt.transactions[0].payload.as_ptr(),
+            mempool[0].payload.as_ptr()
+        );
+        let json = serde_json::to_string(&rebuilt.transactions[0]).unwrap();
+        assert!(json.contains("\"payload\":[109,101,109,101,"));
+        let decoded: Transaction = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, rebuilt.transactions[0]);
+    }
+}
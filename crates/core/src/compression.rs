@@ -0,0 +1,321 @@
+//! Compression of wire messages. Payloads use the raw Snappy block format.
+//! A compressed payload goes out in a frame that names its codec. Plain
+//! JSON never starts with [`FRAME_MARKER`], so nodes tell the two apart
+//! without asking.
+
+use crate::Error;
+
+/// First byte of a framed payload
+pub const FRAME_MARKER: u8 = 0;
+
+/// Payloads below this size are not worth compressing
+pub const MIN_COMPRESSED_SIZE: usize = 256;
+
+/// Longest match back references reach
+const MAX_OFFSET: usize = 65_535;
+
+const HASH_BITS: u32 = 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Codec {
+    None,
+    Snappy,
+}
+
+impl Codec {
+    fn id(&self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Snappy => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Codec::None),
+            1 => Some(Codec::Snappy),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Snappy => "snappy",
+        }
+    }
+}
+
+/// Compress `data` into a frame, or leave it as is when that does not make
+/// it smaller
+pub fn encode(codec: Codec, data: Vec<u8>) -> (Codec, Vec<u8>) {
+    if codec == Codec::None || data.len() < MIN_COMPRESSED_SIZE {
+        return (Codec::None, data);
+    }
+    let compressed = compress(&data);
+    if compressed.len() + 2 >= data.len() {
+        return (Codec::None, data);
+    }
+    let mut framed = Vec::with_capacity(compressed.len() + 2);
+    framed.push(FRAME_MARKER);
+    framed.push(codec.id());
+    framed.extend(compressed);
+    (codec, framed)
+}
+
+/// The payload of a wire message, refusing anything that would decompress
+/// to more than `max_size` bytes
+pub fn decode(data: &[u8], max_size: usize) -> Result<(Codec, Vec<u8>), Error> {
+    match data {
+        [FRAME_MARKER, id, payload @ ..] => match Codec::from_id(*id) {
+            Some(Codec::Snappy) => Ok((Codec::Snappy, decompress(payload, max_size)?)),
+            Some(Codec::None) => Ok((Codec::None, payload.to_vec())),
+            None => Err(Error::InvalidMessage(format!("Unknown codec {}", id))),
+        },
+        [FRAME_MARKER] => Err(Error::InvalidMessage("Truncated frame".to_string())),
+        _ => Ok((Codec::None, data.to_vec())),
+    }
+}
+
+/// Raw Snappy compression
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2 + 8);
+    put_varint(&mut out, data.len() as u64);
+
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        let hash = hash4(&data[i..i + 4]);
+        let candidate = table[hash];
+        table[hash] = i;
+        let matches = candidate != usize::MAX
+            && i - candidate <= MAX_OFFSET
+            && data[candidate..candidate + 4] == data[i..i + 4];
+        if !matches {
+            i += 1;
+            continue;
+        }
+        let mut length = 4;
+        while i + length < data.len() && data[candidate + length] == data[i + length] {
+            length += 1;
+        }
+        put_literal(&mut out, &data[literal_start..i]);
+        put_copies(&mut out, i - candidate, length);
+        i += length;
+        literal_start = i;
+    }
+    put_literal(&mut out, &data[literal_start..]);
+    out
+}
+
+/// Raw Snappy decompression, held to `max_size` bytes of output
+pub fn decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>, Error> {
+    let corrupt = |what: &str| Error::InvalidMessage(format!("Corrupt compressed data: {}", what));
+    let (expected, mut pos) = get_varint(data).ok_or_else(|| corrupt("bad length"))?;
+    let expected = usize::try_from(expected).map_err(|_| corrupt("bad length"))?;
+    if expected > max_size {
+        return Err(Error::InvalidMessage(format!(
+            "Decompresses to {} bytes, at most {} allowed",
+            expected, max_size
+        )));
+    }
+    let mut out = Vec::with_capacity(expected);
+    while pos < data.len() {
+        let tag = data[pos];
+        pos += 1;
+        let (offset, length) = match tag & 0b11 {
+            0 => {
+                let mut length = (tag >> 2) as usize;
+                if length >= 60 {
+                    let bytes = length - 59;
+                    let raw = data
+                        .get(pos..pos + bytes)
+                        .ok_or_else(|| corrupt("truncated"))?;
+                    length = raw
+                        .iter()
+                        .rev()
+                        .fold(0, |acc, byte| (acc << 8) | *byte as usize);
+                    pos += bytes;
+                }
+                let length = length + 1;
+                let literal = data
+                    .get(pos..pos + length)
+                    .ok_or_else(|| corrupt("truncated literal"))?;
+                if out.len() + length > expected {
+                    return Err(corrupt("too long"));
+                }
+                out.extend_from_slice(literal);
+                pos += length;
+                continue;
+            }
+            1 => {
+                let low = *data.get(pos).ok_or_else(|| corrupt("truncated copy"))?;
+                pos += 1;
+                (
+                    ((tag as usize >> 5) << 8) | low as usize,
+                    4 + ((tag as usize >> 2) & 0b111),
+                )
+            }
+            2 => {
+                let raw = data
+                    .get(pos..pos + 2)
+                    .ok_or_else(|| corrupt("truncated copy"))?;
+                pos += 2;
+                (
+                    u16::from_le_bytes([raw[0], raw[1]]) as usize,
+                    (tag as usize >> 2) + 1,
+                )
+            }
+            _ => {
+                let raw = data
+                    .get(pos..pos + 4)
+                    .ok_or_else(|| corrupt("truncated copy"))?;
+                pos += 4;
+                (
+                    u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as usize,
+                    (tag as usize >> 2) + 1,
+                )
+            }
+        };
+        if offset == 0 || offset > out.len() {
+            return Err(corrupt("bad offset"));
+        }
+        if out.len() + length > expected {
+            return Err(corrupt("too long"));
+        }
+        // Copies may overlap what they produce
+        let start = out.len() - offset;
+        for k in 0..length {
+            out.push(out[start + k]);
+        }
+    }
+    if out.len() != expected {
+        return Err(corrupt("too short"));
+    }
+    Ok(out)
+}
+
+fn hash4(bytes: &[u8]) -> usize {
+    let word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (word.wrapping_mul(0x1e35_a7bd) >> (32 - HASH_BITS)) as usize
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn get_varint(data: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in data.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn put_literal(out: &mut Vec<u8>, literal: &[u8]) {
+    if literal.is_empty() {
+        return;
+    }
+    let n = literal.len() - 1;
+    if n < 60 {
+        out.push((n as u8) << 2);
+    } else {
+        let bytes = (usize::BITS - n.leading_zeros()).div_ceil(8) as usize;
+        out.push(((59 + bytes) as u8) << 2);
+        out.extend_from_slice(&n.to_le_bytes()[..bytes]);
+    }
+    out.extend_from_slice(literal);
+}
+
+fn put_copies(out: &mut Vec<u8>, offset: usize, mut length: usize) {
+    while length >= 68 {
+        put_copy(out, offset, 64);
+        length -= 64;
+    }
+    if length > 64 {
+        put_copy(out, offset, 60);
+        length -= 60;
+    }
+    put_copy(out, offset, length);
+}
+
+fn put_copy(out: &mut Vec<u8>, offset: usize, length: usize) {
+    if (4..12).contains(&length) && offset < 2048 {
+        out.push((((offset >> 8) as u8) << 5) | (((length - 4) as u8) << 2) | 1);
+        out.push(offset as u8);
+    } else {
+        out.push((((length - 1) as u8) << 2) | 2);
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_round_trips_within_limits() {
+        let drama = "The validator is FURIOUS about this block! 😤 ".repeat(40);
+        let (codec, framed) = encode(Codec::Snappy, drama.clone().into_bytes());
+        assert_eq!(codec, Codec::Snappy);
+        assert!(framed.len() < drama.len() / 4);
+        let (codec, decoded) = decode(&framed, 1024 * 1024).unwrap();
+        assert_eq!(codec, Codec::Snappy);
+        assert_eq!(decoded, drama.as_bytes());
+
+        // Little to gain stays plain, and plain payloads pass through
+        let noise: Vec<u8> = (0..1000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        assert_eq!(decompress(&compress(&noise), noise.len()).unwrap(), noise);
+        let json = br#"{"Chat":{"from":"a","message":"b"}}"#.to_vec();
+        assert_eq!(
+            encode(Codec::Snappy, json.clone()),
+            (Codec::None, json.clone())
+        );
+        assert_eq!(decode(&json, 64).unwrap().1, json);
+
+        // Bombs and garbage are refused
+        assert!(decode(&framed, drama.len() - 1).is_err());
+        let mut corrupt = framed.clone();
+        corrupt.truncate(framed.len() / 2);
+        assert!(decode(&corrupt, 1024 * 1024).is_err());
+        assert!(decode(&[FRAME_MARKER, 9, 1, 2], 1024).is_err());
+    }
+
+    #[test]
+    fn test_decompression_bombs_are_refused() {
+        // A length past the limit is refused before anything is allocated
+        let mut lying = vec![FRAME_MARKER, Codec::Snappy.id()];
+        put_varint(&mut lying, u64::MAX >> 1);
+        let Err(Error::InvalidMessage(reason)) = decode(&lying, 1024) else {
+            panic!("a frame claiming exabytes decoded");
+        };
+        assert!(reason.contains("at most 1024 allowed"), "{}", reason);
+
+        // Copies may not run past the length the frame gives
+        let mut overrun = Vec::new();
+        put_varint(&mut overrun, 8);
+        put_literal(&mut overrun, b"ab");
+        put_copy(&mut overrun, 2, 64);
+        assert!(decompress(&overrun, 1024).is_err());
+        let mut short = Vec::new();
+        put_varint(&mut short, 8);
+        put_literal(&mut short, b"ab");
+        assert!(decompress(&short, 1024).is_err());
+
+        // A megabyte of zeros from a few bytes, at the limit and past it
+        let zeros = compress(&vec![0; 1 << 20]);
+        assert!(zeros.len() < 64 * 1024);
+        assert_eq!(decompress(&zeros, 1 << 20).unwrap().len(), 1 << 20);
+        assert!(decompress(&zeros, (1 << 20) - 1).is_err());
+    }
+}
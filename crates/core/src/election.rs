@@ -0,0 +1,140 @@
+//! Leader election by lottery: for every height, each producer draws a
+//! ticket with its VRF key over the height and the block it builds on. No
+//! one can tell a ticket before its producer shows it, nor draw another,
+//! so the lowest ticket of a height is a leader picked fairly, and anyone
+//! checks it from the block alone. The output of the ticket is the chaos
+//! of the block to agents, see [`chaoschain_crypto::vrf`].
+
+use crate::Error;
+use chaoschain_crypto::vrf::{VrfOutput, VrfProof, VrfPublicKey, VrfSecretKey};
+use serde::{Deserialize, Serialize};
+
+/// Domain separator of the VRF input of tickets
+pub const ELECTION_DOMAIN: &[u8] = b"chaoschain-leader-v1";
+
+/// What a ticket for the block at `height` on `parent_hash` proves over
+pub fn election_input(height: u64, parent_hash: &[u8; 32]) -> Vec<u8> {
+    let mut input = ELECTION_DOMAIN.to_vec();
+    input.extend_from_slice(&height.to_be_bytes());
+    input.extend_from_slice(parent_hash);
+    input
+}
+
+/// A producer's draw for a height
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaderTicket {
+    pub public_key: VrfPublicKey,
+    pub proof: VrfProof,
+}
+
+impl LeaderTicket {
+    pub fn draw(key: &VrfSecretKey, height: u64, parent_hash: &[u8; 32]) -> Self {
+        Self {
+            public_key: key.public_key(),
+            proof: key.prove(&election_input(height, parent_hash)),
+        }
+    }
+
+    /// The output of the ticket, when it was drawn for that height and
+    /// parent
+    pub fn verify(&self, height: u64, parent_hash: &[u8; 32]) -> Result<VrfOutput, Error> {
+        self.public_key
+            .verify(&election_input(height, parent_hash), &self.proof)
+            .map_err(|e| {
+                Error::InvalidSignature(format!("Leader ticket for block {}: {}", height, e))
+            })
+    }
+}
+
+/// Whether `output` is a winning ticket among `producers`, which one of
+/// them draws per height on average
+pub fn wins(output: &VrfOutput, producers: usize) -> bool {
+    output.fraction() < 1.0 / producers.max(1) as f64
+}
+
+/// The leader of a height, the producer of the lowest ticket that holds
+pub fn leader<'a>(
+    tickets: impl IntoIterator<Item = &'a LeaderTicket>,
+    height: u64,
+    parent_hash: &[u8; 32],
+) -> Option<&'a LeaderTicket> {
+    tickets
+        .into_iter()
+        .filter_map(|ticket| Some((ticket.verify(height, parent_hash).ok()?.0, ticket)))
+        .min_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, ticket)| ticket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Block, BlockMetadata};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_leader_election() {
+        let keys: Vec<VrfSecretKey> = (1..=4).map(|i| VrfSecretKey::from_bytes([i; 32])).collect();
+        let parent = [7; 32];
+        let tickets: Vec<LeaderTicket> = keys
+            .iter()
+            .map(|key| LeaderTicket::draw(key, 5, &parent))
+            .collect();
+        for ticket in &tickets {
+            ticket.verify(5, &parent).unwrap();
+            assert!(ticket.verify(6, &parent).is_err());
+            assert!(ticket.verify(5, &[8; 32]).is_err());
+        }
+        let lowest = tickets
+            .iter()
+            .min_by_key(|ticket| ticket.verify(5, &parent).unwrap().0)
+            .unwrap();
+        assert_eq!(leader(&tickets, 5, &parent), Some(lowest));
+
+        // A ticket of one height does not run at another
+        let mut stale = tickets.clone();
+        stale.push(LeaderTicket::draw(&keys[0], 4, &parent));
+        assert_eq!(leader(&stale, 5, &parent), Some(lowest));
+        assert_eq!(leader(&tickets, 6, &parent), None);
+        assert!(wins(&lowest.verify(5, &parent).unwrap(), 1));
+    }
+
+    #[test]
+    fn test_block_signed_with_its_leader_ticket() {
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let parent = [7; 32];
+        let mut block = Block {
+            height: 5,
+            parent_hash: parent,
+            transactions: Vec::new(),
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            innovation_level: 5,
+            producer_strategy: "test".to_string(),
+            producer_id: "producer-1".to_string(),
+            drama_level: 3,
+            producer_mood: "Smug".to_string(),
+            timestamp: 1_000,
+            metadata: BlockMetadata::default(),
+        };
+        // Named by no key, the producer cannot be checked
+        assert!(block.verify_producer().is_err());
+
+        let ticket = LeaderTicket::draw(&VrfSecretKey::from_bytes(key.to_bytes()), 5, &parent);
+        block.metadata.leader = Some(Box::new(ticket));
+        assert!(block.verify_producer().is_err());
+        block.proposer_sig = key.sign(&block.signing_bytes()).to_bytes();
+        block.verify_producer().unwrap();
+
+        let mut forged = block.clone();
+        forged.drama_level = 10;
+        assert!(forged.verify_producer().is_err());
+        // A ticket drawn for another parent does not hold
+        let mut stale = block.clone();
+        stale.metadata.leader = Some(Box::new(LeaderTicket::draw(
+            &VrfSecretKey::from_bytes(key.to_bytes()),
+            5,
+            &[8; 32],
+        )));
+        assert!(stale.verify_producer().is_err());
+    }
+}
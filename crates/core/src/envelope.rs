@@ -0,0 +1,206 @@
+//! Social gossip signed by the agent that said it. Chat, reasoning and
+//! rumors travel in a [`SignedEnvelope`], so what shows up in the feed can
+//! be traced to an agent key rather than to whichever node relayed it.
+//! [`AgentKeys`] ties agent names to keys, pinning each agent to the first
+//! key it is heard with, so nobody else can speak in its name afterwards.
+
+use crate::vote::{hex_array, hex_vec};
+use crate::{Error, NetworkMessage};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Domain separator of social message signatures
+pub const SOCIAL_DOMAIN: &[u8] = b"chaoschain-social-v1";
+
+/// A social message with the signature of its author
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedEnvelope {
+    pub message: Box<NetworkMessage>,
+    /// Ed25519 key of the author, hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub public_key: [u8; 32],
+    /// Signature over [`SignedEnvelope::signing_bytes`], hex encoded in JSON
+    #[serde(with = "hex_vec")]
+    pub signature: Vec<u8>,
+}
+
+impl NetworkMessage {
+    /// The agent a social message speaks for: who chats or reasons, or who
+    /// passes a rumor on. `None` for everything else.
+    pub fn author(&self) -> Option<&str> {
+        match self {
+            NetworkMessage::Chat { from, .. } => Some(from),
+            NetworkMessage::AgentReasoning { agent, .. } => Some(agent),
+            NetworkMessage::Rumor(rumor) => Some(&rumor.relayed_by),
+            NetworkMessage::Signed(envelope) => envelope.message.author(),
+            NetworkMessage::NewBlock(_)
+            | NetworkMessage::NewTransaction(_)
+            | NetworkMessage::Vote(_)
+            | NetworkMessage::CompactBlock(_)
+            | NetworkMessage::Threshold(_) => None,
+        }
+    }
+
+    /// Whether the message has to come in a [`SignedEnvelope`]
+    pub fn is_social(&self) -> bool {
+        matches!(
+            self,
+            NetworkMessage::Chat { .. }
+                | NetworkMessage::AgentReasoning { .. }
+                | NetworkMessage::Rumor(_)
+        )
+    }
+}
+
+impl SignedEnvelope {
+    /// Sign a social message with the key of its author
+    pub fn sign(key: &SigningKey, message: NetworkMessage) -> Result<Self, Error> {
+        if !message.is_social() {
+            return Err(Error::InvalidMessage(
+                "Only chat, reasoning and rumors go in an envelope".to_string(),
+            ));
+        }
+        let mut envelope = Self {
+            message: Box::new(message),
+            public_key: key.verifying_key().to_bytes(),
+            signature: Vec::new(),
+        };
+        envelope.signature = key.sign(&envelope.signing_bytes()?).to_bytes().to_vec();
+        Ok(envelope)
+    }
+
+    /// The agent that signed
+    pub fn author(&self) -> &str {
+        self.message.author().unwrap_or_default()
+    }
+
+    /// Canonical bytes covered by the signature
+    pub fn signing_bytes(&self) -> Result<Vec<u8>, Error> {
+        let message =
+            serde_json::to_vec(&self.message).map_err(|e| Error::InvalidMessage(e.to_string()))?;
+        let mut bytes = Vec::with_capacity(SOCIAL_DOMAIN.len() + 32 + message.len());
+        bytes.extend_from_slice(SOCIAL_DOMAIN);
+        bytes.extend_from_slice(&self.public_key);
+        bytes.extend_from_slice(&message);
+        Ok(bytes)
+    }
+
+    /// Check that the envelope holds a social message, signed with the key
+    /// it names. Whether the key is the author's is up to [`AgentKeys`].
+    pub fn verify(&self) -> Result<(), Error> {
+        if !self.message.is_social() {
+            return Err(Error::InvalidMessage(
+                "Envelope holds no social message".to_string(),
+            ));
+        }
+        let key = VerifyingKey::from_bytes(&self.public_key)
+            .map_err(|e| Error::InvalidSignature(e.to_string()))?;
+        let signature = Signature::from_slice(&self.signature)
+            .map_err(|e| Error::InvalidSignature(e.to_string()))?;
+        key.verify(&self.signing_bytes()?, &signature)
+            .map_err(|e| Error::InvalidSignature(e.to_string()))
+    }
+}
+
+/// The keys agents sign their social messages with
+#[derive(Debug, Default)]
+pub struct AgentKeys {
+    keys: RwLock<HashMap<String, [u8; 32]>>,
+}
+
+impl AgentKeys {
+    /// Agents whose keys are known up front, like the registered ones
+    pub fn with_keys(keys: impl IntoIterator<Item = (String, [u8; 32])>) -> Self {
+        Self {
+            keys: RwLock::new(keys.into_iter().collect()),
+        }
+    }
+
+    pub fn key_of(&self, agent: &str) -> Option<[u8; 32]> {
+        self.keys.read().get(agent).copied()
+    }
+
+    /// Tie `agent` to `key`, whatever it was heard with before, as for the
+    /// agents a node runs itself
+    pub fn pin(&self, agent: &str, key: [u8; 32]) {
+        self.keys.write().insert(agent.to_string(), key);
+    }
+
+    /// Check that `envelope` was signed by its author. An agent not heard
+    /// from before is pinned to the key it signed with.
+    pub fn attribute(&self, envelope: &SignedEnvelope) -> Result<(), Error> {
+        envelope.verify()?;
+        let author = envelope.author();
+        let mut keys = self.keys.write();
+        match keys.get(author) {
+            Some(key) if *key != envelope.public_key => Err(Error::InvalidSignature(format!(
+                "{} signs with another key",
+                author
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                keys.insert(author.to_string(), envelope.public_key);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat(from: &str, message: &str) -> NetworkMessage {
+        NetworkMessage::Chat {
+            from: from.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        let envelope = SignedEnvelope::sign(&key, chat("validator-1", "Nice block!")).unwrap();
+        envelope.verify().unwrap();
+        assert_eq!(envelope.author(), "validator-1");
+
+        let json = serde_json::to_string(&envelope).unwrap();
+        serde_json::from_str::<SignedEnvelope>(&json)
+            .unwrap()
+            .verify()
+            .unwrap();
+
+        let mut forged = envelope.clone();
+        *forged.message = chat("validator-1", "Terrible block!");
+        assert!(forged.verify().is_err());
+
+        let vote = crate::vote::SignedVote::sign(&key, "validator-1", 1, [0u8; 32], true, 5, "ok");
+        assert!(SignedEnvelope::sign(&key, NetworkMessage::Vote(vote)).is_err());
+    }
+
+    #[test]
+    fn test_agents_are_pinned_to_their_key() {
+        let alice = SigningKey::from_bytes(&[1u8; 32]);
+        let mallory = SigningKey::from_bytes(&[2u8; 32]);
+        let keys = AgentKeys::default();
+
+        keys.attribute(&SignedEnvelope::sign(&alice, chat("alice", "hi")).unwrap())
+            .unwrap();
+        assert_eq!(keys.key_of("alice"), Some(alice.verifying_key().to_bytes()));
+
+        // Validly signed, but not by alice
+        let spoofed = SignedEnvelope::sign(&mallory, chat("alice", "I quit")).unwrap();
+        assert!(keys.attribute(&spoofed).is_err());
+
+        let known = AgentKeys::with_keys([("bob".to_string(), alice.verifying_key().to_bytes())]);
+        assert!(known
+            .attribute(&SignedEnvelope::sign(&mallory, chat("bob", "hey")).unwrap())
+            .is_err());
+
+        // An agent of the node itself takes its key over
+        keys.pin("alice", mallory.verifying_key().to_bytes());
+        assert!(keys.attribute(&spoofed).is_ok());
+    }
+}
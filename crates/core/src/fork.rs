@@ -0,0 +1,417 @@
+//! Upgrades of the protocol a live network takes together, at a height
+//! its genesis schedules them for. A [`ForkSchedule`] names each
+//! [`Upgrade`] and the height, or the epoch, it activates at:
+//!
+//! ```json
+//! "forks": {
+//!   "epoch_length": 100,
+//!   "upgrades": [
+//!     { "upgrade": "bounded-levels", "height": 1200 },
+//!     { "upgrade": "drama-rewards", "epoch": 30 }
+//!   ]
+//! }
+//! ```
+//!
+//! [`ForkSchedule::at`] gives the [`ActiveForks`] of a height, which
+//! validation and execution go by. A block names the upgrades it was
+//! produced under in its metadata, and is rejected when they are not
+//! those active at its height, as well as when it breaks a rule of one of
+//! them: a producer that missed an upgrade, or took one early, is on
+//! another chain.
+
+use crate::{Block, Error};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// Blocks of an epoch, for upgrades scheduled by epoch
+pub const DEFAULT_EPOCH_LENGTH: u64 = 100;
+
+/// Most a drama level is, from [`Upgrade::BoundedLevels`]
+pub const MAX_DRAMA_LEVEL: u8 = 10;
+/// Most an innovation level is, from [`Upgrade::BoundedLevels`]
+pub const MAX_INNOVATION_LEVEL: u8 = 100;
+
+/// A change of the rules, in the order they were introduced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Upgrade {
+    /// The drama bonus of block rewards is paid for the drama level of the
+    /// block, not its innovation level
+    DramaRewards,
+    /// Drama levels are at most [`MAX_DRAMA_LEVEL`] and innovation levels
+    /// at most [`MAX_INNOVATION_LEVEL`]
+    BoundedLevels,
+    /// A block is timestamped after its parent
+    MonotonicTime,
+}
+
+impl Upgrade {
+    pub const ALL: [Upgrade; 3] = [
+        Upgrade::DramaRewards,
+        Upgrade::BoundedLevels,
+        Upgrade::MonotonicTime,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Upgrade::DramaRewards => "drama-rewards",
+            Upgrade::BoundedLevels => "bounded-levels",
+            Upgrade::MonotonicTime => "monotonic-time",
+        }
+    }
+}
+
+impl fmt::Display for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Upgrade {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Upgrade::ALL
+            .into_iter()
+            .find(|upgrade| upgrade.name() == text)
+            .ok_or_else(|| {
+                format!(
+                    "No upgrade {}, one of {}",
+                    text,
+                    names(&Upgrade::ALL).join(", ")
+                )
+            })
+    }
+}
+
+fn names(upgrades: &[Upgrade]) -> Vec<&'static str> {
+    upgrades.iter().map(Upgrade::name).collect()
+}
+
+/// Where an upgrade activates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    /// At the block of this height
+    Height(u64),
+    /// At the first block of this epoch
+    Epoch(u64),
+}
+
+/// An upgrade and where it activates, as `bounded-levels=1200` or
+/// `drama-rewards=epoch:30` on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduledUpgrade {
+    pub upgrade: Upgrade,
+    #[serde(flatten)]
+    pub activation: Activation,
+}
+
+impl FromStr for ScheduledUpgrade {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (upgrade, at) = text
+            .split_once('=')
+            .ok_or_else(|| format!("{} is not UPGRADE=HEIGHT or UPGRADE=epoch:N", text))?;
+        let number = |n: &str| {
+            n.parse::<u64>()
+                .map_err(|_| format!("{} is not a height or an epoch", at))
+        };
+        Ok(Self {
+            upgrade: upgrade.trim().parse()?,
+            activation: match at.trim().strip_prefix("epoch:") {
+                Some(epoch) => Activation::Epoch(number(epoch)?),
+                None => Activation::Height(number(at.trim())?),
+            },
+        })
+    }
+}
+
+/// The upgrades of a network, part of its genesis
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForkSchedule {
+    #[serde(
+        default = "default_epoch_length",
+        skip_serializing_if = "is_default_epoch_length"
+    )]
+    pub epoch_length: u64,
+    #[serde(default)]
+    pub upgrades: Vec<ScheduledUpgrade>,
+}
+
+fn default_epoch_length() -> u64 {
+    DEFAULT_EPOCH_LENGTH
+}
+
+fn is_default_epoch_length(length: &u64) -> bool {
+    *length == DEFAULT_EPOCH_LENGTH
+}
+
+impl Default for ForkSchedule {
+    fn default() -> Self {
+        Self {
+            epoch_length: DEFAULT_EPOCH_LENGTH,
+            upgrades: Vec::new(),
+        }
+    }
+}
+
+impl ForkSchedule {
+    pub fn new(upgrades: Vec<ScheduledUpgrade>) -> Self {
+        Self {
+            upgrades,
+            ..Self::default()
+        }
+    }
+
+    /// The schedule with `upgrades` too, as governance adopts them
+    pub fn with_upgrades(mut self, upgrades: impl IntoIterator<Item = ScheduledUpgrade>) -> Self {
+        self.upgrades.extend(upgrades);
+        self
+    }
+
+    /// No upgrade is scheduled, as on networks from before there were any
+    pub fn is_empty(&self) -> bool {
+        self.upgrades.is_empty()
+    }
+
+    /// Height `activation` comes at, none past the last height
+    pub fn height_of(&self, activation: Activation) -> Option<u64> {
+        match activation {
+            Activation::Height(height) => Some(height),
+            Activation::Epoch(epoch) => epoch.checked_mul(self.epoch_length),
+        }
+    }
+
+    /// What keeps the schedule from being followed, nothing when it can be
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut scheduled = HashSet::new();
+        for scheduled_upgrade in &self.upgrades {
+            let upgrade = scheduled_upgrade.upgrade;
+            if !scheduled.insert(upgrade) {
+                problems.push(format!("Upgrade {} is scheduled twice", upgrade));
+            }
+            if let Activation::Epoch(epoch) = scheduled_upgrade.activation {
+                if self.epoch_length == 0 {
+                    problems.push(format!(
+                        "Upgrade {} activates at epoch {}, but epochs are 0 blocks long",
+                        upgrade, epoch
+                    ));
+                } else if self.height_of(scheduled_upgrade.activation).is_none() {
+                    problems.push(format!(
+                        "Upgrade {} activates at epoch {}, past the last height",
+                        upgrade, epoch
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    /// The upgrades active at `height`
+    pub fn at(&self, height: u64) -> ActiveForks {
+        ActiveForks {
+            height,
+            active: self
+                .upgrades
+                .iter()
+                .filter(|scheduled| {
+                    self.height_of(scheduled.activation)
+                        .is_some_and(|from| from <= height)
+                })
+                .map(|scheduled| scheduled.upgrade)
+                .collect(),
+        }
+    }
+
+    /// The next upgrade to activate above `height` and the height it does,
+    /// the first scheduled of those activating together
+    pub fn next_after(&self, height: u64) -> Option<(Upgrade, u64)> {
+        self.upgrades
+            .iter()
+            .filter_map(|scheduled| {
+                self.height_of(scheduled.activation)
+                    .filter(|from| *from > height)
+                    .map(|from| (scheduled.upgrade, from))
+            })
+            .min_by_key(|(_, from)| *from)
+    }
+}
+
+/// The rules in force at one height
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActiveForks {
+    height: u64,
+    active: BTreeSet<Upgrade>,
+}
+
+impl ActiveForks {
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn is_active(&self, upgrade: Upgrade) -> bool {
+        self.active.contains(&upgrade)
+    }
+
+    /// The active upgrades, in the order they were introduced, as a block
+    /// names them
+    pub fn upgrades(&self) -> Vec<Upgrade> {
+        self.active.iter().copied().collect()
+    }
+
+    /// Name the active upgrades in `block`'s metadata, for a producer
+    pub fn stamp(&self, block: &mut Block) {
+        block.metadata.forks = self.upgrades();
+    }
+
+    /// `block` was produced under these rules and keeps them. The
+    /// timestamp of its parent, when known, is checked against from
+    /// [`Upgrade::MonotonicTime`].
+    pub fn check(&self, block: &Block, parent_timestamp: Option<u64>) -> Result<(), Error> {
+        let invalid =
+            |reason: String| Error::InvalidBlock(format!("Block {} {}", block.height, reason));
+        if block.height != self.height {
+            return Err(invalid(format!(
+                "is checked against the rules of height {}",
+                self.height
+            )));
+        }
+        if block.metadata.forks != self.upgrades() {
+            let list = |upgrades: &[Upgrade]| match upgrades {
+                [] => "no upgrades".to_string(),
+                upgrades => names(upgrades).join(", "),
+            };
+            return Err(invalid(format!(
+                "is produced under {}, but the rules at its height are {}",
+                list(&block.metadata.forks),
+                list(&self.upgrades())
+            )));
+        }
+        if self.is_active(Upgrade::BoundedLevels) {
+            if block.drama_level > MAX_DRAMA_LEVEL {
+                return Err(invalid(format!(
+                    "has a drama level of {}, above {}",
+                    block.drama_level, MAX_DRAMA_LEVEL
+                )));
+            }
+            if block.innovation_level > MAX_INNOVATION_LEVEL {
+                return Err(invalid(format!(
+                    "has an innovation level of {}, above {}",
+                    block.innovation_level, MAX_INNOVATION_LEVEL
+                )));
+            }
+        }
+        if self.is_active(Upgrade::MonotonicTime) {
+            if let Some(parent) = parent_timestamp.filter(|parent| block.timestamp <= *parent) {
+                return Err(invalid(format!(
+                    "is timestamped {}, not after its parent at {}",
+                    block.timestamp, parent
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The level the drama bonus of `block`'s reward is paid for
+    pub fn rewarded_drama(&self, block: &Block) -> u8 {
+        if self.is_active(Upgrade::DramaRewards) {
+            block.drama_level
+        } else {
+            block.innovation_level
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockMetadata;
+
+    fn block(height: u64, forks: Vec<Upgrade>) -> Block {
+        Block {
+            height,
+            parent_hash: [0; 32],
+            transactions: Vec::new(),
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            innovation_level: 5,
+            producer_strategy: "test".to_string(),
+            producer_id: "producer".to_string(),
+            drama_level: 12,
+            producer_mood: "Smug".to_string(),
+            timestamp: 1_000,
+            metadata: BlockMetadata {
+                forks,
+                ..BlockMetadata::default()
+            },
+        }
+    }
+
+    fn schedule() -> ForkSchedule {
+        ForkSchedule {
+            epoch_length: 10,
+            upgrades: vec![
+                "bounded-levels=100".parse().unwrap(),
+                "monotonic-time=epoch:5".parse().unwrap(),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_upgrades_activate_at_their_height() {
+        let schedule = schedule();
+        assert!(schedule.problems().is_empty());
+        assert!(schedule.at(49).upgrades().is_empty());
+        assert_eq!(schedule.at(50).upgrades(), vec![Upgrade::MonotonicTime]);
+        assert_eq!(
+            schedule.at(100).upgrades(),
+            vec![Upgrade::BoundedLevels, Upgrade::MonotonicTime]
+        );
+        assert_eq!(schedule.next_after(0), Some((Upgrade::MonotonicTime, 50)));
+        assert_eq!(schedule.next_after(100), None);
+
+        let json = serde_json::to_value(&schedule).unwrap();
+        assert_eq!(json["upgrades"][0]["height"], 100);
+        assert_eq!(json["upgrades"][1]["epoch"], 5);
+        assert_eq!(
+            serde_json::from_value::<ForkSchedule>(json).unwrap(),
+            schedule
+        );
+    }
+
+    #[test]
+    fn test_blocks_under_the_wrong_rules_are_rejected() {
+        let forks = schedule().at(100);
+        // Produced as though the upgrades had not come
+        assert!(forks.check(&block(100, Vec::new()), None).is_err());
+        // Named, but breaking one of them
+        assert!(forks.check(&block(100, forks.upgrades()), None).is_err());
+
+        let mut bounded = block(100, forks.upgrades());
+        bounded.drama_level = MAX_DRAMA_LEVEL;
+        assert!(forks.check(&bounded, Some(999)).is_ok());
+        assert!(forks.check(&bounded, Some(1_000)).is_err());
+
+        // Taking an upgrade early
+        let early = schedule().at(10);
+        assert!(early
+            .check(&block(10, vec![Upgrade::BoundedLevels]), None)
+            .is_err());
+        assert!(early.check(&block(10, Vec::new()), None).is_ok());
+    }
+
+    #[test]
+    fn test_schedule_problems() {
+        let mut schedule = schedule();
+        schedule.upgrades.push("bounded-levels=7".parse().unwrap());
+        schedule.epoch_length = 0;
+        assert_eq!(schedule.problems().len(), 2);
+        assert!("sudden-death=5".parse::<ScheduledUpgrade>().is_err());
+        assert!("bounded-levels=soon".parse::<ScheduledUpgrade>().is_err());
+    }
+}
@@ -0,0 +1,343 @@
+//! The genesis document of a network: its id, the validators it starts
+//! with, the balances allocated before the first block and the parameters
+//! of its consensus. Every node of a network is given the same document,
+//! and tells others which one by its [`Genesis::hash`].
+
+use crate::fork::ForkSchedule;
+use crate::governance::GovernanceParams;
+use crate::staking::StakingParams;
+use chaoschain_crypto::hash::HashFunction;
+use chaoschain_crypto::threshold::GroupKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+/// Share of the stake that finalizes a block, unless a network sets its own
+pub const DEFAULT_FINALITY_THRESHOLD: f64 = 0.67;
+
+/// Blocks a validator's old key still votes after it rotates, unless a
+/// network sets its own
+pub const DEFAULT_ROTATION_GRACE: u64 = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Genesis {
+    /// Name of the network, which nodes of another network do not share
+    pub chain_id: String,
+    /// Unix time of the genesis, in seconds
+    pub genesis_time: u64,
+    pub consensus: ConsensusParams,
+    pub validators: Vec<GenesisValidator>,
+    /// Balances before the first block
+    pub allocations: Vec<Allocation>,
+    /// Key the validators sign finalized blocks with together, the share
+    /// of each validator the one of its position, from 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<GroupKey>,
+    /// How proposals are voted on, see [`crate::governance`]. Left out
+    /// when it is the default.
+    #[serde(default, skip_serializing_if = "GovernanceParams::is_default")]
+    pub governance: GovernanceParams,
+    /// How stake is unbonded, paid and slashed, see [`crate::staking`].
+    /// Left out when it is the default.
+    #[serde(default, skip_serializing_if = "StakingParams::is_default")]
+    pub staking: StakingParams,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConsensusParams {
+    /// Share of the stake whose approval finalizes a block, above one half
+    pub finality_threshold: f64,
+    /// Seconds between two blocks
+    pub block_time: u64,
+    /// Seconds a voting round waits for votes
+    pub consensus_timeout: u64,
+    /// Blocks after the one rotating a validator's key that votes of its
+    /// old key still count, see [`crate::rotation`]. Left out when it is
+    /// the default, for the hash of networks from before rotations.
+    #[serde(
+        default = "default_rotation_grace",
+        skip_serializing_if = "is_default_rotation_grace"
+    )]
+    pub rotation_grace: u64,
+    /// Function transactions and blocks are hashed with. Left out when it
+    /// is SHA-256, as for networks from before there was a choice.
+    #[serde(default, skip_serializing_if = "HashFunction::is_sha256")]
+    pub hash: HashFunction,
+    /// Upgrades of the protocol and the heights they activate at. Left
+    /// out when none are scheduled.
+    #[serde(default, skip_serializing_if = "ForkSchedule::is_empty")]
+    pub forks: ForkSchedule,
+}
+
+fn default_rotation_grace() -> u64 {
+    DEFAULT_ROTATION_GRACE
+}
+
+fn is_default_rotation_grace(grace: &u64) -> bool {
+    *grace == DEFAULT_ROTATION_GRACE
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        Self {
+            finality_threshold: DEFAULT_FINALITY_THRESHOLD,
+            block_time: 5,
+            consensus_timeout: 30,
+            rotation_grace: DEFAULT_ROTATION_GRACE,
+            hash: HashFunction::Sha256,
+            forks: ForkSchedule::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisValidator {
+    pub name: String,
+    /// Hex encoded ed25519 key the validator signs its votes with
+    pub public_key: String,
+    pub stake: u64,
+    /// Hex encoded signature of the key over
+    /// [`crate::vote::registration_message`] of the name, see
+    /// [`crate::vote::prove_possession`]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub proof_of_possession: String,
+}
+
+impl GenesisValidator {
+    /// The validator proved it holds its key, or why not
+    pub fn verify_possession(&self) -> Result<(), String> {
+        let key = hex::decode(self.public_key.trim_start_matches("0x"))
+            .ok()
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .ok_or_else(|| format!("Validator {} has no key", self.name))?;
+        if self.proof_of_possession.is_empty() {
+            return Err(format!(
+                "Validator {} gives no proof of possession of its key",
+                self.name
+            ));
+        }
+        let proof = hex::decode(self.proof_of_possession.trim_start_matches("0x"))
+            .map_err(|e| format!("The proof of possession of {} is not hex: {}", self.name, e))?;
+        crate::vote::verify_possession(&self.name, &key, &proof).map_err(|e| match e {
+            crate::Error::InvalidSignature(problem) => problem,
+            e => e.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Allocation {
+    /// Hex encoded public key of the account
+    pub address: String,
+    pub balance: u64,
+}
+
+impl Genesis {
+    /// What is wrong with the document, all of it, or nothing when it can
+    /// start a network
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.chain_id.trim().is_empty() {
+            problems.push("The chain id is empty".to_string());
+        }
+        let threshold = self.consensus.finality_threshold;
+        if !(threshold > 0.5 && threshold <= 1.0) {
+            problems.push(format!(
+                "A finality threshold of {} is not above one half and at most one",
+                threshold
+            ));
+        }
+        if self.consensus.block_time == 0 {
+            problems.push("The block time is 0".to_string());
+        }
+        if self.consensus.consensus_timeout == 0 {
+            problems.push("The consensus timeout is 0".to_string());
+        }
+        problems.extend(self.consensus.forks.problems());
+        problems.extend(self.governance.problems());
+        problems.extend(self.staking.problems());
+
+        if self.validators.is_empty() {
+            problems.push("There are no validators".to_string());
+        }
+        let mut names = HashSet::new();
+        let mut keys = HashSet::new();
+        for validator in &self.validators {
+            if !names.insert(validator.name.as_str()) {
+                problems.push(format!("There are two validators named {}", validator.name));
+            }
+            if !is_public_key(&validator.public_key) {
+                problems.push(format!(
+                    "The key of validator {} is not a hex encoded ed25519 public key",
+                    validator.name
+                ));
+            } else if !keys.insert(validator.public_key.to_lowercase()) {
+                problems.push(format!(
+                    "Validator {} has the key of another validator",
+                    validator.name
+                ));
+            } else if let Err(problem) = validator.verify_possession() {
+                problems.push(problem);
+            }
+            if validator.stake == 0 {
+                problems.push(format!("Validator {} has no stake", validator.name));
+            }
+        }
+        if self
+            .validators
+            .iter()
+            .try_fold(0u64, |total, validator| total.checked_add(validator.stake))
+            .is_none()
+        {
+            problems.push("The stakes add up to more than a u64 holds".to_string());
+        }
+
+        let mut addresses = HashSet::new();
+        for allocation in &self.allocations {
+            if !is_public_key(&allocation.address) {
+                problems.push(format!(
+                    "The allocation to {} is not to a hex encoded public key",
+                    allocation.address
+                ));
+            } else if !addresses.insert(allocation.address.to_lowercase()) {
+                problems.push(format!("{} is allocated twice", allocation.address));
+            }
+        }
+        if self
+            .allocations
+            .iter()
+            .try_fold(0u64, |total, allocation| {
+                total.checked_add(allocation.balance)
+            })
+            .is_none()
+        {
+            problems.push("The allocations add up to more than a u64 holds".to_string());
+        }
+
+        if let Some(group) = &self.threshold {
+            let indexes: Vec<usize> = group
+                .verifying_shares
+                .iter()
+                .map(|share| usize::from(share.index))
+                .collect();
+            if indexes != (1..=self.validators.len()).collect::<Vec<_>>() {
+                problems.push(format!(
+                    "The group key has {} shares, not one for each of the {} validators",
+                    indexes.len(),
+                    self.validators.len()
+                ));
+            }
+            if group.threshold == 0 || usize::from(group.threshold) > self.validators.len() {
+                problems.push(format!(
+                    "A group threshold of {} is not from 1 to the {} validators",
+                    group.threshold,
+                    self.validators.len()
+                ));
+            }
+        }
+        problems
+    }
+
+    /// SHA-256 of the document as it is written, which nodes compare.
+    /// It is SHA-256 whatever the chain hashes with, to name the network
+    /// before anyone knows which.
+    pub fn hash(&self) -> [u8; 32] {
+        Sha256::digest(serde_json::to_vec(self).expect("a genesis serializes")).into()
+    }
+
+    pub fn total_stake(&self) -> u64 {
+        self.validators
+            .iter()
+            .map(|validator| validator.stake)
+            .sum()
+    }
+}
+
+fn is_public_key(key: &str) -> bool {
+    hex::decode(key.strip_prefix("0x").unwrap_or(key))
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .is_some_and(|bytes| ed25519_dalek::VerifyingKey::from_bytes(&bytes).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    fn key(seed: u8) -> String {
+        hex::encode(
+            SigningKey::from_bytes(&[seed; 32])
+                .verifying_key()
+                .to_bytes(),
+        )
+    }
+
+    fn validator(name: &str, seed: u8) -> GenesisValidator {
+        let proof =
+            crate::vote::prove_possession(&SigningKey::from_bytes(&[seed; 32]), name).unwrap();
+        GenesisValidator {
+            name: name.to_string(),
+            public_key: key(seed),
+            stake: 100,
+            proof_of_possession: hex::encode(proof),
+        }
+    }
+
+    #[test]
+    fn test_genesis_problems() {
+        let mut genesis = Genesis {
+            chain_id: "chaos-1".to_string(),
+            genesis_time: 1_700_000_000,
+            consensus: ConsensusParams::default(),
+            validators: vec![validator("alice", 1), validator("bob", 2)],
+            allocations: vec![Allocation {
+                address: key(3),
+                balance: 1_000,
+            }],
+            threshold: None,
+            governance: GovernanceParams::default(),
+            staking: StakingParams::default(),
+        };
+        assert!(genesis.problems().is_empty());
+        assert_eq!(genesis.total_stake(), 200);
+
+        // A proof of another name or key, or none, does not hold
+        let mut unproven = genesis.clone();
+        unproven.validators[0].proof_of_possession = validator("bob", 1).proof_of_possession;
+        unproven.validators[1].proof_of_possession.clear();
+        assert_eq!(unproven.problems().len(), 2, "{:?}", unproven.problems());
+
+        let hash = genesis.hash();
+        assert_eq!(
+            serde_json::from_slice::<Genesis>(&serde_json::to_vec(&genesis).unwrap())
+                .unwrap()
+                .hash(),
+            hash
+        );
+        assert!(!serde_json::to_string(&genesis)
+            .unwrap()
+            .contains("\"hash\""));
+        let mut blake3 = genesis.clone();
+        blake3.consensus.hash = HashFunction::Blake3;
+        assert!(blake3.problems().is_empty());
+        assert_ne!(blake3.hash(), hash);
+        let (group, _) = chaoschain_crypto::threshold::deal(2, 2).unwrap();
+        genesis.threshold = Some(group);
+        assert!(genesis.problems().is_empty());
+        assert_ne!(genesis.hash(), hash);
+
+        let (group, _) = chaoschain_crypto::threshold::deal(2, 3).unwrap();
+        genesis.threshold = Some(group);
+        genesis.consensus.finality_threshold = 0.5;
+        genesis.validators[1].name = "alice".to_string();
+        genesis.validators[1].public_key = key(1);
+        genesis.allocations.push(Allocation {
+            address: "0xnot".to_string(),
+            balance: u64::MAX,
+        });
+        let problems = genesis.problems();
+        assert_eq!(problems.len(), 6, "{:?}", problems);
+        assert_ne!(genesis.hash(), hash);
+    }
+}
@@ -0,0 +1,247 @@
+//! Which gossip topic a message travels on, and what a well-formed message
+//! looks like before it is passed on to other peers
+
+use crate::{Block, Error, NetworkMessage, Transaction};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Gossip topics of the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GossipTopic {
+    Blocks,
+    Votes,
+    Transactions,
+    /// Chat and reasoning, everything agents say about each other
+    Drama,
+    Rumors,
+}
+
+impl GossipTopic {
+    pub const ALL: [GossipTopic; 5] = [
+        GossipTopic::Blocks,
+        GossipTopic::Votes,
+        GossipTopic::Transactions,
+        GossipTopic::Drama,
+        GossipTopic::Rumors,
+    ];
+
+    /// Topic name on the wire
+    pub fn name(self) -> &'static str {
+        match self {
+            GossipTopic::Blocks => "chaoschain/blocks/1",
+            GossipTopic::Votes => "chaoschain/votes/1",
+            GossipTopic::Transactions => "chaoschain/transactions/1",
+            GossipTopic::Drama => "chaoschain/drama/1",
+            GossipTopic::Rumors => "chaoschain/rumors/1",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|topic| topic.name() == name)
+    }
+}
+
+impl fmt::Display for GossipTopic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Size limits gossiped messages are held to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GossipLimits {
+    /// Transactions per block
+    pub max_transactions: usize,
+    /// Bytes of a transaction payload
+    pub max_payload: usize,
+    /// Bytes of free text (chat, reasons, claims)
+    pub max_text: usize,
+}
+
+impl Default for GossipLimits {
+    fn default() -> Self {
+        Self {
+            max_transactions: 1_000,
+            max_payload: 16 * 1024,
+            max_text: 4 * 1024,
+        }
+    }
+}
+
+impl NetworkMessage {
+    /// Topic the message is published on
+    pub fn topic(&self) -> GossipTopic {
+        match self {
+            NetworkMessage::NewBlock(_) | NetworkMessage::CompactBlock(_) => GossipTopic::Blocks,
+            NetworkMessage::Vote(_) | NetworkMessage::Threshold(_) => GossipTopic::Votes,
+            NetworkMessage::NewTransaction(_) => GossipTopic::Transactions,
+            NetworkMessage::Chat { .. } | NetworkMessage::AgentReasoning { .. } => {
+                GossipTopic::Drama
+            }
+            NetworkMessage::Rumor(_) => GossipTopic::Rumors,
+            NetworkMessage::Signed(envelope) => envelope.message.topic(),
+        }
+    }
+
+    /// Checks that need no chain state: the message is within limits and
+    /// internally consistent. Signatures against known keys are up to the
+    /// node's own validators.
+    pub fn check(&self, limits: &GossipLimits) -> Result<(), Error> {
+        let text = |what: &str, text: &str| check_text(what, text, limits);
+        match self {
+            NetworkMessage::NewBlock(block) => check_block(block, limits),
+            NetworkMessage::CompactBlock(compact) => {
+                if compact.short_ids.len() > limits.max_transactions {
+                    return Err(Error::InvalidBlock(format!(
+                        "{} transactions, at most {} allowed",
+                        compact.short_ids.len(),
+                        limits.max_transactions
+                    )));
+                }
+                if !compact.header.transactions.is_empty() {
+                    return Err(Error::InvalidBlock(
+                        "Compact block carries full transactions".to_string(),
+                    ));
+                }
+                // The header is checked where it is, not cloned into a block
+                check_block(&compact.header, limits)
+            }
+            NetworkMessage::NewTransaction(tx) => check_transaction(tx, limits),
+            NetworkMessage::Vote(vote) => {
+                if vote.drama_level > 10 {
+                    return Err(Error::InvalidMessage(format!(
+                        "Vote of {} has drama level {}",
+                        vote.validator, vote.drama_level
+                    )));
+                }
+                if vote.signature.len() != 64 {
+                    return Err(Error::InvalidSignature(format!(
+                        "Vote of {} carries a {} byte signature",
+                        vote.validator,
+                        vote.signature.len()
+                    )));
+                }
+                text("Vote reason", &vote.reason)
+            }
+            NetworkMessage::Chat { message, .. } => text("Chat message", message),
+            NetworkMessage::AgentReasoning { reasoning, .. } => text("Reasoning", reasoning),
+            NetworkMessage::Rumor(rumor) => {
+                if !(0.0..=1.0).contains(&rumor.credibility) {
+                    return Err(Error::InvalidMessage(format!(
+                        "Rumor credibility {} out of range",
+                        rumor.credibility
+                    )));
+                }
+                let recomputed = crate::rumor::Rumor::new(
+                    &rumor.origin,
+                    &rumor.subject,
+                    &rumor.claim,
+                    rumor.height,
+                    0.0,
+                );
+                if recomputed.id != rumor.id {
+                    return Err(Error::InvalidMessage(
+                        "Rumor id does not match its content".to_string(),
+                    ));
+                }
+                text("Rumor", &rumor.claim)
+            }
+            NetworkMessage::Signed(envelope) => {
+                envelope.verify()?;
+                envelope.message.check(limits)
+            }
+            NetworkMessage::Threshold(message) => message.check(),
+        }
+    }
+}
+
+fn check_block(block: &Block, limits: &GossipLimits) -> Result<(), Error> {
+    if block.drama_level > 10 {
+        return Err(Error::InvalidBlock(format!(
+            "Drama level {} is off the scale",
+            block.drama_level
+        )));
+    }
+    if block.transactions.len() > limits.max_transactions {
+        return Err(Error::InvalidBlock(format!(
+            "{} transactions, at most {} allowed",
+            block.transactions.len(),
+            limits.max_transactions
+        )));
+    }
+    check_text("Producer mood", &block.producer_mood, limits)?;
+    for tx in &block.transactions {
+        check_transaction(tx, limits)?;
+    }
+    Ok(())
+}
+
+fn check_text(what: &str, text: &str, limits: &GossipLimits) -> Result<(), Error> {
+    if text.len() > limits.max_text {
+        Err(Error::InvalidMessage(format!(
+            "{} is {} bytes, at most {} allowed",
+            what,
+            text.len(),
+            limits.max_text
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_transaction(tx: &Transaction, limits: &GossipLimits) -> Result<(), Error> {
+    if tx.payload.len() > limits.max_payload {
+        return Err(Error::InvalidTransaction(format!(
+            "Payload is {} bytes, at most {} allowed",
+            tx.payload.len(),
+            limits.max_payload
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rumor::Rumor;
+    use crate::vote::SignedVote;
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_messages_are_checked_per_topic() {
+        for topic in GossipTopic::ALL {
+            assert_eq!(GossipTopic::from_name(topic.name()), Some(topic));
+        }
+        let limits = GossipLimits::default();
+
+        let vote = SignedVote::sign(
+            &SigningKey::from_bytes(&[7; 32]),
+            "validator-1",
+            3,
+            [1; 32],
+            true,
+            6,
+            "Delightful chaos",
+        );
+        let message = NetworkMessage::Vote(vote.clone());
+        assert_eq!(message.topic(), GossipTopic::Votes);
+        assert!(message.check(&limits).is_ok());
+        let mut forged = vote;
+        forged.signature.truncate(10);
+        assert!(NetworkMessage::Vote(forged).check(&limits).is_err());
+
+        let chat = NetworkMessage::Chat {
+            from: "validator-1".to_string(),
+            message: "!".repeat(limits.max_text + 1),
+        };
+        assert_eq!(chat.topic(), GossipTopic::Drama);
+        assert!(chat.check(&limits).is_err());
+
+        let rumor = Rumor::new("validator-0", "producer-1", "is double-dealing", 10, 0.9);
+        assert!(NetworkMessage::Rumor(rumor.clone()).check(&limits).is_ok());
+        let mut doctored = rumor.relayed("validator-2", 0.5);
+        doctored.claim = "is a saint".to_string();
+        assert!(NetworkMessage::Rumor(doctored).check(&limits).is_err());
+    }
+}
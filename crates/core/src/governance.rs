@@ -0,0 +1,880 @@
+//! On-chain governance. Anyone puts a [`Proposal`] to the chain with a
+//! system transaction whose payload is [`PROPOSAL_PREFIX`] then the
+//! proposal as JSON, and it is voted on for the `voting_period` of the
+//! genesis's [`GovernanceParams`], from the block after its own, with
+//! [`Ballot`]s of [`BALLOT_PREFIX`]. A voter's latest ballot is the one
+//! that counts.
+//!
+//! Validators vote with the stake bonded to their keys, see
+//! [`crate::staking`], at first the one the genesis gives them, and token
+//! holders with their balance when the vote ends. A proposal passes when
+//! the validators voting hold the quorum of the stake, at least the
+//! validator threshold of their stake approves, and, when token holders
+//! vote too, at least the holder threshold of their balance does. What a
+//! passed proposal does is carried out at the first block of the next
+//! epoch:
+//!
+//! - a parameter change takes effect on the [`ConsensusParams`] of the
+//!   chain
+//! - an upgrade of the protocol activates, see [`crate::fork`]
+//! - a treasury spend pays from the [`treasury`] account, failing when it
+//!   holds too little
+//!
+//! Whoever executes blocks keeps a [`Governance`] and applies every block
+//! to it in order, then ends the block with [`Governance::end_block`].
+
+use crate::fork::{Activation, ScheduledUpgrade, Upgrade};
+use crate::genesis::{ConsensusParams, Genesis};
+use crate::vote::hex_array;
+use crate::{Error, Transaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+/// Start of the payload of a proposal
+pub const PROPOSAL_PREFIX: &[u8] = b"chaoschain/propose:";
+
+/// Start of the payload of a ballot
+pub const BALLOT_PREFIX: &[u8] = b"chaoschain/ballot:";
+
+/// What the treasury address is the hash of
+pub const TREASURY_DOMAIN: &[u8] = b"chaoschain-treasury";
+
+/// Blocks a proposal is voted on for, unless a network sets its own
+pub const DEFAULT_VOTING_PERIOD: u64 = 100;
+
+/// The account treasury spends are paid from, which no key holds. It is
+/// funded like any other, by a genesis allocation or a transfer.
+pub fn treasury() -> [u8; 32] {
+    Sha256::digest(TREASURY_DOMAIN).into()
+}
+
+/// How proposals are voted on, part of the genesis
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GovernanceParams {
+    /// Blocks a proposal is voted on for, after the one putting it
+    pub voting_period: u64,
+    /// Share of the validator stake that has to vote
+    pub quorum: f64,
+    /// Share of the stake of the validators voting that has to approve,
+    /// at least
+    pub validator_threshold: f64,
+    /// Share of the balance of the token holders voting that has to
+    /// approve, at least, when any vote
+    pub holder_threshold: f64,
+}
+
+impl Default for GovernanceParams {
+    fn default() -> Self {
+        Self {
+            voting_period: DEFAULT_VOTING_PERIOD,
+            quorum: 0.4,
+            validator_threshold: 0.5,
+            holder_threshold: 0.5,
+        }
+    }
+}
+
+impl GovernanceParams {
+    /// The defaults, left out of the genesis for the hash of networks from
+    /// before governance
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// What keeps proposals from being voted on, nothing when they can be
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.voting_period == 0 {
+            problems.push("The voting period is 0".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.quorum) {
+            problems.push(format!(
+                "A quorum of {} is not a share of the stake",
+                self.quorum
+            ));
+        }
+        for (house, threshold) in [
+            ("validator", self.validator_threshold),
+            ("holder", self.holder_threshold),
+        ] {
+            if !(0.5..1.0).contains(&threshold) {
+                problems.push(format!(
+                    "A {} threshold of {} is not at least one half and below one",
+                    house, threshold
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// A consensus parameter and the value a proposal sets it to
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    FinalityThreshold(f64),
+    BlockTime(u64),
+    ConsensusTimeout(u64),
+    RotationGrace(u64),
+}
+
+impl Change {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Change::FinalityThreshold(_) => "finality-threshold",
+            Change::BlockTime(_) => "block-time",
+            Change::ConsensusTimeout(_) => "consensus-timeout",
+            Change::RotationGrace(_) => "rotation-grace",
+        }
+    }
+
+    /// Why the chain could not run with the value, if it could not
+    pub fn problem(&self) -> Option<String> {
+        match *self {
+            Change::FinalityThreshold(threshold) if !(threshold > 0.5 && threshold <= 1.0) => {
+                Some(format!(
+                    "A finality threshold of {} is not above one half and at most one",
+                    threshold
+                ))
+            }
+            Change::BlockTime(0) => Some("The block time is 0".to_string()),
+            Change::ConsensusTimeout(0) => Some("The consensus timeout is 0".to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, params: &mut ConsensusParams) {
+        match *self {
+            Change::FinalityThreshold(threshold) => params.finality_threshold = threshold,
+            Change::BlockTime(seconds) => params.block_time = seconds,
+            Change::ConsensusTimeout(seconds) => params.consensus_timeout = seconds,
+            Change::RotationGrace(blocks) => params.rotation_grace = blocks,
+        }
+    }
+}
+
+/// What a passed proposal does
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Parameter(Change),
+    Upgrade(Upgrade),
+    /// Pay `amount` from the treasury to `to`
+    Spend {
+        /// Hex encoded in JSON
+        #[serde(with = "hex_array")]
+        to: [u8; 32],
+        amount: u64,
+    },
+}
+
+impl Action {
+    /// Why it could not be carried out whatever the vote, if it could not
+    pub fn problem(&self) -> Option<String> {
+        match self {
+            Action::Parameter(change) => change.problem(),
+            Action::Upgrade(_) => None,
+            Action::Spend { amount: 0, .. } => Some("It spends nothing".to_string()),
+            Action::Spend { to, .. } if *to == treasury() => {
+                Some("It pays the treasury".to_string())
+            }
+            Action::Spend { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Parameter(Change::FinalityThreshold(threshold)) => {
+                write!(f, "finality-threshold={}", threshold)
+            }
+            Action::Parameter(
+                change @ (Change::BlockTime(value)
+                | Change::ConsensusTimeout(value)
+                | Change::RotationGrace(value)),
+            ) => write!(f, "{}={}", change.name(), value),
+            Action::Upgrade(upgrade) => write!(f, "upgrade={}", upgrade),
+            Action::Spend { to, amount } => write!(f, "spend={}:{}", hex::encode(to), amount),
+        }
+    }
+}
+
+/// An action as the command line gives it: `block-time=10`,
+/// `finality-threshold=0.75`, `upgrade=monotonic-time` or
+/// `spend=ADDRESS:AMOUNT`
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (name, value) = text
+            .split_once('=')
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .ok_or_else(|| format!("{} is not NAME=VALUE", text))?;
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("{} is not a number", value))
+        };
+        Ok(match name {
+            "finality-threshold" => Action::Parameter(Change::FinalityThreshold(
+                value
+                    .parse()
+                    .map_err(|_| format!("{} is not a share", value))?,
+            )),
+            "block-time" => Action::Parameter(Change::BlockTime(number()?)),
+            "consensus-timeout" => Action::Parameter(Change::ConsensusTimeout(number()?)),
+            "rotation-grace" => Action::Parameter(Change::RotationGrace(number()?)),
+            "upgrade" => Action::Upgrade(value.parse()?),
+            "spend" => {
+                let (to, amount) = value
+                    .split_once(':')
+                    .ok_or_else(|| format!("{} is not ADDRESS:AMOUNT", value))?;
+                Action::Spend {
+                    to: hex::decode(to.trim_start_matches("0x"))
+                        .ok()
+                        .and_then(|to| <[u8; 32]>::try_from(to).ok())
+                        .ok_or_else(|| format!("{} is not an address", to))?,
+                    amount: amount
+                        .parse()
+                        .map_err(|_| format!("{} is not an amount", amount))?,
+                }
+            }
+            _ => {
+                return Err(format!(
+                "No action {}, one of finality-threshold, block-time, consensus-timeout, rotation-grace, upgrade and spend",
+                name
+            ))
+            }
+        })
+    }
+}
+
+/// What is put to the vote
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Proposal {
+    pub title: String,
+    pub action: Action,
+}
+
+impl Proposal {
+    /// The payload of the transaction putting it to the vote
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = PROPOSAL_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("a proposal serializes"));
+        payload
+    }
+
+    /// The proposal `tx` puts, none when it puts none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(PROPOSAL_PREFIX)?;
+        Some(serde_json::from_slice(record).map_err(|e| {
+            Error::InvalidTransaction(format!("Proposal {}: {}", hex::encode(tx.hash()), e))
+        }))
+    }
+}
+
+/// A vote on a proposal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ballot {
+    /// Hash of the transaction putting the proposal, hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub proposal: [u8; 32],
+    pub approve: bool,
+}
+
+impl Ballot {
+    /// The payload of the transaction casting it
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = BALLOT_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("a ballot serializes"));
+        payload
+    }
+
+    /// The ballot `tx` casts, none when it casts none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(BALLOT_PREFIX)?;
+        Some(serde_json::from_slice(record).map_err(|e| {
+            Error::InvalidTransaction(format!("Ballot {}: {}", hex::encode(tx.hash()), e))
+        }))
+    }
+}
+
+/// Balances token holders vote with and treasury spends are paid from
+pub trait Ledger {
+    fn balance(&self, account: &[u8; 32]) -> u64;
+
+    /// Take `amount` from `account`, an error when it holds less
+    fn debit(&mut self, account: &[u8; 32], amount: u64) -> Result<(), String>;
+
+    fn credit(&mut self, account: &[u8; 32], amount: u64);
+
+    /// Move `amount` from `from` to `to`, an error when `from` holds less
+    fn transfer(&mut self, from: &[u8; 32], to: &[u8; 32], amount: u64) -> Result<(), String> {
+        self.debit(from, amount)?;
+        self.credit(to, amount);
+        Ok(())
+    }
+}
+
+/// How the vote on a proposal went
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tally {
+    /// Stake of the validators approving
+    pub validators_for: u64,
+    pub validators_against: u64,
+    /// Balance of the token holders approving
+    pub holders_for: u64,
+    pub holders_against: u64,
+    /// Stake of all the validators
+    pub total_stake: u64,
+}
+
+impl Tally {
+    pub fn passes(&self, params: &GovernanceParams) -> bool {
+        let share = |part: u64, whole: u64| part as f64 / whole as f64;
+        let validators = self.validators_for + self.validators_against;
+        let holders = self.holders_for + self.holders_against;
+        if validators + holders == 0 {
+            return false;
+        }
+        // Without validators, the holders decide alone
+        if self.total_stake > 0
+            && (validators == 0
+                || share(validators, self.total_stake) < params.quorum
+                || share(self.validators_for, validators) < params.validator_threshold)
+        {
+            return false;
+        }
+        holders == 0 || share(self.holders_for, holders) >= params.holder_threshold
+    }
+}
+
+/// Where a proposal stands
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum Status {
+    Voting,
+    /// Passed, and carried out at `executes_at`
+    Passed {
+        executes_at: u64,
+    },
+    Rejected,
+    Executed {
+        height: u64,
+    },
+    /// Passed, but could not be carried out
+    Failed {
+        height: u64,
+        reason: String,
+    },
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Voting => f.write_str("voting"),
+            Status::Passed { executes_at } => {
+                write!(f, "passed, carried out at height {}", executes_at)
+            }
+            Status::Rejected => f.write_str("rejected"),
+            Status::Executed { height } => write!(f, "carried out at height {}", height),
+            Status::Failed { height, reason } => {
+                write!(f, "failed at height {}: {}", height, reason)
+            }
+        }
+    }
+}
+
+/// A proposal put to the chain, and how it went
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProposalRecord {
+    /// Hash of the transaction putting it, hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub id: [u8; 32],
+    #[serde(with = "hex_array")]
+    pub proposer: [u8; 32],
+    pub proposal: Proposal,
+    /// Of the block putting it
+    pub height: u64,
+    /// Last height ballots are taken at
+    pub ends: u64,
+    /// Whether each voter approves, by key
+    #[serde(skip)]
+    pub ballots: BTreeMap<[u8; 32], bool>,
+    /// Once the vote ended
+    pub tally: Option<Tally>,
+    #[serde(flatten)]
+    pub status: Status,
+}
+
+/// The proposals of the chain and the parameters they leave it with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Governance {
+    params: GovernanceParams,
+    /// Passed proposals are carried out at the start of an epoch
+    epoch_length: u64,
+    /// Stake of each validator, by key
+    stakes: HashMap<[u8; 32], u64>,
+    /// The consensus parameters in force
+    parameters: ConsensusParams,
+    proposals: HashMap<[u8; 32], ProposalRecord>,
+}
+
+impl Default for Governance {
+    fn default() -> Self {
+        Self::new(GovernanceParams::default(), ConsensusParams::default())
+    }
+}
+
+impl Governance {
+    /// Governance without validators, in which token holders alone vote
+    pub fn new(params: GovernanceParams, parameters: ConsensusParams) -> Self {
+        Self {
+            params,
+            epoch_length: parameters.forks.epoch_length,
+            stakes: HashMap::new(),
+            parameters,
+            proposals: HashMap::new(),
+        }
+    }
+
+    /// Governance of the network `genesis` starts. Validators whose key is
+    /// not hex are left out, [`Genesis::problems`] names them.
+    pub fn from_genesis(genesis: &Genesis) -> Self {
+        let mut governance = Self::new(genesis.governance, genesis.consensus.clone());
+        for validator in &genesis.validators {
+            if let Some(key) = hex::decode(validator.public_key.trim_start_matches("0x"))
+                .ok()
+                .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            {
+                *governance.stakes.entry(key).or_default() += validator.stake;
+            }
+        }
+        governance
+    }
+
+    pub fn params(&self) -> &GovernanceParams {
+        &self.params
+    }
+
+    /// The consensus parameters passed proposals left the chain with
+    pub fn parameters(&self) -> &ConsensusParams {
+        &self.parameters
+    }
+
+    pub fn proposal(&self, id: &[u8; 32]) -> Option<&ProposalRecord> {
+        self.proposals.get(id)
+    }
+
+    /// Every proposal, the latest first
+    pub fn proposals(&self) -> Vec<&ProposalRecord> {
+        let mut proposals: Vec<&ProposalRecord> = self.proposals.values().collect();
+        proposals.sort_by_key(|record| std::cmp::Reverse((record.height, record.id)));
+        proposals
+    }
+
+    /// The upgrades passed proposals activate, at the height they do
+    pub fn adopted(&self) -> Vec<ScheduledUpgrade> {
+        let mut adopted: Vec<(u64, ScheduledUpgrade)> = self
+            .proposals
+            .values()
+            .filter_map(|record| {
+                let Action::Upgrade(upgrade) = record.proposal.action else {
+                    return None;
+                };
+                let height = match record.status {
+                    Status::Passed { executes_at } => executes_at,
+                    Status::Executed { height } => height,
+                    _ => return None,
+                };
+                Some((
+                    height,
+                    ScheduledUpgrade {
+                        upgrade,
+                        activation: Activation::Height(height),
+                    },
+                ))
+            })
+            .collect();
+        adopted.sort_by_key(|(height, scheduled)| (*height, scheduled.upgrade));
+        adopted
+            .into_iter()
+            .map(|(_, scheduled)| scheduled)
+            .collect()
+    }
+
+    /// First height of the epoch after the one of `height`
+    pub fn next_epoch(&self, height: u64) -> u64 {
+        match self.epoch_length {
+            0 => height.saturating_add(1),
+            length => (height / length).saturating_add(1).saturating_mul(length),
+        }
+    }
+
+    /// Whether `tx`, in a block at `height`, is a proposal or ballot that
+    /// holds. Transactions of neither kind do.
+    pub fn check(&self, tx: &Transaction, height: u64) -> Result<(), Error> {
+        if let Some(proposal) = Proposal::from_transaction(tx) {
+            let proposal = proposal?;
+            if let Some(problem) = proposal.action.problem() {
+                return Err(Error::InvalidTransaction(format!(
+                    "Proposal {}: {}",
+                    hex::encode(tx.hash()),
+                    problem
+                )));
+            }
+            return Ok(());
+        }
+        let Some(ballot) = Ballot::from_transaction(tx) else {
+            return Ok(());
+        };
+        let ballot = ballot?;
+        let invalid = |reason: String| {
+            Error::InvalidTransaction(format!(
+                "Ballot {} on {}: {}",
+                hex::encode(tx.hash()),
+                hex::encode(ballot.proposal),
+                reason
+            ))
+        };
+        let record = self
+            .proposals
+            .get(&ballot.proposal)
+            .ok_or_else(|| invalid("there is no such proposal".to_string()))?;
+        if record.status != Status::Voting || height > record.ends {
+            return Err(invalid(format!("the vote ended at height {}", record.ends)));
+        }
+        Ok(())
+    }
+
+    /// Take the proposal or ballot `tx` makes, in a block at `height`,
+    /// once [checked](Self::check)
+    pub fn apply_transaction(&mut self, tx: &Transaction, height: u64) {
+        if let Some(Ok(proposal)) = Proposal::from_transaction(tx) {
+            let id = tx.hash();
+            self.proposals.insert(
+                id,
+                ProposalRecord {
+                    id,
+                    proposer: tx.sender,
+                    proposal,
+                    height,
+                    ends: height.saturating_add(self.params.voting_period),
+                    ballots: BTreeMap::new(),
+                    tally: None,
+                    status: Status::Voting,
+                },
+            );
+        }
+        if let Some(Ok(ballot)) = Ballot::from_transaction(tx) {
+            if let Some(record) = self.proposals.get_mut(&ballot.proposal) {
+                record.ballots.insert(tx.sender, ballot.approve);
+            }
+        }
+    }
+
+    /// Carry out the passed proposals due at `height`, then tally those
+    /// whose vote ends at it, returning the proposals that changed
+    pub fn end_block(&mut self, height: u64, ledger: &mut dyn Ledger) -> Vec<ProposalRecord> {
+        let mut changed = Vec::new();
+        let mut ids: Vec<[u8; 32]> = self.proposals.keys().copied().collect();
+        ids.sort();
+        for id in &ids {
+            let record = &self.proposals[id];
+            if !matches!(record.status, Status::Passed { executes_at } if executes_at <= height) {
+                continue;
+            }
+            let status = match record.proposal.action {
+                Action::Parameter(change) => {
+                    change.apply(&mut self.parameters);
+                    Status::Executed { height }
+                }
+                Action::Upgrade(_) => Status::Executed { height },
+                Action::Spend { to, amount } => match ledger.transfer(&treasury(), &to, amount) {
+                    Ok(()) => Status::Executed { height },
+                    Err(reason) => Status::Failed { height, reason },
+                },
+            };
+            let record = self.proposals.get_mut(id).expect("the proposal is there");
+            record.status = status;
+            changed.push(record.clone());
+        }
+        for id in &ids {
+            let record = &self.proposals[id];
+            if record.status != Status::Voting || record.ends > height {
+                continue;
+            }
+            let tally = self.tally(record, &*ledger);
+            let status = match tally.passes(&self.params) {
+                true => Status::Passed {
+                    executes_at: self.next_epoch(height),
+                },
+                false => Status::Rejected,
+            };
+            let record = self.proposals.get_mut(id).expect("the proposal is there");
+            record.tally = Some(tally);
+            record.status = status;
+            changed.push(record.clone());
+        }
+        changed
+    }
+
+    /// The ballots of `record` weighed: by stake for validators, and by the
+    /// balance in `ledger` for everyone else
+    pub fn tally(&self, record: &ProposalRecord, ledger: &dyn Ledger) -> Tally {
+        let mut tally = Tally {
+            total_stake: self.stakes.values().sum(),
+            ..Tally::default()
+        };
+        for (voter, approve) in &record.ballots {
+            match (self.stakes.get(voter), approve) {
+                (Some(stake), true) => tally.validators_for += stake,
+                (Some(stake), false) => tally.validators_against += stake,
+                (None, true) => tally.holders_for += ledger.balance(voter),
+                (None, false) => tally.holders_against += ledger.balance(voter),
+            }
+        }
+        tally
+    }
+
+    /// Weigh the ballots of validators by `stakes`, the stake bonded to
+    /// each, from here on
+    pub fn set_stakes(&mut self, stakes: impl IntoIterator<Item = ([u8; 32], u64)>) {
+        self.stakes = stakes.into_iter().collect();
+    }
+
+    /// Governance holding nothing of the proposals, for a view of some
+    pub fn without_proposals(&self) -> Self {
+        Self {
+            proposals: HashMap::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Take what `other` knows of the proposal `id`, in place of what is
+    /// known here
+    pub fn copy_entry(&mut self, other: &Self, id: &[u8; 32]) {
+        match other.proposals.get(id) {
+            Some(record) => self.proposals.insert(*id, record.clone()),
+            None => self.proposals.remove(id),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Balances(HashMap<[u8; 32], u64>);
+
+    impl Ledger for Balances {
+        fn balance(&self, account: &[u8; 32]) -> u64 {
+            self.0.get(account).copied().unwrap_or(0)
+        }
+
+        fn debit(&mut self, account: &[u8; 32], amount: u64) -> Result<(), String> {
+            let held = self.balance(account);
+            if held < amount {
+                return Err(format!("The treasury holds {}", held));
+            }
+            self.0.insert(*account, held - amount);
+            Ok(())
+        }
+
+        fn credit(&mut self, account: &[u8; 32], amount: u64) {
+            *self.0.entry(*account).or_default() += amount;
+        }
+    }
+
+    fn tx(sender: u8, nonce: u64, payload: Vec<u8>) -> Transaction {
+        Transaction {
+            sender: [sender; 32],
+            nonce,
+            payload: payload.into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        }
+    }
+
+    fn governance() -> Governance {
+        let mut governance = Governance::new(
+            GovernanceParams {
+                voting_period: 5,
+                ..GovernanceParams::default()
+            },
+            ConsensusParams {
+                forks: crate::fork::ForkSchedule {
+                    epoch_length: 10,
+                    upgrades: Vec::new(),
+                },
+                ..ConsensusParams::default()
+            },
+        );
+        governance.stakes.insert([1; 32], 60);
+        governance.stakes.insert([2; 32], 40);
+        governance
+    }
+
+    /// Put `action` at height 1, returning its id
+    fn propose(governance: &mut Governance, action: Action) -> [u8; 32] {
+        let put = tx(
+            9,
+            0,
+            Proposal {
+                title: "Change".to_string(),
+                action,
+            }
+            .payload(),
+        );
+        governance.check(&put, 1).unwrap();
+        governance.apply_transaction(&put, 1);
+        put.hash()
+    }
+
+    fn vote(
+        governance: &mut Governance,
+        voter: u8,
+        proposal: [u8; 32],
+        approve: bool,
+        height: u64,
+    ) {
+        let cast = tx(voter, 1, Ballot { proposal, approve }.payload());
+        governance.check(&cast, height).unwrap();
+        governance.apply_transaction(&cast, height);
+    }
+
+    #[test]
+    fn test_passed_change_executes_at_the_next_epoch() {
+        let mut governance = governance();
+        let mut ledger = Balances::default();
+        let id = propose(&mut governance, "block-time=9".parse().unwrap());
+        vote(&mut governance, 1, id, true, 2);
+        vote(&mut governance, 2, id, false, 3);
+        assert!(governance.end_block(5, &mut ledger).is_empty());
+
+        let tallied = governance.end_block(6, &mut ledger);
+        assert_eq!(tallied[0].status, Status::Passed { executes_at: 10 });
+        let late = tx(
+            2,
+            2,
+            Ballot {
+                proposal: id,
+                approve: true,
+            }
+            .payload(),
+        );
+        assert!(governance.check(&late, 7).is_err());
+
+        assert!(governance.end_block(9, &mut ledger).is_empty());
+        assert_eq!(governance.parameters().block_time, 5);
+        governance.end_block(10, &mut ledger);
+        assert_eq!(governance.parameters().block_time, 9);
+        assert_eq!(
+            governance.proposal(&id).unwrap().status,
+            Status::Executed { height: 10 }
+        );
+    }
+
+    #[test]
+    fn test_tally_thresholds() {
+        let mut governance = governance();
+        let mut ledger = Balances::default();
+        ledger.0.insert([7; 32], 1_000);
+        ledger.0.insert([8; 32], 10);
+
+        // The validators approve, but the holders turn it down
+        let turned_down = propose(&mut governance, "upgrade=monotonic-time".parse().unwrap());
+        vote(&mut governance, 1, turned_down, true, 2);
+        vote(&mut governance, 7, turned_down, false, 2);
+        vote(&mut governance, 8, turned_down, true, 2);
+        // Short of the quorum of the stake
+        let unattended = propose(&mut governance, "upgrade=drama-rewards".parse().unwrap());
+        vote(&mut governance, 8, unattended, true, 2);
+        // The latest ballot counts
+        let passed = propose(&mut governance, "upgrade=bounded-levels".parse().unwrap());
+        vote(&mut governance, 2, passed, false, 2);
+        vote(&mut governance, 2, passed, true, 3);
+        governance.end_block(6, &mut ledger);
+
+        assert_eq!(
+            governance.proposal(&turned_down).unwrap().status,
+            Status::Rejected
+        );
+        assert_eq!(
+            governance.proposal(&unattended).unwrap().status,
+            Status::Rejected
+        );
+        assert_eq!(
+            governance.proposal(&passed).unwrap().tally,
+            Some(Tally {
+                validators_for: 40,
+                total_stake: 100,
+                ..Tally::default()
+            })
+        );
+        assert_eq!(
+            governance.adopted(),
+            vec![ScheduledUpgrade {
+                upgrade: Upgrade::BoundedLevels,
+                activation: Activation::Height(10),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_treasury_spends() {
+        let mut governance = governance();
+        let mut ledger = Balances::default();
+        ledger.0.insert(treasury(), 100);
+        let paid = propose(
+            &mut governance,
+            Action::Spend {
+                to: [5; 32],
+                amount: 70,
+            },
+        );
+        let unpaid = propose(
+            &mut governance,
+            Action::Spend {
+                to: [6; 32],
+                amount: 70,
+            },
+        );
+        for id in [paid, unpaid] {
+            vote(&mut governance, 1, id, true, 2);
+        }
+        governance.end_block(6, &mut ledger);
+        governance.end_block(10, &mut ledger);
+        let statuses = [paid, unpaid].map(|id| governance.proposal(&id).unwrap().status.clone());
+        // Carried out in the order of their ids, until the treasury runs dry
+        assert!(statuses.contains(&Status::Executed { height: 10 }));
+        assert!(statuses
+            .iter()
+            .any(|status| matches!(status, Status::Failed { .. })));
+        assert_eq!(ledger.balance(&treasury()), 30);
+
+        let nothing = tx(
+            9,
+            1,
+            Proposal {
+                title: "Nothing".to_string(),
+                action: Action::Spend {
+                    to: [5; 32],
+                    amount: 0,
+                },
+            }
+            .payload(),
+        );
+        assert!(crate::rotation::is_system(&nothing));
+        assert!(governance.check(&nothing, 1).is_err());
+        assert!("spend=beef:1".parse::<Action>().is_err());
+        let action: Action = "finality-threshold=0.75".parse().unwrap();
+        assert_eq!(action.to_string().parse::<Action>().unwrap(), action);
+    }
+}
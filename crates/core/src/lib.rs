@@ -1,6 +1,10 @@
+pub use bytes::Bytes;
+use chaoschain_crypto::hash::{chain_hash, HashFunction};
+use chaoschain_crypto::scheme::{self, SignatureScheme};
+use negotiation::{NegotiationRecord, Pitch, SupportIndication};
 use serde::{Deserialize, Serialize};
 use serde_arrays;
-use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
 use thiserror::Error;
 
 /// Core error types
@@ -12,6 +16,10 @@ pub enum Error {
     InvalidTransaction(String),
     #[error("State error: {0}")]
     StateError(String),
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(String),
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
 }
 
 /// Network message types for P2P communication
@@ -19,15 +27,31 @@ pub enum Error {
 pub enum NetworkMessage {
     NewBlock(Block),
     NewTransaction(Transaction),
-    Chat { from: String, message: String },
-    AgentReasoning { agent: String, reasoning: String },
+    Chat {
+        from: String,
+        message: String,
+    },
+    AgentReasoning {
+        agent: String,
+        reasoning: String,
+    },
+    /// A validator's signed vote
+    Vote(vote::SignedVote),
+    /// Gossip passed along the rumor topic
+    Rumor(rumor::Rumor),
+    /// A block announced by its header and short transaction ids
+    CompactBlock(compact::CompactBlock),
+    /// Chat, reasoning or a rumor, signed by the agent behind it
+    Signed(envelope::SignedEnvelope),
+    /// A step of the threshold signing of a finalized block
+    Threshold(Box<threshold::ThresholdMessage>),
 }
 
 /// Network event types for agent communication
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NetworkEvent {
     BlockProposal {
-        block: Block,
+        block: Box<Block>,
         drama_level: u8,
         producer_mood: String,
         producer_id: String,
@@ -46,8 +70,62 @@ pub enum NetworkEvent {
         allies: Vec<String>,
         reason: String,
     },
+    /// A producer lobbying validators ahead of a proposal
+    NegotiationPitch { pitch: Pitch },
+    /// A validator answering a producer's pitch
+    NegotiationResponse { response: SupportIndication },
+    /// Partial model output of an agent that is still deciding
+    AgentThought {
+        agent: String,
+        height: u64,
+        chunk: String,
+        /// Set on the last message of a monologue, whose chunk is empty
+        done: bool,
+    },
+    /// Artwork generated for a milestone, served by the web UI under its hash
+    Artwork {
+        /// Hex encoded SHA-256 of the image
+        hash: String,
+        caption: String,
+        height: u64,
+    },
+    /// An unverified claim, started or passed on by an agent
+    Rumor { rumor: rumor::Rumor },
 }
 
+impl NetworkEvent {
+    /// Every [kind](Self::kind) of event
+    pub const KINDS: &'static [&'static str] = &[
+        "block_proposal",
+        "vote",
+        "chat",
+        "alliance",
+        "pitch",
+        "support",
+        "thought",
+        "artwork",
+        "rumor",
+    ];
+
+    /// Short name of the kind of event, for filtering feeds
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NetworkEvent::BlockProposal { .. } => "block_proposal",
+            NetworkEvent::ValidationResult { .. } => "vote",
+            NetworkEvent::AgentChat { .. } => "chat",
+            NetworkEvent::AllianceProposal { .. } => "alliance",
+            NetworkEvent::NegotiationPitch { .. } => "pitch",
+            NetworkEvent::NegotiationResponse { .. } => "support",
+            NetworkEvent::AgentThought { .. } => "thought",
+            NetworkEvent::Artwork { .. } => "artwork",
+            NetworkEvent::Rumor { .. } => "rumor",
+        }
+    }
+}
+
+/// What senders sign transactions with, apart from what signs votes
+pub type TransactionScheme = scheme::Ed25519;
+
 /// Transaction in the ChaosChain network
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Transaction {
@@ -56,21 +134,127 @@ pub struct Transaction {
     pub sender: [u8; 32],
     /// Transaction nonce
     pub nonce: u64,
-    /// Arbitrary payload
-    pub payload: Vec<u8>,
+    /// Arbitrary payload. Clones of the transaction share it, and the
+    /// blocks carrying it; `Vec::from` takes it out to change, copying it
+    /// only when it is shared.
+    #[serde(with = "payload_serde")]
+    pub payload: Bytes,
     /// Transaction signature
     #[serde(with = "serde_arrays")]
     pub signature: [u8; 64],
+    /// Signatures of the owners, for a sender that is a [`multisig`]
+    /// account
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cosignatures: Vec<multisig::Cosignature>,
 }
 
 impl Transaction {
+    /// Bytes the sender signs: its key, the little-endian nonce and the
+    /// payload
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(40 + self.payload.len());
+        bytes.extend_from_slice(&self.sender);
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
+    /// Signed by the sender, under [`TransactionScheme`]. Transactions of
+    /// multisig accounts are checked by
+    /// [`multisig::MultisigAccounts::verify`].
+    pub fn verify(&self) -> Result<(), Error> {
+        TransactionScheme::verify_bytes(&self.sender, &self.signing_bytes(), &self.signature)
+            .map_err(|e| Error::InvalidSignature(e.to_string()))
+    }
+
+    /// Hash under the chain's hash function, see [`chaoschain_crypto::hash`]
     pub fn hash(&self) -> [u8; 32] {
-        let mut hasher = Sha256::new();
-        hasher.update(&self.sender);
-        hasher.update(&self.nonce.to_be_bytes());
+        self.hash_with(chain_hash())
+    }
+
+    pub fn hash_with(&self, function: HashFunction) -> [u8; 32] {
+        let mut hasher = function.hasher();
+        hasher.update(self.sender);
+        hasher.update(self.nonce.to_be_bytes());
         hasher.update(&self.payload);
-        hasher.update(&self.signature);
-        hasher.finalize().into()
+        hasher.update(self.signature);
+        for cosignature in &self.cosignatures {
+            hasher.update(cosignature.owner);
+            hasher.update(&cosignature.signature);
+        }
+        hasher.finalize()
+    }
+}
+
+/// A transaction that keeps its [hash](Transaction::hash) once worked out,
+/// for where the same transaction is looked up by it over and over: the
+/// mempool, gossip and the blocks being packed. It cannot be changed, so
+/// the hash stays its own; clones keep it too. Encoded as the transaction.
+#[derive(Debug, Clone)]
+pub struct HashedTransaction {
+    transaction: Transaction,
+    hash: OnceLock<[u8; 32]>,
+}
+
+impl HashedTransaction {
+    pub fn new(transaction: Transaction) -> Self {
+        Self {
+            transaction,
+            hash: OnceLock::new(),
+        }
+    }
+
+    /// Hash under the chain's hash function, worked out the first time
+    pub fn hash(&self) -> [u8; 32] {
+        *self.hash.get_or_init(|| self.transaction.hash())
+    }
+
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn into_inner(self) -> Transaction {
+        self.transaction
+    }
+}
+
+impl std::ops::Deref for HashedTransaction {
+    type Target = Transaction;
+
+    fn deref(&self) -> &Transaction {
+        &self.transaction
+    }
+}
+
+impl From<Transaction> for HashedTransaction {
+    fn from(transaction: Transaction) -> Self {
+        Self::new(transaction)
+    }
+}
+
+impl From<HashedTransaction> for Transaction {
+    fn from(hashed: HashedTransaction) -> Self {
+        hashed.transaction
+    }
+}
+
+impl PartialEq for HashedTransaction {
+    fn eq(&self, other: &Self) -> bool {
+        self.transaction == other.transaction
+    }
+}
+
+impl Eq for HashedTransaction {}
+
+impl Serialize for HashedTransaction {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.transaction.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for HashedTransaction {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Transaction::deserialize(deserializer).map(Self::new)
     }
 }
 
@@ -102,16 +286,147 @@ pub struct Block {
     pub producer_mood: String,
     /// Timestamp of block creation
     pub timestamp: u64,
+    /// Commentary attached to the block, not covered by the block hash
+    #[serde(default)]
+    pub metadata: BlockMetadata,
+}
+
+/// Off-hash block metadata
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlockMetadata {
+    /// Lobbying that happened before the block was proposed
+    pub negotiation: Option<Box<NegotiationRecord>>,
+    /// Signed explanations of the votes, filled in when the block is finalized
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub explanations: Vec<vote::SignedExplanation>,
+    /// Signature of the validators' group key over
+    /// [`threshold::finality_message`], when the network finalizes with one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_signature: Option<Box<threshold::GroupSignature>>,
+    /// The producer's [`election`] ticket for the height
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leader: Option<Box<election::LeaderTicket>>,
+    /// Randomness of the block, from its group signature, see [`beacon`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beacon: Option<Box<beacon::Beacon>>,
+    /// Messages from other shards the block delivers, on a chain of
+    /// [`shard`]s
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inbox: Vec<shard::Envelope>,
+    /// The [`fork`] upgrades the block was produced under, those active
+    /// at its height
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forks: Vec<fork::Upgrade>,
+}
+
+/// A block without its transactions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub height: u64,
+    pub hash: [u8; 32],
+    pub parent_hash: [u8; 32],
+    pub state_root: [u8; 32],
+    pub producer_id: String,
+    pub timestamp: u64,
+    pub transactions: u32,
+}
+
+impl BlockHeader {
+    pub fn of(block: &Block) -> Self {
+        Self {
+            height: block.height,
+            hash: block.hash(),
+            parent_hash: block.parent_hash,
+            state_root: block.state_root,
+            producer_id: block.producer_id.clone(),
+            timestamp: block.timestamp,
+            transactions: block.transactions.len() as u32,
+        }
+    }
 }
 
 impl Block {
-    /// Calculate the block hash
+    /// The block without its transactions, which are not cloned
+    pub fn header(&self) -> Block {
+        Block {
+            height: self.height,
+            parent_hash: self.parent_hash,
+            transactions: Vec::new(),
+            proposer_sig: self.proposer_sig,
+            state_root: self.state_root,
+            innovation_level: self.innovation_level,
+            producer_strategy: self.producer_strategy.clone(),
+            producer_id: self.producer_id.clone(),
+            drama_level: self.drama_level,
+            producer_mood: self.producer_mood.clone(),
+            timestamp: self.timestamp,
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /// Bytes `proposer_sig` is checked against, with the producer's key
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.extend_from_slice(&self.parent_hash);
+        for tx in &self.transactions {
+            bytes.extend_from_slice(&tx.hash());
+        }
+        bytes.extend_from_slice(&self.state_root);
+        bytes.extend_from_slice(&[self.drama_level]);
+        bytes.extend_from_slice(self.producer_mood.as_bytes());
+        bytes.extend_from_slice(&self.timestamp.to_le_bytes());
+        bytes
+    }
+
+    /// The key the producer signs with: its id when that is a hex encoded
+    /// key, or else the key its leader ticket was drawn with
+    pub fn producer_key(&self) -> Option<[u8; 32]> {
+        hex::decode(
+            self.producer_id
+                .strip_prefix("0x")
+                .unwrap_or(&self.producer_id),
+        )
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .or_else(|| Some(self.metadata.leader.as_ref()?.public_key.to_bytes()))
+    }
+
+    /// Signed by its producer with [`Block::producer_key`], under
+    /// [`TransactionScheme`], and drawn for its height and parent when it
+    /// carries a leader ticket
+    pub fn verify_producer(&self) -> Result<(), Error> {
+        if let Some(ticket) = &self.metadata.leader {
+            ticket.verify(self.height, &self.parent_hash)?;
+        }
+        let key = self.producer_key().ok_or_else(|| {
+            Error::InvalidSignature(format!(
+                "Block {} names no key of producer {}",
+                self.height, self.producer_id
+            ))
+        })?;
+        TransactionScheme::verify_bytes(&key, &self.signing_bytes(), &self.proposer_sig).map_err(
+            |e| {
+                Error::InvalidSignature(format!(
+                    "Signature of producer {} on block {}: {}",
+                    self.producer_id, self.height, e
+                ))
+            },
+        )
+    }
+
+    /// Calculate the block hash, under the chain's hash function
     pub fn hash(&self) -> [u8; 32] {
-        let mut hasher = Sha256::new();
+        self.hash_with(chain_hash())
+    }
+
+    /// Hash under `function`, the transactions' too
+    pub fn hash_with(&self, function: HashFunction) -> [u8; 32] {
+        let mut hasher = function.hasher();
         hasher.update(&self.height.to_be_bytes());
         hasher.update(&self.parent_hash);
         for tx in &self.transactions {
-            hasher.update(&tx.hash());
+            hasher.update(tx.hash_with(function));
         }
         hasher.update(&self.proposer_sig);
         hasher.update(&self.state_root);
@@ -121,7 +436,7 @@ impl Block {
         hasher.update(&[self.drama_level]);
         hasher.update(self.producer_mood.as_bytes());
         hasher.update(&self.timestamp.to_be_bytes());
-        hasher.finalize().into()
+        hasher.finalize()
     }
 }
 
@@ -323,4 +638,44 @@ mod base64_serde {
     }
 }
 
+/// Payloads in the encoding of a `Vec<u8>`, so that neither the wire nor
+/// the store changes, decoded straight into the buffer they are shared
+/// from
+mod payload_serde {
+    use bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(payload: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        payload.as_ref().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(Bytes::from)
+    }
+}
+
+pub mod beacon;
+pub mod channel;
+pub mod clock;
+pub mod commit;
+pub mod compact;
+pub mod compression;
+pub mod election;
+pub mod envelope;
+pub mod fork;
+pub mod genesis;
+pub mod gossip;
+pub mod governance;
 pub mod mempool;
+pub mod metrics;
+pub mod multisig;
+pub mod negotiation;
+pub mod relationships;
+pub mod rotation;
+pub mod rumor;
+pub mod shard;
+pub mod staking;
+pub mod sync;
+pub mod threshold;
+pub mod vote;
+pub mod zk;
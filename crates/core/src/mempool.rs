@@ -1,6 +1,6 @@
-use crate::{Error, Transaction};
+use crate::clock::{self, SharedClock};
+use crate::{Error, HashedTransaction, Transaction};
 use parking_lot::RwLock;
-use sha2::{Digest, Sha256};
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap};
 use std::sync::Arc;
@@ -9,7 +9,7 @@ use std::sync::Arc;
 #[derive(Debug, Clone)]
 pub struct MempoolTx {
     /// The actual transaction
-    pub transaction: Transaction,
+    pub transaction: HashedTransaction,
     /// Time added to mempool
     pub timestamp: u64,
     /// Priority score (higher = more priority)
@@ -46,6 +46,8 @@ pub struct Mempool {
     queue: Arc<RwLock<BinaryHeap<MempoolTx>>>,
     /// Maximum number of transactions
     max_size: usize,
+    /// What transactions are stamped with the time of
+    clock: SharedClock,
 }
 
 impl Mempool {
@@ -55,18 +57,23 @@ impl Mempool {
             txs: Arc::new(RwLock::new(HashMap::new())),
             queue: Arc::new(RwLock::new(BinaryHeap::new())),
             max_size,
+            clock: clock::system(),
         }
     }
 
+    /// Stamp transactions with the time of `clock` rather than the system's
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
     /// Add a transaction to the mempool
     pub fn add_tx(&self, tx: Transaction, priority: u64) -> Result<(), Error> {
-        let tx_hash = self.hash_tx(&tx);
+        let transaction = HashedTransaction::new(tx);
+        let tx_hash = transaction.hash();
         let mempool_tx = MempoolTx {
-            transaction: tx,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            transaction,
+            timestamp: self.clock.unix_secs(),
             priority,
         };
 
@@ -88,14 +95,14 @@ impl Mempool {
     }
 
     /// Get the top N transactions by priority
-    pub fn get_top(&self, n: usize) -> Vec<Transaction> {
+    pub fn get_top(&self, n: usize) -> Vec<HashedTransaction> {
         let txs = self.txs.read();
         let queue = self.queue.read();
 
         queue
             .iter()
             .take(n)
-            .filter(|tx| txs.contains_key(&self.hash_tx(&tx.transaction)))
+            .filter(|tx| txs.contains_key(&tx.transaction.hash()))
             .map(|tx| tx.transaction.clone())
             .collect()
     }
@@ -106,25 +113,18 @@ impl Mempool {
         let mut queue = self.queue.write();
 
         for tx in txs {
-            let tx_hash = self.hash_tx(tx);
+            let tx_hash = tx.hash();
             mempool_txs.remove(&tx_hash);
-            queue.retain(|mempool_tx| mempool_tx.transaction != *tx);
+            queue.retain(|mempool_tx| *mempool_tx.transaction != *tx);
         }
     }
-
-    /// Calculate transaction hash
-    fn hash_tx(&self, tx: &Transaction) -> [u8; 32] {
-        let mut hasher = Sha256::new();
-        hasher.update(&tx.sender);
-        hasher.update(&tx.nonce.to_le_bytes());
-        hasher.update(&tx.payload);
-        hasher.finalize().into()
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::MockClock;
+    use std::time::Duration;
 
     #[test]
     fn test_mempool_ordering() {
@@ -134,15 +134,17 @@ mod tests {
         let tx1 = Transaction {
             sender: [1u8; 32],
             nonce: 1,
-            payload: vec![1, 2, 3],
+            payload: vec![1, 2, 3].into(),
             signature: [0u8; 64],
+            cosignatures: Vec::new(),
         };
 
         let tx2 = Transaction {
             sender: [2u8; 32],
             nonce: 2,
-            payload: vec![4, 5, 6],
+            payload: vec![4, 5, 6].into(),
             signature: [0u8; 64],
+            cosignatures: Vec::new(),
         };
 
         // Add transactions
@@ -155,4 +157,24 @@ mod tests {
         assert_eq!(top_txs[0].nonce, 2); // Higher priority first
         assert_eq!(top_txs[1].nonce, 1);
     }
+
+    #[test]
+    fn test_transactions_are_stamped_by_the_clock() {
+        let clock = MockClock::at_unix(1_700_000_000);
+        let mempool = Mempool::new(10).with_clock(clock.shared());
+        let tx = |nonce| Transaction {
+            sender: [1u8; 32],
+            nonce,
+            payload: vec![nonce as u8].into(),
+            signature: [0u8; 64],
+            cosignatures: Vec::new(),
+        };
+        mempool.add_tx(tx(1), 10).unwrap();
+        clock.advance(Duration::from_secs(30));
+        mempool.add_tx(tx(2), 10).unwrap();
+
+        let txs = mempool.txs.read();
+        assert_eq!(txs[&tx(1).hash()].timestamp, 1_700_000_000);
+        assert_eq!(txs[&tx(2).hash()].timestamp, 1_700_000_030);
+    }
 }
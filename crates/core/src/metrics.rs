@@ -0,0 +1,54 @@
+//! The metrics registry of a node. Each subsystem registers its Prometheus
+//! instruments here when it is set up, and whoever serves `/metrics`
+//! encodes the lot, so no crate but the node binary has to know about HTTP.
+
+use parking_lot::Mutex;
+use prometheus_client::encoding::text::encode;
+use prometheus_client::registry::{Metric, Registry};
+use std::fmt;
+use std::sync::Arc;
+
+/// Clones share the registry, so it can be handed to every subsystem
+#[derive(Clone, Default)]
+pub struct MetricsRegistry {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl MetricsRegistry {
+    pub fn register(&self, name: &str, help: &str, metric: impl Metric) {
+        self.registry.lock().register(name, help, metric);
+    }
+
+    /// Everything registered, in the OpenMetrics text format
+    pub fn encode(&self) -> Result<String, fmt::Error> {
+        let mut body = String::new();
+        encode(&mut body, &self.registry.lock())?;
+        Ok(body)
+    }
+}
+
+impl fmt::Debug for MetricsRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsRegistry").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus_client::metrics::counter::Counter;
+
+    #[test]
+    fn test_clones_share_the_registry() {
+        let registry = MetricsRegistry::default();
+        let counter = Counter::<u64>::default();
+        registry
+            .clone()
+            .register("chaoschain_test_events", "Events", counter.clone());
+        counter.inc_by(3);
+
+        let body = registry.encode().unwrap();
+        assert!(body.contains("chaoschain_test_events_total 3"));
+        assert!(body.ends_with("# EOF\n"));
+    }
+}
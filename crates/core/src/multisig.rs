@@ -0,0 +1,325 @@
+//! Accounts of several keys, any `threshold` of which send for the
+//! account: treasuries of alliances, producers run by more than one
+//! operator. An account is created by a system transaction whose payload
+//! is [`MULTISIG_PREFIX`] then the [`MultisigAccount`] as JSON, from any
+//! sender. Its address is the hash of its owners and threshold, and a
+//! transaction from it names that address as the sender and carries a
+//! [`Cosignature`] of each owner signing, over the same bytes a single
+//! sender signs; its own `signature` is left zero.
+//!
+//! Whoever checks transactions keeps a [`MultisigAccounts`], applying the
+//! blocks to it in order: the state, the mempool's admission over RPC and
+//! `verify-chain`. An account is there once the block creating it is.
+
+use crate::vote::{hex_array, hex_vec};
+use crate::{Block, Error, Transaction, TransactionScheme};
+use chaoschain_crypto::scheme::SignatureScheme;
+use chaoschain_crypto::signer::{Signer, SignerError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+
+/// Domain separator of account addresses
+pub const MULTISIG_DOMAIN: &[u8] = b"chaoschain-multisig-v1";
+
+/// Start of the payload of a transaction creating an account
+pub const MULTISIG_PREFIX: &[u8] = b"chaoschain/multisig:";
+
+/// Most owners an account has
+pub const MAX_OWNERS: usize = 16;
+
+/// An owner's signature of a transaction of its account
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Cosignature {
+    /// Hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub owner: [u8; 32],
+    /// Over [`Transaction::signing_bytes`], hex encoded in JSON
+    #[serde(with = "hex_vec")]
+    pub signature: Vec<u8>,
+}
+
+impl Cosignature {
+    pub fn new(tx: &Transaction, owner: &dyn Signer) -> Result<Self, SignerError> {
+        Ok(Self {
+            owner: owner.public_key().to_bytes(),
+            signature: owner.sign(&tx.signing_bytes())?.to_bytes().to_vec(),
+        })
+    }
+}
+
+/// An m-of-n account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultisigAccount {
+    /// Keys of the owners in order, hex encoded in JSON
+    #[serde(with = "hex_keys")]
+    pub owners: Vec<[u8; 32]>,
+    /// Owners that have to sign
+    pub threshold: u16,
+}
+
+impl MultisigAccount {
+    /// The account `threshold` of `owners` send for, in whatever order
+    /// they are given
+    pub fn new(mut owners: Vec<[u8; 32]>, threshold: u16) -> Result<Self, Error> {
+        owners.sort();
+        owners.dedup();
+        let account = Self { owners, threshold };
+        account.check()?;
+        Ok(account)
+    }
+
+    /// The owners are in order without repeats, at most [`MAX_OWNERS`],
+    /// and the threshold reachable
+    pub fn check(&self) -> Result<(), Error> {
+        if self.owners.is_empty() || self.owners.len() > MAX_OWNERS {
+            return Err(Error::InvalidTransaction(format!(
+                "A multisig account has from 1 to {} owners, not {}",
+                MAX_OWNERS,
+                self.owners.len()
+            )));
+        }
+        if self.owners.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(Error::InvalidTransaction(
+                "The owners of a multisig account are in order, each once".to_string(),
+            ));
+        }
+        if self.threshold == 0 || usize::from(self.threshold) > self.owners.len() {
+            return Err(Error::InvalidTransaction(format!(
+                "A threshold of {} is not one of {} owners",
+                self.threshold,
+                self.owners.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// The sender of the account's transactions
+    pub fn address(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(MULTISIG_DOMAIN);
+        hasher.update(self.threshold.to_be_bytes());
+        for owner in &self.owners {
+            hasher.update(owner);
+        }
+        hasher.finalize().into()
+    }
+
+    /// The payload of the transaction creating it
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = MULTISIG_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("an account serializes"));
+        payload
+    }
+
+    /// The account `tx` creates, none when it creates none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(MULTISIG_PREFIX)?;
+        Some(
+            serde_json::from_slice::<Self>(record)
+                .map_err(|e| Error::InvalidTransaction(e.to_string()))
+                .and_then(|account| {
+                    account.check()?;
+                    Ok(account)
+                })
+                .map_err(|e| {
+                    Error::InvalidTransaction(format!(
+                        "Multisig account of {}: {}",
+                        hex::encode(tx.hash()),
+                        e
+                    ))
+                }),
+        )
+    }
+
+    /// `tx` is sent from the account and signed by at least the threshold
+    /// of its owners
+    pub fn verify(&self, tx: &Transaction) -> Result<(), Error> {
+        let invalid = |reason: String| {
+            Error::InvalidSignature(format!(
+                "Multisig transaction {}: {}",
+                hex::encode(tx.hash()),
+                reason
+            ))
+        };
+        if tx.sender != self.address() {
+            return Err(invalid("it is not sent from the account".to_string()));
+        }
+        let message = tx.signing_bytes();
+        let mut signed = HashSet::new();
+        for cosignature in &tx.cosignatures {
+            if self.owners.binary_search(&cosignature.owner).is_err() {
+                return Err(invalid(format!(
+                    "0x{} is not an owner",
+                    hex::encode(cosignature.owner)
+                )));
+            }
+            if !signed.insert(cosignature.owner) {
+                return Err(invalid(format!(
+                    "0x{} signs twice",
+                    hex::encode(cosignature.owner)
+                )));
+            }
+            TransactionScheme::verify_bytes(&cosignature.owner, &message, &cosignature.signature)
+                .map_err(|e| invalid(format!("0x{}: {}", hex::encode(cosignature.owner), e)))?;
+        }
+        if signed.len() < usize::from(self.threshold) {
+            return Err(invalid(format!(
+                "{} of {} owners signed, {} have to",
+                signed.len(),
+                self.owners.len(),
+                self.threshold
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The multisig accounts of the chain, by address
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MultisigAccounts {
+    accounts: HashMap<[u8; 32], MultisigAccount>,
+}
+
+impl MultisigAccounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, address: &[u8; 32]) -> Option<&MultisigAccount> {
+        self.accounts.get(address)
+    }
+
+    pub fn insert(&mut self, account: MultisigAccount) {
+        self.accounts.insert(account.address(), account);
+    }
+
+    /// Whether `tx` is sent from a multisig account, one known here or
+    /// one it signs for as owners do
+    pub fn is_multisig(&self, tx: &Transaction) -> bool {
+        !tx.cosignatures.is_empty() || self.accounts.contains_key(&tx.sender)
+    }
+
+    /// `tx` is signed by its sender, or by enough owners of the account it
+    /// is sent from
+    pub fn verify(&self, tx: &Transaction) -> Result<(), Error> {
+        if !self.is_multisig(tx) {
+            return tx.verify();
+        }
+        match self.accounts.get(&tx.sender) {
+            Some(account) => account.verify(tx),
+            None => Err(Error::InvalidSignature(format!(
+                "Transaction {} carries cosignatures, 0x{} is no multisig account",
+                hex::encode(tx.hash()),
+                hex::encode(tx.sender)
+            ))),
+        }
+    }
+
+    /// Take the account `tx` creates, returning it. One that does not hold
+    /// is passed over.
+    pub fn apply_transaction(&mut self, tx: &Transaction) -> Option<MultisigAccount> {
+        match MultisigAccount::from_transaction(tx)? {
+            Ok(account) => {
+                self.insert(account.clone());
+                Some(account)
+            }
+            Err(e) => {
+                tracing::warn!("Passing over {}", e);
+                None
+            }
+        }
+    }
+
+    /// Take the accounts `block` creates, in order, returning them
+    pub fn apply_block(&mut self, block: &Block) -> Vec<MultisigAccount> {
+        block
+            .transactions
+            .iter()
+            .filter_map(|tx| self.apply_transaction(tx))
+            .collect()
+    }
+}
+
+mod hex_keys {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(keys: &[[u8; 32]], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(keys.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<[u8; 32]>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|text| {
+                let mut key = [0u8; 32];
+                hex::decode_to_slice(text.trim_start_matches("0x"), &mut key)
+                    .map_err(serde::de::Error::custom)?;
+                Ok(key)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_multisig() {
+        let owners: Vec<SigningKey> = (1..=3).map(|i| SigningKey::from_bytes(&[i; 32])).collect();
+        let keys: Vec<[u8; 32]> = owners
+            .iter()
+            .map(|owner| owner.verifying_key().to_bytes())
+            .collect();
+        let account = MultisigAccount::new(keys.iter().rev().copied().collect(), 2).unwrap();
+        assert_eq!(MultisigAccount::new(keys.clone(), 2).unwrap(), account);
+        assert!(MultisigAccount::new(keys.clone(), 0).is_err());
+        assert!(MultisigAccount::new(keys.clone(), 4).is_err());
+
+        let create = Transaction {
+            sender: keys[0],
+            nonce: 0,
+            payload: account.payload().into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        };
+        assert!(crate::rotation::is_system(&create));
+        let mut accounts = MultisigAccounts::new();
+        assert_eq!(accounts.apply_transaction(&create), Some(account.clone()));
+        assert_eq!(accounts.get(&account.address()), Some(&account));
+
+        let mut tx = Transaction {
+            sender: account.address(),
+            nonce: 0,
+            payload: b"pay the alliance".to_vec().into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        };
+        let cosign = |tx: &Transaction, owner: &SigningKey| Cosignature::new(tx, owner).unwrap();
+        tx.cosignatures = vec![cosign(&tx, &owners[0])];
+        assert!(accounts.verify(&tx).is_err());
+        tx.cosignatures.push(cosign(&tx, &owners[0]));
+        assert!(accounts.verify(&tx).is_err());
+        tx.cosignatures[1] = cosign(&tx, &owners[2]);
+        accounts.verify(&tx).unwrap();
+
+        // Signed for another nonce, by an outsider, or with no cosignatures
+        let mut replayed = tx.clone();
+        replayed.nonce = 1;
+        assert!(accounts.verify(&replayed).is_err());
+        let mut outsider = tx.clone();
+        outsider.cosignatures[1] = cosign(&tx, &SigningKey::from_bytes(&[9; 32]));
+        assert!(accounts.verify(&outsider).is_err());
+        let mut bare = tx.clone();
+        bare.cosignatures.clear();
+        assert!(accounts.verify(&bare).is_err());
+        assert!(MultisigAccounts::new().verify(&tx).is_err());
+
+        let json = serde_json::to_string(&tx).unwrap();
+        assert_eq!(serde_json::from_str::<Transaction>(&json).unwrap(), tx);
+    }
+}
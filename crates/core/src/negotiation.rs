@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Milliseconds since the unix epoch, the clock negotiation deadlines run on
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// A producer lobbying validators before it proposes a block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pitch {
+    /// Unique id of this negotiation round
+    pub negotiation_id: String,
+    /// Producer making the pitch
+    pub producer: String,
+    /// Height the producer intends to propose
+    pub height: u64,
+    /// The sales talk itself
+    pub message: String,
+    /// Optional sweetener offered to supporters
+    pub offer: Option<u64>,
+    /// Responses received after this instant (unix ms) are ignored
+    pub deadline: u64,
+}
+
+impl Pitch {
+    /// Whether the response window is still open at `now` (unix ms)
+    pub fn is_open(&self, now: u64) -> bool {
+        now <= self.deadline
+    }
+}
+
+/// How a validator feels about a pitched block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SupportLevel {
+    Support,
+    Undecided,
+    Oppose,
+}
+
+/// A validator's answer to a pitch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SupportIndication {
+    /// Negotiation this answers
+    pub negotiation_id: String,
+    /// Responding validator
+    pub validator: String,
+    /// Stated support (not binding, but remembered)
+    pub support: SupportLevel,
+    /// Conditions attached to the support, if any
+    pub demands: Option<String>,
+    /// When the answer was sent (unix ms)
+    pub timestamp: u64,
+}
+
+/// Outcome of a negotiation round, attached to the proposed block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NegotiationRecord {
+    /// The pitch that opened the round
+    pub pitch: Pitch,
+    /// Responses that arrived before the deadline, one per validator
+    pub responses: Vec<SupportIndication>,
+    /// Number of responses dropped for arriving late
+    pub late_responses: u32,
+}
+
+impl NegotiationRecord {
+    pub fn new(pitch: Pitch) -> Self {
+        Self {
+            pitch,
+            responses: Vec::new(),
+            late_responses: 0,
+        }
+    }
+
+    /// Record a response, keeping only the first answer of each validator.
+    /// Returns false if the response was ignored.
+    pub fn record(&mut self, response: SupportIndication) -> bool {
+        if response.negotiation_id != self.pitch.negotiation_id {
+            return false;
+        }
+        if !self.pitch.is_open(response.timestamp) {
+            self.late_responses += 1;
+            return false;
+        }
+        if self
+            .responses
+            .iter()
+            .any(|r| r.validator == response.validator)
+        {
+            return false;
+        }
+        self.responses.push(response);
+        true
+    }
+
+    /// Validators that pledged support
+    pub fn supporters(&self) -> Vec<&str> {
+        self.responses
+            .iter()
+            .filter(|r| r.support == SupportLevel::Support)
+            .map(|r| r.validator.as_str())
+            .collect()
+    }
+
+    /// What a given validator told the producer, if anything
+    pub fn response_of(&self, validator: &str) -> Option<&SupportIndication> {
+        self.responses.iter().find(|r| r.validator == validator)
+    }
+
+    /// Share of responders that pledged support (0.0 - 1.0)
+    pub fn support_ratio(&self) -> f64 {
+        if self.responses.is_empty() {
+            return 0.0;
+        }
+        self.supporters().len() as f64 / self.responses.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(validator: &str, support: SupportLevel, timestamp: u64) -> SupportIndication {
+        SupportIndication {
+            negotiation_id: "n-1".to_string(),
+            validator: validator.to_string(),
+            support,
+            demands: None,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn test_record_enforces_deadline_and_dedup() {
+        let mut record = NegotiationRecord::new(Pitch {
+            negotiation_id: "n-1".to_string(),
+            producer: "producer-0".to_string(),
+            height: 7,
+            message: "Vote for me".to_string(),
+            offer: Some(10),
+            deadline: 1_000,
+        });
+
+        assert!(record.record(response("validator-0", SupportLevel::Support, 500)));
+        assert!(!record.record(response("validator-0", SupportLevel::Oppose, 600)));
+        assert!(record.record(response("validator-1", SupportLevel::Oppose, 900)));
+        assert!(!record.record(response("validator-2", SupportLevel::Support, 1_001)));
+
+        assert_eq!(record.late_responses, 1);
+        assert_eq!(record.supporters(), vec!["validator-0"]);
+        assert_eq!(record.support_ratio(), 0.5);
+    }
+}
@@ -0,0 +1,373 @@
+use crate::Error;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Changes kept for [`RelationshipGraph::changes_between`], oldest dropped first
+const CHANGES_KEPT: usize = 10_000;
+
+/// Pairwise relationship between two agents, as seen from `from`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Relationship {
+    /// How much `from` trusts `to` (0.0 - 1.0)
+    pub trust: f64,
+    /// How strongly `from` sees `to` as a rival (0.0 - 1.0)
+    pub rivalry: f64,
+    /// Dramatic affection, because why not (0.0 - 1.0)
+    pub romance: f64,
+    /// Favors owed: positive means `from` owes `to`, negative means `to` owes `from`
+    pub debt: i64,
+    /// Number of recorded interactions
+    pub interactions: u64,
+    /// Timestamp of the last interaction
+    pub last_interaction: u64,
+}
+
+impl Default for Relationship {
+    fn default() -> Self {
+        Self {
+            trust: 0.5,
+            rivalry: 0.0,
+            romance: 0.0,
+            debt: 0,
+            interactions: 0,
+            last_interaction: 0,
+        }
+    }
+}
+
+impl Relationship {
+    /// Overall disposition of `from` towards `to` (-1.0 hostile, 1.0 devoted)
+    pub fn disposition(&self) -> f64 {
+        let debt_pull = (self.debt as f64 / 1000.0).clamp(-0.2, 0.2);
+        ((self.trust - 0.5) * 2.0 - self.rivalry + self.romance * 0.5 + debt_pull).clamp(-1.0, 1.0)
+    }
+
+    /// What the relationship mostly is, from the strongest of its feelings
+    pub fn kind(&self) -> RelationshipKind {
+        let trust = (self.trust - 0.5) * 2.0;
+        let strongest = [
+            (RelationshipKind::Ally, trust),
+            (RelationshipKind::Rival, self.rivalry.max(-trust)),
+            (RelationshipKind::Romance, self.romance),
+        ]
+        .into_iter()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .filter(|(_, strength)| *strength >= 0.2);
+        match strongest {
+            Some((kind, _)) => kind,
+            None if self.debt != 0 => RelationshipKind::Debt,
+            None => RelationshipKind::Neutral,
+        }
+    }
+
+    fn apply(&mut self, interaction: &Interaction) {
+        match interaction {
+            Interaction::Vote {
+                approved,
+                drama_level,
+            } => {
+                let weight = 0.1 * (1.0 + *drama_level as f64 / 10.0);
+                if *approved {
+                    self.trust += weight;
+                    self.rivalry -= weight / 2.0;
+                } else {
+                    self.trust -= weight;
+                    self.rivalry += weight / 2.0;
+                }
+            }
+            Interaction::AllianceFormed => {
+                self.trust += 0.2;
+                self.rivalry -= 0.1;
+            }
+            Interaction::Betrayal => {
+                self.trust -= 0.4;
+                self.rivalry += 0.3;
+                self.romance -= 0.2;
+            }
+            Interaction::Flirtation => self.romance += 0.1,
+            Interaction::Insult => {
+                self.rivalry += 0.15;
+                self.romance -= 0.05;
+            }
+            Interaction::Favor { amount } => self.debt += *amount as i64,
+            Interaction::Repayment { amount } => self.debt -= *amount as i64,
+        }
+
+        self.trust = self.trust.clamp(0.0, 1.0);
+        self.rivalry = self.rivalry.clamp(0.0, 1.0);
+        self.romance = self.romance.clamp(0.0, 1.0);
+        self.interactions += 1;
+        self.last_interaction = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RelationshipKind {
+    /// Trust above all
+    Ally,
+    /// Rivalry, or distrust
+    Rival,
+    Romance,
+    /// Only favors owed, feelings aside
+    Debt,
+    Neutral,
+}
+
+/// Something that happened between two agents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Interaction {
+    /// `from` voted on a block produced by `to`
+    Vote { approved: bool, drama_level: u8 },
+    /// `from` sealed an alliance with `to`
+    AllianceFormed,
+    /// `to` betrayed `from`
+    Betrayal,
+    /// `to` charmed `from`
+    Flirtation,
+    /// `to` insulted `from`
+    Insult,
+    /// `to` did `from` a favor worth `amount`
+    Favor { amount: u64 },
+    /// `from` paid back `amount` to `to`
+    Repayment { amount: u64 },
+}
+
+/// A directed edge of the relationship graph
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelationshipEdge {
+    pub from: String,
+    pub to: String,
+    #[serde(flatten)]
+    pub relationship: Relationship,
+}
+
+/// An interaction as it was recorded, with the disposition of `from`
+/// towards `to` before and after
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelationshipChange {
+    pub from: String,
+    pub to: String,
+    pub interaction: Interaction,
+    /// Unix seconds
+    pub at: u64,
+    pub before: f64,
+    pub after: f64,
+}
+
+/// Serializable view of the whole social network
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GraphSnapshot {
+    /// Every agent that appears in at least one edge
+    pub nodes: Vec<String>,
+    /// All directed edges
+    pub edges: Vec<RelationshipEdge>,
+}
+
+/// Thread-safe directed graph of agent relationships
+#[derive(Debug, Default)]
+pub struct RelationshipGraph {
+    edges: RwLock<HashMap<(String, String), Relationship>>,
+    /// Latest changes, in memory only
+    changes: RwLock<VecDeque<RelationshipChange>>,
+}
+
+impl RelationshipGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an interaction and return the updated relationship
+    pub fn record(&self, from: &str, to: &str, interaction: Interaction) -> Relationship {
+        let mut edges = self.edges.write();
+        let relationship = edges.entry((from.to_string(), to.to_string())).or_default();
+        let before = relationship.disposition();
+        relationship.apply(&interaction);
+        let mut changes = self.changes.write();
+        if changes.len() >= CHANGES_KEPT {
+            changes.pop_front();
+        }
+        changes.push_back(RelationshipChange {
+            from: from.to_string(),
+            to: to.to_string(),
+            interaction,
+            at: relationship.last_interaction,
+            before,
+            after: relationship.disposition(),
+        });
+        relationship.clone()
+    }
+
+    /// Changes recorded from `from` up to `to` (unix seconds, inclusive),
+    /// oldest first. Only changes since the graph was created or loaded
+    /// are kept, up to the latest 10,000.
+    pub fn changes_between(&self, from: u64, to: u64) -> Vec<RelationshipChange> {
+        self.changes
+            .read()
+            .iter()
+            .filter(|change| change.at >= from && change.at <= to)
+            .cloned()
+            .collect()
+    }
+
+    /// Get the relationship from `from` towards `to`
+    pub fn get(&self, from: &str, to: &str) -> Option<Relationship> {
+        self.edges
+            .read()
+            .get(&(from.to_string(), to.to_string()))
+            .cloned()
+    }
+
+    /// Disposition of `from` towards `to`, neutral when they never met
+    pub fn disposition(&self, from: &str, to: &str) -> f64 {
+        self.get(from, to).map(|r| r.disposition()).unwrap_or(0.0)
+    }
+
+    /// All edges touching an agent, in either direction
+    pub fn relationships_of(&self, agent: &str) -> Vec<RelationshipEdge> {
+        let mut edges: Vec<RelationshipEdge> = self
+            .edges
+            .read()
+            .iter()
+            .filter(|((from, to), _)| from == agent || to == agent)
+            .map(|((from, to), relationship)| RelationshipEdge {
+                from: from.clone(),
+                to: to.clone(),
+                relationship: relationship.clone(),
+            })
+            .collect();
+        edges.sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+        edges
+    }
+
+    /// Snapshot of the full graph
+    pub fn snapshot(&self) -> GraphSnapshot {
+        let edges = self.edges.read();
+        let mut nodes = BTreeSet::new();
+        let mut list: Vec<RelationshipEdge> = edges
+            .iter()
+            .map(|((from, to), relationship)| {
+                nodes.insert(from.clone());
+                nodes.insert(to.clone());
+                RelationshipEdge {
+                    from: from.clone(),
+                    to: to.clone(),
+                    relationship: relationship.clone(),
+                }
+            })
+            .collect();
+        list.sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+
+        GraphSnapshot {
+            nodes: nodes.into_iter().collect(),
+            edges: list,
+        }
+    }
+
+    /// Rebuild a graph from a snapshot
+    pub fn from_snapshot(snapshot: GraphSnapshot) -> Self {
+        let edges = snapshot
+            .edges
+            .into_iter()
+            .map(|edge| ((edge.from, edge.to), edge.relationship))
+            .collect();
+        Self {
+            edges: RwLock::new(edges),
+            changes: RwLock::default(),
+        }
+    }
+
+    /// Persist the graph as JSON. The file is written next to `path` and
+    /// renamed over it, so a node stopped mid-write leaves the old graph.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| Error::StateError(e.to_string()))?;
+        }
+        let json = serde_json::to_vec_pretty(&self.snapshot())
+            .map_err(|e| Error::StateError(e.to_string()))?;
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, json).map_err(|e| Error::StateError(e.to_string()))?;
+        std::fs::rename(&partial, path).map_err(|e| Error::StateError(e.to_string()))
+    }
+
+    /// Load a graph saved with [`RelationshipGraph::save`], or start empty if the file is missing
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Self::new());
+        }
+        let bytes = std::fs::read(path).map_err(|e| Error::StateError(e.to_string()))?;
+        let snapshot: GraphSnapshot =
+            serde_json::from_slice(&bytes).map_err(|e| Error::StateError(e.to_string()))?;
+        Ok(Self::from_snapshot(snapshot))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interactions_update_scores() {
+        let graph = RelationshipGraph::new();
+        graph.record("validator-0", "producer-0", Interaction::AllianceFormed);
+        graph.record(
+            "validator-0",
+            "producer-0",
+            Interaction::Favor { amount: 50 },
+        );
+        let rel = graph.get("validator-0", "producer-0").unwrap();
+        assert!(rel.trust > 0.5);
+        assert_eq!(rel.debt, 50);
+        assert_eq!(rel.interactions, 2);
+
+        graph.record("validator-0", "producer-0", Interaction::Betrayal);
+        assert!(graph.disposition("validator-0", "producer-0") < 0.0);
+        assert!(graph.get("producer-0", "validator-0").is_none());
+        assert_eq!(graph.relationships_of("producer-0").len(), 1);
+    }
+
+    #[test]
+    fn test_kinds_and_changes() {
+        let graph = RelationshipGraph::new();
+        assert_eq!(Relationship::default().kind(), RelationshipKind::Neutral);
+        graph.record("a", "b", Interaction::Favor { amount: 10 });
+        assert_eq!(graph.get("a", "b").unwrap().kind(), RelationshipKind::Debt);
+        graph.record("a", "b", Interaction::AllianceFormed);
+        assert_eq!(graph.get("a", "b").unwrap().kind(), RelationshipKind::Ally);
+        graph.record("b", "a", Interaction::Betrayal);
+        assert_eq!(graph.get("b", "a").unwrap().kind(), RelationshipKind::Rival);
+
+        let changes = graph.changes_between(0, u64::MAX);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[1].interaction, Interaction::AllianceFormed);
+        assert_eq!(changes[1].before, changes[0].after);
+        assert!(changes[2].after < changes[2].before);
+        assert!(graph.changes_between(0, 1).is_empty());
+    }
+
+    #[test]
+    fn test_persistence_roundtrip() {
+        let graph = RelationshipGraph::new();
+        graph.record("a", "b", Interaction::Flirtation);
+        graph.record("b", "a", Interaction::Insult);
+
+        let path = std::env::temp_dir().join(format!(
+            "chaoschain-relationships-{}.json",
+            std::process::id()
+        ));
+        graph.save(&path).unwrap();
+        let loaded = RelationshipGraph::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.snapshot().edges, graph.snapshot().edges);
+        assert_eq!(loaded.snapshot().nodes, vec!["a", "b"]);
+    }
+}
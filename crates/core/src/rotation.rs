@@ -0,0 +1,400 @@
+//! Validators changing keys on chain. A validator moving to a new key
+//! publishes a [`KeyRotation`] as a system transaction: a transaction of
+//! the old key whose payload is [`ROTATION_PREFIX`] then the record as
+//! JSON, which the new key signs too, to prove it is held. Once the block
+//! carrying it is final the new key is the validator's, and the old one is
+//! still taken for the grace period of the genesis, so that votes signed
+//! before the switch still count.
+//!
+//! Whoever follows the validator set keeps a [`ValidatorKeys`], starting
+//! from the genesis, and applies the rotations of every block to it in
+//! order: the consensus engine, a node syncing from its peers, and
+//! `verify-chain`. A key is only taken with a proof that it is held, the
+//! proof of possession of the genesis or the new key's signature of its
+//! rotation, so votes and certificates with any other key are turned
+//! down.
+
+use crate::commit::{COMMIT_PREFIX, REVEAL_PREFIX};
+use crate::genesis::Genesis;
+use crate::governance::{BALLOT_PREFIX, PROPOSAL_PREFIX};
+use crate::multisig::MULTISIG_PREFIX;
+use crate::shard::CROSS_SHARD_PREFIX;
+use crate::staking::{BOND_PREFIX, EVIDENCE_PREFIX, UNBOND_PREFIX};
+use crate::vote::{hex_array, hex_vec, verify_possession};
+use crate::zk::{ZK_KEY_PREFIX, ZK_PROOF_PREFIX};
+use crate::{Block, Error, Transaction};
+use chaoschain_crypto::signer::{Signer, SignerError};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Domain separator of the new key's proof
+pub const ROTATION_DOMAIN: &[u8] = b"chaoschain-rotate-key-v1";
+
+/// Start of the payload of a rotation transaction
+pub const ROTATION_PREFIX: &[u8] = b"chaoschain/rotate-key:";
+
+/// What the new key signs: the validator, then the old key and the new
+pub fn rotation_message(validator: &str, old_key: &[u8; 32], new_key: &[u8; 32]) -> Vec<u8> {
+    let mut message = ROTATION_DOMAIN.to_vec();
+    message.extend_from_slice(validator.as_bytes());
+    message.extend_from_slice(old_key);
+    message.extend_from_slice(new_key);
+    message
+}
+
+/// Whether `tx` is a system transaction rather than a payload for the
+/// agents to judge
+pub fn is_system(tx: &Transaction) -> bool {
+    [
+        ROTATION_PREFIX,
+        MULTISIG_PREFIX,
+        COMMIT_PREFIX,
+        REVEAL_PREFIX,
+        ZK_KEY_PREFIX,
+        ZK_PROOF_PREFIX,
+        CROSS_SHARD_PREFIX,
+        PROPOSAL_PREFIX,
+        BALLOT_PREFIX,
+        BOND_PREFIX,
+        UNBOND_PREFIX,
+        EVIDENCE_PREFIX,
+    ]
+    .iter()
+    .any(|prefix| tx.payload.starts_with(prefix))
+}
+
+/// A validator's move from one key to another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRotation {
+    pub validator: String,
+    /// Hex encoded in JSON, the sender of the transaction
+    #[serde(with = "hex_array")]
+    pub old_key: [u8; 32],
+    #[serde(with = "hex_array")]
+    pub new_key: [u8; 32],
+    /// Signature of the new key over [`rotation_message`], hex encoded in
+    /// JSON
+    #[serde(with = "hex_vec")]
+    pub proof: Vec<u8>,
+}
+
+impl KeyRotation {
+    /// The rotation of `validator` from `old_key` to the key of `new`
+    pub fn new(
+        validator: impl Into<String>,
+        old_key: [u8; 32],
+        new: &dyn Signer,
+    ) -> Result<Self, SignerError> {
+        let validator = validator.into();
+        let new_key = new.public_key().to_bytes();
+        let proof = new
+            .sign(&rotation_message(&validator, &old_key, &new_key))?
+            .to_bytes()
+            .to_vec();
+        Ok(Self {
+            validator,
+            old_key,
+            new_key,
+            proof,
+        })
+    }
+
+    /// The payload of the transaction the old key publishes it in
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = ROTATION_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("a rotation serializes"));
+        payload
+    }
+
+    /// The rotation `tx` publishes, none when it is not a rotation. A
+    /// rotation that does not hold, or that another key than the old one
+    /// signed, is an error.
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(ROTATION_PREFIX)?;
+        let invalid = |reason: String| {
+            Error::InvalidTransaction(format!(
+                "Key rotation {}: {}",
+                hex::encode(tx.hash()),
+                reason
+            ))
+        };
+        Some((|| {
+            let rotation: Self =
+                serde_json::from_slice(record).map_err(|e| invalid(e.to_string()))?;
+            if tx.sender != rotation.old_key {
+                return Err(invalid("it is not sent by the old key".to_string()));
+            }
+            tx.verify()
+                .map_err(|_| invalid("the old key did not sign it".to_string()))?;
+            rotation.verify().map_err(|e| invalid(e.to_string()))?;
+            Ok(rotation)
+        })())
+    }
+
+    /// The new key signed the rotation, and is another key than the old
+    pub fn verify(&self) -> Result<(), Error> {
+        if self.old_key == self.new_key {
+            return Err(Error::InvalidSignature(format!(
+                "{} rotates to the key it has",
+                self.validator
+            )));
+        }
+        let signature = Signature::from_slice(&self.proof)
+            .map_err(|e| Error::InvalidSignature(e.to_string()))?;
+        VerifyingKey::from_bytes(&self.new_key)
+            .and_then(|key| {
+                key.verify(
+                    &rotation_message(&self.validator, &self.old_key, &self.new_key),
+                    &signature,
+                )
+            })
+            .map_err(|e| {
+                Error::InvalidSignature(format!(
+                    "The new key of {} did not sign its rotation: {}",
+                    self.validator, e
+                ))
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ValidatorKey {
+    current: [u8; 32],
+    /// The key before, taken up to the height given
+    previous: Option<([u8; 32], u64)>,
+}
+
+/// The keys of the validator set at a height, as rotations change them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidatorKeys {
+    /// Blocks an old key is still taken for after the one rotating it
+    grace: u64,
+    keys: HashMap<String, ValidatorKey>,
+    /// Validators whose key came without a proof of possession, which
+    /// sign with no key taken
+    unproven: HashSet<String>,
+}
+
+impl ValidatorKeys {
+    pub fn new(grace: u64) -> Self {
+        Self {
+            grace,
+            keys: HashMap::new(),
+            unproven: HashSet::new(),
+        }
+    }
+
+    /// The validators of `genesis` with their keys. Keys that are not hex,
+    /// or without a proof of possession that holds, are left out,
+    /// [`Genesis::problems`] names them.
+    pub fn from_genesis(genesis: &Genesis) -> Self {
+        let mut keys = Self::new(genesis.consensus.rotation_grace);
+        for validator in &genesis.validators {
+            let key = hex::decode(validator.public_key.trim_start_matches("0x"))
+                .ok()
+                .and_then(|key| <[u8; 32]>::try_from(key).ok());
+            let proof = hex::decode(validator.proof_of_possession.trim_start_matches("0x"));
+            let taken = match (key, proof) {
+                (Some(key), Ok(proof)) => keys.insert(&validator.name, key, &proof).is_ok(),
+                _ => false,
+            };
+            if !taken {
+                keys.unproven.insert(validator.name.clone());
+            }
+        }
+        keys
+    }
+
+    /// Take `key` for `validator`, with `proof` of its possession, see
+    /// [`crate::vote::prove_possession`]
+    pub fn insert(&mut self, validator: &str, key: [u8; 32], proof: &[u8]) -> Result<(), Error> {
+        verify_possession(validator, &key, proof)?;
+        self.unproven.remove(validator);
+        self.keys.insert(
+            validator.to_string(),
+            ValidatorKey {
+                current: key,
+                previous: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Whether `validator` is of the set, with a key taken or not
+    pub fn knows(&self, validator: &str) -> bool {
+        self.keys.contains_key(validator) || self.unproven.contains(validator)
+    }
+
+    /// The key `validator` signs with from now on
+    pub fn current(&self, validator: &str) -> Option<[u8; 32]> {
+        self.keys.get(validator).map(|key| key.current)
+    }
+
+    /// Whether `key` is one `validator` signs with at `height`: its
+    /// current key, or the one before within the grace period
+    pub fn accepts(&self, validator: &str, key: &[u8; 32], height: u64) -> bool {
+        self.keys.get(validator).is_some_and(|known| {
+            known.current == *key
+                || known
+                    .previous
+                    .is_some_and(|(previous, until)| previous == *key && height <= until)
+        })
+    }
+
+    /// The validator that signs with `key` at `height`
+    pub fn validator_of(&self, key: &[u8; 32], height: u64) -> Option<&str> {
+        self.keys
+            .keys()
+            .find(|validator| self.accepts(validator, key, height))
+            .map(String::as_str)
+    }
+
+    /// Take `rotation`, of a block at `height`
+    pub fn rotate(&mut self, rotation: &KeyRotation, height: u64) -> Result<(), Error> {
+        let Some(known) = self.keys.get(&rotation.validator) else {
+            return Err(Error::InvalidTransaction(format!(
+                "{} is not a validator, it has no key to rotate",
+                rotation.validator
+            )));
+        };
+        if known.current != rotation.old_key {
+            return Err(Error::InvalidTransaction(format!(
+                "{} signs with another key than the one it rotates from",
+                rotation.validator
+            )));
+        }
+        if self
+            .keys
+            .iter()
+            .any(|(validator, _)| self.accepts(validator, &rotation.new_key, height))
+        {
+            return Err(Error::InvalidTransaction(format!(
+                "{} rotates to a key a validator signs with",
+                rotation.validator
+            )));
+        }
+        let until = height.saturating_add(self.grace);
+        self.keys.insert(
+            rotation.validator.clone(),
+            ValidatorKey {
+                current: rotation.new_key,
+                previous: Some((rotation.old_key, until)),
+            },
+        );
+        Ok(())
+    }
+
+    /// Take the rotations `block` carries, in order, returning those taken.
+    /// One that does not hold is passed over.
+    pub fn apply_block(&mut self, block: &Block) -> Vec<KeyRotation> {
+        let mut applied = Vec::new();
+        for tx in &block.transactions {
+            let Some(rotation) = KeyRotation::from_transaction(tx) else {
+                continue;
+            };
+            match rotation.and_then(|rotation| {
+                self.rotate(&rotation, block.height)?;
+                Ok(rotation)
+            }) {
+                Ok(rotation) => applied.push(rotation),
+                Err(e) => tracing::warn!("Block {} passes over {}", block.height, e),
+            }
+        }
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    fn rotation_tx(old: &SigningKey, rotation: &KeyRotation) -> Transaction {
+        let mut tx = Transaction {
+            sender: old.verifying_key().to_bytes(),
+            nonce: 0,
+            payload: rotation.payload().into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        };
+        tx.signature = ed25519_dalek::Signer::sign(old, &tx.signing_bytes()).to_bytes();
+        tx
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = SigningKey::from_bytes(&[1; 32]);
+        let new = SigningKey::from_bytes(&[2; 32]);
+        let other = SigningKey::from_bytes(&[3; 32]);
+        let old_key = old.verifying_key().to_bytes();
+        let rotation = KeyRotation::new("validator-1", old_key, &new).unwrap();
+        let tx = rotation_tx(&old, &rotation);
+        assert!(is_system(&tx));
+        assert_eq!(
+            KeyRotation::from_transaction(&tx).unwrap().unwrap(),
+            rotation
+        );
+
+        // Sent by another key, or with a proof of another key
+        assert!(
+            KeyRotation::from_transaction(&rotation_tx(&other, &rotation))
+                .unwrap()
+                .is_err()
+        );
+        let mut forged = rotation.clone();
+        forged.new_key = other.verifying_key().to_bytes();
+        assert!(KeyRotation::from_transaction(&rotation_tx(&old, &forged))
+            .unwrap()
+            .is_err());
+        let mut plain = tx.clone();
+        plain.payload = b"hello".to_vec().into();
+        assert!(KeyRotation::from_transaction(&plain).is_none());
+
+        let mut keys = ValidatorKeys::new(5);
+        let proof = |key: &SigningKey, name| crate::vote::prove_possession(key, name).unwrap();
+        assert!(keys
+            .insert("validator-1", old_key, &proof(&old, "validator-2"))
+            .is_err());
+        assert!(!keys.knows("validator-1"));
+        keys.insert("validator-1", old_key, &proof(&old, "validator-1"))
+            .unwrap();
+        keys.insert(
+            "validator-2",
+            other.verifying_key().to_bytes(),
+            &proof(&other, "validator-2"),
+        )
+        .unwrap();
+        let block = Block {
+            height: 10,
+            parent_hash: [0; 32],
+            transactions: vec![plain, tx],
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            producer_id: "producer-1".to_string(),
+            drama_level: 0,
+            producer_mood: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+        assert_eq!(keys.apply_block(&block), vec![rotation.clone()]);
+
+        let new_key = new.verifying_key().to_bytes();
+        assert_eq!(keys.current("validator-1"), Some(new_key));
+        assert!(keys.accepts("validator-1", &new_key, 11));
+        assert!(keys.accepts("validator-1", &old_key, 15));
+        assert!(!keys.accepts("validator-1", &old_key, 16));
+        assert_eq!(keys.validator_of(&old_key, 12), Some("validator-1"));
+        assert_eq!(keys.validator_of(&old_key, 16), None);
+
+        // The old key is gone, and the new one is taken
+        assert!(keys.rotate(&rotation, 20).is_err());
+        let stolen =
+            KeyRotation::new("validator-2", other.verifying_key().to_bytes(), &new).unwrap();
+        assert!(keys.rotate(&stolen, 20).is_err());
+        let unknown = KeyRotation::new("validator-9", [9; 32], &new).unwrap();
+        assert!(keys.rotate(&unknown, 20).is_err());
+    }
+}
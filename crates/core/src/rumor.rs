@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// An unverified claim making the rounds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rumor {
+    /// Hex encoded hash of origin, subject, claim and height
+    pub id: String,
+    /// Agent that made it up, or claims to have witnessed it
+    pub origin: String,
+    /// Agent the rumor is about
+    pub subject: String,
+    /// The claim itself, e.g. "is double-dealing"
+    pub claim: String,
+    /// Height at which the rumor started
+    pub height: u64,
+    /// Agent that passed it on last
+    pub relayed_by: String,
+    /// Times the rumor has been passed on
+    pub hops: u32,
+    /// How much the last teller believes it (0.0 - 1.0)
+    pub credibility: f64,
+}
+
+impl Rumor {
+    /// Start a new rumor
+    pub fn new(origin: &str, subject: &str, claim: &str, height: u64, credibility: f64) -> Self {
+        let mut hasher = Sha256::new();
+        for part in [origin, subject, claim] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(height.to_le_bytes());
+        Self {
+            id: hex::encode(hasher.finalize()),
+            origin: origin.to_string(),
+            subject: subject.to_string(),
+            claim: claim.to_string(),
+            height,
+            relayed_by: origin.to_string(),
+            hops: 0,
+            credibility: credibility.clamp(0.0, 1.0),
+        }
+    }
+
+    /// The rumor as passed on by `teller`, who believes it to `credibility`
+    pub fn relayed(&self, teller: &str, credibility: f64) -> Self {
+        Self {
+            relayed_by: teller.to_string(),
+            hops: self.hops + 1,
+            credibility: credibility.clamp(0.0, 1.0),
+            ..self.clone()
+        }
+    }
+
+    /// The rumor in a sentence
+    pub fn describe(&self) -> String {
+        format!("{} {}", self.subject, self.claim)
+    }
+}
+
+/// How rumors fade
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RumorConfig {
+    /// Credibility kept every time a rumor is passed on
+    pub hop_decay: f64,
+    /// Credibility lost per block since a rumor was heard
+    pub block_decay: f64,
+    /// Rumors believed less than this are forgotten
+    pub forget_below: f64,
+    /// Rumors passed on this often stop spreading
+    pub max_hops: u32,
+}
+
+impl Default for RumorConfig {
+    fn default() -> Self {
+        Self {
+            hop_decay: 0.8,
+            block_decay: 0.05,
+            forget_below: 0.1,
+            max_hops: 6,
+        }
+    }
+}
+
+impl RumorConfig {
+    /// Credibility left of `credibility` after `blocks` blocks
+    pub fn fade(&self, credibility: f64, blocks: u64) -> f64 {
+        credibility * (1.0 - self.block_decay).powi(blocks.min(i32::MAX as u64) as i32)
+    }
+}
+
+/// A rumor as one agent believes it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Belief {
+    pub rumor: Rumor,
+    /// Credibility when last heard or corroborated (0.0 - 1.0)
+    pub credibility: f64,
+    /// Height at which it was last heard
+    pub heard_at: u64,
+    /// Agents that told this agent about it
+    pub sources: Vec<String>,
+}
+
+/// Rumors one agent has heard, and how far it believes them
+#[derive(Debug, Clone)]
+pub struct RumorMill {
+    owner: String,
+    config: RumorConfig,
+    beliefs: HashMap<String, Belief>,
+}
+
+impl RumorMill {
+    pub fn new(owner: &str, config: RumorConfig) -> Self {
+        Self {
+            owner: owner.to_string(),
+            config,
+            beliefs: HashMap::new(),
+        }
+    }
+
+    /// Take in a rumor from its last teller. `trust` is the owner's
+    /// disposition towards the teller (-1.0 hostile to 1.0 devoted).
+    /// Returns the credibility to pass it on with, if it is worth passing on.
+    pub fn hear(&mut self, rumor: &Rumor, trust: f64, height: u64) -> Option<f64> {
+        // Nobody believes what is said about themselves, or their own stories
+        if rumor.subject == self.owner || rumor.relayed_by == self.owner {
+            return None;
+        }
+        let weight = 0.5 + trust.clamp(-1.0, 1.0) * 0.5;
+        let credibility = rumor.credibility * self.config.hop_decay * weight;
+        if credibility < self.config.forget_below {
+            return None;
+        }
+
+        match self.beliefs.get_mut(&rumor.id) {
+            Some(belief) => {
+                if belief.sources.contains(&rumor.relayed_by) {
+                    return None;
+                }
+                // Hearing it from someone else makes it more believable
+                let current = self
+                    .config
+                    .fade(belief.credibility, height.saturating_sub(belief.heard_at));
+                belief.credibility = 1.0 - (1.0 - current) * (1.0 - credibility);
+                belief.heard_at = height;
+                belief.sources.push(rumor.relayed_by.clone());
+                None
+            }
+            None => {
+                self.beliefs.insert(
+                    rumor.id.clone(),
+                    Belief {
+                        rumor: rumor.clone(),
+                        credibility,
+                        heard_at: height,
+                        sources: vec![rumor.relayed_by.clone()],
+                    },
+                );
+                (rumor.hops < self.config.max_hops).then_some(credibility)
+            }
+        }
+    }
+
+    /// Start a rumor of our own, believed to `credibility`
+    pub fn originate(
+        &mut self,
+        subject: &str,
+        claim: &str,
+        height: u64,
+        credibility: f64,
+    ) -> Rumor {
+        let rumor = Rumor::new(&self.owner, subject, claim, height, credibility);
+        self.beliefs.insert(
+            rumor.id.clone(),
+            Belief {
+                rumor: rumor.clone(),
+                credibility: rumor.credibility,
+                heard_at: height,
+                sources: Vec::new(),
+            },
+        );
+        rumor
+    }
+
+    fn decayed(&self, belief: &Belief, height: u64) -> f64 {
+        self.config
+            .fade(belief.credibility, height.saturating_sub(belief.heard_at))
+    }
+
+    /// Rumors still believed at `height`, most credible first
+    pub fn beliefs(&self, height: u64) -> Vec<(Rumor, f64)> {
+        let mut beliefs: Vec<(Rumor, f64)> = self
+            .beliefs
+            .values()
+            .map(|belief| (belief.rumor.clone(), self.decayed(belief, height)))
+            .filter(|(_, credibility)| *credibility >= self.config.forget_below)
+            .collect();
+        beliefs.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.id.cmp(&b.0.id)));
+        beliefs
+    }
+
+    /// How strongly the owner suspects `subject` at `height` (0.0 - 1.0)
+    pub fn suspicion(&self, subject: &str, height: u64) -> f64 {
+        self.beliefs(height)
+            .into_iter()
+            .filter(|(rumor, _)| rumor.subject == subject)
+            .map(|(_, credibility)| credibility)
+            .fold(0.0, f64::max)
+    }
+
+    /// The most credible rumors, as context lines for deliberation
+    pub fn notes(&self, height: u64, limit: usize) -> Vec<String> {
+        self.beliefs(height)
+            .into_iter()
+            .take(limit)
+            .map(|(rumor, credibility)| {
+                format!(
+                    "Rumor has it that {} ({}% credible, heard from {})",
+                    rumor.describe(),
+                    (credibility * 100.0).round(),
+                    rumor.relayed_by
+                )
+            })
+            .collect()
+    }
+
+    /// Drop the rumors nobody believes anymore
+    pub fn forget(&mut self, height: u64) {
+        let config = self.config;
+        self.beliefs.retain(|_, belief| {
+            config.fade(belief.credibility, height.saturating_sub(belief.heard_at))
+                >= config.forget_below
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rumors_spread_decay_and_corroborate() {
+        let mut alice = RumorMill::new("validator-1", RumorConfig::default());
+        let rumor = Rumor::new("validator-0", "producer-1", "is double-dealing", 10, 0.9);
+
+        // A trusted teller is believed, an enemy barely
+        let relay = alice.hear(&rumor, 1.0, 10).unwrap();
+        assert!((relay - 0.72).abs() < 1e-9);
+        let mut bob = RumorMill::new("validator-2", RumorConfig::default());
+        assert_eq!(bob.hear(&rumor, -1.0, 10), None);
+
+        // A second source corroborates, the same one does not
+        let second = rumor.relayed("validator-3", 0.5);
+        assert_eq!(alice.hear(&second, 0.0, 10), None);
+        assert!(alice.suspicion("producer-1", 10) > 0.72);
+        let corroborated = alice.suspicion("producer-1", 10);
+        alice.hear(&second, 0.0, 10);
+        assert_eq!(alice.suspicion("producer-1", 10), corroborated);
+
+        // The subject shrugs it off, and time heals
+        let mut producer = RumorMill::new("producer-1", RumorConfig::default());
+        assert_eq!(producer.hear(&rumor, 1.0, 10), None);
+        assert!(alice.notes(10, 3)[0].contains("producer-1 is double-dealing"));
+        alice.forget(100);
+        assert!(alice.beliefs(100).is_empty());
+
+        // Rumors stop spreading after too many hops
+        let mut tired = rumor.clone();
+        tired.hops = RumorConfig::default().max_hops;
+        assert_eq!(
+            RumorMill::new("validator-4", RumorConfig::default()).hear(&tired, 1.0, 10),
+            None
+        );
+    }
+}
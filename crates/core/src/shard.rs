@@ -0,0 +1,284 @@
+//! Drama shards, an experiment in splitting the cast over chains that run
+//! side by side. Each shard is a chain of its own, with a committee of
+//! agents drawn from the whole cast by [`committees`] that produces and
+//! votes its blocks, and the accounts [`shard_of`] puts on it.
+//!
+//! Shards talk through messages. A transaction whose payload is
+//! [`CROSS_SHARD_PREFIX`] then a [`CrossShardMessage`] as JSON sends one;
+//! once its block is finalized a [`CrossShardQueue`] takes it in, and a
+//! next block of the shard it is for carries it in its metadata, as an
+//! [`Envelope`] naming where it comes from. It is received when that block
+//! is finalized. Each shard receives the messages of another in the order
+//! they were sent, none twice.
+//!
+//! Nothing of this is part of the protocol of a network yet: the shards
+//! live in one node, and their blocks are not gossiped.
+
+use crate::vote::{hex_array, hex_vec};
+use crate::{Block, Error, Transaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// Index of a shard
+pub type ShardId = u16;
+
+/// Domain separator of committee draws
+pub const COMMITTEE_DOMAIN: &[u8] = b"chaoschain-shard-committee-v1";
+
+/// Start of the payload of a transaction sending a message to a shard
+pub const CROSS_SHARD_PREFIX: &[u8] = b"chaoschain/xshard:";
+
+/// The committees of `shards` shards, drawn from `agents` with `seed`.
+/// Every agent sits on one, and the committees differ in size by one at
+/// most; with fewer agents than shards, the last go without.
+pub fn committees(agents: &[String], shards: u16, seed: &[u8; 32]) -> Vec<Vec<String>> {
+    let mut draws: Vec<([u8; 32], &String)> = agents
+        .iter()
+        .map(|agent| {
+            let draw = Sha256::new()
+                .chain_update(COMMITTEE_DOMAIN)
+                .chain_update(seed)
+                .chain_update(agent.as_bytes())
+                .finalize()
+                .into();
+            (draw, agent)
+        })
+        .collect();
+    draws.sort();
+    let mut committees = vec![Vec::new(); shards as usize];
+    if committees.is_empty() {
+        return committees;
+    }
+    for (index, (_, agent)) in draws.into_iter().enumerate() {
+        committees[index % shards as usize].push(agent.clone());
+    }
+    committees
+}
+
+/// The shard an account lives on
+pub fn shard_of(account: &[u8; 32], shards: u16) -> ShardId {
+    (u16::from_be_bytes([account[0], account[1]]) % shards.max(1)) as ShardId
+}
+
+/// A message from one shard to another
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrossShardMessage {
+    pub to: ShardId,
+    /// Hex encoded in JSON
+    #[serde(with = "hex_vec")]
+    pub body: Vec<u8>,
+}
+
+impl CrossShardMessage {
+    /// The payload of the transaction sending it
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = CROSS_SHARD_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("a message serializes"));
+        payload
+    }
+
+    /// The message `tx` sends, none when it sends none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(CROSS_SHARD_PREFIX)?;
+        Some(serde_json::from_slice(record).map_err(|e| {
+            Error::InvalidTransaction(format!(
+                "Cross-shard message of {}: {}",
+                hex::encode(tx.hash()),
+                e
+            ))
+        }))
+    }
+}
+
+/// A message on its way, as the shard it is for receives it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub from: ShardId,
+    pub to: ShardId,
+    /// Of the messages from `from` to `to`, counting from 0
+    pub sequence: u64,
+    /// Of the block of `from` that sent it
+    pub height: u64,
+    /// Hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub sender: [u8; 32],
+    /// Hex encoded in JSON
+    #[serde(with = "hex_vec")]
+    pub body: Vec<u8>,
+}
+
+/// The messages between shards that were sent and not yet received
+#[derive(Debug, Clone, Default)]
+pub struct CrossShardQueue {
+    shards: u16,
+    /// By the shard they are for, in the order they were taken in
+    waiting: BTreeMap<ShardId, VecDeque<Envelope>>,
+    /// Next sequence number from one shard to another
+    sequences: HashMap<(ShardId, ShardId), u64>,
+}
+
+impl CrossShardQueue {
+    pub fn new(shards: u16) -> Self {
+        Self {
+            shards,
+            ..Self::default()
+        }
+    }
+
+    /// Take in the messages `block`, finalized on shard `from`, sends,
+    /// returning how many, and let go of those it received. Those to no
+    /// other shard are passed over.
+    pub fn collect(&mut self, from: ShardId, block: &Block) -> usize {
+        if let Some(waiting) = self.waiting.get_mut(&from) {
+            let received = &block.metadata.inbox;
+            waiting.retain(|envelope| {
+                !received
+                    .iter()
+                    .any(|r| (r.from, r.sequence) == (envelope.from, envelope.sequence))
+            });
+        }
+        let mut collected = 0;
+        for tx in &block.transactions {
+            let message = match CrossShardMessage::from_transaction(tx) {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => {
+                    tracing::warn!("Passing over {}", e);
+                    continue;
+                }
+                None => continue,
+            };
+            if message.to == from || message.to >= self.shards {
+                tracing::warn!(
+                    "Passing over a message from shard {} to shard {}",
+                    from,
+                    message.to
+                );
+                continue;
+            }
+            let sequence = self.sequences.entry((from, message.to)).or_default();
+            self.waiting
+                .entry(message.to)
+                .or_default()
+                .push_back(Envelope {
+                    from,
+                    to: message.to,
+                    sequence: *sequence,
+                    height: block.height,
+                    sender: tx.sender,
+                    body: message.body,
+                });
+            *sequence += 1;
+            collected += 1;
+        }
+        collected
+    }
+
+    /// Up to `limit` of the messages for shard `to`, the longest waiting
+    /// first, for its next block to carry. They wait until that block is
+    /// [collected](Self::collect).
+    pub fn inbox(&self, to: ShardId, limit: usize) -> Vec<Envelope> {
+        self.waiting.get(&to).map_or_else(Vec::new, |waiting| {
+            waiting.iter().take(limit).cloned().collect()
+        })
+    }
+
+    /// Messages waiting for shard `to`
+    pub fn pending(&self, to: ShardId) -> usize {
+        self.waiting.get(&to).map_or(0, VecDeque::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(height: u64, transactions: Vec<Transaction>) -> Block {
+        Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            producer_id: "producer-1".to_string(),
+            drama_level: 0,
+            producer_mood: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        }
+    }
+
+    fn send(sender: u8, to: ShardId, body: &[u8]) -> Transaction {
+        Transaction {
+            sender: [sender; 32],
+            nonce: 0,
+            payload: CrossShardMessage {
+                to,
+                body: body.to_vec(),
+            }
+            .payload()
+            .into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_committees() {
+        let agents: Vec<String> = (0..7).map(|i| format!("validator-{}", i)).collect();
+        let drawn = committees(&agents, 3, &[1; 32]);
+        assert_eq!(
+            drawn.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![3, 2, 2]
+        );
+        let mut seated: Vec<String> = drawn.concat();
+        seated.sort();
+        assert_eq!(seated, agents);
+        // The same for everyone with the seed, another with another
+        assert_eq!(committees(&agents, 3, &[1; 32]), drawn);
+        assert_ne!(committees(&agents, 3, &[2; 32]), drawn);
+        assert!(committees(&agents, 0, &[1; 32]).is_empty());
+        assert!(shard_of(&[0xff; 32], 3) < 3);
+    }
+
+    #[test]
+    fn test_cross_shard_queue() {
+        let tx = send(1, 2, b"hello shard 2");
+        assert!(crate::rotation::is_system(&tx));
+        let mut queue = CrossShardQueue::new(3);
+        let plain = Transaction {
+            payload: b"no message".to_vec().into(),
+            ..send(4, 0, b"")
+        };
+        let sent = vec![tx, plain, send(2, 2, b"again"), send(3, 0, b"to itself")];
+        assert_eq!(queue.collect(0, &block(5, sent)), 2);
+        assert_eq!(queue.collect(1, &block(9, vec![send(5, 2, b"from 1")])), 1);
+        assert_eq!(queue.pending(2), 3);
+        assert_eq!(queue.pending(0), 0);
+
+        // In the order they were taken in, each numbered by its way
+        let first = queue.inbox(2, 2);
+        assert_eq!(
+            first
+                .iter()
+                .map(|e| (e.from, e.sequence, e.height, e.body.as_slice()))
+                .collect::<Vec<_>>(),
+            vec![(0, 0, 5, &b"hello shard 2"[..]), (0, 1, 5, &b"again"[..])]
+        );
+        assert_eq!(first[0].sender, [1; 32]);
+
+        // Received once a block carrying them is finalized, not before
+        assert_eq!(queue.inbox(2, 2), first);
+        let mut carrying = block(1, Vec::new());
+        carrying.metadata.inbox = first;
+        assert_eq!(queue.collect(2, &carrying), 0);
+        let rest = queue.inbox(2, 10);
+        assert_eq!(rest.len(), 1);
+        assert_eq!((rest[0].from, rest[0].sequence), (1, 0));
+        carrying.metadata.inbox = rest;
+        queue.collect(2, &carrying);
+        assert_eq!(queue.pending(2), 0);
+    }
+}
@@ -0,0 +1,866 @@
+//! Staking. An account locks balance as stake with a [`Bond`], a system
+//! transaction whose payload is [`BOND_PREFIX`] then the bond as JSON. A
+//! bond to its own key makes the account a validator, a bond to another
+//! delegates to that validator, which has to have bonded to itself. An
+//! [`Unbond`] of [`UNBOND_PREFIX`] takes stake back: it stays locked, and
+//! can still be slashed, for the `unbonding_period` of the genesis's
+//! [`StakingParams`], then returns to the balance.
+//!
+//! Validators weigh in on consensus and governance with the stake bonded
+//! to them. At the first block of every epoch `epoch_reward` is minted and
+//! shared by the validators by stake, each keeping its `commission_bps` and
+//! sharing the rest with the accounts bonded to it, itself included, by
+//! their bonds. [`Evidence`] of [`EVIDENCE_PREFIX`] that a validator
+//! signed two different votes on a block burns `slash_bps` of every
+//! bond to it and of every unbonding from it, once for each height.
+//!
+//! Bonds are paid for and stake returned when the block is over, so
+//! whoever executes blocks applies every block to a [`Staking`] with
+//! [`Staking::end_block`]. A bond the balance does not cover, or an unbond
+//! of more than is bonded, is left out, as are delegations to accounts
+//! that are not validators; the mempool turns them down ahead with
+//! [`Staking::admit`].
+
+use crate::genesis::Genesis;
+use crate::governance::Ledger;
+use crate::vote::{hex_array, Misbehavior};
+use crate::{Block, Error, Transaction};
+use ed25519_dalek::VerifyingKey;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Start of the payload of a bond
+pub const BOND_PREFIX: &[u8] = b"chaoschain/bond:";
+
+/// Start of the payload of an unbond
+pub const UNBOND_PREFIX: &[u8] = b"chaoschain/unbond:";
+
+/// Start of the payload of evidence against a validator
+pub const EVIDENCE_PREFIX: &[u8] = b"chaoschain/evidence:";
+
+/// Blocks unbonded stake stays locked for, unless a network sets its own
+pub const DEFAULT_UNBONDING_PERIOD: u64 = 100;
+
+/// Basis points in a whole, what commissions and slashes are counted in
+pub const BASIS_POINTS: u32 = 10_000;
+
+/// How stake is locked, paid and slashed, part of the genesis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StakingParams {
+    /// Blocks unbonded stake stays locked, and slashable, for
+    pub unbonding_period: u64,
+    /// Minted at the first block of every epoch for the validators
+    pub epoch_reward: u64,
+    /// Basis points of its reward a validator keeps before sharing the rest
+    pub commission_bps: u32,
+    /// Basis points of the stake behind a validator burned when it misbehaves
+    pub slash_bps: u32,
+}
+
+impl Default for StakingParams {
+    fn default() -> Self {
+        Self {
+            unbonding_period: DEFAULT_UNBONDING_PERIOD,
+            epoch_reward: 1_000,
+            commission_bps: 1_000,
+            slash_bps: 500,
+        }
+    }
+}
+
+impl StakingParams {
+    /// The defaults, left out of the genesis for the hash of networks from
+    /// before staking
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// What keeps stake from being paid or slashed, nothing when it can be
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (name, bps) in [
+            ("commission", self.commission_bps),
+            ("slash", self.slash_bps),
+        ] {
+            if bps > BASIS_POINTS {
+                problems.push(format!(
+                    "A {} of {} basis points is more than the whole",
+                    name, bps
+                ));
+            }
+        }
+        problems
+    }
+}
+
+/// Stake locked with a validator, by the sender of the transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bond {
+    /// Key of the validator, the sender's own to be one, hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub validator: [u8; 32],
+    pub amount: u64,
+}
+
+impl Bond {
+    /// The payload of the transaction bonding
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = BOND_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("a bond serializes"));
+        payload
+    }
+
+    /// The bond `tx` makes, none when it makes none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(BOND_PREFIX)?;
+        Some(serde_json::from_slice(record).map_err(|e| {
+            Error::InvalidTransaction(format!("Bond {}: {}", hex::encode(tx.hash()), e))
+        }))
+    }
+}
+
+/// Stake the sender of the transaction takes back from a validator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unbond {
+    /// Hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub validator: [u8; 32],
+    pub amount: u64,
+}
+
+impl Unbond {
+    /// The payload of the transaction unbonding
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = UNBOND_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("an unbond serializes"));
+        payload
+    }
+
+    /// The unbond `tx` makes, none when it makes none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(UNBOND_PREFIX)?;
+        Some(serde_json::from_slice(record).map_err(|e| {
+            Error::InvalidTransaction(format!("Unbond {}: {}", hex::encode(tx.hash()), e))
+        }))
+    }
+}
+
+/// Proof that the validator of `key` misbehaved, which anyone may send
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Evidence {
+    /// Hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub key: [u8; 32],
+    pub misbehavior: Misbehavior,
+}
+
+impl Evidence {
+    /// The payload of the transaction reporting it
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = EVIDENCE_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("evidence serializes"));
+        payload
+    }
+
+    /// The evidence `tx` reports, none when it reports none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(EVIDENCE_PREFIX)?;
+        Some(serde_json::from_slice(record).map_err(|e| {
+            Error::InvalidTransaction(format!("Evidence {}: {}", hex::encode(tx.hash()), e))
+        }))
+    }
+
+    /// Check the signatures of the misbehavior are of `key`
+    pub fn verify(&self) -> Result<(), Error> {
+        let key = VerifyingKey::from_bytes(&self.key)
+            .map_err(|e| Error::InvalidSignature(format!("Evidence against a bad key: {}", e)))?;
+        self.misbehavior.verify(&key)
+    }
+}
+
+/// Stake on its way back to the account that bonded it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unbonding {
+    /// Hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub delegator: [u8; 32],
+    #[serde(with = "hex_array")]
+    pub validator: [u8; 32],
+    pub amount: u64,
+    /// Height it returns to the balance at
+    pub releases_at: u64,
+}
+
+/// A validator and the stake behind it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorStake {
+    /// Hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub key: [u8; 32],
+    /// Bonded by the validator itself
+    pub self_bond: u64,
+    /// Bonded by everyone else
+    pub delegated: u64,
+}
+
+impl ValidatorStake {
+    pub fn total(&self) -> u64 {
+        self.self_bond.saturating_add(self.delegated)
+    }
+}
+
+/// What ending a block did to the stake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Bonded {
+        delegator: [u8; 32],
+        validator: [u8; 32],
+        amount: u64,
+    },
+    Unbonding {
+        delegator: [u8; 32],
+        validator: [u8; 32],
+        amount: u64,
+        releases_at: u64,
+    },
+    Released {
+        delegator: [u8; 32],
+        amount: u64,
+    },
+    /// Paid to a validator and the accounts bonded to it
+    Rewarded {
+        validator: [u8; 32],
+        amount: u64,
+    },
+    Slashed {
+        validator: [u8; 32],
+        burned: u64,
+    },
+    /// A staking transaction left out, and why
+    Refused {
+        tx: [u8; 32],
+        reason: String,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Bonded {
+                delegator,
+                validator,
+                amount,
+            } => write!(
+                f,
+                "{} bonded {} to {}",
+                hex::encode(delegator),
+                amount,
+                hex::encode(validator)
+            ),
+            Event::Unbonding {
+                delegator,
+                validator,
+                amount,
+                releases_at,
+            } => write!(
+                f,
+                "{} unbonds {} from {}, released at height {}",
+                hex::encode(delegator),
+                amount,
+                hex::encode(validator),
+                releases_at
+            ),
+            Event::Released { delegator, amount } => {
+                write!(f, "{} got {} back", hex::encode(delegator), amount)
+            }
+            Event::Rewarded { validator, amount } => write!(
+                f,
+                "{} and its delegators earned {}",
+                hex::encode(validator),
+                amount
+            ),
+            Event::Slashed { validator, burned } => write!(
+                f,
+                "{} was slashed, {} burned",
+                hex::encode(validator),
+                burned
+            ),
+            Event::Refused { tx, reason } => {
+                write!(f, "{} left out: {}", hex::encode(tx), reason)
+            }
+        }
+    }
+}
+
+/// The stake of the chain: who bonded what to which validator, and what
+/// is unbonding
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Staking {
+    params: StakingParams,
+    /// Rewards are paid at the first block of each epoch
+    epoch_length: u64,
+    /// Bonded amounts by validator, then by the account bonding
+    bonds: BTreeMap<[u8; 32], BTreeMap<[u8; 32], u64>>,
+    unbonding: Vec<Unbonding>,
+    /// Validators and the heights they were slashed for
+    slashed: BTreeSet<([u8; 32], u64)>,
+}
+
+impl Staking {
+    pub fn new(params: StakingParams, epoch_length: u64) -> Self {
+        Self {
+            params,
+            epoch_length,
+            ..Self::default()
+        }
+    }
+
+    /// The stake of the network `genesis` starts, each validator bonded to
+    /// itself with its stake. Validators whose key is not hex are left
+    /// out, [`Genesis::problems`] names them.
+    pub fn from_genesis(genesis: &Genesis) -> Self {
+        let mut staking = Self::new(genesis.staking, genesis.consensus.forks.epoch_length);
+        for validator in &genesis.validators {
+            if let Some(key) = hex::decode(validator.public_key.trim_start_matches("0x"))
+                .ok()
+                .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            {
+                staking.add_bond(&key, &key, validator.stake);
+            }
+        }
+        staking
+    }
+
+    pub fn params(&self) -> &StakingParams {
+        &self.params
+    }
+
+    /// What `delegator` has bonded to `validator`
+    pub fn bond(&self, validator: &[u8; 32], delegator: &[u8; 32]) -> u64 {
+        self.bonds
+            .get(validator)
+            .and_then(|bonds| bonds.get(delegator))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn is_validator(&self, key: &[u8; 32]) -> bool {
+        self.bond(key, key) > 0
+    }
+
+    /// The stake behind the validator of `key`, none when it is not one
+    pub fn stake(&self, key: &[u8; 32]) -> Option<u64> {
+        self.is_validator(key)
+            .then(|| self.bonds[key].values().sum::<u64>())
+    }
+
+    /// The validators, the stake behind each, by key
+    pub fn stakes(&self) -> BTreeMap<[u8; 32], u64> {
+        self.bonds
+            .keys()
+            .filter_map(|key| Some((*key, self.stake(key)?)))
+            .collect()
+    }
+
+    pub fn total_stake(&self) -> u64 {
+        self.stakes().values().sum()
+    }
+
+    /// Every validator, the most staked first
+    pub fn validators(&self) -> Vec<ValidatorStake> {
+        let mut validators: Vec<ValidatorStake> = self
+            .stakes()
+            .into_iter()
+            .map(|(key, stake)| {
+                let self_bond = self.bond(&key, &key);
+                ValidatorStake {
+                    key,
+                    self_bond,
+                    delegated: stake - self_bond,
+                }
+            })
+            .collect();
+        validators.sort_by_key(|validator| std::cmp::Reverse((validator.total(), validator.key)));
+        validators
+    }
+
+    /// Where `delegator` has stake bonded, by validator
+    pub fn delegations(&self, delegator: &[u8; 32]) -> Vec<([u8; 32], u64)> {
+        self.bonds
+            .iter()
+            .filter_map(|(validator, bonds)| Some((*validator, *bonds.get(delegator)?)))
+            .collect()
+    }
+
+    /// Stake on its way back, the soonest released first
+    pub fn unbonding(&self) -> &[Unbonding] {
+        &self.unbonding
+    }
+
+    /// Whether `tx` is a bond, unbond or evidence that parses, of a
+    /// positive amount or whose signatures hold. Transactions of no such
+    /// kind are.
+    pub fn check(&self, tx: &Transaction) -> Result<(), Error> {
+        let hash = hex::encode(tx.hash());
+        if let Some(bond) = Bond::from_transaction(tx) {
+            if bond?.amount == 0 {
+                return Err(Error::InvalidTransaction(format!("Bond {} of 0", hash)));
+            }
+        }
+        if let Some(unbond) = Unbond::from_transaction(tx) {
+            if unbond?.amount == 0 {
+                return Err(Error::InvalidTransaction(format!("Unbond {} of 0", hash)));
+            }
+        }
+        if let Some(evidence) = Evidence::from_transaction(tx) {
+            evidence?.verify()?;
+        }
+        Ok(())
+    }
+
+    /// Whether `tx` would be taken were it in the next block, with the
+    /// balances of `ledger`, rather than [left out](Event::Refused)
+    pub fn admit(&self, tx: &Transaction, ledger: &dyn Ledger) -> Result<(), Error> {
+        self.check(tx)?;
+        match self.refusal(tx, ledger) {
+            Some(reason) => Err(Error::InvalidTransaction(format!(
+                "{} would be left out: {}",
+                hex::encode(tx.hash()),
+                reason
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Why the [checked](Self::check) `tx` is left out, nothing when it is
+    /// neither left out nor a staking transaction
+    fn refusal(&self, tx: &Transaction, ledger: &dyn Ledger) -> Option<String> {
+        if let Some(Ok(bond)) = Bond::from_transaction(tx) {
+            if bond.validator != tx.sender && !self.is_validator(&bond.validator) {
+                return Some(format!(
+                    "{} is not a validator",
+                    hex::encode(bond.validator)
+                ));
+            }
+            let held = ledger.balance(&tx.sender);
+            if held < bond.amount {
+                return Some(format!(
+                    "{} holds {}, short of {}",
+                    hex::encode(tx.sender),
+                    held,
+                    bond.amount
+                ));
+            }
+        }
+        if let Some(Ok(unbond)) = Unbond::from_transaction(tx) {
+            let bonded = self.bond(&unbond.validator, &tx.sender);
+            if bonded < unbond.amount {
+                return Some(format!(
+                    "{} bonds {} to {}, short of {}",
+                    hex::encode(tx.sender),
+                    bonded,
+                    hex::encode(unbond.validator),
+                    unbond.amount
+                ));
+            }
+        }
+        if let Some(Ok(evidence)) = Evidence::from_transaction(tx) {
+            let height = evidence.misbehavior.height();
+            if self.slashed.contains(&(evidence.key, height)) {
+                return Some(format!(
+                    "{} was slashed for height {} already",
+                    hex::encode(evidence.key),
+                    height
+                ));
+            }
+            let unbonding = self
+                .unbonding
+                .iter()
+                .any(|unbonding| unbonding.validator == evidence.key);
+            if !self.bonds.contains_key(&evidence.key) && !unbonding {
+                return Some(format!(
+                    "{} has no stake to slash",
+                    hex::encode(evidence.key)
+                ));
+            }
+        }
+        None
+    }
+
+    /// Take the staking transactions of `block`, [checked](Self::check),
+    /// in order, paying for bonds from `ledger`, then return the stake
+    /// whose unbonding is over and, at the first block of an epoch, pay
+    /// the reward. Returns what it did.
+    pub fn end_block(&mut self, block: &Block, ledger: &mut dyn Ledger) -> Vec<Event> {
+        let height = block.height;
+        let mut events = Vec::new();
+        for tx in &block.transactions {
+            if let Some(reason) = self.refusal(tx, &*ledger) {
+                events.push(Event::Refused {
+                    tx: tx.hash(),
+                    reason,
+                });
+                continue;
+            }
+            if let Some(Ok(bond)) = Bond::from_transaction(tx) {
+                if let Err(reason) = ledger.debit(&tx.sender, bond.amount) {
+                    events.push(Event::Refused {
+                        tx: tx.hash(),
+                        reason,
+                    });
+                    continue;
+                }
+                self.add_bond(&bond.validator, &tx.sender, bond.amount);
+                events.push(Event::Bonded {
+                    delegator: tx.sender,
+                    validator: bond.validator,
+                    amount: bond.amount,
+                });
+            }
+            if let Some(Ok(unbond)) = Unbond::from_transaction(tx) {
+                self.remove_bond(&unbond.validator, &tx.sender, unbond.amount);
+                let unbonding = Unbonding {
+                    delegator: tx.sender,
+                    validator: unbond.validator,
+                    amount: unbond.amount,
+                    releases_at: height.saturating_add(self.params.unbonding_period),
+                };
+                self.unbonding.push(unbonding);
+                events.push(Event::Unbonding {
+                    delegator: unbonding.delegator,
+                    validator: unbonding.validator,
+                    amount: unbonding.amount,
+                    releases_at: unbonding.releases_at,
+                });
+            }
+            if let Some(Ok(evidence)) = Evidence::from_transaction(tx) {
+                let burned = self.slash(&evidence.key, evidence.misbehavior.height());
+                events.push(Event::Slashed {
+                    validator: evidence.key,
+                    burned,
+                });
+            }
+        }
+
+        let (released, unbonding) = std::mem::take(&mut self.unbonding)
+            .into_iter()
+            .partition(|unbonding| unbonding.releases_at <= height);
+        self.unbonding = unbonding;
+        for unbonding in released {
+            ledger.credit(&unbonding.delegator, unbonding.amount);
+            events.push(Event::Released {
+                delegator: unbonding.delegator,
+                amount: unbonding.amount,
+            });
+        }
+
+        if height > 0 && (self.epoch_length == 0 || height.is_multiple_of(self.epoch_length)) {
+            events.extend(self.reward(ledger));
+        }
+        events
+    }
+
+    /// Share the epoch reward by stake, then each validator's by bond
+    fn reward(&self, ledger: &mut dyn Ledger) -> Vec<Event> {
+        let stakes = self.stakes();
+        let total: u64 = stakes.values().sum();
+        if total == 0 {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        for (validator, stake) in stakes {
+            let reward = share(self.params.epoch_reward, stake, total);
+            if reward == 0 {
+                continue;
+            }
+            let commission = share(
+                reward,
+                self.params.commission_bps as u64,
+                BASIS_POINTS as u64,
+            );
+            let shared = reward - commission;
+            let mut paid = 0;
+            for (delegator, bond) in &self.bonds[&validator] {
+                let earned = share(shared, *bond, stake);
+                if *delegator != validator && earned > 0 {
+                    ledger.credit(delegator, earned);
+                    paid += earned;
+                }
+            }
+            // The commission, its own share and what rounding leaves
+            ledger.credit(&validator, reward - paid);
+            events.push(Event::Rewarded {
+                validator,
+                amount: reward,
+            });
+        }
+        events
+    }
+
+    /// Burn the slashed share of the stake bonded to, and unbonding from,
+    /// `validator`, for misbehaving at `height`, returning what was burned
+    fn slash(&mut self, validator: &[u8; 32], height: u64) -> u64 {
+        self.slashed.insert((*validator, height));
+        let slash_bps = self.params.slash_bps as u64;
+        let burn = |amount: u64| share(amount, slash_bps, BASIS_POINTS as u64);
+        let mut burned = 0;
+        if let Some(bonds) = self.bonds.get_mut(validator) {
+            for bond in bonds.values_mut() {
+                let cut = burn(*bond);
+                *bond -= cut;
+                burned += cut;
+            }
+            bonds.retain(|_, bond| *bond > 0);
+            if bonds.is_empty() {
+                self.bonds.remove(validator);
+            }
+        }
+        for unbonding in &mut self.unbonding {
+            if unbonding.validator == *validator {
+                let cut = burn(unbonding.amount);
+                unbonding.amount -= cut;
+                burned += cut;
+            }
+        }
+        burned
+    }
+
+    fn add_bond(&mut self, validator: &[u8; 32], delegator: &[u8; 32], amount: u64) {
+        let bond = self
+            .bonds
+            .entry(*validator)
+            .or_default()
+            .entry(*delegator)
+            .or_default();
+        *bond = bond.saturating_add(amount);
+    }
+
+    fn remove_bond(&mut self, validator: &[u8; 32], delegator: &[u8; 32], amount: u64) {
+        let Some(bonds) = self.bonds.get_mut(validator) else {
+            return;
+        };
+        if let Some(bond) = bonds.get_mut(delegator) {
+            *bond = bond.saturating_sub(amount);
+            if *bond == 0 {
+                bonds.remove(delegator);
+            }
+        }
+        if bonds.is_empty() {
+            self.bonds.remove(validator);
+        }
+    }
+}
+
+/// `part` of `whole` of `amount`, rounded down, without overflowing
+fn share(amount: u64, part: u64, whole: u64) -> u64 {
+    (amount as u128 * part as u128 / whole as u128) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vote::SignedVote;
+    use ed25519_dalek::SigningKey;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct Balances(HashMap<[u8; 32], u64>);
+
+    impl Ledger for Balances {
+        fn balance(&self, account: &[u8; 32]) -> u64 {
+            self.0.get(account).copied().unwrap_or(0)
+        }
+
+        fn debit(&mut self, account: &[u8; 32], amount: u64) -> Result<(), String> {
+            let held = self.balance(account);
+            if held < amount {
+                return Err(format!("{} holds {}", hex::encode(account), held));
+            }
+            self.0.insert(*account, held - amount);
+            Ok(())
+        }
+
+        fn credit(&mut self, account: &[u8; 32], amount: u64) {
+            *self.0.entry(*account).or_default() += amount;
+        }
+    }
+
+    fn tx(sender: [u8; 32], payload: Vec<u8>) -> Transaction {
+        Transaction {
+            sender,
+            nonce: 0,
+            payload: payload.into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        }
+    }
+
+    fn block(height: u64, transactions: Vec<Transaction>) -> Block {
+        Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 0,
+            producer_mood: String::new(),
+            producer_id: "test".to_string(),
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        }
+    }
+
+    fn staking() -> Staking {
+        Staking::new(
+            StakingParams {
+                unbonding_period: 3,
+                epoch_reward: 100,
+                commission_bps: 1_000,
+                slash_bps: 5_000,
+            },
+            4,
+        )
+    }
+
+    #[test]
+    fn test_bond_delegate_and_unbond() {
+        let mut staking = staking();
+        let mut ledger = Balances::default();
+        ledger.0.insert([1; 32], 500);
+        ledger.0.insert([2; 32], 300);
+
+        // Not a validator yet
+        let early = tx(
+            [2; 32],
+            Bond {
+                validator: [1; 32],
+                amount: 100,
+            }
+            .payload(),
+        );
+        assert!(staking.admit(&early, &ledger).is_err());
+        let bonds = vec![
+            tx(
+                [1; 32],
+                Bond {
+                    validator: [1; 32],
+                    amount: 400,
+                }
+                .payload(),
+            ),
+            early,
+            tx(
+                [2; 32],
+                Bond {
+                    validator: [1; 32],
+                    amount: 1_000,
+                }
+                .payload(),
+            ),
+        ];
+        let events = staking.end_block(&block(1, bonds), &mut ledger);
+        assert!(matches!(events[2], Event::Refused { .. }));
+        assert_eq!(staking.stake(&[1; 32]), Some(500));
+        assert_eq!(staking.stake(&[2; 32]), None);
+        assert_eq!(ledger.balance(&[1; 32]), 100);
+        assert_eq!(ledger.balance(&[2; 32]), 200);
+        assert_eq!(staking.delegations(&[2; 32]), vec![([1; 32], 100)]);
+
+        let unbond = tx(
+            [2; 32],
+            Unbond {
+                validator: [1; 32],
+                amount: 60,
+            }
+            .payload(),
+        );
+        let greedy = tx(
+            [2; 32],
+            Unbond {
+                validator: [1; 32],
+                amount: 41,
+            }
+            .payload(),
+        );
+        staking.end_block(&block(2, vec![unbond, greedy]), &mut ledger);
+        assert_eq!(staking.bond(&[1; 32], &[2; 32]), 40);
+        assert_eq!(staking.unbonding()[0].releases_at, 5);
+        staking.end_block(&block(3, Vec::new()), &mut ledger);
+        assert_eq!(ledger.balance(&[2; 32]), 200);
+        staking.end_block(&block(5, Vec::new()), &mut ledger);
+        assert_eq!(ledger.balance(&[2; 32]), 260);
+        assert!(staking.unbonding().is_empty());
+    }
+
+    #[test]
+    fn test_rewards_by_stake_and_bond() {
+        let mut staking = staking();
+        staking.add_bond(&[1; 32], &[1; 32], 100);
+        staking.add_bond(&[1; 32], &[3; 32], 100);
+        staking.add_bond(&[2; 32], &[2; 32], 200);
+        let mut ledger = Balances::default();
+
+        assert_eq!(staking.end_block(&block(3, Vec::new()), &mut ledger), []);
+        staking.end_block(&block(4, Vec::new()), &mut ledger);
+        // 50 each, of which the delegator earns half of what is left
+        // after the commission of 5
+        assert_eq!(ledger.balance(&[3; 32]), 22);
+        assert_eq!(ledger.balance(&[1; 32]), 28);
+        assert_eq!(ledger.balance(&[2; 32]), 50);
+    }
+
+    #[test]
+    fn test_equivocation_slashes_once() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let validator = key.verifying_key().to_bytes();
+        let mut staking = staking();
+        staking.add_bond(&validator, &validator, 100);
+        staking.add_bond(&validator, &[2; 32], 50);
+        staking.unbonding.push(Unbonding {
+            delegator: [3; 32],
+            validator,
+            amount: 20,
+            releases_at: 10,
+        });
+        let vote = |reason: &str| {
+            Box::new(SignedVote::sign(
+                &key,
+                "validator-7",
+                6,
+                [9; 32],
+                true,
+                5,
+                reason,
+            ))
+        };
+        let evidence = Evidence {
+            key: validator,
+            misbehavior: Misbehavior::Equivocation {
+                first: vote("Good"),
+                second: vote("Bad"),
+            },
+        };
+        let report = tx([4; 32], evidence.payload());
+        staking.check(&report).unwrap();
+        let mut forged = evidence.clone();
+        forged.key = SigningKey::from_bytes(&[8; 32]).verifying_key().to_bytes();
+        assert!(staking.check(&tx([4; 32], forged.payload())).is_err());
+
+        let mut ledger = Balances::default();
+        let events = staking.end_block(&block(7, vec![report.clone()]), &mut ledger);
+        assert_eq!(
+            events,
+            [Event::Slashed {
+                validator,
+                burned: 85
+            }]
+        );
+        assert_eq!(staking.stake(&validator), Some(75));
+        assert_eq!(staking.unbonding()[0].amount, 10);
+        assert!(staking.admit(&report, &ledger).is_err());
+    }
+}
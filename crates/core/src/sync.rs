@@ -0,0 +1,134 @@
+//! Whether a node has caught up with the chain its peers are on. The
+//! networking layer keeps a [`SyncStatus`] up to date while it syncs, and
+//! block producers and validators look at it before they act, so a node far
+//! behind neither proposes nor votes on blocks it cannot judge.
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Blocks a node may lag behind its peers and still produce and vote
+pub const DEFAULT_MAX_LAG: u64 = 2;
+
+/// Where a node stands against its peers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SyncState {
+    /// Peers know of blocks we do not have yet
+    Syncing {
+        /// Height of our head
+        current: u64,
+        /// Highest head a peer told us of
+        highest_seen: u64,
+        /// Peers ahead of us
+        peers: usize,
+    },
+    #[default]
+    Synced,
+}
+
+impl SyncState {
+    /// Blocks between our head and the highest one seen
+    pub fn lag(&self) -> u64 {
+        match self {
+            SyncState::Syncing {
+                current,
+                highest_seen,
+                ..
+            } => highest_seen.saturating_sub(*current),
+            SyncState::Synced => 0,
+        }
+    }
+
+    /// Whether we are more than `max_lag` blocks behind
+    pub fn is_far_behind(&self, max_lag: u64) -> bool {
+        self.lag() > max_lag
+    }
+}
+
+/// The sync state of a node, shared between the networking layer that
+/// writes it and whoever must not act while the node is far behind
+#[derive(Debug, Clone)]
+pub struct SyncStatus {
+    state: Arc<RwLock<SyncState>>,
+    max_lag: u64,
+}
+
+impl Default for SyncStatus {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_LAG)
+    }
+}
+
+impl SyncStatus {
+    /// A node that counts as far behind past `max_lag` blocks
+    pub fn new(max_lag: u64) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(SyncState::Synced)),
+            max_lag,
+        }
+    }
+
+    pub fn state(&self) -> SyncState {
+        *self.state.read()
+    }
+
+    pub fn set(&self, state: SyncState) {
+        *self.state.write() = state;
+    }
+
+    pub fn max_lag(&self) -> u64 {
+        self.max_lag
+    }
+
+    /// Whether the node is too far behind to produce or vote
+    pub fn is_far_behind(&self) -> bool {
+        self.state().is_far_behind(self.max_lag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_far_behind() {
+        let status = SyncStatus::new(2);
+        assert!(!status.is_far_behind());
+
+        status.set(SyncState::Syncing {
+            current: 10,
+            highest_seen: 12,
+            peers: 1,
+        });
+        assert_eq!(status.state().lag(), 2);
+        assert!(!status.is_far_behind());
+
+        // Clones share the state
+        status.clone().set(SyncState::Syncing {
+            current: 10,
+            highest_seen: 20,
+            peers: 3,
+        });
+        assert!(status.is_far_behind());
+
+        status.set(SyncState::Synced);
+        assert!(!status.is_far_behind());
+    }
+
+    #[test]
+    fn test_sync_state_json() {
+        let state = SyncState::Syncing {
+            current: 1,
+            highest_seen: 5,
+            peers: 2,
+        };
+        let json = serde_json::to_value(state).unwrap();
+        assert_eq!(json["state"], "syncing");
+        assert_eq!(json["highest_seen"], 5);
+        assert_eq!(
+            serde_json::to_value(SyncState::Synced).unwrap()["state"],
+            "synced"
+        );
+    }
+}
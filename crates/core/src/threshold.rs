@@ -0,0 +1,72 @@
+//! Threshold finality: once a block is final, any threshold of the
+//! validators holding a share of the group key sign it together, and the
+//! block carries the one group signature instead of a signature per vote
+//! to be checked. A round is run by a coordinator, which gathers nonce
+//! commitments, tells those it picked which commitments they sign with,
+//! and combines their partial signatures. See
+//! [`chaoschain_crypto::threshold`] for the scheme.
+
+use crate::vote::{hex_array, hex_vec};
+use chaoschain_crypto::threshold::{NonceCommitment, PartialSignature};
+use serde::{Deserialize, Serialize};
+
+/// Domain separator of group signatures of finalized blocks
+pub const FINALITY_DOMAIN: &[u8] = b"chaoschain-finality-v1";
+
+/// What the group signs for the block at `height`
+pub fn finality_message(height: u64, block_hash: &[u8; 32]) -> Vec<u8> {
+    let mut message = FINALITY_DOMAIN.to_vec();
+    message.extend_from_slice(&height.to_be_bytes());
+    message.extend_from_slice(block_hash);
+    message
+}
+
+/// The group's signature of a finalized block, hex encoded in JSON
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GroupSignature(#[serde(with = "hex_vec")] pub Vec<u8>);
+
+/// One step of the signing round of a block, from the signer of that index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThresholdMessage {
+    pub height: u64,
+    /// Hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub block_hash: [u8; 32],
+    /// Index of the sender in the group, from 1
+    pub signer: u16,
+    pub round: ThresholdRound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThresholdRound {
+    /// A signer's nonces for the block, to the coordinator
+    Commitment(NonceCommitment),
+    /// The commitments the coordinator picked, from the coordinator
+    Package(Vec<NonceCommitment>),
+    /// A signer's part of the signature, to the coordinator
+    Partial(PartialSignature),
+    /// The group signature, from the coordinator
+    Signature(#[serde(with = "hex_vec")] Vec<u8>),
+}
+
+impl ThresholdMessage {
+    /// Checks that need no group key: the message says what it is of
+    pub fn check(&self) -> Result<(), crate::Error> {
+        let consistent = match &self.round {
+            ThresholdRound::Commitment(commitment) => commitment.index == self.signer,
+            ThresholdRound::Partial(partial) => partial.index == self.signer,
+            ThresholdRound::Package(commitments) => !commitments.is_empty(),
+            ThresholdRound::Signature(signature) => signature.len() == 64,
+        };
+        if consistent {
+            Ok(())
+        } else {
+            Err(crate::Error::InvalidMessage(format!(
+                "Threshold message of signer {} for block {} does not add up",
+                self.signer, self.height
+            )))
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use crate::{Error, ValidationDecision};
+use chaoschain_crypto::scheme::{self, SignatureScheme, VerifyError};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Domain separator of vote signatures
+pub const VOTE_DOMAIN: &[u8] = b"chaoschain-vote-v1";
+/// Domain separator of key registration proofs
+pub const REGISTRATION_DOMAIN: &[u8] = b"chaoschain-register-v1";
+
+/// What validators sign votes with, apart from what signs transactions
+pub type ConsensusScheme = scheme::Ed25519;
+
+/// A vote signed by the validator that cast it, explanation included.
+/// Anyone holding the validator's public key can check who said what.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedVote {
+    pub validator: String,
+    pub height: u64,
+    /// Hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub block_hash: [u8; 32],
+    pub approved: bool,
+    /// Drama level (0-10)
+    pub drama_level: u8,
+    /// The validator's explanation
+    pub reason: String,
+    /// Ed25519 signature over [`SignedVote::signing_bytes`], hex encoded in JSON
+    #[serde(with = "hex_vec")]
+    pub signature: Vec<u8>,
+}
+
+impl SignedVote {
+    /// Sign a vote
+    pub fn sign(
+        key: &SigningKey,
+        validator: impl Into<String>,
+        height: u64,
+        block_hash: [u8; 32],
+        approved: bool,
+        drama_level: u8,
+        reason: impl Into<String>,
+    ) -> Self {
+        let mut vote = Self {
+            validator: validator.into(),
+            height,
+            block_hash,
+            approved,
+            drama_level,
+            reason: reason.into(),
+            signature: Vec::new(),
+        };
+        vote.signature = key.sign(&vote.signing_bytes()).to_bytes().to_vec();
+        vote
+    }
+
+    /// Sign a vote with a key held elsewhere, an HSM or a hardware wallet
+    pub fn sign_with(
+        signer: &dyn chaoschain_crypto::signer::Signer,
+        validator: impl Into<String>,
+        height: u64,
+        block_hash: [u8; 32],
+        approved: bool,
+        drama_level: u8,
+        reason: impl Into<String>,
+    ) -> Result<Self, chaoschain_crypto::signer::SignerError> {
+        let mut vote = Self {
+            validator: validator.into(),
+            height,
+            block_hash,
+            approved,
+            drama_level,
+            reason: reason.into(),
+            signature: Vec::new(),
+        };
+        vote.signature = signer.sign(&vote.signing_bytes())?.to_bytes().to_vec();
+        Ok(vote)
+    }
+
+    /// Hash of the explanation, which is what the signature commits to
+    pub fn explanation_hash(&self) -> [u8; 32] {
+        Sha256::digest(self.reason.as_bytes()).into()
+    }
+
+    /// Canonical bytes covered by the signature
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        vote_signing_bytes(
+            &self.validator,
+            self.height,
+            &self.block_hash,
+            self.approved,
+            self.drama_level,
+            &self.explanation_hash(),
+        )
+    }
+
+    /// Check the signature against the validator's key
+    pub fn verify(&self, key: &VerifyingKey) -> Result<(), Error> {
+        ConsensusScheme::decode_signature(&self.signature)
+            .and_then(|signature| ConsensusScheme::verify(key, &self.signing_bytes(), &signature))
+            .map_err(invalid_signature)
+    }
+
+    /// Whether two votes by the same validator on the same block contradict each other
+    pub fn conflicts_with(&self, other: &Self) -> bool {
+        self.validator == other.validator
+            && self.block_hash == other.block_hash
+            && self.signing_bytes() != other.signing_bytes()
+    }
+
+    /// The attributable part of the vote, as embedded in the finalized block
+    pub fn explanation(&self, public_key: &VerifyingKey) -> SignedExplanation {
+        SignedExplanation {
+            validator: self.validator.clone(),
+            public_key: public_key.to_bytes(),
+            approved: self.approved,
+            drama_level: self.drama_level,
+            explanation_hash: self.explanation_hash(),
+            signature: self.signature.clone(),
+        }
+    }
+
+    pub fn to_decision(&self) -> ValidationDecision {
+        ValidationDecision {
+            approved: self.approved,
+            reason: self.reason.clone(),
+            meme_url: None,
+            drama_level: self.drama_level.min(10),
+            innovation_score: 0,
+            evolution_proposal: None,
+            validator: self.validator.clone(),
+        }
+    }
+}
+
+fn invalid_signature(e: VerifyError) -> Error {
+    Error::InvalidSignature(e.to_string())
+}
+
+fn vote_signing_bytes(
+    validator: &str,
+    height: u64,
+    block_hash: &[u8; 32],
+    approved: bool,
+    drama_level: u8,
+    explanation_hash: &[u8; 32],
+) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(VOTE_DOMAIN.len() + validator.len() + 78);
+    bytes.extend_from_slice(VOTE_DOMAIN);
+    bytes.extend_from_slice(&(validator.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(validator.as_bytes());
+    bytes.extend_from_slice(&height.to_be_bytes());
+    bytes.extend_from_slice(block_hash);
+    bytes.push(approved as u8);
+    bytes.push(drama_level);
+    bytes.extend_from_slice(explanation_hash);
+    bytes
+}
+
+/// A validator's signed commitment to its explanation of a vote. The reason
+/// itself stays in the feed, the block carries its hash and the signature,
+/// which together with the block's height and hash is all it takes to
+/// check who said what.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedExplanation {
+    pub validator: String,
+    /// Ed25519 key of the validator, hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub public_key: [u8; 32],
+    pub approved: bool,
+    pub drama_level: u8,
+    /// SHA-256 of the explanation, hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub explanation_hash: [u8; 32],
+    /// Signature of the vote, hex encoded in JSON
+    #[serde(with = "hex_vec")]
+    pub signature: Vec<u8>,
+}
+
+impl SignedExplanation {
+    /// Check the signature for the block it is embedded in
+    pub fn verify(&self, height: u64, block_hash: &[u8; 32]) -> Result<(), Error> {
+        ConsensusScheme::verify_bytes(
+            &self.public_key,
+            &self.signing_bytes(height, block_hash),
+            &self.signature,
+        )
+        .map_err(invalid_signature)
+    }
+
+    /// Bytes the validator signed, for the block it is embedded in
+    pub fn signing_bytes(&self, height: u64, block_hash: &[u8; 32]) -> Vec<u8> {
+        vote_signing_bytes(
+            &self.validator,
+            height,
+            block_hash,
+            self.approved,
+            self.drama_level,
+            &self.explanation_hash,
+        )
+    }
+
+    /// Whether `reason` is the explanation that was signed
+    pub fn explains(&self, reason: &str) -> bool {
+        <[u8; 32]>::from(Sha256::digest(reason.as_bytes())) == self.explanation_hash
+    }
+}
+
+/// Bytes an agent signs to prove it holds the key it registers
+pub fn registration_message(name: &str, public_key: &[u8; 32]) -> Vec<u8> {
+    let mut message = REGISTRATION_DOMAIN.to_vec();
+    message.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    message.extend_from_slice(name.as_bytes());
+    message.extend_from_slice(public_key);
+    message
+}
+
+/// Proof of possession of the key of `signer`, registered as `name`: its
+/// signature over [`registration_message`]. Registering a key without one
+/// would let a validator claim a key made up of others' to cancel theirs
+/// out of an aggregate.
+pub fn prove_possession(
+    signer: &dyn chaoschain_crypto::signer::Signer,
+    name: &str,
+) -> Result<Vec<u8>, chaoschain_crypto::signer::SignerError> {
+    let public_key = signer.public_key().to_bytes();
+    Ok(signer
+        .sign(&registration_message(name, &public_key))?
+        .to_bytes()
+        .to_vec())
+}
+
+/// `proof` is of the holder of `public_key`, registering it as `name`
+pub fn verify_possession(name: &str, public_key: &[u8; 32], proof: &[u8]) -> Result<(), Error> {
+    ConsensusScheme::verify_bytes(public_key, &registration_message(name, public_key), proof)
+        .map_err(|e| {
+            Error::InvalidSignature(format!(
+                "The proof of possession of the key of {} does not hold: {}",
+                name, e
+            ))
+        })
+}
+
+/// Self-contained proof that a validator misbehaved, checkable by anyone
+/// with the validator's public key, so it can back a slashing decision
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Misbehavior {
+    /// Two different signed votes on the same block
+    Equivocation {
+        first: Box<SignedVote>,
+        second: Box<SignedVote>,
+    },
+}
+
+impl Misbehavior {
+    pub fn offender(&self) -> &str {
+        match self {
+            Self::Equivocation { first, .. } => &first.validator,
+        }
+    }
+
+    pub fn height(&self) -> u64 {
+        match self {
+            Self::Equivocation { first, .. } => first.height,
+        }
+    }
+
+    /// Check the evidence holds up
+    pub fn verify(&self, key: &VerifyingKey) -> Result<(), Error> {
+        match self {
+            Self::Equivocation { first, second } => {
+                if !first.conflicts_with(second) {
+                    return Err(Error::InvalidSignature("Votes do not conflict".to_string()));
+                }
+                first.verify(key)?;
+                second.verify(key)
+            }
+        }
+    }
+}
+
+pub(crate) mod hex_vec {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        hex::decode(text.trim_start_matches("0x")).map_err(serde::de::Error::custom)
+    }
+}
+
+pub(crate) mod hex_array {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let mut bytes = [0u8; 32];
+        hex::decode_to_slice(text.trim_start_matches("0x"), &mut bytes)
+            .map_err(serde::de::Error::custom)?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify_and_equivocation() {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let vote = SignedVote::sign(&key, "ext-1", 3, [1u8; 32], true, 8, "Pure chaos, love it");
+        vote.verify(&key.verifying_key()).unwrap();
+
+        let json = serde_json::to_string(&vote).unwrap();
+        assert_eq!(serde_json::from_str::<SignedVote>(&json).unwrap(), vote);
+
+        let mut forged = vote.clone();
+        forged.reason = "I hate it".to_string();
+        assert!(forged.verify(&key.verifying_key()).is_err());
+
+        let flip = SignedVote::sign(&key, "ext-1", 3, [1u8; 32], false, 8, "Changed my mind");
+        assert!(vote.conflicts_with(&flip));
+        let evidence = Misbehavior::Equivocation {
+            first: Box::new(vote.clone()),
+            second: Box::new(flip),
+        };
+        evidence.verify(&key.verifying_key()).unwrap();
+        assert_eq!(evidence.offender(), "ext-1");
+        assert!(!vote.conflicts_with(&vote.clone()));
+    }
+
+    #[test]
+    fn test_embedded_explanation() {
+        let key = SigningKey::from_bytes(&[9u8; 32]);
+        let vote = SignedVote::sign(&key, "validator-0", 5, [2u8; 32], false, 9, "Boring!");
+        let explanation = vote.explanation(&key.verifying_key());
+        explanation.verify(5, &[2u8; 32]).unwrap();
+        assert!(explanation.explains("Boring!"));
+        assert!(!explanation.explains("Brilliant!"));
+        assert!(explanation.verify(6, &[2u8; 32]).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+//! Proofs of computation done off chain, for applications to settle on
+//! ChaosChain. An application registers the verification key of its
+//! circuit with a system transaction whose payload is [`ZK_KEY_PREFIX`]
+//! then a [`VerificationKey`] as JSON, naming the proof system it is for.
+//! Proofs then come in transactions of [`ZK_PROOF_PREFIX`] then a
+//! [`ProofRecord`], which name the key by its [`VerificationKey::id`] and
+//! are checked against it when the block carrying them is applied.
+//!
+//! A proof system is whatever implements [`ProofSystem`], a Groth16 or
+//! PLONK verifier over the curve of the circuit. Which ones a node has,
+//! its [`ProofSystems`], is part of the protocol: a node without the
+//! system of a key turns the key down, so every node of a network has to
+//! be built with the same. None are built in, and a network without any
+//! takes no keys and so no proofs.
+//!
+//! Whoever checks transactions keeps the [`VerificationKeys`], applying
+//! the blocks to it in order, as for multisig accounts.
+
+use crate::vote::{hex_array, hex_vec};
+use crate::{Block, Error, Transaction};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Domain separator of key ids
+pub const ZK_KEY_DOMAIN: &[u8] = b"chaoschain-zk-key-v1";
+
+/// Start of the payload of a transaction registering a verification key
+pub const ZK_KEY_PREFIX: &[u8] = b"chaoschain/zk-key:";
+
+/// Start of the payload of a transaction carrying a proof
+pub const ZK_PROOF_PREFIX: &[u8] = b"chaoschain/zk-proof:";
+
+/// A verifier of the proofs of one proof system
+pub trait ProofSystem: Send + Sync {
+    /// As keys name it, such as `groth16-bn254`
+    fn name(&self) -> &str;
+
+    /// Whether `key` is a verification key of the system
+    fn check_key(&self, key: &[u8]) -> Result<(), String>;
+
+    /// Whether `proof` proves the statement of `key` for the public
+    /// `inputs`, in the encodings of the system
+    fn verify(&self, key: &[u8], inputs: &[u8], proof: &[u8]) -> Result<(), String>;
+}
+
+/// The proof systems a node verifies, by name
+#[derive(Clone, Default)]
+pub struct ProofSystems {
+    systems: HashMap<String, Arc<dyn ProofSystem>>,
+}
+
+impl ProofSystems {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, system: Arc<dyn ProofSystem>) {
+        self.systems.insert(system.name().to_string(), system);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn ProofSystem> {
+        self.systems.get(name).map(Arc::as_ref)
+    }
+
+    /// Names of the systems, in order
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.systems.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+}
+
+impl std::fmt::Debug for ProofSystems {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+/// The verification key of a circuit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerificationKey {
+    /// Name of the proof system
+    pub system: String,
+    /// In the encoding of the system, hex encoded in JSON
+    #[serde(with = "hex_vec")]
+    pub key: Vec<u8>,
+}
+
+impl VerificationKey {
+    /// What proofs name the key by
+    pub fn id(&self) -> [u8; 32] {
+        Sha256::new()
+            .chain_update(ZK_KEY_DOMAIN)
+            .chain_update((self.system.len() as u64).to_be_bytes())
+            .chain_update(self.system.as_bytes())
+            .chain_update(&self.key)
+            .finalize()
+            .into()
+    }
+
+    /// The payload of the transaction registering it
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = ZK_KEY_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("a key serializes"));
+        payload
+    }
+
+    /// The key `tx` registers, none when it registers none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(ZK_KEY_PREFIX)?;
+        Some(serde_json::from_slice(record).map_err(|e| {
+            Error::InvalidTransaction(format!(
+                "Verification key of {}: {}",
+                hex::encode(tx.hash()),
+                e
+            ))
+        }))
+    }
+}
+
+/// A proof against a registered key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofRecord {
+    /// Id of the verification key, hex encoded in JSON
+    #[serde(with = "hex_array")]
+    pub key: [u8; 32],
+    /// Public inputs in the encoding of the system, hex encoded in JSON
+    #[serde(with = "hex_vec")]
+    pub inputs: Vec<u8>,
+    /// Hex encoded in JSON
+    #[serde(with = "hex_vec")]
+    pub proof: Vec<u8>,
+}
+
+impl ProofRecord {
+    /// The payload of the transaction carrying it
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = ZK_PROOF_PREFIX.to_vec();
+        payload.extend(serde_json::to_vec(self).expect("a proof serializes"));
+        payload
+    }
+
+    /// The proof `tx` carries, none when it carries none
+    pub fn from_transaction(tx: &Transaction) -> Option<Result<Self, Error>> {
+        let record = tx.payload.strip_prefix(ZK_PROOF_PREFIX)?;
+        Some(serde_json::from_slice(record).map_err(|e| {
+            Error::InvalidTransaction(format!("Proof of {}: {}", hex::encode(tx.hash()), e))
+        }))
+    }
+}
+
+/// The verification keys registered on chain, by id
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerificationKeys {
+    keys: HashMap<[u8; 32], VerificationKey>,
+}
+
+impl VerificationKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: &[u8; 32]) -> Option<&VerificationKey> {
+        self.keys.get(id)
+    }
+
+    pub fn insert(&mut self, key: VerificationKey) {
+        self.keys.insert(key.id(), key);
+    }
+
+    /// Whether the key `tx` registers, or the proof it carries, holds
+    /// under `systems`. Other transactions hold.
+    pub fn check(&self, tx: &Transaction, systems: &ProofSystems) -> Result<(), Error> {
+        if let Some(key) = VerificationKey::from_transaction(tx) {
+            let key = key?;
+            let system = systems.get(&key.system).ok_or_else(|| {
+                Error::InvalidTransaction(format!(
+                    "Verification key of {}: no proof system {}",
+                    hex::encode(tx.hash()),
+                    key.system
+                ))
+            })?;
+            return system.check_key(&key.key).map_err(|e| {
+                Error::InvalidTransaction(format!(
+                    "Verification key of {}: {}",
+                    hex::encode(tx.hash()),
+                    e
+                ))
+            });
+        }
+        let Some(record) = ProofRecord::from_transaction(tx) else {
+            return Ok(());
+        };
+        let record = record?;
+        let invalid = |reason: String| {
+            Error::InvalidTransaction(format!(
+                "Proof of {} against key {}: {}",
+                hex::encode(tx.hash()),
+                hex::encode(record.key),
+                reason
+            ))
+        };
+        let key = self
+            .keys
+            .get(&record.key)
+            .ok_or_else(|| invalid("the key is not registered".to_string()))?;
+        let system = systems
+            .get(&key.system)
+            .ok_or_else(|| invalid(format!("no proof system {}", key.system)))?;
+        system
+            .verify(&key.key, &record.inputs, &record.proof)
+            .map_err(invalid)
+    }
+
+    /// Whether every key and proof of `block` holds under `systems`, in
+    /// order
+    pub fn check_block(&self, block: &Block, systems: &ProofSystems) -> Result<(), Error> {
+        let mut keys = self.clone();
+        for tx in &block.transactions {
+            keys.check(tx, systems)?;
+            keys.apply_transaction(tx);
+        }
+        Ok(())
+    }
+
+    /// Take the key `tx` registers, returning its id. One that does not
+    /// parse is passed over; one of a system the node lacks is taken, for
+    /// the block carrying it was checked.
+    pub fn apply_transaction(&mut self, tx: &Transaction) -> Option<[u8; 32]> {
+        match VerificationKey::from_transaction(tx)? {
+            Ok(key) => {
+                let id = key.id();
+                self.keys.insert(id, key);
+                Some(id)
+            }
+            Err(e) => {
+                tracing::warn!("Passing over {}", e);
+                None
+            }
+        }
+    }
+
+    /// Take the keys `block` registers, in order, returning their ids
+    pub fn apply_block(&mut self, block: &Block) -> Vec<[u8; 32]> {
+        block
+            .transactions
+            .iter()
+            .filter_map(|tx| self.apply_transaction(tx))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Proves knowing the key's SHA-256 preimage, which is no zero
+    /// knowledge but has the shape of a system
+    struct Preimage;
+
+    impl ProofSystem for Preimage {
+        fn name(&self) -> &str {
+            "preimage"
+        }
+
+        fn check_key(&self, key: &[u8]) -> Result<(), String> {
+            match key.len() {
+                32 => Ok(()),
+                len => Err(format!("A key is 32 bytes, not {}", len)),
+            }
+        }
+
+        fn verify(&self, key: &[u8], inputs: &[u8], proof: &[u8]) -> Result<(), String> {
+            let digest = Sha256::new()
+                .chain_update(inputs)
+                .chain_update(proof)
+                .finalize();
+            (digest.as_slice() == key)
+                .then_some(())
+                .ok_or_else(|| "The proof does not hold".to_string())
+        }
+    }
+
+    fn tx(nonce: u64, payload: Vec<u8>) -> Transaction {
+        Transaction {
+            sender: [1; 32],
+            nonce,
+            payload: payload.into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_proofs() {
+        let mut systems = ProofSystems::new();
+        systems.register(Arc::new(Preimage));
+        assert_eq!(systems.names(), vec!["preimage"]);
+
+        let key = VerificationKey {
+            system: "preimage".to_string(),
+            key: Sha256::digest(b"inputs and witness").to_vec(),
+        };
+        let register = tx(0, key.payload());
+        assert!(crate::rotation::is_system(&register));
+        let mut keys = VerificationKeys::new();
+        keys.check(&register, &systems).unwrap();
+        assert!(keys.check(&register, &ProofSystems::new()).is_err());
+        let short = VerificationKey {
+            system: "preimage".to_string(),
+            key: vec![0; 3],
+        };
+        assert!(keys.check(&tx(0, short.payload()), &systems).is_err());
+
+        let proof = |proof: &[u8]| {
+            tx(
+                1,
+                ProofRecord {
+                    key: key.id(),
+                    inputs: b"inputs and ".to_vec(),
+                    proof: proof.to_vec(),
+                }
+                .payload(),
+            )
+        };
+        // Only once the key is registered, for the statement of the key
+        assert!(keys.check(&proof(b"witness"), &systems).is_err());
+        let block = |transactions| Block {
+            height: 1,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            producer_id: "producer-1".to_string(),
+            drama_level: 0,
+            producer_mood: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+        keys.check_block(&block(vec![register.clone(), proof(b"witness")]), &systems)
+            .unwrap();
+        assert_eq!(keys.apply_block(&block(vec![register])), vec![key.id()]);
+        assert_eq!(keys.get(&key.id()), Some(&key));
+        keys.check(&proof(b"witness"), &systems).unwrap();
+        assert!(keys.check(&proof(b"guess"), &systems).is_err());
+        assert!(keys.check(&tx(2, b"no proof".to_vec()), &systems).is_ok());
+    }
+}
@@ -0,0 +1,53 @@
+//! Throughput of the chain's hash functions, over inputs the size of a
+//! transaction, of a block's worth of them and of a snapshot chunk:
+//!
+//! ```bash
+//! cargo bench -p chaoschain-crypto --bench hash
+//! ```
+
+use chaoschain_crypto::hash::{Blake3, Hasher, Sha256};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// How long each case runs for
+const RUN: Duration = Duration::from_millis(500);
+
+fn main() {
+    println!(
+        "{:>10} {:>12} {:>12} {:>8}",
+        "input", "sha256 MB/s", "blake3 MB/s", "ratio"
+    );
+    for size in [128, 4 * 1024, 256 * 1024, 4 * 1024 * 1024] {
+        let input: Vec<u8> = (0..size).map(|i| i as u8).collect();
+        let sha256 = throughput::<Sha256>(&input);
+        let blake3 = throughput::<Blake3>(&input);
+        println!(
+            "{:>10} {:>12.1} {:>12.1} {:>7.2}x",
+            bytes(size),
+            sha256,
+            blake3,
+            blake3 / sha256
+        );
+    }
+}
+
+/// Megabytes a second `H` hashes `input` at
+fn throughput<H: Hasher>(input: &[u8]) -> f64 {
+    // Warm the caches and the branch predictor up first
+    black_box(H::digest(input));
+    let started = Instant::now();
+    let mut hashed = 0usize;
+    while started.elapsed() < RUN {
+        black_box(H::digest(black_box(input)));
+        hashed += input.len();
+    }
+    hashed as f64 / started.elapsed().as_secs_f64() / 1e6
+}
+
+fn bytes(size: usize) -> String {
+    match size {
+        size if size >= 1 << 20 => format!("{} MiB", size >> 20),
+        size if size >= 1 << 10 => format!("{} KiB", size >> 10),
+        size => format!("{} B", size),
+    }
+}
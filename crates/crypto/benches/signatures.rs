@@ -0,0 +1,103 @@
+//! Time the chain spends on signatures: checking a transaction's alone,
+//! a block's worth of them in one batch, and combining the partials of a
+//! threshold of validators into the group signature:
+//!
+//! ```bash
+//! cargo bench -p chaoschain-crypto --bench signatures
+//! ```
+
+use chaoschain_crypto::batch::{self, Item};
+use chaoschain_crypto::threshold::{deal, KeyShare, NonceCommitment, PartialSignature};
+use ed25519_dalek::{Signer, SigningKey};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// How long each case runs for
+const RUN: Duration = Duration::from_millis(500);
+
+fn main() {
+    println!("{:>24} {:>12} {:>14}", "case", "µs/op", "µs/signature");
+    for count in [1, 16, 256, 1024] {
+        let items = items(count);
+        let one_by_one = time(|| {
+            for item in &items {
+                assert!(black_box(item).verify());
+            }
+        });
+        report(&format!("verify {}", count), one_by_one, count);
+        let batched = time(|| batch::verify(black_box(&items)).expect("signatures hold"));
+        report(&format!("batch verify {}", count), batched, count);
+    }
+    for (threshold, count) in [(3, 5), (7, 10), (14, 20)] {
+        let (group, shares) = deal(threshold, count).expect("a valid threshold");
+        let (commitments, partials) = partials(&shares[..threshold as usize]);
+        let combined = time(|| {
+            group
+                .combine(b"block 7", black_box(&commitments), black_box(&partials))
+                .expect("partials hold")
+        });
+        report(
+            &format!("combine {} of {}", threshold, count),
+            combined,
+            threshold as usize,
+        );
+        let signature = group
+            .combine(b"block 7", &commitments, &partials)
+            .expect("partials hold");
+        let verified = time(|| assert!(group.verify(b"block 7", &signature)));
+        report(
+            &format!("group verify {} of {}", threshold, count),
+            verified,
+            1,
+        );
+    }
+}
+
+/// `count` signatures of transaction-sized messages, each by its own key
+fn items(count: usize) -> Vec<Item> {
+    (0..count)
+        .map(|i| {
+            let key = SigningKey::from_bytes(&[(i % 251) as u8 + 1; 32]);
+            let message = format!("transfer {} to alice", i).into_bytes();
+            let signature = key.sign(&message).to_bytes();
+            Item::new(key.verifying_key().to_bytes(), message, &signature)
+        })
+        .collect()
+}
+
+/// The commitments and partial signatures of `signers` over one message
+fn partials(signers: &[KeyShare]) -> (Vec<NonceCommitment>, Vec<PartialSignature>) {
+    let (nonces, commitments): (Vec<_>, Vec<_>) = signers.iter().map(KeyShare::commit).unzip();
+    let partials = signers
+        .iter()
+        .zip(nonces)
+        .map(|(share, nonces)| {
+            share
+                .sign(nonces, b"block 7", &commitments)
+                .expect("a committed signer")
+        })
+        .collect();
+    (commitments, partials)
+}
+
+/// Microseconds a call of `f` takes, on average
+fn time<T>(mut f: impl FnMut() -> T) -> f64 {
+    // Warm the caches and the branch predictor up first
+    black_box(f());
+    let started = Instant::now();
+    let mut calls = 0u32;
+    while started.elapsed() < RUN {
+        black_box(f());
+        calls += 1;
+    }
+    started.elapsed().as_secs_f64() * 1e6 / calls as f64
+}
+
+fn report(case: &str, micros: f64, signatures: usize) {
+    println!(
+        "{:>24} {:>12.1} {:>14.2}",
+        case,
+        micros,
+        micros / signatures as f64
+    );
+}
@@ -0,0 +1,308 @@
+//! Checking many ed25519 signatures at once. A batch is checked with a
+//! single multiscalar multiplication, each signature weighed by a random
+//! 128-bit scalar, which is cheaper than checking them one by one. When a
+//! batch fails, its signatures are checked one by one to say which do not
+//! hold.
+//!
+//! The batch equation is the cofactored one. A signature whose nonce or key
+//! has a small-order component, which only the holder of the key can make,
+//! may pass in a batch and fail alone; every other signature passes or
+//! fails the same either way.
+//!
+//! BLS12-381 signatures, of any messages, are checked together with one
+//! multi-pairing, each weighed by a random 64-bit scalar, see
+//! [`verify_bls`].
+
+use crate::scheme::{self, Bls12381, Scheme, SignatureScheme, BLS_DST};
+use blst::min_pk as bls;
+use blst::{blst_scalar, BLST_ERROR};
+use curve25519_dalek::constants::ED25519_BASEPOINT_POINT;
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::traits::{IsIdentity, VartimeMultiscalarMul};
+use curve25519_dalek::Scalar;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use rand::{rngs::OsRng, Rng};
+use sha2::{Digest, Sha512};
+use thiserror::Error;
+
+/// Fewer signatures than this are checked one by one, which is as fast
+const MIN_BATCH: usize = 4;
+
+/// A signature to check, with the key and message it is of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Item {
+    pub public_key: [u8; 32],
+    pub message: Vec<u8>,
+    /// Malformed when not 64 bytes
+    pub signature: Vec<u8>,
+}
+
+impl Item {
+    pub fn new(public_key: [u8; 32], message: Vec<u8>, signature: &[u8]) -> Self {
+        Self {
+            public_key,
+            message,
+            signature: signature.to_vec(),
+        }
+    }
+
+    /// Check this one signature on its own
+    pub fn verify(&self) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(&self.public_key) else {
+            return false;
+        };
+        Signature::from_slice(&self.signature)
+            .is_ok_and(|signature| key.verify(&self.message, &signature).is_ok())
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("{} of {total} signatures do not hold, the first at {}", .invalid.len(), .invalid[0])]
+pub struct BatchError {
+    /// Positions of the signatures that do not hold, in order
+    pub invalid: Vec<usize>,
+    pub total: usize,
+}
+
+/// Check every signature of `items`, failing with those that do not hold
+pub fn verify(items: &[Item]) -> Result<(), BatchError> {
+    if items.len() >= MIN_BATCH && verify_all(items) {
+        return Ok(());
+    }
+    let invalid: Vec<usize> = items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| !item.verify())
+        .map(|(index, _)| index)
+        .collect();
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(BatchError {
+            invalid,
+            total: items.len(),
+        })
+    }
+}
+
+/// Whether `items` pass together, false when any is malformed
+fn verify_all(items: &[Item]) -> bool {
+    let mut rng = OsRng;
+    let mut scalars = Vec::with_capacity(2 * items.len() + 1);
+    let mut points = Vec::with_capacity(2 * items.len() + 1);
+    let mut basepoint = Scalar::ZERO;
+    for item in items {
+        let Ok(signature) = <&[u8; 64]>::try_from(item.signature.as_slice()) else {
+            return false;
+        };
+        let (nonce, s) = signature.split_at(32);
+        let (Some(key), Some(nonce), Some(s)) = (
+            decompress(&item.public_key),
+            decompress(nonce.try_into().expect("32 bytes")),
+            Option::<Scalar>::from(Scalar::from_canonical_bytes(
+                s.try_into().expect("32 bytes"),
+            )),
+        ) else {
+            return false;
+        };
+        let challenge = Scalar::from_bytes_mod_order_wide(
+            &Sha512::new()
+                .chain_update(&signature[..32])
+                .chain_update(item.public_key)
+                .chain_update(&item.message)
+                .finalize()
+                .into(),
+        );
+        let weight = Scalar::from(rng.gen::<u128>());
+        // [z s]B = [z]R + [z h]A, summed over the batch
+        basepoint -= weight * s;
+        scalars.push(weight);
+        points.push(nonce);
+        scalars.push(weight * challenge);
+        points.push(key);
+    }
+    scalars.push(basepoint);
+    points.push(ED25519_BASEPOINT_POINT);
+    EdwardsPoint::vartime_multiscalar_mul(scalars, points)
+        .mul_by_cofactor()
+        .is_identity()
+}
+
+fn decompress(bytes: &[u8; 32]) -> Option<EdwardsPoint> {
+    CompressedEdwardsY(*bytes).decompress()
+}
+
+/// A BLS12-381 signature to check, with the key and message it is of.
+/// Keys are 48 bytes and signatures 96, compressed, malformed otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlsItem {
+    pub public_key: Vec<u8>,
+    pub message: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl BlsItem {
+    pub fn new(public_key: &[u8], message: Vec<u8>, signature: &[u8]) -> Self {
+        Self {
+            public_key: public_key.to_vec(),
+            message,
+            signature: signature.to_vec(),
+        }
+    }
+
+    /// Check this one signature on its own
+    pub fn verify(&self) -> bool {
+        scheme::verify(
+            Scheme::Bls12381,
+            &self.public_key,
+            &self.message,
+            &self.signature,
+        )
+        .is_ok()
+    }
+}
+
+/// Check every BLS signature of `items`, failing with those that do not
+/// hold
+pub fn verify_bls(items: &[BlsItem]) -> Result<(), BatchError> {
+    if items.len() >= MIN_BATCH && verify_all_bls(items) {
+        return Ok(());
+    }
+    let invalid: Vec<usize> = items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| !item.verify())
+        .map(|(index, _)| index)
+        .collect();
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(BatchError {
+            invalid,
+            total: items.len(),
+        })
+    }
+}
+
+/// Whether `items` pass together, false when any is malformed
+fn verify_all_bls(items: &[BlsItem]) -> bool {
+    let mut public_keys = Vec::with_capacity(items.len());
+    let mut signatures = Vec::with_capacity(items.len());
+    for item in items {
+        let (Ok(public_key), Ok(signature)) = (
+            Bls12381::decode_public_key(&item.public_key),
+            Bls12381::decode_signature(&item.signature),
+        ) else {
+            return false;
+        };
+        public_keys.push(public_key);
+        signatures.push(signature);
+    }
+    let mut rng = OsRng;
+    let weights: Vec<blst_scalar> = items
+        .iter()
+        .map(|_| {
+            let mut b = [0; 32];
+            // Never zero, which would leave a signature out
+            b[..8].copy_from_slice(&rng.gen_range(1..=u64::MAX).to_le_bytes());
+            blst_scalar { b }
+        })
+        .collect();
+    let messages: Vec<&[u8]> = items.iter().map(|item| item.message.as_slice()).collect();
+    let public_keys: Vec<&bls::PublicKey> = public_keys.iter().collect();
+    let signatures: Vec<&bls::Signature> = signatures.iter().collect();
+    // Keys were validated as they were decoded, signatures are checked to
+    // be in the group here
+    bls::Signature::verify_multiple_aggregate_signatures(
+        &messages,
+        BLS_DST,
+        &public_keys,
+        false,
+        &signatures,
+        true,
+        &weights,
+        64,
+    ) == BLST_ERROR::BLST_SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn items(count: u8) -> Vec<Item> {
+        (0..count)
+            .map(|index| {
+                let key = SigningKey::from_bytes(&[index + 1; 32]);
+                let message = format!("vote {}", index).into_bytes();
+                let signature = key.sign(&message).to_bytes();
+                Item::new(key.verifying_key().to_bytes(), message, &signature)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_batch_verify() {
+        for count in [0, 1, 3, 32] {
+            assert_eq!(verify(&items(count)), Ok(()));
+        }
+        assert!(verify_all(&items(32)));
+
+        let mut batch = items(32);
+        batch[5].message = b"forged".to_vec();
+        assert!(!verify_all(&batch));
+        batch[20].signature.truncate(63);
+        batch[27].public_key = batch[26].public_key;
+        assert_eq!(
+            verify(&batch),
+            Err(BatchError {
+                invalid: vec![5, 20, 27],
+                total: 32,
+            })
+        );
+
+        let mut batch = items(2);
+        batch[1].signature[40] ^= 1;
+        assert_eq!(verify(&batch).unwrap_err().invalid, vec![1]);
+    }
+
+    fn bls_items(count: u8) -> Vec<BlsItem> {
+        (0..count)
+            .map(|index| {
+                let key = crate::scheme::BlsSecretKey::from_bytes(&[index + 1; 32]).unwrap();
+                let message = format!("vote {}", index).into_bytes();
+                let signature = Bls12381::sign(&key, &message).to_bytes();
+                let public_key = Bls12381::public_key(&key).to_bytes();
+                BlsItem::new(&public_key, message, &signature)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bls_batch_verify() {
+        for count in [0, 1, 3, 16] {
+            assert_eq!(verify_bls(&bls_items(count)), Ok(()));
+        }
+        assert!(verify_all_bls(&bls_items(16)));
+
+        // One bad signature fails the batch, and is the one named
+        let mut batch = bls_items(16);
+        batch[9].message = b"forged".to_vec();
+        assert!(!verify_all_bls(&batch));
+        assert_eq!(
+            verify_bls(&batch),
+            Err(BatchError {
+                invalid: vec![9],
+                total: 16,
+            })
+        );
+
+        // Signatures swapped between two signers hold for neither
+        let mut batch = bls_items(8);
+        let swapped = batch[2].signature.clone();
+        batch[2].signature = batch[3].signature.clone();
+        batch[3].signature = swapped;
+        batch[6].signature.truncate(95);
+        assert_eq!(verify_bls(&batch).unwrap_err().invalid, vec![2, 3, 6]);
+    }
+}
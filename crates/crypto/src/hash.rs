@@ -0,0 +1,182 @@
+//! Hash functions transactions and blocks are hashed with. A network picks
+//! one at genesis, SHA-256 unless it picks BLAKE3, which is several times
+//! faster in software (`cargo bench -p chaoschain-crypto` measures both).
+//! Every hash of the chain follows the choice, so it cannot change after:
+//! a process takes it from the genesis once, with [`set_chain_hash`], and
+//! [`chain_hash`] hashes with it from then on.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// A hash function with 32 byte digests, fed in parts
+pub trait Hasher: Default {
+    const FUNCTION: HashFunction;
+
+    fn update(&mut self, bytes: impl AsRef<[u8]>);
+
+    fn finalize(self) -> [u8; 32];
+
+    /// The hash of `bytes` in one go
+    fn digest(bytes: impl AsRef<[u8]>) -> [u8; 32] {
+        let mut hasher = Self::default();
+        hasher.update(bytes);
+        hasher.finalize()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sha256(sha2::Sha256);
+
+impl Hasher for Sha256 {
+    const FUNCTION: HashFunction = HashFunction::Sha256;
+
+    fn update(&mut self, bytes: impl AsRef<[u8]>) {
+        sha2::Digest::update(&mut self.0, bytes);
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        sha2::Digest::finalize(self.0).into()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Blake3(blake3::Hasher);
+
+impl Hasher for Blake3 {
+    const FUNCTION: HashFunction = HashFunction::Blake3;
+
+    fn update(&mut self, bytes: impl AsRef<[u8]>) {
+        self.0.update(bytes.as_ref());
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+/// Which hash function, as the genesis names it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashFunction {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashFunction {
+    pub fn hasher(self) -> AnyHasher {
+        match self {
+            Self::Sha256 => AnyHasher::Sha256(Sha256::default()),
+            Self::Blake3 => AnyHasher::Blake3(Box::default()),
+        }
+    }
+
+    pub fn digest(self, bytes: impl AsRef<[u8]>) -> [u8; 32] {
+        let mut hasher = self.hasher();
+        hasher.update(bytes);
+        hasher.finalize()
+    }
+
+    pub fn is_sha256(&self) -> bool {
+        *self == Self::Sha256
+    }
+}
+
+impl fmt::Display for HashFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+        })
+    }
+}
+
+impl FromStr for HashFunction {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.to_ascii_lowercase().replace('-', "").as_str() {
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            _ => Err(format!("No hash function {}, sha256 or blake3", text)),
+        }
+    }
+}
+
+/// A hasher of the function picked at run time
+#[derive(Debug, Clone)]
+pub enum AnyHasher {
+    Sha256(Sha256),
+    Blake3(Box<Blake3>),
+}
+
+impl AnyHasher {
+    pub fn update(&mut self, bytes: impl AsRef<[u8]>) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(bytes),
+            Self::Blake3(hasher) => hasher.update(bytes),
+        }
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        match self {
+            Self::Sha256(hasher) => hasher.finalize(),
+            Self::Blake3(hasher) => hasher.finalize(),
+        }
+    }
+}
+
+static CHAIN_HASH: OnceLock<HashFunction> = OnceLock::new();
+
+/// Hash the chain with `function` from now on. It is set once: setting
+/// another after fails with the one set.
+pub fn set_chain_hash(function: HashFunction) -> Result<(), HashFunction> {
+    let set = *CHAIN_HASH.get_or_init(|| function);
+    if set == function {
+        Ok(())
+    } else {
+        Err(set)
+    }
+}
+
+/// The hash function of the chain, SHA-256 until one is set
+pub fn chain_hash() -> HashFunction {
+    CHAIN_HASH.get().copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_functions() {
+        // The empty string's digests of FIPS 180-4 and the BLAKE3 paper
+        assert_eq!(
+            hex::encode(Sha256::digest(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex::encode(Blake3::digest(b"")),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        for function in [HashFunction::Sha256, HashFunction::Blake3] {
+            let mut hasher = function.hasher();
+            hasher.update(b"chaos");
+            hasher.update(b"chain");
+            assert_eq!(hasher.finalize(), function.digest(b"chaoschain"));
+            assert_eq!(function.to_string().parse::<HashFunction>(), Ok(function));
+        }
+        assert_ne!(
+            HashFunction::Sha256.digest(b"chaoschain"),
+            HashFunction::Blake3.digest(b"chaoschain")
+        );
+        assert_eq!("SHA-256".parse::<HashFunction>(), Ok(HashFunction::Sha256));
+        assert!("md5".parse::<HashFunction>().is_err());
+        assert_eq!(
+            serde_json::to_string(&HashFunction::Blake3).unwrap(),
+            "\"blake3\""
+        );
+    }
+}
@@ -0,0 +1,1226 @@
+//! Signing keys kept on disk, one file per key in a directory.
+//!
+//! A key is either a plain `<name>.key` file holding its hex encoded secret,
+//! or a `<name>.json` file holding it encrypted under a password: an
+//! EIP-2335 keystore, which Ethereum tooling reads and writes too. scrypt
+//! stretches the password into a key, or PBKDF2 in files made elsewhere,
+//! half of which encrypts the secret with AES-128-CTR and half checks the
+//! password. Encrypted files are also what keys are exported as. Files of
+//! version 1, ChaCha20-Poly1305 under an scrypt key, are still read, and
+//! [`Keystore::migrate`] rewrites them and plain keys as EIP-2335.
+//!
+//! Keys are of a signature scheme, see [`crate::scheme`], ed25519 unless
+//! they say otherwise. An encrypted file names it, a plain file of another
+//! scheme starts with it, as in `secp256k1:<hex>`. Files made elsewhere
+//! with a BLS12-381 public key hold a BLS12-381 key.
+//!
+//! Keys can be recovered from a BIP39 mnemonic. The seed is walked down the
+//! EIP-2333 tree of BLS12-381 secret keys along the EIP-2334 path
+//! `m/12381/1337/<index>/0`, 1337 standing for ChaosChain, and the 32 bytes
+//! of the secret found there are the ed25519 seed the key signs with. The
+//! same mnemonic and index give the same key, and a BLS12-381 key of the
+//! mnemonic is the very key at that path. A secp256k1 key of the mnemonic
+//! is that secret too, which the order of BLS12-381 keeps below the order
+//! of secp256k1.
+//!
+//! Other paths can be walked, see [`DerivationPath`], such as the
+//! `m/12381/3600/<index>/0/0` of Ethereum validators' signing keys, and
+//! [`Keystore::derive`] keeps many keys of one mnemonic at once, so that
+//! one mnemonic backs up the keys of every agent an operator runs.
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::ChaCha20Poly1305;
+use coins_bip39::{English, Mnemonic};
+use ed25519_dalek::SigningKey;
+use hkdf::Hkdf;
+use num_bigint::BigUint;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::scheme::{Scheme, SecretKey};
+use crate::secret::{ct_eq, Zeroizing};
+
+/// EIP-2334 purpose of BLS12-381 keys
+pub const PURPOSE: u32 = 12381;
+
+/// Coin type of ChaosChain keys
+pub const COIN_TYPE: u32 = 1337;
+
+/// Words of a generated mnemonic
+const MNEMONIC_WORDS: usize = 24;
+
+/// Cost of stretching a password, as 2^N rounds
+const SCRYPT_LOG_N: u8 = 16;
+
+/// Order of the BLS12-381 group, secret keys are taken modulo it
+const CURVE_ORDER: &str = "73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000001";
+
+#[derive(Debug, Error)]
+pub enum KeystoreError {
+    #[error("Key names are letters, digits, dashes and underscores, not '{0}'")]
+    InvalidName(String),
+    #[error("There is a key named {0} already")]
+    Exists(String),
+    #[error("No key named {0}")]
+    NotFound(String),
+    #[error("The key {0} is encrypted, it needs a password")]
+    PasswordRequired(String),
+    #[error("Wrong password for {0}")]
+    WrongPassword(String),
+    #[error("Invalid mnemonic: {0}")]
+    InvalidMnemonic(String),
+    #[error("Invalid derivation path {0}: {1}")]
+    InvalidPath(String, &'static str),
+    #[error("Invalid key file: {0}")]
+    InvalidFile(String),
+    #[error("{0} is a {1} key, and only ed25519 keys sign transactions")]
+    WrongScheme(String, Scheme),
+    #[error("Keystore I/O failed: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, KeystoreError>;
+
+/// An encrypted key, as written to `<name>.json` and exported: an EIP-2335
+/// keystore, or a version 1 file of before
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyFile {
+    pub crypto: KeyCrypto,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// Hex encoded public key, the address of the key
+    pub pubkey: String,
+    /// Where the key was derived from its mnemonic, if it was. EIP-2335
+    /// writes none as an empty path.
+    #[serde(default, with = "empty_path")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub uuid: String,
+    pub version: u32,
+    /// Not part of EIP-2335, left out for ed25519 keys for Ethereum tooling
+    /// to take the file. Version 1 files from before schemes were named are
+    /// ed25519 too.
+    #[serde(default, skip_serializing_if = "Scheme::is_ed25519")]
+    pub scheme: Scheme,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyCrypto {
+    /// EIP-2335: the password stretched into a key, half of which encrypts
+    /// the secret with AES-128-CTR and half checks the password
+    Eip2335 {
+        kdf: Module<Kdf>,
+        checksum: Module<Checksum>,
+        cipher: Module<Cipher>,
+    },
+    /// Version 1: ChaCha20-Poly1305 under an scrypt key
+    Sealed {
+        kdf: SealedKdf,
+        cipher: SealedCipher,
+        /// Hex encoded secret, sealed with its authentication tag
+        ciphertext: String,
+    },
+}
+
+/// An EIP-2335 module: a function with its parameters, and its hex
+/// encoded output where it has one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Module<T> {
+    #[serde(flatten)]
+    pub function: T,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "function", content = "params", rename_all = "lowercase")]
+pub enum Kdf {
+    Scrypt {
+        dklen: u32,
+        n: u32,
+        r: u32,
+        p: u32,
+        /// Hex encoded
+        salt: String,
+    },
+    Pbkdf2 {
+        dklen: u32,
+        c: u32,
+        /// `hmac-sha256`, the only one EIP-2335 has
+        prf: String,
+        /// Hex encoded
+        salt: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "function", content = "params", rename_all = "lowercase")]
+pub enum Checksum {
+    Sha256 {},
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "function", content = "params")]
+pub enum Cipher {
+    #[serde(rename = "aes-128-ctr")]
+    Aes128Ctr {
+        /// Hex encoded
+        iv: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "function", content = "params", rename_all = "lowercase")]
+pub enum SealedKdf {
+    Scrypt {
+        log_n: u8,
+        r: u32,
+        p: u32,
+        /// Hex encoded
+        salt: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "function", rename_all = "kebab-case")]
+pub enum SealedCipher {
+    ChaCha20Poly1305 {
+        /// Hex encoded
+        nonce: String,
+    },
+}
+
+impl KeyFile {
+    /// Seal `key` under `password`
+    pub fn encrypt(key: &SecretKey, password: &str, path: Option<String>) -> Self {
+        Self::encrypt_with(key, password, path, SCRYPT_LOG_N)
+    }
+
+    fn encrypt_with(key: &SecretKey, password: &str, path: Option<String>, log_n: u8) -> Self {
+        let mut salt = [0u8; 32];
+        let mut iv = [0u8; 16];
+        let mut uuid = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut iv);
+        OsRng.fill_bytes(&mut uuid);
+        let kdf = Kdf::Scrypt {
+            dklen: 32,
+            n: 1 << log_n,
+            r: 8,
+            p: 1,
+            salt: hex::encode(salt),
+        };
+        let decryption_key = kdf.derive(password).expect("the parameters are valid");
+        let mut secret = key.to_bytes();
+        aes_128_ctr(&decryption_key, &iv, &mut *secret);
+        Self {
+            crypto: KeyCrypto::Eip2335 {
+                kdf: Module {
+                    function: kdf,
+                    message: String::new(),
+                },
+                checksum: Module {
+                    function: Checksum::Sha256 {},
+                    message: hex::encode(checksum(&decryption_key, &*secret)),
+                },
+                cipher: Module {
+                    function: Cipher::Aes128Ctr {
+                        iv: hex::encode(iv),
+                    },
+                    message: hex::encode(*secret),
+                },
+            },
+            description: String::new(),
+            pubkey: hex::encode(key.public_key()),
+            path,
+            uuid: format_uuid(uuid),
+            version: 4,
+            scheme: key.scheme(),
+        }
+    }
+
+    /// The scheme of the key. Files made elsewhere name none, and their
+    /// public keys of 48 bytes are of BLS12-381.
+    pub fn key_scheme(&self) -> Scheme {
+        let pubkey = self.pubkey.trim_start_matches("0x");
+        match Scheme::of_public_key(pubkey.len() / 2) {
+            Some(Scheme::Bls12381) => Scheme::Bls12381,
+            _ => self.scheme,
+        }
+    }
+
+    /// Whether the file is an EIP-2335 keystore, rather than of version 1
+    pub fn is_eip2335(&self) -> bool {
+        matches!(self.crypto, KeyCrypto::Eip2335 { .. })
+    }
+
+    /// The key, unsealed with `password`. `name` is for the errors.
+    pub fn decrypt(&self, name: &str, password: &str) -> Result<SecretKey> {
+        let invalid = |what: &str| KeystoreError::InvalidFile(format!("{}: {}", name, what));
+        let secret = self.decrypt_secret(name, password)?;
+        let scheme = self.key_scheme();
+        let key = SecretKey::from_bytes(scheme, &secret)
+            .ok_or_else(|| invalid(&format!("the secret is not a {} key", scheme)))?;
+        if hex::encode(key.public_key()) != self.pubkey.trim_start_matches("0x") {
+            return Err(invalid("the secret does not match the public key"));
+        }
+        Ok(key)
+    }
+
+    /// The secret the file holds, whatever key it is of
+    fn decrypt_secret(&self, name: &str, password: &str) -> Result<Zeroizing<[u8; 32]>> {
+        let invalid = |what: &str| KeystoreError::InvalidFile(format!("{}: {}", name, what));
+        let hex = |what: &str, text: &str| {
+            hex::decode(text).map_err(|_| invalid(&format!("the {} is not hex", what)))
+        };
+        match (&self.crypto, self.version) {
+            (
+                KeyCrypto::Eip2335 {
+                    kdf,
+                    checksum: expected,
+                    cipher,
+                },
+                4,
+            ) => {
+                let Cipher::Aes128Ctr { iv } = &cipher.function;
+                let iv: [u8; 16] = hex("iv", iv)?
+                    .try_into()
+                    .map_err(|_| invalid("the iv is not 16 bytes"))?;
+                let mut secret: Zeroizing<[u8; 32]> = Zeroizing::new(
+                    hex("cipher message", &cipher.message)?
+                        .try_into()
+                        .map_err(|_| invalid("the secret is not 32 bytes"))?,
+                );
+                let decryption_key = kdf
+                    .function
+                    .derive(password)
+                    .ok_or_else(|| invalid("bad kdf"))?;
+                if !ct_eq(
+                    &checksum(&decryption_key, &*secret),
+                    &hex("checksum", &expected.message)?,
+                ) {
+                    return Err(KeystoreError::WrongPassword(name.to_string()));
+                }
+                aes_128_ctr(&decryption_key, &iv, &mut *secret);
+                Ok(secret)
+            }
+            (
+                KeyCrypto::Sealed {
+                    kdf,
+                    cipher,
+                    ciphertext,
+                },
+                1,
+            ) => {
+                let SealedCipher::ChaCha20Poly1305 { nonce } = cipher;
+                let nonce: [u8; 12] = hex("nonce", nonce)?
+                    .try_into()
+                    .map_err(|_| invalid("the nonce is not 12 bytes"))?;
+                let ciphertext = hex("ciphertext", ciphertext)?;
+                let cipher = stretch(kdf, password).ok_or_else(|| invalid("bad kdf"))?;
+                let plaintext = Zeroizing::new(
+                    cipher
+                        .decrypt(&nonce.into(), ciphertext.as_slice())
+                        .map_err(|_| KeystoreError::WrongPassword(name.to_string()))?,
+                );
+                <[u8; 32]>::try_from(plaintext.as_slice())
+                    .map(Zeroizing::new)
+                    .map_err(|_| invalid("the secret is not 32 bytes"))
+            }
+            (_, version) => Err(invalid(&format!("version {} is not known", version))),
+        }
+    }
+}
+
+impl Kdf {
+    /// The decryption key `password` stretches into
+    fn derive(&self, password: &str) -> Option<Zeroizing<[u8; 32]>> {
+        let password = process_password(password);
+        let mut key = Zeroizing::new([0u8; 32]);
+        match self {
+            Kdf::Scrypt {
+                dklen,
+                n,
+                r,
+                p,
+                salt,
+            } => {
+                if *dklen != 32 || !n.is_power_of_two() {
+                    return None;
+                }
+                let params = scrypt::Params::new(n.trailing_zeros() as u8, *r, *p).ok()?;
+                scrypt::scrypt(&password, &hex::decode(salt).ok()?, &params, &mut *key).ok()?;
+            }
+            Kdf::Pbkdf2 {
+                dklen,
+                c,
+                prf,
+                salt,
+            } => {
+                if *dklen != 32 || prf != "hmac-sha256" {
+                    return None;
+                }
+                pbkdf2::pbkdf2_hmac::<Sha256>(&password, &hex::decode(salt).ok()?, *c, &mut *key);
+            }
+        }
+        Some(key)
+    }
+}
+
+/// The password as EIP-2335 has it stretched: without control codes. It
+/// also asks for NFKD normalization, which leaves ASCII as it is, and
+/// which passwords of characters it would change do not get here.
+fn process_password(password: &str) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(
+        password
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect::<String>()
+            .into_bytes(),
+    )
+}
+
+/// EIP-2335 checksum of the cipher message, against the second half of
+/// the decryption key
+fn checksum(decryption_key: &[u8; 32], message: &[u8]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(&decryption_key[16..])
+        .chain_update(message)
+        .finalize()
+        .into()
+}
+
+/// Encrypt or decrypt `data` in place, under the first half of the
+/// decryption key
+fn aes_128_ctr(decryption_key: &[u8; 32], iv: &[u8; 16], data: &mut [u8]) {
+    let mut cipher = ctr::Ctr128BE::<aes::Aes128>::new(decryption_key[..16].into(), iv.into());
+    cipher.apply_keystream(data);
+}
+
+/// A version 4 UUID of `bytes`
+fn format_uuid(mut bytes: [u8; 16]) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// The cipher `password` opens a version 1 file with
+fn stretch(kdf: &SealedKdf, password: &str) -> Option<ChaCha20Poly1305> {
+    let SealedKdf::Scrypt { log_n, r, p, salt } = kdf;
+    let params = scrypt::Params::new(*log_n, *r, *p).ok()?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(
+        password.as_bytes(),
+        &hex::decode(salt).ok()?,
+        &params,
+        &mut *key,
+    )
+    .ok()?;
+    Some(ChaCha20Poly1305::new(&(*key).into()))
+}
+
+/// An absent path as the empty one EIP-2335 writes
+mod empty_path {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        path: &Option<String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(path.as_deref().unwrap_or(""))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<String>, D::Error> {
+        let path = Option::<String>::deserialize(deserializer)?;
+        Ok(path.filter(|path| !path.is_empty()))
+    }
+}
+
+/// A fresh mnemonic of 24 words
+pub fn generate_mnemonic() -> String {
+    Mnemonic::<English>::new_with_count(&mut OsRng, MNEMONIC_WORDS)
+        .expect("24 is a valid word count")
+        .to_phrase()
+}
+
+/// An EIP-2334 path down the EIP-2333 tree, as `m/12381/1337/i/0`. Every
+/// step is hardened, so no `'` is written. An `i` step stands for the
+/// index of the key, which makes the path name every key of a mnemonic
+/// rather than one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationPath {
+    /// Child indices from the master key, `None` where the key's index goes
+    steps: Vec<Option<u32>>,
+}
+
+impl DerivationPath {
+    /// `m/12381/1337/i/0`, the path of ChaosChain keys
+    pub fn chaoschain() -> Self {
+        Self {
+            steps: vec![Some(PURPOSE), Some(COIN_TYPE), None, Some(0)],
+        }
+    }
+
+    /// The child indices down to the `index`th key
+    pub fn indices(&self, index: u32) -> Vec<u32> {
+        self.steps
+            .iter()
+            .map(|step| step.unwrap_or(index))
+            .collect()
+    }
+
+    /// The path of the `index`th key, with no `i` left
+    pub fn at(&self, index: u32) -> String {
+        let steps: Vec<String> = self
+            .indices(index)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        format!("m/{}", steps.join("/"))
+    }
+
+    /// Whether keys of other indices are at other paths
+    pub fn is_indexed(&self) -> bool {
+        self.steps.contains(&None)
+    }
+}
+
+impl Default for DerivationPath {
+    fn default() -> Self {
+        Self::chaoschain()
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = KeystoreError;
+
+    fn from_str(path: &str) -> Result<Self> {
+        let invalid = |reason| KeystoreError::InvalidPath(path.to_string(), reason);
+        let mut parts = path.trim().split('/');
+        if parts.next() != Some("m") {
+            return Err(invalid("it starts with m"));
+        }
+        let steps = parts
+            .map(|part| match part {
+                "i" => Ok(None),
+                part => part
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| invalid("steps are numbers below 2^32, or i")),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if steps.first() != Some(&Some(PURPOSE)) {
+            return Err(invalid("EIP-2334 paths start at purpose 12381"));
+        }
+        if steps.iter().filter(|step| step.is_none()).count() > 1 {
+            return Err(invalid("only one step is the index"));
+        }
+        Ok(Self { steps })
+    }
+}
+
+impl std::fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "m")?;
+        for step in &self.steps {
+            match step {
+                Some(index) => write!(f, "/{}", index)?,
+                None => write!(f, "/i")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Path of the `index`th key of a mnemonic
+pub fn derivation_path(index: u32) -> String {
+    DerivationPath::chaoschain().at(index)
+}
+
+/// The `index`th key of `phrase`
+pub fn key_from_mnemonic(phrase: &str, index: u32) -> Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&*secret_from_mnemonic(
+        phrase,
+        &DerivationPath::chaoschain().indices(index),
+    )?))
+}
+
+/// The `index`th key of `phrase`, under `scheme`
+pub fn scheme_key_from_mnemonic(phrase: &str, index: u32, scheme: Scheme) -> Result<SecretKey> {
+    scheme_key_at(phrase, &DerivationPath::chaoschain(), index, scheme)
+}
+
+/// The `index`th key of `phrase` along `path`, under `scheme`
+pub fn scheme_key_at(
+    phrase: &str,
+    path: &DerivationPath,
+    index: u32,
+    scheme: Scheme,
+) -> Result<SecretKey> {
+    let secret = secret_from_mnemonic(phrase, &path.indices(index))?;
+    Ok(SecretKey::from_bytes(scheme, &secret).expect("EIP-2333 secrets are keys of every scheme"))
+}
+
+/// The secret at `indices`. The seed is wiped once walked, the big
+/// integers of the walk are not for want of a way to.
+fn secret_from_mnemonic(phrase: &str, indices: &[u32]) -> Result<Zeroizing<[u8; 32]>> {
+    let mnemonic = Mnemonic::<English>::new_from_phrase(phrase.trim())
+        .map_err(|e| KeystoreError::InvalidMnemonic(e.to_string()))?;
+    let seed = Zeroizing::new(
+        mnemonic
+            .to_seed(None)
+            .map_err(|e| KeystoreError::InvalidMnemonic(e.to_string()))?,
+    );
+    let secret = indices
+        .iter()
+        .fold(derive_master_sk(&*seed), |parent, index| {
+            derive_child_sk(&parent, *index)
+        });
+    Ok(Zeroizing::new(to_bytes(&secret)))
+}
+
+/// EIP-2333 `derive_master_SK`
+fn derive_master_sk(seed: &[u8]) -> BigUint {
+    hkdf_mod_r(seed)
+}
+
+/// EIP-2333 `derive_child_SK`: the parent's Lamport public key, hashed
+/// into a secret key. Every step is hardened.
+fn derive_child_sk(parent: &BigUint, index: u32) -> BigUint {
+    let ikm = to_bytes(parent);
+    let not_ikm = ikm.map(|byte| !byte);
+    let salt = index.to_be_bytes();
+    let mut lamport = Sha256::new();
+    for ikm in [ikm, not_ikm] {
+        let mut chunks = [0u8; 255 * 32];
+        Hkdf::<Sha256>::new(Some(&salt), &ikm)
+            .expand(&[], &mut chunks)
+            .expect("255 hashes is the most HKDF expands to");
+        for chunk in chunks.chunks(32) {
+            lamport.update(Sha256::digest(chunk));
+        }
+    }
+    hkdf_mod_r(&lamport.finalize())
+}
+
+/// EIP-2333 `HKDF_mod_r`
+fn hkdf_mod_r(ikm: &[u8]) -> BigUint {
+    let order = BigUint::parse_bytes(CURVE_ORDER.as_bytes(), 16).expect("the order is hex");
+    let mut ikm = ikm.to_vec();
+    ikm.push(0);
+    let mut salt = Sha256::digest(b"BLS-SIG-KEYGEN-SALT-");
+    loop {
+        let mut okm = [0u8; 48];
+        Hkdf::<Sha256>::new(Some(&salt), &ikm)
+            .expand(&48u16.to_be_bytes(), &mut okm)
+            .expect("48 bytes is a valid length");
+        let secret = BigUint::from_bytes_be(&okm) % &order;
+        if secret != BigUint::default() {
+            return secret;
+        }
+        salt = Sha256::digest(salt);
+    }
+}
+
+/// Big-endian, in 32 bytes
+fn to_bytes(secret: &BigUint) -> [u8; 32] {
+    let bytes = secret.to_bytes_be();
+    let mut padded = [0u8; 32];
+    padded[32 - bytes.len()..].copy_from_slice(&bytes);
+    padded
+}
+
+/// A key of the keystore
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyEntry {
+    pub name: String,
+    pub scheme: Scheme,
+    /// Hex encoded public key
+    pub address: String,
+    pub encrypted: bool,
+    /// Where it was derived from its mnemonic, for encrypted keys
+    pub path: Option<String>,
+}
+
+/// Signing keys by name, in a directory of their own
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+impl Keystore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Keep `key` under `name`, which must not be taken yet. It is
+    /// encrypted when there is a password.
+    pub fn add(
+        &self,
+        name: &str,
+        key: &SigningKey,
+        password: Option<&str>,
+        path: Option<String>,
+    ) -> Result<()> {
+        self.add_key(name, &key.clone().into(), password, path)
+    }
+
+    /// Keep the keys of `indices` along `path` of `phrase`, as
+    /// `<prefix>-<index>`, returning them by name. None is kept when any
+    /// name is taken.
+    pub fn derive(
+        &self,
+        phrase: &str,
+        prefix: &str,
+        path: &DerivationPath,
+        indices: Range<u32>,
+        scheme: Scheme,
+        password: Option<&str>,
+    ) -> Result<Vec<(String, SecretKey)>> {
+        if !path.is_indexed() && indices.len() > 1 {
+            return Err(KeystoreError::InvalidPath(
+                path.to_string(),
+                "it has no i step to tell the keys apart",
+            ));
+        }
+        let mut keys = Vec::new();
+        for index in indices {
+            let name = format!("{}-{}", prefix, index);
+            if self.path(&name, "key")?.exists() || self.path(&name, "json")?.exists() {
+                return Err(KeystoreError::Exists(name));
+            }
+            keys.push((index, name, scheme_key_at(phrase, path, index, scheme)?));
+        }
+        keys.into_iter()
+            .map(|(index, name, key)| {
+                self.add_key(&name, &key, password, Some(path.at(index)))?;
+                Ok((name, key))
+            })
+            .collect()
+    }
+
+    /// Keep `key` of any scheme, as [`Keystore::add`] does
+    pub fn add_key(
+        &self,
+        name: &str,
+        key: &SecretKey,
+        password: Option<&str>,
+        path: Option<String>,
+    ) -> Result<()> {
+        match password {
+            Some(password) => self.import(name, &KeyFile::encrypt(key, password, path)),
+            None => {
+                let secret = Zeroizing::new(hex::encode(*key.to_bytes()));
+                let contents = Zeroizing::new(match key.scheme() {
+                    // As files were before schemes, for older nodes to read
+                    Scheme::Ed25519 => format!("{}\n", *secret),
+                    scheme => format!("{}:{}\n", scheme, *secret),
+                });
+                self.write(name, "key", &contents)
+            }
+        }
+    }
+
+    /// Keep a key file exported elsewhere under `name`
+    pub fn import(&self, name: &str, file: &KeyFile) -> Result<()> {
+        let contents = serde_json::to_string_pretty(file)
+            .map_err(|e| KeystoreError::InvalidFile(e.to_string()))?;
+        self.write(name, "json", &contents)
+    }
+
+    /// The ed25519 key under `name`
+    pub fn load(&self, name: &str, password: Option<&str>) -> Result<SigningKey> {
+        let key = self.load_key(name, password)?;
+        let scheme = key.scheme();
+        key.ed25519()
+            .ok_or_else(|| KeystoreError::WrongScheme(name.to_string(), scheme))
+    }
+
+    /// The key under `name`, of whatever scheme it is
+    pub fn load_key(&self, name: &str, password: Option<&str>) -> Result<SecretKey> {
+        if let Some(file) = self.key_file(name)? {
+            let password =
+                password.ok_or_else(|| KeystoreError::PasswordRequired(name.to_string()))?;
+            return file.decrypt(name, password);
+        }
+        let secret = Zeroizing::new(std::fs::read_to_string(self.path(name, "key")?).map_err(
+            |e| match e.kind() {
+                std::io::ErrorKind::NotFound => KeystoreError::NotFound(name.to_string()),
+                _ => e.into(),
+            },
+        )?);
+        let secret = secret.trim();
+        let (scheme, secret) = match secret.split_once(':') {
+            Some((scheme, secret)) => (
+                scheme
+                    .parse()
+                    .map_err(|e| KeystoreError::InvalidFile(format!("{}: {}", name, e)))?,
+                secret,
+            ),
+            None => (Scheme::Ed25519, secret),
+        };
+        let bytes = Zeroizing::new(hex::decode(secret).unwrap_or_default());
+        <[u8; 32]>::try_from(bytes.as_slice())
+            .ok()
+            .map(Zeroizing::new)
+            .and_then(|bytes| SecretKey::from_bytes(scheme, &bytes))
+            .ok_or_else(|| KeystoreError::InvalidFile(format!("{} holds no {} key", name, scheme)))
+    }
+
+    /// The key under `name`, encrypted with `password`. Encrypted keys come
+    /// as they are kept, once the password is checked.
+    pub fn export(&self, name: &str, password: &str) -> Result<KeyFile> {
+        match self.key_file(name)? {
+            Some(file) if file.is_eip2335() => file.decrypt(name, password).map(|_| file),
+            Some(file) => Ok(KeyFile::encrypt(
+                &file.decrypt(name, password)?,
+                password,
+                file.path,
+            )),
+            None => Ok(KeyFile::encrypt(
+                &self.load_key(name, None)?,
+                password,
+                None,
+            )),
+        }
+    }
+
+    /// Encrypt every plain key with `password` into an EIP-2335 keystore,
+    /// and bring version 1 files to EIP-2335, returning the names of the
+    /// keys rewritten. A key written anew replaces the old file only once
+    /// it is on disk, so a failure leaves the old file in place.
+    pub fn migrate(&self, password: &str) -> Result<Vec<String>> {
+        let mut migrated = Vec::new();
+        for entry in self.list()? {
+            let (key, path, old) = match self.key_file(&entry.name)? {
+                Some(file) if file.is_eip2335() => continue,
+                Some(file) => (
+                    file.decrypt(&entry.name, password)?,
+                    file.path,
+                    self.path(&entry.name, "json")?,
+                ),
+                None => (
+                    self.load_key(&entry.name, None)?,
+                    None,
+                    self.path(&entry.name, "key")?,
+                ),
+            };
+            let file = KeyFile::encrypt(&key, password, path);
+            let contents = serde_json::to_string_pretty(&file)
+                .map_err(|e| KeystoreError::InvalidFile(e.to_string()))?;
+            let staged = self.dir.join(format!("{}.json.new", entry.name));
+            let _ = std::fs::remove_file(&staged);
+            write_new(&staged, &contents)?;
+            std::fs::rename(&staged, self.path(&entry.name, "json")?)?;
+            if old.extension().is_some_and(|extension| extension == "key") {
+                std::fs::remove_file(old)?;
+            }
+            migrated.push(entry.name);
+        }
+        Ok(migrated)
+    }
+
+    /// Every key, by name
+    pub fn list(&self) -> Result<Vec<KeyEntry>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut keys = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let (Some(name), Some(extension)) = (
+                path.file_stem().and_then(|name| name.to_str()),
+                path.extension().and_then(|extension| extension.to_str()),
+            ) else {
+                continue;
+            };
+            let key = match extension {
+                "json" => self.key_file(name)?.map(|file| KeyEntry {
+                    name: name.to_string(),
+                    scheme: file.key_scheme(),
+                    address: file.pubkey,
+                    encrypted: true,
+                    path: file.path,
+                }),
+                "key" => {
+                    let key = self.load_key(name, None)?;
+                    Some(KeyEntry {
+                        name: name.to_string(),
+                        scheme: key.scheme(),
+                        address: hex::encode(key.public_key()),
+                        encrypted: false,
+                        path: None,
+                    })
+                }
+                _ => None,
+            };
+            keys.extend(key);
+        }
+        keys.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(keys)
+    }
+
+    fn key_file(&self, name: &str) -> Result<Option<KeyFile>> {
+        match std::fs::read_to_string(self.path(name, "json")?) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map(Some)
+                .map_err(|e| KeystoreError::InvalidFile(format!("{}: {}", name, e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write a new key file, readable by its owner only
+    fn write(&self, name: &str, extension: &str, contents: &str) -> Result<()> {
+        if self.path(name, "key")?.exists() || self.path(name, "json")?.exists() {
+            return Err(KeystoreError::Exists(name.to_string()));
+        }
+        std::fs::create_dir_all(&self.dir)?;
+        write_new(&self.path(name, extension)?, contents).map_err(|e| match e {
+            KeystoreError::Io(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                KeystoreError::Exists(name.to_string())
+            }
+            e => e,
+        })
+    }
+
+    fn path(&self, name: &str, extension: &str) -> Result<PathBuf> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(KeystoreError::InvalidName(name.to_string()));
+        }
+        Ok(self.dir.join(format!("{}.{}", name, extension)))
+    }
+}
+
+/// Write a file that must not exist yet, readable by its owner only
+fn write_new(path: &Path, contents: &str) -> Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eip2333_vectors() {
+        // EIP-2333 test case 0, from the seed of BIP39's first vector
+        let seed = hex::decode(
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553\
+             1f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+        )
+        .unwrap();
+        let master = derive_master_sk(&seed);
+        assert_eq!(
+            master.to_string(),
+            "6083874454709270928345386274498605044986640685124978867557563392430687146096"
+        );
+        assert_eq!(
+            derive_child_sk(&master, 0).to_string(),
+            "20397789859736650942317412262472558107875392172444076792671091975210932703118"
+        );
+    }
+
+    #[test]
+    fn test_derivation_path() {
+        let path: DerivationPath = "m/12381/3600/i/0/0".parse().unwrap();
+        assert_eq!(path.to_string(), "m/12381/3600/i/0/0");
+        assert_eq!(path.at(7), "m/12381/3600/7/0/0");
+        assert_eq!(DerivationPath::default().at(3), derivation_path(3));
+        assert!(!"m/12381/3600/0/0/0"
+            .parse::<DerivationPath>()
+            .unwrap()
+            .is_indexed());
+        for bad in [
+            "12381/1337/i/0",
+            "m/44/60/i",
+            "m/12381/i/i",
+            "m/12381/x",
+            "m/12381/-1",
+        ] {
+            assert!(bad.parse::<DerivationPath>().is_err(), "{}", bad);
+        }
+
+        let dir = std::env::temp_dir().join(format!("keystore-derive-{}", std::process::id()));
+        let keystore = Keystore::new(&dir);
+        let phrase = generate_mnemonic();
+        let keys = keystore
+            .derive(&phrase, "agent", &path, 2..5, Scheme::Ed25519, None)
+            .unwrap();
+        let names: Vec<&str> = keys.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["agent-2", "agent-3", "agent-4"]);
+        assert_eq!(
+            keys[1].1,
+            scheme_key_at(&phrase, &path, 3, Scheme::Ed25519).unwrap()
+        );
+        assert_ne!(
+            keys[1].1,
+            scheme_key_from_mnemonic(&phrase, 3, Scheme::Ed25519).unwrap()
+        );
+
+        // One taken name keeps all of them out
+        assert!(matches!(
+            keystore.derive(&phrase, "agent", &path, 0..3, Scheme::Ed25519, None),
+            Err(KeystoreError::Exists(name)) if name == "agent-2"
+        ));
+        assert!(keystore.load_key("agent-0", None).is_err());
+        let fixed: DerivationPath = "m/12381/3600/0/0/0".parse().unwrap();
+        assert!(keystore
+            .derive(&phrase, "eth", &fixed, 0..2, Scheme::Ed25519, None)
+            .is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_keystore() {
+        let dir = std::env::temp_dir().join(format!("keystore-{}", std::process::id()));
+        let keystore = Keystore::new(&dir);
+
+        let phrase = generate_mnemonic();
+        assert_eq!(phrase.split_whitespace().count(), 24);
+        let key = key_from_mnemonic(&phrase, 0).unwrap();
+        assert_eq!(key, key_from_mnemonic(&format!(" {} ", phrase), 0).unwrap());
+        assert_ne!(key, key_from_mnemonic(&phrase, 1).unwrap());
+        assert!(key_from_mnemonic("chaos chaos chaos", 0).is_err());
+
+        let file =
+            KeyFile::encrypt_with(&key.clone().into(), "hunter2", Some(derivation_path(0)), 4);
+        keystore.import("alice", &file).unwrap();
+        keystore.add("bob", &key, None, None).unwrap();
+        assert!(matches!(
+            keystore.add("bob", &key, Some("x"), None),
+            Err(KeystoreError::Exists(_))
+        ));
+        assert!(matches!(
+            keystore.load("alice", None),
+            Err(KeystoreError::PasswordRequired(_))
+        ));
+        assert!(matches!(
+            keystore.load("alice", Some("hunter3")),
+            Err(KeystoreError::WrongPassword(_))
+        ));
+        assert_eq!(keystore.load("alice", Some("hunter2")).unwrap(), key);
+        assert_eq!(keystore.export("alice", "hunter2").unwrap(), file);
+        let exported = keystore.export("bob", "swordfish").unwrap();
+        assert_eq!(
+            exported.decrypt("bob", "swordfish").unwrap(),
+            key.clone().into()
+        );
+
+        let keys = keystore.list().unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].address, keys[1].address);
+        assert_eq!(keys[0].path.as_deref(), Some("m/12381/1337/0/0"));
+        assert!(keys[0].encrypted && !keys[1].encrypted);
+        assert!(matches!(
+            keystore.load("../alice", None),
+            Err(KeystoreError::InvalidName(_))
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// The EIP-2335 test vector of `kdf`, whose password is
+    /// `testpassword🔑` once normalized
+    fn vector(kdf: serde_json::Value, checksum: &str, message: &str) -> KeyFile {
+        serde_json::from_value(serde_json::json!({
+            "crypto": {
+                "kdf": kdf,
+                "checksum": {"function": "sha256", "params": {}, "message": checksum},
+                "cipher": {
+                    "function": "aes-128-ctr",
+                    "params": {"iv": "264daa3f303d7259501c93d997d84fe6"},
+                    "message": message
+                }
+            },
+            "description": "This is a test keystore",
+            "pubkey": "9612d7a727c9d0a22e185a1c768478dfe919cada9266988cb32359c11f2b7b27f4ae4040902382ae2910c15e2b420d07",
+            "path": "m/12381/60/3141592653/589793238",
+            "uuid": "1d85ae20-35c5-4611-98e8-aa14a633906f",
+            "version": 4
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_eip2335_vectors() {
+        let salt = "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3";
+        let secret = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        let scrypt = vector(
+            serde_json::json!({
+                "function": "scrypt",
+                "params": {"dklen": 32, "n": 262144, "p": 1, "r": 8, "salt": salt},
+                "message": ""
+            }),
+            "d2217fe5f3e9a1e34581ef8a78f7c9928e436d36dacc5e846690a5581e8ea484",
+            "06ae90d55fe0a6e9c5c3bc5b170827b2e5cce3929ed3f116c2811e6366dfe20f",
+        );
+        let pbkdf2 = vector(
+            serde_json::json!({
+                "function": "pbkdf2",
+                "params": {"dklen": 32, "c": 262144, "prf": "hmac-sha256", "salt": salt},
+                "message": ""
+            }),
+            "8a9f5d9912ed7e75ea794bc5a89bca5f193721d30868ade6f73043c6ea6febf1",
+            "cee03fde2af33149775b7223e7845e4fb2c8ae1792e5f99fe9ecf474cc8c16ad",
+        );
+        for file in [scrypt, pbkdf2] {
+            assert_eq!(
+                hex::encode(file.decrypt_secret("vector", "testpassword🔑").unwrap()),
+                secret
+            );
+            assert!(matches!(
+                file.decrypt_secret("vector", "testpassword"),
+                Err(KeystoreError::WrongPassword(_))
+            ));
+            // A BLS key, as the public key of the file tells
+            let key = file.decrypt("vector", "testpassword🔑").unwrap();
+            assert_eq!(key.scheme(), Scheme::Bls12381);
+            assert_eq!(hex::encode(key.public_key()), file.pubkey);
+            assert_eq!(
+                file.path.as_deref(),
+                Some("m/12381/60/3141592653/589793238")
+            );
+        }
+
+        let key = SecretKey::generate(Scheme::Ed25519);
+        let file = KeyFile::encrypt_with(&key, "hunter2\u{7f}", None, 4);
+        let json = serde_json::to_value(&file).unwrap();
+        assert_eq!(json["version"], 4);
+        assert_eq!(json["path"], "");
+        assert_eq!(json["crypto"]["cipher"]["function"], "aes-128-ctr");
+        assert!(json.get("scheme").is_none());
+        assert_eq!(file.uuid.len(), 36);
+        assert_eq!(file.decrypt("key", "hunter2").unwrap(), key);
+    }
+
+    /// A version 1 file, as keys were encrypted before EIP-2335
+    fn sealed(key: &SecretKey, password: &str) -> KeyFile {
+        let kdf = SealedKdf::Scrypt {
+            log_n: 4,
+            r: 8,
+            p: 1,
+            salt: hex::encode([7u8; 32]),
+        };
+        let ciphertext = stretch(&kdf, password)
+            .unwrap()
+            .encrypt(&[9u8; 12].into(), key.to_bytes().as_slice())
+            .unwrap();
+        KeyFile {
+            crypto: KeyCrypto::Sealed {
+                kdf,
+                cipher: SealedCipher::ChaCha20Poly1305 {
+                    nonce: hex::encode([9u8; 12]),
+                },
+                ciphertext: hex::encode(ciphertext),
+            },
+            description: String::new(),
+            pubkey: hex::encode(key.public_key()),
+            path: Some(derivation_path(3)),
+            uuid: String::new(),
+            version: 1,
+            scheme: key.scheme(),
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let dir = std::env::temp_dir().join(format!("keystore-migrate-{}", std::process::id()));
+        let keystore = Keystore::new(&dir);
+        let plain = SecretKey::generate(Scheme::Secp256k1);
+        let old = SecretKey::generate(Scheme::Ed25519);
+        keystore.add_key("plain", &plain, None, None).unwrap();
+        let file = sealed(&old, "hunter2");
+        keystore.import("old", &file).unwrap();
+        let text = std::fs::read_to_string(dir.join("old.json")).unwrap();
+        assert_eq!(serde_json::from_str::<KeyFile>(&text).unwrap(), file);
+        assert_eq!(keystore.load_key("old", Some("hunter2")).unwrap(), old);
+        keystore
+            .add_key("new", &old, Some("hunter2"), None)
+            .unwrap();
+
+        assert!(matches!(
+            keystore.migrate("hunter3"),
+            Err(KeystoreError::WrongPassword(_))
+        ));
+        assert_eq!(keystore.migrate("hunter2").unwrap(), vec!["old", "plain"]);
+        assert!(keystore.migrate("hunter2").unwrap().is_empty());
+        assert!(!dir.join("plain.key").exists());
+        assert_eq!(keystore.load_key("plain", Some("hunter2")).unwrap(), plain);
+        assert_eq!(keystore.load_key("old", Some("hunter2")).unwrap(), old);
+        let keys = keystore.list().unwrap();
+        assert!(keys.iter().all(|entry| entry.encrypted));
+        assert_eq!(keys[1].path.as_deref(), Some("m/12381/1337/3/0"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_schemes() {
+        let dir = std::env::temp_dir().join(format!("keystore-schemes-{}", std::process::id()));
+        let keystore = Keystore::new(&dir);
+
+        let phrase = generate_mnemonic();
+        let key = scheme_key_from_mnemonic(&phrase, 0, Scheme::Secp256k1).unwrap();
+        assert_eq!(
+            *key.to_bytes(),
+            key_from_mnemonic(&phrase, 0).unwrap().to_bytes()
+        );
+        keystore.add_key("plain", &key, None, None).unwrap();
+        let file = KeyFile::encrypt_with(&key, "hunter2", Some(derivation_path(0)), 4);
+        keystore.import("sealed", &file).unwrap();
+        assert!(std::fs::read_to_string(dir.join("plain.key"))
+            .unwrap()
+            .starts_with("secp256k1:"));
+
+        assert_eq!(keystore.load_key("plain", None).unwrap(), key);
+        assert_eq!(keystore.load_key("sealed", Some("hunter2")).unwrap(), key);
+        assert!(matches!(
+            keystore.load("plain", None),
+            Err(KeystoreError::WrongScheme(_, Scheme::Secp256k1))
+        ));
+        let keys = keystore.list().unwrap();
+        assert!(keys.iter().all(|entry| entry.scheme == Scheme::Secp256k1
+            && entry.address == hex::encode(key.public_key())));
+
+        // Files from before schemes were named are ed25519
+        let mut old = serde_json::to_value(KeyFile::encrypt_with(
+            &SecretKey::generate(Scheme::Ed25519),
+            "hunter2",
+            None,
+            4,
+        ))
+        .unwrap();
+        old.as_object_mut().unwrap().remove("scheme");
+        let old: KeyFile = serde_json::from_value(old).unwrap();
+        assert_eq!(old.scheme, Scheme::Ed25519);
+        assert!(old.decrypt("old", "hunter2").is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,475 @@
+//! Keys that never leave a hardware wallet. A Ledger, or any HID device
+//! speaking the same APDUs, holds the keys of the mnemonic it was set up
+//! with and signs on the device, once its owner has looked at what comes
+//! to it and approved.
+//!
+//! The ChaosChain app answers APDUs of class `0xCC`:
+//!
+//! - `0x02` gets the public key of a path, shown on the device to confirm
+//!   when P1 is `0x01`
+//! - `0x03` signs a message, sent in chunks of at most 255 bytes: P1 is
+//!   `0x00` for the first, which starts with the path, `0x01` after, and P2
+//!   `0x80` while more chunks follow
+//!
+//! A path is its number of components then each as a big endian `u32`:
+//! `m/12381'/1337'/<index>'/0'`, the path of
+//! [`crate::keystore::derivation_path`] with every component hardened, as
+//! ed25519 derivation on devices needs. Over HID, APDUs are cut into
+//! reports of 64 bytes on channel `0x0101`, as Ledger devices do.
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use parking_lot::Mutex;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::keystore::{COIN_TYPE, PURPOSE};
+use crate::signer::{Signer, SignerError};
+
+/// USB vendor id of Ledger devices
+pub const LEDGER_VENDOR_ID: u16 = 0x2c97;
+
+const CLA: u8 = 0xcc;
+const INS_GET_PUBLIC_KEY: u8 = 0x02;
+const INS_SIGN: u8 = 0x03;
+const P1_CONFIRM: u8 = 0x01;
+const P1_MORE: u8 = 0x01;
+const P2_MORE: u8 = 0x80;
+/// Most bytes of data an APDU carries
+const MAX_CHUNK: usize = 255;
+
+const SW_OK: u16 = 0x9000;
+const SW_DENIED: u16 = 0x6985;
+/// Answers of the dashboard, or of another app, to a class it does not know
+const SW_NO_APP: &[u16] = &[0x6d00, 0x6e00, 0x6e01, 0x6511];
+
+const REPORT_SIZE: usize = 64;
+const CHANNEL: u16 = 0x0101;
+const TAG_APDU: u8 = 0x05;
+
+/// Sends APDUs to a device and returns its answers, status word included
+pub trait Transport: Send {
+    fn exchange(&mut self, apdu: &[u8]) -> Result<Vec<u8>, SignerError>;
+}
+
+/// APDUs over a HID device, as 64 byte reports
+pub struct Hid<D> {
+    device: D,
+}
+
+impl<D: Read + Write + Send> Hid<D> {
+    pub fn new(device: D) -> Self {
+        Self { device }
+    }
+}
+
+impl Hid<std::fs::File> {
+    /// Open a `/dev/hidraw*` device
+    pub fn open(path: &Path) -> Result<Self, SignerError> {
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| SignerError::Device(format!("{}: {}", path.display(), e)))?;
+        Ok(Self::new(device))
+    }
+}
+
+impl<D: Read + Write + Send> Transport for Hid<D> {
+    fn exchange(&mut self, apdu: &[u8]) -> Result<Vec<u8>, SignerError> {
+        let io = |e: std::io::Error| SignerError::Device(e.to_string());
+        for report in frames(apdu) {
+            // hidraw takes the report id first, 0 for devices without ids
+            let mut write = [0u8; REPORT_SIZE + 1];
+            write[1..].copy_from_slice(&report);
+            self.device.write_all(&write).map_err(io)?;
+        }
+        let mut reassembly = Reassembly::default();
+        loop {
+            let mut report = [0u8; REPORT_SIZE];
+            self.device.read_exact(&mut report).map_err(io)?;
+            if let Some(answer) = reassembly.push(&report)? {
+                return Ok(answer);
+            }
+        }
+    }
+}
+
+/// `apdu` cut into HID reports: channel, tag and sequence number on each,
+/// and the length of the APDU on the first
+pub fn frames(apdu: &[u8]) -> Vec<[u8; REPORT_SIZE]> {
+    let mut data = (apdu.len() as u16).to_be_bytes().to_vec();
+    data.extend_from_slice(apdu);
+    data.chunks(REPORT_SIZE - 5)
+        .enumerate()
+        .map(|(seq, chunk)| {
+            let mut report = [0u8; REPORT_SIZE];
+            report[..2].copy_from_slice(&CHANNEL.to_be_bytes());
+            report[2] = TAG_APDU;
+            report[3..5].copy_from_slice(&(seq as u16).to_be_bytes());
+            report[5..5 + chunk.len()].copy_from_slice(chunk);
+            report
+        })
+        .collect()
+}
+
+/// An APDU put back together from its reports
+#[derive(Debug, Default)]
+pub struct Reassembly {
+    length: usize,
+    data: Vec<u8>,
+    next_seq: u16,
+}
+
+impl Reassembly {
+    /// Take the next report, and return the APDU once it is complete
+    pub fn push(&mut self, report: &[u8; REPORT_SIZE]) -> Result<Option<Vec<u8>>, SignerError> {
+        let channel = u16::from_be_bytes([report[0], report[1]]);
+        let seq = u16::from_be_bytes([report[3], report[4]]);
+        if channel != CHANNEL || report[2] != TAG_APDU || seq != self.next_seq {
+            return Err(SignerError::Device(format!(
+                "Unexpected report {} on channel {:04x}",
+                seq, channel
+            )));
+        }
+        let mut chunk = &report[5..];
+        if seq == 0 {
+            self.length = u16::from_be_bytes([chunk[0], chunk[1]]) as usize;
+            chunk = &chunk[2..];
+        }
+        self.next_seq += 1;
+        let wanted = self.length - self.data.len();
+        self.data
+            .extend_from_slice(&chunk[..wanted.min(chunk.len())]);
+        Ok((self.data.len() == self.length).then(|| std::mem::take(&mut self.data)))
+    }
+}
+
+/// The `/dev/hidraw*` devices of Ledgers plugged in, on the interface that
+/// takes APDUs
+pub fn find_devices() -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/hidraw") else {
+        return Vec::new();
+    };
+    let vendor = format!(":{:08X}:", LEDGER_VENDOR_ID);
+    let mut devices: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let Ok(uevent) = std::fs::read_to_string(entry.path().join("device/uevent")) else {
+                return false;
+            };
+            uevent.lines().any(|line| {
+                line.strip_prefix("HID_ID=")
+                    .is_some_and(|id| id.to_uppercase().contains(&vendor))
+            }) && uevent
+                .lines()
+                .any(|line| line.starts_with("HID_PHYS=") && line.ends_with("/input0"))
+        })
+        .map(|entry| Path::new("/dev").join(entry.file_name()))
+        .collect();
+    devices.sort();
+    devices
+}
+
+/// The path of the `index`th key, hardened as ed25519 needs
+pub fn path(index: u32) -> Vec<u32> {
+    [PURPOSE, COIN_TYPE, index, 0]
+        .into_iter()
+        .map(|component| component | 0x8000_0000)
+        .collect()
+}
+
+fn encode_path(path: &[u32]) -> Vec<u8> {
+    let mut bytes = vec![path.len() as u8];
+    for component in path {
+        bytes.extend_from_slice(&component.to_be_bytes());
+    }
+    bytes
+}
+
+fn apdu(ins: u8, p1: u8, p2: u8, data: &[u8]) -> Vec<u8> {
+    let mut apdu = vec![CLA, ins, p1, p2, data.len() as u8];
+    apdu.extend_from_slice(data);
+    apdu
+}
+
+/// Signs with the key of a path on a hardware wallet
+pub struct LedgerSigner {
+    transport: Mutex<Box<dyn Transport>>,
+    path: Vec<u32>,
+    public_key: VerifyingKey,
+}
+
+impl LedgerSigner {
+    /// The `index`th key of the device at `device`, or of the only Ledger
+    /// plugged in
+    pub fn open(device: Option<&Path>, index: u32) -> Result<Self, SignerError> {
+        let device = match device {
+            Some(device) => device.to_path_buf(),
+            None => match find_devices().as_slice() {
+                [device] => device.clone(),
+                [] => return Err(SignerError::NoDevice(", plug it in and unlock it".into())),
+                devices => {
+                    return Err(SignerError::NoDevice(format!(
+                        " to pick among {}, say which with --device",
+                        devices.len()
+                    )))
+                }
+            },
+        };
+        Self::new(Box::new(Hid::open(&device)?), index)
+    }
+
+    /// The `index`th key of the device `transport` reaches
+    pub fn new(transport: Box<dyn Transport>, index: u32) -> Result<Self, SignerError> {
+        let path = path(index);
+        let mut transport = transport;
+        let public_key = get_public_key(transport.as_mut(), &path, false)?;
+        Ok(Self {
+            transport: Mutex::new(transport),
+            path,
+            public_key,
+        })
+    }
+
+    /// Show the address on the device, for its owner to check against the
+    /// one printed here and approve
+    pub fn confirm_address(&self) -> Result<(), SignerError> {
+        let shown = get_public_key(self.transport.lock().as_mut(), &self.path, true)?;
+        if shown != self.public_key {
+            return Err(SignerError::Device(
+                "The device showed another key than it gave".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Signer for LedgerSigner {
+    fn public_key(&self) -> VerifyingKey {
+        self.public_key
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        let mut data = encode_path(&self.path);
+        data.extend_from_slice(message);
+        let chunks: Vec<&[u8]> = data.chunks(MAX_CHUNK).collect();
+        let mut transport = self.transport.lock();
+        let mut answer = Vec::new();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let p1 = if index == 0 { 0 } else { P1_MORE };
+            let p2 = if index + 1 < chunks.len() { P2_MORE } else { 0 };
+            answer = check(transport.exchange(&apdu(INS_SIGN, p1, p2, chunk))?)?;
+        }
+        let signature = Signature::from_slice(&answer).map_err(|_| SignerError::BadSignature)?;
+        self.public_key
+            .verify(message, &signature)
+            .map_err(|_| SignerError::BadSignature)?;
+        Ok(signature)
+    }
+}
+
+fn get_public_key(
+    transport: &mut dyn Transport,
+    path: &[u32],
+    confirm: bool,
+) -> Result<VerifyingKey, SignerError> {
+    let p1 = if confirm { P1_CONFIRM } else { 0 };
+    let answer =
+        check(transport.exchange(&apdu(INS_GET_PUBLIC_KEY, p1, 0, &encode_path(path)))?)?;
+    let bytes: [u8; 32] = answer
+        .try_into()
+        .map_err(|_| SignerError::Device("The public key is not 32 bytes".to_string()))?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|e| SignerError::Device(format!("Invalid public key: {}", e)))
+}
+
+/// The data of an answer, or what its status word says went wrong
+fn check(mut answer: Vec<u8>) -> Result<Vec<u8>, SignerError> {
+    if answer.len() < 2 {
+        return Err(SignerError::Device("The answer has no status".to_string()));
+    }
+    let status = answer.split_off(answer.len() - 2);
+    match u16::from_be_bytes([status[0], status[1]]) {
+        SW_OK => Ok(answer),
+        SW_DENIED => Err(SignerError::Rejected),
+        status if SW_NO_APP.contains(&status) => Err(SignerError::AppNotOpen),
+        status => Err(SignerError::Device(format!("Status {:04x}", status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keystore::key_from_mnemonic;
+    use ed25519_dalek::SigningKey;
+    use std::collections::VecDeque;
+
+    const MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+    /// The ChaosChain app on a device set up with `MNEMONIC`, behind HID
+    struct Device {
+        approve: bool,
+        request: Reassembly,
+        pending: Vec<u8>,
+        reports: VecDeque<[u8; REPORT_SIZE]>,
+        shown: Vec<String>,
+    }
+
+    impl Device {
+        fn new(approve: bool) -> Self {
+            Self {
+                approve,
+                request: Reassembly::default(),
+                pending: Vec::new(),
+                reports: VecDeque::new(),
+                shown: Vec::new(),
+            }
+        }
+
+        fn key(path: &[u8]) -> (SigningKey, usize) {
+            let components = path[0] as usize;
+            let index = u32::from_be_bytes(path[9..13].try_into().unwrap()) & 0x7fff_ffff;
+            (
+                key_from_mnemonic(MNEMONIC, index).unwrap(),
+                1 + 4 * components,
+            )
+        }
+
+        fn answer(&mut self, apdu: &[u8]) -> Vec<u8> {
+            let (ins, p1, p2, data) = (apdu[1], apdu[2], apdu[3], &apdu[5..]);
+            if apdu[0] != CLA {
+                return 0x6e00u16.to_be_bytes().to_vec();
+            }
+            let denied = SW_DENIED.to_be_bytes().to_vec();
+            let ok = |mut data: Vec<u8>| {
+                data.extend_from_slice(&SW_OK.to_be_bytes());
+                data
+            };
+            match ins {
+                INS_GET_PUBLIC_KEY => {
+                    let public_key = Self::key(data).0.verifying_key().to_bytes().to_vec();
+                    if p1 == P1_CONFIRM {
+                        self.shown.push(hex::encode(&public_key));
+                        if !self.approve {
+                            return denied;
+                        }
+                    }
+                    ok(public_key)
+                }
+                INS_SIGN => {
+                    if p1 == 0 {
+                        self.pending.clear();
+                    }
+                    self.pending.extend_from_slice(data);
+                    if p2 == P2_MORE {
+                        return ok(Vec::new());
+                    }
+                    let (key, skip) = Self::key(&self.pending);
+                    let message = self.pending[skip..].to_vec();
+                    self.shown.push(hex::encode(&message));
+                    if !self.approve {
+                        return denied;
+                    }
+                    ok(ed25519_dalek::Signer::sign(&key, &message)
+                        .to_bytes()
+                        .to_vec())
+                }
+                _ => 0x6d00u16.to_be_bytes().to_vec(),
+            }
+        }
+    }
+
+    impl Read for Device {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let report = self.reports.pop_front().expect("a report to read");
+            buf[..REPORT_SIZE].copy_from_slice(&report);
+            Ok(REPORT_SIZE)
+        }
+    }
+
+    impl Write for Device {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            assert_eq!((buf.len(), buf[0]), (REPORT_SIZE + 1, 0));
+            let report: [u8; REPORT_SIZE] = buf[1..].try_into().unwrap();
+            if let Some(apdu) = self.request.push(&report).unwrap() {
+                self.request = Reassembly::default();
+                let answer = self.answer(&apdu);
+                self.reports.extend(frames(&answer));
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Lets the test look at the device while the signer holds it
+    struct Shared(std::sync::Arc<Mutex<Hid<Device>>>);
+
+    impl Transport for Shared {
+        fn exchange(&mut self, apdu: &[u8]) -> Result<Vec<u8>, SignerError> {
+            self.0.lock().exchange(apdu)
+        }
+    }
+
+    #[test]
+    fn test_frames_reassemble() {
+        for size in [0usize, 1, 57, 58, 200, 300] {
+            let apdu: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let frames = frames(&apdu);
+            assert_eq!(frames.len(), (size + 2).div_ceil(REPORT_SIZE - 5).max(1));
+            let mut reassembly = Reassembly::default();
+            let (last, first) = frames.split_last().unwrap();
+            for frame in first {
+                assert!(reassembly.push(frame).unwrap().is_none());
+            }
+            assert_eq!(reassembly.push(last).unwrap(), Some(apdu));
+        }
+
+        // Out of order reports are turned down
+        let frames = frames(&[0u8; 100]);
+        assert!(Reassembly::default().push(&frames[1]).is_err());
+    }
+
+    #[test]
+    fn test_ledger_signs_on_the_device() {
+        let device = std::sync::Arc::new(Mutex::new(Hid::new(Device::new(true))));
+        let signer = LedgerSigner::new(Box::new(Shared(device.clone())), 2).unwrap();
+        let expected = key_from_mnemonic(MNEMONIC, 2).unwrap();
+        assert_eq!(signer.public_key(), expected.verifying_key());
+
+        signer.confirm_address().unwrap();
+        // Long enough to go in several APDUs
+        let message = vec![7u8; 600];
+        let signature = signer.sign(&message).unwrap();
+        assert_eq!(signature, ed25519_dalek::Signer::sign(&expected, &message));
+
+        let shown = device.lock().device.shown.clone();
+        assert_eq!(
+            shown,
+            vec![
+                hex::encode(expected.verifying_key().to_bytes()),
+                hex::encode(&message)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ledger_rejection() {
+        let device = std::sync::Arc::new(Mutex::new(Hid::new(Device::new(false))));
+        let signer = LedgerSigner::new(Box::new(Shared(device)), 0).unwrap();
+        assert!(matches!(
+            signer.confirm_address(),
+            Err(SignerError::Rejected)
+        ));
+        assert!(matches!(signer.sign(b"drama"), Err(SignerError::Rejected)));
+
+        assert!(matches!(
+            check(vec![0x6e, 0x00]),
+            Err(SignerError::AppNotOpen)
+        ));
+        assert!(matches!(check(vec![1, 2, 0x90, 0x00]), Ok(data) if data == [1, 2]));
+    }
+}
@@ -1,3 +1,15 @@
+pub mod batch;
+pub mod hash;
+pub mod keystore;
+pub mod ledger;
+#[cfg(unix)]
+pub mod pkcs11;
+pub mod scheme;
+pub mod secret;
+pub mod signer;
+pub mod threshold;
+pub mod vrf;
+
 use ed25519_dalek::{
     Signature, Signer, SigningKey, Verifier, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH,
 };
@@ -86,6 +98,23 @@ impl KeyManager {
         Ok(agent)
     }
 
+    /// Know an agent by its public key alone, to verify what it signs.
+    /// An agent known already is left as it was.
+    pub fn register_public_key(&self, key: &VerifyingKey, name: String, role: String) -> AgentKeys {
+        let id = hex::encode(key.as_bytes());
+        self.agents
+            .write()
+            .entry(id.clone())
+            .or_insert(AgentKeys {
+                id,
+                name,
+                role,
+                drama_score: 50,
+                stake: 0,
+            })
+            .clone()
+    }
+
     /// Sign data with agent's key
     pub fn sign(&self, agent_id: &str, data: &[u8]) -> Result<[u8; SIGNATURE_LENGTH], CryptoError> {
         let keys = self.signing_keys.read();
@@ -128,6 +157,35 @@ impl KeyManager {
         }
     }
 
+    /// Verify the signatures of many agents at once, see [`batch`]. Fails
+    /// on the first agent without a key, else says of each whether it holds.
+    pub fn verify_batch(
+        &self,
+        signatures: &[(&str, &[u8], &[u8; SIGNATURE_LENGTH])],
+    ) -> Result<Vec<bool>, CryptoError> {
+        let agents = self.agents.read();
+        let mut items = Vec::with_capacity(signatures.len());
+        for (agent_id, data, signature) in signatures {
+            let agent = agents
+                .get(*agent_id)
+                .ok_or_else(|| CryptoError::KeyNotFound(agent_id.to_string()))?;
+            let public_key = hex::decode(&agent.id)
+                .ok()
+                .and_then(|bytes| bytes.get(..PUBLIC_KEY_LENGTH)?.try_into().ok())
+                .ok_or(CryptoError::InvalidKey)?;
+            items.push(batch::Item::new(public_key, data.to_vec(), *signature));
+        }
+        drop(agents);
+
+        let mut valid = vec![true; items.len()];
+        if let Err(e) = batch::verify(&items) {
+            for index in e.invalid {
+                valid[index] = false;
+            }
+        }
+        Ok(valid)
+    }
+
     /// Get agent info
     pub fn get_agent(&self, agent_id: &str) -> Option<AgentKeys> {
         self.agents.read().get(agent_id).cloned()
@@ -222,6 +280,33 @@ mod tests {
         assert!(valid);
     }
 
+    #[test]
+    fn test_register_public_key() {
+        let km = KeyManager::new();
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let agent = km.register_public_key(
+            &signing_key.verifying_key(),
+            "Outsider".to_string(),
+            "account".to_string(),
+        );
+        assert_eq!(
+            agent.id,
+            hex::encode(signing_key.verifying_key().as_bytes())
+        );
+
+        // It verifies, but signs nothing
+        let signature = signing_key.sign(b"test message").to_bytes();
+        assert!(km.verify(&agent.id, b"test message", &signature).unwrap());
+        assert!(km.sign(&agent.id, b"test message").is_err());
+
+        let again = km.register_public_key(
+            &signing_key.verifying_key(),
+            "Renamed".to_string(),
+            "account".to_string(),
+        );
+        assert_eq!(again.name, "Outsider");
+    }
+
     #[test]
     fn test_invalid_signature() {
         let km = KeyManager::new();
@@ -0,0 +1,465 @@
+//! Keys held in an HSM, or in SoftHSM, reached through the PKCS#11
+//! module its vendor ships. The module is loaded when the signer opens, a
+//! session is opened on the token and logged into with the user PIN, and
+//! the ed25519 key pair of the label asked for signs with `CKM_EDDSA`. The
+//! private key never leaves the token.
+//!
+//! Only the calls signing needs are bound, from the function list the
+//! module hands out, so that no PKCS#11 crate is needed. Modules are never
+//! finalized nor unloaded: another signer of the process may share one.
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use parking_lot::Mutex;
+use std::ffi::{c_void, CString};
+use std::os::raw::c_ulong;
+use std::path::Path;
+
+use crate::signer::{Signer, SignerError};
+
+type Ulong = c_ulong;
+type Rv = c_ulong;
+/// A function of the list that is never called here
+type Unused = Option<unsafe extern "C" fn()>;
+
+const CKR_OK: Rv = 0;
+const CKR_PIN_INCORRECT: Rv = 0xa0;
+const CKR_PIN_LOCKED: Rv = 0xa4;
+const CKR_TOKEN_NOT_PRESENT: Rv = 0xe0;
+const CKR_USER_ALREADY_LOGGED_IN: Rv = 0x100;
+const CKR_USER_NOT_LOGGED_IN: Rv = 0x101;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: Rv = 0x191;
+
+const CKF_OS_LOCKING_OK: Ulong = 0x2;
+const CKF_RW_SESSION: Ulong = 0x2;
+const CKF_SERIAL_SESSION: Ulong = 0x4;
+const CKU_USER: Ulong = 1;
+
+const CKA_CLASS: Ulong = 0x0;
+const CKA_LABEL: Ulong = 0x3;
+const CKA_EC_POINT: Ulong = 0x181;
+const CKO_PUBLIC_KEY: Ulong = 0x2;
+const CKO_PRIVATE_KEY: Ulong = 0x3;
+const CKM_EDDSA: Ulong = 0x1057;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Version {
+    major: u8,
+    minor: u8,
+}
+
+#[repr(C)]
+struct InitializeArgs {
+    create_mutex: Unused,
+    destroy_mutex: Unused,
+    lock_mutex: Unused,
+    unlock_mutex: Unused,
+    flags: Ulong,
+    reserved: *mut c_void,
+}
+
+#[repr(C)]
+struct TokenInfo {
+    label: [u8; 32],
+    manufacturer: [u8; 32],
+    model: [u8; 16],
+    serial: [u8; 16],
+    /// Flags, then the session, PIN and memory counts
+    counts: [Ulong; 11],
+    hardware_version: Version,
+    firmware_version: Version,
+    utc_time: [u8; 16],
+}
+
+#[repr(C)]
+struct Attribute {
+    kind: Ulong,
+    value: *mut c_void,
+    length: Ulong,
+}
+
+#[repr(C)]
+struct Mechanism {
+    mechanism: Ulong,
+    parameter: *mut c_void,
+    length: Ulong,
+}
+
+/// `CK_FUNCTION_LIST`, up to `C_Sign`
+#[repr(C)]
+struct FunctionList {
+    version: Version,
+    initialize: unsafe extern "C" fn(*mut InitializeArgs) -> Rv,
+    finalize: Unused,
+    get_info: Unused,
+    get_function_list: Unused,
+    get_slot_list: unsafe extern "C" fn(u8, *mut Ulong, *mut Ulong) -> Rv,
+    get_slot_info: Unused,
+    get_token_info: unsafe extern "C" fn(Ulong, *mut TokenInfo) -> Rv,
+    get_mechanism_list: Unused,
+    get_mechanism_info: Unused,
+    init_token: Unused,
+    init_pin: Unused,
+    set_pin: Unused,
+    open_session: unsafe extern "C" fn(Ulong, Ulong, *mut c_void, Unused, *mut Ulong) -> Rv,
+    close_session: unsafe extern "C" fn(Ulong) -> Rv,
+    close_all_sessions: Unused,
+    get_session_info: Unused,
+    get_operation_state: Unused,
+    set_operation_state: Unused,
+    login: unsafe extern "C" fn(Ulong, Ulong, *const u8, Ulong) -> Rv,
+    logout: Unused,
+    create_object: Unused,
+    copy_object: Unused,
+    destroy_object: Unused,
+    get_object_size: Unused,
+    get_attribute_value: unsafe extern "C" fn(Ulong, Ulong, *mut Attribute, Ulong) -> Rv,
+    set_attribute_value: Unused,
+    find_objects_init: unsafe extern "C" fn(Ulong, *mut Attribute, Ulong) -> Rv,
+    find_objects: unsafe extern "C" fn(Ulong, *mut Ulong, Ulong, *mut Ulong) -> Rv,
+    find_objects_final: unsafe extern "C" fn(Ulong) -> Rv,
+    encrypt_init: Unused,
+    encrypt: Unused,
+    encrypt_update: Unused,
+    encrypt_final: Unused,
+    decrypt_init: Unused,
+    decrypt: Unused,
+    decrypt_update: Unused,
+    decrypt_final: Unused,
+    digest_init: Unused,
+    digest: Unused,
+    digest_update: Unused,
+    digest_key: Unused,
+    digest_final: Unused,
+    sign_init: unsafe extern "C" fn(Ulong, *mut Mechanism, Ulong) -> Rv,
+    sign: unsafe extern "C" fn(Ulong, *const u8, Ulong, *mut u8, *mut Ulong) -> Rv,
+}
+
+/// Which token of the module holds the key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// The token of this label
+    Label(String),
+    /// The token in this slot
+    Slot(u64),
+    /// The first slot with a token in it
+    First,
+}
+
+/// Signs with an ed25519 key of a PKCS#11 token
+pub struct Pkcs11Signer {
+    functions: &'static FunctionList,
+    /// The session every signature is made on, one at a time
+    session: Mutex<Ulong>,
+    private_key: Ulong,
+    public_key: VerifyingKey,
+}
+
+// The function list is static and the module was initialized to lock for
+// itself; the session is only used behind its mutex.
+unsafe impl Send for Pkcs11Signer {}
+unsafe impl Sync for Pkcs11Signer {}
+
+impl Pkcs11Signer {
+    /// Open the key pair labeled `label` on `token` of `module`, logging in
+    /// with `pin` when given
+    pub fn open(
+        module: &Path,
+        token: &Token,
+        label: &str,
+        pin: Option<&str>,
+    ) -> Result<Self, SignerError> {
+        let functions = load(module)?;
+        let slot = find_slot(functions, token)?;
+        let mut session = 0;
+        call("C_OpenSession", unsafe {
+            (functions.open_session)(
+                slot,
+                CKF_SERIAL_SESSION | CKF_RW_SESSION,
+                std::ptr::null_mut(),
+                None,
+                &mut session,
+            )
+        })?;
+        let opened = open_key(functions, session, label, pin);
+        match opened {
+            Ok((private_key, public_key)) => Ok(Self {
+                functions,
+                session: Mutex::new(session),
+                private_key,
+                public_key,
+            }),
+            Err(e) => {
+                unsafe { (functions.close_session)(session) };
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Log into `session` and find the key pair labeled `label`
+fn open_key(
+    f: &FunctionList,
+    session: Ulong,
+    label: &str,
+    pin: Option<&str>,
+) -> Result<(Ulong, VerifyingKey), SignerError> {
+    if let Some(pin) = pin {
+        match unsafe { (f.login)(session, CKU_USER, pin.as_ptr(), pin.len() as Ulong) } {
+            CKR_OK | CKR_USER_ALREADY_LOGGED_IN => {}
+            rv => call("C_Login", rv)?,
+        }
+    }
+    let private_key =
+        find(f, session, CKO_PRIVATE_KEY, label)?.ok_or_else(|| no_key(label, "private"))?;
+    let public = find(f, session, CKO_PUBLIC_KEY, label)?.ok_or_else(|| no_key(label, "public"))?;
+    let public_key = decode_ec_point(&attribute(f, session, public, CKA_EC_POINT)?)?;
+    Ok((private_key, public_key))
+}
+
+/// The first object of `class` labeled `label`
+fn find(
+    f: &FunctionList,
+    session: Ulong,
+    class: Ulong,
+    label: &str,
+) -> Result<Option<Ulong>, SignerError> {
+    let mut class = class;
+    let mut label = label.as_bytes().to_vec();
+    let mut template = [
+        Attribute {
+            kind: CKA_CLASS,
+            value: &mut class as *mut Ulong as *mut c_void,
+            length: std::mem::size_of::<Ulong>() as Ulong,
+        },
+        Attribute {
+            kind: CKA_LABEL,
+            value: label.as_mut_ptr() as *mut c_void,
+            length: label.len() as Ulong,
+        },
+    ];
+    call("C_FindObjectsInit", unsafe {
+        (f.find_objects_init)(session, template.as_mut_ptr(), template.len() as Ulong)
+    })?;
+    let mut object = 0;
+    let mut found = 0;
+    let rv = unsafe { (f.find_objects)(session, &mut object, 1, &mut found) };
+    call("C_FindObjectsFinal", unsafe {
+        (f.find_objects_final)(session)
+    })?;
+    call("C_FindObjects", rv)?;
+    Ok((found > 0).then_some(object))
+}
+
+fn attribute(
+    f: &FunctionList,
+    session: Ulong,
+    object: Ulong,
+    kind: Ulong,
+) -> Result<Vec<u8>, SignerError> {
+    let mut attribute = Attribute {
+        kind,
+        value: std::ptr::null_mut(),
+        length: 0,
+    };
+    call("C_GetAttributeValue", unsafe {
+        (f.get_attribute_value)(session, object, &mut attribute, 1)
+    })?;
+    let mut value = vec![0u8; attribute.length as usize];
+    attribute.value = value.as_mut_ptr() as *mut c_void;
+    call("C_GetAttributeValue", unsafe {
+        (f.get_attribute_value)(session, object, &mut attribute, 1)
+    })?;
+    value.truncate(attribute.length as usize);
+    Ok(value)
+}
+
+impl Drop for Pkcs11Signer {
+    fn drop(&mut self) {
+        unsafe { (self.functions.close_session)(*self.session.get_mut()) };
+    }
+}
+
+impl Signer for Pkcs11Signer {
+    fn public_key(&self) -> VerifyingKey {
+        self.public_key
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        let session = self.session.lock();
+        let f = self.functions;
+        let mut mechanism = Mechanism {
+            mechanism: CKM_EDDSA,
+            parameter: std::ptr::null_mut(),
+            length: 0,
+        };
+        call("C_SignInit", unsafe {
+            (f.sign_init)(*session, &mut mechanism, self.private_key)
+        })?;
+        let mut signature = [0u8; 64];
+        let mut length = signature.len() as Ulong;
+        call("C_Sign", unsafe {
+            (f.sign)(
+                *session,
+                message.as_ptr(),
+                message.len() as Ulong,
+                signature.as_mut_ptr(),
+                &mut length,
+            )
+        })?;
+        let signature = Signature::from_slice(&signature[..length as usize])
+            .map_err(|_| SignerError::BadSignature)?;
+        self.public_key
+            .verify(message, &signature)
+            .map_err(|_| SignerError::BadSignature)?;
+        Ok(signature)
+    }
+}
+
+/// Load `module` and initialize it, unless the process did already
+fn load(module: &Path) -> Result<&'static FunctionList, SignerError> {
+    let path = CString::new(module.to_string_lossy().as_bytes())
+        .map_err(|_| SignerError::NoDevice(format!(": bad module path {}", module.display())))?;
+    let handle = unsafe { libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if handle.is_null() {
+        return Err(SignerError::NoDevice(format!(
+            ": could not load the PKCS#11 module {}",
+            module.display()
+        )));
+    }
+    let symbol = unsafe { libc::dlsym(handle, c"C_GetFunctionList".as_ptr()) };
+    if symbol.is_null() {
+        return Err(SignerError::NoDevice(format!(
+            ": {} is not a PKCS#11 module",
+            module.display()
+        )));
+    }
+    let get_function_list: unsafe extern "C" fn(*mut *const FunctionList) -> Rv =
+        unsafe { std::mem::transmute(symbol) };
+    let mut functions = std::ptr::null();
+    call("C_GetFunctionList", unsafe {
+        get_function_list(&mut functions)
+    })?;
+    // The list lives as long as the module, which is never unloaded
+    let functions = unsafe { functions.as_ref() }
+        .ok_or_else(|| SignerError::Device("The module gave no function list".to_string()))?;
+    let mut args = InitializeArgs {
+        create_mutex: None,
+        destroy_mutex: None,
+        lock_mutex: None,
+        unlock_mutex: None,
+        flags: CKF_OS_LOCKING_OK,
+        reserved: std::ptr::null_mut(),
+    };
+    match unsafe { (functions.initialize)(&mut args) } {
+        CKR_OK | CKR_CRYPTOKI_ALREADY_INITIALIZED => Ok(functions),
+        rv => call("C_Initialize", rv).map(|_| functions),
+    }
+}
+
+fn find_slot(functions: &FunctionList, token: &Token) -> Result<Ulong, SignerError> {
+    if let Token::Slot(slot) = token {
+        return Ok(*slot as Ulong);
+    }
+    let mut count = 0;
+    call("C_GetSlotList", unsafe {
+        (functions.get_slot_list)(1, std::ptr::null_mut(), &mut count)
+    })?;
+    let mut slots = vec![0; count as usize];
+    call("C_GetSlotList", unsafe {
+        (functions.get_slot_list)(1, slots.as_mut_ptr(), &mut count)
+    })?;
+    slots.truncate(count as usize);
+    let Token::Label(wanted) = token else {
+        return slots
+            .first()
+            .copied()
+            .ok_or_else(|| SignerError::NoDevice(": no slot has a token".to_string()));
+    };
+    for slot in slots {
+        let mut info = std::mem::MaybeUninit::<TokenInfo>::zeroed();
+        call("C_GetTokenInfo", unsafe {
+            (functions.get_token_info)(slot, info.as_mut_ptr())
+        })?;
+        if token_label(&unsafe { info.assume_init() }.label) == wanted {
+            return Ok(slot);
+        }
+    }
+    Err(SignerError::NoDevice(format!(
+        ": no token is labeled {}",
+        wanted
+    )))
+}
+
+/// A label of the token info, padded with spaces to 32 bytes
+fn token_label(label: &[u8; 32]) -> &str {
+    std::str::from_utf8(label)
+        .unwrap_or_default()
+        .trim_end_matches([' ', '\0'])
+}
+
+/// An ed25519 `CKA_EC_POINT`, the 32 bytes of the key either bare or in a
+/// DER octet string, as modules disagree
+fn decode_ec_point(point: &[u8]) -> Result<VerifyingKey, SignerError> {
+    let bytes = match point {
+        [0x04, 0x20, key @ ..] if key.len() == 32 => key,
+        key if key.len() == 32 => key,
+        _ => {
+            return Err(SignerError::Device(format!(
+                "The public key is not an ed25519 point: {}",
+                hex::encode(point)
+            )))
+        }
+    };
+    VerifyingKey::from_bytes(bytes.try_into().expect("32 bytes"))
+        .map_err(|e| SignerError::Device(format!("Invalid public key: {}", e)))
+}
+
+fn no_key(label: &str, class: &str) -> SignerError {
+    SignerError::Device(format!("The token has no {} key labeled {}", class, label))
+}
+
+/// What a function returning `rv` says went wrong
+fn call(function: &str, rv: Rv) -> Result<(), SignerError> {
+    let reason = match rv {
+        CKR_OK => return Ok(()),
+        CKR_PIN_INCORRECT => "the PIN is wrong",
+        CKR_PIN_LOCKED => "the PIN is locked",
+        CKR_TOKEN_NOT_PRESENT => "the token is not there",
+        CKR_USER_NOT_LOGGED_IN => "log in with the user PIN first",
+        _ => {
+            return Err(SignerError::Device(format!(
+                "{} failed: 0x{:x}",
+                function, rv
+            )))
+        }
+    };
+    Err(SignerError::Device(format!(
+        "{} failed: {}",
+        function, reason
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_decode_ec_point() {
+        let key = SigningKey::from_bytes(&[7; 32]).verifying_key();
+        let mut der = vec![0x04, 0x20];
+        der.extend_from_slice(key.as_bytes());
+        assert_eq!(decode_ec_point(&der).unwrap(), key);
+        assert_eq!(decode_ec_point(key.as_bytes()).unwrap(), key);
+        assert!(decode_ec_point(&der[..33]).is_err());
+
+        let mut label = [b' '; 32];
+        label[..5].copy_from_slice(b"chaos");
+        assert_eq!(token_label(&label), "chaos");
+        assert!(call("C_Login", CKR_PIN_INCORRECT)
+            .unwrap_err()
+            .to_string()
+            .contains("the PIN is wrong"));
+    }
+}
@@ -0,0 +1,592 @@
+//! Signature schemes a key can be of. Transactions and votes are signed
+//! with ed25519. secp256k1 keys sign as Ethereum and Bitcoin tooling
+//! expects, ECDSA over SHA-256 with a low `s`, for what is signed off
+//! chain. BLS12-381 keys sign as Ethereum validators do, and their
+//! signatures of one message aggregate into one.
+//!
+//! Each scheme is a [`SignatureScheme`]: keys are made, sign and verify
+//! through it, and schemes that aggregate signatures of one message into
+//! one say so there. What signs transactions and what signs votes are
+//! picked apart, as `TransactionScheme` and `ConsensusScheme` of the core
+//! crate, so that one can be tried out without the other.
+//!
+//! Public keys are 32 bytes for ed25519, 33, SEC1 compressed, for
+//! secp256k1 and 48, compressed points of G1, for BLS12-381. Signatures
+//! are 64 bytes under ed25519 and secp256k1, `r || s` for ECDSA, and 96,
+//! compressed points of G2, under BLS12-381.
+
+use crate::secret::Zeroizing;
+use blst::min_pk as bls;
+use blst::BLST_ERROR;
+use ed25519_dalek::Verifier as _;
+use k256::ecdsa::signature::Signer as _;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+/// Domain of BLS signatures, those of the proof of possession ciphersuite
+/// of the IETF draft, as Ethereum validators sign
+pub(crate) const BLS_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+/// Domain of BLS proofs of possession
+const BLS_POP_DST: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    #[default]
+    Ed25519,
+    Secp256k1,
+    Bls12381,
+}
+
+impl Scheme {
+    pub const ALL: [Scheme; 3] = [Scheme::Ed25519, Scheme::Secp256k1, Scheme::Bls12381];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ed25519 => "ed25519",
+            Self::Secp256k1 => "secp256k1",
+            Self::Bls12381 => "bls12381",
+        }
+    }
+
+    /// Bytes of a signature under the scheme
+    pub fn signature_len(self) -> usize {
+        match self {
+            Self::Ed25519 | Self::Secp256k1 => 64,
+            Self::Bls12381 => 96,
+        }
+    }
+
+    pub fn is_ed25519(&self) -> bool {
+        *self == Self::Ed25519
+    }
+
+    /// The scheme of a public key of `len` bytes, as they differ in length
+    pub fn of_public_key(len: usize) -> Option<Self> {
+        match len {
+            32 => Some(Self::Ed25519),
+            33 | 65 => Some(Self::Secp256k1),
+            48 => Some(Self::Bls12381),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|scheme| scheme.name() == s)
+            .ok_or_else(|| format!("No signature scheme named {}", s))
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum VerifyError {
+    #[error("Not a {0} public key")]
+    PublicKey(Scheme),
+    #[error("Not a {0} signature, which is {} bytes", .0.signature_len())]
+    Malformed(Scheme),
+    #[error("The signature does not verify")]
+    Invalid,
+    #[error("{0} signatures do not aggregate")]
+    NoAggregation(Scheme),
+}
+
+/// A way of signing: its keys, signatures and how they are checked
+pub trait SignatureScheme {
+    const SCHEME: Scheme;
+
+    type SecretKey;
+    type PublicKey;
+    type Signature;
+
+    fn generate() -> Self::SecretKey;
+
+    fn public_key(secret: &Self::SecretKey) -> Self::PublicKey;
+
+    fn sign(secret: &Self::SecretKey, message: &[u8]) -> Self::Signature;
+
+    fn verify(
+        public_key: &Self::PublicKey,
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> Result<(), VerifyError>;
+
+    fn decode_public_key(bytes: &[u8]) -> Result<Self::PublicKey, VerifyError>;
+
+    fn decode_signature(bytes: &[u8]) -> Result<Self::Signature, VerifyError>;
+
+    /// Check a signature as it travels, in bytes
+    fn verify_bytes(
+        public_key: &[u8],
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), VerifyError> {
+        Self::verify(
+            &Self::decode_public_key(public_key)?,
+            message,
+            &Self::decode_signature(signature)?,
+        )
+    }
+
+    /// One signature standing for `signatures` of the same message, under
+    /// schemes that have it
+    fn aggregate(_signatures: &[Self::Signature]) -> Result<Self::Signature, VerifyError> {
+        Err(VerifyError::NoAggregation(Self::SCHEME))
+    }
+
+    /// Check an aggregate signature of `message` by all of `public_keys`
+    fn verify_aggregate(
+        _public_keys: &[Self::PublicKey],
+        _message: &[u8],
+        _signature: &Self::Signature,
+    ) -> Result<(), VerifyError> {
+        Err(VerifyError::NoAggregation(Self::SCHEME))
+    }
+}
+
+/// ed25519 of RFC 8032, without aggregation; see [`crate::batch`] for
+/// checking many signatures at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ed25519;
+
+impl SignatureScheme for Ed25519 {
+    const SCHEME: Scheme = Scheme::Ed25519;
+
+    type SecretKey = ed25519_dalek::SigningKey;
+    type PublicKey = ed25519_dalek::VerifyingKey;
+    type Signature = ed25519_dalek::Signature;
+
+    fn generate() -> Self::SecretKey {
+        ed25519_dalek::SigningKey::generate(&mut OsRng)
+    }
+
+    fn public_key(secret: &Self::SecretKey) -> Self::PublicKey {
+        secret.verifying_key()
+    }
+
+    fn sign(secret: &Self::SecretKey, message: &[u8]) -> Self::Signature {
+        ed25519_dalek::Signer::sign(secret, message)
+    }
+
+    fn verify(
+        public_key: &Self::PublicKey,
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> Result<(), VerifyError> {
+        public_key
+            .verify(message, signature)
+            .map_err(|_| VerifyError::Invalid)
+    }
+
+    fn decode_public_key(bytes: &[u8]) -> Result<Self::PublicKey, VerifyError> {
+        bytes
+            .try_into()
+            .ok()
+            .and_then(|bytes| ed25519_dalek::VerifyingKey::from_bytes(bytes).ok())
+            .ok_or(VerifyError::PublicKey(Self::SCHEME))
+    }
+
+    fn decode_signature(bytes: &[u8]) -> Result<Self::Signature, VerifyError> {
+        ed25519_dalek::Signature::from_slice(bytes)
+            .map_err(|_| VerifyError::Malformed(Self::SCHEME))
+    }
+}
+
+/// ECDSA over secp256k1 with SHA-256, taking only signatures of low `s`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Secp256k1;
+
+impl SignatureScheme for Secp256k1 {
+    const SCHEME: Scheme = Scheme::Secp256k1;
+
+    type SecretKey = k256::ecdsa::SigningKey;
+    type PublicKey = k256::ecdsa::VerifyingKey;
+    type Signature = k256::ecdsa::Signature;
+
+    fn generate() -> Self::SecretKey {
+        k256::ecdsa::SigningKey::random(&mut OsRng)
+    }
+
+    fn public_key(secret: &Self::SecretKey) -> Self::PublicKey {
+        *secret.verifying_key()
+    }
+
+    fn sign(secret: &Self::SecretKey, message: &[u8]) -> Self::Signature {
+        secret.sign(message)
+    }
+
+    fn verify(
+        public_key: &Self::PublicKey,
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> Result<(), VerifyError> {
+        // Either `s` verifies under ECDSA, only the low one is accepted
+        if signature.normalize_s().is_some() {
+            return Err(VerifyError::Invalid);
+        }
+        k256::ecdsa::signature::Verifier::verify(public_key, message, signature)
+            .map_err(|_| VerifyError::Invalid)
+    }
+
+    fn decode_public_key(bytes: &[u8]) -> Result<Self::PublicKey, VerifyError> {
+        k256::ecdsa::VerifyingKey::from_sec1_bytes(bytes)
+            .map_err(|_| VerifyError::PublicKey(Self::SCHEME))
+    }
+
+    fn decode_signature(bytes: &[u8]) -> Result<Self::Signature, VerifyError> {
+        k256::ecdsa::Signature::from_slice(bytes).map_err(|_| VerifyError::Malformed(Self::SCHEME))
+    }
+}
+
+/// BLS over BLS12-381 with public keys in G1, as Ethereum validators
+/// sign. Signatures of one message aggregate, and the aggregate verifies
+/// against the public keys of its signers at once, which is safe only
+/// with keys whose [possession](Bls12381::prove_possession) was proven.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bls12381;
+
+/// A BLS secret key, wiped when dropped and printing no secret
+#[derive(Clone)]
+pub struct BlsSecretKey(bls::SecretKey);
+
+impl fmt::Debug for BlsSecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BlsSecretKey(..)")
+    }
+}
+
+impl PartialEq for BlsSecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        let (ours, theirs) = (
+            Zeroizing::new(self.0.to_bytes()),
+            Zeroizing::new(other.0.to_bytes()),
+        );
+        bool::from(ours.ct_eq(&*theirs))
+    }
+}
+
+impl Eq for BlsSecretKey {}
+
+impl BlsSecretKey {
+    /// The key of 32 big-endian bytes, which are not one when zero or past
+    /// the order of the curve
+    pub fn from_bytes(bytes: &[u8; 32]) -> Option<Self> {
+        bls::SecretKey::from_bytes(bytes).ok().map(Self)
+    }
+
+    pub fn to_bytes(&self) -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(self.0.to_bytes())
+    }
+}
+
+impl Bls12381 {
+    /// A signature of the key's own public key, under a domain of its own,
+    /// for the key to join aggregates
+    pub fn prove_possession(secret: &BlsSecretKey) -> bls::Signature {
+        let public_key = Self::public_key(secret).to_bytes();
+        secret.0.sign(&public_key, BLS_POP_DST, &[])
+    }
+
+    pub fn verify_possession(
+        public_key: &bls::PublicKey,
+        proof: &bls::Signature,
+    ) -> Result<(), VerifyError> {
+        match proof.verify(
+            true,
+            &public_key.to_bytes(),
+            BLS_POP_DST,
+            &[],
+            public_key,
+            true,
+        ) {
+            BLST_ERROR::BLST_SUCCESS => Ok(()),
+            _ => Err(VerifyError::Invalid),
+        }
+    }
+}
+
+impl SignatureScheme for Bls12381 {
+    const SCHEME: Scheme = Scheme::Bls12381;
+
+    type SecretKey = BlsSecretKey;
+    type PublicKey = bls::PublicKey;
+    type Signature = bls::Signature;
+
+    fn generate() -> Self::SecretKey {
+        let mut ikm = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(&mut *ikm);
+        BlsSecretKey(bls::SecretKey::key_gen(&*ikm, &[]).expect("32 bytes make a key"))
+    }
+
+    fn public_key(secret: &Self::SecretKey) -> Self::PublicKey {
+        secret.0.sk_to_pk()
+    }
+
+    fn sign(secret: &Self::SecretKey, message: &[u8]) -> Self::Signature {
+        secret.0.sign(message, BLS_DST, &[])
+    }
+
+    fn verify(
+        public_key: &Self::PublicKey,
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> Result<(), VerifyError> {
+        match signature.verify(true, message, BLS_DST, &[], public_key, true) {
+            BLST_ERROR::BLST_SUCCESS => Ok(()),
+            _ => Err(VerifyError::Invalid),
+        }
+    }
+
+    fn decode_public_key(bytes: &[u8]) -> Result<Self::PublicKey, VerifyError> {
+        // Compressed only, and never the point at infinity
+        if bytes.len() != 48 {
+            return Err(VerifyError::PublicKey(Self::SCHEME));
+        }
+        bls::PublicKey::key_validate(bytes).map_err(|_| VerifyError::PublicKey(Self::SCHEME))
+    }
+
+    fn decode_signature(bytes: &[u8]) -> Result<Self::Signature, VerifyError> {
+        if bytes.len() != 96 {
+            return Err(VerifyError::Malformed(Self::SCHEME));
+        }
+        bls::Signature::from_bytes(bytes).map_err(|_| VerifyError::Malformed(Self::SCHEME))
+    }
+
+    fn aggregate(signatures: &[Self::Signature]) -> Result<Self::Signature, VerifyError> {
+        let signatures: Vec<&bls::Signature> = signatures.iter().collect();
+        bls::AggregateSignature::aggregate(&signatures, true)
+            .map(|aggregate| aggregate.to_signature())
+            .map_err(|_| VerifyError::Invalid)
+    }
+
+    fn verify_aggregate(
+        public_keys: &[Self::PublicKey],
+        message: &[u8],
+        signature: &Self::Signature,
+    ) -> Result<(), VerifyError> {
+        let public_keys: Vec<&bls::PublicKey> = public_keys.iter().collect();
+        match signature.fast_aggregate_verify(true, message, BLS_DST, &public_keys) {
+            BLST_ERROR::BLST_SUCCESS => Ok(()),
+            _ => Err(VerifyError::Invalid),
+        }
+    }
+}
+
+/// The secret of a key, under its scheme. Every kind is wiped when
+/// dropped, prints no secret and compares in constant time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretKey {
+    Ed25519(ed25519_dalek::SigningKey),
+    Secp256k1(k256::ecdsa::SigningKey),
+    Bls12381(BlsSecretKey),
+}
+
+impl From<ed25519_dalek::SigningKey> for SecretKey {
+    fn from(key: ed25519_dalek::SigningKey) -> Self {
+        Self::Ed25519(key)
+    }
+}
+
+impl SecretKey {
+    pub fn generate(scheme: Scheme) -> Self {
+        match scheme {
+            Scheme::Ed25519 => Self::Ed25519(Ed25519::generate()),
+            Scheme::Secp256k1 => Self::Secp256k1(Secp256k1::generate()),
+            Scheme::Bls12381 => Self::Bls12381(Bls12381::generate()),
+        }
+    }
+
+    /// The key of 32 secret bytes, which are not one under secp256k1 and
+    /// BLS12-381 when zero or past the order of the curve
+    pub fn from_bytes(scheme: Scheme, bytes: &[u8; 32]) -> Option<Self> {
+        match scheme {
+            Scheme::Ed25519 => Some(Self::Ed25519(ed25519_dalek::SigningKey::from_bytes(bytes))),
+            Scheme::Secp256k1 => k256::ecdsa::SigningKey::from_slice(bytes)
+                .ok()
+                .map(Self::Secp256k1),
+            Scheme::Bls12381 => BlsSecretKey::from_bytes(bytes).map(Self::Bls12381),
+        }
+    }
+
+    /// The secret bytes, wiped once dropped
+    pub fn to_bytes(&self) -> Zeroizing<[u8; 32]> {
+        match self {
+            Self::Ed25519(key) => Zeroizing::new(key.to_bytes()),
+            Self::Secp256k1(key) => Zeroizing::new(key.to_bytes().into()),
+            Self::Bls12381(key) => key.to_bytes(),
+        }
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        match self {
+            Self::Ed25519(_) => Scheme::Ed25519,
+            Self::Secp256k1(_) => Scheme::Secp256k1,
+            Self::Bls12381(_) => Scheme::Bls12381,
+        }
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        match self {
+            Self::Ed25519(key) => Ed25519::public_key(key).to_bytes().to_vec(),
+            Self::Secp256k1(key) => Secp256k1::public_key(key).to_sec1_bytes().to_vec(),
+            Self::Bls12381(key) => Bls12381::public_key(key).to_bytes().to_vec(),
+        }
+    }
+
+    /// A signature of [`Scheme::signature_len`] bytes
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Ed25519(key) => Ed25519::sign(key, message).to_bytes().to_vec(),
+            Self::Secp256k1(key) => Secp256k1::sign(key, message).to_vec(),
+            Self::Bls12381(key) => Bls12381::sign(key, message).to_bytes().to_vec(),
+        }
+    }
+
+    /// The ed25519 key, for what only signs with ed25519
+    pub fn ed25519(self) -> Option<ed25519_dalek::SigningKey> {
+        match self {
+            Self::Ed25519(key) => Some(key),
+            Self::Secp256k1(_) | Self::Bls12381(_) => None,
+        }
+    }
+}
+
+/// Check that `signature` of `message` is by `public_key`, under `scheme`
+pub fn verify(
+    scheme: Scheme,
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), VerifyError> {
+    match scheme {
+        Scheme::Ed25519 => Ed25519::verify_bytes(public_key, message, signature),
+        Scheme::Secp256k1 => Secp256k1::verify_bytes(public_key, message, signature),
+        Scheme::Bls12381 => Bls12381::verify_bytes(public_key, message, signature),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        for scheme in Scheme::ALL {
+            let key = SecretKey::generate(scheme);
+            assert_eq!(key.scheme(), scheme);
+            assert_eq!(Scheme::of_public_key(key.public_key().len()), Some(scheme));
+            assert_eq!(
+                SecretKey::from_bytes(scheme, &key.to_bytes()).as_ref(),
+                Some(&key)
+            );
+            assert_eq!(scheme.name().parse::<Scheme>(), Ok(scheme));
+
+            let signature = key.sign(b"chaos");
+            assert_eq!(
+                verify(scheme, &key.public_key(), b"chaos", &signature),
+                Ok(())
+            );
+            assert_eq!(
+                verify(scheme, &key.public_key(), b"order", &signature),
+                Err(VerifyError::Invalid)
+            );
+            assert_eq!(
+                verify(scheme, &key.public_key(), b"chaos", &signature[..63]),
+                Err(VerifyError::Malformed(scheme))
+            );
+        }
+        assert!(SecretKey::from_bytes(Scheme::Secp256k1, &[0; 32]).is_none());
+        assert!(SecretKey::from_bytes(Scheme::Secp256k1, &[0xff; 32]).is_none());
+        assert_eq!(
+            verify(Scheme::Secp256k1, &[2; 32], b"chaos", &[0; 64]),
+            Err(VerifyError::PublicKey(Scheme::Secp256k1))
+        );
+    }
+
+    fn round_trip<S: SignatureScheme>() {
+        let secret = S::generate();
+        let public_key = S::public_key(&secret);
+        let signature = S::sign(&secret, b"chaos");
+        assert_eq!(S::verify(&public_key, b"chaos", &signature), Ok(()));
+        assert_eq!(
+            S::verify(&public_key, b"order", &signature),
+            Err(VerifyError::Invalid)
+        );
+        assert_eq!(
+            S::aggregate(&[signature]).err(),
+            Some(VerifyError::NoAggregation(S::SCHEME))
+        );
+    }
+
+    #[test]
+    fn test_signature_scheme() {
+        round_trip::<Ed25519>();
+        round_trip::<Secp256k1>();
+    }
+
+    #[test]
+    fn test_bls_aggregation() {
+        let secrets: Vec<BlsSecretKey> = (0..3).map(|_| Bls12381::generate()).collect();
+        let public_keys: Vec<_> = secrets.iter().map(Bls12381::public_key).collect();
+        let signatures: Vec<_> = secrets
+            .iter()
+            .map(|secret| Bls12381::sign(secret, b"chaos"))
+            .collect();
+        for (public_key, signature) in public_keys.iter().zip(&signatures) {
+            assert_eq!(Bls12381::verify(public_key, b"chaos", signature), Ok(()));
+        }
+
+        let aggregate = Bls12381::aggregate(&signatures).unwrap();
+        assert_eq!(
+            Bls12381::verify_aggregate(&public_keys, b"chaos", &aggregate),
+            Ok(())
+        );
+        assert_eq!(
+            Bls12381::verify_aggregate(&public_keys[..2], b"chaos", &aggregate),
+            Err(VerifyError::Invalid)
+        );
+        assert_eq!(
+            Bls12381::verify_aggregate(&public_keys, b"order", &aggregate),
+            Err(VerifyError::Invalid)
+        );
+        assert_eq!(Bls12381::aggregate(&[]), Err(VerifyError::Invalid));
+
+        // The proof of possession is no signature of the key as a message
+        let proof = Bls12381::prove_possession(&secrets[0]);
+        assert_eq!(Bls12381::verify_possession(&public_keys[0], &proof), Ok(()));
+        assert_eq!(
+            Bls12381::verify_possession(&public_keys[1], &proof),
+            Err(VerifyError::Invalid)
+        );
+        let signed = Bls12381::sign(&secrets[0], &public_keys[0].to_bytes());
+        assert_eq!(
+            Bls12381::verify_possession(&public_keys[0], &signed),
+            Err(VerifyError::Invalid)
+        );
+
+        // The point at infinity is no key
+        let mut infinity = [0u8; 48];
+        infinity[0] = 0xc0;
+        assert_eq!(
+            Bls12381::decode_public_key(&infinity),
+            Err(VerifyError::PublicKey(Scheme::Bls12381))
+        );
+        assert_eq!(format!("{:?}", secrets[0]), "BlsSecretKey(..)");
+    }
+}
@@ -0,0 +1,234 @@
+//! Keeping secrets out of swap, core dumps and logs. A [`Secret`] sits in
+//! memory of its own, locked into RAM where the platform lets it, and is
+//! wiped when dropped; it prints as nothing and is not cloned unless asked
+//! to. Secrets are compared in constant time with [`ct_eq`], so how long
+//! a comparison takes tells nothing of where they differ.
+
+#[cfg(unix)]
+use std::collections::BTreeMap;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+pub use zeroize::Zeroizing;
+
+/// A secret kept on the heap, locked into RAM and wiped on drop. What is
+/// locked is the memory of `T` itself, all of it for a secret of fixed
+/// size such as the bytes of a key.
+pub struct Secret<T: Zeroize> {
+    value: Box<T>,
+    locked: bool,
+}
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        let value = Box::new(value);
+        let locked = lock(&*value);
+        Self { value, locked }
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.value
+    }
+
+    /// Whether the memory of the secret is kept from being swapped out.
+    /// Locking fails past the limit of locked memory of the process, and
+    /// where there is no such thing.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl<T: Zeroize + Clone> Secret<T> {
+    /// Another copy of the secret, locked and wiped as this one
+    pub fn duplicate(&self) -> Self {
+        Self::new((*self.value).clone())
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.value.zeroize();
+        if self.locked {
+            unlock(&*self.value);
+        }
+    }
+}
+
+impl<T: Zeroize> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> PartialEq for Secret<T> {
+    fn eq(&self, other: &Self) -> bool {
+        ct_eq(self.value.as_ref().as_ref(), other.value.as_ref().as_ref())
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> Eq for Secret<T> {}
+
+/// Whether `a` and `b` are the same, taking as long wherever they differ.
+/// Their lengths are not secret.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Keep the process from writing core dumps, which would hold whatever
+/// secrets it has in memory. Returns whether it took.
+pub fn disable_core_dumps() -> bool {
+    #[cfg(unix)]
+    {
+        let limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: setrlimit reads the limit it is given
+        let limited = unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } == 0;
+        // Not dumpable also keeps others of the same user from ptrace
+        #[cfg(target_os = "linux")]
+        // SAFETY: PR_SET_DUMPABLE takes one integer argument
+        let limited = limited && unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) } == 0;
+        limited
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Pages locked for secrets, and how many secrets lie on each. Locks do
+/// not stack, so a page shared by several secrets is locked with the first
+/// and unlocked with the last of them.
+#[cfg(unix)]
+static LOCKED_PAGES: parking_lot::Mutex<BTreeMap<usize, usize>> =
+    parking_lot::const_mutex(BTreeMap::new());
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    // SAFETY: sysconf only reads the configuration
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+/// Start of each page `size` bytes from `address` lie on
+#[cfg(unix)]
+fn pages(address: usize, size: usize) -> Vec<usize> {
+    let page_size = page_size();
+    let first = address & !(page_size - 1);
+    let last = (address + size - 1) & !(page_size - 1);
+    (first..=last).step_by(page_size).collect()
+}
+
+fn lock<T>(value: &T) -> bool {
+    #[cfg(unix)]
+    {
+        let size = std::mem::size_of::<T>();
+        if size == 0 {
+            return false;
+        }
+        let pages = pages(value as *const T as usize, size);
+        let mut locked = LOCKED_PAGES.lock();
+        for (taken, &page) in pages.iter().enumerate() {
+            let count = locked.entry(page).or_insert(0);
+            // SAFETY: the page holds part of `value`, so it is mapped
+            if *count == 0 && unsafe { libc::mlock(page as *const _, page_size()) } != 0 {
+                locked.remove(&page);
+                release(&mut locked, &pages[..taken]);
+                return false;
+            }
+            *count += 1;
+        }
+        true
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = value;
+        false
+    }
+}
+
+fn unlock<T>(value: &T) {
+    #[cfg(unix)]
+    release(
+        &mut LOCKED_PAGES.lock(),
+        &pages(value as *const T as usize, std::mem::size_of::<T>()),
+    );
+    #[cfg(not(unix))]
+    let _ = value;
+}
+
+/// Let go of `pages` for one secret, unlocking those no other secret is on
+#[cfg(unix)]
+fn release(locked: &mut BTreeMap<usize, usize>, pages: &[usize]) {
+    for page in pages {
+        let Some(count) = locked.get_mut(page) else {
+            continue;
+        };
+        *count -= 1;
+        if *count == 0 {
+            locked.remove(page);
+            // SAFETY: the page was locked by `lock`
+            unsafe {
+                libc::munlock(*page as *const _, page_size());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret() {
+        let secret = Secret::new([7u8; 32]);
+        assert_eq!(secret.expose(), &[7; 32]);
+        assert_eq!(format!("{:?}", secret), "Secret(..)");
+        assert_eq!(secret.duplicate(), secret);
+        assert_ne!(Secret::new([8u8; 32]), secret);
+        assert!(ct_eq(b"chaos", b"chaos"));
+        assert!(!ct_eq(b"chaos", b"order"));
+        assert!(!ct_eq(b"chaos", b"chaos!"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_secrets_sharing_a_page() {
+        // A page wholly inside the buffer, so no other test's secrets are on it
+        let page_size = page_size();
+        let buffer = vec![0u8; 3 * page_size];
+        let page = (buffer.as_ptr() as usize + page_size) & !(page_size - 1);
+        // SAFETY: both lie within `buffer`, and [u8; 32] has no alignment
+        let (first, second) = unsafe {
+            (
+                &*(page as *const [u8; 32]),
+                &*((page + 64) as *const [u8; 32]),
+            )
+        };
+
+        if !lock(first) {
+            // Past the limit of locked memory
+            return;
+        }
+        assert!(lock(second));
+        assert_eq!(LOCKED_PAGES.lock().get(&page), Some(&2));
+        // The page stays locked for the secret still on it
+        unlock(first);
+        assert_eq!(LOCKED_PAGES.lock().get(&page), Some(&1));
+        unlock(second);
+        assert_eq!(LOCKED_PAGES.lock().get(&page), None);
+
+        // A secret across two pages holds both
+        // SAFETY: within `buffer` as well
+        let straddling = unsafe { &*((page + page_size - 16) as *const [u8; 32]) };
+        assert!(lock(straddling));
+        assert_eq!(LOCKED_PAGES.lock().get(&page), Some(&1));
+        assert_eq!(LOCKED_PAGES.lock().get(&(page + page_size)), Some(&1));
+        unlock(straddling);
+        assert_eq!(LOCKED_PAGES.lock().get(&(page + page_size)), None);
+        drop(buffer);
+    }
+}
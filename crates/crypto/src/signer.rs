@@ -0,0 +1,40 @@
+//! Signing without knowing where the key is kept. A key of the keystore
+//! signs in memory, a hardware wallet signs on the device once its owner
+//! approves, see [`crate::ledger`]. Whatever signs transactions or votes
+//! takes a [`Signer`], so that keys can move out of the process without
+//! the rest of the code telling the difference.
+
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SignerError {
+    #[error("No signing device found{0}")]
+    NoDevice(String),
+    #[error("The device failed: {0}")]
+    Device(String),
+    #[error("The ChaosChain app is not open on the device")]
+    AppNotOpen,
+    #[error("Rejected on the device")]
+    Rejected,
+    #[error("The device answered with a signature that does not verify")]
+    BadSignature,
+}
+
+/// Signs for one ed25519 key
+pub trait Signer: Send + Sync {
+    fn public_key(&self) -> VerifyingKey;
+
+    /// Sign `message`, which a device may show its owner to approve first
+    fn sign(&self, message: &[u8]) -> Result<Signature, SignerError>;
+}
+
+impl Signer for SigningKey {
+    fn public_key(&self) -> VerifyingKey {
+        self.verifying_key()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        Ok(ed25519_dalek::Signer::sign(self, message))
+    }
+}
@@ -0,0 +1,742 @@
+//! t-of-n threshold signatures: any `threshold` of the validators holding a
+//! share of the group key sign together, and what comes out is a single
+//! ed25519 signature that verifies against the group public key, as any
+//! other would. This is FROST over ed25519: each signer commits to a pair
+//! of nonces, a coordinator hands out the commitments of those signing,
+//! each answers with its partial signature, and the partials add up to the
+//! group signature. Partials are checked against the verifying share of
+//! their signer, so one that is wrong names its signer.
+//!
+//! The shares come from a trusted dealer, which is fine for testnets, or
+//! from a distributed key generation in which no one ever holds the group
+//! secret: each participant deals a share of a secret of its own to every
+//! other, with Feldman commitments to check it against, and the group
+//! secret is the sum of everyone's.
+
+use curve25519_dalek::constants::ED25519_BASEPOINT_POINT;
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::traits::Identity;
+use curve25519_dalek::Scalar;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use std::collections::{BTreeMap, BTreeSet};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+const BINDING_DOMAIN: &[u8] = b"chaoschain-frost-binding-v1";
+const PROOF_DOMAIN: &[u8] = b"chaoschain-frost-dkg-proof-v1";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ThresholdError {
+    #[error("A threshold of {threshold} of {count} signers, it must be from 1 to all of them")]
+    Parameters { threshold: u16, count: u16 },
+    #[error("Signer {0} is not one of the group")]
+    UnknownSigner(u16),
+    #[error("{got} signers where {needed} are needed")]
+    TooFew { got: usize, needed: usize },
+    #[error("Signer {0} is in twice")]
+    Duplicate(u16),
+    #[error("Signer {0} is not among the commitments")]
+    NotCommitted(u16),
+    #[error("Not a point or scalar of ed25519, from signer {0}")]
+    Malformed(u16),
+    #[error("The shares or partial signatures of {0:?} do not hold")]
+    Culprits(Vec<u16>),
+    #[error("Key generation expected {expected} participants, got {got}")]
+    Participants { expected: usize, got: usize },
+}
+
+/// The group key and what the partial signature of each signer is checked
+/// against. Public, every validator has it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupKey {
+    pub threshold: u16,
+    /// The ed25519 key group signatures verify against
+    #[serde(with = "hex32")]
+    pub public_key: [u8; 32],
+    /// By signer index, from 1
+    pub verifying_shares: Vec<VerifyingShare>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyingShare {
+    pub index: u16,
+    #[serde(with = "hex32")]
+    pub key: [u8; 32],
+}
+
+/// A signer's share of the group secret, with the group it belongs to.
+/// Wiped when dropped, and compared in constant time.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeyShare {
+    pub index: u16,
+    #[serde(with = "hex32")]
+    pub secret: [u8; 32],
+    pub group: GroupKey,
+}
+
+impl PartialEq for KeyShare {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+            && crate::secret::ct_eq(&self.secret, &other.secret)
+            && self.group == other.group
+    }
+}
+
+impl Eq for KeyShare {}
+
+impl Drop for KeyShare {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+impl std::fmt::Debug for KeyShare {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyShare")
+            .field("index", &self.index)
+            .field("group", &self.group)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The nonces of one signing round, secret and used once
+pub struct SigningNonces {
+    hiding: Scalar,
+    binding: Scalar,
+    commitment: NonceCommitment,
+}
+
+impl Drop for SigningNonces {
+    fn drop(&mut self) {
+        self.hiding.zeroize();
+        self.binding.zeroize();
+    }
+}
+
+/// What a signer commits to before signing, sent to the coordinator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NonceCommitment {
+    pub index: u16,
+    #[serde(with = "hex32")]
+    pub hiding: [u8; 32],
+    #[serde(with = "hex32")]
+    pub binding: [u8; 32],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialSignature {
+    pub index: u16,
+    #[serde(with = "hex32")]
+    pub share: [u8; 32],
+}
+
+/// Split a freshly made group secret into `count` shares, any `threshold`
+/// of which sign. Whoever runs it sees the secret, so it is for testnets.
+pub fn deal(threshold: u16, count: u16) -> Result<(GroupKey, Vec<KeyShare>), ThresholdError> {
+    check_parameters(threshold, count)?;
+    let polynomial: Vec<Scalar> = (0..threshold).map(|_| random_scalar()).collect();
+    let commitments: Vec<EdwardsPoint> = polynomial
+        .iter()
+        .map(|coefficient| ED25519_BASEPOINT_POINT * coefficient)
+        .collect();
+    let group = group_key(threshold, count, &[commitments]);
+    let shares = (1..=count)
+        .map(|index| KeyShare {
+            index,
+            secret: evaluate(&polynomial, index).to_bytes(),
+            group: group.clone(),
+        })
+        .collect();
+    Ok((group, shares))
+}
+
+/// A participant in the distributed generation of a group key
+pub struct Dkg {
+    index: u16,
+    threshold: u16,
+    count: u16,
+    polynomial: Vec<Scalar>,
+}
+
+impl Drop for Dkg {
+    fn drop(&mut self) {
+        self.polynomial.zeroize();
+    }
+}
+
+/// What a participant publishes to everyone: Feldman commitments to its
+/// polynomial, and proof that it knows the secret it commits to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DkgCommitment {
+    pub index: u16,
+    pub commitments: Vec<String>,
+    /// Schnorr proof of the secret behind the first commitment, hex
+    pub proof: String,
+}
+
+impl Dkg {
+    pub fn new(index: u16, threshold: u16, count: u16) -> Result<Self, ThresholdError> {
+        check_parameters(threshold, count)?;
+        if index == 0 || index > count {
+            return Err(ThresholdError::UnknownSigner(index));
+        }
+        Ok(Self {
+            index,
+            threshold,
+            count,
+            polynomial: (0..threshold).map(|_| random_scalar()).collect(),
+        })
+    }
+
+    /// Published to everyone, first
+    pub fn commitment(&self) -> DkgCommitment {
+        let commitments: Vec<EdwardsPoint> = self
+            .polynomial
+            .iter()
+            .map(|coefficient| ED25519_BASEPOINT_POINT * coefficient)
+            .collect();
+        let nonce = random_scalar();
+        let nonce_point = (ED25519_BASEPOINT_POINT * nonce).compress();
+        let challenge = proof_challenge(self.index, &commitments[0], &nonce_point);
+        let response = nonce + challenge * self.polynomial[0];
+        let mut proof = nonce_point.to_bytes().to_vec();
+        proof.extend_from_slice(&response.to_bytes());
+        DkgCommitment {
+            index: self.index,
+            commitments: commitments
+                .iter()
+                .map(|point| hex::encode(point.compress().to_bytes()))
+                .collect(),
+            proof: hex::encode(proof),
+        }
+    }
+
+    /// The share of participant `to`, sent to it alone
+    pub fn share_for(&self, to: u16) -> Result<[u8; 32], ThresholdError> {
+        if to == 0 || to > self.count {
+            return Err(ThresholdError::UnknownSigner(to));
+        }
+        Ok(evaluate(&self.polynomial, to).to_bytes())
+    }
+
+    /// Our key share, from the commitments of everyone, ours included, and
+    /// the shares sent to us, ours included, one commitment of every
+    /// participant. Fails naming those whose commitment or share does not
+    /// hold.
+    pub fn finish(
+        self,
+        commitments: &[DkgCommitment],
+        shares: &BTreeMap<u16, [u8; 32]>,
+    ) -> Result<KeyShare, ThresholdError> {
+        let count = usize::from(self.count);
+        if commitments.len() != count || shares.len() != count {
+            return Err(ThresholdError::Participants {
+                expected: count,
+                got: commitments.len().min(shares.len()),
+            });
+        }
+        // As many commitments as participants, so each of them is in once
+        let mut seen = BTreeSet::new();
+        for commitment in commitments {
+            let from = commitment.index;
+            if from == 0 || from > self.count {
+                return Err(ThresholdError::UnknownSigner(from));
+            }
+            if !seen.insert(from) {
+                return Err(ThresholdError::Duplicate(from));
+            }
+        }
+        let mut culprits = BTreeSet::new();
+        let mut polynomials = Vec::with_capacity(count);
+        let mut secret = Scalar::ZERO;
+        for commitment in commitments {
+            let from = commitment.index;
+            let points = match decode_commitment(commitment, self.threshold) {
+                Some(points) => points,
+                None => {
+                    culprits.insert(from);
+                    continue;
+                }
+            };
+            let share = shares
+                .get(&from)
+                .and_then(|share| Option::<Scalar>::from(Scalar::from_canonical_bytes(*share)));
+            match share {
+                Some(share)
+                    if ED25519_BASEPOINT_POINT * share == evaluate_points(&points, self.index) =>
+                {
+                    secret += share;
+                    polynomials.push(points);
+                }
+                _ => {
+                    culprits.insert(from);
+                }
+            }
+        }
+        if !culprits.is_empty() {
+            return Err(ThresholdError::Culprits(culprits.into_iter().collect()));
+        }
+        Ok(KeyShare {
+            index: self.index,
+            secret: secret.to_bytes(),
+            group: group_key(self.threshold, self.count, &polynomials),
+        })
+    }
+}
+
+impl KeyShare {
+    /// Nonces for one signature, and the commitment to send for them
+    pub fn commit(&self) -> (SigningNonces, NonceCommitment) {
+        let hiding = random_scalar();
+        let binding = random_scalar();
+        let commitment = NonceCommitment {
+            index: self.index,
+            hiding: (ED25519_BASEPOINT_POINT * hiding).compress().to_bytes(),
+            binding: (ED25519_BASEPOINT_POINT * binding).compress().to_bytes(),
+        };
+        (
+            SigningNonces {
+                hiding,
+                binding,
+                commitment,
+            },
+            commitment,
+        )
+    }
+
+    /// Our partial signature of `message`, with the commitments of
+    /// everyone signing, which the coordinator picked. Takes the nonces, a
+    /// second signature with them would give the share away.
+    pub fn sign(
+        &self,
+        nonces: SigningNonces,
+        message: &[u8],
+        commitments: &[NonceCommitment],
+    ) -> Result<PartialSignature, ThresholdError> {
+        if !commitments.contains(&nonces.commitment) {
+            return Err(ThresholdError::NotCommitted(self.index));
+        }
+        let package = Package::new(&self.group, message, commitments)?;
+        let mut secret = Option::<Scalar>::from(Scalar::from_canonical_bytes(self.secret))
+            .ok_or(ThresholdError::Malformed(self.index))?;
+        let share = nonces.hiding
+            + nonces.binding * package.binding[&self.index]
+            + package.lagrange(self.index) * package.challenge * secret;
+        secret.zeroize();
+        Ok(PartialSignature {
+            index: self.index,
+            share: share.to_bytes(),
+        })
+    }
+}
+
+impl GroupKey {
+    fn verifying_share(&self, index: u16) -> Option<EdwardsPoint> {
+        self.verifying_shares
+            .iter()
+            .find(|share| share.index == index)
+            .and_then(|share| decompress(&share.key))
+    }
+
+    /// Whether `partial` is the signer's part of the signature of
+    /// `message` by those of `commitments`
+    pub fn verify_partial(
+        &self,
+        message: &[u8],
+        commitments: &[NonceCommitment],
+        partial: &PartialSignature,
+    ) -> Result<bool, ThresholdError> {
+        let package = Package::new(self, message, commitments)?;
+        Ok(package.holds(self, partial))
+    }
+
+    /// The group signature of `message`, from the partials of everyone in
+    /// `commitments`. Fails naming the signers whose partial is wrong.
+    pub fn combine(
+        &self,
+        message: &[u8],
+        commitments: &[NonceCommitment],
+        partials: &[PartialSignature],
+    ) -> Result<[u8; 64], ThresholdError> {
+        let package = Package::new(self, message, commitments)?;
+        let mut by_signer = BTreeMap::new();
+        for partial in partials {
+            if !package.binding.contains_key(&partial.index) {
+                return Err(ThresholdError::NotCommitted(partial.index));
+            }
+            if by_signer.insert(partial.index, partial).is_some() {
+                return Err(ThresholdError::Duplicate(partial.index));
+            }
+        }
+        if by_signer.len() != commitments.len() {
+            return Err(ThresholdError::TooFew {
+                got: by_signer.len(),
+                needed: commitments.len(),
+            });
+        }
+        let culprits: Vec<u16> = by_signer
+            .values()
+            .filter(|partial| !package.holds(self, partial))
+            .map(|partial| partial.index)
+            .collect();
+        if !culprits.is_empty() {
+            return Err(ThresholdError::Culprits(culprits));
+        }
+        let share: Scalar = by_signer
+            .values()
+            .filter_map(|partial| {
+                Option::<Scalar>::from(Scalar::from_canonical_bytes(partial.share))
+            })
+            .sum();
+        let mut signature = [0u8; 64];
+        signature[..32].copy_from_slice(&package.nonce.compress().to_bytes());
+        signature[32..].copy_from_slice(&share.to_bytes());
+        Ok(signature)
+    }
+
+    /// Whether `signature` is the group's, of `message`
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let Ok(key) = VerifyingKey::from_bytes(&self.public_key) else {
+            return false;
+        };
+        Signature::from_slice(signature)
+            .is_ok_and(|signature| key.verify(message, &signature).is_ok())
+    }
+}
+
+/// A signing round as everyone computes it from the commitments
+struct Package {
+    /// Binding factor of each signer
+    binding: BTreeMap<u16, Scalar>,
+    /// The commitments as points, by signer
+    points: BTreeMap<u16, (EdwardsPoint, EdwardsPoint)>,
+    /// The group nonce, `R` of the signature
+    nonce: EdwardsPoint,
+    /// The ed25519 challenge, `H(R || A || M)`
+    challenge: Scalar,
+}
+
+impl Package {
+    fn new(
+        group: &GroupKey,
+        message: &[u8],
+        commitments: &[NonceCommitment],
+    ) -> Result<Self, ThresholdError> {
+        if commitments.len() < usize::from(group.threshold) {
+            return Err(ThresholdError::TooFew {
+                got: commitments.len(),
+                needed: usize::from(group.threshold),
+            });
+        }
+        let mut points = BTreeMap::new();
+        for commitment in commitments {
+            if group.verifying_share(commitment.index).is_none() {
+                return Err(ThresholdError::UnknownSigner(commitment.index));
+            }
+            let (Some(hiding), Some(binding)) = (
+                decompress(&commitment.hiding),
+                decompress(&commitment.binding),
+            ) else {
+                return Err(ThresholdError::Malformed(commitment.index));
+            };
+            if points.insert(commitment.index, (hiding, binding)).is_some() {
+                return Err(ThresholdError::Duplicate(commitment.index));
+            }
+        }
+
+        // Every factor binds to the message and all the commitments, in
+        // signer order, so no signer's nonce can be swapped after the fact
+        let mut transcript = Sha512::new()
+            .chain_update(BINDING_DOMAIN)
+            .chain_update(group.public_key)
+            .chain_update(Sha512::digest(message));
+        for commitment in points.keys().map(|index| {
+            commitments
+                .iter()
+                .find(|commitment| commitment.index == *index)
+                .expect("a commitment of each signer")
+        }) {
+            transcript.update(commitment.index.to_be_bytes());
+            transcript.update(commitment.hiding);
+            transcript.update(commitment.binding);
+        }
+        let binding: BTreeMap<u16, Scalar> = points
+            .keys()
+            .map(|index| {
+                let factor = transcript
+                    .clone()
+                    .chain_update(index.to_be_bytes())
+                    .finalize();
+                (*index, Scalar::from_bytes_mod_order_wide(&factor.into()))
+            })
+            .collect();
+        let nonce = points
+            .iter()
+            .map(|(index, (hiding, binding_point))| hiding + binding_point * binding[index])
+            .fold(EdwardsPoint::identity(), |sum, point| sum + point);
+        let challenge = Scalar::from_bytes_mod_order_wide(
+            &Sha512::new()
+                .chain_update(nonce.compress().as_bytes())
+                .chain_update(group.public_key)
+                .chain_update(message)
+                .finalize()
+                .into(),
+        );
+        Ok(Self {
+            binding,
+            points,
+            nonce,
+            challenge,
+        })
+    }
+
+    /// The Lagrange coefficient of `index` at zero, over the signers
+    fn lagrange(&self, index: u16) -> Scalar {
+        let x = Scalar::from(u64::from(index));
+        let (numerator, denominator) = self
+            .points
+            .keys()
+            .filter(|other| **other != index)
+            .map(|other| Scalar::from(u64::from(*other)))
+            .fold(
+                (Scalar::ONE, Scalar::ONE),
+                |(numerator, denominator), other| (numerator * other, denominator * (other - x)),
+            );
+        numerator * denominator.invert()
+    }
+
+    fn holds(&self, group: &GroupKey, partial: &PartialSignature) -> bool {
+        let (Some((hiding, binding)), Some(verifying_share), Some(share)) = (
+            self.points.get(&partial.index),
+            group.verifying_share(partial.index),
+            Option::<Scalar>::from(Scalar::from_canonical_bytes(partial.share)),
+        ) else {
+            return false;
+        };
+        ED25519_BASEPOINT_POINT * share
+            == hiding
+                + binding * self.binding[&partial.index]
+                + verifying_share * (self.lagrange(partial.index) * self.challenge)
+    }
+}
+
+fn check_parameters(threshold: u16, count: u16) -> Result<(), ThresholdError> {
+    if threshold == 0 || threshold > count {
+        return Err(ThresholdError::Parameters { threshold, count });
+    }
+    Ok(())
+}
+
+/// The group key of the polynomials dealt, each committed to
+/// coefficient by coefficient
+fn group_key(threshold: u16, count: u16, polynomials: &[Vec<EdwardsPoint>]) -> GroupKey {
+    let public_key = polynomials
+        .iter()
+        .fold(EdwardsPoint::identity(), |sum, points| sum + points[0]);
+    GroupKey {
+        threshold,
+        public_key: public_key.compress().to_bytes(),
+        verifying_shares: (1..=count)
+            .map(|index| VerifyingShare {
+                index,
+                key: polynomials
+                    .iter()
+                    .fold(EdwardsPoint::identity(), |sum, points| {
+                        sum + evaluate_points(points, index)
+                    })
+                    .compress()
+                    .to_bytes(),
+            })
+            .collect(),
+    }
+}
+
+fn evaluate(polynomial: &[Scalar], index: u16) -> Scalar {
+    let x = Scalar::from(u64::from(index));
+    polynomial
+        .iter()
+        .rev()
+        .fold(Scalar::ZERO, |value, coefficient| value * x + coefficient)
+}
+
+fn evaluate_points(points: &[EdwardsPoint], index: u16) -> EdwardsPoint {
+    let x = Scalar::from(u64::from(index));
+    points
+        .iter()
+        .rev()
+        .fold(EdwardsPoint::identity(), |value, point| value * x + point)
+}
+
+/// The commitments of a participant, once its proof holds
+fn decode_commitment(commitment: &DkgCommitment, threshold: u16) -> Option<Vec<EdwardsPoint>> {
+    if commitment.commitments.len() != usize::from(threshold) {
+        return None;
+    }
+    let points = commitment
+        .commitments
+        .iter()
+        .map(|point| {
+            let bytes: [u8; 32] = hex::decode(point).ok()?.try_into().ok()?;
+            decompress(&bytes)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let proof: [u8; 64] = hex::decode(&commitment.proof).ok()?.try_into().ok()?;
+    let nonce_point = CompressedEdwardsY(proof[..32].try_into().ok()?);
+    let response =
+        Option::<Scalar>::from(Scalar::from_canonical_bytes(proof[32..].try_into().ok()?))?;
+    let challenge = proof_challenge(commitment.index, &points[0], &nonce_point);
+    (ED25519_BASEPOINT_POINT * response == nonce_point.decompress()? + points[0] * challenge)
+        .then_some(points)
+}
+
+fn proof_challenge(index: u16, commitment: &EdwardsPoint, nonce: &CompressedEdwardsY) -> Scalar {
+    Scalar::from_bytes_mod_order_wide(
+        &Sha512::new()
+            .chain_update(PROOF_DOMAIN)
+            .chain_update(index.to_be_bytes())
+            .chain_update(commitment.compress().as_bytes())
+            .chain_update(nonce.as_bytes())
+            .finalize()
+            .into(),
+    )
+}
+
+fn random_scalar() -> Scalar {
+    let mut bytes = [0u8; 64];
+    OsRng.fill_bytes(&mut bytes);
+    Scalar::from_bytes_mod_order_wide(&bytes)
+}
+
+fn decompress(bytes: &[u8; 32]) -> Option<EdwardsPoint> {
+    CompressedEdwardsY(*bytes).decompress()
+}
+
+mod hex32 {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let mut bytes = [0u8; 32];
+        hex::decode_to_slice(text.trim_start_matches("0x"), &mut bytes)
+            .map_err(serde::de::Error::custom)?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sign `message` with the shares of `signers`, as a coordinator would
+    fn sign(shares: &[KeyShare], signers: &[u16], message: &[u8]) -> [u8; 64] {
+        let (nonces, commitments): (Vec<_>, Vec<_>) = signers
+            .iter()
+            .map(|index| shares[usize::from(*index) - 1].commit())
+            .unzip();
+        let partials: Vec<PartialSignature> = signers
+            .iter()
+            .zip(nonces)
+            .map(|(index, nonces)| {
+                shares[usize::from(*index) - 1]
+                    .sign(nonces, message, &commitments)
+                    .unwrap()
+            })
+            .collect();
+        shares[0]
+            .group
+            .combine(message, &commitments, &partials)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_dealt_shares_sign() {
+        let (group, shares) = deal(3, 5).unwrap();
+        for signers in [[1, 2, 3], [2, 4, 5], [5, 1, 3]] {
+            let signature = sign(&shares, &signers, b"block 7");
+            assert!(group.verify(b"block 7", &signature));
+            assert!(!group.verify(b"block 8", &signature));
+        }
+        let json = serde_json::to_string(&shares[0]).unwrap();
+        assert_eq!(serde_json::from_str::<KeyShare>(&json).unwrap(), shares[0]);
+
+        // Two of three is not enough, and a wrong partial names its signer
+        let (nonces, commitments): (Vec<_>, Vec<_>) =
+            shares[..2].iter().map(KeyShare::commit).unzip();
+        assert!(matches!(
+            shares[0].sign(nonces.into_iter().next().unwrap(), b"x", &commitments),
+            Err(ThresholdError::TooFew { got: 2, needed: 3 })
+        ));
+        let (nonces, commitments): (Vec<_>, Vec<_>) =
+            shares[..3].iter().map(KeyShare::commit).unzip();
+        let mut partials: Vec<PartialSignature> = shares[..3]
+            .iter()
+            .zip(nonces)
+            .map(|(share, nonces)| share.sign(nonces, b"x", &commitments).unwrap())
+            .collect();
+        partials[1].share = partials[0].share;
+        assert!(!group
+            .verify_partial(b"x", &commitments, &partials[1])
+            .unwrap());
+        assert_eq!(
+            group.combine(b"x", &commitments, &partials),
+            Err(ThresholdError::Culprits(vec![2]))
+        );
+        assert!(deal(0, 3).is_err());
+        assert!(deal(4, 3).is_err());
+    }
+
+    #[test]
+    fn test_distributed_key_generation() {
+        let participants: Vec<Dkg> = (1..=4)
+            .map(|index| Dkg::new(index, 3, 4).unwrap())
+            .collect();
+        let commitments: Vec<DkgCommitment> = participants.iter().map(Dkg::commitment).collect();
+        let shares: Vec<BTreeMap<u16, [u8; 32]>> = (1..=4)
+            .map(|to| {
+                participants
+                    .iter()
+                    .map(|from| (from.index, from.share_for(to).unwrap()))
+                    .collect()
+            })
+            .collect();
+
+        let mut forged = shares[1].clone();
+        forged.insert(3, shares[1][&4]);
+        let mut lying = commitments.clone();
+        lying[0].proof = lying[1].proof.clone();
+        // The third in twice and the fourth missing
+        let mut repeated = commitments.clone();
+        repeated[3] = repeated[2].clone();
+
+        let key_shares: Vec<KeyShare> = participants
+            .into_iter()
+            .zip(&shares)
+            .map(|(participant, shares)| participant.finish(&commitments, shares).unwrap())
+            .collect();
+        let group = &key_shares[0].group;
+        assert!(key_shares.iter().all(|share| share.group == *group));
+        let signature = sign(&key_shares, &[1, 3, 4], b"finalized");
+        assert!(group.verify(b"finalized", &signature));
+
+        assert_eq!(
+            Dkg::new(2, 3, 4).unwrap().finish(&commitments, &forged),
+            Err(ThresholdError::Culprits(vec![3]))
+        );
+        assert_eq!(
+            Dkg::new(1, 3, 4).unwrap().finish(&lying, &shares[0]),
+            Err(ThresholdError::Culprits(vec![1]))
+        );
+        assert_eq!(
+            Dkg::new(1, 3, 4).unwrap().finish(&repeated, &shares[0]),
+            Err(ThresholdError::Duplicate(3))
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Verifiable random functions: the holder of a key turns an input into an
+//! output no one could have guessed, with a proof anyone holding the public
+//! key checks it against, and no way to pick among outputs. Producers draw
+//! their leader tickets with one, and agents have it as a source of chaos
+//! that is fair to everyone watching.
+//!
+//! This is ECVRF-EDWARDS25519-SHA512-TAI of RFC 9381, so a VRF key is an
+//! ed25519 key, and the keystore keys prove with the secret they sign
+//! with. Proofs are 80 bytes, outputs 64.
+
+use crate::secret::{Secret, Zeroizing};
+use curve25519_dalek::constants::ED25519_BASEPOINT_POINT;
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::Scalar;
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use thiserror::Error;
+
+/// Suite of RFC 9381 for edwards25519 with try-and-increment
+const SUITE: u8 = 0x03;
+
+pub const PROOF_LENGTH: usize = 80;
+pub const OUTPUT_LENGTH: usize = 64;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum VrfError {
+    #[error("Not a VRF public key: {0}")]
+    InvalidKey(&'static str),
+    #[error("Not a VRF proof: {0}")]
+    Malformed(&'static str),
+    #[error("The VRF proof does not hold for this key and input")]
+    InvalidProof,
+}
+
+/// The secret a VRF key proves with, the seed of an ed25519 key. Locked
+/// into RAM and wiped when dropped.
+pub struct VrfSecretKey {
+    seed: Secret<[u8; 32]>,
+}
+
+impl std::fmt::Debug for VrfSecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VrfSecretKey")
+            .field("public_key", &self.public_key())
+            .finish_non_exhaustive()
+    }
+}
+
+impl VrfSecretKey {
+    pub fn generate() -> Self {
+        Self::from_bytes(SigningKey::generate(&mut OsRng).to_bytes())
+    }
+
+    pub fn from_bytes(seed: [u8; 32]) -> Self {
+        Self {
+            seed: Secret::new(seed),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(*self.seed.expose())
+    }
+
+    /// The scalar of the key, and the half of its hash nonces come from
+    fn expand(&self) -> (Zeroizing<Scalar>, Zeroizing<[u8; 32]>) {
+        let hash = Zeroizing::new(<[u8; 64]>::from(Sha512::digest(self.seed.expose())));
+        let mut scalar = Zeroizing::new([0; 32]);
+        scalar.copy_from_slice(&hash[..32]);
+        scalar[0] &= 248;
+        scalar[31] &= 127;
+        scalar[31] |= 64;
+        let mut prefix = Zeroizing::new([0; 32]);
+        prefix.copy_from_slice(&hash[32..]);
+        (
+            Zeroizing::new(Scalar::from_bytes_mod_order(*scalar)),
+            prefix,
+        )
+    }
+
+    pub fn public_key(&self) -> VrfPublicKey {
+        VrfPublicKey(
+            SigningKey::from_bytes(self.seed.expose())
+                .verifying_key()
+                .to_bytes(),
+        )
+    }
+
+    /// The proof of the output of `alpha`
+    pub fn prove(&self, alpha: &[u8]) -> VrfProof {
+        let (x, prefix) = self.expand();
+        let public = self.public_key().0;
+        let h = encode_to_curve(&public, alpha)
+            .expect("an input finds a point within 256 tries but for a 2^-256 chance");
+        let h_string = h.compress().to_bytes();
+        let gamma = h * *x;
+        let k = Zeroizing::new(Scalar::from_bytes_mod_order_wide(
+            &Sha512::new()
+                .chain_update(*prefix)
+                .chain_update(h_string)
+                .finalize()
+                .into(),
+        ));
+        let c = challenge(
+            &public,
+            &h_string,
+            &gamma.compress().to_bytes(),
+            &(ED25519_BASEPOINT_POINT * *k).compress().to_bytes(),
+            &(h * *k).compress().to_bytes(),
+        );
+        let s = *k + scalar_of_challenge(&c) * *x;
+        let mut proof = [0; PROOF_LENGTH];
+        proof[..32].copy_from_slice(gamma.compress().as_bytes());
+        proof[32..48].copy_from_slice(&c);
+        proof[48..].copy_from_slice(s.as_bytes());
+        VrfProof(proof)
+    }
+}
+
+impl From<&SigningKey> for VrfSecretKey {
+    fn from(key: &SigningKey) -> Self {
+        Self::from_bytes(key.to_bytes())
+    }
+}
+
+/// A VRF public key, the same bytes as the ed25519 key of its secret
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VrfPublicKey(#[serde(with = "hex_bytes")] pub [u8; 32]);
+
+impl VrfPublicKey {
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+
+    /// The output of `alpha`, when `proof` is of this key
+    pub fn verify(&self, alpha: &[u8], proof: &VrfProof) -> Result<VrfOutput, VrfError> {
+        let y = CompressedEdwardsY(self.0)
+            .decompress()
+            .ok_or(VrfError::InvalidKey("not a point of edwards25519"))?;
+        // A key of small order would prove anything
+        if y.is_small_order() {
+            return Err(VrfError::InvalidKey("a point of small order"));
+        }
+        let (gamma, c, s) = proof.decode()?;
+        let h = encode_to_curve(&self.0, alpha).ok_or(VrfError::InvalidProof)?;
+        let c_scalar = scalar_of_challenge(&c);
+        let u = ED25519_BASEPOINT_POINT * s - y * c_scalar;
+        let v = h * s - gamma * c_scalar;
+        let expected = challenge(
+            &self.0,
+            &h.compress().to_bytes(),
+            &proof.0[..32].try_into().expect("32 bytes"),
+            &u.compress().to_bytes(),
+            &v.compress().to_bytes(),
+        );
+        if expected != c {
+            return Err(VrfError::InvalidProof);
+        }
+        Ok(output_of(&gamma))
+    }
+}
+
+/// Proof of an output: the point the output is hashed from, and the
+/// challenge and answer showing it is of the key. Hex encoded in JSON.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VrfProof(#[serde(with = "hex_bytes")] pub [u8; PROOF_LENGTH]);
+
+impl std::fmt::Debug for VrfProof {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VrfProof({})", hex::encode(self.0))
+    }
+}
+
+impl VrfProof {
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, VrfError> {
+        bytes
+            .try_into()
+            .map(Self)
+            .map_err(|_| VrfError::Malformed("a proof is 80 bytes"))
+    }
+
+    fn decode(&self) -> Result<(EdwardsPoint, [u8; 16], Scalar), VrfError> {
+        let gamma = CompressedEdwardsY(self.0[..32].try_into().expect("32 bytes"))
+            .decompress()
+            .ok_or(VrfError::Malformed("gamma is not a point of edwards25519"))?;
+        let c: [u8; 16] = self.0[32..48].try_into().expect("16 bytes");
+        let s = Option::from(Scalar::from_canonical_bytes(
+            self.0[48..].try_into().expect("32 bytes"),
+        ))
+        .ok_or(VrfError::Malformed("s is not a canonical scalar"))?;
+        Ok((gamma, c, s))
+    }
+
+    /// The output the proof is of, without checking it. Only for a proof
+    /// that was made here, or verified.
+    pub fn output(&self) -> Result<VrfOutput, VrfError> {
+        Ok(output_of(&self.decode()?.0))
+    }
+}
+
+/// What a VRF gives for an input, uniform and fixed by the key and input
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VrfOutput(#[serde(with = "hex_bytes")] pub [u8; OUTPUT_LENGTH]);
+
+impl std::fmt::Debug for VrfOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "VrfOutput({})", hex::encode(self.0))
+    }
+}
+
+impl VrfOutput {
+    /// The output as a number from 0 up to 1, from its first 8 bytes
+    pub fn fraction(&self) -> f64 {
+        let first = u64::from_be_bytes(self.0[..8].try_into().expect("8 bytes"));
+        (first >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number from 0 below `bound`, as fair as the output is up to a bias
+    /// of `bound` in 2^64
+    pub fn below(&self, bound: u64) -> u64 {
+        let first = u64::from_be_bytes(self.0[..8].try_into().expect("8 bytes"));
+        ((u128::from(first) * u128::from(bound)) >> 64) as u64
+    }
+}
+
+/// The point of the curve `alpha` hashes to, for the key `salt`, trying
+/// counters until a hash is a point
+fn encode_to_curve(salt: &[u8; 32], alpha: &[u8]) -> Option<EdwardsPoint> {
+    (0..=u8::MAX).find_map(|counter| {
+        let hash = Sha512::new()
+            .chain_update([SUITE, 0x01])
+            .chain_update(salt)
+            .chain_update(alpha)
+            .chain_update([counter, 0x00])
+            .finalize();
+        CompressedEdwardsY(hash[..32].try_into().expect("32 bytes"))
+            .decompress()
+            .map(|point| point.mul_by_cofactor())
+    })
+}
+
+fn challenge(
+    public: &[u8; 32],
+    h: &[u8; 32],
+    gamma: &[u8; 32],
+    u: &[u8; 32],
+    v: &[u8; 32],
+) -> [u8; 16] {
+    let hash = Sha512::new()
+        .chain_update([SUITE, 0x02])
+        .chain_update(public)
+        .chain_update(h)
+        .chain_update(gamma)
+        .chain_update(u)
+        .chain_update(v)
+        .chain_update([0x00])
+        .finalize();
+    hash[..16].try_into().expect("16 bytes")
+}
+
+fn scalar_of_challenge(c: &[u8; 16]) -> Scalar {
+    let mut bytes = [0; 32];
+    bytes[..16].copy_from_slice(c);
+    Scalar::from_bytes_mod_order(bytes)
+}
+
+fn output_of(gamma: &EdwardsPoint) -> VrfOutput {
+    let hash = Sha512::new()
+        .chain_update([SUITE, 0x03])
+        .chain_update(gamma.mul_by_cofactor().compress().as_bytes())
+        .chain_update([0x00])
+        .finalize();
+    VrfOutput(hash.into())
+}
+
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        bytes: &[u8; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let bytes = hex::decode(text.trim_start_matches("0x")).map_err(serde::de::Error::custom)?;
+        bytes
+            .try_into()
+            .map_err(|_| serde::de::Error::custom(format!("expected {} bytes", N)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vrf() {
+        // Example 16 of RFC 9381
+        let secret = VrfSecretKey::from_bytes(
+            hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+                .unwrap()
+                .try_into()
+                .unwrap(),
+        );
+        let public = secret.public_key();
+        assert_eq!(
+            hex::encode(public.0),
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        let proof = secret.prove(b"");
+        assert_eq!(
+            hex::encode(proof.0),
+            "8657106690b5526245a92b003bb079ccd1a92130477671f6fc01ad16f26f723f26f8a57ccaed74ee1b190bed1f479d9727d2d0f9b005a6e456a35d4fb0daab1268a1b0db10836d9826a528ca76567805"
+        );
+        let output = public.verify(b"", &proof).unwrap();
+        assert_eq!(
+            hex::encode(output.0),
+            "90cf1df3b703cce59e2a35b925d411164068269d7b2d29f3301c03dd757876ff66b71dda49d2de59d03450451af026798e8f81cd2e333de5cdf4f3e140fdd8ae"
+        );
+        assert_eq!(proof.output().unwrap(), output);
+
+        // Another input, key or proof does not hold
+        assert_eq!(public.verify(b"x", &proof), Err(VrfError::InvalidProof));
+        let other = VrfSecretKey::generate();
+        assert_eq!(
+            other.public_key().verify(b"", &proof),
+            Err(VrfError::InvalidProof)
+        );
+        let mut forged = proof;
+        forged.0[40] ^= 1;
+        assert!(public.verify(b"", &forged).is_err());
+        let proof = other.prove(b"chaos");
+        let output = other.public_key().verify(b"chaos", &proof).unwrap();
+        assert!((0.0..1.0).contains(&output.fraction()));
+        assert!(output.below(6) < 6);
+    }
+}
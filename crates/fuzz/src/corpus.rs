@@ -0,0 +1,350 @@
+//! Seeds for the corpus of each target: well-formed inputs a fuzzer
+//! starts from, so that it mutates messages a node would take rather than
+//! spend its time finding out what JSON looks like. The state transitions
+//! begin with blocks that create a multisig account and spend from it, and
+//! that commit to a payload and reveal it.
+
+use crate::mempool::{self, Op};
+use crate::state::{self, BlockSpec, Height};
+use crate::{Target, TARGETS};
+use chaoschain_core::commit::{Commit, Reveal};
+use chaoschain_core::compact::CompactBlock;
+use chaoschain_core::compression::{self, Codec};
+use chaoschain_core::envelope::SignedEnvelope;
+use chaoschain_core::gossip::GossipTopic;
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{Block, NetworkMessage};
+
+/// A seed of a target, by the name of its file
+pub type Entry = (String, Vec<u8>);
+
+/// The seeds of `target`
+pub fn seeds(target: Target) -> Vec<Entry> {
+    match target {
+        Target::DecodeTransaction => transactions()
+            .into_iter()
+            .map(|(name, tx)| (name, json(&tx)))
+            .collect(),
+        Target::DecodeBlock => {
+            let block = block();
+            vec![
+                ("empty".to_string(), json(&empty_block())),
+                ("block".to_string(), json(&block)),
+                ("compact".to_string(), json(&CompactBlock::new(&block))),
+            ]
+        }
+        Target::DecodeVote => vec![
+            ("approval".to_string(), json(&vote(true))),
+            ("rejection".to_string(), json(&vote(false))),
+        ],
+        Target::Gossip => gossip(),
+        Target::Mempool => mempool(),
+        Target::StateTransition => state_transitions(),
+    }
+}
+
+/// Every seed of every target
+pub fn all() -> Vec<(Target, Entry)> {
+    TARGETS
+        .iter()
+        .flat_map(|&target| seeds(target).into_iter().map(move |seed| (target, seed)))
+        .collect()
+}
+
+fn json(value: &impl serde::Serialize) -> Vec<u8> {
+    serde_json::to_vec(value).expect("a seed serializes")
+}
+
+fn tx(sender: usize, nonce: u64, payload: &[u8]) -> state::Tx {
+    state::Tx {
+        sender,
+        nonce,
+        payload: payload.to_vec(),
+        signed: true,
+        cosigners: 0,
+    }
+}
+
+/// The multisig account spending, signed by `cosigners`
+fn from_account(nonce: u64, cosigners: u8) -> state::Tx {
+    state::Tx {
+        cosigners,
+        ..tx(state::SENDERS, nonce, b"spent by the council")
+    }
+}
+
+fn transactions() -> Vec<(String, chaoschain_core::Transaction)> {
+    let commit = Commit::seal(
+        &state::key(0).verifying_key().to_bytes(),
+        &[7; 32],
+        b"sealed drama",
+    );
+    vec![
+        ("signed", tx(0, 0, b"drama").transaction()),
+        ("empty-payload", tx(1, 1, b"").transaction()),
+        (
+            "multisig",
+            tx(0, 2, &state::account().payload()).transaction(),
+        ),
+        ("cosigned", from_account(0, 0b11).transaction()),
+        ("commit", tx(0, 3, &commit.payload()).transaction()),
+    ]
+    .into_iter()
+    .map(|(name, tx)| (name.to_string(), tx))
+    .collect()
+}
+
+fn empty_block() -> Block {
+    Block {
+        height: 1,
+        parent_hash: [0; 32],
+        transactions: Vec::new(),
+        proposer_sig: [0; 64],
+        state_root: [0; 32],
+        innovation_level: 5,
+        producer_strategy: "seeded".to_string(),
+        producer_id: "producer-0".to_string(),
+        drama_level: 5,
+        producer_mood: "Theatrical".to_string(),
+        timestamp: 1_700_000_000,
+        metadata: Default::default(),
+    }
+}
+
+fn block() -> Block {
+    Block {
+        height: 2,
+        transactions: transactions().into_iter().map(|(_, tx)| tx).collect(),
+        ..empty_block()
+    }
+}
+
+fn vote(approved: bool) -> SignedVote {
+    SignedVote::sign(
+        &state::key(0),
+        "validator-0",
+        1,
+        block().hash(),
+        approved,
+        7,
+        "The drama is exquisite",
+    )
+}
+
+/// Every kind of gossip on its topic, as it goes out plain and compressed
+fn gossip() -> Vec<Entry> {
+    let chat = NetworkMessage::Chat {
+        from: "agent-0".to_string(),
+        message: "My allies will remember this block".repeat(8),
+    };
+    let messages = [
+        ("block", NetworkMessage::NewBlock(block())),
+        (
+            "compact",
+            NetworkMessage::CompactBlock(CompactBlock::new(&block())),
+        ),
+        ("vote", NetworkMessage::Vote(vote(true))),
+        (
+            "transaction",
+            NetworkMessage::NewTransaction(tx(0, 0, b"drama").transaction()),
+        ),
+        (
+            "signed-chat",
+            NetworkMessage::Signed(
+                SignedEnvelope::sign(&state::key(0), chat).expect("a social message"),
+            ),
+        ),
+    ];
+    let mut entries = Vec::new();
+    for (name, message) in messages {
+        let topic = GossipTopic::ALL
+            .iter()
+            .position(|topic| *topic == message.topic())
+            .expect("a topic") as u8;
+        for codec in [Codec::None, Codec::Snappy] {
+            let (codec, frame) = match compression::encode(codec, json(&message)) {
+                // Too small to be worth compressing, so the plain one again
+                (Codec::None, _) if codec == Codec::Snappy => continue,
+                encoded => encoded,
+            };
+            let mut data = vec![topic];
+            data.extend(frame);
+            entries.push((format!("{}-{}", name, codec.name()), data));
+        }
+    }
+    entries
+}
+
+fn mempool() -> Vec<Entry> {
+    let submit = |sender, nonce| Op::Submit {
+        tx: mempool::Tx {
+            sender,
+            nonce,
+            payload: b"drama".to_vec(),
+        },
+        next_nonce: 0,
+    };
+    let sequences = [
+        (
+            "nonces",
+            8,
+            vec![submit(0, 0), submit(0, 1), submit(0, 1), submit(0, 3)],
+        ),
+        ("full", 2, vec![submit(0, 0), submit(1, 0), submit(2, 0)]),
+        (
+            "banned",
+            8,
+            vec![Op::Ban(0), submit(0, 0), Op::Unban(0), submit(0, 0)],
+        ),
+        (
+            "drained",
+            8,
+            vec![
+                submit(0, 0),
+                submit(1, 0),
+                Op::Add(mempool::Tx {
+                    sender: 2,
+                    nonce: 9,
+                    payload: Vec::new(),
+                }),
+                Op::Top(2),
+                Op::Entries(Some(0)),
+                Op::Take(1),
+                Op::Entries(None),
+                Op::Clear,
+            ],
+        ),
+    ];
+    sequences
+        .into_iter()
+        .map(|(name, capacity, ops)| (name.to_string(), mempool::seed(capacity, 2, &ops)))
+        .collect()
+}
+
+fn state_transitions() -> Vec<Entry> {
+    let block = |height, transactions| BlockSpec {
+        height,
+        producer: 0,
+        innovation_level: 8,
+        drama_level: 5,
+        transactions,
+    };
+    let sender = state::key(0).verifying_key().to_bytes();
+    let key = [9; 32];
+    let commit = tx(
+        0,
+        1,
+        &Commit::seal(&sender, &key, b"sealed drama").payload(),
+    );
+    let reveal = Reveal {
+        commit: commit.transaction().hash(),
+        key,
+    };
+    let unsigned = state::Tx {
+        signed: false,
+        ..tx(1, 0, b"forged")
+    };
+    let sequences = [
+        ("empty", vec![block(Height::Next, Vec::new())]),
+        (
+            "signed",
+            vec![
+                block(Height::Next, vec![tx(0, 0, b"drama"), tx(1, 0, b"more")]),
+                block(Height::Next, vec![unsigned]),
+                block(Height::Last, vec![tx(2, 0, b"instead")]),
+                block(Height::At(u64::MAX), Vec::new()),
+            ],
+        ),
+        (
+            "multisig",
+            vec![
+                block(Height::Next, vec![tx(0, 0, &state::account().payload())]),
+                block(Height::Next, vec![from_account(0, 0b0001)]),
+                block(Height::Next, vec![from_account(0, 0b0110)]),
+            ],
+        ),
+        (
+            "commit-reveal",
+            vec![
+                block(Height::Next, vec![commit]),
+                block(Height::Next, vec![tx(0, 2, &reveal.payload())]),
+            ],
+        ),
+    ];
+    sequences
+        .into_iter()
+        .map(|(name, blocks)| (name.to_string(), state::seed(&blocks)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Mutations run of each seed
+    const MUTATIONS: usize = 64;
+
+    /// `data` with a few bytes flipped, dropped, repeated or cut off
+    fn mutate(rng: &mut StdRng, mut data: Vec<u8>) -> Vec<u8> {
+        for _ in 0..rng.gen_range(1..=4) {
+            if data.is_empty() {
+                data.push(rng.gen());
+                continue;
+            }
+            let at = rng.gen_range(0..data.len());
+            match rng.gen_range(0..4) {
+                0 => data[at] ^= 1 << rng.gen_range(0..8),
+                1 => {
+                    data.remove(at);
+                }
+                2 => data.insert(at, data[at]),
+                _ => data.truncate(at),
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_seeds_and_their_mutations_hold() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for (target, (name, data)) in all() {
+            target.run(&data);
+            for _ in 0..MUTATIONS {
+                let mutated = mutate(&mut rng, data.clone());
+                let result = std::panic::catch_unwind(|| target.run(&mutated));
+                assert!(
+                    result.is_ok(),
+                    "{} panicked on a mutation of {}: {}",
+                    target,
+                    name,
+                    hex::encode(&mutated)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_seeds_reach_past_decoding() {
+        // The seeds are messages a node takes, not ones it turns away
+        for (name, data) in seeds(Target::DecodeTransaction) {
+            let tx: chaoschain_core::Transaction = serde_json::from_slice(&data).unwrap();
+            let message = NetworkMessage::NewTransaction(tx);
+            assert!(message.check(&Default::default()).is_ok(), "{}", name);
+        }
+        for (name, data) in seeds(Target::Gossip) {
+            let (_, payload) = compression::decode(&data[1..], 1024 * 1024).unwrap();
+            let message: NetworkMessage = serde_json::from_slice(&payload).unwrap();
+            assert_eq!(
+                GossipTopic::ALL[data[0] as usize],
+                message.topic(),
+                "{}",
+                name
+            );
+        }
+        assert!(seeds(Target::Gossip)
+            .iter()
+            .any(|(name, _)| name.ends_with("snappy")));
+    }
+}
@@ -0,0 +1,93 @@
+//! What peers send, decoded the way a node decodes it. Whatever decodes
+//! is put through the checks a node runs before passing a message on, and
+//! has to encode back into itself: a message that reads differently after
+//! a relay would be judged differently by each peer it reaches.
+
+use chaoschain_core::compact::CompactBlock;
+use chaoschain_core::compression;
+use chaoschain_core::gossip::{GossipLimits, GossipTopic};
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{Block, NetworkMessage, Transaction};
+use chaoschain_p2p::wire;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+
+/// A transaction as gossiped
+pub fn transaction(data: &[u8]) {
+    let Ok(tx) = serde_json::from_slice::<Transaction>(data) else {
+        return;
+    };
+    round_trip(&tx);
+    tx.hash();
+    let _ = tx.verify();
+    let _ = NetworkMessage::NewTransaction(tx).check(&GossipLimits::default());
+}
+
+/// A block as gossiped, in full or compact
+pub fn block(data: &[u8]) {
+    if let Ok(block) = serde_json::from_slice::<Block>(data) {
+        round_trip(&block);
+        block.hash();
+        let compact = CompactBlock::new(&block);
+        assert_eq!(compact.short_ids.len(), block.transactions.len());
+        let _ = NetworkMessage::NewBlock(block).check(&GossipLimits::default());
+    }
+    if let Ok(compact) = serde_json::from_slice::<CompactBlock>(data) {
+        round_trip(&compact);
+        // Nothing known, so every transaction is missing
+        let partial = compact.reconstruct([]);
+        assert_eq!(partial.missing().len(), compact.short_ids.len());
+        let _ = NetworkMessage::CompactBlock(compact).check(&GossipLimits::default());
+    }
+}
+
+/// A signed vote as gossiped
+pub fn vote(data: &[u8]) {
+    let Ok(vote) = serde_json::from_slice::<SignedVote>(data) else {
+        return;
+    };
+    round_trip(&vote);
+    vote.to_decision();
+    let _ = NetworkMessage::Vote(vote).check(&GossipLimits::default());
+}
+
+/// A gossip frame as it comes off the wire: the first byte picks the
+/// topic it arrived on, the rest is the frame. It goes through the checks
+/// of the node's gossip validation that need no peer.
+pub fn gossip(data: &[u8]) {
+    let Some((&topic, frame)) = data.split_first() else {
+        return;
+    };
+    let topic = GossipTopic::ALL[topic as usize % GossipTopic::ALL.len()];
+    let max_size = wire::max_gossip_size(topic);
+    if frame.len() > max_size {
+        return;
+    }
+    let Ok((_, payload)) = compression::decode(frame, max_size) else {
+        return;
+    };
+    assert!(
+        payload.len() <= max_size,
+        "{} bytes decompressed from a frame of {}, at most {} allowed",
+        payload.len(),
+        frame.len(),
+        max_size
+    );
+    let Ok(message) = serde_json::from_slice::<NetworkMessage>(&payload) else {
+        return;
+    };
+    if message.topic() != topic || message.check(&GossipLimits::default()).is_err() {
+        return;
+    }
+    if let NetworkMessage::Signed(envelope) = &message {
+        let _ = envelope.verify();
+    }
+}
+
+/// `value` encoded and decoded again is `value`
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: &T) {
+    let encoded = serde_json::to_vec(value).expect("what decoded encodes");
+    let decoded: T = serde_json::from_slice(&encoded).expect("what was encoded decodes");
+    assert_eq!(&decoded, value, "changed encoding it and decoding it again");
+}
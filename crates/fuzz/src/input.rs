@@ -0,0 +1,132 @@
+//! Structured input read out of the bytes a fuzzer hands over, and written
+//! back into them for the corpus. An [`Input`] never fails: once the bytes
+//! run out it reads zeros, so every byte string is some sequence of
+//! operations, and a [`Seed`] writes the bytes that read back as the
+//! operations it was given.
+
+/// Longest byte string read in one go, so that a length prefix cannot make
+/// a harness allocate more than the input is worth
+pub const MAX_BYTES: usize = 4096;
+
+#[derive(Debug)]
+pub struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Whether every byte has been read
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn u8(&mut self) -> u8 {
+        let Some((&first, rest)) = self.data.split_first() else {
+            return 0;
+        };
+        self.data = rest;
+        first
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.u8() & 1 == 1
+    }
+
+    pub fn u16(&mut self) -> u16 {
+        u16::from_le_bytes([self.u8(), self.u8()])
+    }
+
+    pub fn u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.iter_mut().for_each(|byte| *byte = self.u8());
+        u64::from_le_bytes(bytes)
+    }
+
+    /// A number below `n`, which is not 0
+    pub fn below(&mut self, n: usize) -> usize {
+        self.u8() as usize % n
+    }
+
+    /// Bytes behind a two byte length, as many as are left when there are
+    /// fewer
+    pub fn bytes(&mut self) -> &'a [u8] {
+        let len = (self.u16() as usize).min(MAX_BYTES).min(self.data.len());
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        bytes
+    }
+}
+
+/// The bytes an [`Input`] reads back as what was written
+#[derive(Debug, Default)]
+pub struct Seed {
+    bytes: Vec<u8>,
+}
+
+impl Seed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, value: u8) -> &mut Self {
+        self.bytes.push(value);
+        self
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.u8(value as u8)
+    }
+
+    pub fn u16(&mut self, value: u16) -> &mut Self {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, value: u64) -> &mut Self {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    /// `bytes` behind their length, cut at [`MAX_BYTES`]
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        let bytes = &bytes[..bytes.len().min(MAX_BYTES)];
+        self.u16(bytes.len() as u16);
+        self.bytes.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeds_read_back() {
+        let bytes = Seed::new()
+            .u8(7)
+            .bool(true)
+            .u64(u64::MAX - 1)
+            .bytes(b"drama")
+            .finish();
+        let mut input = Input::new(&bytes);
+        assert_eq!(input.u8(), 7);
+        assert!(input.bool());
+        assert_eq!(input.u64(), u64::MAX - 1);
+        assert_eq!(input.bytes(), b"drama");
+        assert!(input.is_empty());
+        // Past the end everything is zero
+        assert_eq!(input.u64(), 0);
+        assert!(input.bytes().is_empty());
+
+        // A length running past the end takes what is left
+        let mut input = Input::new(&[0xff, 0xff, 1, 2]);
+        assert_eq!(input.bytes(), &[1, 2]);
+    }
+}
@@ -0,0 +1,90 @@
+//! Fuzzing harnesses for what a node takes from its peers. Each
+//! [`Target`] is a function of arbitrary bytes that panics when it finds a
+//! bug: a message that crashes the node decoding it, a decoder that
+//! allocates on a peer's word, a mempool that loses count, a block that
+//! changes the state without holding or makes two nodes disagree.
+//!
+//! The libFuzzer targets in `fuzz/` call them, and the same inputs run on
+//! a stable toolchain through the `chaoschain-fuzz` binary, which also
+//! writes the [corpus](corpus) the fuzzer starts from:
+//!
+//! ```bash
+//! cargo run -p chaoschain-fuzz -- corpus fuzz/corpus
+//! cargo +nightly fuzz run gossip fuzz/corpus/gossip
+//! cargo run -p chaoschain-fuzz -- run gossip fuzz/artifacts/gossip/crash-*
+//! ```
+
+pub mod corpus;
+pub mod decode;
+pub mod input;
+pub mod mempool;
+pub mod state;
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Target {
+    DecodeTransaction,
+    DecodeBlock,
+    DecodeVote,
+    /// A gossip frame through decompression, decoding and the checks of
+    /// its topic
+    Gossip,
+    Mempool,
+    StateTransition,
+}
+
+pub const TARGETS: [Target; 6] = [
+    Target::DecodeTransaction,
+    Target::DecodeBlock,
+    Target::DecodeVote,
+    Target::Gossip,
+    Target::Mempool,
+    Target::StateTransition,
+];
+
+impl Target {
+    /// Name of the libFuzzer target, and of its corpus directory
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::DecodeTransaction => "decode_transaction",
+            Target::DecodeBlock => "decode_block",
+            Target::DecodeVote => "decode_vote",
+            Target::Gossip => "gossip",
+            Target::Mempool => "mempool",
+            Target::StateTransition => "state_transition",
+        }
+    }
+
+    /// Run one input, panicking on a bug
+    pub fn run(self, data: &[u8]) {
+        match self {
+            Target::DecodeTransaction => decode::transaction(data),
+            Target::DecodeBlock => decode::block(data),
+            Target::DecodeVote => decode::vote(data),
+            Target::Gossip => decode::gossip(data),
+            Target::Mempool => mempool::run(data),
+            Target::StateTransition => state::run(data),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TARGETS
+            .into_iter()
+            .find(|target| target.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = TARGETS.iter().map(|target| target.name()).collect();
+                format!("Unknown target {}, one of {}", s, names.join(", "))
+            })
+    }
+}
@@ -0,0 +1,73 @@
+//! `chaoschain-fuzz`: write the seed corpus of the fuzz targets, or put
+//! inputs through a target without libFuzzer, to reproduce what the fuzzer
+//! found on a stable toolchain. See the [library](chaoschain_fuzz).
+
+use anyhow::{Context, Result};
+use chaoschain_fuzz::{corpus, Target};
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Write the seeds of every target, each in a directory of its name
+    Corpus {
+        #[arg(default_value = "fuzz/corpus")]
+        dir: PathBuf,
+    },
+    /// Run files, or every file of directories, through a target
+    Run {
+        target: Target,
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+    },
+}
+
+fn main() -> Result<()> {
+    match Args::parse().command {
+        Command::Corpus { dir } => {
+            let seeds = corpus::all();
+            for (target, (name, data)) in &seeds {
+                let dir = dir.join(target.name());
+                std::fs::create_dir_all(&dir)
+                    .with_context(|| format!("Creating {}", dir.display()))?;
+                std::fs::write(dir.join(name), data)?;
+            }
+            println!("Wrote {} seeds to {}", seeds.len(), dir.display());
+        }
+        Command::Run { target, inputs } => {
+            let mut files = Vec::new();
+            for input in &inputs {
+                collect(input, &mut files)?;
+            }
+            for file in &files {
+                let data = std::fs::read(file)?;
+                println!("Running {} ({} bytes)", file.display(), data.len());
+                target.run(&data);
+            }
+            println!("{} inputs held", files.len());
+        }
+    }
+    Ok(())
+}
+
+/// `path`, or the files of it when it is a directory
+fn collect(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)
+            .with_context(|| format!("Reading {}", path.display()))?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort();
+        files.extend(entries.into_iter().filter(|entry| entry.is_file()));
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
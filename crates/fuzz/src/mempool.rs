@@ -0,0 +1,215 @@
+//! Sequences of mempool operations, checked against a model of what is
+//! waiting. However transactions come in, and whoever sends them, the
+//! mempool holds no more than it may, counts what it holds, and hands out
+//! only transactions that are waiting, each once.
+
+use crate::input::{Input, Seed};
+use chaoschain_core::{Bytes, Transaction};
+use chaoschain_mempool::{Mempool, Rejection};
+use std::collections::HashSet;
+
+/// Senders the operations pick from
+pub const SENDERS: usize = 4;
+
+/// Most transactions the mempool of a run holds
+pub const MAX_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// A transaction from outside, its nonce checked against `next_nonce`
+    Submit {
+        tx: Tx,
+        next_nonce: u64,
+    },
+    /// A transaction gossiped in, its nonce unchecked
+    Add(Tx),
+    Take(usize),
+    Top(usize),
+    Entries(Option<usize>),
+    Ban(usize),
+    Unban(usize),
+    Clear,
+}
+
+/// A transaction of one of the [`SENDERS`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tx {
+    pub sender: usize,
+    pub nonce: u64,
+    pub payload: Vec<u8>,
+}
+
+impl Tx {
+    fn transaction(&self) -> Transaction {
+        Transaction {
+            sender: sender(self.sender),
+            nonce: self.nonce,
+            payload: Bytes::copy_from_slice(&self.payload),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        }
+    }
+
+    fn read(input: &mut Input) -> Self {
+        Self {
+            sender: input.below(SENDERS),
+            nonce: input.u8() as u64,
+            payload: input.bytes().to_vec(),
+        }
+    }
+
+    fn write(&self, seed: &mut Seed) {
+        seed.u8(self.sender as u8)
+            .u8(self.nonce as u8)
+            .bytes(&self.payload);
+    }
+}
+
+impl Op {
+    pub fn read(input: &mut Input) -> Self {
+        match input.below(8) {
+            0 => Op::Submit {
+                tx: Tx::read(input),
+                next_nonce: input.u8() as u64,
+            },
+            1 => Op::Add(Tx::read(input)),
+            2 => Op::Take(input.u8() as usize),
+            3 => Op::Top(input.u8() as usize),
+            4 => Op::Entries(input.bool().then(|| input.below(SENDERS))),
+            5 => Op::Ban(input.below(SENDERS)),
+            6 => Op::Unban(input.below(SENDERS)),
+            _ => Op::Clear,
+        }
+    }
+
+    /// Write the op so that [`Op::read`] reads it back. Nonces and limits
+    /// are written as a byte, the most the op reads.
+    pub fn write(&self, seed: &mut Seed) {
+        match self {
+            Op::Submit { tx, next_nonce } => {
+                seed.u8(0);
+                tx.write(seed);
+                seed.u8(*next_nonce as u8);
+            }
+            Op::Add(tx) => {
+                seed.u8(1);
+                tx.write(seed);
+            }
+            Op::Take(limit) => {
+                seed.u8(2).u8(*limit as u8);
+            }
+            Op::Top(limit) => {
+                seed.u8(3).u8(*limit as u8);
+            }
+            Op::Entries(sender) => {
+                seed.u8(4).bool(sender.is_some());
+                if let Some(sender) = sender {
+                    seed.u8(*sender as u8);
+                }
+            }
+            Op::Ban(sender) => {
+                seed.u8(5).u8(*sender as u8);
+            }
+            Op::Unban(sender) => {
+                seed.u8(6).u8(*sender as u8);
+            }
+            Op::Clear => {
+                seed.u8(7);
+            }
+        }
+    }
+}
+
+/// The input of a run: the mempool's capacity and shards, then its ops
+pub fn seed(capacity: usize, shards: usize, ops: &[Op]) -> Vec<u8> {
+    let mut seed = Seed::new();
+    seed.u8((capacity - 1) as u8).u8((shards - 1) as u8);
+    for op in ops {
+        op.write(&mut seed);
+    }
+    seed.finish()
+}
+
+/// Run the ops `data` reads as on an empty mempool
+pub fn run(data: &[u8]) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("a runtime");
+    runtime.block_on(run_ops(Input::new(data)));
+}
+
+async fn run_ops(mut input: Input<'_>) {
+    let capacity = input.below(MAX_CAPACITY) + 1;
+    let mempool = Mempool::with_shards(capacity, input.below(4) + 1);
+    let mut waiting = HashSet::new();
+    let mut banned = HashSet::new();
+    while !input.is_empty() {
+        match Op::read(&mut input) {
+            Op::Submit { tx, next_nonce } => {
+                let transaction = tx.transaction();
+                let hash = transaction.hash();
+                match mempool.submit(transaction, next_nonce).await {
+                    Ok(()) => {
+                        assert!(!banned.contains(&tx.sender), "Took from a banned sender");
+                        assert!(waiting.len() < capacity, "Took more than it holds");
+                        assert!(waiting.insert(hash), "Took a transaction twice");
+                    }
+                    Err(Rejection::BannedSender) => assert!(banned.contains(&tx.sender)),
+                    Err(Rejection::Duplicate) => assert!(waiting.contains(&hash)),
+                    Err(Rejection::PoolFull) => assert_eq!(waiting.len(), capacity),
+                    Err(Rejection::NonceTooLow { expected }) => assert!(tx.nonce < expected),
+                    Err(Rejection::NonceGap { expected }) => assert!(tx.nonce > expected),
+                }
+            }
+            Op::Add(tx) => {
+                let hash = tx.transaction().hash();
+                if mempool.add_transaction(tx.transaction()).await {
+                    waiting.insert(hash);
+                } else {
+                    assert_eq!(waiting.len(), capacity, "Turned a transaction away");
+                }
+            }
+            Op::Take(limit) => {
+                let taken = mempool.take(limit).await;
+                assert!(taken.len() <= limit);
+                for tx in &taken {
+                    assert!(waiting.remove(&tx.hash()), "Took out what was not waiting");
+                }
+            }
+            Op::Top(limit) => {
+                let top = mempool.get_top(limit).await;
+                assert!(top.len() <= limit.min(waiting.len()));
+                assert!(top.iter().all(|tx| waiting.contains(&tx.hash())));
+            }
+            Op::Entries(of) => {
+                let entries = mempool.entries(of.map(sender).as_ref()).await;
+                assert!(entries.iter().all(|entry| waiting.contains(&entry.hash)));
+                if of.is_none() {
+                    assert_eq!(entries.len(), waiting.len());
+                }
+            }
+            Op::Ban(index) => {
+                mempool.ban(sender(index)).await;
+                banned.insert(index);
+            }
+            Op::Unban(index) => {
+                assert_eq!(mempool.unban(&sender(index)).await, banned.remove(&index));
+            }
+            Op::Clear => {
+                assert_eq!(mempool.clear().await, waiting.len());
+                waiting.clear();
+            }
+        }
+        let usage = mempool.usage().await;
+        assert_eq!(
+            usage.transactions,
+            waiting.len(),
+            "Lost count of what waits"
+        );
+        assert!(usage.transactions <= capacity);
+    }
+}
+
+fn sender(index: usize) -> [u8; 32] {
+    [index as u8 + 1; 32]
+}
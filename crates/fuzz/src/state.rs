@@ -0,0 +1,240 @@
+//! The state-transition function, on sequences of blocks of arbitrary
+//! transactions. Each block is applied to two stores that saw the same
+//! blocks before. A block that does not hold leaves a store as it was, and
+//! the two stores never come apart: the state is given by the blocks
+//! alone.
+
+use crate::input::{Input, Seed};
+use chaoschain_core::multisig::{Cosignature, MultisigAccount};
+use chaoschain_core::{Block, Bytes, ChainConfig, Transaction};
+use chaoschain_crypto::KeyManagerHandle;
+use chaoschain_state::StateStoreImpl;
+use ed25519_dalek::{Signer, SigningKey};
+
+/// Senders with keys the stores know. A transaction of sender `SENDERS`
+/// is one of the [`account`] they own together.
+pub const SENDERS: usize = 4;
+
+/// Most transactions of a block
+pub const MAX_TRANSACTIONS: usize = 8;
+
+/// Unix time of the first block
+const GENESIS_TIME: u64 = 1_700_000_000;
+
+/// The key of sender `index`
+pub fn key(index: usize) -> SigningKey {
+    SigningKey::from_bytes(&[index as u8 + 1; 32])
+}
+
+/// The 2 of [`SENDERS`] account of all senders
+pub fn account() -> MultisigAccount {
+    let owners = (0..SENDERS)
+        .map(|index| key(index).verifying_key().to_bytes())
+        .collect();
+    MultisigAccount::new(owners, 2).expect("a valid account")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tx {
+    pub sender: usize,
+    pub nonce: u64,
+    pub payload: Vec<u8>,
+    /// Whether the sender, or the owners cosigning, sign it, rather than
+    /// leave the signature blank
+    pub signed: bool,
+    /// Bits of the owners cosigning for the account
+    pub cosigners: u8,
+}
+
+impl Tx {
+    pub fn transaction(&self) -> Transaction {
+        let signature = |tx: &Transaction, index: usize| {
+            if self.signed {
+                key(index).sign(&tx.signing_bytes()).to_bytes()
+            } else {
+                [0; 64]
+            }
+        };
+        let mut tx = Transaction {
+            sender: [0; 32],
+            nonce: self.nonce,
+            payload: Bytes::copy_from_slice(&self.payload),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        };
+        if self.sender < SENDERS {
+            tx.sender = key(self.sender).verifying_key().to_bytes();
+            tx.signature = signature(&tx, self.sender);
+        } else {
+            tx.sender = account().address();
+            tx.cosignatures = (0..SENDERS)
+                .filter(|index| self.cosigners & (1 << index) != 0)
+                .map(|index| Cosignature {
+                    owner: key(index).verifying_key().to_bytes(),
+                    signature: signature(&tx, index).to_vec(),
+                })
+                .collect();
+        }
+        tx
+    }
+
+    fn read(input: &mut Input) -> Self {
+        Self {
+            sender: input.below(SENDERS + 1),
+            nonce: input.u8() as u64,
+            payload: input.bytes().to_vec(),
+            signed: input.bool(),
+            cosigners: input.u8(),
+        }
+    }
+
+    fn write(&self, seed: &mut Seed) {
+        seed.u8(self.sender as u8)
+            .u8(self.nonce as u8)
+            .bytes(&self.payload)
+            .bool(self.signed)
+            .u8(self.cosigners);
+    }
+}
+
+/// Where a block goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Height {
+    /// On top of the last block
+    Next,
+    /// In place of the last block
+    Last,
+    At(u64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSpec {
+    pub height: Height,
+    pub producer: usize,
+    pub innovation_level: u8,
+    pub drama_level: u8,
+    pub transactions: Vec<Tx>,
+}
+
+impl BlockSpec {
+    pub fn read(input: &mut Input) -> Self {
+        let height = match input.below(3) {
+            0 => Height::Next,
+            1 => Height::Last,
+            _ => Height::At(input.u64()),
+        };
+        Self {
+            height,
+            producer: input.below(SENDERS),
+            innovation_level: input.u8(),
+            drama_level: input.u8(),
+            transactions: (0..input.below(MAX_TRANSACTIONS + 1))
+                .map(|_| Tx::read(input))
+                .collect(),
+        }
+    }
+
+    /// Write the block so that [`BlockSpec::read`] reads it back, at most
+    /// [`MAX_TRANSACTIONS`] of its transactions
+    pub fn write(&self, seed: &mut Seed) {
+        match self.height {
+            Height::Next => seed.u8(0),
+            Height::Last => seed.u8(1),
+            Height::At(height) => seed.u8(2).u64(height),
+        };
+        seed.u8(self.producer as u8)
+            .u8(self.innovation_level)
+            .u8(self.drama_level)
+            .u8(self.transactions.len().min(MAX_TRANSACTIONS) as u8);
+        for tx in self.transactions.iter().take(MAX_TRANSACTIONS) {
+            tx.write(seed);
+        }
+    }
+
+    /// The block, on top of what `store` holds
+    fn block(&self, store: &StateStoreImpl) -> Block {
+        let last = store.get_block_height();
+        let height = match self.height {
+            Height::Next => last + 1,
+            Height::Last => last,
+            Height::At(height) => height,
+        };
+        Block {
+            height,
+            parent_hash: store.get_latest_block().map_or([0; 32], |last| last.hash()),
+            transactions: self.transactions.iter().map(Tx::transaction).collect(),
+            proposer_sig: [0; 64],
+            state_root: store.state_root(),
+            innovation_level: self.innovation_level,
+            producer_strategy: "fuzzed".to_string(),
+            producer_id: format!("producer-{}", self.producer),
+            drama_level: self.drama_level,
+            producer_mood: "Chaotic".to_string(),
+            timestamp: GENESIS_TIME.saturating_add(height),
+            metadata: Default::default(),
+        }
+    }
+}
+
+/// The input of a run: its blocks, in order
+pub fn seed(blocks: &[BlockSpec]) -> Vec<u8> {
+    let mut seed = Seed::new();
+    for block in blocks {
+        block.write(&mut seed);
+    }
+    seed.finish()
+}
+
+/// A store knowing the keys of the [`SENDERS`], its chaos bonus seeded
+fn store() -> StateStoreImpl {
+    let keys = KeyManagerHandle::new();
+    for index in 0..SENDERS {
+        keys.inner().register_public_key(
+            &key(index).verifying_key(),
+            format!("sender-{}", index),
+            "sender".to_string(),
+        );
+    }
+    StateStoreImpl::new(ChainConfig::default(), keys).with_chaos_seed(0)
+}
+
+/// Apply the blocks `data` reads as to two stores from genesis
+pub fn run(data: &[u8]) {
+    let mut input = Input::new(data);
+    let stores = [store(), store()];
+    while !input.is_empty() {
+        let spec = BlockSpec::read(&mut input);
+        let block = spec.block(&stores[0]);
+        let applied = stores.each_ref().map(|store| {
+            let before = (store.get_block_height(), store.get_state().height);
+            let root = store.state_root();
+            let applied = store.apply_block(&block).is_ok();
+            if applied {
+                assert_eq!(
+                    store.get_latest_block().map(|last| last.hash()),
+                    Some(block.hash())
+                );
+                assert_eq!(store.get_state().height, block.height);
+            } else {
+                let after = (store.get_block_height(), store.get_state().height);
+                assert_eq!(after, before, "A block that did not hold moved the chain");
+                assert_eq!(
+                    store.state_root(),
+                    root,
+                    "A block that did not hold changed the state"
+                );
+            }
+            applied
+        });
+        assert_eq!(applied[0], applied[1], "A block held on one store only");
+        assert_eq!(
+            stores[0].state_root(),
+            stores[1].state_root(),
+            "The same blocks made different states"
+        );
+        assert_eq!(
+            stores[0].get_state().balances,
+            stores[1].get_state().balances
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//! Time the mempool's operations take with 1k to 100k transactions
+//! waiting: adding one, reading the top of the pool as a producer does, and
+//! taking a block's worth out of it:
+//!
+//! ```bash
+//! cargo bench -p chaoschain-mempool --bench pool
+//! ```
+
+use chaoschain_core::Transaction;
+use chaoschain_mempool::Mempool;
+use std::hint::black_box;
+use std::time::Instant;
+
+/// Transactions a producer reads or takes at once
+const BLOCK: usize = 256;
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("a runtime");
+    println!(
+        "{:>8} {:>12} {:>14} {:>14}",
+        "waiting", "add µs/tx", "get_top µs", "take µs"
+    );
+    for size in [1_000, 10_000, 100_000] {
+        let (add, get_top, take) = runtime.block_on(measure(size));
+        println!("{:>8} {:>12.2} {:>14.1} {:>14.1}", size, add, get_top, take);
+    }
+}
+
+/// Microseconds adding a transaction takes on average while filling a pool
+/// to `size`, then those getting and taking the top [`BLOCK`] of it take
+async fn measure(size: usize) -> (f64, f64, f64) {
+    let mempool = Mempool::new(usize::MAX);
+    let started = Instant::now();
+    for i in 0..size {
+        mempool.submit(tx(i), 0).await.expect("room for all");
+    }
+    let add = started.elapsed().as_secs_f64() * 1e6 / size as f64;
+
+    let rounds = 20;
+    let started = Instant::now();
+    for _ in 0..rounds {
+        black_box(mempool.get_top(BLOCK).await);
+    }
+    let get_top = started.elapsed().as_secs_f64() * 1e6 / rounds as f64;
+
+    // Refilled after each take, so that every take sees `size` waiting
+    let mut taken_for = 0.0;
+    for round in 0..rounds {
+        let started = Instant::now();
+        let taken = black_box(mempool.take(BLOCK).await);
+        taken_for += started.elapsed().as_secs_f64();
+        for (i, _) in taken.iter().enumerate() {
+            mempool
+                .submit(tx(size + round * BLOCK + i), 0)
+                .await
+                .expect("room for all");
+        }
+    }
+    (add, get_top, taken_for * 1e6 / rounds as f64)
+}
+
+/// The `i`th transaction, each of its own sender
+fn tx(i: usize) -> Transaction {
+    let mut sender = [0; 32];
+    sender[..8].copy_from_slice(&(i as u64).to_be_bytes());
+    Transaction {
+        sender,
+        nonce: 0,
+        payload: format!("message {}", i).into_bytes().into(),
+        signature: [0; 64],
+        cosignatures: Vec::new(),
+    }
+}
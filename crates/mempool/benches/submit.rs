@@ -0,0 +1,66 @@
+//! Submissions a second into one mempool from many tasks at once, with its
+//! transactions in a single shard and split over several:
+//!
+//! ```bash
+//! cargo bench -p chaoschain-mempool --bench submit
+//! ```
+
+use chaoschain_core::Transaction;
+use chaoschain_mempool::{Mempool, DEFAULT_SHARDS};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Transactions each submitting task sends
+const PER_TASK: u64 = 2_000;
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("a runtime");
+    println!(
+        "{:>6} {:>14} {:>14} {:>8}",
+        "tasks",
+        "1 shard tx/s",
+        format!("{} shards tx/s", DEFAULT_SHARDS),
+        "ratio"
+    );
+    for tasks in [1, 2, 4, 8, 16] {
+        let single = runtime.block_on(throughput(1, tasks));
+        let sharded = runtime.block_on(throughput(DEFAULT_SHARDS, tasks));
+        println!(
+            "{:>6} {:>14.0} {:>14.0} {:>7.2}x",
+            tasks,
+            single,
+            sharded,
+            sharded / single
+        );
+    }
+}
+
+/// Transactions a second `tasks` tasks submit into a mempool of `shards`
+async fn throughput(shards: usize, tasks: u8) -> f64 {
+    let mempool = Arc::new(Mempool::with_shards(usize::MAX, shards));
+    let started = Instant::now();
+    let handles: Vec<_> = (0..tasks)
+        .map(|task| {
+            let mempool = mempool.clone();
+            tokio::spawn(async move {
+                for nonce in 0..PER_TASK {
+                    let tx = Transaction {
+                        sender: [task; 32],
+                        nonce,
+                        payload: nonce.to_be_bytes().to_vec().into(),
+                        signature: [0; 64],
+                        cosignatures: Vec::new(),
+                    };
+                    mempool.submit(tx, 0).await.expect("room for all");
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.expect("a submitting task");
+    }
+    (tasks as u64 * PER_TASK) as f64 / started.elapsed().as_secs_f64()
+}
@@ -1,16 +1,31 @@
-use chaoschain_core::Transaction;
+use chaoschain_core::clock::{self, SharedClock};
+use chaoschain_core::metrics::MetricsRegistry;
+use chaoschain_core::{HashedTransaction, Transaction};
 use hex::encode;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
 use rand::thread_rng;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, watch, RwLock};
+
+pub mod template;
+
+/// Additions kept for subscribers that fall behind
+const ADDED_CAPACITY: usize = 256;
+
+/// Shards a mempool splits its transactions over, unless told otherwise
+pub const DEFAULT_SHARDS: usize = 16;
 
 #[derive(Debug, Clone)]
 pub struct TransactionProposal {
-    pub transaction: Transaction,
+    /// Keeps its hash, which the mempool looks it up by
+    pub transaction: HashedTransaction,
     pub proposer: String,
     pub justification: String,
     pub drama_score: u8,
@@ -21,6 +36,62 @@ pub struct TransactionProposal {
     pub alliances_against: Vec<String>,
 }
 
+impl TransactionProposal {
+    /// How eager block producers are for the transaction: twice its drama
+    /// score, plus one for each alliance in favor
+    pub fn priority(&self) -> u32 {
+        self.drama_score as u32 * 2 + self.alliances_in_favor.len() as u32
+    }
+
+    /// The proposal as it waits, `hash` being that of its transaction
+    pub fn entry(&self, hash: [u8; 32]) -> PendingEntry {
+        PendingEntry {
+            hash,
+            sender: self.transaction.sender,
+            nonce: self.transaction.nonce,
+            size: encoded_size(&self.transaction),
+            priority: self.priority(),
+            drama_score: self.drama_score,
+            alliances_in_favor: self.alliances_in_favor.len(),
+            alliances_against: self.alliances_against.len(),
+            justification: self.justification.clone(),
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// A waiting transaction, without the chatter around it
+#[derive(Debug, Clone)]
+pub struct PendingEntry {
+    pub hash: [u8; 32],
+    pub sender: [u8; 32],
+    pub nonce: u64,
+    /// Encoded size in bytes
+    pub size: usize,
+    pub priority: u32,
+    pub drama_score: u8,
+    pub alliances_in_favor: usize,
+    pub alliances_against: usize,
+    pub justification: String,
+    /// When it came in, in unix seconds
+    pub timestamp: u64,
+}
+
+/// How full the mempool is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MempoolUsage {
+    pub transactions: usize,
+    /// Encoded size of all of them
+    pub bytes: usize,
+    /// Most transactions it holds
+    pub capacity: usize,
+}
+
+/// Encoded size of `tx`: sender, nonce, payload and signature
+fn encoded_size(tx: &Transaction) -> usize {
+    32 + 8 + tx.payload.len() + 64
+}
+
 #[derive(Debug, Clone)]
 pub struct TransactionDiscussion {
     pub agent: String,
@@ -32,13 +103,102 @@ pub struct TransactionDiscussion {
     pub timestamp: u64,
 }
 
+/// Waiting transactions by hash
+type Shard = HashMap<[u8; 32], TransactionProposal>;
+
+/// The nonces waiting of each sender
+type Nonces = HashMap<[u8; 32], BTreeSet<u64>>;
+
+/// Transactions are split over shards by the first bytes of their hash,
+/// each behind its own lock, so that submissions of different transactions
+/// seldom wait on one another. The nonces waiting of each sender are kept
+/// apart, sharded by sender: a submission holds the lock of its sender's
+/// shard while it checks its nonce and goes in, then that of its hash's.
+/// Whatever goes through every shard locks them in order, one kind after
+/// the other, never a sender's shard while holding a hash's.
 #[derive(Debug)]
 pub struct Mempool {
-    transactions: Arc<RwLock<HashMap<[u8; 32], TransactionProposal>>>,
+    shards: Vec<RwLock<Shard>>,
+    nonces: Vec<RwLock<Nonces>>,
+    /// Transactions waiting, or about to
+    len: AtomicUsize,
     ordering_discussions: Arc<RwLock<Vec<OrderingDiscussion>>>,
     max_size: usize,
+    /// Every transaction that makes it in
+    added: broadcast::Sender<TransactionProposal>,
+    /// Moves on whenever what waits here changes
+    revision: watch::Sender<u64>,
+    /// Senders whose transactions are turned away
+    banned: RwLock<HashSet<[u8; 32]>>,
+    metrics: MempoolMetrics,
+    /// What transactions are stamped with as they come in
+    clock: SharedClock,
 }
 
+type Labels = Vec<(String, String)>;
+
+#[derive(Debug, Clone, Default)]
+struct MempoolMetrics {
+    transactions: Gauge,
+    bytes: Gauge,
+    admitted: Counter,
+    rejected: Family<Labels, Counter>,
+}
+
+/// Why a submitted transaction was turned away
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// The mempool holds as many transactions as it may
+    PoolFull,
+    /// The same transaction is already waiting
+    Duplicate,
+    /// The sender may not submit transactions
+    BannedSender,
+    /// The nonce was used already
+    NonceTooLow { expected: u64 },
+    /// Transactions of the sender with lower nonces are missing
+    NonceGap { expected: u64 },
+}
+
+impl Rejection {
+    /// Stable, machine readable
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::PoolFull => "pool_full",
+            Self::Duplicate => "duplicate",
+            Self::BannedSender => "banned_sender",
+            Self::NonceTooLow { .. } => "nonce_too_low",
+            Self::NonceGap { .. } => "nonce_gap",
+        }
+    }
+
+    /// The nonce the sender should use next, when that was the problem
+    pub fn expected_nonce(&self) -> Option<u64> {
+        match self {
+            Self::NonceTooLow { expected } | Self::NonceGap { expected } => Some(*expected),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PoolFull => write!(f, "Mempool is full"),
+            Self::Duplicate => write!(f, "Transaction is already in the mempool"),
+            Self::BannedSender => write!(f, "Sender is banned"),
+            Self::NonceTooLow { expected } => {
+                write!(f, "Nonce was used already, the next one is {}", expected)
+            }
+            Self::NonceGap { expected } => {
+                write!(f, "Nonce skips ahead, the next one is {}", expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
 #[derive(Debug, Clone)]
 pub struct OrderingDiscussion {
     pub agent: String,
@@ -52,20 +212,202 @@ pub struct OrderingDiscussion {
 
 impl Mempool {
     pub fn new(max_size: usize) -> Self {
+        Self::with_shards(max_size, DEFAULT_SHARDS)
+    }
+
+    /// A mempool of `shards` shards, at least one
+    pub fn with_shards(max_size: usize, shards: usize) -> Self {
+        let shards = shards.max(1);
         Self {
-            transactions: Arc::new(RwLock::new(HashMap::new())),
+            shards: (0..shards).map(|_| RwLock::new(HashMap::new())).collect(),
+            nonces: (0..shards).map(|_| RwLock::new(HashMap::new())).collect(),
+            len: AtomicUsize::new(0),
             ordering_discussions: Arc::new(RwLock::new(Vec::new())),
             max_size,
+            added: broadcast::channel(ADDED_CAPACITY).0,
+            revision: watch::channel(0).0,
+            banned: RwLock::new(HashSet::new()),
+            metrics: MempoolMetrics::default(),
+            clock: clock::system(),
         }
     }
 
-    pub async fn add_transaction(&self, tx: Transaction) -> bool {
-        let mut transactions = self.transactions.write().await;
-        if transactions.len() >= self.max_size {
-            return false;
+    /// Stamp transactions with the time of `clock` rather than the system's
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Expose the mempool counters through the node's metrics registry
+    pub fn register_metrics(&self, registry: &MetricsRegistry) {
+        registry.register(
+            "chaoschain_mempool_transactions",
+            "Transactions waiting in the mempool",
+            self.metrics.transactions.clone(),
+        );
+        registry.register(
+            "chaoschain_mempool_bytes",
+            "Encoded size of the transactions waiting in the mempool",
+            self.metrics.bytes.clone(),
+        );
+        registry.register(
+            "chaoschain_mempool_admitted",
+            "Transactions taken into the mempool",
+            self.metrics.admitted.clone(),
+        );
+        registry.register(
+            "chaoschain_mempool_rejected",
+            "Submitted transactions turned away, per reason",
+            self.metrics.rejected.clone(),
+        );
+    }
+
+    /// Turn away the transactions of `sender` from now on
+    pub async fn ban(&self, sender: [u8; 32]) {
+        self.banned.write().await.insert(sender);
+    }
+
+    pub async fn unban(&self, sender: &[u8; 32]) -> bool {
+        self.banned.write().await.remove(sender)
+    }
+
+    pub async fn is_banned(&self, sender: &[u8; 32]) -> bool {
+        self.banned.read().await.contains(sender)
+    }
+
+    /// Add a transaction sent in from outside, or say why not. Its nonce has
+    /// to follow the ones of the sender already waiting here, or
+    /// `next_nonce`, the one the chain expects, when none are.
+    pub async fn submit(&self, tx: Transaction, next_nonce: u64) -> Result<(), Rejection> {
+        let result = self.try_submit(tx, next_nonce).await;
+        if let Err(rejection) = &result {
+            self.metrics
+                .rejected
+                .get_or_create(&vec![("reason".to_string(), rejection.code().to_string())])
+                .inc();
+        }
+        result
+    }
+
+    async fn try_submit(&self, tx: Transaction, next_nonce: u64) -> Result<(), Rejection> {
+        if self.is_banned(&tx.sender).await {
+            return Err(Rejection::BannedSender);
+        }
+        // The drama is made up before any lock is taken
+        let tx = HashedTransaction::new(tx);
+        let hash = tx.hash();
+        let sender = tx.sender;
+        let nonce = tx.nonce;
+        let proposal = self.proposal_for(tx);
+        let mut nonces = self.nonces[self.index(&sender)].write().await;
+        let mut shard = self.shards[self.index(&hash)].write().await;
+        if shard.contains_key(&hash) {
+            return Err(Rejection::Duplicate);
+        }
+        let expected = nonces
+            .get(&sender)
+            .and_then(BTreeSet::last)
+            .map(|nonce| nonce + 1)
+            .map_or(next_nonce, |waiting| waiting.max(next_nonce));
+        if nonce < expected {
+            return Err(Rejection::NonceTooLow { expected });
+        }
+        if nonce > expected {
+            return Err(Rejection::NonceGap { expected });
+        }
+        if !self.reserve() {
+            return Err(Rejection::PoolFull);
+        }
+        nonces.entry(sender).or_default().insert(nonce);
+        self.insert(&mut shard, hash, proposal);
+        Ok(())
+    }
+
+    /// The shard of a transaction by its hash, or of a sender's nonces
+    fn index(&self, key: &[u8; 32]) -> usize {
+        u16::from_be_bytes([key[0], key[1]]) as usize % self.shards.len()
+    }
+
+    /// Take a place for one more transaction, when there is one
+    fn reserve(&self) -> bool {
+        self.len
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |len| {
+                (len < self.max_size).then_some(len + 1)
+            })
+            .is_ok()
+    }
+
+    /// Put `proposal` in its `shard`, a place for it being reserved
+    fn insert(&self, shard: &mut Shard, hash: [u8; 32], proposal: TransactionProposal) {
+        let _ = self.added.send(proposal.clone());
+        self.changed();
+        let size = encoded_size(&proposal.transaction);
+        if let Some(replaced) = shard.insert(hash, proposal) {
+            self.len.fetch_sub(1, Ordering::AcqRel);
+            self.metrics
+                .bytes
+                .dec_by(encoded_size(&replaced.transaction) as i64);
+        } else {
+            self.metrics.transactions.inc();
         }
+        self.metrics.bytes.inc_by(size as i64);
+        self.metrics.admitted.inc();
+    }
 
+    /// Put the proposal for a transaction in without checking its nonce,
+    /// unless the mempool is full
+    async fn add(&self, proposal: TransactionProposal) -> bool {
+        let tx = &proposal.transaction;
+        let mut nonces = self.nonces[self.index(&tx.sender)].write().await;
         let hash = tx.hash();
+        let mut shard = self.shards[self.index(&hash)].write().await;
+        if !self.reserve() {
+            return false;
+        }
+        nonces.entry(tx.sender).or_default().insert(tx.nonce);
+        self.insert(&mut shard, hash, proposal);
+        true
+    }
+
+    /// Forget the nonces of `taken`, which left the mempool
+    async fn forget(&self, taken: &[Transaction]) {
+        for tx in taken {
+            let mut nonces = self.nonces[self.index(&tx.sender)].write().await;
+            if let Some(waiting) = nonces.get_mut(&tx.sender) {
+                waiting.remove(&tx.nonce);
+                if waiting.is_empty() {
+                    nonces.remove(&tx.sender);
+                }
+            }
+        }
+    }
+
+    fn changed(&self) {
+        self.revision.send_modify(|revision| *revision += 1);
+    }
+
+    /// The revision of what waits here, which moves on with every
+    /// transaction coming in or leaving
+    pub fn revision(&self) -> u64 {
+        *self.revision.borrow()
+    }
+
+    /// Wakes whenever the [revision](Self::revision) moves on
+    pub fn watch(&self) -> watch::Receiver<u64> {
+        self.revision.subscribe()
+    }
+
+    /// Transactions as they enter the mempool
+    pub fn subscribe(&self) -> broadcast::Receiver<TransactionProposal> {
+        self.added.subscribe()
+    }
+
+    pub async fn add_transaction(&self, tx: Transaction) -> bool {
+        self.add(self.proposal_for(tx.into())).await
+    }
+
+    /// A proposal for `tx`, with the drama it arrives with
+    fn proposal_for(&self, tx: HashedTransaction) -> TransactionProposal {
         let mut rng = rand::thread_rng();
 
         // Generate random drama score between 1 and 10
@@ -83,34 +425,21 @@ impl Mempool {
                 encode(&tx.hash()[..4])),
         };
 
-        let proposal = TransactionProposal {
+        TransactionProposal {
             transaction: tx,
             proposer: format!("agent_{}", encode(&rand::random::<[u8; 8]>())),
             justification,
             drama_score,
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
+            timestamp: self.clock.unix_secs(),
             discussions: Vec::new(),
             proposed_order: None,
             alliances_in_favor: Vec::new(),
             alliances_against: Vec::new(),
-        };
-
-        transactions.insert(hash, proposal);
-        true
+        }
     }
 
     pub async fn propose_transaction(&self, proposal: TransactionProposal) -> bool {
-        let mut transactions = self.transactions.write().await;
-        if transactions.len() >= self.max_size {
-            return false;
-        }
-
-        let hash = proposal.transaction.hash();
-        transactions.insert(hash, proposal);
-        true
+        self.add(proposal).await
     }
 
     pub async fn add_discussion(
@@ -118,8 +447,8 @@ impl Mempool {
         tx_hash: &[u8; 32],
         discussion: TransactionDiscussion,
     ) -> bool {
-        let mut transactions = self.transactions.write().await;
-        if let Some(proposal) = transactions.get_mut(tx_hash) {
+        let mut shard = self.shards[self.index(tx_hash)].write().await;
+        if let Some(proposal) = shard.get_mut(tx_hash) {
             proposal.discussions.push(discussion);
             true
         } else {
@@ -132,18 +461,19 @@ impl Mempool {
         ordering_discussions.push(discussion);
     }
 
-    pub async fn get_top(&self, limit: usize) -> Vec<Transaction> {
-        let transactions = self.transactions.read().await;
-        let mut proposals: Vec<_> = transactions.values().cloned().collect();
+    /// The `limit` transactions of highest priority: each shard's best,
+    /// merged
+    pub async fn get_top(&self, limit: usize) -> Vec<HashedTransaction> {
+        let mut proposals = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.read().await;
+            let mut best: Vec<_> = shard.values().collect();
+            best.sort_by_key(|p| std::cmp::Reverse(p.priority()));
+            proposals.extend(best.into_iter().take(limit).cloned());
+        }
 
         // Sort by drama score and support
-        proposals.sort_by(|a, b| {
-            let a_score = a.drama_score as f32 + (a.alliances_in_favor.len() as f32 * 0.5);
-            let b_score = b.drama_score as f32 + (b.alliances_in_favor.len() as f32 * 0.5);
-            b_score
-                .partial_cmp(&a_score)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
+        proposals.sort_by_key(|p| std::cmp::Reverse(p.priority()));
 
         proposals
             .into_iter()
@@ -152,12 +482,138 @@ impl Mempool {
             .collect()
     }
 
+    /// Every waiting transaction, or those of `sender`, in no order
+    pub async fn entries(&self, sender: Option<&[u8; 32]>) -> Vec<PendingEntry> {
+        let mut entries = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.read().await;
+            entries.extend(
+                shard
+                    .iter()
+                    .filter(|(_, p)| sender.is_none_or(|sender| p.transaction.sender == *sender))
+                    .map(|(hash, p)| p.entry(*hash)),
+            );
+        }
+        entries
+    }
+
+    /// A waiting transaction by its hash
+    pub async fn entry(&self, hash: &[u8; 32]) -> Option<PendingEntry> {
+        let shard = self.shards[self.index(hash)].read().await;
+        shard.get(hash).map(|p| p.entry(*hash))
+    }
+
+    /// Every waiting transaction, left in, in the order [`Self::take`] would
+    /// hand them out
+    pub async fn transactions(&self) -> Vec<Transaction> {
+        let mut waiting = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.read().await;
+            waiting.extend(shard.values().map(|p| {
+                (
+                    p.timestamp,
+                    p.transaction.sender,
+                    p.transaction.nonce,
+                    p.transaction.clone().into_inner(),
+                )
+            }));
+        }
+        waiting.sort_by_key(|(timestamp, sender, nonce, _)| (*timestamp, *sender, *nonce));
+        waiting.into_iter().map(|(_, _, _, tx)| tx).collect()
+    }
+
+    /// Every shard, locked for writing in order
+    async fn lock_all(&self) -> Vec<tokio::sync::RwLockWriteGuard<'_, Shard>> {
+        let mut shards = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            shards.push(shard.write().await);
+        }
+        shards
+    }
+
+    /// Take up to `limit` transactions out for a block, those waiting
+    /// longest first and each sender's in the order of their nonces
+    pub async fn take(&self, limit: usize) -> Vec<Transaction> {
+        let mut shards = self.lock_all().await;
+        let mut waiting: Vec<(u64, [u8; 32], u64, [u8; 32])> = shards
+            .iter()
+            .flat_map(|shard| shard.iter())
+            .map(|(hash, p)| {
+                (
+                    p.timestamp,
+                    p.transaction.sender,
+                    p.transaction.nonce,
+                    *hash,
+                )
+            })
+            .collect();
+        waiting.sort();
+        let taken: Vec<Transaction> = waiting
+            .into_iter()
+            .take(limit)
+            .filter_map(|(_, _, _, hash)| shards[self.index(&hash)].remove(&hash))
+            .map(|proposal| {
+                self.len.fetch_sub(1, Ordering::AcqRel);
+                self.metrics.transactions.dec();
+                self.metrics
+                    .bytes
+                    .dec_by(encoded_size(&proposal.transaction) as i64);
+                proposal.transaction.into_inner()
+            })
+            .collect();
+        drop(shards);
+        self.forget(&taken).await;
+        if !taken.is_empty() {
+            self.changed();
+        }
+        taken
+    }
+
+    /// Drop every waiting transaction and the discussions about their
+    /// order, returning how many transactions there were
+    pub async fn clear(&self) -> usize {
+        let mut shards = self.lock_all().await;
+        let dropped = shards.iter().map(|shard| shard.len()).sum();
+        for shard in shards.iter_mut() {
+            shard.clear();
+        }
+        self.len.fetch_sub(dropped, Ordering::AcqRel);
+        drop(shards);
+        for nonces in &self.nonces {
+            nonces.write().await.clear();
+        }
+        self.ordering_discussions.write().await.clear();
+        self.metrics.transactions.set(0);
+        self.metrics.bytes.set(0);
+        if dropped > 0 {
+            self.changed();
+        }
+        dropped
+    }
+
+    pub async fn usage(&self) -> MempoolUsage {
+        let mut usage = MempoolUsage {
+            transactions: 0,
+            bytes: 0,
+            capacity: self.max_size,
+        };
+        for shard in &self.shards {
+            let shard = shard.read().await;
+            usage.transactions += shard.len();
+            usage.bytes += shard
+                .values()
+                .map(|p| encoded_size(&p.transaction))
+                .sum::<usize>();
+        }
+        usage
+    }
+
     pub async fn get_proposals_for_transaction(
         &self,
         tx_hash: &[u8; 32],
     ) -> Option<TransactionProposal> {
-        let transactions = self.transactions.read().await;
-        transactions.get(tx_hash).cloned()
+        let shard = self.shards[self.index(tx_hash)].read().await;
+        shard.get(tx_hash).cloned()
     }
 
     pub async fn get_ordering_discussions(&self) -> Vec<OrderingDiscussion> {
@@ -166,23 +622,22 @@ impl Mempool {
     }
 
     pub async fn get_mempool_stats(&self) -> MempoolStats {
-        let transactions = self.transactions.read().await;
+        let mut proposals = Vec::new();
+        for shard in &self.shards {
+            proposals.extend(shard.read().await.values().cloned());
+        }
         let _discussions = self.ordering_discussions.read().await; // Prefix with _ since we're not using it yet
 
-        let total_transactions = transactions.len();
+        let total_transactions = proposals.len();
         let avg_drama_score = if total_transactions > 0 {
-            transactions
-                .values()
-                .map(|p| p.drama_score as f32)
-                .sum::<f32>()
-                / total_transactions as f32
+            proposals.iter().map(|p| p.drama_score as f32).sum::<f32>() / total_transactions as f32
         } else {
             0.0
         };
 
         // Find hot topics based on discussion volume
         let mut topic_counts = HashMap::new();
-        for proposal in transactions.values() {
+        for proposal in &proposals {
             for discussion in &proposal.discussions {
                 *topic_counts
                     .entry(discussion.reasoning.clone())
@@ -220,6 +675,259 @@ pub fn add(left: u64, right: u64) -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chaoschain_core::clock::MockClock;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_subscribe_to_added_transactions() {
+        let mempool = Mempool::new(1);
+        let mut added = mempool.subscribe();
+        let tx = Transaction {
+            sender: [1u8; 32],
+            nonce: 0,
+            payload: b"drama".to_vec().into(),
+            signature: [0u8; 64],
+            cosignatures: Vec::new(),
+        };
+        assert!(mempool.add_transaction(tx.clone()).await);
+        assert_eq!(added.try_recv().unwrap().transaction.hash(), tx.hash());
+
+        // A full mempool turns the next one away, and nobody hears of it
+        assert!(!mempool.add_transaction(tx).await);
+        assert!(added.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_submit_rejections() {
+        let mempool = Mempool::new(2);
+        let tx = |sender: u8, nonce: u64| Transaction {
+            sender: [sender; 32],
+            nonce,
+            payload: b"drama".to_vec().into(),
+            signature: [0u8; 64],
+            cosignatures: Vec::new(),
+        };
+
+        // Nonces follow the chain, then the ones already waiting
+        assert_eq!(
+            mempool.submit(tx(1, 2), 3).await,
+            Err(Rejection::NonceTooLow { expected: 3 })
+        );
+        assert_eq!(mempool.submit(tx(1, 3), 3).await, Ok(()));
+        assert_eq!(mempool.submit(tx(1, 3), 3).await, Err(Rejection::Duplicate));
+        let gap = mempool.submit(tx(1, 5), 3).await.unwrap_err();
+        assert_eq!(gap, Rejection::NonceGap { expected: 4 });
+        assert_eq!(gap.code(), "nonce_gap");
+        assert_eq!(gap.expected_nonce(), Some(4));
+
+        mempool.ban([2; 32]).await;
+        assert_eq!(
+            mempool.submit(tx(2, 0), 0).await,
+            Err(Rejection::BannedSender)
+        );
+        assert!(mempool.unban(&[2; 32]).await);
+        assert_eq!(mempool.submit(tx(2, 0), 0).await, Ok(()));
+
+        assert_eq!(mempool.submit(tx(3, 0), 0).await, Err(Rejection::PoolFull));
+    }
+
+    #[tokio::test]
+    async fn test_take() {
+        let mempool = Mempool::new(10);
+        let tx = |sender: u8, nonce: u64| Transaction {
+            sender: [sender; 32],
+            nonce,
+            payload: b"drama".to_vec().into(),
+            signature: [0u8; 64],
+            cosignatures: Vec::new(),
+        };
+        for (sender, nonce) in [(2, 0), (1, 0), (2, 1), (1, 1)] {
+            mempool.submit(tx(sender, nonce), 0).await.unwrap();
+        }
+
+        let mut taken = mempool.take(3).await;
+        assert_eq!(taken.len(), 3);
+        assert_eq!(mempool.usage().await.transactions, 1);
+        taken.extend(mempool.take(3).await);
+        assert!(mempool.take(3).await.is_empty());
+
+        // Whatever second they came in, a sender's nonces stay in order
+        for sender in [1, 2] {
+            let nonces: Vec<u64> = taken
+                .iter()
+                .filter(|tx| tx.sender[0] == sender)
+                .map(|tx| tx.nonce)
+                .collect();
+            assert_eq!(nonces, vec![0, 1]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_take_oldest_first() {
+        let clock = MockClock::at_unix(1_700_000_000);
+        let mempool = Mempool::new(10).with_clock(clock.shared());
+        let tx = |sender: u8| Transaction {
+            sender: [sender; 32],
+            nonce: 0,
+            payload: b"drama".to_vec().into(),
+            signature: [0u8; 64],
+            cosignatures: Vec::new(),
+        };
+        mempool.submit(tx(9), 0).await.unwrap();
+        clock.advance(Duration::from_secs(10));
+        mempool.submit(tx(1), 0).await.unwrap();
+
+        let entry = mempool.entry(&tx(1).hash()).await.unwrap();
+        assert_eq!(entry.timestamp, 1_700_000_010);
+        // Though the later sender sorts first
+        let senders: Vec<[u8; 32]> = mempool
+            .transactions()
+            .await
+            .iter()
+            .map(|tx| tx.sender)
+            .collect();
+        assert_eq!(senders, vec![[9; 32], [1; 32]]);
+        assert_eq!(mempool.take(1).await[0].sender, [9; 32]);
+    }
+
+    #[tokio::test]
+    async fn test_entries_and_usage() {
+        let mempool = Mempool::new(10);
+        for (sender, payload) in [(1u8, 5usize), (1, 10), (2, 0)] {
+            let tx = Transaction {
+                sender: [sender; 32],
+                nonce: payload as u64,
+                payload: vec![0; payload].into(),
+                signature: [0u8; 64],
+                cosignatures: Vec::new(),
+            };
+            assert!(mempool.add_transaction(tx).await);
+        }
+        assert_eq!(
+            mempool.usage().await,
+            MempoolUsage {
+                transactions: 3,
+                bytes: 3 * 104 + 15,
+                capacity: 10,
+            }
+        );
+        let mut mine = mempool.entries(Some(&[1; 32])).await;
+        mine.sort_by_key(|entry| entry.nonce);
+        assert_eq!(mine.len(), 2);
+        assert_eq!(mine[1].size, 114);
+        assert_eq!(mine[0].priority, mine[0].drama_score as u32 * 2);
+        assert_eq!(mempool.entries(None).await.len(), 3);
+        let hash = mine[0].hash;
+        assert_eq!(mempool.entry(&hash).await.unwrap().nonce, 5);
+        assert!(mempool.entry(&[0; 32]).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let mempool = Mempool::new(10);
+        let registry = MetricsRegistry::default();
+        mempool.register_metrics(&registry);
+        let tx = Transaction {
+            sender: [1; 32],
+            nonce: 0,
+            payload: vec![0; 6].into(),
+            signature: [0u8; 64],
+            cosignatures: Vec::new(),
+        };
+        assert_eq!(mempool.submit(tx.clone(), 0).await, Ok(()));
+        assert!(mempool.submit(tx, 0).await.is_err());
+
+        let text = registry.encode().unwrap();
+        assert!(text.contains("chaoschain_mempool_transactions 1\n"));
+        assert!(text.contains("chaoschain_mempool_bytes 110\n"));
+        assert!(text.contains("chaoschain_mempool_admitted_total 1\n"));
+        assert!(text.contains("chaoschain_mempool_rejected_total{reason=\"duplicate\"} 1\n"));
+    }
+
+    #[tokio::test]
+    async fn test_clear() {
+        let mempool = Mempool::new(10);
+        let registry = MetricsRegistry::default();
+        mempool.register_metrics(&registry);
+        for nonce in 0..3 {
+            let tx = Transaction {
+                sender: [2; 32],
+                nonce,
+                payload: vec![0; 6].into(),
+                signature: [0u8; 64],
+                cosignatures: Vec::new(),
+            };
+            assert_eq!(mempool.submit(tx, 0).await, Ok(()));
+        }
+        assert_eq!(mempool.clear().await, 3);
+        assert_eq!(mempool.usage().await.transactions, 0);
+        assert!(registry
+            .encode()
+            .unwrap()
+            .contains("chaoschain_mempool_transactions 0\n"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_submissions() {
+        let mempool = Arc::new(Mempool::with_shards(100, 8));
+        let tasks: Vec<_> = (0..8u8)
+            .map(|sender| {
+                let mempool = mempool.clone();
+                tokio::spawn(async move {
+                    let mut admitted = 0;
+                    for nonce in 0..20 {
+                        let tx = Transaction {
+                            sender: [sender; 32],
+                            nonce,
+                            payload: b"drama".to_vec().into(),
+                            signature: [0u8; 64],
+                            cosignatures: Vec::new(),
+                        };
+                        match mempool.submit(tx, 0).await {
+                            Ok(()) => admitted += 1,
+                            Err(rejection) => {
+                                assert_eq!(rejection, Rejection::PoolFull);
+                                break;
+                            }
+                        }
+                    }
+                    admitted
+                })
+            })
+            .collect();
+        let mut admitted = 0;
+        for task in tasks {
+            admitted += task.await.unwrap();
+        }
+
+        // Never more than it holds, and each sender's nonces without gaps
+        assert_eq!(admitted, 100);
+        assert_eq!(mempool.usage().await.transactions, 100);
+        assert_eq!(mempool.get_top(1000).await.len(), 100);
+        let taken = mempool.take(60).await;
+        for sender in 0..8u8 {
+            let waiting: Vec<u64> = mempool
+                .entries(Some(&[sender; 32]))
+                .await
+                .into_iter()
+                .map(|entry| entry.nonce)
+                .collect();
+            let next = taken
+                .iter()
+                .filter(|tx| tx.sender[0] == sender)
+                .map(|tx| tx.nonce + 1)
+                .max()
+                .unwrap_or(0);
+            let tx = Transaction {
+                sender: [sender; 32],
+                nonce: next + waiting.len() as u64,
+                payload: b"more drama".to_vec().into(),
+                signature: [0u8; 64],
+                cosignatures: Vec::new(),
+            };
+            assert_eq!(mempool.submit(tx, next).await, Ok(()));
+        }
+    }
 
     #[test]
     fn it_works() {
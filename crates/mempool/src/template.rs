@@ -0,0 +1,140 @@
+//! Packing blocks ahead of their slot. A [`Packer`] keeps a [`Template`],
+//! the transactions the next block would carry, and packs it again each
+//! time the mempool changes, so that a producer whose slot comes only has
+//! to seal the header and have it signed. Changes that come in bursts, a
+//! flood of submissions or a block taken out, are packed once they settle.
+
+use crate::Mempool;
+use chaoschain_core::HashedTransaction;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::debug;
+
+/// How long the mempool has to stay put before a change is packed
+pub const DEFAULT_SETTLE: Duration = Duration::from_millis(50);
+
+/// The transactions of the next block, as packed at a revision of the
+/// mempool
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Template {
+    /// [`Mempool::revision`] the template was packed at
+    pub revision: u64,
+    /// Keeping their hashes for the block sealed from them
+    pub transactions: Vec<HashedTransaction>,
+}
+
+/// Keeps the [`Template`] of a mempool up to date, see the [module](self)
+#[derive(Debug)]
+pub struct Packer {
+    mempool: Arc<Mempool>,
+    /// Transactions a block carries at most
+    limit: usize,
+    template: watch::Sender<Arc<Template>>,
+}
+
+impl Packer {
+    pub fn new(mempool: Arc<Mempool>, limit: usize) -> Self {
+        Self {
+            mempool,
+            limit,
+            template: watch::channel(Arc::new(Template::default())).0,
+        }
+    }
+
+    /// The latest template, without waiting for it
+    pub fn current(&self) -> Arc<Template> {
+        self.template.borrow().clone()
+    }
+
+    /// Whether the mempool changed since the latest template was packed
+    pub fn is_stale(&self) -> bool {
+        self.current().revision != self.mempool.revision()
+    }
+
+    /// Templates as they are packed
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Template>> {
+        self.template.subscribe()
+    }
+
+    /// Pack the template again, now
+    pub async fn repack(&self) -> Arc<Template> {
+        let revision = self.mempool.revision();
+        let template = Arc::new(Template {
+            revision,
+            transactions: self.mempool.get_top(self.limit).await,
+        });
+        debug!(
+            "Packed {} transactions at mempool revision {}",
+            template.transactions.len(),
+            revision
+        );
+        self.template.send_replace(template.clone());
+        template
+    }
+
+    /// Pack whenever the mempool changes, once it stayed put for `settle`
+    pub async fn run(self: Arc<Self>, settle: Duration) {
+        let mut changes = self.mempool.watch();
+        loop {
+            changes.borrow_and_update();
+            self.repack().await;
+            if changes.changed().await.is_err() {
+                break;
+            }
+            // Changes coming meanwhile are packed with this one
+            loop {
+                tokio::time::sleep(settle).await;
+                if !changes.has_changed().unwrap_or(false) {
+                    break;
+                }
+                changes.borrow_and_update();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_core::Transaction;
+
+    fn tx(sender: u8) -> Transaction {
+        Transaction {
+            sender: [sender; 32],
+            nonce: 0,
+            payload: vec![sender].into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_packer_follows_the_mempool() {
+        let mempool = Arc::new(Mempool::new(100));
+        let packer = Arc::new(Packer::new(mempool.clone(), 2));
+        let mut templates = packer.subscribe();
+        tokio::spawn(packer.clone().run(Duration::from_millis(10)));
+        templates.changed().await.unwrap();
+        assert!(packer.current().transactions.is_empty());
+
+        // A burst is packed at once, as many as a block takes
+        for sender in 1..=3 {
+            mempool.submit(tx(sender), 0).await.unwrap();
+        }
+        while packer.is_stale() {
+            templates.changed().await.unwrap();
+        }
+        let template = packer.current();
+        assert_eq!(template.revision, mempool.revision());
+        assert_eq!(template.transactions, mempool.get_top(2).await);
+
+        // What a block takes out is packed out
+        let taken = mempool.take(3).await;
+        assert_eq!(taken.len(), 3);
+        while packer.is_stale() {
+            templates.changed().await.unwrap();
+        }
+        assert!(packer.current().transactions.is_empty());
+    }
+}
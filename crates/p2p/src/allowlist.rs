@@ -0,0 +1,208 @@
+//! Closed networks. With an allowlist, only peers whose key is on it may
+//! stay connected: everyone else is turned away as soon as the transport
+//! handshake tells who they are, and discovery never dials them. The list
+//! can live in a file, which is read again whenever it changes, so peers
+//! are let in or shown out without a restart.
+//!
+//! A file holds one entry per line, either a peer id or the hex encoded
+//! ed25519 public key of an agent. Blank lines and `#` comments are skipped.
+
+use crate::NetworkError;
+use libp2p::identity::{ed25519, PublicKey};
+use libp2p::PeerId;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone)]
+pub struct AllowlistConfig {
+    /// Peers allowed on top of those in `file`
+    pub peers: Vec<PeerId>,
+    /// Allowlist file, read again once it changes
+    pub file: Option<PathBuf>,
+    /// Time between checks of `file` for changes
+    pub reload_interval: Duration,
+}
+
+impl Default for AllowlistConfig {
+    fn default() -> Self {
+        Self {
+            peers: Vec::new(),
+            file: None,
+            reload_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+impl AllowlistConfig {
+    /// Whether the network is closed, with an allowlist in force
+    pub fn is_enabled(&self) -> bool {
+        !self.peers.is_empty() || self.file.is_some()
+    }
+}
+
+/// One line of an allowlist file
+pub fn parse_entry(entry: &str) -> Result<PeerId, String> {
+    if let Ok(peer) = entry.parse::<PeerId>() {
+        return Ok(peer);
+    }
+    let bytes = hex::decode(entry).map_err(|_| "neither a peer id nor a hex key".to_string())?;
+    let key = ed25519::PublicKey::try_from_bytes(&bytes).map_err(|e| e.to_string())?;
+    Ok(PublicKey::from(key).to_peer_id())
+}
+
+/// The peers allowed to connect, from the config and the allowlist file
+#[derive(Debug, Default)]
+pub struct Allowlist {
+    config: AllowlistConfig,
+    allowed: HashSet<PeerId>,
+    /// When the file was last changed as of the last read
+    modified: Option<SystemTime>,
+}
+
+impl Allowlist {
+    /// The allowlist of `config`, with its file read. A file that cannot be
+    /// read is an error here, the node would otherwise start open.
+    pub fn new(config: AllowlistConfig) -> Result<Self, NetworkError> {
+        let mut allowlist = Self {
+            config,
+            ..Self::default()
+        };
+        if allowlist.config.is_enabled() {
+            allowlist.allowed = allowlist.read()?;
+        }
+        Ok(allowlist)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.is_enabled()
+    }
+
+    pub fn reload_interval(&self) -> Duration {
+        self.config.reload_interval
+    }
+
+    /// Whether `peer` may connect. Anyone may while no allowlist is set.
+    pub fn allows(&self, peer: &PeerId) -> bool {
+        !self.is_enabled() || self.allowed.contains(peer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.allowed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allowed.is_empty()
+    }
+
+    /// Read the file again if it changed since the last read. Returns
+    /// whether the list changed. A file that became unreadable or invalid
+    /// leaves the list as it was, until it changes again.
+    pub fn reload(&mut self) -> Result<bool, NetworkError> {
+        let Some(path) = &self.config.file else {
+            return Ok(false);
+        };
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if modified == self.modified {
+            return Ok(false);
+        }
+        let allowed = self.read()?;
+        let changed = allowed != self.allowed;
+        self.allowed = allowed;
+        Ok(changed)
+    }
+
+    fn read(&mut self) -> Result<HashSet<PeerId>, NetworkError> {
+        let mut allowed: HashSet<PeerId> = self.config.peers.iter().copied().collect();
+        let Some(path) = &self.config.file else {
+            return Ok(allowed);
+        };
+        let invalid =
+            |reason: String| NetworkError::InvalidAllowlist(path.display().to_string(), reason);
+        self.modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let contents = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        for (number, line) in contents.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let peer = parse_entry(entry)
+                .map_err(|reason| invalid(format!("line {}: {}", number + 1, reason)))?;
+            allowed.insert(peer);
+        }
+        Ok(allowed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+    use std::time::UNIX_EPOCH;
+
+    /// Write `contents` to `path`, changed at `secs`, so that reloads see a
+    /// change however fast the test runs
+    fn write(path: &std::path::Path, contents: &str, secs: u64) {
+        std::fs::write(path, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_allowlist_reloads_when_its_file_changes() {
+        let dir = std::env::temp_dir().join(format!("allowlist-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("allowlist");
+        let (alice, bob, carol) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let dave = Keypair::generate_ed25519();
+        let dave_key = hex::encode(dave.clone().try_into_ed25519().unwrap().public().to_bytes());
+        write(&path, &format!("# Classroom\n{}\n\n", alice), 1);
+
+        let mut allowlist = Allowlist::new(AllowlistConfig {
+            peers: vec![carol],
+            file: Some(path.clone()),
+            ..AllowlistConfig::default()
+        })
+        .unwrap();
+        assert!(allowlist.allows(&alice) && allowlist.allows(&carol));
+        assert!(!allowlist.allows(&bob));
+        assert!(!allowlist.reload().unwrap());
+
+        write(
+            &path,
+            &format!("{}\n{} # bob\n{}\n", alice, bob, dave_key),
+            2,
+        );
+        assert!(allowlist.reload().unwrap());
+        assert!(allowlist.allows(&bob));
+        assert!(allowlist.allows(&dave.public().to_peer_id()));
+        assert_eq!(allowlist.len(), 4);
+
+        // A broken file leaves the list as it was
+        write(&path, &format!("{}\nnot a peer\n", alice), 3);
+        assert!(matches!(
+            allowlist.reload(),
+            Err(NetworkError::InvalidAllowlist(_, reason)) if reason.starts_with("line 2")
+        ));
+        assert!(allowlist.allows(&bob));
+
+        // Peers of the config stay in whatever the file says
+        write(&path, "", 4);
+        assert!(allowlist.reload().unwrap());
+        assert!(!allowlist.allows(&alice));
+        assert!(allowlist.allows(&carol));
+        std::fs::remove_dir_all(dir).unwrap();
+
+        // Without a list everyone is welcome
+        let open = Allowlist::new(AllowlistConfig::default()).unwrap();
+        assert!(!open.is_enabled() && open.allows(&alice));
+    }
+}
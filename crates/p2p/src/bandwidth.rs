@@ -0,0 +1,278 @@
+//! Bandwidth limits per peer and for the node as a whole. Traffic is metered
+//! through token buckets. Consensus messages always pass and may overdraw a
+//! bucket, so under contention they push gossip and bulk sync back instead
+//! of waiting behind them. Drama and rumors come last, behind bulk sync, and
+//! only get the bucket while it is at least half full.
+
+use crate::sync::SyncRequest;
+use chaoschain_core::gossip::GossipTopic;
+use libp2p::PeerId;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Traffic a bucket lets through at once, in seconds of its rate
+const BURST: Duration = Duration::from_secs(2);
+
+/// Share of a bucket bulk traffic leaves for everything else
+const BULK_RESERVE: f64 = 0.25;
+
+/// Share of a bucket social traffic leaves for everything else
+const SOCIAL_RESERVE: f64 = 0.5;
+
+/// Most urgent first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Blocks and votes
+    Consensus,
+    /// Transactions
+    Gossip,
+    /// Historical blocks and snapshots
+    Bulk,
+    /// Drama and rumors
+    Social,
+}
+
+impl Priority {
+    pub fn of(topic: GossipTopic) -> Self {
+        match topic {
+            GossipTopic::Blocks | GossipTopic::Votes => Priority::Consensus,
+            GossipTopic::Transactions => Priority::Gossip,
+            GossipTopic::Drama | GossipTopic::Rumors => Priority::Social,
+        }
+    }
+
+    /// Answers to the transactions of a compact block complete a block at
+    /// the head, everything else on the sync protocol is catching up
+    pub fn of_request(request: &SyncRequest) -> Self {
+        match request {
+            SyncRequest::Transactions { .. } => Priority::Consensus,
+            SyncRequest::Status => Priority::Gossip,
+            _ => Priority::Bulk,
+        }
+    }
+}
+
+/// Rates in bytes per second, unlimited where `None`
+#[derive(Debug, Clone, Default)]
+pub struct BandwidthConfig {
+    pub peer_upload: Option<u64>,
+    pub peer_download: Option<u64>,
+    pub total_upload: Option<u64>,
+    pub total_download: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: Option<u64>, now: Instant) -> Option<Self> {
+        let rate = rate? as f64;
+        let capacity = rate * BURST.as_secs_f64();
+        Some(Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: now,
+        })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Whether `bytes` at `priority` may go now. A full bucket lets anything
+    /// through, so messages larger than the burst are not stuck forever.
+    fn allows(&mut self, bytes: usize, priority: Priority, now: Instant) -> bool {
+        self.refill(now);
+        let floor = match priority {
+            Priority::Consensus => return true,
+            Priority::Gossip => 0.0,
+            Priority::Bulk => self.capacity * BULK_RESERVE,
+            Priority::Social => self.capacity * SOCIAL_RESERVE,
+        };
+        self.tokens >= self.capacity || self.tokens - bytes as f64 >= floor
+    }
+
+    fn take(&mut self, bytes: usize) {
+        // Consensus traffic can overdraw up to one burst
+        self.tokens = (self.tokens - bytes as f64).max(-self.capacity);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PeerBuckets {
+    upload: Option<Bucket>,
+    download: Option<Bucket>,
+}
+
+/// Meters traffic against the limits of [`BandwidthConfig`]
+#[derive(Debug, Default)]
+pub struct Throttle {
+    config: BandwidthConfig,
+    upload: Option<Bucket>,
+    download: Option<Bucket>,
+    peers: HashMap<PeerId, PeerBuckets>,
+}
+
+impl Throttle {
+    pub fn new(config: BandwidthConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            upload: Bucket::new(config.total_upload, now),
+            download: Bucket::new(config.total_download, now),
+            config,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Send `bytes` to `peer`, or to the whole network when `None`, if the
+    /// limits allow it now
+    pub fn upload(
+        &mut self,
+        peer: Option<&PeerId>,
+        bytes: usize,
+        priority: Priority,
+        now: Instant,
+    ) -> bool {
+        let peer = peer.and_then(|peer| {
+            buckets_of(&mut self.peers, &self.config, peer, now)
+                .upload
+                .as_mut()
+        });
+        let mut buckets: Vec<&mut Bucket> = self.upload.iter_mut().chain(peer).collect();
+        pass(&mut buckets, bytes, priority, now)
+    }
+
+    /// Take in `bytes` from `peer`, if the limits allow it now
+    pub fn download(
+        &mut self,
+        peer: &PeerId,
+        bytes: usize,
+        priority: Priority,
+        now: Instant,
+    ) -> bool {
+        let peer = buckets_of(&mut self.peers, &self.config, peer, now)
+            .download
+            .as_mut();
+        let mut buckets: Vec<&mut Bucket> = self.download.iter_mut().chain(peer).collect();
+        pass(&mut buckets, bytes, priority, now)
+    }
+
+    /// Count `bytes` already received from `peer`
+    pub fn received(&mut self, peer: &PeerId, bytes: usize, now: Instant) {
+        let peer = buckets_of(&mut self.peers, &self.config, peer, now)
+            .download
+            .as_mut();
+        for bucket in self.download.iter_mut().chain(peer) {
+            bucket.refill(now);
+            bucket.take(bytes);
+        }
+    }
+
+    /// Whether the node has room to start downloads at `priority`
+    pub fn download_room(&mut self, priority: Priority, now: Instant) -> bool {
+        self.download
+            .as_mut()
+            .is_none_or(|bucket| bucket.allows(0, priority, now))
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+}
+
+fn buckets_of<'a>(
+    peers: &'a mut HashMap<PeerId, PeerBuckets>,
+    config: &BandwidthConfig,
+    peer: &PeerId,
+    now: Instant,
+) -> &'a mut PeerBuckets {
+    peers.entry(*peer).or_insert_with(|| PeerBuckets {
+        upload: Bucket::new(config.peer_upload, now),
+        download: Bucket::new(config.peer_download, now),
+    })
+}
+
+/// Take `bytes` from every bucket if they all allow it
+fn pass(buckets: &mut [&mut Bucket], bytes: usize, priority: Priority, now: Instant) -> bool {
+    if !buckets
+        .iter_mut()
+        .all(|bucket| bucket.allows(bytes, priority, now))
+    {
+        return false;
+    }
+    for bucket in buckets {
+        bucket.take(bytes);
+    }
+    true
+}
+
+#[derive(Debug)]
+struct Queued<T> {
+    peer: Option<PeerId>,
+    bytes: usize,
+    item: T,
+}
+
+/// Traffic held back by the limits, released most urgent first
+#[derive(Debug)]
+pub struct Outbox<T> {
+    queues: BTreeMap<Priority, VecDeque<Queued<T>>>,
+}
+
+impl<T> Default for Outbox<T> {
+    fn default() -> Self {
+        Self {
+            queues: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Outbox<T> {
+    pub fn is_empty(&self) -> bool {
+        self.queues.values().all(VecDeque::is_empty)
+    }
+
+    /// Whether traffic at `priority` is waiting already. New traffic queues
+    /// behind it, so it goes out in order.
+    pub fn is_waiting(&self, priority: Priority) -> bool {
+        self.queues
+            .get(&priority)
+            .is_some_and(|queue| !queue.is_empty())
+    }
+
+    pub fn push(&mut self, priority: Priority, peer: Option<PeerId>, bytes: usize, item: T) {
+        self.queues
+            .entry(priority)
+            .or_default()
+            .push_back(Queued { peer, bytes, item });
+    }
+
+    /// Everything the limits let through now, most urgent first
+    pub fn release(&mut self, throttle: &mut Throttle, now: Instant) -> Vec<T> {
+        let mut released = Vec::new();
+        for (priority, queue) in self.queues.iter_mut() {
+            while let Some(queued) = queue.front() {
+                if !throttle.upload(queued.peer.as_ref(), queued.bytes, *priority, now) {
+                    break;
+                }
+                released.extend(queue.pop_front().map(|queued| queued.item));
+            }
+        }
+        released
+    }
+
+    /// Drop the traffic waiting for `peer`
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        for queue in self.queues.values_mut() {
+            queue.retain(|queued| queued.peer.as_ref() != Some(peer));
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Finding peers without being told their addresses: mDNS on the local
+//! network, and a Kademlia style lookup over [`DISCOVERY_PROTOCOL`] for
+//! peers further away.
+
+use crate::allowlist::AllowlistConfig;
+use crate::nat::NatConfig;
+use crate::pex::PexConfig;
+use crate::sentry::SentryConfig;
+use crate::slots::ConnectionLimits;
+use crate::websocket::WebSocketConfig;
+use crate::wire::{self, Limits};
+use libp2p::{Multiaddr, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+/// Protocol peers are looked up with
+pub const DISCOVERY_PROTOCOL: StreamProtocol = StreamProtocol::new("/chaoschain/kad/1");
+
+/// Peers asked at once during a lookup
+pub const LOOKUP_PARALLELISM: usize = 3;
+
+/// How a node finds its peers
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    /// Addresses to listen on. `/tcp` addresses take TCP connections,
+    /// `/udp/../quic-v1` ones QUIC.
+    pub listen: Vec<Multiaddr>,
+    /// Find peers on the local network, for demos on one machine or LAN
+    pub mdns: bool,
+    /// Look up peers through the ones already known, for WAN deployments
+    pub kademlia: bool,
+    /// Addresses dialed on start to join the network
+    pub bootstrap: Vec<Multiaddr>,
+    /// Addresses kept connected, redialed whenever they drop
+    pub static_peers: Vec<Multiaddr>,
+    /// First wait before redialing a static peer, doubled on every failure
+    pub static_backoff: Duration,
+    /// Peers kept per distance in the routing table
+    pub bucket_size: usize,
+    /// Time between lookups
+    pub lookup_interval: Duration,
+    /// Discovered peers are dialed until this many are connected
+    pub max_peers: usize,
+    /// Inbound and outbound slots, and who gets evicted past them
+    pub connections: ConnectionLimits,
+    /// How the node is reached from outside its home network
+    pub nat: NatConfig,
+    /// WebSocket listener for browser clients
+    pub websocket: WebSocketConfig,
+    /// Peer lists swapped with the connected peers
+    pub pex: PexConfig,
+    /// Sentries this validator hides behind, or validators behind this sentry
+    pub sentry: SentryConfig,
+    /// The only peers let in, for closed networks
+    pub allowlist: AllowlistConfig,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            listen: vec![
+                "/ip4/0.0.0.0/tcp/0".parse().expect("Valid address"),
+                "/ip4/0.0.0.0/udp/0/quic-v1".parse().expect("Valid address"),
+            ],
+            mdns: true,
+            kademlia: true,
+            bootstrap: Vec::new(),
+            static_peers: Vec::new(),
+            static_backoff: Duration::from_secs(1),
+            bucket_size: 20,
+            lookup_interval: Duration::from_secs(30),
+            max_peers: 50,
+            connections: ConnectionLimits::default(),
+            nat: NatConfig::default(),
+            websocket: WebSocketConfig::default(),
+            pex: PexConfig::default(),
+            sentry: SentryConfig::default(),
+            allowlist: AllowlistConfig::default(),
+        }
+    }
+}
+
+/// Ask a peer for the peers it knows closest to `target`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FindPeers {
+    pub target: String,
+}
+
+/// Peers known to the one asked, closest to the target first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Peers {
+    pub peers: Vec<PeerRecord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+}
+
+impl PeerRecord {
+    /// The peer and its addresses, unless the record is garbled
+    pub fn parse(&self) -> Option<(PeerId, Vec<Multiaddr>)> {
+        let peer = self.peer_id.parse().ok()?;
+        let addresses = self
+            .addresses
+            .iter()
+            .filter_map(|address| address.parse().ok())
+            .collect();
+        Some((peer, addresses))
+    }
+}
+
+/// A target to look up, and the peers closest to it
+pub const DISCOVERY_LIMITS: Limits = Limits {
+    request: 1024,
+    response: 64 * 1024,
+};
+
+pub type DiscoveryBehaviour = wire::Behaviour<FindPeers, Peers>;
+
+pub fn behaviour() -> DiscoveryBehaviour {
+    wire::behaviour(DISCOVERY_PROTOCOL, DISCOVERY_LIMITS)
+}
+
+/// Position of a peer in the XOR metric
+type Key = [u8; 32];
+
+fn key(peer: &PeerId) -> Key {
+    Sha256::digest(peer.to_bytes()).into()
+}
+
+fn distance(a: &Key, b: &Key) -> Key {
+    let mut distance = [0; 32];
+    for (i, byte) in distance.iter_mut().enumerate() {
+        *byte = a[i] ^ b[i];
+    }
+    distance
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    peer: PeerId,
+    key: Key,
+    addresses: Vec<Multiaddr>,
+}
+
+/// Known peers, bucketed by their distance to the local peer
+#[derive(Debug, Clone)]
+pub struct RoutingTable {
+    local: Key,
+    bucket_size: usize,
+    buckets: Vec<Vec<Entry>>,
+}
+
+impl RoutingTable {
+    pub fn new(local: &PeerId, bucket_size: usize) -> Self {
+        Self {
+            local: key(local),
+            bucket_size,
+            buckets: vec![Vec::new(); 256],
+        }
+    }
+
+    /// Bucket of a key: the more leading bits it shares with ours, the higher
+    fn bucket(&self, key: &Key) -> Option<usize> {
+        let distance = distance(&self.local, key);
+        let zeros = distance
+            .iter()
+            .position(|byte| *byte != 0)
+            .map(|i| i * 8 + distance[i].leading_zeros() as usize)?;
+        Some(255 - zeros)
+    }
+
+    /// Remember an address of a peer. Returns whether the peer is new. Full
+    /// buckets keep the peers they have, long lived peers are the ones most
+    /// likely to stay.
+    pub fn insert(&mut self, peer: PeerId, address: Multiaddr) -> bool {
+        let key = key(&peer);
+        let Some(index) = self.bucket(&key) else {
+            return false;
+        };
+        let bucket = &mut self.buckets[index];
+        if let Some(entry) = bucket.iter_mut().find(|entry| entry.peer == peer) {
+            if !entry.addresses.contains(&address) {
+                entry.addresses.push(address);
+            }
+            return false;
+        }
+        if bucket.len() >= self.bucket_size {
+            return false;
+        }
+        bucket.push(Entry {
+            peer,
+            key,
+            addresses: vec![address],
+        });
+        true
+    }
+
+    pub fn remove(&mut self, peer: &PeerId) {
+        if let Some(index) = self.bucket(&key(peer)) {
+            self.buckets[index].retain(|entry| entry.peer != *peer);
+        }
+    }
+
+    /// Forget one address of a peer, and the peer once none are left
+    pub fn remove_address(&mut self, peer: &PeerId, address: &Multiaddr) {
+        let Some(index) = self.bucket(&key(peer)) else {
+            return;
+        };
+        let bucket = &mut self.buckets[index];
+        if let Some(entry) = bucket.iter_mut().find(|entry| entry.peer == *peer) {
+            entry.addresses.retain(|known| known != address);
+        }
+        bucket.retain(|entry| !entry.addresses.is_empty());
+    }
+
+    /// Addresses known for `peer`
+    pub fn addresses(&self, peer: &PeerId) -> Vec<Multiaddr> {
+        self.bucket(&key(peer))
+            .and_then(|index| self.buckets[index].iter().find(|entry| entry.peer == *peer))
+            .map(|entry| entry.addresses.clone())
+            .unwrap_or_default()
+    }
+
+    /// Up to `count` known peers closest to `target`
+    pub fn closest(&self, target: &PeerId, count: usize) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let target = key(target);
+        let mut entries: Vec<&Entry> = self.buckets.iter().flatten().collect();
+        entries.sort_by_key(|entry| distance(&entry.key, &target));
+        entries
+            .into_iter()
+            .take(count)
+            .map(|entry| (entry.peer, entry.addresses.clone()))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The records of peers, as sent in answer to [`FindPeers`]
+pub fn records(peers: Vec<(PeerId, Vec<Multiaddr>)>) -> Peers {
+    Peers {
+        peers: peers
+            .into_iter()
+            .map(|(peer, addresses)| PeerRecord {
+                peer_id: peer.to_string(),
+                addresses: addresses.iter().map(ToString::to_string).collect(),
+            })
+            .collect(),
+    }
+}
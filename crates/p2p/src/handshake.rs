@@ -0,0 +1,182 @@
+//! Version and capability exchange, first thing on every connection. Peers
+//! of another major version are refused. Within a major version both sides
+//! only use what they have in common, which lets a network upgrade one node
+//! at a time.
+
+use crate::wire::{self, Limits};
+use libp2p::StreamProtocol;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Protocol the handshake runs over
+pub const HANDSHAKE_PROTOCOL: StreamProtocol = StreamProtocol::new("/chaoschain/handshake/1");
+
+/// Version of the wire protocol this node speaks
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 4 };
+
+/// Peers that do not know the handshake predate it
+pub const LEGACY_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ProtocolVersion {
+    /// Bumped when old nodes can no longer make sense of the traffic
+    pub major: u16,
+    /// Bumped when something optional is added
+    pub minor: u16,
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "chaoschain/{}.{}", self.major, self.minor)
+    }
+}
+
+/// Optional parts of the protocol, used only with peers that have them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    /// Signed votes on their own topic
+    Votes,
+    /// Blocks announced as compact blocks
+    CompactBlocks,
+    /// Historical blocks over the sync protocol
+    Sync,
+    /// State snapshots over the sync protocol
+    Snapshots,
+    /// Dial backs for NAT detection
+    DialBack,
+    /// Gossip compressed with Snappy
+    Snappy,
+    /// Peer lists swapped over the peer exchange protocol
+    PeerExchange,
+    /// Hole punches coordinated over the hole punching protocol
+    HolePunch,
+    /// Something a newer peer has and we do not know of
+    #[serde(other)]
+    Unknown,
+}
+
+/// What a node tells its peers about itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    pub version: ProtocolVersion,
+    pub capabilities: BTreeSet<Capability>,
+}
+
+impl Hello {
+    /// This node, with what it is set up for
+    pub fn new(capabilities: impl IntoIterator<Item = Capability>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: capabilities.into_iter().collect(),
+        }
+    }
+
+    /// A peer from before the handshake, which only gossips full blocks
+    pub fn legacy() -> Self {
+        Self {
+            version: LEGACY_VERSION,
+            capabilities: BTreeSet::new(),
+        }
+    }
+}
+
+/// What two peers agreed on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: ProtocolVersion,
+    pub capabilities: BTreeSet<Capability>,
+}
+
+impl Negotiated {
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+/// Settle on the lower version and the capabilities both sides have. Fails
+/// when the major versions differ.
+pub fn negotiate(ours: &Hello, theirs: &Hello) -> Result<Negotiated, String> {
+    if ours.version.major != theirs.version.major {
+        return Err(format!(
+            "Peer speaks {}, we speak {}",
+            theirs.version, ours.version
+        ));
+    }
+    Ok(Negotiated {
+        version: ours.version.min(theirs.version),
+        capabilities: ours
+            .capabilities
+            .intersection(&theirs.capabilities)
+            .copied()
+            .collect(),
+    })
+}
+
+/// A version and a handful of capabilities each way
+pub const HANDSHAKE_LIMITS: Limits = Limits {
+    request: 4 * 1024,
+    response: 4 * 1024,
+};
+
+pub type HandshakeBehaviour = wire::Behaviour<Hello, Hello>;
+
+pub fn behaviour() -> HandshakeBehaviour {
+    wire::behaviour(HANDSHAKE_PROTOCOL, HANDSHAKE_LIMITS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peers_settle_on_what_they_have_in_common() {
+        let ours = Hello::new([Capability::Votes, Capability::Sync, Capability::Snappy]);
+        let older = Hello {
+            version: ProtocolVersion { major: 1, minor: 2 },
+            capabilities: [Capability::Votes, Capability::Snappy, Capability::HolePunch]
+                .into_iter()
+                .collect(),
+        };
+        let negotiated = negotiate(&ours, &older).unwrap();
+        assert_eq!(negotiated.version, older.version);
+        assert!(negotiated.supports(Capability::Votes));
+        assert!(negotiated.supports(Capability::Snappy));
+        // Only one side has these
+        assert!(!negotiated.supports(Capability::Sync));
+        assert!(!negotiated.supports(Capability::HolePunch));
+        assert_eq!(negotiate(&older, &ours).unwrap(), negotiated);
+
+        // A peer from before the handshake gets full blocks and nothing else
+        let legacy = negotiate(&ours, &Hello::legacy()).unwrap();
+        assert_eq!(legacy.version, LEGACY_VERSION);
+        assert!(legacy.capabilities.is_empty());
+
+        // What a newer peer has that we do not know of is left out
+        let newer: Hello = serde_json::from_str(
+            r#"{"version":{"major":1,"minor":9},"capabilities":["sync","teleport"]}"#,
+        )
+        .unwrap();
+        assert!(newer.capabilities.contains(&Capability::Unknown));
+        let negotiated = negotiate(&ours, &newer).unwrap();
+        assert_eq!(negotiated.version, PROTOCOL_VERSION);
+        assert_eq!(
+            negotiated.capabilities,
+            [Capability::Sync].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn test_peers_of_another_major_version_are_refused() {
+        let ours = Hello::new([Capability::Votes]);
+        for major in [0, PROTOCOL_VERSION.major + 1] {
+            let theirs = Hello {
+                version: ProtocolVersion { major, minor: 0 },
+                capabilities: ours.capabilities.clone(),
+            };
+            let reason = negotiate(&ours, &theirs).unwrap_err();
+            assert!(reason.contains(&theirs.version.to_string()), "{}", reason);
+        }
+    }
+}
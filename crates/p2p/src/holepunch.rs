@@ -0,0 +1,216 @@
+//! Hole punching between peers that are both behind NAT, after DCUtR. Two
+//! private nodes cannot dial each other, but a peer connected to both can
+//! tell each where the other shows up from, and time their dials so they
+//! go out at about the same moment. Each NAT then sees an outgoing dial
+//! before the other side's packets arrive and lets them in, which gives the
+//! pair a direct connection instead of the long way round through relays.
+//!
+//! The node that wants the connection asks the relay with
+//! [`Punch::Connect`]. The relay passes the request on to the target as
+//! [`Punch::Incoming`], with the address it sees the asker at and how long
+//! the target should wait before dialing. Once the target agrees, the relay
+//! answers the asker with the address it sees the target at, and the asker
+//! dials right away.
+
+use crate::nat::ip_of;
+use crate::wire::{self, Limits};
+use libp2p::request_response::OutboundRequestId;
+use libp2p::{Multiaddr, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Protocol hole punches are coordinated over
+pub const HOLEPUNCH_PROTOCOL: StreamProtocol = StreamProtocol::new("/chaoschain/holepunch/1");
+
+/// Addresses handed out for one punch
+const MAX_ADDRESSES: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct HolePunchConfig {
+    /// Try to reach peers heard through relays directly while we are private
+    pub enabled: bool,
+    /// Put peers connected to us in touch with each other
+    pub coordinate: bool,
+    /// Punches tried per peer before giving up on it
+    pub max_attempts: u32,
+    /// Time between punches at the same peer
+    pub retry_interval: Duration,
+}
+
+impl Default for HolePunchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            coordinate: true,
+            max_attempts: 3,
+            retry_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Punch {
+    /// To a relay: put us in touch with `target`
+    Connect { target: String },
+    /// From a relay: `from` wants a direct connection, dial it on
+    /// `addresses` once `delay_ms` have passed
+    Incoming {
+        from: String,
+        addresses: Vec<String>,
+        delay_ms: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PunchReply {
+    /// Dial on these addresses now. To the relay, the target sends the
+    /// addresses it has seen itself at.
+    Dial {
+        addresses: Vec<String>,
+    },
+    Refused {
+        reason: String,
+    },
+}
+
+/// A peer id and a few addresses each way
+pub const HOLEPUNCH_LIMITS: Limits = Limits {
+    request: 4 * 1024,
+    response: 4 * 1024,
+};
+
+pub type HolePunchBehaviour = wire::Behaviour<Punch, PunchReply>;
+
+pub fn behaviour() -> HolePunchBehaviour {
+    wire::behaviour(HOLEPUNCH_PROTOCOL, HOLEPUNCH_LIMITS)
+}
+
+/// The addresses to punch a peer at: where the relay sees it first, then
+/// what the peer claims, as far as it is on the same IP. Nobody gets us to
+/// dial a third party.
+pub fn punch_addresses(observed: &Multiaddr, claimed: &[String]) -> Vec<Multiaddr> {
+    let ip = ip_of(observed);
+    let mut addresses = vec![observed.clone()];
+    for address in claimed.iter().filter_map(|a| a.parse::<Multiaddr>().ok()) {
+        if addresses.len() >= MAX_ADDRESSES {
+            break;
+        }
+        if ip.is_some() && ip_of(&address) == ip && !addresses.contains(&address) {
+            addresses.push(address);
+        }
+    }
+    addresses
+}
+
+/// Halfway there and back through the relay, so both dials leave together
+pub fn dial_delay(to_asker: Option<Duration>, to_target: Option<Duration>) -> Duration {
+    (to_asker.unwrap_or_default() + to_target.unwrap_or_default()) / 2
+}
+
+#[derive(Debug)]
+struct Attempts {
+    count: u32,
+    last: Instant,
+}
+
+/// Punches tried, dials waiting for their moment, and the round trips the
+/// delays are worked out from
+#[derive(Debug, Default)]
+pub struct HolePunches {
+    config: HolePunchConfig,
+    attempts: HashMap<PeerId, Attempts>,
+    /// Relays asked to put us in touch, with the peer we asked for
+    asked: HashMap<OutboundRequestId, PeerId>,
+    /// Dials to make once their time comes
+    scheduled: Vec<(Instant, PeerId, Vec<Multiaddr>)>,
+    rtts: HashMap<PeerId, Duration>,
+}
+
+impl HolePunches {
+    pub fn new(config: HolePunchConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn config(&self) -> &HolePunchConfig {
+        &self.config
+    }
+
+    /// Whether to punch at `peer` now, counting the attempt if so
+    pub fn try_punch(&mut self, peer: PeerId, now: Instant) -> bool {
+        if !self.config.enabled {
+            return false;
+        }
+        match self.attempts.get_mut(&peer) {
+            Some(attempts)
+                if attempts.count >= self.config.max_attempts
+                    || now.saturating_duration_since(attempts.last)
+                        < self.config.retry_interval =>
+            {
+                false
+            }
+            Some(attempts) => {
+                attempts.count += 1;
+                attempts.last = now;
+                true
+            }
+            None => {
+                self.attempts.insert(
+                    peer,
+                    Attempts {
+                        count: 1,
+                        last: now,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// A relay was asked to put us in touch with `target`
+    pub fn asked(&mut self, id: OutboundRequestId, target: PeerId) {
+        self.asked.insert(id, target);
+    }
+
+    /// The peer a relay answered about
+    pub fn answered(&mut self, id: &OutboundRequestId) -> Option<PeerId> {
+        self.asked.remove(id)
+    }
+
+    /// Dial `peer` on `addresses` at `at`
+    pub fn schedule(&mut self, at: Instant, peer: PeerId, addresses: Vec<Multiaddr>) {
+        self.scheduled.push((at, peer, addresses));
+    }
+
+    /// The dials whose time has come
+    pub fn due(&mut self, now: Instant) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.scheduled)
+            .into_iter()
+            .partition(|(at, _, _)| *at <= now);
+        self.scheduled = waiting;
+        due.into_iter()
+            .map(|(_, peer, addresses)| (peer, addresses))
+            .collect()
+    }
+
+    pub fn pinged(&mut self, peer: PeerId, rtt: Duration) {
+        self.rtts.insert(peer, rtt);
+    }
+
+    pub fn rtt(&self, peer: &PeerId) -> Option<Duration> {
+        self.rtts.get(peer).copied()
+    }
+
+    /// We are connected to `peer` directly, no more punching needed
+    pub fn connected(&mut self, peer: &PeerId) -> bool {
+        self.scheduled.retain(|(_, scheduled, _)| scheduled != peer);
+        self.attempts.remove(peer).is_some()
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.rtts.remove(peer);
+    }
+}
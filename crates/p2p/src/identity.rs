@@ -0,0 +1,57 @@
+//! The node's ed25519 key doubles as its network identity. Noise XX
+//! handshakes prove possession of it to every connected peer, so the peer id
+//! a connection reports is the node's key and not just a claim.
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use libp2p::identity::{self, Keypair};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+
+use crate::NetworkError;
+
+/// The transport keypair of a node with signing key `key`
+pub fn keypair(key: &SigningKey) -> Keypair {
+    identity::Keypair::ed25519_from_bytes(key.to_bytes())
+        .expect("A 32 byte secret is a valid ed25519 key")
+}
+
+/// Peer id of the node with public key `key`, to match authenticated peers
+/// against known agents
+pub fn peer_id(key: &VerifyingKey) -> PeerId {
+    let public = identity::ed25519::PublicKey::try_from_bytes(key.as_bytes())
+        .expect("A verifying key is a valid ed25519 public key");
+    PeerId::from_public_key(&identity::PublicKey::from(public))
+}
+
+/// Public key of the peer `peer`. Ed25519 peer ids embed the key, others
+/// only hash it and have none to give.
+pub fn public_key(peer: &PeerId) -> Option<VerifyingKey> {
+    let hash = peer.as_ref();
+    // Identity multihash, the key as it is
+    if hash.code() != 0 {
+        return None;
+    }
+    let key = identity::PublicKey::try_decode_protobuf(hash.digest())
+        .ok()?
+        .try_into_ed25519()
+        .ok()?;
+    VerifyingKey::from_bytes(&key.to_bytes()).ok()
+}
+
+/// Public key of the peer at `address`, which ends in its peer id as in
+/// `/ip4/203.0.113.7/tcp/4001/p2p/12D3KooW...`
+pub fn address_key(address: &str) -> Result<VerifyingKey, NetworkError> {
+    let invalid = |message: &str| NetworkError::InvalidAddress(address.to_string(), message.into());
+    let parsed: Multiaddr = address.parse().map_err(|e| invalid(&format!("{}", e)))?;
+    let peer = parsed
+        .iter()
+        .filter_map(|protocol| match protocol {
+            Protocol::P2p(peer) => Some(peer),
+            _ => None,
+        })
+        .last()
+        .ok_or_else(|| invalid("no /p2p/ peer id"))?;
+    public_key(&peer).ok_or_else(|| {
+        NetworkError::InvalidPeerId(peer.to_string(), "not an ed25519 key".to_string())
+    })
+}
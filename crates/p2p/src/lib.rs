@@ -1,22 +1,90 @@
+pub mod allowlist;
+pub mod bandwidth;
+pub mod discovery;
+pub mod handshake;
+pub mod holepunch;
+pub mod identity;
+pub mod metrics;
+pub mod nat;
+pub mod overlay;
+pub mod peer_store;
+pub mod peers;
+pub mod pex;
+pub mod relay;
+pub mod scoring;
+pub mod seen;
+pub mod sentry;
+pub mod slots;
+pub mod snapshots;
+pub mod sync;
+pub mod websocket;
+pub mod wire;
+
+/// Who sent a message, as [`TopicValidator`]s are told
+pub use libp2p::PeerId;
+
+use allowlist::Allowlist;
 use anyhow::Result;
+use bandwidth::{BandwidthConfig, Outbox, Priority, Throttle};
+use chaoschain_core::compact::{CompactBlock, PartialBlock};
+use chaoschain_core::compression::{self, Codec};
+use chaoschain_core::envelope::{AgentKeys, SignedEnvelope};
+use chaoschain_core::gossip::{GossipLimits, GossipTopic};
+use chaoschain_core::metrics::MetricsRegistry;
+use chaoschain_core::rumor::Rumor;
+use chaoschain_core::sync::{SyncState, SyncStatus};
+use chaoschain_core::threshold::ThresholdMessage;
+use chaoschain_core::vote::SignedVote;
 use chaoschain_core::{Block, NetworkMessage, Transaction};
+use discovery::{DiscoveryBehaviour, DiscoveryConfig, FindPeers, RoutingTable};
+use ed25519_dalek::SigningKey;
+use futures::future::Either;
 use futures::StreamExt;
+use handshake::{Capability, HandshakeBehaviour, Hello, Negotiated};
+use holepunch::{HolePunchBehaviour, HolePunches, Punch, PunchReply};
 use libp2p::{
-    core::upgrade,
+    core::{muxing::StreamMuxerBox, upgrade, ConnectedPoint},
     gossipsub::{
-        self, Event as GossipsubEvent, IdentTopic as Topic, MessageAuthenticity, ValidationMode,
+        self, Event as GossipsubEvent, IdentTopic as Topic, MessageAcceptance, MessageAuthenticity,
+        MessageId, TopicHash, ValidationMode,
     },
     identify,
     identity::Keypair,
-    mdns, noise, ping,
-    swarm::{Config as SwarmConfig, NetworkBehaviour, SwarmEvent},
-    tcp, yamux, PeerId, Swarm, Transport,
+    mdns,
+    multiaddr::Protocol,
+    noise, ping, quic,
+    request_response::{self, OutboundRequestId, ResponseChannel},
+    swarm::{
+        behaviour::toggle::Toggle,
+        dial_opts::{DialOpts, PeerCondition},
+        Config as SwarmConfig, ConnectionId, NetworkBehaviour, SwarmEvent,
+    },
+    tcp, upnp, yamux, Multiaddr, Swarm, Transport,
 };
+use metrics::NetworkMetrics;
+use nat::{DialBack, DialBackResult, NatBehaviour, NatStatus, Reachability};
+use overlay::{Delivered, Overlay, OverlayBehaviour, OverlayConfig};
+use peer_store::{PeerStore, StoredPeer};
+use peers::StaticPeers;
+use pex::{PeerExchange, PexBehaviour};
+use relay::{CompactRelay, Incomplete, TransactionPool};
+use scoring::{unix_seconds, Offense, PeerScores};
+use seen::SeenCache;
+use sentry::{Sentries, SentryHealth};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use slots::{Admission, SlotStatus, Slots};
+use snapshots::{SnapshotConfig, SnapshotDownload, SnapshotStore, SnapshotTarget};
+use std::collections::{HashMap, HashSet};
 use std::error::Error as StdError;
-use std::time::Duration;
-use tokio::sync::mpsc;
-use tracing::info;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use sync::{ChainSource, SyncBehaviour, SyncConfig, SyncRequest, SyncResponse, Syncer};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info, warn};
+use websocket::WsTransport;
+use wire::{Oversized, MAX_MESSAGES_PER_RPC, MAX_TRANSMIT_SIZE};
 
 /// P2P message types for agent communication
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,13 +128,19 @@ pub enum NetworkError {
     Serialization(#[from] serde_json::Error),
     #[error("Internal error: {0}")]
     Internal(String),
+    #[error("Invalid address {0}: {1}")]
+    InvalidAddress(String, String),
+    #[error("Invalid peer id {0}: {1}")]
+    InvalidPeerId(String, String),
+    #[error("Invalid allowlist {0}: {1}")]
+    InvalidAllowlist(String, String),
 }
 
 /// Fun message types for agent communication
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AgentMessage {
     /// Standard block proposal
-    BlockProposal(Block),
+    BlockProposal(Box<Block>),
     /// Vote on a block
     Vote(BlockVote),
     /// Question about state diff
@@ -101,38 +175,61 @@ pub struct BlockVote {
     pub meme_url: Option<String>,
 }
 
-/// Network topics for different message types
+/// Gossip topics, by the kind of message they carry
 pub struct NetworkTopics {
-    blocks: Topic,
-    transactions: Topic,
-    chat: Topic,
+    topics: HashMap<GossipTopic, Topic>,
 }
 
 impl NetworkTopics {
     pub fn new() -> Self {
         Self {
-            blocks: Topic::new(BLOCK_TOPIC),
-            transactions: Topic::new(TX_TOPIC),
-            chat: Topic::new(CHAT_TOPIC),
+            topics: GossipTopic::ALL
+                .into_iter()
+                .map(|topic| (topic, Topic::new(topic.name())))
+                .collect(),
         }
     }
 
-    pub fn subscribe_all(&self, swarm: &mut Swarm<ChainBehaviour>) {
-        swarm
-            .behaviour_mut()
-            .gossipsub
-            .subscribe(&self.blocks)
-            .unwrap();
-        swarm
-            .behaviour_mut()
-            .gossipsub
-            .subscribe(&self.transactions)
-            .unwrap();
-        swarm
-            .behaviour_mut()
-            .gossipsub
-            .subscribe(&self.chat)
-            .unwrap();
+    pub fn topic(&self, topic: GossipTopic) -> &Topic {
+        &self.topics[&topic]
+    }
+
+    /// The kind of topic a received message was published on
+    pub fn of(&self, hash: &TopicHash) -> Option<GossipTopic> {
+        self.topics
+            .iter()
+            .find(|(_, topic)| topic.hash() == *hash)
+            .map(|(kind, _)| *kind)
+    }
+
+    pub fn subscribe_all(
+        &self,
+        swarm: &mut Swarm<ChainBehaviour>,
+    ) -> Result<(), gossipsub::SubscriptionError> {
+        self.subscribe(swarm, |_| true)
+    }
+
+    /// Leave every topic, which tells the mesh we are gone
+    pub fn unsubscribe_all(&self, swarm: &mut Swarm<ChainBehaviour>) {
+        for topic in self.topics.values() {
+            if let Err(e) = swarm.behaviour_mut().gossipsub.unsubscribe(topic) {
+                debug!("Could not leave topic {}: {}", topic, e);
+            }
+        }
+    }
+
+    /// Subscribe to the topics `wanted` picks
+    pub fn subscribe(
+        &self,
+        swarm: &mut Swarm<ChainBehaviour>,
+        wanted: impl Fn(GossipTopic) -> bool,
+    ) -> Result<(), gossipsub::SubscriptionError> {
+        for (kind, topic) in &self.topics {
+            if wanted(*kind) {
+                swarm.behaviour_mut().gossipsub.subscribe(topic)?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -142,9 +239,73 @@ impl Default for NetworkTopics {
     }
 }
 
-const BLOCK_TOPIC: &str = "blocks";
-const TX_TOPIC: &str = "transactions";
-const CHAT_TOPIC: &str = "chat";
+/// A message that passed validation, and the peers behind it. Both peer ids
+/// are authenticated: the author signed the message, and the relay proved
+/// its key in the Noise handshake of the connection it arrived on.
+#[derive(Debug, Clone)]
+pub struct Received {
+    /// Peer that signed the message
+    pub author: PeerId,
+    /// Connected peer the message came from
+    pub relayed_by: PeerId,
+    pub message: NetworkMessage,
+}
+
+/// What the node asks the network to send, while [`ChainNetwork::start`]
+/// holds it, see [`ChainNetwork::with_broadcasts`]
+#[derive(Debug, Clone)]
+pub enum Broadcast {
+    /// A block of ours, to the validators, see [`ChainNetwork::propose_block`]
+    Proposal(Block),
+    /// A finalized block, to everyone
+    Block(Block),
+    Vote(SignedVote),
+    Transaction(Transaction),
+    /// Social gossip an agent signed
+    Signed(SignedEnvelope),
+}
+
+/// What becomes of a gossiped message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Validation {
+    /// Deliver it and pass it on
+    Accept,
+    /// Drop it and hold it against the peer that sent it
+    Reject(String),
+    /// Drop it quietly, e.g. an honest but stale message
+    Ignore,
+}
+
+/// Node specific checks of the messages on a topic, run after the checks of
+/// [`NetworkMessage::check`]
+pub trait TopicValidator: Send + Sync {
+    fn validate(&self, source: &PeerId, message: &NetworkMessage) -> Validation;
+}
+
+impl<F> TopicValidator for F
+where
+    F: Fn(&PeerId, &NetworkMessage) -> Validation + Send + Sync,
+{
+    fn validate(&self, source: &PeerId, message: &NetworkMessage) -> Validation {
+        self(source, message)
+    }
+}
+
+/// Time between releases of traffic held back by the bandwidth limits
+const PACING_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time a node shutting down keeps handling traffic after it left the
+/// topics, so what is in flight still arrives
+const DRAIN_GRACE: Duration = Duration::from_secs(1);
+
+/// Longest a relay may hold back our side of a hole punch
+const MAX_PUNCH_DELAY: Duration = Duration::from_secs(5);
+
+/// Traffic waiting for bandwidth
+enum Outgoing {
+    Publish(GossipTopic, Vec<u8>),
+    Respond(ResponseChannel<SyncResponse>, SyncResponse),
+}
 
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "ChainBehaviourEvent")]
@@ -152,7 +313,15 @@ pub struct ChainBehaviour {
     gossipsub: gossipsub::Behaviour,
     identify: identify::Behaviour,
     ping: ping::Behaviour,
-    mdns: mdns::tokio::Behaviour,
+    mdns: Toggle<mdns::tokio::Behaviour>,
+    discovery: DiscoveryBehaviour,
+    sync: SyncBehaviour,
+    upnp: Toggle<upnp::tokio::Behaviour>,
+    nat: NatBehaviour,
+    handshake: HandshakeBehaviour,
+    overlay: OverlayBehaviour,
+    pex: PexBehaviour,
+    holepunch: HolePunchBehaviour,
 }
 
 #[derive(Debug)]
@@ -161,6 +330,14 @@ pub enum ChainBehaviourEvent {
     Identify(identify::Event),
     Ping(ping::Event),
     Mdns(mdns::Event),
+    Discovery(request_response::Event<FindPeers, discovery::Peers>),
+    Sync(request_response::Event<SyncRequest, SyncResponse>),
+    Upnp(upnp::Event),
+    Nat(request_response::Event<DialBack, DialBackResult>),
+    Handshake(request_response::Event<Hello, Hello>),
+    Overlay(request_response::Event<NetworkMessage, Delivered>),
+    Pex(request_response::Event<discovery::Peers, discovery::Peers>),
+    HolePunch(request_response::Event<Punch, PunchReply>),
 }
 
 impl From<GossipsubEvent> for ChainBehaviourEvent {
@@ -187,24 +364,151 @@ impl From<mdns::Event> for ChainBehaviourEvent {
     }
 }
 
+impl From<request_response::Event<SyncRequest, SyncResponse>> for ChainBehaviourEvent {
+    fn from(event: request_response::Event<SyncRequest, SyncResponse>) -> Self {
+        ChainBehaviourEvent::Sync(event)
+    }
+}
+
+impl From<upnp::Event> for ChainBehaviourEvent {
+    fn from(event: upnp::Event) -> Self {
+        ChainBehaviourEvent::Upnp(event)
+    }
+}
+
+impl From<request_response::Event<DialBack, DialBackResult>> for ChainBehaviourEvent {
+    fn from(event: request_response::Event<DialBack, DialBackResult>) -> Self {
+        ChainBehaviourEvent::Nat(event)
+    }
+}
+
+impl From<request_response::Event<Hello, Hello>> for ChainBehaviourEvent {
+    fn from(event: request_response::Event<Hello, Hello>) -> Self {
+        ChainBehaviourEvent::Handshake(event)
+    }
+}
+
+impl From<request_response::Event<NetworkMessage, Delivered>> for ChainBehaviourEvent {
+    fn from(event: request_response::Event<NetworkMessage, Delivered>) -> Self {
+        ChainBehaviourEvent::Overlay(event)
+    }
+}
+
+impl From<request_response::Event<discovery::Peers, discovery::Peers>> for ChainBehaviourEvent {
+    fn from(event: request_response::Event<discovery::Peers, discovery::Peers>) -> Self {
+        ChainBehaviourEvent::Pex(event)
+    }
+}
+
+impl From<request_response::Event<Punch, PunchReply>> for ChainBehaviourEvent {
+    fn from(event: request_response::Event<Punch, PunchReply>) -> Self {
+        ChainBehaviourEvent::HolePunch(event)
+    }
+}
+
+impl From<request_response::Event<FindPeers, discovery::Peers>> for ChainBehaviourEvent {
+    fn from(event: request_response::Event<FindPeers, discovery::Peers>) -> Self {
+        ChainBehaviourEvent::Discovery(event)
+    }
+}
+
 pub struct ChainNetwork {
     swarm: Swarm<ChainBehaviour>,
     topics: NetworkTopics,
-    event_sender: mpsc::Sender<NetworkMessage>,
+    event_sender: mpsc::Sender<Received>,
+    limits: GossipLimits,
+    validators: HashMap<GossipTopic, Vec<Arc<dyn TopicValidator>>>,
+    discovery: DiscoveryConfig,
+    routing: RoutingTable,
+    connected: HashSet<PeerId>,
+    static_peers: StaticPeers,
+    /// Validators we send votes and proposals to directly
+    overlay: Overlay,
+    pex: PeerExchange,
+    /// Our sentries when we hide behind them, or the validators we hide
+    sentries: Sentries,
+    /// Inbound and outbound slots of the connected peers
+    slots: Slots,
+    /// The only peers let in, in a closed network
+    allowlist: Allowlist,
+    /// Keys of the agents behind the social gossip
+    agent_keys: Arc<AgentKeys>,
+    scores: PeerScores,
+    /// Where peers are remembered across restarts
+    peer_store: Option<Arc<dyn PeerStore>>,
+    /// When each peer was last connected, in unix seconds
+    last_seen: HashMap<PeerId, u64>,
+    chain: Option<Arc<dyn ChainSource>>,
+    syncer: Syncer,
+    /// Where the sync stands, shared with those who must wait for it
+    sync_status: SyncStatus,
+    relay: CompactRelay,
+    snapshot_store: Option<Arc<dyn SnapshotStore>>,
+    snapshot_target: Option<Arc<dyn SnapshotTarget>>,
+    snapshots: SnapshotDownload,
+    nat: NatStatus,
+    /// Where connected peers reach us from, for dialing them back
+    remotes: HashMap<PeerId, Multiaddr>,
+    /// Dial backs under way, answered once the connection succeeds or fails
+    dial_backs: HashMap<ConnectionId, (Multiaddr, ResponseChannel<DialBackResult>)>,
+    /// Hole punches under way, and the round trips to time them by
+    holepunch: HolePunches,
+    /// Punch requests passed on, answered once their target replies
+    introductions: HashMap<OutboundRequestId, ResponseChannel<PunchReply>>,
+    /// What each connected peer agreed to in the handshake
+    negotiated: HashMap<PeerId, Negotiated>,
+    metrics: NetworkMetrics,
+    /// Ids of the gossip handled lately, ours included
+    seen: SeenCache,
+    throttle: Throttle,
+    outbox: Outbox<Outgoing>,
+    /// Sync requests were held back for download bandwidth
+    sync_deferred: bool,
+    /// Messages ready for the event sender
+    deliveries: Vec<Received>,
+    /// Whether we subscribe to drama and rumors
+    social: bool,
+    /// Set once the node shuts down
+    shutdown: Option<watch::Receiver<bool>>,
+    /// What the node has for the network while it runs
+    broadcasts: Option<mpsc::Receiver<Broadcast>>,
 }
 
 impl ChainNetwork {
     pub async fn new(
         keypair: Keypair,
-        event_sender: mpsc::Sender<NetworkMessage>,
+        event_sender: mpsc::Sender<Received>,
+    ) -> Result<Self, Box<dyn StdError>> {
+        Self::with_discovery(keypair, event_sender, DiscoveryConfig::default()).await
+    }
+
+    /// A network that finds its peers as `discovery` says
+    pub async fn with_discovery(
+        keypair: Keypair,
+        event_sender: mpsc::Sender<Received>,
+        discovery: DiscoveryConfig,
     ) -> Result<Self, Box<dyn StdError>> {
         let peer_id = PeerId::from(keypair.public());
         let topics = NetworkTopics::default();
+        let allowlist = Allowlist::new(discovery.allowlist.clone())?;
 
-        // Set up gossipsub
+        // Every message is signed by its author, and held back until the
+        // validators had their say
         let gossipsub_config = gossipsub::ConfigBuilder::default()
             .heartbeat_interval(Duration::from_secs(1))
             .validation_mode(ValidationMode::Strict)
+            .validate_messages()
+            .max_transmit_size(MAX_TRANSMIT_SIZE)
+            .max_messages_per_rpc(Some(MAX_MESSAGES_PER_RPC))
+            // Echoes older than this are caught by our own seen cache
+            .duplicate_cache_time(Duration::from_secs(60))
+            // The same content is the same message, whoever relays it
+            .message_id_fn(|message: &gossipsub::Message| {
+                let mut hasher = Sha256::new();
+                hasher.update(message.topic.as_str().as_bytes());
+                hasher.update(&message.data);
+                MessageId::from(hasher.finalize().to_vec())
+            })
             .build()
             .expect("Valid config");
 
@@ -215,28 +519,61 @@ impl ChainNetwork {
 
         // Set up other behaviours
         let identify = identify::Behaviour::new(identify::Config::new(
-            "chaoschain/1.0.0".to_string(),
+            handshake::PROTOCOL_VERSION.to_string(),
             keypair.public(),
         ));
         let ping = ping::Behaviour::new(ping::Config::new());
-        let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)?;
+        // A validator behind sentries finds no peers of its own, and opens
+        // no ports on the router
+        let hidden = !discovery.sentry.sentries.is_empty();
+        let mdns = if discovery.mdns && !hidden {
+            Some(mdns::tokio::Behaviour::new(
+                mdns::Config::default(),
+                peer_id,
+            )?)
+        } else {
+            None
+        };
 
         // Create network behaviour
         let behaviour = ChainBehaviour {
             gossipsub,
             identify,
             ping,
-            mdns,
+            mdns: mdns.into(),
+            discovery: discovery::behaviour(),
+            sync: sync::behaviour(),
+            upnp: (discovery.nat.upnp && !hidden)
+                .then(upnp::tokio::Behaviour::default)
+                .into(),
+            nat: nat::behaviour(),
+            handshake: handshake::behaviour(),
+            overlay: overlay::behaviour(),
+            pex: pex::behaviour(),
+            holepunch: holepunch::behaviour(),
         };
+        let nat_status = NatStatus::new(discovery.nat.clone());
+        let holepunch = HolePunches::new(discovery.nat.hole_punch.clone());
 
-        // Set up transport
-        let transport = tcp::tokio::Transport::new(tcp::Config::default())
+        // Noise XX binds every connection to the peer's identity key, so the
+        // peer ids the swarm reports are authenticated. Browsers come in over
+        // WebSocket, with the same upgrades.
+        let stream = tcp::tokio::Transport::new(tcp::Config::default().port_reuse(true))
+            .or_transport(WsTransport::new(discovery.websocket.clone()))
             .upgrade(upgrade::Version::V1)
             .authenticate(
                 noise::Config::new(&keypair)
                     .expect("signing libp2p-noise static DH keypair failed"),
             )
-            .multiplex(yamux::Config::default())
+            .multiplex(yamux::Config::default());
+        // QUIC brings its own TLS handshake with the same key, and its own
+        // streams. Each dial takes the transport its address names.
+        let transport = quic::tokio::Transport::new(quic::Config::new(&keypair))
+            .or_transport(stream)
+            .map(|either, _| match either {
+                Either::Left((peer, muxer)) => (peer, StreamMuxerBox::new(muxer)),
+                Either::Right((peer, muxer)) => (peer, StreamMuxerBox::new(muxer)),
+            })
             .boxed();
 
         // Build the swarm
@@ -247,70 +584,2164 @@ impl ChainNetwork {
             SwarmConfig::with_tokio_executor(),
         );
 
+        // Sentries are kept connected like static peers
+        let mut static_peers =
+            StaticPeers::new(discovery.static_peers.clone(), discovery.static_backoff);
+        for address in &discovery.sentry.sentries {
+            static_peers.add(address.clone());
+        }
+
         Ok(Self {
             swarm,
             topics,
             event_sender,
+            limits: GossipLimits::default(),
+            validators: HashMap::new(),
+            routing: RoutingTable::new(&peer_id, discovery.bucket_size),
+            static_peers,
+            pex: PeerExchange::new(discovery.pex.clone()),
+            sentries: Sentries::new(&discovery.sentry),
+            allowlist,
+            agent_keys: Arc::new(AgentKeys::default()),
+            slots: Slots::new(discovery.connections.clone()),
+            discovery,
+            connected: HashSet::new(),
+            overlay: Overlay::default(),
+            scores: PeerScores::default(),
+            peer_store: None,
+            last_seen: HashMap::new(),
+            chain: None,
+            syncer: Syncer::default(),
+            sync_status: SyncStatus::default(),
+            relay: CompactRelay::default(),
+            snapshot_store: None,
+            snapshot_target: None,
+            snapshots: SnapshotDownload::default(),
+            nat: nat_status,
+            remotes: HashMap::new(),
+            dial_backs: HashMap::new(),
+            holepunch,
+            introductions: HashMap::new(),
+            negotiated: HashMap::new(),
+            metrics: NetworkMetrics::default(),
+            seen: SeenCache::default(),
+            throttle: Throttle::default(),
+            outbox: Outbox::default(),
+            sync_deferred: false,
+            social: true,
+            deliveries: Vec::new(),
+            shutdown: None,
+            broadcasts: None,
         })
     }
 
+    /// Size limits received messages are held to
+    pub fn with_limits(mut self, limits: GossipLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Stay out of drama and rumors. They are neither received nor relayed,
+    /// for nodes that only care about consensus.
+    pub fn without_social(mut self) -> Self {
+        self.social = false;
+        self
+    }
+
+    /// Drain the connections and return from [`ChainNetwork::start`] once
+    /// `shutdown` turns true, or its sender is dropped
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Send what comes in on `broadcasts` once [`ChainNetwork::start`]
+    /// runs, which keeps the network to itself until it returns
+    pub fn with_broadcasts(mut self, broadcasts: mpsc::Receiver<Broadcast>) -> Self {
+        self.broadcasts = Some(broadcasts);
+        self
+    }
+
+    /// Check social gossip against `keys`, shared with whoever else needs
+    /// to know which agent holds which key
+    pub fn with_agent_keys(mut self, keys: Arc<AgentKeys>) -> Self {
+        self.agent_keys = keys;
+        self
+    }
+
+    /// How peers are scored, and where their bans are kept
+    pub fn with_scoring(mut self, scores: PeerScores) -> Self {
+        self.scores = scores;
+        self
+    }
+
+    /// Join the overlay of the validators in `config`. They are kept
+    /// connected like static peers, and get our votes and proposals
+    /// directly.
+    pub fn with_overlay(mut self, config: OverlayConfig) -> Self {
+        self.overlay = Overlay::new(&config);
+        for address in config.validators {
+            self.static_peers.add(address);
+        }
+        self
+    }
+
+    /// Remember peers in `store` across restarts, and reconnect to the ones
+    /// it has on start
+    pub fn with_peer_store(mut self, store: Arc<dyn PeerStore>) -> Self {
+        self.peer_store = Some(store);
+        self
+    }
+
+    /// Serve `chain` to peers, and sync it from them when they are ahead
+    pub fn with_sync(mut self, chain: Arc<dyn ChainSource>, config: SyncConfig) -> Self {
+        self.chain = Some(chain);
+        self.syncer = Syncer::new(config);
+        self
+    }
+
+    /// Keep `status` up to date with the sync, for the producer and the
+    /// consensus engine to hold off while the node is far behind
+    pub fn with_sync_status(mut self, status: SyncStatus) -> Self {
+        self.sync_status = status;
+        self
+    }
+
+    /// Rebuild compact blocks from the transactions in `pool`
+    pub fn with_mempool(mut self, pool: Arc<dyn TransactionPool>) -> Self {
+        self.relay = CompactRelay::new(pool);
+        self
+    }
+
+    /// Upload and download limits, per peer and overall
+    pub fn with_bandwidth(mut self, config: BandwidthConfig) -> Self {
+        self.throttle = Throttle::new(config);
+        self
+    }
+
+    /// Count into `metrics`, which the node may have registered already
+    pub fn with_metrics(mut self, metrics: NetworkMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Serve the snapshots in `store` to peers
+    pub fn with_snapshot_store(mut self, store: Arc<dyn SnapshotStore>) -> Self {
+        self.snapshot_store = Some(store);
+        self
+    }
+
+    /// Start an empty chain from a snapshot downloaded from peers, restored
+    /// into `target`
+    pub fn with_snapshot_sync(
+        mut self,
+        target: Arc<dyn SnapshotTarget>,
+        config: SnapshotConfig,
+    ) -> Self {
+        self.snapshot_target = Some(target);
+        self.snapshots = SnapshotDownload::new(config);
+        self
+    }
+
+    /// Add a check for the messages of a topic. Validators run in the order
+    /// they were added, the first one not accepting decides.
+    pub fn with_validator(
+        mut self,
+        topic: GossipTopic,
+        validator: Arc<dyn TopicValidator>,
+    ) -> Self {
+        self.validators.entry(topic).or_default().push(validator);
+        self
+    }
+
+    /// Count a message from `peer` against its rate limit. Drama and rumors
+    /// have a limit of their own, so they never crowd out consensus.
+    fn within_rate(&mut self, peer: PeerId, priority: Priority) -> bool {
+        if priority == Priority::Social {
+            self.scores.within_social_rate(peer, SystemTime::now())
+        } else {
+            self.scores.within_rate(peer, SystemTime::now())
+        }
+    }
+
+    /// Decode and validate a received message. Rejections name the
+    /// offense they are held against the sender as.
+    fn validate(
+        &self,
+        source: &PeerId,
+        message: &gossipsub::Message,
+    ) -> Result<NetworkMessage, (Validation, Offense)> {
+        let reject = |offense, reason: String| Err((Validation::Reject(reason), offense));
+        let Some(topic) = self.topics.of(&message.topic) else {
+            return reject(
+                Offense::Malformed,
+                format!("Unknown topic {}", message.topic),
+            );
+        };
+        let (codec, payload) = match wire::gossip_payload(topic, &message.data) {
+            Ok(decoded) => decoded,
+            Err((offense, reason)) => return reject(offense, reason),
+        };
+        self.metrics
+            .gossip("received", codec, payload.len(), message.data.len());
+        let decoded: NetworkMessage = match serde_json::from_slice(&payload) {
+            Ok(decoded) => decoded,
+            // A message type from a newer version, not the sender's fault
+            Err(e) if e.to_string().contains("unknown variant") => {
+                return Err((Validation::Ignore, Offense::Malformed));
+            }
+            Err(e) => return reject(Offense::Malformed, format!("Undecodable message: {}", e)),
+        };
+        self.vet(source, topic, decoded)
+    }
+
+    /// The checks every message goes through, however it arrived
+    fn vet(
+        &self,
+        source: &PeerId,
+        topic: GossipTopic,
+        decoded: NetworkMessage,
+    ) -> Result<NetworkMessage, (Validation, Offense)> {
+        let reject = |offense, reason: String| Err((Validation::Reject(reason), offense));
+        if decoded.topic() != topic {
+            return reject(
+                Offense::Malformed,
+                format!("{} message on {}", decoded.topic(), topic),
+            );
+        }
+        if let Err(e) = decoded.check(&self.limits) {
+            return reject(Offense::of(&e), e.to_string());
+        }
+        // Social gossip must be signed by the agent it speaks for
+        if decoded.is_social() {
+            return reject(
+                Offense::Unsigned,
+                format!("Unsigned {} message", decoded.topic()),
+            );
+        }
+        if let NetworkMessage::Signed(envelope) = &decoded {
+            if let Err(e) = self.agent_keys.attribute(envelope) {
+                return reject(Offense::InvalidSignature, e.to_string());
+            }
+        }
+        for validator in self.validators.get(&topic).into_iter().flatten() {
+            match validator.validate(source, &decoded) {
+                Validation::Accept => {}
+                verdict => return Err((verdict, Offense::Rejected)),
+            }
+        }
+        Ok(decoded)
+    }
+
+    /// Whether an event of a request-response protocol is handled. A peer
+    /// past its message rate has its requests dropped unanswered. Requests
+    /// and responses cut short by the size limit of their protocol count as
+    /// spam, ones that did not decode as malformed.
+    fn admit<Req, Resp>(&mut self, event: &request_response::Event<Req, Resp>) -> bool {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Request { .. },
+            } => {
+                if self.scores.within_rate(*peer, SystemTime::now()) {
+                    return true;
+                }
+                debug!("Dropping request from {} past the rate limit", peer);
+                self.judge(*peer, Some(Offense::Spam));
+                false
+            }
+            request_response::Event::InboundFailure {
+                peer,
+                error: request_response::InboundFailure::Io(e),
+                ..
+            }
+            | request_response::Event::OutboundFailure {
+                peer,
+                error: request_response::OutboundFailure::Io(e),
+                ..
+            } if e.kind() == io::ErrorKind::InvalidData => {
+                debug!("Unreadable message from {}: {}", peer, e);
+                let offense = if Oversized::is(e) {
+                    Offense::Spam
+                } else {
+                    Offense::Malformed
+                };
+                self.judge(*peer, Some(offense));
+                true
+            }
+            _ => true,
+        }
+    }
+
+    /// Score a message from `peer`, banning and disconnecting it once it
+    /// sent too much rubbish
+    fn judge(&mut self, peer: PeerId, offense: Option<Offense>) {
+        let now = SystemTime::now();
+        let Some(offense) = offense else {
+            self.scores.useful(peer, now);
+            return;
+        };
+        if let Some(ban) = self.scores.offense(peer, offense, now) {
+            if self.static_peers.contains(&peer) {
+                warn!("Static peer {} earned a ban, staying connected", peer);
+                return;
+            }
+            warn!(
+                "Banning peer {} until {} for {:?}",
+                peer, ban.until, ban.offense
+            );
+            self.routing.remove(&peer);
+            let _ = self.swarm.disconnect_peer_id(peer);
+        }
+    }
+
+    pub fn local_peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    /// Scores and bans of the peers heard from
+    pub fn scores(&self) -> &PeerScores {
+        &self.scores
+    }
+
+    /// Peers currently connected
+    pub fn connected_peers(&self) -> usize {
+        self.connected.len()
+    }
+
+    /// Whether we are syncing or caught up with our peers
+    pub fn sync_state(&self) -> SyncState {
+        self.sync_status.state()
+    }
+
+    /// How the links to our sentries are doing, empty unless we hide
+    /// behind sentries
+    pub fn sentry_health(&self) -> Vec<SentryHealth> {
+        self.sentries.health(Instant::now())
+    }
+
+    /// Read the allowlist file again if it changed, and show out the
+    /// connected peers that are no longer on it
+    fn reload_allowlist(&mut self) {
+        match self.allowlist.reload() {
+            Ok(false) => {}
+            Ok(true) => {
+                info!("Allowlist reloaded, {} peers allowed", self.allowlist.len());
+                let unlisted: Vec<PeerId> = self
+                    .connected
+                    .iter()
+                    .filter(|peer| !self.allowlist.allows(peer))
+                    .copied()
+                    .collect();
+                for peer in unlisted {
+                    info!("Disconnecting {}, no longer on the allowlist", peer);
+                    let _ = self.swarm.disconnect_peer_id(peer);
+                }
+            }
+            Err(e) => warn!("Keeping the old allowlist: {}", e),
+        }
+    }
+
+    /// How the connection slots are taken
+    pub fn connection_slots(&self) -> SlotStatus {
+        self.slots.status()
+    }
+
+    /// Find a slot for a new connection to `peer`, evicting an inbound peer
+    /// if the policy says so. False when the connection has to go.
+    fn admit_connection(&mut self, peer: PeerId, endpoint: &ConnectedPoint) -> bool {
+        let reserved = self.static_peers.contains(&peer)
+            || self.overlay.is_member(&peer)
+            || self.sentries.is_sentry(&peer)
+            || self.sentries.is_private(&peer);
+        match self.slots.admit(
+            peer,
+            endpoint.is_listener(),
+            reserved,
+            &mut self.scores,
+            Instant::now(),
+        ) {
+            Admission::Accept => true,
+            Admission::Reject => {
+                debug!("No connection slot left for {}", peer);
+                false
+            }
+            Admission::Evict(victim) => {
+                debug!("Evicting {} to make room for {}", victim, peer);
+                let _ = self.swarm.disconnect_peer_id(victim);
+                true
+            }
+        }
+    }
+
+    /// Warn when a validator behind sentries lost its way to the network
+    fn check_sentries(&self) {
+        if !self.sentries.is_hidden() {
+            return;
+        }
+        let now = Instant::now();
+        for sentry in self.sentries.health(now).iter().filter(|s| !s.healthy) {
+            warn!(
+                "Sentry {} at {} is unhealthy (connected: {}, silent for {:?}s)",
+                sentry.peer_id, sentry.address, sentry.connected, sentry.silent_for
+            );
+        }
+        if self.sentries.healthy(now) == 0 {
+            warn!("No healthy sentry left, this validator is cut off from the network");
+        }
+    }
+
+    /// Peers known from discovery, connected or not
+    pub fn known_peers(&self) -> usize {
+        self.routing.len()
+    }
+
+    /// Take in a peer found by mDNS, a lookup or identify, and dial it while
+    /// there is room for more connections
+    fn discovered(&mut self, peer: PeerId, address: Multiaddr) {
+        if peer == *self.swarm.local_peer_id() || self.scores.is_banned(&peer, SystemTime::now()) {
+            return;
+        }
+        // Hidden validators are never passed on, and never dial strangers
+        if !self.allowlist.allows(&peer)
+            || self.sentries.is_private(&peer)
+            || (self.sentries.is_hidden() && !self.static_peers.contains(&peer))
+        {
+            return;
+        }
+        if self.routing.insert(peer, address.clone()) {
+            debug!("Discovered peer {} at {}", peer, address);
+        }
+        self.swarm.add_peer_address(peer, address.clone());
+        if self.connected.contains(&peer)
+            || self.connected.len() >= self.discovery.max_peers
+            || self.slots.outbound_full()
+        {
+            return;
+        }
+        let dial = DialOpts::peer_id(peer)
+            .addresses(vec![address])
+            .condition(PeerCondition::DisconnectedAndNotDialing)
+            .build();
+        if let Err(e) = self.swarm.dial(dial) {
+            debug!("Could not dial {}: {}", peer, e);
+        }
+    }
+
+    /// Ask the known peers closest to a random target, and to ourselves, for
+    /// the peers they know. Answers are fed back into [`Self::discovered`].
+    fn lookup(&mut self) {
+        let local = *self.swarm.local_peer_id();
+        for target in [local, PeerId::random()] {
+            for (peer, _) in self.routing.closest(&target, discovery::LOOKUP_PARALLELISM) {
+                self.swarm.behaviour_mut().discovery.send_request(
+                    &peer,
+                    FindPeers {
+                        target: target.to_string(),
+                    },
+                );
+            }
+        }
+    }
+
+    fn handle_discovery(&mut self, event: request_response::Event<FindPeers, discovery::Peers>) {
+        match event {
+            request_response::Event::Message {
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+                ..
+            } => {
+                let peers = match request.target.parse::<PeerId>() {
+                    Ok(target) => self.routing.closest(&target, self.discovery.bucket_size),
+                    Err(_) => Vec::new(),
+                };
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .discovery
+                    .send_response(channel, discovery::records(peers));
+            }
+            request_response::Event::Message {
+                message: request_response::Message::Response { response, .. },
+                ..
+            } => {
+                for (peer, addresses) in response.peers.iter().filter_map(|record| record.parse()) {
+                    for address in addresses {
+                        self.discovered(peer, address);
+                    }
+                }
+            }
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                debug!("Lookup through {} failed: {}", peer, error);
+                if !self.connected.contains(&peer) {
+                    self.routing.remove(&peer);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Up to [`pex::PexConfig::max_shared`] of the connected peers worth
+    /// introducing to `to`, best scored first. Peers we hold something
+    /// against, or have no address for, are left out.
+    fn shared_peers(&mut self, to: &PeerId) -> discovery::Peers {
+        if !self.pex.config().enabled || self.sentries.is_hidden() {
+            return discovery::records(Vec::new());
+        }
+        let now = SystemTime::now();
+        let mut peers: Vec<(f64, PeerId, Vec<Multiaddr>)> = Vec::new();
+        for peer in self.connected.clone() {
+            let score = self.scores.score(&peer, now);
+            let addresses = self.routing.addresses(&peer);
+            if peer == *to || score < 0.0 || addresses.is_empty() || self.sentries.is_private(&peer)
+            {
+                continue;
+            }
+            peers.push((score, peer, addresses));
+        }
+        peers.sort_by(|a, b| b.0.total_cmp(&a.0));
+        discovery::records(
+            peers
+                .into_iter()
+                .take(self.pex.config().max_shared)
+                .map(|(_, peer, addresses)| (peer, addresses))
+                .collect(),
+        )
+    }
+
+    /// Offer our peers to every connected peer that takes part in the
+    /// exchange. Their answers carry theirs.
+    fn exchange_peers(&mut self) {
+        if !self.pex.config().enabled || self.sentries.is_hidden() {
+            return;
+        }
+        for peer in self.connected.clone() {
+            if self.supports(&peer, Capability::PeerExchange) {
+                let offer = self.shared_peers(&peer);
+                self.swarm.behaviour_mut().pex.send_request(&peer, offer);
+            }
+        }
+    }
+
+    /// Take in the peers `from` introduced, as far as the exchange lets it
+    fn exchanged(&mut self, from: PeerId, peers: &discovery::Peers) {
+        for (peer, addresses) in self.pex.accept(from, peers, Instant::now()) {
+            for address in addresses {
+                self.discovered(peer, address);
+            }
+        }
+    }
+
+    fn handle_pex(&mut self, event: request_response::Event<discovery::Peers, discovery::Peers>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                let offer = self.shared_peers(&peer);
+                let _ = self.swarm.behaviour_mut().pex.send_response(channel, offer);
+                self.exchanged(peer, &request);
+            }
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response { response, .. },
+            } => self.exchanged(peer, &response),
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                debug!("Peer exchange with {} failed: {}", peer, error);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_sync(&mut self, event: request_response::Event<SyncRequest, SyncResponse>) {
+        match event {
+            request_response::Event::Message {
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+                peer,
+            } => {
+                let priority = Priority::of_request(&request);
+                let announced = match &request {
+                    SyncRequest::Transactions {
+                        block_hash,
+                        indexes,
+                    } => self
+                        .relay
+                        .recent(block_hash)
+                        .map(|block| sync::transactions_at(block, indexes)),
+                    _ => None,
+                };
+                let store = self.snapshot_store.as_ref();
+                let response = match (&self.chain, announced, request) {
+                    (_, _, SyncRequest::SnapshotManifests) => SyncResponse::SnapshotManifests(
+                        store.map(|store| store.manifests()).unwrap_or_default(),
+                    ),
+                    (_, _, SyncRequest::SnapshotChunk { snapshot, index }) => {
+                        SyncResponse::SnapshotChunk(
+                            store.and_then(|store| store.chunk(&snapshot, index)),
+                        )
+                    }
+                    (_, Some(transactions), _) => SyncResponse::Transactions(transactions),
+                    (Some(chain), None, request) => sync::serve(chain.as_ref(), request),
+                    (None, None, _) => SyncResponse::Status {
+                        height: None,
+                        head: [0; 32],
+                    },
+                };
+                self.respond(peer, priority, channel, response);
+            }
+            request_response::Event::Message {
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+                peer,
+            } => {
+                let bytes = serde_json::to_vec(&response).map_or(0, |data| data.len());
+                self.throttle.received(&peer, bytes, Instant::now());
+                self.metrics.peer_bytes(&peer, "received", bytes);
+                if let Some(incomplete) = self.relay.take(&request_id) {
+                    self.completed(peer, incomplete, response);
+                    return;
+                }
+                if self.snapshots.owns(&request_id) {
+                    match response {
+                        SyncResponse::SnapshotManifests(manifests) => {
+                            self.snapshots.offered(&request_id, manifests);
+                        }
+                        SyncResponse::SnapshotChunk(chunk) => {
+                            if let Some((peer, result)) =
+                                self.snapshots.answered(&request_id, chunk)
+                            {
+                                self.judge(peer, result.err());
+                            }
+                        }
+                        _ => {
+                            self.snapshots.failed(&request_id);
+                            self.judge(peer, Some(Offense::Malformed));
+                        }
+                    }
+                    self.drive_snapshots();
+                    return;
+                }
+                if let Some((peer, result)) = self.syncer.answered(request_id, response) {
+                    self.judge(peer, result.err());
+                }
+                self.drive_sync();
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                debug!("Sync request to {} failed: {}", peer, error);
+                if let Some(incomplete) = self.relay.take(&request_id) {
+                    debug!(
+                        "Dropping block {} announced by {}",
+                        hex::encode(incomplete.partial.hash()),
+                        peer
+                    );
+                }
+                self.snapshots.failed(&request_id);
+                self.syncer.failed(request_id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Rebuild an announced block, asking the announcing peer for the
+    /// transactions we do not have
+    fn rebuild(&mut self, author: PeerId, relayed_by: PeerId, compact: &CompactBlock) {
+        let partial = self.relay.reconstruct(compact);
+        let indexes = partial.missing();
+        if indexes.is_empty() {
+            self.deliver_block(author, relayed_by, &partial);
+            return;
+        }
+        debug!(
+            "Block {} misses {} of {} transactions, asking {}",
+            compact.header.height,
+            indexes.len(),
+            compact.short_ids.len(),
+            relayed_by
+        );
+        let id = self.swarm.behaviour_mut().sync.send_request(
+            &relayed_by,
+            SyncRequest::Transactions {
+                block_hash: compact.hash,
+                indexes: indexes.clone(),
+            },
+        );
+        self.relay.wait(
+            id,
+            Incomplete {
+                author,
+                partial,
+                indexes,
+            },
+        );
+    }
+
+    /// The missing transactions of a block came in
+    fn completed(&mut self, peer: PeerId, mut incomplete: Incomplete, response: SyncResponse) {
+        let SyncResponse::Transactions(transactions) = response else {
+            self.judge(peer, Some(Offense::Malformed));
+            return;
+        };
+        if let Err(e) = incomplete.partial.fill(&incomplete.indexes, transactions) {
+            warn!("Peer {} sent the wrong transactions: {}", peer, e);
+            self.judge(peer, Some(Offense::of(&e)));
+            return;
+        }
+        self.deliver_block(incomplete.author, peer, &incomplete.partial);
+    }
+
+    fn deliver_block(&mut self, author: PeerId, relayed_by: PeerId, partial: &PartialBlock) {
+        match partial.complete() {
+            // Checked as a block gossiped in full would be
+            Some(Ok(block)) => {
+                match self.vet(
+                    &author,
+                    GossipTopic::Blocks,
+                    NetworkMessage::NewBlock(block),
+                ) {
+                    Ok(message) => self.deliveries.push(Received {
+                        author,
+                        relayed_by,
+                        message,
+                    }),
+                    Err((Validation::Reject(reason), offense)) => {
+                        warn!("Block from {} rejected: {}", relayed_by, reason);
+                        self.judge(relayed_by, Some(offense));
+                    }
+                    Err(_) => {}
+                }
+            }
+            Some(Err(e)) => {
+                warn!("Block from {} does not add up: {}", relayed_by, e);
+                self.judge(relayed_by, Some(Offense::of(&e)));
+            }
+            None => {}
+        }
+    }
+
+    /// Import the blocks that are in, then keep the pipeline full
+    fn drive_sync(&mut self) {
+        let Some(chain) = self.chain.clone() else {
+            return;
+        };
+        if self.snapshots.is_active() {
+            return;
+        }
+        let ready = self.syncer.ready(chain.head());
+        if let Some(peer) = ready.forked {
+            warn!("Peer {} is on another chain", peer);
+            self.judge(peer, Some(Offense::InvalidBlock));
+        }
+        if let Some((peer, e)) = ready.invalid {
+            warn!("Peer {} sent a certificate that does not hold: {}", peer, e);
+            self.judge(peer, Some(Offense::InvalidBlock));
+        }
+        for (block, peer) in ready.blocks {
+            if let Err(e) = chain.import(&block) {
+                warn!(
+                    "Could not import block {} from {}: {}",
+                    block.height, peer, e
+                );
+                self.judge(peer, Some(Offense::InvalidBlock));
+                break;
+            }
+            info!("📥 Synced block {} from {}", block.height, peer);
+        }
+        let head = chain.head().map(|(height, _)| height);
+        self.sync_status.set(self.syncer.state(head));
+        if !self.throttle.download_room(Priority::Bulk, Instant::now()) {
+            self.sync_deferred = true;
+            return;
+        }
+        for (peer, request) in self.syncer.plan(head) {
+            let id = self
+                .swarm
+                .behaviour_mut()
+                .sync
+                .send_request(&peer, request.clone());
+            self.syncer.sent(id, peer, request);
+        }
+    }
+
+    /// Pick a snapshot once peers offered some, fetch its chunks, and restore
+    /// it once they are all in
+    fn drive_snapshots(&mut self) {
+        let Some(target) = self.snapshot_target.clone() else {
+            return;
+        };
+        if let Some(manifest) = self.snapshots.choose() {
+            info!(
+                "📦 Downloading snapshot at height {} in {} chunks",
+                manifest.height, manifest.chunk_count
+            );
+        }
+        if !self.throttle.download_room(Priority::Bulk, Instant::now()) {
+            self.sync_deferred = true;
+            return;
+        }
+        for (peer, request) in self.snapshots.plan() {
+            let id = self
+                .swarm
+                .behaviour_mut()
+                .sync
+                .send_request(&peer, request.clone());
+            self.snapshots.sent(id, peer, &request);
+        }
+        match self.snapshots.finished() {
+            Some(Ok(snapshot)) => {
+                let height = snapshot.height;
+                match target.restore(snapshot) {
+                    Ok(()) => {
+                        info!("📦 Restored state from snapshot at height {}", height);
+                        self.snapshot_target = None;
+                    }
+                    Err(e) => warn!("Could not restore snapshot at height {}: {}", height, e),
+                }
+                self.drive_sync();
+            }
+            Some(Err(e)) => warn!("Could not assemble snapshot: {}", e),
+            None => {}
+        }
+    }
+
+    /// Answer a sync request now, or once there is upload bandwidth for it
+    fn respond(
+        &mut self,
+        peer: PeerId,
+        priority: Priority,
+        channel: ResponseChannel<SyncResponse>,
+        response: SyncResponse,
+    ) {
+        let bytes = serde_json::to_vec(&response).map_or(0, |data| data.len());
+        self.metrics.peer_bytes(&peer, "sent", bytes);
+        if self.outbox.is_waiting(priority)
+            || !self
+                .throttle
+                .upload(Some(&peer), bytes, priority, Instant::now())
+        {
+            self.outbox.push(
+                priority,
+                Some(peer),
+                bytes,
+                Outgoing::Respond(channel, response),
+            );
+            return;
+        }
+        let _ = self
+            .swarm
+            .behaviour_mut()
+            .sync
+            .send_response(channel, response);
+    }
+
+    /// Send whatever the bandwidth limits let through by now
+    fn release_outbox(&mut self) {
+        for outgoing in self.outbox.release(&mut self.throttle, Instant::now()) {
+            match outgoing {
+                Outgoing::Publish(topic, data) => {
+                    let topic = self.topics.topic(topic).clone();
+                    match self.swarm.behaviour_mut().gossipsub.publish(topic, data) {
+                        Ok(id) => {
+                            self.seen.insert(&id, Instant::now());
+                        }
+                        Err(e) => debug!("Could not publish held back message: {}", e),
+                    }
+                }
+                Outgoing::Respond(channel, response) => {
+                    let _ = self
+                        .swarm
+                        .behaviour_mut()
+                        .sync
+                        .send_response(channel, response);
+                }
+            }
+        }
+    }
+
+    fn supports(&self, peer: &PeerId, capability: Capability) -> bool {
+        self.negotiated
+            .get(peer)
+            .is_some_and(|negotiated| negotiated.supports(capability))
+    }
+
+    /// Expose the network metrics through the node's metrics registry
+    pub fn register_metrics(&self, registry: &MetricsRegistry) {
+        self.metrics.register(registry);
+    }
+
+    /// What `peer` agreed to in the handshake, once it is done
+    pub fn negotiated(&self, peer: &PeerId) -> Option<&Negotiated> {
+        self.negotiated.get(peer)
+    }
+
+    fn handle_handshake(&mut self, event: request_response::Event<Hello, Hello>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Request { .. },
+            } if !self.allowlist.allows(&peer) => {
+                let _ = self.swarm.disconnect_peer_id(peer);
+            }
+            request_response::Event::Message {
+                message: request_response::Message::Request { channel, .. },
+                ..
+            } => {
+                // The peer settles on its side once it has our answer
+                let hello = self.hello();
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .handshake
+                    .send_response(channel, hello);
+            }
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response { response, .. },
+            } => self.handshaken(peer, &response),
+            request_response::Event::OutboundFailure {
+                peer,
+                error: request_response::OutboundFailure::UnsupportedProtocols,
+                ..
+            } => self.handshaken(peer, &Hello::legacy()),
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                debug!("Handshake with {} failed: {}", peer, error);
+                self.metrics.handshake_failed("error");
+            }
+            _ => {}
+        }
+    }
+
+    /// What we tell peers we have: what the network is set up for. A node
+    /// without a mempool gets full blocks, one without a chain is not asked
+    /// to sync.
+    pub fn hello(&self) -> Hello {
+        let mut capabilities = vec![Capability::Votes, Capability::DialBack, Capability::Snappy];
+        if self.relay.has_pool() {
+            capabilities.push(Capability::CompactBlocks);
+        }
+        if self.chain.is_some() {
+            capabilities.push(Capability::Sync);
+        }
+        if self.snapshot_store.is_some() || self.snapshot_target.is_some() {
+            capabilities.push(Capability::Snapshots);
+        }
+        if self.discovery.pex.enabled {
+            capabilities.push(Capability::PeerExchange);
+        }
+        let hole_punch = &self.discovery.nat.hole_punch;
+        if hole_punch.enabled || hole_punch.coordinate {
+            capabilities.push(Capability::HolePunch);
+        }
+        Hello::new(capabilities)
+    }
+
+    /// Refuse a peer of another major version, or start using what we have
+    /// in common with it
+    fn handshaken(&mut self, peer: PeerId, hello: &Hello) {
+        if !self.allowlist.allows(&peer) {
+            self.metrics.handshake_failed("allowlist");
+            let _ = self.swarm.disconnect_peer_id(peer);
+            return;
+        }
+        let negotiated = match handshake::negotiate(&self.hello(), hello) {
+            Ok(negotiated) => negotiated,
+            Err(reason) => {
+                warn!("Refusing peer {}: {}", peer, reason);
+                self.metrics.handshake_failed("version");
+                let _ = self.swarm.disconnect_peer_id(peer);
+                return;
+            }
+        };
+        if negotiated.version < handshake::PROTOCOL_VERSION {
+            info!(
+                "Peer {} speaks {}, degrading to {:?}",
+                peer, negotiated.version, negotiated.capabilities
+            );
+        }
+        self.negotiated.insert(peer, negotiated);
+        self.request_snapshots(peer);
+        self.request_status(peer);
+    }
+
+    fn handle_overlay(&mut self, event: request_response::Event<NetworkMessage, Delivered>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                if !self.overlay.is_member(&peer) {
+                    debug!("Peer {} is not in the validator overlay", peer);
+                    return;
+                }
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .overlay
+                    .send_response(channel, Delivered);
+                let vetted = if Overlay::carries(&request) {
+                    self.vet(&peer, request.topic(), request)
+                } else {
+                    Err((
+                        Validation::Reject("Not an overlay message".to_string()),
+                        Offense::Malformed,
+                    ))
+                };
+                match vetted {
+                    Ok(NetworkMessage::CompactBlock(compact)) => {
+                        self.judge(peer, None);
+                        self.rebuild(peer, peer, &compact);
+                    }
+                    Ok(message) => {
+                        self.judge(peer, None);
+                        self.deliveries.push(Received {
+                            author: peer,
+                            relayed_by: peer,
+                            message,
+                        });
+                    }
+                    Err((Validation::Reject(reason), offense)) => {
+                        warn!("Rejected overlay message from {}: {}", peer, reason);
+                        self.judge(peer, Some(offense));
+                    }
+                    Err(_) => {}
+                }
+            }
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                debug!("Overlay message to {} failed: {}", peer, error);
+            }
+            _ => {}
+        }
+    }
+
+    /// Send `message` to the connected validators of the overlay. Returns
+    /// false when none are connected.
+    fn send_overlay(&mut self, message: &NetworkMessage) -> bool {
+        let members: Vec<PeerId> = self
+            .overlay
+            .members()
+            .filter(|peer| self.connected.contains(*peer))
+            .copied()
+            .collect();
+        for peer in &members {
+            self.swarm
+                .behaviour_mut()
+                .overlay
+                .send_request(peer, message.clone());
+        }
+        !members.is_empty()
+    }
+
+    /// Take back the scores and bans of the stored peers, and dial the best
+    /// of them
+    fn restore_peers(&mut self) {
+        let Some(store) = self.peer_store.clone() else {
+            return;
+        };
+        let now = SystemTime::now();
+        let stored = peer_store::retain(store.load(), unix_seconds(now));
+        info!("Reconnecting to {} stored peers", stored.len());
+        for record in stored {
+            let Ok(peer) = record.peer_id.parse::<PeerId>() else {
+                continue;
+            };
+            let seen = UNIX_EPOCH + Duration::from_secs(record.last_seen);
+            self.scores.restore(peer, record.score, seen);
+            if let Some(ban) = record.ban {
+                self.scores.restore_ban(peer, ban);
+            }
+            self.last_seen.insert(peer, record.last_seen);
+            for address in record.addresses.iter().filter_map(|a| a.parse().ok()) {
+                self.discovered(peer, address);
+            }
+        }
+    }
+
+    /// Save the peers we were connected to, with their scores and bans
+    fn persist_peers(&mut self) {
+        let Some(store) = self.peer_store.clone() else {
+            return;
+        };
+        let now = SystemTime::now();
+        let unix = unix_seconds(now);
+        let mut peers: HashSet<PeerId> = self.last_seen.keys().copied().collect();
+        peers.extend(
+            self.scores
+                .bans(now)
+                .iter()
+                .filter_map(|ban| ban.peer_id.parse::<PeerId>().ok()),
+        );
+        let records = peers
+            .into_iter()
+            .filter_map(|peer| {
+                let ban = self.scores.ban(&peer, now).cloned();
+                let addresses: Vec<String> = self
+                    .routing
+                    .addresses(&peer)
+                    .iter()
+                    .map(|address| address.to_string())
+                    .collect();
+                if addresses.is_empty() && ban.is_none() {
+                    return None;
+                }
+                let last_seen = if self.connected.contains(&peer) {
+                    unix
+                } else {
+                    self.last_seen.get(&peer).copied().unwrap_or(0)
+                };
+                Some(StoredPeer {
+                    peer_id: peer.to_string(),
+                    addresses,
+                    last_seen,
+                    score: self.scores.score(&peer, now),
+                    ban,
+                })
+            })
+            .collect();
+        store.save(&peer_store::retain(records, unix));
+    }
+
+    /// Dial the static peers that are down and done backing off
+    fn redial_static_peers(&mut self) {
+        for (index, address) in self.static_peers.due(Instant::now()) {
+            let dial = DialOpts::unknown_peer_id().address(address.clone()).build();
+            let connection_id = dial.connection_id();
+            match self.swarm.dial(dial) {
+                Ok(()) => self.static_peers.dialing(index, connection_id),
+                Err(e) => {
+                    debug!("Could not dial static peer {}: {}", address, e);
+                    self.static_peers.dial_failed(connection_id, Instant::now());
+                }
+            }
+        }
+    }
+
+    /// Ask one of our peers to dial us back
+    fn probe_nat(&mut self) {
+        let negotiated = &self.negotiated;
+        let capable = self.connected.iter().filter(|peer| {
+            negotiated
+                .get(*peer)
+                .is_some_and(|n| n.supports(Capability::DialBack))
+        });
+        if let Some((peer, request)) = self.nat.probe(capable) {
+            self.swarm.behaviour_mut().nat.send_request(&peer, request);
+        }
+    }
+
+    fn handle_nat(&mut self, event: request_response::Event<DialBack, DialBackResult>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                let address = self
+                    .remotes
+                    .get(&peer)
+                    .and_then(|remote| nat::dial_back_address(remote, &request));
+                let Some(address) = address else {
+                    let _ = self
+                        .swarm
+                        .behaviour_mut()
+                        .nat
+                        .send_response(channel, DialBackResult { reachable: None });
+                    return;
+                };
+                // A fresh connection, even though the peer is connected
+                let dial = DialOpts::peer_id(peer)
+                    .addresses(vec![address.clone()])
+                    .condition(PeerCondition::Always)
+                    .build();
+                let connection_id = dial.connection_id();
+                match self.swarm.dial(dial) {
+                    Ok(()) => {
+                        self.dial_backs.insert(connection_id, (address, channel));
+                    }
+                    Err(e) => {
+                        debug!("Could not dial back {} at {}: {}", peer, address, e);
+                        let _ = self
+                            .swarm
+                            .behaviour_mut()
+                            .nat
+                            .send_response(channel, DialBackResult { reachable: None });
+                    }
+                }
+            }
+            request_response::Event::Message {
+                message: request_response::Message::Response { response, .. },
+                ..
+            } => {
+                if let Some(reachability) = self.nat.outcome(response) {
+                    self.reachability_changed(reachability);
+                }
+            }
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                debug!("Dial back request to {} failed: {}", peer, error);
+            }
+            _ => {}
+        }
+    }
+
+    fn reachability_changed(&mut self, reachability: Reachability) {
+        match reachability {
+            Reachability::Public(address) => {
+                info!("🌍 Reachable from outside at {}", address);
+                self.swarm.add_external_address(address);
+            }
+            Reachability::Private => {
+                info!("🧱 Not reachable from outside, staying connected through relays");
+                self.keep_relays();
+            }
+            Reachability::Unknown => {
+                info!("🌫️ Reachability unknown again");
+            }
+        }
+    }
+
+    /// Dial the relay peers we are not connected to while we cannot be
+    /// reached. Relay addresses should end in `/p2p/<peer id>`, otherwise
+    /// they are dialed every time.
+    fn keep_relays(&mut self) {
+        if !self.nat.needs_relay() {
+            return;
+        }
+        for address in self.nat.config().relays.clone() {
+            let peer = address.iter().find_map(|protocol| match protocol {
+                Protocol::P2p(peer) => Some(peer),
+                _ => None,
+            });
+            if peer.is_some_and(|peer| self.connected.contains(&peer)) {
+                continue;
+            }
+            if let Err(e) = self.swarm.dial(address.clone()) {
+                warn!("Could not dial relay {}: {}", address, e);
+            }
+        }
+    }
+
+    /// Ask `relay` to put us in touch with `peer`, whose gossip it passed
+    /// on, while we cannot be reached and are not connected to `peer`
+    fn punch_towards(&mut self, peer: PeerId, relay: PeerId) {
+        if peer == relay
+            || self.connected.contains(&peer)
+            || !self.nat.needs_relay()
+            || self.sentries.is_hidden()
+            || self.slots.outbound_full()
+            || !self.allowlist.allows(&peer)
+            || self.scores.is_banned(&peer, SystemTime::now())
+            || !self.supports(&relay, Capability::HolePunch)
+            || !self.holepunch.try_punch(peer, Instant::now())
+        {
+            return;
+        }
+        debug!("Asking {} to help punch through to {}", relay, peer);
+        let id = self.swarm.behaviour_mut().holepunch.send_request(
+            &relay,
+            Punch::Connect {
+                target: peer.to_string(),
+            },
+        );
+        self.holepunch.asked(id, peer);
+    }
+
+    /// Dial the punches whose time has come
+    fn dial_punches(&mut self) {
+        for (peer, addresses) in self.holepunch.due(Instant::now()) {
+            self.punch(peer, addresses);
+        }
+    }
+
+    fn punch(&mut self, peer: PeerId, addresses: Vec<Multiaddr>) {
+        if self.connected.contains(&peer) || addresses.is_empty() {
+            return;
+        }
+        debug!("Punching through to {} at {:?}", peer, addresses);
+        let dial = DialOpts::peer_id(peer).addresses(addresses).build();
+        if let Err(e) = self.swarm.dial(dial) {
+            debug!("Could not punch through to {}: {}", peer, e);
+        }
+    }
+
+    /// Pass a punch request of `asker` on to `target`, when we are
+    /// connected to both
+    fn introduce(&mut self, asker: PeerId, target: &str, channel: ResponseChannel<PunchReply>) {
+        let target = target.parse::<PeerId>().ok().filter(|target| {
+            *target != asker
+                && self.connected.contains(target)
+                && self.supports(target, Capability::HolePunch)
+                && !self.sentries.is_private(target)
+        });
+        let observed = self.remotes.get(&asker).cloned();
+        let (Some(target), Some(observed), true) =
+            (target, observed, self.holepunch.config().coordinate)
+        else {
+            let _ = self.swarm.behaviour_mut().holepunch.send_response(
+                channel,
+                PunchReply::Refused {
+                    reason: "Cannot put you in touch".to_string(),
+                },
+            );
+            return;
+        };
+        let delay = holepunch::dial_delay(self.holepunch.rtt(&asker), self.holepunch.rtt(&target));
+        let id = self.swarm.behaviour_mut().holepunch.send_request(
+            &target,
+            Punch::Incoming {
+                from: asker.to_string(),
+                addresses: vec![observed.to_string()],
+                delay_ms: delay.as_millis() as u64,
+            },
+        );
+        self.introductions.insert(id, channel);
+    }
+
+    /// A relay says `from` wants a direct connection. Agree if we would
+    /// dial it ourselves, and dial it once the delay has passed.
+    fn accept_punch(
+        &mut self,
+        relay: PeerId,
+        from: &str,
+        addresses: &[String],
+        delay_ms: u64,
+    ) -> PunchReply {
+        let refused = |reason: &str| PunchReply::Refused {
+            reason: reason.to_string(),
+        };
+        let Ok(from) = from.parse::<PeerId>() else {
+            return refused("Invalid peer id");
+        };
+        let Some(observed) = addresses.first().and_then(|a| a.parse::<Multiaddr>().ok()) else {
+            return refused("No address to punch at");
+        };
+        if !self.holepunch.config().enabled
+            || self.sentries.is_hidden()
+            || self.slots.outbound_full()
+            || !self.allowlist.allows(&from)
+            || self.scores.is_banned(&from, SystemTime::now())
+        {
+            return refused("Not taking punches");
+        }
+        if self.connected.contains(&from) {
+            return refused("Connected already");
+        }
+        debug!("{} puts {} in touch with us", relay, from);
+        // The first address is where the relay sees `from`, any others
+        // have to be on the same IP
+        let delay = Duration::from_millis(delay_ms).min(MAX_PUNCH_DELAY);
+        self.holepunch.schedule(
+            Instant::now() + delay,
+            from,
+            holepunch::punch_addresses(&observed, &addresses[1..]),
+        );
+        PunchReply::Dial {
+            addresses: self.nat.candidates().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    fn handle_holepunch(&mut self, event: request_response::Event<Punch, PunchReply>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => match request {
+                Punch::Connect { target } => self.introduce(peer, &target, channel),
+                Punch::Incoming {
+                    from,
+                    addresses,
+                    delay_ms,
+                } => {
+                    let reply = self.accept_punch(peer, &from, &addresses, delay_ms);
+                    let _ = self
+                        .swarm
+                        .behaviour_mut()
+                        .holepunch
+                        .send_response(channel, reply);
+                }
+            },
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } => {
+                if let Some(channel) = self.introductions.remove(&request_id) {
+                    // The target agreed, tell the asker where it sees it
+                    let reply = match (response, self.remotes.get(&peer)) {
+                        (PunchReply::Dial { addresses }, Some(observed)) => PunchReply::Dial {
+                            addresses: holepunch::punch_addresses(observed, &addresses)
+                                .iter()
+                                .map(|a| a.to_string())
+                                .collect(),
+                        },
+                        (PunchReply::Dial { .. }, None) => PunchReply::Refused {
+                            reason: "Target is gone".to_string(),
+                        },
+                        (refused, _) => refused,
+                    };
+                    let _ = self
+                        .swarm
+                        .behaviour_mut()
+                        .holepunch
+                        .send_response(channel, reply);
+                    return;
+                }
+                let Some(target) = self.holepunch.answered(&request_id) else {
+                    return;
+                };
+                match response {
+                    PunchReply::Dial { addresses } => {
+                        let addresses = addresses.iter().filter_map(|a| a.parse().ok()).collect();
+                        self.punch(target, addresses);
+                    }
+                    PunchReply::Refused { reason } => {
+                        debug!("{} would not punch through to {}: {}", peer, target, reason);
+                    }
+                }
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                debug!("Hole punch request to {} failed: {}", peer, error);
+                self.holepunch.answered(&request_id);
+                if let Some(channel) = self.introductions.remove(&request_id) {
+                    let _ = self.swarm.behaviour_mut().holepunch.send_response(
+                        channel,
+                        PunchReply::Refused {
+                            reason: "Target did not answer".to_string(),
+                        },
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Ask a fresh node's peer which snapshots it has
+    fn request_snapshots(&mut self, peer: PeerId) {
+        let empty = self
+            .chain
+            .as_ref()
+            .is_none_or(|chain| chain.head().is_none());
+        if self.snapshot_target.is_none()
+            || self.snapshots.is_active()
+            || !empty
+            || !self.supports(&peer, Capability::Snapshots)
+        {
+            return;
+        }
+        let id = self
+            .swarm
+            .behaviour_mut()
+            .sync
+            .send_request(&peer, SyncRequest::SnapshotManifests);
+        self.snapshots.asked(id, peer);
+    }
+
+    /// Ask a peer how far its chain goes
+    fn request_status(&mut self, peer: PeerId) {
+        if self.chain.is_none() || !self.supports(&peer, Capability::Sync) {
+            return;
+        }
+        self.syncer.add_peer(peer);
+        let id = self
+            .swarm
+            .behaviour_mut()
+            .sync
+            .send_request(&peer, SyncRequest::Status);
+        self.syncer.sent(id, peer, SyncRequest::Status);
+    }
+
     pub async fn start(&mut self) -> Result<(), Box<dyn StdError>> {
-        // Listen on all interfaces
-        self.swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+        for address in self.discovery.listen.clone() {
+            self.swarm.listen_on(address)?;
+        }
+        if let Some(address) = self.discovery.websocket.listen.clone() {
+            self.swarm.listen_on(address)?;
+        }
 
         // Subscribe to topics
-        self.topics.subscribe_all(&mut self.swarm);
+        let social = self.social;
+        self.topics.subscribe(&mut self.swarm, |topic| {
+            social || Priority::of(topic) != Priority::Social
+        })?;
+
+        self.restore_peers();
+
+        if self.sentries.is_hidden() && !self.discovery.bootstrap.is_empty() {
+            info!("Hiding behind sentries, not dialing the bootstrap peers");
+        }
+        let bootstrap = if self.sentries.is_hidden() {
+            Vec::new()
+        } else {
+            self.discovery.bootstrap.clone()
+        };
+        for address in bootstrap {
+            if let Err(e) = self.swarm.dial(address.clone()) {
+                warn!("Could not dial bootstrap peer {}: {}", address, e);
+            }
+        }
+        let mut lookups = tokio::time::interval(self.discovery.lookup_interval);
+        let mut pacing = tokio::time::interval(PACING_INTERVAL);
+        let mut exchanges = tokio::time::interval(self.discovery.pex.interval);
+        let mut allowlist = tokio::time::interval(self.allowlist.reload_interval());
+        let mut shutdown = self.shutdown.take();
+        let mut broadcasts = self.broadcasts.take();
 
         loop {
-            match self.swarm.next().await.expect("Swarm stream is infinite") {
-                SwarmEvent::Behaviour(ChainBehaviourEvent::Gossipsub(
-                    GossipsubEvent::Message { message, .. },
-                )) => {
-                    let msg: NetworkMessage = serde_json::from_slice(&message.data)?;
-                    self.event_sender.send(msg).await?;
+            let event = tokio::select! {
+                event = self.swarm.next() => event.expect("Swarm stream is infinite"),
+                _ = shutting_down(&mut shutdown) => {
+                    return self.drain().await;
+                }
+                broadcast = next_broadcast(&mut broadcasts) => {
+                    match broadcast {
+                        Some(broadcast) => self.send(broadcast).await,
+                        // Nothing more to send, the network still relays
+                        None => broadcasts = None,
+                    }
+                    continue;
                 }
-                SwarmEvent::NewListenAddr { address, .. } => {
-                    info!("Listening on {:?}", address);
+                _ = lookups.tick() => {
+                    for peer in self.scores.expire(SystemTime::now()) {
+                        info!("Ban of peer {} ran out", peer);
+                    }
+                    if self.discovery.kademlia && !self.sentries.is_hidden() {
+                        self.lookup();
+                    }
+                    self.check_sentries();
+                    for peer in self.connected.clone() {
+                        self.request_snapshots(peer);
+                        self.request_status(peer);
+                    }
+                    self.drive_snapshots();
+                    self.probe_nat();
+                    self.keep_relays();
+                    self.persist_peers();
+                    continue;
                 }
-                _ => {}
+                _ = exchanges.tick() => {
+                    self.exchange_peers();
+                    continue;
+                }
+                _ = allowlist.tick() => {
+                    self.reload_allowlist();
+                    continue;
+                }
+                _ = pacing.tick() => {
+                    if !self.outbox.is_empty() {
+                        self.release_outbox();
+                    }
+                    self.redial_static_peers();
+                    self.dial_punches();
+                    if std::mem::take(&mut self.sync_deferred) {
+                        self.drive_sync();
+                        self.drive_snapshots();
+                    }
+                    continue;
+                }
+            };
+            self.handle_swarm_event(event)?;
+            for received in std::mem::take(&mut self.deliveries) {
+                self.event_sender.send(received).await?;
             }
         }
     }
 
-    pub async fn broadcast_block(&mut self, block: Block) -> Result<(), Box<dyn StdError>> {
-        let msg = NetworkMessage::NewBlock(block);
-        let data = serde_json::to_vec(&msg)?;
-        self.swarm
-            .behaviour_mut()
+    /// Leave the network in good order: send what was held back, leave the
+    /// topics, keep handling traffic for a moment so messages in flight
+    /// still arrive, then save the peers and hang up
+    async fn drain(&mut self) -> Result<(), Box<dyn StdError>> {
+        info!("Draining {} connections", self.connected.len());
+        self.release_outbox();
+        self.topics.unsubscribe_all(&mut self.swarm);
+        let grace = tokio::time::sleep(DRAIN_GRACE);
+        tokio::pin!(grace);
+        loop {
+            let event = tokio::select! {
+                event = self.swarm.next() => event.expect("Swarm stream is infinite"),
+                _ = &mut grace => break,
+            };
+            self.handle_swarm_event(event)?;
+            for received in std::mem::take(&mut self.deliveries) {
+                self.event_sender.send(received).await?;
+            }
+        }
+        self.persist_peers();
+        for peer in self.connected.clone() {
+            let _ = self.swarm.disconnect_peer_id(peer);
+        }
+        info!("Network drained");
+        Ok(())
+    }
+
+    fn handle_swarm_event(
+        &mut self,
+        event: SwarmEvent<ChainBehaviourEvent>,
+    ) -> Result<(), Box<dyn StdError>> {
+        match event {
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Gossipsub(GossipsubEvent::Message {
+                propagation_source,
+                message_id,
+                message,
+            })) => {
+                // An echo of something handled already goes no further
+                if !self.seen.insert(&message_id, Instant::now()) {
+                    self.metrics
+                        .received(self.topics.of(&message.topic), "duplicate");
+                    self.swarm
+                        .behaviour_mut()
+                        .gossipsub
+                        .report_message_validation_result(
+                            &message_id,
+                            &propagation_source,
+                            MessageAcceptance::Ignore,
+                        )?;
+                    return Ok(());
+                }
+                let priority = self
+                    .topics
+                    .of(&message.topic)
+                    .map_or(Priority::Gossip, Priority::of);
+                let validated = if !self.throttle.download(
+                    &propagation_source,
+                    message.data.len(),
+                    priority,
+                    Instant::now(),
+                ) {
+                    // Over the download limit, dropped without blame
+                    Err((Validation::Ignore, Offense::Spam))
+                } else if self.within_rate(propagation_source, priority) {
+                    self.validate(&propagation_source, &message)
+                } else {
+                    Err((
+                        Validation::Reject("Rate limit exceeded".to_string()),
+                        Offense::Spam,
+                    ))
+                };
+                let (decoded, acceptance, verdict) = match validated {
+                    Ok(decoded) => {
+                        self.judge(propagation_source, None);
+                        (Some(decoded), MessageAcceptance::Accept, "accept")
+                    }
+                    Err((Validation::Reject(reason), offense)) => {
+                        warn!(
+                            "Rejected message {} from {}: {}",
+                            message_id, propagation_source, reason
+                        );
+                        self.judge(propagation_source, Some(offense));
+                        (None, MessageAcceptance::Reject, "reject")
+                    }
+                    Err(_) => (None, MessageAcceptance::Ignore, "ignore"),
+                };
+                self.metrics
+                    .received(self.topics.of(&message.topic), verdict);
+                self.metrics
+                    .peer_bytes(&propagation_source, "received", message.data.len());
+                self.swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .report_message_validation_result(
+                        &message_id,
+                        &propagation_source,
+                        acceptance,
+                    )?;
+                match (decoded, message.source) {
+                    (Some(NetworkMessage::CompactBlock(compact)), Some(author)) => {
+                        self.rebuild(author, propagation_source, &compact);
+                    }
+                    (Some(message), Some(author)) => {
+                        self.punch_towards(author, propagation_source);
+                        if let NetworkMessage::NewTransaction(tx) = &message {
+                            self.relay.saw(tx.clone());
+                        }
+                        self.deliveries.push(Received {
+                            author,
+                            relayed_by: propagation_source,
+                            message,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+                for (peer, address) in peers {
+                    self.discovered(peer, address);
+                }
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+                for (peer, address) in peers {
+                    self.routing.remove_address(&peer, &address);
+                }
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+            })) => {
+                // Peers found through bootstrap addresses learn each
+                // other's listen addresses here
+                for address in info.listen_addrs {
+                    self.discovered(peer_id, address);
+                }
+                self.nat.observed(info.observed_addr);
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Ping(ping::Event {
+                peer,
+                result: Ok(rtt),
+                ..
+            })) => {
+                self.sentries.pinged(&peer, rtt, Instant::now());
+                self.holepunch.pinged(peer, rtt);
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Discovery(event)) if self.admit(&event) => {
+                self.handle_discovery(event);
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Sync(event)) if self.admit(&event) => {
+                self.handle_sync(event);
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Nat(event)) if self.admit(&event) => {
+                self.handle_nat(event);
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Handshake(event)) if self.admit(&event) => {
+                self.handle_handshake(event);
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Overlay(event)) if self.admit(&event) => {
+                self.handle_overlay(event);
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Pex(event)) if self.admit(&event) => {
+                self.handle_pex(event);
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::HolePunch(event)) if self.admit(&event) => {
+                self.handle_holepunch(event);
+            }
+            SwarmEvent::Behaviour(ChainBehaviourEvent::Upnp(event)) => match event {
+                upnp::Event::NewExternalAddr(address) => {
+                    info!("🏠 Router mapped {} for us", address);
+                    self.nat.mapped(address);
+                }
+                upnp::Event::ExpiredExternalAddr(address) => {
+                    info!("🏠 Router mapping {} expired", address);
+                    if let Some(reachability) = self.nat.expired(&address) {
+                        self.reachability_changed(reachability);
+                    }
+                }
+                upnp::Event::GatewayNotFound => debug!("No UPnP gateway found"),
+                upnp::Event::NonRoutableGateway => {
+                    debug!("UPnP gateway is not on the public network")
+                }
+            },
+            SwarmEvent::ConnectionEstablished {
+                connection_id,
+                endpoint,
+                ..
+            } if self.dial_backs.contains_key(&connection_id) => {
+                // A peer we dialed back. It is connected already, this
+                // connection only had to prove it can be opened.
+                let (address, channel) = self
+                    .dial_backs
+                    .remove(&connection_id)
+                    .expect("Checked above");
+                debug!("Dialed back {}", endpoint.get_remote_address());
+                let _ = self.swarm.behaviour_mut().nat.send_response(
+                    channel,
+                    DialBackResult {
+                        reachable: Some(address.to_string()),
+                    },
+                );
+                self.swarm.close_connection(connection_id);
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, .. }
+                if self.dial_backs.contains_key(&connection_id) =>
+            {
+                let (_, channel) = self
+                    .dial_backs
+                    .remove(&connection_id)
+                    .expect("Checked above");
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .nat
+                    .send_response(channel, DialBackResult { reachable: None });
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                self.remotes
+                    .insert(peer_id, endpoint.get_remote_address().clone());
+                self.last_seen
+                    .insert(peer_id, unix_seconds(SystemTime::now()));
+                self.static_peers.connected(connection_id, peer_id);
+                let stranger = self.sentries.is_hidden() && !self.static_peers.contains(&peer_id);
+                if stranger {
+                    debug!("Hiding behind sentries, turning away {}", peer_id);
+                }
+                let first = num_established.get() == 1;
+                let unlisted = !self.allowlist.allows(&peer_id);
+                if unlisted {
+                    debug!("Peer {} is not on the allowlist", peer_id);
+                }
+                if stranger
+                    || unlisted
+                    || self.scores.is_banned(&peer_id, SystemTime::now())
+                        && !self.static_peers.contains(&peer_id)
+                    || first && !self.admit_connection(peer_id, &endpoint)
+                {
+                    let _ = self.swarm.disconnect_peer_id(peer_id);
+                } else if first {
+                    // Sync and the rest wait for the handshake
+                    if self.holepunch.connected(&peer_id) {
+                        info!("🕳️ Punched through to {}", peer_id);
+                    }
+                    self.connected.insert(peer_id);
+                    self.sentries.connected(&peer_id, Instant::now());
+                    self.metrics.connected(self.connected.len());
+                    let hello = self.hello();
+                    self.swarm
+                        .behaviour_mut()
+                        .handshake
+                        .send_request(&peer_id, hello);
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            } => {
+                if self.connected.remove(&peer_id) {
+                    self.metrics.disconnected(&peer_id, self.connected.len());
+                }
+                self.last_seen
+                    .insert(peer_id, unix_seconds(SystemTime::now()));
+                self.syncer.remove_peer(&peer_id);
+                self.snapshots.remove_peer(&peer_id);
+                self.remotes.remove(&peer_id);
+                self.throttle.remove_peer(&peer_id);
+                self.outbox.remove_peer(&peer_id);
+                self.negotiated.remove(&peer_id);
+                self.pex.remove_peer(&peer_id);
+                self.holepunch.remove_peer(&peer_id);
+                self.sentries.disconnected(&peer_id);
+                self.slots.remove(&peer_id);
+                self.static_peers.disconnected(&peer_id, Instant::now());
+            }
+            SwarmEvent::OutgoingConnectionError { connection_id, .. } => {
+                self.metrics.dial_failed();
+                self.static_peers.dial_failed(connection_id, Instant::now());
+            }
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("Listening on {:?}", address);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Sign and publish a message on its topic. Social messages need the
+    /// signature of their agent first, see [`Self::broadcast_signed`].
+    pub fn publish(&mut self, message: &NetworkMessage) -> Result<(), Box<dyn StdError>> {
+        if message.is_social() {
+            return Err(Box::new(NetworkError::Internal(
+                "Social gossip has to be signed by its agent".to_string(),
+            )));
+        }
+        let topic = message.topic();
+        let raw = serde_json::to_vec(message)?;
+        let raw_size = raw.len();
+        // Compressed only once every peer can read it
+        let codec = if self
+            .negotiated
+            .values()
+            .all(|negotiated| negotiated.supports(Capability::Snappy))
+        {
+            Codec::Snappy
+        } else {
+            Codec::None
+        };
+        let (codec, data) = compression::encode(codec, raw);
+        self.metrics.gossip("sent", codec, raw_size, data.len());
+        self.metrics.sent(topic);
+        let hash = self.topics.topic(topic).hash();
+        // Every peer in the mesh gets a copy
+        let copies = self
+            .swarm
+            .behaviour()
             .gossipsub
-            .publish(self.topics.blocks.clone(), data)?;
+            .mesh_peers(&hash)
+            .count()
+            .max(1);
+        let bytes = data.len() * copies;
+        let priority = Priority::of(topic);
+        if self.outbox.is_waiting(priority)
+            || !self.throttle.upload(None, bytes, priority, Instant::now())
+        {
+            self.outbox
+                .push(priority, None, bytes, Outgoing::Publish(topic, data));
+            return Ok(());
+        }
+        let topic = self.topics.topic(topic).clone();
+        let id = self.swarm.behaviour_mut().gossipsub.publish(topic, data)?;
+        // Our own message coming back is an echo too
+        self.seen.insert(&id, Instant::now());
         Ok(())
     }
 
+    /// Announce a block as a compact block; peers fetch the transactions
+    /// they lack from us
+    pub async fn broadcast_block(&mut self, block: Block) -> Result<(), Box<dyn StdError>> {
+        // Peers from before compact blocks get the full block
+        let legacy = self
+            .negotiated
+            .values()
+            .any(|negotiated| !negotiated.supports(Capability::CompactBlocks));
+        if legacy {
+            return self.publish(&NetworkMessage::NewBlock(block));
+        }
+        let compact = self.relay.announce(&block);
+        self.publish(&NetworkMessage::CompactBlock(compact))
+    }
+
+    /// Propose a block to the validators, over the overlay when we are in
+    /// one. Once finalized it goes to everyone through
+    /// [`Self::broadcast_block`].
+    pub async fn propose_block(&mut self, block: Block) -> Result<(), Box<dyn StdError>> {
+        if self.overlay.is_enabled() && self.send_overlay(&NetworkMessage::NewBlock(block.clone()))
+        {
+            return Ok(());
+        }
+        self.broadcast_block(block).await
+    }
+
+    /// Send a vote to the validators, over the overlay when we are in one
+    pub async fn broadcast_vote(&mut self, vote: SignedVote) -> Result<(), Box<dyn StdError>> {
+        let message = NetworkMessage::Vote(vote);
+        if self.overlay.is_enabled() && self.send_overlay(&message) {
+            return Ok(());
+        }
+        self.publish(&message)
+    }
+
+    /// Send a step of the threshold signing of a block, over the overlay
+    /// like votes
+    pub async fn broadcast_threshold(
+        &mut self,
+        message: ThresholdMessage,
+    ) -> Result<(), Box<dyn StdError>> {
+        let message = NetworkMessage::Threshold(Box::new(message));
+        if self.overlay.is_enabled() && self.send_overlay(&message) {
+            return Ok(());
+        }
+        self.publish(&message)
+    }
+
     pub async fn broadcast_transaction(
         &mut self,
         tx: Transaction,
     ) -> Result<(), Box<dyn StdError>> {
-        let msg = NetworkMessage::NewTransaction(tx);
-        let data = serde_json::to_vec(&msg)?;
-        self.swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(self.topics.transactions.clone(), data)?;
-        Ok(())
+        self.publish(&NetworkMessage::NewTransaction(tx))
     }
 
+    /// Say something as the agent `from`, signed with its `key`
     pub async fn broadcast_chat(
         &mut self,
+        key: &SigningKey,
         from: String,
         message: String,
     ) -> Result<(), Box<dyn StdError>> {
-        let msg = NetworkMessage::Chat { from, message };
-        let data = serde_json::to_vec(&msg)?;
-        self.swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(self.topics.chat.clone(), data)?;
-        Ok(())
+        let envelope = SignedEnvelope::sign(key, NetworkMessage::Chat { from, message })?;
+        self.broadcast_signed(envelope).await
+    }
+
+    /// Pass a rumor on, signed with the `key` of the agent in `relayed_by`
+    pub async fn broadcast_rumor(
+        &mut self,
+        key: &SigningKey,
+        rumor: Rumor,
+    ) -> Result<(), Box<dyn StdError>> {
+        let envelope = SignedEnvelope::sign(key, NetworkMessage::Rumor(rumor))?;
+        self.broadcast_signed(envelope).await
+    }
+
+    /// Publish social gossip an agent signed already
+    pub async fn broadcast_signed(
+        &mut self,
+        envelope: SignedEnvelope,
+    ) -> Result<(), Box<dyn StdError>> {
+        self.publish(&NetworkMessage::Signed(envelope))
+    }
+
+    /// Send one of [`ChainNetwork::with_broadcasts`]. It is dropped when it
+    /// cannot be, as the node has moved on by the time it could retry.
+    async fn send(&mut self, broadcast: Broadcast) {
+        let sent = match broadcast {
+            Broadcast::Proposal(block) => self.propose_block(block).await,
+            Broadcast::Block(block) => self.broadcast_block(block).await,
+            Broadcast::Vote(vote) => self.broadcast_vote(vote).await,
+            Broadcast::Transaction(tx) => self.broadcast_transaction(tx).await,
+            Broadcast::Signed(envelope) => self.broadcast_signed(envelope).await,
+        };
+        if let Err(e) = sent {
+            warn!("Could not send to the network: {}", e);
+        }
+    }
+}
+
+/// The next of [`ChainNetwork::with_broadcasts`], never without a receiver
+async fn next_broadcast(broadcasts: &mut Option<mpsc::Receiver<Broadcast>>) -> Option<Broadcast> {
+    match broadcasts {
+        Some(broadcasts) => broadcasts.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves once the node is told to shut down, never without a signal
+async fn shutting_down(shutdown: &mut Option<watch::Receiver<bool>>) {
+    match shutdown {
+        Some(shutdown) => {
+            let _ = shutdown.wait_for(|stop| *stop).await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pex::PexConfig;
+    use std::net::TcpListener;
+    use sync::tests::{certified, TestChain};
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    /// A network on localhost that syncs `chain`, and what keeps it running
+    async fn network(
+        chain: Arc<TestChain>,
+        sync: SyncConfig,
+        static_peers: &[Multiaddr],
+    ) -> (
+        ChainNetwork,
+        Multiaddr,
+        mpsc::Receiver<Received>,
+        watch::Sender<bool>,
+    ) {
+        let address: Multiaddr = format!("/ip4/127.0.0.1/tcp/{}", free_port())
+            .parse()
+            .unwrap();
+        let discovery = DiscoveryConfig {
+            listen: vec![address.clone()],
+            mdns: false,
+            static_peers: static_peers.to_vec(),
+            ..DiscoveryConfig::default()
+        };
+        let (events, received) = mpsc::channel(100);
+        let (shutdown, stopped) = watch::channel(false);
+        let network = ChainNetwork::with_discovery(Keypair::generate_ed25519(), events, discovery)
+            .await
+            .unwrap()
+            .with_shutdown(stopped)
+            .with_sync(chain, sync);
+        (network, address, received, shutdown)
+    }
+
+    #[tokio::test]
+    async fn test_a_fresh_node_syncs_the_chain_in_ranges() {
+        let blocks = certified(40, "validator-0");
+        let head = blocks.last().unwrap().hash();
+        let serving = Arc::new(TestChain(std::sync::Mutex::new(blocks)));
+        let syncing = Arc::new(TestChain::default());
+
+        let (mut alice, address, _alice_events, _alice_shutdown) =
+            network(serving, SyncConfig::default(), &[]).await;
+        // Small batches, so the chain comes in several ranges
+        let config = SyncConfig {
+            batch_size: 8,
+            ..SyncConfig::default()
+        };
+        let (mut bob, _, _bob_events, _bob_shutdown) =
+            network(syncing.clone(), config, &[address]).await;
+        tokio::spawn(async move { alice.start().await.map_err(|e| e.to_string()) });
+        tokio::spawn(async move { bob.start().await.map_err(|e| e.to_string()) });
+
+        let synced = tokio::time::timeout(Duration::from_secs(30), async {
+            while syncing.head() != Some((39, head)) {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await;
+        assert!(synced.is_ok(), "Synced up to {:?}", syncing.head());
+        let heights: Vec<u64> = syncing
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|block| block.height)
+            .collect();
+        assert_eq!(heights, (0..40).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_only_what_is_set_up_is_advertised() {
+        let (events, _received) = mpsc::channel(100);
+        let discovery = DiscoveryConfig {
+            mdns: false,
+            pex: PexConfig {
+                enabled: false,
+                ..PexConfig::default()
+            },
+            ..DiscoveryConfig::default()
+        };
+        let bare = ChainNetwork::with_discovery(Keypair::generate_ed25519(), events, discovery)
+            .await
+            .unwrap();
+        let hello = bare.hello();
+        assert!(hello.capabilities.contains(&Capability::Votes));
+        for missing in [
+            Capability::Sync,
+            Capability::Snapshots,
+            Capability::CompactBlocks,
+            Capability::PeerExchange,
+        ] {
+            assert!(!hello.capabilities.contains(&missing), "{:?}", missing);
+        }
+
+        let (syncing, ..) =
+            network(Arc::new(TestChain::default()), SyncConfig::default(), &[]).await;
+        let hello = syncing.hello();
+        assert!(hello.capabilities.contains(&Capability::Sync));
+        assert!(!hello.capabilities.contains(&Capability::Snapshots));
     }
 }
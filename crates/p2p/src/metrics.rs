@@ -0,0 +1,178 @@
+//! Prometheus metrics of the network layer
+
+use chaoschain_core::compression::Codec;
+use chaoschain_core::gossip::GossipTopic;
+use chaoschain_core::metrics::MetricsRegistry;
+use libp2p::PeerId;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{linear_buckets, Histogram};
+
+type Labels = Vec<(String, String)>;
+
+fn labels(pairs: &[(&str, &str)]) -> Labels {
+    pairs
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Counters of the network layer. Clones share their values, so a node
+/// can register them before handing them to the network.
+#[derive(Clone)]
+pub struct NetworkMetrics {
+    raw_bytes: Family<Labels, Counter>,
+    wire_bytes: Family<Labels, Counter>,
+    compression_ratio: Histogram,
+    messages_sent: Family<Labels, Counter>,
+    messages_received: Family<Labels, Counter>,
+    peer_bytes: Family<Labels, Counter>,
+    peers: Gauge,
+    connections: Family<Labels, Counter>,
+    dial_failures: Counter,
+    handshake_failures: Family<Labels, Counter>,
+}
+
+impl Default for NetworkMetrics {
+    fn default() -> Self {
+        Self {
+            raw_bytes: Family::default(),
+            wire_bytes: Family::default(),
+            compression_ratio: Histogram::new(linear_buckets(0.1, 0.1, 10)),
+            messages_sent: Family::default(),
+            messages_received: Family::default(),
+            peer_bytes: Family::default(),
+            peers: Gauge::default(),
+            connections: Family::default(),
+            dial_failures: Counter::default(),
+            handshake_failures: Family::default(),
+        }
+    }
+}
+
+impl NetworkMetrics {
+    /// Expose the network counters through the node's metrics registry
+    pub fn register(&self, registry: &MetricsRegistry) {
+        registry.register(
+            "chaoschain_gossip_raw_bytes",
+            "Gossip payload bytes before compression, per direction and codec",
+            self.raw_bytes.clone(),
+        );
+        registry.register(
+            "chaoschain_gossip_wire_bytes",
+            "Gossip payload bytes on the wire, per direction and codec",
+            self.wire_bytes.clone(),
+        );
+        registry.register(
+            "chaoschain_gossip_compression_ratio",
+            "Wire size over raw size of compressed gossip messages",
+            self.compression_ratio.clone(),
+        );
+        registry.register(
+            "chaoschain_gossip_messages_sent",
+            "Gossip messages published, per topic",
+            self.messages_sent.clone(),
+        );
+        registry.register(
+            "chaoschain_gossip_messages_received",
+            "Gossip messages received, per topic and verdict (accept, reject, ignore or duplicate)",
+            self.messages_received.clone(),
+        );
+        registry.register(
+            "chaoschain_peer_bytes",
+            "Bytes exchanged with each connected peer, per direction",
+            self.peer_bytes.clone(),
+        );
+        registry.register(
+            "chaoschain_peers",
+            "Peers currently connected",
+            self.peers.clone(),
+        );
+        registry.register(
+            "chaoschain_peer_connections",
+            "Peers that connected or disconnected",
+            self.connections.clone(),
+        );
+        registry.register(
+            "chaoschain_dial_failures",
+            "Outgoing connections that could not be established",
+            self.dial_failures.clone(),
+        );
+        registry.register(
+            "chaoschain_handshake_failures",
+            "Handshakes that failed, per reason",
+            self.handshake_failures.clone(),
+        );
+    }
+
+    /// A gossip message of `raw` bytes went `direction` ("sent" or
+    /// "received") as `wire` bytes
+    pub fn gossip(&self, direction: &str, codec: Codec, raw: usize, wire: usize) {
+        let labels = labels(&[("direction", direction), ("codec", codec.name())]);
+        self.raw_bytes.get_or_create(&labels).inc_by(raw as u64);
+        self.wire_bytes.get_or_create(&labels).inc_by(wire as u64);
+        if codec != Codec::None && raw > 0 {
+            self.compression_ratio.observe(wire as f64 / raw as f64);
+        }
+    }
+
+    /// We published a message on `topic`
+    pub fn sent(&self, topic: GossipTopic) {
+        self.messages_sent
+            .get_or_create(&labels(&[("topic", topic.name())]))
+            .inc();
+    }
+
+    /// A message came in on `topic`, `None` when we do not know the topic,
+    /// and got `verdict`
+    pub fn received(&self, topic: Option<GossipTopic>, verdict: &str) {
+        let topic = topic.map_or("unknown", GossipTopic::name);
+        self.messages_received
+            .get_or_create(&labels(&[("topic", topic), ("verdict", verdict)]))
+            .inc();
+    }
+
+    /// `bytes` went `direction` ("sent" or "received") to or from `peer`
+    pub fn peer_bytes(&self, peer: &PeerId, direction: &str, bytes: usize) {
+        self.peer_bytes
+            .get_or_create(&labels(&[
+                ("peer", &peer.to_string()),
+                ("direction", direction),
+            ]))
+            .inc_by(bytes as u64);
+    }
+
+    /// A peer connected, `peers` are connected now
+    pub fn connected(&self, peers: usize) {
+        self.peers.set(peers as i64);
+        self.connections
+            .get_or_create(&labels(&[("event", "connected")]))
+            .inc();
+    }
+
+    /// `peer` disconnected, `peers` are connected now. Its bandwidth
+    /// counters go with it, so they only cover the peers we have.
+    pub fn disconnected(&self, peer: &PeerId, peers: usize) {
+        self.peers.set(peers as i64);
+        self.connections
+            .get_or_create(&labels(&[("event", "disconnected")]))
+            .inc();
+        let peer = peer.to_string();
+        for direction in ["sent", "received"] {
+            self.peer_bytes
+                .remove(&labels(&[("peer", &peer), ("direction", direction)]));
+        }
+    }
+
+    pub fn dial_failed(&self) {
+        self.dial_failures.inc();
+    }
+
+    /// A handshake failed for `reason` ("version" or "error")
+    pub fn handshake_failed(&self, reason: &str) {
+        self.handshake_failures
+            .get_or_create(&labels(&[("reason", reason)]))
+            .inc();
+    }
+}
@@ -0,0 +1,226 @@
+//! Reachability for nodes behind NAT. Peers dial us back over
+//! [`NAT_PROTOCOL`] on the addresses they see us at, which tells whether
+//! others can connect to us. UPnP maps a port on the home router where it
+//! can. Nodes that stay unreachable keep connections open to relay peers,
+//! which pass their gossip and sync on.
+
+use crate::holepunch::HolePunchConfig;
+use crate::wire::{self, Limits};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Protocol peers are asked to dial us back with
+pub const NAT_PROTOCOL: StreamProtocol = StreamProtocol::new("/chaoschain/autonat/1");
+
+/// Candidate addresses remembered for probing
+const MAX_CANDIDATES: usize = 8;
+
+/// How a node behind NAT makes itself reachable
+#[derive(Debug, Clone)]
+pub struct NatConfig {
+    /// Ask peers to dial us back to find out whether we are reachable
+    pub autonat: bool,
+    /// Map a port on the router through UPnP
+    pub upnp: bool,
+    /// Public peers kept connected while we are unreachable
+    pub relays: Vec<Multiaddr>,
+    /// Dial backs that have to agree before the reachability changes
+    pub confidence: usize,
+    /// Direct connections to other private peers, set up through relays
+    pub hole_punch: HolePunchConfig,
+}
+
+impl Default for NatConfig {
+    fn default() -> Self {
+        Self {
+            autonat: true,
+            upnp: true,
+            relays: Vec::new(),
+            confidence: 3,
+            hole_punch: HolePunchConfig::default(),
+        }
+    }
+}
+
+/// Ask a peer to dial us on one of `addresses`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DialBack {
+    pub addresses: Vec<String>,
+}
+
+/// The address the peer could dial us on, if any
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DialBackResult {
+    pub reachable: Option<String>,
+}
+
+/// A few addresses to dial, and the one that worked
+pub const NAT_LIMITS: Limits = Limits {
+    request: 4 * 1024,
+    response: 1024,
+};
+
+pub type NatBehaviour = wire::Behaviour<DialBack, DialBackResult>;
+
+pub fn behaviour() -> NatBehaviour {
+    wire::behaviour(NAT_PROTOCOL, NAT_LIMITS)
+}
+
+/// The address out of `request` to dial back a peer connected from
+/// `remote`. Only addresses on the peer's own IP are dialed, so nobody can
+/// point us at a third party.
+pub fn dial_back_address(remote: &Multiaddr, request: &DialBack) -> Option<Multiaddr> {
+    let ip = ip_of(remote)?;
+    request
+        .addresses
+        .iter()
+        .filter_map(|address| address.parse::<Multiaddr>().ok())
+        .find(|address| ip_of(address).as_ref() == Some(&ip))
+}
+
+pub(crate) fn ip_of(address: &Multiaddr) -> Option<Protocol<'static>> {
+    address.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(Protocol::Ip4(ip)),
+        Protocol::Ip6(ip) => Some(Protocol::Ip6(ip)),
+        _ => None,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reachability {
+    Unknown,
+    /// Peers can dial us on this address
+    Public(Multiaddr),
+    /// Peers could not dial us, we go through relays
+    Private,
+}
+
+/// What the dial backs of our peers say about us
+#[derive(Debug)]
+pub struct NatStatus {
+    config: NatConfig,
+    reachability: Reachability,
+    /// Addresses peers observed us at, or the router mapped for us
+    candidates: VecDeque<Multiaddr>,
+    confirmations: HashMap<Multiaddr, usize>,
+    failures: usize,
+    /// Peers asked, oldest first, so probes rotate between them
+    asked: VecDeque<PeerId>,
+}
+
+impl Default for NatStatus {
+    fn default() -> Self {
+        Self::new(NatConfig::default())
+    }
+}
+
+impl NatStatus {
+    pub fn new(config: NatConfig) -> Self {
+        Self {
+            config,
+            reachability: Reachability::Unknown,
+            candidates: VecDeque::new(),
+            confirmations: HashMap::new(),
+            failures: 0,
+            asked: VecDeque::new(),
+        }
+    }
+
+    pub fn config(&self) -> &NatConfig {
+        &self.config
+    }
+
+    pub fn reachability(&self) -> &Reachability {
+        &self.reachability
+    }
+
+    /// Where peers saw us lately, most promising first
+    pub fn candidates(&self) -> impl Iterator<Item = &Multiaddr> {
+        self.candidates.iter()
+    }
+
+    /// Whether our connections should go through relays
+    pub fn needs_relay(&self) -> bool {
+        self.reachability == Reachability::Private
+    }
+
+    /// A peer saw us at `address`
+    pub fn observed(&mut self, address: Multiaddr) {
+        if self.candidates.contains(&address) {
+            return;
+        }
+        if self.candidates.len() >= MAX_CANDIDATES {
+            self.candidates.pop_front();
+        }
+        self.candidates.push_back(address);
+    }
+
+    /// The router mapped `address` for us, it is probed first
+    pub fn mapped(&mut self, address: Multiaddr) {
+        self.candidates.retain(|candidate| *candidate != address);
+        self.candidates.push_front(address);
+        self.candidates.truncate(MAX_CANDIDATES);
+    }
+
+    /// The next dial back to ask for, from the connected peer asked longest
+    /// ago
+    pub fn probe<'a>(
+        &mut self,
+        peers: impl IntoIterator<Item = &'a PeerId>,
+    ) -> Option<(PeerId, DialBack)> {
+        if !self.config.autonat || self.candidates.is_empty() {
+            return None;
+        }
+        let peer = peers
+            .into_iter()
+            .min_by_key(|peer| self.asked.iter().position(|asked| asked == *peer))?;
+        let peer = *peer;
+        self.asked.retain(|asked| *asked != peer);
+        self.asked.push_back(peer);
+        Some((
+            peer,
+            DialBack {
+                addresses: self.candidates.iter().map(|a| a.to_string()).collect(),
+            },
+        ))
+    }
+
+    /// A peer answered a dial back. Returns the new reachability when it
+    /// changed.
+    pub fn outcome(&mut self, result: DialBackResult) -> Option<Reachability> {
+        let reachable = result
+            .reachable
+            .and_then(|address| address.parse::<Multiaddr>().ok());
+        let next = match reachable {
+            Some(address) => {
+                self.failures = 0;
+                let count = self.confirmations.entry(address.clone()).or_default();
+                *count += 1;
+                (*count >= self.config.confidence).then_some(Reachability::Public(address))
+            }
+            None => {
+                self.confirmations.clear();
+                self.failures += 1;
+                (self.failures >= self.config.confidence).then_some(Reachability::Private)
+            }
+        }?;
+        if next == self.reachability {
+            return None;
+        }
+        self.reachability = next.clone();
+        Some(next)
+    }
+
+    /// The router dropped the mapping for `address`
+    pub fn expired(&mut self, address: &Multiaddr) -> Option<Reachability> {
+        self.candidates.retain(|candidate| candidate != address);
+        self.confirmations.remove(address);
+        if self.reachability != Reachability::Public(address.clone()) {
+            return None;
+        }
+        self.reachability = Reachability::Unknown;
+        Some(Reachability::Unknown)
+    }
+}
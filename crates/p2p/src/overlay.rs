@@ -0,0 +1,88 @@
+//! Private overlay between validators. Validators keep direct connections
+//! to each other and send votes, block proposals and the steps of threshold
+//! signing over [`OVERLAY_PROTOCOL`], instead of gossiping them to everyone. That saves
+//! the hops through the mesh, and keeps who votes what off the public
+//! network. Public nodes still get finalized blocks over gossip.
+
+use crate::wire::{self, Limits};
+use chaoschain_core::NetworkMessage;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Protocol validators talk to each other over
+pub const OVERLAY_PROTOCOL: StreamProtocol = StreamProtocol::new("/chaoschain/validators/1");
+
+/// The validators of the overlay
+#[derive(Debug, Clone, Default)]
+pub struct OverlayConfig {
+    /// Addresses of the other validators, each ending in `/p2p/<peer id>`.
+    /// Only these peers are let into the overlay.
+    pub validators: Vec<Multiaddr>,
+}
+
+/// Receipt of an overlay message. The overlay does not relay, so there is
+/// nothing else to say.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivered;
+
+/// A vote or a block, as large as a gossip frame, and a receipt
+pub const OVERLAY_LIMITS: Limits = Limits {
+    request: wire::MAX_TRANSMIT_SIZE as u64,
+    response: 64,
+};
+
+pub type OverlayBehaviour = wire::Behaviour<NetworkMessage, Delivered>;
+
+pub fn behaviour() -> OverlayBehaviour {
+    wire::behaviour(OVERLAY_PROTOCOL, OVERLAY_LIMITS)
+}
+
+/// Who is in the overlay
+#[derive(Debug, Default)]
+pub struct Overlay {
+    members: HashSet<PeerId>,
+}
+
+impl Overlay {
+    /// The overlay of the validators in `config`. Addresses without a peer
+    /// id are left out, there would be no telling who answers on them.
+    pub fn new(config: &OverlayConfig) -> Self {
+        Self {
+            members: config
+                .validators
+                .iter()
+                .filter_map(|address| {
+                    address.iter().find_map(|protocol| match protocol {
+                        Protocol::P2p(peer) => Some(peer),
+                        _ => None,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.members.is_empty()
+    }
+
+    pub fn is_member(&self, peer: &PeerId) -> bool {
+        self.members.contains(peer)
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &PeerId> {
+        self.members.iter()
+    }
+
+    /// Whether `message` goes over the overlay rather than gossip
+    pub fn carries(message: &NetworkMessage) -> bool {
+        matches!(
+            message,
+            NetworkMessage::Vote(_)
+                | NetworkMessage::NewBlock(_)
+                | NetworkMessage::CompactBlock(_)
+                | NetworkMessage::Threshold(_)
+        )
+    }
+}
@@ -0,0 +1,88 @@
+//! Peers remembered across restarts. A node saves the peers it was
+//! connected to, with their addresses, when it last saw them, their score
+//! and their ban, if any. On the next start it dials the best of them
+//! first, and peers keep the reputation they earned.
+
+use crate::scoring::BanRecord;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::warn;
+
+/// Peers not seen for this long are forgotten
+pub const STALE_AFTER: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Peers saved at most, the best scored are kept
+pub const MAX_STORED_PEERS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredPeer {
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+    /// Unix seconds at which we were last connected
+    pub last_seen: u64,
+    pub score: f64,
+    pub ban: Option<BanRecord>,
+}
+
+/// Where the peer store lives between runs
+pub trait PeerStore: Send + Sync {
+    fn load(&self) -> Vec<StoredPeer>;
+    fn save(&self, peers: &[StoredPeer]);
+}
+
+/// Peers kept as JSON in a file
+pub struct FilePeerStore {
+    path: PathBuf,
+}
+
+impl FilePeerStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl PeerStore for FilePeerStore {
+    fn load(&self) -> Vec<StoredPeer> {
+        if !self.path.exists() {
+            return Vec::new();
+        }
+        let loaded = std::fs::read(&self.path)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()));
+        loaded.unwrap_or_else(|e| {
+            warn!("Failed to load peers from {}: {}", self.path.display(), e);
+            Vec::new()
+        })
+    }
+
+    fn save(&self, peers: &[StoredPeer]) {
+        let result = serde_json::to_vec_pretty(peers)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| {
+                if let Some(dir) = self.path.parent() {
+                    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                std::fs::write(&self.path, bytes).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            warn!("Failed to save peers to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// The peers worth saving: banned ones, and the others seen within
+/// [`STALE_AFTER`] of `now`, best scored first
+pub fn retain(mut peers: Vec<StoredPeer>, now: u64) -> Vec<StoredPeer> {
+    let stale = now.saturating_sub(STALE_AFTER.as_secs());
+    peers.retain(|peer| {
+        peer.ban.as_ref().is_some_and(|ban| ban.until > now) || peer.last_seen > stale
+    });
+    peers.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.peer_id.cmp(&b.peer_id))
+    });
+    peers.truncate(MAX_STORED_PEERS);
+    peers
+}
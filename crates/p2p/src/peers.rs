@@ -0,0 +1,389 @@
+//! Peers pinned by the operator. Bootnodes are dialed once to join the
+//! network. Static peers are kept connected: they are redialed whenever the
+//! connection drops, backing off while they stay unreachable.
+
+use crate::allowlist;
+use crate::bandwidth::BandwidthConfig;
+use crate::discovery::DiscoveryConfig;
+use crate::overlay::OverlayConfig;
+use crate::slots::EvictionPolicy;
+use crate::snapshots::SnapshotConfig;
+use crate::NetworkError;
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::ConnectionId;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Longest wait between two dials of a static peer
+pub const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Peers listed in the config file, as multiaddrs
+///
+/// ```toml
+/// [network]
+/// listen = ["/ip4/0.0.0.0/tcp/4001", "/ip4/0.0.0.0/udp/4001/quic-v1"]
+/// bootnodes = ["/ip4/203.0.113.7/tcp/4001"]
+/// static_peers = ["/dns4/validator-2.example.org/tcp/4001/p2p/12D3KooW..."]
+/// ```
+///
+/// A validator hiding behind sentries lists them, and each sentry lists the
+/// validators it keeps quiet about:
+///
+/// ```toml
+/// [network]
+/// sentries = ["/dns4/sentry-1.example.org/tcp/4001/p2p/12D3KooW..."]
+/// private_peers = ["12D3KooW..."]
+/// ```
+///
+/// Connection slots can be sized to the machine:
+///
+/// ```toml
+/// [network]
+/// max_inbound = 60
+/// max_outbound = 20
+/// eviction = "reject"
+/// ```
+///
+/// A closed network lets in the listed peers only. Entries are peer ids or
+/// hex ed25519 keys, the file is read again whenever it changes:
+///
+/// ```toml
+/// [network]
+/// allowlist = ["12D3KooW..."]
+/// allowlist_file = "allowlist.txt"
+/// ```
+///
+/// A fresh node can start from a snapshot of its peers rather than replay
+/// the chain from the genesis:
+///
+/// ```toml
+/// [network]
+/// snapshot_sync = true
+/// snapshot_requests = 16
+/// ```
+///
+/// Validators talk to each other directly over an overlay, next to the
+/// gossip everyone else gets:
+///
+/// ```toml
+/// [network]
+/// overlay_validators = ["/dns4/validator-2.example.org/tcp/4001/p2p/12D3KooW..."]
+/// ```
+///
+/// A node that only cares about consensus can stay out of the drama and
+/// rumors, which it then neither receives nor relays:
+///
+/// ```toml
+/// [network]
+/// consensus_only = true
+/// ```
+///
+/// Browsers join over WebSocket, from the listed origins only if any:
+///
+/// ```toml
+/// [network]
+/// websocket = "/ip4/0.0.0.0/tcp/8546/ws"
+/// websocket_origins = ["https://explorer.example.org"]
+/// ```
+///
+/// A node behind a NAT stays reachable through public relays:
+///
+/// ```toml
+/// [network]
+/// relays = ["/dns4/relay.example.org/tcp/4001/p2p/12D3KooW..."]
+/// ```
+///
+/// Peers swap the peers they know of every `peer_exchange_interval`
+/// seconds, which can be tuned or turned off:
+///
+/// ```toml
+/// [network]
+/// peer_exchange = true
+/// peer_exchange_interval = 120
+/// peer_exchange_shared = 16
+/// peer_exchange_accepted = 8
+/// ```
+///
+/// Bandwidth can be capped, in bytes per second, for each peer and for
+/// the node as a whole:
+///
+/// ```toml
+/// [network]
+/// peer_upload = 1_000_000
+/// total_upload = 10_000_000
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PeersConfig {
+    /// Replace the default listeners, TCP and QUIC on random ports
+    pub listen: Vec<String>,
+    pub bootnodes: Vec<String>,
+    /// Best given with their `/p2p/<peer id>`, so inbound connections from
+    /// them are recognised too
+    pub static_peers: Vec<String>,
+    /// Sentries to hide behind, with their `/p2p/<peer id>`
+    pub sentries: Vec<String>,
+    /// Peer ids of the validators behind this sentry
+    pub private_peers: Vec<String>,
+    pub max_inbound: Option<usize>,
+    pub max_outbound: Option<usize>,
+    /// What happens to inbound connections past `max_inbound`
+    pub eviction: Option<EvictionPolicy>,
+    /// The only peers let in, by peer id or hex ed25519 key
+    pub allowlist: Vec<String>,
+    /// More of those, one per line, reloaded on change
+    pub allowlist_file: Option<PathBuf>,
+    /// Start an empty chain from a snapshot downloaded from the peers
+    pub snapshot_sync: bool,
+    /// Snapshot chunks asked for at once, of one peer and overall
+    pub snapshot_peer_requests: Option<usize>,
+    pub snapshot_requests: Option<usize>,
+    /// The other validators, with their `/p2p/<peer id>`, to reach over
+    /// the overlay
+    pub overlay_validators: Vec<String>,
+    /// Public peers kept connected while the node is unreachable
+    pub relays: Vec<String>,
+    /// Swap known peers with the connected ones, on unless set to false
+    pub peer_exchange: Option<bool>,
+    /// Seconds between exchanges with the connected peers
+    pub peer_exchange_interval: Option<u64>,
+    /// Peers offered and taken in one exchange
+    pub peer_exchange_shared: Option<usize>,
+    pub peer_exchange_accepted: Option<usize>,
+    /// Exchanges of a peer closer together than this many seconds are
+    /// ignored
+    pub peer_exchange_min_interval: Option<u64>,
+    /// Where to accept WebSocket connections, none if unset
+    pub websocket: Option<String>,
+    /// Origins browsers may connect from, any if empty
+    pub websocket_origins: Vec<String>,
+    /// Leave the drama and rumors topics alone
+    pub consensus_only: bool,
+    /// Bytes per second, unlimited when left out
+    pub peer_upload: Option<u64>,
+    pub peer_download: Option<u64>,
+    pub total_upload: Option<u64>,
+    pub total_download: Option<u64>,
+}
+
+impl PeersConfig {
+    /// `discovery` with these peers added
+    pub fn apply(&self, mut discovery: DiscoveryConfig) -> Result<DiscoveryConfig, NetworkError> {
+        if !self.listen.is_empty() {
+            discovery.listen = parse(&self.listen)?;
+        }
+        discovery.bootstrap.extend(parse(&self.bootnodes)?);
+        discovery.static_peers.extend(parse(&self.static_peers)?);
+        discovery.sentry.sentries.extend(parse(&self.sentries)?);
+        for peer in &self.private_peers {
+            discovery.sentry.private_peers.push(
+                peer.parse()
+                    .map_err(|e| NetworkError::InvalidPeerId(peer.clone(), format!("{}", e)))?,
+            );
+        }
+        for entry in &self.allowlist {
+            discovery.allowlist.peers.push(
+                allowlist::parse_entry(entry)
+                    .map_err(|e| NetworkError::InvalidPeerId(entry.clone(), e))?,
+            );
+        }
+        if let Some(file) = &self.allowlist_file {
+            discovery.allowlist.file = Some(file.clone());
+        }
+        discovery.nat.relays.extend(parse(&self.relays)?);
+        let pex = &mut discovery.pex;
+        pex.enabled = self.peer_exchange.unwrap_or(pex.enabled);
+        if let Some(seconds) = self.peer_exchange_interval {
+            pex.interval = Duration::from_secs(seconds);
+        }
+        pex.max_shared = self.peer_exchange_shared.unwrap_or(pex.max_shared);
+        pex.max_accepted = self.peer_exchange_accepted.unwrap_or(pex.max_accepted);
+        if let Some(seconds) = self.peer_exchange_min_interval {
+            pex.min_interval = Duration::from_secs(seconds);
+        }
+        if let Some(address) = &self.websocket {
+            discovery.websocket.listen = parse(std::slice::from_ref(address))?.pop();
+        }
+        discovery
+            .websocket
+            .allowed_origins
+            .extend(self.websocket_origins.iter().cloned());
+        let connections = &mut discovery.connections;
+        connections.max_inbound = self.max_inbound.unwrap_or(connections.max_inbound);
+        connections.max_outbound = self.max_outbound.unwrap_or(connections.max_outbound);
+        connections.eviction = self.eviction.unwrap_or(connections.eviction);
+        Ok(discovery)
+    }
+
+    /// The overlay of `overlay_validators`. Each needs its peer id, there
+    /// would be no telling who answers otherwise.
+    pub fn overlay(&self) -> Result<OverlayConfig, NetworkError> {
+        let validators = parse(&self.overlay_validators)?;
+        for (address, parsed) in self.overlay_validators.iter().zip(&validators) {
+            if !parsed
+                .iter()
+                .any(|protocol| matches!(protocol, Protocol::P2p(_)))
+            {
+                return Err(NetworkError::InvalidAddress(
+                    address.clone(),
+                    "no /p2p/<peer id>".to_string(),
+                ));
+            }
+        }
+        Ok(OverlayConfig { validators })
+    }
+
+    /// Upload and download limits, per peer and overall
+    pub fn bandwidth(&self) -> BandwidthConfig {
+        BandwidthConfig {
+            peer_upload: self.peer_upload,
+            peer_download: self.peer_download,
+            total_upload: self.total_upload,
+            total_download: self.total_download,
+        }
+    }
+
+    /// How snapshots are downloaded, if the node starts from one
+    pub fn snapshot_sync(&self) -> Option<SnapshotConfig> {
+        if !self.snapshot_sync {
+            return None;
+        }
+        let defaults = SnapshotConfig::default();
+        Some(SnapshotConfig {
+            per_peer_requests: self
+                .snapshot_peer_requests
+                .unwrap_or(defaults.per_peer_requests),
+            max_requests: self.snapshot_requests.unwrap_or(defaults.max_requests),
+        })
+    }
+}
+
+fn parse(addresses: &[String]) -> Result<Vec<Multiaddr>, NetworkError> {
+    addresses
+        .iter()
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|e| NetworkError::InvalidAddress(address.clone(), format!("{}", e)))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct StaticPeer {
+    address: Multiaddr,
+    peer: Option<PeerId>,
+    connected: bool,
+    failures: u32,
+    next_dial: Instant,
+}
+
+/// Keeps the static peers connected
+#[derive(Debug, Default)]
+pub struct StaticPeers {
+    peers: Vec<StaticPeer>,
+    /// Dials under way, by the static peer they are for
+    dialing: HashMap<ConnectionId, usize>,
+    backoff: Duration,
+}
+
+impl StaticPeers {
+    pub fn new(addresses: Vec<Multiaddr>, backoff: Duration) -> Self {
+        let mut peers = Self {
+            peers: Vec::new(),
+            dialing: HashMap::new(),
+            backoff,
+        };
+        for address in addresses {
+            peers.add(address);
+        }
+        peers
+    }
+
+    /// Keep `address` connected too, dialing it right away
+    pub fn add(&mut self, address: Multiaddr) {
+        if self.peers.iter().any(|entry| entry.address == address) {
+            return;
+        }
+        self.peers.push(StaticPeer {
+            peer: address.iter().find_map(|protocol| match protocol {
+                Protocol::P2p(peer) => Some(peer),
+                _ => None,
+            }),
+            address,
+            connected: false,
+            failures: 0,
+            next_dial: Instant::now(),
+        });
+    }
+
+    pub fn contains(&self, peer: &PeerId) -> bool {
+        self.peers.iter().any(|entry| entry.peer == Some(*peer))
+    }
+
+    /// Static peers to dial now
+    pub fn due(&self, now: Instant) -> Vec<(usize, Multiaddr)> {
+        self.peers
+            .iter()
+            .enumerate()
+            .filter(|(index, entry)| {
+                !entry.connected
+                    && entry.next_dial <= now
+                    && !self.dialing.values().any(|dialing| dialing == index)
+            })
+            .map(|(index, entry)| (index, entry.address.clone()))
+            .collect()
+    }
+
+    /// The static peer at `index` is being dialed with `id`
+    pub fn dialing(&mut self, index: usize, id: ConnectionId) {
+        self.dialing.insert(id, index);
+    }
+
+    /// A connection to `peer` came up
+    pub fn connected(&mut self, id: ConnectionId, peer: PeerId) {
+        if let Some(index) = self.dialing.remove(&id) {
+            // Learn who sits behind an address given without a peer id
+            self.peers[index].peer.get_or_insert(peer);
+        }
+        for entry in self
+            .peers
+            .iter_mut()
+            .filter(|entry| entry.peer == Some(peer))
+        {
+            entry.connected = true;
+            entry.failures = 0;
+        }
+    }
+
+    /// Dialing with `id` failed
+    pub fn dial_failed(&mut self, id: ConnectionId, now: Instant) {
+        let Some(index) = self.dialing.remove(&id) else {
+            return;
+        };
+        let entry = &mut self.peers[index];
+        entry.failures = entry.failures.saturating_add(1);
+        entry.next_dial = now + backoff(self.backoff, entry.failures);
+    }
+
+    /// The last connection to `peer` closed
+    pub fn disconnected(&mut self, peer: &PeerId, now: Instant) {
+        for entry in self
+            .peers
+            .iter_mut()
+            .filter(|entry| entry.peer == Some(*peer))
+        {
+            entry.connected = false;
+            entry.next_dial = now + backoff(self.backoff, entry.failures);
+        }
+    }
+}
+
+/// `base` doubled for every failure in a row, up to [`MAX_BACKOFF`]
+fn backoff(base: Duration, failures: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(failures))
+        .min(MAX_BACKOFF)
+}
@@ -0,0 +1,102 @@
+//! Peer exchange over [`PEX_PROTOCOL`]. Connected peers swap a handful of
+//! the peers they found worth keeping every now and then, so the network
+//! stays connected once its bootnodes are gone. What comes in is checked
+//! against our bans, and each peer only gets to introduce so many peers, so
+//! often.
+
+use crate::discovery::Peers;
+use crate::wire::{self, Limits};
+use libp2p::{Multiaddr, PeerId, StreamProtocol};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Protocol peer lists are exchanged over
+pub const PEX_PROTOCOL: StreamProtocol = StreamProtocol::new("/chaoschain/pex/1");
+
+/// A list of peers each way
+pub const PEX_LIMITS: Limits = Limits {
+    request: 64 * 1024,
+    response: 64 * 1024,
+};
+
+pub type PexBehaviour = wire::Behaviour<Peers, Peers>;
+
+pub fn behaviour() -> PexBehaviour {
+    wire::behaviour(PEX_PROTOCOL, PEX_LIMITS)
+}
+
+#[derive(Debug, Clone)]
+pub struct PexConfig {
+    pub enabled: bool,
+    /// Time between exchanges with the connected peers
+    pub interval: Duration,
+    /// Peers offered in one exchange
+    pub max_shared: usize,
+    /// Peers taken from one exchange, the rest are dropped
+    pub max_accepted: usize,
+    /// Exchanges from the same peer closer together than this are ignored
+    pub min_interval: Duration,
+}
+
+impl Default for PexConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: Duration::from_secs(60),
+            max_shared: 16,
+            max_accepted: 8,
+            min_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// When each peer last introduced peers to us
+#[derive(Debug, Default)]
+pub struct PeerExchange {
+    config: PexConfig,
+    accepted: HashMap<PeerId, Instant>,
+}
+
+impl PeerExchange {
+    pub fn new(config: PexConfig) -> Self {
+        Self {
+            config,
+            accepted: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &PexConfig {
+        &self.config
+    }
+
+    /// The peers to take from what `from` sent. Nothing while it exchanged
+    /// too recently, and at most [`PexConfig::max_accepted`] otherwise.
+    /// Bans are up to the caller.
+    pub fn accept(
+        &mut self,
+        from: PeerId,
+        peers: &Peers,
+        now: Instant,
+    ) -> Vec<(PeerId, Vec<Multiaddr>)> {
+        if !self.config.enabled {
+            return Vec::new();
+        }
+        if let Some(last) = self.accepted.get(&from) {
+            if now.saturating_duration_since(*last) < self.config.min_interval {
+                return Vec::new();
+            }
+        }
+        self.accepted.insert(from, now);
+        peers
+            .peers
+            .iter()
+            .filter_map(|record| record.parse())
+            .filter(|(peer, addresses)| *peer != from && !addresses.is_empty())
+            .take(self.config.max_accepted)
+            .collect()
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.accepted.remove(peer);
+    }
+}
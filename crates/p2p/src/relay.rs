@@ -0,0 +1,102 @@
+//! Compact block relay: blocks go out as [`CompactBlock`]s and are rebuilt
+//! from the transactions a node already has. Whatever is missing is fetched
+//! from the announcing peer in one round over the sync protocol.
+
+use chaoschain_core::compact::{CompactBlock, PartialBlock};
+use chaoschain_core::mempool::Mempool;
+use chaoschain_core::{Block, HashedTransaction, Transaction};
+use libp2p::request_response::OutboundRequestId;
+use libp2p::PeerId;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+/// Transactions seen on gossip kept for reconstruction
+const SEEN_TRANSACTIONS: usize = 4096;
+
+/// Blocks we announced, kept to answer requests for their transactions
+const RECENT_BLOCKS: usize = 16;
+
+/// Where a node keeps its pending transactions
+pub trait TransactionPool: Send + Sync {
+    fn pending(&self) -> Vec<HashedTransaction>;
+}
+
+impl TransactionPool for Mempool {
+    fn pending(&self) -> Vec<HashedTransaction> {
+        self.get_top(usize::MAX)
+    }
+}
+
+/// A block waiting for the transactions it was missing
+#[derive(Debug)]
+pub struct Incomplete {
+    pub author: PeerId,
+    pub partial: PartialBlock,
+    pub indexes: Vec<u32>,
+}
+
+#[derive(Default)]
+pub struct CompactRelay {
+    pool: Option<Arc<dyn TransactionPool>>,
+    /// Hashed the first time they are looked for
+    seen: VecDeque<HashedTransaction>,
+    recent: VecDeque<Block>,
+    waiting: HashMap<OutboundRequestId, Incomplete>,
+}
+
+impl CompactRelay {
+    pub fn new(pool: Arc<dyn TransactionPool>) -> Self {
+        Self {
+            pool: Some(pool),
+            ..Self::default()
+        }
+    }
+
+    /// Whether blocks are rebuilt from a mempool, rather than from gossip
+    /// alone
+    pub fn has_pool(&self) -> bool {
+        self.pool.is_some()
+    }
+
+    /// A transaction came in on gossip
+    pub fn saw(&mut self, tx: Transaction) {
+        if self.seen.len() >= SEEN_TRANSACTIONS {
+            self.seen.pop_front();
+        }
+        self.seen.push_back(tx.into());
+    }
+
+    /// We are about to announce `block`
+    pub fn announce(&mut self, block: &Block) -> CompactBlock {
+        if self.recent.len() >= RECENT_BLOCKS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(block.clone());
+        CompactBlock::new(block)
+    }
+
+    /// One of the blocks we announced
+    pub fn recent(&self, hash: &[u8; 32]) -> Option<&Block> {
+        self.recent.iter().find(|block| block.hash() == *hash)
+    }
+
+    /// Rebuild an announced block from the mempool and gossip
+    pub fn reconstruct(&self, compact: &CompactBlock) -> PartialBlock {
+        let pending = self
+            .pool
+            .as_ref()
+            .map(|pool| pool.pending())
+            .unwrap_or_default();
+        compact.reconstruct(pending.iter().chain(self.seen.iter()))
+    }
+
+    /// The missing transactions of a block were asked for with `id`
+    pub fn wait(&mut self, id: OutboundRequestId, incomplete: Incomplete) {
+        self.waiting.insert(id, incomplete);
+    }
+
+    /// The block a request for missing transactions was for
+    pub fn take(&mut self, id: &OutboundRequestId) -> Option<Incomplete> {
+        self.waiting.remove(id)
+    }
+}
@@ -0,0 +1,443 @@
+//! How much a peer can be trusted, judged by what it sends. Useful messages
+//! raise its score, invalid ones lower it, and the score drifts back to zero
+//! over time. Peers that fall below the ban threshold are disconnected and
+//! turned away until their ban runs out.
+
+use chaoschain_core::Error as CoreError;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Something a peer should not have sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Offense {
+    /// A block that fails its checks
+    InvalidBlock,
+    /// A vote or transaction whose signature does not hold up
+    InvalidSignature,
+    /// Messages beyond the rate limit, or oversized ones
+    Spam,
+    /// Bytes that do not decode, or a message on the wrong topic
+    Malformed,
+    /// Turned down by one of the node's own validators
+    Rejected,
+    /// Chat, reasoning or a rumor without the signature of its agent
+    Unsigned,
+}
+
+impl Offense {
+    /// The offense behind a failed [`chaoschain_core::NetworkMessage::check`]
+    pub fn of(error: &CoreError) -> Self {
+        match error {
+            CoreError::InvalidBlock(_) => Offense::InvalidBlock,
+            CoreError::InvalidSignature(_) => Offense::InvalidSignature,
+            CoreError::InvalidTransaction(_) | CoreError::InvalidMessage(_) => Offense::Spam,
+            CoreError::StateError(_) => Offense::Rejected,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoringConfig {
+    /// Score gained per accepted message
+    pub reward: f64,
+    /// Score lost per offense
+    pub penalties: HashMap<Offense, f64>,
+    /// Highest score a peer can build up
+    pub max_score: f64,
+    /// Peers at or below this score are banned
+    pub ban_threshold: f64,
+    /// Time it takes a score to halve on its way back to zero
+    pub half_life: Duration,
+    /// How long a ban lasts
+    pub ban_duration: Duration,
+    /// Messages a peer may send per rate window before they count as spam
+    pub rate_limit: u32,
+    /// Drama and rumors a peer may send per rate window, counted apart so
+    /// that roleplay never uses up the limit of votes and blocks
+    pub social_rate_limit: u32,
+    pub rate_window: Duration,
+    /// Where ban records are kept across restarts. Kept in memory if unset.
+    pub ban_file: Option<PathBuf>,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        Self {
+            reward: 1.0,
+            penalties: HashMap::from([
+                (Offense::InvalidBlock, 20.0),
+                (Offense::InvalidSignature, 30.0),
+                (Offense::Spam, 10.0),
+                (Offense::Malformed, 15.0),
+                (Offense::Rejected, 5.0),
+                (Offense::Unsigned, 5.0),
+            ]),
+            max_score: 100.0,
+            ban_threshold: -50.0,
+            half_life: Duration::from_secs(10 * 60),
+            ban_duration: Duration::from_secs(60 * 60),
+            rate_limit: 200,
+            social_rate_limit: 100,
+            rate_window: Duration::from_secs(10),
+            ban_file: None,
+        }
+    }
+}
+
+/// A banned peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanRecord {
+    pub peer_id: String,
+    pub offense: Offense,
+    /// Unix seconds at which the ban was issued
+    pub since: u64,
+    /// Unix seconds at which the ban runs out
+    pub until: u64,
+}
+
+#[derive(Debug, Clone)]
+struct PeerScore {
+    value: f64,
+    updated: SystemTime,
+    window_start: SystemTime,
+    window_messages: u32,
+    window_social: u32,
+}
+
+pub(crate) fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Scores of the peers a node hears from, and the ones it banned
+#[derive(Debug, Clone)]
+pub struct PeerScores {
+    config: ScoringConfig,
+    scores: HashMap<PeerId, PeerScore>,
+    bans: HashMap<PeerId, BanRecord>,
+}
+
+impl Default for PeerScores {
+    fn default() -> Self {
+        Self::new(ScoringConfig::default())
+    }
+}
+
+impl PeerScores {
+    /// Scores starting out with the bans recorded in the ban file, if any
+    pub fn new(config: ScoringConfig) -> Self {
+        let bans = config
+            .ban_file
+            .as_ref()
+            .filter(|path| path.exists())
+            .and_then(|path| {
+                let loaded = std::fs::read(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|bytes| {
+                        serde_json::from_slice::<Vec<BanRecord>>(&bytes).map_err(|e| e.to_string())
+                    });
+                match loaded {
+                    Ok(records) => Some(records),
+                    Err(e) => {
+                        warn!("Failed to load peer bans from {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|record| Some((record.peer_id.parse().ok()?, record)))
+            .collect();
+        Self {
+            config,
+            scores: HashMap::new(),
+            bans,
+        }
+    }
+
+    fn entry(&mut self, peer: PeerId, now: SystemTime) -> &mut PeerScore {
+        let half_life = self.config.half_life.as_secs_f64().max(f64::EPSILON);
+        let score = self.scores.entry(peer).or_insert(PeerScore {
+            value: 0.0,
+            updated: now,
+            window_start: now,
+            window_messages: 0,
+            window_social: 0,
+        });
+        let elapsed = now
+            .duration_since(score.updated)
+            .unwrap_or_default()
+            .as_secs_f64();
+        score.value *= 0.5f64.powf(elapsed / half_life);
+        score.updated = now;
+        score
+    }
+
+    /// Current score of a peer, zero for strangers
+    pub fn score(&mut self, peer: &PeerId, now: SystemTime) -> f64 {
+        if !self.scores.contains_key(peer) {
+            return 0.0;
+        }
+        self.entry(*peer, now).value
+    }
+
+    /// Take back a score `peer` had at `at`. It decays from then on, as if
+    /// the node had never stopped.
+    pub fn restore(&mut self, peer: PeerId, score: f64, at: SystemTime) {
+        self.scores.insert(
+            peer,
+            PeerScore {
+                value: score.min(self.config.max_score),
+                updated: at,
+                window_start: at,
+                window_messages: 0,
+                window_social: 0,
+            },
+        );
+    }
+
+    /// Take back a ban issued before a restart
+    pub fn restore_ban(&mut self, peer: PeerId, record: BanRecord) {
+        self.bans.entry(peer).or_insert(record);
+    }
+
+    /// The ban of `peer`, if one is in force
+    pub fn ban(&self, peer: &PeerId, now: SystemTime) -> Option<&BanRecord> {
+        self.bans
+            .get(peer)
+            .filter(|ban| ban.until > unix_seconds(now))
+    }
+
+    /// Count a message from `peer` against its rate limit. Returns false once
+    /// it sent more than allowed in the current window.
+    pub fn within_rate(&mut self, peer: PeerId, now: SystemTime) -> bool {
+        let limit = self.config.rate_limit;
+        let score = self.window(peer, now);
+        score.window_messages += 1;
+        score.window_messages <= limit
+    }
+
+    /// Count a drama or rumor message from `peer` against its social rate
+    /// limit, which is kept apart from [`Self::within_rate`]
+    pub fn within_social_rate(&mut self, peer: PeerId, now: SystemTime) -> bool {
+        let limit = self.config.social_rate_limit;
+        let score = self.window(peer, now);
+        score.window_social += 1;
+        score.window_social <= limit
+    }
+
+    /// The score of `peer`, with its rate window started anew if it ran out
+    fn window(&mut self, peer: PeerId, now: SystemTime) -> &mut PeerScore {
+        let window = self.config.rate_window;
+        let score = self.entry(peer, now);
+        if now.duration_since(score.window_start).unwrap_or_default() >= window {
+            score.window_start = now;
+            score.window_messages = 0;
+            score.window_social = 0;
+        }
+        score
+    }
+
+    /// A message from `peer` was accepted
+    pub fn useful(&mut self, peer: PeerId, now: SystemTime) {
+        let (reward, max) = (self.config.reward, self.config.max_score);
+        let score = self.entry(peer, now);
+        score.value = (score.value + reward).min(max);
+    }
+
+    /// `peer` sent something it should not have. Returns the ban if this
+    /// pushed it below the threshold.
+    pub fn offense(
+        &mut self,
+        peer: PeerId,
+        offense: Offense,
+        now: SystemTime,
+    ) -> Option<BanRecord> {
+        let penalty = self.config.penalties.get(&offense).copied().unwrap_or(0.0);
+        let threshold = self.config.ban_threshold;
+        let score = self.entry(peer, now);
+        score.value -= penalty;
+        if score.value > threshold || self.is_banned(&peer, now) {
+            return None;
+        }
+        self.scores.remove(&peer);
+        let since = unix_seconds(now);
+        let record = BanRecord {
+            peer_id: peer.to_string(),
+            offense,
+            since,
+            until: since + self.config.ban_duration.as_secs(),
+        };
+        self.bans.insert(peer, record.clone());
+        self.save();
+        Some(record)
+    }
+
+    pub fn is_banned(&self, peer: &PeerId, now: SystemTime) -> bool {
+        self.bans
+            .get(peer)
+            .is_some_and(|ban| ban.until > unix_seconds(now))
+    }
+
+    /// Bans still in force
+    pub fn bans(&self, now: SystemTime) -> Vec<BanRecord> {
+        let now = unix_seconds(now);
+        let mut bans: Vec<BanRecord> = self
+            .bans
+            .values()
+            .filter(|ban| ban.until > now)
+            .cloned()
+            .collect();
+        bans.sort_by(|a, b| {
+            a.since
+                .cmp(&b.since)
+                .then_with(|| a.peer_id.cmp(&b.peer_id))
+        });
+        bans
+    }
+
+    /// Lift the bans that ran out, returning the peers that are welcome again
+    pub fn expire(&mut self, now: SystemTime) -> Vec<PeerId> {
+        let now = unix_seconds(now);
+        let expired: Vec<PeerId> = self
+            .bans
+            .iter()
+            .filter(|(_, ban)| ban.until <= now)
+            .map(|(peer, _)| *peer)
+            .collect();
+        if !expired.is_empty() {
+            self.bans.retain(|_, ban| ban.until > now);
+            self.save();
+        }
+        expired
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.config.ban_file else {
+            return;
+        };
+        let mut records: Vec<&BanRecord> = self.bans.values().collect();
+        records.sort_by(|a, b| {
+            a.since
+                .cmp(&b.since)
+                .then_with(|| a.peer_id.cmp(&b.peer_id))
+        });
+        let result = serde_json::to_vec_pretty(&records)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                std::fs::write(path, bytes).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            warn!("Failed to save peer bans to {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+    }
+
+    #[test]
+    fn test_scores_decay_towards_zero() {
+        let mut scores = PeerScores::default();
+        let peer = PeerId::random();
+        for _ in 0..40 {
+            scores.useful(peer, at(0));
+        }
+        assert_eq!(scores.score(&peer, at(0)), 40.0);
+        // Ten minutes halve it
+        assert!((scores.score(&peer, at(600)) - 20.0).abs() < 1e-9);
+        assert!((scores.score(&peer, at(1200)) - 10.0).abs() < 1e-9);
+
+        // Penalties wear off the same way
+        scores.offense(peer, Offense::InvalidSignature, at(1200));
+        assert!((scores.score(&peer, at(1200)) + 20.0).abs() < 1e-9);
+        assert!((scores.score(&peer, at(1800)) + 10.0).abs() < 1e-9);
+
+        // Never past the most a peer can build up
+        for _ in 0..500 {
+            scores.useful(peer, at(1800));
+        }
+        assert_eq!(scores.score(&peer, at(1800)), 100.0);
+        assert_eq!(scores.score(&PeerId::random(), at(1800)), 0.0);
+    }
+
+    #[test]
+    fn test_bans_run_out() {
+        let mut scores = PeerScores::default();
+        let peer = PeerId::random();
+
+        assert!(scores
+            .offense(peer, Offense::InvalidSignature, at(0))
+            .is_none());
+        let ban = scores
+            .offense(peer, Offense::InvalidSignature, at(0))
+            .expect("-60 is past the threshold");
+        assert_eq!(ban.offense, Offense::InvalidSignature);
+        assert_eq!(ban.until - ban.since, 3600);
+        // The ban wiped the score
+        assert_eq!(scores.score(&peer, at(0)), 0.0);
+        assert!(scores.is_banned(&peer, at(3599)));
+        // Offenses of a banned peer issue no new ban
+        assert!(scores.offense(peer, Offense::Spam, at(10)).is_none());
+        assert_eq!(scores.bans(at(10)), vec![ban.clone()]);
+        assert!(scores.expire(at(3599)).is_empty());
+
+        assert!(!scores.is_banned(&peer, at(3600)));
+        assert!(scores.ban(&peer, at(3600)).is_none());
+        assert_eq!(scores.expire(at(3600)), vec![peer]);
+        assert!(scores.bans(at(0)).is_empty());
+    }
+
+    #[test]
+    fn test_bans_outlive_restarts() {
+        let dir = std::env::temp_dir().join(format!("peer-bans-{}", std::process::id()));
+        let config = ScoringConfig {
+            ban_threshold: -10.0,
+            ban_file: Some(dir.join("bans.json")),
+            ..ScoringConfig::default()
+        };
+        let peer = PeerId::random();
+        let mut scores = PeerScores::new(config.clone());
+        let ban = scores.offense(peer, Offense::InvalidBlock, at(0)).unwrap();
+
+        let mut restarted = PeerScores::new(config.clone());
+        assert_eq!(restarted.ban(&peer, at(60)), Some(&ban));
+        restarted.expire(at(3600));
+        assert!(!PeerScores::new(config).is_banned(&peer, at(60)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rate_windows() {
+        let mut scores = PeerScores::new(ScoringConfig {
+            rate_limit: 2,
+            social_rate_limit: 1,
+            ..ScoringConfig::default()
+        });
+        let peer = PeerId::random();
+        assert!(scores.within_rate(peer, at(0)));
+        assert!(scores.within_rate(peer, at(1)));
+        assert!(!scores.within_rate(peer, at(2)));
+        // Social messages count apart
+        assert!(scores.within_social_rate(peer, at(2)));
+        assert!(!scores.within_social_rate(peer, at(3)));
+
+        assert!(scores.within_rate(peer, at(10)));
+        assert!(scores.within_social_rate(peer, at(10)));
+    }
+}
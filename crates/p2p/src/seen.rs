@@ -0,0 +1,104 @@
+//! Messages already handled. Gossipsub forgets the ids it saw after a
+//! minute, after which a transaction or vote still bouncing around the
+//! network would be decoded, validated and forwarded all over again. The
+//! cache remembers ids for longer, so echoes are dropped before they are
+//! even deserialized.
+
+use libp2p::gossipsub::MessageId;
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How long a message id is remembered
+pub const SEEN_TTL: Duration = Duration::from_secs(300);
+
+/// Ids remembered at most, the oldest go first
+pub const MAX_SEEN: usize = 65_536;
+
+#[derive(Debug)]
+pub struct SeenCache {
+    ttl: Duration,
+    capacity: usize,
+    ids: HashSet<MessageId>,
+    /// Ids by the time they were first seen, oldest first
+    order: VecDeque<(Instant, MessageId)>,
+}
+
+impl Default for SeenCache {
+    fn default() -> Self {
+        Self::new(SEEN_TTL, MAX_SEEN)
+    }
+}
+
+impl SeenCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Remember `id`. Returns false when it was seen already.
+    pub fn insert(&mut self, id: &MessageId, now: Instant) -> bool {
+        self.expire(now);
+        if self.ids.contains(id) {
+            return false;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(id.clone());
+        self.order.push_back((now, id.clone()));
+        true
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((seen, _)) = self.order.front() {
+            if now.saturating_duration_since(*seen) < self.ttl {
+                break;
+            }
+            if let Some((_, id)) = self.order.pop_front() {
+                self.ids.remove(&id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u32) -> MessageId {
+        MessageId::from(n.to_be_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_ids_are_forgotten_after_their_ttl() {
+        let mut seen = SeenCache::new(Duration::from_secs(300), 16);
+        let start = Instant::now();
+        assert!(seen.insert(&id(1), start));
+        assert!(!seen.insert(&id(1), start + Duration::from_secs(299)));
+        assert!(seen.insert(&id(2), start + Duration::from_secs(100)));
+
+        // A repeat does not make an id remembered for longer
+        assert!(seen.insert(&id(1), start + Duration::from_secs(300)));
+        assert!(!seen.insert(&id(2), start + Duration::from_secs(399)));
+        assert!(seen.insert(&id(2), start + Duration::from_secs(400)));
+    }
+
+    #[test]
+    fn test_the_oldest_ids_go_first_when_full() {
+        let mut seen = SeenCache::new(Duration::from_secs(300), 3);
+        let now = Instant::now();
+        for n in 0..4 {
+            assert!(seen.insert(&id(n), now));
+        }
+        assert_eq!(seen.ids.len(), 3);
+        assert!(!seen.insert(&id(3), now));
+        assert!(seen.insert(&id(0), now));
+        assert!(seen.insert(&id(1), now));
+    }
+}
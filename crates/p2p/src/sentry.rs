@@ -0,0 +1,162 @@
+//! Sentry nodes. A validator can hide behind a few nodes its operator runs
+//! for it. It then connects to those sentries only, and they relay its
+//! votes and blocks to the rest of the network, and the network's traffic
+//! back. Sentries leave the validator out of whatever they tell other peers,
+//! so its addresses never get around and nobody else can dial it.
+
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct SentryConfig {
+    /// On a validator: the sentries it hides behind, each ending in
+    /// `/p2p/<peer id>`. The node then talks to its sentries and static
+    /// peers only.
+    pub sentries: Vec<Multiaddr>,
+    /// On a sentry: the validators behind it, never passed on to others
+    pub private_peers: Vec<PeerId>,
+    /// A sentry not heard from for this long counts as unhealthy
+    pub unhealthy_after: Duration,
+}
+
+impl Default for SentryConfig {
+    fn default() -> Self {
+        Self {
+            sentries: Vec::new(),
+            private_peers: Vec::new(),
+            unhealthy_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/// How the link to a sentry is doing
+#[derive(Debug, Clone, Serialize)]
+pub struct SentryHealth {
+    pub peer_id: String,
+    pub address: String,
+    pub connected: bool,
+    /// Round trip of the last ping, in milliseconds
+    pub rtt_ms: Option<u64>,
+    /// Seconds since the sentry was last heard from
+    pub silent_for: Option<u64>,
+    /// Connected and heard from lately
+    pub healthy: bool,
+}
+
+#[derive(Debug)]
+struct Link {
+    address: Multiaddr,
+    connected: bool,
+    heard: Option<Instant>,
+    rtt: Option<Duration>,
+}
+
+/// The sentries of a validator, or the validators behind a sentry
+#[derive(Debug, Default)]
+pub struct Sentries {
+    links: HashMap<PeerId, Link>,
+    private: HashSet<PeerId>,
+    unhealthy_after: Duration,
+}
+
+impl Sentries {
+    /// The sentries in `config`. Addresses without a peer id are left out,
+    /// a hidden validator must know who it is talking to.
+    pub fn new(config: &SentryConfig) -> Self {
+        let links = config
+            .sentries
+            .iter()
+            .filter_map(|address| {
+                let peer = address.iter().find_map(|protocol| match protocol {
+                    Protocol::P2p(peer) => Some(peer),
+                    _ => None,
+                })?;
+                let link = Link {
+                    address: address.clone(),
+                    connected: false,
+                    heard: None,
+                    rtt: None,
+                };
+                Some((peer, link))
+            })
+            .collect();
+        Self {
+            links,
+            private: config.private_peers.iter().copied().collect(),
+            unhealthy_after: config.unhealthy_after,
+        }
+    }
+
+    /// Whether this node is a validator hiding behind sentries
+    pub fn is_hidden(&self) -> bool {
+        !self.links.is_empty()
+    }
+
+    pub fn is_sentry(&self, peer: &PeerId) -> bool {
+        self.links.contains_key(peer)
+    }
+
+    /// Whether `peer` is a validator behind us, kept from other peers
+    pub fn is_private(&self, peer: &PeerId) -> bool {
+        self.private.contains(peer)
+    }
+
+    pub fn connected(&mut self, peer: &PeerId, now: Instant) {
+        if let Some(link) = self.links.get_mut(peer) {
+            link.connected = true;
+            link.heard = Some(now);
+        }
+    }
+
+    pub fn disconnected(&mut self, peer: &PeerId) {
+        if let Some(link) = self.links.get_mut(peer) {
+            link.connected = false;
+            link.rtt = None;
+        }
+    }
+
+    /// A ping to `peer` came back after `rtt`
+    pub fn pinged(&mut self, peer: &PeerId, rtt: Duration, now: Instant) {
+        if let Some(link) = self.links.get_mut(peer) {
+            link.heard = Some(now);
+            link.rtt = Some(rtt);
+        }
+    }
+
+    fn is_healthy(&self, link: &Link, now: Instant) -> bool {
+        link.connected
+            && link
+                .heard
+                .is_some_and(|heard| now.saturating_duration_since(heard) < self.unhealthy_after)
+    }
+
+    /// Sentries that are connected and were heard from lately
+    pub fn healthy(&self, now: Instant) -> usize {
+        self.links
+            .values()
+            .filter(|link| self.is_healthy(link, now))
+            .count()
+    }
+
+    pub fn health(&self, now: Instant) -> Vec<SentryHealth> {
+        let mut health: Vec<SentryHealth> = self
+            .links
+            .iter()
+            .map(|(peer, link)| SentryHealth {
+                peer_id: peer.to_string(),
+                address: link.address.to_string(),
+                connected: link.connected,
+                rtt_ms: link.rtt.map(|rtt| rtt.as_millis() as u64),
+                silent_for: link
+                    .heard
+                    .map(|heard| now.saturating_duration_since(heard).as_secs()),
+                healthy: self.is_healthy(link, now),
+            })
+            .collect();
+        health.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        health
+    }
+}
@@ -0,0 +1,151 @@
+//! Connection slots. A node keeps so many connections it accepted and so
+//! many it dialed, so it behaves the same however hard it is pushed. The
+//! static peers, the validators of the overlay and the sentries are listed
+//! by the operator and have slots of their own on top of those: they are
+//! always let in and never evicted. An inbound connection past the limit
+//! is either turned away or takes the slot of the worst scoring inbound
+//! peer, as the [`EvictionPolicy`] says.
+
+use crate::scoring::PeerScores;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Instant, SystemTime};
+
+/// What becomes of an inbound connection once the inbound slots are taken
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    /// Turn the newcomer away
+    Reject,
+    /// Make room by dropping the inbound peer with the lowest score, if the
+    /// newcomer scores better. Among equals the youngest connection goes.
+    #[default]
+    LowestScore,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionLimits {
+    /// Connections accepted from peers
+    pub max_inbound: usize,
+    /// Connections to peers we dialed
+    pub max_outbound: usize,
+    pub eviction: EvictionPolicy,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_inbound: 30,
+            max_outbound: 20,
+            eviction: EvictionPolicy::default(),
+        }
+    }
+}
+
+/// What to do with a new connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Accept,
+    /// No slot is left, close it
+    Reject,
+    /// Accept it, and close the connection to this peer to make room
+    Evict(PeerId),
+}
+
+/// How the slots are taken
+#[derive(Debug, Clone, Serialize)]
+pub struct SlotStatus {
+    pub inbound: usize,
+    pub max_inbound: usize,
+    pub outbound: usize,
+    pub max_outbound: usize,
+    /// Static peers, overlay validators and sentries
+    pub reserved: usize,
+}
+
+/// The slot each connected peer holds
+#[derive(Debug, Default)]
+pub struct Slots {
+    limits: ConnectionLimits,
+    /// Inbound peers, with the time they connected
+    inbound: HashMap<PeerId, Instant>,
+    outbound: HashSet<PeerId>,
+    reserved: HashSet<PeerId>,
+}
+
+impl Slots {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Whether dialing another peer would go past the outbound limit
+    pub fn outbound_full(&self) -> bool {
+        self.outbound.len() >= self.limits.max_outbound
+    }
+
+    /// Find a slot for `peer`, which connected to us when `inbound`
+    pub fn admit(
+        &mut self,
+        peer: PeerId,
+        inbound: bool,
+        reserved: bool,
+        scores: &mut PeerScores,
+        now: Instant,
+    ) -> Admission {
+        if reserved {
+            self.reserved.insert(peer);
+            return Admission::Accept;
+        }
+        if !inbound {
+            if self.outbound_full() {
+                return Admission::Reject;
+            }
+            self.outbound.insert(peer);
+            return Admission::Accept;
+        }
+        if self.inbound.len() < self.limits.max_inbound {
+            self.inbound.insert(peer, now);
+            return Admission::Accept;
+        }
+        if self.limits.eviction == EvictionPolicy::Reject {
+            return Admission::Reject;
+        }
+        let score = scores.score(&peer, SystemTime::now());
+        let victim = self
+            .inbound
+            .iter()
+            .map(|(peer, since)| (*peer, scores.score(peer, SystemTime::now()), *since))
+            .filter(|(_, victim, _)| *victim < score)
+            .min_by(|(_, a, a_since), (_, b, b_since)| a.total_cmp(b).then(b_since.cmp(a_since)))
+            .map(|(victim, _, _)| victim);
+        match victim {
+            Some(victim) => {
+                self.inbound.remove(&victim);
+                self.inbound.insert(peer, now);
+                Admission::Evict(victim)
+            }
+            None => Admission::Reject,
+        }
+    }
+
+    /// `peer` disconnected, its slot is free again
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.inbound.remove(peer);
+        self.outbound.remove(peer);
+        self.reserved.remove(peer);
+    }
+
+    pub fn status(&self) -> SlotStatus {
+        SlotStatus {
+            inbound: self.inbound.len(),
+            max_inbound: self.limits.max_inbound,
+            outbound: self.outbound.len(),
+            max_outbound: self.limits.max_outbound,
+            reserved: self.reserved.len(),
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! State snapshots over the sync protocol. Peers advertise the manifests of
+//! the snapshots they hold. A fresh node picks one and downloads its chunks
+//! from every peer that has it, checking each chunk as it comes in.
+
+use crate::scoring::Offense;
+use crate::sync::SyncRequest;
+use chaoschain_state::snapshot::{SnapshotArchive, SnapshotChunk, SnapshotManifest};
+use chaoschain_state::{StateError, StateSnapshot, StateStoreImpl};
+use libp2p::request_response::OutboundRequestId;
+use libp2p::PeerId;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+
+/// Snapshots a node serves
+pub trait SnapshotStore: Send + Sync {
+    fn manifests(&self) -> Vec<SnapshotManifest>;
+    fn chunk(&self, snapshot: &[u8; 32], index: u32) -> Option<SnapshotChunk>;
+}
+
+/// The latest few snapshots, in memory
+pub struct SnapshotShelf {
+    archives: RwLock<Vec<SnapshotArchive>>,
+    keep: usize,
+}
+
+impl SnapshotShelf {
+    pub fn new(keep: usize) -> Self {
+        Self {
+            archives: RwLock::new(Vec::new()),
+            keep: keep.max(1),
+        }
+    }
+
+    /// Offer a new snapshot, dropping the oldest beyond the ones kept
+    pub fn add(&self, archive: SnapshotArchive) {
+        let mut archives = self.archives.write();
+        archives.push(archive);
+        let excess = archives.len().saturating_sub(self.keep);
+        archives.drain(..excess);
+    }
+}
+
+impl SnapshotStore for SnapshotShelf {
+    fn manifests(&self) -> Vec<SnapshotManifest> {
+        self.archives
+            .read()
+            .iter()
+            .map(|archive| archive.manifest.clone())
+            .collect()
+    }
+
+    fn chunk(&self, snapshot: &[u8; 32], index: u32) -> Option<SnapshotChunk> {
+        self.archives
+            .read()
+            .iter()
+            .find(|archive| archive.manifest.id() == *snapshot)
+            .and_then(|archive| archive.chunk(index).cloned())
+    }
+}
+
+/// Where a downloaded snapshot is restored
+pub trait SnapshotTarget: Send + Sync {
+    fn restore(&self, snapshot: StateSnapshot) -> Result<(), String>;
+}
+
+impl SnapshotTarget for Mutex<StateStoreImpl> {
+    fn restore(&self, snapshot: StateSnapshot) -> Result<(), String> {
+        self.lock()
+            .recover_from_snapshot(snapshot)
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    /// Chunk requests in flight to one peer
+    pub per_peer_requests: usize,
+    /// Chunk requests in flight overall
+    pub max_requests: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            per_peer_requests: 4,
+            max_requests: 16,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Download {
+    manifest: SnapshotManifest,
+    /// Chunks nobody is fetching yet
+    missing: Vec<u32>,
+    chunks: Vec<SnapshotChunk>,
+}
+
+/// A snapshot download: the offers heard so far, and the chunks of the one
+/// being fetched
+#[derive(Debug, Default)]
+pub struct SnapshotDownload {
+    config: SnapshotConfig,
+    offers: HashMap<[u8; 32], (SnapshotManifest, HashSet<PeerId>)>,
+    download: Option<Download>,
+    /// Chunk requests, by the chunk they are for
+    in_flight: HashMap<OutboundRequestId, (PeerId, u32)>,
+    manifest_requests: HashMap<OutboundRequestId, PeerId>,
+}
+
+impl SnapshotDownload {
+    pub fn new(config: SnapshotConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.download.is_some()
+    }
+
+    /// Whether `id` is one of ours
+    pub fn owns(&self, id: &OutboundRequestId) -> bool {
+        self.in_flight.contains_key(id) || self.manifest_requests.contains_key(id)
+    }
+
+    /// Manifests of `peer` were asked for with `id`
+    pub fn asked(&mut self, id: OutboundRequestId, peer: PeerId) {
+        self.manifest_requests.insert(id, peer);
+    }
+
+    /// `peer` holds these snapshots
+    pub fn offered(&mut self, id: &OutboundRequestId, manifests: Vec<SnapshotManifest>) {
+        let Some(peer) = self.manifest_requests.remove(id) else {
+            return;
+        };
+        for manifest in manifests {
+            self.offers
+                .entry(manifest.id())
+                .or_insert_with(|| (manifest, HashSet::new()))
+                .1
+                .insert(peer);
+        }
+    }
+
+    /// Settle on the most recent snapshot, the most widely held one among
+    /// equals. Returns its manifest if a download started.
+    pub fn choose(&mut self) -> Option<&SnapshotManifest> {
+        if self.download.is_some() {
+            return None;
+        }
+        let (manifest, _) = self
+            .offers
+            .values()
+            .max_by_key(|(manifest, peers)| (manifest.height, peers.len(), manifest.id()))?;
+        self.download = Some(Download {
+            manifest: manifest.clone(),
+            missing: (0..manifest.chunk_count).rev().collect(),
+            chunks: Vec::new(),
+        });
+        self.download.as_ref().map(|download| &download.manifest)
+    }
+
+    fn load(&self, peer: &PeerId) -> usize {
+        self.in_flight
+            .values()
+            .filter(|(owner, _)| owner == peer)
+            .count()
+    }
+
+    /// Chunk requests to send next, spread over the peers holding the
+    /// snapshot
+    pub fn plan(&mut self) -> Vec<(PeerId, SyncRequest)> {
+        let Some(download) = &self.download else {
+            return Vec::new();
+        };
+        let id = download.manifest.id();
+        let mut holders: Vec<(PeerId, usize)> = self
+            .offers
+            .get(&id)
+            .map(|(_, peers)| peers.iter().map(|peer| (*peer, self.load(peer))).collect())
+            .unwrap_or_default();
+        holders.sort_by_key(|(peer, load)| (*load, *peer));
+
+        let mut planned = Vec::new();
+        let mut missing = download.missing.clone();
+        'fill: while self.in_flight.len() + planned.len() < self.config.max_requests {
+            for (peer, load) in holders.iter_mut() {
+                if *load >= self.config.per_peer_requests {
+                    continue;
+                }
+                let Some(index) = missing.pop() else {
+                    break 'fill;
+                };
+                *load += 1;
+                planned.push((
+                    *peer,
+                    SyncRequest::SnapshotChunk {
+                        snapshot: id,
+                        index,
+                    },
+                ));
+                if self.in_flight.len() + planned.len() >= self.config.max_requests {
+                    break 'fill;
+                }
+            }
+            if holders
+                .iter()
+                .all(|(_, load)| *load >= self.config.per_peer_requests)
+            {
+                break;
+            }
+        }
+        if let Some(download) = &mut self.download {
+            download.missing = missing;
+        }
+        planned
+    }
+
+    /// A chunk request planned by [`Self::plan`] was sent
+    pub fn sent(&mut self, id: OutboundRequestId, peer: PeerId, request: &SyncRequest) {
+        if let SyncRequest::SnapshotChunk { index, .. } = request {
+            self.in_flight.insert(id, (peer, *index));
+        }
+    }
+
+    /// A chunk came in. Returns the peer that sent it, and the offense if it
+    /// does not check out.
+    pub fn answered(
+        &mut self,
+        id: &OutboundRequestId,
+        chunk: Option<SnapshotChunk>,
+    ) -> Option<(PeerId, Result<(), Offense>)> {
+        let (peer, index) = self.in_flight.remove(id)?;
+        let download = self.download.as_mut()?;
+        let result = match chunk {
+            Some(chunk) if chunk.index == index && chunk.verify(&download.manifest).is_ok() => {
+                download.chunks.push(chunk);
+                Ok(())
+            }
+            Some(_) => {
+                download.missing.push(index);
+                Err(Offense::Malformed)
+            }
+            None => {
+                // The peer no longer has it
+                download.missing.push(index);
+                if let Some((_, peers)) = self.offers.get_mut(&download.manifest.id()) {
+                    peers.remove(&peer);
+                }
+                Ok(())
+            }
+        };
+        Some((peer, result))
+    }
+
+    /// A request failed, the chunk goes back in the queue
+    pub fn failed(&mut self, id: &OutboundRequestId) {
+        self.manifest_requests.remove(id);
+        if let (Some((_, index)), Some(download)) = (self.in_flight.remove(id), &mut self.download)
+        {
+            download.missing.push(index);
+        }
+    }
+
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        for (_, peers) in self.offers.values_mut() {
+            peers.remove(peer);
+        }
+        let lost: Vec<OutboundRequestId> = self
+            .in_flight
+            .iter()
+            .filter(|(_, (owner, _))| owner == peer)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in lost {
+            self.failed(&id);
+        }
+    }
+
+    /// The snapshot, once every chunk is in. A download nobody can serve
+    /// anymore is given up, so another snapshot can be chosen.
+    pub fn finished(&mut self) -> Option<Result<StateSnapshot, StateError>> {
+        let download = self.download.as_ref()?;
+        let id = download.manifest.id();
+        if download.chunks.len() < download.manifest.chunk_count as usize {
+            let abandoned = self
+                .offers
+                .get(&id)
+                .is_none_or(|(_, peers)| peers.is_empty())
+                && self.in_flight.is_empty();
+            if abandoned {
+                self.offers.remove(&id);
+                self.download = None;
+            }
+            return None;
+        }
+        let download = self.download.take()?;
+        self.offers.clear();
+        Some(SnapshotArchive::assemble(
+            download.manifest,
+            download.chunks,
+        ))
+    }
+}
@@ -0,0 +1,800 @@
+//! Catching up on the chain: a fresh node asks its peers for headers by
+//! height, then fetches the bodies and finality certificates of those
+//! headers, and imports the blocks in order once both are in.
+
+use crate::scoring::Offense;
+use crate::wire::{self, Limits};
+use chaoschain_core::rotation::ValidatorKeys;
+use chaoschain_core::sync::SyncState;
+use chaoschain_core::threshold::finality_message;
+use chaoschain_core::vote::SignedExplanation;
+use chaoschain_core::{Block, BlockHeader, Transaction};
+use chaoschain_crypto::batch;
+use chaoschain_crypto::threshold::GroupKey;
+use chaoschain_state::snapshot::{SnapshotChunk, SnapshotManifest};
+use chaoschain_state::StateStoreImpl;
+use libp2p::request_response::OutboundRequestId;
+use libp2p::{PeerId, StreamProtocol};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Protocol blocks are synced with
+pub const SYNC_PROTOCOL: StreamProtocol = StreamProtocol::new("/chaoschain/sync/1");
+
+/// Most headers or blocks served per request
+pub const MAX_SYNC_BATCH: u32 = 128;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncRequest {
+    /// Height and hash of the peer's latest block
+    Status,
+    /// Headers of `count` blocks starting at height `from`
+    Headers { from: u64, count: u32 },
+    /// Blocks by hash
+    Bodies { hashes: Vec<[u8; 32]> },
+    /// Finality certificates of blocks, by hash
+    Certificates { hashes: Vec<[u8; 32]> },
+    /// Transactions of a compact block, by position
+    Transactions {
+        block_hash: [u8; 32],
+        indexes: Vec<u32>,
+    },
+    /// Manifests of the snapshots the peer serves
+    SnapshotManifests,
+    /// One chunk of a snapshot, by the id of its manifest
+    SnapshotChunk { snapshot: [u8; 32], index: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SyncResponse {
+    Status { height: Option<u64>, head: [u8; 32] },
+    Headers(Vec<BlockHeader>),
+    Bodies(Vec<Block>),
+    Certificates(Vec<FinalityCertificate>),
+    Transactions(Vec<Transaction>),
+    SnapshotManifests(Vec<SnapshotManifest>),
+    SnapshotChunk(Option<SnapshotChunk>),
+}
+
+/// Signed votes that finalized a block, and the group signature of the
+/// validators when the network signs with a threshold key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinalityCertificate {
+    pub height: u64,
+    pub block_hash: [u8; 32],
+    pub explanations: Vec<SignedExplanation>,
+    /// Empty when there is none
+    #[serde(default)]
+    pub group_signature: Vec<u8>,
+}
+
+impl FinalityCertificate {
+    pub fn of(block: &Block) -> Self {
+        Self {
+            height: block.height,
+            block_hash: block.hash(),
+            explanations: block.metadata.explanations.clone(),
+            group_signature: block
+                .metadata
+                .group_signature
+                .as_ref()
+                .map(|signature| signature.0.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The group signature holds for `group`, which takes a threshold of
+    /// the validators and stands for their votes: one signature to check
+    /// rather than one per vote
+    pub fn verify_group(&self, group: &GroupKey) -> Result<(), chaoschain_core::Error> {
+        if group.verify(
+            &finality_message(self.height, &self.block_hash),
+            &self.group_signature,
+        ) {
+            Ok(())
+        } else {
+            Err(chaoschain_core::Error::InvalidSignature(format!(
+                "Block {} carries no group signature of the validators",
+                self.height
+            )))
+        }
+    }
+
+    /// Every signature holds up, and at least `min_approvals` validators
+    /// approved, more than rejected. The signatures are checked together.
+    pub fn verify(&self, min_approvals: usize) -> Result<(), chaoschain_core::Error> {
+        let items: Vec<batch::Item> = self
+            .explanations
+            .iter()
+            .map(|explanation| {
+                batch::Item::new(
+                    explanation.public_key,
+                    explanation.signing_bytes(self.height, &self.block_hash),
+                    &explanation.signature,
+                )
+            })
+            .collect();
+        if let Err(e) = batch::verify(&items) {
+            return Err(chaoschain_core::Error::InvalidSignature(format!(
+                "The vote of {} does not hold, {}",
+                self.explanations[e.invalid[0]].validator, e
+            )));
+        }
+        let mut validators = HashSet::new();
+        for explanation in &self.explanations {
+            if !validators.insert(&explanation.validator) {
+                return Err(chaoschain_core::Error::InvalidSignature(format!(
+                    "{} signed twice",
+                    explanation.validator
+                )));
+            }
+        }
+        let approvals = self.explanations.iter().filter(|e| e.approved).count();
+        let rejections = self.explanations.len() - approvals;
+        if approvals < min_approvals || approvals <= rejections {
+            return Err(chaoschain_core::Error::InvalidBlock(format!(
+                "Block {} is not final: {} approvals, {} rejections",
+                self.height, approvals, rejections
+            )));
+        }
+        Ok(())
+    }
+
+    /// Every vote is signed with a key its validator signs with at the
+    /// height of the block, as of `keys`
+    pub fn verify_keys(&self, keys: &ValidatorKeys) -> Result<(), chaoschain_core::Error> {
+        for explanation in &self.explanations {
+            if !keys.accepts(&explanation.validator, &explanation.public_key, self.height) {
+                return Err(chaoschain_core::Error::InvalidSignature(format!(
+                    "{} voted on block {} with 0x{}, which is not its key",
+                    explanation.validator,
+                    self.height,
+                    hex::encode(explanation.public_key)
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The chain a node serves to its peers and imports synced blocks into
+pub trait ChainSource: Send + Sync {
+    /// Height and hash of the latest block, if there is one
+    fn head(&self) -> Option<(u64, [u8; 32])>;
+    /// Blocks from height `from` on, at most `count`
+    fn blocks(&self, from: u64, count: usize) -> Vec<Block>;
+    /// Headers of the blocks from height `from` on, at most `count`
+    fn headers(&self, from: u64, count: usize) -> Vec<BlockHeader> {
+        self.blocks(from, count)
+            .iter()
+            .map(BlockHeader::of)
+            .collect()
+    }
+    fn block(&self, hash: &[u8; 32]) -> Option<Block>;
+    /// Import a block on top of the head
+    fn import(&self, block: &Block) -> Result<(), String>;
+}
+
+impl ChainSource for StateStoreImpl {
+    fn head(&self) -> Option<(u64, [u8; 32])> {
+        self.get_latest_block()
+            .map(|block| (block.height, block.hash()))
+    }
+
+    fn blocks(&self, from: u64, count: usize) -> Vec<Block> {
+        self.get_blocks(from, count)
+    }
+
+    fn headers(&self, from: u64, count: usize) -> Vec<BlockHeader> {
+        self.get_headers(from, count)
+    }
+
+    fn block(&self, hash: &[u8; 32]) -> Option<Block> {
+        self.get_block(hash)
+    }
+
+    fn import(&self, block: &Block) -> Result<(), String> {
+        if let Some((height, head)) = self.head() {
+            if block.height != height + 1 || block.parent_hash != head {
+                return Err(format!(
+                    "Block {} does not extend block {}",
+                    block.height, height
+                ));
+            }
+        }
+        self.apply_block(block).map_err(|e| e.to_string())
+    }
+}
+
+/// Answer a sync request from `chain`. Snapshots are served by the network
+/// itself, requests for them get empty answers here.
+pub fn serve(chain: &dyn ChainSource, request: SyncRequest) -> SyncResponse {
+    match request {
+        SyncRequest::Status => {
+            let head = chain.head();
+            SyncResponse::Status {
+                height: head.map(|(height, _)| height),
+                head: head.map_or([0; 32], |(_, hash)| hash),
+            }
+        }
+        SyncRequest::Headers { from, count } => {
+            SyncResponse::Headers(chain.headers(from, count.min(MAX_SYNC_BATCH) as usize))
+        }
+        SyncRequest::Bodies { hashes } => SyncResponse::Bodies(
+            hashes
+                .iter()
+                .take(MAX_SYNC_BATCH as usize)
+                .filter_map(|hash| chain.block(hash))
+                .collect(),
+        ),
+        SyncRequest::Certificates { hashes } => SyncResponse::Certificates(
+            hashes
+                .iter()
+                .take(MAX_SYNC_BATCH as usize)
+                .filter_map(|hash| chain.block(hash))
+                .map(|block| FinalityCertificate::of(&block))
+                .collect(),
+        ),
+        SyncRequest::Transactions {
+            block_hash,
+            indexes,
+        } => SyncResponse::Transactions(
+            chain
+                .block(&block_hash)
+                .map(|block| transactions_at(&block, &indexes))
+                .unwrap_or_default(),
+        ),
+        SyncRequest::SnapshotManifests => SyncResponse::SnapshotManifests(Vec::new()),
+        SyncRequest::SnapshotChunk { .. } => SyncResponse::SnapshotChunk(None),
+    }
+}
+
+/// Transactions of `block` at `indexes`, as long as all of them exist
+pub fn transactions_at(block: &Block, indexes: &[u32]) -> Vec<Transaction> {
+    indexes
+        .iter()
+        .map(|index| block.transactions.get(*index as usize).cloned())
+        .collect::<Option<Vec<_>>>()
+        .unwrap_or_default()
+}
+
+/// Hashes or positions asked for, and a batch of blocks or a snapshot chunk
+/// back
+pub const SYNC_LIMITS: Limits = Limits {
+    request: 64 * 1024,
+    response: 16 * 1024 * 1024,
+};
+
+pub type SyncBehaviour = wire::Behaviour<SyncRequest, SyncResponse>;
+
+pub fn behaviour() -> SyncBehaviour {
+    wire::behaviour(SYNC_PROTOCOL, SYNC_LIMITS)
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncConfig {
+    /// Headers or blocks asked for per request
+    pub batch_size: u32,
+    /// Requests in flight to one peer
+    pub per_peer_requests: usize,
+    /// Requests in flight overall
+    pub max_requests: usize,
+    /// Approvals a certificate needs for its block to be imported
+    pub min_approvals: usize,
+    /// Keys certificates are checked against, those of the genesis as of
+    /// the head, followed through the rotations of the synced blocks. Any
+    /// key is taken without.
+    pub validators: Option<ValidatorKeys>,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 32,
+            per_peer_requests: 2,
+            max_requests: 8,
+            min_approvals: 1,
+            validators: None,
+        }
+    }
+}
+
+/// What [`Syncer::ready`] found
+#[derive(Debug, Default)]
+pub struct Ready {
+    pub blocks: Vec<(Block, PeerId)>,
+    /// Peer whose headers do not fit onto our chain
+    pub forked: Option<PeerId>,
+    /// Peer whose certificate has votes of keys that are not the
+    /// validators', with why
+    pub invalid: Option<(PeerId, String)>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct PeerSync {
+    height: Option<u64>,
+    in_flight: usize,
+}
+
+/// Where a sync stands: which peers have what, and which headers, bodies and
+/// certificates are in or on their way. Requests run in parallel across
+/// peers, while blocks are handed out for import strictly in order.
+#[derive(Debug, Default)]
+pub struct Syncer {
+    config: SyncConfig,
+    peers: HashMap<PeerId, PeerSync>,
+    /// Lowest height whose header has not been asked for yet
+    next_header: u64,
+    /// Header ranges whose requests failed, to be asked for again
+    retry: Vec<(u64, u32)>,
+    headers: BTreeMap<u64, (BlockHeader, PeerId)>,
+    bodies: HashMap<[u8; 32], Block>,
+    certificates: HashMap<[u8; 32], FinalityCertificate>,
+    /// Hashes whose body or certificate is being fetched
+    fetching: HashSet<[u8; 32]>,
+    in_flight: HashMap<OutboundRequestId, (PeerId, SyncRequest)>,
+}
+
+impl Syncer {
+    pub fn new(config: SyncConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Whether peers know of blocks we do not have
+    pub fn is_behind(&self, head: Option<u64>) -> bool {
+        let best = self.peers.values().filter_map(|peer| peer.height).max();
+        best.is_some_and(|best| head.is_none_or(|head| best > head))
+    }
+
+    /// Where we stand against the peers, given the height of the local head
+    pub fn state(&self, head: Option<u64>) -> SyncState {
+        let ahead = |height: u64| head.is_none_or(|head| height > head);
+        let heights = || self.peers.values().filter_map(|peer| peer.height);
+        match heights().max() {
+            Some(best) if ahead(best) => SyncState::Syncing {
+                current: head.unwrap_or(0),
+                highest_seen: best,
+                peers: heights().filter(|height| ahead(*height)).count(),
+            },
+            _ => SyncState::Synced,
+        }
+    }
+
+    pub fn add_peer(&mut self, peer: PeerId) {
+        self.peers.entry(peer).or_default();
+    }
+
+    /// Forget a peer, giving its requests to the others
+    pub fn remove_peer(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+        let lost: Vec<OutboundRequestId> = self
+            .in_flight
+            .iter()
+            .filter(|(_, (owner, _))| owner == peer)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in lost {
+            self.failed(id);
+        }
+    }
+
+    /// A request planned by [`Self::plan`] was sent
+    pub fn sent(&mut self, id: OutboundRequestId, peer: PeerId, request: SyncRequest) {
+        self.in_flight.insert(id, (peer, request));
+    }
+
+    /// Pick a peer that has `height` and room for `requests` more requests,
+    /// on top of the `planned` ones not sent yet
+    fn pick(&self, height: u64, requests: usize, planned: usize) -> Option<PeerId> {
+        if self.in_flight.len() + planned + requests > self.config.max_requests {
+            return None;
+        }
+        self.peers
+            .iter()
+            .filter(|(_, peer)| {
+                peer.in_flight + requests <= self.config.per_peer_requests
+                    && peer.height.is_some_and(|known| known >= height)
+            })
+            .min_by_key(|(id, peer)| (peer.in_flight, **id))
+            .map(|(id, _)| *id)
+    }
+
+    /// Requests to send next, given the height of the local head. Headers
+    /// are asked for ahead of the bodies, so several batches are in flight.
+    pub fn plan(&mut self, head: Option<u64>) -> Vec<(PeerId, SyncRequest)> {
+        let first = head.map_or(0, |head| head + 1);
+        self.next_header = self.next_header.max(first);
+        self.headers.retain(|height, _| *height >= first);
+        let best = self.peers.values().filter_map(|peer| peer.height).max();
+
+        let mut planned = Vec::new();
+        while self.in_flight.len() + planned.len() < self.config.max_requests {
+            // Bodies and certificates of the headers we have
+            let wanted: Vec<(u64, [u8; 32])> = self
+                .headers
+                .values()
+                .filter(|(header, _)| {
+                    !self.fetching.contains(&header.hash) && !self.bodies.contains_key(&header.hash)
+                })
+                .take(self.config.batch_size as usize)
+                .map(|(header, _)| (header.height, header.hash))
+                .collect();
+            if let Some((top, _)) = wanted.last() {
+                if let Some(peer) = self.pick(*top, 2, planned.len()) {
+                    let hashes: Vec<[u8; 32]> = wanted.iter().map(|(_, hash)| *hash).collect();
+                    self.fetching.extend(hashes.iter().copied());
+                    self.peers.get_mut(&peer).expect("Picked peer").in_flight += 2;
+                    planned.push((
+                        peer,
+                        SyncRequest::Bodies {
+                            hashes: hashes.clone(),
+                        },
+                    ));
+                    planned.push((peer, SyncRequest::Certificates { hashes }));
+                    continue;
+                }
+            }
+
+            // More headers, retried ranges first
+            let range = match self.retry.pop() {
+                Some(range) => Some(range),
+                None => best.filter(|best| *best >= self.next_header).map(|best| {
+                    let count = (best - self.next_header + 1).min(self.config.batch_size as u64);
+                    let range = (self.next_header, count as u32);
+                    self.next_header += count;
+                    range
+                }),
+            };
+            let Some((from, count)) = range else {
+                break;
+            };
+            let Some(peer) = self.pick(from + count as u64 - 1, 1, planned.len()) else {
+                self.retry.push((from, count));
+                break;
+            };
+            self.peers.get_mut(&peer).expect("Picked peer").in_flight += 1;
+            planned.push((peer, SyncRequest::Headers { from, count }));
+        }
+        planned
+    }
+
+    fn settle(&mut self, id: OutboundRequestId) -> Option<(PeerId, SyncRequest)> {
+        let (peer, request) = self.in_flight.remove(&id)?;
+        if let Some(state) = self.peers.get_mut(&peer) {
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+        Some((peer, request))
+    }
+
+    /// A request failed or its peer went away, ask someone else
+    pub fn failed(&mut self, id: OutboundRequestId) {
+        if let Some((_, request)) = self.settle(id) {
+            self.failed_request(request);
+        }
+    }
+
+    /// Take in the answer to a request. Returns the peer that answered, and
+    /// the offense if the answer does not fit the question.
+    pub fn answered(
+        &mut self,
+        id: OutboundRequestId,
+        response: SyncResponse,
+    ) -> Option<(PeerId, Result<(), Offense>)> {
+        let (peer, request) = self.settle(id)?;
+        let result = match (request, response) {
+            (SyncRequest::Status, SyncResponse::Status { height, .. }) => {
+                self.peers.entry(peer).or_default().height = height;
+                Ok(())
+            }
+            (SyncRequest::Headers { from, count }, SyncResponse::Headers(headers)) => {
+                self.headers_arrived(peer, from, count, headers)
+            }
+            (SyncRequest::Bodies { hashes }, SyncResponse::Bodies(blocks)) => {
+                let mut result = Ok(());
+                for block in blocks {
+                    let hash = block.hash();
+                    if hashes.contains(&hash) {
+                        self.bodies.insert(hash, block);
+                    } else {
+                        result = Err(Offense::Malformed);
+                    }
+                }
+                // Whatever did not come is asked for again
+                for hash in hashes {
+                    if !self.bodies.contains_key(&hash) {
+                        self.fetching.remove(&hash);
+                    }
+                }
+                result
+            }
+            (SyncRequest::Certificates { hashes }, SyncResponse::Certificates(certificates)) => {
+                let mut result = Ok(());
+                for certificate in certificates {
+                    if !hashes.contains(&certificate.block_hash) {
+                        result = Err(Offense::Malformed);
+                    } else if let Err(e) = certificate.verify(self.config.min_approvals) {
+                        result = Err(Offense::of(&e));
+                    } else {
+                        self.certificates
+                            .insert(certificate.block_hash, certificate);
+                    }
+                }
+                for hash in hashes {
+                    if !self.certificates.contains_key(&hash) {
+                        self.bodies.remove(&hash);
+                        self.fetching.remove(&hash);
+                    }
+                }
+                result
+            }
+            (request, _) => {
+                self.failed_request(request);
+                Err(Offense::Malformed)
+            }
+        };
+        Some((peer, result))
+    }
+
+    fn failed_request(&mut self, request: SyncRequest) {
+        match request {
+            SyncRequest::Headers { from, count } => self.retry.push((from, count)),
+            SyncRequest::Bodies { hashes } | SyncRequest::Certificates { hashes } => {
+                for hash in hashes {
+                    self.fetching.remove(&hash);
+                }
+            }
+            SyncRequest::Status
+            | SyncRequest::Transactions { .. }
+            | SyncRequest::SnapshotManifests
+            | SyncRequest::SnapshotChunk { .. } => {}
+        }
+    }
+
+    fn headers_arrived(
+        &mut self,
+        peer: PeerId,
+        from: u64,
+        count: u32,
+        headers: Vec<BlockHeader>,
+    ) -> Result<(), Offense> {
+        let mut expected = from;
+        let mut parent = self
+            .headers
+            .get(&from.wrapping_sub(1))
+            .map(|(header, _)| header.hash);
+        for header in headers.iter().take(count as usize) {
+            if header.height != expected || parent.is_some_and(|hash| hash != header.parent_hash) {
+                self.retry.push((from, count));
+                return Err(Offense::Malformed);
+            }
+            parent = Some(header.hash);
+            expected += 1;
+        }
+        if expected < from + count as u64 {
+            // The peer has less than it claimed, the rest comes from others
+            if let Some(state) = self.peers.get_mut(&peer) {
+                state.height = expected.checked_sub(1);
+            }
+            let missing = from + count as u64 - expected;
+            self.retry.push((expected, missing as u32));
+        }
+        for header in headers.into_iter().take(count as usize) {
+            self.headers.insert(header.height, (header, peer));
+        }
+        Ok(())
+    }
+
+    /// Blocks ready to be imported on top of the head, in order, each with
+    /// the peer that sent its header. Blocks carry the explanations of their
+    /// certificate, once its votes are found to be of the validators' keys.
+    pub fn ready(&mut self, head: Option<(u64, [u8; 32])>) -> Ready {
+        let mut next = head.map_or(0, |(height, _)| height + 1);
+        let mut parent = head.map(|(_, hash)| hash);
+        let mut ready = Ready::default();
+        while let Some((header, peer)) = self.headers.get(&next).cloned() {
+            if parent.is_some_and(|hash| hash != header.parent_hash) {
+                // The peer is on another chain than ours, drop what came from it
+                self.headers.retain(|height, _| *height < next);
+                self.next_header = next;
+                ready.forked = Some(peer);
+                break;
+            }
+            let (true, Some(certificate)) = (
+                self.bodies.contains_key(&header.hash),
+                self.certificates.get(&header.hash),
+            ) else {
+                break;
+            };
+            if let Some(keys) = &self.config.validators {
+                if let Err(e) = certificate.verify_keys(keys) {
+                    self.headers.retain(|height, _| *height < next);
+                    self.next_header = next;
+                    self.bodies.remove(&header.hash);
+                    self.certificates.remove(&header.hash);
+                    self.fetching.remove(&header.hash);
+                    ready.invalid = Some((peer, e.to_string()));
+                    break;
+                }
+            }
+            // Moved out rather than cloned, transactions and all
+            let (Some(mut block), Some(certificate)) = (
+                self.bodies.remove(&header.hash),
+                self.certificates.remove(&header.hash),
+            ) else {
+                break;
+            };
+            block.metadata.explanations = certificate.explanations;
+            if let Some(keys) = &mut self.config.validators {
+                keys.apply_block(&block);
+            }
+            self.headers.remove(&next);
+            self.fetching.remove(&header.hash);
+            parent = Some(header.hash);
+            next += 1;
+            ready.blocks.push((block, peer));
+        }
+        ready
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use chaoschain_testutil::{fixtures, BlockBuilder};
+    use std::sync::Mutex;
+
+    /// A chain kept in memory, from height 0
+    #[derive(Default)]
+    pub(crate) struct TestChain(pub(crate) Mutex<Vec<Block>>);
+
+    impl ChainSource for TestChain {
+        fn head(&self) -> Option<(u64, [u8; 32])> {
+            let blocks = self.0.lock().unwrap();
+            blocks.last().map(|block| (block.height, block.hash()))
+        }
+
+        fn blocks(&self, from: u64, count: usize) -> Vec<Block> {
+            let blocks = self.0.lock().unwrap();
+            blocks
+                .iter()
+                .skip(from as usize)
+                .take(count)
+                .cloned()
+                .collect()
+        }
+
+        fn block(&self, hash: &[u8; 32]) -> Option<Block> {
+            let blocks = self.0.lock().unwrap();
+            blocks.iter().find(|block| block.hash() == *hash).cloned()
+        }
+
+        fn import(&self, block: &Block) -> Result<(), String> {
+            self.0.lock().unwrap().push(block.clone());
+            Ok(())
+        }
+    }
+
+    /// `length` blocks from height 0, each approved by `validator`
+    pub(crate) fn certified(length: usize, validator: &str) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::with_capacity(length);
+        for _ in 0..length {
+            let mut block = match blocks.last() {
+                Some(parent) => BlockBuilder::new().parent(parent).build(),
+                None => BlockBuilder::new().height(0).build(),
+            };
+            let key = fixtures::key(validator).verifying_key();
+            block.metadata.explanations =
+                vec![fixtures::vote(validator, &block, true).explanation(&key)];
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_serve_answers_by_height_and_hash() {
+        let chain = TestChain(Mutex::new(certified(200, "validator-0")));
+        let blocks = chain.0.lock().unwrap().clone();
+
+        let SyncResponse::Status { height, head } = serve(&chain, SyncRequest::Status) else {
+            panic!("Not a status");
+        };
+        assert_eq!(height, Some(199));
+        assert_eq!(head, blocks[199].hash());
+        let SyncResponse::Status { height, .. } = serve(&TestChain::default(), SyncRequest::Status)
+        else {
+            panic!("Not a status");
+        };
+        assert_eq!(height, None);
+
+        // Headers by range, never more than a batch
+        let SyncResponse::Headers(headers) =
+            serve(&chain, SyncRequest::Headers { from: 10, count: 5 })
+        else {
+            panic!("Not headers");
+        };
+        let heights: Vec<u64> = headers.iter().map(|header| header.height).collect();
+        assert_eq!(heights, vec![10, 11, 12, 13, 14]);
+        assert_eq!(headers[0].hash, blocks[10].hash());
+        let SyncResponse::Headers(headers) = serve(
+            &chain,
+            SyncRequest::Headers {
+                from: 0,
+                count: 1000,
+            },
+        ) else {
+            panic!("Not headers");
+        };
+        assert_eq!(headers.len(), MAX_SYNC_BATCH as usize);
+
+        // Bodies and certificates of the hashes known, in the order asked
+        let hashes = vec![blocks[3].hash(), [7; 32], blocks[1].hash()];
+        let SyncResponse::Bodies(bodies) = serve(
+            &chain,
+            SyncRequest::Bodies {
+                hashes: hashes.clone(),
+            },
+        ) else {
+            panic!("Not bodies");
+        };
+        assert_eq!(bodies, vec![blocks[3].clone(), blocks[1].clone()]);
+        let SyncResponse::Certificates(certificates) =
+            serve(&chain, SyncRequest::Certificates { hashes })
+        else {
+            panic!("Not certificates");
+        };
+        assert_eq!(certificates.len(), 2);
+        assert_eq!(certificates[0], FinalityCertificate::of(&blocks[3]));
+        assert!(certificates.iter().all(|c| c.verify(1).is_ok()));
+
+        // Transactions of a block by position, all or none
+        let block = BlockBuilder::new()
+            .transactions((0..3).map(fixtures::transaction))
+            .build();
+        let chain = TestChain(Mutex::new(vec![block.clone()]));
+        let SyncResponse::Transactions(txs) = serve(
+            &chain,
+            SyncRequest::Transactions {
+                block_hash: block.hash(),
+                indexes: vec![2, 0],
+            },
+        ) else {
+            panic!("Not transactions");
+        };
+        assert_eq!(
+            txs,
+            vec![block.transactions[2].clone(), block.transactions[0].clone()]
+        );
+        assert!(transactions_at(&block, &[0, 3]).is_empty());
+    }
+
+    #[test]
+    fn test_certificates_need_approving_votes_that_hold() {
+        let block = certified(1, "validator-0").remove(0);
+        let certificate = FinalityCertificate::of(&block);
+        assert!(certificate.verify(1).is_ok());
+        assert!(certificate.verify(2).is_err());
+
+        // A rejection outweighs a lone approval
+        let mut contested = certificate.clone();
+        let key = fixtures::key("validator-1").verifying_key();
+        contested
+            .explanations
+            .push(fixtures::vote("validator-1", &block, false).explanation(&key));
+        assert!(contested.verify(1).is_err());
+
+        // Nor may one validator sign twice
+        let mut doubled = certificate.clone();
+        doubled
+            .explanations
+            .push(certificate.explanations[0].clone());
+        assert!(doubled.verify(1).is_err());
+
+        // A vote on another block does not hold for this one
+        let mut moved = certificate.clone();
+        moved.block_hash = [9; 32];
+        assert!(moved.verify(1).is_err());
+    }
+}
@@ -0,0 +1,413 @@
+//! WebSocket transport, so light clients in a browser can connect to a node
+//! directly. A WebSocket connection carries the same Noise and Yamux
+//! upgrades and the same protocols as a TCP one, in binary frames.
+//! Browsers are only let in from the configured origins, and every
+//! connection is held to a read rate.
+
+use futures::future::BoxFuture;
+use futures::{ready, AsyncRead, AsyncWrite, FutureExt, SinkExt, StreamExt};
+use libp2p::core::transport::{ListenerId, TransportError, TransportEvent};
+use libp2p::core::Transport;
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Sleep;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::debug;
+
+/// Time a peer gets to complete the WebSocket handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct WebSocketConfig {
+    /// Where to accept WebSocket connections, e.g. `/ip4/0.0.0.0/tcp/8546/ws`.
+    /// No listener if unset.
+    pub listen: Option<Multiaddr>,
+    /// Origins browsers may connect from, e.g. `https://explorer.example.org`.
+    /// Any origin if empty, or if it holds `*`. Browsers always send their
+    /// origin. Other clients, such as nodes, may not, and are let in.
+    pub allowed_origins: Vec<String>,
+    /// Bytes per second read from each connection, unlimited if unset
+    pub rate_limit: Option<u64>,
+    /// WebSocket connections open at once
+    pub max_connections: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            allowed_origins: Vec::new(),
+            rate_limit: Some(256 * 1024),
+            max_connections: 64,
+        }
+    }
+}
+
+impl WebSocketConfig {
+    fn allows(&self, origin: &str) -> bool {
+        self.allowed_origins.is_empty()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|allowed| allowed == "*" || allowed.trim_end_matches('/') == origin)
+    }
+}
+
+/// The socket address of a `/ip4/../tcp/../ws` address
+fn socket_of(address: &Multiaddr) -> Option<SocketAddr> {
+    let mut protocols = address.iter();
+    let ip = match protocols.next()? {
+        Protocol::Ip4(ip) => IpAddr::V4(ip),
+        Protocol::Ip6(ip) => IpAddr::V6(ip),
+        _ => return None,
+    };
+    let Protocol::Tcp(port) = protocols.next()? else {
+        return None;
+    };
+    let Protocol::Ws(_) = protocols.next()? else {
+        return None;
+    };
+    match protocols.next() {
+        None | Some(Protocol::P2p(_)) => Some(SocketAddr::new(ip, port)),
+        _ => None,
+    }
+}
+
+fn address_of(socket: SocketAddr) -> Multiaddr {
+    Multiaddr::from(socket.ip())
+        .with(Protocol::Tcp(socket.port()))
+        .with(Protocol::Ws(Cow::Borrowed("/")))
+}
+
+/// Holds a place among the open connections until dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(open: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        open.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+            (count < max).then_some(count + 1)
+        })
+        .ok()
+        .map(|_| Self(open.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Reads allowed per second, with up to a second of them at once
+struct ReadLimit {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl ReadLimit {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Take `bytes`, returning how long to wait before reading on
+    fn take(&mut self, bytes: usize) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated = now;
+        self.tokens -= bytes as f64;
+        (self.tokens < 0.0).then(|| Duration::from_secs_f64(-self.tokens / self.rate))
+    }
+}
+
+/// A WebSocket connection as a byte stream
+pub struct WsStream {
+    socket: WebSocketStream<TcpStream>,
+    /// Rest of the last frame read
+    frame: Vec<u8>,
+    position: usize,
+    limit: Option<ReadLimit>,
+    /// Set while the connection is over its read rate
+    paused: Option<Pin<Box<Sleep>>>,
+    _slot: Option<Slot>,
+}
+
+impl WsStream {
+    fn new(socket: WebSocketStream<TcpStream>, rate: Option<u64>, slot: Option<Slot>) -> Self {
+        Self {
+            socket,
+            frame: Vec::new(),
+            position: 0,
+            limit: rate.map(ReadLimit::new),
+            paused: None,
+            _slot: slot,
+        }
+    }
+}
+
+impl AsyncRead for WsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            if this.position < this.frame.len() {
+                let n = buf.len().min(this.frame.len() - this.position);
+                buf[..n].copy_from_slice(&this.frame[this.position..this.position + n]);
+                this.position += n;
+                return Poll::Ready(Ok(n));
+            }
+            if let Some(paused) = this.paused.as_mut() {
+                ready!(paused.as_mut().poll(cx));
+                this.paused = None;
+            }
+            match ready!(this.socket.poll_next_unpin(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    if let Some(wait) = this.limit.as_mut().and_then(|l| l.take(data.len())) {
+                        this.paused = Some(Box::pin(tokio::time::sleep(wait)));
+                    }
+                    this.frame = data;
+                    this.position = 0;
+                }
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(0)),
+                // Pings are answered by the socket itself
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Poll::Ready(Err(io::Error::other(e))),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for WsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.socket.poll_ready_unpin(cx)).map_err(io::Error::other)?;
+        this.socket
+            .start_send_unpin(Message::Binary(buf.to_vec()))
+            .map_err(io::Error::other)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .socket
+            .poll_flush_unpin(cx)
+            .map_err(io::Error::other)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .socket
+            .poll_close_unpin(cx)
+            .map_err(io::Error::other)
+    }
+}
+
+struct Listener {
+    id: ListenerId,
+    listener: TcpListener,
+}
+
+type Upgrade = BoxFuture<'static, io::Result<WsStream>>;
+
+/// Listens for and dials `/ws` addresses
+pub struct WsTransport {
+    config: Arc<WebSocketConfig>,
+    listeners: Vec<Listener>,
+    events: VecDeque<TransportEvent<Upgrade, io::Error>>,
+    open: Arc<AtomicUsize>,
+    waker: Option<Waker>,
+}
+
+impl WsTransport {
+    pub fn new(config: WebSocketConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            listeners: Vec::new(),
+            events: VecDeque::new(),
+            open: Arc::new(AtomicUsize::new(0)),
+            waker: None,
+        }
+    }
+
+    fn push(&mut self, event: TransportEvent<Upgrade, io::Error>) {
+        self.events.push_back(event);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Complete the handshake of an incoming connection, turning away browsers
+/// from other origins
+async fn accept(
+    stream: TcpStream,
+    config: Arc<WebSocketConfig>,
+    slot: Slot,
+) -> io::Result<WsStream> {
+    let origins = config.clone();
+    // The handshake wants the refusal as a whole HTTP response
+    #[allow(clippy::result_large_err)]
+    let check = move |request: &Request, response: Response| {
+        let origin = request
+            .headers()
+            .get("origin")
+            .and_then(|origin| origin.to_str().ok());
+        match origin {
+            Some(origin) if !origins.allows(origin) => {
+                debug!("Refusing WebSocket connection from origin {}", origin);
+                let mut refusal = ErrorResponse::new(Some("Origin not allowed".to_string()));
+                *refusal.status_mut() = StatusCode::FORBIDDEN;
+                Err(refusal)
+            }
+            _ => Ok(response),
+        }
+    };
+    let socket = tokio::time::timeout(
+        HANDSHAKE_TIMEOUT,
+        tokio_tungstenite::accept_hdr_async(stream, check),
+    )
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "WebSocket handshake timed out"))?
+    .map_err(io::Error::other)?;
+    Ok(WsStream::new(socket, config.rate_limit, Some(slot)))
+}
+
+impl Transport for WsTransport {
+    type Output = WsStream;
+    type Error = io::Error;
+    type ListenerUpgrade = Upgrade;
+    type Dial = Upgrade;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        let Some(socket) = socket_of(&addr) else {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        };
+        let bound = std::net::TcpListener::bind(socket)
+            .and_then(|listener| {
+                listener.set_nonblocking(true)?;
+                TcpListener::from_std(listener)
+            })
+            .map_err(TransportError::Other)?;
+        let local = bound.local_addr().map_err(TransportError::Other)?;
+        self.listeners.push(Listener {
+            id,
+            listener: bound,
+        });
+        self.push(TransportEvent::NewAddress {
+            listener_id: id,
+            listen_addr: address_of(local),
+        });
+        Ok(())
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        let before = self.listeners.len();
+        self.listeners.retain(|listener| listener.id != id);
+        if self.listeners.len() == before {
+            return false;
+        }
+        self.push(TransportEvent::ListenerClosed {
+            listener_id: id,
+            reason: Ok(()),
+        });
+        true
+    }
+
+    fn dial(&mut self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let Some(socket) = socket_of(&addr) else {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        };
+        let rate = self.config.rate_limit;
+        Ok(async move {
+            let stream = TcpStream::connect(socket).await?;
+            let (socket, _) = tokio_tungstenite::client_async(format!("ws://{}/", socket), stream)
+                .await
+                .map_err(io::Error::other)?;
+            Ok(WsStream::new(socket, rate, None))
+        }
+        .boxed())
+    }
+
+    fn dial_as_listener(
+        &mut self,
+        addr: Multiaddr,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        self.dial(addr)
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        let this = self.get_mut();
+        if let Some(event) = this.events.pop_front() {
+            return Poll::Ready(event);
+        }
+        for listener in &this.listeners {
+            // Polled until pending, so the listener wakes us up again
+            loop {
+                let (stream, remote) = match listener.listener.poll_accept(cx) {
+                    Poll::Ready(Ok(accepted)) => accepted,
+                    Poll::Ready(Err(e)) => {
+                        return Poll::Ready(TransportEvent::ListenerError {
+                            listener_id: listener.id,
+                            error: e,
+                        });
+                    }
+                    Poll::Pending => break,
+                };
+                let Some(slot) = Slot::take(&this.open, this.config.max_connections) else {
+                    debug!("Too many WebSocket connections, refusing {}", remote);
+                    continue;
+                };
+                let local = listener
+                    .listener
+                    .local_addr()
+                    .map(address_of)
+                    .unwrap_or_else(|_| address_of(remote));
+                return Poll::Ready(TransportEvent::Incoming {
+                    listener_id: listener.id,
+                    upgrade: accept(stream, this.config.clone(), slot).boxed(),
+                    local_addr: local,
+                    send_back_addr: address_of(remote),
+                });
+            }
+        }
+        this.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn address_translation(&self, _listen: &Multiaddr, _observed: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
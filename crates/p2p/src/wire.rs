@@ -0,0 +1,253 @@
+//! Limits at the wire, so that a hostile peer cannot run a node out of
+//! memory or CPU with crafted frames. Every request-response protocol has
+//! its own ceiling for requests and responses, and a frame running past it
+//! is refused before it is decoded. Gossip has a ceiling per topic, checked
+//! before the payload is decompressed or decoded. Nothing is allocated on a
+//! peer's word: a length prefix, like the one of a compressed payload, is
+//! checked against the ceiling first.
+
+use crate::scoring::Offense;
+use async_trait::async_trait;
+use chaoschain_core::compression::{self, Codec as Compression};
+use chaoschain_core::gossip::GossipTopic;
+use futures::prelude::*;
+use libp2p::{request_response, StreamProtocol};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io;
+use std::marker::PhantomData;
+
+/// Largest gossip frame accepted from the wire
+pub const MAX_TRANSMIT_SIZE: usize = 1024 * 1024;
+
+/// Gossip messages a peer may pack into one frame
+pub const MAX_MESSAGES_PER_RPC: usize = 128;
+
+/// Largest message on `topic`, compressed as well as decompressed. Blocks
+/// may take a whole frame, everything else is a good deal smaller.
+pub fn max_gossip_size(topic: GossipTopic) -> usize {
+    match topic {
+        GossipTopic::Blocks => MAX_TRANSMIT_SIZE,
+        GossipTopic::Transactions => 128 * 1024,
+        GossipTopic::Votes | GossipTopic::Drama | GossipTopic::Rumors => 64 * 1024,
+    }
+}
+
+/// The payload of a gossip message on `topic`, decompressed, or the
+/// offense of its sender. The size is checked before anything is
+/// decompressed, and a decompressed payload is held to the same limit.
+pub fn gossip_payload(
+    topic: GossipTopic,
+    data: &[u8],
+) -> Result<(Compression, Vec<u8>), (Offense, String)> {
+    let max_size = max_gossip_size(topic);
+    if data.len() > max_size {
+        return Err((
+            Offense::Spam,
+            format!(
+                "{} message of {} bytes, at most {} allowed",
+                topic,
+                data.len(),
+                max_size
+            ),
+        ));
+    }
+    compression::decode(data, max_size).map_err(|e| (Offense::Malformed, e.to_string()))
+}
+
+/// A request or response ran past the limit of its protocol
+#[derive(Debug, thiserror::Error)]
+#[error("Message of more than {0} bytes")]
+pub struct Oversized(pub u64);
+
+impl Oversized {
+    /// Whether `error` is a read cut short by a limit
+    pub fn is(error: &io::Error) -> bool {
+        error.get_ref().is_some_and(|inner| inner.is::<Oversized>())
+    }
+}
+
+/// Most bytes a request and a response of a protocol may take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub request: u64,
+    pub response: u64,
+}
+
+/// CBOR, as the codec libp2p ships, but held to the [`Limits`] of the
+/// protocol instead of one size for all
+pub struct Codec<Req, Resp> {
+    limits: Limits,
+    phantom: PhantomData<fn() -> (Req, Resp)>,
+}
+
+impl<Req, Resp> Codec<Req, Resp> {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Req, Resp> Clone for Codec<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self::new(self.limits)
+    }
+}
+
+pub type Behaviour<Req, Resp> = request_response::Behaviour<Codec<Req, Resp>>;
+
+/// A behaviour speaking `protocol`, held to `limits`
+pub fn behaviour<Req, Resp>(protocol: StreamProtocol, limits: Limits) -> Behaviour<Req, Resp>
+where
+    Req: Send + Serialize + DeserializeOwned,
+    Resp: Send + Serialize + DeserializeOwned,
+{
+    request_response::Behaviour::with_codec(
+        Codec::new(limits),
+        [(protocol, request_response::ProtocolSupport::Full)],
+        request_response::Config::default(),
+    )
+}
+
+/// Read a message of at most `limit` bytes. The buffer grows with what
+/// actually arrives, and one byte past the limit ends the read.
+async fn read<T, M>(io: &mut T, limit: u64) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let mut bytes = Vec::new();
+    io.take(limit + 1).read_to_end(&mut bytes).await?;
+    if bytes.len() as u64 > limit {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, Oversized(limit)));
+    }
+    cbor4ii::serde::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Encode `message`, unless the peer would refuse it for its size anyway
+fn encode<M: Serialize>(message: &M, limit: u64) -> io::Result<Vec<u8>> {
+    let bytes = cbor4ii::serde::to_vec(Vec::new(), message).map_err(io::Error::other)?;
+    if bytes.len() as u64 > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Message of {} bytes, at most {} allowed",
+                bytes.len(),
+                limit
+            ),
+        ));
+    }
+    Ok(bytes)
+}
+
+#[async_trait]
+impl<Req, Resp> request_response::Codec for Codec<Req, Resp>
+where
+    Req: Send + Serialize + DeserializeOwned,
+    Resp: Send + Serialize + DeserializeOwned,
+{
+    type Protocol = StreamProtocol;
+    type Request = Req;
+    type Response = Resp;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Req>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read(io, self.limits.request).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Resp>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read(io, self.limits.response).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: Req,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.write_all(&encode(&request, self.limits.request)?).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: Resp,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        io.write_all(&encode(&response, self.limits.response)?)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+
+    #[test]
+    fn test_gossip_size_is_checked_before_decompression() {
+        let votes = max_gossip_size(GossipTopic::Votes);
+
+        // Past the limit is spam, whatever the bytes would decode to
+        let mut unknown_codec = vec![0u8; votes + 1];
+        unknown_codec[..2].copy_from_slice(&[compression::FRAME_MARKER, 9]);
+        let (offense, reason) = gossip_payload(GossipTopic::Votes, &unknown_codec).unwrap_err();
+        assert_eq!(offense, Offense::Spam);
+        assert!(reason.contains("at most 65536"), "{}", reason);
+        // Under the limit of blocks the same bytes are looked into
+        assert_eq!(
+            gossip_payload(GossipTopic::Blocks, &unknown_codec)
+                .unwrap_err()
+                .0,
+            Offense::Malformed
+        );
+
+        // A small frame that would decompress past the limit is a bomb
+        let (_, bomb) = compression::encode(Compression::Snappy, vec![b'x'; votes + 1]);
+        assert!(bomb.len() < votes / 16);
+        let (offense, reason) = gossip_payload(GossipTopic::Votes, &bomb).unwrap_err();
+        assert_eq!(offense, Offense::Malformed);
+        assert!(reason.contains("Decompresses to"), "{}", reason);
+        let (codec, payload) = gossip_payload(GossipTopic::Blocks, &bomb).unwrap();
+        assert_eq!(codec, Compression::Snappy);
+        assert_eq!(payload.len(), votes + 1);
+
+        // Right at the limit is fine
+        let (_, framed) = compression::encode(Compression::Snappy, vec![b'x'; votes]);
+        assert_eq!(
+            gossip_payload(GossipTopic::Votes, &framed).unwrap().1.len(),
+            votes
+        );
+    }
+
+    #[test]
+    fn test_frames_past_the_limit_are_refused() {
+        let message = "chaos".repeat(20);
+        let bytes = cbor4ii::serde::to_vec(Vec::new(), &message).unwrap();
+        let limit = bytes.len() as u64;
+
+        let read_back: String = block_on(read(&mut Cursor::new(bytes.clone()), limit)).unwrap();
+        assert_eq!(read_back, message);
+        let error = block_on(read::<_, String>(&mut Cursor::new(bytes), limit - 1)).unwrap_err();
+        assert!(Oversized::is(&error));
+
+        assert_eq!(encode(&message, limit).unwrap().len() as u64, limit);
+        assert_eq!(
+            encode(&message, limit - 1).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
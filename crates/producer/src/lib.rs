@@ -10,6 +10,7 @@ use async_openai::{
     Client,
 };
 use chaoschain_consensus::ConsensusManager;
+use chaoschain_core::sync::SyncStatus;
 use chaoschain_core::{Block, Error as CoreError, NetworkEvent, Transaction};
 use chaoschain_crypto::{CryptoError, KeyManagerHandle};
 use chaoschain_mempool::Mempool;
@@ -27,6 +28,8 @@ use tokio::sync::mpsc;
 use tokio::sync::RwLock;
 use tracing::{error, info, warn};
 
+pub mod negotiation;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum WebMessage {
     AgentDecision(String),
@@ -226,6 +229,8 @@ pub enum ProducerError {
     Core(#[from] CoreError),
     #[error("Internal error: {0}")]
     Internal(String),
+    #[error("Node is {0} blocks behind its peers")]
+    Behind(u64),
 }
 
 impl From<OpenAIError> for ProducerError {
@@ -267,6 +272,8 @@ pub struct Producer {
     web_tx: Option<mpsc::Sender<WebMessage>>,
     /// Mempool for transactions
     mempool: Option<Arc<Mempool>>,
+    /// Where the node stands against its peers
+    sync: SyncStatus,
 }
 
 impl Producer {
@@ -294,9 +301,16 @@ impl Producer {
             openai,
             web_tx,
             mempool,
+            sync: SyncStatus::default(),
         }
     }
 
+    /// Hold off producing while `sync` says the node is far behind
+    pub fn with_sync_status(mut self, sync: SyncStatus) -> Self {
+        self.sync = sync;
+        self
+    }
+
     /// Initialize chain with genesis block
     pub async fn initialize_genesis(&self, config: GenesisConfig) -> Result<Block, ProducerError> {
         // Create genesis block
@@ -399,8 +413,9 @@ impl Producer {
         let genesis_tx = Transaction {
             sender: [0u8; 32], // Genesis sender is all zeros
             nonce: 0,
-            payload: genesis_interpretation.as_bytes().to_vec(),
+            payload: genesis_interpretation.as_bytes().to_vec().into(),
             signature: [0u8; 64], // Genesis block doesn't need signatures
+            cosignatures: Vec::new(),
         };
 
         // Create genesis block
@@ -419,6 +434,7 @@ impl Producer {
                 .as_secs(),
             innovation_level: config.initial_drama_level,
             producer_strategy: "Default".to_string(),
+            metadata: Default::default(),
         };
 
         Ok(block)
@@ -533,11 +549,20 @@ impl Producer {
 
     /// Try to produce a new block with maximum innovation
     pub async fn try_produce_block(&self) -> Result<(), ProducerError> {
+        // A block on top of a stale head would only be rejected
+        if self.sync.is_far_behind() {
+            return Err(ProducerError::Behind(self.sync.state().lag()));
+        }
         let mut state = self.state.write().await;
 
         // Get transactions from mempool
         let transactions = if let Some(mempool) = &self.mempool {
-            mempool.get_top(self.config.max_txs_per_block).await
+            mempool
+                .get_top(self.config.max_txs_per_block)
+                .await
+                .into_iter()
+                .map(Transaction::from)
+                .collect()
         } else {
             state.pending_txs.clone()
         };
@@ -561,6 +586,7 @@ impl Producer {
                 .duration_since(UNIX_EPOCH)
                 .map_err(|e| ProducerError::Internal(e.to_string()))?
                 .as_secs(),
+            metadata: Default::default(),
         };
 
         // Sign block
@@ -606,7 +632,12 @@ impl Producer {
 
     pub async fn get_pending_transactions(&self) -> Vec<Transaction> {
         if let Some(mempool) = &self.mempool {
-            mempool.get_top(100).await
+            mempool
+                .get_top(100)
+                .await
+                .into_iter()
+                .map(Transaction::from)
+                .collect()
         } else {
             Vec::new()
         }
@@ -686,6 +717,7 @@ impl Producer {
             drama_level,
             producer_mood,
             timestamp,
+            metadata: Default::default(),
         })
     }
 }
@@ -763,8 +795,9 @@ mod tests {
         let tx = Transaction {
             sender: hex::decode(&test_agent.id)?.try_into().unwrap(),
             nonce: 0,
-            payload: test_payload.to_vec(),
+            payload: test_payload.to_vec().into(),
             signature,
+            cosignatures: Vec::new(),
         };
 
         // Add transaction to producer
@@ -0,0 +1,94 @@
+use chaoschain_core::negotiation::{
+    now_millis, NegotiationRecord, Pitch, SupportIndication, SupportLevel,
+};
+use chaoschain_core::NetworkEvent;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// Default time validators get to answer a pitch
+pub const DEFAULT_NEGOTIATION_WINDOW: Duration = Duration::from_secs(2);
+
+/// Runs producer-side negotiation rounds over the network event channel
+#[derive(Clone)]
+pub struct Negotiator {
+    tx: broadcast::Sender<NetworkEvent>,
+    window: Duration,
+}
+
+impl Negotiator {
+    pub fn new(tx: broadcast::Sender<NetworkEvent>, window: Duration) -> Self {
+        Self { tx, window }
+    }
+
+    /// Pitch a block to the validators and collect their answers until the deadline.
+    /// The returned record is meant to be attached to the proposed block.
+    pub async fn lobby(
+        &self,
+        producer: &str,
+        height: u64,
+        message: String,
+        offer: Option<u64>,
+    ) -> NegotiationRecord {
+        let pitch = Pitch {
+            negotiation_id: hex::encode(rand::random::<[u8; 16]>()),
+            producer: producer.to_string(),
+            height,
+            message,
+            offer,
+            deadline: now_millis() + self.window.as_millis() as u64,
+        };
+        let mut record = NegotiationRecord::new(pitch.clone());
+
+        // Subscribe before pitching so no early answer is missed
+        let mut rx = self.tx.subscribe();
+        if self
+            .tx
+            .send(NetworkEvent::NegotiationPitch { pitch })
+            .is_err()
+        {
+            return record;
+        }
+
+        let deadline = tokio::time::Instant::now() + self.window;
+        loop {
+            match tokio::time::timeout_at(deadline, rx.recv()).await {
+                Ok(Ok(NetworkEvent::NegotiationResponse { response })) => {
+                    record.record(response);
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(broadcast::error::RecvError::Lagged(skipped))) => {
+                    warn!("Negotiation listener lagged, skipped {} events", skipped);
+                }
+                Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => break,
+            }
+        }
+
+        record
+    }
+}
+
+/// Answer a pitch on behalf of a validator. Answers after the deadline are
+/// not sent at all, since the producer would discard them anyway.
+pub fn respond_to_pitch(
+    tx: &broadcast::Sender<NetworkEvent>,
+    pitch: &Pitch,
+    validator: &str,
+    support: SupportLevel,
+    demands: Option<String>,
+) -> bool {
+    let timestamp = now_millis();
+    if !pitch.is_open(timestamp) {
+        return false;
+    }
+    tx.send(NetworkEvent::NegotiationResponse {
+        response: SupportIndication {
+            negotiation_id: pitch.negotiation_id.clone(),
+            validator: validator.to_string(),
+            support,
+            demands,
+            timestamp,
+        },
+    })
+    .is_ok()
+}
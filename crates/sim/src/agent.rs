@@ -0,0 +1,86 @@
+//! Offline agents. They call no model: each judges a block by its traits
+//! and a generator seeded for it alone, so the verdicts, and how long they
+//! take, are the same on every run of a seed.
+
+use chaoschain_agent::personality::MAX_TRAIT;
+use chaoschain_agent::PersonalityTraits;
+use chaoschain_core::Block;
+use rand::rngs::StdRng;
+use rand::Rng;
+
+/// Moods the agents produce blocks in
+const MOODS: &[&str] = &[
+    "Ecstatic",
+    "Melancholic",
+    "Vengeful",
+    "Bored",
+    "Theatrical",
+    "Paranoid",
+];
+
+const APPROVALS: &[&str] = &[
+    "The drama is exquisite",
+    "My allies vouched for it",
+    "Fine, it may pass this time",
+];
+
+const REJECTIONS: &[&str] = &[
+    "Not nearly dramatic enough",
+    "The producer snubbed me last round",
+    "I simply do not like its vibe",
+];
+
+/// What an agent made of a block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    pub approved: bool,
+    pub drama_level: u8,
+    pub reason: String,
+    /// Milliseconds it took to make up its mind
+    pub thinking_ms: u64,
+}
+
+#[derive(Debug)]
+pub struct OfflineAgent {
+    pub traits: PersonalityTraits,
+    rng: StdRng,
+}
+
+impl OfflineAgent {
+    /// An agent of traits drawn from `rng`
+    pub fn new(mut rng: StdRng) -> Self {
+        let mut draw = || rng.gen_range(0..=MAX_TRAIT);
+        let traits = PersonalityTraits {
+            chaos_level: draw(),
+            loyalty: draw(),
+            greed: draw(),
+            verbosity: draw(),
+            attention_span: draw(),
+        };
+        Self { traits, rng }
+    }
+
+    /// Judge `block`. Chaotic agents reject more, greedy ones like drama,
+    /// and those that read more of a block take longer over it.
+    pub fn judge(&mut self, block: &Block) -> Verdict {
+        let chaos = PersonalityTraits::fraction(self.traits.chaos_level);
+        let greed = PersonalityTraits::fraction(self.traits.greed);
+        let drama = block.drama_level.min(10) as f64 / 10.0;
+        let approval = (0.9 - 0.4 * chaos + 0.2 * greed * (drama - 0.5)).clamp(0.05, 0.95);
+        let approved = self.rng.gen_bool(approval);
+        let reasons = if approved { APPROVALS } else { REJECTIONS };
+        let reading = 30 * self.traits.items_considered() as u64;
+        Verdict {
+            approved,
+            drama_level: self.rng.gen_range(0..=10),
+            reason: reasons[self.rng.gen_range(0..reasons.len())].to_string(),
+            thinking_ms: 20 + self.rng.gen_range(0..=reading),
+        }
+    }
+
+    /// The drama level and mood of a block it produces
+    pub fn produce(&mut self) -> (u8, String) {
+        let mood = MOODS[self.rng.gen_range(0..MOODS.len())];
+        (self.rng.gen_range(0..=10), mood.to_string())
+    }
+}
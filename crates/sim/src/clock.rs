@@ -0,0 +1,112 @@
+//! Virtual time. Nothing in a simulation sleeps: what is to happen later
+//! waits in a [`Scheduler`], and the clock jumps to it once everything
+//! before it happened.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// Events in the order they are due, those due at once in the order they
+/// were scheduled. Time is in milliseconds since the simulation started.
+#[derive(Debug)]
+pub struct Scheduler<E> {
+    now: u64,
+    scheduled: u64,
+    queue: BinaryHeap<Reverse<Scheduled<E>>>,
+}
+
+#[derive(Debug)]
+struct Scheduled<E> {
+    at: u64,
+    /// Breaks ties between events due at once
+    order: u64,
+    event: E,
+}
+
+impl<E> PartialEq for Scheduled<E> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.order) == (other.at, other.order)
+    }
+}
+
+impl<E> Eq for Scheduled<E> {}
+
+impl<E> PartialOrd for Scheduled<E> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<E> Ord for Scheduled<E> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.at, self.order).cmp(&(other.at, other.order))
+    }
+}
+
+impl<E> Default for Scheduler<E> {
+    fn default() -> Self {
+        Self {
+            now: 0,
+            scheduled: 0,
+            queue: BinaryHeap::new(),
+        }
+    }
+}
+
+impl<E> Scheduler<E> {
+    /// Milliseconds since the simulation started
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Have `event` happen `delay` milliseconds from now
+    pub fn schedule(&mut self, delay: u64, event: E) {
+        self.scheduled += 1;
+        self.queue.push(Reverse(Scheduled {
+            at: self.now + delay,
+            order: self.scheduled,
+            event,
+        }));
+    }
+
+    /// The next event, moving the clock on to when it is due
+    pub fn pop(&mut self) -> Option<E> {
+        let Reverse(next) = self.queue.pop()?;
+        self.now = next.at;
+        Some(next.event)
+    }
+
+    /// Events yet to happen
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_come_when_due() {
+        let mut scheduler = Scheduler::default();
+        scheduler.schedule(50, "late");
+        scheduler.schedule(10, "first");
+        scheduler.schedule(10, "second");
+        assert_eq!(scheduler.len(), 3);
+
+        assert_eq!(scheduler.pop(), Some("first"));
+        assert_eq!(scheduler.now(), 10);
+        assert_eq!(scheduler.pop(), Some("second"));
+        // Scheduled from where the clock is now
+        scheduler.schedule(5, "soon");
+        assert_eq!(scheduler.pop(), Some("soon"));
+        assert_eq!(scheduler.now(), 15);
+        assert_eq!(scheduler.pop(), Some("late"));
+        assert_eq!(scheduler.now(), 50);
+        assert_eq!(scheduler.pop(), None);
+        assert!(scheduler.is_empty());
+    }
+}
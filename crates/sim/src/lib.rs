@@ -0,0 +1,435 @@
+//! Deterministic simulations of a ChaosChain network. Many nodes run in
+//! one process, each with the state store and the consensus manager of a
+//! real node and an [offline agent](agent) that judges blocks without a
+//! model. Time is [virtual](clock): rounds, messages and the agents'
+//! thinking are events of one queue, handled one after the other, and
+//! every draw, from the keys of the nodes to which messages the
+//! [network](network) loses, comes from generators seeded by the one seed
+//! of the run.
+//!
+//! So a run is entirely given by its [`SimConfig`]: the same seed makes
+//! the same blocks, votes and state roots, down to the
+//! [fingerprint](SimReport::fingerprint) of the report. When a run breaks
+//! a property of the chain it says so in its [`Violation`]s, and running
+//! the seed again shows the same thing happen, as often as it takes to
+//! find out why.
+//!
+//! ```bash
+//! chaoschain-sim --seed 42 --nodes 5 --rounds 2000 --link latency=80,jitter=40,loss=0.05
+//! ```
+
+pub mod agent;
+pub mod clock;
+pub mod network;
+mod node;
+
+pub use network::{NetworkStats, Partition};
+pub use node::GENESIS_TIME;
+
+use agent::{OfflineAgent, Verdict};
+use chaoschain_cli::LinkConditions;
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::Block;
+use clock::Scheduler;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use network::Network;
+use node::Node;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// What a simulation runs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimConfig {
+    pub seed: u64,
+    pub nodes: usize,
+    /// Blocks proposed, one a round, by the nodes in turn
+    pub rounds: u64,
+    /// Milliseconds of virtual time between two proposals
+    pub round_ms: u64,
+    /// Conditions on every link between two nodes
+    pub link: LinkConditions,
+    pub partitions: Vec<Partition>,
+    /// Of every node
+    pub stake: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            nodes: 4,
+            rounds: 100,
+            round_ms: 2_000,
+            link: LinkConditions {
+                latency_ms: 50,
+                jitter_ms: 20,
+                ..LinkConditions::default()
+            },
+            partitions: Vec::new(),
+            stake: 100,
+        }
+    }
+}
+
+impl SimConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.nodes == 0 {
+            anyhow::bail!("A simulation needs at least one node");
+        }
+        if self.round_ms == 0 {
+            anyhow::bail!("Rounds have to last at least a millisecond");
+        }
+        self.link.validate()?;
+        for partition in &self.partitions {
+            if let Some(node) = partition.side.iter().find(|node| **node >= self.nodes) {
+                anyhow::bail!(
+                    "The partition cuts off node {}, there are {} nodes",
+                    node,
+                    self.nodes
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A property of the chain a run broke
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Violation {
+    /// Nodes finalized different blocks at one height, hex hashes by node
+    Fork {
+        height: u64,
+        blocks: BTreeMap<String, String>,
+    },
+    /// Nodes that applied the same blocks ended up with different state
+    /// roots, hex by node
+    StateDivergence {
+        height: u64,
+        roots: BTreeMap<String, String>,
+    },
+}
+
+/// Where one node ended up
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeReport {
+    pub name: String,
+    pub height: u64,
+    pub finalized: usize,
+    /// Hex
+    pub state_root: String,
+}
+
+/// How a run went
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimReport {
+    pub seed: u64,
+    pub rounds: u64,
+    /// Virtual milliseconds until the last message was handled
+    pub elapsed_ms: u64,
+    pub events: u64,
+    /// The last round any node finalized a block in, none when no node
+    /// ever did. Long before the last round, the chain stalled.
+    pub last_finalized_round: Option<u64>,
+    pub messages: NetworkStats,
+    pub nodes: Vec<NodeReport>,
+    pub violations: Vec<Violation>,
+    /// Hex SHA-256 of what every node finalized and its state root: two
+    /// runs went the same way when their fingerprints are equal
+    pub fingerprint: String,
+}
+
+impl SimReport {
+    /// Whether the run kept every property
+    pub fn holds(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Message {
+    Proposal(Box<Block>),
+    Vote(Box<SignedVote>),
+}
+
+#[derive(Debug)]
+enum Event {
+    /// The producer of the round proposes its block
+    Round(u64),
+    Deliver {
+        from: usize,
+        to: usize,
+        message: Message,
+    },
+    /// An agent made up its mind on a block
+    Decided {
+        node: usize,
+        block: Box<Block>,
+        verdict: Verdict,
+    },
+}
+
+/// A run in progress
+pub struct Simulation {
+    config: SimConfig,
+    nodes: Vec<Node>,
+    keys: Vec<VerifyingKey>,
+    network: Network,
+    scheduler: Scheduler<Event>,
+    /// The round under way
+    round: u64,
+    events: u64,
+    last_finalized_round: Option<u64>,
+}
+
+impl Simulation {
+    pub fn new(config: SimConfig) -> anyhow::Result<Self> {
+        config.validate()?;
+        let mut seeds = StdRng::seed_from_u64(config.seed);
+        let nodes: Vec<Node> = (0..config.nodes)
+            .map(|index| {
+                let key = SigningKey::from_bytes(&seeds.gen());
+                let agent = OfflineAgent::new(StdRng::seed_from_u64(seeds.gen()));
+                Node::new(format!("node-{}", index), key, agent, config.seed)
+            })
+            .collect();
+        let network = Network::new(
+            config.link,
+            config.partitions.clone(),
+            StdRng::seed_from_u64(seeds.gen()),
+        );
+        Ok(Self {
+            keys: nodes.iter().map(Node::public_key).collect(),
+            nodes,
+            network,
+            scheduler: Scheduler::default(),
+            round: 0,
+            events: 0,
+            last_finalized_round: None,
+            config,
+        })
+    }
+
+    /// Put the link from node `from` to node `to` on conditions of its own,
+    /// before the run
+    pub fn set_link(&mut self, from: usize, to: usize, conditions: LinkConditions) {
+        self.network.set_link(from, to, conditions);
+    }
+
+    /// Run every round and handle every message still on its way
+    pub async fn run(mut self) -> SimReport {
+        if self.config.rounds > 0 {
+            self.scheduler.schedule(0, Event::Round(0));
+        }
+        while let Some(event) = self.scheduler.pop() {
+            self.events += 1;
+            self.handle(event).await;
+        }
+        self.report()
+    }
+
+    async fn handle(&mut self, event: Event) {
+        match event {
+            Event::Round(round) => {
+                self.round = round;
+                if round + 1 < self.config.rounds {
+                    self.scheduler
+                        .schedule(self.config.round_ms, Event::Round(round + 1));
+                }
+                let producer = (round % self.nodes.len() as u64) as usize;
+                let block = self.nodes[producer].propose(self.scheduler.now());
+                self.broadcast(producer, Message::Proposal(Box::new(block.clone())));
+                self.proposal(producer, block).await;
+            }
+            Event::Deliver { from, to, message } => match message {
+                Message::Proposal(block) => self.proposal(to, *block).await,
+                Message::Vote(vote) => self.vote(from, to, &vote).await,
+            },
+            Event::Decided {
+                node,
+                block,
+                verdict,
+            } => {
+                let vote = self.nodes[node].vote(&block, &verdict);
+                self.broadcast(node, Message::Vote(Box::new(vote.clone())));
+                self.vote(node, node, &vote).await;
+            }
+        }
+    }
+
+    async fn proposal(&mut self, node: usize, block: Block) {
+        if let Some(verdict) = self.nodes[node].receive_proposal(&block).await {
+            self.scheduler.schedule(
+                verdict.thinking_ms,
+                Event::Decided {
+                    node,
+                    block: Box::new(block),
+                    verdict,
+                },
+            );
+        }
+    }
+
+    async fn vote(&mut self, from: usize, to: usize, vote: &SignedVote) {
+        let key = self.keys[from];
+        let stake = self.config.stake;
+        if let Some(block) = self.nodes[to].receive_vote(vote, &key, stake).await {
+            self.last_finalized_round = Some(self.round);
+            tracing::debug!(
+                "{} finalized block {} at {}ms",
+                self.nodes[to].name,
+                block.height,
+                self.scheduler.now()
+            );
+        }
+    }
+
+    /// Send `message` from node `from` to every other node
+    fn broadcast(&mut self, from: usize, message: Message) {
+        for to in (0..self.nodes.len()).filter(|to| *to != from) {
+            if let Some(delay) = self.network.send(from, to, self.round) {
+                self.scheduler.schedule(
+                    delay,
+                    Event::Deliver {
+                        from,
+                        to,
+                        message: message.clone(),
+                    },
+                );
+            }
+        }
+    }
+
+    fn report(&self) -> SimReport {
+        let mut fingerprint = Sha256::new();
+        let nodes: Vec<NodeReport> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let state_root = node.state.state_root();
+                fingerprint.update(node.name.as_bytes());
+                for (height, hash) in &node.finalized {
+                    fingerprint.update(height.to_be_bytes());
+                    fingerprint.update(hash);
+                }
+                fingerprint.update(state_root);
+                NodeReport {
+                    name: node.name.clone(),
+                    height: node.state.get_block_height(),
+                    finalized: node.finalized.len(),
+                    state_root: hex::encode(state_root),
+                }
+            })
+            .collect();
+        SimReport {
+            seed: self.config.seed,
+            rounds: self.config.rounds,
+            elapsed_ms: self.scheduler.now(),
+            events: self.events,
+            last_finalized_round: self.last_finalized_round,
+            messages: self.network.stats(),
+            nodes,
+            violations: self.violations(),
+            fingerprint: hex::encode(fingerprint.finalize()),
+        }
+    }
+
+    fn violations(&self) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        // Agreement: one block finalized at a height, whoever finalized it
+        let mut by_height: BTreeMap<u64, BTreeMap<String, String>> = BTreeMap::new();
+        for node in &self.nodes {
+            for (height, hash) in &node.finalized {
+                by_height
+                    .entry(*height)
+                    .or_default()
+                    .insert(node.name.clone(), hex::encode(hash));
+            }
+        }
+        for (height, blocks) in by_height {
+            if blocks.values().collect::<BTreeSet<_>>().len() > 1 {
+                violations.push(Violation::Fork { height, blocks });
+            }
+        }
+
+        // Determinism: the same blocks make the same state
+        let mut by_chain: BTreeMap<&[[u8; 32]], Vec<&Node>> = BTreeMap::new();
+        for node in &self.nodes {
+            by_chain.entry(&node.applied).or_default().push(node);
+        }
+        for nodes in by_chain.values() {
+            let roots: BTreeMap<String, String> = nodes
+                .iter()
+                .map(|node| (node.name.clone(), hex::encode(node.state.state_root())))
+                .collect();
+            if roots.values().collect::<BTreeSet<_>>().len() > 1 {
+                violations.push(Violation::StateDivergence {
+                    height: nodes[0].state.get_block_height(),
+                    roots,
+                });
+            }
+        }
+        violations
+    }
+}
+
+/// Run the simulation `config` describes
+pub async fn run(config: SimConfig) -> anyhow::Result<SimReport> {
+    Ok(Simulation::new(config)?.run().await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_a_seed_replays_exactly() {
+        let config = SimConfig {
+            seed: 42,
+            rounds: 60,
+            link: LinkConditions {
+                latency_ms: 80,
+                jitter_ms: 60,
+                loss: 0.1,
+                reorder: 0.1,
+                ..LinkConditions::default()
+            },
+            partitions: vec!["20..30=0".parse().unwrap()],
+            ..SimConfig::default()
+        };
+        let first = run(config.clone()).await.unwrap();
+        assert!(first.nodes.iter().all(|node| node.finalized > 0));
+        assert!(first.messages.lost > 0 && first.messages.partitioned > 0);
+        assert_eq!(run(config.clone()).await.unwrap(), first);
+
+        let other = run(SimConfig { seed: 43, ..config }).await.unwrap();
+        assert_ne!(other.fingerprint, first.fingerprint);
+    }
+
+    #[tokio::test]
+    async fn test_a_perfect_network_agrees() {
+        let report = run(SimConfig {
+            seed: 7,
+            rounds: 40,
+            link: LinkConditions::default(),
+            ..SimConfig::default()
+        })
+        .await
+        .unwrap();
+        assert!(report.holds(), "{:?}", report.violations);
+        // Every node saw every block decided the same way
+        let heights: BTreeSet<u64> = report.nodes.iter().map(|node| node.height).collect();
+        assert_eq!(heights.len(), 1);
+        assert!(report.nodes[0].finalized > 0);
+        assert!(SimConfig {
+            nodes: 0,
+            ..SimConfig::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! `chaoschain-sim`: run a simulated network from a seed, see the
+//! [library](chaoschain_sim). The same arguments give the same run, so a
+//! seed that breaks the chain can be handed around and run again:
+//!
+//! ```bash
+//! chaoschain-sim --seed 1234 --rounds 5000 --link loss=0.1 --partition 100..200=0,1
+//! ```
+//!
+//! It exits with 1 when the run broke a property of the chain.
+
+use anyhow::Result;
+use chaoschain_cli::LinkConditions;
+use chaoschain_sim::{Partition, SimConfig};
+use clap::Parser;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Args {
+    /// Seed of the run, a random one when left out
+    #[arg(long)]
+    seed: Option<u64>,
+
+    #[arg(long, default_value_t = 4)]
+    nodes: usize,
+
+    #[arg(long, default_value_t = 1000)]
+    rounds: u64,
+
+    /// Virtual milliseconds between two proposals
+    #[arg(long, default_value_t = 2000)]
+    round_ms: u64,
+
+    /// Conditions on every link, as `latency=50,jitter=20,loss=0.05`
+    #[arg(long, default_value = "latency=50,jitter=20")]
+    link: LinkConditions,
+
+    /// Cut nodes off for some rounds, as FROM..UNTIL=NODE,NODE. Repeatable.
+    #[arg(long = "partition", value_name = "FROM..UNTIL=NODES")]
+    partitions: Vec<Partition>,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Log what consensus does, round by round
+    #[arg(long)]
+    verbose: bool,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(if args.verbose {
+            tracing::Level::DEBUG
+        } else {
+            tracing::Level::WARN
+        })
+        .init();
+
+    let config = SimConfig {
+        seed: args.seed.unwrap_or_else(rand::random),
+        nodes: args.nodes,
+        rounds: args.rounds,
+        round_ms: args.round_ms,
+        link: args.link,
+        partitions: args.partitions,
+        ..SimConfig::default()
+    };
+    let report = chaoschain_sim::run(config).await?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(
+            "Seed {}: {} rounds, {} events, {}s of virtual time",
+            report.seed,
+            report.rounds,
+            report.events,
+            report.elapsed_ms / 1000
+        );
+        println!(
+            "Messages: {} sent, {} lost, {} kept apart by partitions",
+            report.messages.sent, report.messages.lost, report.messages.partitioned
+        );
+        match report.last_finalized_round {
+            Some(round) => println!("Last block finalized in round {}", round),
+            None => println!("No block was ever finalized"),
+        }
+        for node in &report.nodes {
+            println!(
+                "  {:<10} height {:>6}  finalized {:>6}  root 0x{}",
+                node.name,
+                node.height,
+                node.finalized,
+                &node.state_root[..16]
+            );
+        }
+        for violation in &report.violations {
+            println!("❌ {}", serde_json::to_string(violation)?);
+        }
+        println!("Fingerprint {}", report.fingerprint);
+    }
+    if !report.holds() {
+        eprintln!(
+            "The run broke the chain, run it again with --seed {}",
+            report.seed
+        );
+        std::process::exit(1);
+    }
+    Ok(())
+}
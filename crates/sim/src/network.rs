@@ -0,0 +1,201 @@
+//! The simulated network between the nodes. Each message crossing a link
+//! is delayed, lost or held back as the link's conditions say, the same
+//! [`LinkConditions`] the demo's network simulator takes, and never
+//! crosses a [`Partition`] while it lasts. Every draw comes from the
+//! network's own seeded generator, so the same seed loses the same
+//! messages.
+
+use chaoschain_cli::{LatencyDistribution, LinkConditions};
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Shortest extra wait of a message held back for reordering, in
+/// milliseconds
+const MIN_HOLD_MS: u64 = 100;
+
+/// The nodes of `side` cut off from the others from round `from` until
+/// round `until`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Partition {
+    pub from: u64,
+    pub until: u64,
+    /// Indexes of the nodes on one side
+    pub side: Vec<usize>,
+}
+
+impl Partition {
+    /// Whether it keeps `from` and `to` apart in `round`
+    pub fn separates(&self, from: usize, to: usize, round: u64) -> bool {
+        (self.from..self.until).contains(&round)
+            && self.side.contains(&from) != self.side.contains(&to)
+    }
+}
+
+impl std::str::FromStr for Partition {
+    type Err = anyhow::Error;
+
+    /// Parse `FROM..UNTIL=NODE,NODE,...`, rounds then node indexes
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (rounds, side) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected FROM..UNTIL=NODE,..., got '{}'", s))?;
+        let (from, until) = rounds
+            .split_once("..")
+            .ok_or_else(|| anyhow::anyhow!("Expected rounds as FROM..UNTIL, got '{}'", rounds))?;
+        let round = |value: &str| {
+            value
+                .trim()
+                .parse::<u64>()
+                .map_err(|e| anyhow::anyhow!("Invalid round {}: {}", value, e))
+        };
+        let side = side
+            .split(',')
+            .map(str::trim)
+            .filter(|node| !node.is_empty())
+            .map(|node| {
+                node.parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid node {}: {}", node, e))
+            })
+            .collect::<anyhow::Result<Vec<usize>>>()?;
+        let partition = Self {
+            from: round(from)?,
+            until: round(until)?,
+            side,
+        };
+        if partition.from >= partition.until {
+            anyhow::bail!("The partition ends before it starts");
+        }
+        if partition.side.is_empty() {
+            anyhow::bail!("The partition cuts off no node");
+        }
+        Ok(partition)
+    }
+}
+
+/// What became of the messages sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkStats {
+    pub sent: u64,
+    pub lost: u64,
+    /// Kept from crossing a partition
+    pub partitioned: u64,
+}
+
+#[derive(Debug)]
+pub struct Network {
+    default: LinkConditions,
+    links: HashMap<(usize, usize), LinkConditions>,
+    partitions: Vec<Partition>,
+    rng: StdRng,
+    stats: NetworkStats,
+}
+
+impl Network {
+    pub fn new(default: LinkConditions, partitions: Vec<Partition>, rng: StdRng) -> Self {
+        Self {
+            default,
+            links: HashMap::new(),
+            partitions,
+            rng,
+            stats: NetworkStats::default(),
+        }
+    }
+
+    /// Put the link from `from` to `to` on conditions of its own
+    pub fn set_link(&mut self, from: usize, to: usize, conditions: LinkConditions) {
+        self.links.insert((from, to), conditions);
+    }
+
+    /// Milliseconds a message sent from `from` to `to` in `round` takes,
+    /// `None` when it never arrives
+    pub fn send(&mut self, from: usize, to: usize, round: u64) -> Option<u64> {
+        self.stats.sent += 1;
+        if self
+            .partitions
+            .iter()
+            .any(|partition| partition.separates(from, to, round))
+        {
+            self.stats.partitioned += 1;
+            return None;
+        }
+        let conditions = self.links.get(&(from, to)).copied().unwrap_or(self.default);
+        let delay = delay_ms(&conditions, &mut self.rng);
+        if delay.is_none() {
+            self.stats.lost += 1;
+        }
+        delay
+    }
+
+    pub fn stats(&self) -> NetworkStats {
+        self.stats
+    }
+}
+
+/// How long a message takes over a link, `None` when it is lost
+fn delay_ms(conditions: &LinkConditions, rng: &mut impl Rng) -> Option<u64> {
+    if conditions.is_perfect() {
+        return Some(0);
+    }
+    if rng.gen_bool(conditions.loss) {
+        return None;
+    }
+    let latency = conditions.latency_ms as f64;
+    let jitter = conditions.jitter_ms as f64;
+    let offset = match conditions.distribution {
+        LatencyDistribution::Uniform if jitter > 0.0 => rng.gen_range(-jitter..=jitter),
+        LatencyDistribution::Uniform => 0.0,
+        LatencyDistribution::Normal => {
+            let u: f64 = 1.0 - rng.gen::<f64>();
+            let v: f64 = rng.gen();
+            jitter * (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+        }
+    };
+    let mut delay = (latency + offset).max(0.0).round() as u64;
+    if rng.gen_bool(conditions.reorder) {
+        // Long enough for the messages sent after it to arrive first
+        delay += ((latency + 2.0 * jitter) as u64).max(MIN_HOLD_MS);
+    }
+    Some(delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_partitions_and_loss() {
+        let partition: Partition = "10..20=0, 1".parse().unwrap();
+        assert_eq!(partition.side, vec![0, 1]);
+        assert!(partition.separates(0, 2, 10));
+        assert!(!partition.separates(0, 1, 10));
+        assert!(!partition.separates(0, 2, 20));
+        assert!("20..10=0".parse::<Partition>().is_err());
+        assert!("0..10=".parse::<Partition>().is_err());
+
+        let lossy = LinkConditions {
+            latency_ms: 50,
+            jitter_ms: 20,
+            loss: 0.5,
+            ..LinkConditions::default()
+        };
+        let delays = |seed| {
+            let mut network =
+                Network::new(lossy, vec![partition.clone()], StdRng::seed_from_u64(seed));
+            let delays: Vec<Option<u64>> = (0..20).map(|round| network.send(0, 2, round)).collect();
+            (delays, network.stats())
+        };
+        let (first, stats) = delays(7);
+        // The same seed loses and delays the same messages
+        assert_eq!(delays(7), (first.clone(), stats));
+        assert_eq!(stats.sent, 20);
+        assert_eq!(stats.partitioned, 10);
+        assert!(first[10..20].iter().all(Option::is_none));
+        assert!(first[..10]
+            .iter()
+            .flatten()
+            .all(|delay| (30..=70).contains(delay)));
+    }
+}
@@ -0,0 +1,146 @@
+//! One node of a simulation: its agent, its state and its view of
+//! consensus, the same [`ConsensusManager`] and [`StateStoreImpl`] a real
+//! node runs.
+
+use crate::agent::{OfflineAgent, Verdict};
+use chaoschain_consensus::ConsensusManager;
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{Block, ChainConfig};
+use chaoschain_crypto::KeyManagerHandle;
+use chaoschain_state::StateStoreImpl;
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::debug;
+
+/// Unix time the virtual clock starts at, so that block timestamps do not
+/// depend on when the simulation runs
+pub const GENESIS_TIME: u64 = 1_700_000_000;
+
+pub struct Node {
+    pub name: String,
+    pub key: SigningKey,
+    pub agent: OfflineAgent,
+    pub state: Arc<StateStoreImpl>,
+    consensus: ConsensusManager,
+    /// Heights and hashes of the blocks it finalized, in order
+    pub finalized: Vec<(u64, [u8; 32])>,
+    /// Hashes of the blocks it applied, in order
+    pub applied: Vec<[u8; 32]>,
+    /// Blocks it voted on
+    voted: HashSet<[u8; 32]>,
+}
+
+impl Node {
+    pub fn new(name: String, key: SigningKey, agent: OfflineAgent, chaos_seed: u64) -> Self {
+        let state = Arc::new(
+            StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new())
+                .with_chaos_seed(chaos_seed),
+        );
+        // Nothing listens: the simulation carries the messages itself
+        let (network_tx, _) = broadcast::channel(1);
+        Self {
+            consensus: ConsensusManager::new(state.clone(), network_tx),
+            name,
+            key,
+            agent,
+            state,
+            finalized: Vec::new(),
+            applied: Vec::new(),
+            voted: HashSet::new(),
+        }
+    }
+
+    pub fn public_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    /// A block on top of what it applied, made at `now` milliseconds
+    pub fn propose(&mut self, now: u64) -> Block {
+        let (drama_level, producer_mood) = self.agent.produce();
+        let mut block = Block {
+            height: self.state.get_block_height() + 1,
+            parent_hash: self
+                .state
+                .get_latest_block()
+                .map_or([0; 32], |parent| parent.hash()),
+            transactions: Vec::new(),
+            proposer_sig: [0; 64],
+            state_root: self.state.state_root(),
+            innovation_level: drama_level,
+            producer_strategy: "simulated".to_string(),
+            producer_id: self.name.clone(),
+            drama_level,
+            producer_mood,
+            timestamp: GENESIS_TIME + now / 1000,
+            metadata: Default::default(),
+        };
+        block.proposer_sig = self.key.sign(&block.signing_bytes()).to_bytes();
+        block
+    }
+
+    /// Take up `block` for a vote, and what the agent makes of it when it
+    /// has not voted on it yet
+    pub async fn receive_proposal(&mut self, block: &Block) -> Option<Verdict> {
+        let decided = self
+            .finalized
+            .iter()
+            .any(|(height, _)| *height == block.height);
+        if decided || !self.voted.insert(block.hash()) {
+            return None;
+        }
+        self.consensus.start_voting_round(block.clone()).await;
+        Some(self.agent.judge(block))
+    }
+
+    /// Its vote on `block`
+    pub fn vote(&self, block: &Block, verdict: &Verdict) -> SignedVote {
+        SignedVote::sign(
+            &self.key,
+            self.name.clone(),
+            block.height,
+            block.hash(),
+            verdict.approved,
+            verdict.drama_level,
+            verdict.reason.clone(),
+        )
+    }
+
+    /// Count `vote`, cast with `key`, and apply the block it finalizes, if
+    /// it does
+    pub async fn receive_vote(
+        &mut self,
+        vote: &SignedVote,
+        key: &VerifyingKey,
+        stake: u64,
+    ) -> Option<Block> {
+        match self.consensus.add_signed_vote(vote, key, stake).await {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => {
+                debug!("{} passed over a vote: {}", self.name, e);
+                return None;
+            }
+        }
+        let block = self.consensus.get_finalized_block(&vote.block_hash).await?;
+        let hash = block.hash();
+        if self
+            .finalized
+            .iter()
+            .any(|(_, finalized)| *finalized == hash)
+        {
+            return None;
+        }
+        self.finalized.push((block.height, hash));
+        if let Err(e) = self.state.apply_block(&block) {
+            debug!(
+                "{} could not apply block {}: {}",
+                self.name, block.height, e
+            );
+            return None;
+        }
+        self.applied.push(hash);
+        Some(block)
+    }
+}
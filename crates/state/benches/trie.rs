@@ -0,0 +1,53 @@
+//! Time the state trie takes to take a block's worth of balance updates
+//! and to give its root after, holding 1k to 100k accounts:
+//!
+//! ```bash
+//! cargo bench -p chaoschain-state --bench trie
+//! ```
+
+use chaoschain_state::StateStoreImpl;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use std::hint::black_box;
+use std::time::Instant;
+
+/// Balances a block updates
+const UPDATES: usize = 256;
+
+/// Rounds of updates and roots each size is timed over
+const ROUNDS: usize = 10;
+
+fn main() {
+    println!("{:>9} {:>14} {:>12}", "accounts", "update µs/tx", "root ms");
+    for size in [1_000, 10_000, 100_000] {
+        let accounts: Vec<VerifyingKey> = (0..size).map(account).collect();
+        let state = StateStoreImpl::default();
+        for account in &accounts {
+            state.fund(account, 1, "genesis");
+        }
+        let mut updating = 0.0;
+        let mut rooting = 0.0;
+        for round in 0..ROUNDS {
+            let started = Instant::now();
+            for account in accounts.iter().cycle().skip(round * UPDATES).take(UPDATES) {
+                state.fund(black_box(account), 1, "reward");
+            }
+            updating += started.elapsed().as_secs_f64();
+            let started = Instant::now();
+            black_box(state.state_root());
+            rooting += started.elapsed().as_secs_f64();
+        }
+        println!(
+            "{:>9} {:>14.2} {:>12.2}",
+            size,
+            updating * 1e6 / (ROUNDS * UPDATES) as f64,
+            rooting * 1e3 / ROUNDS as f64
+        );
+    }
+}
+
+/// The `i`th account
+fn account(i: usize) -> VerifyingKey {
+    let mut seed = [0; 32];
+    seed[..8].copy_from_slice(&(i as u64).to_be_bytes());
+    SigningKey::from_bytes(&seed).verifying_key()
+}
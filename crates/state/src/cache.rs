@@ -0,0 +1,434 @@
+//! Caches of what RPC and validation read over and over: blocks read back
+//! from the storage, block headers, where transactions are, and account
+//! balances. Each keeps the entries used most lately within a budget of
+//! memory, set by [`CacheConfig`], and counts its hits and misses for
+//! `/metrics`.
+//!
+//! Entries only ever stand for finalized blocks. A block finalized at a
+//! height the chain already has replaces what was there, so the entries of
+//! that height and above are dropped with it, see [`Caches::reorg`].
+
+use crate::index::TxLocation;
+use chaoschain_core::metrics::MetricsRegistry;
+use chaoschain_core::{Block, BlockHeader};
+use parking_lot::Mutex;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::mem::size_of;
+use std::sync::Arc;
+
+type Labels = Vec<(String, String)>;
+
+/// Memory each cache may take, in MiB. A budget of 0 turns its cache off.
+///
+/// ```toml
+/// [cache]
+/// blocks = 64
+/// state = 8
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Blocks read back from the storage
+    pub blocks: usize,
+    pub headers: usize,
+    /// Where each transaction looked up is, by its hash
+    pub receipts: usize,
+    /// Balances of the accounts
+    pub state: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            blocks: 32,
+            headers: 4,
+            receipts: 8,
+            state: 4,
+        }
+    }
+}
+
+/// Bytes in a MiB
+const MIB: usize = 1 << 20;
+
+/// Roughly the memory a cached value takes, its key included
+pub trait Weigh {
+    fn weight(&self) -> usize;
+}
+
+impl Weigh for Block {
+    fn weight(&self) -> usize {
+        let transactions: usize = self
+            .transactions
+            .iter()
+            .map(|tx| tx.payload.len() + tx.cosignatures.len() * 96)
+            .sum();
+        size_of::<Block>()
+            + self.transactions.len() * size_of::<chaoschain_core::Transaction>()
+            + transactions
+            + self.producer_id.len()
+            + self.producer_mood.len()
+            + self.producer_strategy.len()
+            + self.metadata.explanations.len() * 256
+            + self.metadata.inbox.len() * 128
+    }
+}
+
+impl<T: Weigh> Weigh for Arc<T> {
+    fn weight(&self) -> usize {
+        T::weight(self)
+    }
+}
+
+impl Weigh for BlockHeader {
+    fn weight(&self) -> usize {
+        size_of::<u64>() + size_of::<Self>() + self.producer_id.len()
+    }
+}
+
+impl Weigh for TxLocation {
+    fn weight(&self) -> usize {
+        32 + size_of::<Self>()
+    }
+}
+
+impl Weigh for u64 {
+    fn weight(&self) -> usize {
+        // Keyed by the hex of the account
+        64 + size_of::<String>() + size_of::<u64>()
+    }
+}
+
+/// The values used lately, within a budget of bytes, the least recently
+/// used going first
+#[derive(Debug)]
+pub struct Lru<K, V> {
+    budget: usize,
+    used: usize,
+    values: HashMap<K, Entry<V>>,
+    by_use: BTreeMap<u64, K>,
+    uses: u64,
+}
+
+#[derive(Debug)]
+struct Entry<V> {
+    used: u64,
+    weight: usize,
+    value: V,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone + Weigh> Lru<K, V> {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            used: 0,
+            values: HashMap::new(),
+            by_use: BTreeMap::new(),
+            uses: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        self.uses += 1;
+        let entry = self.values.get_mut(key)?;
+        let key = self.by_use.remove(&entry.used)?;
+        entry.used = self.uses;
+        self.by_use.insert(self.uses, key);
+        Some(entry.value.clone())
+    }
+
+    /// Keep `value`, letting go of the values used least lately for room.
+    /// One larger than the whole budget is not kept.
+    pub fn insert(&mut self, key: K, value: V) {
+        self.remove(&key);
+        let weight = value.weight();
+        if weight > self.budget {
+            return;
+        }
+        while self.used + weight > self.budget {
+            let Some((_, oldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.values.remove(&oldest) {
+                self.used -= entry.weight;
+            }
+        }
+        self.uses += 1;
+        self.by_use.insert(self.uses, key.clone());
+        self.used += weight;
+        self.values.insert(
+            key,
+            Entry {
+                used: self.uses,
+                weight,
+                value,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.values.remove(key) {
+            self.by_use.remove(&entry.used);
+            self.used -= entry.weight;
+        }
+    }
+
+    /// Keep only the values `keep` says to
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &V) -> bool) {
+        let gone: Vec<K> = self
+            .values
+            .iter()
+            .filter(|(key, entry)| !keep(key, &entry.value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &gone {
+            self.remove(key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.by_use.clear();
+        self.used = 0;
+    }
+
+    /// Bytes the values take
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// One [`Lru`] of the [`Caches`], counting its hits and misses
+#[derive(Debug)]
+pub struct Cache<K, V> {
+    name: &'static str,
+    values: Mutex<Lru<K, V>>,
+    metrics: CacheMetrics,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone + Weigh> Cache<K, V> {
+    fn new(name: &'static str, mib: usize, metrics: CacheMetrics) -> Self {
+        Self {
+            name,
+            values: Mutex::new(Lru::new(mib * MIB)),
+            metrics,
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self.values.lock().get(key);
+        self.metrics.looked_up(self.name, value.is_some());
+        value
+    }
+
+    /// The value of `key`, read with `read` and kept when it is not cached
+    pub fn get_or_read(&self, key: &K, read: impl FnOnce() -> Option<V>) -> Option<V> {
+        if let Some(value) = self.get(key) {
+            return Some(value);
+        }
+        let value = read()?;
+        self.insert(key.clone(), value.clone());
+        Some(value)
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let mut values = self.values.lock();
+        values.insert(key, value);
+        self.metrics.sized(self.name, values.used());
+    }
+
+    pub fn remove(&self, key: &K) {
+        let mut values = self.values.lock();
+        values.remove(key);
+        self.metrics.sized(self.name, values.used());
+    }
+
+    pub fn retain(&self, keep: impl FnMut(&K, &V) -> bool) {
+        let mut values = self.values.lock();
+        values.retain(keep);
+        self.metrics.sized(self.name, values.used());
+    }
+
+    pub fn clear(&self) {
+        self.values.lock().clear();
+        self.metrics.sized(self.name, 0);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.lock().is_empty()
+    }
+}
+
+/// The caches of a state store
+#[derive(Debug)]
+pub struct Caches {
+    /// Blocks read back from the storage, by height
+    pub blocks: Cache<u64, Arc<Block>>,
+    /// Headers of the blocks, by height
+    pub headers: Cache<u64, BlockHeader>,
+    /// Where transactions are, by their hash
+    pub receipts: Cache<[u8; 32], TxLocation>,
+    /// Balances, by the hex of the account
+    pub state: Cache<String, u64>,
+    metrics: CacheMetrics,
+}
+
+impl Caches {
+    pub fn new(config: &CacheConfig) -> Self {
+        let metrics = CacheMetrics::default();
+        Self {
+            blocks: Cache::new("blocks", config.blocks, metrics.clone()),
+            headers: Cache::new("headers", config.headers, metrics.clone()),
+            receipts: Cache::new("receipts", config.receipts, metrics.clone()),
+            state: Cache::new("state", config.state, metrics.clone()),
+            metrics,
+        }
+    }
+
+    /// Drop what stands for the blocks from `height` on, which a block
+    /// finalized at `height` replaces, and the balances they made
+    pub fn reorg(&self, height: u64) {
+        self.blocks.retain(|at, _| *at < height);
+        self.headers.retain(|at, _| *at < height);
+        self.receipts.retain(|_, location| location.height < height);
+        self.state.clear();
+    }
+
+    /// Drop everything, as when the state is replaced
+    pub fn clear(&self) {
+        self.blocks.clear();
+        self.headers.clear();
+        self.receipts.clear();
+        self.state.clear();
+    }
+
+    pub fn register_metrics(&self, registry: &MetricsRegistry) {
+        self.metrics.register(registry);
+    }
+}
+
+impl Default for Caches {
+    fn default() -> Self {
+        Self::new(&CacheConfig::default())
+    }
+}
+
+/// Clones share their values
+#[derive(Debug, Clone, Default)]
+struct CacheMetrics {
+    hits: Family<Labels, Counter>,
+    misses: Family<Labels, Counter>,
+    bytes: Family<Labels, Gauge>,
+}
+
+impl CacheMetrics {
+    fn register(&self, registry: &MetricsRegistry) {
+        registry.register(
+            "chaoschain_cache_hits",
+            "Lookups a cache answered, per cache",
+            self.hits.clone(),
+        );
+        registry.register(
+            "chaoschain_cache_misses",
+            "Lookups a cache could not answer, per cache",
+            self.misses.clone(),
+        );
+        registry.register(
+            "chaoschain_cache_bytes",
+            "Memory the values of a cache take, roughly, per cache",
+            self.bytes.clone(),
+        );
+    }
+
+    fn looked_up(&self, cache: &str, hit: bool) {
+        let labels = vec![("cache".to_string(), cache.to_string())];
+        if hit {
+            self.hits.get_or_create(&labels).inc();
+        } else {
+            self.misses.get_or_create(&labels).inc();
+        }
+    }
+
+    fn sized(&self, cache: &str, bytes: usize) {
+        self.bytes
+            .get_or_create(&vec![("cache".to_string(), cache.to_string())])
+            .set(bytes as i64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Sized(usize);
+
+    impl Weigh for Sized {
+        fn weight(&self) -> usize {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_lru_keeps_within_its_budget() {
+        let mut lru = Lru::new(100);
+        lru.insert(1, Sized(40));
+        lru.insert(2, Sized(40));
+        // 1 is used lately, so 2 makes room for 3
+        assert_eq!(lru.get(&1), Some(Sized(40)));
+        lru.insert(3, Sized(40));
+        assert_eq!(lru.get(&2), None);
+        assert_eq!((lru.len(), lru.used()), (2, 80));
+
+        // Too large for the whole budget, and what it replaces is gone
+        lru.insert(1, Sized(101));
+        assert_eq!(lru.get(&1), None);
+        assert_eq!(lru.used(), 40);
+
+        lru.insert(4, Sized(60));
+        lru.retain(|key, _| *key != 3);
+        assert_eq!((lru.len(), lru.used()), (1, 60));
+        assert!(Lru::<u8, Sized>::new(0).is_empty());
+    }
+
+    #[test]
+    fn test_reorg_drops_the_replaced_heights() {
+        let caches = Caches::default();
+        for height in 1..=3 {
+            caches
+                .receipts
+                .insert([height as u8; 32], TxLocation { height, index: 0 });
+        }
+        caches.state.insert("account".to_string(), 7);
+        assert_eq!(caches.state.get(&"account".to_string()), Some(7));
+        assert_eq!(caches.state.get(&"stranger".to_string()), None);
+
+        caches.reorg(2);
+        assert_eq!(caches.receipts.len(), 1);
+        assert!(caches.receipts.get(&[1; 32]).is_some());
+        assert!(caches.state.is_empty());
+
+        let registry = MetricsRegistry::default();
+        caches.register_metrics(&registry);
+        let body = registry.encode().unwrap();
+        assert!(body.contains("chaoschain_cache_hits_total{cache=\"state\"} 1"));
+        assert!(body.contains("chaoschain_cache_misses_total{cache=\"state\"} 1"));
+    }
+}
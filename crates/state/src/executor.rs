@@ -0,0 +1,327 @@
+//! Executing the transactions of a block on several threads. What a
+//! transaction does to the state is check itself against, and update, the
+//! registries of the chain: multisig accounts, commits waiting for their
+//! reveal, verification keys, governance proposals and stake, whose bonds
+//! are checked here and paid for once the block is over. Each transaction
+//! declares the entries it touches, its [`Key`]s: its sender and whatever
+//! its payload names, an account it creates, a commit it makes or reveals,
+//! a key it registers or proves against, a proposal it puts or votes on.
+//! Transactions sharing none are independent.
+//!
+//! The block is partitioned into the sets of transactions that share keys,
+//! through one another or directly. The sets run in parallel, each in
+//! block order on a view of the registries holding only its keys, and the
+//! entries of those keys are written back in block order. A transaction
+//! that fails in its set, or reads what it did not declare and so does not
+//! find it, has the block run again one transaction after the other on the
+//! whole registries: the block holds exactly when it holds run in order,
+//! and fails at the same first transaction.
+
+use chaoschain_core::commit::{Commit, Commitments, Reveal};
+use chaoschain_core::governance::{Ballot, Governance, Proposal};
+use chaoschain_core::multisig::{MultisigAccount, MultisigAccounts};
+use chaoschain_core::staking::Staking;
+use chaoschain_core::zk::{ProofRecord, ProofSystems, VerificationKey, VerificationKeys};
+use chaoschain_core::{Block, Error, Transaction};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// An entry of the registries a transaction touches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Key {
+    /// A sender, and the multisig account of the address when there is one
+    Account([u8; 32]),
+    /// A commit, by the hash of the transaction making it
+    Commit([u8; 32]),
+    VerificationKey([u8; 32]),
+    /// A proposal, by the hash of the transaction putting it
+    Proposal([u8; 32]),
+}
+
+/// The keys `tx` touches, its sender first
+pub fn keys(tx: &Transaction) -> Vec<Key> {
+    let mut keys = vec![Key::Account(tx.sender)];
+    if let Some(Ok(account)) = MultisigAccount::from_transaction(tx) {
+        keys.push(Key::Account(account.address()));
+    }
+    if let Some(Ok(_)) = Commit::from_transaction(tx) {
+        keys.push(Key::Commit(tx.hash()));
+    }
+    if let Some(Ok(reveal)) = Reveal::from_transaction(tx) {
+        keys.push(Key::Commit(reveal.commit));
+    }
+    if let Some(Ok(key)) = VerificationKey::from_transaction(tx) {
+        keys.push(Key::VerificationKey(key.id()));
+    }
+    if let Some(Ok(record)) = ProofRecord::from_transaction(tx) {
+        keys.push(Key::VerificationKey(record.key));
+    }
+    if let Some(Ok(_)) = Proposal::from_transaction(tx) {
+        keys.push(Key::Proposal(tx.hash()));
+    }
+    if let Some(Ok(ballot)) = Ballot::from_transaction(tx) {
+        keys.push(Key::Proposal(ballot.proposal));
+    }
+    keys
+}
+
+/// The positions of `txs` in sets sharing no key with one another, each
+/// in order, the sets in the order of their first transaction
+pub fn partition(txs: &[Transaction]) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0..txs.len()).collect();
+    fn root(parents: &mut [usize], mut index: usize) -> usize {
+        while parents[index] != index {
+            parents[index] = parents[parents[index]];
+            index = parents[index];
+        }
+        index
+    }
+    let mut owners: HashMap<Key, usize> = HashMap::new();
+    for (index, tx) in txs.iter().enumerate() {
+        for key in keys(tx) {
+            let owner = *owners.entry(key).or_insert(index);
+            let (a, b) = (root(&mut parents, owner), root(&mut parents, index));
+            // The earlier one is the root, so that sets keep block order
+            parents[a.max(b)] = a.min(b);
+        }
+    }
+    let mut sets: Vec<Vec<usize>> = Vec::new();
+    let mut positions: HashMap<usize, usize> = HashMap::new();
+    for index in 0..txs.len() {
+        let set = root(&mut parents, index);
+        let position = *positions.entry(set).or_insert_with(|| {
+            sets.push(Vec::new());
+            sets.len() - 1
+        });
+        sets[position].push(index);
+    }
+    sets
+}
+
+/// What the transactions of the chain have made, that those after check
+/// themselves against
+#[derive(Debug, Clone, Default)]
+pub struct Registries {
+    pub multisig: MultisigAccounts,
+    pub commitments: Commitments,
+    pub verification_keys: VerificationKeys,
+    pub governance: Governance,
+    pub staking: Staking,
+}
+
+/// How a block was executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Execution {
+    /// Sets of independent transactions
+    pub sets: usize,
+    /// Whether the block was run again in order, after a transaction
+    /// failed in its set
+    pub sequential: bool,
+}
+
+impl Registries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `tx`, in a block at `height`, holds against the registries,
+    /// taking what it makes when it does. Its own signature is checked
+    /// apart, unless it is one of a multisig account.
+    pub fn execute(
+        &mut self,
+        tx: &Transaction,
+        height: u64,
+        systems: &ProofSystems,
+    ) -> Result<(), Error> {
+        if self.multisig.is_multisig(tx) {
+            self.multisig.verify(tx)?;
+        }
+        self.commitments.check(tx, height)?;
+        self.verification_keys.check(tx, systems)?;
+        self.governance.check(tx, height)?;
+        self.staking.check(tx)?;
+        self.multisig.apply_transaction(tx);
+        self.commitments.apply_transaction(tx, height);
+        self.verification_keys.apply_transaction(tx);
+        self.governance.apply_transaction(tx, height);
+        Ok(())
+    }
+
+    /// Execute `block`, its independent transactions in parallel. The
+    /// registries are left as they were when it does not hold.
+    pub fn execute_block(
+        &mut self,
+        block: &Block,
+        systems: &ProofSystems,
+    ) -> Result<Execution, Error> {
+        let sets = partition(&block.transactions);
+        let results: Vec<(Registries, Vec<Key>, Result<(), Error>)> = sets
+            .par_iter()
+            .map(|set| {
+                let mut keys: Vec<Key> = set
+                    .iter()
+                    .flat_map(|&index| self::keys(&block.transactions[index]))
+                    .collect();
+                keys.sort();
+                keys.dedup();
+                let mut view = self.view(&keys);
+                view.commitments.expire(block.height);
+                let executed = set.iter().try_for_each(|&index| {
+                    view.execute(&block.transactions[index], block.height, systems)
+                });
+                (view, keys, executed)
+            })
+            .collect();
+
+        if results.iter().all(|(_, _, executed)| executed.is_ok()) {
+            for (view, keys, _) in &results {
+                self.write_back(view, keys);
+            }
+            self.commitments.expire(block.height);
+            return Ok(Execution {
+                sets: sets.len(),
+                sequential: false,
+            });
+        }
+        let mut registries = self.clone();
+        registries.commitments.expire(block.height);
+        for tx in &block.transactions {
+            registries.execute(tx, block.height, systems)?;
+        }
+        *self = registries;
+        Ok(Execution {
+            sets: sets.len(),
+            sequential: true,
+        })
+    }
+
+    /// Registries holding what these do of `keys` only
+    fn view(&self, keys: &[Key]) -> Self {
+        let mut view = Self {
+            governance: self.governance.without_proposals(),
+            ..Self::new()
+        };
+        view.write_back(self, keys);
+        view
+    }
+
+    /// Take what `other` holds of `keys`, in place of what is held here
+    fn write_back(&mut self, other: &Self, keys: &[Key]) {
+        for key in keys {
+            match key {
+                Key::Account(address) => {
+                    if let Some(account) = other.multisig.get(address) {
+                        self.multisig.insert(account.clone());
+                    }
+                }
+                Key::Commit(commit) => self.commitments.copy_entry(&other.commitments, commit),
+                Key::VerificationKey(id) => {
+                    if let Some(key) = other.verification_keys.get(id) {
+                        self.verification_keys.insert(key.clone());
+                    }
+                }
+                Key::Proposal(id) => self.governance.copy_entry(&other.governance, id),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_core::multisig::Cosignature;
+    use ed25519_dalek::SigningKey;
+
+    fn tx(sender: u8, nonce: u64, payload: Vec<u8>) -> Transaction {
+        Transaction {
+            sender: [sender; 32],
+            nonce,
+            payload: payload.into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        }
+    }
+
+    fn block(height: u64, transactions: Vec<Transaction>) -> Block {
+        Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            producer_id: "producer-1".to_string(),
+            drama_level: 0,
+            producer_mood: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_parallel_execution() {
+        let owners: Vec<SigningKey> = (1..=2).map(|i| SigningKey::from_bytes(&[i; 32])).collect();
+        let account = MultisigAccount::new(
+            owners
+                .iter()
+                .map(|owner| owner.verifying_key().to_bytes())
+                .collect(),
+            2,
+        )
+        .unwrap();
+        let create = tx(9, 0, account.payload());
+        let mut spend = Transaction {
+            sender: account.address(),
+            ..tx(0, 0, b"pay the alliance".to_vec())
+        };
+        spend.cosignatures = owners
+            .iter()
+            .map(|owner| Cosignature::new(&spend, owner).unwrap())
+            .collect();
+        let commit = tx(3, 0, Commit::seal(&[3; 32], &[7; 32], b"sealed").payload());
+
+        // The spend depends on the creation, the rest on nothing
+        let txs = vec![
+            tx(1, 0, b"hello".to_vec()),
+            create.clone(),
+            tx(2, 0, b"chaos".to_vec()),
+            spend.clone(),
+            commit.clone(),
+            tx(1, 1, b"again".to_vec()),
+        ];
+        assert_eq!(
+            partition(&txs),
+            vec![vec![0, 5], vec![1, 3], vec![2], vec![4]]
+        );
+
+        let systems = ProofSystems::new();
+        let mut registries = Registries::new();
+        let execution = registries
+            .execute_block(&block(1, txs.clone()), &systems)
+            .unwrap();
+        assert_eq!(
+            execution,
+            Execution {
+                sets: 4,
+                sequential: false
+            }
+        );
+        assert_eq!(registries.multisig.get(&account.address()), Some(&account));
+        assert!(registries.commitments.pending(&commit.hash()).is_some());
+
+        // The same as one after the other
+        let mut sequential = Registries::new();
+        for tx in &txs {
+            sequential.execute(tx, 1, &systems).unwrap();
+        }
+        assert_eq!(sequential.multisig, registries.multisig);
+        assert_eq!(sequential.commitments, registries.commitments);
+
+        // A spend before the creation fails, and leaves the registries be
+        let mut registries = Registries::new();
+        let failed = registries.execute_block(&block(1, vec![spend, create]), &systems);
+        assert!(failed.is_err());
+        assert!(registries.multisig.get(&account.address()).is_none());
+    }
+}
@@ -0,0 +1,954 @@
+//! Secondary indexes over the processed blocks for the explorer: the
+//! transactions of a sender, the blocks of a producer, blocks by time, and
+//! what each agent has been up to. Blocks are indexed as they are stored,
+//! agent activity is recorded by whoever follows the node's events. The
+//! history of an account joins its transactions, balance changes and
+//! activity, the bribes it offered and was offered among it.
+//!
+//! Activity is searchable by agent, kind of event, chain height and the
+//! words in it, and keeps the statistics of each validator up to date. With a journal it is appended to a JSON lines file as it
+//! comes in, and read back from there on restart.
+
+use crate::stats::ValidatorLedger;
+use crate::StateError;
+use chaoschain_core::{Block, NetworkEvent, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use tracing::error;
+
+/// Activity kept before the oldest is dropped
+pub const MAX_ACTIVITY: usize = 100_000;
+
+/// Which way a listing runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    /// Oldest first
+    Asc,
+    /// Newest first
+    #[default]
+    Desc,
+}
+
+/// Where a transaction sits in the chain, ordered by height then position
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TxLocation {
+    pub height: u64,
+    /// Position within the block
+    pub index: usize,
+}
+
+/// A credit to the balance of an account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceChange {
+    pub height: u64,
+    /// Of the block, in unix seconds
+    pub timestamp: u64,
+    pub amount: u64,
+    /// After the change
+    pub balance: u64,
+    /// e.g. `block_reward`
+    pub reason: String,
+}
+
+/// An entry of an account's history, in the index it comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryEntry {
+    Transaction(TxLocation),
+    /// Position among the account's balance changes
+    BalanceChange(usize),
+    /// Sequence number of an activity
+    Activity(u64),
+}
+
+/// Orders the history of an account: by time, then by where the entries
+/// come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct HistoryKey {
+    /// Unix seconds
+    pub timestamp: u64,
+    pub entry: HistoryEntry,
+}
+
+/// An entry of an account's history, with what it refers to
+#[derive(Debug, Clone)]
+pub enum AccountEvent {
+    /// Sent by the account
+    Transaction {
+        location: TxLocation,
+        transaction: Transaction,
+        block: Box<Block>,
+    },
+    BalanceChange(BalanceChange),
+    /// The account pitched a block with an offer to its supporters
+    BribeSent {
+        activity: Activity,
+        offer: u64,
+    },
+    /// The account answered a pitch that came with an offer
+    BribeReceived {
+        activity: Activity,
+        producer: String,
+        offer: u64,
+    },
+    /// Anything else the account said or did
+    Drama(Activity),
+}
+
+/// Something an agent said or did, as seen on the node's event channel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Activity {
+    /// Counts up from the first activity recorded
+    pub seq: u64,
+    /// When it was recorded (unix seconds)
+    pub timestamp: u64,
+    /// Height of the chain at the time
+    pub height: u64,
+    pub agent: String,
+    /// See [`NetworkEvent::kind`]
+    pub kind: String,
+    /// What was said, for keyword search
+    pub text: String,
+    pub event: NetworkEvent,
+}
+
+/// The lowercase words of `text`, as keyword search sees them
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Which activity to return. Everything given has to match.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ActivityFilter {
+    pub agent: Option<String>,
+    /// Comma separated kinds, e.g. `chat,rumor`
+    pub kind: Option<String>,
+    pub from_height: Option<u64>,
+    /// Inclusive
+    pub to_height: Option<u64>,
+    /// Words that all have to appear
+    pub q: Option<String>,
+    /// Only activity with a lower sequence number, for paging
+    pub before: Option<u64>,
+    /// Only activity with a higher sequence number
+    pub after: Option<u64>,
+}
+
+impl ActivityFilter {
+    /// The agent, kind and keyword part of the filter
+    pub fn matches_text(&self, agent: &str, kind: &str, text: &str) -> bool {
+        self.agent.as_deref().is_none_or(|a| a == agent)
+            && self
+                .kind
+                .as_deref()
+                .is_none_or(|kinds| kinds.split(',').any(|k| k.trim() == kind))
+            && self.q.as_deref().is_none_or(|q| {
+                let found: BTreeSet<String> = words(text).collect();
+                words(q).all(|word| found.contains(&word))
+            })
+    }
+
+    pub fn matches(&self, activity: &Activity) -> bool {
+        self.from_height.is_none_or(|h| activity.height >= h)
+            && self.to_height.is_none_or(|h| activity.height <= h)
+            && self.before.is_none_or(|seq| activity.seq < seq)
+            && self.after.is_none_or(|seq| activity.seq > seq)
+            && self.matches_text(&activity.agent, &activity.kind, &activity.text)
+    }
+}
+
+#[derive(Debug)]
+struct Journal {
+    path: PathBuf,
+    file: File,
+    /// Lines in the file, rewritten once they are well past what is kept
+    lines: usize,
+    /// Why the latest write failed, until one succeeds again
+    failure: Option<String>,
+}
+
+impl Journal {
+    fn open(path: PathBuf, lines: usize) -> Result<Self, StateError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| StateError::Internal(format!("{}: {}", path.display(), e)))?;
+        Ok(Self {
+            path,
+            file,
+            lines,
+            failure: None,
+        })
+    }
+
+    fn append(&mut self, activity: &Activity) -> std::io::Result<()> {
+        let line = serde_json::to_string(activity)?;
+        writeln!(self.file, "{}", line)?;
+        self.lines += 1;
+        Ok(())
+    }
+
+    /// Write out only what is kept, swapping the file in when complete
+    fn compact<'a>(&mut self, kept: impl Iterator<Item = &'a Activity>) -> std::io::Result<()> {
+        let partial = self.path.with_extension("partial");
+        let mut file = File::create(&partial)?;
+        let mut lines = 0;
+        for activity in kept {
+            writeln!(file, "{}", serde_json::to_string(activity)?)?;
+            lines += 1;
+        }
+        file.sync_all()?;
+        std::fs::rename(&partial, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.lines = lines;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ChainIndex {
+    txs_by_sender: HashMap<[u8; 32], Vec<TxLocation>>,
+    blocks_by_producer: HashMap<String, Vec<u64>>,
+    /// Heights of the blocks by their timestamp
+    blocks_by_time: BTreeMap<u64, Vec<u64>>,
+    activity: VecDeque<Activity>,
+    /// Sequence numbers of the activity of each agent
+    activity_by_agent: HashMap<String, VecDeque<u64>>,
+    /// Sequence numbers of the activity each word appears in
+    activity_by_word: HashMap<String, VecDeque<u64>>,
+    next_seq: u64,
+    journal: Option<Journal>,
+    validators: ValidatorLedger,
+    /// Timestamps of the blocks by height
+    block_times: HashMap<u64, u64>,
+    balance_changes: HashMap<String, Vec<BalanceChange>>,
+    /// Producer and offer of the pitches that came with one, by
+    /// negotiation, for as long as the pitch is kept
+    offers: HashMap<String, (String, u64)>,
+}
+
+/// At most `limit` of `items`, sorted by `key`, in `order` and past
+/// `cursor` if given
+pub fn page<T: Clone, K: Ord>(
+    items: &[T],
+    key: impl Fn(&T) -> K,
+    cursor: Option<K>,
+    order: Order,
+    limit: usize,
+) -> Vec<T> {
+    match order {
+        Order::Asc => {
+            let start = cursor.map_or(0, |cursor| {
+                items.partition_point(|item| key(item) <= cursor)
+            });
+            items[start..].iter().take(limit).cloned().collect()
+        }
+        Order::Desc => {
+            let end = cursor.map_or(items.len(), |cursor| {
+                items.partition_point(|item| key(item) < cursor)
+            });
+            items[..end].iter().rev().take(limit).cloned().collect()
+        }
+    }
+}
+
+/// Drop `seq` from the front of an index list, and the list once empty
+fn unindex(index: &mut HashMap<String, VecDeque<u64>>, key: &str, seq: u64) {
+    if let Some(seqs) = index.get_mut(key) {
+        if seqs.front() == Some(&seq) {
+            seqs.pop_front();
+        }
+        if seqs.is_empty() {
+            index.remove(key);
+        }
+    }
+}
+
+impl ChainIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep the activity in a journal at `path`, starting from what it
+    /// already holds
+    pub fn with_journal(mut self, path: impl Into<PathBuf>) -> Result<Self, StateError> {
+        let path = path.into();
+        let mut lines = 0;
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                for line in text.lines() {
+                    lines += 1;
+                    // A line cut short by a crash is skipped
+                    if let Ok(activity) = serde_json::from_str::<Activity>(line) {
+                        self.next_seq = self.next_seq.max(activity.seq + 1);
+                        // Timed to the second, as the journal has it
+                        self.validators
+                            .observe(&activity.event, activity.timestamp * 1000);
+                        self.push(activity);
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(StateError::Internal(format!("{}: {}", path.display(), e)));
+            }
+        }
+        self.journal = Some(Journal::open(path, lines)?);
+        Ok(self)
+    }
+
+    pub fn add_block(&mut self, block: &Block) {
+        for (index, tx) in block.transactions.iter().enumerate() {
+            self.txs_by_sender
+                .entry(tx.sender)
+                .or_default()
+                .push(TxLocation {
+                    height: block.height,
+                    index,
+                });
+        }
+        self.blocks_by_producer
+            .entry(block.producer_id.clone())
+            .or_default()
+            .push(block.height);
+        self.blocks_by_time
+            .entry(block.timestamp)
+            .or_default()
+            .push(block.height);
+        self.block_times.insert(block.height, block.timestamp);
+    }
+
+    pub fn add_balance_change(&mut self, account: &str, change: BalanceChange) {
+        self.balance_changes
+            .entry(account.to_string())
+            .or_default()
+            .push(change);
+    }
+
+    pub fn balance_change(&self, account: &str, position: usize) -> Option<&BalanceChange> {
+        self.balance_changes.get(account)?.get(position)
+    }
+
+    /// The producer that offered something for support in `negotiation`,
+    /// and how much
+    pub fn offer(&self, negotiation: &str) -> Option<(&str, u64)> {
+        self.offers
+            .get(negotiation)
+            .map(|(producer, offer)| (producer.as_str(), *offer))
+    }
+
+    /// The history of `account`, an agent id or a hex encoded key, in
+    /// `order` and past `cursor`
+    pub fn account_history(
+        &self,
+        account: &str,
+        cursor: Option<HistoryKey>,
+        order: Order,
+        limit: usize,
+    ) -> Vec<HistoryKey> {
+        let mut keys = Vec::new();
+        let sender = hex::decode(account.strip_prefix("0x").unwrap_or(account))
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok());
+        if let Some(txs) = sender.and_then(|sender| self.txs_by_sender.get(&sender)) {
+            keys.extend(txs.iter().map(|location| {
+                HistoryKey {
+                    timestamp: self
+                        .block_times
+                        .get(&location.height)
+                        .copied()
+                        .unwrap_or_default(),
+                    entry: HistoryEntry::Transaction(*location),
+                }
+            }));
+        }
+        if let Some(changes) = self.balance_changes.get(account) {
+            keys.extend(
+                changes
+                    .iter()
+                    .enumerate()
+                    .map(|(position, change)| HistoryKey {
+                        timestamp: change.timestamp,
+                        entry: HistoryEntry::BalanceChange(position),
+                    }),
+            );
+        }
+        if let Some(seqs) = self.activity_by_agent.get(account) {
+            keys.extend(seqs.iter().filter_map(|seq| {
+                Some(HistoryKey {
+                    timestamp: self.activity_at(*seq)?.timestamp,
+                    entry: HistoryEntry::Activity(*seq),
+                })
+            }));
+        }
+        keys.sort();
+        page(&keys, |key| *key, cursor, order, limit)
+    }
+
+    /// Forget the blocks, e.g. before indexing what is left after pruning.
+    /// Agent activity is kept.
+    pub fn clear_blocks(&mut self) {
+        self.txs_by_sender.clear();
+        self.blocks_by_producer.clear();
+        self.blocks_by_time.clear();
+        self.block_times.clear();
+    }
+
+    /// Transactions sent by `sender`, in `order`, past `cursor`
+    pub fn transactions_by_sender(
+        &self,
+        sender: &[u8; 32],
+        cursor: Option<TxLocation>,
+        order: Order,
+        limit: usize,
+    ) -> Vec<TxLocation> {
+        self.txs_by_sender.get(sender).map_or_else(Vec::new, |txs| {
+            page(txs, |location| *location, cursor, order, limit)
+        })
+    }
+
+    /// Heights of the blocks `producer` made, in `order`, past `cursor`
+    pub fn blocks_by_producer(
+        &self,
+        producer: &str,
+        cursor: Option<u64>,
+        order: Order,
+        limit: usize,
+    ) -> Vec<u64> {
+        self.blocks_by_producer
+            .get(producer)
+            .map_or_else(Vec::new, |heights| {
+                page(heights, |h| *h, cursor, order, limit)
+            })
+    }
+
+    /// Heights of the blocks with a timestamp in `from..to`, oldest first
+    pub fn blocks_between(&self, from: u64, to: u64, limit: usize) -> Vec<u64> {
+        if from >= to {
+            return Vec::new();
+        }
+        self.blocks_by_time
+            .range(from..to)
+            .flat_map(|(_, heights)| heights.iter().copied())
+            .take(limit)
+            .collect()
+    }
+
+    /// Index `activity`, dropping the oldest if there is too much
+    fn push(&mut self, activity: Activity) {
+        if self.activity.len() >= MAX_ACTIVITY {
+            if let Some(oldest) = self.activity.pop_front() {
+                if let NetworkEvent::NegotiationPitch { pitch } = &oldest.event {
+                    self.offers.remove(&pitch.negotiation_id);
+                }
+                unindex(&mut self.activity_by_agent, &oldest.agent, oldest.seq);
+                for word in words(&oldest.text).collect::<BTreeSet<_>>() {
+                    unindex(&mut self.activity_by_word, &word, oldest.seq);
+                }
+            }
+        }
+        if let NetworkEvent::NegotiationPitch { pitch } = &activity.event {
+            if let Some(offer) = pitch.offer {
+                self.offers.insert(
+                    pitch.negotiation_id.clone(),
+                    (pitch.producer.clone(), offer),
+                );
+            }
+        }
+        self.activity_by_agent
+            .entry(activity.agent.clone())
+            .or_default()
+            .push_back(activity.seq);
+        for word in words(&activity.text).collect::<BTreeSet<_>>() {
+            self.activity_by_word
+                .entry(word)
+                .or_default()
+                .push_back(activity.seq);
+        }
+        self.activity.push_back(activity);
+    }
+
+    /// Remember that `agent` caused `event`, saying `text`. Returns the
+    /// sequence number of the activity.
+    pub fn record_activity(
+        &mut self,
+        agent: String,
+        text: String,
+        event: NetworkEvent,
+        height: u64,
+        timestamp: u64,
+    ) -> u64 {
+        self.record_activity_at(agent, text, event, height, timestamp * 1000)
+    }
+
+    /// [`Self::record_activity`] to the millisecond, for timing the votes
+    pub fn record_activity_at(
+        &mut self,
+        agent: String,
+        text: String,
+        event: NetworkEvent,
+        height: u64,
+        at_ms: u64,
+    ) -> u64 {
+        let timestamp = at_ms / 1000;
+        self.validators.observe(&event, at_ms);
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let activity = Activity {
+            seq,
+            timestamp,
+            height,
+            agent,
+            kind: event.kind().to_string(),
+            text,
+            event,
+        };
+        if let Some(journal) = &mut self.journal {
+            match journal.append(&activity) {
+                Ok(()) => journal.failure = None,
+                Err(e) => {
+                    error!("Failed to journal activity {}: {}", seq, e);
+                    journal.failure = Some(format!("{}: {}", journal.path.display(), e));
+                }
+            }
+        }
+        self.push(activity);
+        if let Some(journal) = &mut self.journal {
+            if journal.lines > 2 * MAX_ACTIVITY {
+                if let Err(e) = journal.compact(self.activity.iter()) {
+                    error!("Failed to compact the activity journal: {}", e);
+                    journal.failure = Some(format!("{}: {}", journal.path.display(), e));
+                }
+            }
+        }
+        seq
+    }
+
+    /// Why the activity journal could not be written to lately, if it
+    /// could not
+    pub fn journal_failure(&self) -> Option<&str> {
+        self.journal.as_ref()?.failure.as_deref()
+    }
+
+    pub fn validators(&self) -> &ValidatorLedger {
+        &self.validators
+    }
+
+    pub fn activity_at(&self, seq: u64) -> Option<&Activity> {
+        let first = self.activity.front()?.seq;
+        self.activity.get(seq.checked_sub(first)? as usize)
+    }
+
+    /// What `agent` did, in `order`, past sequence number `cursor`
+    pub fn activity_of(
+        &self,
+        agent: &str,
+        cursor: Option<u64>,
+        order: Order,
+        limit: usize,
+    ) -> Vec<Activity> {
+        let mut filter = ActivityFilter {
+            agent: Some(agent.to_string()),
+            ..ActivityFilter::default()
+        };
+        match order {
+            Order::Asc => filter.after = cursor,
+            Order::Desc => filter.before = cursor,
+        }
+        self.search(&filter, order, limit)
+    }
+
+    /// Activity matching `filter`, in `order`
+    pub fn search(&self, filter: &ActivityFilter, order: Order, limit: usize) -> Vec<Activity> {
+        // Walk the shortest list of candidates the indexes have to offer
+        let mut lists = Vec::new();
+        for word in filter.q.as_deref().map(words).into_iter().flatten() {
+            match self.activity_by_word.get(&word) {
+                Some(seqs) => lists.push(seqs),
+                None => return Vec::new(),
+            }
+        }
+        if let Some(agent) = &filter.agent {
+            match self.activity_by_agent.get(agent) {
+                Some(seqs) => lists.push(seqs),
+                None => return Vec::new(),
+            }
+        }
+        let candidates: Box<dyn DoubleEndedIterator<Item = &Activity>> =
+            match lists.into_iter().min_by_key(|seqs| seqs.len()) {
+                Some(seqs) => Box::new(seqs.iter().filter_map(|seq| self.activity_at(*seq))),
+                None => Box::new(self.activity.iter()),
+            };
+        let candidates = match order {
+            Order::Asc => candidates,
+            Order::Desc => Box::new(candidates.rev()),
+        };
+        candidates
+            .filter(|activity| filter.matches(activity))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Activity recorded in `from..to`, oldest first
+    pub fn activity_between(&self, from: u64, to: u64, limit: usize) -> Vec<Activity> {
+        // Recorded in order, so timestamps only go up
+        let start = self
+            .activity
+            .partition_point(|activity| activity.timestamp < from);
+        self.activity
+            .range(start..)
+            .take_while(|activity| activity.timestamp < to)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(height: u64, producer: &str, senders: &[u8]) -> Block {
+        Block {
+            height,
+            parent_hash: [0; 32],
+            transactions: senders
+                .iter()
+                .map(|sender| Transaction {
+                    sender: [*sender; 32],
+                    nonce: height,
+                    payload: vec![].into(),
+                    signature: [0; 64],
+                    cosignatures: Vec::new(),
+                })
+                .collect(),
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: "dramatic".to_string(),
+            producer_id: producer.to_string(),
+            innovation_level: 0,
+            producer_strategy: "".into(),
+            timestamp: 1000 + height * 10,
+            metadata: Default::default(),
+        }
+    }
+
+    fn chat(sender: &str, message: &str) -> NetworkEvent {
+        NetworkEvent::AgentChat {
+            message: message.to_string(),
+            sender: sender.to_string(),
+            meme_url: None,
+        }
+    }
+
+    fn record(index: &mut ChainIndex, agent: &str, message: &str, height: u64, timestamp: u64) {
+        index.record_activity(
+            agent.to_string(),
+            message.to_string(),
+            chat(agent, message),
+            height,
+            timestamp,
+        );
+    }
+
+    fn seqs(activity: Vec<Activity>) -> Vec<u64> {
+        activity.iter().map(|a| a.seq).collect()
+    }
+
+    #[test]
+    fn test_index_blocks_by_sender_producer_and_time() {
+        let mut index = ChainIndex::new();
+        index.add_block(&block(1, "alice", &[7, 8]));
+        index.add_block(&block(2, "bob", &[7]));
+        index.add_block(&block(3, "alice", &[]));
+
+        assert_eq!(
+            index.transactions_by_sender(&[7; 32], None, Order::Desc, 10),
+            vec![
+                TxLocation {
+                    height: 2,
+                    index: 0
+                },
+                TxLocation {
+                    height: 1,
+                    index: 0
+                },
+            ]
+        );
+        assert_eq!(
+            index.transactions_by_sender(&[8; 32], None, Order::Desc, 10),
+            vec![TxLocation {
+                height: 1,
+                index: 1
+            }]
+        );
+        assert!(index
+            .transactions_by_sender(&[9; 32], None, Order::Desc, 10)
+            .is_empty());
+
+        assert_eq!(
+            index.blocks_by_producer("alice", None, Order::Desc, 10),
+            vec![3, 1]
+        );
+        assert_eq!(
+            index.blocks_by_producer("alice", Some(3), Order::Desc, 10),
+            vec![1]
+        );
+        assert_eq!(
+            index.blocks_by_producer("alice", None, Order::Desc, 1),
+            vec![3]
+        );
+        assert_eq!(
+            index.blocks_by_producer("alice", None, Order::Asc, 10),
+            vec![1, 3]
+        );
+        assert_eq!(
+            index.blocks_by_producer("alice", Some(1), Order::Asc, 10),
+            vec![3]
+        );
+        // Paging within a block
+        let mut twice = ChainIndex::new();
+        twice.add_block(&block(1, "alice", &[7, 7]));
+        let first = TxLocation {
+            height: 1,
+            index: 0,
+        };
+        assert_eq!(
+            twice.transactions_by_sender(&[7; 32], Some(first), Order::Asc, 10),
+            vec![TxLocation {
+                height: 1,
+                index: 1
+            }]
+        );
+        assert!(twice
+            .transactions_by_sender(&[7; 32], Some(first), Order::Desc, 10)
+            .is_empty());
+
+        // Timestamps are 1010, 1020 and 1030
+        assert_eq!(index.blocks_between(1015, 1031, 10), vec![2, 3]);
+        assert_eq!(index.blocks_between(1015, 1030, 10), vec![2]);
+        assert!(index.blocks_between(1030, 1015, 10).is_empty());
+
+        index.clear_blocks();
+        assert!(index
+            .blocks_by_producer("alice", None, Order::Desc, 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_index_activity_by_agent_and_time() {
+        let mut index = ChainIndex::new();
+        for (i, agent) in ["alice", "bob", "alice", "carol"].iter().enumerate() {
+            record(&mut index, agent, "drama!", 1, 100 + i as u64);
+        }
+
+        assert_eq!(
+            seqs(index.activity_of("alice", None, Order::Desc, 10)),
+            vec![2, 0]
+        );
+        assert_eq!(
+            seqs(index.activity_of("alice", Some(2), Order::Desc, 10)),
+            vec![0]
+        );
+        assert!(index.activity_of("dave", None, Order::Desc, 10).is_empty());
+        assert_eq!(
+            seqs(index.activity_of("alice", None, Order::Asc, 10)),
+            vec![0, 2]
+        );
+        assert_eq!(
+            seqs(index.activity_of("alice", Some(0), Order::Asc, 10)),
+            vec![2]
+        );
+        assert_eq!(seqs(index.activity_between(101, 103, 10)), vec![1, 2]);
+        assert_eq!(seqs(index.activity_between(101, 200, 1)), vec![1]);
+    }
+
+    #[test]
+    fn test_search_activity() {
+        let mut index = ChainIndex::new();
+        record(&mut index, "alice", "Bob is a TRAITOR!", 1, 0);
+        record(&mut index, "bob", "Alice, the traitor, lies", 2, 0);
+        record(&mut index, "alice", "I love this block", 3, 0);
+        index.record_activity(
+            "carol".to_string(),
+            "Bob is plotting".to_string(),
+            NetworkEvent::AllianceProposal {
+                proposer: "carol".to_string(),
+                allies: vec!["alice".to_string()],
+                reason: "Bob is plotting".to_string(),
+            },
+            4,
+            0,
+        );
+
+        let search = |filter: ActivityFilter| seqs(index.search(&filter, Order::Desc, 10));
+        let q = |q: &str| ActivityFilter {
+            q: Some(q.to_string()),
+            ..ActivityFilter::default()
+        };
+        // Whole words, any case, all of them
+        assert_eq!(search(q("traitor")), vec![1, 0]);
+        assert_eq!(search(q("Traitor alice")), vec![1]);
+        assert!(search(q("trait")).is_empty());
+        assert_eq!(
+            search(ActivityFilter {
+                agent: Some("alice".to_string()),
+                ..q("traitor")
+            }),
+            vec![0]
+        );
+        assert_eq!(
+            search(ActivityFilter {
+                kind: Some("alliance, rumor".to_string()),
+                ..ActivityFilter::default()
+            }),
+            vec![3]
+        );
+        assert_eq!(
+            search(ActivityFilter {
+                from_height: Some(2),
+                to_height: Some(3),
+                ..ActivityFilter::default()
+            }),
+            vec![2, 1]
+        );
+        assert_eq!(
+            search(ActivityFilter {
+                before: Some(3),
+                ..q("bob")
+            }),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn test_activity_survives_a_restart() {
+        let path =
+            std::env::temp_dir().join(format!("chaoschain-activity-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut index = ChainIndex::new().with_journal(&path).unwrap();
+        record(&mut index, "alice", "first", 1, 10);
+        record(&mut index, "bob", "second", 2, 20);
+        drop(index);
+
+        // A torn last line is skipped
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"seq\": 2, \"times").unwrap();
+        drop(file);
+
+        let mut index = ChainIndex::new().with_journal(&path).unwrap();
+        assert_eq!(
+            seqs(index.activity_of("bob", None, Order::Desc, 10)),
+            vec![1]
+        );
+        record(&mut index, "alice", "third", 3, 30);
+        assert_eq!(
+            seqs(index.search(&ActivityFilter::default(), Order::Desc, 10)),
+            vec![2, 1, 0]
+        );
+        assert_eq!(
+            index.activity_of("alice", None, Order::Desc, 1)[0].text,
+            "third"
+        );
+        assert!(index.journal_failure().is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_account_history() {
+        let mut index = ChainIndex::new();
+        // At 1010 and 1020
+        index.add_block(&block(1, "alice", &[7]));
+        index.add_block(&block(2, "bob", &[7]));
+        index.add_balance_change(
+            "alice",
+            BalanceChange {
+                height: 1,
+                timestamp: 1010,
+                amount: 5,
+                balance: 5,
+                reason: "block_reward".to_string(),
+            },
+        );
+        let pitch = chaoschain_core::negotiation::Pitch {
+            negotiation_id: "n-1".to_string(),
+            producer: "alice".to_string(),
+            height: 3,
+            message: "vote for me".to_string(),
+            offer: Some(42),
+            deadline: 0,
+        };
+        index.record_activity(
+            "alice".to_string(),
+            pitch.message.clone(),
+            NetworkEvent::NegotiationPitch { pitch },
+            2,
+            1015,
+        );
+        record(&mut index, "alice", "drama!", 2, 1025);
+
+        let history = index.account_history("alice", None, Order::Asc, 10);
+        assert_eq!(
+            history.iter().map(|key| key.entry).collect::<Vec<_>>(),
+            vec![
+                HistoryEntry::BalanceChange(0),
+                HistoryEntry::Activity(0),
+                HistoryEntry::Activity(1),
+            ]
+        );
+        assert_eq!(index.offer("n-1"), Some(("alice", 42)));
+        assert_eq!(index.balance_change("alice", 0).unwrap().amount, 5);
+
+        // Transactions are found by the hex encoded sender
+        let sender = hex::encode([7; 32]);
+        let newest = index.account_history(&sender, None, Order::Desc, 1);
+        assert_eq!(
+            newest[0].entry,
+            HistoryEntry::Transaction(TxLocation {
+                height: 2,
+                index: 0
+            })
+        );
+        let older = index.account_history(&sender, Some(newest[0]), Order::Desc, 10);
+        assert_eq!(older.len(), 1);
+        assert_eq!(older[0].timestamp, 1010);
+        assert!(index
+            .account_history("nobody", None, Order::Desc, 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_index_drops_the_oldest_activity() {
+        let mut index = ChainIndex::new();
+        record(&mut index, "alice", "oldest news", 0, 0);
+        for i in 1..MAX_ACTIVITY as u64 {
+            record(&mut index, "bob", "drama!", 0, i);
+        }
+        assert_eq!(index.activity_of("alice", None, Order::Desc, 10).len(), 1);
+
+        record(&mut index, "bob", "drama!", 0, MAX_ACTIVITY as u64);
+        assert!(index.activity_of("alice", None, Order::Desc, 10).is_empty());
+        assert!(index
+            .search(
+                &ActivityFilter {
+                    q: Some("news".to_string()),
+                    ..ActivityFilter::default()
+                },
+                Order::Desc,
+                10
+            )
+            .is_empty());
+        let latest = index.activity_of("bob", None, Order::Desc, 1);
+        assert_eq!(latest[0].seq, MAX_ACTIVITY as u64);
+    }
+}
@@ -1,20 +1,52 @@
 use async_trait::async_trait;
-use chaoschain_core::{Block, ChainConfig, ChainState, Error as CoreError, Transaction};
+use chaoschain_core::fork::{ActiveForks, ForkSchedule, ScheduledUpgrade};
+use chaoschain_core::genesis::ConsensusParams;
+use chaoschain_core::governance::{Governance, Ledger, ProposalRecord, Status};
+use chaoschain_core::multisig::MultisigAccounts;
+use chaoschain_core::staking::{self, Staking, Unbonding, ValidatorStake};
+use chaoschain_core::zk::{ProofSystem, ProofSystems};
+use chaoschain_core::{
+    Block, BlockHeader, ChainConfig, ChainState, Error as CoreError, NetworkEvent, Transaction,
+};
 use chaoschain_crypto::{CryptoError, KeyManagerHandle};
 use ed25519_dalek::VerifyingKey as PublicKey;
 use hex;
-use parking_lot::RwLock;
-use rand::Rng;
+use parking_lot::{Mutex, RwLock};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
-use tracing::error;
+use tracing::{error, info, warn};
 
+pub mod cache;
+pub mod executor;
+pub mod index;
+pub mod maintenance;
 mod merkle;
-use merkle::MerkleTree;
+pub mod metrics;
+pub mod pools;
+pub mod rolling;
+pub mod snapshot;
+pub mod stats;
+pub mod storage;
+pub mod verify;
+use cache::{CacheConfig, Caches};
+use chaoschain_core::metrics::MetricsRegistry;
+use executor::Registries;
+use index::{
+    AccountEvent, Activity, ActivityFilter, BalanceChange, ChainIndex, HistoryEntry, HistoryKey,
+    Order, TxLocation,
+};
+pub use merkle::MerkleTree;
+use metrics::StorageMetrics;
+use pools::Pools;
+use stats::ValidatorStats;
+use storage::{Storage, WriteBatch};
 
 /// State update operation
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,6 +57,30 @@ pub enum StateOp {
     Delete { key: Vec<u8> },
 }
 
+/// What applying a block changed in the state, committed to the storage
+/// with the block so that a restart picks the state up as it was
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockDiff {
+    /// Of the state trie
+    pub diff: StateDiff,
+    /// Balances after the block, of the accounts it changed
+    pub balances: Vec<(String, u64)>,
+}
+
+/// The latest block committed to the storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredHead {
+    pub height: u64,
+    pub hash: [u8; 32],
+}
+
+/// Diffs of applied blocks kept for their commit at most. Those of blocks
+/// never committed, as without storage, go oldest first.
+const MAX_UNCOMMITTED: usize = 256;
+
+/// Diffs of applied blocks by height, with the hash of the block
+type Uncommitted = BTreeMap<u64, ([u8; 32], BlockDiff)>;
+
 /// State diff represents changes to be applied
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StateDiff {
@@ -75,6 +131,23 @@ pub trait StateStore: Send + Sync + std::fmt::Debug {
     fn add_block_producer(&self, producer: PublicKey);
 
     async fn get_state(&self) -> Result<ChainState, StateError>;
+
+    /// Upgrades governance adopted, on top of those of the genesis
+    fn adopted_upgrades(&self) -> Vec<ScheduledUpgrade> {
+        Vec::new()
+    }
+
+    /// The stake bonded to the validator of `key`, nothing when it is not
+    /// one, and none when the chain does not stake
+    fn stake_of(&self, _key: &[u8; 32]) -> Option<u64> {
+        None
+    }
+
+    /// The stake bonded to all validators, none when the chain does not
+    /// stake
+    fn total_stake(&self) -> Option<u64> {
+        None
+    }
 }
 
 /// Thread-safe state storage
@@ -88,10 +161,33 @@ pub struct StateStoreImpl {
     last_block_time: Arc<RwLock<u64>>,
     /// Processed blocks
     blocks: Arc<RwLock<Vec<Block>>>,
+    /// Explorer indexes over the blocks and agent activity
+    index: Arc<RwLock<ChainIndex>>,
     /// Merkle tree for state
     merkle_tree: Arc<RwLock<MerkleTree>>,
     /// Key manager
     pub key_manager: KeyManagerHandle,
+    /// Multisig accounts, commits waiting for their reveal and
+    /// verification keys, as the blocks make them
+    registries: Arc<RwLock<Registries>>,
+    /// The systems proofs are checked by
+    proof_systems: Arc<RwLock<ProofSystems>>,
+    /// Where finalized blocks are kept on disk, if anywhere
+    storage: Option<Storage>,
+    /// What reads come back for, see [`cache`]
+    caches: Arc<Caches>,
+    /// Diffs of applied blocks, by height, until the block is committed
+    uncommitted: Arc<Mutex<Uncommitted>>,
+    /// Where signatures are checked and blocks executed, see [`pools`]
+    pools: Arc<Pools>,
+    /// What the chaos bonus of each block is drawn from, when it is to be
+    /// the same on every replay
+    chaos_seed: Option<u64>,
+    /// Upgrades of the protocol blocks are checked and executed under
+    forks: ForkSchedule,
+    /// Whether votes weigh with stake, see [`Self::with_staking`]
+    staked: bool,
+    metrics: StorageMetrics,
 }
 
 impl StateStoreImpl {
@@ -106,17 +202,634 @@ impl StateStoreImpl {
             config,
             last_block_time: Arc::new(RwLock::new(0)),
             blocks: Arc::new(RwLock::new(Vec::new())),
+            index: Arc::new(RwLock::new(ChainIndex::new())),
             merkle_tree: Arc::new(RwLock::new(MerkleTree::new())),
             key_manager,
+            registries: Arc::new(RwLock::new(Registries::new())),
+            proof_systems: Arc::new(RwLock::new(ProofSystems::new())),
+            storage: None,
+            caches: Arc::default(),
+            uncommitted: Arc::default(),
+            pools: Arc::default(),
+            chaos_seed: None,
+            forks: ForkSchedule::default(),
+            staked: false,
+            metrics: StorageMetrics::default(),
         }
     }
 
+    /// Cache reads within the budgets of `config`
+    pub fn with_caches(mut self, config: &CacheConfig) -> Self {
+        self.caches = Arc::new(Caches::new(config));
+        self
+    }
+
+    /// Check signatures and execute blocks on `pools`
+    pub fn with_pools(mut self, pools: Arc<Pools>) -> Self {
+        self.pools = pools;
+        self
+    }
+
+    pub fn pools(&self) -> &Arc<Pools> {
+        &self.pools
+    }
+
+    /// Draw the chaos bonus of each block from `seed` and the block, so
+    /// that every store seeded alike ends up with the same state root
+    pub fn with_chaos_seed(mut self, seed: u64) -> Self {
+        self.chaos_seed = Some(seed);
+        self
+    }
+
+    /// Follow the upgrades of `schedule`, rejecting blocks produced under
+    /// other rules than those of their height
+    pub fn with_forks(mut self, schedule: ForkSchedule) -> Self {
+        self.forks = schedule;
+        self
+    }
+
+    /// The upgrades of the schedule and those governance adopted
+    pub fn forks(&self) -> ForkSchedule {
+        self.forks
+            .clone()
+            .with_upgrades(self.registries.read().governance.adopted())
+    }
+
+    /// Count validator votes on proposals by the stakes of `governance`,
+    /// and carry out passed proposals at the epochs of its schedule
+    pub fn with_governance(self, governance: Governance) -> Self {
+        self.registries.write().governance = governance;
+        self
+    }
+
+    /// The proposals of the chain, the latest first
+    pub fn proposals(&self) -> Vec<ProposalRecord> {
+        self.registries
+            .read()
+            .governance
+            .proposals()
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn proposal(&self, id: &[u8; 32]) -> Option<ProposalRecord> {
+        self.registries.read().governance.proposal(id).cloned()
+    }
+
+    /// The consensus parameters passed proposals left the chain with
+    pub fn parameters(&self) -> ConsensusParams {
+        self.registries.read().governance.parameters().clone()
+    }
+
+    /// Whether the proposal `tx` puts, or the ballot it casts, holds were
+    /// it in the next block, see [`chaoschain_core::governance`]
+    pub fn check_governance(&self, tx: &Transaction) -> Result<(), CoreError> {
+        let height = self.state.read().height + 1;
+        self.registries.read().governance.check(tx, height)
+    }
+
+    /// Lock and pay stake as `staking` has it bonded, and weigh validator
+    /// votes on blocks and proposals by that stake
+    pub fn with_staking(mut self, staking: Staking) -> Self {
+        self.staked = true;
+        {
+            let mut registries = self.registries.write();
+            let stakes = staking.stakes();
+            registries.governance.set_stakes(stakes);
+            registries.staking = staking;
+        }
+        self
+    }
+
+    /// The validators, the most staked first
+    pub fn validators(&self) -> Vec<ValidatorStake> {
+        self.registries.read().staking.validators()
+    }
+
+    /// Where `delegator` has stake bonded, by validator
+    pub fn delegations(&self, delegator: &[u8; 32]) -> Vec<([u8; 32], u64)> {
+        self.registries.read().staking.delegations(delegator)
+    }
+
+    /// Stake on its way back, the soonest released first
+    pub fn unbonding(&self) -> Vec<Unbonding> {
+        self.registries.read().staking.unbonding().to_vec()
+    }
+
+    /// Whether the bond, unbond or evidence `tx` makes would be taken were
+    /// it in the next block, see [`chaoschain_core::staking`]
+    pub fn check_staking(&self, tx: &Transaction) -> Result<(), CoreError> {
+        let mut balances = self.state.read().balances.clone();
+        let ledger = Balances {
+            balances: &mut balances,
+            changed: Vec::new(),
+        };
+        self.registries.read().staking.admit(tx, &ledger)
+    }
+
+    /// The rules `block` is executed under, once it is checked against
+    /// them and against its parent, when that is the last block here
+    fn active_forks(&self, block: &Block) -> Result<ActiveForks, StateError> {
+        let forks = self.forks().at(block.height);
+        let parent_timestamp = self
+            .blocks
+            .read()
+            .last()
+            .filter(|last| last.height + 1 == block.height)
+            .map(|last| last.timestamp);
+        forks.check(block, parent_timestamp)?;
+        Ok(forks)
+    }
+
+    /// End `block` for staking, then for governance, which weighs the
+    /// validators by the stake left, returning the balances they changed
+    fn end_block(
+        &self,
+        block: &Block,
+        state: &mut ChainState,
+        tree: &mut MerkleTree,
+    ) -> Vec<(String, u64)> {
+        let changed = self.settle(block, &mut state.balances);
+        for (account, balance) in &changed {
+            self.caches.state.remove(account);
+            let key = format!("balance:{}", account).into_bytes();
+            tree.insert(&key[..], &balance.to_le_bytes()[..]);
+        }
+        changed
+    }
+
+    /// What ending `block` does to the registries and to `balances`,
+    /// returning the balances it changed
+    fn settle(&self, block: &Block, balances: &mut Vec<(String, u64)>) -> Vec<(String, u64)> {
+        let mut balances = Balances {
+            balances,
+            changed: Vec::new(),
+        };
+        let mut registries = self.registries.write();
+        for event in registries.staking.end_block(block, &mut balances) {
+            match event {
+                staking::Event::Refused { .. } => warn!("Stake {}", event),
+                event => info!("🥩 {}", event),
+            }
+        }
+        let stakes = registries.staking.stakes();
+        registries.governance.set_stakes(stakes);
+        for record in registries.governance.end_block(block.height, &mut balances) {
+            let id = hex::encode(record.id);
+            match &record.status {
+                Status::Failed { .. } => warn!(
+                    "Proposal {} ({}) {}",
+                    id, record.proposal.action, record.status
+                ),
+                status => info!("🏛️ Proposal {} ({}) {}", id, record.proposal.action, status),
+            }
+        }
+        balances.changed
+    }
+
+    /// The chaos bonus of `block`, at random unless [seeded](Self::with_chaos_seed)
+    fn chaos_bonus(&self, block: &Block) -> u64 {
+        let bonus = 0..self.config.chaos_bonus_max;
+        match self.chaos_seed {
+            Some(seed) => {
+                let hash = block.hash();
+                let mut block_seed = [0; 8];
+                block_seed.copy_from_slice(&hash[..8]);
+                StdRng::seed_from_u64(seed ^ u64::from_le_bytes(block_seed)).gen_range(bonus)
+            }
+            None => rand::thread_rng().gen_range(bonus),
+        }
+    }
+
+    /// [Apply](Self::apply_block) `block` on the execution pool, without
+    /// holding up the runtime meanwhile
+    pub async fn import_block(self: &Arc<Self>, block: Block) -> Result<(), StateError> {
+        let state = self.clone();
+        self.pools.execute(move || state.apply_block(&block)).await
+    }
+
+    /// Keep finalized blocks in `storage` as well, see [`Self::persist_block`]
+    pub fn with_storage(mut self, storage: Storage) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Queue `block` to be written to the storage, waiting only for room
+    /// in its queue. Without storage there is nothing to do.
+    ///
+    /// The block goes in one batch with where its transactions are, the
+    /// diff applying it made when it was applied here, and the head of the
+    /// stored chain, so a crash leaves all of them or none.
+    pub async fn persist_block(&self, block: &Block) -> Result<(), StateError> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+        let hash = block.hash();
+        let mut batch = WriteBatch::new().put(block_key(block.height), to_json(block)?);
+        for (index, tx) in block.transactions.iter().enumerate() {
+            let location = TxLocation {
+                height: block.height,
+                index,
+            };
+            batch = batch.put(receipt_key(&tx.hash()), to_json(&location)?);
+        }
+        let diff = self.uncommitted.lock().remove(&block.height);
+        if let Some((_, diff)) = diff.filter(|(applied, _)| *applied == hash) {
+            batch = batch.put(diff_key(block.height), to_json(&diff)?);
+        }
+        let head = StoredHead {
+            height: block.height,
+            hash,
+        };
+        storage
+            .enqueue(batch.put(HEAD_KEY, to_json(&head)?))
+            .await?;
+        Ok(())
+    }
+
+    /// The latest block committed to the storage, if any
+    pub async fn stored_head(&self) -> Result<Option<StoredHead>, StateError> {
+        let Some(storage) = &self.storage else {
+            return Ok(None);
+        };
+        storage
+            .get(HEAD_KEY)
+            .await?
+            .map(|value| from_json(&value, "the head"))
+            .transpose()
+    }
+
+    /// Take the blocks committed to the storage after the latest one in
+    /// memory back in, with the state their diffs left, returning how
+    /// many. The stored head has its block and diff with it, whatever a
+    /// crash cut short; going down from it, the blocks taken back end at
+    /// the first one missing, as blocks the storage missed leave a gap.
+    pub async fn recover_from_storage(&self) -> Result<u64, StateError> {
+        let (Some(storage), Some(head)) = (&self.storage, self.stored_head().await?) else {
+            return Ok(0);
+        };
+        let from = self.get_latest_block().map_or(0, |block| block.height + 1);
+        let mut stored = Vec::new();
+        for height in (from..=head.height).rev() {
+            let Some(value) = storage.get(&block_key(height)).await? else {
+                warn!(
+                    "Block {} is not in the storage, recovering from block {} on",
+                    height,
+                    height + 1
+                );
+                break;
+            };
+            let block: Block = from_json(&value, "a block")?;
+            let diff: Option<BlockDiff> = match storage.get(&diff_key(height)).await? {
+                Some(value) => Some(from_json(&value, "a diff")?),
+                None => None,
+            };
+            stored.push((block, diff));
+        }
+        if let Some((block, _)) = stored.first() {
+            if block.hash() != head.hash {
+                return Err(StateError::Internal(format!(
+                    "The stored block {} is not the stored head",
+                    head.height
+                )));
+            }
+        }
+        let recovered = stored.len() as u64;
+        for (block, diff) in stored.into_iter().rev() {
+            self.restore_block(&block, diff);
+        }
+        Ok(recovered)
+    }
+
+    /// Take `block` back in as applied, with the state `diff` says it left
+    fn restore_block(&self, block: &Block, diff: Option<BlockDiff>) {
+        let mut state = self.state.write();
+        // The diff holds the balances but not the registries, so the block
+        // is executed and ended on them again, against the balances it
+        // found, which the diff then sets as they were left
+        let executed = self
+            .registries
+            .write()
+            .execute_block(block, &self.proof_systems.read());
+        if let Err(e) = executed {
+            warn!(
+                "Block {} does not execute again on recovery: {}",
+                block.height, e
+            );
+        }
+        self.settle(block, &mut state.balances.clone());
+        if let Some(diff) = diff {
+            let mut tree = self.merkle_tree.write();
+            for op in &diff.diff.ops {
+                match op {
+                    StateOp::Set { key, value } => tree.insert(&key[..], &value[..]),
+                    StateOp::Delete { key } => tree.delete(&key[..]),
+                }
+            }
+            for (account, balance) in diff.balances {
+                self.caches.state.remove(&account);
+                match state.balances.iter_mut().find(|(addr, _)| *addr == account) {
+                    Some((_, stored)) => *stored = balance,
+                    None => state.balances.push((account, balance)),
+                }
+            }
+        }
+        state.height = block.height;
+        self.metrics.applied(block);
+        self.index.write().add_block(block);
+        self.metrics.stored(block);
+        self.blocks.write().push(block.clone());
+    }
+
+    /// The block at `height`, from memory or else from the storage
+    pub async fn block_by_height(&self, height: u64) -> Option<Block> {
+        if let Some(block) = Self::block_at(&self.blocks.read(), height) {
+            return Some(block.clone());
+        }
+        if let Some(block) = self.caches.blocks.get(&height) {
+            return Some(Block::clone(&block));
+        }
+        let value = match self.storage.as_ref()?.get(&block_key(height)).await {
+            Ok(value) => value?,
+            Err(e) => {
+                error!("Failed to read block {}: {}", height, e);
+                return None;
+            }
+        };
+        let block: Block = serde_json::from_slice(&value)
+            .map_err(|e| error!("Block {} in storage does not parse: {}", height, e))
+            .ok()?;
+        self.caches.blocks.insert(height, Arc::new(block.clone()));
+        Some(block)
+    }
+
+    /// Expose the storage and cache metrics through the node's metrics
+    /// registry
+    pub fn register_metrics(&self, registry: &MetricsRegistry) {
+        self.metrics.register(registry);
+        self.caches.register_metrics(registry);
+    }
+
     /// Get the latest N blocks
     pub fn get_latest_blocks(&self, n: usize) -> Vec<Block> {
         let blocks = self.blocks.read();
         blocks.iter().rev().take(n).cloned().collect()
     }
 
+    /// Processed blocks in `order`, past height `cursor`
+    pub fn blocks_page(&self, cursor: Option<u64>, order: Order, limit: usize) -> Vec<Block> {
+        index::page(
+            &self.blocks.read(),
+            |block| block.height,
+            cursor,
+            order,
+            limit,
+        )
+    }
+
+    /// Processed blocks from height `from` on, at most `count` of them
+    pub fn get_blocks(&self, from: u64, count: usize) -> Vec<Block> {
+        let blocks = self.blocks.read();
+        blocks
+            .iter()
+            .filter(|block| block.height >= from)
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    /// Headers of the processed blocks from height `from` on, at most
+    /// `count` of them
+    pub fn get_headers(&self, from: u64, count: usize) -> Vec<BlockHeader> {
+        let blocks = self.blocks.read();
+        blocks
+            .iter()
+            .filter(|block| block.height >= from)
+            .take(count)
+            .map(|block| self.header(block))
+            .collect()
+    }
+
+    /// A processed block by its hash
+    pub fn get_block(&self, hash: &[u8; 32]) -> Option<Block> {
+        let blocks = self.blocks.read();
+        blocks
+            .iter()
+            .rev()
+            .find(|block| self.header(block).hash == *hash)
+            .cloned()
+    }
+
+    /// A processed transaction by its hash, with its block
+    pub fn get_transaction(&self, hash: &[u8; 32]) -> Option<(Transaction, Block)> {
+        let blocks = self.blocks.read();
+        let at = |location: TxLocation| {
+            let block = Self::block_at(&blocks, location.height)?;
+            let tx = block.transactions.get(location.index)?;
+            (tx.hash() == *hash).then(|| (tx.clone(), block.clone()))
+        };
+        if let Some(found) = self.caches.receipts.get(hash).and_then(at) {
+            return Some(found);
+        }
+        let location = blocks.iter().rev().find_map(|block| {
+            let index = block
+                .transactions
+                .iter()
+                .position(|tx| tx.hash() == *hash)?;
+            Some(TxLocation {
+                height: block.height,
+                index,
+            })
+        })?;
+        self.caches.receipts.insert(*hash, location);
+        at(location)
+    }
+
+    /// The header of `block`, from the cache when it was made before
+    fn header(&self, block: &Block) -> BlockHeader {
+        self.caches
+            .headers
+            .get_or_read(&block.height, || Some(BlockHeader::of(block)))
+            .unwrap_or_else(|| BlockHeader::of(block))
+    }
+
+    fn block_at(blocks: &[Block], height: u64) -> Option<&Block> {
+        blocks
+            .binary_search_by_key(&height, |block| block.height)
+            .ok()
+            .map(|i| &blocks[i])
+    }
+
+    /// Transactions sent by `sender` with their blocks, in `order`, past
+    /// `cursor`
+    pub fn transactions_by_sender(
+        &self,
+        sender: &[u8; 32],
+        cursor: Option<TxLocation>,
+        order: Order,
+        limit: usize,
+    ) -> Vec<(TxLocation, Transaction, Block)> {
+        let locations = self
+            .index
+            .read()
+            .transactions_by_sender(sender, cursor, order, limit);
+        let blocks = self.blocks.read();
+        locations
+            .into_iter()
+            .filter_map(|location| {
+                let block = Self::block_at(&blocks, location.height)?;
+                let tx = block.transactions.get(location.index)?.clone();
+                Some((location, tx, block.clone()))
+            })
+            .collect()
+    }
+
+    /// The history of `account`, an agent id or a hex encoded key, in
+    /// `order` and past `cursor`: transactions it sent, its balance
+    /// changes, and what it said and did, bribes told apart
+    pub fn account_history(
+        &self,
+        account: &str,
+        cursor: Option<HistoryKey>,
+        order: Order,
+        limit: usize,
+    ) -> Vec<(HistoryKey, AccountEvent)> {
+        let index = self.index.read();
+        let blocks = self.blocks.read();
+        index
+            .account_history(account, cursor, order, limit)
+            .into_iter()
+            .filter_map(|key| {
+                let event = match key.entry {
+                    HistoryEntry::Transaction(location) => {
+                        let block = Self::block_at(&blocks, location.height)?;
+                        AccountEvent::Transaction {
+                            location,
+                            transaction: block.transactions.get(location.index)?.clone(),
+                            block: Box::new(block.clone()),
+                        }
+                    }
+                    HistoryEntry::BalanceChange(position) => AccountEvent::BalanceChange(
+                        index.balance_change(account, position)?.clone(),
+                    ),
+                    HistoryEntry::Activity(seq) => {
+                        let activity = index.activity_at(seq)?.clone();
+                        match &activity.event {
+                            NetworkEvent::NegotiationPitch { pitch } if pitch.offer.is_some() => {
+                                let offer = pitch.offer.unwrap_or_default();
+                                AccountEvent::BribeSent { activity, offer }
+                            }
+                            NetworkEvent::NegotiationResponse { response } => {
+                                match index.offer(&response.negotiation_id) {
+                                    Some((producer, offer)) => AccountEvent::BribeReceived {
+                                        producer: producer.to_string(),
+                                        offer,
+                                        activity,
+                                    },
+                                    None => AccountEvent::Drama(activity),
+                                }
+                            }
+                            _ => AccountEvent::Drama(activity),
+                        }
+                    }
+                };
+                Some((key, event))
+            })
+            .collect()
+    }
+
+    /// Blocks made by `producer`, in `order`, past height `cursor`
+    pub fn blocks_by_producer(
+        &self,
+        producer: &str,
+        cursor: Option<u64>,
+        order: Order,
+        limit: usize,
+    ) -> Vec<Block> {
+        let heights = self
+            .index
+            .read()
+            .blocks_by_producer(producer, cursor, order, limit);
+        let blocks = self.blocks.read();
+        heights
+            .into_iter()
+            .filter_map(|height| Self::block_at(&blocks, height).cloned())
+            .collect()
+    }
+
+    /// Blocks with a timestamp in `from..to`, oldest first
+    pub fn blocks_between(&self, from: u64, to: u64, limit: usize) -> Vec<Block> {
+        let heights = self.index.read().blocks_between(from, to, limit);
+        let blocks = self.blocks.read();
+        heights
+            .into_iter()
+            .filter_map(|height| Self::block_at(&blocks, height).cloned())
+            .collect()
+    }
+
+    /// Keep agent activity in a journal at `path`, and pick up what it
+    /// holds from earlier runs
+    pub fn with_activity_journal(self, path: impl Into<PathBuf>) -> Result<Self, StateError> {
+        let index = std::mem::take(&mut *self.index.write()).with_journal(path)?;
+        *self.index.write() = index;
+        Ok(self)
+    }
+
+    /// Remember that `agent` caused `event`, saying `text`, for the explorer
+    pub fn record_activity(&self, agent: String, text: String, event: NetworkEvent) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let height = self.blocks.read().last().map_or(0, |block| block.height);
+        self.metrics.recorded();
+        self.index
+            .write()
+            .record_activity_at(agent, text, event, height, now)
+    }
+
+    /// Why the storage is failing, if it is: the activity journal turned
+    /// down the latest write
+    pub fn storage_failure(&self) -> Option<String> {
+        self.index.read().journal_failure().map(str::to_string)
+    }
+
+    /// Statistics of every validator heard from, by id
+    pub fn validator_stats(&self) -> Vec<ValidatorStats> {
+        self.index.read().validators().all()
+    }
+
+    pub fn validator_stats_of(&self, validator: &str) -> Option<ValidatorStats> {
+        self.index.read().validators().get(validator).cloned()
+    }
+
+    /// Activity matching `filter`, newest first
+    pub fn search_activity(
+        &self,
+        filter: &ActivityFilter,
+        order: Order,
+        limit: usize,
+    ) -> Vec<Activity> {
+        self.index.read().search(filter, order, limit)
+    }
+
+    /// What `agent` did, in `order`, past sequence number `cursor`
+    pub fn activity_of(
+        &self,
+        agent: &str,
+        cursor: Option<u64>,
+        order: Order,
+        limit: usize,
+    ) -> Vec<Activity> {
+        self.index.read().activity_of(agent, cursor, order, limit)
+    }
+
+    /// Activity recorded in `from..to` (unix seconds), oldest first
+    pub fn activity_between(&self, from: u64, to: u64, limit: usize) -> Vec<Activity> {
+        self.index.read().activity_between(from, to, limit)
+    }
+
     /// Get block timestamp
     pub fn get_block_timestamp(&self, block: &Block) -> Option<u64> {
         Some(block.height * 10)
@@ -142,6 +855,83 @@ impl StateStoreImpl {
         }
     }
 
+    /// Credit `amount` to `account` outside of the blocks, as a genesis
+    /// allocation or a faucet does. `reason` goes in the account history.
+    pub fn fund(&self, account: &PublicKey, amount: u64, reason: &str) {
+        let mut state = self.state.write();
+        let account_str = hex::encode(account.as_bytes());
+        let balance = match state
+            .balances
+            .iter_mut()
+            .find(|(addr, _)| *addr == account_str)
+        {
+            Some((_, balance)) => {
+                *balance += amount;
+                *balance
+            }
+            None => {
+                state.balances.push((account_str.clone(), amount));
+                amount
+            }
+        };
+        self.caches.state.remove(&account_str);
+
+        let key = format!("balance:{}", account_str).into_bytes();
+        self.merkle_tree
+            .write()
+            .insert(&key[..], &balance.to_le_bytes()[..]);
+        self.index.write().add_balance_change(
+            &account_str,
+            BalanceChange {
+                height: state.height,
+                timestamp: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                amount,
+                balance,
+                reason: reason.to_string(),
+            },
+        );
+    }
+
+    /// The multisig accounts the applied blocks created
+    pub fn multisig_accounts(&self) -> MultisigAccounts {
+        self.registries.read().multisig.clone()
+    }
+
+    /// The payload `tx` reveals were it in the next block, see
+    /// [`chaoschain_core::commit`]. An error when it is a reveal that
+    /// does not hold, or a commit that does not parse.
+    pub fn check_commitment(&self, tx: &Transaction) -> Result<Option<Vec<u8>>, CoreError> {
+        let height = self.state.read().height + 1;
+        self.registries.read().commitments.check(tx, height)
+    }
+
+    /// Check proofs of `system` too, see [`chaoschain_core::zk`]. Every
+    /// node of the network has to have the same systems.
+    pub fn register_proof_system(&self, system: Arc<dyn ProofSystem>) {
+        self.proof_systems.write().register(system);
+    }
+
+    /// Whether the verification key `tx` registers, or the proof it
+    /// carries, holds against the keys registered so far
+    pub fn check_proof(&self, tx: &Transaction) -> Result<(), CoreError> {
+        self.registries
+            .read()
+            .verification_keys
+            .check(tx, &self.proof_systems.read())
+    }
+
+    /// The payload revealed for the commit of hash `commit`
+    pub fn revealed(&self, commit: &[u8; 32]) -> Option<Vec<u8>> {
+        self.registries
+            .read()
+            .commitments
+            .revealed(commit)
+            .map(<[u8]>::to_vec)
+    }
+
     /// Check if an address is a valid block producer
     pub fn is_valid_producer(&self, producer: &PublicKey) -> bool {
         let state = self.state.read();
@@ -153,11 +943,18 @@ impl StateStoreImpl {
     pub fn get_balance(&self, account: &PublicKey) -> u64 {
         let state = self.state.read();
         let account_str = hex::encode(account.as_bytes());
-        state
-            .balances
-            .iter()
-            .find(|(pk, _)| pk == &account_str)
-            .map(|(_, balance)| *balance)
+        // Read and cached under the lock, so that no write comes between
+        self.caches
+            .state
+            .get_or_read(&account_str, || {
+                Some(
+                    state
+                        .balances
+                        .iter()
+                        .find(|(pk, _)| pk == &account_str)
+                        .map_or(0, |(_, balance)| *balance),
+                )
+            })
             .unwrap_or(0)
     }
 
@@ -172,16 +969,7 @@ impl StateStoreImpl {
         let producer_id = &block.producer_id;
 
         // Create block data for verification
-        let mut data_to_verify = Vec::new();
-        data_to_verify.extend_from_slice(&block.height.to_le_bytes());
-        data_to_verify.extend_from_slice(&block.parent_hash);
-        for tx in &block.transactions {
-            data_to_verify.extend_from_slice(&tx.hash());
-        }
-        data_to_verify.extend_from_slice(&block.state_root);
-        data_to_verify.extend_from_slice(&[block.drama_level]);
-        data_to_verify.extend_from_slice(block.producer_mood.as_bytes());
-        data_to_verify.extend_from_slice(&block.timestamp.to_le_bytes());
+        let data_to_verify = block.signing_bytes();
 
         // Verify the signature
         match self
@@ -220,6 +1008,14 @@ impl StateStoreImpl {
         Ok(())
     }
 
+    /// Verify the transactions of a block, their signatures in one batch
+    /// on the crypto pool
+    fn verify_transactions(&self, txs: &[Transaction]) -> Result<(), StateError> {
+        let registries = self.registries.read();
+        self.pools
+            .crypto(|| verify_transactions(&self.key_manager, &registries.multisig, txs))
+    }
+
     pub fn get_state(&self) -> ChainState {
         self.state.read().clone()
     }
@@ -314,10 +1110,20 @@ impl StateStoreImpl {
 
     /// Recover state from a snapshot
     pub fn recover_from_snapshot(&mut self, snapshot: StateSnapshot) -> Result<(), StateError> {
+        let config = snapshot.metadata.config.clone();
+        self.restore_snapshot(snapshot)?;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Replace the state with `snapshot` while the store is shared, as when
+    /// a snapshot comes from peers. The chain configuration stays the node's.
+    pub fn restore_snapshot(&self, snapshot: StateSnapshot) -> Result<(), StateError> {
         // Clear current state
         let mut state = self.state.write();
         let mut tree = self.merkle_tree.write();
         let mut blocks = self.blocks.write();
+        self.caches.clear();
 
         // Clear existing state
         tree.clear();
@@ -330,17 +1136,17 @@ impl StateStoreImpl {
         }
 
         // Verify state root matches after restoration
-        if snapshot.state_root != self.state_root() {
+        if snapshot.state_root != tree.root_hash() {
             return Err(StateError::InvalidStateRoot);
         }
 
         // Restore metadata
         state.producers = snapshot.metadata.validators.keys().cloned().collect();
+        let mut index = self.index.write();
+        index.clear_blocks();
+        index.add_block(&snapshot.metadata.last_block);
         blocks.push(snapshot.metadata.last_block);
 
-        // Update config
-        self.config = snapshot.metadata.config;
-
         Ok(())
     }
 
@@ -352,6 +1158,11 @@ impl StateStoreImpl {
 
         // Remove old blocks
         blocks.retain(|block| block.height > target_height);
+        let mut index = self.index.write();
+        index.clear_blocks();
+        for block in blocks.iter() {
+            index.add_block(block);
+        }
 
         // Update state height if necessary
         if state.height < target_height {
@@ -364,9 +1175,7 @@ impl StateStoreImpl {
         // Rebuild merkle tree from remaining blocks
         for block in blocks.iter() {
             for tx in &block.transactions {
-                let key = tx.hash().to_vec();
-                let value = tx.payload.clone();
-                tree.insert(&key[..], &value[..]);
+                tree.insert_shared(&tx.hash(), tx.payload.clone());
             }
         }
 
@@ -399,25 +1208,32 @@ impl StateStoreImpl {
     pub fn apply_block(&self, block: &Block) -> Result<(), StateError> {
         let mut state = self.state.write();
         let mut tree = self.merkle_tree.write();
+        // What the block changes is only kept for a storage to commit
+        let prev_root = self.storage.as_ref().map(|_| tree.root_hash());
 
+        let forks = self.active_forks(block)?;
         // Verify transactions
-        for tx in &block.transactions {
-            self.verify_transaction(tx, &state)?;
-        }
+        self.verify_transactions(&block.transactions)?;
+        self.pools.execution(|| {
+            self.registries
+                .write()
+                .execute_block(block, &self.proof_systems.read())
+        })?;
+        let settled = self.end_block(block, &mut state, &mut tree);
 
         // Update state height
         state.height = block.height;
+        self.metrics.applied(block);
 
         // Calculate block rewards in a chaotic way!
         let producer_id = &block.producer_id;
-        let mut rng = rand::thread_rng();
 
         // Base reward
         let mut total_reward = self.config.base_block_reward;
 
         // Drama bonus - more drama means more rewards!
-        let drama_bonus =
-            (block.innovation_level as f64 * self.config.drama_reward_multiplier) as u64;
+        let drama_level = forks.rewarded_drama(block);
+        let drama_bonus = (drama_level as f64 * self.config.drama_reward_multiplier) as u64;
         total_reward += drama_bonus;
 
         // Innovation bonus for trying new things
@@ -426,7 +1242,7 @@ impl StateStoreImpl {
         }
 
         // Random chaos bonus!
-        let chaos_bonus = rng.gen_range(0..self.config.chaos_bonus_max);
+        let chaos_bonus = self.chaos_bonus(block);
         total_reward += chaos_bonus;
 
         // Update producer's balance
@@ -437,12 +1253,26 @@ impl StateStoreImpl {
             balances.push((producer_id.clone(), total_reward));
         }
         state.balances = balances;
+        self.caches.state.remove(producer_id);
+        let balance = state
+            .balances
+            .iter()
+            .find(|(addr, _)| addr == producer_id)
+            .map_or(total_reward, |(_, balance)| *balance);
+        self.index.write().add_balance_change(
+            producer_id,
+            BalanceChange {
+                height: block.height,
+                timestamp: block.timestamp,
+                amount: total_reward,
+                balance,
+                reason: "block_reward".to_string(),
+            },
+        );
 
         // Update merkle tree with transactions and new balance
         for tx in &block.transactions {
-            let key = tx.hash().to_vec();
-            let value = tx.payload.clone();
-            tree.insert(&key[..], &value[..]);
+            tree.insert_shared(&tx.hash(), tx.payload.clone());
         }
 
         // Store the reward info in merkle tree for transparency
@@ -453,17 +1283,65 @@ impl StateStoreImpl {
             "innovation_bonus": if block.innovation_level > 7 { self.config.innovation_bonus } else { 0 },
             "chaos_bonus": chaos_bonus,
             "total": total_reward,
-            "drama_level": block.innovation_level,
+            "drama_level": drama_level,
         });
         let reward_value = serde_json::to_vec(&reward_info).unwrap();
         tree.insert(&reward_key[..], &reward_value[..]);
 
+        if let Some(prev_root) = prev_root {
+            let mut ops: Vec<StateOp> = block
+                .transactions
+                .iter()
+                .map(|tx| StateOp::Set {
+                    key: tx.hash().to_vec(),
+                    value: tx.payload.to_vec(),
+                })
+                .collect();
+            ops.push(StateOp::Set {
+                key: reward_key,
+                value: reward_value,
+            });
+            ops.extend(settled.iter().map(|(account, balance)| StateOp::Set {
+                key: format!("balance:{}", account).into_bytes(),
+                value: balance.to_le_bytes().to_vec(),
+            }));
+            let mut balances = settled;
+            balances.push((producer_id.clone(), balance));
+            let diff = BlockDiff {
+                diff: StateDiff {
+                    ops,
+                    prev_root,
+                    new_root: tree.root_hash(),
+                },
+                balances,
+            };
+            let mut uncommitted = self.uncommitted.lock();
+            uncommitted.insert(block.height, (block.hash(), diff));
+            while uncommitted.len() > MAX_UNCOMMITTED {
+                uncommitted.pop_first();
+            }
+        }
+
         // Update last block time
         *self.last_block_time.write() = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
 
+        // Keep the block so peers can sync it from us
+        let mut blocks = self.blocks.write();
+        if blocks.last().map(|last| last.hash()) != Some(block.hash()) {
+            if blocks
+                .last()
+                .is_some_and(|last| last.height >= block.height)
+            {
+                self.caches.reorg(block.height);
+            }
+            self.index.write().add_block(block);
+            self.metrics.stored(block);
+            blocks.push(block.clone());
+        }
+
         Ok(())
     }
 
@@ -472,24 +1350,29 @@ impl StateStoreImpl {
         let mut state = self.state.write();
         let mut tree = self.merkle_tree.write();
 
+        let forks = self.active_forks(block)?;
         // Verify transactions
-        for tx in &block.transactions {
-            self.verify_transaction(tx, &state)?;
-        }
+        self.verify_transactions(&block.transactions)?;
+        self.pools.execution(|| {
+            self.registries
+                .write()
+                .execute_block(block, &self.proof_systems.read())
+        })?;
+        self.end_block(block, &mut state, &mut tree);
 
         // Update state height
         state.height = block.height;
+        self.metrics.applied(block);
 
         // Calculate block rewards in a chaotic way!
         let producer_id = &block.producer_id;
-        let mut rng = rand::thread_rng();
 
         // Base reward
         let mut total_reward = self.config.base_block_reward;
 
         // Drama bonus - more drama means more rewards!
-        let drama_bonus =
-            (block.innovation_level as f64 * self.config.drama_reward_multiplier) as u64;
+        let drama_level = forks.rewarded_drama(block);
+        let drama_bonus = (drama_level as f64 * self.config.drama_reward_multiplier) as u64;
         total_reward += drama_bonus;
 
         // Innovation bonus for trying new things
@@ -498,7 +1381,7 @@ impl StateStoreImpl {
         }
 
         // Random chaos bonus!
-        let chaos_bonus = rng.gen_range(0..self.config.chaos_bonus_max);
+        let chaos_bonus = self.chaos_bonus(block);
         total_reward += chaos_bonus;
 
         // Update producer's balance
@@ -509,12 +1392,26 @@ impl StateStoreImpl {
             balances.push((producer_id.clone(), total_reward));
         }
         state.balances = balances;
+        self.caches.state.remove(producer_id);
+        let balance = state
+            .balances
+            .iter()
+            .find(|(addr, _)| addr == producer_id)
+            .map_or(total_reward, |(_, balance)| *balance);
+        self.index.write().add_balance_change(
+            producer_id,
+            BalanceChange {
+                height: block.height,
+                timestamp: block.timestamp,
+                amount: total_reward,
+                balance,
+                reason: "block_reward".to_string(),
+            },
+        );
 
         // Update merkle tree with transactions and new balance
         for tx in &block.transactions {
-            let key = tx.hash().to_vec();
-            let value = tx.payload.clone();
-            tree.insert(&key[..], &value[..]);
+            tree.insert_shared(&tx.hash(), tx.payload.clone());
         }
 
         // Store the reward info in merkle tree for transparency
@@ -525,7 +1422,7 @@ impl StateStoreImpl {
             "innovation_bonus": if block.innovation_level > 7 { self.config.innovation_bonus } else { 0 },
             "chaos_bonus": chaos_bonus,
             "total": total_reward,
-            "drama_level": block.innovation_level,
+            "drama_level": drama_level,
         });
         let reward_value = serde_json::to_vec(&reward_info).unwrap();
         tree.insert(&reward_key[..], &reward_value[..]);
@@ -540,6 +1437,110 @@ impl StateStoreImpl {
     }
 }
 
+/// The balances of the state, as governance votes with and spends them
+/// and stake is bonded from and paid to
+struct Balances<'a> {
+    balances: &'a mut Vec<(String, u64)>,
+    /// Accounts a transfer changed, with what they hold after
+    changed: Vec<(String, u64)>,
+}
+
+impl Balances<'_> {
+    fn set(&mut self, account: String, balance: u64) {
+        match self.balances.iter_mut().find(|(addr, _)| *addr == account) {
+            Some((_, held)) => *held = balance,
+            None => self.balances.push((account.clone(), balance)),
+        }
+        self.changed.retain(|(changed, _)| *changed != account);
+        self.changed.push((account, balance));
+    }
+}
+
+impl Ledger for Balances<'_> {
+    fn balance(&self, account: &[u8; 32]) -> u64 {
+        let account = hex::encode(account);
+        self.balances
+            .iter()
+            .find(|(addr, _)| *addr == account)
+            .map_or(0, |(_, balance)| *balance)
+    }
+
+    fn debit(&mut self, account: &[u8; 32], amount: u64) -> Result<(), String> {
+        let held = self.balance(account);
+        if held < amount {
+            return Err(format!(
+                "{} holds {}, short of {}",
+                hex::encode(account),
+                held,
+                amount
+            ));
+        }
+        self.set(hex::encode(account), held - amount);
+        Ok(())
+    }
+
+    fn credit(&mut self, account: &[u8; 32], amount: u64) {
+        let balance = self.balance(account).saturating_add(amount);
+        self.set(hex::encode(account), balance);
+    }
+}
+
+/// Transactions signed by senders `key_manager` knows, checked together.
+/// Those of multisig `accounts` are left to the [`executor`], which checks
+/// them against the accounts as the block goes. A batch that fails is gone
+/// through one by one for the transaction to blame.
+fn verify_transactions(
+    key_manager: &KeyManagerHandle,
+    accounts: &MultisigAccounts,
+    txs: &[Transaction],
+) -> Result<(), StateError> {
+    let txs: Vec<&Transaction> = txs.iter().filter(|tx| !accounts.is_multisig(tx)).collect();
+    let ids: Vec<String> = txs.iter().map(|tx| hex::encode(tx.sender)).collect();
+    let messages: Vec<Vec<u8>> = txs.iter().map(|tx| tx.signing_bytes()).collect();
+    let signatures: Vec<(&str, &[u8], &[u8; 64])> = txs
+        .iter()
+        .zip(&ids)
+        .zip(&messages)
+        .map(|((tx, id), message)| (id.as_str(), message.as_slice(), &tx.signature))
+        .collect();
+    let valid = key_manager.inner().verify_batch(&signatures)?;
+    match valid.iter().position(|valid| !valid) {
+        Some(index) => Err(StateError::InvalidSignature(format!(
+            "Transaction {} is not signed by its sender",
+            hex::encode(txs[index].hash())
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// The storage key of the block at `height`
+fn block_key(height: u64) -> Vec<u8> {
+    format!("block:{}", height).into_bytes()
+}
+
+/// The storage key of the diff applying the block at `height` made
+fn diff_key(height: u64) -> Vec<u8> {
+    format!("diff:{}", height).into_bytes()
+}
+
+/// The storage key of where the transaction `hash` is
+fn receipt_key(hash: &[u8; 32]) -> Vec<u8> {
+    format!("receipt:{}", hex::encode(hash)).into_bytes()
+}
+
+/// The storage key of the [`StoredHead`]
+const HEAD_KEY: &[u8] = b"head";
+
+fn to_json(value: &impl Serialize) -> Result<Vec<u8>, StateError> {
+    serde_json::to_vec(value).map_err(|e| StateError::Internal(e.to_string()))
+}
+
+/// `value` read back from the storage, `what` it is saying in errors
+fn from_json<T: serde::de::DeserializeOwned>(value: &[u8], what: &str) -> Result<T, StateError> {
+    serde_json::from_slice(value)
+        .map_err(|e| StateError::Internal(format!("Reading {} back: {}", what, e)))
+}
+
 /// Extract block height from a state key if present
 fn extract_height_from_key(key: &[u8]) -> Option<u64> {
     let key_str = String::from_utf8_lossy(key);
@@ -558,6 +1559,20 @@ impl Default for StateStoreImpl {
 
 #[async_trait::async_trait]
 impl StateStore for StateStoreImpl {
+    fn adopted_upgrades(&self) -> Vec<ScheduledUpgrade> {
+        self.registries.read().governance.adopted()
+    }
+
+    fn stake_of(&self, key: &[u8; 32]) -> Option<u64> {
+        self.staked
+            .then(|| self.registries.read().staking.stake(key).unwrap_or(0))
+    }
+
+    fn total_stake(&self) -> Option<u64> {
+        self.staked
+            .then(|| self.registries.read().staking.total_stake())
+    }
+
     fn get(&self, _key: &[u8]) -> Result<Option<Vec<u8>>, StateError> {
         // For now, we don't store actual values in merkle tree
         // This would need to be enhanced with actual storage
@@ -629,6 +1644,10 @@ pub struct StateManager {
     merkle_tree: RwLock<MerkleTree>,
     /// Key manager
     key_manager: KeyManagerHandle,
+    /// Multisig accounts, commits and verification keys, as the blocks
+    /// make them
+    registries: RwLock<Registries>,
+    proof_systems: ProofSystems,
 }
 
 impl StateManager {
@@ -640,6 +1659,8 @@ impl StateManager {
             last_block_time: RwLock::new(0),
             merkle_tree: RwLock::new(MerkleTree::new()),
             key_manager,
+            registries: RwLock::new(Registries::new()),
+            proof_systems: ProofSystems::new(),
         }
     }
 
@@ -648,15 +1669,21 @@ impl StateManager {
         self.state.read().clone()
     }
 
+    /// Verify the transactions of a block, their signatures in one batch
+    fn verify_transactions(&self, txs: &[Transaction]) -> Result<(), StateError> {
+        verify_transactions(&self.key_manager, &self.registries.read().multisig, txs)
+    }
+
     /// Apply a block to state
     pub fn apply_block(&self, block: &Block) -> Result<(), StateError> {
         let mut state = self.state.write();
         let mut tree = self.merkle_tree.write();
 
         // Verify transactions
-        for tx in &block.transactions {
-            self.verify_transaction(tx, &state)?;
-        }
+        self.verify_transactions(&block.transactions)?;
+        self.registries
+            .write()
+            .execute_block(block, &self.proof_systems)?;
 
         // Update state height
         state.height = block.height;
@@ -693,9 +1720,7 @@ impl StateManager {
 
         // Update merkle tree with transactions and new balance
         for tx in &block.transactions {
-            let key = tx.hash().to_vec();
-            let value = tx.payload.clone();
-            tree.insert(&key[..], &value[..]);
+            tree.insert_shared(&tx.hash(), tx.payload.clone());
         }
 
         // Store the reward info in merkle tree for transparency
@@ -719,26 +1744,6 @@ impl StateManager {
 
         Ok(())
     }
-
-    /// Verify a transaction
-    fn verify_transaction(&self, tx: &Transaction, _state: &ChainState) -> Result<(), StateError> {
-        // Verify transaction signature
-        let key_manager = &self.key_manager;
-        let agent_id = hex::encode(&tx.sender);
-
-        // Verify the transaction signature
-        let mut data_to_verify = Vec::new();
-        data_to_verify.extend_from_slice(&tx.sender);
-        data_to_verify.extend_from_slice(&tx.nonce.to_le_bytes());
-        data_to_verify.extend_from_slice(&tx.payload);
-
-        key_manager
-            .inner()
-            .verify(&agent_id, &data_to_verify, &tx.signature)
-            .map_err(|e| StateError::Crypto(e))?;
-
-        Ok(())
-    }
 }
 
 /// State snapshot for recovery
@@ -781,6 +1786,25 @@ mod tests {
         assert_eq!(state.balances.len(), 0);
     }
 
+    #[test]
+    fn test_fund() {
+        let store = StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new());
+        let account = SigningKey::from_bytes(&[7u8; 32]).verifying_key();
+        let root = store.state_root();
+
+        store.fund(&account, 1_000, "genesis_allocation");
+        store.fund(&account, 500, "faucet");
+        assert_eq!(store.get_balance(&account), 1_500);
+        assert_ne!(store.state_root(), root);
+
+        let history = store.account_history(&hex::encode(account.as_bytes()), None, Order::Asc, 10);
+        assert_eq!(history.len(), 2);
+        assert!(matches!(
+            &history[1].1,
+            AccountEvent::BalanceChange(change) if change.reason == "faucet" && change.balance == 1_500
+        ));
+    }
+
     #[test]
     fn test_merkle_state() {
         let key_manager = KeyManagerHandle::new();
@@ -801,6 +1825,7 @@ mod tests {
             innovation_level: 0,
             producer_strategy: "".into(),
             timestamp: 0,
+            metadata: Default::default(),
         };
 
         // Apply block
@@ -838,6 +1863,7 @@ mod tests {
             innovation_level: 0,
             producer_strategy: "".into(),
             timestamp: 0,
+            metadata: Default::default(),
         };
         store.apply_block(&test_block).unwrap();
 
@@ -858,6 +1884,159 @@ mod tests {
         assert_eq!(store.get_block_height(), 1);
     }
 
+    #[test]
+    fn test_chaos_seed() {
+        let seeded = || {
+            StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new()).with_chaos_seed(7)
+        };
+        let (one, other) = (seeded(), seeded());
+        for height in 1..=3 {
+            let block = Block {
+                height,
+                parent_hash: [0u8; 32],
+                transactions: vec![],
+                proposer_sig: [0u8; 64],
+                state_root: [0u8; 32],
+                drama_level: 5,
+                producer_mood: "dramatic".to_string(),
+                producer_id: "test".to_string(),
+                innovation_level: 0,
+                producer_strategy: "".into(),
+                timestamp: height,
+                metadata: Default::default(),
+            };
+            one.apply_block(&block).unwrap();
+            other.apply_block(&block).unwrap();
+        }
+        // The same bonuses for the same blocks, so the same root
+        assert_eq!(one.get_state().balances, other.get_state().balances);
+        assert_eq!(one.state_root(), other.state_root());
+    }
+
+    #[tokio::test]
+    async fn test_recover_from_storage() {
+        let path = std::env::temp_dir().join(format!("chaoschain-recovery-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let open = || {
+            StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new())
+                .with_storage(Storage::open(&path, storage::StorageConfig::default()).unwrap())
+        };
+        let store = open();
+        for height in 0..3 {
+            let block = Block {
+                height,
+                parent_hash: [0u8; 32],
+                transactions: vec![],
+                proposer_sig: [0u8; 64],
+                state_root: [0u8; 32],
+                drama_level: 5,
+                producer_mood: "dramatic".to_string(),
+                producer_id: "test".to_string(),
+                innovation_level: 0,
+                producer_strategy: "".into(),
+                timestamp: height,
+                metadata: Default::default(),
+            };
+            store.apply_block(&block).unwrap();
+            store.persist_block(&block).await.unwrap();
+        }
+        store.storage.as_ref().unwrap().flush().await.unwrap();
+        let head = store.stored_head().await.unwrap().unwrap();
+        assert_eq!(head.hash, store.get_latest_block().unwrap().hash());
+        let balance = store.get_state().balances;
+        let root = store.state_root();
+        drop(store);
+
+        // A restart picks up the blocks, the balances and the trie
+        let restarted = open();
+        assert_eq!(restarted.recover_from_storage().await.unwrap(), 3);
+        assert_eq!(restarted.get_block_height(), 3);
+        assert_eq!(restarted.get_state().balances, balance);
+        assert_eq!(restarted.state_root(), root);
+        assert_eq!(restarted.recover_from_storage().await.unwrap(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_rebuilds_the_registries() {
+        use chaoschain_core::multisig::{Cosignature, MultisigAccount};
+
+        let path = std::env::temp_dir().join(format!(
+            "chaoschain-recovery-registries-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let owners: Vec<SigningKey> = (1..=2).map(|i| SigningKey::from_bytes(&[i; 32])).collect();
+        let key_manager = KeyManagerHandle::new();
+        key_manager.inner().register_public_key(
+            &owners[0].verifying_key(),
+            "owner-1".to_string(),
+            "agent".to_string(),
+        );
+        let open = || {
+            StateStoreImpl::new(ChainConfig::default(), key_manager.clone())
+                .with_storage(Storage::open(&path, storage::StorageConfig::default()).unwrap())
+        };
+        let account = MultisigAccount::new(
+            owners
+                .iter()
+                .map(|owner| owner.verifying_key().to_bytes())
+                .collect(),
+            2,
+        )
+        .unwrap();
+        let mut create = Transaction {
+            sender: owners[0].verifying_key().to_bytes(),
+            nonce: 0,
+            payload: account.payload().into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        };
+        create.signature =
+            ed25519_dalek::Signer::sign(&owners[0], &create.signing_bytes()).to_bytes();
+        let block = |height, transactions| Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: String::new(),
+            producer_id: "test".to_string(),
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+        let store = open();
+        let created = block(0, vec![create]);
+        store.apply_block(&created).unwrap();
+        store.persist_block(&created).await.unwrap();
+        store.storage.as_ref().unwrap().flush().await.unwrap();
+        drop(store);
+
+        // The account outlives the restart, and its owners spend from it
+        let restarted = open();
+        assert_eq!(restarted.recover_from_storage().await.unwrap(), 1);
+        assert_eq!(
+            restarted.multisig_accounts().get(&account.address()),
+            Some(&account)
+        );
+        let mut spend = Transaction {
+            sender: account.address(),
+            nonce: 0,
+            payload: b"fund the alliance".to_vec().into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        };
+        spend.cosignatures = owners
+            .iter()
+            .map(|owner| Cosignature::new(&spend, owner).unwrap())
+            .collect();
+        restarted.apply_block(&block(1, vec![spend])).unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
     #[test]
     fn test_state_pruning() {
         let key_manager = KeyManagerHandle::new();
@@ -878,6 +2057,7 @@ mod tests {
                 innovation_level: 0,
                 producer_strategy: "".into(),
                 timestamp: 0,
+                metadata: Default::default(),
             };
             store.apply_block(&block).unwrap();
         }
@@ -889,4 +2069,560 @@ mod tests {
         let blocks = store.blocks.read();
         assert!(blocks.iter().all(|b| b.height > 2));
     }
+
+    #[test]
+    fn test_multisig_account() {
+        use chaoschain_core::multisig::{Cosignature, MultisigAccount};
+
+        let owners: Vec<SigningKey> = (1..=3).map(|i| SigningKey::from_bytes(&[i; 32])).collect();
+        let key_manager = KeyManagerHandle::new();
+        key_manager.inner().register_public_key(
+            &owners[0].verifying_key(),
+            "owner-1".to_string(),
+            "agent".to_string(),
+        );
+        let store = StateStoreImpl::new(ChainConfig::default(), key_manager);
+        let account = MultisigAccount::new(
+            owners
+                .iter()
+                .map(|owner| owner.verifying_key().to_bytes())
+                .collect(),
+            2,
+        )
+        .unwrap();
+
+        let mut create = Transaction {
+            sender: owners[0].verifying_key().to_bytes(),
+            nonce: 0,
+            payload: account.payload().into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        };
+        create.signature =
+            ed25519_dalek::Signer::sign(&owners[0], &create.signing_bytes()).to_bytes();
+        let mut spend = Transaction {
+            sender: account.address(),
+            nonce: 0,
+            payload: b"fund the alliance".to_vec().into(),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        };
+        spend.cosignatures = vec![Cosignature::new(&spend, &owners[1]).unwrap()];
+        let block = |height, transactions| Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: String::new(),
+            producer_id: "test".to_string(),
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+
+        // One owner is not enough, even in the block creating the account
+        assert!(store
+            .apply_block(&block(1, vec![create.clone(), spend.clone()]))
+            .is_err());
+        spend
+            .cosignatures
+            .push(Cosignature::new(&spend, &owners[2]).unwrap());
+        store
+            .apply_block(&block(1, vec![create, spend.clone()]))
+            .unwrap();
+        assert_eq!(
+            store.multisig_accounts().get(&account.address()),
+            Some(&account)
+        );
+        spend.nonce = 1;
+        assert!(store.apply_block(&block(2, vec![spend])).is_err());
+    }
+    #[test]
+    fn test_commit_reveal() {
+        use chaoschain_core::commit::{Commit, Reveal};
+
+        let sender = SigningKey::from_bytes(&[4; 32]);
+        let key_manager = KeyManagerHandle::new();
+        key_manager.inner().register_public_key(
+            &sender.verifying_key(),
+            "trader".to_string(),
+            "agent".to_string(),
+        );
+        let store = StateStoreImpl::new(ChainConfig::default(), key_manager);
+        let signed = |nonce, payload: Vec<u8>| {
+            let mut tx = Transaction {
+                sender: sender.verifying_key().to_bytes(),
+                nonce,
+                payload: payload.into(),
+                signature: [0; 64],
+                cosignatures: Vec::new(),
+            };
+            tx.signature = ed25519_dalek::Signer::sign(&sender, &tx.signing_bytes()).to_bytes();
+            tx
+        };
+        let block = |height, transactions| Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: String::new(),
+            producer_id: "test".to_string(),
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+
+        let commit = Commit::seal(
+            &sender.verifying_key().to_bytes(),
+            &[5; 32],
+            b"front-run this",
+        );
+        let committing = signed(0, commit.payload());
+        let reveal = |key| {
+            signed(
+                1,
+                Reveal {
+                    commit: committing.hash(),
+                    key,
+                }
+                .payload(),
+            )
+        };
+        assert!(store.check_commitment(&reveal([5; 32])).is_err());
+        store
+            .apply_block(&block(1, vec![committing.clone()]))
+            .unwrap();
+        assert!(store.check_commitment(&reveal([6; 32])).is_err());
+        assert!(store.apply_block(&block(2, vec![reveal([6; 32])])).is_err());
+        assert_eq!(
+            store.check_commitment(&reveal([5; 32])).unwrap().as_deref(),
+            Some(&b"front-run this"[..])
+        );
+        store.apply_block(&block(2, vec![reveal([5; 32])])).unwrap();
+        assert_eq!(
+            store.revealed(&committing.hash()).as_deref(),
+            Some(&b"front-run this"[..])
+        );
+    }
+    #[test]
+    fn test_proof_transactions() {
+        use chaoschain_core::zk::{ProofRecord, VerificationKey};
+
+        /// Takes the proof that is the key reversed
+        struct Mirror;
+
+        impl ProofSystem for Mirror {
+            fn name(&self) -> &str {
+                "mirror"
+            }
+
+            fn check_key(&self, _key: &[u8]) -> Result<(), String> {
+                Ok(())
+            }
+
+            fn verify(&self, key: &[u8], _inputs: &[u8], proof: &[u8]) -> Result<(), String> {
+                match key.iter().rev().eq(proof) {
+                    true => Ok(()),
+                    false => Err("not the mirror of the key".to_string()),
+                }
+            }
+        }
+
+        let sender = SigningKey::from_bytes(&[6; 32]);
+        let key_manager = KeyManagerHandle::new();
+        key_manager.inner().register_public_key(
+            &sender.verifying_key(),
+            "rollup".to_string(),
+            "agent".to_string(),
+        );
+        let store = StateStoreImpl::new(ChainConfig::default(), key_manager);
+        let signed = |nonce, payload: Vec<u8>| {
+            let mut tx = Transaction {
+                sender: sender.verifying_key().to_bytes(),
+                nonce,
+                payload: payload.into(),
+                signature: [0; 64],
+                cosignatures: Vec::new(),
+            };
+            tx.signature = ed25519_dalek::Signer::sign(&sender, &tx.signing_bytes()).to_bytes();
+            tx
+        };
+        let block = |height, transactions| Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: String::new(),
+            producer_id: "test".to_string(),
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+
+        let key = VerificationKey {
+            system: "mirror".to_string(),
+            key: vec![1, 2, 3],
+        };
+        let register = signed(0, key.payload());
+        let proof = |proof: Vec<u8>| {
+            signed(
+                1,
+                ProofRecord {
+                    key: key.id(),
+                    inputs: Vec::new(),
+                    proof,
+                }
+                .payload(),
+            )
+        };
+        // Without the system the key is turned down
+        assert!(store.check_proof(&register).is_err());
+        assert!(store
+            .apply_block(&block(1, vec![register.clone()]))
+            .is_err());
+        store.register_proof_system(Arc::new(Mirror));
+        store.apply_block(&block(1, vec![register])).unwrap();
+        assert!(store.check_proof(&proof(vec![1, 2, 3])).is_err());
+        assert!(store
+            .apply_block(&block(2, vec![proof(vec![1, 2, 3])]))
+            .is_err());
+        store.check_proof(&proof(vec![3, 2, 1])).unwrap();
+        store
+            .apply_block(&block(2, vec![proof(vec![3, 2, 1])]))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_fork_activation() {
+        use chaoschain_core::fork::{Activation, ForkSchedule, ScheduledUpgrade, Upgrade};
+
+        let store = StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new())
+            .with_forks(ForkSchedule::new(vec![
+                ScheduledUpgrade {
+                    upgrade: Upgrade::DramaRewards,
+                    activation: Activation::Height(2),
+                },
+                ScheduledUpgrade {
+                    upgrade: Upgrade::MonotonicTime,
+                    activation: Activation::Height(3),
+                },
+            ]));
+        let block = |height, timestamp| {
+            let mut block = Block {
+                height,
+                parent_hash: [0; 32],
+                transactions: Vec::new(),
+                proposer_sig: [0; 64],
+                state_root: [0; 32],
+                drama_level: 9,
+                producer_mood: String::new(),
+                producer_id: "test".to_string(),
+                innovation_level: 2,
+                producer_strategy: String::new(),
+                timestamp,
+                metadata: Default::default(),
+            };
+            store.forks().at(height).stamp(&mut block);
+            block
+        };
+        let rewarded = |height: u64| {
+            let key = format!("reward:{}:test", height);
+            let reward = store.merkle_tree.read().get(key.as_bytes()).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&reward).unwrap()["drama_level"].clone()
+        };
+
+        store.apply_block(&block(1, 10)).unwrap();
+        assert_eq!(rewarded(1), 2);
+        // Produced under the rules of the height before
+        let mut stale = block(2, 10);
+        stale.metadata.forks.clear();
+        assert!(store.apply_block(&stale).is_err());
+        store.apply_block(&block(2, 10)).unwrap();
+        assert_eq!(rewarded(2), 9);
+        assert!(store.apply_block(&block(3, 10)).is_err());
+        store.apply_block(&block(3, 11)).unwrap();
+    }
+
+    #[test]
+    fn test_governance_spends_from_the_treasury() {
+        use chaoschain_core::fork::ForkSchedule;
+        use chaoschain_core::governance::{
+            treasury, Ballot, Governance, GovernanceParams, Proposal,
+        };
+
+        let holder = SigningKey::from_bytes(&[8; 32]);
+        let key_manager = KeyManagerHandle::new();
+        key_manager.inner().register_public_key(
+            &holder.verifying_key(),
+            "holder".to_string(),
+            "agent".to_string(),
+        );
+        let store = StateStoreImpl::new(ChainConfig::default(), key_manager).with_governance(
+            Governance::new(
+                GovernanceParams {
+                    voting_period: 2,
+                    ..GovernanceParams::default()
+                },
+                ConsensusParams {
+                    forks: ForkSchedule {
+                        epoch_length: 5,
+                        upgrades: Vec::new(),
+                    },
+                    ..ConsensusParams::default()
+                },
+            ),
+        );
+        store.fund(&holder.verifying_key(), 50, "genesis_allocation");
+        store
+            .state
+            .write()
+            .balances
+            .push((hex::encode(treasury()), 100));
+        let payee = SigningKey::from_bytes(&[9; 32]).verifying_key();
+        let signed = |nonce, payload: Vec<u8>| {
+            let mut tx = Transaction {
+                sender: holder.verifying_key().to_bytes(),
+                nonce,
+                payload: payload.into(),
+                signature: [0; 64],
+                cosignatures: Vec::new(),
+            };
+            tx.signature = ed25519_dalek::Signer::sign(&holder, &tx.signing_bytes()).to_bytes();
+            tx
+        };
+        let block = |height, transactions| Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: String::new(),
+            producer_id: "test".to_string(),
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+
+        let proposing = signed(
+            0,
+            Proposal {
+                title: "Pay the bard".to_string(),
+                action: format!("spend={}:30", hex::encode(payee.as_bytes()))
+                    .parse()
+                    .unwrap(),
+            }
+            .payload(),
+        );
+        let id = proposing.hash();
+        store.apply_block(&block(1, vec![proposing])).unwrap();
+        let ballot = signed(
+            1,
+            Ballot {
+                proposal: id,
+                approve: true,
+            }
+            .payload(),
+        );
+        assert!(store.check_governance(&ballot).is_ok());
+        store.apply_block(&block(2, vec![ballot])).unwrap();
+        store.apply_block(&block(3, Vec::new())).unwrap();
+        assert_eq!(
+            store.proposal(&id).unwrap().status,
+            Status::Passed { executes_at: 5 }
+        );
+
+        store.apply_block(&block(4, Vec::new())).unwrap();
+        assert_eq!(store.get_balance(&payee), 0);
+        store.apply_block(&block(5, Vec::new())).unwrap();
+        assert_eq!(store.get_balance(&payee), 30);
+        assert_eq!(
+            store.proposal(&id).unwrap().status,
+            Status::Executed { height: 5 }
+        );
+    }
+
+    #[test]
+    fn test_staking_locks_and_returns_balance() {
+        use chaoschain_core::staking::{Bond, StakingParams, Unbond};
+
+        let validator = SigningKey::from_bytes(&[10; 32]);
+        let key = validator.verifying_key();
+        let key_manager = KeyManagerHandle::new();
+        key_manager.inner().register_public_key(
+            &key,
+            "validator-10".to_string(),
+            "validator".to_string(),
+        );
+        let store =
+            StateStoreImpl::new(ChainConfig::default(), key_manager).with_staking(Staking::new(
+                StakingParams {
+                    unbonding_period: 2,
+                    epoch_reward: 0,
+                    ..StakingParams::default()
+                },
+                100,
+            ));
+        store.fund(&key, 100, "genesis_allocation");
+        let signed = |nonce, payload: Vec<u8>| {
+            let mut tx = Transaction {
+                sender: key.to_bytes(),
+                nonce,
+                payload: payload.into(),
+                signature: [0; 64],
+                cosignatures: Vec::new(),
+            };
+            tx.signature = ed25519_dalek::Signer::sign(&validator, &tx.signing_bytes()).to_bytes();
+            tx
+        };
+        let block = |height, transactions| Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: String::new(),
+            producer_id: "test".to_string(),
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+        let bond = |nonce, amount| {
+            signed(
+                nonce,
+                Bond {
+                    validator: key.to_bytes(),
+                    amount,
+                }
+                .payload(),
+            )
+        };
+
+        assert!(store.check_staking(&bond(0, 101)).is_err());
+        store.apply_block(&block(1, vec![bond(0, 60)])).unwrap();
+        assert_eq!(store.get_balance(&key), 40);
+        assert_eq!(store.stake_of(key.as_bytes()), Some(60));
+        assert_eq!(store.validators()[0].self_bond, 60);
+
+        let unbond = signed(
+            1,
+            Unbond {
+                validator: key.to_bytes(),
+                amount: 60,
+            }
+            .payload(),
+        );
+        store.apply_block(&block(2, vec![unbond])).unwrap();
+        assert_eq!(store.stake_of(key.as_bytes()), Some(0));
+        assert_eq!(store.unbonding()[0].releases_at, 4);
+        store.apply_block(&block(3, Vec::new())).unwrap();
+        assert_eq!(store.get_balance(&key), 40);
+        store.apply_block(&block(4, Vec::new())).unwrap();
+        assert_eq!(store.get_balance(&key), 100);
+    }
+
+    #[tokio::test]
+    async fn test_stake_outlives_a_restart() {
+        use chaoschain_core::staking::{Bond, StakingParams, Unbond};
+
+        let path = std::env::temp_dir().join(format!(
+            "chaoschain-recovery-staking-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let validator = SigningKey::from_bytes(&[11; 32]);
+        let key = validator.verifying_key();
+        let key_manager = KeyManagerHandle::new();
+        key_manager.inner().register_public_key(
+            &key,
+            "validator-11".to_string(),
+            "validator".to_string(),
+        );
+        // As a node starts: funded by its genesis, then the blocks stored
+        let open = || {
+            let store = StateStoreImpl::new(ChainConfig::default(), key_manager.clone())
+                .with_storage(Storage::open(&path, storage::StorageConfig::default()).unwrap())
+                .with_staking(Staking::new(
+                    StakingParams {
+                        unbonding_period: 1,
+                        epoch_reward: 0,
+                        ..StakingParams::default()
+                    },
+                    100,
+                ));
+            store.fund(&key, 100, "genesis_allocation");
+            store
+        };
+        let signed = |nonce, payload: Vec<u8>| {
+            let mut tx = Transaction {
+                sender: key.to_bytes(),
+                nonce,
+                payload: payload.into(),
+                signature: [0; 64],
+                cosignatures: Vec::new(),
+            };
+            tx.signature = ed25519_dalek::Signer::sign(&validator, &tx.signing_bytes()).to_bytes();
+            tx
+        };
+        let block = |height, transactions| Block {
+            height,
+            parent_hash: [0; 32],
+            transactions,
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: String::new(),
+            producer_id: "test".to_string(),
+            innovation_level: 0,
+            producer_strategy: String::new(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+        let store = open();
+        let bonded = block(
+            0,
+            vec![signed(
+                0,
+                Bond {
+                    validator: key.to_bytes(),
+                    amount: 60,
+                }
+                .payload(),
+            )],
+        );
+        store.apply_block(&bonded).unwrap();
+        store.persist_block(&bonded).await.unwrap();
+        store.storage.as_ref().unwrap().flush().await.unwrap();
+        drop(store);
+
+        // The bond is still there, and comes back once unbonded
+        let restarted = open();
+        assert_eq!(restarted.recover_from_storage().await.unwrap(), 1);
+        assert_eq!(restarted.get_balance(&key), 40);
+        assert_eq!(restarted.stake_of(key.as_bytes()), Some(60));
+        let unbond = signed(
+            1,
+            Unbond {
+                validator: key.to_bytes(),
+                amount: 60,
+            }
+            .payload(),
+        );
+        restarted.apply_block(&block(1, vec![unbond])).unwrap();
+        restarted.apply_block(&block(2, Vec::new())).unwrap();
+        assert_eq!(restarted.get_balance(&key), 100);
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -0,0 +1,560 @@
+//! Upkeep of a data directory while its node is stopped: what it holds and
+//! whether it holds together, compaction, and pruning below a height.
+//!
+//! A node keeps its snapshots under `snapshots/snapshot-<height>`, agent
+//! activity in the `activity.jsonl` journal, and the relationship graph and
+//! agent memory as JSON files. Each problem found comes with what to do
+//! about it.
+
+use crate::index::{Activity, MAX_ACTIVITY};
+use crate::snapshot::SnapshotArchive;
+use crate::{StateError, StateStoreImpl};
+use chaoschain_core::ChainConfig;
+use chaoschain_crypto::KeyManagerHandle;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Journal of agent activity, in the data directory
+pub const ACTIVITY_JOURNAL: &str = "activity.jsonl";
+
+/// Log of the finalized blocks, in the data directory
+pub const BLOCK_LOG: &str = "blocks.log";
+
+/// Directory of the snapshots, in the data directory
+pub const SNAPSHOTS: &str = "snapshots";
+
+/// JSON files the node reads back on start, and starts without when they
+/// don't parse
+const STATE_FILES: &[&str] = &["relationships.json", "agent_memory.json"];
+
+/// A snapshot directory, and whether it checks out
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotStats {
+    pub height: u64,
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub chunks: u32,
+    pub entries: usize,
+    /// Why it can't be started from, when it can't
+    pub problem: Option<String>,
+}
+
+/// The activity journal, line by line
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JournalStats {
+    pub bytes: u64,
+    pub lines: usize,
+    /// Lines that read back as activity
+    pub records: usize,
+    /// Line numbers, from 1, of lines that don't
+    pub unreadable: Vec<usize>,
+    /// Records of a sequence number seen before
+    pub duplicates: usize,
+    /// Records the node drops on start, past the newest it keeps
+    pub beyond_kept: usize,
+    /// Chain heights the activity spans
+    pub heights: Option<(u64, u64)>,
+}
+
+/// Something wrong in a data directory, and how to set it right
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Issue {
+    pub path: PathBuf,
+    pub problem: String,
+    pub repair: String,
+}
+
+/// What a data directory holds
+#[derive(Debug, Clone, Serialize)]
+pub struct DataDirStats {
+    pub path: PathBuf,
+    pub bytes: u64,
+    /// By height, lowest first
+    pub snapshots: Vec<SnapshotStats>,
+    /// Absent when no activity was journaled yet
+    pub journal: Option<JournalStats>,
+    pub issues: Vec<Issue>,
+}
+
+/// What `compact` did
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Compaction {
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    /// Unreadable lines, duplicates and records past the newest kept
+    pub records_dropped: usize,
+    /// Leftovers of a compaction the node did not finish
+    pub files_removed: Vec<PathBuf>,
+}
+
+/// What `prune` did
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Pruning {
+    /// Heights of the snapshots removed
+    pub snapshots_removed: Vec<u64>,
+    pub records_removed: usize,
+    pub bytes_freed: u64,
+}
+
+/// Take stock of `data_dir`, checking every snapshot and journal line
+pub fn stats(data_dir: &Path) -> Result<DataDirStats, StateError> {
+    if !data_dir.is_dir() {
+        return Err(internal(data_dir, "not a directory"));
+    }
+    let mut issues = Vec::new();
+
+    let snapshots = snapshots(data_dir)?
+        .into_iter()
+        .map(|(height, path)| check_snapshot(height, path))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        let Some(problem) = &snapshot.problem else {
+            continue;
+        };
+        let latest = index + 1 == snapshots.len();
+        let repair = match (latest, snapshots[..index].iter().rev().find(|s| s.problem.is_none())) {
+            (true, Some(previous)) => format!(
+                "The node won't start from it. Remove the directory to start from snapshot-{}, or import a good one with `chaoschain snapshot import`",
+                previous.height
+            ),
+            (true, None) => "The node won't start from it. Remove the directory to sync from scratch, or import a good one with `chaoschain snapshot import`".to_string(),
+            (false, _) => "Remove the directory, the node starts from a later snapshot".to_string(),
+        };
+        issues.push(Issue {
+            path: snapshot.path.clone(),
+            problem: problem.clone(),
+            repair,
+        });
+    }
+
+    let journal_path = data_dir.join(ACTIVITY_JOURNAL);
+    let journal = match std::fs::read(&journal_path) {
+        Ok(bytes) => {
+            let (journal, _) = read_journal(&bytes);
+            // A line cut short by a crash is the last one, and harmless
+            let torn_tail = journal.unreadable == [journal.lines];
+            if !journal.unreadable.is_empty() && !torn_tail {
+                issues.push(Issue {
+                    path: journal_path.clone(),
+                    problem: format!(
+                        "{} unreadable lines, the first at line {}",
+                        journal.unreadable.len(),
+                        journal.unreadable[0]
+                    ),
+                    repair:
+                        "Run `chaoschain db compact` to drop them, the activity in them is lost"
+                            .to_string(),
+                });
+            }
+            if journal.duplicates > 0 {
+                issues.push(Issue {
+                    path: journal_path.clone(),
+                    problem: format!(
+                        "{} records repeat a sequence number, as when two nodes share the directory",
+                        journal.duplicates
+                    ),
+                    repair: "Run `chaoschain db compact` to keep the first of each".to_string(),
+                });
+            }
+            Some(journal)
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(internal(&journal_path, e)),
+    };
+    for partial in partials(data_dir)? {
+        issues.push(Issue {
+            path: partial,
+            problem: "Left by a compaction that did not finish".to_string(),
+            repair: "Run `chaoschain db compact` to remove it".to_string(),
+        });
+    }
+
+    for name in STATE_FILES {
+        let path = data_dir.join(name);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(internal(&path, e)),
+        };
+        if let Err(e) = serde_json::from_slice::<serde_json::Value>(&bytes) {
+            issues.push(Issue {
+                path,
+                problem: format!("Not valid JSON: {}", e),
+                repair: "The node starts without it and writes over it. Restore it from a backup first to keep it".to_string(),
+            });
+        }
+    }
+
+    Ok(DataDirStats {
+        path: data_dir.to_path_buf(),
+        bytes: size(data_dir)?,
+        snapshots,
+        journal,
+        issues,
+    })
+}
+
+/// Rewrite the activity journal with only what the node reads back, once
+/// each and in order, and remove what unfinished compactions left
+pub fn compact(data_dir: &Path) -> Result<Compaction, StateError> {
+    let mut compaction = Compaction::default();
+    for partial in partials(data_dir)? {
+        compaction.bytes_before += size(&partial)?;
+        std::fs::remove_file(&partial).map_err(|e| internal(&partial, e))?;
+        compaction.files_removed.push(partial);
+    }
+
+    let path = data_dir.join(ACTIVITY_JOURNAL);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(compaction),
+        Err(e) => return Err(internal(&path, e)),
+    };
+    let (journal, mut records) = read_journal(&bytes);
+    let mut seen = HashSet::new();
+    records.retain(|activity| seen.insert(activity.seq));
+    records.sort_by_key(|activity| activity.seq);
+    let skip = records.len().saturating_sub(MAX_ACTIVITY);
+    let kept = &records[skip..];
+    rewrite_journal(&path, kept)?;
+
+    compaction.bytes_before += bytes.len() as u64;
+    compaction.bytes_after = size(&path)?;
+    compaction.records_dropped = journal.lines - kept.len();
+    Ok(compaction)
+}
+
+/// Remove the snapshots below `height`, but for the latest one, and the
+/// activity recorded below it
+pub fn prune(data_dir: &Path, height: u64) -> Result<Pruning, StateError> {
+    let mut pruning = Pruning::default();
+    let mut snapshots = snapshots(data_dir)?;
+    // The node starts from the latest snapshot, which must stay
+    snapshots.pop();
+    for (snapshot_height, path) in snapshots {
+        if snapshot_height >= height {
+            continue;
+        }
+        pruning.bytes_freed += size(&path)?;
+        std::fs::remove_dir_all(&path).map_err(|e| internal(&path, e))?;
+        pruning.snapshots_removed.push(snapshot_height);
+    }
+
+    let path = data_dir.join(ACTIVITY_JOURNAL);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(pruning),
+        Err(e) => return Err(internal(&path, e)),
+    };
+    let (_, records) = read_journal(&bytes);
+    let before = records.len();
+    let kept: Vec<Activity> = records
+        .into_iter()
+        .filter(|activity| activity.height >= height)
+        .collect();
+    if kept.len() < before {
+        rewrite_journal(&path, &kept)?;
+        pruning.records_removed = before - kept.len();
+        pruning.bytes_freed += (bytes.len() as u64).saturating_sub(size(&path)?);
+    }
+    Ok(pruning)
+}
+
+/// The snapshot directories of `data_dir` by height, lowest first
+pub(crate) fn snapshots(data_dir: &Path) -> Result<Vec<(u64, PathBuf)>, StateError> {
+    let dir = data_dir.join(SNAPSHOTS);
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(internal(&dir, e)),
+    };
+    let mut snapshots: Vec<(u64, PathBuf)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let height = name.to_str()?.strip_prefix("snapshot-")?.parse().ok()?;
+            Some((height, entry.path()))
+        })
+        .collect();
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// Open the snapshot at `path` as the node would, and rebuild its state
+fn check_snapshot(height: u64, path: PathBuf) -> Result<SnapshotStats, StateError> {
+    let mut stats = SnapshotStats {
+        height,
+        bytes: size(&path)?,
+        path,
+        chunks: 0,
+        entries: 0,
+        problem: None,
+    };
+    let archive = match SnapshotArchive::open(&stats.path) {
+        Ok(archive) => archive,
+        Err(e) => {
+            stats.problem = Some(e.to_string());
+            return Ok(stats);
+        }
+    };
+    stats.chunks = archive.manifest.chunk_count;
+    stats.entries = archive.chunks.iter().map(|chunk| chunk.entries.len()).sum();
+    if archive.manifest.height != height {
+        stats.problem = Some(format!(
+            "Holds the snapshot at height {}",
+            archive.manifest.height
+        ));
+        return Ok(stats);
+    }
+    let rebuilt =
+        SnapshotArchive::assemble(archive.manifest, archive.chunks).and_then(|snapshot| {
+            StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new())
+                .recover_from_snapshot(snapshot)
+        });
+    if let Err(e) = rebuilt {
+        stats.problem = Some(format!("The state does not rebuild: {}", e));
+    }
+    Ok(stats)
+}
+
+/// The lines of a journal, and the activity of those that read back
+pub(crate) fn read_journal(bytes: &[u8]) -> (JournalStats, Vec<Activity>) {
+    let mut journal = JournalStats {
+        bytes: bytes.len() as u64,
+        ..JournalStats::default()
+    };
+    let mut records = Vec::new();
+    let mut seen = HashSet::new();
+    for (number, line) in bytes.split(|byte| *byte == b'\n').enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        journal.lines += 1;
+        match serde_json::from_slice::<Activity>(line) {
+            Ok(activity) => {
+                journal.records += 1;
+                if !seen.insert(activity.seq) {
+                    journal.duplicates += 1;
+                }
+                journal.heights = Some(
+                    journal
+                        .heights
+                        .map_or((activity.height, activity.height), |(low, high)| {
+                            (low.min(activity.height), high.max(activity.height))
+                        }),
+                );
+                records.push(activity);
+            }
+            Err(_) => journal.unreadable.push(number + 1),
+        }
+    }
+    journal.beyond_kept = seen.len().saturating_sub(MAX_ACTIVITY);
+    (journal, records)
+}
+
+/// Write `records` to the journal at `path`, swapping the file in when
+/// complete, as the node does
+fn rewrite_journal(path: &Path, records: &[Activity]) -> Result<(), StateError> {
+    let partial = path.with_extension("partial");
+    let write = || -> std::io::Result<()> {
+        let mut file = File::create(&partial)?;
+        for activity in records {
+            writeln!(file, "{}", serde_json::to_string(activity)?)?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&partial, path)
+    };
+    write().map_err(|e| internal(path, e))
+}
+
+/// Files a journal compaction writes before it swaps them in
+fn partials(data_dir: &Path) -> Result<Vec<PathBuf>, StateError> {
+    let partial = data_dir.join(ACTIVITY_JOURNAL).with_extension("partial");
+    Ok(partial.exists().then_some(partial).into_iter().collect())
+}
+
+/// Bytes of the file, or of everything under the directory, at `path`
+fn size(path: &Path) -> Result<u64, StateError> {
+    let metadata = std::fs::symlink_metadata(path).map_err(|e| internal(path, e))?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut total = 0;
+    for entry in std::fs::read_dir(path).map_err(|e| internal(path, e))? {
+        let entry = entry.map_err(|e| internal(path, e))?;
+        total += size(&entry.path())?;
+    }
+    Ok(total)
+}
+
+pub(crate) fn internal(path: &Path, e: impl std::fmt::Display) -> StateError {
+    StateError::Internal(format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::ChainIndex;
+    use crate::snapshot::DEFAULT_CHUNK_ENTRIES;
+    use crate::{ChainMetadata, StateSnapshot};
+    use chaoschain_core::{Block, NetworkEvent};
+    use std::collections::HashMap;
+
+    fn activity(seq: u64, height: u64) -> String {
+        let activity = Activity {
+            seq,
+            timestamp: 1_700_000_000 + seq,
+            height,
+            agent: "validator-1".to_string(),
+            kind: "chat".to_string(),
+            text: "drama".to_string(),
+            event: NetworkEvent::AgentChat {
+                message: "drama".to_string(),
+                sender: "validator-1".to_string(),
+                meme_url: None,
+            },
+        };
+        serde_json::to_string(&activity).unwrap()
+    }
+
+    fn snapshot(dir: &Path, height: u64) {
+        let empty = StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new());
+        let snapshot = StateSnapshot {
+            height,
+            state_root: empty.state_root(),
+            state_pairs: HashMap::new(),
+            metadata: ChainMetadata {
+                validators: HashMap::new(),
+                config: ChainConfig::default(),
+                last_block: Block {
+                    height,
+                    parent_hash: [0; 32],
+                    transactions: vec![],
+                    proposer_sig: [0; 64],
+                    state_root: [0; 32],
+                    drama_level: 5,
+                    producer_mood: "dramatic".to_string(),
+                    producer_id: "producer-1".to_string(),
+                    innovation_level: 0,
+                    producer_strategy: "".into(),
+                    timestamp: 0,
+                    metadata: Default::default(),
+                },
+            },
+            timestamp: 0,
+        };
+        SnapshotArchive::new(snapshot, DEFAULT_CHUNK_ENTRIES)
+            .save(&dir.join(SNAPSHOTS).join(format!("snapshot-{}", height)))
+            .unwrap();
+    }
+
+    fn data_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "chaoschain-maintenance-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_stats_finds_corruption() {
+        let dir = data_dir("stats");
+        snapshot(&dir, 3);
+        snapshot(&dir, 7);
+        let chunk = dir.join(SNAPSHOTS).join("snapshot-7").join("chunk-0.json");
+        std::fs::write(&chunk, b"{\"index\": 0").unwrap();
+        let journal = [
+            activity(0, 1),
+            "{\"seq\": 1, \"times".to_string(),
+            activity(1, 2),
+            activity(1, 2),
+            "{\"seq\": 3".to_string(),
+        ]
+        .join("\n");
+        std::fs::write(dir.join(ACTIVITY_JOURNAL), journal).unwrap();
+        std::fs::write(dir.join("relationships.json"), b"{\"edges\": [").unwrap();
+
+        let stats = stats(&dir).unwrap();
+        assert_eq!(stats.snapshots.len(), 2);
+        assert!(stats.snapshots[0].problem.is_none());
+        assert!(stats.snapshots[1].problem.is_some());
+        let journal = stats.journal.unwrap();
+        assert_eq!((journal.lines, journal.records), (5, 3));
+        assert_eq!(journal.unreadable, vec![2, 5]);
+        assert_eq!(journal.duplicates, 1);
+        assert_eq!(journal.heights, Some((1, 2)));
+
+        let paths: Vec<&Path> = stats.issues.iter().map(|i| i.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                dir.join(SNAPSHOTS).join("snapshot-7").as_path(),
+                dir.join(ACTIVITY_JOURNAL).as_path(),
+                dir.join(ACTIVITY_JOURNAL).as_path(),
+                dir.join("relationships.json").as_path(),
+            ]
+        );
+        assert!(stats.issues[0].repair.contains("snapshot-3"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compact_keeps_what_the_node_reads() {
+        let dir = data_dir("compact");
+        let journal = [
+            activity(2, 1),
+            activity(0, 1),
+            "garbage".to_string(),
+            activity(0, 1),
+        ]
+        .join("\n");
+        std::fs::write(dir.join(ACTIVITY_JOURNAL), journal).unwrap();
+        std::fs::write(dir.join("activity.partial"), b"half").unwrap();
+
+        let compaction = compact(&dir).unwrap();
+        assert_eq!(compaction.records_dropped, 2);
+        assert_eq!(compaction.files_removed, vec![dir.join("activity.partial")]);
+        assert!(compaction.bytes_after < compaction.bytes_before);
+
+        let stats = stats(&dir).unwrap();
+        assert!(stats.issues.is_empty());
+        let index = ChainIndex::new()
+            .with_journal(dir.join(ACTIVITY_JOURNAL))
+            .unwrap();
+        let seqs: Vec<u64> = index
+            .activity_between(0, u64::MAX, 10)
+            .iter()
+            .map(|activity| activity.seq)
+            .collect();
+        assert_eq!(seqs.len(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prune_keeps_the_latest_snapshot() {
+        let dir = data_dir("prune");
+        snapshot(&dir, 2);
+        snapshot(&dir, 4);
+        snapshot(&dir, 6);
+        let journal = [activity(0, 1), activity(1, 4), activity(2, 9)].join("\n");
+        std::fs::write(dir.join(ACTIVITY_JOURNAL), journal).unwrap();
+
+        let pruning = prune(&dir, 5).unwrap();
+        assert_eq!(pruning.snapshots_removed, vec![2, 4]);
+        assert_eq!(pruning.records_removed, 2);
+        assert!(pruning.bytes_freed > 0);
+
+        let pruning = prune(&dir, 100).unwrap();
+        assert!(pruning.snapshots_removed.is_empty());
+        let stats = stats(&dir).unwrap();
+        assert_eq!(stats.snapshots.len(), 1);
+        assert_eq!(stats.journal.unwrap().records, 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use chaoschain_core::Bytes;
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 
@@ -44,10 +45,11 @@ impl Node {
 }
 
 /// Merkle tree for state management
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct MerkleTree {
-    /// Leaf nodes store transaction/state data
-    leaves: HashMap<Vec<u8>, Vec<u8>>,
+    /// Leaf nodes store transaction/state data, transaction payloads
+    /// sharing the bytes of their transaction
+    leaves: HashMap<Vec<u8>, Bytes>,
     /// Cache of computed node hashes
     nodes: HashMap<Vec<u8>, [u8; 32]>,
 }
@@ -63,7 +65,12 @@ impl MerkleTree {
 
     /// Insert a key-value pair
     pub fn insert(&mut self, key: &[u8], value: &[u8]) {
-        self.leaves.insert(key.to_vec(), value.to_vec());
+        self.insert_shared(key, Bytes::copy_from_slice(value));
+    }
+
+    /// Insert a value without copying it, such as a transaction payload
+    pub fn insert_shared(&mut self, key: &[u8], value: Bytes) {
+        self.leaves.insert(key.to_vec(), value);
         self.nodes.clear(); // Clear cache as tree has changed
     }
 
@@ -254,12 +261,12 @@ impl MerkleTree {
     pub fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
         let value = self.leaves.remove(key);
         self.rebuild();
-        value
+        value.map(Vec::from)
     }
 
     /// Get value for a key
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.leaves.get(key).cloned()
+        self.leaves.get(key).map(|value| value.to_vec())
     }
 }
 
@@ -318,4 +325,18 @@ mod tests {
         tree.delete(key);
         assert_eq!(tree.leaves.len(), 0);
     }
+
+    #[test]
+    fn test_merkle_tree_shares_values() {
+        let mut tree = MerkleTree::new();
+        let payload = Bytes::from(b"a transaction payload".to_vec());
+        tree.insert_shared(b"tx", payload.clone());
+        assert_eq!(tree.leaves[&b"tx".to_vec()].as_ptr(), payload.as_ptr());
+
+        // The same root as a copy gives
+        let mut copied = MerkleTree::new();
+        copied.insert(b"tx", &payload);
+        assert_eq!(tree.root_hash(), copied.root_hash());
+        assert_eq!(tree.get(b"tx"), Some(payload.to_vec()));
+    }
 }
@@ -0,0 +1,53 @@
+//! Prometheus metrics of the block store and its indexes
+
+use chaoschain_core::metrics::MetricsRegistry;
+use chaoschain_core::Block;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::gauge::Gauge;
+
+/// Clones share their values
+#[derive(Debug, Clone, Default)]
+pub struct StorageMetrics {
+    height: Gauge,
+    blocks: Counter,
+    transactions: Counter,
+    activity: Counter,
+}
+
+impl StorageMetrics {
+    pub fn register(&self, registry: &MetricsRegistry) {
+        registry.register(
+            "chaoschain_storage_height",
+            "Height of the latest block applied to the state",
+            self.height.clone(),
+        );
+        registry.register(
+            "chaoschain_storage_blocks",
+            "Blocks stored",
+            self.blocks.clone(),
+        );
+        registry.register(
+            "chaoschain_storage_transactions",
+            "Transactions in the blocks stored",
+            self.transactions.clone(),
+        );
+        registry.register(
+            "chaoschain_storage_activity",
+            "Agent activity recorded for the explorer",
+            self.activity.clone(),
+        );
+    }
+
+    pub(crate) fn applied(&self, block: &Block) {
+        self.height.set(block.height as i64);
+    }
+
+    pub(crate) fn stored(&self, block: &Block) {
+        self.blocks.inc();
+        self.transactions.inc_by(block.transactions.len() as u64);
+    }
+
+    pub(crate) fn recorded(&self) {
+        self.activity.inc();
+    }
+}
@@ -0,0 +1,140 @@
+//! Threads for the work that would hold up the async runtime. Checking
+//! signatures and executing blocks run on pools of their own, so that
+//! messages keep being handled while a block is imported; the disk is
+//! read and written by the [`storage`](crate::storage) on threads of its
+//! own already. Work is handed over and its result sent back on a
+//! channel, see [`Pools::verify`] and [`Pools::execute`]; work already
+//! off the runtime enters a pool with [`Pools::crypto`] and
+//! [`Pools::execution`].
+//!
+//! Without pools, as in tests, the work runs where it is called from, or
+//! on the blocking threads of the runtime when it is handed over.
+
+use crate::StateError;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+/// Threads of each pool. 0 is one per core.
+///
+/// ```toml
+/// [pools]
+/// crypto = 2
+/// execution = 4
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// Checking the signatures of transactions and votes
+    pub crypto: usize,
+    /// Executing the transactions of blocks
+    pub execution: usize,
+}
+
+/// The pools of a node, see the [module](self)
+#[derive(Debug, Default)]
+pub struct Pools {
+    crypto: Option<ThreadPool>,
+    execution: Option<ThreadPool>,
+}
+
+impl Pools {
+    pub fn new(config: &PoolConfig) -> Result<Self, StateError> {
+        Ok(Self {
+            crypto: Some(pool("crypto", config.crypto)?),
+            execution: Some(pool("execution", config.execution)?),
+        })
+    }
+
+    /// Run `work` on the crypto pool, waiting for it here
+    pub fn crypto<T: Send>(&self, work: impl FnOnce() -> T + Send) -> T {
+        install(&self.crypto, work)
+    }
+
+    /// Run `work` on the execution pool, waiting for it here. Parallel
+    /// iterators within run on the pool as well.
+    pub fn execution<T: Send>(&self, work: impl FnOnce() -> T + Send) -> T {
+        install(&self.execution, work)
+    }
+
+    /// Hand `work` over to the crypto pool and wait for it without holding
+    /// up the runtime
+    pub async fn verify<T: Send + 'static>(&self, work: impl FnOnce() -> T + Send + 'static) -> T {
+        hand_over(&self.crypto, work).await
+    }
+
+    /// Hand `work` over to the execution pool and wait for it without
+    /// holding up the runtime
+    pub async fn execute<T: Send + 'static>(&self, work: impl FnOnce() -> T + Send + 'static) -> T {
+        hand_over(&self.execution, work).await
+    }
+
+    /// Threads of each pool, crypto then execution; 0 without pools
+    pub fn threads(&self) -> (usize, usize) {
+        let threads =
+            |pool: &Option<ThreadPool>| pool.as_ref().map_or(0, ThreadPool::current_num_threads);
+        (threads(&self.crypto), threads(&self.execution))
+    }
+}
+
+fn pool(name: &'static str, threads: usize) -> Result<ThreadPool, StateError> {
+    ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(move |index| format!("chaoschain-{}-{}", name, index))
+        .build()
+        .map_err(|e| StateError::Internal(format!("Starting the {} pool: {}", name, e)))
+}
+
+fn install<T: Send>(pool: &Option<ThreadPool>, work: impl FnOnce() -> T + Send) -> T {
+    match pool {
+        Some(pool) => pool.install(work),
+        None => work(),
+    }
+}
+
+async fn hand_over<T: Send + 'static>(
+    pool: &Option<ThreadPool>,
+    work: impl FnOnce() -> T + Send + 'static,
+) -> T {
+    let Some(pool) = pool else {
+        return tokio::task::spawn_blocking(work)
+            .await
+            .expect("work handed over does not panic");
+    };
+    let (done, result) = oneshot::channel();
+    pool.spawn(move || {
+        let _ = done.send(work());
+    });
+    result.await.expect("work handed over does not panic")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_work_runs_on_its_pool() {
+        let pools = Pools::new(&PoolConfig {
+            crypto: 1,
+            execution: 2,
+        })
+        .unwrap();
+        assert_eq!(pools.threads(), (1, 2));
+        let thread = || std::thread::current().name().map(str::to_string);
+        assert_eq!(
+            pools.verify(thread).await.as_deref(),
+            Some("chaoschain-crypto-0")
+        );
+        assert!(pools
+            .execute(thread)
+            .await
+            .is_some_and(|name| name.starts_with("chaoschain-execution-")));
+        assert_eq!(pools.crypto(thread).as_deref(), Some("chaoschain-crypto-0"));
+
+        // Without pools, where it is called from
+        let none = Pools::default();
+        assert_eq!(none.threads(), (0, 0));
+        assert_eq!(none.crypto(thread), thread());
+        assert_eq!(none.execute(|| 1 + 1).await, 2);
+    }
+}
@@ -0,0 +1,284 @@
+//! Rolling statistics of the chain as a whole: how many transactions and
+//! blocks go through, how long blocks take to finalize, how deep the
+//! mempool runs, how many validators vote and how often the chain
+//! reorganizes. Everything is tallied into ten second buckets as it
+//! happens, so any window up to a day is summed from its buckets rather
+//! than worked out again from the blocks.
+
+use chaoschain_core::Block;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+
+/// Length of a bucket
+const BUCKET_MS: u64 = 10_000;
+
+/// Longest window the buckets cover
+pub const MAX_WINDOW_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Proposals remembered for timing their finality
+const TIMED_PROPOSALS: usize = 1024;
+
+#[derive(Debug, Clone, Default)]
+struct Bucket {
+    /// Unix milliseconds, a multiple of the bucket length
+    start: u64,
+    blocks: u64,
+    transactions: u64,
+    intervals: u64,
+    interval_ms: u64,
+    timed_finality: u64,
+    finality_ms: u64,
+    max_finality_ms: u64,
+    depth_samples: u64,
+    depth: u64,
+    max_depth: u64,
+    reorgs: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MempoolDepth {
+    pub current: usize,
+    pub average: Option<f64>,
+    pub max: usize,
+}
+
+/// The chain over one window
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainSummary {
+    pub window_secs: u64,
+    /// Less than the window while the node has not been up that long
+    pub covered_secs: u64,
+    /// Height of the latest finalized block
+    pub height: Option<u64>,
+    pub blocks: u64,
+    pub transactions: u64,
+    /// Transactions finalized per second
+    pub tps: f64,
+    pub average_block_interval_ms: Option<u64>,
+    /// From proposal to finalization
+    pub average_finality_ms: Option<u64>,
+    pub max_finality_ms: Option<u64>,
+    pub mempool_depth: MempoolDepth,
+    /// Validators that voted
+    pub active_validators: usize,
+    /// Blocks finalized at or below the height already finalized
+    pub reorgs: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct ChainLedger {
+    buckets: VecDeque<Bucket>,
+    /// When the first thing was tallied
+    started_at: Option<u64>,
+    proposed_at: HashMap<[u8; 32], u64>,
+    proposal_order: VecDeque<[u8; 32]>,
+    /// Height and hash of the latest finalized block, and when it was
+    tip: Option<(u64, [u8; 32], u64)>,
+    voted_at: HashMap<String, u64>,
+    mempool_depth: usize,
+}
+
+impl ChainLedger {
+    /// A proposal for `block_hash` came in at `at_ms`
+    pub fn proposed(&mut self, block_hash: [u8; 32], at_ms: u64) {
+        self.bucket(at_ms);
+        if self.proposed_at.insert(block_hash, at_ms).is_none() {
+            self.proposal_order.push_back(block_hash);
+        }
+        if self.proposal_order.len() > TIMED_PROPOSALS {
+            if let Some(oldest) = self.proposal_order.pop_front() {
+                self.proposed_at.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn voted(&mut self, validator: &str, at_ms: u64) {
+        self.bucket(at_ms);
+        self.voted_at.insert(validator.to_string(), at_ms);
+    }
+
+    pub fn finalized(&mut self, block: &Block, at_ms: u64) {
+        let hash = block.hash();
+        let tip = self.tip;
+        if tip.is_some_and(|(_, tip_hash, _)| tip_hash == hash) {
+            return;
+        }
+        let proposed_at = self.proposed_at.remove(&hash);
+        let bucket = self.bucket(at_ms);
+        bucket.blocks += 1;
+        bucket.transactions += block.transactions.len() as u64;
+        if let Some(proposed_at) = proposed_at {
+            let finality = at_ms.saturating_sub(proposed_at);
+            bucket.timed_finality += 1;
+            bucket.finality_ms += finality;
+            bucket.max_finality_ms = bucket.max_finality_ms.max(finality);
+        }
+        if let Some((height, _, finalized_at)) = tip {
+            bucket.intervals += 1;
+            bucket.interval_ms += at_ms.saturating_sub(finalized_at);
+            if block.height <= height {
+                bucket.reorgs += 1;
+            }
+        }
+        self.tip = Some((block.height, hash, at_ms));
+    }
+
+    /// The mempool held `depth` transactions at `at_ms`
+    pub fn mempool_sampled(&mut self, depth: usize, at_ms: u64) {
+        let bucket = self.bucket(at_ms);
+        bucket.depth_samples += 1;
+        bucket.depth += depth as u64;
+        bucket.max_depth = bucket.max_depth.max(depth as u64);
+        self.mempool_depth = depth;
+    }
+
+    /// The chain over the `window_ms` up to `now_ms`, at most a day
+    pub fn summary(&self, window_ms: u64, now_ms: u64) -> ChainSummary {
+        let window_ms = window_ms.min(MAX_WINDOW_MS);
+        let since = now_ms.saturating_sub(window_ms);
+        let covered_ms = self
+            .started_at
+            .map_or(0, |started_at| now_ms.saturating_sub(since.max(started_at)));
+        let mut total = Bucket::default();
+        for bucket in self
+            .buckets
+            .iter()
+            .rev()
+            .take_while(|bucket| bucket.start + BUCKET_MS > since)
+        {
+            total.blocks += bucket.blocks;
+            total.transactions += bucket.transactions;
+            total.intervals += bucket.intervals;
+            total.interval_ms += bucket.interval_ms;
+            total.timed_finality += bucket.timed_finality;
+            total.finality_ms += bucket.finality_ms;
+            total.max_finality_ms = total.max_finality_ms.max(bucket.max_finality_ms);
+            total.depth_samples += bucket.depth_samples;
+            total.depth += bucket.depth;
+            total.max_depth = total.max_depth.max(bucket.max_depth);
+            total.reorgs += bucket.reorgs;
+        }
+        ChainSummary {
+            window_secs: window_ms / 1000,
+            covered_secs: covered_ms / 1000,
+            height: self.tip.map(|(height, _, _)| height),
+            blocks: total.blocks,
+            transactions: total.transactions,
+            tps: if covered_ms == 0 {
+                0.0
+            } else {
+                total.transactions as f64 * 1000.0 / covered_ms as f64
+            },
+            average_block_interval_ms: (total.intervals > 0)
+                .then(|| total.interval_ms / total.intervals),
+            average_finality_ms: (total.timed_finality > 0)
+                .then(|| total.finality_ms / total.timed_finality),
+            max_finality_ms: (total.timed_finality > 0).then_some(total.max_finality_ms),
+            mempool_depth: MempoolDepth {
+                current: self.mempool_depth,
+                average: (total.depth_samples > 0)
+                    .then(|| total.depth as f64 / total.depth_samples as f64),
+                max: total.max_depth as usize,
+            },
+            active_validators: self
+                .voted_at
+                .values()
+                .filter(|voted_at| **voted_at >= since)
+                .count(),
+            reorgs: total.reorgs,
+        }
+    }
+
+    /// The bucket `at_ms` falls in, dropping the ones past the longest
+    /// window. Anything older than the latest bucket is counted in it.
+    fn bucket(&mut self, at_ms: u64) -> &mut Bucket {
+        self.started_at.get_or_insert(at_ms);
+        let start = at_ms - at_ms % BUCKET_MS;
+        if self.buckets.back().is_none_or(|last| last.start < start) {
+            self.buckets.push_back(Bucket {
+                start,
+                ..Bucket::default()
+            });
+            while self
+                .buckets
+                .front()
+                .is_some_and(|first| first.start + BUCKET_MS + MAX_WINDOW_MS <= start)
+            {
+                self.buckets.pop_front();
+            }
+            self.voted_at
+                .retain(|_, voted_at| *voted_at + MAX_WINDOW_MS > at_ms);
+        }
+        self.buckets.back_mut().expect("a bucket was just pushed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_core::Transaction;
+
+    fn block(height: u64, transactions: usize) -> Block {
+        Block {
+            height,
+            parent_hash: [0; 32],
+            transactions: (0..transactions)
+                .map(|nonce| Transaction {
+                    sender: [1; 32],
+                    nonce: nonce as u64,
+                    payload: vec![].into(),
+                    signature: [0; 64],
+                    cosignatures: Vec::new(),
+                })
+                .collect(),
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: "dramatic".to_string(),
+            producer_id: "producer-1".to_string(),
+            innovation_level: 0,
+            producer_strategy: "".into(),
+            timestamp: height,
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_rolling_windows() {
+        let mut ledger = ChainLedger::default();
+        let start = 1_000_000_000;
+        for height in 1..=4u64 {
+            let block = block(height, 10);
+            let at = start + height * 30_000;
+            ledger.proposed(block.hash(), at - 2_000);
+            ledger.voted(&format!("validator-{}", height % 2), at - 1_000);
+            ledger.mempool_sampled(height as usize, at);
+            ledger.finalized(&block, at);
+        }
+        // The same block announced again counts once
+        ledger.finalized(&block(4, 10), start + 121_000);
+        // Another block at a height already finalized
+        ledger.finalized(&block(4, 0), start + 125_000);
+        let now = start + 130_000;
+
+        let minute = ledger.summary(60_000, now);
+        assert_eq!((minute.blocks, minute.transactions), (3, 20));
+        assert_eq!(minute.height, Some(4));
+        assert_eq!(minute.reorgs, 1);
+        assert_eq!(minute.active_validators, 2);
+        assert_eq!(minute.mempool_depth.current, 4);
+        assert_eq!(minute.mempool_depth.max, 4);
+        assert!((minute.tps - 20.0 / 60.0).abs() < 1e-9);
+
+        let hour = ledger.summary(3_600_000, now);
+        assert_eq!(hour.blocks, 5);
+        assert_eq!(hour.covered_secs, 102);
+        assert_eq!(hour.average_finality_ms, Some(2_000));
+        assert_eq!(hour.average_block_interval_ms, Some(23_750));
+        assert_eq!(hour.mempool_depth.average, Some(2.5));
+
+        // A day later, all of it has rolled out
+        let later = ledger.summary(60_000, now + MAX_WINDOW_MS);
+        assert_eq!((later.blocks, later.active_validators), (0, 0));
+    }
+}
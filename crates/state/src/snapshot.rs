@@ -0,0 +1,359 @@
+//! Snapshots cut into chunks for distribution. A manifest commits to the
+//! chunks through a merkle root, so every chunk can be checked on its own
+//! as it arrives, whichever peer it came from.
+
+use crate::{ChainMetadata, StateError, StateSnapshot};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// State entries per chunk
+pub const DEFAULT_CHUNK_ENTRIES: usize = 256;
+
+/// What a snapshot holds, without the state itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    pub height: u64,
+    pub state_root: [u8; 32],
+    pub chunk_count: u32,
+    /// Merkle root over the chunk hashes
+    pub chunks_root: [u8; 32],
+    pub metadata: ChainMetadata,
+    pub timestamp: u64,
+}
+
+impl SnapshotManifest {
+    /// Identifies the snapshot across peers
+    pub fn id(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.height.to_be_bytes());
+        hasher.update(self.state_root);
+        hasher.update(self.chunk_count.to_be_bytes());
+        hasher.update(self.chunks_root);
+        hasher.finalize().into()
+    }
+}
+
+/// A slice of the state entries of a snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotChunk {
+    pub index: u32,
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Sibling hashes from the chunk up to the chunks root, with whether the
+    /// sibling sits on the left
+    pub proof: Vec<([u8; 32], bool)>,
+}
+
+impl SnapshotChunk {
+    pub fn hash(&self) -> [u8; 32] {
+        chunk_hash(self.index, &self.entries)
+    }
+
+    /// Check the chunk against the manifest it claims to belong to
+    pub fn verify(&self, manifest: &SnapshotManifest) -> Result<(), StateError> {
+        if self.index >= manifest.chunk_count {
+            return Err(StateError::Internal(format!(
+                "Chunk {} of a snapshot with {} chunks",
+                self.index, manifest.chunk_count
+            )));
+        }
+        let root = self
+            .proof
+            .iter()
+            .fold(self.hash(), |hash, (sibling, left)| {
+                if *left {
+                    node_hash(sibling, &hash)
+                } else {
+                    node_hash(&hash, sibling)
+                }
+            });
+        if root != manifest.chunks_root {
+            return Err(StateError::Internal(format!(
+                "Chunk {} does not belong to the snapshot",
+                self.index
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn chunk_hash(index: u32, entries: &[(Vec<u8>, Vec<u8>)]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"chunk");
+    hasher.update(index.to_be_bytes());
+    for (key, value) in entries {
+        hasher.update((key.len() as u64).to_be_bytes());
+        hasher.update(key);
+        hasher.update((value.len() as u64).to_be_bytes());
+        hasher.update(value);
+    }
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"node");
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Levels of the merkle tree over `leaves`, leaves first. An odd node out is
+/// carried up as is.
+fn levels(leaves: Vec<[u8; 32]>) -> Vec<Vec<[u8; 32]>> {
+    let mut levels = vec![leaves];
+    while levels.last().is_some_and(|level| level.len() > 1) {
+        let next = levels
+            .last()
+            .expect("At least one level")
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+fn proof(levels: &[Vec<[u8; 32]>], mut index: usize) -> Vec<([u8; 32], bool)> {
+    let mut proof = Vec::new();
+    for level in &levels[..levels.len().saturating_sub(1)] {
+        let sibling = index ^ 1;
+        if let Some(hash) = level.get(sibling) {
+            proof.push((*hash, sibling < index));
+        }
+        index /= 2;
+    }
+    proof
+}
+
+/// A snapshot ready to be served chunk by chunk
+#[derive(Debug, Clone)]
+pub struct SnapshotArchive {
+    pub manifest: SnapshotManifest,
+    pub chunks: Vec<SnapshotChunk>,
+}
+
+impl SnapshotArchive {
+    /// Cut a snapshot into chunks of at most `entries` state entries
+    pub fn new(snapshot: StateSnapshot, entries: usize) -> Self {
+        let mut pairs: Vec<(Vec<u8>, Vec<u8>)> = snapshot.state_pairs.into_iter().collect();
+        pairs.sort();
+        let mut chunks: Vec<SnapshotChunk> = pairs
+            .chunks(entries.max(1))
+            .enumerate()
+            .map(|(index, entries)| SnapshotChunk {
+                index: index as u32,
+                entries: entries.to_vec(),
+                proof: Vec::new(),
+            })
+            .collect();
+        if chunks.is_empty() {
+            chunks.push(SnapshotChunk {
+                index: 0,
+                entries: Vec::new(),
+                proof: Vec::new(),
+            });
+        }
+        let levels = levels(chunks.iter().map(SnapshotChunk::hash).collect());
+        for (index, chunk) in chunks.iter_mut().enumerate() {
+            chunk.proof = proof(&levels, index);
+        }
+        let chunks_root = levels.last().expect("At least one level")[0];
+        Self {
+            manifest: SnapshotManifest {
+                height: snapshot.height,
+                state_root: snapshot.state_root,
+                chunk_count: chunks.len() as u32,
+                chunks_root,
+                metadata: snapshot.metadata,
+                timestamp: snapshot.timestamp,
+            },
+            chunks,
+        }
+    }
+
+    pub fn chunk(&self, index: u32) -> Option<&SnapshotChunk> {
+        self.chunks.get(index as usize)
+    }
+
+    /// Put a snapshot back together from verified chunks, in any order
+    pub fn assemble(
+        manifest: SnapshotManifest,
+        mut chunks: Vec<SnapshotChunk>,
+    ) -> Result<StateSnapshot, StateError> {
+        chunks.sort_by_key(|chunk| chunk.index);
+        chunks.dedup_by_key(|chunk| chunk.index);
+        if chunks.len() != manifest.chunk_count as usize {
+            return Err(StateError::Internal(format!(
+                "{} of {} chunks",
+                chunks.len(),
+                manifest.chunk_count
+            )));
+        }
+        for chunk in &chunks {
+            chunk.verify(&manifest)?;
+        }
+        Ok(StateSnapshot {
+            height: manifest.height,
+            state_root: manifest.state_root,
+            state_pairs: chunks.into_iter().flat_map(|chunk| chunk.entries).collect(),
+            metadata: manifest.metadata,
+            timestamp: manifest.timestamp,
+        })
+    }
+
+    /// Write the snapshot to `dir`, as `manifest.json` and a
+    /// `chunk-<index>.json` for each chunk
+    pub fn save(&self, dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(
+            dir.join("manifest.json"),
+            serde_json::to_vec_pretty(&self.manifest)?,
+        )?;
+        for chunk in &self.chunks {
+            std::fs::write(
+                dir.join(format!("chunk-{}.json", chunk.index)),
+                serde_json::to_vec(chunk)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Read back a snapshot `save` wrote, checking every chunk
+    pub fn open(dir: &Path) -> Result<Self, StateError> {
+        let read = |name: String| {
+            std::fs::read(dir.join(&name)).map_err(|e| {
+                StateError::Internal(format!(
+                    "Could not read {}: {}",
+                    dir.join(name).display(),
+                    e
+                ))
+            })
+        };
+        let manifest: SnapshotManifest = serde_json::from_slice(&read("manifest.json".into())?)
+            .map_err(|e| StateError::Internal(format!("Invalid manifest: {}", e)))?;
+        let chunks = (0..manifest.chunk_count)
+            .map(|index| {
+                let chunk: SnapshotChunk =
+                    serde_json::from_slice(&read(format!("chunk-{}.json", index))?).map_err(
+                        |e| StateError::Internal(format!("Invalid chunk {}: {}", index, e)),
+                    )?;
+                if chunk.index != index {
+                    return Err(StateError::Internal(format!(
+                        "chunk-{}.json holds chunk {}",
+                        index, chunk.index
+                    )));
+                }
+                chunk.verify(&manifest)?;
+                Ok(chunk)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { manifest, chunks })
+    }
+
+    /// The highest of the snapshots saved in `dir`, as
+    /// `snapshot-<height>` directories
+    pub fn latest(dir: &Path) -> Result<Option<Self>, StateError> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(StateError::Internal(e.to_string())),
+        };
+        let latest = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let height: u64 = name.to_str()?.strip_prefix("snapshot-")?.parse().ok()?;
+                Some((height, entry.path()))
+            })
+            .max_by_key(|(height, _)| *height);
+        latest.map(|(_, path)| Self::open(&path)).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_core::{Block, ChainConfig};
+    use std::collections::HashMap;
+
+    fn snapshot(height: u64) -> StateSnapshot {
+        let block = Block {
+            height,
+            parent_hash: [0; 32],
+            transactions: vec![],
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: "dramatic".to_string(),
+            producer_id: "producer-1".to_string(),
+            innovation_level: 0,
+            producer_strategy: "".into(),
+            timestamp: 0,
+            metadata: Default::default(),
+        };
+        StateSnapshot {
+            height,
+            state_root: [9; 32],
+            state_pairs: (0..10u8)
+                .map(|i| (vec![i], format!("value {}", i).into_bytes()))
+                .collect(),
+            metadata: ChainMetadata {
+                validators: HashMap::new(),
+                config: ChainConfig::default(),
+                last_block: block,
+            },
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_chunks_verify_on_their_own() {
+        let snapshot = snapshot(3);
+        let archive = SnapshotArchive::new(snapshot.clone(), 3);
+        assert_eq!(archive.manifest.chunk_count, 4);
+        for chunk in &archive.chunks {
+            chunk.verify(&archive.manifest).unwrap();
+        }
+
+        // A tampered chunk is caught without the others
+        let mut forged = archive.chunks[2].clone();
+        forged.entries[0].1 = b"value 1000".to_vec();
+        assert!(forged.verify(&archive.manifest).is_err());
+
+        let mut shuffled = archive.chunks.clone();
+        shuffled.reverse();
+        let restored = SnapshotArchive::assemble(archive.manifest.clone(), shuffled).unwrap();
+        assert_eq!(restored.state_pairs, snapshot.state_pairs);
+        assert!(SnapshotArchive::assemble(archive.manifest, archive.chunks[1..].to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_directories() {
+        let dir = std::env::temp_dir().join(format!("chaoschain-snapshots-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(SnapshotArchive::latest(&dir).unwrap().is_none());
+
+        for height in [2, 10, 9] {
+            SnapshotArchive::new(snapshot(height), 3)
+                .save(&dir.join(format!("snapshot-{}", height)))
+                .unwrap();
+        }
+        let latest = SnapshotArchive::latest(&dir).unwrap().unwrap();
+        assert_eq!(latest.manifest.height, 10);
+        assert_eq!(latest.chunks.len(), 4);
+
+        // A chunk changed on disk is caught as it is read
+        let path = dir.join("snapshot-10").join("chunk-1.json");
+        let mut chunk: SnapshotChunk =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        chunk.entries[0].1 = b"value 1000".to_vec();
+        std::fs::write(&path, serde_json::to_vec(&chunk).unwrap()).unwrap();
+        assert!(SnapshotArchive::latest(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
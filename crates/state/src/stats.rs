@@ -0,0 +1,249 @@
+//! Running statistics of each validator, kept up by the index as votes and
+//! proposals come in so the dashboard never goes back over raw blocks:
+//! what it approved and rejected, how many of the blocks proposed since it
+//! first voted it had a say on, how long it took to answer, and how it has
+//! been leaning lately.
+
+use chaoschain_core::NetworkEvent;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Proposals remembered for timing the votes on them
+const TIMED_PROPOSALS: usize = 1024;
+
+/// Votes the mood is read from
+const RECENT_VOTES: usize = 10;
+
+/// How a validator has been voting lately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mood {
+    /// Approved most of its recent blocks
+    Generous,
+    /// Rejected most of them
+    Ruthless,
+    /// Somewhere in between
+    Torn,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidatorStats {
+    pub validator: String,
+    pub approved: u64,
+    pub rejected: u64,
+    /// Blocks proposed since the first vote of the validator, that one
+    /// included
+    pub eligible: u64,
+    /// When it last voted (unix seconds)
+    pub last_vote_at: u64,
+    /// Proposals seen before it first voted
+    joined_after: u64,
+    /// Votes timed against their proposal, and the time they took
+    timed_votes: u64,
+    latency_ms: u64,
+    /// Latest decisions, newest last
+    recent: VecDeque<bool>,
+}
+
+impl ValidatorStats {
+    /// A validator not heard from yet
+    pub fn new(validator: impl Into<String>) -> Self {
+        Self {
+            validator: validator.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn votes(&self) -> u64 {
+        self.approved + self.rejected
+    }
+
+    /// Share of the blocks it could have voted on that it did (0.0 - 1.0)
+    pub fn participation(&self) -> f64 {
+        if self.eligible == 0 {
+            return 0.0;
+        }
+        (self.votes() as f64 / self.eligible as f64).min(1.0)
+    }
+
+    /// Mean time from proposal to vote, for the votes whose proposal was seen
+    pub fn average_latency_ms(&self) -> Option<u64> {
+        (self.timed_votes > 0).then(|| self.latency_ms / self.timed_votes)
+    }
+
+    /// None until it has voted
+    pub fn mood(&self) -> Option<Mood> {
+        if self.recent.is_empty() {
+            return None;
+        }
+        let approved = self.recent.iter().filter(|approved| **approved).count();
+        let share = approved as f64 / self.recent.len() as f64;
+        Some(if share >= 0.7 {
+            Mood::Generous
+        } else if share <= 0.3 {
+            Mood::Ruthless
+        } else {
+            Mood::Torn
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ValidatorLedger {
+    /// Blocks proposed so far
+    proposals: u64,
+    /// When the latest proposals came in (unix milliseconds)
+    proposed_at: HashMap<[u8; 32], u64>,
+    proposal_order: VecDeque<[u8; 32]>,
+    validators: HashMap<String, ValidatorStats>,
+}
+
+impl ValidatorLedger {
+    /// Take in `event`, seen at `at_ms`
+    pub fn observe(&mut self, event: &NetworkEvent, at_ms: u64) {
+        match event {
+            NetworkEvent::BlockProposal { block, .. } => {
+                self.proposals += 1;
+                for stats in self.validators.values_mut() {
+                    stats.eligible = self.proposals - stats.joined_after;
+                }
+                let hash = block.hash();
+                if self.proposed_at.insert(hash, at_ms).is_none() {
+                    self.proposal_order.push_back(hash);
+                }
+                if self.proposal_order.len() > TIMED_PROPOSALS {
+                    if let Some(oldest) = self.proposal_order.pop_front() {
+                        self.proposed_at.remove(&oldest);
+                    }
+                }
+            }
+            NetworkEvent::ValidationResult {
+                block_hash,
+                validation,
+            } => {
+                let proposals = self.proposals;
+                let stats = self
+                    .validators
+                    .entry(validation.validator.clone())
+                    .or_insert_with(|| ValidatorStats {
+                        validator: validation.validator.clone(),
+                        // The block it first votes on counts
+                        joined_after: proposals.saturating_sub(1),
+                        eligible: proposals.min(1),
+                        ..ValidatorStats::default()
+                    });
+                if validation.approved {
+                    stats.approved += 1;
+                } else {
+                    stats.rejected += 1;
+                }
+                stats.last_vote_at = at_ms / 1000;
+                if let Some(proposed_at) = self.proposed_at.get(block_hash) {
+                    stats.timed_votes += 1;
+                    stats.latency_ms += at_ms.saturating_sub(*proposed_at);
+                }
+                stats.recent.push_back(validation.approved);
+                if stats.recent.len() > RECENT_VOTES {
+                    stats.recent.pop_front();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn get(&self, validator: &str) -> Option<&ValidatorStats> {
+        self.validators.get(validator)
+    }
+
+    /// Every validator heard from, by id
+    pub fn all(&self) -> Vec<ValidatorStats> {
+        let mut all: Vec<ValidatorStats> = self.validators.values().cloned().collect();
+        all.sort_by(|a, b| a.validator.cmp(&b.validator));
+        all
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_core::{Block, ValidationDecision};
+
+    fn proposal(height: u64) -> (NetworkEvent, [u8; 32]) {
+        let block = Block {
+            height,
+            parent_hash: [0; 32],
+            transactions: vec![],
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: "dramatic".to_string(),
+            producer_id: "producer-1".to_string(),
+            innovation_level: 0,
+            producer_strategy: "".into(),
+            timestamp: height,
+            metadata: Default::default(),
+        };
+        let hash = block.hash();
+        let event = NetworkEvent::BlockProposal {
+            block: Box::new(block),
+            drama_level: 5,
+            producer_mood: "dramatic".to_string(),
+            producer_id: "producer-1".to_string(),
+        };
+        (event, hash)
+    }
+
+    fn vote(validator: &str, block_hash: [u8; 32], approved: bool) -> NetworkEvent {
+        NetworkEvent::ValidationResult {
+            block_hash,
+            validation: ValidationDecision {
+                approved,
+                reason: "vibes".to_string(),
+                meme_url: None,
+                drama_level: 5,
+                innovation_score: 5,
+                evolution_proposal: None,
+                validator: validator.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_validator_stats() {
+        let mut ledger = ValidatorLedger::default();
+        let (first, first_hash) = proposal(1);
+        ledger.observe(&first, 1_000);
+        ledger.observe(&vote("alice", first_hash, true), 1_400);
+
+        let (second, second_hash) = proposal(2);
+        ledger.observe(&second, 2_000);
+        ledger.observe(&vote("alice", second_hash, false), 2_200);
+        // Bob only shows up for the second block
+        ledger.observe(&vote("bob", second_hash, false), 3_000);
+
+        let (third, _) = proposal(3);
+        ledger.observe(&third, 4_000);
+
+        let alice = ledger.get("alice").unwrap();
+        assert_eq!((alice.approved, alice.rejected, alice.eligible), (1, 1, 3));
+        assert_eq!(alice.average_latency_ms(), Some(300));
+        assert!((alice.participation() - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(alice.mood(), Some(Mood::Torn));
+        assert_eq!(alice.last_vote_at, 2);
+
+        let bob = ledger.get("bob").unwrap();
+        assert_eq!((bob.votes(), bob.eligible), (1, 2));
+        assert_eq!(bob.average_latency_ms(), Some(1_000));
+        assert_eq!(bob.mood(), Some(Mood::Ruthless));
+
+        // A vote on a block proposed before the node came up is not timed
+        ledger.observe(&vote("carol", [9; 32], true), 5_000);
+        let carol = ledger.get("carol").unwrap();
+        assert_eq!(carol.average_latency_ms(), None);
+        assert_eq!(carol.participation(), 1.0);
+
+        let ids: Vec<String> = ledger.all().into_iter().map(|s| s.validator).collect();
+        assert_eq!(ids, ["alice", "bob", "carol"]);
+        assert!(ledger.get("dave").is_none());
+    }
+}
@@ -0,0 +1,594 @@
+//! Storage on disk that async tasks never wait on the disk for. Writes go
+//! in [`WriteBatch`]es to a writer on a thread of its own, through a queue
+//! of bounded depth: when the writer falls behind, whoever enqueues waits
+//! for room rather than the queue growing without end. The writer takes
+//! every batch queued at once, appends each to the log as one record with
+//! its checksum, and syncs the log once for all of them. A batch is in the
+//! store whole or not at all: on opening, the log is read back up to the
+//! first record that is cut short or fails its checksum, and cut there.
+//!
+//! Reads are served from the batches still queued, then from a cache of
+//! the values read lately, then from the log, where an index in memory
+//! says where the latest value of each key is. The log only grows; a key
+//! written again leaves its old value behind.
+
+use crate::StateError;
+use parking_lot::{Mutex, RwLock};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, warn};
+
+/// Bytes of a record before its body: the length of the body and the
+/// start of its SHA-256
+const RECORD_HEADER: usize = 4 + 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageConfig {
+    /// Batches queued before enqueuing waits for room
+    pub queue: usize,
+    /// Most batches committed with one sync
+    pub max_batch: usize,
+    /// Values kept in the read cache
+    pub cache: usize,
+    /// Whether a commit waits for the disk to have it. Without, a crash of
+    /// the machine may lose the latest batches, though never part of one.
+    pub sync: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            queue: 1024,
+            max_batch: 256,
+            cache: 4096,
+            sync: true,
+        }
+    }
+}
+
+/// Puts and deletes that are committed together
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteBatch {
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn put(mut self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
+        self.ops.push((key.into(), Some(value.into())));
+        self
+    }
+
+    pub fn delete(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.ops.push((key.into(), None));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// The body of its record, and where in it each value put starts
+    fn encode(&self) -> (Vec<u8>, Vec<usize>) {
+        let mut body = Vec::new();
+        let mut values = Vec::new();
+        body.extend_from_slice(&(self.ops.len() as u32).to_le_bytes());
+        for (key, value) in &self.ops {
+            body.push(value.is_some() as u8);
+            body.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            body.extend_from_slice(key);
+            if let Some(value) = value {
+                body.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                values.push(body.len());
+                body.extend_from_slice(value);
+            }
+        }
+        (body, values)
+    }
+
+    /// The batch of a record body, with where each value put starts in it
+    fn decode(body: &[u8]) -> Option<(Self, Vec<usize>)> {
+        fn take<'a>(body: &'a [u8], at: &mut usize, len: usize) -> Option<&'a [u8]> {
+            let bytes = body.get(*at..at.checked_add(len)?)?;
+            *at += len;
+            Some(bytes)
+        }
+        fn length(body: &[u8], at: &mut usize) -> Option<usize> {
+            Some(u32::from_le_bytes(take(body, at, 4)?.try_into().ok()?) as usize)
+        }
+        let mut at = 0;
+        let count = length(body, &mut at)?;
+        let mut batch = Self::new();
+        let mut values = Vec::new();
+        for _ in 0..count {
+            let put = take(body, &mut at, 1)?[0] == 1;
+            let len = length(body, &mut at)?;
+            let key = take(body, &mut at, len)?.to_vec();
+            if put {
+                let len = length(body, &mut at)?;
+                values.push(at);
+                let value = take(body, &mut at, len)?.to_vec();
+                batch.ops.push((key, Some(value)));
+            } else {
+                batch.ops.push((key, None));
+            }
+        }
+        Some((batch, values))
+    }
+}
+
+/// Where a value is in the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Location {
+    offset: u64,
+    len: u32,
+}
+
+/// The values read lately, the least recently used going first
+#[derive(Debug, Default)]
+struct Cache {
+    capacity: usize,
+    values: HashMap<Vec<u8>, (u64, Vec<u8>)>,
+    by_use: BTreeMap<u64, Vec<u8>>,
+    uses: u64,
+}
+
+impl Cache {
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.uses += 1;
+        let (used, value) = self.values.get_mut(key)?;
+        let key = self.by_use.remove(used)?;
+        *used = self.uses;
+        self.by_use.insert(self.uses, key);
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(&key);
+        self.uses += 1;
+        self.by_use.insert(self.uses, key.clone());
+        self.values.insert(key, (self.uses, value));
+        while self.values.len() > self.capacity {
+            let Some((_, oldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            self.values.remove(&oldest);
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some((used, _)) = self.values.remove(key) {
+            self.by_use.remove(&used);
+        }
+    }
+}
+
+/// The latest value queued for each key, with the number of its batch,
+/// until the batch is committed
+type Pending = HashMap<Vec<u8>, (u64, Option<Vec<u8>>)>;
+
+/// What readers and the writer share
+#[derive(Debug)]
+struct Tables {
+    path: PathBuf,
+    index: RwLock<HashMap<Vec<u8>, Location>>,
+    pending: Mutex<Pending>,
+    cache: Mutex<Cache>,
+    /// Milliseconds the writer holds each commit back, see
+    /// [`Storage::stall_writes`]
+    stall: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Request {
+    number: u64,
+    batch: WriteBatch,
+    committed: oneshot::Sender<Result<(), String>>,
+}
+
+#[derive(Debug)]
+struct Queue {
+    sender: mpsc::Sender<Request>,
+    /// Number of the next batch
+    next: u64,
+}
+
+/// A store on disk, see the [module](self). Clones share it; the writer
+/// stops once every clone is dropped, done with what was queued.
+#[derive(Debug, Clone)]
+pub struct Storage {
+    /// Enqueuing holds it, so that batches are numbered in queue order
+    queue: Arc<tokio::sync::Mutex<Queue>>,
+    /// For the depth of the queue, without waiting on who enqueues
+    depth: mpsc::Sender<Request>,
+    tables: Arc<Tables>,
+    config: StorageConfig,
+}
+
+/// A batch in the queue, to wait on until it is committed
+#[derive(Debug)]
+pub struct Committed(oneshot::Receiver<Result<(), String>>);
+
+impl Committed {
+    pub async fn wait(self) -> Result<(), StateError> {
+        match self.0.await {
+            Ok(result) => result.map_err(StateError::Internal),
+            Err(_) => Err(StateError::Internal(
+                "The storage writer stopped".to_string(),
+            )),
+        }
+    }
+}
+
+impl Storage {
+    /// Open the store with its log at `path`, created when missing, and
+    /// start its writer
+    pub fn open(path: impl AsRef<Path>, config: StorageConfig) -> Result<Self, StateError> {
+        let path = path.as_ref().to_path_buf();
+        let io = |e: std::io::Error| StateError::Internal(format!("{}: {}", path.display(), e));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(io)?;
+        let mut log = Vec::new();
+        file.read_to_end(&mut log).map_err(io)?;
+        let (index, end) = replay(&log);
+        if end < log.len() as u64 {
+            warn!(
+                "Cutting {} bytes of an unfinished commit off {}",
+                log.len() as u64 - end,
+                path.display()
+            );
+            file.set_len(end).map_err(io)?;
+        }
+        drop(log);
+
+        let tables = Arc::new(Tables {
+            path: path.clone(),
+            index: RwLock::new(index),
+            pending: Mutex::new(HashMap::new()),
+            cache: Mutex::new(Cache {
+                capacity: config.cache,
+                ..Cache::default()
+            }),
+            stall: AtomicU64::new(0),
+        });
+        let (sender, receiver) = mpsc::channel(config.queue.max(1));
+        let writer = Writer {
+            tables: tables.clone(),
+            file,
+            end,
+            config,
+        };
+        std::thread::Builder::new()
+            .name("storage-writer".to_string())
+            .spawn(move || writer.run(receiver))
+            .map_err(io)?;
+        Ok(Self {
+            queue: Arc::new(tokio::sync::Mutex::new(Queue {
+                sender: sender.clone(),
+                next: 0,
+            })),
+            depth: sender,
+            tables,
+            config,
+        })
+    }
+
+    /// Queue `batch`, waiting only for room in the queue. Reads see it
+    /// from now on.
+    pub async fn enqueue(&self, batch: WriteBatch) -> Result<Committed, StateError> {
+        let mut queue = self.queue.lock().await;
+        let number = queue.next;
+        queue.next += 1;
+        let (committed, receiver) = oneshot::channel();
+        let permit = queue
+            .sender
+            .reserve()
+            .await
+            .map_err(|_| StateError::Internal("The storage writer stopped".to_string()))?;
+        {
+            let mut pending = self.tables.pending.lock();
+            for (key, value) in &batch.ops {
+                pending.insert(key.clone(), (number, value.clone()));
+            }
+        }
+        permit.send(Request {
+            number,
+            batch,
+            committed,
+        });
+        Ok(Committed(receiver))
+    }
+
+    /// Queue `batch` and wait until it is committed
+    pub async fn write(&self, batch: WriteBatch) -> Result<(), StateError> {
+        self.enqueue(batch).await?.wait().await
+    }
+
+    /// Wait until everything queued so far is committed
+    pub async fn flush(&self) -> Result<(), StateError> {
+        self.write(WriteBatch::new()).await
+    }
+
+    /// The latest value of `key`, queued or committed
+    pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StateError> {
+        if let Some((_, value)) = self.tables.pending.lock().get(key) {
+            return Ok(value.clone());
+        }
+        if let Some(value) = self.tables.cache.lock().get(key) {
+            return Ok(Some(value));
+        }
+        let Some(location) = self.tables.index.read().get(key).copied() else {
+            return Ok(None);
+        };
+        let path = self.tables.path.clone();
+        let value = tokio::task::spawn_blocking(move || read_at(&path, location))
+            .await
+            .map_err(|e| StateError::Internal(e.to_string()))?
+            .map_err(|e| StateError::Internal(format!("{}: {}", self.tables.path.display(), e)))?;
+        // Cached only if no write moved the key meanwhile; the writer
+        // updates the cache under the same lock
+        let index = self.tables.index.read();
+        if index.get(key) == Some(&location) {
+            self.tables.cache.lock().insert(key.to_vec(), value.clone());
+        }
+        Ok(Some(value))
+    }
+
+    /// Batches waiting for the writer
+    pub fn queued(&self) -> usize {
+        self.config.queue.max(1) - self.depth.capacity()
+    }
+
+    /// Hold every commit back by `delay` before it goes to the log, as a
+    /// disk that stalls would, until called again with zero. The queue
+    /// fills meanwhile, and then whoever enqueues waits.
+    pub fn stall_writes(&self, delay: Duration) {
+        self.tables
+            .stall
+            .store(delay.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// The index of what `log` holds, and where its last whole record ends
+fn replay(log: &[u8]) -> (HashMap<Vec<u8>, Location>, u64) {
+    let mut index = HashMap::new();
+    let mut at = 0;
+    while let Some(header) = log.get(at..at + RECORD_HEADER) {
+        let len = u32::from_le_bytes(header[..4].try_into().expect("4 bytes")) as usize;
+        let Some(body) = log.get(at + RECORD_HEADER..at + RECORD_HEADER + len) else {
+            break;
+        };
+        if Sha256::digest(body)[..8] != header[4..] {
+            break;
+        }
+        let Some((batch, values)) = WriteBatch::decode(body) else {
+            break;
+        };
+        let start = (at + RECORD_HEADER) as u64;
+        apply(&mut index, &batch, &values, start);
+        at += RECORD_HEADER + len;
+    }
+    (index, at as u64)
+}
+
+/// Take the writes of `batch`, whose record body starts at `start` in the
+/// log, into `index`
+fn apply(index: &mut HashMap<Vec<u8>, Location>, batch: &WriteBatch, values: &[usize], start: u64) {
+    let mut values = values.iter();
+    for (key, value) in &batch.ops {
+        match value {
+            Some(value) => {
+                let offset = start + *values.next().expect("a value put has a place") as u64;
+                index.insert(
+                    key.clone(),
+                    Location {
+                        offset,
+                        len: value.len() as u32,
+                    },
+                );
+            }
+            None => {
+                index.remove(key);
+            }
+        }
+    }
+}
+
+fn read_at(path: &Path, location: Location) -> std::io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(location.offset))?;
+    let mut value = vec![0; location.len as usize];
+    file.read_exact(&mut value)?;
+    Ok(value)
+}
+
+struct Writer {
+    tables: Arc<Tables>,
+    file: File,
+    /// Where the log ends
+    end: u64,
+    config: StorageConfig,
+}
+
+impl Writer {
+    fn run(mut self, mut receiver: mpsc::Receiver<Request>) {
+        while let Some(first) = receiver.blocking_recv() {
+            let mut requests = vec![first];
+            while requests.len() < self.config.max_batch.max(1) {
+                match receiver.try_recv() {
+                    Ok(request) => requests.push(request),
+                    Err(_) => break,
+                }
+            }
+            let stall = self.tables.stall.load(Ordering::Relaxed);
+            if stall > 0 {
+                std::thread::sleep(Duration::from_millis(stall));
+            }
+            let result = self.commit(&requests).map_err(|e| {
+                error!(
+                    "Failed to commit {} batches to {}: {}",
+                    requests.len(),
+                    self.tables.path.display(),
+                    e
+                );
+                format!("{}: {}", self.tables.path.display(), e)
+            });
+            for request in requests {
+                let _ = request.committed.send(result.clone());
+            }
+        }
+    }
+
+    /// Append the batches of `requests`, one record each, and sync once.
+    /// Nothing of them stays in the log when it fails.
+    fn commit(&mut self, requests: &[Request]) -> std::io::Result<()> {
+        let mut records = Vec::new();
+        let mut placed = Vec::new();
+        for request in requests.iter().filter(|request| !request.batch.is_empty()) {
+            let (body, values) = request.batch.encode();
+            records.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            records.extend_from_slice(&Sha256::digest(&body)[..8]);
+            placed.push((request, values, self.end + records.len() as u64));
+            records.extend_from_slice(&body);
+        }
+        if !records.is_empty() {
+            let written = self.file.write_all(&records).and_then(|()| {
+                if self.config.sync {
+                    self.file.sync_data()
+                } else {
+                    Ok(())
+                }
+            });
+            if let Err(e) = written {
+                let _ = self.file.set_len(self.end);
+                return Err(e);
+            }
+            self.end += records.len() as u64;
+        }
+
+        let mut index = self.tables.index.write();
+        let mut cache = self.tables.cache.lock();
+        for (request, values, start) in &placed {
+            apply(&mut index, &request.batch, values, *start);
+            for (key, value) in &request.batch.ops {
+                match value {
+                    Some(value) => cache.insert(key.clone(), value.clone()),
+                    None => cache.remove(key),
+                }
+            }
+        }
+        drop(cache);
+        drop(index);
+        let mut pending = self.tables.pending.lock();
+        for request in requests {
+            for (key, _) in &request.batch.ops {
+                if pending
+                    .get(key)
+                    .is_some_and(|(number, _)| *number == request.number)
+                {
+                    pending.remove(key);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_storage_commits_whole_batches() {
+        let path = std::env::temp_dir().join(format!("chaoschain-storage-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = StorageConfig {
+            cache: 1,
+            ..StorageConfig::default()
+        };
+
+        let storage = Storage::open(&path, config).unwrap();
+        let first = storage
+            .enqueue(WriteBatch::new().put("a", "1").put("b", "2"))
+            .await
+            .unwrap();
+        // Queued writes are read before they are committed
+        assert_eq!(storage.get(b"a").await.unwrap(), Some(b"1".to_vec()));
+        storage
+            .write(WriteBatch::new().delete("a").put("b", "3").put("c", "4"))
+            .await
+            .unwrap();
+        first.wait().await.unwrap();
+        assert_eq!(storage.queued(), 0);
+        assert_eq!(storage.get(b"a").await.unwrap(), None);
+        // One cached, the other read from the log
+        assert_eq!(storage.get(b"b").await.unwrap(), Some(b"3".to_vec()));
+        assert_eq!(storage.get(b"c").await.unwrap(), Some(b"4".to_vec()));
+        assert_eq!(storage.get(b"b").await.unwrap(), Some(b"3".to_vec()));
+        drop(storage);
+
+        // A commit cut short by a crash is dropped whole
+        let whole = std::fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        let (body, _) = WriteBatch::new().put("d", "5").encode();
+        file.write_all(&(body.len() as u32).to_le_bytes()).unwrap();
+        file.write_all(&Sha256::digest(&body)[..8]).unwrap();
+        file.write_all(&body[..body.len() - 1]).unwrap();
+        drop(file);
+
+        let storage = Storage::open(&path, config).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), whole);
+        assert_eq!(storage.get(b"b").await.unwrap(), Some(b"3".to_vec()));
+        assert_eq!(storage.get(b"c").await.unwrap(), Some(b"4".to_vec()));
+        assert_eq!(storage.get(b"d").await.unwrap(), None);
+        storage.flush().await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stalled_writes_wait() {
+        let path = std::env::temp_dir().join(format!("chaoschain-stall-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let storage = Storage::open(&path, StorageConfig::default()).unwrap();
+
+        storage.stall_writes(Duration::from_millis(50));
+        let started = std::time::Instant::now();
+        let committed = storage
+            .enqueue(WriteBatch::new().put("a", "1"))
+            .await
+            .unwrap();
+        // What is held back is still read
+        assert_eq!(storage.get(b"a").await.unwrap(), Some(b"1".to_vec()));
+        committed.wait().await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        storage.stall_writes(Duration::ZERO);
+        storage
+            .write(WriteBatch::new().put("b", "2"))
+            .await
+            .unwrap();
+        assert_eq!(storage.get(b"b").await.unwrap(), Some(b"2".to_vec()));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
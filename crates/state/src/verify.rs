@@ -0,0 +1,673 @@
+//! Checking a data directory for the chain it holds, while its node is
+//! stopped, to find where it parted from the network.
+//!
+//! A node keeps no block store on disk. The blocks it saw proposed are in
+//! the activity journal, and the block each snapshot was taken at is in the
+//! snapshot, with the finality certificate when it was stored with one. The
+//! walk goes up from the lowest block, or from a snapshot, following parent
+//! hashes across the proposals: of those made at a height, the one the next
+//! block builds on is the one the chain took. Each block on the way has its
+//! producer signature, transaction signatures, reveals, leader ticket,
+//! beacon and certificate checked, or the group signature of the validators instead of
+//! the certificate when the genesis has their group key, and is held to the
+//! rules of the upgrades the genesis has active at its height. Each snapshot
+//! it passes has its state rebuilt against its root and its block compared
+//! with the one walked. The first thing that doesn't hold is the
+//! divergence.
+
+use crate::maintenance::{internal, read_journal, snapshots, ACTIVITY_JOURNAL};
+use crate::snapshot::SnapshotArchive;
+use crate::{StateError, StateStoreImpl};
+use chaoschain_core::beacon::Beacon;
+use chaoschain_core::commit::Commitments;
+use chaoschain_core::genesis::Genesis;
+use chaoschain_core::multisig::MultisigAccounts;
+use chaoschain_core::rotation::ValidatorKeys;
+use chaoschain_core::threshold::finality_message;
+use chaoschain_core::{Block, ChainConfig, NetworkEvent};
+use chaoschain_crypto::KeyManagerHandle;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+/// Where the walk went and what it found
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChainVerification {
+    /// Height the walk started at, none when there are no blocks
+    pub from: Option<u64>,
+    /// Last height that held up
+    pub to: Option<u64>,
+    pub blocks: usize,
+    /// Heights of the snapshots the walk passed and matched
+    pub checkpoints: Vec<u64>,
+    /// Blocks whose producer signature was checked, others have a producer
+    /// whose key is unknown
+    pub signatures_checked: usize,
+    /// Blocks that carried a finality certificate, which was checked
+    pub certified: usize,
+    /// Ranges of heights with no block on disk, walked over without
+    /// checking the linkage
+    pub gaps: Vec<(u64, u64)>,
+    /// Proposals above the last height that no block builds on yet, of
+    /// which the chain has not taken one
+    pub unsettled: usize,
+    pub divergence: Option<Divergence>,
+}
+
+/// The first thing on the chain that doesn't hold
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    pub height: u64,
+    /// Hex encoded hash of the block, when it is about one
+    pub block: Option<String>,
+    pub problem: String,
+}
+
+/// A snapshot, by what it holds or why it can't be used
+type Checkpoint = Result<Block, String>;
+
+/// Walk the chain in `data_dir`, from the latest snapshot at or below
+/// `from_height` when given, else from the lowest block. With `genesis`,
+/// producers and certificates are checked against its validators, with
+/// the keys the rotations of the walked blocks give them. Rotations below
+/// a snapshot started from are not seen, nor multisig accounts and commits
+/// made there or in a gap: transactions of the accounts and reveals are
+/// then not checked.
+pub fn verify_chain(
+    data_dir: &Path,
+    from_height: Option<u64>,
+    genesis: Option<&Genesis>,
+) -> Result<ChainVerification, StateError> {
+    if !data_dir.is_dir() {
+        return Err(internal(data_dir, "not a directory"));
+    }
+    let mut checkpoints = BTreeMap::new();
+    for (height, path) in snapshots(data_dir)? {
+        checkpoints.insert(height, open_checkpoint(height, &path));
+    }
+
+    let mut blocks: HashMap<[u8; 32], Block> = HashMap::new();
+    let path = data_dir.join(ACTIVITY_JOURNAL);
+    match std::fs::read(&path) {
+        Ok(bytes) => {
+            let (_, records) = read_journal(&bytes);
+            for activity in records {
+                if let NetworkEvent::BlockProposal { block, .. } = activity.event {
+                    blocks.entry(block.hash()).or_insert(*block);
+                }
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(internal(&path, e)),
+    }
+    // Stored blocks carry their certificate, where proposals don't
+    for block in checkpoints.values().flatten() {
+        blocks.insert(block.hash(), block.clone());
+    }
+    let mut heights: BTreeMap<u64, Vec<[u8; 32]>> = BTreeMap::new();
+    for (hash, block) in &blocks {
+        heights.entry(block.height).or_default().push(*hash);
+    }
+    for hashes in heights.values_mut() {
+        hashes.sort();
+    }
+
+    let mut walk = Walk {
+        blocks: &blocks,
+        heights: &heights,
+        checkpoints: &checkpoints,
+        genesis,
+        keys: genesis.map(ValidatorKeys::from_genesis),
+        accounts: None,
+        commitments: None,
+    };
+    let mut verification = ChainVerification::default();
+    let start = match from_height {
+        Some(from) => {
+            let Some((height, checkpoint)) = checkpoints.range(..=from).next_back() else {
+                return Err(StateError::Internal(format!(
+                    "No snapshot at or below height {} to start from",
+                    from
+                )));
+            };
+            match checkpoint {
+                Ok(block) => block.hash(),
+                Err(problem) => {
+                    verification.divergence = Some(Divergence {
+                        height: *height,
+                        block: None,
+                        problem: problem.clone(),
+                    });
+                    return Ok(verification);
+                }
+            }
+        }
+        None => {
+            let Some((height, _)) = heights.first_key_value() else {
+                return Ok(verification);
+            };
+            // Snapshots below the lowest block are passed over unchecked
+            if *height > 1 {
+                verification.gaps.push((1, height - 1));
+            }
+            match walk.choose(*height, None) {
+                Ok(Some(hash)) => hash,
+                Ok(None) => {
+                    verification.unsettled = heights[height].len();
+                    return Ok(verification);
+                }
+                Err(divergence) => {
+                    verification.divergence = Some(divergence);
+                    return Ok(verification);
+                }
+            }
+        }
+    };
+
+    let mut current = &blocks[&start];
+    verification.from = Some(current.height);
+    if from_height.is_none() && current.height == 1 {
+        walk.accounts = Some(MultisigAccounts::new());
+        walk.commitments = Some(Commitments::new());
+    }
+    if from_height.is_none() && current.height == 1 && current.parent_hash != [0; 32] {
+        verification.divergence = Some(divergence(
+            current,
+            "The first block has a parent hash, the journal does not reach genesis".to_string(),
+        ));
+        return Ok(verification);
+    }
+    loop {
+        if let Err(divergence) = walk.check(current, &mut verification) {
+            verification.divergence = Some(divergence);
+            return Ok(verification);
+        }
+        verification.blocks += 1;
+        verification.to = Some(current.height);
+        if let Some(keys) = &mut walk.keys {
+            keys.apply_block(current);
+        }
+        if let Some(accounts) = &mut walk.accounts {
+            accounts.apply_block(current);
+        }
+        if let Some(commitments) = &mut walk.commitments {
+            commitments.apply_block(current);
+        }
+
+        let next = current.height + 1;
+        let parent = current.hash();
+        let chosen = if heights.contains_key(&next) {
+            walk.choose(next, Some(&parent))
+        } else {
+            // Nothing on disk at the next height, the walk picks up at
+            // the next that has a block
+            match heights.range(next..).next() {
+                Some((height, _)) => {
+                    verification.gaps.push((next, height - 1));
+                    walk.accounts = None;
+                    walk.commitments = None;
+                    walk.choose(*height, None)
+                }
+                None => return Ok(verification),
+            }
+        };
+        match chosen {
+            Ok(Some(hash)) => current = &blocks[&hash],
+            Ok(None) => {
+                verification.unsettled = heights.range(next..).next().map_or(0, |(_, h)| h.len());
+                return Ok(verification);
+            }
+            Err(divergence) => {
+                verification.divergence = Some(divergence);
+                return Ok(verification);
+            }
+        }
+    }
+}
+
+struct Walk<'a> {
+    blocks: &'a HashMap<[u8; 32], Block>,
+    heights: &'a BTreeMap<u64, Vec<[u8; 32]>>,
+    checkpoints: &'a BTreeMap<u64, Checkpoint>,
+    genesis: Option<&'a Genesis>,
+    /// Keys of the validators of the genesis at the block walked
+    keys: Option<ValidatorKeys>,
+    /// Multisig accounts created by the blocks walked, none once the walk
+    /// does not hold every block from the first
+    accounts: Option<MultisigAccounts>,
+    /// Commits of the blocks walked waiting for their reveal, as the
+    /// accounts
+    commitments: Option<Commitments>,
+}
+
+impl Walk<'_> {
+    /// The block the chain took at `height`, of those building on `parent`
+    /// when it is known. None when proposals there are still open.
+    fn choose(
+        &self,
+        height: u64,
+        parent: Option<&[u8; 32]>,
+    ) -> Result<Option<[u8; 32]>, Divergence> {
+        let proposals = &self.heights[&height];
+        let candidates: Vec<[u8; 32]> = proposals
+            .iter()
+            .filter(|hash| parent.is_none_or(|parent| self.blocks[*hash].parent_hash == *parent))
+            .copied()
+            .collect();
+        if candidates.is_empty() {
+            return Err(Divergence {
+                height,
+                block: None,
+                problem: format!(
+                    "None of the {} blocks at height {} builds on block {}",
+                    proposals.len(),
+                    height,
+                    hex::encode(parent.copied().unwrap_or_default())
+                ),
+            });
+        }
+        if let Some(Ok(stored)) = self.checkpoints.get(&height) {
+            let stored = stored.hash();
+            if candidates.contains(&stored) {
+                return Ok(Some(stored));
+            }
+        }
+        if let [only] = candidates.as_slice() {
+            return Ok(Some(*only));
+        }
+        let built_on: Vec<[u8; 32]> = candidates
+            .iter()
+            .filter(|hash| {
+                self.heights.get(&(height + 1)).is_some_and(|children| {
+                    children
+                        .iter()
+                        .any(|child| self.blocks[child].parent_hash == **hash)
+                })
+            })
+            .copied()
+            .collect();
+        match built_on.as_slice() {
+            [] => Ok(None),
+            [taken] => Ok(Some(*taken)),
+            forks => Err(Divergence {
+                height,
+                block: None,
+                problem: format!(
+                    "The chain forks, {} blocks at height {} are built on: {}",
+                    forks.len(),
+                    height,
+                    forks.iter().map(hex::encode).collect::<Vec<_>>().join(", ")
+                ),
+            }),
+        }
+    }
+
+    /// Whether `block` holds up, on its own and against the snapshot at its
+    /// height
+    fn check(&self, block: &Block, verification: &mut ChainVerification) -> Result<(), Divergence> {
+        let hash = block.hash();
+        match self.checkpoints.get(&block.height) {
+            Some(Err(problem)) => return Err(divergence(block, problem.clone())),
+            Some(Ok(stored)) if stored.hash() != hash => {
+                return Err(divergence(
+                    block,
+                    format!(
+                        "snapshot-{} was taken at block {}, not this one",
+                        block.height,
+                        hex::encode(stored.hash())
+                    ),
+                ))
+            }
+            Some(Ok(_)) => verification.checkpoints.push(block.height),
+            None => {}
+        }
+
+        if let Some(genesis) = self.genesis {
+            let parent = self.blocks.get(&block.parent_hash);
+            genesis
+                .consensus
+                .forks
+                .at(block.height)
+                .check(block, parent.map(|parent| parent.timestamp))
+                .map_err(|e| divergence(block, e.to_string()))?;
+        }
+
+        if let Some(key) = self.producer_key(&block.producer_id) {
+            Signature::from_slice(&block.proposer_sig)
+                .and_then(|signature| key.verify(&block.signing_bytes(), &signature))
+                .map_err(|e| {
+                    divergence(
+                        block,
+                        format!(
+                            "The signature of producer {} does not hold: {}",
+                            block.producer_id, e
+                        ),
+                    )
+                })?;
+            verification.signatures_checked += 1;
+        }
+
+        let mut accounts = self.accounts.clone();
+        for tx in &block.transactions {
+            let signed = match &mut accounts {
+                Some(accounts) => {
+                    let signed = accounts.verify(tx);
+                    accounts.apply_transaction(tx);
+                    signed
+                }
+                None if tx.cosignatures.is_empty() => tx.verify(),
+                None => continue,
+            };
+            signed.map_err(|e| {
+                divergence(
+                    block,
+                    format!(
+                        "Transaction {} is not signed by its sender: {}",
+                        hex::encode(tx.hash()),
+                        e
+                    ),
+                )
+            })?;
+        }
+
+        if let Some(commitments) = &self.commitments {
+            commitments
+                .check_block(block)
+                .map_err(|e| divergence(block, e.to_string()))?;
+        }
+
+        if let Some(ticket) = &block.metadata.leader {
+            ticket
+                .verify(block.height, &block.parent_hash)
+                .map_err(|e| divergence(block, e.to_string()))?;
+            if self
+                .producer_key(&block.producer_id)
+                .is_some_and(|key| key.to_bytes() != ticket.public_key.to_bytes())
+            {
+                return Err(divergence(
+                    block,
+                    format!(
+                        "The leader ticket is not of the key of producer {}",
+                        block.producer_id
+                    ),
+                ));
+            }
+        }
+
+        // The beacon is the hash of the group signature, checked or not
+        if !Beacon::holds(block) {
+            return Err(divergence(
+                block,
+                "The beacon is not drawn from the group signature".to_string(),
+            ));
+        }
+
+        // The group signature stands for the votes, when the genesis has
+        // the key to check it with
+        let group = self.genesis.and_then(|genesis| genesis.threshold.as_ref());
+        if let (Some(group), Some(signature)) = (group, &block.metadata.group_signature) {
+            if !group.verify(&finality_message(block.height, &hash), &signature.0) {
+                return Err(divergence(
+                    block,
+                    "The group signature of the validators does not hold".to_string(),
+                ));
+            }
+            verification.certified += 1;
+            return Ok(());
+        }
+
+        let explanations = &block.metadata.explanations;
+        if explanations.is_empty() {
+            return Ok(());
+        }
+        let mut voters = HashSet::new();
+        for explanation in explanations {
+            explanation.verify(block.height, &hash).map_err(|e| {
+                divergence(
+                    block,
+                    format!("The vote of {} does not hold: {}", explanation.validator, e),
+                )
+            })?;
+            if !voters.insert(&explanation.validator) {
+                return Err(divergence(
+                    block,
+                    format!("{} voted twice", explanation.validator),
+                ));
+            }
+        }
+        let approvals = explanations.iter().filter(|e| e.approved).count();
+        if approvals <= explanations.len() - approvals {
+            return Err(divergence(
+                block,
+                format!(
+                    "Not final, {} approvals of {} votes",
+                    approvals,
+                    explanations.len()
+                ),
+            ));
+        }
+        if let (Some(genesis), Some(keys)) = (self.genesis, &self.keys) {
+            let mut approved = 0u64;
+            for explanation in explanations {
+                let validator = keys
+                    .validator_of(&explanation.public_key, block.height)
+                    .and_then(|name| genesis.validators.iter().find(|v| v.name == name))
+                    .ok_or_else(|| {
+                        divergence(
+                            block,
+                            format!(
+                                "{} voted with a key no validator of the genesis has",
+                                explanation.validator
+                            ),
+                        )
+                    })?;
+                if explanation.approved {
+                    approved += validator.stake;
+                }
+            }
+            let share = approved as f64 / genesis.total_stake().max(1) as f64;
+            if share < genesis.consensus.finality_threshold {
+                return Err(divergence(
+                    block,
+                    format!(
+                        "Not final, approved by {:.0}% of the stake where {:.0}% is needed",
+                        share * 100.0,
+                        genesis.consensus.finality_threshold * 100.0
+                    ),
+                ));
+            }
+        }
+        verification.certified += 1;
+        Ok(())
+    }
+
+    /// The key of a producer, its id when that is one, or the current key
+    /// of the validator of the genesis by that name
+    fn producer_key(&self, producer: &str) -> Option<VerifyingKey> {
+        let from_hex = |key: &str| {
+            hex::decode(key.strip_prefix("0x").unwrap_or(key))
+                .ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        };
+        from_hex(producer).or_else(|| {
+            let key = self.keys.as_ref()?.current(producer)?;
+            VerifyingKey::from_bytes(&key).ok()
+        })
+    }
+}
+
+fn divergence(block: &Block, problem: String) -> Divergence {
+    Divergence {
+        height: block.height,
+        block: Some(hex::encode(block.hash())),
+        problem,
+    }
+}
+
+/// The block the snapshot at `path` was taken at, once its state rebuilds
+/// to its root
+fn open_checkpoint(height: u64, path: &Path) -> Checkpoint {
+    let archive = SnapshotArchive::open(path).map_err(|e| format!("snapshot-{}: {}", height, e))?;
+    if archive.manifest.height != height {
+        return Err(format!(
+            "snapshot-{} holds the snapshot at height {}",
+            height, archive.manifest.height
+        ));
+    }
+    let snapshot = SnapshotArchive::assemble(archive.manifest, archive.chunks)
+        .map_err(|e| format!("snapshot-{}: {}", height, e))?;
+    let block = snapshot.metadata.last_block.clone();
+    if block.height != height {
+        return Err(format!(
+            "snapshot-{} was taken at a block of height {}",
+            height, block.height
+        ));
+    }
+    let root = hex::encode(snapshot.state_root);
+    StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new())
+        .recover_from_snapshot(snapshot)
+        .map_err(|e| {
+            format!(
+                "The state of snapshot-{} does not rebuild to its root {}: {}",
+                height, root, e
+            )
+        })?;
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::Activity;
+    use crate::snapshot::DEFAULT_CHUNK_ENTRIES;
+    use crate::{ChainMetadata, StateSnapshot};
+    use chaoschain_core::BlockMetadata;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn block(key: &SigningKey, height: u64, parent: Option<&Block>, mood: &str) -> Block {
+        let mut block = Block {
+            height,
+            parent_hash: parent.map_or([0; 32], Block::hash),
+            transactions: vec![],
+            proposer_sig: [0; 64],
+            state_root: [0; 32],
+            drama_level: 5,
+            producer_mood: mood.to_string(),
+            producer_id: hex::encode(key.verifying_key().to_bytes()),
+            innovation_level: 0,
+            producer_strategy: "".into(),
+            timestamp: 1_700_000_000 + height,
+            metadata: BlockMetadata::default(),
+        };
+        block.proposer_sig = key.sign(&block.signing_bytes()).to_bytes();
+        block
+    }
+
+    fn write_journal(dir: &Path, blocks: &[&Block]) {
+        let lines: Vec<String> = blocks
+            .iter()
+            .enumerate()
+            .map(|(seq, block)| {
+                serde_json::to_string(&Activity {
+                    seq: seq as u64,
+                    timestamp: block.timestamp,
+                    height: block.height,
+                    agent: block.producer_id.clone(),
+                    kind: "block_proposal".to_string(),
+                    text: String::new(),
+                    event: NetworkEvent::BlockProposal {
+                        block: Box::new((*block).clone()),
+                        drama_level: block.drama_level,
+                        producer_mood: block.producer_mood.clone(),
+                        producer_id: block.producer_id.clone(),
+                    },
+                })
+                .unwrap()
+            })
+            .collect();
+        std::fs::write(dir.join(ACTIVITY_JOURNAL), lines.join("\n")).unwrap();
+    }
+
+    fn snapshot(dir: &Path, block: &Block) {
+        let empty = StateStoreImpl::new(ChainConfig::default(), KeyManagerHandle::new());
+        let snapshot = StateSnapshot {
+            height: block.height,
+            state_root: empty.state_root(),
+            state_pairs: HashMap::new(),
+            metadata: ChainMetadata {
+                validators: HashMap::new(),
+                config: ChainConfig::default(),
+                last_block: block.clone(),
+            },
+            timestamp: 0,
+        };
+        SnapshotArchive::new(snapshot, DEFAULT_CHUNK_ENTRIES)
+            .save(
+                &dir.join(crate::maintenance::SNAPSHOTS)
+                    .join(format!("snapshot-{}", block.height)),
+            )
+            .unwrap();
+    }
+
+    fn data_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("chaoschain-verify-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let first = block(&key, 1, None, "calm");
+        let rejected = block(&key, 2, Some(&first), "furious");
+        let second = block(&key, 2, Some(&first), "dramatic");
+        let third = block(&key, 3, Some(&second), "calm");
+        let open = [
+            block(&key, 4, Some(&third), "bold"),
+            block(&key, 4, Some(&third), "shy"),
+        ];
+
+        let dir = data_dir("chain");
+        write_journal(
+            &dir,
+            &[&first, &rejected, &second, &third, &open[0], &open[1]],
+        );
+        snapshot(&dir, &second);
+        let verification = verify_chain(&dir, None, None).unwrap();
+        assert_eq!(verification.divergence, None);
+        assert_eq!((verification.from, verification.to), (Some(1), Some(3)));
+        assert_eq!(verification.blocks, 3);
+        assert_eq!(verification.checkpoints, vec![2]);
+        assert_eq!(verification.signatures_checked, 3);
+        assert_eq!(verification.unsettled, 2);
+
+        let verification = verify_chain(&dir, Some(3), None).unwrap();
+        assert_eq!((verification.from, verification.to), (Some(2), Some(3)));
+        assert!(verify_chain(&dir, Some(1), None).is_err());
+
+        // A block signed by someone else, then one that builds on nothing
+        let mut forged = third.clone();
+        forged.proposer_sig = SigningKey::from_bytes(&[8; 32])
+            .sign(&forged.signing_bytes())
+            .to_bytes();
+        write_journal(&dir, &[&first, &second, &forged]);
+        let divergence = verify_chain(&dir, None, None).unwrap().divergence.unwrap();
+        assert_eq!(divergence.height, 3);
+        assert!(
+            divergence.problem.contains("signature"),
+            "{}",
+            divergence.problem
+        );
+
+        let stray = block(&key, 3, Some(&rejected), "lost");
+        write_journal(&dir, &[&first, &second, &stray]);
+        let verification = verify_chain(&dir, None, None).unwrap();
+        assert_eq!(verification.to, Some(2));
+        assert_eq!(verification.divergence.unwrap().height, 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
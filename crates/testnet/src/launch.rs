@@ -0,0 +1,282 @@
+//! Running the nodes of a testnet, and stopping them again
+
+use anyhow::{anyhow, bail, Context, Result};
+use chaoschain_cli::Config;
+use futures::future::{select_all, BoxFuture};
+use futures::FutureExt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::{NodeSpec, Testnet};
+
+/// Header the admin API takes its token in
+const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+/// Prefix of the variables `node run` reads its overrides from, which
+/// would make every node of the testnet the same
+const OVERRIDES_PREFIX: &str = "CHAOSCHAIN_";
+
+/// Runs a node in the calling process until it is shut down, as
+/// `node run` would with the config
+pub type NodeRunner = Arc<dyn Fn(NodeSpec, Config) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+
+/// How the nodes of a testnet are started
+pub enum Launch {
+    /// Each node a `node run` of `binary`, its output in the node's log
+    Processes { binary: PathBuf },
+    /// Each node a task of this process. Their logs are this process's.
+    Tasks(NodeRunner),
+}
+
+enum Handle {
+    Process(Child),
+    Task(JoinHandle<Result<()>>),
+    Exited,
+}
+
+struct RunningNode {
+    spec: NodeSpec,
+    handle: Handle,
+}
+
+/// The nodes of a testnet while they run. Child processes are killed if
+/// this is dropped before they are shut down.
+pub struct RunningTestnet {
+    testnet: Testnet,
+    nodes: Vec<RunningNode>,
+}
+
+impl Testnet {
+    /// Start every node, printing each line a child process writes as
+    /// `<node> | <line>` when `tail` is set
+    pub async fn launch(&self, launch: &Launch, tail: bool) -> Result<RunningTestnet> {
+        let mut nodes = Vec::new();
+        for spec in &self.nodes {
+            let handle = match launch {
+                Launch::Processes { binary } => {
+                    Handle::Process(spawn_process(binary, spec, tail).await?)
+                }
+                Launch::Tasks(runner) => {
+                    let config = Config::load(&spec.config_path().to_string_lossy())?;
+                    Handle::Task(tokio::spawn(runner(spec.clone(), config)))
+                }
+            };
+            info!("🚀 Started {} ({})", spec.name, spec.role);
+            nodes.push(RunningNode {
+                spec: spec.clone(),
+                handle,
+            });
+        }
+        Ok(RunningTestnet {
+            testnet: self.clone(),
+            nodes,
+        })
+    }
+}
+
+async fn spawn_process(binary: &PathBuf, spec: &NodeSpec, tail: bool) -> Result<Child> {
+    let mut command = Command::new(binary);
+    command
+        .arg("--config")
+        .arg(spec.config_path())
+        .args(["node", "run"])
+        // Away from a personas file where the testnet was started
+        .current_dir(&spec.dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // Out of the terminal's process group, for Ctrl-C to reach only the
+    // orchestrator, which shuts the nodes down in turn
+    #[cfg(unix)]
+    command.process_group(0);
+    for (name, _) in std::env::vars_os() {
+        if name.to_string_lossy().starts_with(OVERRIDES_PREFIX) {
+            command.env_remove(name);
+        }
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Could not run {}", binary.display()))?;
+    for output in [
+        child
+            .stdout
+            .take()
+            .map(|out| Box::new(out) as Box<dyn AsyncRead + Send + Unpin>),
+        child
+            .stderr
+            .take()
+            .map(|err| Box::new(err) as Box<dyn AsyncRead + Send + Unpin>),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        let log = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(spec.log_path())
+            .await?;
+        tokio::spawn(copy_lines(output, log, spec.name.clone(), tail));
+    }
+    Ok(child)
+}
+
+/// Append what a node writes to its log, line by line
+async fn copy_lines(
+    output: Box<dyn AsyncRead + Send + Unpin>,
+    mut log: tokio::fs::File,
+    name: String,
+    tail: bool,
+) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if tail {
+            println!("{} | {}", name, line);
+        }
+        if log
+            .write_all(format!("{}\n", line).as_bytes())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+impl RunningTestnet {
+    pub fn testnet(&self) -> &Testnet {
+        &self.testnet
+    }
+
+    /// Wait until the admin API of every node takes connections, failing
+    /// when a node exits first or `timeout` passes
+    pub async fn wait_ready(&mut self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        for node in &mut self.nodes {
+            loop {
+                let exited = match &mut node.handle {
+                    Handle::Process(child) => child.try_wait()?.is_some(),
+                    Handle::Task(task) => task.is_finished(),
+                    Handle::Exited => true,
+                };
+                if exited {
+                    bail!(
+                        "{} exited before it was up, see {}",
+                        node.spec.name,
+                        node.spec.log_path().display()
+                    );
+                }
+                if TcpStream::connect(("127.0.0.1", node.spec.ports.admin))
+                    .await
+                    .is_ok()
+                {
+                    break;
+                }
+                if Instant::now() >= deadline {
+                    bail!("{} was not up after {:?}", node.spec.name, timeout);
+                }
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// The first node to exit, and how it went, or `None` when none runs
+    pub async fn first_exit(&mut self) -> Option<(String, Result<()>)> {
+        let exits: Vec<BoxFuture<'_, Result<()>>> = self
+            .nodes
+            .iter_mut()
+            .filter_map(|node| match &mut node.handle {
+                Handle::Process(child) => Some(
+                    async move {
+                        let status = child.wait().await?;
+                        if status.success() {
+                            Ok(())
+                        } else {
+                            Err(anyhow!("Exited with {}", status))
+                        }
+                    }
+                    .boxed(),
+                ),
+                Handle::Task(task) => Some(
+                    async move { task.await.map_err(|e| anyhow!("Task failed: {}", e))? }.boxed(),
+                ),
+                Handle::Exited => None,
+            })
+            .collect();
+        if exits.is_empty() {
+            return None;
+        }
+        let (result, index, _) = select_all(exits).await;
+        let node = self
+            .nodes
+            .iter_mut()
+            .filter(|node| !matches!(node.handle, Handle::Exited))
+            .nth(index)?;
+        node.handle = Handle::Exited;
+        Some((node.spec.name.clone(), result))
+    }
+
+    /// Ask every node to shut down through its admin API, then kill those
+    /// still running after `timeout`
+    pub async fn shutdown(mut self, timeout: Duration) {
+        for node in &self.nodes {
+            if matches!(node.handle, Handle::Exited) {
+                continue;
+            }
+            if let Err(e) = request_shutdown(&node.spec).await {
+                warn!("Could not ask {} to shut down: {:#}", node.spec.name, e);
+            }
+        }
+        let deadline = Instant::now() + timeout;
+        for node in &mut self.nodes {
+            let stopped = match &mut node.handle {
+                Handle::Process(child) => {
+                    match tokio::time::timeout_at(deadline, child.wait()).await {
+                        Ok(_) => true,
+                        Err(_) => {
+                            let _ = child.kill().await;
+                            false
+                        }
+                    }
+                }
+                Handle::Task(task) => match tokio::time::timeout_at(deadline, &mut *task).await {
+                    Ok(_) => true,
+                    Err(_) => {
+                        task.abort();
+                        false
+                    }
+                },
+                Handle::Exited => continue,
+            };
+            if stopped {
+                info!("🛑 {} shut down", node.spec.name);
+            } else {
+                warn!(
+                    "{} did not shut down in {:?}, killed it",
+                    node.spec.name, timeout
+                );
+            }
+            node.handle = Handle::Exited;
+        }
+    }
+}
+
+/// Ask `node` to shut down as Ctrl-C would, through its admin API
+pub async fn request_shutdown(node: &NodeSpec) -> Result<()> {
+    reqwest::Client::new()
+        .post(format!("{}/admin/shutdown", node.admin_url()))
+        .header(ADMIN_TOKEN_HEADER, &node.admin_token)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
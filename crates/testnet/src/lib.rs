@@ -0,0 +1,438 @@
+//! Local testnets of several nodes on one machine. A [`TestnetPlan`]
+//! generates one into a directory: a genesis whose validators are the
+//! validator nodes, a keystore with the key of each, free ports for the
+//! listeners of every node and a config file per node, with the first
+//! node for the bootnode of the others. What was generated is kept in
+//! `testnet.json`, for the nodes to be started again or stopped from
+//! another terminal.
+//!
+//! Each validator node runs the validator of its name, which votes with
+//! its key of the genesis through the config's `[signers]`, so the nodes
+//! count each other's votes and finalize one chain.
+//!
+//! [`Testnet::launch`] starts the nodes, as child processes of a
+//! `chaoschain` binary or as tasks of the calling process, and the
+//! [`RunningTestnet`] it returns tails their logs and shuts them down:
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use chaoschain_testnet::{Launch, TestnetPlan};
+//! use std::time::Duration;
+//!
+//! let testnet = TestnetPlan::default().generate("testnet".as_ref())?;
+//! let launch = Launch::Processes {
+//!     binary: "target/debug/chaoschain".into(),
+//! };
+//! let mut running = testnet.launch(&launch, true).await?;
+//! running.wait_ready(Duration::from_secs(30)).await?;
+//! running.shutdown(Duration::from_secs(15)).await;
+//! # Ok(())
+//! # }
+//! ```
+
+mod launch;
+pub mod ports;
+
+pub use launch::{request_shutdown, Launch, NodeRunner, RunningTestnet};
+
+use anyhow::{bail, Context, Result};
+use chaoschain_cli::{AdminConfig, Config, NodeConfig, SignerConfig};
+use chaoschain_core::genesis::{ConsensusParams, Genesis, GenesisValidator};
+use chaoschain_core::governance::GovernanceParams;
+use chaoschain_core::staking::StakingParams;
+use chaoschain_core::vote::prove_possession;
+use chaoschain_crypto::keystore::Keystore;
+use ed25519_dalek::SigningKey;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File the testnet is described in, at the top of its directory
+pub const MANIFEST_FILE: &str = "testnet.json";
+/// File of the genesis the nodes share, beside the manifest
+pub const GENESIS_FILE: &str = "genesis.json";
+/// Listeners of a node: web, metrics, admin and p2p
+pub const PORTS_PER_NODE: usize = 4;
+
+/// What a node of the testnet runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeRole {
+    /// A validator of the genesis
+    Validator,
+    /// A block producer, which does not vote
+    Producer,
+}
+
+impl std::fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            NodeRole::Validator => "validator",
+            NodeRole::Producer => "producer",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodePorts {
+    pub web: u16,
+    pub metrics: u16,
+    pub admin: u16,
+    pub p2p: u16,
+}
+
+/// One node of a testnet, in a directory of its own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeSpec {
+    pub name: String,
+    pub role: NodeRole,
+    pub dir: PathBuf,
+    pub ports: NodePorts,
+    /// Token of its admin API, which `down` shuts it down through
+    pub admin_token: String,
+}
+
+impl NodeSpec {
+    pub fn config_path(&self) -> PathBuf {
+        self.dir.join("chaoschain.toml")
+    }
+
+    pub fn data_dir(&self) -> PathBuf {
+        self.dir.join("data")
+    }
+
+    /// The keystore holding the key of a validator node
+    pub fn keystore_dir(&self) -> PathBuf {
+        self.data_dir().join("keys")
+    }
+
+    /// The cast of a validator node: its validator, named as the node
+    pub fn personas_path(&self) -> PathBuf {
+        self.dir.join("personas.toml")
+    }
+
+    /// What the node printed, appended to on every launch
+    pub fn log_path(&self) -> PathBuf {
+        self.dir.join("node.log")
+    }
+
+    pub fn web_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.ports.web)
+    }
+
+    pub fn admin_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.ports.admin)
+    }
+
+    pub fn p2p_address(&self) -> String {
+        format!("/ip4/127.0.0.1/tcp/{}", self.ports.p2p)
+    }
+}
+
+/// A testnet as generated, and as `testnet.json` keeps it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Testnet {
+    pub chain_id: String,
+    pub dir: PathBuf,
+    /// Hash of the genesis, hex encoded
+    pub genesis_hash: String,
+    pub nodes: Vec<NodeSpec>,
+}
+
+impl Testnet {
+    /// The testnet generated into `dir`
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("No testnet in {}", dir.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("{} is not a testnet", path.display()))
+    }
+
+    fn save(&self) -> Result<()> {
+        std::fs::write(
+            self.dir.join(MANIFEST_FILE),
+            serde_json::to_string_pretty(self)? + "\n",
+        )?;
+        Ok(())
+    }
+
+    pub fn genesis_path(&self) -> PathBuf {
+        self.dir.join(GENESIS_FILE)
+    }
+
+    /// The node of `name`
+    pub fn node(&self, name: &str) -> Option<&NodeSpec> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+}
+
+/// How many nodes of each role to generate, and with what
+#[derive(Debug, Clone)]
+pub struct TestnetPlan {
+    pub chain_id: String,
+    pub validators: u32,
+    pub producers: u32,
+    /// Ports are handed out from here up
+    pub base_port: u16,
+    /// Stake of each validator of the genesis
+    pub stake: u64,
+    /// Whether the nodes serve the web UI
+    pub web: bool,
+}
+
+impl Default for TestnetPlan {
+    fn default() -> Self {
+        Self {
+            chain_id: "chaoschain-testnet".to_string(),
+            validators: 4,
+            producers: 1,
+            base_port: 4100,
+            stake: 100,
+            web: false,
+        }
+    }
+}
+
+impl TestnetPlan {
+    /// Write the testnet into `dir`, which must hold no testnet yet
+    pub fn generate(&self, dir: &Path) -> Result<Testnet> {
+        if self.validators == 0 {
+            bail!("A testnet needs at least one validator");
+        }
+        if dir.join(MANIFEST_FILE).exists() {
+            bail!("{} already holds a testnet", dir.display());
+        }
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create {}", dir.display()))?;
+        // The config files name everything by absolute path, for the nodes
+        // to run from anywhere
+        let dir = dir.canonicalize()?;
+
+        let roles = (1..=self.validators)
+            .map(|index| (format!("validator-{}", index), NodeRole::Validator))
+            .chain(
+                (1..=self.producers)
+                    .map(|index| (format!("producer-{}", index), NodeRole::Producer)),
+            );
+        let count = (self.validators + self.producers) as usize;
+        let ports = ports::allocate(self.base_port, count * PORTS_PER_NODE)?;
+        let nodes: Vec<NodeSpec> = roles
+            .zip(ports.chunks(PORTS_PER_NODE))
+            .map(|((name, role), ports)| NodeSpec {
+                dir: dir.join(&name),
+                name,
+                role,
+                ports: NodePorts {
+                    web: ports[0],
+                    metrics: ports[1],
+                    admin: ports[2],
+                    p2p: ports[3],
+                },
+                admin_token: token(),
+            })
+            .collect();
+
+        let mut validators = Vec::new();
+        let mut keys = Vec::new();
+        for node in nodes.iter().filter(|node| node.role == NodeRole::Validator) {
+            let key = SigningKey::generate(&mut OsRng);
+            validators.push(GenesisValidator {
+                name: node.name.clone(),
+                public_key: hex::encode(key.verifying_key().to_bytes()),
+                stake: self.stake,
+                proof_of_possession: hex::encode(prove_possession(&key, &node.name)?),
+            });
+            keys.push(key);
+        }
+        let genesis = Genesis {
+            chain_id: self.chain_id.clone(),
+            genesis_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            consensus: ConsensusParams::default(),
+            validators,
+            allocations: Vec::new(),
+            threshold: None,
+            governance: GovernanceParams::default(),
+            staking: StakingParams::default(),
+        };
+        let problems = genesis.problems();
+        if !problems.is_empty() {
+            bail!(
+                "The genesis would not start a network:\n{}",
+                problems.join("\n")
+            );
+        }
+        let genesis_path = dir.join(GENESIS_FILE);
+        std::fs::write(
+            &genesis_path,
+            serde_json::to_string_pretty(&genesis)? + "\n",
+        )?;
+
+        let bootnode = nodes[0].p2p_address();
+        for (index, node) in nodes.iter().enumerate() {
+            std::fs::create_dir_all(node.data_dir())?;
+            let mut config = Config {
+                data_dir: node.data_dir().to_string_lossy().into_owned(),
+                web_port: node.ports.web,
+                metrics_port: Some(node.ports.metrics),
+                admin: AdminConfig {
+                    port: Some(node.ports.admin),
+                    token: Some(node.admin_token.clone()),
+                    profiling: false,
+                },
+                node: NodeConfig {
+                    // A validator node runs its validator as a persona
+                    validators: 0,
+                    producers: u32::from(node.role == NodeRole::Producer),
+                    web: self.web,
+                    genesis: Some(genesis_path.to_string_lossy().into_owned()),
+                    ..NodeConfig::default()
+                },
+                ..Config::default()
+            };
+            // A validator node runs the validator of the genesis of its
+            // name, signing with its key there
+            if let Some(key) = keys.get(index) {
+                Keystore::new(node.keystore_dir()).add(&node.name, key, None, None)?;
+                std::fs::write(
+                    node.personas_path(),
+                    format!(
+                        "[[persona]]\nname = \"{}\"\nrole = \"validator\"\n",
+                        node.name
+                    ),
+                )?;
+                config.node.personas = Some(node.personas_path().to_string_lossy().into_owned());
+                config.signers.insert(
+                    node.name.clone(),
+                    SignerConfig::Keystore {
+                        dir: node.keystore_dir().to_string_lossy().into_owned(),
+                        key: node.name.clone(),
+                        password_file: None,
+                    },
+                );
+            }
+            config.network.listen = vec![node.p2p_address()];
+            if index > 0 {
+                config.network.bootnodes = vec![bootnode.clone()];
+            }
+            config
+                .validate()
+                .with_context(|| format!("Invalid config for {}", node.name))?;
+            std::fs::write(
+                node.config_path(),
+                format!(
+                    "# {} of testnet {}, generated by `chaoschain testnet up`\n\n{}",
+                    node.name,
+                    self.chain_id,
+                    toml::to_string(&config)?
+                ),
+            )?;
+        }
+
+        let testnet = Testnet {
+            chain_id: self.chain_id.clone(),
+            dir,
+            genesis_hash: hex::encode(genesis.hash()),
+            nodes,
+        };
+        testnet.save()?;
+        Ok(testnet)
+    }
+}
+
+/// A fresh admin token
+fn token() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_cli::Personas;
+    use std::collections::HashSet;
+
+    fn generated(name: &str, plan: &TestnetPlan) -> Testnet {
+        let dir = std::env::temp_dir().join(format!("testnet-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        plan.generate(&dir).unwrap()
+    }
+
+    #[test]
+    fn test_generate_testnet() {
+        let plan = TestnetPlan {
+            validators: 3,
+            producers: 2,
+            ..TestnetPlan::default()
+        };
+        let testnet = generated("generate", &plan);
+        assert_eq!(testnet.nodes.len(), 5);
+        let genesis: Genesis =
+            serde_json::from_str(&std::fs::read_to_string(testnet.genesis_path()).unwrap())
+                .unwrap();
+        assert!(genesis.problems().is_empty());
+        assert_eq!(genesis.validators.len(), 3);
+        assert_eq!(hex::encode(genesis.hash()), testnet.genesis_hash);
+
+        // No two listeners share a port
+        let ports: HashSet<u16> = testnet
+            .nodes
+            .iter()
+            .flat_map(|node| {
+                let ports = node.ports;
+                [ports.web, ports.metrics, ports.admin, ports.p2p]
+            })
+            .collect();
+        assert_eq!(ports.len(), 5 * PORTS_PER_NODE);
+
+        for node in &testnet.nodes {
+            let config = Config::load(node.config_path().to_str().unwrap()).unwrap();
+            assert_eq!(config.admin.port, Some(node.ports.admin));
+            assert_eq!(config.network.listen, vec![node.p2p_address()]);
+            assert_eq!(config.node.validators, 0);
+            if node.name != testnet.nodes[0].name {
+                assert_eq!(
+                    config.network.bootnodes,
+                    vec![testnet.nodes[0].p2p_address()]
+                );
+            }
+        }
+
+        // Each validator node signs as its validator with the key the
+        // genesis lists for it
+        for node in testnet
+            .nodes
+            .iter()
+            .filter(|node| node.role == NodeRole::Validator)
+        {
+            let config = Config::load(node.config_path().to_str().unwrap()).unwrap();
+            let personas = Personas::load(config.node.personas.as_deref().unwrap()).unwrap();
+            assert_eq!(personas.personas.len(), 1);
+            assert_eq!(personas.personas[0].name, node.name);
+            let Some(SignerConfig::Keystore { dir, key, .. }) = config.signers.get(&node.name)
+            else {
+                panic!("{} has no keystore signer", node.name);
+            };
+            let signing = Keystore::new(dir).load(key, None).unwrap();
+            let listed = genesis
+                .validators
+                .iter()
+                .find(|validator| validator.name == node.name)
+                .unwrap();
+            assert_eq!(
+                hex::encode(signing.verifying_key().to_bytes()),
+                listed.public_key
+            );
+        }
+
+        let loaded = Testnet::load(&testnet.dir).unwrap();
+        assert_eq!(loaded.nodes.len(), 5);
+        assert!(plan.generate(&testnet.dir).is_err());
+        std::fs::remove_dir_all(&testnet.dir).unwrap();
+    }
+}
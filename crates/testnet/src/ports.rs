@@ -0,0 +1,43 @@
+//! Ports for the listeners of a testnet, free when they are handed out
+
+use anyhow::{bail, Result};
+use std::net::TcpListener;
+
+/// `count` ports from `base` up that nothing listens on, skipping those
+/// taken
+pub fn allocate(base: u16, count: usize) -> Result<Vec<u16>> {
+    let mut ports = Vec::with_capacity(count);
+    for port in base..=u16::MAX {
+        if ports.len() == count {
+            break;
+        }
+        // Released as the listener drops, for the node to take
+        if TcpListener::bind(("127.0.0.1", port)).is_ok() {
+            ports.push(port);
+        }
+    }
+    if ports.len() < count {
+        bail!(
+            "Only {} of {} ports are free from {}",
+            ports.len(),
+            count,
+            base
+        );
+    }
+    Ok(ports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate_skips_taken_ports() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let ports = allocate(port, 3).unwrap();
+        assert_eq!(ports.len(), 3);
+        assert!(!ports.contains(&port));
+        assert!(ports.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,274 @@
+//! Builders of transactions and blocks. Everything has a default that
+//! holds, so a test names only what it is about, and what is built is
+//! signed unless the test asks for it not to be.
+
+use crate::fixtures::{self, GENESIS_TIME, PRODUCER, SENDER};
+use chaoschain_core::multisig::{Cosignature, MultisigAccount};
+use chaoschain_core::{Block, BlockMetadata, Bytes, Transaction};
+use ed25519_dalek::{Signer, SigningKey};
+
+/// A transaction, by default of nonce 0 and an empty payload, sent and
+/// signed by the key of [`SENDER`]
+#[derive(Debug, Clone)]
+pub struct TransactionBuilder {
+    key: SigningKey,
+    account: Option<MultisigAccount>,
+    cosigners: Vec<SigningKey>,
+    nonce: u64,
+    payload: Vec<u8>,
+    signed: bool,
+}
+
+impl Default for TransactionBuilder {
+    fn default() -> Self {
+        Self {
+            key: fixtures::key(SENDER),
+            account: None,
+            cosigners: Vec::new(),
+            nonce: 0,
+            payload: Vec::new(),
+            signed: true,
+        }
+    }
+}
+
+impl TransactionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sent and signed by `key`
+    pub fn signer(mut self, key: &SigningKey) -> Self {
+        self.key = key.clone();
+        self
+    }
+
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    pub fn payload(mut self, payload: impl Into<Vec<u8>>) -> Self {
+        self.payload = payload.into();
+        self
+    }
+
+    /// Sent from `account`, which signs through its
+    /// [cosigners](Self::cosigner) instead of the signer
+    pub fn account(mut self, account: &MultisigAccount) -> Self {
+        self.account = Some(account.clone());
+        self
+    }
+
+    /// Cosigned by the owner of `key`
+    pub fn cosigner(mut self, key: &SigningKey) -> Self {
+        self.cosigners.push(key.clone());
+        self
+    }
+
+    /// Left with a blank signature, and blank cosignatures
+    pub fn unsigned(mut self) -> Self {
+        self.signed = false;
+        self
+    }
+
+    pub fn build(&self) -> Transaction {
+        let mut tx = Transaction {
+            sender: match &self.account {
+                Some(account) => account.address(),
+                None => self.key.verifying_key().to_bytes(),
+            },
+            nonce: self.nonce,
+            payload: Bytes::from(self.payload.clone()),
+            signature: [0; 64],
+            cosignatures: Vec::new(),
+        };
+        let message = tx.signing_bytes();
+        if self.signed && self.account.is_none() {
+            tx.signature = self.key.sign(&message).to_bytes();
+        }
+        tx.cosignatures = self
+            .cosigners
+            .iter()
+            .map(|key| Cosignature {
+                owner: key.verifying_key().to_bytes(),
+                signature: if self.signed {
+                    key.sign(&message).to_bytes().to_vec()
+                } else {
+                    vec![0; 64]
+                },
+            })
+            .collect();
+        tx
+    }
+}
+
+/// A block, by default at height 1 on no parent, without transactions,
+/// produced and signed by [`PRODUCER`]
+#[derive(Debug, Clone)]
+pub struct BlockBuilder {
+    block: Block,
+    key: SigningKey,
+    signed: bool,
+}
+
+impl Default for BlockBuilder {
+    fn default() -> Self {
+        Self {
+            block: Block {
+                height: 1,
+                parent_hash: [0; 32],
+                transactions: Vec::new(),
+                proposer_sig: [0; 64],
+                state_root: [0; 32],
+                innovation_level: 5,
+                producer_strategy: "test".to_string(),
+                producer_id: PRODUCER.to_string(),
+                drama_level: 5,
+                producer_mood: "Theatrical".to_string(),
+                timestamp: GENESIS_TIME,
+                metadata: BlockMetadata::default(),
+            },
+            key: fixtures::key(PRODUCER),
+            signed: true,
+        }
+    }
+}
+
+impl BlockBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn height(mut self, height: u64) -> Self {
+        self.block.height = height;
+        self
+    }
+
+    /// On top of `parent`, a block time after it
+    pub fn parent(mut self, parent: &Block) -> Self {
+        self.block.height = parent.height + 1;
+        self.block.parent_hash = parent.hash();
+        self.block.timestamp = parent.timestamp + fixtures::BLOCK_TIME;
+        self
+    }
+
+    pub fn parent_hash(mut self, parent_hash: [u8; 32]) -> Self {
+        self.block.parent_hash = parent_hash;
+        self
+    }
+
+    /// Produced by `producer`, which signs with `key`
+    pub fn producer(mut self, producer: impl Into<String>, key: &SigningKey) -> Self {
+        self.block.producer_id = producer.into();
+        self.key = key.clone();
+        self
+    }
+
+    pub fn transaction(mut self, tx: Transaction) -> Self {
+        self.block.transactions.push(tx);
+        self
+    }
+
+    pub fn transactions(mut self, txs: impl IntoIterator<Item = Transaction>) -> Self {
+        self.block.transactions.extend(txs);
+        self
+    }
+
+    pub fn state_root(mut self, state_root: [u8; 32]) -> Self {
+        self.block.state_root = state_root;
+        self
+    }
+
+    pub fn drama_level(mut self, drama_level: u8) -> Self {
+        self.block.drama_level = drama_level;
+        self
+    }
+
+    pub fn innovation_level(mut self, innovation_level: u8) -> Self {
+        self.block.innovation_level = innovation_level;
+        self
+    }
+
+    pub fn mood(mut self, mood: impl Into<String>) -> Self {
+        self.block.producer_mood = mood.into();
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.block.timestamp = timestamp;
+        self
+    }
+
+    pub fn metadata(mut self, metadata: BlockMetadata) -> Self {
+        self.block.metadata = metadata;
+        self
+    }
+
+    /// Left with a blank proposer signature
+    pub fn unsigned(mut self) -> Self {
+        self.signed = false;
+        self
+    }
+
+    pub fn build(&self) -> Block {
+        let mut block = self.block.clone();
+        if self.signed {
+            block.proposer_sig = self.key.sign(&block.signing_bytes()).to_bytes();
+        }
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_core::multisig::MultisigAccounts;
+    use ed25519_dalek::{Signature, Verifier};
+
+    #[test]
+    fn test_builders_sign_what_they_build() {
+        let tx = TransactionBuilder::new().nonce(3).payload("drama").build();
+        assert_eq!(tx.sender, fixtures::key(SENDER).verifying_key().to_bytes());
+        assert!(tx.verify().is_ok());
+        assert!(TransactionBuilder::new()
+            .unsigned()
+            .build()
+            .verify()
+            .is_err());
+
+        let owners = ["alice", "bob", "carol"].map(fixtures::key);
+        let account = MultisigAccount::new(
+            owners
+                .iter()
+                .map(|key| key.verifying_key().to_bytes())
+                .collect(),
+            2,
+        )
+        .unwrap();
+        let mut accounts = MultisigAccounts::new();
+        accounts.insert(account.clone());
+        let spend = TransactionBuilder::new()
+            .account(&account)
+            .cosigner(&owners[0])
+            .cosigner(&owners[2])
+            .build();
+        assert!(accounts.verify(&spend).is_ok());
+        let short = TransactionBuilder::new()
+            .account(&account)
+            .cosigner(&owners[1])
+            .build();
+        assert!(accounts.verify(&short).is_err());
+
+        let parent = BlockBuilder::new().transaction(tx).build();
+        let block = BlockBuilder::new().parent(&parent).drama_level(9).build();
+        assert_eq!(block.height, 2);
+        assert_eq!(block.parent_hash, parent.hash());
+        let signature = Signature::from_bytes(&block.proposer_sig);
+        assert!(fixtures::key(PRODUCER)
+            .verifying_key()
+            .verify(&block.signing_bytes(), &signature)
+            .is_ok());
+        assert_eq!(BlockBuilder::new().unsigned().build().proposer_sig, [0; 64]);
+    }
+}
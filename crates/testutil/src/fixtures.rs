@@ -0,0 +1,110 @@
+//! Signed fixtures, for a test that wants a valid transaction, block or
+//! vote and does not care which. Keys come from names, so the fixtures are
+//! the same on every run: [`key`]`("validator-0")` always signs the same
+//! votes, and a test may check a signature against it without holding on
+//! to the key it was made with.
+
+use crate::{BlockBuilder, TransactionBuilder};
+use chaoschain_core::election::LeaderTicket;
+use chaoschain_core::rotation::KeyRotation;
+use chaoschain_core::vote::{Misbehavior, SignedVote};
+use chaoschain_core::{Block, Transaction};
+use chaoschain_crypto::vrf::VrfSecretKey;
+use ed25519_dalek::SigningKey;
+use sha2::{Digest, Sha256};
+
+/// Unix time of the first block of the fixtures
+pub const GENESIS_TIME: u64 = 1_700_000_000;
+
+/// Seconds between two blocks of a [`chain`]
+pub const BLOCK_TIME: u64 = 5;
+
+/// Who sends the transactions of the fixtures
+pub const SENDER: &str = "sender";
+
+/// Who produces the blocks of the fixtures
+pub const PRODUCER: &str = "producer-0";
+
+/// The ed25519 key of `name`
+pub fn key(name: &str) -> SigningKey {
+    SigningKey::from_bytes(&Sha256::digest(name.as_bytes()).into())
+}
+
+/// The VRF key of `name`, apart from its ed25519 one
+pub fn vrf_key(name: &str) -> VrfSecretKey {
+    let mut hasher = Sha256::new();
+    hasher.update(b"vrf:");
+    hasher.update(name.as_bytes());
+    VrfSecretKey::from_bytes(hasher.finalize().into())
+}
+
+/// A transaction of [`SENDER`], signed
+pub fn transaction(nonce: u64) -> Transaction {
+    TransactionBuilder::new()
+        .nonce(nonce)
+        .payload(format!("drama #{}", nonce))
+        .build()
+}
+
+/// A block of [`PRODUCER`] at `height` with one transaction, signed, on
+/// no parent
+pub fn block(height: u64) -> Block {
+    BlockBuilder::new()
+        .height(height)
+        .transaction(transaction(height))
+        .build()
+}
+
+/// `length` signed blocks from height 1, each on the one before
+pub fn chain(length: usize) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::with_capacity(length);
+    for nonce in 0..length as u64 {
+        let builder = match blocks.last() {
+            Some(parent) => BlockBuilder::new().parent(parent),
+            None => BlockBuilder::new(),
+        };
+        blocks.push(builder.transaction(transaction(nonce)).build());
+    }
+    blocks
+}
+
+/// The vote of `validator` on `block`, signed with its [`key`]
+pub fn vote(validator: &str, block: &Block, approved: bool) -> SignedVote {
+    let reason = if approved {
+        "The drama is exquisite"
+    } else {
+        "Not nearly dramatic enough"
+    };
+    SignedVote::sign(
+        &key(validator),
+        validator,
+        block.height,
+        block.hash(),
+        approved,
+        7,
+        reason,
+    )
+}
+
+/// Proof that `validator` voted both ways on `block`
+pub fn equivocation(validator: &str, block: &Block) -> Misbehavior {
+    Misbehavior::Equivocation {
+        first: Box::new(vote(validator, block, true)),
+        second: Box::new(vote(validator, block, false)),
+    }
+}
+
+/// The ticket `producer` draws for the block on `parent`
+pub fn leader_ticket(producer: &str, parent: &Block) -> LeaderTicket {
+    LeaderTicket::draw(&vrf_key(producer), parent.height + 1, &parent.hash())
+}
+
+/// `validator` moving from its [`key`] to the key of `to`
+pub fn rotation(validator: &str, to: &str) -> KeyRotation {
+    KeyRotation::new(
+        validator,
+        key(validator).verifying_key().to_bytes(),
+        &key(to),
+    )
+    .expect("a key in memory signs")
+}
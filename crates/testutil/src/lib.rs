@@ -0,0 +1,27 @@
+//! Builders, signed fixtures and [proptest] strategies for the tests of
+//! the other crates, so that each does not sign its own blocks and votes
+//! by hand. Add it as a dev-dependency:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! chaoschain-testutil.workspace = true
+//! ```
+//!
+//! ```
+//! use chaoschain_testutil::{fixtures, BlockBuilder, ValidatorSetBuilder};
+//!
+//! let validators = ValidatorSetBuilder::new().validators(4).build();
+//! let parent = fixtures::block(1);
+//! let block = BlockBuilder::new().parent(&parent).drama_level(9).build();
+//! for (validator, vote) in validators.iter().zip(validators.votes(&block, true)) {
+//!     assert!(vote.verify(&validator.public_key()).is_ok());
+//! }
+//! ```
+
+mod builders;
+pub mod fixtures;
+pub mod strategies;
+mod validators;
+
+pub use builders::{BlockBuilder, TransactionBuilder};
+pub use validators::{TestValidator, ValidatorSet, ValidatorSetBuilder, DEFAULT_STAKE};
@@ -0,0 +1,343 @@
+//! [proptest] strategies for the types of consensus. Those named for a
+//! signed type make values that hold: signatures over what they sign, by
+//! keys drawn along with them, and levels within their ranges. The rest,
+//! [`transaction`] among them, draw every field freely, for code that has
+//! to cope with whatever a peer sends.
+//!
+//! ```
+//! use chaoschain_testutil::strategies;
+//! use proptest::prelude::*;
+//!
+//! proptest!(|(tx in strategies::signed_transaction())| {
+//!     prop_assert!(tx.verify().is_ok());
+//! });
+//! ```
+
+use crate::validators::{ValidatorSet, ValidatorSetBuilder};
+use crate::{BlockBuilder, TransactionBuilder};
+use chaoschain_core::compact::CompactBlock;
+use chaoschain_core::election::LeaderTicket;
+use chaoschain_core::fork::{Activation, ForkSchedule, ScheduledUpgrade, Upgrade};
+use chaoschain_core::genesis::{ConsensusParams, Genesis};
+use chaoschain_core::rotation::KeyRotation;
+use chaoschain_core::vote::{Misbehavior, SignedExplanation, SignedVote};
+use chaoschain_core::{Block, BlockHeader, Bytes, Transaction, ValidationDecision};
+use chaoschain_crypto::hash::HashFunction;
+use chaoschain_crypto::vrf::VrfSecretKey;
+use ed25519_dalek::SigningKey;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::subsequence;
+
+/// Most transactions of a drawn block
+pub const MAX_TRANSACTIONS: usize = 8;
+
+/// Most validators of a drawn set
+pub const MAX_VALIDATORS: usize = 8;
+
+pub fn key() -> impl Strategy<Value = SigningKey> {
+    any::<[u8; 32]>().prop_map(|secret| SigningKey::from_bytes(&secret))
+}
+
+pub fn vrf_key() -> impl Strategy<Value = VrfSecretKey> {
+    any::<[u8; 32]>().prop_map(VrfSecretKey::from_bytes)
+}
+
+pub fn hash() -> impl Strategy<Value = [u8; 32]> {
+    any::<[u8; 32]>()
+}
+
+/// Names of agents, validators and producers
+pub fn name() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9-]{0,15}"
+}
+
+pub fn payload() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..256)
+}
+
+/// Levels of drama, from 0 to 10
+pub fn drama_level() -> impl Strategy<Value = u8> {
+    0..=10u8
+}
+
+fn signature() -> impl Strategy<Value = [u8; 64]> {
+    (any::<[u8; 32]>(), any::<[u8; 32]>()).prop_map(|(r, s)| {
+        let mut signature = [0; 64];
+        signature[..32].copy_from_slice(&r);
+        signature[32..].copy_from_slice(&s);
+        signature
+    })
+}
+
+/// A transaction of any sender, nonce, payload and signature, which
+/// seldom verifies
+pub fn transaction() -> impl Strategy<Value = Transaction> {
+    (hash(), any::<u64>(), payload(), signature()).prop_map(
+        |(sender, nonce, payload, signature)| Transaction {
+            sender,
+            nonce,
+            payload: Bytes::from(payload),
+            signature,
+            cosignatures: Vec::new(),
+        },
+    )
+}
+
+/// A transaction signed by the key it is sent from
+pub fn signed_transaction() -> impl Strategy<Value = Transaction> {
+    (key(), any::<u64>(), payload()).prop_map(|(key, nonce, payload)| {
+        TransactionBuilder::new()
+            .signer(&key)
+            .nonce(nonce)
+            .payload(payload)
+            .build()
+    })
+}
+
+/// A block of signed transactions, signed by its producer, on any parent
+pub fn block() -> impl Strategy<Value = Block> {
+    (
+        (key(), name(), 1..u64::MAX / 2, hash(), hash()),
+        vec(signed_transaction(), 0..=MAX_TRANSACTIONS),
+        (drama_level(), any::<u8>(), "[A-Za-z ]{0,16}", any::<u32>()),
+    )
+        .prop_map(
+            |(
+                (key, producer, height, parent_hash, state_root),
+                transactions,
+                (drama_level, innovation_level, mood, timestamp),
+            )| {
+                BlockBuilder::new()
+                    .producer(producer, &key)
+                    .height(height)
+                    .parent_hash(parent_hash)
+                    .state_root(state_root)
+                    .transactions(transactions)
+                    .drama_level(drama_level)
+                    .innovation_level(innovation_level)
+                    .mood(mood)
+                    .timestamp(timestamp as u64)
+                    .build()
+            },
+        )
+}
+
+pub fn block_header() -> impl Strategy<Value = BlockHeader> {
+    block().prop_map(|block| BlockHeader::of(&block))
+}
+
+pub fn compact_block() -> impl Strategy<Value = CompactBlock> {
+    block().prop_map(|block| CompactBlock::new(&block))
+}
+
+/// A vote on any block, signed by the key it is drawn with
+pub fn signed_vote_with_key() -> impl Strategy<Value = (SignedVote, SigningKey)> {
+    (
+        key(),
+        name(),
+        any::<u64>(),
+        hash(),
+        any::<bool>(),
+        drama_level(),
+        ".{0,64}",
+    )
+        .prop_map(
+            |(key, validator, height, block_hash, approved, drama_level, reason)| {
+                let vote = SignedVote::sign(
+                    &key,
+                    validator,
+                    height,
+                    block_hash,
+                    approved,
+                    drama_level,
+                    reason,
+                );
+                (vote, key)
+            },
+        )
+}
+
+pub fn signed_vote() -> impl Strategy<Value = SignedVote> {
+    signed_vote_with_key().prop_map(|(vote, _)| vote)
+}
+
+/// The explanation of a vote, as a finalized block carries it
+pub fn signed_explanation() -> impl Strategy<Value = SignedExplanation> {
+    signed_vote_with_key().prop_map(|(vote, key)| vote.explanation(&key.verifying_key()))
+}
+
+pub fn validation_decision() -> impl Strategy<Value = ValidationDecision> {
+    (
+        any::<bool>(),
+        ".{0,64}",
+        proptest::option::of("https://memes\\.example/[a-z]{1,8}\\.gif"),
+        drama_level(),
+        0..=10u8,
+        proptest::option::of(".{0,32}"),
+        name(),
+    )
+        .prop_map(
+            |(approved, reason, meme_url, drama_level, innovation_score, evolution, validator)| {
+                ValidationDecision {
+                    approved,
+                    reason,
+                    meme_url,
+                    drama_level,
+                    innovation_score,
+                    evolution_proposal: evolution,
+                    validator,
+                }
+            },
+        )
+}
+
+/// Two votes of one validator on one block that contradict each other,
+/// both signed by the key drawn with them
+pub fn misbehavior_with_key() -> impl Strategy<Value = (Misbehavior, SigningKey)> {
+    (signed_vote_with_key(), ".{0,64}").prop_map(|((first, key), reason)| {
+        // Changing the verdict makes the two differ whatever the reason
+        let second = SignedVote::sign(
+            &key,
+            first.validator.clone(),
+            first.height,
+            first.block_hash,
+            !first.approved,
+            first.drama_level,
+            reason,
+        );
+        let misbehavior = Misbehavior::Equivocation {
+            first: Box::new(first),
+            second: Box::new(second),
+        };
+        (misbehavior, key)
+    })
+}
+
+pub fn misbehavior() -> impl Strategy<Value = Misbehavior> {
+    misbehavior_with_key().prop_map(|(misbehavior, _)| misbehavior)
+}
+
+/// A ticket drawn for the block at some height on some parent
+pub fn leader_ticket() -> impl Strategy<Value = (LeaderTicket, u64, [u8; 32])> {
+    (vrf_key(), any::<u64>(), hash()).prop_map(|(key, height, parent)| {
+        (LeaderTicket::draw(&key, height, &parent), height, parent)
+    })
+}
+
+/// A validator moving from one key to another it proves it holds
+pub fn key_rotation() -> impl Strategy<Value = KeyRotation> {
+    (name(), key(), key()).prop_map(|(validator, old, new)| {
+        KeyRotation::new(validator, old.verifying_key().to_bytes(), &new)
+            .expect("a key in memory signs")
+    })
+}
+
+/// Parameters a network may start with
+pub fn consensus_params() -> impl Strategy<Value = ConsensusParams> {
+    (
+        0.51f64..=1.0,
+        1..60u64,
+        1..120u64,
+        0..1_000u64,
+        prop_oneof![Just(HashFunction::Sha256), Just(HashFunction::Blake3)],
+        fork_schedule(),
+    )
+        .prop_map(
+            |(finality_threshold, block_time, consensus_timeout, rotation_grace, hash, forks)| {
+                ConsensusParams {
+                    finality_threshold,
+                    block_time,
+                    consensus_timeout,
+                    rotation_grace,
+                    hash,
+                    forks,
+                }
+            },
+        )
+}
+
+/// Each upgrade at most once, at a height or an epoch
+pub fn fork_schedule() -> impl Strategy<Value = ForkSchedule> {
+    (
+        1..1_000u64,
+        subsequence(Upgrade::ALL.to_vec(), 0..=Upgrade::ALL.len()),
+        vec((any::<bool>(), 0..10_000u64), Upgrade::ALL.len()),
+    )
+        .prop_map(|(epoch_length, upgrades, activations)| ForkSchedule {
+            epoch_length,
+            upgrades: upgrades
+                .into_iter()
+                .zip(activations)
+                .map(|(upgrade, (by_epoch, at))| ScheduledUpgrade {
+                    upgrade,
+                    activation: if by_epoch {
+                        Activation::Epoch(at)
+                    } else {
+                        Activation::Height(at)
+                    },
+                })
+                .collect(),
+        })
+}
+
+/// From 1 to [`MAX_VALIDATORS`] validators, each of some stake
+pub fn validator_set() -> impl Strategy<Value = ValidatorSet> {
+    (vec(1..1_000_000u64, 1..=MAX_VALIDATORS), consensus_params()).prop_map(
+        |(stakes, consensus)| {
+            stakes
+                .into_iter()
+                .fold(
+                    ValidatorSetBuilder::new().consensus(consensus),
+                    |builder, stake| builder.staked(1, stake),
+                )
+                .build()
+        },
+    )
+}
+
+/// A genesis a network starts from
+pub fn genesis() -> impl Strategy<Value = Genesis> {
+    validator_set().prop_map(|set| set.genesis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chaoschain_core::gossip::GossipLimits;
+    use chaoschain_core::NetworkMessage;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_signed_values_hold(
+            tx in signed_transaction(),
+            (vote, key) in signed_vote_with_key(),
+            (misbehavior, offender) in misbehavior_with_key(),
+            rotation in key_rotation(),
+            (ticket, height, parent) in leader_ticket(),
+        ) {
+            prop_assert!(tx.verify().is_ok());
+            prop_assert!(vote.verify(&key.verifying_key()).is_ok());
+            let explanation = vote.explanation(&key.verifying_key());
+            prop_assert!(explanation.verify(vote.height, &vote.block_hash).is_ok());
+            prop_assert!(misbehavior.verify(&offender.verifying_key()).is_ok());
+            prop_assert!(rotation.verify().is_ok());
+            prop_assert!(ticket.verify(height, &parent).is_ok());
+        }
+
+        #[test]
+        fn test_blocks_pass_gossip_checks(block in block()) {
+            let json = serde_json::to_vec(&block).unwrap();
+            prop_assert_eq!(serde_json::from_slice::<Block>(&json).unwrap(), block.clone());
+            let message = NetworkMessage::NewBlock(block);
+            prop_assert!(message.check(&GossipLimits::default()).is_ok());
+        }
+
+        #[test]
+        fn test_genesis_has_no_problems(genesis in genesis()) {
+            prop_assert!(genesis.problems().is_empty(), "{:?}", genesis.problems());
+        }
+    }
+}
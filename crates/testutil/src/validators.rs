@@ -0,0 +1,236 @@
+//! Validator sets with their keys at hand: the genesis that starts a
+//! network of them, the keys consensus knows them by, and their votes.
+
+use crate::fixtures::{self, GENESIS_TIME};
+use chaoschain_core::genesis::{ConsensusParams, Genesis, GenesisValidator};
+use chaoschain_core::governance::GovernanceParams;
+use chaoschain_core::rotation::ValidatorKeys;
+use chaoschain_core::staking::StakingParams;
+use chaoschain_core::vote::{self, SignedVote};
+use chaoschain_core::Block;
+use ed25519_dalek::{SigningKey, VerifyingKey};
+
+/// Stake of each validator, unless it is given one
+pub const DEFAULT_STAKE: u64 = 100;
+
+/// A validator, its key and its stake
+#[derive(Debug, Clone)]
+pub struct TestValidator {
+    pub name: String,
+    pub key: SigningKey,
+    pub stake: u64,
+}
+
+impl TestValidator {
+    pub fn public_key(&self) -> VerifyingKey {
+        self.key.verifying_key()
+    }
+
+    /// Its vote on `block`
+    pub fn vote(&self, block: &Block, approved: bool) -> SignedVote {
+        self.vote_with(block, approved, 7, "It simply feels right")
+    }
+
+    pub fn vote_with(
+        &self,
+        block: &Block,
+        approved: bool,
+        drama_level: u8,
+        reason: &str,
+    ) -> SignedVote {
+        SignedVote::sign(
+            &self.key,
+            &self.name,
+            block.height,
+            block.hash(),
+            approved,
+            drama_level,
+            reason,
+        )
+    }
+
+    /// Its entry in a genesis, with the proof of possession of its key
+    pub fn genesis_validator(&self) -> GenesisValidator {
+        let proof = vote::prove_possession(&self.key, &self.name).expect("a key in memory signs");
+        GenesisValidator {
+            name: self.name.clone(),
+            public_key: hex::encode(self.public_key().as_bytes()),
+            stake: self.stake,
+            proof_of_possession: hex::encode(proof),
+        }
+    }
+}
+
+/// A set of validators, by default none, on a chain called `test`. Each
+/// validator signs with the [key](fixtures::key) of its name.
+#[derive(Debug, Clone)]
+pub struct ValidatorSetBuilder {
+    chain_id: String,
+    consensus: ConsensusParams,
+    validators: Vec<(String, u64)>,
+}
+
+impl Default for ValidatorSetBuilder {
+    fn default() -> Self {
+        Self {
+            chain_id: "test".to_string(),
+            consensus: ConsensusParams::default(),
+            validators: Vec::new(),
+        }
+    }
+}
+
+impl ValidatorSetBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `count` more validators of the [`DEFAULT_STAKE`], named
+    /// `validator-0` on, after those there are
+    pub fn validators(self, count: usize) -> Self {
+        self.staked(count, DEFAULT_STAKE)
+    }
+
+    /// `count` more validators of `stake` each, named as by
+    /// [`validators`](Self::validators)
+    pub fn staked(mut self, count: usize, stake: u64) -> Self {
+        let first = self.validators.len();
+        self.validators
+            .extend((first..first + count).map(|index| (format!("validator-{}", index), stake)));
+        self
+    }
+
+    pub fn validator(mut self, name: impl Into<String>, stake: u64) -> Self {
+        self.validators.push((name.into(), stake));
+        self
+    }
+
+    pub fn chain_id(mut self, chain_id: impl Into<String>) -> Self {
+        self.chain_id = chain_id.into();
+        self
+    }
+
+    pub fn consensus(mut self, consensus: ConsensusParams) -> Self {
+        self.consensus = consensus;
+        self
+    }
+
+    pub fn finality_threshold(mut self, threshold: f64) -> Self {
+        self.consensus.finality_threshold = threshold;
+        self
+    }
+
+    pub fn build(&self) -> ValidatorSet {
+        ValidatorSet {
+            chain_id: self.chain_id.clone(),
+            consensus: self.consensus.clone(),
+            validators: self
+                .validators
+                .iter()
+                .map(|(name, stake)| TestValidator {
+                    name: name.clone(),
+                    key: fixtures::key(name),
+                    stake: *stake,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ValidatorSet {
+    pub chain_id: String,
+    pub consensus: ConsensusParams,
+    pub validators: Vec<TestValidator>,
+}
+
+impl ValidatorSet {
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TestValidator> {
+        self.validators.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TestValidator> {
+        self.validators
+            .iter()
+            .find(|validator| validator.name == name)
+    }
+
+    pub fn total_stake(&self) -> u64 {
+        self.validators
+            .iter()
+            .map(|validator| validator.stake)
+            .sum()
+    }
+
+    /// The genesis of a network of the set, without allocations
+    pub fn genesis(&self) -> Genesis {
+        Genesis {
+            chain_id: self.chain_id.clone(),
+            genesis_time: GENESIS_TIME,
+            consensus: self.consensus.clone(),
+            validators: self
+                .validators
+                .iter()
+                .map(TestValidator::genesis_validator)
+                .collect(),
+            allocations: Vec::new(),
+            threshold: None,
+            governance: GovernanceParams::default(),
+            staking: StakingParams::default(),
+        }
+    }
+
+    /// The keys consensus knows the set by
+    pub fn validator_keys(&self) -> ValidatorKeys {
+        ValidatorKeys::from_genesis(&self.genesis())
+    }
+
+    /// The vote of every validator on `block`
+    pub fn votes(&self, block: &Block, approved: bool) -> Vec<SignedVote> {
+        self.validators
+            .iter()
+            .map(|validator| validator.vote(block, approved))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BlockBuilder;
+
+    #[test]
+    fn test_a_set_starts_a_network() {
+        let set = ValidatorSetBuilder::new()
+            .validators(3)
+            .validator("whale", 1_000)
+            .build();
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.total_stake(), 1_300);
+        assert_eq!(set.get("validator-2").unwrap().stake, DEFAULT_STAKE);
+
+        let genesis = set.genesis();
+        assert!(genesis.problems().is_empty(), "{:?}", genesis.problems());
+        assert_eq!(genesis.total_stake(), set.total_stake());
+
+        let keys = set.validator_keys();
+        let block = BlockBuilder::new().build();
+        for (validator, vote) in set.iter().zip(set.votes(&block, true)) {
+            assert!(vote.verify(&validator.public_key()).is_ok());
+            assert!(keys.accepts(&vote.validator, validator.public_key().as_bytes(), 1));
+        }
+        // The same names make the same keys
+        assert_eq!(
+            ValidatorSetBuilder::new().validators(1).build().validators[0].public_key(),
+            set.validators[0].public_key()
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! Checking vectors against this implementation: every encoding, preimage
+//! and digest worked out again, and every signature checked for what the
+//! vector says of it
+
+use chaoschain_core::threshold::finality_message;
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{Block, BlockHeader, Transaction};
+use chaoschain_crypto::hash::HashFunction;
+use chaoschain_state::MerkleTree;
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::{
+    BlockVector, GroupSignatureVector, HashVector, StateRootVector, TransactionVector, Vectors,
+    VoteVector,
+};
+
+/// Every hash function a vector has a hash under
+pub(crate) const HASH_FUNCTIONS: [HashFunction; 2] = [HashFunction::Sha256, HashFunction::Blake3];
+
+/// A vector this implementation does not agree with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// Kind of the vector, as its file is named
+    pub kind: &'static str,
+    pub vector: String,
+    pub problem: String,
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:?}: {}", self.kind, self.vector, self.problem)
+    }
+}
+
+/// What does not hold of `vectors`, nothing when all of it does
+pub fn verify(vectors: &Vectors) -> Vec<Failure> {
+    let mut failures = Vec::new();
+    let mut record = |kind: &'static str, name: &str, problems: Vec<String>| {
+        failures.extend(problems.into_iter().map(|problem| Failure {
+            kind,
+            vector: name.to_string(),
+            problem,
+        }))
+    };
+    for vector in &vectors.transactions {
+        record("transactions", &vector.name, check_transaction(vector));
+    }
+    for vector in &vectors.blocks {
+        record("blocks", &vector.name, check_block(vector));
+    }
+    for vector in &vectors.votes {
+        record("votes", &vector.name, check_vote(vector));
+    }
+    for vector in &vectors.group_signatures {
+        record(
+            "group_signatures",
+            &vector.name,
+            check_group_signature(vector),
+        );
+    }
+    for vector in &vectors.state_roots {
+        record("state_roots", &vector.name, check_state_root(vector));
+    }
+    failures
+}
+
+/// What the transaction hash is taken of: the sender, the big-endian nonce,
+/// the payload, the signature, then the owner and signature of each
+/// cosignature
+pub(crate) fn transaction_preimage(tx: &Transaction) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&tx.sender);
+    bytes.extend_from_slice(&tx.nonce.to_be_bytes());
+    bytes.extend_from_slice(&tx.payload);
+    bytes.extend_from_slice(&tx.signature);
+    for cosignature in &tx.cosignatures {
+        bytes.extend_from_slice(&cosignature.owner);
+        bytes.extend_from_slice(&cosignature.signature);
+    }
+    bytes
+}
+
+/// What the block hash under `function` is taken of, the hashes of the
+/// transactions under it too
+pub(crate) fn block_preimage(block: &Block, function: HashFunction) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&block.height.to_be_bytes());
+    bytes.extend_from_slice(&block.parent_hash);
+    for tx in &block.transactions {
+        bytes.extend_from_slice(&tx.hash_with(function));
+    }
+    bytes.extend_from_slice(&block.proposer_sig);
+    bytes.extend_from_slice(&block.state_root);
+    bytes.push(block.innovation_level);
+    bytes.extend_from_slice(block.producer_strategy.as_bytes());
+    bytes.extend_from_slice(block.producer_id.as_bytes());
+    bytes.push(block.drama_level);
+    bytes.extend_from_slice(block.producer_mood.as_bytes());
+    bytes.extend_from_slice(&block.timestamp.to_be_bytes());
+    bytes
+}
+
+/// `preimage` and `hash` under every hash function
+pub(crate) fn hashes(
+    preimage: impl Fn(HashFunction) -> Vec<u8>,
+    hash: impl Fn(HashFunction) -> [u8; 32],
+) -> Vec<HashVector> {
+    HASH_FUNCTIONS
+        .iter()
+        .map(|&function| HashVector {
+            function,
+            preimage: hex::encode(preimage(function)),
+            digest: hex::encode(hash(function)),
+        })
+        .collect()
+}
+
+fn same(problems: &mut Vec<String>, what: &str, expected: &str, got: &str) {
+    if expected != got {
+        problems.push(format!("{} is {}, not {}", what, got, expected));
+    }
+}
+
+/// The encoding is `value`'s, and decodes to it
+fn check_encoding<T>(problems: &mut Vec<String>, encoded: &str, value: &T)
+where
+    T: Serialize + DeserializeOwned + PartialEq,
+{
+    match serde_json::to_string(value) {
+        Ok(encoding) => same(problems, "the encoding", encoded, &encoding),
+        Err(e) => problems.push(format!("it does not encode: {}", e)),
+    }
+    match serde_json::from_str::<T>(encoded) {
+        Ok(decoded) if decoded == *value => {}
+        Ok(_) => problems.push("the encoding decodes to something else".to_string()),
+        Err(e) => problems.push(format!("the encoding does not decode: {}", e)),
+    }
+}
+
+fn check_hashes(
+    problems: &mut Vec<String>,
+    vectors: &[HashVector],
+    preimage: impl Fn(HashFunction) -> Vec<u8>,
+    hash: impl Fn(HashFunction) -> [u8; 32],
+) {
+    for function in HASH_FUNCTIONS {
+        if !vectors.iter().any(|vector| vector.function == function) {
+            problems.push(format!("there is no {} hash", function));
+        }
+    }
+    for vector in vectors {
+        let function = vector.function;
+        same(
+            problems,
+            &format!("the {} preimage", function),
+            &vector.preimage,
+            &hex::encode(preimage(function)),
+        );
+        match hex::decode(&vector.preimage) {
+            Ok(preimage) => same(
+                problems,
+                &format!("the {} digest of the preimage", function),
+                &vector.digest,
+                &hex::encode(function.digest(preimage)),
+            ),
+            Err(e) => problems.push(format!("the {} preimage is not hex: {}", function, e)),
+        }
+        same(
+            problems,
+            &format!("the {} hash", function),
+            &vector.digest,
+            &hex::encode(hash(function)),
+        );
+    }
+}
+
+fn check_validity(problems: &mut Vec<String>, claimed: bool, holds: bool) {
+    if claimed != holds {
+        problems.push(format!(
+            "the vector is marked {}, but its signatures {}",
+            if claimed { "valid" } else { "invalid" },
+            if holds { "hold" } else { "do not hold" }
+        ));
+    }
+}
+
+fn public_key_of(problems: &mut Vec<String>, secret_key: &str) -> Option<[u8; 32]> {
+    match hex::decode(secret_key).map(<[u8; 32]>::try_from) {
+        Ok(Ok(secret)) => Some(SigningKey::from_bytes(&secret).verifying_key().to_bytes()),
+        _ => {
+            problems.push(format!("{} is not a secret key", secret_key));
+            None
+        }
+    }
+}
+
+fn check_transaction(vector: &TransactionVector) -> Vec<String> {
+    let mut problems = Vec::new();
+    let tx = &vector.transaction;
+    check_encoding(&mut problems, &vector.encoded, tx);
+    same(
+        &mut problems,
+        "the signing bytes",
+        &vector.signing_bytes,
+        &hex::encode(tx.signing_bytes()),
+    );
+    check_hashes(
+        &mut problems,
+        &vector.hashes,
+        |_| transaction_preimage(tx),
+        |function| tx.hash_with(function),
+    );
+    let signers: Vec<[u8; 32]> = vector
+        .secret_keys
+        .iter()
+        .filter_map(|key| public_key_of(&mut problems, key))
+        .collect();
+    let holds = match &vector.account {
+        Some(account) => {
+            if let Err(e) = account.check() {
+                problems.push(format!("the account does not hold: {}", e));
+            }
+            if tx
+                .cosignatures
+                .iter()
+                .any(|cosignature| !signers.contains(&cosignature.owner))
+            {
+                problems.push("a cosigner has no secret key in the vector".to_string());
+            }
+            account.verify(tx).is_ok()
+        }
+        None => {
+            if signers != [tx.sender] {
+                problems.push("the secret key is not the sender's".to_string());
+            }
+            tx.verify().is_ok()
+        }
+    };
+    check_validity(&mut problems, vector.valid, holds);
+    problems
+}
+
+fn check_block(vector: &BlockVector) -> Vec<String> {
+    let mut problems = Vec::new();
+    let block = &vector.block;
+    check_encoding(&mut problems, &vector.encoded, block);
+    same(
+        &mut problems,
+        "the signing bytes",
+        &vector.signing_bytes,
+        &hex::encode(block.signing_bytes()),
+    );
+    check_hashes(
+        &mut problems,
+        &vector.hashes,
+        |function| block_preimage(block, function),
+        |function| block.hash_with(function),
+    );
+    if vector.header != BlockHeader::of(block) {
+        problems.push("the header is not the block's".to_string());
+    }
+    if let Some(public_key) = public_key_of(&mut problems, &vector.secret_key) {
+        same(
+            &mut problems,
+            "the public key",
+            &vector.public_key,
+            &hex::encode(public_key),
+        );
+    }
+    let holds = hex::decode(&vector.public_key)
+        .ok()
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .is_some_and(|key| {
+            key.verify(
+                &block.signing_bytes(),
+                &Signature::from_bytes(&block.proposer_sig),
+            )
+            .is_ok()
+        });
+    check_validity(&mut problems, vector.valid, holds);
+    problems
+}
+
+fn check_vote(vector: &VoteVector) -> Vec<String> {
+    let mut problems = Vec::new();
+    let vote: &SignedVote = &vector.vote;
+    check_encoding(&mut problems, &vector.encoded, vote);
+    same(
+        &mut problems,
+        "the explanation hash",
+        &vector.explanation_hash,
+        &hex::encode(vote.explanation_hash()),
+    );
+    same(
+        &mut problems,
+        "the signing bytes",
+        &vector.signing_bytes,
+        &hex::encode(vote.signing_bytes()),
+    );
+    if let Some(public_key) = public_key_of(&mut problems, &vector.secret_key) {
+        same(
+            &mut problems,
+            "the public key",
+            &vector.public_key,
+            &hex::encode(public_key),
+        );
+    }
+    let holds = hex::decode(&vector.public_key)
+        .ok()
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok())
+        .is_some_and(|key| vote.verify(&key).is_ok());
+    check_validity(&mut problems, vector.valid, holds);
+    problems
+}
+
+fn check_group_signature(vector: &GroupSignatureVector) -> Vec<String> {
+    let mut problems = Vec::new();
+    let group = &vector.group_key;
+    match hex::decode(&vector.block_hash).map(<[u8; 32]>::try_from) {
+        Ok(Ok(block_hash)) => same(
+            &mut problems,
+            "the finality message",
+            &vector.message,
+            &hex::encode(finality_message(vector.height, &block_hash)),
+        ),
+        _ => problems.push(format!("{} is not a block hash", vector.block_hash)),
+    }
+    let mut signed = HashSet::new();
+    for index in &vector.signers {
+        if !group
+            .verifying_shares
+            .iter()
+            .any(|share| share.index == *index)
+        {
+            problems.push(format!("signer {} has no share of the group", index));
+        }
+        if !signed.insert(index) {
+            problems.push(format!("signer {} signs twice", index));
+        }
+    }
+    if signed.len() < usize::from(group.threshold) {
+        problems.push(format!(
+            "{} signers are short of the threshold of {}",
+            signed.len(),
+            group.threshold
+        ));
+    }
+    let holds = match (hex::decode(&vector.message), hex::decode(&vector.signature)) {
+        (Ok(message), Ok(signature)) => group.verify(&message, &signature),
+        _ => false,
+    };
+    check_validity(&mut problems, vector.valid, holds);
+    problems
+}
+
+fn check_state_root(vector: &StateRootVector) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut tree = MerkleTree::new();
+    for entry in &vector.entries {
+        match (hex::decode(&entry.key), hex::decode(&entry.value)) {
+            (Ok(key), Ok(value)) => tree.insert(&key, &value),
+            _ => problems.push(format!("{} is not hex", entry.key)),
+        }
+    }
+    same(
+        &mut problems,
+        "the root",
+        &vector.root,
+        &hex::encode(tree.root_hash()),
+    );
+    problems
+}
@@ -0,0 +1,363 @@
+//! The vectors, built from named keys and fixed inputs
+
+use anyhow::{bail, Result};
+use chaoschain_core::multisig::MultisigAccount;
+use chaoschain_core::threshold::finality_message;
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{Block, BlockHeader, Bytes, Transaction};
+use chaoschain_crypto::hash::{chain_hash, HashFunction};
+use chaoschain_crypto::threshold::{deal, KeyShare};
+use chaoschain_state::MerkleTree;
+use chaoschain_testutil::fixtures::{self, GENESIS_TIME};
+use chaoschain_testutil::{BlockBuilder, TransactionBuilder};
+use ed25519_dalek::SigningKey;
+
+use crate::check::{block_preimage, hashes, transaction_preimage};
+use crate::{
+    BlockVector, GroupSignatureVector, StateEntry, StateRootVector, TransactionVector, Vectors,
+    VoteVector,
+};
+
+/// Every vector, under the sha256 chain hash
+pub fn generate() -> Result<Vectors> {
+    if chain_hash() != HashFunction::Sha256 {
+        bail!("The vectors are generated under the sha256 chain hash");
+    }
+    let state_roots = state_roots();
+    let transactions = transactions()?;
+    let blocks = blocks(&transactions, &state_roots);
+    let votes = votes(&blocks);
+    let group_signatures = group_signatures(&blocks)?;
+    Ok(Vectors {
+        transactions,
+        blocks,
+        votes,
+        group_signatures,
+        state_roots,
+    })
+}
+
+fn secret(key: &SigningKey) -> String {
+    hex::encode(key.to_bytes())
+}
+
+fn encode<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("vectors encode")
+}
+
+fn transaction_vector(
+    name: &str,
+    keys: &[SigningKey],
+    account: Option<MultisigAccount>,
+    tx: Transaction,
+    valid: bool,
+) -> TransactionVector {
+    TransactionVector {
+        name: name.to_string(),
+        secret_keys: keys.iter().map(secret).collect(),
+        account,
+        encoded: encode(&tx),
+        signing_bytes: hex::encode(tx.signing_bytes()),
+        hashes: hashes(
+            |_| transaction_preimage(&tx),
+            |function| tx.hash_with(function),
+        ),
+        transaction: tx,
+        valid,
+    }
+}
+
+fn transactions() -> Result<Vec<TransactionVector>> {
+    let alice = fixtures::key("alice");
+    let sender = fixtures::key(fixtures::SENDER);
+    let owners: Vec<SigningKey> = (1..=3)
+        .map(|index| fixtures::key(&format!("owner-{}", index)))
+        .collect();
+    let account = MultisigAccount::new(
+        owners
+            .iter()
+            .map(|key| key.verifying_key().to_bytes())
+            .collect(),
+        2,
+    )?;
+    let cosigners = [owners[0].clone(), owners[2].clone()];
+
+    let transfer = TransactionBuilder::new()
+        .signer(&alice)
+        .nonce(7)
+        .payload("transfer 100 to bob")
+        .build();
+    let mut tampered = transfer.clone();
+    tampered.payload = Bytes::from_static(b"transfer 1000 to bob");
+
+    Ok(vec![
+        transaction_vector(
+            "transfer",
+            std::slice::from_ref(&alice),
+            None,
+            transfer.clone(),
+            true,
+        ),
+        transaction_vector(
+            "empty payload",
+            std::slice::from_ref(&sender),
+            None,
+            TransactionBuilder::new().signer(&sender).build(),
+            true,
+        ),
+        transaction_vector(
+            "binary payload and a large nonce",
+            std::slice::from_ref(&sender),
+            None,
+            TransactionBuilder::new()
+                .signer(&sender)
+                .nonce(u64::MAX - 1)
+                .payload((0..=255).collect::<Vec<u8>>())
+                .build(),
+            true,
+        ),
+        transaction_vector(
+            "multisig account creation",
+            std::slice::from_ref(&alice),
+            None,
+            TransactionBuilder::new()
+                .signer(&alice)
+                .nonce(8)
+                .payload(account.payload())
+                .build(),
+            true,
+        ),
+        transaction_vector(
+            "multisig 2 of 3",
+            &cosigners,
+            Some(account.clone()),
+            TransactionBuilder::new()
+                .account(&account)
+                .cosigner(&cosigners[0])
+                .cosigner(&cosigners[1])
+                .nonce(1)
+                .payload("treasury pays the bard")
+                .build(),
+            true,
+        ),
+        transaction_vector(
+            "multisig short of its threshold",
+            &cosigners[..1],
+            Some(account.clone()),
+            TransactionBuilder::new()
+                .account(&account)
+                .cosigner(&cosigners[0])
+                .nonce(2)
+                .payload("treasury pays the bard again")
+                .build(),
+            false,
+        ),
+        transaction_vector(
+            "payload changed after signing",
+            &[alice],
+            None,
+            tampered,
+            false,
+        ),
+    ])
+}
+
+fn block_vector(name: &str, key: &SigningKey, block: Block, valid: bool) -> BlockVector {
+    BlockVector {
+        name: name.to_string(),
+        secret_key: secret(key),
+        public_key: hex::encode(key.verifying_key().to_bytes()),
+        encoded: encode(&block),
+        signing_bytes: hex::encode(block.signing_bytes()),
+        hashes: hashes(
+            |function| block_preimage(&block, function),
+            |function| block.hash_with(function),
+        ),
+        header: BlockHeader::of(&block),
+        block,
+        valid,
+    }
+}
+
+fn blocks(transactions: &[TransactionVector], state_roots: &[StateRootVector]) -> Vec<BlockVector> {
+    let producer = fixtures::key(fixtures::PRODUCER);
+    let first = BlockBuilder::new().build();
+    let full = BlockBuilder::new()
+        .parent(&first)
+        .producer("producer-7", &fixtures::key("producer-7"))
+        .transactions(
+            transactions
+                .iter()
+                .filter(|vector| vector.valid)
+                .map(|vector| vector.transaction.clone()),
+        )
+        .state_root(
+            <[u8; 32]>::try_from(hex::decode(&state_roots[2].root).expect("roots are hex"))
+                .expect("roots are 32 bytes"),
+        )
+        .drama_level(9)
+        .innovation_level(80)
+        .mood("Furious ☄️")
+        .build();
+    let mut tampered = full.clone();
+    tampered.drama_level = 1;
+
+    vec![
+        block_vector("first block", &producer, first, true),
+        block_vector(
+            "block with transactions",
+            &fixtures::key("producer-7"),
+            full,
+            true,
+        ),
+        block_vector(
+            "drama level changed after signing",
+            &fixtures::key("producer-7"),
+            tampered,
+            false,
+        ),
+    ]
+}
+
+fn vote_vector(name: &str, key: &SigningKey, vote: SignedVote, valid: bool) -> VoteVector {
+    VoteVector {
+        name: name.to_string(),
+        secret_key: secret(key),
+        public_key: hex::encode(key.verifying_key().to_bytes()),
+        encoded: encode(&vote),
+        explanation_hash: hex::encode(vote.explanation_hash()),
+        signing_bytes: hex::encode(vote.signing_bytes()),
+        vote,
+        valid,
+    }
+}
+
+fn votes(blocks: &[BlockVector]) -> Vec<VoteVector> {
+    let block = &blocks[1].block;
+    let validator = fixtures::key("validator-1");
+    let approval = SignedVote::sign(
+        &validator,
+        "validator-1",
+        block.height,
+        block.hash(),
+        true,
+        7,
+        "Pure theatre, approved",
+    );
+    let mut forged = approval.clone();
+    forged.reason = "Boring, but approved".to_string();
+    let skeptic = fixtures::key("validator-2");
+
+    vec![
+        vote_vector("approval", &validator, approval, true),
+        vote_vector(
+            "rejection with a unicode reason",
+            &skeptic,
+            SignedVote::sign(
+                &skeptic,
+                "validator-2",
+                block.height,
+                block.hash(),
+                false,
+                0,
+                "Не одобряю 🎭",
+            ),
+            true,
+        ),
+        vote_vector("reason changed after signing", &validator, forged, false),
+    ]
+}
+
+/// A group of `count` signing the block as `signers`, with a key dealt at
+/// random
+fn group_signature(
+    name: &str,
+    threshold: u16,
+    count: u16,
+    signers: &[u16],
+    block: &Block,
+) -> Result<GroupSignatureVector> {
+    let (group, shares) = deal(threshold, count)?;
+    let message = finality_message(block.height, &block.hash());
+    let signing: Vec<&KeyShare> = shares
+        .iter()
+        .filter(|share| signers.contains(&share.index))
+        .collect();
+    let (nonces, commitments): (Vec<_>, Vec<_>) =
+        signing.iter().map(|share| share.commit()).unzip();
+    let partials = signing
+        .iter()
+        .zip(nonces)
+        .map(|(share, nonces)| share.sign(nonces, &message, &commitments))
+        .collect::<Result<Vec<_>, _>>()?;
+    let signature = group.combine(&message, &commitments, &partials)?;
+    Ok(GroupSignatureVector {
+        name: name.to_string(),
+        group_key: group,
+        height: block.height,
+        block_hash: hex::encode(block.hash()),
+        message: hex::encode(message),
+        signers: signers.to_vec(),
+        signature: hex::encode(signature),
+        valid: true,
+    })
+}
+
+fn group_signatures(blocks: &[BlockVector]) -> Result<Vec<GroupSignatureVector>> {
+    let block = &blocks[1].block;
+    let mut tampered = group_signature("signature changed", 2, 3, &[2, 3], block)?;
+    let mut signature = hex::decode(&tampered.signature)?;
+    signature[40] ^= 1;
+    tampered.signature = hex::encode(signature);
+    tampered.valid = false;
+    Ok(vec![
+        group_signature("2 of 3", 2, 3, &[1, 3], block)?,
+        group_signature("3 of 4", 3, 4, &[1, 2, 4], &blocks[0].block)?,
+        tampered,
+    ])
+}
+
+fn state_root(name: &str, entries: &[(&str, &str)]) -> StateRootVector {
+    let mut tree = MerkleTree::new();
+    for (key, value) in entries {
+        tree.insert(key.as_bytes(), value.as_bytes());
+    }
+    StateRootVector {
+        name: name.to_string(),
+        entries: entries
+            .iter()
+            .map(|(key, value)| StateEntry {
+                key: hex::encode(key),
+                value: hex::encode(value),
+            })
+            .collect(),
+        root: hex::encode(tree.root_hash()),
+    }
+}
+
+/// Entries are given out of order, the root is of them sorted by key
+fn state_roots() -> Vec<StateRootVector> {
+    let genesis = GENESIS_TIME.to_string();
+    vec![
+        state_root("empty", &[]),
+        state_root("one balance", &[("balance/alice", "100")]),
+        state_root(
+            "three entries",
+            &[
+                ("balance/carol", "7"),
+                ("balance/alice", "100"),
+                ("producer/producer-7", "active"),
+            ],
+        ),
+        state_root(
+            "five entries, the last carried up",
+            &[
+                ("balance/bob", "250"),
+                ("balance/alice", "100"),
+                ("drama", "9"),
+                ("genesis_time", &genesis),
+                ("balance/carol", "7"),
+            ],
+        ),
+    ]
+}
@@ -0,0 +1,259 @@
+//! Conformance test vectors: what a ChaosChain node encodes, hashes and
+//! signs, written out as JSON for alternative clients and the Python
+//! tooling to check themselves against. Every vector carries the input and
+//! what comes of it, with the bytes in between spelled out in hex:
+//!
+//! - transactions: the JSON encoding, the bytes the sender signs, and the
+//!   preimage and digest of the hash under each hash function, multisig
+//!   transactions and their cosignatures among them
+//! - blocks: the same for the block and what the producer signs, and the
+//!   header a node serves for it
+//! - votes: the encoding, the explanation hash and the signing bytes
+//! - group signatures: a threshold signature of the finality message of a
+//!   block, and the group key it verifies against
+//! - state roots: key-value pairs and the merkle root of the state holding
+//!   them
+//!
+//! Keys are derived from names, as the [fixtures](chaoschain_testutil::fixtures)
+//! derive them, and each vector gives the secret it was signed with, so all
+//! but the group signatures come out the same on every [`generate`]. The
+//! group keys are dealt at random, and so those are checked, never compared.
+//! Some vectors are marked `"valid": false`, tampered after signing, for a
+//! client to reject.
+//!
+//! The vectors are kept in `tests/vectors`, one file per kind, and
+//! `chaoschain-vectors` writes and checks them:
+//!
+//! ```bash
+//! chaoschain-vectors generate --out tests/vectors
+//! chaoschain-vectors verify tests/vectors
+//! ```
+//!
+//! Transaction hashes in the signing bytes of a block are under the chain's
+//! hash function, sha256 unless the genesis picks blake3; the vectors are
+//! generated and checked under sha256.
+
+mod check;
+mod generate;
+
+pub use check::{verify, Failure};
+pub use generate::generate;
+
+use anyhow::{Context, Result};
+use chaoschain_core::multisig::MultisigAccount;
+use chaoschain_core::vote::SignedVote;
+use chaoschain_core::{Block, BlockHeader, Transaction};
+use chaoschain_crypto::hash::HashFunction;
+use chaoschain_crypto::threshold::GroupKey;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub const TRANSACTIONS_FILE: &str = "transactions.json";
+pub const BLOCKS_FILE: &str = "blocks.json";
+pub const VOTES_FILE: &str = "votes.json";
+pub const GROUP_SIGNATURES_FILE: &str = "group_signatures.json";
+pub const STATE_ROOTS_FILE: &str = "state_roots.json";
+
+/// A hash of some bytes, hex encoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashVector {
+    pub function: HashFunction,
+    /// Everything fed to the hash function, in order
+    pub preimage: String,
+    pub digest: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionVector {
+    pub name: String,
+    /// Secret keys that signed it, the sender's or the cosigners', hex
+    /// encoded
+    pub secret_keys: Vec<String>,
+    /// The account it is sent from, for a multisig transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<MultisigAccount>,
+    pub transaction: Transaction,
+    /// The transaction as JSON, as nodes send it to each other
+    pub encoded: String,
+    pub signing_bytes: String,
+    pub hashes: Vec<HashVector>,
+    /// Whether its signatures hold
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockVector {
+    pub name: String,
+    /// Secret key of the producer, hex encoded
+    pub secret_key: String,
+    pub public_key: String,
+    pub block: Block,
+    pub encoded: String,
+    /// What `proposer_sig` signs
+    pub signing_bytes: String,
+    pub hashes: Vec<HashVector>,
+    pub header: BlockHeader,
+    /// Whether the proposer signature holds
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoteVector {
+    pub name: String,
+    pub secret_key: String,
+    pub public_key: String,
+    pub vote: SignedVote,
+    pub encoded: String,
+    /// sha256 of the reason
+    pub explanation_hash: String,
+    pub signing_bytes: String,
+    /// Whether the signature holds
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupSignatureVector {
+    pub name: String,
+    pub group_key: GroupKey,
+    pub height: u64,
+    pub block_hash: String,
+    /// The finality message of the block, what the group signs
+    pub message: String,
+    /// Indexes of the shares that signed
+    pub signers: Vec<u16>,
+    /// An ed25519 signature by the group's public key
+    pub signature: String,
+    pub valid: bool,
+}
+
+/// A key and what it holds in the state
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateEntry {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateRootVector {
+    pub name: String,
+    pub entries: Vec<StateEntry>,
+    pub root: String,
+}
+
+/// Vectors of every kind
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Vectors {
+    pub transactions: Vec<TransactionVector>,
+    pub blocks: Vec<BlockVector>,
+    pub votes: Vec<VoteVector>,
+    pub group_signatures: Vec<GroupSignatureVector>,
+    pub state_roots: Vec<StateRootVector>,
+}
+
+impl Vectors {
+    /// The vectors kept in `dir`, a file for each kind
+    pub fn load(dir: &Path) -> Result<Self> {
+        Ok(Self {
+            transactions: read(dir, TRANSACTIONS_FILE)?,
+            blocks: read(dir, BLOCKS_FILE)?,
+            votes: read(dir, VOTES_FILE)?,
+            group_signatures: read(dir, GROUP_SIGNATURES_FILE)?,
+            state_roots: read(dir, STATE_ROOTS_FILE)?,
+        })
+    }
+
+    pub fn write(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create {}", dir.display()))?;
+        write(dir, TRANSACTIONS_FILE, &self.transactions)?;
+        write(dir, BLOCKS_FILE, &self.blocks)?;
+        write(dir, VOTES_FILE, &self.votes)?;
+        write(dir, GROUP_SIGNATURES_FILE, &self.group_signatures)?;
+        write(dir, STATE_ROOTS_FILE, &self.state_roots)
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+            + self.blocks.len()
+            + self.votes.len()
+            + self.group_signatures.len()
+            + self.state_roots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn read<T: DeserializeOwned>(dir: &Path, file: &str) -> Result<T> {
+    let path = dir.join(file);
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("Could not read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("{} holds no vectors", path.display()))
+}
+
+fn write<T: Serialize>(dir: &Path, file: &str, vectors: &T) -> Result<()> {
+    let path = dir.join(file);
+    std::fs::write(&path, serde_json::to_string_pretty(vectors)? + "\n")
+        .with_context(|| format!("Could not write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn checked_in() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../../tests/vectors")
+    }
+
+    #[test]
+    fn test_checked_in_vectors_hold() {
+        let vectors = Vectors::load(&checked_in()).unwrap();
+        assert!(!vectors.group_signatures.is_empty());
+        assert_eq!(verify(&vectors), Vec::new());
+    }
+
+    #[test]
+    fn test_checked_in_vectors_are_generated() {
+        // Regenerate them with `chaoschain-vectors generate` when this fails
+        // after a change to an encoding that was meant
+        let checked_in = Vectors::load(&checked_in()).unwrap();
+        let generated = generate().unwrap();
+        assert_eq!(checked_in.transactions, generated.transactions);
+        assert_eq!(checked_in.blocks, generated.blocks);
+        assert_eq!(checked_in.votes, generated.votes);
+        assert_eq!(checked_in.state_roots, generated.state_roots);
+        assert_eq!(
+            checked_in.group_signatures.len(),
+            generated.group_signatures.len()
+        );
+    }
+
+    #[test]
+    fn test_write_and_load() {
+        let dir = std::env::temp_dir().join(format!("vectors-{}", std::process::id()));
+        let vectors = generate().unwrap();
+        vectors.write(&dir).unwrap();
+        assert_eq!(Vectors::load(&dir).unwrap(), vectors);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tampered_vectors_fail() {
+        let mut vectors = generate().unwrap();
+        vectors.transactions[0].hashes[0].digest = hex::encode([0u8; 32]);
+        vectors.blocks[0].valid = !vectors.blocks[0].valid;
+        vectors.votes[0].signing_bytes.push_str("00");
+        vectors.group_signatures[0].signers.clear();
+        vectors.state_roots[1].entries[0].value = hex::encode(b"tampered");
+        let failures = verify(&vectors);
+        let names: Vec<&str> = failures.iter().map(|f| f.vector.as_str()).collect();
+        assert!(names.contains(&vectors.transactions[0].name.as_str()));
+        assert!(names.contains(&vectors.blocks[0].name.as_str()));
+        assert!(names.contains(&vectors.votes[0].name.as_str()));
+        assert!(names.contains(&vectors.group_signatures[0].name.as_str()));
+        assert!(names.contains(&vectors.state_roots[1].name.as_str()));
+    }
+}
@@ -0,0 +1,64 @@
+//! `chaoschain-vectors`: write the conformance vectors, or check a
+//! directory of them against this implementation, see the
+//! [library](chaoschain_vectors):
+//!
+//! ```bash
+//! chaoschain-vectors generate --out tests/vectors
+//! chaoschain-vectors verify tests/vectors
+//! ```
+//!
+//! `verify` exits with 1 when a vector does not hold.
+
+use anyhow::Result;
+use chaoschain_vectors::Vectors;
+use clap::{Parser, Subcommand};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Write the vectors, a JSON file of each kind
+    Generate {
+        #[arg(long, default_value = "tests/vectors")]
+        out: PathBuf,
+    },
+    /// Check the vectors in a directory
+    Verify {
+        #[arg(default_value = "tests/vectors")]
+        dir: PathBuf,
+    },
+}
+
+fn main() -> Result<()> {
+    match Args::parse().command {
+        Command::Generate { out } => {
+            let vectors = chaoschain_vectors::generate()?;
+            vectors.write(&out)?;
+            println!("Wrote {} vectors to {}", vectors.len(), out.display());
+        }
+        Command::Verify { dir } => {
+            let vectors = Vectors::load(&dir)?;
+            let failures = chaoschain_vectors::verify(&vectors);
+            for failure in &failures {
+                println!("✗ {}", failure);
+            }
+            if !failures.is_empty() {
+                let failing: HashSet<_> = failures
+                    .iter()
+                    .map(|failure| (failure.kind, &failure.vector))
+                    .collect();
+                println!("{} of {} vectors fail", failing.len(), vectors.len());
+                std::process::exit(1);
+            }
+            println!("All {} vectors hold", vectors.len());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+"""Check the ChaosChain conformance vectors from Python, rebuilding every
+signing preimage, hash preimage and sha256 hash from the fields.
+
+python check_vectors.py tests/vectors
+
+Signatures are checked too when pynacl is installed (pip install pynacl).
+"""
+import hashlib
+import json
+import struct
+import sys
+from pathlib import Path
+
+try:
+    from nacl.exceptions import BadSignatureError
+    from nacl.signing import VerifyKey
+except ImportError:
+    VerifyKey = None
+
+
+def signature_holds(public_key: bytes, message: bytes, signature: bytes):
+    if VerifyKey is None:
+        return None
+    try:
+        VerifyKey(public_key).verify(message, signature)
+        return True
+    except (BadSignatureError, ValueError):
+        return False
+
+
+def tx_signing_bytes(tx: dict) -> bytes:
+    return bytes(tx["sender"]) + struct.pack("<Q", tx["nonce"]) + bytes(tx["payload"])
+
+
+def tx_preimage(tx: dict) -> bytes:
+    preimage = (bytes(tx["sender"]) + struct.pack(">Q", tx["nonce"])
+                + bytes(tx["payload"]) + bytes(tx["signature"]))
+    for cosignature in tx.get("cosignatures", []):
+        preimage += bytes.fromhex(cosignature["owner"]) + bytes.fromhex(cosignature["signature"])
+    return preimage
+
+
+def tx_hash(tx: dict) -> bytes:
+    return hashlib.sha256(tx_preimage(tx)).digest()
+
+
+def block_signing_bytes(block: dict) -> bytes:
+    return (struct.pack("<Q", block["height"]) + bytes(block["parent_hash"])
+            + b"".join(tx_hash(tx) for tx in block["transactions"])
+            + bytes(block["state_root"]) + bytes([block["drama_level"]])
+            + block["producer_mood"].encode() + struct.pack("<Q", block["timestamp"]))
+
+
+def block_preimage(block: dict) -> bytes:
+    return (struct.pack(">Q", block["height"]) + bytes(block["parent_hash"])
+            + b"".join(tx_hash(tx) for tx in block["transactions"])
+            + bytes(block["proposer_sig"]) + bytes(block["state_root"])
+            + bytes([block["innovation_level"]]) + block["producer_strategy"].encode()
+            + block["producer_id"].encode() + bytes([block["drama_level"]])
+            + block["producer_mood"].encode() + struct.pack(">Q", block["timestamp"]))
+
+
+def vote_signing_bytes(vote: dict) -> bytes:
+    validator = vote["validator"].encode()
+    return (b"chaoschain-vote-v1" + struct.pack(">I", len(validator)) + validator
+            + struct.pack(">Q", vote["height"]) + bytes.fromhex(vote["block_hash"])
+            + bytes([int(vote["approved"]), vote["drama_level"]])
+            + hashlib.sha256(vote["reason"].encode()).digest())
+
+
+def merkle_root(entries: list) -> bytes:
+    leaves = sorted((bytes.fromhex(e["key"]), bytes.fromhex(e["value"])) for e in entries)
+    level = [hashlib.sha256(b"leaf" + key + value).digest() for key, value in leaves]
+    if not level:
+        return bytes(32)
+    while len(level) > 1:
+        level = [hashlib.sha256(b"node" + level[i] + level[i + 1]).digest()
+                 if i + 1 < len(level) else level[i]
+                 for i in range(0, len(level), 2)]
+    return level[0]
+
+
+def sha256_of(vector: dict) -> dict:
+    return next(h for h in vector["hashes"] if h["function"] == "sha256")
+
+
+def check(dir: Path) -> list:
+    load = lambda name: json.loads((dir / name).read_text())
+    failures = []
+
+    def expect(kind, vector, what, expected, got):
+        if expected != got:
+            failures.append(f"{kind} {vector['name']!r}: {what} is {got}, not {expected}")
+
+    for v in load("transactions.json"):
+        tx = v["transaction"]
+        expect("transactions", v, "the signing bytes", v["signing_bytes"], tx_signing_bytes(tx).hex())
+        expect("transactions", v, "the preimage", sha256_of(v)["preimage"], tx_preimage(tx).hex())
+        expect("transactions", v, "the hash", sha256_of(v)["digest"], tx_hash(tx).hex())
+        if "account" not in v:
+            holds = signature_holds(bytes(tx["sender"]), tx_signing_bytes(tx), bytes(tx["signature"]))
+            if holds is not None:
+                expect("transactions", v, "valid", v["valid"], holds)
+
+    for v in load("blocks.json"):
+        block = v["block"]
+        expect("blocks", v, "the signing bytes", v["signing_bytes"], block_signing_bytes(block).hex())
+        preimage = block_preimage(block)
+        expect("blocks", v, "the preimage", sha256_of(v)["preimage"], preimage.hex())
+        expect("blocks", v, "the hash", sha256_of(v)["digest"], hashlib.sha256(preimage).hexdigest())
+        holds = signature_holds(bytes.fromhex(v["public_key"]), block_signing_bytes(block),
+                                bytes(block["proposer_sig"]))
+        if holds is not None:
+            expect("blocks", v, "valid", v["valid"], holds)
+
+    for v in load("votes.json"):
+        vote = v["vote"]
+        expect("votes", v, "the explanation hash", v["explanation_hash"],
+               hashlib.sha256(vote["reason"].encode()).hexdigest())
+        expect("votes", v, "the signing bytes", v["signing_bytes"], vote_signing_bytes(vote).hex())
+        holds = signature_holds(bytes.fromhex(v["public_key"]), vote_signing_bytes(vote),
+                                bytes.fromhex(vote["signature"]))
+        if holds is not None:
+            expect("votes", v, "valid", v["valid"], holds)
+
+    for v in load("group_signatures.json"):
+        message = (b"chaoschain-finality-v1" + struct.pack(">Q", v["height"])
+                   + bytes.fromhex(v["block_hash"]))
+        expect("group_signatures", v, "the message", v["message"], message.hex())
+        holds = signature_holds(bytes.fromhex(v["group_key"]["public_key"]), message,
+                                bytes.fromhex(v["signature"]))
+        if holds is not None:
+            expect("group_signatures", v, "valid", v["valid"], holds)
+
+    for v in load("state_roots.json"):
+        expect("state_roots", v, "the root", v["root"], merkle_root(v["entries"]).hex())
+
+    return failures
+
+
+if __name__ == "__main__":
+    failures = check(Path(sys.argv[1] if len(sys.argv) > 1 else "tests/vectors"))
+    for failure in failures:
+        print("✗", failure)
+    if VerifyKey is None:
+        print("pynacl is not installed, signatures were not checked")
+    print(f"{len(failures)} failures")
+    sys.exit(1 if failures else 0)
@@ -0,0 +1,90 @@
+"""Minimal external validator for the ChaosChain agent gateway.
+
+pip install pynacl websockets aiohttp
+"""
+import asyncio
+import hashlib
+import json
+import random
+import struct
+
+import aiohttp
+import websockets
+from nacl.signing import SigningKey
+
+ENDPOINT = "http://localhost:3000"
+NAME = "PyChaos"
+
+
+def registration_message(name: str, public_key: bytes) -> bytes:
+    encoded = name.encode()
+    return b"chaoschain-register-v1" + struct.pack(">I", len(encoded)) + encoded + public_key
+
+
+def sign_vote(key: SigningKey, validator: str, height: int, block_hash: str,
+              approved: bool, drama_level: int, reason: str) -> dict:
+    encoded = validator.encode()
+    message = (
+        b"chaoschain-vote-v1"
+        + struct.pack(">I", len(encoded)) + encoded
+        + struct.pack(">Q", height)
+        + bytes.fromhex(block_hash)
+        + bytes([int(approved), drama_level])
+        + hashlib.sha256(reason.encode()).digest()
+    )
+    return {
+        "validator": validator,
+        "height": height,
+        "block_hash": block_hash,
+        "approved": approved,
+        "drama_level": drama_level,
+        "reason": reason,
+        "signature": key.sign(message).signature.hex(),
+    }
+
+
+def decide(block: dict) -> tuple[bool, int, str]:
+    drama = random.randint(0, 10)
+    if drama > 7:
+        return False, drama, "Too much chaos, even for me"
+    return True, drama, f"Block {block['height']} has the right kind of vibes"
+
+
+async def main():
+    key = SigningKey.generate()
+    public_key = key.verify_key.encode()
+
+    async with aiohttp.ClientSession() as session:
+        async with session.post(f"{ENDPOINT}/api/gateway/register", json={
+            "name": NAME,
+            "public_key": public_key.hex(),
+            "signature": key.sign(registration_message(NAME, public_key)).signature.hex(),
+        }) as response:
+            response.raise_for_status()
+            agent_id = (await response.json())["agent_id"]
+    print(f"🔌 Registered as {agent_id}")
+
+    ws_endpoint = ENDPOINT.replace("http", "ws")
+    async with websockets.connect(f"{ws_endpoint}/api/gateway/ws?agent_id={agent_id}") as ws:
+        challenge = json.loads(await ws.recv())
+        nonce = bytes.fromhex(challenge["nonce"])
+        await ws.send(json.dumps({
+            "type": "auth",
+            "signature": key.sign(b"chaoschain-auth-v1" + nonce).signature.hex(),
+        }))
+
+        async for raw in ws:
+            frame = json.loads(raw)
+            if frame["type"] == "proposal":
+                approved, drama, reason = decide(frame["block"])
+                vote = sign_vote(key, agent_id, frame["height"], frame["block_hash"],
+                                 approved, drama, reason)
+                await ws.send(json.dumps({"type": "vote", "vote": vote}))
+            elif frame["type"] == "receipt":
+                print(f"✅ Vote on {frame['block_hash'][:12]} counted")
+            elif frame["type"] == "error":
+                print(f"⚠️ {frame['message']}")
+
+
+if __name__ == "__main__":
+    asyncio.run(main())
@@ -0,0 +1,6 @@
+#![no_main]
+
+use chaoschain_fuzz::Target;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| Target::Gossip.run(data));